@@ -0,0 +1,142 @@
+//! Measure the stages of the clifp frontend on generated modules: lexing the source, parsing
+//! its tokens into a module, and lowering the functions of the module to Cranelift IR.
+//!
+//! There are two corpora, one mostly made of integer and floating-point literals and one mostly
+//! made of long names, each at a tenth of its full size and at its full size, so that a stage
+//! whose cost grows faster than the source shows a lower throughput on the larger module.
+
+use cranelift_clifp::{compile, frontend, lexer, parser, sexp};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Linkage, Module};
+use criterion::{
+    black_box, criterion_group, criterion_main, BenchmarkId, Criterion, SamplingMode, Throughput,
+};
+
+/// The number of functions of the full-size modules, each of about twenty expressions.
+const FUNCTIONS: usize = 10_000;
+
+/// A number derived from `i`, the same on every run.
+fn mix(i: usize) -> u64 {
+    (i as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 40
+}
+
+/// A module of `functions` integer and floating-point functions, whose bodies are mostly
+/// literals in every base and notation the lexer accepts.
+fn numbers(functions: usize) -> String {
+    let mut src = String::new();
+    for i in 0..functions {
+        let (a, b, c) = (mix(i), mix(i + functions), mix(i + 2 * functions));
+        if i % 2 == 0 {
+            src.push_str(&format!(
+                "(func n{i} ((x i64)) (i64)\n  \
+                   (let ((a (iadd (imul x {a}) 0x{b:x}))\n        \
+                         (b (bxor (isub a -{c}) 0b{:b}))\n        \
+                         (c (band (iadd b 1_000_{:03}) 0o{:o})))\n    \
+                     (iadd (imul c {b}) (isub {a} 0x{c:x}_{:04x}))))\n",
+                a & 0xff,
+                c % 1000,
+                b,
+                a & 0xffff,
+            ));
+        } else {
+            src.push_str(&format!(
+                "(func n{i} ((x f64)) (f64)\n  \
+                   (let ((a (fadd (fmul x {a}.{b}) -{c}e-3))\n        \
+                         (b (fsub (fdiv a 1_024.5) {b}.25e2))\n        \
+                         (c (fmul (fadd b 0.{c}) -{a}e+1)))\n    \
+                     (fadd (fmul c {b}.0) (fsub {a}.5 {c}e4))))\n"
+            ));
+        }
+    }
+    src
+}
+
+/// A module of `functions` functions whose bodies are mostly long parameter and binding names,
+/// and calls of other functions by name.
+fn identifiers(functions: usize) -> String {
+    let mut src = String::new();
+    for i in 0..functions {
+        let name = |stem: &str| format!("{stem}_{i}");
+        let (first, second) = ("first_operand_value", "second_operand_value");
+        let (sum, product) = (name("accumulated_sum"), name("scaled_product"));
+        let (difference, result) = (name("shifted_difference"), name("combined_result"));
+        let callee = format!("compute_intermediate_value_{}", mix(i) as usize % functions);
+        src.push_str(&format!(
+            "(func {} (({first} i64) ({second} i64)) (i64)\n  \
+               (let (({sum} (iadd {first} {second}))\n        \
+                     ({product} (imul {sum} {first}))\n        \
+                     ({difference} (isub {product} (ishl {second} {sum})))\n        \
+                     ({result} (bxor {difference} (band {product} {sum}))))\n    \
+                 (if {first}\n      \
+                   (call {callee} (isub {first} {result}) {second})\n      \
+                   (iadd {result} {difference}))))\n",
+            name("compute_intermediate_value"),
+        ));
+    }
+    src
+}
+
+/// Declare the functions of `module` in a JIT module, which `compile::lower` looks them up in.
+fn declared(module: &parser::Module) -> JITModule {
+    let mut jit = JITModule::new(JITBuilder::new(default_libcall_names()).unwrap());
+    let call_conv = jit.isa().default_call_conv();
+    for func in &module.functions {
+        let sig = func.signature(call_conv).unwrap();
+        jit.declare_function(&func.name, Linkage::Export, &sig)
+            .unwrap();
+    }
+    jit
+}
+
+fn frontend_benchmarks(c: &mut Criterion) {
+    for (corpus, generate) in [
+        ("numbers", numbers as fn(usize) -> String),
+        ("identifiers", identifiers),
+    ] {
+        let mut group = c.benchmark_group(format!("clifp frontend {corpus}"));
+        group.sample_size(10).sampling_mode(SamplingMode::Flat);
+        for functions in [FUNCTIONS / 10, FUNCTIONS] {
+            let src = generate(functions);
+            let tokens = lexer::lex(&src).unwrap();
+            let (module, _) = frontend(&src).unwrap();
+            let mut jit = declared(&module);
+            group.throughput(Throughput::Bytes(src.len() as u64));
+
+            group.bench_with_input(BenchmarkId::new("lex", functions), &src, |b, src| {
+                b.iter(|| lexer::lex(black_box(src)).unwrap().len())
+            });
+            group.bench_with_input(
+                BenchmarkId::new("parse", functions),
+                &tokens,
+                |b, tokens| {
+                    b.iter(|| {
+                        let (forms, spans) = sexp::parse_with_spans(black_box(tokens)).unwrap();
+                        parser::parse(&forms, &spans).unwrap().functions.len()
+                    })
+                },
+            );
+            group.bench_with_input(
+                BenchmarkId::new("lower", functions),
+                &module,
+                |b, module| {
+                    b.iter(|| {
+                        module
+                            .functions
+                            .iter()
+                            .map(|func| {
+                                compile::lower(func, &module.constants, &mut jit)
+                                    .unwrap()
+                                    .dfg
+                                    .num_insts()
+                            })
+                            .sum::<usize>()
+                    })
+                },
+            );
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, frontend_benchmarks);
+criterion_main!(benches);
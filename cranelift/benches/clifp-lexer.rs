@@ -0,0 +1,47 @@
+//! Measure lexing about a megabyte of clifp source with the streaming `Lexer`, compared to
+//! collecting its tokens with `lex`, and to also copying every name into a `String`, as the
+//! lexer did before its tokens borrowed the source.
+
+use cranelift_clifp::lexer::{self, Lexer, Token};
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+/// Generate functions of a few dozen tokens each until the source is at least `size` bytes.
+fn source(size: usize) -> String {
+    let mut src = String::new();
+    let mut i = 0;
+    while src.len() < size {
+        src.push_str(&format!(
+            "; function {i}\n\
+             (func f{i} ((n i64) (acc_{i} i64)) (i64)\n  \
+               (if (< n 1) acc_{i}\n    \
+                 (call f{i} (- n 1) (bxor (* acc_{i} 0x9e37_79b9) (+ n {i})))))\n"
+        ));
+        i += 1;
+    }
+    src
+}
+
+fn lexer_benchmarks(c: &mut Criterion) {
+    let src = source(1 << 20);
+    let mut group = c.benchmark_group("clifp lexer");
+    group.throughput(Throughput::Bytes(src.len() as u64));
+    group.bench_function("stream", |b| b.iter(|| Lexer::new(black_box(&src)).count()));
+    group.bench_function("collect", |b| {
+        b.iter(|| lexer::lex(black_box(&src)).unwrap().len())
+    });
+    group.bench_function("collect owned names", |b| {
+        b.iter(|| {
+            Lexer::new(black_box(&src))
+                .map(|token| match token.unwrap().0 {
+                    Token::Ident(name) => Some(name.to_string()),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+                .len()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, lexer_benchmarks);
+criterion_main!(benches);
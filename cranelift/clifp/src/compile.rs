@@ -0,0 +1,4192 @@
+//! Lowering clifp functions to Cranelift IR, and compiling a [`Module`] into a Cranelift module
+//! or CLIF text.
+
+use super::diagnostic::Diagnostic;
+use super::lexer::Span;
+use super::parser::{Constant, Expected, Function, Module, Test};
+use super::sexp::{Sexp, SpanTree};
+use super::typeck::{resolve_type, TypeError};
+use cranelift_codegen::control::ControlPlane;
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::immediates::Imm64;
+use cranelift_codegen::ir::{self, types, InstBuilder, MemFlags, Type, UserFuncName, Value};
+use cranelift_codegen::isa::{CallConv, OwnedTargetIsa, TargetIsa};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_codegen::{Context, MachReloc};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_module::{
+    DataDescription, DataId, FuncId, FuncOrDataId, Linkage, ModuleCompiledFunction,
+    ModuleDeclarations, ModuleError, ModuleResult,
+};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// The opcodes of two operands of the same type.
+pub(super) const BINARY: &[&str] = &[
+    "iadd", "isub", "imul", "band", "bor", "bxor", "fadd", "fsub", "fmul", "fdiv",
+];
+/// The opcodes of [`BINARY`] which apply to floats, and only to them.
+const FLOAT_BINARY: &[&str] = &["fadd", "fsub", "fmul", "fdiv"];
+/// The opcodes shifting their first operand by their second, which can have any integer type.
+pub(super) const SHIFTS: &[&str] = &["ishl", "ushr", "sshr"];
+/// The opcodes dividing their first operand by their second, of the same type, which trap if
+/// the divisor is zero. `sdiv` and `srem` divide signed integers, and `sdiv` also traps on the
+/// overflow of dividing the smallest one by -1, while `udiv` and `urem` divide unsigned ones.
+pub(super) const DIVISIONS: &[&str] = &["sdiv", "udiv", "srem", "urem"];
+/// The opcodes of one operand.
+pub(super) const UNARY: &[&str] = &["ineg", "bnot"];
+/// The operators which are other names of opcodes, those of integers and those of floats.
+const SUGAR: &[(&str, &str, &str)] = &[
+    ("+", "iadd", "fadd"),
+    ("-", "isub", "fsub"),
+    ("*", "imul", "fmul"),
+];
+/// The opcodes converting their operand to the type named by their first operand.
+const CONVERSIONS: &[&str] = &["fcvt_from_sint", "fcvt_to_sint"];
+/// The comparison operators, which compare two operands of the same type as signed integers.
+const COMPARISONS: &[(&str, IntCC)] = &[
+    ("=", IntCC::Equal),
+    ("!=", IntCC::NotEqual),
+    ("<", IntCC::SignedLessThan),
+    ("<=", IntCC::SignedLessThanOrEqual),
+    (">", IntCC::SignedGreaterThan),
+    (">=", IntCC::SignedGreaterThanOrEqual),
+];
+/// The condition codes of `icmp` and `icmp_imm`, by their names in CLIF.
+const CONDITION_CODES: &[(&str, IntCC)] = &[
+    ("eq", IntCC::Equal),
+    ("ne", IntCC::NotEqual),
+    ("slt", IntCC::SignedLessThan),
+    ("sle", IntCC::SignedLessThanOrEqual),
+    ("sgt", IntCC::SignedGreaterThan),
+    ("sge", IntCC::SignedGreaterThanOrEqual),
+    ("ult", IntCC::UnsignedLessThan),
+    ("ule", IntCC::UnsignedLessThanOrEqual),
+    ("ugt", IntCC::UnsignedGreaterThan),
+    ("uge", IntCC::UnsignedGreaterThanOrEqual),
+];
+
+/// The opcode of the operator `op`, applied to integers.
+pub(super) fn opcode(op: &str) -> &str {
+    SUGAR
+        .iter()
+        .find(|&&(sugar, _, _)| sugar == op)
+        .map_or(op, |&(_, opcode, _)| opcode)
+}
+
+/// The opcode of the operator `op`, applied to floats.
+fn float_opcode(op: &str) -> &str {
+    SUGAR
+        .iter()
+        .find(|&&(sugar, _, _)| sugar == op)
+        .map_or(op, |&(_, _, opcode)| opcode)
+}
+
+/// The condition code of the comparison operator `op`, if it is one.
+pub(super) fn comparison(op: &str) -> Option<IntCC> {
+    COMPARISONS
+        .iter()
+        .find(|&&(name, _)| name == op)
+        .map(|&(_, cc)| cc)
+}
+
+/// The condition code named `name` in CLIF, if there is one.
+pub(super) fn condition_code(name: &str) -> Option<IntCC> {
+    CONDITION_CODES
+        .iter()
+        .find(|&&(cc_name, _)| cc_name == name)
+        .map(|&(_, cc)| cc)
+}
+
+/// How the context of an integer literal interprets it, which decides the values it can have
+/// in its type.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum Interpretation {
+    /// As a signed or an unsigned integer, such as the operands of `iadd`.
+    Either,
+    /// As a signed integer, such as the operands of the comparisons.
+    Signed,
+    /// As an unsigned integer, such as the shift amount of the shifts.
+    Unsigned,
+}
+
+impl Interpretation {
+    /// How comparing with `cc` interprets its operands.
+    pub(super) fn of_comparison(cc: IntCC) -> Self {
+        match cc {
+            IntCC::Equal | IntCC::NotEqual => Self::Either,
+            cc if cc.unsigned() == cc => Self::Unsigned,
+            _ => Self::Signed,
+        }
+    }
+}
+
+/// An integer literal, or the name of a constant, which is lowered as a literal of its value.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Literal<'a> {
+    value: i128,
+    /// The name of the constant, for one.
+    constant: Option<&'a str>,
+}
+
+impl fmt::Display for Literal<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.constant {
+            None => write!(f, "literal {}", self.value),
+            Some(name) => write!(f, "constant `{name}`, which is {},", self.value),
+        }
+    }
+}
+
+/// An error lowering a clifp function.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LowerError {
+    /// A type name of the signature which isn't the name of a type.
+    Type(TypeError),
+    /// An operator which is neither a supported opcode nor a special form such as `call`.
+    UnknownOpcode(String),
+    /// A condition code of `icmp` or `icmp_imm` which isn't one of [`CONDITION_CODES`].
+    UnknownConditionCode(String),
+    /// An operator applied to the wrong number of operands.
+    Arity {
+        /// The operator, and the called function for `call`.
+        op: String,
+        /// The number of operands the operator takes.
+        expected: usize,
+        /// The number of operands it was given.
+        found: usize,
+    },
+    /// A name which isn't a parameter, `let` binding or loop variable in scope, or a constant.
+    UndefinedName(String),
+    /// A name bound by a `let` or a `loop`, used outside of it.
+    OutOfScope(String),
+    /// A `let` or a `loop` binding the name of a parameter.
+    RebindParameter(String),
+    /// A call to a function which isn't declared in the module.
+    UndefinedFunction(String),
+    /// A reference to a data object which isn't declared in the module.
+    UndefinedData(String),
+    /// A branch to a block which isn't defined in the function.
+    UndefinedBlock(String),
+    /// An access to a stack slot which isn't declared in the function.
+    UndefinedStackSlot(String),
+    /// A branch passing the wrong number of arguments to a block.
+    BlockArity {
+        /// The name of the block.
+        block: String,
+        /// The number of parameters of the block.
+        expected: usize,
+        /// The number of arguments of the branch.
+        found: usize,
+    },
+    /// A division, named by the operator, whose divisor is the literal or constant zero.
+    DivisionByZero(String),
+    /// Values of the wrong types, described by the message.
+    TypeMismatch(String),
+    /// An expression which isn't well-formed or isn't supported, described by the message.
+    Malformed(String),
+}
+
+impl fmt::Display for LowerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Type(err) => write!(f, "{err}"),
+            Self::UnknownOpcode(op) => write!(f, "unknown opcode `{op}`"),
+            Self::UnknownConditionCode(cc) => {
+                let names: Vec<String> = CONDITION_CODES
+                    .iter()
+                    .map(|(name, _)| format!("`{name}`"))
+                    .collect();
+                write!(
+                    f,
+                    "unknown condition code `{cc}`, expected one of {}",
+                    names.join(", ")
+                )
+            }
+            Self::Arity {
+                op,
+                expected,
+                found,
+            } => {
+                let plural = if *expected == 1 { "" } else { "s" };
+                write!(f, "`{op}` takes {expected} operand{plural}, not {found}")
+            }
+            Self::UndefinedName(name) => write!(f, "undefined name `{name}`"),
+            Self::OutOfScope(name) => {
+                write!(f, "`{name}` is used outside the `let` or `loop` binding it")
+            }
+            Self::RebindParameter(name) => {
+                write!(
+                    f,
+                    "`{name}` is a parameter, which `let` and `loop` can't bind"
+                )
+            }
+            Self::UndefinedFunction(name) => write!(f, "call to undefined function `{name}`"),
+            Self::UndefinedData(name) => write!(f, "reference to undefined data `{name}`"),
+            Self::UndefinedBlock(name) => write!(f, "undefined block `{name}`"),
+            Self::UndefinedStackSlot(name) => write!(f, "undefined stack slot `{name}`"),
+            Self::BlockArity {
+                block,
+                expected,
+                found,
+            } => {
+                let plural = if *expected == 1 { "" } else { "s" };
+                write!(
+                    f,
+                    "block `{block}` takes {expected} argument{plural}, not {found}"
+                )
+            }
+            Self::DivisionByZero(op) => write!(f, "`{op}` by zero, which always traps"),
+            Self::TypeMismatch(message) | Self::Malformed(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for LowerError {}
+
+/// An error compiling a clifp module.
+#[derive(Debug)]
+pub enum CompileError {
+    /// Signatures with unknown types, or functions which couldn't be lowered, each with its
+    /// own error.
+    Diagnostics(Vec<Diagnostic>),
+    /// Declaring or defining a function or data object failed.
+    Module(ModuleError),
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Diagnostics(diagnostics) => {
+                for (i, diagnostic) in diagnostics.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(f)?;
+                    }
+                    write!(f, "{diagnostic}")?;
+                }
+                Ok(())
+            }
+            Self::Module(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+impl From<ModuleError> for CompileError {
+    fn from(err: ModuleError) -> Self {
+        Self::Module(err)
+    }
+}
+
+/// The functions and data objects defined by [`compile`].
+pub struct Compiled {
+    /// The names and ids of the functions, in order.
+    pub functions: Vec<(String, FuncId)>,
+    /// The names and ids of the data objects, in order.
+    pub data: Vec<(String, DataId)>,
+    /// The total size of their code and constant data, in bytes.
+    pub code_bytes: u64,
+}
+
+/// Declare and define the functions and data objects of `module` in `target`.
+///
+/// The functions are exported under their names, and are all declared before any is lowered,
+/// so that they can call each other and themselves, whichever order they're defined in. The
+/// functions which make tail calls and those which are
+/// tail-called use the `tail` calling convention, which tail calls need on both ends, and the
+/// others the default calling convention of `target`. Tail calls also need the
+/// `preserve_frame_pointers` setting in the flags of `target`, without which every function
+/// making one has an error. The host functions are imported under
+/// their names in the default calling convention, so `target` resolves them: a `JITModule` looks
+/// them up among the symbols of its `JITBuilder` when it's finalized. The data objects are
+/// read-only and local to the module, and are defined first. Finalizing or emitting `target` is
+/// left to the caller.
+///
+/// Every signature with an unknown type has an error, and if there are none, every function
+/// which can't be lowered has one, after which no more functions are defined.
+pub fn compile<M: cranelift_module::Module>(
+    target: &mut M,
+    module: &Module,
+) -> Result<Compiled, CompileError> {
+    let mut data = Vec::new();
+    for object in &module.data {
+        let id = target.declare_data(&object.name, Linkage::Local, false, false)?;
+        let mut desc = DataDescription::new();
+        desc.define(object.bytes.clone().into_boxed_slice());
+        target.define_data(id, &desc)?;
+        data.push((object.name.clone(), id));
+    }
+
+    let mut tail = HashSet::new();
+    for func in &module.functions {
+        let callees = tail_callees(func);
+        if !callees.is_empty() {
+            tail.insert(func.name.as_str());
+            tail.extend(callees);
+        }
+    }
+    let call_conv = target.isa().default_call_conv();
+    let mut functions = Vec::new();
+    let mut errors = Vec::new();
+    for func in &module.functions {
+        let call_conv = if tail.contains(func.name.as_str()) {
+            CallConv::Tail
+        } else {
+            call_conv
+        };
+        match func.signature(call_conv) {
+            Ok(sig) => {
+                let id = target.declare_function(&func.name, Linkage::Export, &sig)?;
+                functions.push((func.name.clone(), id));
+            }
+            Err(err) => errors.push(func.type_error(&err)),
+        }
+    }
+    // The host functions are declared after the functions, so that the ids of those follow the
+    // order of the module.
+    for host in &module.externs {
+        match host.signature(call_conv) {
+            Ok(sig) => {
+                target.declare_function(&host.name, Linkage::Import, &sig)?;
+            }
+            Err(err) => errors.push(host.type_error(&err)),
+        }
+    }
+    // Functions calling those which couldn't be declared would have errors of their own.
+    if !errors.is_empty() {
+        errors.sort_by_key(|error| error.span.start);
+        return Err(CompileError::Diagnostics(errors));
+    }
+
+    let mut ctx = target.make_context();
+    let mut code_bytes = 0;
+    // Tail calls on x86_64 rely on frame pointers, and the backend panics without them.
+    let frame_pointers = target.isa().flags().preserve_frame_pointers();
+    for (func, &(_, id)) in module.functions.iter().zip(&functions) {
+        match lower(func, &module.constants, target) {
+            Ok(_) if !frame_pointers && first_tail_call(&func.body).is_some() => {
+                errors.push(frame_pointer_error(func));
+            }
+            Ok(ir_func) if errors.is_empty() => {
+                ctx.func = ir_func;
+                code_bytes += u64::from(target.define_function(id, &mut ctx)?.size);
+                target.clear_context(&mut ctx);
+            }
+            Ok(_) => {}
+            Err(err) => errors.push(err),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(CompileError::Diagnostics(errors));
+    }
+
+    Ok(Compiled {
+        functions,
+        data,
+        code_bytes,
+    })
+}
+
+/// Declare and define a function computing the expression of each test of `module` in `target`,
+/// after [`compile`] defined the functions and data objects the tests use. Returns the name, the
+/// id and the result type of each test function, in order.
+///
+/// A test function takes no parameters and returns the value of the test's expression, an `i32`
+/// or an `i64`, in the default calling convention of `target`. It's anonymous, so it doesn't
+/// clash with the functions of the module.
+///
+/// Every test which can't be lowered, or whose expected value doesn't fit in the type of its
+/// expression, has an error, after which no more tests are defined. The tests expecting a trap
+/// are defined like the others, but calling them traps, which in a `JITModule` is a signal that
+/// kills the process; [`Module::to_ir`] lowers them to run in `cranelift-interpreter` instead.
+pub fn compile_tests<M: cranelift_module::Module>(
+    target: &mut M,
+    module: &Module,
+) -> Result<Vec<(String, FuncId, Type)>, CompileError> {
+    let mut ctx = target.make_context();
+    let mut tests = Vec::new();
+    let mut errors = Vec::new();
+    for test in &module.tests {
+        match lower_test(test, &module.constants, target) {
+            Ok((ir_func, ty)) if errors.is_empty() => {
+                let id = target.declare_anonymous_function(&ir_func.signature)?;
+                ctx.func = ir_func;
+                ctx.func.name = UserFuncName::user(0, id.as_u32());
+                target.define_function(id, &mut ctx)?;
+                target.clear_context(&mut ctx);
+                tests.push((test.name.clone(), id, ty));
+            }
+            Ok(_) => {}
+            Err(err) => errors.push(err),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(CompileError::Diagnostics(errors));
+    }
+    Ok(tests)
+}
+
+impl Module {
+    /// Lower the functions of the module to CLIF text, which `cranelift-reader` parses, for
+    /// `clif-util` and the filetests.
+    ///
+    /// The functions are declared as [`compile`] declares them, with the calling conventions of
+    /// the host, so the function defined `n`th is named `u0:n`, the host functions are numbered
+    /// after them, and the data objects are named `u1:n` in the same way. Each function is
+    /// preceded by a comment with its clifp name. The bytes of the data objects aren't part of
+    /// the text.
+    ///
+    /// ```
+    /// let src = "(func inc ((x i32)) (i32) (iadd x 1))";
+    /// let module = cranelift_clifp::Module::parse(src).unwrap();
+    /// let clif = module.to_clif().unwrap();
+    /// assert!(clif.starts_with("; inc\nfunction u0:0(i32) -> i32"));
+    /// assert!(clif.contains("iadd"));
+    /// ```
+    pub fn to_clif(&self) -> Result<String, CompileError> {
+        let mut target = ClifModule::for_host()?;
+        let compiled = compile(&mut target, self)?;
+
+        let mut clif = String::new();
+        for ((name, _), func) in compiled.functions.iter().zip(&target.functions) {
+            if !clif.is_empty() {
+                clif.push('\n');
+            }
+            clif.push_str(&format!("; {name}\n"));
+            cranelift_codegen::write_function(&mut clif, func)
+                .expect("writing to a string can't fail");
+        }
+        Ok(clif)
+    }
+
+    /// Lower the functions of the module, and then its tests, to Cranelift IR, as [`compile`]
+    /// and [`compile_tests`] lower them, for `cranelift-interpreter`, where a trap is a result
+    /// of the call instead of a signal.
+    ///
+    /// The functions are named as in [`Module::to_clif`], and each test is named `u0:n` after
+    /// its declaration, which follows those of the functions and the host functions, so that a
+    /// `FunctionStore` of the functions under their names resolves the calls between them. The
+    /// interpreter has neither the host functions nor the bytes of the data objects.
+    pub fn to_ir(&self) -> Result<(Vec<ir::Function>, Vec<ir::Function>), CompileError> {
+        let mut target = ClifModule::for_host()?;
+        compile(&mut target, self)?;
+        let count = target.functions.len();
+        compile_tests(&mut target, self)?;
+        let tests = target.functions.split_off(count);
+        Ok((target.functions, tests))
+    }
+}
+
+/// A module which only declares, and keeps the IR of the functions defined in it instead of
+/// compiling them, for [`Module::to_clif`] and [`Module::to_ir`].
+struct ClifModule {
+    isa: OwnedTargetIsa,
+    declarations: ModuleDeclarations,
+    /// The defined functions, in order.
+    functions: Vec<ir::Function>,
+}
+
+impl ClifModule {
+    /// An empty module for the host ISA, with frame pointers so that tail calls can be
+    /// lowered.
+    fn for_host() -> Result<Self, CompileError> {
+        let mut flags = settings::builder();
+        flags.set("preserve_frame_pointers", "true").unwrap();
+        let isa = cranelift_native::builder()
+            .and_then(|builder| {
+                builder
+                    .finish(settings::Flags::new(flags))
+                    .map_err(|_| "the host ISA can't be configured")
+            })
+            .map_err(|msg| {
+                ModuleError::Backend(anyhow::anyhow!("host machine is not supported: {msg}"))
+            })?;
+        Ok(Self {
+            isa,
+            declarations: ModuleDeclarations::default(),
+            functions: Vec::new(),
+        })
+    }
+}
+
+impl cranelift_module::Module for ClifModule {
+    fn isa(&self) -> &dyn TargetIsa {
+        &*self.isa
+    }
+
+    fn declarations(&self) -> &ModuleDeclarations {
+        &self.declarations
+    }
+
+    fn declare_function(
+        &mut self,
+        name: &str,
+        linkage: Linkage,
+        signature: &ir::Signature,
+    ) -> ModuleResult<FuncId> {
+        let (id, _linkage) = self
+            .declarations
+            .declare_function(name, linkage, signature)?;
+        Ok(id)
+    }
+
+    fn declare_anonymous_function(&mut self, signature: &ir::Signature) -> ModuleResult<FuncId> {
+        self.declarations.declare_anonymous_function(signature)
+    }
+
+    fn declare_data(
+        &mut self,
+        name: &str,
+        linkage: Linkage,
+        writable: bool,
+        tls: bool,
+    ) -> ModuleResult<DataId> {
+        let (id, _linkage) = self
+            .declarations
+            .declare_data(name, linkage, writable, tls)?;
+        Ok(id)
+    }
+
+    fn declare_anonymous_data(&mut self, writable: bool, tls: bool) -> ModuleResult<DataId> {
+        self.declarations.declare_anonymous_data(writable, tls)
+    }
+
+    fn define_function_with_control_plane(
+        &mut self,
+        _func: FuncId,
+        ctx: &mut Context,
+        _ctrl_plane: &mut ControlPlane,
+    ) -> ModuleResult<ModuleCompiledFunction> {
+        self.functions.push(ctx.func.clone());
+        Ok(ModuleCompiledFunction { size: 0 })
+    }
+
+    fn define_function_bytes(
+        &mut self,
+        func_id: FuncId,
+        _func: &ir::Function,
+        _alignment: u64,
+        _bytes: &[u8],
+        _relocs: &[MachReloc],
+    ) -> ModuleResult<ModuleCompiledFunction> {
+        let name = &self.declarations.get_function_decl(func_id).name;
+        Err(ModuleError::Backend(anyhow::anyhow!(
+            "the code of {} has no CLIF text",
+            name.as_deref().unwrap_or("an anonymous function")
+        )))
+    }
+
+    fn define_data(&mut self, _data_id: DataId, _data: &DataDescription) -> ModuleResult<()> {
+        Ok(())
+    }
+}
+
+/// Lower `func` to Cranelift IR, with the `constants` of its module.
+///
+/// The functions `func` calls and the data objects it refers to must be declared in `module`. If
+/// `func` is itself declared, the IR function is named after its id, so that it can be defined
+/// right away, and has the calling convention of the declaration. Otherwise it has the `tail`
+/// calling convention if it makes tail calls, and the default calling convention of `module` if
+/// it doesn't.
+///
+/// An error is at the innermost expression or form which couldn't be lowered, or at the type
+/// name or the name of the function it's about.
+///
+/// ```
+/// use cranelift_jit::{JITBuilder, JITModule};
+/// use cranelift_module::default_libcall_names;
+///
+/// let module = cranelift_clifp::Module::parse("(func double ((x i64)) (i64) (* x 2))").unwrap();
+/// let mut jit = JITModule::new(JITBuilder::new(default_libcall_names()).unwrap());
+/// let func = cranelift_clifp::lower(&module.functions[0], &module.constants, &mut jit).unwrap();
+/// assert_eq!(func.signature.params.len(), 1);
+/// assert_eq!(func.signature.returns.len(), 1);
+/// ```
+pub fn lower(
+    func: &Function,
+    constants: &[Constant],
+    module: &mut dyn cranelift_module::Module,
+) -> Result<ir::Function, Diagnostic> {
+    let mut at = None;
+    lower_function(func, constants, module, &mut at).map_err(|error| match error {
+        LowerError::Type(err) => func.type_error(&err),
+        error => Diagnostic::error(
+            at.unwrap_or(func.spans.item(1).span),
+            format!("in function `{}`: {error}", func.name),
+        ),
+    })
+}
+
+/// Lower `func` as [`lower`] does, setting `at` to the span of the source text an error is
+/// about, if there is one other than the name of the function.
+fn lower_function(
+    func: &Function,
+    constants: &[Constant],
+    module: &mut dyn cranelift_module::Module,
+    at: &mut Option<Span>,
+) -> Result<ir::Function, LowerError> {
+    // The body follows `func`, the name, the parameters and the results.
+    let body_spans = func.spans.items.get(4..).unwrap_or(&[]);
+    let mut fail_at = |form: &Sexp, error: LowerError| {
+        *at = find_span(&func.body, body_spans, form);
+        error
+    };
+
+    let declared = match module.get_name(&func.name) {
+        Some(FuncOrDataId::Func(id)) => Some(id),
+        _ => None,
+    };
+    let call_conv = match declared {
+        Some(id) => {
+            module
+                .declarations()
+                .get_function_decl(id)
+                .signature
+                .call_conv
+        }
+        None if !tail_callees(func).is_empty() => CallConv::Tail,
+        None => module.isa().default_call_conv(),
+    };
+    let sig = func.signature(call_conv).map_err(LowerError::Type)?;
+    let returns: Vec<Type> = sig.returns.iter().map(|ret| ret.value_type).collect();
+
+    let name = match declared {
+        Some(id) => UserFuncName::user(0, id.as_u32()),
+        None => UserFuncName::testcase(&func.name),
+    };
+    let mut ir_func = ir::Function::with_name_signature(name, sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    let mut builder = FunctionBuilder::new(&mut ir_func, &mut func_ctx);
+    let entry = builder.create_block();
+    builder.append_block_params_for_function_params(entry);
+    let params: HashMap<_, _> = func
+        .params
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .zip(builder.block_params(entry).iter().copied())
+        .collect();
+    // The parameters shadow the constants of the same names in the whole function.
+    let constants: HashMap<_, _> = constants
+        .iter()
+        .map(|constant| (constant.name.as_str(), constant.value))
+        .filter(|(name, _)| !params.contains_key(name))
+        .collect();
+
+    // The body starts with the declarations of the stack slots, which every block can access.
+    let first_expr = func
+        .body
+        .iter()
+        .position(|form| head(form) != Some("stack"))
+        .unwrap_or(func.body.len());
+    let (stack_forms, body) = func.body.split_at(first_expr);
+    let mut slots = HashMap::new();
+    for form in stack_forms {
+        let (name, size) = stack_slot(form, &constants).map_err(|e| fail_at(form, e))?;
+        let slot = builder.create_sized_stack_slot(ir::StackSlotData::new(
+            ir::StackSlotKind::ExplicitSlot,
+            size,
+        ));
+        if slots.insert(name, (slot, size)).is_some() {
+            return Err(fail_at(
+                form,
+                LowerError::Malformed(format!("stack slot `{name}` is declared twice")),
+            ));
+        }
+    }
+
+    // The expressions before the first block are those of the entry block. All the blocks are
+    // created before any is lowered, so that branches can go to blocks defined later.
+    let first_block = body
+        .iter()
+        .position(|form| head(form) == Some("block"))
+        .unwrap_or(body.len());
+    let (entry_body, block_forms) = body.split_at(first_block);
+    let mut blocks = HashMap::new();
+    let mut defs = Vec::new();
+    for form in block_forms {
+        if head(form) != Some("block") {
+            return Err(fail_at(
+                form,
+                LowerError::Malformed(format!(
+                    "`{form}` follows the blocks, but the expressions of a block go inside it"
+                )),
+            ));
+        }
+        let def = declare_block(&mut builder, form).map_err(|e| fail_at(form, e))?;
+        if blocks.insert(def.name, def.block).is_some() {
+            return Err(fail_at(
+                form,
+                LowerError::Malformed(format!("block `{}` is defined twice", def.name)),
+            ));
+        }
+        defs.push(def);
+    }
+
+    let mut bound = HashSet::new();
+    bound_names(&func.body, &mut bound);
+    let mut lowerer = Lowerer {
+        builder,
+        module,
+        vars: params.clone(),
+        params: params.keys().copied().collect(),
+        bound,
+        constants,
+        loops: Vec::new(),
+        blocks,
+        slots,
+        callees: HashMap::new(),
+        globals: HashMap::new(),
+        returns,
+        failed: None,
+    };
+    if let Err(error) = lowerer.blocks(entry, entry_body, defs, &params) {
+        return Err(match lowerer.failed {
+            Some(form) => fail_at(form, error),
+            None => error,
+        });
+    }
+    lowerer.builder.seal_all_blocks();
+    lowerer.builder.finalize();
+    Ok(ir_func)
+}
+
+/// Lower the expression of `test` to a function which returns its value, and the type of the
+/// value, for [`compile_tests`].
+///
+/// An error is at the innermost expression which couldn't be lowered, at the expected value if
+/// it doesn't fit, or at the name of the test.
+fn lower_test(
+    test: &Test,
+    constants: &[Constant],
+    module: &mut dyn cranelift_module::Module,
+) -> Result<(ir::Function, Type), Diagnostic> {
+    // The expression follows `test`, the name and the expected value.
+    let expr_spans = std::slice::from_ref(test.spans.item(3));
+    let in_test = |span: Option<Span>, error: LowerError| {
+        Diagnostic::error(
+            span.unwrap_or(test.spans.item(1).span),
+            format!("in test `{}`: {error}", test.name),
+        )
+    };
+
+    let sig = ir::Signature::new(module.isa().default_call_conv());
+    let mut ir_func = ir::Function::with_name_signature(UserFuncName::testcase(&test.name), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    let mut builder = FunctionBuilder::new(&mut ir_func, &mut func_ctx);
+    let entry = builder.create_block();
+    builder.switch_to_block(entry);
+    let mut bound = HashSet::new();
+    bound_names(std::slice::from_ref(&test.expr), &mut bound);
+    let mut lowerer = Lowerer {
+        builder,
+        module,
+        vars: HashMap::new(),
+        params: HashSet::new(),
+        bound,
+        constants: constants
+            .iter()
+            .map(|constant| (constant.name.as_str(), constant.value))
+            .collect(),
+        loops: Vec::new(),
+        blocks: HashMap::new(),
+        slots: HashMap::new(),
+        callees: HashMap::new(),
+        globals: HashMap::new(),
+        returns: Vec::new(),
+        failed: None,
+    };
+    let value = lowerer.expr(&test.expr, None).map_err(|error| {
+        let at = lowerer
+            .failed
+            .and_then(|form| find_span(std::slice::from_ref(&test.expr), expr_spans, form));
+        in_test(at, error)
+    })?;
+    let ty = lowerer.value_type(value);
+    if ty != types::I32 && ty != types::I64 {
+        return Err(in_test(
+            Some(expr_spans[0].span),
+            LowerError::TypeMismatch(format!(
+                "`{}` has type {ty}, but tests compare values of type i32 or i64",
+                test.expr
+            )),
+        ));
+    }
+    // A test expecting a trap has no value to check.
+    if let Expected::Value(value) = test.expected {
+        let expected = Literal {
+            value,
+            constant: None,
+        };
+        check_literal(expected, ty, Interpretation::Either)
+            .map_err(|error| in_test(Some(test.spans.item(2).item(1).span), error))?;
+    }
+    lowerer.builder.ins().return_(&[value]);
+    lowerer.builder.seal_all_blocks();
+    lowerer.builder.finalize();
+    ir_func.signature.returns.push(ir::AbiParam::new(ty));
+    Ok((ir_func, ty))
+}
+
+/// Whether each function of `module`, and then each of its tests, lowers as [`compile`] and
+/// [`compile_tests`] lower them for the host, for [`optimize`](super::optimize), which leaves
+/// the others as they are. Nothing lowers if the host isn't supported.
+pub(super) fn lowers(module: &Module) -> (Vec<bool>, Vec<bool>) {
+    let mut target = match ClifModule::for_host() {
+        Ok(target) => target,
+        Err(_) => {
+            return (
+                vec![false; module.functions.len()],
+                vec![false; module.tests.len()],
+            )
+        }
+    };
+    // Compiling declares the functions and data objects, as far as their declarations go,
+    // and its errors are found again one function at a time.
+    let _ = compile(&mut target, module);
+    let functions = module
+        .functions
+        .iter()
+        .map(|func| lower(func, &module.constants, &mut target).is_ok())
+        .collect();
+    let tests = module
+        .tests
+        .iter()
+        .map(|test| lower_test(test, &module.constants, &mut target).is_ok())
+        .collect();
+    (functions, tests)
+}
+
+/// Add the names bound by the `let`s and `loop`s of `forms` to `names`.
+pub(super) fn bound_names<'a>(forms: &'a [Sexp], names: &mut HashSet<&'a str>) {
+    for form in forms {
+        if let Sexp::List(items) = form {
+            if let (Some("let" | "loop"), Some(Sexp::List(bindings))) = (head(form), items.get(1)) {
+                // The name of a binding `(name expr)` is its head, and the names of a binding
+                // `((name...) expr)` are those of its head.
+                for binding in bindings {
+                    match binding {
+                        Sexp::List(binding) => match binding.first() {
+                            Some(Sexp::Ident(name)) => {
+                                names.insert(name);
+                            }
+                            Some(Sexp::List(bound)) => names.extend(bound.iter().filter_map(ident)),
+                            _ => {}
+                        },
+                        _ => {}
+                    }
+                }
+            }
+            bound_names(items, names);
+        }
+    }
+}
+
+/// The names of the functions which `func` tail-calls.
+fn tail_callees(func: &Function) -> HashSet<&str> {
+    fn visit<'a>(forms: &'a [Sexp], names: &mut HashSet<&'a str>) {
+        for form in forms {
+            if let Sexp::List(items) = form {
+                if let (Some("tail_call"), Some(Sexp::Ident(callee))) = (head(form), items.get(1)) {
+                    names.insert(callee);
+                }
+                visit(items, names);
+            }
+        }
+    }
+    let mut names = HashSet::new();
+    visit(&func.body, &mut names);
+    names
+}
+
+/// The error of `func`, which makes tail calls, when the frame pointers they need aren't
+/// preserved.
+fn frame_pointer_error(func: &Function) -> Diagnostic {
+    let body_spans = func.spans.items.get(4..).unwrap_or(&[]);
+    let span = first_tail_call(&func.body).and_then(|form| find_span(&func.body, body_spans, form));
+    Diagnostic::error(
+        span.unwrap_or(func.spans.item(1).span),
+        format!(
+            "in function `{}`: `tail_call` needs the `preserve_frame_pointers` setting",
+            func.name
+        ),
+    )
+}
+
+/// The first `tail_call` form of `forms` or inside them, in source order.
+pub(super) fn first_tail_call(forms: &[Sexp]) -> Option<&Sexp> {
+    forms.iter().find_map(|form| match form {
+        Sexp::List(_) if head(form) == Some("tail_call") => Some(form),
+        Sexp::List(items) => first_tail_call(items),
+        _ => None,
+    })
+}
+
+/// The span of `target`, which is one of `forms` or inside one of them, if `spans` are those of
+/// `forms`.
+///
+/// `target` is found by its address, since the lowering only borrows the forms.
+fn find_span(forms: &[Sexp], spans: &[SpanTree], target: &Sexp) -> Option<Span> {
+    forms.iter().zip(spans).find_map(|(form, spans)| {
+        if std::ptr::eq(form, target) {
+            return Some(spans.span);
+        }
+        match form {
+            Sexp::List(items) => find_span(items, &spans.items, target),
+            _ => None,
+        }
+    })
+}
+
+/// The name `form` is, if it's one.
+fn ident(form: &Sexp) -> Option<&str> {
+    match form {
+        Sexp::Ident(name) => Some(name),
+        _ => None,
+    }
+}
+
+/// The operator of `form`, if it's the application of one.
+pub(super) fn head(form: &Sexp) -> Option<&str> {
+    match form {
+        Sexp::List(items) => match items.first() {
+            Some(Sexp::Ident(op)) => Some(op),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The name and the size in bytes of the stack slot declared by `(stack name size)`, whose size
+/// is an integer literal or one of `constants`.
+fn stack_slot<'a>(
+    form: &'a Sexp,
+    constants: &HashMap<&str, i128>,
+) -> Result<(&'a str, u32), LowerError> {
+    let (name, size) = match form {
+        Sexp::List(items) => match &items[..] {
+            [_, Sexp::Ident(name), Sexp::Int(size)] => (name, *size),
+            [_, Sexp::Ident(name), Sexp::Ident(size)] if constants.contains_key(size.as_str()) => {
+                (name, constants[size.as_str()])
+            }
+            _ => {
+                return Err(LowerError::Malformed(format!(
+                    "expected `(stack name size)`, found `{form}`"
+                )))
+            }
+        },
+        _ => unreachable!("stack slots are declared by lists"),
+    };
+    let size = u32::try_from(size).map_err(|_| {
+        LowerError::Malformed(format!("the size {size} of stack slot `{name}` is invalid"))
+    })?;
+    Ok((name, size))
+}
+
+/// A block definition `(block name ((param type) ...) body ...)`.
+struct BlockDef<'a> {
+    name: &'a str,
+    block: ir::Block,
+    /// The names of the parameters.
+    params: Vec<&'a str>,
+    body: &'a [Sexp],
+}
+
+/// Create the block defined by `form`, with its parameters.
+fn declare_block<'a>(
+    builder: &mut FunctionBuilder,
+    form: &'a Sexp,
+) -> Result<BlockDef<'a>, LowerError> {
+    let malformed = || {
+        LowerError::Malformed(format!(
+            "expected `(block name ((param type)...) body...)`, found `{form}`"
+        ))
+    };
+    let (name, params, body) = match form {
+        Sexp::List(items) => match &items[..] {
+            [_, Sexp::Ident(name), Sexp::List(params), body @ ..] => (name, params, body),
+            _ => return Err(malformed()),
+        },
+        _ => return Err(malformed()),
+    };
+
+    let block = builder.create_block();
+    let mut names = Vec::new();
+    for param in params {
+        let (param, ty) = match param {
+            Sexp::List(param) => match &param[..] {
+                [Sexp::Ident(param), Sexp::Ident(ty)] => (param.as_str(), ty),
+                _ => return Err(malformed()),
+            },
+            _ => return Err(malformed()),
+        };
+        if names.contains(&param) {
+            return Err(LowerError::Malformed(format!(
+                "parameter `{param}` of block `{name}` is declared twice"
+            )));
+        }
+        let ty = resolve_type(ty).ok_or_else(|| {
+            LowerError::Malformed(format!(
+                "unknown type `{ty}` of parameter `{param}` of block `{name}`"
+            ))
+        })?;
+        builder.append_block_param(block, ty);
+        names.push(param);
+    }
+    Ok(BlockDef {
+        name,
+        block,
+        params: names,
+        body,
+    })
+}
+
+/// The state of [`lower`] while it builds a function.
+struct Lowerer<'a> {
+    builder: FunctionBuilder<'a>,
+    module: &'a mut dyn cranelift_module::Module,
+    /// The value of each parameter, `let` binding and loop variable in scope.
+    vars: HashMap<&'a str, Value>,
+    /// The names of the parameters in scope, of the function and of the current block, which a
+    /// `let` can't bind.
+    params: HashSet<&'a str>,
+    /// The names bound by the `let`s and `loop`s of the function, whether they're in scope or
+    /// not.
+    bound: HashSet<&'a str>,
+    /// The value of each constant of the module, except those the parameters of the function
+    /// shadow.
+    constants: HashMap<&'a str, i128>,
+    /// The loops around the current expression, innermost last.
+    loops: Vec<Loop<'a>>,
+    /// The block of each block name.
+    blocks: HashMap<&'a str, ir::Block>,
+    /// The stack slot and its size in bytes of each stack slot name.
+    slots: HashMap<&'a str, (ir::StackSlot, u32)>,
+    /// The references to the functions called so far.
+    callees: HashMap<FuncId, ir::FuncRef>,
+    /// The global values of the data objects referred to so far.
+    globals: HashMap<DataId, ir::GlobalValue>,
+    /// The result types of the function.
+    returns: Vec<Type>,
+    /// The innermost expression or form which couldn't be lowered, once one couldn't.
+    failed: Option<&'a Sexp>,
+}
+
+/// A loop or a `while` being lowered.
+struct Loop<'a> {
+    /// The block which `continue` jumps back to, where the body of a loop starts and a `while`
+    /// tests its condition.
+    header: ir::Block,
+    /// The names and types of the variables, which are the parameters of the header. A `while`
+    /// has none.
+    vars: Vec<(&'a str, Type)>,
+}
+
+impl<'a> Lowerer<'a> {
+    fn value_type(&self, value: Value) -> Type {
+        self.builder.func.dfg.value_type(value)
+    }
+
+    /// `result`, the result of lowering `form`. If it's the first error, `form` is where it is,
+    /// since the forms inside `form` are lowered, and fail, first.
+    fn locate<T>(
+        &mut self,
+        form: &'a Sexp,
+        result: Result<T, LowerError>,
+    ) -> Result<T, LowerError> {
+        if result.is_err() && self.failed.is_none() {
+            self.failed = Some(form);
+        }
+        result
+    }
+
+    /// Lower the expressions of the entry block, then those of each block of `defs`.
+    fn blocks(
+        &mut self,
+        entry: ir::Block,
+        entry_body: &'a [Sexp],
+        defs: Vec<BlockDef<'a>>,
+        params: &HashMap<&'a str, Value>,
+    ) -> Result<(), LowerError> {
+        self.builder.switch_to_block(entry);
+        self.body("the body", entry_body)?;
+        for def in defs {
+            // The parameters of the function are in scope in every block, since the entry block
+            // dominates them all, unless a parameter of the block has the same name.
+            self.vars = params.clone();
+            let values = self.builder.block_params(def.block).to_vec();
+            self.vars.extend(def.params.iter().copied().zip(values));
+            self.params = params.keys().chain(&def.params).copied().collect();
+            self.builder.switch_to_block(def.block);
+            self.body(&format!("block `{}`", def.name), def.body)?;
+        }
+        Ok(())
+    }
+
+    /// The function `name` refers to, if it's a declared function.
+    fn function(&self, name: &str) -> Option<FuncId> {
+        match self.module.get_name(name) {
+            Some(FuncOrDataId::Func(id)) => Some(id),
+            _ => None,
+        }
+    }
+
+    /// The data object `name` refers to, if it's a declared data object.
+    fn data(&self, name: &str) -> Option<DataId> {
+        match self.module.get_name(name) {
+            Some(FuncOrDataId::Data(id)) => Some(id),
+            _ => None,
+        }
+    }
+
+    fn pointer_type(&self) -> Type {
+        self.module.isa().pointer_type()
+    }
+
+    /// The type of `expr`, if it can be told without the context it's used in.
+    ///
+    /// Integer literals take their type from their context: the other operands of their
+    /// operator, or the type of the result they compute. Those which can't be inferred have
+    /// type `i64`.
+    fn infer(&self, expr: &Sexp) -> Option<Type> {
+        let (op, operands) = match expr {
+            Sexp::Ident(name) => {
+                return self
+                    .vars
+                    .get(name.as_str())
+                    .map(|&value| self.value_type(value))
+            }
+            Sexp::List(items) => match items.split_first() {
+                Some((Sexp::Ident(op), operands)) => (op.as_str(), operands),
+                _ => return None,
+            },
+            Sexp::Int(_) | Sexp::Float(_) | Sexp::Str(_) => return None,
+        };
+        match op {
+            "call" => {
+                let id = match operands.first() {
+                    Some(Sexp::Ident(name)) => self.function(name)?,
+                    _ => return None,
+                };
+                match self
+                    .module
+                    .declarations()
+                    .get_function_decl(id)
+                    .signature
+                    .returns[..]
+                {
+                    [ret] => Some(ret.value_type),
+                    _ => None,
+                }
+            }
+            "if" | "select" => operands.iter().skip(1).find_map(|expr| self.infer(expr)),
+            // A `return` leaves the function, so it has no value of its own.
+            "return" => None,
+            // The names in the body of a `let` or a `loop` may be bound by it, so they aren't in
+            // scope yet.
+            "let" | "loop" => None,
+            // A `while` ends when its condition is zero, which is its value.
+            "while" => operands.first().and_then(|cond| self.infer(cond)),
+            "continue" | "tail_call" => None,
+            "symbol" => Some(self.pointer_type()),
+            _ if resolve_type(op).is_some() => resolve_type(op),
+            "load" | "stack_load" | "stack_store" => match operands.first() {
+                Some(Sexp::Ident(ty)) => resolve_type(ty),
+                _ => None,
+            },
+            _ if CONVERSIONS.contains(&op) => match operands.first() {
+                Some(Sexp::Ident(ty)) => resolve_type(ty),
+                _ => None,
+            },
+            // Comparisons of scalars have type `i8`, and those of vectors the type of the vectors.
+            "icmp" | "icmp_imm" => {
+                match operands.iter().skip(1).find_map(|expr| self.infer(expr)) {
+                    Some(ty) if ty.is_vector() => Some(ty),
+                    _ => Some(types::I8),
+                }
+            }
+            _ if comparison(op).is_some() => {
+                match operands.iter().find_map(|expr| self.infer(expr)) {
+                    Some(ty) if ty.is_vector() => Some(ty),
+                    _ => Some(types::I8),
+                }
+            }
+            _ if BINARY.contains(&opcode(op)) || DIVISIONS.contains(&op) => {
+                operands.iter().find_map(|expr| self.infer(expr))
+            }
+            _ => operands.first().and_then(|expr| self.infer(expr)),
+        }
+    }
+
+    /// Lower the expressions `exprs` of the current block, described by `what`.
+    ///
+    /// The block ends with a branch or a `return` if the last expression is one, or is an `if`
+    /// or a `let` which returns, and otherwise returns the value of the last expression. A
+    /// function with several results can only return them with `return`.
+    fn body(&mut self, what: &str, exprs: &'a [Sexp]) -> Result<(), LowerError> {
+        let ret = match self.returns[..] {
+            [ret] => Some(ret),
+            _ => None,
+        };
+        let mut result = None;
+        for (i, expr) in exprs.iter().enumerate() {
+            if i + 1 < exprs.len() {
+                result = Some(self.expr(expr, None)?);
+                continue;
+            }
+            result = match (head(expr), expr) {
+                (Some(op @ ("brif" | "jump")), Sexp::List(items)) => {
+                    let result = self.branch(op, &items[1..]);
+                    return self.locate(expr, result);
+                }
+                // The value of the last expression is the result, unless it returns itself.
+                _ => match self.arm(expr, ret)? {
+                    Some(value) => Some(value),
+                    None => return Ok(()),
+                },
+            };
+        }
+        let returns = describe_types(&self.returns);
+        match (&self.returns[..], result) {
+            ([], _) => {
+                self.builder.ins().return_(&[]);
+            }
+            (_, Some(result)) => {
+                let ty = self.value_type(result);
+                if ret != Some(ty) {
+                    return Err(LowerError::TypeMismatch(format!(
+                        "{what} has type {ty}, not the result type {returns}"
+                    )));
+                }
+                self.builder.ins().return_(&[result]);
+            }
+            (_, None) => {
+                return Err(LowerError::TypeMismatch(format!(
+                    "{what} is empty, but the result type is {returns}"
+                )))
+            }
+        }
+        Ok(())
+    }
+
+    /// Lower `expr`, whose type is `hint` if it can't be inferred.
+    fn expr(&mut self, expr: &'a Sexp, hint: Option<Type>) -> Result<Value, LowerError> {
+        let result = self.lower_expr(expr, hint);
+        self.locate(expr, result)
+    }
+
+    fn lower_expr(&mut self, expr: &'a Sexp, hint: Option<Type>) -> Result<Value, LowerError> {
+        match expr {
+            Sexp::Int(_) => self.operand(expr, hint, Interpretation::Either),
+            Sexp::Float(value) => {
+                let ty = hint.unwrap_or(types::F64);
+                match ty {
+                    // The literal is rounded to the nearest `f32`.
+                    types::F32 => Ok(self.builder.ins().f32const(*value as f32)),
+                    types::F64 => Ok(self.builder.ins().f64const(*value)),
+                    _ => Err(LowerError::TypeMismatch(format!(
+                        "floating-point literals can't have type {ty}"
+                    ))),
+                }
+            }
+            Sexp::Str(_) => Err(LowerError::Malformed(format!(
+                "string literals are only the paths of `include`, not values like `{expr}`"
+            ))),
+            Sexp::Ident(name) => match self.vars.get(name.as_str()) {
+                Some(&value) => Ok(value),
+                None if self.constants.contains_key(name.as_str()) => {
+                    self.operand(expr, hint, Interpretation::Either)
+                }
+                None if self.bound.contains(name.as_str()) => {
+                    Err(LowerError::OutOfScope(name.clone()))
+                }
+                None => Err(LowerError::UndefinedName(name.clone())),
+            },
+            Sexp::List(items) => match items.split_first() {
+                Some((Sexp::Ident(op), operands)) => match op.as_str() {
+                    "call" => self.call(operands),
+                    "if" => self.if_(operands, hint)?.ok_or_else(|| {
+                        LowerError::Malformed(
+                            "an `if` whose branches both return has no value, so it can only be \
+                             the last expression of a block"
+                                .to_string(),
+                        )
+                    }),
+                    "let" => self.let_(operands, hint)?.ok_or_else(|| {
+                        LowerError::Malformed(
+                            "a `let` whose body returns has no value, so it can only be the last \
+                             expression of a block"
+                                .to_string(),
+                        )
+                    }),
+                    "symbol" => self.symbol(operands),
+                    op if resolve_type(op).is_some() => self.typed_literal(op, operands),
+                    "load" => self.load(operands),
+                    "stack_load" => self.stack_load(operands),
+                    "stack_store" => self.stack_store(operands),
+                    op if CONVERSIONS.contains(&op) => self.convert(op, operands),
+                    "icmp" | "icmp_imm" => self.icmp(op, operands),
+                    "select" => self.select(operands, hint),
+                    "brif" | "jump" => Err(LowerError::Malformed(format!(
+                        "`{op}` can only be the last expression of a block"
+                    ))),
+                    "loop" => self.loop_(operands, hint)?.ok_or_else(|| {
+                        LowerError::Malformed(
+                            "a `loop` whose body always continues or returns has no value, so it \
+                             can only be the last expression of a block"
+                                .to_string(),
+                        )
+                    }),
+                    "while" => self.while_(operands),
+                    "return" | "tail_call" => Err(LowerError::Malformed(format!(
+                        "`{op}` can only be the last expression of a block, of a branch of `if` \
+                         or of the body of a `let`, a `loop` or a `while`"
+                    ))),
+                    "continue" if self.loops.is_empty() => Err(continue_outside_loop()),
+                    "continue" => Err(LowerError::Malformed(
+                        "`continue` can only be the last expression of the body of a `loop` or a \
+                         `while`, or of a branch of `if` or a `let` there"
+                            .to_string(),
+                    )),
+                    "block" => Err(LowerError::Malformed(
+                        "blocks can only be defined at the end of the body of a function"
+                            .to_string(),
+                    )),
+                    "stack" => Err(LowerError::Malformed(
+                        "stack slots can only be declared at the start of the body of a function"
+                            .to_string(),
+                    )),
+                    op => {
+                        let result = self.op(op, operands, hint);
+                        // An unknown opcode is the fault of the operator alone.
+                        if let Err(LowerError::UnknownOpcode(_)) = result {
+                            return self.locate(&items[0], result);
+                        }
+                        result
+                    }
+                },
+                Some((op, _)) => Err(LowerError::Malformed(format!(
+                    "expected an operator, found `{op}`"
+                ))),
+                None => Err(LowerError::Malformed(
+                    "expected an expression, found `()`".to_string(),
+                )),
+            },
+        }
+    }
+
+    /// The integer literal `expr` is, or the constant it names if no variable in scope shadows
+    /// it.
+    fn literal<'e>(&self, expr: &'e Sexp) -> Option<Literal<'e>> {
+        match expr {
+            Sexp::Int(value) => Some(Literal {
+                value: *value,
+                constant: None,
+            }),
+            Sexp::Ident(name) if !self.vars.contains_key(name.as_str()) => Some(Literal {
+                value: *self.constants.get(name.as_str())?,
+                constant: Some(name),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Lower the integer `literal` to a constant of `ty`, if it's one of the values of `ty`
+    /// interpreted as `interpretation`.
+    ///
+    /// The constants of 128-bit integers are made of two 64-bit ones, since `iconst` only makes
+    /// integers of up to 64 bits.
+    fn int_literal(
+        &mut self,
+        literal: Literal,
+        ty: Type,
+        interpretation: Interpretation,
+    ) -> Result<Value, LowerError> {
+        check_literal(literal, ty, interpretation)?;
+        let value = literal.value;
+        let ins = self.builder.ins();
+        if ty.bits() < 128 {
+            return Ok(ins.iconst(ty, imm64(value, ty)));
+        }
+        match i64::try_from(value) {
+            Ok(value) => {
+                let value = ins.iconst(types::I64, value);
+                Ok(self.builder.ins().sextend(ty, value))
+            }
+            Err(_) => {
+                let low = ins.iconst(types::I64, value as i64);
+                let high = self.builder.ins().iconst(types::I64, (value >> 64) as i64);
+                Ok(self.builder.ins().iconcat(low, high))
+            }
+        }
+    }
+
+    /// Lower `(icmp cc x y)`, or `(icmp_imm cc x literal)`, comparing `x` with the condition
+    /// code named `cc`.
+    fn icmp(&mut self, op: &str, operands: &'a [Sexp]) -> Result<Value, LowerError> {
+        check_arity(op, 3, operands)?;
+        let cc = match &operands[0] {
+            Sexp::Ident(name) => condition_code(name),
+            _ => None,
+        };
+        let cc = match cc {
+            Some(cc) => cc,
+            None => {
+                let err = LowerError::UnknownConditionCode(operands[0].to_string());
+                return self.locate(&operands[0], Err(err));
+            }
+        };
+        let interpretation = Interpretation::of_comparison(cc);
+        let hint = if op == "icmp" {
+            operands[1..].iter().find_map(|expr| self.infer(expr))
+        } else {
+            self.infer(&operands[1])
+        };
+        let x = self.operand(&operands[1], hint, interpretation)?;
+        let x_ty = self.value_type(x);
+        if !x_ty.lane_type().is_int() {
+            return Err(LowerError::TypeMismatch(format!(
+                "`{op}` of {x_ty}, which isn't an integer type"
+            )));
+        }
+
+        if op == "icmp" {
+            let y = self.operand(&operands[2], Some(x_ty), interpretation)?;
+            let y_ty = self.value_type(y);
+            if x_ty != y_ty {
+                return Err(LowerError::TypeMismatch(format!(
+                    "`{op}` of {x_ty} and {y_ty}"
+                )));
+            }
+            return Ok(self.builder.ins().icmp(cc, x, y));
+        }
+        if x_ty.is_vector() {
+            return Err(LowerError::TypeMismatch(format!(
+                "`{op}` of {x_ty}, which isn't a scalar integer type"
+            )));
+        }
+        let literal = match self.literal(&operands[2]) {
+            Some(literal) => literal,
+            None => {
+                let operand = &operands[2];
+                let err = LowerError::Malformed(format!(
+                    "the immediate of `{op}` is an integer literal or a constant, not `{operand}`"
+                ));
+                return self.locate(operand, Err(err));
+            }
+        };
+        let value = literal.value;
+        // The immediate of a 128-bit comparison is a 64-bit one, sign-extended.
+        let result = check_literal(literal, x_ty, interpretation).and_then(|()| {
+            if x_ty.bits() == 128 && i64::try_from(value).is_err() {
+                return Err(LowerError::TypeMismatch(format!(
+                    "the immediate of `{op}` of {x_ty} is 64 bits, which {value} doesn't fit in"
+                )));
+            }
+            Ok(())
+        });
+        self.locate(&operands[2], result)?;
+        Ok(self.builder.ins().icmp_imm(cc, x, imm64(value, x_ty)))
+    }
+
+    /// Lower `(type literal)`, the literal with the type named by the operator.
+    fn typed_literal(&mut self, op: &str, operands: &'a [Sexp]) -> Result<Value, LowerError> {
+        check_arity(op, 1, operands)?;
+        let ty = resolve_type(op).unwrap();
+        match (&operands[0], self.literal(&operands[0])) {
+            (_, Some(literal)) => self.int_literal(literal, ty, Interpretation::Either),
+            (Sexp::Float(_), None) => self.expr(&operands[0], Some(ty)),
+            (operand, None) => Err(LowerError::Malformed(format!(
+                "`({op} literal)` takes a literal or a constant, not `{operand}`"
+            ))),
+        }
+    }
+
+    /// Lower `expr`, an operand of an operator interpreting it as `interpretation` if it's an
+    /// integer literal or a constant.
+    fn operand(
+        &mut self,
+        expr: &'a Sexp,
+        hint: Option<Type>,
+        interpretation: Interpretation,
+    ) -> Result<Value, LowerError> {
+        match self.literal(expr) {
+            Some(literal) => {
+                let result = self.int_literal(literal, hint.unwrap_or(types::I64), interpretation);
+                self.locate(expr, result)
+            }
+            None => self.expr(expr, hint),
+        }
+    }
+
+    /// Lower the application of the opcode or operator `op` to `operands`.
+    fn op(
+        &mut self,
+        op: &str,
+        operands: &'a [Sexp],
+        hint: Option<Type>,
+    ) -> Result<Value, LowerError> {
+        let opcode = opcode(op);
+        let cc = comparison(op);
+        let arity = if UNARY.contains(&opcode) {
+            1
+        } else if BINARY.contains(&opcode)
+            || DIVISIONS.contains(&opcode)
+            || SHIFTS.contains(&opcode)
+            || cc.is_some()
+        {
+            2
+        } else {
+            return Err(LowerError::UnknownOpcode(op.to_string()));
+        };
+        check_arity(op, arity, operands)?;
+
+        // The operands of a comparison don't have the type of its result.
+        let x_hint = if BINARY.contains(&opcode) || DIVISIONS.contains(&opcode) || cc.is_some() {
+            operands.iter().find_map(|expr| self.infer(expr))
+        } else {
+            self.infer(&operands[0])
+        };
+        let x_hint = if cc.is_some() {
+            x_hint
+        } else {
+            x_hint.or(hint)
+        };
+        // Comparisons are signed, `ushr` shifts an unsigned integer by an unsigned amount, and
+        // the divisions divide integers of their signedness.
+        let x_interpretation = match opcode {
+            _ if cc.is_some() => Interpretation::Signed,
+            "sshr" | "sdiv" | "srem" => Interpretation::Signed,
+            "ushr" | "udiv" | "urem" => Interpretation::Unsigned,
+            _ => Interpretation::Either,
+        };
+        let x = self.operand(&operands[0], x_hint, x_interpretation)?;
+        let x_ty = self.value_type(x);
+        let not_int =
+            || LowerError::TypeMismatch(format!("`{op}` of {x_ty}, which isn't an integer type"));
+        if arity == 1 {
+            if !x_ty.lane_type().is_int() {
+                return Err(not_int());
+            }
+            let ins = self.builder.ins();
+            return Ok(match opcode {
+                "ineg" => ins.ineg(x),
+                _ => ins.bnot(x),
+            });
+        }
+
+        // The shift amount can have any integer type.
+        let y_hint = if SHIFTS.contains(&opcode) {
+            None
+        } else {
+            Some(x_ty)
+        };
+        let shift = SHIFTS.contains(&opcode);
+        let y_interpretation = match opcode {
+            _ if shift => Interpretation::Unsigned,
+            _ if cc.is_some() => Interpretation::Signed,
+            _ if DIVISIONS.contains(&opcode) => x_interpretation,
+            _ => Interpretation::Either,
+        };
+        // A division by a literal zero, typed or not, would trap whatever it divides.
+        if DIVISIONS.contains(&opcode) {
+            let divisor = match (head(&operands[1]), &operands[1]) {
+                (Some(ty), Sexp::List(items)) if items.len() == 2 && resolve_type(ty).is_some() => {
+                    self.literal(&items[1])
+                }
+                (_, divisor) => self.literal(divisor),
+            };
+            if let Some(Literal { value: 0, .. }) = divisor {
+                let error = Err(LowerError::DivisionByZero(op.to_string()));
+                return self.locate(&operands[1], error);
+            }
+        }
+        let y = self.operand(&operands[1], y_hint, y_interpretation)?;
+        let y_ty = self.value_type(y);
+        if (!shift && x_ty != y_ty) || (shift && !y_ty.is_int()) {
+            return Err(LowerError::TypeMismatch(format!(
+                "`{op}` of {x_ty} and {y_ty}"
+            )));
+        }
+        if x_ty.lane_type().is_float() && cc.is_none() {
+            let ins = self.builder.ins();
+            return match float_opcode(op) {
+                "fadd" => Ok(ins.fadd(x, y)),
+                "fsub" => Ok(ins.fsub(x, y)),
+                "fmul" => Ok(ins.fmul(x, y)),
+                "fdiv" => Ok(ins.fdiv(x, y)),
+                _ => Err(not_int()),
+            };
+        }
+        if FLOAT_BINARY.contains(&opcode) {
+            return Err(LowerError::TypeMismatch(format!(
+                "`{op}` of {x_ty}, which isn't a floating-point type"
+            )));
+        }
+        if !x_ty.lane_type().is_int() {
+            return Err(not_int());
+        }
+        let ins = self.builder.ins();
+        if let Some(cc) = cc {
+            return Ok(ins.icmp(cc, x, y));
+        }
+        Ok(match opcode {
+            "iadd" => ins.iadd(x, y),
+            "isub" => ins.isub(x, y),
+            "imul" => ins.imul(x, y),
+            "band" => ins.band(x, y),
+            "bor" => ins.bor(x, y),
+            "bxor" => ins.bxor(x, y),
+            "sdiv" => ins.sdiv(x, y),
+            "udiv" => ins.udiv(x, y),
+            "srem" => ins.srem(x, y),
+            "urem" => ins.urem(x, y),
+            "ishl" => ins.ishl(x, y),
+            "ushr" => ins.ushr(x, y),
+            _ => ins.sshr(x, y),
+        })
+    }
+
+    /// Lower `(call name args...)`.
+    fn call(&mut self, operands: &'a [Sexp]) -> Result<Value, LowerError> {
+        Ok(self.call_values(operands, 1)?[0])
+    }
+
+    /// Lower `(call name args...)` to the `count` results of `name`.
+    fn call_values(
+        &mut self,
+        operands: &'a [Sexp],
+        count: usize,
+    ) -> Result<Vec<Value>, LowerError> {
+        let (name, callee, sig, args) = self.callee("call", operands)?;
+        if sig.returns.len() != count {
+            let plural = if sig.returns.len() == 1 { "" } else { "s" };
+            return Err(LowerError::TypeMismatch(format!(
+                "`{name}` returns {} value{plural}, not {count}",
+                sig.returns.len()
+            )));
+        }
+        let call = self.builder.ins().call(callee, &args);
+        Ok(self.builder.inst_results(call).to_vec())
+    }
+
+    /// Lower `(tail_call name args...)`, which returns the results of `(call name args...)`
+    /// with `return_call`, so that the callee reuses the stack frame of the function. It ends
+    /// the current block.
+    fn tail_call(&mut self, operands: &'a [Sexp]) -> Result<(), LowerError> {
+        let (name, callee, sig, args) = self.callee("tail_call", operands)?;
+        let caller = &self.builder.func.signature;
+        if !caller.call_conv.supports_tail_calls() || sig.call_conv != caller.call_conv {
+            return Err(LowerError::TypeMismatch(format!(
+                "`tail_call` needs the `tail` calling convention, but the function uses `{}` \
+                 and `{name}` uses `{}`",
+                caller.call_conv, sig.call_conv
+            )));
+        }
+        let returns = |sig: &ir::Signature| -> Vec<Type> {
+            sig.returns.iter().map(|ret| ret.value_type).collect()
+        };
+        let (callee_returns, caller_returns) = (returns(&sig), returns(caller));
+        if callee_returns != caller_returns {
+            return Err(LowerError::TypeMismatch(format!(
+                "`{name}` returns {}, but the function returns {}",
+                describe_types(&callee_returns),
+                describe_types(&caller_returns)
+            )));
+        }
+        self.builder.ins().return_call(callee, &args);
+        Ok(())
+    }
+
+    /// Lower the callee and the arguments of `(op name args...)`, to the name, the reference and
+    /// the signature of the callee, and the values of the arguments.
+    fn callee(
+        &mut self,
+        op: &str,
+        operands: &'a [Sexp],
+    ) -> Result<(&'a str, ir::FuncRef, ir::Signature, Vec<Value>), LowerError> {
+        let (name, args) = match operands.split_first() {
+            Some((Sexp::Ident(name), args)) => (name, args),
+            Some((callee, _)) => {
+                return Err(LowerError::Malformed(format!(
+                    "expected the name of a function, found `{callee}`"
+                )))
+            }
+            None => {
+                return Err(LowerError::Malformed(format!(
+                    "`{op}` needs the name of a function"
+                )))
+            }
+        };
+        let id = self
+            .function(name)
+            .ok_or_else(|| LowerError::UndefinedFunction(name.clone()))?;
+        let sig = self
+            .module
+            .declarations()
+            .get_function_decl(id)
+            .signature
+            .clone();
+        check_arity(&format!("{op} {name}"), sig.params.len(), args)?;
+
+        let mut values = Vec::new();
+        for (i, (arg, param)) in args.iter().zip(&sig.params).enumerate() {
+            let value = self.expr(arg, Some(param.value_type))?;
+            let ty = self.value_type(value);
+            if ty != param.value_type {
+                return Err(LowerError::TypeMismatch(format!(
+                    "argument {i} of `{name}` has type {ty}, not {}",
+                    param.value_type
+                )));
+            }
+            values.push(value);
+        }
+        let callee = match self.callees.get(&id) {
+            Some(&callee) => callee,
+            None => {
+                let callee = self.module.declare_func_in_func(id, self.builder.func);
+                self.callees.insert(id, callee);
+                callee
+            }
+        };
+        Ok((name, callee, sig, values))
+    }
+
+    /// Lower `(symbol name)`, the address of the data object `name`.
+    fn symbol(&mut self, operands: &'a [Sexp]) -> Result<Value, LowerError> {
+        check_arity("symbol", 1, operands)?;
+        let name = match &operands[0] {
+            Sexp::Ident(name) => name,
+            operand => {
+                return Err(LowerError::Malformed(format!(
+                    "expected the name of data, found `{operand}`"
+                )))
+            }
+        };
+        let id = self
+            .data(name)
+            .ok_or_else(|| LowerError::UndefinedData(name.clone()))?;
+        let global = match self.globals.get(&id) {
+            Some(&global) => global,
+            None => {
+                let global = self.module.declare_data_in_func(id, self.builder.func);
+                self.globals.insert(id, global);
+                global
+            }
+        };
+        let pointer_type = self.pointer_type();
+        Ok(self.builder.ins().symbol_value(pointer_type, global))
+    }
+
+    /// Lower `(load type address offset)`, which loads a value of `type` from `offset` bytes
+    /// after `address`.
+    fn load(&mut self, operands: &'a [Sexp]) -> Result<Value, LowerError> {
+        check_arity("load", 3, operands)?;
+        let ty = type_operand("load", &operands[0])?;
+        let pointer_type = self.pointer_type();
+        let address = self.expr(&operands[1], Some(pointer_type))?;
+        let address_ty = self.value_type(address);
+        if address_ty != pointer_type {
+            return Err(LowerError::TypeMismatch(format!(
+                "the address of `load` has type {address_ty}, not the pointer type {pointer_type}"
+            )));
+        }
+        let offset = self.offset_operand("load", &operands[2])?;
+        Ok(self
+            .builder
+            .ins()
+            .load(ty, MemFlags::new(), address, offset))
+    }
+
+    /// The offset `operand` of `op`, an integer literal or a constant.
+    fn offset_operand(&self, op: &str, operand: &Sexp) -> Result<i32, LowerError> {
+        match self.literal(operand) {
+            Some(Literal { value, .. }) => i32::try_from(value).map_err(|_| {
+                LowerError::Malformed(format!("the offset {value} of `{op}` is too large"))
+            }),
+            None => Err(LowerError::Malformed(format!(
+                "the offset of `{op}` must be an integer literal or a constant, not `{operand}`"
+            ))),
+        }
+    }
+
+    /// Lower `(stack_load type slot offset)`, which loads a value of `type` from `offset` bytes
+    /// into the stack slot `slot`.
+    fn stack_load(&mut self, operands: &'a [Sexp]) -> Result<Value, LowerError> {
+        check_arity("stack_load", 3, operands)?;
+        let ty = type_operand("stack_load", &operands[0])?;
+        let (slot, offset) = self.stack_access("stack_load", ty, &operands[1], &operands[2])?;
+        Ok(self.builder.ins().stack_load(ty, slot, offset))
+    }
+
+    /// Lower `(stack_store type slot offset value)`, which stores `value` of `type` at `offset`
+    /// bytes into the stack slot `slot`. Its value is the stored value.
+    fn stack_store(&mut self, operands: &'a [Sexp]) -> Result<Value, LowerError> {
+        check_arity("stack_store", 4, operands)?;
+        let ty = type_operand("stack_store", &operands[0])?;
+        let (slot, offset) = self.stack_access("stack_store", ty, &operands[1], &operands[2])?;
+        let value = self.expr(&operands[3], Some(ty))?;
+        let value_ty = self.value_type(value);
+        if value_ty != ty {
+            return Err(LowerError::TypeMismatch(format!(
+                "`stack_store` of {value_ty}, not {ty}"
+            )));
+        }
+        self.builder.ins().stack_store(value, slot, offset);
+        Ok(value)
+    }
+
+    /// The stack slot named by `slot` and the offset `offset` of the access `op` to a value of
+    /// `ty` in it, which must be within the slot.
+    fn stack_access(
+        &self,
+        op: &str,
+        ty: Type,
+        slot: &Sexp,
+        offset: &Sexp,
+    ) -> Result<(ir::StackSlot, i32), LowerError> {
+        let name = match slot {
+            Sexp::Ident(name) => name,
+            _ => {
+                return Err(LowerError::Malformed(format!(
+                    "expected the name of a stack slot, found `{slot}`"
+                )))
+            }
+        };
+        let (slot, size) = *self
+            .slots
+            .get(name.as_str())
+            .ok_or_else(|| LowerError::UndefinedStackSlot(name.clone()))?;
+        let offset = self.offset_operand(op, offset)?;
+        if offset < 0 || u64::from(offset as u32) + u64::from(ty.bytes()) > u64::from(size) {
+            return Err(LowerError::Malformed(format!(
+                "`{op}` of {ty} at offset {offset} is outside the {size} bytes of stack slot \
+                 `{name}`"
+            )));
+        }
+        Ok((slot, offset))
+    }
+
+    /// Lower the conversion `(fcvt_from_sint type x)` of the signed integer `x` to the nearest
+    /// value of the floating-point `type`, or `(fcvt_to_sint type x)` of the float `x` to the
+    /// integer `type`, rounding toward zero, which traps if `x` is NaN or out of its range.
+    fn convert(&mut self, op: &str, operands: &'a [Sexp]) -> Result<Value, LowerError> {
+        check_arity(op, 2, operands)?;
+        let ty = type_operand(op, &operands[0])?;
+        let from_sint = op == "fcvt_from_sint";
+        // Whether `ty` is a scalar float type, or else a scalar integer type `fcvt` supports.
+        let scalar = |ty: Type, float: bool| {
+            if float {
+                ty.is_float()
+            } else {
+                ty.is_int() && ty.bits() <= 64
+            }
+        };
+        let kind = |float: bool| if float { "float" } else { "integer" };
+        if !scalar(ty, from_sint) {
+            return Err(LowerError::TypeMismatch(format!(
+                "`{op}` converts to a scalar {} type, not {ty}",
+                kind(from_sint)
+            )));
+        }
+        let (hint, interpretation) = if from_sint {
+            (None, Interpretation::Signed)
+        } else {
+            (Some(types::F64), Interpretation::Either)
+        };
+        let x = self.operand(&operands[1], hint, interpretation)?;
+        let x_ty = self.value_type(x);
+        if !scalar(x_ty, !from_sint) {
+            return Err(LowerError::TypeMismatch(format!(
+                "`{op}` of {x_ty}, which isn't a scalar {} type",
+                kind(!from_sint)
+            )));
+        }
+        let ins = self.builder.ins();
+        Ok(if from_sint {
+            ins.fcvt_from_sint(ty, x)
+        } else {
+            ins.fcvt_to_sint(ty, x)
+        })
+    }
+
+    /// Lower `(if cond then else)`, which computes `then` if `cond` isn't zero and `else`
+    /// otherwise.
+    ///
+    /// The values of the branches are passed to a block joining them, whose parameter is the
+    /// value of the `if`. A branch which is a `return` doesn't reach the join block, and if both
+    /// are, there is no join block and the `if` has no value.
+    fn if_(
+        &mut self,
+        operands: &'a [Sexp],
+        hint: Option<Type>,
+    ) -> Result<Option<Value>, LowerError> {
+        check_arity("if", 3, operands)?;
+        let hint = operands[1..]
+            .iter()
+            .find_map(|expr| self.infer(expr))
+            .or(hint);
+        let cond = self.condition("if", &operands[0])?;
+        let then_block = self.builder.create_block();
+        let else_block = self.builder.create_block();
+        self.builder
+            .ins()
+            .brif(cond, then_block, &[], else_block, &[]);
+
+        self.builder.switch_to_block(then_block);
+        let mut join = None;
+        if let Some(then_value) = self.arm(&operands[1], hint)? {
+            let ty = self.value_type(then_value);
+            let block = self.builder.create_block();
+            self.builder.ins().jump(block, &[then_value]);
+            join = Some((block, ty));
+        }
+
+        self.builder.switch_to_block(else_block);
+        let else_hint = join.map(|(_, ty)| ty).or(hint);
+        if let Some(else_value) = self.arm(&operands[2], else_hint)? {
+            let else_ty = self.value_type(else_value);
+            let (block, ty) = match join {
+                Some((block, ty)) => (block, ty),
+                None => (self.builder.create_block(), else_ty),
+            };
+            if else_ty != ty {
+                return Err(LowerError::TypeMismatch(format!(
+                    "the branches of `if` have types {ty} and {else_ty}"
+                )));
+            }
+            self.builder.ins().jump(block, &[else_value]);
+            join = Some((block, ty));
+        }
+
+        Ok(join.map(|(block, ty)| {
+            self.builder.switch_to_block(block);
+            self.builder.append_block_param(block, ty)
+        }))
+    }
+
+    /// Lower `(select cond a b)`, which is `a` if `cond` isn't zero and `b` otherwise, to the
+    /// `select` instruction. Unlike an `if`, it computes both `a` and `b`, before it chooses
+    /// between their values without branching.
+    fn select(&mut self, operands: &'a [Sexp], hint: Option<Type>) -> Result<Value, LowerError> {
+        check_arity("select", 3, operands)?;
+        let hint = operands[1..]
+            .iter()
+            .find_map(|expr| self.infer(expr))
+            .or(hint);
+        let cond = self.condition("select", &operands[0])?;
+        let a = self.expr(&operands[1], hint)?;
+        let a_ty = self.value_type(a);
+        let b = self.expr(&operands[2], Some(a_ty))?;
+        let b_ty = self.value_type(b);
+        if a_ty != b_ty {
+            return Err(LowerError::TypeMismatch(format!(
+                "the values of `select` have types {a_ty} and {b_ty}"
+            )));
+        }
+        Ok(self.builder.ins().select(cond, a, b))
+    }
+
+    /// Lower the branch `expr` of an `if`, the body of a `let` or a `loop`, or the last expression
+    /// of the body of a `while`, to its value unless it returns, tail-calls or continues a loop.
+    fn arm(&mut self, expr: &'a Sexp, hint: Option<Type>) -> Result<Option<Value>, LowerError> {
+        let result = self.lower_arm(expr, hint);
+        self.locate(expr, result)
+    }
+
+    fn lower_arm(
+        &mut self,
+        expr: &'a Sexp,
+        hint: Option<Type>,
+    ) -> Result<Option<Value>, LowerError> {
+        match (head(expr), expr) {
+            (Some("return"), Sexp::List(items)) => {
+                self.return_(&items[1..])?;
+                Ok(None)
+            }
+            (Some("if"), Sexp::List(items)) => self.if_(&items[1..], hint),
+            (Some("let"), Sexp::List(items)) => self.let_(&items[1..], hint),
+            (Some("loop"), Sexp::List(items)) => self.loop_(&items[1..], hint),
+            (Some("continue"), Sexp::List(items)) => {
+                self.continue_(&items[1..])?;
+                Ok(None)
+            }
+            (Some("tail_call"), Sexp::List(items)) => {
+                self.tail_call(&items[1..])?;
+                Ok(None)
+            }
+            _ => self.expr(expr, hint).map(Some),
+        }
+    }
+
+    /// Lower `(let ((name expr)...) body)`, which computes `body` with each name bound to the
+    /// value of its expression. The names are bound in order, so an expression can use the
+    /// names bound before it, and a name bound again shadows the earlier binding. A binding
+    /// `((name...) (call f args...))` binds the names to the results of `f`, in order.
+    ///
+    /// Like a branch of an `if`, `body` can return, and then the `let` has no value.
+    fn let_(
+        &mut self,
+        operands: &'a [Sexp],
+        hint: Option<Type>,
+    ) -> Result<Option<Value>, LowerError> {
+        check_arity("let", 2, operands)?;
+        let bindings = match &operands[0] {
+            Sexp::List(bindings) => bindings,
+            operand => {
+                return Err(LowerError::Malformed(format!(
+                    "expected the bindings `((name expr)...)` of `let`, found `{operand}`"
+                )))
+            }
+        };
+        let outer = self.vars.clone();
+        for binding in bindings {
+            let (names, expr, several) = match binding {
+                Sexp::List(items) => match &items[..] {
+                    [Sexp::Ident(name), expr] => Some((vec![name.as_str()], expr, false)),
+                    [Sexp::List(names), expr] => names
+                        .iter()
+                        .map(ident)
+                        .collect::<Option<Vec<_>>>()
+                        .map(|names| (names, expr, true)),
+                    _ => None,
+                },
+                _ => None,
+            }
+            .ok_or_else(|| {
+                LowerError::Malformed(format!(
+                    "expected a binding `(name expr)` or `((name...) (call f args...))`, found \
+                     `{binding}`"
+                ))
+            })?;
+            if let Some(name) = names.iter().find(|name| self.params.contains(*name)) {
+                return Err(LowerError::RebindParameter(name.to_string()));
+            }
+            let values = match (several, head(expr), expr) {
+                (false, _, _) => vec![self.expr(expr, None)?],
+                (true, Some("call"), Sexp::List(items)) => {
+                    let result = self.call_values(&items[1..], names.len());
+                    self.locate(expr, result)?
+                }
+                (true, _, _) => {
+                    return Err(LowerError::Malformed(format!(
+                        "only a `call` has several values to bind, not `{expr}`"
+                    )))
+                }
+            };
+            self.vars.extend(names.into_iter().zip(values));
+        }
+        let value = self.arm(&operands[1], hint)?;
+        self.vars = outer;
+        Ok(value)
+    }
+
+    /// Lower `(loop ((name init)...) body)`, which computes `body` with each name bound to a
+    /// variable of the loop, starting at the value of its `init`. A `(continue values...)` in
+    /// `body` computes it again with new values of the variables, and otherwise the value of
+    /// `body` is the value of the loop.
+    ///
+    /// The initial values are computed before the loop, where its variables aren't in scope. A
+    /// variable can be written `(name type init)` to give literals in `init` a type other than
+    /// `i64`.
+    ///
+    /// The variables are the parameters of a header block, which the body starts in. The value of
+    /// the body is passed to an exit block, unless the body always continues or returns, and then
+    /// the loop has no value.
+    fn loop_(
+        &mut self,
+        operands: &'a [Sexp],
+        hint: Option<Type>,
+    ) -> Result<Option<Value>, LowerError> {
+        check_arity("loop", 2, operands)?;
+        let bindings = match &operands[0] {
+            Sexp::List(bindings) => bindings,
+            operand => {
+                return Err(LowerError::Malformed(format!(
+                    "expected the variables `((name init)...)` of `loop`, found `{operand}`"
+                )))
+            }
+        };
+        let mut vars = Vec::new();
+        let mut inits = Vec::new();
+        for binding in bindings {
+            let (name, ty, init) = match binding {
+                Sexp::List(items) => match &items[..] {
+                    [Sexp::Ident(name), init] => Some((name.as_str(), None, init)),
+                    [Sexp::Ident(name), Sexp::Ident(ty), init] => {
+                        Some((name.as_str(), Some(ty), init))
+                    }
+                    _ => None,
+                },
+                _ => None,
+            }
+            .ok_or_else(|| {
+                LowerError::Malformed(format!(
+                    "expected a variable `(name init)` or `(name type init)`, found `{binding}`"
+                ))
+            })?;
+            if self.params.contains(name) {
+                return Err(LowerError::RebindParameter(name.to_string()));
+            }
+            let ty = match ty {
+                Some(ty) => Some(resolve_type(ty).ok_or_else(|| {
+                    LowerError::Malformed(format!("unknown type `{ty}` of variable `{name}`"))
+                })?),
+                None => None,
+            };
+            let value = self.expr(init, ty)?;
+            let value_ty = self.value_type(value);
+            if let Some(ty) = ty.filter(|&ty| ty != value_ty) {
+                return Err(LowerError::TypeMismatch(format!(
+                    "the initial value of `{name}` has type {value_ty}, not {ty}"
+                )));
+            }
+            vars.push((name, value_ty));
+            inits.push(value);
+        }
+
+        let header = self.builder.create_block();
+        for &(_, ty) in &vars {
+            self.builder.append_block_param(header, ty);
+        }
+        self.builder.ins().jump(header, &inits);
+        self.builder.switch_to_block(header);
+        let outer = self.vars.clone();
+        let values = self.builder.block_params(header).to_vec();
+        self.vars
+            .extend(vars.iter().map(|&(name, _)| name).zip(values));
+        self.loops.push(Loop { header, vars });
+        let value = self.arm(&operands[1], hint)?;
+        self.loops.pop();
+        self.vars = outer;
+
+        Ok(value.map(|value| {
+            let ty = self.value_type(value);
+            let exit = self.builder.create_block();
+            self.builder.ins().jump(exit, &[value]);
+            self.builder.switch_to_block(exit);
+            self.builder.append_block_param(exit, ty)
+        }))
+    }
+
+    /// Lower `(continue values...)`, which jumps back to the header of the innermost loop with
+    /// the new values of its variables, ending the current block.
+    fn continue_(&mut self, operands: &'a [Sexp]) -> Result<(), LowerError> {
+        let (header, vars) = match self.loops.last() {
+            Some(innermost) => (innermost.header, innermost.vars.clone()),
+            None => return Err(continue_outside_loop()),
+        };
+        check_arity("continue", vars.len(), operands)?;
+        let mut values = Vec::new();
+        for (operand, (name, ty)) in operands.iter().zip(vars) {
+            let value = self.expr(operand, Some(ty))?;
+            let value_ty = self.value_type(value);
+            if value_ty != ty {
+                return Err(LowerError::TypeMismatch(format!(
+                    "`continue` passes {value_ty} to `{name}`, which has type {ty}"
+                )));
+            }
+            values.push(value);
+        }
+        self.builder.ins().jump(header, &values);
+        Ok(())
+    }
+
+    /// Lower `(while cond body...)`, which computes the expressions of `body` in order for as long
+    /// as `cond` isn't zero, testing it before each time, for their effects on memory. Its value
+    /// is that of the condition which ended it, zero. A `(continue)` as the last expression of
+    /// `body`, or of a branch of an `if` or the body of a `let` there, goes on to the next test.
+    ///
+    /// As for a loop without variables, the condition is computed in a header block, which
+    /// branches to the block of the body or to an exit block, and the body jumps back to the
+    /// header. The condition and the body are computed again on every iteration, their parts
+    /// which don't depend on the `while` included, and the names bound outside of it keep the
+    /// values computed before it. Cranelift moves the invariant computations which don't access
+    /// memory out of the loop when it optimizes the function.
+    fn while_(&mut self, operands: &'a [Sexp]) -> Result<Value, LowerError> {
+        let (cond, body) = match operands.split_first() {
+            Some((cond, body)) if !body.is_empty() => (cond, body),
+            _ => {
+                return Err(LowerError::Malformed(
+                    "expected `(while cond body...)`, with at least one expression in the body"
+                        .to_string(),
+                ))
+            }
+        };
+        let header = self.builder.create_block();
+        let body_block = self.builder.create_block();
+        let exit = self.builder.create_block();
+        self.builder.ins().jump(header, &[]);
+
+        self.builder.switch_to_block(header);
+        let value = self.expr(cond, Some(types::I32))?;
+        let ty = self.value_type(value);
+        if ty != types::I8 && ty != types::I32 {
+            let error = LowerError::TypeMismatch(format!(
+                "the condition of `while` has type {ty}, not i8 or i32"
+            ));
+            return self.locate(cond, Err(error));
+        }
+        self.builder.ins().brif(value, body_block, &[], exit, &[]);
+
+        self.builder.switch_to_block(body_block);
+        self.loops.push(Loop {
+            header,
+            vars: Vec::new(),
+        });
+        let (last, exprs) = body.split_last().unwrap();
+        for expr in exprs {
+            self.expr(expr, None)?;
+        }
+        if self.arm(last, None)?.is_some() {
+            self.builder.ins().jump(header, &[]);
+        }
+        self.loops.pop();
+
+        // The header dominates the exit, so the condition is in scope there.
+        self.builder.switch_to_block(exit);
+        Ok(value)
+    }
+
+    /// Lower `(return values...)`, with a value for each result of the function, which ends
+    /// the current block.
+    fn return_(&mut self, operands: &'a [Sexp]) -> Result<(), LowerError> {
+        let returns = self.returns.clone();
+        check_arity("return", returns.len(), operands)?;
+        let mut values = Vec::new();
+        for (operand, &ret) in operands.iter().zip(&returns) {
+            values.push(self.expr(operand, Some(ret))?);
+        }
+        let types: Vec<Type> = values.iter().map(|&value| self.value_type(value)).collect();
+        if types != returns {
+            return Err(LowerError::TypeMismatch(format!(
+                "`return` of {}, not the result type {}",
+                describe_types(&types),
+                describe_types(&returns)
+            )));
+        }
+        self.builder.ins().return_(&values);
+        Ok(())
+    }
+
+    /// Lower the condition `expr` of the branching operator `op`.
+    fn condition(&mut self, op: &str, expr: &'a Sexp) -> Result<Value, LowerError> {
+        let cond = self.expr(expr, None)?;
+        let ty = self.value_type(cond);
+        if !ty.is_int() {
+            return Err(LowerError::TypeMismatch(format!(
+                "the condition of `{op}` has type {ty}, which isn't a scalar integer type"
+            )));
+        }
+        Ok(cond)
+    }
+
+    /// Lower the branch `(jump (block args...))` or `(brif cond (then args...) (else args...))`,
+    /// which ends the current block.
+    fn branch(&mut self, op: &str, operands: &'a [Sexp]) -> Result<(), LowerError> {
+        if op == "jump" {
+            check_arity(op, 1, operands)?;
+            let (block, args) = self.block_call(&operands[0])?;
+            self.builder.ins().jump(block, &args);
+        } else {
+            check_arity(op, 3, operands)?;
+            let cond = self.condition(op, &operands[0])?;
+            let (then_block, then_args) = self.block_call(&operands[1])?;
+            let (else_block, else_args) = self.block_call(&operands[2])?;
+            self.builder
+                .ins()
+                .brif(cond, then_block, &then_args, else_block, &else_args);
+        }
+        Ok(())
+    }
+
+    /// Lower the target `(block args...)` of a branch, to the block and the values of the
+    /// arguments.
+    fn block_call(&mut self, target: &'a Sexp) -> Result<(ir::Block, Vec<Value>), LowerError> {
+        let result = self.lower_block_call(target);
+        self.locate(target, result)
+    }
+
+    fn lower_block_call(
+        &mut self,
+        target: &'a Sexp,
+    ) -> Result<(ir::Block, Vec<Value>), LowerError> {
+        let items = match target {
+            Sexp::List(items) => &items[..],
+            _ => &[],
+        };
+        let (name, args) = match items.split_first() {
+            Some((Sexp::Ident(name), args)) => (name, args),
+            _ => {
+                return Err(LowerError::Malformed(format!(
+                    "expected a branch target `(block args...)`, found `{target}`"
+                )))
+            }
+        };
+        let block = *self
+            .blocks
+            .get(name.as_str())
+            .ok_or_else(|| LowerError::UndefinedBlock(name.clone()))?;
+        let params: Vec<Type> = self.builder.func.dfg.block_param_types(block).collect();
+        if args.len() != params.len() {
+            return Err(LowerError::BlockArity {
+                block: name.clone(),
+                expected: params.len(),
+                found: args.len(),
+            });
+        }
+
+        let mut values = Vec::new();
+        for (i, (arg, &param)) in args.iter().zip(&params).enumerate() {
+            let value = self.expr(arg, Some(param))?;
+            let ty = self.value_type(value);
+            if ty != param {
+                return Err(LowerError::TypeMismatch(format!(
+                    "argument {i} of block `{name}` has type {ty}, not {param}"
+                )));
+            }
+            values.push(value);
+        }
+        Ok((block, values))
+    }
+}
+
+/// `types`, as the results of a function.
+fn describe_types(types: &[Type]) -> String {
+    match types {
+        [] => "nothing".to_string(),
+        [ty] => ty.to_string(),
+        _ => {
+            let types: Vec<_> = types.iter().map(Type::to_string).collect();
+            format!("({})", types.join(", "))
+        }
+    }
+}
+
+/// The type named by `operand`, the first operand of `op`.
+fn type_operand(op: &str, operand: &Sexp) -> Result<Type, LowerError> {
+    match operand {
+        Sexp::Ident(name) => resolve_type(name)
+            .ok_or_else(|| LowerError::Malformed(format!("unknown type `{name}` of `{op}`"))),
+        operand => Err(LowerError::Malformed(format!(
+            "expected the type of `{op}`, found `{operand}`"
+        ))),
+    }
+}
+
+fn continue_outside_loop() -> LowerError {
+    LowerError::Malformed("`continue` outside of a `loop` or a `while`".to_string())
+}
+
+/// Check that the value of the integer `literal` is one of the values of `ty`, interpreted as
+/// `interpretation`.
+fn check_literal(
+    literal: Literal,
+    ty: Type,
+    interpretation: Interpretation,
+) -> Result<(), LowerError> {
+    let value = literal.value;
+    if !ty.is_int() {
+        return Err(LowerError::TypeMismatch(format!(
+            "integer literals can't have type {ty}"
+        )));
+    }
+    let bits = ty.bits();
+    // The literals are `i128`s, which hold every value of the narrower types.
+    let signed = match bits {
+        128 => true,
+        _ => (-(1 << (bits - 1))..1 << (bits - 1)).contains(&value),
+    };
+    let unsigned = match bits {
+        128 => value >= 0,
+        _ => (0..1 << bits).contains(&value),
+    };
+    let (fits, as_) = match interpretation {
+        Interpretation::Either => (signed || unsigned, ""),
+        Interpretation::Signed => (signed, " as a signed integer"),
+        Interpretation::Unsigned => (unsigned, " as an unsigned integer"),
+    };
+    if !fits {
+        return Err(LowerError::TypeMismatch(format!(
+            "{literal} does not fit in {ty}{as_}"
+        )));
+    }
+    Ok(())
+}
+
+/// The immediate of the integer literal `value` of `ty`, which fits in 64 bits: the immediates
+/// of narrower types hold the bits of the value sign-extended.
+fn imm64(value: i128, ty: Type) -> Imm64 {
+    let mut imm = Imm64::new(value as i64);
+    if ty.bits() < 64 {
+        imm.sign_extend_from_width(ty.bits());
+    }
+    imm
+}
+
+fn check_arity(op: &str, expected: usize, operands: &[Sexp]) -> Result<(), LowerError> {
+    if operands.len() == expected {
+        Ok(())
+    } else {
+        Err(LowerError::Arity {
+            op: op.to_string(),
+            expected,
+            found: operands.len(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::frontend;
+    use super::*;
+    use cranelift_codegen::settings;
+    use cranelift_jit::{JITBuilder, JITModule};
+    use cranelift_module::{default_libcall_names, Module as _};
+
+    fn jit_module() -> JITModule {
+        JITModule::new(JITBuilder::new(default_libcall_names()).unwrap())
+    }
+
+    /// Compile `src` in a new JIT module, and return the module and the compiled functions.
+    fn compile_jit(src: &str) -> Result<(JITModule, Compiled), String> {
+        let (module, _) = frontend(src).map_err(|e| CompileError::Diagnostics(e).to_string())?;
+        let mut jit = jit_module();
+        let compiled = compile(&mut jit, &module).map_err(|e| e.to_string())?;
+        jit.finalize_definitions().unwrap();
+        Ok((jit, compiled))
+    }
+
+    #[test]
+    fn recursive_factorial() {
+        let (jit, compiled) = compile_jit(
+            "(func fact ((n i32)) (i32)
+               (if n (imul n (call fact (isub n 1))) 1))",
+        )
+        .unwrap();
+        let code = jit.get_finalized_function(compiled.functions[0].1);
+        let fact = unsafe { std::mem::transmute::<*const u8, extern "C" fn(i32) -> i32>(code) };
+        assert_eq!(fact(5), 120);
+        assert_eq!(fact(30), 1_409_286_144);
+    }
+
+    #[test]
+    fn definition_order() {
+        // `main` calls `sum` and `helper` before they're defined, and `sum` calls itself.
+        let (jit, compiled) = compile_jit(
+            "(func main () (i64) (call helper (call sum 10)))
+             (func sum ((n i64)) (i64)
+               (if (> n 0) (+ n (call sum (- n 1))) 0))
+             (func helper ((x i64)) (i64) (* x 2))",
+        )
+        .unwrap();
+        let get = |i: usize| jit.get_finalized_function(compiled.functions[i].1);
+        let main = unsafe { std::mem::transmute::<*const u8, extern "C" fn() -> i64>(get(0)) };
+        let sum = unsafe { std::mem::transmute::<*const u8, extern "C" fn(i64) -> i64>(get(1)) };
+        assert_eq!(main(), 110);
+        assert_eq!(sum(100), 5050);
+    }
+
+    #[test]
+    fn blocks() {
+        // The control flow of the recursive factorial built by hand in `cranelift-jit`'s tests:
+        // the entry block branches forward to the blocks defined after it.
+        let src = "(func fact ((n i64)) (i64)
+                     (brif n (recurse) (done 1))
+                     (block recurse ()
+                       (jump (done (imul n (call fact (isub n 1))))))
+                     (block done ((result i64))
+                       result))";
+        let mut jit = jit_module();
+        let (module, _) = frontend(src).unwrap();
+        let func = &module.functions[0];
+        let sig = func.signature(jit.isa().default_call_conv()).unwrap();
+        let id = jit
+            .declare_function(&func.name, Linkage::Export, &sig)
+            .unwrap();
+        let ir_func = lower(func, &module.constants, &mut jit).unwrap();
+        cranelift_codegen::verify_function(&ir_func, &settings::Flags::new(settings::builder()))
+            .unwrap();
+        let blocks: Vec<_> = ir_func.layout.blocks().collect();
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(
+            ir_func.dfg.block_param_types(blocks[2]).collect::<Vec<_>>(),
+            [types::I64]
+        );
+
+        let mut ctx = jit.make_context();
+        ctx.func = ir_func;
+        jit.define_function(id, &mut ctx).unwrap();
+        jit.finalize_definitions().unwrap();
+        let code = jit.get_finalized_function(id);
+        let fact = unsafe { std::mem::transmute::<*const u8, extern "C" fn(i64) -> i64>(code) };
+        assert_eq!(fact(0), 1);
+        assert_eq!(fact(20), 2_432_902_008_176_640_000);
+
+        // A loop, whose block parameters shadow the parameter of the function.
+        let (jit, compiled) = compile_jit(
+            "(func fact ((n i32)) (i32)
+               (jump (loop n 1))
+               (block loop ((n i32) (acc i32))
+                 (brif n (body n acc) (done acc)))
+               (block body ((n i32) (acc i32))
+                 (jump (loop (isub n 1) (imul acc n))))
+               (block done ((acc i32)) acc))",
+        )
+        .unwrap();
+        let code = jit.get_finalized_function(compiled.functions[0].1);
+        let fact = unsafe { std::mem::transmute::<*const u8, extern "C" fn(i32) -> i32>(code) };
+        assert_eq!(fact(5), 120);
+        assert_eq!(fact(30), 1_409_286_144);
+    }
+
+    #[test]
+    fn if_expressions() {
+        let (jit, compiled) = compile_jit(
+            "(func fact ((n i64)) (i64)
+               (if n (* n (call fact (- n 1))) 1))
+             (func sign ((x i64)) (i64)
+               (+ 10 (if (< x 0) -1 (if (= x 0) 0 1))))
+             (func clamp ((x i64)) (i64)
+               (if (> x 100) (return 100) (if (< x 0) (return 0) (return x))))
+             (func positive ((x i64)) (i64)
+               (+ 1 (if (< x 0) (return 0) x)))",
+        )
+        .unwrap();
+        let get = |i: usize| {
+            let code = jit.get_finalized_function(compiled.functions[i].1);
+            unsafe { std::mem::transmute::<*const u8, extern "C" fn(i64) -> i64>(code) }
+        };
+        let (fact, sign, clamp, positive) = (get(0), get(1), get(2), get(3));
+        assert_eq!(fact(5), 120);
+        assert_eq!(fact(20), 2_432_902_008_176_640_000);
+        assert_eq!([sign(-5), sign(0), sign(7)], [9, 10, 11]);
+        assert_eq!([clamp(-5), clamp(42), clamp(500)], [0, 42, 100]);
+        assert_eq!([positive(-5), positive(41)], [0, 42]);
+
+        // The arms of an `if` which both return end their own blocks, without a join block.
+        let (module, _) =
+            frontend("(func f ((x i64)) (i64) (if x (return 1) (return 2)))").unwrap();
+        let func = lower(&module.functions[0], &module.constants, &mut jit_module()).unwrap();
+        cranelift_codegen::verify_function(&func, &settings::Flags::new(settings::builder()))
+            .unwrap();
+        assert_eq!(func.layout.blocks().count(), 3);
+    }
+
+    #[test]
+    fn selects() {
+        let (jit, compiled) = compile_jit(
+            "(func max ((x i64) (y i64)) (i64)
+               (select (> x y) x y))
+             (func narrow ((x i32)) (i32)
+               (select (icmp_imm ult x 10) 1 (* x 2)))
+             (func both ((c i64)) (i64)
+               (stack s 16)
+               (stack_store i64 s 0 0)
+               (stack_store i64 s 8 0)
+               (let ((chosen (select c (stack_store i64 s 0 10) (stack_store i64 s 8 20))))
+                 (+ chosen (+ (stack_load i64 s 0) (stack_load i64 s 8)))))",
+        )
+        .unwrap();
+        let get = |i: usize| jit.get_finalized_function(compiled.functions[i].1);
+        let max =
+            unsafe { std::mem::transmute::<*const u8, extern "C" fn(i64, i64) -> i64>(get(0)) };
+        let narrow = unsafe { std::mem::transmute::<*const u8, extern "C" fn(i32) -> i32>(get(1)) };
+        let both = unsafe { std::mem::transmute::<*const u8, extern "C" fn(i64) -> i64>(get(2)) };
+        assert_eq!([max(3, 5), max(5, 3), max(-1, -2)], [5, 5, -1]);
+        assert_eq!([narrow(3), narrow(10), narrow(-1)], [1, 20, -2]);
+        // Both values are stored whichever one is chosen, which an `if` would only store one of.
+        assert_eq!([both(1), both(0)], [40, 50]);
+
+        // A `select` doesn't branch.
+        let (module, _) = frontend("(func f ((x i64) (y i64)) (i64) (select x x y))").unwrap();
+        let func = lower(&module.functions[0], &module.constants, &mut jit_module()).unwrap();
+        assert_eq!(func.layout.blocks().count(), 1);
+        assert!(func.display().to_string().contains(" = select v0, v0, v1"));
+
+        for (src, message) in [
+            (
+                "(func f ((x f64) (y i64)) (i64) (select x y y))",
+                "the condition of `select` has type f64, which isn't a scalar integer type",
+            ),
+            (
+                "(func f ((x i64) (y f64)) (i64) (select x x y))",
+                "the values of `select` have types i64 and f64",
+            ),
+            (
+                "(func f ((x i64) (y i32)) (i64) (select x (i64 1) y))",
+                "the values of `select` have types i64 and i32",
+            ),
+            (
+                "(func f ((x i64)) (i64) (select x x))",
+                "`select` takes 3 operands, not 2",
+            ),
+        ] {
+            let (module, _) = frontend(src).unwrap();
+            let err = compile(&mut jit_module(), &module).err().unwrap();
+            assert_eq!(
+                err.to_string(),
+                format!("in function `f`: {message}"),
+                "{src}"
+            );
+        }
+    }
+
+    #[test]
+    fn let_bindings() {
+        let (jit, compiled) = compile_jit(
+            "(func poly ((x i64)) (i64)
+               (let ((a (* x x)) (b (+ a 1)))
+                 (* a b)))
+             (func shadow ((x i64)) (i64)
+               (let ((a x))
+                 (+ (let ((a (* a 10))) a) a)))
+             (func fact ((n i64)) (i64)
+               (if n (let ((rest (call fact (- n 1)))) (* n rest)) 1))
+             (func early ((n i64)) (i64)
+               (let ((m (- n 1)))
+                 (if (< m 0) (return 0) (let ((square (* m m))) (+ square n)))))
+             (func block ((n i64)) (i64)
+               (jump (next (+ n 1)))
+               (block next ((k i64))
+                 (let ((sum (+ n k))) (if (> sum 10) (return 10) (return sum)))))",
+        )
+        .unwrap();
+        let get = |i: usize| {
+            let code = jit.get_finalized_function(compiled.functions[i].1);
+            unsafe { std::mem::transmute::<*const u8, extern "C" fn(i64) -> i64>(code) }
+        };
+        let (poly, shadow, fact, early, block) = (get(0), get(1), get(2), get(3), get(4));
+        assert_eq!(poly(3), 90);
+        assert_eq!(shadow(2), 22);
+        assert_eq!(fact(5), 120);
+        assert_eq!([early(0), early(3)], [0, 7]);
+        assert_eq!([block(2), block(7)], [5, 10]);
+    }
+
+    #[test]
+    fn loops() {
+        let (jit, compiled) = compile_jit(
+            "(func fact ((n i64)) (i64)
+               (loop ((i n) (acc 1))
+                 (if (> i 1) (continue (- i 1) (* acc i)) acc)))
+             (func narrow ((n i32)) (i32)
+               (loop ((i n) (acc i32 1))
+                 (if (> i 1) (continue (- i 1) (* acc i)) acc)))
+             (func triangle ((n i64)) (i64)
+               (loop ((i n) (sum 0))
+                 (if (> i 0)
+                   (continue (- i 1) (+ sum (loop ((j i) (k 0))
+                                              (if (> j 0) (continue (- j 1) (+ k 1)) k))))
+                   sum)))
+             (func find ((n i64)) (i64)
+               (loop ((i 0))
+                 (let ((square (* i i)))
+                   (if (>= square n) (return i) (continue (+ i 1))))))",
+        )
+        .unwrap();
+        let get = |i: usize| jit.get_finalized_function(compiled.functions[i].1);
+        let fact = unsafe { std::mem::transmute::<*const u8, extern "C" fn(i64) -> i64>(get(0)) };
+        let narrow = unsafe { std::mem::transmute::<*const u8, extern "C" fn(i32) -> i32>(get(1)) };
+        let triangle =
+            unsafe { std::mem::transmute::<*const u8, extern "C" fn(i64) -> i64>(get(2)) };
+        let find = unsafe { std::mem::transmute::<*const u8, extern "C" fn(i64) -> i64>(get(3)) };
+        assert_eq!([fact(0), fact(1), fact(5)], [1, 1, 120]);
+        assert_eq!(fact(20), 2_432_902_008_176_640_000);
+        assert_eq!(narrow(30), 1_409_286_144);
+        assert_eq!([triangle(0), triangle(4), triangle(100)], [0, 10, 5050]);
+        assert_eq!([find(0), find(10), find(49)], [0, 4, 7]);
+
+        // A loop without an exit still ends every block, with its back edge.
+        let (module, _) =
+            frontend("(func spin ((n i64)) (i64) (loop ((i n)) (continue (+ i 1))))").unwrap();
+        let func = lower(&module.functions[0], &module.constants, &mut jit_module()).unwrap();
+        cranelift_codegen::verify_function(&func, &settings::Flags::new(settings::builder()))
+            .unwrap();
+        assert_eq!(func.layout.blocks().count(), 2);
+    }
+
+    #[test]
+    fn whiles() {
+        let (jit, compiled) = compile_jit(
+            "(func sum ((n i64)) (i64)
+               (stack i 8)
+               (stack acc 8)
+               (stack_store i64 i 0 0)
+               (stack_store i64 acc 0 0)
+               (while (< (stack_load i64 i 0) n)
+                 (stack_store i64 acc 0 (+ (stack_load i64 acc 0) (+ (* n 2) (stack_load i64 i 0))))
+                 (stack_store i64 i 0 (+ (stack_load i64 i 0) 1)))
+               (stack_load i64 acc 0))
+             (func odds ((n i32)) (i32)
+               (stack s 8)
+               (stack_store i32 s 0 0)
+               (stack_store i32 s 4 0)
+               (while (< (stack_load i32 s 0) n)
+                 (stack_store i32 s 0 (+ (stack_load i32 s 0) 1))
+                 (if (band (stack_load i32 s 0) 1)
+                   (stack_store i32 s 4 (+ (stack_load i32 s 4) 1))
+                   (continue)))
+               (stack_load i32 s 4))
+             (func root ((n i64)) (i64)
+               (stack i 8)
+               (stack_store i64 i 0 0)
+               (while 1
+                 (let ((i (stack_load i64 i 0)))
+                   (if (>= (* i i) n) (return i) (stack_store i64 i 0 (+ i 1)))))
+               -1)
+             (func grid ((n i32)) (i32)
+               (stack s 12)
+               (stack_store i32 s 0 0)
+               (stack_store i32 s 8 0)
+               (while (< (stack_load i32 s 0) n)
+                 (stack_store i32 s 4 0)
+                 (while (< (stack_load i32 s 4) n)
+                   (stack_store i32 s 8 (+ (stack_load i32 s 8) 1))
+                   (stack_store i32 s 4 (+ (stack_load i32 s 4) 1)))
+                 (stack_store i32 s 0 (+ (stack_load i32 s 0) 1)))
+               (stack_load i32 s 8))
+             (func ended ((n i32)) (i8)
+               (while (> n 0) (return 7)))",
+        )
+        .unwrap();
+        let get = |i: usize| jit.get_finalized_function(compiled.functions[i].1);
+        let sum = unsafe { std::mem::transmute::<*const u8, extern "C" fn(i64) -> i64>(get(0)) };
+        let odds = unsafe { std::mem::transmute::<*const u8, extern "C" fn(i32) -> i32>(get(1)) };
+        let root = unsafe { std::mem::transmute::<*const u8, extern "C" fn(i64) -> i64>(get(2)) };
+        let grid = unsafe { std::mem::transmute::<*const u8, extern "C" fn(i32) -> i32>(get(3)) };
+        let ended = unsafe { std::mem::transmute::<*const u8, extern "C" fn(i32) -> i8>(get(4)) };
+        // The invariant `(* n 2)` is added on every iteration.
+        assert_eq!([sum(0), sum(1), sum(10)], [0, 2, 245]);
+        assert_eq!([odds(0), odds(1), odds(10)], [0, 1, 5]);
+        assert_eq!([root(0), root(10), root(49)], [0, 4, 7]);
+        assert_eq!([grid(0), grid(1), grid(7)], [0, 1, 49]);
+        // A `while` which ends has the value of its condition, zero.
+        assert_eq!([ended(-1), ended(5)], [0, 7]);
+
+        for (src, message) in [
+            (
+                "(func f ((x i64)) (i64) (while x (+ x 1)) x)",
+                "the condition of `while` has type i64, not i8 or i32",
+            ),
+            (
+                "(func f ((x f64)) (f64) (while x (fadd x 1.0)) x)",
+                "the condition of `while` has type f64, not i8 or i32",
+            ),
+            (
+                "(func f ((x i64)) (i64) (while (< x 0)) x)",
+                "expected `(while cond body...)`, with at least one expression in the body",
+            ),
+            (
+                "(func f ((x i64)) (i64) (while) x)",
+                "expected `(while cond body...)`, with at least one expression in the body",
+            ),
+            (
+                "(func f ((x i64)) (i64) (while (< x 0) (continue x)) x)",
+                "`continue` takes 0 operands, not 1",
+            ),
+            (
+                "(func f ((x i64)) (i64) (while (< x 0) (continue) x) x)",
+                "`continue` can only be the last expression of the body of a `loop` or a \
+                 `while`, or of a branch of `if` or a `let` there",
+            ),
+        ] {
+            let (module, _) = frontend(src).unwrap();
+            let err = compile(&mut jit_module(), &module).err().unwrap();
+            assert_eq!(
+                err.to_string(),
+                format!("in function `f`: {message}"),
+                "{src}"
+            );
+        }
+
+        // The error is at the condition.
+        let src = "(func f ((x i64)) (i64) (while (+ x 1) x) x)";
+        let (module, _) = frontend(src).unwrap();
+        match compile(&mut jit_module(), &module) {
+            Err(CompileError::Diagnostics(diagnostics)) => {
+                let span = diagnostics[0].span;
+                assert_eq!(&src[span.start..span.end], "(+ x 1)");
+            }
+            _ => panic!("{src:?} compiles"),
+        }
+    }
+
+    // Tail calls are only lowered on x86_64.
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn tail_calls() {
+        let src = "(func fact ((n i64)) (i64)
+                     (call go n 1))
+                   (func go ((n i64) (acc i64)) (i64)
+                     (if (> n 1) (tail_call go (- n 1) (* acc n)) acc))
+                   (func parity ((n i64)) (i8)
+                     (call even n))
+                   (func even ((n i64)) (i8)
+                     (if n (tail_call odd (- n 1)) 1))
+                   (func odd ((n i64)) (i8)
+                     (if n (tail_call even (- n 1)) 0))";
+        // Tail calls on x86_64 rely on frame pointers.
+        let flags = [("preserve_frame_pointers", "true")];
+        let mut jit =
+            JITModule::new(JITBuilder::with_flags(&flags, default_libcall_names()).unwrap());
+        let (module, _) = frontend(src).unwrap();
+        let compiled = compile(&mut jit, &module).unwrap();
+        jit.finalize_definitions().unwrap();
+        let get = |i: usize| jit.get_finalized_function(compiled.functions[i].1);
+        let fact = unsafe { std::mem::transmute::<*const u8, extern "C" fn(i64) -> i64>(get(0)) };
+        let parity = unsafe { std::mem::transmute::<*const u8, extern "C" fn(i64) -> i8>(get(2)) };
+        assert_eq!([fact(0), fact(5)], [1, 120]);
+        assert_eq!(fact(20), 2_432_902_008_176_640_000);
+        // Far more calls than frames would fit on the stack of the test thread.
+        let n = 10_000_000;
+        assert_eq!(fact(n), (2..=n).fold(1i64, |acc, i| acc.wrapping_mul(i)));
+        assert_eq!([parity(n), parity(n + 1)], [1, 0]);
+
+        // Only the functions at either end of a tail call use the `tail` calling convention.
+        let clif = module.to_clif().unwrap();
+        let call_conv = jit.isa().default_call_conv();
+        assert!(clif.contains(&format!("function u0:0(i64) -> i64 {call_conv} ")));
+        assert!(
+            clif.contains("function u0:1(i64, i64) -> i64 tail "),
+            "{clif}"
+        );
+        assert!(clif.contains("return_call fn0(v"), "{clif}");
+        assert!(clif.contains("function u0:4(i64) -> i8 tail "), "{clif}");
+
+        // A function declared without the `tail` calling convention can't be tail-called.
+        let (module, _) = frontend(
+            "(func f ((x i64)) (i64) (tail_call g x))
+             (func g ((x i64)) (i64) x)",
+        )
+        .unwrap();
+        let mut jit = jit_module();
+        let g = &module.functions[1];
+        let sig = g.signature(call_conv).unwrap();
+        jit.declare_function(&g.name, Linkage::Export, &sig)
+            .unwrap();
+        assert_eq!(
+            lower(&module.functions[0], &module.constants, &mut jit)
+                .unwrap_err()
+                .to_string(),
+            format!(
+                "in function `f`: `tail_call` needs the `tail` calling convention, but the \
+                 function uses `tail` and `g` uses `{call_conv}`"
+            )
+        );
+    }
+
+    #[test]
+    fn tail_calls_need_frame_pointers() {
+        let src = "(func go ((n i64) (acc i64)) (i64)
+                     (if (> n 1) (tail_call go (- n 1) (* acc n)) acc))
+                   (func even ((n i64)) (i8)
+                     (if n (tail_call odd (- n 1)) 1))
+                   (func odd ((n i64)) (i8)
+                     (if n (tail_call even (- n 1)) 0))";
+        let (module, _) = frontend(src).unwrap();
+        match compile(&mut jit_module(), &module) {
+            Err(CompileError::Diagnostics(diagnostics)) => {
+                let messages: Vec<_> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+                assert_eq!(
+                    messages,
+                    ["go", "even", "odd"].map(|name| format!(
+                        "in function `{name}`: `tail_call` needs the `preserve_frame_pointers` \
+                         setting"
+                    ))
+                );
+                let span = diagnostics[0].span;
+                assert_eq!(
+                    &src[span.start..span.end],
+                    "(tail_call go (- n 1) (* acc n))"
+                );
+            }
+            _ => panic!("tail calls compile without frame pointers"),
+        }
+    }
+
+    #[test]
+    fn calls_between_functions() {
+        let (jit, compiled) = compile_jit(
+            "(func main () (i64) (call twice (call square 3)))
+             (func square ((x i64)) (i64) (imul x x))
+             (func twice ((x i64)) (i64) (ishl x 1))",
+        )
+        .unwrap();
+        let code = jit.get_finalized_function(compiled.functions[0].1);
+        let main = unsafe { std::mem::transmute::<*const u8, extern "C" fn() -> i64>(code) };
+        assert_eq!(main(), 18);
+    }
+
+    #[test]
+    fn several_results() {
+        // Functions with several results are called from clifp, which has no ABI to get wrong.
+        let (jit, compiled) = compile_jit(
+            "(func divmod ((a i32) (b i32)) (i32 i32)
+               (loop ((q i32 0) (r a))
+                 (if (>= r b) (continue (+ q 1) (- r b)) (return q r))))
+             (func digits ((a i32) (b i32)) (i32)
+               (let (((q r) (call divmod a b)) (q (* q 100)))
+                 (+ q r)))
+             (func swap ((x i64) (y i64)) (i64 i64)
+               (return y x))
+             (func sub ((x i64) (y i64)) (i64)
+               (let (((b a) (call swap x y)))
+                 (- a b)))",
+        )
+        .unwrap();
+        let get = |i: usize| jit.get_finalized_function(compiled.functions[i].1);
+        let digits =
+            unsafe { std::mem::transmute::<*const u8, extern "C" fn(i32, i32) -> i32>(get(1)) };
+        let sub =
+            unsafe { std::mem::transmute::<*const u8, extern "C" fn(i64, i64) -> i64>(get(3)) };
+        assert_eq!([digits(47, 5), digits(10, 3), digits(2, 7)], [902, 301, 2]);
+        assert_eq!(sub(10, 3), 7);
+
+        let (module, _) = frontend("(func swap ((x i64) (y i64)) (i64 i64) (return y x))").unwrap();
+        let clif = module.to_clif().unwrap();
+        assert!(clif.contains("(i64, i64) -> i64, i64"), "{clif}");
+        assert!(clif.contains("return v1, v0"), "{clif}");
+    }
+
+    extern "C" fn double(x: i32) -> i32 {
+        x * 2
+    }
+
+    #[test]
+    fn host_functions() {
+        let src = "(extern double ((i32)) (i32))
+                   (func main ((x i32)) (i32) (+ (call double x) 1))";
+        let (module, _) = frontend(src).unwrap();
+        let mut builder = JITBuilder::new(default_libcall_names()).unwrap();
+        builder.symbol("double", double as *const u8);
+        let mut jit = JITModule::new(builder);
+        let compiled = compile(&mut jit, &module).unwrap();
+        jit.finalize_definitions().unwrap();
+        assert_eq!(compiled.functions.len(), 1);
+        let code = jit.get_finalized_function(compiled.functions[0].1);
+        let main = unsafe { std::mem::transmute::<*const u8, extern "C" fn(i32) -> i32>(code) };
+        assert_eq!([main(20), main(-3)], [41, -5]);
+
+        // The host function is imported, not defined.
+        let clif = module.to_clif().unwrap();
+        assert!(clif.contains("fn0 = u0:1 sig0"), "{clif}");
+
+        // Without the symbol, finalizing fails with its name.
+        let mut jit = jit_module();
+        let src = "(extern clifp_missing_host ((i32)) (i32))
+                   (func main ((x i32)) (i32) (call clifp_missing_host x))";
+        let (module, _) = frontend(src).unwrap();
+        compile(&mut jit, &module).unwrap();
+        assert_eq!(
+            jit.finalize_definitions().unwrap_err().to_string(),
+            "Backend error: can't resolve symbol clifp_missing_host"
+        );
+
+        let (module, _) =
+            frontend("(extern f ((i32)) (i32)) (func g () (i32) (call f 1 2))").unwrap();
+        assert_eq!(
+            compile(&mut jit_module(), &module)
+                .err()
+                .unwrap()
+                .to_string(),
+            "in function `g`: `call f` takes 1 operand, not 2"
+        );
+        // `compile` checks the types of host functions too, for modules which skip `frontend`.
+        let module = Module::parse("(extern f ((u8)) ())").unwrap();
+        assert_eq!(
+            compile(&mut jit_module(), &module)
+                .err()
+                .unwrap()
+                .to_string(),
+            "in extern `f`: unknown type `u8` of parameter 0"
+        );
+    }
+
+    #[test]
+    fn operators() {
+        let (jit, compiled) = compile_jit(
+            "(func add ((a i64) (b i64)) (i64) (+ a b))
+             (func sub ((a i64) (b i64)) (i64) (- a b))
+             (func mul ((a i64) (b i64)) (i64) (* a b))
+             (func eq ((a i64) (b i64)) (i8) (= a b))
+             (func ne ((a i64) (b i64)) (i8) (!= a b))
+             (func lt ((a i64) (b i64)) (i8) (< a b))
+             (func le ((a i64) (b i64)) (i8) (<= a b))
+             (func gt ((a i64) (b i64)) (i8) (> a b))
+             (func ge ((a i64) (b i64)) (i8) (>= a b))
+             (func nested ((n i64) (unused i64)) (i64) (* n (+ n 1)))
+             (func max ((a i64) (b i64)) (i64)
+               (brif (< a b) (second) (first))
+               (block first () a)
+               (block second () b))
+             (func small ((a i64) (b i64)) (i64) (if (< (- a b) 10) 1 0))",
+        )
+        .unwrap();
+        let func = |i: usize| {
+            let code = jit.get_finalized_function(compiled.functions[i].1);
+            unsafe { std::mem::transmute::<*const u8, extern "C" fn(i64, i64) -> i64>(code) }
+        };
+        let cmp = |i: usize| {
+            let code = jit.get_finalized_function(compiled.functions[i].1);
+            unsafe { std::mem::transmute::<*const u8, extern "C" fn(i64, i64) -> i8>(code) }
+        };
+        assert_eq!(func(0)(7, -3), 4);
+        assert_eq!(func(1)(7, -3), 10);
+        assert_eq!(func(2)(7, -3), -21);
+        for (a, b) in [(1, 2), (2, 2), (2, 1), (-1, 1)] {
+            assert_eq!(cmp(3)(a, b), (a == b) as i8);
+            assert_eq!(cmp(4)(a, b), (a != b) as i8);
+            assert_eq!(cmp(5)(a, b), (a < b) as i8);
+            assert_eq!(cmp(6)(a, b), (a <= b) as i8);
+            assert_eq!(cmp(7)(a, b), (a > b) as i8);
+            assert_eq!(cmp(8)(a, b), (a >= b) as i8);
+        }
+        assert_eq!(func(9)(6, 0), 42);
+        assert_eq!(func(10)(3, 8), 8);
+        assert_eq!(func(10)(-3, -8), -3);
+        assert_eq!(func(11)(15, 10), 1);
+        assert_eq!(func(11)(25, 10), 0);
+    }
+
+    #[test]
+    fn divisions() {
+        let (jit, compiled) = compile_jit(
+            "(func sdiv ((a i64) (b i64)) (i64) (sdiv a b))
+             (func udiv ((a i64) (b i64)) (i64) (udiv a b))
+             (func srem ((a i64) (b i64)) (i64) (srem a b))
+             (func urem ((a i64) (b i64)) (i64) (urem a b))
+             (func halves ((a i32)) (i32) (+ (sdiv a 2) (srem -7 a)))
+             (func unsigned () (i32) (udiv 4294967295 (i32 0x10)))",
+        )
+        .unwrap();
+        let func = |i: usize| {
+            let code = jit.get_finalized_function(compiled.functions[i].1);
+            unsafe { std::mem::transmute::<*const u8, extern "C" fn(i64, i64) -> i64>(code) }
+        };
+        // The signed divisions round toward zero, and the remainder has the sign of the dividend.
+        assert_eq!(func(0)(-7, 2), -3);
+        assert_eq!(func(1)(-7, 2), i64::MAX - 3);
+        assert_eq!(func(2)(-7, 2), -1);
+        assert_eq!(func(3)(-7, 2), 1);
+        assert_eq!(func(2)(7, -2), 1);
+        // The remainder of the smallest integer by -1 is 0, although dividing them overflows.
+        assert_eq!(func(2)(i64::MIN, -1), 0);
+        let get = |i: usize| jit.get_finalized_function(compiled.functions[i].1);
+        unsafe {
+            let halves = std::mem::transmute::<*const u8, extern "C" fn(i32) -> i32>(get(4));
+            assert_eq!(halves(4), -1);
+            let unsigned = std::mem::transmute::<*const u8, extern "C" fn() -> i32>(get(5));
+            assert_eq!(unsigned(), 0x0fff_ffff);
+        }
+
+        // A literal divisor of zero is an error, where the divisions of unsigned integers also
+        // read their literals as unsigned.
+        for (src, message, at) in [
+            (
+                "(func f ((x i64)) (i64) (sdiv x 0))",
+                "in function `f`: `sdiv` by zero, which always traps",
+                "0",
+            ),
+            (
+                "(const ZERO 0) (func f ((x i32)) (i32) (urem x (i32 ZERO)))",
+                "in function `f`: `urem` by zero, which always traps",
+                "(i32 ZERO)",
+            ),
+            (
+                "(func f ((x i32)) (i32) (udiv x -1))",
+                "in function `f`: literal -1 does not fit in i32 as an unsigned integer",
+                "-1",
+            ),
+            (
+                "(func f ((x i32)) (i32) (srem 0xffff_ffff x))",
+                "in function `f`: literal 4294967295 does not fit in i32 as a signed integer",
+                "0xffff_ffff",
+            ),
+            (
+                "(func f ((x f64)) (f64) (sdiv x x))",
+                "in function `f`: `sdiv` of f64, which isn't an integer type",
+                "(sdiv x x)",
+            ),
+        ] {
+            let (module, _) = frontend(src).unwrap();
+            let diagnostics = match compile(&mut jit_module(), &module) {
+                Err(CompileError::Diagnostics(diagnostics)) => diagnostics,
+                _ => panic!("{src} compiled"),
+            };
+            assert_eq!(diagnostics.len(), 1, "{src}");
+            assert_eq!(diagnostics[0].message, message, "{src}");
+            let span = diagnostics[0].span;
+            assert_eq!(&src[span.start..span.end], at, "{src}");
+        }
+        // A divisor which is zero once it's computed traps when the function runs instead.
+        assert!(compile_jit("(func f ((x i64)) (i64) (sdiv x (- 1 1)))").is_ok());
+    }
+
+    #[test]
+    fn floats() {
+        let (jit, compiled) = compile_jit(
+            "(func poly ((x f64)) (f64)
+               (+ (* (- (* 2.5 x) 3.0) (* x x)) (fdiv x 7.0)))
+             (func horner ((x f64)) (f64)
+               (loop ((i 4) (acc 0.0))
+                 (if i (continue (- i 1) (+ (* acc x) (fcvt_from_sint f64 i))) acc)))
+             (func half ((x f32)) (f32) (* x 0.1))
+             (func scale ((n i32)) (i32)
+               (fcvt_to_sint i32 (fmul (fcvt_from_sint f64 n) -1.5)))",
+        )
+        .unwrap();
+        let code = |i: usize| jit.get_finalized_function(compiled.functions[i].1);
+        let poly = unsafe { std::mem::transmute::<_, extern "C" fn(f64) -> f64>(code(0)) };
+        let horner = unsafe { std::mem::transmute::<_, extern "C" fn(f64) -> f64>(code(1)) };
+        let half = unsafe { std::mem::transmute::<_, extern "C" fn(f32) -> f32>(code(2)) };
+        let scale = unsafe { std::mem::transmute::<_, extern "C" fn(i32) -> i32>(code(3)) };
+        // The same operations in the same order round the same way, so the results are equal.
+        for x in [0.0, 1.0, -0.3, 1.1, 1e10, -7.25, f64::MIN_POSITIVE] {
+            assert_eq!(poly(x), (2.5 * x - 3.0) * (x * x) + x / 7.0);
+            assert_eq!(horner(x), (((0.0 * x + 4.0) * x + 3.0) * x + 2.0) * x + 1.0);
+        }
+        assert!(poly(f64::NAN).is_nan());
+        assert_eq!(half(3.0), 3.0 * 0.1f32);
+        assert_eq!(scale(7), -10);
+        assert_eq!(scale(-1_000_001), 1_500_001);
+    }
+
+    #[test]
+    fn literals() {
+        let (jit, compiled) = compile_jit(
+            "(func i32_max () (i32) 2147483647)
+             (func i32_min () (i32) -2147483648)
+             (func u32_max () (i32) 4294967295)
+             (func i64_max () (i64) 9223372036854775807)
+             (func i64_min () (i64) -9223372036854775808)
+             (func u64_max () (i64) 18446744073709551615)
+             (func typed ((x i32)) (i64) (+ (i64 5_000_000_000) 1))
+             (func half () (f32) (f32 0.5))
+             (func all_ones ((x i32)) (i32) (ushr (i32 -1) x))
+             (func wide () (i8)
+               (= (i128 18446744073709551616) (+ (i128 18446744073709551615) 1)))",
+        )
+        .unwrap();
+        let get = |i: usize| jit.get_finalized_function(compiled.functions[i].1);
+        unsafe {
+            let call_i32 = |i| std::mem::transmute::<*const u8, extern "C" fn() -> i32>(get(i))();
+            let call_i64 = |i| std::mem::transmute::<*const u8, extern "C" fn() -> i64>(get(i))();
+            assert_eq!(
+                [call_i32(0), call_i32(1), call_i32(2)],
+                [i32::MAX, i32::MIN, -1]
+            );
+            assert_eq!(
+                [call_i64(3), call_i64(4), call_i64(5)],
+                [i64::MAX, i64::MIN, -1]
+            );
+            let typed = std::mem::transmute::<*const u8, extern "C" fn(i32) -> i64>(get(6));
+            assert_eq!(typed(0), 5_000_000_001);
+            let half = std::mem::transmute::<*const u8, extern "C" fn() -> f32>(get(7));
+            assert_eq!(half(), 0.5);
+            let all_ones = std::mem::transmute::<*const u8, extern "C" fn(i32) -> i32>(get(8));
+            assert_eq!(all_ones(28), 0xf);
+            let wide = std::mem::transmute::<*const u8, extern "C" fn() -> i8>(get(9));
+            assert_eq!(wide(), 1);
+        }
+
+        for (src, message) in [
+            (
+                "(func f () (i32) 4294967296)",
+                "literal 4294967296 does not fit in i32",
+            ),
+            (
+                "(func f () (i32) -2147483649)",
+                "literal -2147483649 does not fit in i32",
+            ),
+            (
+                "(func f () (i64) 18446744073709551616)",
+                "literal 18446744073709551616 does not fit in i64",
+            ),
+            (
+                "(func f () (i64) -9223372036854775809)",
+                "literal -9223372036854775809 does not fit in i64",
+            ),
+            ("(func f () (i8) 256)", "literal 256 does not fit in i8"),
+            (
+                "(func f () (i32) (i32 5_000_000_000))",
+                "literal 5000000000 does not fit in i32",
+            ),
+            (
+                "(func f ((x i32)) (i8) (< x 4294967295))",
+                "literal 4294967295 does not fit in i32 as a signed integer",
+            ),
+            (
+                "(func f ((x i32)) (i8) (< x 2147483648))",
+                "literal 2147483648 does not fit in i32 as a signed integer",
+            ),
+            (
+                "(func f ((x i32)) (i32) (ushr -1 x))",
+                "literal -1 does not fit in i32 as an unsigned integer",
+            ),
+            (
+                "(func f ((x i64)) (i64) (ishl x -1))",
+                "literal -1 does not fit in i64 as an unsigned integer",
+            ),
+            (
+                "(func f ((x i64)) (i64) (i64 x))",
+                "`(i64 literal)` takes a literal or a constant, not `x`",
+            ),
+            (
+                "(func f () (i64) (i64 1 2))",
+                "`i64` takes 1 operand, not 2",
+            ),
+            (
+                "(func f () (i32) (i64 1))",
+                "the body has type i64, not the result type i32",
+            ),
+        ] {
+            let (module, _) = frontend(src).unwrap();
+            let err = compile(&mut jit_module(), &module).err().unwrap();
+            assert_eq!(
+                err.to_string(),
+                format!("in function `f`: {message}"),
+                "{src}"
+            );
+        }
+
+        // Wide literals are made of 64-bit halves, and narrow ones are sign-extended.
+        let (module, _) = frontend(
+            "(func f () (i128) 18446744073709551616)
+             (func g () (i32) 4294967295)",
+        )
+        .unwrap();
+        let clif = module.to_clif().unwrap();
+        assert!(clif.contains("iconcat"), "{clif}");
+        assert!(clif.contains("iconst.i32 -1"), "{clif}");
+    }
+
+    #[test]
+    fn constants() {
+        let (jit, compiled) = compile_jit(
+            "(const LIMIT 1000)
+             (const BIG (* LIMIT 5_000_000))
+             (const OFFSET 4)
+             (const SIZE (+ OFFSET 4))
+             (func scale ((x i64)) (i64) (imul x LIMIT))
+             (func clamp ((x i32)) (i32) (if (icmp_imm sgt x LIMIT) LIMIT x))
+             (func big () (i64) (+ BIG 1))
+             (func stored ((x i32)) (i32)
+               (stack buf SIZE)
+               (stack_store i32 buf OFFSET x)
+               (stack_load i32 buf OFFSET))
+             (func shadowed ((LIMIT i64)) (i64) (+ LIMIT 1))
+             (func bound ((x i64)) (i64)
+               (+ (let ((LIMIT x)) LIMIT) LIMIT))
+             (func typed () (i8) (i8 OFFSET))",
+        )
+        .unwrap();
+        let get = |i: usize| jit.get_finalized_function(compiled.functions[i].1);
+        unsafe {
+            let scale = std::mem::transmute::<*const u8, extern "C" fn(i64) -> i64>(get(0));
+            assert_eq!(scale(3), 3000);
+            let clamp = std::mem::transmute::<*const u8, extern "C" fn(i32) -> i32>(get(1));
+            assert_eq!([clamp(7), clamp(1001)], [7, 1000]);
+            let big = std::mem::transmute::<*const u8, extern "C" fn() -> i64>(get(2));
+            assert_eq!(big(), 5_000_000_001);
+            let stored = std::mem::transmute::<*const u8, extern "C" fn(i32) -> i32>(get(3));
+            assert_eq!(stored(-9), -9);
+            let shadowed = std::mem::transmute::<*const u8, extern "C" fn(i64) -> i64>(get(4));
+            assert_eq!(shadowed(41), 42);
+            let bound = std::mem::transmute::<*const u8, extern "C" fn(i64) -> i64>(get(5));
+            assert_eq!(bound(2), 1002);
+            let typed = std::mem::transmute::<*const u8, extern "C" fn() -> i8>(get(6));
+            assert_eq!(typed(), 4);
+        }
+
+        // Constants are checked against their types wherever they're used, as literals are.
+        for (src, message) in [
+            (
+                "(const BIG 5_000_000_000) (func f () (i32) BIG)",
+                "constant `BIG`, which is 5000000000, does not fit in i32",
+            ),
+            (
+                "(const BIG 5_000_000_000) (func f ((x i32)) (i32) (iadd x BIG))",
+                "constant `BIG`, which is 5000000000, does not fit in i32",
+            ),
+            (
+                "(const BIG 5_000_000_000) (func f ((x i32)) (i8) (icmp_imm eq x BIG))",
+                "constant `BIG`, which is 5000000000, does not fit in i32",
+            ),
+            (
+                "(const MAX 0xffff_ffff) (func f ((x i32)) (i8) (< x MAX))",
+                "constant `MAX`, which is 4294967295, does not fit in i32 as a signed integer",
+            ),
+            (
+                "(const HALF 1) (func f () (f64) (fadd 0.5 HALF))",
+                "integer literals can't have type f64",
+            ),
+            (
+                "(const FAR 0x1_0000_0000) (func f ((x i64)) (i64) (load i64 x FAR))",
+                "the offset 4294967296 of `load` is too large",
+            ),
+            ("(func f () (i64) LIMIT)", "undefined name `LIMIT`"),
+        ] {
+            let (module, _) = frontend(src).unwrap();
+            let err = compile(&mut jit_module(), &module).err().unwrap();
+            assert_eq!(
+                err.to_string(),
+                format!("in function `f`: {message}"),
+                "{src}"
+            );
+        }
+
+        // The error is at the use of the constant.
+        let src = "(const BIG 5_000_000_000)\n(func f ((x i32)) (i32) (iadd x BIG))";
+        let (module, _) = frontend(src).unwrap();
+        match compile(&mut jit_module(), &module) {
+            Err(CompileError::Diagnostics(diagnostics)) => {
+                let span = diagnostics[0].span;
+                assert_eq!(&src[span.start..span.end], "BIG");
+                assert_eq!(span.start, 58);
+            }
+            _ => panic!("{src:?} compiles"),
+        }
+    }
+
+    #[test]
+    fn condition_codes() {
+        let mut src = String::new();
+        for (name, _) in CONDITION_CODES {
+            src.push_str(&format!(
+                "(func {name} ((x i64) (y i64)) (i8) (icmp {name} x y))\n\
+                 (func {name}_imm ((x i64)) (i8) (icmp_imm {name} x 1))\n"
+            ));
+        }
+        src.push_str(
+            "(func narrow ((x i32)) (i8) (icmp_imm ult x 4294967295))
+             (func wide () (i8)
+               (band (icmp ugt (i128 -1) 1) (icmp_imm sgt (i128 -1) -5)))
+             (func branch ((x i64)) (i64) (if (icmp sle x -1) 10 20))",
+        );
+        let (jit, compiled) = compile_jit(&src).unwrap();
+        let get = |name: &str| {
+            let &(_, id) = compiled.functions.iter().find(|(n, _)| n == name).unwrap();
+            jit.get_finalized_function(id)
+        };
+
+        // -1 and 1 are ordered one way as signed integers and the other way as unsigned ones.
+        let pairs = [(-1, 1), (1, -1), (1, 1), (-1, -1), (0, 1), (i64::MIN, 1)];
+        for (name, _) in CONDITION_CODES {
+            let expected = |x: i64, y: i64| {
+                let (ux, uy) = (x as u64, y as u64);
+                match *name {
+                    "eq" => x == y,
+                    "ne" => x != y,
+                    "slt" => x < y,
+                    "sle" => x <= y,
+                    "sgt" => x > y,
+                    "sge" => x >= y,
+                    "ult" => ux < uy,
+                    "ule" => ux <= uy,
+                    "ugt" => ux > uy,
+                    _ => ux >= uy,
+                }
+            };
+            let (icmp, icmp_imm) = unsafe {
+                (
+                    std::mem::transmute::<*const u8, extern "C" fn(i64, i64) -> i8>(get(name)),
+                    std::mem::transmute::<*const u8, extern "C" fn(i64) -> i8>(get(&format!(
+                        "{name}_imm"
+                    ))),
+                )
+            };
+            for (x, y) in pairs {
+                assert_eq!(icmp(x, y), expected(x, y) as i8, "({name} {x} {y})");
+                assert_eq!(icmp_imm(x), expected(x, 1) as i8, "({name}_imm {x})");
+            }
+        }
+        unsafe {
+            let narrow = std::mem::transmute::<*const u8, extern "C" fn(i32) -> i8>(get("narrow"));
+            assert_eq!([narrow(-1), narrow(1)], [0, 1]);
+            let wide = std::mem::transmute::<*const u8, extern "C" fn() -> i8>(get("wide"));
+            assert_eq!(wide(), 1);
+            let branch = std::mem::transmute::<*const u8, extern "C" fn(i64) -> i64>(get("branch"));
+            assert_eq!([branch(-2), branch(-1), branch(0)], [10, 10, 20]);
+        }
+
+        for (src, message) in [
+            (
+                "(func f ((x i64)) (i8) (icmp less x 1))",
+                "unknown condition code `less`, expected one of `eq`, `ne`, `slt`, `sle`, `sgt`, \
+                 `sge`, `ult`, `ule`, `ugt`, `uge`",
+            ),
+            (
+                "(func f ((x i64)) (i8) (icmp_imm (ult) x 1))",
+                "unknown condition code `(ult)`, expected one of `eq`, `ne`, `slt`, `sle`, `sgt`, \
+                 `sge`, `ult`, `ule`, `ugt`, `uge`",
+            ),
+            (
+                "(func f ((x i64)) (i8) (icmp eq x))",
+                "`icmp` takes 3 operands, not 2",
+            ),
+            (
+                "(func f ((x i64)) (i8) (icmp eq x (i32 1)))",
+                "`icmp` of i64 and i32",
+            ),
+            (
+                "(func f ((x f64)) (i8) (icmp eq x 1.5))",
+                "`icmp` of f64, which isn't an integer type",
+            ),
+            (
+                "(func f ((x i64) (y i64)) (i8) (icmp_imm eq x y))",
+                "the immediate of `icmp_imm` is an integer literal or a constant, not `y`",
+            ),
+            (
+                "(func f ((x i64)) (i8) (icmp_imm ult x -1))",
+                "literal -1 does not fit in i64 as an unsigned integer",
+            ),
+            (
+                "(func f ((x i32)) (i8) (icmp_imm sgt x 4294967295))",
+                "literal 4294967295 does not fit in i32 as a signed integer",
+            ),
+            (
+                "(func f () (i8) (icmp_imm eq (i128 0) 9223372036854775808))",
+                "the immediate of `icmp_imm` of i128 is 64 bits, which 9223372036854775808 \
+                 doesn't fit in",
+            ),
+            (
+                "(func f ((x i32x4)) (i32x4) (icmp_imm eq x 1))",
+                "`icmp_imm` of i32x4, which isn't a scalar integer type",
+            ),
+        ] {
+            let (module, _) = frontend(src).unwrap();
+            let err = compile(&mut jit_module(), &module).err().unwrap();
+            assert_eq!(
+                err.to_string(),
+                format!("in function `f`: {message}"),
+                "{src}"
+            );
+        }
+    }
+
+    #[test]
+    fn stack_slots() {
+        let (jit, compiled) = compile_jit(
+            "(func sum ((x i32) (y i32)) (i32)
+               (stack buf 16)
+               (stack_store i32 buf 0 x)
+               (stack_store i32 buf 12 (* y 2))
+               (jump (done))
+               (block done ()
+                 (+ (stack_load i32 buf 0) (stack_load i32 buf 12))))
+             (func bytes () (i64)
+               (stack scratch 8)
+               (stack_store i64 scratch 0 0x0102_0304_0506_0708)
+               (stack_store i8 scratch 0 (+ (stack_load i8 scratch 7) 0x10))
+               (stack_load i64 scratch 0))",
+        )
+        .unwrap();
+        let code = |i: usize| jit.get_finalized_function(compiled.functions[i].1);
+        let sum = unsafe { std::mem::transmute::<_, extern "C" fn(i32, i32) -> i32>(code(0)) };
+        let bytes = unsafe { std::mem::transmute::<_, extern "C" fn() -> i64>(code(1)) };
+        assert_eq!(sum(30, 6), 42);
+        assert_eq!(sum(-1, i32::MAX), i32::MAX.wrapping_mul(2) - 1);
+        let mut expected = 0x0102_0304_0506_0708i64.to_ne_bytes();
+        expected[0] = expected[7] + 0x10;
+        assert_eq!(bytes(), i64::from_ne_bytes(expected));
+    }
+
+    #[test]
+    fn mutual_recursion() {
+        let (jit, compiled) = compile_jit(
+            "(func is_even ((n i64)) (i8) (if n (call is_odd (isub n 1)) 1))
+             (func is_odd ((n i64)) (i8) (if n (call is_even (isub n 1)) 0))",
+        )
+        .unwrap();
+        let code = jit.get_finalized_function(compiled.functions[0].1);
+        let is_even = unsafe { std::mem::transmute::<*const u8, extern "C" fn(i64) -> i8>(code) };
+        let code = jit.get_finalized_function(compiled.functions[1].1);
+        let is_odd = unsafe { std::mem::transmute::<*const u8, extern "C" fn(i64) -> i8>(code) };
+        for n in 0..20 {
+            assert_eq!(is_even(n), (n % 2 == 0) as i8, "{n}");
+            assert_eq!(is_odd(n), (n % 2 == 1) as i8, "{n}");
+        }
+    }
+
+    #[test]
+    fn constant_data() {
+        let (jit, compiled) = compile_jit(
+            "(data table (bytes 1 2 3 4))
+             (func sum () (i8)
+               (+ (+ (load i8 (symbol table) 0) (load i8 (symbol table) 1))
+                  (+ (load i8 (symbol table) 2) (load i8 (symbol table) 3))))",
+        )
+        .unwrap();
+        assert_eq!(compiled.data.len(), 1);
+        let code = jit.get_finalized_function(compiled.functions[0].1);
+        let sum = unsafe { std::mem::transmute::<*const u8, extern "C" fn() -> i8>(code) };
+        assert_eq!(sum(), 10);
+
+        // The same sum in a loop over the indices of the table, with the data defined after the
+        // function which refers to it.
+        let (jit, compiled) = compile_jit(
+            "(func sum ((n i64)) (i8)
+               (jump (loop 0 0))
+               (block loop ((i i64) (acc i8))
+                 (brif (< i n) (body i acc) (done acc)))
+               (block body ((i i64) (acc i8))
+                 (jump (loop (+ i 1) (+ acc (load i8 (+ (symbol table) i) 0)))))
+               (block done ((acc i8)) acc))
+             (data table (bytes 1 2 3 4))",
+        )
+        .unwrap();
+        let code = jit.get_finalized_function(compiled.functions[0].1);
+        let sum = unsafe { std::mem::transmute::<*const u8, extern "C" fn(i64) -> i8>(code) };
+        assert_eq!(sum(4), 10);
+        assert_eq!(sum(2), 3);
+    }
+
+    #[test]
+    fn tests() {
+        let (module, _) = frontend(
+            "(const N 20)
+             (data table (bytes 5 0 0 0))
+             (func fact ((n i64)) (i64) (if n (* n (call fact (- n 1))) 1))
+             (test fact_n (expect 2432902008176640000) (call fact N))
+             (test narrow (expect -1) (i32 -1))
+             (test table (expect 5) (load i32 (symbol table) 0))
+             (test sum (expect 6) (let ((x 1) (y 2)) (+ x (+ y 3))))",
+        )
+        .unwrap();
+        let mut jit = jit_module();
+        compile(&mut jit, &module).unwrap();
+        let tests = compile_tests(&mut jit, &module).unwrap();
+        jit.finalize_definitions().unwrap();
+        let names: Vec<(&str, Type)> = tests.iter().map(|(name, _, ty)| (&name[..], *ty)).collect();
+        assert_eq!(
+            names,
+            [
+                ("fact_n", types::I64),
+                ("narrow", types::I32),
+                ("table", types::I32),
+                ("sum", types::I64),
+            ]
+        );
+        let code = |i: usize| jit.get_finalized_function(tests[i].1);
+        let i64_test =
+            |i| unsafe { std::mem::transmute::<*const u8, extern "C" fn() -> i64>(code(i)) };
+        let i32_test =
+            |i| unsafe { std::mem::transmute::<*const u8, extern "C" fn() -> i32>(code(i)) };
+        assert_eq!(i64_test(0)(), 2_432_902_008_176_640_000);
+        assert_eq!(i32_test(1)(), -1);
+        assert_eq!(i32_test(2)(), 5);
+        assert_eq!(i64_test(3)(), 6);
+
+        // The tests are compiled after the functions they call.
+        assert_eq!(
+            compile_tests(&mut jit_module(), &module)
+                .unwrap_err()
+                .to_string(),
+            "in test `fact_n`: call to undefined function `fact`\n\
+             in test `table`: reference to undefined data `table`"
+        );
+
+        for (src, message, at) in [
+            (
+                "(test t (expect 1) (f64 1.5))",
+                "in test `t`: `(f64 1.5)` has type f64, but tests compare values of type i32 or \
+                 i64",
+                "(f64 1.5)",
+            ),
+            (
+                "(test t (expect 1) (i8 1))",
+                "in test `t`: `(i8 1)` has type i8, but tests compare values of type i32 or i64",
+                "(i8 1)",
+            ),
+            (
+                "(test t (expect 0x1_0000_0000) (i32 1))",
+                "in test `t`: literal 4294967296 does not fit in i32",
+                "0x1_0000_0000",
+            ),
+            (
+                "(test t (expect 1) (+ (i32 1) x))",
+                "in test `t`: undefined name `x`",
+                "x",
+            ),
+        ] {
+            let (module, _) = frontend(src).unwrap();
+            let diagnostics = match compile_tests(&mut jit_module(), &module) {
+                Err(CompileError::Diagnostics(diagnostics)) => diagnostics,
+                _ => panic!("{src} compiled"),
+            };
+            assert_eq!(diagnostics.len(), 1, "{src}");
+            assert_eq!(diagnostics[0].message, message, "{src}");
+            let span = diagnostics[0].span;
+            assert_eq!(&src[span.start..span.end], at, "{src}");
+        }
+        // Every test which can't be lowered has an error.
+        let (module, _) =
+            frontend("(test a (expect 1) y) (test b (expect 1) 1) (test c (expect 1) z)").unwrap();
+        assert_eq!(
+            compile_tests(&mut jit_module(), &module)
+                .unwrap_err()
+                .to_string(),
+            "in test `a`: undefined name `y`\nin test `c`: undefined name `z`"
+        );
+    }
+
+    #[test]
+    fn clif_round_trip() {
+        let (module, _) = frontend(
+            "(data table (bytes 1 2 3 4 5 6 7 8))
+             (func fact ((n i64)) (i64)
+               (brif n (recurse) (done 1))
+               (block recurse ()
+                 (jump (done (imul n (call fact (isub n 1))))))
+               (block done ((result i64))
+                 result))
+             (func main () (i64)
+               (+ (call fact 5) (load i64 (symbol table) 0)))",
+        )
+        .unwrap();
+        let clif = module.to_clif().unwrap();
+        assert_eq!(clif, module.to_clif().unwrap());
+        assert!(
+            clif.starts_with("; fact\nfunction u0:0(i64) -> i64 "),
+            "{clif}"
+        );
+        assert!(clif.contains("\n; main\nfunction u0:1() -> i64 "), "{clif}");
+
+        let funcs = cranelift_reader::parse_functions(&clif).unwrap();
+        assert_eq!(funcs.len(), 2);
+        let flags = settings::Flags::new(settings::builder());
+        for (func, index) in funcs.iter().zip(0..) {
+            cranelift_codegen::verify_function(func, &flags).unwrap();
+            assert_eq!(func.name, UserFuncName::user(0, index));
+        }
+
+        // The signature, the block parameters and the callee of `fact` survive the text.
+        let fact = &funcs[0];
+        assert_eq!(fact.signature.params.len(), 1);
+        let blocks: Vec<_> = fact.layout.blocks().collect();
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(
+            fact.dfg.block_param_types(blocks[2]).collect::<Vec<_>>(),
+            [types::I64]
+        );
+        let callees: Vec<_> = fact.dfg.ext_funcs.values().collect();
+        assert_eq!(callees.len(), 1);
+        assert_eq!(
+            callees[0].name.display(Some(&fact.params)).to_string(),
+            "u0:0"
+        );
+        // And `main` refers to the table, whose bytes aren't part of the text.
+        assert!(clif.contains("= symbol colocated userextname"), "{clif}");
+    }
+
+    #[test]
+    fn interpreted_tests() {
+        use cranelift_codegen::ir::TrapCode;
+        use cranelift_interpreter::environment::FunctionStore;
+        use cranelift_interpreter::interpreter::{Interpreter, InterpreterState};
+        use cranelift_interpreter::step::{ControlFlow, CraneliftTrap};
+
+        let (module, _) = frontend(
+            "(func div ((a i32) (b i32)) (i32) (sdiv a b))
+             (test quotient (expect -3) (call div -7 2))
+             (test by_zero (trap int_divz) (call div 1 0))
+             (test overflow (trap int_ovf) (call div -2147483648 -1))",
+        )
+        .unwrap();
+        let (functions, tests) = module.to_ir().unwrap();
+        assert_eq!(functions.len(), 1);
+        assert_eq!(tests.len(), 3);
+        let mut store = FunctionStore::default();
+        for func in functions.iter().chain(&tests) {
+            store.add(func.name.to_string(), func);
+        }
+        let run = |test: &ir::Function| {
+            let state = InterpreterState::default().with_function_store(store.clone());
+            Interpreter::new(state)
+                .call_by_name(&test.name.to_string(), &[])
+                .unwrap()
+        };
+        assert_eq!(
+            run(&tests[0]),
+            ControlFlow::Return(vec![(-3i32).into()].into())
+        );
+        for (test, code) in tests[1..]
+            .iter()
+            .zip([TrapCode::IntegerDivisionByZero, TrapCode::IntegerOverflow])
+        {
+            assert_eq!(run(test), ControlFlow::Trap(CraneliftTrap::User(code)));
+        }
+    }
+
+    #[test]
+    fn lowered_ir() {
+        let module =
+            super::super::parser::Module::parse("(func f ((x i8)) (i8) (iadd (ineg x) 3))")
+                .unwrap();
+        let func = lower(&module.functions[0], &module.constants, &mut jit_module()).unwrap();
+        cranelift_codegen::verify_function(&func, &settings::Flags::new(settings::builder()))
+            .unwrap();
+        assert_eq!(
+            func.display().to_string(),
+            "function %f(i8) -> i8 system_v {
+block0(v0: i8):
+    v1 = ineg v0
+    v2 = iconst.i8 3
+    v3 = iadd v1, v2  ; v2 = 3
+    return v3
+}
+"
+        );
+    }
+
+    #[test]
+    fn lowering_errors() {
+        for (src, message) in [
+            (
+                "(func f ((x i64)) (i64) (imul x))",
+                "in function `f`: `imul` takes 2 operands, not 1",
+            ),
+            (
+                "(func f ((x i64)) (i64) (ineg x x))",
+                "in function `f`: `ineg` takes 1 operand, not 2",
+            ),
+            (
+                "(func f ((x i64)) (i64) (rotl x 2))",
+                "in function `f`: unknown opcode `rotl`",
+            ),
+            (
+                "(func f ((x i64)) (i64) (iadd x y))",
+                "in function `f`: undefined name `y`",
+            ),
+            (
+                "(func f ((x i64)) (i64) (call g x))",
+                "in function `f`: call to undefined function `g`",
+            ),
+            (
+                "(func f ((x i64)) (i64) (call f x x))",
+                "in function `f`: `call f` takes 1 operand, not 2",
+            ),
+            (
+                "(func f ((x i64) (y i32)) (i64) (iadd x y))",
+                "in function `f`: `iadd` of i64 and i32",
+            ),
+            (
+                "(func f ((x i32)) (i64) (ineg x))",
+                "in function `f`: the body has type i32, not the result type i64",
+            ),
+            (
+                "(func f ((x i64)) (i64))",
+                "in function `f`: the body is empty, but the result type is i64",
+            ),
+            (
+                "(func f ((x i64)) (i64) (x 1))",
+                "in function `f`: unknown opcode `x`",
+            ),
+            (
+                "(func f () (i64) (iadd 1.5 2))",
+                "in function `f`: floating-point literals can't have type i64",
+            ),
+            (
+                "(func f () (i64) (iadd \"x\" 1))",
+                "in function `f`: string literals are only the paths of `include`, not values \
+                 like `\"x\"`",
+            ),
+            (
+                "(func f ((x f64)) (f64) (fmul x 2))",
+                "in function `f`: integer literals can't have type f64",
+            ),
+            (
+                "(func f ((x f64)) (f64) (iadd x x))",
+                "in function `f`: `iadd` of f64, which isn't an integer type",
+            ),
+            (
+                "(func f ((x i64)) (i64) (fdiv x x))",
+                "in function `f`: `fdiv` of i64, which isn't a floating-point type",
+            ),
+            (
+                "(func f ((x f32) (y f64)) (f64) (fadd x y))",
+                "in function `f`: `fadd` of f32 and f64",
+            ),
+            (
+                "(func f ((x f64)) (f64) (bxor x x))",
+                "in function `f`: `bxor` of f64, which isn't an integer type",
+            ),
+            (
+                "(func f ((x f64)) (f64) (fcvt_from_sint f64 x))",
+                "in function `f`: `fcvt_from_sint` of f64, which isn't a scalar integer type",
+            ),
+            (
+                "(func f ((x i64)) (i64) (fcvt_to_sint i64 x))",
+                "in function `f`: `fcvt_to_sint` of i64, which isn't a scalar float type",
+            ),
+            (
+                "(func f ((x i64)) (i64) (fcvt_from_sint i64 x))",
+                "in function `f`: `fcvt_from_sint` converts to a scalar float type, not i64",
+            ),
+            (
+                "(func f ((x f64)) (i64) (fcvt_to_sint u64 x))",
+                "in function `f`: unknown type `u64` of `fcvt_to_sint`",
+            ),
+            (
+                "(func f ((x f64)) (i64) (fcvt_to_sint x))",
+                "in function `f`: `fcvt_to_sint` takes 2 operands, not 1",
+            ),
+            (
+                "(func f () (i32) (stack_load i32 buf 0))",
+                "in function `f`: undefined stack slot `buf`",
+            ),
+            (
+                "(func f () (i32) (stack buf 8) (stack_load i32 buf 5))",
+                "in function `f`: `stack_load` of i32 at offset 5 is outside the 8 bytes of \
+                 stack slot `buf`",
+            ),
+            (
+                "(func f () (i32) (stack buf 8) (stack_store i32 buf -4 1))",
+                "in function `f`: `stack_store` of i32 at offset -4 is outside the 8 bytes of \
+                 stack slot `buf`",
+            ),
+            (
+                "(func f ((x i64)) (i32) (stack buf 8) (stack_store i32 buf 0 x))",
+                "in function `f`: `stack_store` of i64, not i32",
+            ),
+            (
+                "(func f ((x i64)) (i64) (stack buf 8) (stack_load i64 buf x))",
+                "in function `f`: the offset of `stack_load` must be an integer literal or a \
+                 constant, not `x`",
+            ),
+            (
+                "(func f () (i64) (stack buf 8) (stack buf 4) 1)",
+                "in function `f`: stack slot `buf` is declared twice",
+            ),
+            (
+                "(func f () (i64) (stack buf) 1)",
+                "in function `f`: expected `(stack name size)`, found `(stack buf)`",
+            ),
+            (
+                "(func f () (i64) (stack buf -1) 1)",
+                "in function `f`: the size -1 of stack slot `buf` is invalid",
+            ),
+            (
+                "(func f () (i64) 1 (stack buf 8) 2)",
+                "in function `f`: stack slots can only be declared at the start of the body of a \
+                 function",
+            ),
+            (
+                "(func f () (i64 i64) 1)",
+                "in function `f`: the body has type i64, not the result type (i64, i64)",
+            ),
+            (
+                "(func f () (i32 i32) (return 1))",
+                "in function `f`: `return` takes 2 operands, not 1",
+            ),
+            (
+                "(func f () (i32) (return 1 2))",
+                "in function `f`: `return` takes 1 operand, not 2",
+            ),
+            (
+                "(func f ((x i64)) (i32 i32) (return 1 x))",
+                "in function `f`: `return` of (i32, i64), not the result type (i32, i32)",
+            ),
+            (
+                "(func f () (i64) (let (((a b) (call g))) a)) (func g () (i64) 1)",
+                "in function `f`: `g` returns 1 value, not 2",
+            ),
+            (
+                "(func f ((x i32)) (i64) (call g x)) (func g ((y i64)) (i64) y)",
+                "in function `f`: argument 0 of `g` has type i32, not i64",
+            ),
+            (
+                "(func f ((x i64) (y f64)) (i64) (call f y y))",
+                "in function `f`: argument 0 of `f` has type f64, not i64",
+            ),
+            (
+                "(func f () (i64) (call g)) (func g () (i64 i64) (return 1 2))",
+                "in function `f`: `g` returns 2 values, not 1",
+            ),
+            (
+                "(func f () (i64) (let (((a b) (+ 1 2))) a))",
+                "in function `f`: only a `call` has several values to bind, not `(+ 1 2)`",
+            ),
+            (
+                "(func f ((x i64)) (i64) (let (((x y) (call g))) x)) \
+                 (func g () (i64 i64) (return 1 2))",
+                "in function `f`: `x` is a parameter, which `let` and `loop` can't bind",
+            ),
+            (
+                "(func f ((x f64) (y i64)) (f64) (+ x y))",
+                "in function `f`: `+` of f64 and i64",
+            ),
+            (
+                "(func f ((x i32) (y f32)) (i8) (< x y))",
+                "in function `f`: `<` of i32 and f32",
+            ),
+            (
+                "(func f ((x f64)) (i8) (= x x))",
+                "in function `f`: `=` of f64, which isn't an integer type",
+            ),
+            (
+                "(func f ((x i64)) (i64) (* x))",
+                "in function `f`: `*` takes 2 operands, not 1",
+            ),
+            (
+                "(func f ((x i64)) (i64) (< x 1))",
+                "in function `f`: the body has type i8, not the result type i64",
+            ),
+            (
+                "(func f ((x u64)) (i64) 1)",
+                "in function `f`: unknown type `u64` of parameter 0",
+            ),
+            (
+                "(func f ((x f64)) (f64) (ineg x))",
+                "in function `f`: `ineg` of f64, which isn't an integer type",
+            ),
+            (
+                "(func f ((x i64x2)) (i64x2) (ishl x x))",
+                "in function `f`: `ishl` of i64x2 and i64x2",
+            ),
+            (
+                "(func f () (f32) 1)",
+                "in function `f`: integer literals can't have type f32",
+            ),
+            (
+                "(func f ((x i32x4)) (i64) (if x 1 2))",
+                "in function `f`: the condition of `if` has type i32x4, which isn't a scalar \
+                 integer type",
+            ),
+            (
+                "(func f ((x i64) (y i32)) (i64) (if x x y))",
+                "in function `f`: the branches of `if` have types i64 and i32",
+            ),
+            (
+                "(func f ((x i64)) (i64) (+ (if x (return 1) (return 2)) 1))",
+                "in function `f`: an `if` whose branches both return has no value, so it can \
+                 only be the last expression of a block",
+            ),
+            (
+                "(func f ((x i64)) (i64) (+ (return x) 1))",
+                "in function `f`: `return` can only be the last expression of a block, of a \
+                 branch of `if` or of the body of a `let`, a `loop` or a `while`",
+            ),
+            (
+                "(func f ((x i32)) (i64) (return x))",
+                "in function `f`: `return` of i32, not the result type i64",
+            ),
+            (
+                "(func f ((x i64)) (i64) (return))",
+                "in function `f`: `return` takes 1 operand, not 0",
+            ),
+            (
+                "(func f () (i64) (let ((y z)) y))",
+                "in function `f`: undefined name `z`",
+            ),
+            (
+                "(func f ((x i64)) (i64) (+ (let ((y x)) y) y))",
+                "in function `f`: `y` is used outside the `let` or `loop` binding it",
+            ),
+            (
+                "(func f ((x i64)) (i64) (+ y (let ((y x)) y)))",
+                "in function `f`: `y` is used outside the `let` or `loop` binding it",
+            ),
+            (
+                "(func f ((x i64)) (i64) (let ((x 1)) x))",
+                "in function `f`: `x` is a parameter, which `let` and `loop` can't bind",
+            ),
+            (
+                "(func f () (i64) (jump (a 1)) (block a ((y i64)) (let ((y 2)) y)))",
+                "in function `f`: `y` is a parameter, which `let` and `loop` can't bind",
+            ),
+            (
+                "(func f () (i64) (let ((y 1))))",
+                "in function `f`: `let` takes 2 operands, not 1",
+            ),
+            (
+                "(func f () (i64) (let y y))",
+                "in function `f`: expected the bindings `((name expr)...)` of `let`, found `y`",
+            ),
+            (
+                "(func f () (i64) (let (y 1) y))",
+                "in function `f`: expected a binding `(name expr)` or `((name...) (call f \
+                 args...))`, found `y`",
+            ),
+            (
+                "(func f ((x i64)) (i64) (+ (let ((y x)) (return y)) 1))",
+                "in function `f`: a `let` whose body returns has no value, so it can only be the \
+                 last expression of a block",
+            ),
+            (
+                "(func f ((x i64)) (i64) (continue x))",
+                "in function `f`: `continue` outside of a `loop` or a `while`",
+            ),
+            (
+                "(func f ((x i64)) (i64) (+ (continue x) 1))",
+                "in function `f`: `continue` outside of a `loop` or a `while`",
+            ),
+            (
+                "(func f ((x i64)) (i64) (loop ((i x)) (+ (continue i) 1)))",
+                "in function `f`: `continue` can only be the last expression of the body of a \
+                 `loop` or a `while`, or of a branch of `if` or a `let` there",
+            ),
+            (
+                "(func f ((x i64)) (i64) (loop ((i x) (j x)) (continue i)))",
+                "in function `f`: `continue` takes 2 operands, not 1",
+            ),
+            (
+                "(func f ((x i64) (y i32)) (i64) (loop ((i x)) (if i (continue y) i)))",
+                "in function `f`: `continue` passes i32 to `i`, which has type i64",
+            ),
+            (
+                "(func f ((x i64)) (i64) (+ (loop ((i x)) (continue i)) 1))",
+                "in function `f`: a `loop` whose body always continues or returns has no value, \
+                 so it can only be the last expression of a block",
+            ),
+            (
+                "(func f ((x i64)) (i64) (loop ((x 1)) x))",
+                "in function `f`: `x` is a parameter, which `let` and `loop` can't bind",
+            ),
+            (
+                "(func f ((x i64)) (i64) (+ (loop ((i x)) i) i))",
+                "in function `f`: `i` is used outside the `let` or `loop` binding it",
+            ),
+            (
+                "(func f ((x i64)) (i64) (loop ((i x) (j (+ i 1))) j))",
+                "in function `f`: `i` is used outside the `let` or `loop` binding it",
+            ),
+            (
+                "(func f ((x i64)) (i64) (loop ((i u64 x)) i))",
+                "in function `f`: unknown type `u64` of variable `i`",
+            ),
+            (
+                "(func f ((x i64)) (i64) (loop ((i i32 x)) 1))",
+                "in function `f`: the initial value of `i` has type i64, not i32",
+            ),
+            (
+                "(func f ((x i64)) (i64) (loop (i x) i))",
+                "in function `f`: expected a variable `(name init)` or `(name type init)`, \
+                 found `i`",
+            ),
+            (
+                "(func f ((x i64)) (i64) (loop i i))",
+                "in function `f`: expected the variables `((name init)...)` of `loop`, found `i`",
+            ),
+            (
+                "(func f ((x i64)) (i64) (+ (tail_call f x) 1))",
+                "in function `f`: `tail_call` can only be the last expression of a block, of a \
+                 branch of `if` or of the body of a `let`, a `loop` or a `while`",
+            ),
+            (
+                "(func f ((x i64)) (i64) (tail_call g x)) (func g ((x i64)) (i32) 1)",
+                "in function `f`: `g` returns i32, but the function returns i64",
+            ),
+            (
+                "(func f ((x i64)) () (tail_call g x)) (func g ((x i64)) (i32) 1)",
+                "in function `f`: `g` returns i32, but the function returns nothing",
+            ),
+            (
+                "(func f ((x i64)) (i64) (tail_call f))",
+                "in function `f`: `tail_call f` takes 1 operand, not 0",
+            ),
+            (
+                "(func f ((x i64)) (i64) (tail_call))",
+                "in function `f`: `tail_call` needs the name of a function",
+            ),
+            (
+                "(func f ((x i64)) (i64) (tail_call g x))",
+                "in function `f`: call to undefined function `g`",
+            ),
+            (
+                "(func f ((x i64)) (i64) (jump (nowhere x)))",
+                "in function `f`: undefined block `nowhere`",
+            ),
+            (
+                "(func f ((x i64)) (i64) (brif x (a x) (a)) (block a ((y i64)) y))",
+                "in function `f`: block `a` takes 1 argument, not 0",
+            ),
+            (
+                "(func f ((x i64)) (i64) (jump (a x 1)) (block a ((y i64)) y))",
+                "in function `f`: block `a` takes 1 argument, not 2",
+            ),
+            (
+                "(func f ((x i32)) (i64) (jump (a x)) (block a ((y i64)) y))",
+                "in function `f`: argument 0 of block `a` has type i32, not i64",
+            ),
+            (
+                "(func f () (i64) (jump (a)) (block a () 1) (block a () 2))",
+                "in function `f`: block `a` is defined twice",
+            ),
+            (
+                "(func f () (i64) (jump (a)) (block a ((y u64)) 1))",
+                "in function `f`: unknown type `u64` of parameter `y` of block `a`",
+            ),
+            (
+                "(func f () (i64) (jump (a)) (block a ()))",
+                "in function `f`: block `a` is empty, but the result type is i64",
+            ),
+            (
+                "(func f () (i64) (jump (a)) (block a () 1) 2)",
+                "in function `f`: `2` follows the blocks, but the expressions of a block go \
+                 inside it",
+            ),
+            (
+                "(func f () (i64) (iadd (block a () 1) 2))",
+                "in function `f`: blocks can only be defined at the end of the body of a \
+                 function",
+            ),
+            (
+                "(func f () (i64) (jump (a)) (iadd 1 2) (block a () 1))",
+                "in function `f`: `jump` can only be the last expression of a block",
+            ),
+            (
+                "(func f () (i64) (jump a) (block a () 1))",
+                "in function `f`: expected a branch target `(block args...)`, found `a`",
+            ),
+            (
+                "(func f () (i64) (jump (a)) (block a))",
+                "in function `f`: expected `(block name ((param type)...) body...)`, found \
+                 `(block a)`",
+            ),
+            (
+                "(func f () (i64) (symbol table))",
+                "in function `f`: reference to undefined data `table`",
+            ),
+            (
+                "(func f () (i64) (symbol f))",
+                "in function `f`: reference to undefined data `f`",
+            ),
+            (
+                "(data t (bytes 1)) (func f () (i64) (symbol t t))",
+                "in function `f`: `symbol` takes 1 operand, not 2",
+            ),
+            (
+                "(data t (bytes 1)) (func f () (i64) (symbol 1))",
+                "in function `f`: expected the name of data, found `1`",
+            ),
+            (
+                "(data t (bytes 1)) (func f () (i8) (load i8 (symbol t)))",
+                "in function `f`: `load` takes 3 operands, not 2",
+            ),
+            (
+                "(data t (bytes 1)) (func f () (i8) (load u8 (symbol t) 0))",
+                "in function `f`: unknown type `u8` of `load`",
+            ),
+            (
+                "(func f ((p i32)) (i8) (load i8 p 0))",
+                "in function `f`: the address of `load` has type i32, not the pointer type i64",
+            ),
+            (
+                "(data t (bytes 1)) (func f ((x i32)) (i8) (load i8 (symbol t) x))",
+                "in function `f`: the offset of `load` must be an integer literal or a constant, \
+                 not `x`",
+            ),
+            (
+                "(data t (bytes 1)) (func f () (i8) (load i8 (symbol t) 0x1_0000_0000))",
+                "in function `f`: the offset 4294967296 of `load` is too large",
+            ),
+        ] {
+            match compile_jit(src) {
+                Ok(_) => panic!("{src} compiled"),
+                Err(err) => assert_eq!(err, message, "{src}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,403 @@
+//! Problems found in clifp source text, and rendering them against the text.
+
+use super::lexer::{LexError, Span};
+use super::sexp;
+use super::source::SourceMap;
+use std::fmt;
+
+/// The number of columns between tab stops when rendering source lines.
+const TAB_WIDTH: usize = 4;
+
+/// How serious a [`Diagnostic`] is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Severity {
+    /// The program can't be compiled.
+    Error,
+    /// The program can be compiled, but is likely wrong.
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Error => write!(f, "error"),
+            Self::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A problem with a clifp program, and the span of the source text it's about.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// How serious the problem is.
+    pub severity: Severity,
+    /// The offending source text.
+    pub span: Span,
+    /// What is wrong.
+    pub message: String,
+}
+
+/// Prints the message, without the source text.
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+impl Diagnostic {
+    /// An error about the source text at `span`.
+    pub fn error(span: Span, message: impl fmt::Display) -> Self {
+        Self {
+            severity: Severity::Error,
+            span,
+            message: message.to_string(),
+        }
+    }
+
+    /// A warning about the source text at `span`.
+    pub fn warning(span: Span, message: impl fmt::Display) -> Self {
+        Self {
+            severity: Severity::Warning,
+            span,
+            message: message.to_string(),
+        }
+    }
+
+    /// The error `err` lexing `source`, at the offending character, or at the end of `source`
+    /// if the error is there.
+    pub fn lex(source: &str, err: &LexError) -> Self {
+        let len = source[err.offset..]
+            .chars()
+            .next()
+            .map_or(0, char::len_utf8);
+        Self::error(
+            Span {
+                start: err.offset,
+                end: err.offset + len,
+            },
+            &err.kind,
+        )
+    }
+
+    /// The diagnostic as a message giving the line and column of the start of its span, followed
+    /// by the line of `source` it starts in and carets under the span:
+    ///
+    /// ```text
+    /// error: unknown opcode `imull` at line 2, column 4
+    ///   |
+    /// 2 |   (imull x 3))
+    ///   |    ^^^^^
+    /// ```
+    ///
+    /// Lines and columns start at 1, and columns count characters, as those of [`LexError`] do.
+    /// Tabs are expanded to the next multiple of four columns, and every other character takes
+    /// one column. A span running over several lines is underlined to the end of its first line,
+    /// and an empty span, such as the end of the text, gets a single caret.
+    pub fn render(&self, source: &str) -> String {
+        self.render_at(source, self.span, "")
+    }
+
+    /// The diagnostic rendered as [`render`](Self::render) renders it, against the file of
+    /// `sources` its span is in, whose path follows the line and column:
+    ///
+    /// ```text
+    /// error: unknown opcode `imull` at line 2, column 4 of lib/helpers.clifp
+    /// ```
+    ///
+    /// A diagnostic which isn't about any of the files, such as a file which couldn't be read,
+    /// is only its message.
+    pub fn render_in(&self, sources: &SourceMap) -> String {
+        match sources.lookup(self.span.start) {
+            Some((file, start)) => {
+                let span = Span {
+                    start,
+                    end: start + (self.span.end - self.span.start),
+                };
+                let of = format!(" of {}", file.path.display());
+                self.render_at(&file.text, span, &of)
+            }
+            None => format!("{}: {}", self.severity, self.message),
+        }
+    }
+
+    /// The diagnostic rendered with its span at `span` of `source`, and `of` after the line and
+    /// column.
+    fn render_at(&self, source: &str, span: Span, of: &str) -> String {
+        let start = floor_char_boundary(source, span.start);
+        let line_start = source[..start].rfind('\n').map_or(0, |newline| newline + 1);
+        let line_end = source[start..]
+            .find('\n')
+            .map_or(source.len(), |newline| start + newline);
+        let end = floor_char_boundary(source, span.end.clamp(start, line_end));
+        let line_number = 1 + source[..start].matches('\n').count();
+        let column = 1 + source[line_start..start].chars().count();
+
+        let line = source[line_start..line_end].trim_end_matches('\r');
+        let (text, widths) = expand_tabs(line);
+        // The display columns of the characters before the span, and of those in it.
+        let before = source[line_start..start].chars().count();
+        let within = source[start..end].chars().count();
+        let indent: usize = widths.iter().take(before).sum();
+        let carets: usize = widths.iter().skip(before).take(within).sum();
+
+        let number = line_number.to_string();
+        let gutter = " ".repeat(number.len());
+        format!(
+            "{}: {} at line {line_number}, column {column}{of}\n\
+             {gutter} |\n\
+             {number} | {text}\n\
+             {gutter} | {}{}",
+            self.severity,
+            self.message,
+            " ".repeat(indent),
+            "^".repeat(carets.max(1)),
+        )
+    }
+}
+
+/// `line` with its tabs expanded to spaces, and the width in columns of each of its characters.
+fn expand_tabs(line: &str) -> (String, Vec<usize>) {
+    let mut text = String::new();
+    let mut widths = Vec::new();
+    let mut column = 0;
+    for c in line.chars() {
+        let width = if c == '\t' {
+            let width = TAB_WIDTH - column % TAB_WIDTH;
+            text.extend(std::iter::repeat(' ').take(width));
+            width
+        } else {
+            text.push(c);
+            1
+        };
+        column += width;
+        widths.push(width);
+    }
+    (text, widths)
+}
+
+/// The greatest character boundary of `source` which is at most `offset`.
+fn floor_char_boundary(source: &str, offset: usize) -> usize {
+    let mut offset = offset.min(source.len());
+    while !source.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+impl From<sexp::ParseError> for Diagnostic {
+    fn from(err: sexp::ParseError) -> Self {
+        let message = match err {
+            sexp::ParseError::Unclosed(_) => "`(` is never closed".to_string(),
+            sexp::ParseError::Unopened(_) => "`)` doesn't close any list".to_string(),
+            sexp::ParseError::TooDeep(_) => {
+                format!("`(` nests lists more than {} levels deep", sexp::MAX_DEPTH)
+            }
+        };
+        Self::error(err.span(), message)
+    }
+}
+
+/// `diagnostics` rendered against `source`, separated by blank lines.
+pub fn render_all(diagnostics: &[Diagnostic], source: &str) -> String {
+    let rendered: Vec<String> = diagnostics
+        .iter()
+        .map(|diagnostic| diagnostic.render(source))
+        .collect();
+    rendered.join("\n\n")
+}
+
+/// `diagnostics` rendered against the files of `sources` they're in, separated by blank lines.
+pub fn render_all_in(diagnostics: &[Diagnostic], sources: &SourceMap) -> String {
+    let rendered: Vec<String> = diagnostics
+        .iter()
+        .map(|diagnostic| diagnostic.render_in(sources))
+        .collect();
+    rendered.join("\n\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::compile::CompileError;
+    use super::super::frontend;
+    use super::*;
+
+    /// The errors of `source`, from the frontend or from lowering, rendered against it.
+    fn rendered(source: &str) -> String {
+        let diagnostics = match frontend(source) {
+            Err(diagnostics) => diagnostics,
+            Ok((module, _)) => match module.to_clif() {
+                Err(CompileError::Diagnostics(diagnostics)) => diagnostics,
+                Err(err) => panic!("{err}"),
+                Ok(_) => panic!("{source:?} compiles"),
+            },
+        };
+        render_all(&diagnostics, source)
+    }
+
+    #[test]
+    fn render() {
+        let render = |source: &str, start, end| {
+            Diagnostic::error(Span { start, end }, "oops").render(source)
+        };
+        assert_eq!(
+            render("(iadd x y)", 8, 9),
+            "error: oops at line 1, column 9\n  |\n1 | (iadd x y)\n  |         ^"
+        );
+
+        // Tabs are expanded to the next tab stop, both in the echoed line and before the carets.
+        assert_eq!(
+            render("a\n\t(f\tx)", 6, 7),
+            "error: oops at line 2, column 5\n  |\n2 |     (f  x)\n  |         ^"
+        );
+        assert_eq!(
+            render("\t\tsome", 2, 6),
+            "error: oops at line 1, column 3\n  |\n1 |         some\n  |         ^^^^"
+        );
+
+        // Columns count characters rather than bytes.
+        assert_eq!(
+            render("(é ü x)", 7, 8),
+            "error: oops at line 1, column 6\n  |\n1 | (é ü x)\n  |      ^"
+        );
+        assert_eq!(
+            render("(éé)", 1, 5),
+            "error: oops at line 1, column 2\n  |\n1 | (éé)\n  |  ^^"
+        );
+
+        // Spans over several lines are underlined to the end of their first line, empty spans
+        // get a single caret, and line numbers widen the gutter.
+        assert_eq!(
+            render("(f\n  (g x\n   y))", 5, 14),
+            "error: oops at line 2, column 3\n  |\n2 |   (g x\n  |   ^^^^"
+        );
+        assert_eq!(
+            render("(f\r\n", 4, 4),
+            "error: oops at line 2, column 1\n  |\n2 | \n  | ^"
+        );
+        let source = "\n".repeat(9) + "(f x)";
+        assert_eq!(
+            render(&source, 12, 13),
+            "error: oops at line 10, column 4\n   |\n10 | (f x)\n   |    ^"
+        );
+
+        assert_eq!(
+            Diagnostic::warning(Span { start: 1, end: 2 }, "careful").render("(f)"),
+            "warning: careful at line 1, column 2\n  |\n1 | (f)\n  |  ^"
+        );
+    }
+
+    #[test]
+    fn lowering_errors() {
+        assert_eq!(
+            rendered("(func f ((x i64)) (i64)\n  (let ((y (imul x 3)))\n    (imull y 2)))"),
+            "\
+error: in function `f`: unknown opcode `imull` at line 3, column 6
+  |
+3 |     (imull y 2)))
+  |      ^^^^^"
+        );
+        assert_eq!(
+            rendered("(func f ((x i64)) (i64)\r\n  (stack buf 4)\r\n  (stack_load i64 buf 0))\r\n"),
+            "\
+error: in function `f`: `stack_load` of i64 at offset 0 is outside the 4 bytes of stack slot `buf` at line 3, column 3
+  |
+3 |   (stack_load i64 buf 0))
+  |   ^^^^^^^^^^^^^^^^^^^^^^"
+        );
+        assert_eq!(
+            rendered("(func f () (i64)\n\t; déjà vu\n\t(iadd 1\tzz))"),
+            "\
+error: in function `f`: undefined name `zz` at line 3, column 10
+  |
+3 |     (iadd 1 zz))
+  |             ^^"
+        );
+        assert_eq!(
+            rendered("(func f ((x i64)) (i8)\n  (icmp ugte x 1))"),
+            "\
+error: in function `f`: unknown condition code `ugte`, expected one of `eq`, `ne`, `slt`, `sle`, \
+`sgt`, `sge`, `ult`, `ule`, `ugt`, `uge` at line 2, column 9
+  |
+2 |   (icmp ugte x 1))
+  |         ^^^^"
+        );
+    }
+
+    #[test]
+    fn several_errors() {
+        // Every function which doesn't lower has an error.
+        assert_eq!(
+            rendered(
+                "(func f ((x i64)) (i64) (iadd x y))\n\
+                 (func g ((x f64)) (i64) (+ x 1))\n\
+                 (func h () (i64) 1)"
+            ),
+            "\
+error: in function `f`: undefined name `y` at line 1, column 33
+  |
+1 | (func f ((x i64)) (i64) (iadd x y))
+  |                                 ^
+
+error: in function `g`: integer literals can't have type f64 at line 2, column 30
+  |
+2 | (func g ((x f64)) (i64) (+ x 1))
+  |                              ^"
+        );
+
+        // As does every malformed form, and every signature with unknown types.
+        assert_eq!(
+            rendered("(func f () (i64) 1)\n(data d (bytes 1 2 300))\n(func f () (i64) 2)\n(fn g)"),
+            "\
+error: in data `d`: 300 doesn't fit in a byte at line 2, column 20
+  |
+2 | (data d (bytes 1 2 300))
+  |                    ^^^
+
+error: function `f` is defined twice at line 3, column 7
+  |
+3 | (func f () (i64) 2)
+  |       ^
+
+error: expected `func`, `extern`, `data`, `const`, `test` or `include`, found `fn` at line 4, \
+column 2
+  |
+4 | (fn g)
+  |  ^^"
+        );
+        assert_eq!(
+            rendered("(func f ((x u8)) (bool) 1)\n(extern e ((i8x3)) ())"),
+            "\
+error: in function `f`: unknown type `u8` of parameter 0 at line 1, column 13
+  |
+1 | (func f ((x u8)) (bool) 1)
+  |             ^^
+
+error: in extern `e`: unknown type `i8x3` of parameter 0 at line 2, column 13
+  |
+2 | (extern e ((i8x3)) ())
+  |             ^^^^"
+        );
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert_eq!(
+            rendered("(func f () (i64)\n\t(iadd #é# 1))"),
+            "\
+error: unexpected character '#' at line 2, column 8
+  |
+2 |     (iadd #é# 1))
+  |           ^"
+        );
+        assert_eq!(
+            rendered("(func f ((x u8)) (i64)\n  1"),
+            "\
+error: `(` is never closed at line 1, column 1
+  |
+1 | (func f ((x u8)) (i64)
+  | ^"
+        );
+    }
+}
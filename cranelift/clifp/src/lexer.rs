@@ -0,0 +1,988 @@
+//! Splitting clifp source text into tokens.
+
+use std::fmt;
+
+/// A token of clifp source text, whose names borrow the text.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Token<'a> {
+    /// `(`
+    LParen,
+    /// `)`
+    RParen,
+    /// A name: a letter followed by letters, digits, `_` and `.`, such as `iadd_imm` or
+    /// `f64.const`, or an operator made of `+`, `-`, `*`, `<`, `>`, `=` and `!`, such as `<=`.
+    Ident(&'a str),
+    /// An integer literal: an optional `-`, an optional `0x`, `0o` or `0b` radix prefix, and
+    /// digits, which may be separated by `_`.
+    Int(i128),
+    /// A floating-point literal: an optional `-`, digits, then a `.` and digits, an exponent, or
+    /// both, such as `2.5`, `1e10` or `2.5e-3`. The digits before the exponent may be separated
+    /// by `_`, as in `1_000.000_5`. An exponent is an `e` or `E`, an optional sign, and digits.
+    /// `inf`, `-inf` and `nan` are also floats.
+    Float(f64),
+    /// A string literal: characters other than `"` and line breaks between two `"`, such as the
+    /// path `"helpers.clifp"`. The token holds the characters without the quotes, and there are
+    /// no escapes.
+    Str(&'a str),
+}
+
+/// The range of bytes of the source text a token was lexed from, or an s-expression was parsed
+/// from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Span {
+    /// The byte offset of the first character of the token.
+    pub start: usize,
+    /// The byte offset right after the last character of the token.
+    pub end: usize,
+}
+
+/// An error found while lexing, and where it was found.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LexError {
+    /// What is wrong.
+    pub kind: LexErrorKind,
+    /// The byte offset in the source of the offending character, or of the start of the
+    /// offending literal.
+    pub offset: usize,
+    /// The line of `offset`, starting at 1.
+    pub line: usize,
+    /// The column of `offset` in characters, starting at 1.
+    pub column: usize,
+}
+
+/// The kinds of [`LexError`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum LexErrorKind {
+    /// A character which can't start a token, or which can't follow the previous one.
+    UnexpectedChar(char),
+    /// A `-` which isn't followed by the digits of a number.
+    LoneMinus,
+    /// A digit which isn't valid in the radix of its integer literal.
+    InvalidDigit {
+        /// The digit.
+        digit: char,
+        /// The radix of the literal.
+        radix: u32,
+    },
+    /// An integer literal with a radix prefix but no digits.
+    NoRadixDigits,
+    /// An `_` in the digits of a number which isn't followed by a digit.
+    MisplacedUnderscore,
+    /// An integer literal whose value doesn't fit in an `i128`.
+    IntTooLarge,
+    /// A float literal with no digits after its `.`.
+    NoFractionDigits,
+    /// A float literal with no digits after the `e` of its exponent and its sign.
+    NoExponentDigits,
+    /// A float literal which isn't representable.
+    InvalidFloat,
+    /// A string literal whose line or text ends before its closing `"`.
+    UnterminatedString,
+}
+
+impl fmt::Display for LexErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnexpectedChar(c) => write!(f, "unexpected character {c:?}"),
+            Self::LoneMinus => write!(f, "`-` must be followed by the digits of a number"),
+            Self::InvalidDigit { digit, radix } => {
+                write!(f, "invalid digit {digit:?} in base-{radix} integer literal")
+            }
+            Self::NoRadixDigits => {
+                write!(f, "integer literal has no digits after its radix prefix")
+            }
+            Self::MisplacedUnderscore => write!(f, "`_` must be followed by a digit"),
+            Self::IntTooLarge => write!(f, "integer literal is too large"),
+            Self::NoFractionDigits => write!(f, "float literal has no digits after the `.`"),
+            Self::NoExponentDigits => write!(f, "float literal has no digits in its exponent"),
+            Self::InvalidFloat => write!(f, "invalid float literal"),
+            Self::UnterminatedString => write!(f, "string literal is never closed"),
+        }
+    }
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.kind)
+    }
+}
+
+impl std::error::Error for LexError {}
+
+/// Prints the canonical source text of the token, which lexes back to the same token.
+impl fmt::Display for Token<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::LParen => write!(f, "("),
+            Self::RParen => write!(f, ")"),
+            Self::Ident(name) => write!(f, "{name}"),
+            Self::Int(value) => write!(f, "{value}"),
+            Self::Float(value) => write_float(f, *value),
+            Self::Str(text) => write!(f, "\"{text}\""),
+        }
+    }
+}
+
+/// Write `value` as a float literal which lexes back to it.
+pub(super) fn write_float(f: &mut fmt::Formatter, value: f64) -> fmt::Result {
+    if value.is_nan() {
+        write!(f, "nan")
+    } else {
+        // Debug formatting keeps the `.0` of whole floats, writes `inf` for infinities, and
+        // switches to exponents for very large and small magnitudes, all of which lex back to
+        // the same value.
+        write!(f, "{value:?}")
+    }
+}
+
+impl LexError {
+    /// An error of the given kind at the byte offset `pos` of `src`.
+    fn new(kind: LexErrorKind, src: &str, pos: usize) -> Self {
+        let before = &src[..pos];
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+        Self {
+            kind,
+            offset: pos,
+            line: 1 + before.matches('\n').count(),
+            column: 1 + before[line_start..].chars().count(),
+        }
+    }
+}
+
+/// An iterator over the tokens of clifp source text, along with the span of each of them.
+///
+/// The names of the tokens borrow the source text, so lexing allocates nothing. The iterator ends
+/// after the first error.
+pub struct Lexer<'a> {
+    src: &'a str,
+    /// The byte offset of the next character to lex.
+    pos: usize,
+}
+
+impl<'a> Lexer<'a> {
+    /// A lexer of the tokens of `src`.
+    pub fn new(src: &'a str) -> Self {
+        Self { src, pos: 0 }
+    }
+
+    /// Lex the next token, if there is one.
+    fn token(&mut self) -> Result<Option<(Token<'a>, Span)>, LexError> {
+        let src = self.src;
+        let bytes = src.as_bytes();
+        let mut pos = self.pos;
+        let c = loop {
+            let c = match src[pos..].chars().next() {
+                Some(c) => c,
+                None => {
+                    self.pos = pos;
+                    return Ok(None);
+                }
+            };
+            if c.is_whitespace() {
+                pos += c.len_utf8();
+            } else if c == ';' {
+                // A comment runs to the end of the line.
+                pos = src[pos..]
+                    .find('\n')
+                    .map_or(src.len(), |newline| pos + newline);
+            } else {
+                break c;
+            }
+        };
+        let start = pos;
+        let token = if c == '(' {
+            pos += 1;
+            Token::LParen
+        } else if c == ')' {
+            pos += 1;
+            Token::RParen
+        } else if c.is_ascii_alphabetic() {
+            while pos < bytes.len() && is_ident_continuation(bytes[pos]) {
+                pos += 1;
+            }
+            expect_separator(src, pos)?;
+            match &src[start..pos] {
+                "inf" => Token::Float(f64::INFINITY),
+                "nan" => Token::Float(f64::NAN),
+                name => Token::Ident(name),
+            }
+        } else if is_operator(bytes[pos]) && !is_minus_sign(src, pos) {
+            // An operator such as `+` or `<=`, which is a name.
+            while pos < bytes.len() && is_operator(bytes[pos]) {
+                pos += 1;
+            }
+            expect_separator(src, pos)?;
+            Token::Ident(&src[start..pos])
+        } else if c.is_ascii_digit() || c == '-' {
+            let (token, end) = lex_number(src, pos)?;
+            pos = end;
+            expect_separator(src, pos)?;
+            token
+        } else if c == '"' {
+            let len = src[pos + 1..]
+                .find(['"', '\n', '\r'])
+                .filter(|&len| bytes[pos + 1 + len] == b'"')
+                .ok_or_else(|| LexError::new(LexErrorKind::UnterminatedString, src, start))?;
+            pos += len + 2;
+            expect_separator(src, pos)?;
+            Token::Str(&src[start + 1..pos - 1])
+        } else {
+            return Err(LexError::new(LexErrorKind::UnexpectedChar(c), src, pos));
+        };
+        self.pos = pos;
+        Ok(Some((token, Span { start, end: pos })))
+    }
+}
+
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Result<(Token<'a>, Span), LexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.token() {
+            Ok(token) => token.map(Ok),
+            Err(err) => {
+                self.pos = self.src.len();
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// Split `src` into tokens, along with the span of each of them.
+///
+/// ```
+/// use cranelift_clifp::lexer::{LexErrorKind, Token};
+///
+/// let tokens = cranelift_clifp::lex("(iadd x 0x10)").unwrap();
+/// let tokens: Vec<_> = tokens.into_iter().map(|(token, _)| token).collect();
+/// assert_eq!(
+///     tokens,
+///     [Token::LParen, Token::Ident("iadd"), Token::Ident("x"), Token::Int(16), Token::RParen],
+/// );
+///
+/// let err = cranelift_clifp::lex("(iadd x @)").unwrap_err();
+/// assert_eq!(err.kind, LexErrorKind::UnexpectedChar('@'));
+/// assert_eq!((err.line, err.column), (1, 9));
+/// ```
+pub fn lex(src: &str) -> Result<Vec<(Token<'_>, Span)>, LexError> {
+    Lexer::new(src).collect()
+}
+
+/// Whether `c` can follow the first letter of a name. Names can't start with `_`, `.` or a digit,
+/// so that those always start a number or are an error.
+fn is_ident_continuation(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_' || c == b'.'
+}
+
+/// Whether `c` can be part of an operator name such as `+` or `<=`.
+fn is_operator(c: u8) -> bool {
+    matches!(c, b'+' | b'-' | b'*' | b'<' | b'>' | b'=' | b'!')
+}
+
+/// Whether the character at `pos` is the `-` sign of a number rather than the operator `-`.
+fn is_minus_sign(src: &str, pos: usize) -> bool {
+    src.as_bytes()[pos] == b'-'
+        && matches!(src[pos + 1..].chars().next(), Some(c) if !is_separator(c))
+}
+
+/// Whether `c` ends the token before it: whitespace, a parenthesis, or the start of a comment.
+fn is_separator(c: char) -> bool {
+    c.is_whitespace() || c == '(' || c == ')' || c == ';'
+}
+
+/// Check that the name or number ending at `pos` is followed by a separator or the end of the
+/// input, so that e.g. `x-1` isn't read as `x` and `-1`.
+fn expect_separator(src: &str, pos: usize) -> Result<(), LexError> {
+    match src[pos..].chars().next() {
+        Some(c) if !is_separator(c) => {
+            Err(LexError::new(LexErrorKind::UnexpectedChar(c), src, pos))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The position of the first byte from `pos` which isn't a digit or an `_`.
+fn skip_digits(bytes: &[u8], mut pos: usize) -> usize {
+    while pos < bytes.len() && (bytes[pos].is_ascii_digit() || bytes[pos] == b'_') {
+        pos += 1;
+    }
+    pos
+}
+
+/// Lex the number starting at `start`, returning it and the position after it.
+fn lex_number<'a>(src: &str, start: usize) -> Result<(Token<'a>, usize), LexError> {
+    let bytes = src.as_bytes();
+    let negative = bytes[start] == b'-';
+    let digits_start = if negative { start + 1 } else { start };
+    if negative && bytes[digits_start..].starts_with(b"inf") {
+        return Ok((Token::Float(f64::NEG_INFINITY), digits_start + 3));
+    }
+    if !matches!(bytes.get(digits_start), Some(c) if c.is_ascii_digit()) {
+        return Err(LexError::new(LexErrorKind::LoneMinus, src, start));
+    }
+    if bytes[digits_start] == b'0' {
+        let radix = match bytes.get(digits_start + 1) {
+            Some(b'x') => Some(16),
+            Some(b'o') => Some(8),
+            Some(b'b') => Some(2),
+            _ => None,
+        };
+        if let Some(radix) = radix {
+            return lex_radix_int(src, start, negative, digits_start + 2, radix);
+        }
+    }
+    let pos = skip_digits(bytes, digits_start);
+    check_underscores(src, digits_start, pos)?;
+    match bytes.get(pos) {
+        Some(b'.') => return lex_float(src, start, pos + 1),
+        Some(b'e' | b'E') => return lex_exponent(src, start, pos + 1),
+        _ => {}
+    }
+    int_token(src, start, negative, digits_start, 10, pos)
+}
+
+/// Lex the digits in base `radix` of the integer starting at `start`, from `pos` right after its
+/// radix prefix.
+fn lex_radix_int<'a>(
+    src: &str,
+    start: usize,
+    negative: bool,
+    mut pos: usize,
+    radix: u32,
+) -> Result<(Token<'a>, usize), LexError> {
+    let bytes = src.as_bytes();
+    let digits_start = pos;
+    while pos < bytes.len() && (bytes[pos].is_ascii_alphanumeric() || bytes[pos] == b'_') {
+        let digit = char::from(bytes[pos]);
+        if digit != '_' && !digit.is_digit(radix) {
+            let kind = LexErrorKind::InvalidDigit { digit, radix };
+            return Err(LexError::new(kind, src, pos));
+        }
+        pos += 1;
+    }
+    if bytes[digits_start..pos].iter().all(|&c| c == b'_') {
+        return Err(LexError::new(LexErrorKind::NoRadixDigits, src, start));
+    }
+    check_underscores(src, digits_start, pos)?;
+    int_token(src, start, negative, digits_start, radix, pos)
+}
+
+/// Check that every `_` of the digits from `start` to `end` is followed by a digit.
+fn check_underscores(src: &str, start: usize, end: usize) -> Result<(), LexError> {
+    let bytes = src.as_bytes();
+    for pos in start..end {
+        if bytes[pos] == b'_' && (pos + 1 == end || bytes[pos + 1] == b'_') {
+            return Err(LexError::new(LexErrorKind::MisplacedUnderscore, src, pos));
+        }
+    }
+    Ok(())
+}
+
+/// Make the token for the integer starting at `start` and ending at `end`, whose magnitude is
+/// written in base `radix` from `digits_start`. The digits are known to be valid, so the value
+/// is only missing if it doesn't fit in an `i128`.
+fn int_token<'a>(
+    src: &str,
+    start: usize,
+    negative: bool,
+    digits_start: usize,
+    radix: u32,
+    end: usize,
+) -> Result<(Token<'a>, usize), LexError> {
+    let too_large = || LexError::new(LexErrorKind::IntTooLarge, src, start);
+    let mut magnitude: u128 = 0;
+    for &digit in &src.as_bytes()[digits_start..end] {
+        if digit != b'_' {
+            let digit = char::from(digit).to_digit(radix).unwrap();
+            magnitude = magnitude
+                .checked_mul(radix.into())
+                .and_then(|m| m.checked_add(digit.into()))
+                .ok_or_else(too_large)?;
+        }
+    }
+    let value = if negative {
+        0i128.checked_sub_unsigned(magnitude)
+    } else {
+        i128::try_from(magnitude).ok()
+    };
+    Ok((Token::Int(value.ok_or_else(too_large)?), end))
+}
+
+/// Lex the fractional part of the float starting at `start`, from `pos` right after the `.`.
+///
+/// The fraction starts with a digit, and its digits may be separated by `_`, so neither `1.` nor
+/// `1._5` is a float.
+fn lex_float<'a>(src: &str, start: usize, pos: usize) -> Result<(Token<'a>, usize), LexError> {
+    let bytes = src.as_bytes();
+    if !matches!(bytes.get(pos), Some(c) if c.is_ascii_digit()) {
+        return Err(LexError::new(LexErrorKind::NoFractionDigits, src, start));
+    }
+    let end = skip_digits(bytes, pos);
+    check_underscores(src, pos, end)?;
+    if matches!(bytes.get(end), Some(b'e' | b'E')) {
+        return lex_exponent(src, start, end + 1);
+    }
+    float_token(src, start, end)
+}
+
+/// Lex the exponent of the float starting at `start`, from `pos` right after the `e`.
+fn lex_exponent<'a>(
+    src: &str,
+    start: usize,
+    mut pos: usize,
+) -> Result<(Token<'a>, usize), LexError> {
+    let bytes = src.as_bytes();
+    if matches!(bytes.get(pos), Some(b'+' | b'-')) {
+        pos += 1;
+    }
+    let digits = pos;
+    while pos < bytes.len() && bytes[pos].is_ascii_digit() {
+        pos += 1;
+    }
+    if pos == digits {
+        return Err(LexError::new(LexErrorKind::NoExponentDigits, src, start));
+    }
+    float_token(src, start, pos)
+}
+
+/// Make the token for the float starting at `start` and ending at `end`.
+fn float_token<'a>(src: &str, start: usize, end: usize) -> Result<(Token<'a>, usize), LexError> {
+    let text = &src[start..end];
+    let invalid = |_| LexError::new(LexErrorKind::InvalidFloat, src, start);
+    let value = if text.contains('_') {
+        text.replace('_', "").parse().map_err(invalid)?
+    } else {
+        text.parse().map_err(invalid)?
+    };
+    Ok((Token::Float(value), end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The tokens of `src`, without their spans.
+    fn tokens(src: &str) -> Result<Vec<Token<'_>>, LexError> {
+        Ok(lex(src)?.into_iter().map(|(token, _)| token).collect())
+    }
+
+    /// The kind and the (offset, line, column) position of the error lexing `src`.
+    fn error(src: &str) -> (LexErrorKind, (usize, usize, usize)) {
+        let e = lex(src).unwrap_err();
+        (e.kind, (e.offset, e.line, e.column))
+    }
+
+    #[test]
+    fn streaming() {
+        let src = "(f x) 1 #";
+        let mut lexer = Lexer::new(src);
+        assert_eq!(
+            lexer.next(),
+            Some(Ok((Token::LParen, Span { start: 0, end: 1 })))
+        );
+        // Names are slices of the source.
+        match lexer.next() {
+            Some(Ok((Token::Ident(name), _))) => assert_eq!(name.as_ptr(), src[1..].as_ptr()),
+            token => panic!("{token:?}"),
+        }
+        assert_eq!(
+            lexer.by_ref().take(3).collect::<Result<Vec<_>, _>>(),
+            Ok(vec![
+                (Token::Ident("x"), Span { start: 3, end: 4 }),
+                (Token::RParen, Span { start: 4, end: 5 }),
+                (Token::Int(1), Span { start: 6, end: 7 }),
+            ])
+        );
+        assert_eq!(
+            lexer.next().map(|token| token.unwrap_err().kind),
+            Some(LexErrorKind::UnexpectedChar('#'))
+        );
+        assert_eq!(lexer.next(), None);
+        assert_eq!(Lexer::new(" ; only a comment").next(), None);
+    }
+
+    #[test]
+    fn identifiers() {
+        let ident = |name: &'static str| Ok(vec![Token::Ident(name)]);
+        for name in [
+            "iadd_imm",
+            "icmp_imm",
+            "v0",
+            "block3",
+            "f64.const",
+            "i32x4",
+            "a_.b",
+        ] {
+            assert_eq!(tokens(name), ident(name));
+        }
+        assert_eq!(
+            tokens("(iadd_imm v0 1)"),
+            Ok(vec![
+                Token::LParen,
+                Token::Ident("iadd_imm"),
+                Token::Ident("v0"),
+                Token::Int(1),
+                Token::RParen,
+            ])
+        );
+        // Only a letter starts a name.
+        assert_eq!(
+            error("_private"),
+            (LexErrorKind::UnexpectedChar('_'), (0, 1, 1))
+        );
+        assert_eq!(error(".5"), (LexErrorKind::UnexpectedChar('.'), (0, 1, 1)));
+        assert_eq!(
+            error("1abc"),
+            (LexErrorKind::UnexpectedChar('a'), (1, 1, 2))
+        );
+        assert_eq!(error("3_x"), (LexErrorKind::MisplacedUnderscore, (1, 1, 2)));
+        assert_eq!(error("x-y"), (LexErrorKind::UnexpectedChar('-'), (1, 1, 2)));
+    }
+
+    #[test]
+    fn negative_literals() {
+        assert_eq!(tokens("-0"), Ok(vec![Token::Int(0)]));
+        assert_eq!(tokens("-1_000"), Ok(vec![Token::Int(-1000)]));
+        assert_eq!(tokens("-2.5"), Ok(vec![Token::Float(-2.5)]));
+        assert!(matches!(tokens("-0.0").unwrap()[..], [Token::Float(f)] if f.is_sign_negative()));
+        assert_eq!(
+            tokens("(isub v0 -1)"),
+            Ok(vec![
+                Token::LParen,
+                Token::Ident("isub"),
+                Token::Ident("v0"),
+                Token::Int(-1),
+                Token::RParen,
+            ])
+        );
+        assert_eq!(
+            tokens(&format!("-{}", 1u128 << 127)),
+            Ok(vec![Token::Int(i128::MIN)])
+        );
+        assert_eq!(
+            error(&format!("{}", 1u128 << 127)).0,
+            LexErrorKind::IntTooLarge
+        );
+    }
+
+    #[test]
+    fn large_literals() {
+        assert_eq!(
+            error(&u128::MAX.to_string()),
+            (LexErrorKind::IntTooLarge, (0, 1, 1))
+        );
+        // u128::MAX + 1
+        assert_eq!(
+            error("(iadd x 340282366920938463463374607431768211456)"),
+            (LexErrorKind::IntTooLarge, (8, 1, 9))
+        );
+        assert_eq!(
+            error(&format!("0x{:x}0", u128::MAX)),
+            (LexErrorKind::IntTooLarge, (0, 1, 1))
+        );
+        let digits = "9".repeat(1000);
+        assert_eq!(error(&digits), (LexErrorKind::IntTooLarge, (0, 1, 1)));
+        assert_eq!(
+            error(&format!("-{digits}")),
+            (LexErrorKind::IntTooLarge, (0, 1, 1))
+        );
+        assert_eq!(
+            tokens(&format!("{digits}.5")),
+            Ok(vec![Token::Float(f64::INFINITY)])
+        );
+        assert_eq!(tokens(&format!("0.{digits}")), Ok(vec![Token::Float(1.0)]));
+        assert_eq!(tokens(&format!("1e-{digits}")), Ok(vec![Token::Float(0.0)]));
+    }
+
+    #[test]
+    fn underscores() {
+        assert_eq!(tokens("1_000_000"), Ok(vec![Token::Int(1_000_000)]));
+        assert_eq!(tokens("-1_0.5"), Ok(vec![Token::Float(-10.5)]));
+        assert_eq!(tokens("0xff_ff"), Ok(vec![Token::Int(0xffff)]));
+        assert_eq!(tokens("0b1_0"), Ok(vec![Token::Int(2)]));
+        assert_eq!(tokens("0x_ff"), Ok(vec![Token::Int(0xff)]));
+        for (src, offset) in [
+            ("1__", 1),
+            ("1_", 1),
+            ("1__000", 1),
+            ("1_000_", 5),
+            ("-1_", 2),
+            ("0x__ff", 2),
+            ("0xff_", 4),
+            ("0b1__0", 3),
+            ("1_.5", 1),
+            ("1_e5", 1),
+        ] {
+            assert_eq!(
+                error(src),
+                (LexErrorKind::MisplacedUnderscore, (offset, 1, offset + 1)),
+                "{src}"
+            );
+        }
+        assert_eq!(
+            tokens("1__").unwrap_err().to_string(),
+            "1:2: `_` must be followed by a digit"
+        );
+    }
+
+    #[test]
+    fn float_digits() {
+        assert_eq!(tokens("1_000.5"), Ok(vec![Token::Float(1000.5)]));
+        assert_eq!(tokens("1_000.000_5"), Ok(vec![Token::Float(1000.0005)]));
+        assert_eq!(tokens("-0.2_5e1"), Ok(vec![Token::Float(-2.5)]));
+        assert_eq!(error("1."), (LexErrorKind::NoFractionDigits, (0, 1, 1)));
+        assert_eq!(error("(f 1.)"), (LexErrorKind::NoFractionDigits, (3, 1, 4)));
+        assert_eq!(error("1._5"), (LexErrorKind::NoFractionDigits, (0, 1, 1)));
+        assert_eq!(error("1.e5"), (LexErrorKind::NoFractionDigits, (0, 1, 1)));
+        assert_eq!(error("1..5"), (LexErrorKind::NoFractionDigits, (0, 1, 1)));
+        assert_eq!(
+            error("1.5_"),
+            (LexErrorKind::MisplacedUnderscore, (3, 1, 4))
+        );
+        assert_eq!(
+            error("1.5__5"),
+            (LexErrorKind::MisplacedUnderscore, (3, 1, 4))
+        );
+        assert_eq!(
+            error("1.5.5"),
+            (LexErrorKind::UnexpectedChar('.'), (3, 1, 4))
+        );
+        assert_eq!(error(".."), (LexErrorKind::UnexpectedChar('.'), (0, 1, 1)));
+        assert_eq!(error(".5"), (LexErrorKind::UnexpectedChar('.'), (0, 1, 1)));
+    }
+
+    #[test]
+    fn operators() {
+        let ident = Token::Ident;
+        for name in ["+", "-", "*", "<", "<=", ">", ">=", "=", "!="] {
+            assert_eq!(tokens(name), Ok(vec![ident(name)]), "{name}");
+        }
+        assert_eq!(
+            tokens("(- x -1)(+ 1 2);"),
+            Ok(vec![
+                Token::LParen,
+                ident("-"),
+                ident("x"),
+                Token::Int(-1),
+                Token::RParen,
+                Token::LParen,
+                ident("+"),
+                Token::Int(1),
+                Token::Int(2),
+                Token::RParen,
+            ])
+        );
+        assert_eq!(error("+1"), (LexErrorKind::UnexpectedChar('1'), (1, 1, 2)));
+        assert_eq!(error("<x"), (LexErrorKind::UnexpectedChar('x'), (1, 1, 2)));
+    }
+
+    #[test]
+    fn lone_minus() {
+        assert_eq!(error("-x"), (LexErrorKind::LoneMinus, (0, 1, 1)));
+        assert_eq!(error("(isub x -y)"), (LexErrorKind::LoneMinus, (8, 1, 9)));
+        assert!(tokens("--1").is_err());
+        // A `-` doesn't start a literal in the middle of another token.
+        assert!(tokens("(isub x-1 y)").is_err());
+        assert!(tokens("1-1").is_err());
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(
+            tokens("(include \"lib/helpers.clifp\")"),
+            Ok(vec![
+                Token::LParen,
+                Token::Ident("include"),
+                Token::Str("lib/helpers.clifp"),
+                Token::RParen,
+            ])
+        );
+        // Anything but a quote or a line break can be in a string, which ends at the first quote.
+        assert_eq!(
+            tokens("\"\" \"a ; (b) é\"\"c\""),
+            Err(LexError {
+                kind: LexErrorKind::UnexpectedChar('"'),
+                offset: 15,
+                line: 1,
+                column: 15,
+            })
+        );
+        assert_eq!(
+            tokens("\"\" \"a ; (b) é\""),
+            Ok(vec![Token::Str(""), Token::Str("a ; (b) é")])
+        );
+        assert_eq!(
+            error("(include \"a.clifp"),
+            (LexErrorKind::UnterminatedString, (9, 1, 10))
+        );
+        assert_eq!(
+            error("x \"a\nb\""),
+            (LexErrorKind::UnterminatedString, (2, 1, 3))
+        );
+        assert_eq!(
+            tokens("\"a\r\n\"").unwrap_err().to_string(),
+            "1:1: string literal is never closed"
+        );
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(
+            tokens("; add one\n(iadd ; the opcode\n  x 1) ; base case\n"),
+            tokens("(iadd x 1)")
+        );
+        assert_eq!(
+            tokens("(iadd x 1);no space\n"),
+            Ok(vec![
+                Token::LParen,
+                Token::Ident("iadd"),
+                Token::Ident("x"),
+                Token::Int(1),
+                Token::RParen,
+            ])
+        );
+        assert_eq!(tokens("x; at the end"), Ok(vec![Token::Ident("x")]));
+        assert_eq!(tokens("-1;(\n2"), Ok(vec![Token::Int(-1), Token::Int(2)]));
+        assert_eq!(tokens(";; only\n; comments\r\n;"), Ok(vec![]));
+        assert_eq!(tokens(""), Ok(vec![]));
+    }
+
+    #[test]
+    fn radix_literals() {
+        assert_eq!(tokens("0x10"), Ok(vec![Token::Int(16)]));
+        assert_eq!(tokens("0xffff_0000"), Ok(vec![Token::Int(0xffff_0000)]));
+        assert_eq!(
+            tokens("0XfF"),
+            Err(LexError {
+                kind: LexErrorKind::UnexpectedChar('X'),
+                offset: 1,
+                line: 1,
+                column: 2,
+            })
+        );
+        assert_eq!(tokens("0xAbC"), Ok(vec![Token::Int(0xabc)]));
+        assert_eq!(tokens("0o17"), Ok(vec![Token::Int(15)]));
+        assert_eq!(tokens("0b1010"), Ok(vec![Token::Int(10)]));
+        assert_eq!(tokens("0b_1_0"), Ok(vec![Token::Int(2)]));
+        assert_eq!(tokens("-0x80"), Ok(vec![Token::Int(-128)]));
+        assert_eq!(tokens("010"), Ok(vec![Token::Int(10)]));
+        assert_eq!(
+            tokens("0xffff_ffff_ffff_ffff"),
+            Ok(vec![Token::Int(u64::MAX.into())])
+        );
+        assert_eq!(
+            tokens(&format!("0x7{}", "f".repeat(31))),
+            Ok(vec![Token::Int(i128::MAX)])
+        );
+        assert_eq!(
+            tokens(&format!("-0x8{}", "0".repeat(31))),
+            Ok(vec![Token::Int(i128::MIN)])
+        );
+        assert!(tokens(&format!("0x8{}", "0".repeat(31))).is_err());
+        assert!(tokens(&format!("0x1{}", "0".repeat(32))).is_err());
+        assert_eq!(
+            tokens("(band v0 0xff)"),
+            Ok(vec![
+                Token::LParen,
+                Token::Ident("band"),
+                Token::Ident("v0"),
+                Token::Int(0xff),
+                Token::RParen,
+            ])
+        );
+    }
+
+    #[test]
+    fn malformed_radix_literals() {
+        assert_eq!(error("0x"), (LexErrorKind::NoRadixDigits, (0, 1, 1)));
+        assert_eq!(error("0x_"), (LexErrorKind::NoRadixDigits, (0, 1, 1)));
+        assert_eq!(
+            error("(iadd 0b 1)"),
+            (LexErrorKind::NoRadixDigits, (6, 1, 7))
+        );
+        let invalid = |digit, radix| LexErrorKind::InvalidDigit { digit, radix };
+        assert_eq!(error("0b2"), (invalid('2', 2), (2, 1, 3)));
+        assert_eq!(error("0o8"), (invalid('8', 8), (2, 1, 3)));
+        assert_eq!(error("-0xfg"), (invalid('g', 16), (4, 1, 5)));
+        assert!(tokens("0x1.5").is_err());
+    }
+
+    #[test]
+    fn error_positions() {
+        assert_eq!(
+            error("(func f () i64\n  (iadd 1 #))"),
+            (LexErrorKind::UnexpectedChar('#'), (25, 2, 11))
+        );
+        // Offsets count bytes, and columns count characters.
+        assert_eq!(
+            error("; é\n\tπ"),
+            (LexErrorKind::UnexpectedChar('π'), (6, 2, 2))
+        );
+        assert_eq!(error("1.x"), (LexErrorKind::NoFractionDigits, (0, 1, 1)));
+        assert_eq!(
+            error("\n\n  x1$"),
+            (LexErrorKind::UnexpectedChar('$'), (6, 3, 5))
+        );
+        assert_eq!(
+            tokens("(iadd\n  0b12)").unwrap_err().to_string(),
+            "2:6: invalid digit '2' in base-2 integer literal"
+        );
+    }
+
+    /// The bits of the single float `src` lexes to.
+    fn float_bits(src: &str) -> u64 {
+        match tokens(src).unwrap()[..] {
+            [Token::Float(f)] => f.to_bits(),
+            ref other => panic!("{src:?} lexed to {other:?}"),
+        }
+    }
+
+    #[test]
+    fn float_literals() {
+        for (src, value) in [
+            ("1e10", 1e10),
+            ("1E10", 1e10),
+            ("2.5e-3", 2.5e-3),
+            ("1.5e+3", 1.5e3),
+            ("-1.5E+3", -1.5e3),
+            ("1_000e0", 1000.0),
+            ("0e0", 0.0),
+            ("1e-400", 0.0),
+            ("1e400", f64::INFINITY),
+            ("inf", f64::INFINITY),
+            ("-inf", f64::NEG_INFINITY),
+        ] {
+            assert_eq!(float_bits(src), value.to_bits(), "{src}");
+        }
+        assert!(f64::from_bits(float_bits("nan")).is_nan());
+        assert_eq!(float_bits("-0e5"), (-0.0f64).to_bits());
+
+        // The sign of the exponent is part of the literal.
+        assert_eq!(
+            tokens("(fadd 1.5e+3 -inf)"),
+            Ok(vec![
+                Token::LParen,
+                Token::Ident("fadd"),
+                Token::Float(1.5e3),
+                Token::Float(f64::NEG_INFINITY),
+                Token::RParen,
+            ])
+        );
+        assert_eq!(lex("1.5e+3 x").unwrap()[1].1, Span { start: 7, end: 8 });
+        // Other names starting like the special floats are names.
+        for name in ["info", "nan2", "inf.x"] {
+            assert_eq!(tokens(name), Ok(vec![Token::Ident(name)]));
+        }
+    }
+
+    #[test]
+    fn malformed_float_literals() {
+        assert_eq!(error("1e"), (LexErrorKind::NoExponentDigits, (0, 1, 1)));
+        assert_eq!(
+            error("(f 2.5e+)"),
+            (LexErrorKind::NoExponentDigits, (3, 1, 4))
+        );
+        assert_eq!(error("-1E-"), (LexErrorKind::NoExponentDigits, (0, 1, 1)));
+        assert_eq!(error("1ex"), (LexErrorKind::NoExponentDigits, (0, 1, 1)));
+        assert_eq!(
+            error("1e5x"),
+            (LexErrorKind::UnexpectedChar('x'), (3, 1, 4))
+        );
+        assert_eq!(
+            error("1e5.0"),
+            (LexErrorKind::UnexpectedChar('.'), (3, 1, 4))
+        );
+        assert_eq!(
+            error("-infinity"),
+            (LexErrorKind::UnexpectedChar('i'), (4, 1, 5))
+        );
+        assert_eq!(error("-nan"), (LexErrorKind::LoneMinus, (0, 1, 1)));
+        assert_eq!(
+            tokens("1e_5").unwrap_err().to_string(),
+            "1:1: float literal has no digits in its exponent"
+        );
+    }
+
+    #[test]
+    fn spans() {
+        let src = "(func f ((x i64))\n\t(iadd x -0x1_0)) ; é\n(é";
+        let spans: Vec<(Token, (usize, usize))> = lex(&src[..src.len() - 3])
+            .unwrap()
+            .into_iter()
+            .map(|(token, span)| (token, (span.start, span.end)))
+            .collect();
+        let ident = Token::Ident;
+        assert_eq!(
+            spans,
+            [
+                (Token::LParen, (0, 1)),
+                (ident("func"), (1, 5)),
+                (ident("f"), (6, 7)),
+                (Token::LParen, (8, 9)),
+                (Token::LParen, (9, 10)),
+                (ident("x"), (10, 11)),
+                (ident("i64"), (12, 15)),
+                (Token::RParen, (15, 16)),
+                (Token::RParen, (16, 17)),
+                (Token::LParen, (19, 20)),
+                (ident("iadd"), (20, 24)),
+                (ident("x"), (25, 26)),
+                (Token::Int(-16), (27, 33)),
+                (Token::RParen, (33, 34)),
+                (Token::RParen, (34, 35)),
+            ]
+        );
+        for (token, (start, end)) in &spans {
+            if let Token::Ident(name) = token {
+                assert_eq!(&src[*start..*end], *name);
+            }
+        }
+
+        // The comment holds a 2-byte character, so the `(` after it is at byte 41.
+        let error = lex(src).unwrap_err();
+        assert_eq!(error.kind, LexErrorKind::UnexpectedChar('é'));
+        assert_eq!((error.offset, error.line, error.column), (42, 3, 2));
+        let (token, span) = lex(&src[..src.len() - 2]).unwrap().pop().unwrap();
+        assert_eq!(token, Token::LParen);
+        assert_eq!((span.start, span.end), (41, 42));
+        assert_eq!(
+            tokens("1.5\t2").unwrap(),
+            [Token::Float(1.5), Token::Int(2)]
+        );
+        assert_eq!(lex("1.5\t2").unwrap()[1].1, Span { start: 4, end: 5 });
+    }
+
+    #[test]
+    fn display() {
+        let src = "(iadd_imm v0 0x10) (fadd 1.5e+3 -inf) (f64.const 2.0) nan -0b11 1e-9";
+        let printed: Vec<String> = tokens(src)
+            .unwrap()
+            .iter()
+            .map(|token| token.to_string())
+            .collect();
+        assert_eq!(
+            printed.join(" "),
+            "( iadd_imm v0 16 ) ( fadd 1500.0 -inf ) ( f64.const 2.0 ) nan -3 1e-9"
+        );
+
+        // Every token lexes back to itself.
+        for token in tokens(src).unwrap().into_iter().chain([
+            Token::Int(i128::MAX),
+            Token::Float(0.1),
+            Token::Float(-0.0),
+            Token::Float(1e300),
+            Token::Float(f64::MIN_POSITIVE),
+            Token::Float(123456789.0),
+            Token::Str("lib/a b.clifp"),
+        ]) {
+            let printed = token.to_string();
+            match (&token, tokens(&printed).unwrap().as_slice()) {
+                (Token::Float(x), [Token::Float(y)]) => {
+                    assert_eq!(x.to_bits(), y.to_bits(), "{printed}")
+                }
+                (_, relexed) => assert_eq!(relexed, std::slice::from_ref(&token), "{printed}"),
+            }
+        }
+        assert_eq!(Token::Float(-f64::NAN).to_string(), "nan");
+    }
+}
@@ -0,0 +1,354 @@
+//! clifp, a tiny language of parenthesized forms compiled with Cranelift.
+//!
+//! A program is a sequence of function, data, constant and test definitions and declarations of
+//! host functions. A function definition lists the parameters and their types, then the types of
+//! the results, then the expressions of the body, whose last one computes the result. Functions
+//! can call each other and themselves whichever order they're defined in. An expression
+//! is an integer literal, a parameter, a CLIF integer opcode applied to operands, a call
+//! `(call name args...)`, or a conditional `(if cond then else)`, which computes `then` if
+//! `cond` isn't zero and `else` otherwise:
+//!
+//! ```text
+//! (func mix ((x i64) (y i64)) (i64)
+//!   (bxor (imul x 3) (ishl y 4)))
+//!
+//! (func fact ((n i32)) (i32)
+//!   (if n (imul n (call fact (isub n 1))) 1))
+//! ```
+//!
+//! A `(let ((name expr)...) body)` computes `body` with each name bound to the value of its
+//! expression. The names are bound in order, so each expression can use the names before it,
+//! and a name bound again shadows the earlier binding, but a `let` can't bind the name of a
+//! parameter:
+//!
+//! ```text
+//! (func poly ((x i64)) (i64)
+//!   (let ((square (imul x x)) (next (iadd square 1)))
+//!     (imul square next)))
+//! ```
+//!
+//! A `(loop ((name init)...) body)` computes `body` with each name bound to a variable, which
+//! starts at the value of its `init`. A `(continue values...)` as the last expression of `body`,
+//! or of a branch of an `if` or the body of a `let` there, computes `body` again with new values
+//! of the variables, and otherwise the value of `body` is the value of the loop. The initial
+//! values are computed outside the loop. A variable written `(name type init)` gives the literals
+//! of `init` that type:
+//!
+//! ```text
+//! (func fact ((n i32)) (i32)
+//!   (loop ((i n) (acc i32 1))
+//!     (if (> i 1) (continue (- i 1) (* acc i)) acc)))
+//! ```
+//!
+//! A `(while cond body...)` computes the expressions of `body` in order for as long as `cond`,
+//! of type `i8` or `i32`, isn't zero, testing it again before each time. Since the values of
+//! names don't change, the body works on stack slots, described below, and memory. A
+//! `(continue)` in the body, where a `continue` can be in a loop, goes on to the next test. The
+//! value of a `while` is that of the condition which ended it, zero:
+//!
+//! ```text
+//! (func triangle ((n i32)) (i32)
+//!   (stack s 8)
+//!   (stack_store i32 s 0 n)
+//!   (stack_store i32 s 4 0)
+//!   (while (> (stack_load i32 s 0) 0)
+//!     (stack_store i32 s 4 (+ (stack_load i32 s 4) (stack_load i32 s 0)))
+//!     (stack_store i32 s 0 (- (stack_load i32 s 0) 1)))
+//!   (stack_load i32 s 4))
+//! ```
+//!
+//! The body of a function can end with block definitions `(block name ((param type)...) body...)`,
+//! after the expressions of the entry block. The last expression of a block, the entry block
+//! included, can be a branch to blocks defined anywhere in the function, `(jump (name args...))`
+//! or `(brif cond (then args...) (else args...))`, instead of computing the result. The
+//! parameters of the function are in scope in every block, along with those of the block:
+//!
+//! ```text
+//! (func fact ((n i64)) (i64)
+//!   (jump (loop n 1))
+//!   (block loop ((i i64) (acc i64))
+//!     (brif i (step i acc) (done acc)))
+//!   (block step ((i i64) (acc i64))
+//!     (jump (loop (isub i 1) (imul acc i))))
+//!   (block done ((acc i64))
+//!     acc))
+//! ```
+//!
+//! The last expression of a block, or a branch of an `if`, can also be `(return value)`, which
+//! returns from the function right away:
+//!
+//! ```text
+//! (func bump ((x i64)) (i64)
+//!   (+ 1 (if (< x 0) (return 0) x)))
+//! ```
+//!
+//! A function can have several results, which it returns with `(return values...)`, a value
+//! for each. The results of a call to one are bound by a binding `((name...) (call name
+//! args...))` of a `let`, in order:
+//!
+//! ```text
+//! (func divmod ((a i32) (b i32)) (i32 i32)
+//!   (loop ((q i32 0) (r a))
+//!     (if (>= r b) (continue (+ q 1) (- r b)) (return q r))))
+//!
+//! (func digits ((a i32) (b i32)) (i32)
+//!   (let (((q r) (call divmod a b)))
+//!     (+ (* q 100) r)))
+//! ```
+//!
+//! In the same places, `(tail_call name args...)` returns the result of calling `name`, reusing
+//! the stack frame of the function, so a tail-recursive function runs in constant stack space.
+//! Tail calls need the `tail` calling convention, which the calling function and the called one
+//! use when they are compiled together, and which other functions call like any other function.
+//! They also need the `preserve_frame_pointers` setting, without which [`compile`] reports an
+//! error at the first tail call of each function making them:
+//!
+//! ```text
+//! (func fact ((n i64)) (i64)
+//!   (call go n 1))
+//!
+//! (func go ((n i64) (acc i64)) (i64)
+//!   (if (> n 1) (tail_call go (- n 1) (* acc n)) acc))
+//! ```
+//!
+//! A declaration `(extern name ((type)...) (types...))` lists the types of the parameters and
+//! results of a function defined outside the program, in the host, which `(call name args...)`
+//! calls in the default calling convention. Its symbol is resolved when the program is linked,
+//! or, in a JIT, among the symbols registered with `JITBuilder::symbol` and those of the process:
+//!
+//! ```text
+//! (extern double ((i32)) (i32))
+//!
+//! (func quadruple ((x i32)) (i32)
+//!   (call double (call double x)))
+//! ```
+//!
+//! A data definition `(data name (bytes byte...))` defines a read-only object of the given
+//! bytes. In a function, `(symbol name)` is its address, and `(load type address offset)` loads
+//! a value of `type` from `offset` bytes after an address, where `offset` is an integer literal:
+//!
+//! ```text
+//! (data table (bytes 1 2 3 4))
+//!
+//! (func second () (i8)
+//!   (load i8 (symbol table) 1))
+//! ```
+//!
+//! The body of a function can start with declarations `(stack name size)` of stack slots of
+//! `size` bytes, which every block of the function can access. `(stack_load type slot offset)`
+//! loads a value of `type` from `offset` bytes into a slot, and `(stack_store type slot offset
+//! value)` stores a value there and has the stored value as its own. The offset is an integer
+//! literal, and the value must be within the slot:
+//!
+//! ```text
+//! (func sum ((x i32) (y i32)) (i32)
+//!   (stack pair 8)
+//!   (stack_store i32 pair 0 x)
+//!   (stack_store i32 pair 4 y)
+//!   (+ (stack_load i32 pair 0) (stack_load i32 pair 4)))
+//! ```
+//!
+//! The operators `+`, `-` and `*` are other names of `iadd`, `isub` and `imul`, or of `fadd`,
+//! `fsub` and `fmul` for floats. The comparisons `=`, `!=`, `<`, `<=`, `>` and `>=` compare two
+//! integers of the same type as signed integers, giving an `i8` which is 1 if the comparison
+//! holds and 0 otherwise, as a condition of `if` or `brif`.
+//! `(icmp cc x y)` compares `x` and `y` with the condition code `cc` of CLIF, one of `eq`,
+//! `ne`, `slt`, `sle`, `sgt`, `sge`, `ult`, `ule`, `ugt` and `uge`, so that integers can also
+//! be compared as unsigned integers, and `(icmp_imm cc x literal)` compares `x` with an integer
+//! literal:
+//!
+//! ```text
+//! (func below ((x i64) (limit i64)) (i8)
+//!   (band (icmp ult x limit) (icmp_imm sge x 0)))
+//! ```
+//!
+//! `(select cond a b)` is `a` if `cond` isn't zero and `b` otherwise, like an `if`, but it
+//! computes both `a` and `b` and chooses between their values without branching:
+//!
+//! ```text
+//! (func max ((x i64) (y i64)) (i64)
+//!   (select (> x y) x y))
+//! ```
+//!
+//! `(sdiv x y)` and `(srem x y)` divide `x` by `y` as signed integers, rounding the quotient
+//! toward zero, so the remainder has the sign of `x`, and `(udiv x y)` and `(urem x y)` divide
+//! them as unsigned integers. A division traps if `y` is zero, and `sdiv` also traps on the
+//! overflow of dividing the smallest integer of its type by -1, of which `srem` is 0. A literal
+//! or constant divisor of zero is an error, since the division would always trap:
+//!
+//! ```text
+//! (func round_up ((x i64) (step i64)) (i64)
+//!   (* (udiv (+ x (- step 1)) step) step))
+//! ```
+//!
+//! Integer literals, such as `42`, `-1_000` or `0xff_00`, take the type of the other operands
+//! of their opcode, or of the parameter or result whose value they are, and have type `i64`
+//! otherwise. A `0x`, `0o` or `0b` prefix selects hexadecimal, octal or binary digits.
+//! Floating-point literals, such as `1.5`, `-2e10` or `inf`, take their type the same way, and
+//! have type `f64` otherwise.
+//!
+//! An integer literal must fit in its type, either as a signed or as an unsigned integer, so an
+//! `i32` literal is between -2147483648 and 4294967295, and one above 2147483647 has the bits
+//! of the corresponding negative number. Where an opcode only reads its operand one way, such
+//! as the signed comparisons, the value `sshr` shifts, the shift amounts and the operands of the
+//! divisions, the literal must fit that way. `(type literal)` gives a literal the type `type`,
+//! whatever its context:
+//!
+//! ```text
+//! (func big () (i64)
+//!   (iadd (i64 5_000_000_000) (i64 0xffff_ffff)))
+//! ```
+//!
+//! A constant definition `(const name value)` names an integer, which the functions use wherever
+//! an integer literal can be, where it takes its type and must fit in it as a literal does. Its
+//! value is an integer literal, a constant defined before it, or `(+ a b)`, `(- a b)` or `(* a
+//! b)` of those. A parameter, or a name bound by a `let` or a `loop`, shadows the constant of the
+//! same name, and a parameter doing so gets a warning:
+//!
+//! ```text
+//! (const LIMIT 1000)
+//! (const CAPPED (* LIMIT 2))
+//!
+//! (func clamp ((x i64)) (i64)
+//!   (if (> x LIMIT) CAPPED x))
+//! ```
+//!
+//! A test definition `(test name (expect value) expr)` checks that the expression `expr`, which
+//! can call the functions of the program and use its constants, computes the integer literal
+//! `value`. Its value must be an `i32` or an `i64`, which `value` must fit in as a literal does.
+//! Tests have a namespace of their own. `cargo test --test clifp_tests` compiles the programs
+//! in `cranelift/tests/clifp` with their tests in a JIT, runs each test and reports whether it
+//! passed:
+//!
+//! ```text
+//! (func fact ((n i32)) (i32)
+//!   (if n (imul n (call fact (isub n 1))) 1))
+//!
+//! (test fact_30 (expect 1409286144) (call fact 30))
+//! ```
+//!
+//! `(test name (trap code) expr)` checks instead that computing `expr` traps with `code`, the
+//! name of a trap code in CLIF, such as `int_divz` for a division by zero or `int_ovf` for the
+//! overflow of `sdiv`. A trap in the JIT would kill the process running the tests, so the
+//! harness runs these tests in `cranelift-interpreter`, where they can't call host functions
+//! or read data objects:
+//!
+//! ```text
+//! (func quot ((a i32) (b i32)) (i32) (sdiv a b))
+//!
+//! (test quot_by_zero (trap int_divz) (call quot 1 0))
+//! ```
+//!
+//! The types are those of CLIF: the integer types `i8` to `i128`, the floating-point types `f32`
+//! and `f64`, and vectors of them such as `i32x4`. The opcodes `fadd`, `fsub`, `fmul` and `fdiv`
+//! apply to floats, which `+`, `-` and `*` also name when their operands are floats, and the
+//! other opcodes only apply to integers and vectors of integers. The operands of an opcode have
+//! the same type, so integers and floats don't mix, and a condition must be a scalar integer.
+//! `(fcvt_from_sint type x)` converts the signed integer `x` to the float `type`, and
+//! `(fcvt_to_sint type x)` converts the float `x` to the integer `type`, rounding toward zero
+//! and trapping if `x` is NaN or out of its range:
+//!
+//! ```text
+//! (func mean ((sum f64) (n i64)) (f64)
+//!   (fdiv sum (fcvt_from_sint f64 n)))
+//! ```
+//!
+//! Errors point into the source text: each is printed with its line and column, followed by
+//! the line with carets under the offending text. Every malformed top-level form, signature with
+//! an unknown type and function which doesn't lower gets its own error, so one run reports the
+//! independent problems of a program together.
+//!
+//! A `(include "path")` among the definitions is replaced by the definitions of the file at
+//! `path`, relative to the directory of the including file, so that several programs can share
+//! helper functions and constants. A file included again adds nothing, and a file which includes
+//! itself, directly or through others, is an error. Includes are only supported in programs read
+//! from files, and the errors in an included file name it:
+//!
+//! ```text
+//! (include "lib/fact.clifp")
+//!
+//! (func main () (i64)
+//!   (call fact 20))
+//! ```
+//!
+//! [`optimize`] simplifies a program before it's compiled, as `clifp-aot -O` does. It folds
+//! the integer arithmetic of literals and constants, wrapping at the width of its type,
+//! replaces each `if` whose condition is a literal by the branch it takes, and drops the `let`
+//! bindings whose values are unused and have no effects. Calls and memory accesses are kept
+//! as they are, in order, and the functions and tests which don't compile are left as they
+//! are, so that a program has the same errors with and without it. The body of
+//!
+//! ```text
+//! (func seconds ((hours i64)) (i64)
+//!   (let ((minutes (* hours 60)))
+//!     (if (- 4 4) (call slow hours) (* hours (* 60 60)))))
+//! ```
+//!
+//! is simplified to `(* hours 3600)`.
+//!
+//! A `;` starts a comment, which runs to the end of the line. String literals, between `"`s,
+//! are the paths of includes.
+//!
+//! [`frontend`] parses a program and resolves the types of its functions, [`compile`] defines
+//! them in a Cranelift module, and [`lower`] lowers a single function to Cranelift IR. Here a
+//! program is compiled in a JIT, and its function called:
+//!
+//! ```
+//! use cranelift_jit::{JITBuilder, JITModule};
+//! use cranelift_module::default_libcall_names;
+//!
+//! let src = "(func fact ((n i64)) (i64) (if (> n 1) (* n (call fact (- n 1))) 1))";
+//! let (program, _) = cranelift_clifp::frontend(src).unwrap();
+//! let mut jit = JITModule::new(JITBuilder::new(default_libcall_names()).unwrap());
+//! let compiled = cranelift_clifp::compile(&mut jit, &program).unwrap();
+//! jit.finalize_definitions().unwrap();
+//!
+//! let (_, id) = compiled.functions[0];
+//! let code = jit.get_finalized_function(id);
+//! let fact = unsafe { std::mem::transmute::<*const u8, extern "C" fn(i64) -> i64>(code) };
+//! assert_eq!(fact(10), 3_628_800);
+//! ```
+
+pub mod compile;
+pub mod diagnostic;
+pub mod lexer;
+pub mod optimize;
+pub mod parser;
+pub mod sexp;
+pub mod source;
+pub mod typeck;
+
+pub use compile::{compile, compile_tests, lower, CompileError, Compiled};
+pub use lexer::lex;
+pub use optimize::optimize;
+pub use parser::{Function, Module};
+
+/// Parse `src`, and resolve the types of its functions.
+///
+/// ```
+/// let (module, types) = cranelift_clifp::frontend("(func id ((x i32)) (i32) x)").unwrap();
+/// assert_eq!(module.functions[0].name, "id");
+/// assert_eq!(types.len(), 1);
+///
+/// let errors = cranelift_clifp::frontend("(func id ((x i33)) (i32) x)").unwrap_err();
+/// assert_eq!(errors.len(), 1);
+/// ```
+pub fn frontend(
+    src: &str,
+) -> Result<(parser::Module, Vec<typeck::FuncType>), Vec<diagnostic::Diagnostic>> {
+    let module = parser::Module::parse(src)?;
+    let types = typeck::check(&module)?;
+    Ok((module, types))
+}
+
+/// Parse the program at `path` and the files it includes, read with `loader` and added to
+/// `sources`, and resolve the types of its functions.
+pub fn frontend_file(
+    path: &std::path::Path,
+    loader: &dyn source::Loader,
+    sources: &mut source::SourceMap,
+) -> Result<(parser::Module, Vec<typeck::FuncType>), Vec<diagnostic::Diagnostic>> {
+    let module = parser::Module::parse_file(path, loader, sources)?;
+    let types = typeck::check(&module)?;
+    Ok((module, types))
+}
@@ -0,0 +1,1143 @@
+//! Simplifying the functions and tests of a clifp [`Module`] before they're lowered, see
+//! [`optimize`].
+
+use super::compile::{
+    bound_names, comparison, condition_code, first_tail_call, head, lowers, opcode, Interpretation,
+    BINARY, DIVISIONS, SHIFTS, UNARY,
+};
+use super::parser::{Function, Module};
+use super::sexp::Sexp;
+use super::typeck::{resolve_type, FuncType};
+use cranelift_codegen::ir::{types, Type};
+use std::collections::{HashMap, HashSet};
+
+/// Simplify the functions and the tests of `module`: fold the integer arithmetic of literals
+/// and constants to literals, replace each `if` whose condition is a literal by the branch it
+/// takes, and drop the `let` bindings whose values are unused and have no effects.
+///
+/// The operators `+`, `-`, `*`, `iadd`, `isub`, `imul`, `band`, `bor`, `bxor`, `ineg`, `bnot`,
+/// `ishl`, `ushr` and `sshr` are folded, wrapping at the width of the type their literals have
+/// when they're lowered, so that the folded literal has the bits they compute. The literals
+/// take their type from their context, which the pass follows as the lowering does, from the
+/// types of the parameters, the bindings, the results and the callees. Nothing is folded where
+/// the type can't be told without lowering, or where a literal must be unsigned, as the operand
+/// of `ushr` and those of the unsigned comparisons, since a folded literal is written as a
+/// signed integer. Calls, loads, stores and the other operators are never folded or moved, so
+/// all that may have effects is computed in the same order as before. The divisions, which can
+/// trap, are among them, and a divisor isn't simplified to a literal zero, which doesn't lower.
+///
+/// A program which compiles computes the same values once it's simplified. The functions and
+/// tests which don't lower for the host are left as they are, so that the errors of the code
+/// the pass would remove, such as a branch which isn't taken, are still reported, and a branch
+/// which makes a tail call is kept for its error on the targets which can't make one.
+///
+/// ```
+/// use cranelift_clifp::{optimize, sexp, Module};
+///
+/// let src = "(func seconds ((hours i64)) (i64) (if (- 4 4) 0 (* hours (* 60 60))))";
+/// let mut module = Module::parse(src).unwrap();
+/// optimize(&mut module);
+/// assert_eq!(sexp::print(&module.functions[0].body).trim(), "(* hours 3600)");
+/// ```
+pub fn optimize(module: &mut Module) {
+    // A name of several functions is an error of the program, whose callee isn't known.
+    let mut callees: HashMap<String, Option<FuncType>> = HashMap::new();
+    let signatures = module
+        .functions
+        .iter()
+        .map(|func| (&func.name, func.func_type().ok()))
+        .chain(
+            module
+                .externs
+                .iter()
+                .map(|host| (&host.name, host.func_type().ok())),
+        );
+    for (name, ty) in signatures {
+        callees
+            .entry(name.clone())
+            .and_modify(|known| *known = None)
+            .or_insert(ty);
+    }
+    let constants: HashMap<String, i128> = module
+        .constants
+        .iter()
+        .map(|constant| (constant.name.clone(), constant.value))
+        .collect();
+
+    let (functions, tests) = lowers(module);
+    for (func, _) in module
+        .functions
+        .iter_mut()
+        .zip(functions)
+        .filter(|&(_, ok)| ok)
+    {
+        if let Ok(ty) = func.func_type() {
+            Optimizer::new(&callees, &constants, &func.body).function(func, ty);
+        }
+    }
+    for (test, _) in module.tests.iter_mut().zip(tests).filter(|&(_, ok)| ok) {
+        let forms = std::slice::from_ref(&test.expr);
+        Optimizer::new(&callees, &constants, forms).expr(&mut test.expr, Ty::Free, false);
+    }
+}
+
+/// What the pass knows of the type of an expression, or of the type it's lowered with if it
+/// can't be inferred.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Ty {
+    Known(Type),
+    /// The expression takes its type from its context, as a literal does. As the type it's
+    /// lowered with, its context has none to give, so that integer literals are `i64`s.
+    Free,
+    /// The type can't be told without lowering.
+    Unknown,
+}
+
+impl Ty {
+    /// `self`, or `other` if the expression takes its type from its context.
+    fn or(self, other: Ty) -> Ty {
+        match self {
+            Ty::Free => other,
+            ty => ty,
+        }
+    }
+
+    /// The first of `tys` which isn't [`Ty::Free`], as the lowering infers the type of the
+    /// first operand which has one.
+    fn first(tys: impl IntoIterator<Item = Ty>) -> Ty {
+        tys.into_iter()
+            .find(|&ty| ty != Ty::Free)
+            .unwrap_or(Ty::Free)
+    }
+
+    /// The integer type of the literals lowered with `self`, if it's known to be one.
+    fn literal_type(self) -> Option<Type> {
+        match self {
+            Ty::Known(ty) if ty.is_int() => Some(ty),
+            Ty::Free => Some(types::I64),
+            _ => None,
+        }
+    }
+}
+
+/// The state of [`optimize`] in a function or a test.
+struct Optimizer<'a> {
+    /// The type of each function and host function of the module, if it's known.
+    callees: &'a HashMap<String, Option<FuncType>>,
+    /// The value of each constant of the module which no parameter, block parameter, `let`
+    /// binding or loop variable of the function has the name of, whether it's in scope or not.
+    constants: HashMap<&'a str, i128>,
+    /// The type of each parameter, `let` binding and loop variable in scope.
+    vars: HashMap<String, Ty>,
+    /// The types of the variables of the loops around the current expression, innermost last.
+    /// A `while` has none.
+    loops: Vec<Vec<Ty>>,
+    /// The types of the parameters of each block of the function, by name.
+    blocks: HashMap<String, Vec<Ty>>,
+    /// The result types of the function.
+    returns: Vec<Type>,
+}
+
+impl<'a> Optimizer<'a> {
+    /// An optimizer of `forms`, the body of a function or the expression of a test.
+    fn new(
+        callees: &'a HashMap<String, Option<FuncType>>,
+        constants: &'a HashMap<String, i128>,
+        forms: &[Sexp],
+    ) -> Self {
+        let mut bound = HashSet::new();
+        bound_names(forms, &mut bound);
+        for form in forms.iter().filter(|form| head(form) == Some("block")) {
+            if let Some((_, params)) = block_signature(form) {
+                bound.extend(params.into_iter().map(|(name, _)| name));
+            }
+        }
+        Optimizer {
+            callees,
+            constants: constants
+                .iter()
+                .map(|(name, &value)| (name.as_str(), value))
+                .filter(|(name, _)| !bound.contains(name))
+                .collect(),
+            vars: HashMap::new(),
+            loops: Vec::new(),
+            blocks: HashMap::new(),
+            returns: Vec::new(),
+        }
+    }
+
+    /// Simplify the body of `func`, whose type is `ty`, block by block.
+    fn function(&mut self, func: &mut Function, ty: FuncType) {
+        let params: HashMap<String, Ty> = func
+            .params
+            .iter()
+            .map(|(name, _)| name.clone())
+            .zip(ty.params.iter().map(|&ty| Ty::Known(ty)))
+            .collect();
+        self.constants.retain(|name, _| !params.contains_key(*name));
+        let ret = match ty.returns[..] {
+            [ret] => Ty::Known(ret),
+            _ => Ty::Free,
+        };
+        self.returns = ty.returns;
+
+        // The stack slots are declared before the expressions of the entry block, and the
+        // blocks are defined after them.
+        let first_expr = func
+            .body
+            .iter()
+            .position(|form| head(form) != Some("stack"))
+            .unwrap_or(func.body.len());
+        let body = &mut func.body[first_expr..];
+        let first_block = body
+            .iter()
+            .position(|form| head(form) == Some("block"))
+            .unwrap_or(body.len());
+        let (entry, blocks) = body.split_at_mut(first_block);
+        let mut defs = Vec::new();
+        for form in blocks.iter() {
+            // A function whose blocks can't be lowered isn't simplified.
+            let (name, params) = match block_signature(form) {
+                Some(signature) => signature,
+                None => return,
+            };
+            self.blocks
+                .insert(name.to_string(), params.iter().map(|&(_, ty)| ty).collect());
+            let params: Vec<_> = params
+                .into_iter()
+                .map(|(name, ty)| (name.to_string(), ty))
+                .collect();
+            defs.push(params);
+        }
+
+        self.vars = params.clone();
+        self.body(entry, ret);
+        for (form, block_params) in blocks.iter_mut().zip(defs) {
+            // The parameters of the function are in scope in every block, along with those of
+            // the block.
+            self.vars = params.clone();
+            self.vars.extend(block_params);
+            if let Sexp::List(items) = form {
+                self.body(&mut items[3..], ret);
+            }
+        }
+    }
+
+    /// Simplify the expressions of a block, whose last one computes the result, of type `ret`,
+    /// unless it's a branch.
+    fn body(&mut self, exprs: &mut [Sexp], ret: Ty) {
+        let (last, exprs) = match exprs.split_last_mut() {
+            Some(split) => split,
+            None => return,
+        };
+        for expr in exprs {
+            self.expr(expr, Ty::Free, false);
+        }
+        let op = head(last);
+        let (jump, brif) = (op == Some("jump"), op == Some("brif"));
+        match last {
+            Sexp::List(items) if jump || brif => self.branch(jump, &mut items[1..]),
+            last => self.expr(last, ret, true),
+        }
+    }
+
+    /// Simplify `expr`, which is lowered with `hint` if its type can't be inferred, and which
+    /// is a branch of an `if`, the body of a `let` or a `loop` or the last expression of a
+    /// block or of the body of a `while` if `arm`, where it can return or continue a loop.
+    fn expr(&mut self, expr: &mut Sexp, hint: Ty, arm: bool) {
+        let replacement = match expr {
+            Sexp::List(items) => match items.split_first_mut() {
+                Some((Sexp::Ident(op), operands)) => self.list(op.as_str(), operands, hint, arm),
+                _ => None,
+            },
+            _ => None,
+        };
+        if let Some(replacement) = replacement {
+            *expr = replacement;
+        }
+    }
+
+    /// Simplify the operands of `(op operands...)`, and return the expression it simplifies to,
+    /// if it isn't the application of `op` anymore.
+    ///
+    /// The operands are given the types the lowering gives them. A malformed expression, which
+    /// doesn't lower, is left as it is.
+    fn list(&mut self, op: &str, operands: &mut [Sexp], hint: Ty, arm: bool) -> Option<Sexp> {
+        match (op, operands.len()) {
+            ("call" | "tail_call", _) => {
+                let callee = match operands.first() {
+                    Some(Sexp::Ident(name)) => self.callees.get(name.as_str()),
+                    _ => None,
+                };
+                let params = match callee {
+                    Some(Some(ty)) if ty.params.len() + 1 == operands.len() => {
+                        ty.params.iter().map(|&ty| Ty::Known(ty)).collect()
+                    }
+                    _ => Vec::new(),
+                };
+                self.exprs(&mut operands[1..], &params);
+            }
+            ("if", 3) => return self.if_(operands, hint, arm),
+            ("select", 3) => {
+                self.expr(&mut operands[0], Ty::Free, false);
+                let hint = self.infer_first(&operands[1..]).or(hint);
+                self.expr(&mut operands[1], hint, false);
+                let b_hint = self.value_type(&operands[1], hint);
+                self.expr(&mut operands[2], b_hint, false);
+            }
+            ("let", 2) => return self.let_(operands, hint),
+            ("loop", 2) => self.loop_(operands, hint),
+            ("while", n) if n >= 2 => {
+                self.expr(&mut operands[0], Ty::Known(types::I32), false);
+                self.loops.push(Vec::new());
+                self.body(&mut operands[1..], Ty::Free);
+                self.loops.pop();
+            }
+            ("continue", _) => {
+                let vars = match self.loops.last() {
+                    Some(vars) if vars.len() == operands.len() => vars.clone(),
+                    _ => Vec::new(),
+                };
+                self.exprs(operands, &vars);
+            }
+            ("return", _) => {
+                let returns = if self.returns.len() == operands.len() {
+                    self.returns.iter().map(|&ty| Ty::Known(ty)).collect()
+                } else {
+                    Vec::new()
+                };
+                self.exprs(operands, &returns);
+            }
+            // The address has the pointer type, which depends on the target.
+            ("load", 3) => self.expr(&mut operands[1], Ty::Unknown, false),
+            ("stack_store", 4) => {
+                let ty = type_named(&operands[0]);
+                self.expr(&mut operands[3], ty, false);
+            }
+            ("fcvt_from_sint", 2) => self.expr(&mut operands[1], Ty::Free, false),
+            ("fcvt_to_sint", 2) => self.expr(&mut operands[1], Ty::Known(types::F64), false),
+            ("icmp" | "icmp_imm", 3) => {
+                let cc = match &operands[0] {
+                    Sexp::Ident(name) => condition_code(name)?,
+                    _ => return None,
+                };
+                let x_hint = match op {
+                    "icmp" => self.infer_first(&operands[1..]),
+                    _ => self.infer(&operands[1]),
+                };
+                if Interpretation::of_comparison(cc) == Interpretation::Unsigned {
+                    self.exprs(&mut operands[1..2], &[]);
+                    if op == "icmp" {
+                        self.exprs(&mut operands[2..], &[]);
+                    }
+                    return None;
+                }
+                self.expr(&mut operands[1], x_hint, false);
+                // The immediate of `icmp_imm` is a literal already.
+                if op == "icmp" {
+                    let y_hint = self.value_type(&operands[1], x_hint);
+                    self.expr(&mut operands[2], y_hint, false);
+                }
+            }
+            (_, 2) if comparison(op).is_some() => {
+                let x_hint = self.infer_first(operands);
+                self.expr(&mut operands[0], x_hint, false);
+                let y_hint = self.value_type(&operands[0], x_hint);
+                self.expr(&mut operands[1], y_hint, false);
+            }
+            (_, 1) if UNARY.contains(&op) => {
+                let x_hint = self.infer(&operands[0]).or(hint);
+                self.expr(&mut operands[0], x_hint, false);
+                return self.fold(op, operands, hint);
+            }
+            (_, 2) if BINARY.contains(&opcode(op)) => {
+                let x_hint = self.infer_first(operands).or(hint);
+                self.expr(&mut operands[0], x_hint, false);
+                let y_hint = self.value_type(&operands[0], x_hint);
+                self.expr(&mut operands[1], y_hint, false);
+                return self.fold(op, operands, hint);
+            }
+            // A division of unsigned integers interprets its operands as unsigned. A divisor which
+            // would be simplified to zero stays as it is, to trap when it's computed.
+            (_, 2) if DIVISIONS.contains(&op) => {
+                let unsigned = matches!(op, "udiv" | "urem");
+                let x_hint = match self.infer_first(operands).or(hint) {
+                    _ if unsigned => Ty::Unknown,
+                    x_hint => x_hint,
+                };
+                self.expr(&mut operands[0], x_hint, false);
+                let y_hint = match self.value_type(&operands[0], x_hint) {
+                    _ if unsigned => Ty::Unknown,
+                    y_hint => y_hint,
+                };
+                let divisor = operands[1].clone();
+                self.expr(&mut operands[1], y_hint, false);
+                if self.literal(&operands[1]) == Some(0) {
+                    operands[1] = divisor;
+                }
+            }
+            // `ushr` interprets its operand as unsigned, and the shift amounts can have any
+            // type, so the arithmetic there isn't folded.
+            (_, 2) if SHIFTS.contains(&op) => {
+                let x_hint = match op {
+                    "ushr" => Ty::Unknown,
+                    _ => self.infer(&operands[0]).or(hint),
+                };
+                self.expr(&mut operands[0], x_hint, false);
+                self.expr(&mut operands[1], Ty::Unknown, false);
+                return self.fold(op, operands, hint);
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Simplify `exprs`, each lowered with its type of `tys`, or an unknown one if there are
+    /// fewer types.
+    fn exprs(&mut self, exprs: &mut [Sexp], tys: &[Ty]) {
+        for (i, expr) in exprs.iter_mut().enumerate() {
+            let ty = tys.get(i).copied().unwrap_or(Ty::Unknown);
+            self.expr(expr, ty, false);
+        }
+    }
+
+    /// Simplify `(if cond then else)` to the branch it takes if `cond` is a literal once it's
+    /// simplified, and otherwise simplify its branches.
+    ///
+    /// The taken branch is only kept on its own if it has no value or it's lowered with the
+    /// same type as in the `if`, and if it has a value or the `if` is an arm, where a branch
+    /// which returns can be. The other branch must not make a tail call, which is an error
+    /// without frame pointers.
+    fn if_(&mut self, operands: &mut [Sexp], hint: Ty, arm: bool) -> Option<Sexp> {
+        self.expr(&mut operands[0], Ty::Free, false);
+        let joined = self.infer_first(&operands[1..]).or(hint);
+        if let Some(cond) = self.literal(&operands[0]) {
+            let (taken, other) = if cond != 0 { (1, 2) } else { (2, 1) };
+            // The else branch is lowered with the type of the then branch if it has a value,
+            // which is that of the `if` when the then branch is a literal.
+            let same_type = match (self.infer(&operands[taken]), self.infer(&operands[other])) {
+                _ if diverges(&operands[taken]) => true,
+                (Ty::Known(_), _) => true,
+                (Ty::Free, Ty::Free) => {
+                    taken == 1 || diverges(&operands[1]) || self.literal_like(&operands[1])
+                }
+                _ => false,
+            };
+            let tail_call = first_tail_call(&operands[other..=other]).is_some();
+            if same_type && !tail_call && (arm || !diverges(&operands[taken])) {
+                let mut taken = std::mem::replace(&mut operands[taken], Sexp::List(Vec::new()));
+                self.expr(&mut taken, hint, arm);
+                return Some(taken);
+            }
+        }
+        self.expr(&mut operands[1], joined, true);
+        let else_hint = if diverges(&operands[1]) {
+            joined
+        } else {
+            self.value_type(&operands[1], joined)
+        };
+        self.expr(&mut operands[2], else_hint, true);
+        None
+    }
+
+    /// Simplify `(let ((name expr)...) body)`, then drop the bindings whose values have no
+    /// effects and whose names neither the expressions after them nor `body` use, and
+    /// simplify the `let` to `body` if none is left.
+    fn let_(&mut self, operands: &mut [Sexp], hint: Ty) -> Option<Sexp> {
+        let (bindings, body) = operands.split_at_mut(1);
+        let bindings = match &mut bindings[0] {
+            Sexp::List(bindings) => bindings,
+            _ => return None,
+        };
+        let outer = self.vars.clone();
+        for binding in bindings.iter_mut() {
+            let bound = match binding {
+                Sexp::List(items) if items.len() == 2 => self.binding(items),
+                _ => None,
+            };
+            match bound {
+                Some(bound) => self.vars.extend(bound),
+                None => {
+                    self.vars = outer;
+                    return None;
+                }
+            }
+        }
+        self.expr(&mut body[0], hint, true);
+        self.vars = outer;
+
+        // The names are used by the expressions after their bindings, which are kept if their
+        // own are, so the bindings are dropped from the last one.
+        let mut used = HashSet::new();
+        add_names(&body[0], &mut used);
+        let mut kept = Vec::new();
+        for binding in bindings.drain(..).rev() {
+            if let Sexp::List(items) = &binding {
+                if let [Sexp::Ident(name), value] = &items[..] {
+                    if !used.contains(name) && pure(value) {
+                        continue;
+                    }
+                }
+                add_names(&items[1], &mut used);
+            }
+            kept.push(binding);
+        }
+        kept.reverse();
+        *bindings = kept;
+        if !bindings.is_empty() {
+            return None;
+        }
+        Some(std::mem::replace(&mut body[0], Sexp::List(Vec::new())))
+    }
+
+    /// Simplify the expression of the binding `[name, expr]` or `[(names...), (call f
+    /// args...)]` of a `let`, and return the names it binds with their types.
+    fn binding(&mut self, items: &mut [Sexp]) -> Option<Vec<(String, Ty)>> {
+        let (names, value) = items.split_at_mut(1);
+        let value = &mut value[0];
+        match &names[0] {
+            Sexp::Ident(name) => {
+                self.expr(value, Ty::Free, false);
+                Some(vec![(name.clone(), self.value_type(value, Ty::Free))])
+            }
+            Sexp::List(names) if head(value) == Some("call") => {
+                self.expr(value, Ty::Free, false);
+                let returns = match value {
+                    Sexp::List(items) => match items.get(1) {
+                        Some(Sexp::Ident(callee)) => match self.callees.get(callee.as_str()) {
+                            Some(Some(ty)) if ty.returns.len() == names.len() => {
+                                ty.returns.iter().map(|&ty| Ty::Known(ty)).collect()
+                            }
+                            _ => vec![Ty::Unknown; names.len()],
+                        },
+                        _ => return None,
+                    },
+                    _ => return None,
+                };
+                names
+                    .iter()
+                    .zip(returns)
+                    .map(|(name, ty)| match name {
+                        Sexp::Ident(name) => Some((name.clone(), ty)),
+                        _ => None,
+                    })
+                    .collect()
+            }
+            _ => None,
+        }
+    }
+
+    /// Simplify `(loop ((name init)...) body)`, whose variables can be written `(name type
+    /// init)`.
+    fn loop_(&mut self, operands: &mut [Sexp], hint: Ty) {
+        let (bindings, body) = operands.split_at_mut(1);
+        let bindings = match &mut bindings[0] {
+            Sexp::List(bindings) => bindings,
+            _ => return,
+        };
+        // The initial values are computed before the loop, where its variables aren't in
+        // scope.
+        let mut vars = Vec::new();
+        for binding in bindings.iter_mut() {
+            let items = match binding {
+                Sexp::List(items) => items,
+                _ => return,
+            };
+            let (name, ty) = match &items[..] {
+                [Sexp::Ident(name), _] => (name.clone(), None),
+                [Sexp::Ident(name), Sexp::Ident(ty), _] => match resolve_type(ty) {
+                    Some(ty) => (name.clone(), Some(ty)),
+                    None => return,
+                },
+                _ => return,
+            };
+            let init = items.last_mut().unwrap();
+            let ty = match ty {
+                Some(ty) => {
+                    self.expr(init, Ty::Known(ty), false);
+                    Ty::Known(ty)
+                }
+                None => {
+                    self.expr(init, Ty::Free, false);
+                    self.value_type(init, Ty::Free)
+                }
+            };
+            vars.push((name, ty));
+        }
+
+        let outer = self.vars.clone();
+        self.loops.push(vars.iter().map(|&(_, ty)| ty).collect());
+        self.vars.extend(vars);
+        self.expr(&mut body[0], hint, true);
+        self.loops.pop();
+        self.vars = outer;
+    }
+
+    /// Simplify the branch `(jump (block args...))` if `jump`, or else `(brif cond (then
+    /// args...) (else args...))`.
+    fn branch(&mut self, jump: bool, operands: &mut [Sexp]) {
+        let targets = match (jump, operands.len()) {
+            (true, 1) => operands,
+            (false, 3) => {
+                self.expr(&mut operands[0], Ty::Free, false);
+                &mut operands[1..]
+            }
+            _ => return,
+        };
+        for target in targets {
+            if let Sexp::List(items) = target {
+                if let Some((Sexp::Ident(name), args)) = items.split_first_mut() {
+                    let params = match self.blocks.get(name.as_str()) {
+                        Some(params) if params.len() == args.len() => params.clone(),
+                        _ => Vec::new(),
+                    };
+                    self.exprs(args, &params);
+                }
+            }
+        }
+    }
+
+    /// Fold `(op operands...)`, lowered with `hint`, to a literal if it's one of the folded
+    /// operators and its operands are integer literals or constants which the lowering accepts.
+    fn fold(&self, op: &str, operands: &[Sexp], hint: Ty) -> Option<Sexp> {
+        let ty = hint.literal_type()?;
+        let values = operands
+            .iter()
+            .map(|operand| self.literal(operand))
+            .collect::<Option<Vec<_>>>()?;
+        let fits_either = |value| fits(value, ty, Interpretation::Either);
+        // The arithmetic of `i128`s wraps at a multiple of the width of every type, so it
+        // computes the same bits as that of the type.
+        let value = match (opcode(op), &values[..]) {
+            (op, &[x, amount]) if SHIFTS.contains(&op) => {
+                // The shifted operand is signed for `sshr` and unsigned for `ushr`, and the shift
+                // amount is an `i64` literal, of which the shifts use the bits below the width.
+                let interpretation = match op {
+                    "sshr" => Interpretation::Signed,
+                    "ushr" => Interpretation::Unsigned,
+                    _ => Interpretation::Either,
+                };
+                if !fits(x, ty, interpretation)
+                    || !fits(amount, types::I64, Interpretation::Unsigned)
+                {
+                    return None;
+                }
+                let amount = (amount & i128::from(ty.bits() - 1)) as u32;
+                match op {
+                    "ishl" => x.wrapping_shl(amount),
+                    _ => x >> amount,
+                }
+            }
+            (_, values) if !values.iter().all(|&value| fits_either(value)) => return None,
+            ("iadd", &[x, y]) => x.wrapping_add(y),
+            ("isub", &[x, y]) => x.wrapping_sub(y),
+            ("imul", &[x, y]) => x.wrapping_mul(y),
+            ("band", &[x, y]) => x & y,
+            ("bor", &[x, y]) => x | y,
+            ("bxor", &[x, y]) => x ^ y,
+            ("ineg", &[x]) => x.wrapping_neg(),
+            ("bnot", &[x]) => !x,
+            _ => return None,
+        };
+        Some(Sexp::Int(wrap(value, ty)))
+    }
+
+    /// The value of `expr`, if it's an integer literal or a constant.
+    fn literal(&self, expr: &Sexp) -> Option<i128> {
+        match expr {
+            Sexp::Int(value) => Some(*value),
+            Sexp::Ident(name) if !self.vars.contains_key(name) => {
+                self.constants.get(name.as_str()).copied()
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether `expr` is a literal, a constant or arithmetic of them, whose type is the one it's
+    /// lowered with.
+    fn literal_like(&self, expr: &Sexp) -> bool {
+        match expr {
+            Sexp::List(items) => match items.split_first() {
+                Some((Sexp::Ident(op), operands)) => {
+                    let op = op.as_str();
+                    (BINARY.contains(&opcode(op)) || UNARY.contains(&op) || SHIFTS.contains(&op))
+                        && operands.iter().all(|operand| self.literal_like(operand))
+                }
+                _ => false,
+            },
+            expr => self.literal(expr).is_some(),
+        }
+    }
+
+    /// The type of the value of `expr`, lowered with `hint`.
+    fn value_type(&self, expr: &Sexp, hint: Ty) -> Ty {
+        match self.infer(expr) {
+            Ty::Free if self.literal_like(expr) => match hint.literal_type() {
+                Some(ty) => Ty::Known(ty),
+                None => Ty::Unknown,
+            },
+            Ty::Free => Ty::Unknown,
+            ty => ty,
+        }
+    }
+
+    /// The type of the first of `exprs` whose type can be inferred.
+    fn infer_first(&self, exprs: &[Sexp]) -> Ty {
+        Ty::first(exprs.iter().map(|expr| self.infer(expr)))
+    }
+
+    /// The type of `expr` without the context it's used in, as the lowering infers it.
+    fn infer(&self, expr: &Sexp) -> Ty {
+        let (op, operands) = match expr {
+            Sexp::Ident(name) => return self.vars.get(name).copied().unwrap_or(Ty::Free),
+            Sexp::List(items) => match items.split_first() {
+                Some((Sexp::Ident(op), operands)) => (op.as_str(), operands),
+                _ => return Ty::Free,
+            },
+            Sexp::Int(_) | Sexp::Float(_) | Sexp::Str(_) => return Ty::Free,
+        };
+        match op {
+            "call" => match operands.first() {
+                Some(Sexp::Ident(name)) => match self.callees.get(name.as_str()) {
+                    Some(Some(ty)) => match ty.returns[..] {
+                        [ret] => Ty::Known(ret),
+                        _ => Ty::Free,
+                    },
+                    _ => Ty::Unknown,
+                },
+                _ => Ty::Free,
+            },
+            "if" | "select" => self.infer_first(operands.get(1..).unwrap_or(&[])),
+            "return" | "let" | "loop" | "continue" | "tail_call" => Ty::Free,
+            "while" => match operands.first() {
+                Some(cond) => self.infer(cond),
+                None => Ty::Free,
+            },
+            // The pointer type depends on the target.
+            "symbol" => Ty::Unknown,
+            _ if resolve_type(op).is_some() => Ty::Known(resolve_type(op).unwrap()),
+            "load" | "stack_load" | "stack_store" | "fcvt_from_sint" | "fcvt_to_sint" => {
+                match operands.first() {
+                    Some(Sexp::Ident(ty)) => resolve_type(ty).map_or(Ty::Free, Ty::Known),
+                    _ => Ty::Free,
+                }
+            }
+            // Comparisons of scalars have type `i8`, and those of vectors the type of the
+            // vectors.
+            "icmp" | "icmp_imm" => {
+                comparison_type(self.infer_first(operands.get(1..).unwrap_or(&[])))
+            }
+            _ if comparison(op).is_some() => comparison_type(self.infer_first(operands)),
+            _ if BINARY.contains(&opcode(op)) || DIVISIONS.contains(&op) => {
+                self.infer_first(operands)
+            }
+            _ => match operands.first() {
+                Some(operand) => self.infer(operand),
+                None => Ty::Free,
+            },
+        }
+    }
+}
+
+/// The type of a comparison of operands of type `operands`.
+fn comparison_type(operands: Ty) -> Ty {
+    match operands {
+        Ty::Known(ty) if ty.is_vector() => Ty::Known(ty),
+        Ty::Unknown => Ty::Unknown,
+        _ => Ty::Known(types::I8),
+    }
+}
+
+/// The type named by `form`, the type operand of an operator.
+fn type_named(form: &Sexp) -> Ty {
+    match form {
+        Sexp::Ident(name) => resolve_type(name).map_or(Ty::Unknown, Ty::Known),
+        _ => Ty::Unknown,
+    }
+}
+
+/// The name and the names and types of the parameters of the block definition `(block name
+/// ((param type)...) body...)`, if it's well-formed.
+fn block_signature(form: &Sexp) -> Option<(&str, Vec<(&str, Ty)>)> {
+    let (name, params) = match form {
+        Sexp::List(items) => match &items[..] {
+            [_, Sexp::Ident(name), Sexp::List(params), ..] => (name, params),
+            _ => return None,
+        },
+        _ => return None,
+    };
+    let params = params
+        .iter()
+        .map(|param| match param {
+            Sexp::List(param) => match &param[..] {
+                [Sexp::Ident(param), ty] => Some((param.as_str(), type_named(ty))),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect::<Option<_>>()?;
+    Some((name, params))
+}
+
+/// Whether `expr`, a branch of an `if` or the body of a `let` or a `loop`, has no value, since
+/// it always returns, tail-calls or continues a loop.
+fn diverges(expr: &Sexp) -> bool {
+    match (head(expr), expr) {
+        (Some("return" | "continue" | "tail_call"), _) => true,
+        (Some("if"), Sexp::List(items)) if items.len() == 4 => {
+            diverges(&items[2]) && diverges(&items[3])
+        }
+        (Some("let" | "loop"), Sexp::List(items)) if items.len() == 3 => diverges(&items[2]),
+        _ => false,
+    }
+}
+
+/// Whether computing `expr` has no effects: it doesn't call, access memory, branch or trap,
+/// and computes its value from literals and names alone.
+fn pure(expr: &Sexp) -> bool {
+    match expr {
+        Sexp::Int(_) | Sexp::Float(_) | Sexp::Ident(_) => true,
+        Sexp::Str(_) => false,
+        Sexp::List(items) => match head(expr) {
+            Some(op) => {
+                let pure_op = BINARY.contains(&opcode(op))
+                    || UNARY.contains(&op)
+                    || SHIFTS.contains(&op)
+                    || comparison(op).is_some()
+                    || matches!(op, "icmp" | "icmp_imm" | "select" | "symbol")
+                    || resolve_type(op).is_some();
+                pure_op && items[1..].iter().all(pure)
+            }
+            None => false,
+        },
+    }
+}
+
+/// Add the names in `expr` to `names`, whatever they name.
+fn add_names(expr: &Sexp, names: &mut HashSet<String>) {
+    match expr {
+        Sexp::Ident(name) => {
+            names.insert(name.clone());
+        }
+        Sexp::List(items) => items.iter().for_each(|item| add_names(item, names)),
+        _ => {}
+    }
+}
+
+/// Whether the integer literal `value` is one of the values of the integer type `ty`,
+/// interpreted as `interpretation`, as the lowering checks.
+fn fits(value: i128, ty: Type, interpretation: Interpretation) -> bool {
+    let bits = ty.bits();
+    let signed = bits == 128 || (-(1 << (bits - 1))..1 << (bits - 1)).contains(&value);
+    let unsigned = value >= 0 && (bits == 128 || value < 1 << bits);
+    match interpretation {
+        Interpretation::Either => signed || unsigned,
+        Interpretation::Signed => signed,
+        Interpretation::Unsigned => unsigned,
+    }
+}
+
+/// The signed integer of `ty` with the low bits of `value`, which is one of the values of `ty`
+/// whichever way its context interprets it, unless that's as an unsigned integer.
+fn wrap(value: i128, ty: Type) -> i128 {
+    let bits = ty.bits();
+    if bits == 128 {
+        return value;
+    }
+    let low = value & ((1 << bits) - 1);
+    if low >> (bits - 1) == 1 {
+        low - (1 << bits)
+    } else {
+        low
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::compile::{compile, compile_tests, lower, Compiled};
+    use super::super::frontend;
+    use super::*;
+    use cranelift_jit::{JITBuilder, JITModule};
+    use cranelift_module::default_libcall_names;
+
+    /// The bodies of the functions of `src` once it's optimized, a line each.
+    fn optimized(src: &str) -> String {
+        let (mut module, _) = frontend(src).unwrap();
+        optimize(&mut module);
+        let bodies: Vec<String> = module
+            .functions
+            .iter()
+            .map(|func| {
+                let exprs: Vec<String> = func.body.iter().map(Sexp::to_string).collect();
+                exprs.join(" ")
+            })
+            .collect();
+        bodies.join("\n")
+    }
+
+    /// Compile `module` in a new JIT module, and return the module, the compiled functions and
+    /// the number of instructions of the functions lowered.
+    fn compile_jit(module: &Module) -> (JITModule, Compiled, usize) {
+        let mut jit = JITModule::new(JITBuilder::new(default_libcall_names()).unwrap());
+        let compiled = compile(&mut jit, module).unwrap();
+        jit.finalize_definitions().unwrap();
+        let insts = module
+            .functions
+            .iter()
+            .map(|func| {
+                lower(func, &module.constants, &mut jit)
+                    .unwrap()
+                    .dfg
+                    .num_insts()
+            })
+            .sum();
+        (jit, compiled, insts)
+    }
+
+    #[test]
+    fn folding() {
+        for (src, expected) in [
+            ("(func f ((x i64)) (i64) (+ x (* 3 (+ 1 2))))", "(+ x 9)"),
+            // The folded literals wrap at the width of their type.
+            (
+                "(func f ((x i8)) (i8) (iadd x (imul 100 3)))",
+                "(iadd x 44)",
+            ),
+            ("(func f () (i8) (+ 100 100))", "-56"),
+            ("(func f () (i32) (imul 65536 65537))", "65536"),
+            ("(func f ((x i16)) (i8) (= x (* 256 256)))", "(= x 0)"),
+            ("(func f () (i64) (bnot (ineg 5)))", "4"),
+            (
+                "(func f () (i16) (bxor (bor 0xff00 0x0f) (band 0xffff 0xf0f0)))",
+                "4095",
+            ),
+            ("(func f () (i32) (ishl 3 33))", "6"),
+            ("(func f () (i8) (ushr 0x80 7))", "1"),
+            ("(func f () (i8) (sshr -128 7))", "-1"),
+            (
+                "(const K 10) (func f ((x i64)) (i64) (+ x (* K K)))",
+                "(+ x 100)",
+            ),
+            // The literals of bindings, of `while` conditions, of stores and of arguments have
+            // the types the lowering gives them.
+            (
+                "(func f ((x i32)) (i32) (let ((y (* 0x10000 0x10000))) (+ x 1)))",
+                "(+ x 1)",
+            ),
+            (
+                "(func f ((x i32)) (i32) (stack s 4) (stack_store i32 s 0 (* 0x10000 0x10000)))",
+                "(stack s 4) (stack_store i32 s 0 0)",
+            ),
+            (
+                "(func g ((x i8)) (i8) x) (func f () (i8) (call g (* 16 16)))",
+                "x\n(call g 0)",
+            ),
+            (
+                "(func f ((x i64)) (i64) (loop ((i i8 (* 16 16)) (n (* 16 16))) n))",
+                "(loop ((i i8 0) (n 256)) n)",
+            ),
+            // The literals whose type the pass can't tell, or which must be unsigned, aren't
+            // folded, and neither are those the lowering rejects.
+            (
+                "(func f ((x i64)) (i64) (ishl x (+ 1 2)))",
+                "(ishl x (+ 1 2))",
+            ),
+            (
+                "(func f ((x i32)) (i8) (icmp ult x (- 0 1)))",
+                "(icmp ult x (- 0 1))",
+            ),
+            (
+                "(func f ((x i64)) (i64) (ushr (- 0 1) x))",
+                "(ushr (- 0 1) x)",
+            ),
+            ("(func f ((x i8)) (i8) (+ x (+ 300 1)))", "(+ x (+ 300 1))"),
+            ("(func f () (f64) (+ 1 2))", "(+ 1 2)"),
+            (
+                "(const K 2) (func f ((K i64)) (i64) (+ K (+ K 1)))",
+                "(+ K (+ K 1))",
+            ),
+            (
+                "(const K 2) (func f ((x i64)) (i64) (let ((K x)) (+ K (+ K 1))))",
+                "(let ((K x)) (+ K (+ K 1)))",
+            ),
+            // Calls and loads aren't folded, nor moved for the literals around them to be.
+            (
+                "(func g () (i64) 1) (func f () (i64) (+ 1 (+ (call g) 2)))",
+                "1\n(+ 1 (+ (call g) 2))",
+            ),
+            (
+                "(data d (bytes 1 2 3 4 5 6 7 8)) \
+                 (func f () (i64) (+ (load i64 (symbol d) 0) (* 2 3)))",
+                "(+ (load i64 (symbol d) 0) 6)",
+            ),
+            // Divisions trap, so they're neither folded nor dropped, and a divisor keeps the
+            // arithmetic which computes zero.
+            ("(func f ((x i64)) (i64) (sdiv x (* 2 3)))", "(sdiv x 6)"),
+            ("(func f () (i64) (srem (+ 6 1) 2))", "(srem 7 2)"),
+            (
+                "(func f ((x i64)) (i64) (urem x (+ 1 1)))",
+                "(urem x (+ 1 1))",
+            ),
+            (
+                "(func f ((x i64)) (i64) (sdiv x (- 1 1)))",
+                "(sdiv x (- 1 1))",
+            ),
+            (
+                "(func f ((x i64) (y i64)) (i64) (let ((q (udiv x y))) x))",
+                "(let ((q (udiv x y))) x)",
+            ),
+        ] {
+            assert_eq!(optimized(src), expected, "{src}");
+        }
+    }
+
+    #[test]
+    fn literal_conditions() {
+        for (src, expected) in [
+            ("(func f ((x i64)) (i64) (if 1 x 2))", "x"),
+            (
+                "(func f ((x i64)) (i64) (if (- 3 3) (call f x) (* 2 x)))",
+                "(* 2 x)",
+            ),
+            ("(func f () (i64) (if 0 2 (+ 1 1)))", "2"),
+            ("(func f ((x i64)) (i64) (if x 1 2))", "(if x 1 2)"),
+            (
+                "(func f ((x i64)) (i64) (if 0 x (return (+ x 1))))",
+                "(return (+ x 1))",
+            ),
+            // A branch which returns has no value, so it can only replace an arm.
+            (
+                "(func f ((x i64)) (i64) (+ 1 (if 1 (return 0) x)))",
+                "(+ 1 (if 1 (return 0) x))",
+            ),
+            // The literal `5` is an `i32` in the `if`, but would be an `i64` on its own.
+            (
+                "(func f ((x i32)) (i32) (let ((y (if 1 5 x))) y))",
+                "(let ((y (if 1 5 x))) y)",
+            ),
+        ] {
+            assert_eq!(optimized(src), expected, "{src}");
+        }
+    }
+
+    #[test]
+    fn dead_bindings() {
+        for (src, expected) in [
+            (
+                "(func f ((x i64)) (i64) (let ((a (* x 2)) (b (+ a 1))) x))",
+                "x",
+            ),
+            (
+                "(func f ((x i64)) (i64) (let ((a (* x 2)) (b (+ a 1))) a))",
+                "(let ((a (* x 2))) a)",
+            ),
+            (
+                "(func f ((x i64)) (i64) (let ((a 1) (b (+ a 1)) (a 5)) (+ a b)))",
+                "(let ((a 1) (b (+ a 1)) (a 5)) (+ a b))",
+            ),
+            ("(func f () (i64) (let ((a (if 0 2 (+ 1 1)))) 5))", "5"),
+            // The bindings of calls, stores and loads are kept for their effects.
+            (
+                "(func g ((x i64)) (i64) x) (func f ((x i64)) (i64) (let ((a (call g x))) x))",
+                "x\n(let ((a (call g x))) x)",
+            ),
+            (
+                "(func f ((x i64)) (i64) (stack s 8) \
+                 (let ((a (stack_store i64 s 0 x)) (b (stack_load i64 s 0))) x))",
+                "(stack s 8) (let ((a (stack_store i64 s 0 x)) (b (stack_load i64 s 0))) x)",
+            ),
+            (
+                "(func f ((x i64)) (i64) (let ((q (fcvt_to_sint i64 (fcvt_from_sint f64 x)))) x))",
+                "(let ((q (fcvt_to_sint i64 (fcvt_from_sint f64 x)))) x)",
+            ),
+        ] {
+            assert_eq!(optimized(src), expected, "{src}");
+        }
+    }
+
+    /// The code the pass would remove keeps its errors, since the functions and tests which
+    /// don't lower are left as they are.
+    #[test]
+    fn errors_of_removed_code() {
+        for src in [
+            "(func f ((x i64)) (i64) (if (- 2 2) (call 78391 (- x 1)) x))",
+            "(func f ((x i64)) (i64) (if 1 x (frobnicate x)))",
+            "(func f ((x i64)) (i64) (if 0 (+ x y) x))",
+            "(func f ((x i64)) (i64) (let ((a (iadd x))) x))",
+            "(func f ((x i8)) (i8) (let ((a (+ x 300))) x))",
+            "(func f ((x i64)) (i64) x) (test t (expect 1) (if 0 (call f 1 2) 1))",
+        ] {
+            let errors = |module: &Module| {
+                let mut jit = JITModule::new(JITBuilder::new(default_libcall_names()).unwrap());
+                let error = match compile(&mut jit, module) {
+                    Ok(_) => compile_tests(&mut jit, module).err(),
+                    Err(error) => Some(error),
+                };
+                error.map(|error| error.to_string())
+            };
+            let mut module = Module::parse(src).unwrap();
+            let expected = errors(&module);
+            assert!(expected.is_some(), "{src}");
+            optimize(&mut module);
+            assert_eq!(errors(&module), expected, "{src}");
+        }
+
+        // The functions which lower are still simplified.
+        assert_eq!(
+            optimized("(func g () (i64) (+ 1 2)) (func f ((x i64)) (i64) (if 0 (undefined) x))"),
+            "3\n(if 0 (undefined) x)"
+        );
+        // A tail call is an error without frame pointers, so its branch is kept.
+        assert_eq!(
+            optimized("(func f ((x i64)) (i64) (if 0 (tail_call f x) x))"),
+            "(if 0 (tail_call f x) x)"
+        );
+    }
+
+    /// The optimized functions compute what the functions do, with fewer instructions.
+    #[test]
+    fn same_results_with_fewer_instructions() {
+        for src in [
+            "(func f ((x i64)) (i64)
+               (let ((scale (* 3 (+ 1 2))) (unused (* x 1000)))
+                 (if (- 9 (* 3 3)) (call f x) (+ x (* scale (bxor 5 3))))))",
+            "(const BIAS 0x1_0000_0000)
+             (func f ((x i64)) (i64)
+               (loop ((i x) (acc i64 (- BIAS (* 2 BIAS))))
+                 (if (> i 0)
+                   (continue (- i (+ 0 1)) (+ acc (bor (ishl 1 4) (band 0xff 7))))
+                   (+ acc (* i (if 1 (- 0 1) 2))))))",
+            "(func f ((x i64)) (i64)
+               (stack s 8)
+               (stack_store i64 s 0 (* (+ 2 3) (+ 4 5)))
+               (let ((dead (+ (* x x) 1)) (loaded (stack_load i64 s 0)))
+                 (+ loaded (* x (- (ishl 1 62) (* 2 (ishl 1 61)))))))",
+        ] {
+            let (module, _) = frontend(src).unwrap();
+            let mut folded = module.clone();
+            optimize(&mut folded);
+            let (jit, compiled, insts) = compile_jit(&module);
+            let (folded_jit, folded_compiled, folded_insts) = compile_jit(&folded);
+            assert!(folded_insts < insts, "{folded_insts} < {insts}: {src}");
+
+            let run = |jit: &JITModule, compiled: &Compiled, x: i64| {
+                let (_, id) = compiled
+                    .functions
+                    .iter()
+                    .find(|(name, _)| name == "f")
+                    .unwrap();
+                let code = jit.get_finalized_function(*id);
+                let f =
+                    unsafe { std::mem::transmute::<*const u8, extern "C" fn(i64) -> i64>(code) };
+                f(x)
+            };
+            for x in [-3, 0, 1, 10] {
+                assert_eq!(
+                    run(&folded_jit, &folded_compiled, x),
+                    run(&jit, &compiled, x),
+                    "f({x}): {src}"
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,1159 @@
+//! Parsing clifp source text into a [`Module`].
+
+use super::diagnostic::Diagnostic;
+use super::lexer::{self, Span};
+use super::sexp::{self, Sexp, SpanTree};
+use super::source::{self, Loader, SourceMap};
+use cranelift_codegen::ir::TrapCode;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+
+/// A clifp program: a sequence of functions, declarations of host functions, data objects,
+/// constants and tests.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Module {
+    /// The functions, in the order they were written.
+    pub functions: Vec<Function>,
+    /// The host functions, in the order they were declared.
+    pub externs: Vec<Extern>,
+    /// The data objects, in the order they were written.
+    pub data: Vec<Data>,
+    /// The constants, in the order they were written.
+    pub constants: Vec<Constant>,
+    /// The tests, in the order they were written.
+    pub tests: Vec<Test>,
+    /// The problems of the program which don't keep it from compiling.
+    pub warnings: Vec<Diagnostic>,
+}
+
+/// A function definition: `(func name ((param type) ...) (return-type ...) body ...)`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Function {
+    /// The name of the function, which is also its symbol name.
+    pub name: String,
+    /// The names and type names of the parameters.
+    pub params: Vec<(String, String)>,
+    /// The names of the types of the results.
+    pub returns: Vec<String>,
+    /// The expressions and block definitions of the body, in order.
+    pub body: Vec<Sexp>,
+    /// Where the definition is, with the spans of its parts.
+    pub spans: SpanTree,
+}
+
+/// A host function, defined outside the module: `(extern name ((type) ...) (return-type ...))`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Extern {
+    /// The symbol name of the function.
+    pub name: String,
+    /// The names of the types of the parameters.
+    pub params: Vec<String>,
+    /// The names of the types of the results.
+    pub returns: Vec<String>,
+    /// Where the declaration is, with the spans of its parts.
+    pub spans: SpanTree,
+}
+
+/// A data object: `(data name (bytes byte ...))`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Data {
+    /// The name of the data object, which is also its symbol name.
+    pub name: String,
+    /// The contents of the data object.
+    pub bytes: Vec<u8>,
+}
+
+/// An integer constant: `(const name value)`.
+///
+/// The value is an integer literal, a constant defined before it, or `(+ a b)`, `(- a b)` or
+/// `(* a b)` of those, which is computed when the module is parsed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Constant {
+    /// The name the functions of the module use the constant by.
+    pub name: String,
+    /// The value of the constant, which takes a type wherever it's used, as a literal does.
+    pub value: i128,
+}
+
+/// A test of the functions of the module: `(test name (expect value) expr)`, which passes if
+/// `expr`, computed in a function of its own, is `value`, or `(test name (trap code) expr)`,
+/// which passes if computing `expr` traps with `code`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Test {
+    /// The name of the test, which its results are reported by.
+    pub name: String,
+    /// What computing `expr` is expected to do.
+    pub expected: Expected,
+    /// The expression the test computes, which can call the functions of the module and use its
+    /// constants.
+    pub expr: Sexp,
+    /// Where the test is, with the spans of its parts.
+    pub spans: SpanTree,
+}
+
+/// What a [`Test`] expects of its expression.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Expected {
+    /// `(expect value)`: computing the integer literal `value`.
+    Value(i128),
+    /// `(trap code)`: trapping with the trap code named `code` in CLIF, such as `int_divz` for a
+    /// division by zero.
+    Trap(TrapCode),
+}
+
+/// An error in a top-level form of a clifp program.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ParseError {
+    /// A top-level form isn't a function, data, constant or test definition or an `extern`
+    /// declaration, or a part of one is malformed.
+    Syntax(String),
+    /// Two functions have the given name.
+    DuplicateFunction(String),
+    /// Two `extern` declarations have the given name.
+    DuplicateExtern(String),
+    /// An `extern` declaration has the name of a function or data object of the module.
+    ExternAndDefinition(String),
+    /// Two data objects have the given name.
+    DuplicateData(String),
+    /// A function and a data object have the given name.
+    DataAndFunction(String),
+    /// Two constants have the given name.
+    DuplicateConstant(String),
+    /// Two tests have the given name.
+    DuplicateTest(String),
+    /// A function has two parameters with the same name.
+    DuplicateParam {
+        /// The name of the function.
+        function: String,
+        /// The name shared by the parameters.
+        param: String,
+    },
+    /// The function of the given name has no list of return types after its parameters.
+    MissingReturns(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Syntax(message) => write!(f, "{message}"),
+            Self::DuplicateFunction(name) => write!(f, "function `{name}` is defined twice"),
+            Self::DuplicateExtern(name) => write!(f, "extern `{name}` is declared twice"),
+            Self::ExternAndDefinition(name) => {
+                write!(f, "`{name}` is declared `extern` but defined in the module")
+            }
+            Self::DuplicateData(name) => write!(f, "data `{name}` is defined twice"),
+            Self::DataAndFunction(name) => {
+                write!(f, "`{name}` is the name of both a function and data")
+            }
+            Self::DuplicateConstant(name) => write!(f, "constant `{name}` is defined twice"),
+            Self::DuplicateTest(name) => write!(f, "test `{name}` is defined twice"),
+            Self::DuplicateParam { function, param } => write!(
+                f,
+                "in function `{function}`: parameter `{param}` is declared twice"
+            ),
+            Self::MissingReturns(name) => write!(
+                f,
+                "function `{name}` has no return types; list them after the parameters, as in \
+                 `(i64)`, or write `()` if it returns nothing"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A [`ParseError`] at the given span.
+type Located = (Span, ParseError);
+
+impl Module {
+    /// Parse the module written in `source`.
+    ///
+    /// Text which doesn't lex or doesn't form s-expressions has a single error, but every
+    /// malformed top-level form has its own, and all of them are returned.
+    ///
+    /// ```
+    /// use cranelift_clifp::Module;
+    ///
+    /// let module = Module::parse("(const TEN 10) (func ten () (i64) TEN)").unwrap();
+    /// assert_eq!(module.constants[0].name, "TEN");
+    /// assert_eq!(module.functions[0].returns, ["i64"]);
+    ///
+    /// let errors = Module::parse("(func) (data)").unwrap_err();
+    /// assert_eq!(errors.len(), 2);
+    /// ```
+    pub fn parse(source: &str) -> Result<Module, Vec<Diagnostic>> {
+        let tokens = lexer::lex(source).map_err(|err| vec![Diagnostic::lex(source, &err)])?;
+        let (forms, spans) =
+            sexp::parse_with_spans(&tokens).map_err(|err| vec![Diagnostic::from(err)])?;
+        parse(&forms, &spans)
+    }
+
+    /// Parse the module of the program at `path`, read with `loader` along with the files it
+    /// includes, which are added to `sources`, where the spans of the module are.
+    ///
+    /// The forms of the included files take the places of the `include`s, so the functions,
+    /// constants and other definitions of all the files are in the module, and the names they
+    /// define clash as if they were in one file.
+    pub fn parse_file(
+        path: &Path,
+        loader: &dyn Loader,
+        sources: &mut SourceMap,
+    ) -> Result<Module, Vec<Diagnostic>> {
+        let (forms, spans) = source::load(path, loader, sources)?;
+        parse(&forms, &spans)
+    }
+}
+
+/// Parse a module from the top-level `forms` of a program, which are where `spans` say.
+pub fn parse(forms: &[Sexp], spans: &[SpanTree]) -> Result<Module, Vec<Diagnostic>> {
+    // Functions, host functions and data objects share the namespace of symbols. Constants are
+    // used by the functions, and have a namespace of their own, as do tests.
+    let mut function_names = HashSet::new();
+    let mut extern_names = HashSet::new();
+    let mut data_names = HashSet::new();
+    let mut functions = Vec::new();
+    let mut externs = Vec::new();
+    let mut data = Vec::new();
+    let mut constants = Vec::new();
+    let mut tests = Vec::new();
+    let mut errors = Vec::new();
+    for (form, spans) in forms.iter().zip(spans) {
+        let keyword = match form {
+            Sexp::List(items) => match items.first() {
+                Some(Sexp::Ident(keyword)) => keyword.as_str(),
+                _ => "",
+            },
+            _ => "",
+        };
+        // A name clashing with an earlier one is reported at the later name.
+        let name_span = spans.item(1).span;
+        let result = match keyword {
+            "data" => data_object(form, spans).and_then(|object| {
+                if function_names.contains(&object.name) {
+                    return Err((name_span, ParseError::DataAndFunction(object.name)));
+                }
+                if extern_names.contains(&object.name) {
+                    return Err((name_span, ParseError::ExternAndDefinition(object.name)));
+                }
+                if !data_names.insert(object.name.clone()) {
+                    return Err((name_span, ParseError::DuplicateData(object.name)));
+                }
+                data.push(object);
+                Ok(())
+            }),
+            "extern" => extern_function(form, spans).and_then(|host| {
+                if function_names.contains(&host.name) || data_names.contains(&host.name) {
+                    return Err((name_span, ParseError::ExternAndDefinition(host.name)));
+                }
+                if !extern_names.insert(host.name.clone()) {
+                    return Err((name_span, ParseError::DuplicateExtern(host.name)));
+                }
+                externs.push(host);
+                Ok(())
+            }),
+            "const" => constant(form, spans, &constants).and_then(|constant| {
+                if constants.iter().any(|other| other.name == constant.name) {
+                    return Err((name_span, ParseError::DuplicateConstant(constant.name)));
+                }
+                constants.push(constant);
+                Ok(())
+            }),
+            // The includes of the files of a program are replaced by the forms of the files they
+            // include before the forms are parsed.
+            "include" => Err(syntax((
+                spans.span,
+                "`include` is only supported in programs read from files".to_string(),
+            ))),
+            "test" => test(form, spans).and_then(|test| {
+                if tests.iter().any(|other: &Test| other.name == test.name) {
+                    return Err((name_span, ParseError::DuplicateTest(test.name)));
+                }
+                tests.push(test);
+                Ok(())
+            }),
+            _ => function(form, spans).and_then(|func| {
+                if data_names.contains(&func.name) {
+                    return Err((name_span, ParseError::DataAndFunction(func.name)));
+                }
+                if extern_names.contains(&func.name) {
+                    return Err((name_span, ParseError::ExternAndDefinition(func.name)));
+                }
+                if !function_names.insert(func.name.clone()) {
+                    return Err((name_span, ParseError::DuplicateFunction(func.name)));
+                }
+                functions.push(func);
+                Ok(())
+            }),
+        };
+        if let Err((span, error)) = result {
+            errors.push(Diagnostic::error(span, error));
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    // A parameter shadows the constant of the same name in its function, which is likely a
+    // mistake when the constant is defined anywhere in the module.
+    let mut warnings = Vec::new();
+    for func in &functions {
+        for (i, (param, _)) in func.params.iter().enumerate() {
+            if constants.iter().any(|constant| &constant.name == param) {
+                warnings.push(Diagnostic::warning(
+                    func.spans.item(2).item(i).item(0).span,
+                    format!(
+                        "in function `{}`: parameter `{param}` shadows the constant `{param}`, \
+                         so the function uses the parameter",
+                        func.name
+                    ),
+                ));
+            }
+        }
+    }
+    Ok(Module {
+        functions,
+        externs,
+        data,
+        constants,
+        tests,
+        warnings,
+    })
+}
+
+fn ident(sexp: &Sexp, spans: &SpanTree) -> Result<String, (Span, String)> {
+    match sexp {
+        Sexp::Ident(name) => Ok(name.clone()),
+        sexp => Err((spans.span, format!("expected a name, found `{sexp}`"))),
+    }
+}
+
+fn list<'a>(sexp: &'a Sexp, spans: &SpanTree) -> Result<&'a [Sexp], (Span, String)> {
+    match sexp {
+        Sexp::List(items) => Ok(items),
+        sexp => Err((spans.span, format!("expected a list, found `{sexp}`"))),
+    }
+}
+
+fn syntax((span, message): (Span, String)) -> Located {
+    (span, ParseError::Syntax(message))
+}
+
+fn function(form: &Sexp, spans: &SpanTree) -> Result<Function, Located> {
+    let items = list(form, spans).map_err(syntax)?;
+    let (keyword, name, rest) = match items {
+        [keyword, name, rest @ ..] => (keyword, name, rest),
+        _ => {
+            return Err(syntax((
+                spans.span,
+                "expected `(func name (params...) (types...) body...)`".to_string(),
+            )))
+        }
+    };
+    let keyword = ident(keyword, spans.item(0)).map_err(syntax)?;
+    if keyword != "func" {
+        return Err(syntax((
+            spans.item(0).span,
+            format!(
+                "expected `func`, `extern`, `data`, `const`, `test` or `include`, found \
+                 `{keyword}`"
+            ),
+        )));
+    }
+    let name = ident(name, spans.item(1)).map_err(syntax)?;
+    let in_function = |(span, message): (Span, String)| {
+        syntax((span, format!("in function `{name}`: {message}")))
+    };
+
+    let (params, returns, body) = match rest {
+        [params, returns, body @ ..] => (params, returns, body),
+        [_] => return Err((spans.span, ParseError::MissingReturns(name))),
+        [] => {
+            return Err(in_function((
+                spans.span,
+                "expected a parameter list".to_string(),
+            )))
+        }
+    };
+
+    let mut param_names = HashSet::new();
+    let mut parsed_params = Vec::new();
+    let params_spans = spans.item(2);
+    for (i, param) in list(params, params_spans)
+        .map_err(in_function)?
+        .iter()
+        .enumerate()
+    {
+        let param_spans = params_spans.item(i);
+        let (param, ty) = match list(param, param_spans).map_err(in_function)? {
+            [param, ty] => (
+                ident(param, param_spans.item(0)).map_err(in_function)?,
+                ident(ty, param_spans.item(1)).map_err(in_function)?,
+            ),
+            _ => {
+                return Err(in_function((
+                    param_spans.span,
+                    "expected a parameter `(name type)`".to_string(),
+                )))
+            }
+        };
+        if !param_names.insert(param.clone()) {
+            return Err((
+                param_spans.item(0).span,
+                ParseError::DuplicateParam {
+                    function: name.clone(),
+                    param,
+                },
+            ));
+        }
+        parsed_params.push((param, ty));
+    }
+
+    // Without a return list, what follows the parameters is a bare type name or an expression
+    // of the body.
+    let returns_spans = spans.item(3);
+    let returns = match returns {
+        Sexp::List(types) => types
+            .iter()
+            .enumerate()
+            .map(|(i, ty)| ident(ty, returns_spans.item(i)))
+            .collect::<Result<_, _>>()
+            .ok(),
+        _ => None,
+    };
+    let returns =
+        returns.ok_or_else(|| (returns_spans.span, ParseError::MissingReturns(name.clone())))?;
+
+    Ok(Function {
+        name,
+        params: parsed_params,
+        returns,
+        body: body.to_vec(),
+        spans: spans.clone(),
+    })
+}
+
+fn extern_function(form: &Sexp, spans: &SpanTree) -> Result<Extern, Located> {
+    let malformed = || {
+        syntax((
+            spans.span,
+            format!("expected `(extern name ((type)...) (types...))`, found `{form}`"),
+        ))
+    };
+    let (name, params, returns) = match list(form, spans).map_err(syntax)? {
+        [_, Sexp::Ident(name), Sexp::List(params), Sexp::List(returns)] => (name, params, returns),
+        _ => return Err(malformed()),
+    };
+    let params = params
+        .iter()
+        .map(|param| match param {
+            Sexp::List(ty) => match &ty[..] {
+                [Sexp::Ident(ty)] => Some(ty.clone()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect::<Option<_>>()
+        .ok_or_else(malformed)?;
+    let returns = returns
+        .iter()
+        .map(|ty| match ty {
+            Sexp::Ident(ty) => Some(ty.clone()),
+            _ => None,
+        })
+        .collect::<Option<_>>()
+        .ok_or_else(malformed)?;
+    Ok(Extern {
+        name: name.clone(),
+        params,
+        returns,
+        spans: spans.clone(),
+    })
+}
+
+fn data_object(form: &Sexp, spans: &SpanTree) -> Result<Data, Located> {
+    let malformed = || {
+        syntax((
+            spans.span,
+            format!("expected `(data name (bytes byte...))`, found `{form}`"),
+        ))
+    };
+    let (name, contents) = match list(form, spans).map_err(syntax)? {
+        [_, Sexp::Ident(name), Sexp::List(contents)] => (name, contents),
+        _ => return Err(malformed()),
+    };
+    let bytes = match contents.split_first() {
+        Some((Sexp::Ident(kind), bytes)) if kind == "bytes" => bytes,
+        _ => return Err(malformed()),
+    };
+    // The bytes follow `bytes` in the contents.
+    let contents_spans = spans.item(2);
+    let bytes = bytes
+        .iter()
+        .enumerate()
+        .map(|(i, byte)| {
+            let span = contents_spans.item(i + 1).span;
+            match byte {
+                Sexp::Int(value) => u8::try_from(*value).map_err(|_| {
+                    syntax((
+                        span,
+                        format!("in data `{name}`: {value} doesn't fit in a byte"),
+                    ))
+                }),
+                byte => Err(syntax((
+                    span,
+                    format!("in data `{name}`: expected a byte, found `{byte}`"),
+                ))),
+            }
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(Data {
+        name: name.clone(),
+        bytes,
+    })
+}
+
+fn constant(form: &Sexp, spans: &SpanTree, constants: &[Constant]) -> Result<Constant, Located> {
+    let (name, value) = match list(form, spans).map_err(syntax)? {
+        [_, Sexp::Ident(name), value] => (name, value),
+        _ => {
+            return Err(syntax((
+                spans.span,
+                format!("expected `(const name value)`, found `{form}`"),
+            )))
+        }
+    };
+    let in_constant = |(span, message): (Span, String)| {
+        syntax((span, format!("in constant `{name}`: {message}")))
+    };
+    let value = constant_value(value, spans.item(2), constants).map_err(in_constant)?;
+    Ok(Constant {
+        name: name.clone(),
+        value,
+    })
+}
+
+fn test(form: &Sexp, spans: &SpanTree) -> Result<Test, Located> {
+    let malformed = || {
+        syntax((
+            spans.span,
+            format!(
+                "expected `(test name (expect value) expr)` or `(test name (trap code) expr)`, \
+                 found `{form}`"
+            ),
+        ))
+    };
+    let (name, expect, expr) = match list(form, spans).map_err(syntax)? {
+        [_, Sexp::Ident(name), Sexp::List(expect), expr] => (name, expect, expr),
+        _ => return Err(malformed()),
+    };
+    let (keyword, expected) = match &expect[..] {
+        [Sexp::Ident(keyword), expected] if keyword == "expect" || keyword == "trap" => {
+            (keyword, expected)
+        }
+        _ => return Err(malformed()),
+    };
+    let expected = match expected {
+        Sexp::Int(value) if keyword == "expect" => Expected::Value(*value),
+        Sexp::Ident(code) if keyword == "trap" => match code.parse() {
+            Ok(code) => Expected::Trap(code),
+            Err(()) => {
+                return Err(syntax((
+                    spans.item(2).item(1).span,
+                    format!("in test `{name}`: unknown trap code `{code}`"),
+                )))
+            }
+        },
+        expected => {
+            let what = if keyword == "expect" {
+                "an integer literal"
+            } else {
+                "a trap code"
+            };
+            return Err(syntax((
+                spans.item(2).item(1).span,
+                format!("in test `{name}`: expected {what}, found `{expected}`"),
+            )));
+        }
+    };
+    Ok(Test {
+        name: name.clone(),
+        expected,
+        expr: expr.clone(),
+        spans: spans.clone(),
+    })
+}
+
+/// The value of `expr`, the value of a constant, given the constants defined before it.
+fn constant_value(
+    expr: &Sexp,
+    spans: &SpanTree,
+    constants: &[Constant],
+) -> Result<i128, (Span, String)> {
+    match expr {
+        Sexp::Int(value) => Ok(*value),
+        Sexp::Ident(name) => constants
+            .iter()
+            .find(|constant| &constant.name == name)
+            .map(|constant| constant.value)
+            .ok_or_else(|| {
+                (
+                    spans.span,
+                    format!("`{name}` isn't a constant defined before it"),
+                )
+            }),
+        Sexp::List(items) => {
+            let (op, a, b) = match &items[..] {
+                [Sexp::Ident(op), a, b] if ["+", "-", "*"].contains(&op.as_str()) => (op, a, b),
+                _ => {
+                    return Err((
+                        spans.span,
+                        format!("expected `(+ a b)`, `(- a b)` or `(* a b)`, found `{expr}`"),
+                    ))
+                }
+            };
+            let a = constant_value(a, spans.item(1), constants)?;
+            let b = constant_value(b, spans.item(2), constants)?;
+            let value = match op.as_str() {
+                "+" => a.checked_add(b),
+                "-" => a.checked_sub(b),
+                _ => a.checked_mul(b),
+            };
+            value.ok_or_else(|| (spans.span, format!("`{expr}` overflows 128 bits")))
+        }
+        Sexp::Float(_) | Sexp::Str(_) => {
+            Err((spans.span, format!("constants are integers, not `{expr}`")))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::diagnostic::Severity;
+    use super::*;
+
+    fn parse(source: &str) -> Result<Module, Vec<Diagnostic>> {
+        Module::parse(source)
+    }
+
+    /// The messages of the errors parsing `source`, one per line.
+    fn error(source: &str) -> String {
+        let messages: Vec<String> = parse(source)
+            .unwrap_err()
+            .iter()
+            .map(Diagnostic::to_string)
+            .collect();
+        messages.join("\n")
+    }
+
+    #[test]
+    fn functions() {
+        let module = parse(
+            "(func fact ((n i32)) (i32)\n  (imul n (fact (isub n 1))))\n\
+             (func pair ((x i64) (y i8)) (i64 i8) x y)\n\
+             (func nothing () ())",
+        )
+        .unwrap();
+        let ident = |name: &str| Sexp::Ident(name.to_string());
+        assert_eq!(
+            module.functions,
+            [
+                Function {
+                    name: "fact".to_string(),
+                    params: vec![("n".to_string(), "i32".to_string())],
+                    returns: vec!["i32".to_string()],
+                    body: vec![Sexp::List(vec![
+                        ident("imul"),
+                        ident("n"),
+                        Sexp::List(vec![
+                            ident("fact"),
+                            Sexp::List(vec![ident("isub"), ident("n"), Sexp::Int(1)]),
+                        ]),
+                    ])],
+                    ..module.functions[0].clone()
+                },
+                Function {
+                    name: "pair".to_string(),
+                    params: vec![
+                        ("x".to_string(), "i64".to_string()),
+                        ("y".to_string(), "i8".to_string()),
+                    ],
+                    returns: vec!["i64".to_string(), "i8".to_string()],
+                    body: vec![ident("x"), ident("y")],
+                    ..module.functions[1].clone()
+                },
+                Function {
+                    name: "nothing".to_string(),
+                    params: vec![],
+                    returns: vec![],
+                    body: vec![],
+                    ..module.functions[2].clone()
+                },
+            ]
+        );
+        assert_eq!(
+            parse("; empty\n"),
+            Ok(Module {
+                functions: vec![],
+                externs: vec![],
+                data: vec![],
+                constants: vec![],
+                tests: vec![],
+                warnings: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn data() {
+        let module = parse(
+            "(data table (bytes 1 2 0xff 0b1))\n\
+             (func f () (i64) 1)\n\
+             (data empty (bytes))",
+        )
+        .unwrap();
+        assert_eq!(module.functions.len(), 1);
+        assert_eq!(
+            module.data,
+            [
+                Data {
+                    name: "table".to_string(),
+                    bytes: vec![1, 2, 255, 1],
+                },
+                Data {
+                    name: "empty".to_string(),
+                    bytes: vec![],
+                },
+            ]
+        );
+
+        assert_eq!(
+            error("(data t (bytes 1)) (data u (bytes 2)) (data t (bytes 3))"),
+            ParseError::DuplicateData("t".to_string()).to_string()
+        );
+        assert_eq!(
+            error("(data t (bytes 1)) (data t (bytes 1))"),
+            "data `t` is defined twice"
+        );
+        assert_eq!(
+            error("(func t () (i64) 1) (data t (bytes 1))"),
+            ParseError::DataAndFunction("t".to_string()).to_string()
+        );
+        assert_eq!(
+            error("(data t (bytes 1)) (func t () (i64) 1)"),
+            "`t` is the name of both a function and data"
+        );
+
+        assert_eq!(
+            error("(data t (bytes 1 256))"),
+            "in data `t`: 256 doesn't fit in a byte"
+        );
+        assert_eq!(
+            error("(data t (bytes -1))"),
+            "in data `t`: -1 doesn't fit in a byte"
+        );
+        assert_eq!(
+            error("(data t (bytes 1 x))"),
+            "in data `t`: expected a byte, found `x`"
+        );
+        for source in [
+            "(data t)",
+            "(data (bytes 1))",
+            "(data t (1 2))",
+            "(data t (words 1))",
+        ] {
+            assert_eq!(
+                error(source),
+                format!("expected `(data name (bytes byte...))`, found `{source}`"),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn externs() {
+        let module = parse(
+            "(extern env_print ((i32) (i64)) ())\n\
+             (func f () (i32) (call double 2))\n\
+             (extern double ((i32)) (i32))",
+        )
+        .unwrap();
+        assert_eq!(module.functions.len(), 1);
+        assert_eq!(
+            module.externs,
+            [
+                Extern {
+                    name: "env_print".to_string(),
+                    params: vec!["i32".to_string(), "i64".to_string()],
+                    returns: vec![],
+                    ..module.externs[0].clone()
+                },
+                Extern {
+                    name: "double".to_string(),
+                    params: vec!["i32".to_string()],
+                    returns: vec!["i32".to_string()],
+                    ..module.externs[1].clone()
+                },
+            ]
+        );
+
+        assert_eq!(
+            error("(extern e () ()) (extern e ((i32)) ())"),
+            ParseError::DuplicateExtern("e".to_string()).to_string()
+        );
+        assert_eq!(
+            error("(extern e () ()) (extern e () ())"),
+            "extern `e` is declared twice"
+        );
+        for source in [
+            "(func e () ()) (extern e () ())",
+            "(extern e () ()) (func e () ())",
+            "(data e (bytes 1)) (extern e () ())",
+            "(extern e () ()) (data e (bytes 1))",
+        ] {
+            assert_eq!(
+                error(source),
+                ParseError::ExternAndDefinition("e".to_string()).to_string(),
+                "{source}"
+            );
+        }
+        assert_eq!(
+            error("(extern e () ()) (func e () ())"),
+            "`e` is declared `extern` but defined in the module"
+        );
+        for source in [
+            "(extern e)",
+            "(extern e ())",
+            "(extern (i32) ())",
+            "(extern e (i32) ())",
+            "(extern e ((x i32)) ())",
+            "(extern e () i32)",
+            "(extern e () ((i32)))",
+            "(extern e () () 1)",
+        ] {
+            assert_eq!(
+                error(source),
+                format!("expected `(extern name ((type)...) (types...))`, found `{source}`"),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn constants() {
+        let module = parse(
+            "(const LIMIT 1_000)\n\
+             (func f () (i64) LIMIT)\n\
+             (const NEXT (+ LIMIT 1))\n\
+             (const MASK (- (* NEXT 0x10) -1))",
+        )
+        .unwrap();
+        let constant = |name: &str, value| Constant {
+            name: name.to_string(),
+            value,
+        };
+        assert_eq!(
+            module.constants,
+            [
+                constant("LIMIT", 1000),
+                constant("NEXT", 1001),
+                constant("MASK", 16017),
+            ]
+        );
+        assert_eq!(module.warnings, []);
+
+        assert_eq!(
+            error("(const A 1) (const B 2) (const A 3)"),
+            ParseError::DuplicateConstant("A".to_string()).to_string()
+        );
+        assert_eq!(
+            error("(const A 1) (const A 1)"),
+            "constant `A` is defined twice"
+        );
+        // Constants have a namespace of their own.
+        assert!(parse("(const f 1) (func f () (i64) 1) (data d (bytes 1)) (const d 2)").is_ok());
+
+        assert_eq!(
+            error("(const A B) (const B 1)"),
+            "in constant `A`: `B` isn't a constant defined before it"
+        );
+        assert_eq!(
+            error("(const A A)"),
+            "in constant `A`: `A` isn't a constant defined before it"
+        );
+        assert_eq!(
+            error("(const A 1.5)"),
+            "in constant `A`: constants are integers, not `1.5`"
+        );
+        assert_eq!(
+            error("(const A (band 4 2))"),
+            "in constant `A`: expected `(+ a b)`, `(- a b)` or `(* a b)`, found `(band 4 2)`"
+        );
+        assert_eq!(
+            error("(const A (+ 1 2 3))"),
+            "in constant `A`: expected `(+ a b)`, `(- a b)` or `(* a b)`, found `(+ 1 2 3)`"
+        );
+        assert_eq!(
+            error("(const A 0x7fff_ffff_ffff_ffff_ffff_ffff_ffff_ffff) (const B (* A 2))"),
+            "in constant `B`: `(* A 2)` overflows 128 bits"
+        );
+        for source in ["(const A)", "(const 1 2)", "(const A 1 2)"] {
+            assert_eq!(
+                error(source),
+                format!("expected `(const name value)`, found `{source}`"),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn shadowed_constants() {
+        let source = "(func f ((x i64) (N i32)) (i32) N)\n\
+                      (const N 10)\n\
+                      (func g ((N i64)) (i64) N)";
+        let module = parse(source).unwrap();
+        let warnings: Vec<(&str, Severity, String)> = module
+            .warnings
+            .iter()
+            .map(|w| {
+                (
+                    &source[w.span.start..w.span.end],
+                    w.severity,
+                    w.message.clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            warnings,
+            [
+                (
+                    "N",
+                    Severity::Warning,
+                    "in function `f`: parameter `N` shadows the constant `N`, so the function \
+                     uses the parameter"
+                        .to_string()
+                ),
+                (
+                    "N",
+                    Severity::Warning,
+                    "in function `g`: parameter `N` shadows the constant `N`, so the function \
+                     uses the parameter"
+                        .to_string()
+                ),
+            ]
+        );
+        assert_eq!(module.warnings[0].span.start, 18);
+    }
+
+    #[test]
+    fn tests() {
+        let source = "(func f ((x i64)) (i64) x)\n\
+                      (test f_1 (expect 1) (call f 1))\n\
+                      (test f_negative (expect -0x10) (call f -16))\n\
+                      (test f_divz (trap int_divz) (sdiv 1 (call f 0)))";
+        let module = parse(source).unwrap();
+        let tests: Vec<(&str, Expected, String)> = module
+            .tests
+            .iter()
+            .map(|test| (test.name.as_str(), test.expected, test.expr.to_string()))
+            .collect();
+        assert_eq!(
+            tests,
+            [
+                ("f_1", Expected::Value(1), "(call f 1)".to_string()),
+                (
+                    "f_negative",
+                    Expected::Value(-16),
+                    "(call f -16)".to_string()
+                ),
+                (
+                    "f_divz",
+                    Expected::Trap(TrapCode::IntegerDivisionByZero),
+                    "(sdiv 1 (call f 0))".to_string()
+                ),
+            ]
+        );
+        let span = module.tests[0].spans.item(3).span;
+        assert_eq!(&source[span.start..span.end], "(call f 1)");
+
+        assert_eq!(
+            error("(test t (expect 1) 1) (test t (expect 2) 2)"),
+            ParseError::DuplicateTest("t".to_string()).to_string()
+        );
+        assert_eq!(
+            error("(test t (expect 1) 1) (test t (expect 1) 1)"),
+            "test `t` is defined twice"
+        );
+        // Tests have a namespace of their own.
+        assert!(parse("(func f () (i64) 1) (const f 1) (test f (expect 1) (call f))").is_ok());
+
+        assert_eq!(
+            error("(test t (expect 1.5) 1)"),
+            "in test `t`: expected an integer literal, found `1.5`"
+        );
+        assert_eq!(
+            error("(test t (expect N) N)"),
+            "in test `t`: expected an integer literal, found `N`"
+        );
+        assert_eq!(
+            error("(test t (trap 0) 1)"),
+            "in test `t`: expected a trap code, found `0`"
+        );
+        assert_eq!(
+            error("(test t (trap divz) 1)"),
+            "in test `t`: unknown trap code `divz`"
+        );
+        for source in [
+            "(test t)",
+            "(test t (expect 1))",
+            "(test 1 (expect 1) 1)",
+            "(test t (expected 1) 1)",
+            "(test t (expect) 1)",
+            "(test t (expect 1 2) 1)",
+            "(test t (expect 1) 1 2)",
+            "(test t (trap) 1)",
+        ] {
+            assert_eq!(
+                error(source),
+                format!(
+                    "expected `(test name (expect value) expr)` or `(test name (trap code) \
+                     expr)`, found `{source}`"
+                ),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn duplicates() {
+        assert_eq!(
+            error("(func f () (i64) 1)\n(func g () (i64) 2)\n(func f () (i64) 3)"),
+            ParseError::DuplicateFunction("f".to_string()).to_string()
+        );
+        assert_eq!(
+            error("(func f () (i64) 1) (func f () (i64) 3)"),
+            "function `f` is defined twice"
+        );
+        assert_eq!(
+            error("(func f ((x i64) (y i64) (x i32)) (i64) x)"),
+            ParseError::DuplicateParam {
+                function: "f".to_string(),
+                param: "x".to_string(),
+            }
+            .to_string()
+        );
+        assert_eq!(
+            error("(func f ((x i64) (x i64)) (i64) x)"),
+            "in function `f`: parameter `x` is declared twice"
+        );
+        // Parameters of different functions don't clash.
+        assert!(parse("(func f ((x i64)) (i64) x) (func g ((x i64)) (i64) x)").is_ok());
+    }
+
+    #[test]
+    fn missing_returns() {
+        for source in [
+            "(func f ((x i64)))",
+            "(func f ((x i64)) i64 x)",
+            "(func f ((x i64)) (iadd x 1))",
+            "(func f ((x i64)) 1)",
+        ] {
+            assert_eq!(
+                error(source),
+                ParseError::MissingReturns("f".to_string()).to_string(),
+                "{source}"
+            );
+        }
+        assert_eq!(
+            error("(func f () 1)"),
+            "function `f` has no return types; list them after the parameters, as in `(i64)`, \
+             or write `()` if it returns nothing"
+        );
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(
+            error("(fn f () (i64) 1)"),
+            "expected `func`, `extern`, `data`, `const`, `test` or `include`, found `fn`"
+        );
+        assert_eq!(
+            error("(func)"),
+            "expected `(func name (params...) (types...) body...)`"
+        );
+        assert_eq!(
+            error("(func f)"),
+            "in function `f`: expected a parameter list"
+        );
+        assert_eq!(
+            error("(func f ((x)) (i64) 1)"),
+            "in function `f`: expected a parameter `(name type)`"
+        );
+        assert_eq!(
+            error("(func f x (i64) 1)"),
+            "in function `f`: expected a list, found `x`"
+        );
+        assert_eq!(
+            error("(include \"lib.clifp\")"),
+            "`include` is only supported in programs read from files"
+        );
+        assert_eq!(
+            error("(const A \"1\")"),
+            "in constant `A`: constants are integers, not `\"1\"`"
+        );
+        assert_eq!(error("(func f () (i64) 1"), "`(` is never closed");
+        assert_eq!(error("(func f () (i64) #)"), "unexpected character '#'");
+    }
+
+    #[test]
+    fn spans() {
+        let source = "(func f ((x i64)) (i64)\n  (iadd x 1))\n(extern e ((i32)) ())";
+        let module = parse(source).unwrap();
+        let span = |spans: &SpanTree| &source[spans.span.start..spans.span.end];
+        let func = &module.functions[0];
+        assert_eq!(span(&func.spans), "(func f ((x i64)) (i64)\n  (iadd x 1))");
+        assert_eq!(span(func.spans.item(2).item(0).item(1)), "i64");
+        assert_eq!(span(func.spans.item(4)), "(iadd x 1)");
+        assert_eq!(span(func.spans.item(4).item(2)), "1");
+        assert_eq!(span(&module.externs[0].spans), "(extern e ((i32)) ())");
+    }
+
+    #[test]
+    fn several_errors() {
+        let source = "(func f ((x i64) (x i64)) (i64) x)\n\
+                      (func g () (i64) 1)\n\
+                      (data d (bytes 1 300))\n\
+                      (func g () i64 2)\n\
+                      (fn h)";
+        let errors = parse(source).unwrap_err();
+        let located: Vec<(&str, String)> = errors
+            .iter()
+            .map(|e| (&source[e.span.start..e.span.end], e.message.clone()))
+            .collect();
+        assert_eq!(
+            located,
+            [
+                (
+                    "x",
+                    "in function `f`: parameter `x` is declared twice".to_string()
+                ),
+                ("300", "in data `d`: 300 doesn't fit in a byte".to_string()),
+                (
+                    "i64",
+                    ParseError::MissingReturns("g".to_string()).to_string()
+                ),
+                (
+                    "fn",
+                    "expected `func`, `extern`, `data`, `const`, `test` or `include`, found `fn`"
+                        .to_string()
+                ),
+            ]
+        );
+        // The duplicate parameter is the second one.
+        assert_eq!(errors[0].span.start, 18);
+
+        let errors = parse("(func f () (i64) 1)\n(func f () (i64) 2)").unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].span.start, 26);
+    }
+}
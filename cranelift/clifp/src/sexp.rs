@@ -0,0 +1,393 @@
+//! Grouping clifp tokens into s-expressions.
+
+use super::lexer::{self, Span, Token};
+use std::fmt;
+
+/// The deepest nesting of lists [`parse`] accepts. Parsing doesn't recurse, but the later passes
+/// and dropping the lists do, once per level.
+pub const MAX_DEPTH: usize = 256;
+
+/// An s-expression: an atom, or a parenthesized list of s-expressions.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Sexp {
+    /// `(` followed by s-expressions and `)`.
+    List(Vec<Sexp>),
+    /// An integer literal.
+    Int(i128),
+    /// A floating-point literal.
+    Float(f64),
+    /// A name.
+    Ident(String),
+    /// A string literal, without its quotes.
+    Str(String),
+}
+
+/// Where an s-expression is in the source text: its span, and those of its items if it's a
+/// list.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SpanTree {
+    /// The span of the s-expression, from the `(` to the `)` of a list.
+    pub span: Span,
+    /// The spans of the items of a list, in order. An atom has none.
+    pub items: Vec<SpanTree>,
+}
+
+impl SpanTree {
+    /// The spans of the item at `index` of a list, or those of the whole s-expression if there
+    /// is no such item.
+    pub fn item(&self, index: usize) -> &SpanTree {
+        self.items.get(index).unwrap_or(self)
+    }
+}
+
+/// Prints the canonical source text of the s-expression: its atoms as [`Token`]s print them,
+/// separated by single spaces.
+impl fmt::Display for Sexp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::List(items) => {
+                write!(f, "(")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                write!(f, ")")
+            }
+            Self::Int(value) => write!(f, "{value}"),
+            Self::Float(value) => lexer::write_float(f, *value),
+            Self::Ident(name) => write!(f, "{name}"),
+            Self::Str(text) => write!(f, "\"{text}\""),
+        }
+    }
+}
+
+/// The canonical source text of `forms`, one per line, which [`parse`] turns back into `forms`.
+pub fn print(forms: &[Sexp]) -> String {
+    let lines: Vec<String> = forms.iter().map(Sexp::to_string).collect();
+    lines.join("\n")
+}
+
+/// An error grouping tokens into s-expressions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// A `(`, at the given span, which isn't closed before the end of the input.
+    Unclosed(Span),
+    /// A `)`, at the given span, which doesn't close any list.
+    Unopened(Span),
+    /// A `(`, at the given span, opening a list nested more than [`MAX_DEPTH`] levels deep.
+    TooDeep(Span),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Unclosed(span) => write!(f, "`(` at byte {} is never closed", span.start),
+            Self::Unopened(span) => {
+                write!(f, "`)` at byte {} doesn't close any list", span.start)
+            }
+            Self::TooDeep(span) => write!(
+                f,
+                "`(` at byte {} nests lists more than {MAX_DEPTH} levels deep",
+                span.start
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl ParseError {
+    /// The span of the offending parenthesis.
+    pub fn span(&self) -> Span {
+        match *self {
+            Self::Unclosed(span) | Self::Unopened(span) | Self::TooDeep(span) => span,
+        }
+    }
+}
+
+/// Group `tokens` into the s-expressions they spell, in order.
+pub fn parse(tokens: &[(Token<'_>, Span)]) -> Result<Vec<Sexp>, ParseError> {
+    parse_with_spans(tokens).map(|(forms, _)| forms)
+}
+
+/// Group `tokens` into the s-expressions they spell, in order, along with where each of them
+/// is.
+pub fn parse_with_spans(
+    tokens: &[(Token<'_>, Span)],
+) -> Result<(Vec<Sexp>, Vec<SpanTree>), ParseError> {
+    // The lists being parsed, outermost first, with the span of their `(` and the spans of
+    // their items, below the top-level forms.
+    let mut open: Vec<(Span, Vec<Sexp>, Vec<SpanTree>)> = Vec::new();
+    let mut forms = Vec::new();
+    let mut spans = Vec::new();
+    for (token, span) in tokens {
+        let (sexp, tree) = match token {
+            Token::LParen => {
+                if open.len() == MAX_DEPTH {
+                    return Err(ParseError::TooDeep(*span));
+                }
+                open.push((*span, Vec::new(), Vec::new()));
+                continue;
+            }
+            Token::RParen => match open.pop() {
+                Some((start, items, item_spans)) => (
+                    Sexp::List(items),
+                    SpanTree {
+                        span: Span {
+                            start: start.start,
+                            end: span.end,
+                        },
+                        items: item_spans,
+                    },
+                ),
+                None => return Err(ParseError::Unopened(*span)),
+            },
+            Token::Int(value) => (Sexp::Int(*value), atom(*span)),
+            Token::Float(value) => (Sexp::Float(*value), atom(*span)),
+            Token::Str(text) => (Sexp::Str(text.to_string()), atom(*span)),
+            Token::Ident(name) => (Sexp::Ident(name.to_string()), atom(*span)),
+        };
+        match open.last_mut() {
+            Some((_, items, item_spans)) => {
+                items.push(sexp);
+                item_spans.push(tree);
+            }
+            None => {
+                forms.push(sexp);
+                spans.push(tree);
+            }
+        }
+    }
+    match open.pop() {
+        // The innermost list is the one missing a `)`.
+        Some((span, _, _)) => Err(ParseError::Unclosed(span)),
+        None => Ok((forms, spans)),
+    }
+}
+
+fn atom(span: Span) -> SpanTree {
+    SpanTree {
+        span,
+        items: Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::lexer::lex;
+    use super::*;
+    use proptest::num::f64::{
+        INFINITE, NEGATIVE, NORMAL, POSITIVE, QUIET_NAN, SIGNALING_NAN, SUBNORMAL, ZERO,
+    };
+    use proptest::prelude::*;
+
+    fn sexps(src: &str) -> Result<Vec<Sexp>, ParseError> {
+        parse(&lex(src).unwrap())
+    }
+
+    fn list(items: impl Into<Vec<Sexp>>) -> Sexp {
+        Sexp::List(items.into())
+    }
+
+    fn ident(name: &str) -> Sexp {
+        Sexp::Ident(name.to_string())
+    }
+
+    fn span(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+
+    #[test]
+    fn atoms_and_lists() {
+        assert_eq!(sexps(""), Ok(vec![]));
+        assert_eq!(sexps("; nothing\n"), Ok(vec![]));
+        assert_eq!(
+            sexps("x 1 -2.5"),
+            Ok(vec![ident("x"), Sexp::Int(1), Sexp::Float(-2.5)])
+        );
+        assert_eq!(sexps("()"), Ok(vec![list([])]));
+        assert_eq!(sexps("(())"), Ok(vec![list([list([])])]));
+        assert_eq!(
+            sexps("(iadd (imul x 3) 0x10)"),
+            Ok(vec![list([
+                ident("iadd"),
+                list([ident("imul"), ident("x"), Sexp::Int(3)]),
+                Sexp::Int(16),
+            ])])
+        );
+    }
+
+    #[test]
+    fn top_level_forms() {
+        assert_eq!(
+            sexps("(func f () i64 1)\n(func g () i64 (f))\nend"),
+            Ok(vec![
+                list([
+                    ident("func"),
+                    ident("f"),
+                    list([]),
+                    ident("i64"),
+                    Sexp::Int(1),
+                ]),
+                list([
+                    ident("func"),
+                    ident("g"),
+                    list([]),
+                    ident("i64"),
+                    list([ident("f")]),
+                ]),
+                ident("end"),
+            ])
+        );
+        assert_eq!(sexps("()()"), Ok(vec![list([]), list([])]));
+    }
+
+    #[test]
+    fn unbalanced_parens() {
+        assert_eq!(sexps("("), Err(ParseError::Unclosed(span(0, 1))));
+        assert_eq!(sexps(")"), Err(ParseError::Unopened(span(0, 1))));
+        // The innermost unclosed list is reported.
+        assert_eq!(
+            sexps("(func f\n  (iadd (x) 1"),
+            Err(ParseError::Unclosed(span(10, 11)))
+        );
+        assert_eq!(sexps("(a) (b))"), Err(ParseError::Unopened(span(7, 8))));
+        assert_eq!(sexps("x )("), Err(ParseError::Unopened(span(2, 3))));
+        assert_eq!(
+            sexps("(a\n(b)").unwrap_err().to_string(),
+            "`(` at byte 0 is never closed"
+        );
+        assert_eq!(
+            sexps("())").unwrap_err().to_string(),
+            "`)` at byte 2 doesn't close any list"
+        );
+    }
+
+    #[test]
+    fn deep_nesting() {
+        let nested = |depth: usize| format!("{}x{}", "(".repeat(depth), ")".repeat(depth));
+        let mut expected = ident("x");
+        for _ in 0..MAX_DEPTH {
+            expected = list([expected]);
+        }
+        assert_eq!(sexps(&nested(MAX_DEPTH)), Ok(vec![expected]));
+
+        let depth = MAX_DEPTH + 1;
+        assert_eq!(
+            sexps(&nested(depth)),
+            Err(ParseError::TooDeep(span(MAX_DEPTH, depth)))
+        );
+        // Far deeper nesting is rejected without overflowing the stack.
+        let deep = "(".repeat(1_000_000);
+        assert!(matches!(sexps(&deep), Err(ParseError::TooDeep(_))));
+        let closing = ")".repeat(1_000_000);
+        assert_eq!(sexps(&closing), Err(ParseError::Unopened(span(0, 1))));
+    }
+
+    #[test]
+    fn printing() {
+        assert_eq!(
+            print(&sexps("( iadd\n (imul  x 0x3 ) ; comment\n 1.50e1 )\n\n(f)()").unwrap()),
+            "(iadd (imul x 3) 15.0)\n(f)\n()"
+        );
+        assert_eq!(list([]).to_string(), "()");
+        assert_eq!(
+            list([Sexp::Float(2.0), Sexp::Float(-0.5), Sexp::Int(-7)]).to_string(),
+            "(2.0 -0.5 -7)"
+        );
+
+        // Printing and parsing again gives back the same s-expressions.
+        let corpus = [
+            "",
+            "x",
+            "(func mix ((x i64) (y i64)) (i64)\n  (bxor (imul x 3) (ishl y 4)))",
+            "(func fact ((n i32)) (i32) (if n (imul n (call fact (isub n 1))) 1))",
+            "(((())) () (()))",
+            "(f64.const 1e300 -inf 0.1 1e-9 123456789.0 -0.0)",
+            "(iconst 0xffff_ffff_ffff_ffff -0o17 0b1010 170141183460469231731687303715884105727)",
+            "(a)\n(b c)\n; trailing comment",
+            "(include \"lib/helpers.clifp\" \"\")",
+        ];
+        for src in corpus {
+            let forms = sexps(src).unwrap();
+            let printed = print(&forms);
+            assert_eq!(sexps(&printed), Ok(forms), "{printed}");
+            assert!(
+                printed.lines().all(|line| line == line.trim_end()),
+                "{printed}"
+            );
+            // The canonical text is a fixed point.
+            assert_eq!(print(&sexps(&printed).unwrap()), printed);
+        }
+    }
+
+    /// The names the lexer reads as names: a letter followed by letters, digits, `_` and `.`,
+    /// other than `inf` and `nan`, which are floats, or an operator, which only starts with a `-`
+    /// if it's just `-`, since a `-` followed by more is the sign of a number.
+    fn name() -> impl Strategy<Value = String> {
+        prop_oneof![
+            "[a-zA-Z][a-zA-Z0-9_.]{0,8}"
+                .prop_filter("a float", |name| name != "inf" && name != "nan"),
+            "-|[+*<>=!][-+*<>=!]{0,2}",
+        ]
+    }
+
+    /// The atoms of s-expressions, with floats drawn from `floats`.
+    fn atom(floats: impl Strategy<Value = f64>) -> impl Strategy<Value = Sexp> {
+        prop_oneof![
+            any::<i128>().prop_map(Sexp::Int),
+            floats.prop_map(Sexp::Float),
+            name().prop_map(Sexp::Ident),
+            "[^\"\n\r]{0,8}".prop_map(Sexp::Str),
+        ]
+    }
+
+    /// Top-level forms of atoms from `atom`, in lists nested at most 8 deep of at most 6 items.
+    fn forms(atom: impl Strategy<Value = Sexp> + 'static) -> impl Strategy<Value = Vec<Sexp>> {
+        let sexp = atom.prop_recursive(8, 64, 6, |inner| {
+            prop::collection::vec(inner, 0..6).prop_map(Sexp::List)
+        });
+        prop::collection::vec(sexp, 0..6)
+    }
+
+    /// Whether `a` and `b` are the same s-expressions, counting every NaN as the same float.
+    fn same(a: &Sexp, b: &Sexp) -> bool {
+        match (a, b) {
+            (Sexp::List(a), Sexp::List(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same(a, b))
+            }
+            (Sexp::Float(a), Sexp::Float(b)) if a.is_nan() => b.is_nan(),
+            _ => a == b,
+        }
+    }
+
+    proptest! {
+        // The floats other than NaN include both zeros, which compare equal, so the sign of a
+        // zero which doesn't round-trip shows in the printed text instead.
+        #[test]
+        fn printing_round_trips(
+            forms in forms(atom(POSITIVE | NEGATIVE | NORMAL | SUBNORMAL | ZERO | INFINITE)),
+        ) {
+            let printed = print(&forms);
+            let reparsed = sexps(&printed);
+            prop_assert_eq!(reparsed.as_ref(), Ok(&forms), "{}", printed);
+            prop_assert_eq!(print(&reparsed.unwrap()), printed);
+        }
+
+        // Every NaN prints as `nan`, which lexes to a NaN, but not one with the same bits.
+        #[test]
+        fn printing_round_trips_nan(
+            forms in forms(atom(POSITIVE | NEGATIVE | QUIET_NAN | SIGNALING_NAN)),
+        ) {
+            let printed = print(&forms);
+            let reparsed = sexps(&printed).unwrap();
+            prop_assert_eq!(reparsed.len(), forms.len(), "{}", printed);
+            for (reparsed, form) in reparsed.iter().zip(&forms) {
+                prop_assert!(same(reparsed, form), "{} is not {}", reparsed, form);
+            }
+            prop_assert_eq!(print(&reparsed), printed);
+        }
+    }
+}
@@ -0,0 +1,456 @@
+//! The source files of a clifp program, and reading the files it includes.
+
+use super::diagnostic::Diagnostic;
+use super::lexer::{self, Span};
+use super::sexp::{self, Sexp, SpanTree};
+use std::collections::HashSet;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+
+/// Where the files of a program are read from.
+pub trait Loader {
+    /// The text of the file at `path`.
+    fn load(&self, path: &Path) -> io::Result<String>;
+}
+
+/// The file system, where relative paths are relative to the working directory.
+pub struct FileSystem;
+
+impl Loader for FileSystem {
+    fn load(&self, path: &Path) -> io::Result<String> {
+        std::fs::read_to_string(path)
+    }
+}
+
+/// A file of a program.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SourceFile {
+    /// The path the file was read from.
+    pub path: PathBuf,
+    /// The text of the file.
+    pub text: String,
+    /// The offset in the [`SourceMap`] of the start of the text.
+    pub start: usize,
+}
+
+/// The files of a program, laid end to end so that the spans of each are offsets of its own,
+/// and a span says which file it's in.
+///
+/// The first file starts at 0, so its spans are the byte offsets of its text, as those of a
+/// program parsed from a string are. Each file also has the offset after its last byte, where
+/// errors at the end of its text are.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    /// A map without files.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The files, in the order they were read.
+    pub fn files(&self) -> &[SourceFile] {
+        &self.files
+    }
+
+    /// Add the file at `path` with the given text after the others, and return its start.
+    pub fn add(&mut self, path: PathBuf, text: String) -> usize {
+        let start = self.end();
+        self.files.push(SourceFile { path, text, start });
+        start
+    }
+
+    /// The file `offset` is in, and the byte offset in its text `offset` is.
+    pub fn lookup(&self, offset: usize) -> Option<(&SourceFile, usize)> {
+        let index = self.files.partition_point(|file| file.start <= offset);
+        let file = &self.files[index.checked_sub(1)?];
+        let local = offset - file.start;
+        if local <= file.text.len() {
+            Some((file, local))
+        } else {
+            None
+        }
+    }
+
+    /// The offset after the last file, which isn't in any file.
+    fn end(&self) -> usize {
+        self.files
+            .last()
+            .map_or(0, |file| file.start + file.text.len() + 1)
+    }
+}
+
+/// Read the program at `path` with `loader`, adding its files to `sources`, and return its
+/// top-level forms, with each `(include "path")` replaced by the forms of the file at `path`,
+/// relative to the directory of the file including it.
+///
+/// A file already included, by any of the files, adds no forms when it's included again, so
+/// that files can include the helpers they use whether or not other files do. A file including
+/// itself, directly or through other files, has an error naming the files of the cycle. Every
+/// file which can't be read or lexed, and every malformed `include`, has an error of its own.
+/// The paths of the files are compared without their `.` and `..` components, but symbolic
+/// links aren't resolved.
+pub fn load(
+    path: &Path,
+    loader: &dyn Loader,
+    sources: &mut SourceMap,
+) -> Result<(Vec<Sexp>, Vec<SpanTree>), Vec<Diagnostic>> {
+    let path = normalize(path);
+    let mut includes = Includes {
+        loader,
+        sources,
+        chain: Vec::new(),
+        included: HashSet::new(),
+        forms: Vec::new(),
+        spans: Vec::new(),
+        errors: Vec::new(),
+    };
+    match loader.load(&path) {
+        Ok(text) => includes.file(path, text),
+        Err(err) => {
+            // The program has no text for the error to point into.
+            let end = includes.sources.end();
+            let span = Span { start: end, end };
+            let message = format!("can't read `{}`: {err}", path.display());
+            includes.errors.push(Diagnostic::error(span, message));
+        }
+    }
+    if !includes.errors.is_empty() {
+        return Err(includes.errors);
+    }
+    Ok((includes.forms, includes.spans))
+}
+
+/// The state of [`load`] while it reads the files of a program.
+struct Includes<'a> {
+    loader: &'a dyn Loader,
+    sources: &'a mut SourceMap,
+    /// The files being read, each included by the one before it.
+    chain: Vec<PathBuf>,
+    /// The files read so far, including those being read.
+    included: HashSet<PathBuf>,
+    forms: Vec<Sexp>,
+    spans: Vec<SpanTree>,
+    errors: Vec<Diagnostic>,
+}
+
+impl Includes<'_> {
+    /// Add the forms of the file at `path` with the given text, and those of the files it
+    /// includes in their places.
+    fn file(&mut self, path: PathBuf, text: String) {
+        self.included.insert(path.clone());
+        let start = self.sources.add(path.clone(), text);
+        let text = &self.sources.files.last().unwrap().text;
+        let parsed = match lexer::lex(text) {
+            Ok(tokens) => {
+                let tokens: Vec<_> = tokens
+                    .into_iter()
+                    .map(|(token, span)| (token, shift(span, start)))
+                    .collect();
+                sexp::parse_with_spans(&tokens).map_err(Diagnostic::from)
+            }
+            Err(err) => {
+                let error = Diagnostic::lex(text, &err);
+                Err(Diagnostic {
+                    span: shift(error.span, start),
+                    ..error
+                })
+            }
+        };
+        let (forms, spans) = match parsed {
+            Ok(parsed) => parsed,
+            Err(error) => return self.errors.push(error),
+        };
+
+        self.chain.push(path);
+        for (form, spans) in forms.into_iter().zip(spans) {
+            let include = match &form {
+                Sexp::List(items) => {
+                    matches!(items.first(), Some(Sexp::Ident(op)) if op == "include")
+                }
+                _ => false,
+            };
+            if include {
+                self.include(&form, &spans);
+            } else {
+                self.forms.push(form);
+                self.spans.push(spans);
+            }
+        }
+        self.chain.pop();
+    }
+
+    /// Add the forms of the file `(include "path")` includes in the last file of the chain.
+    fn include(&mut self, form: &Sexp, spans: &SpanTree) {
+        let written = match form {
+            Sexp::List(items) => match &items[..] {
+                [_, Sexp::Str(path)] => Some(path),
+                _ => None,
+            },
+            _ => None,
+        };
+        let written = match written {
+            Some(written) => written,
+            None => {
+                let message = format!("expected `(include \"path\")`, found `{form}`");
+                return self.error(spans.span, message);
+            }
+        };
+        let span = spans.item(1).span;
+        let including = self.chain.last().unwrap();
+        let path = normalize(&including.parent().unwrap_or(Path::new("")).join(written));
+        if let Some(cycle) = self.chain.iter().position(|file| *file == path) {
+            let mut files: Vec<String> = self.chain[cycle..]
+                .iter()
+                .chain([&path])
+                .map(|file| format!("`{}`", file.display()))
+                .collect();
+            let first = files.remove(0);
+            return self.error(
+                span,
+                format!(
+                    "`{}` includes itself: {first} includes {}",
+                    path.display(),
+                    files.join(", which includes ")
+                ),
+            );
+        }
+        if self.included.contains(&path) {
+            return;
+        }
+        match self.loader.load(&path) {
+            Ok(text) => self.file(path, text),
+            Err(err) => self.error(span, format!("can't read `{}`: {err}", path.display())),
+        }
+    }
+
+    fn error(&mut self, span: Span, message: String) {
+        self.errors.push(Diagnostic::error(span, message));
+    }
+}
+
+/// `span`, moved `offset` bytes further.
+fn shift(span: Span, offset: usize) -> Span {
+    Span {
+        start: span.start + offset,
+        end: span.end + offset,
+    }
+}
+
+/// `path` without its `.` components, and with each `..` component removing the component
+/// before it, if there is one, so that a file has the same path however it's included.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(normal.components().next_back(), Some(Component::Normal(_))) =>
+            {
+                normal.pop();
+            }
+            component => normal.push(component),
+        }
+    }
+    normal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::compile::{self, CompileError};
+    use super::super::diagnostic::render_all_in;
+    use super::super::parser::Module;
+    use super::*;
+    use cranelift_jit::{JITBuilder, JITModule};
+    use cranelift_module::default_libcall_names;
+    use std::collections::HashMap;
+
+    /// Files in memory, by their paths.
+    impl Loader for HashMap<&str, &str> {
+        fn load(&self, path: &Path) -> io::Result<String> {
+            match path.to_str().and_then(|path| self.get(path)) {
+                Some(text) => Ok(text.to_string()),
+                None => Err(io::Error::new(io::ErrorKind::NotFound, "no such file")),
+            }
+        }
+    }
+
+    /// Parse the program `main.clifp` of `files`, and return its module or its rendered errors,
+    /// and its source map.
+    fn parse(files: &[(&str, &str)]) -> (Result<Module, String>, SourceMap) {
+        let files: HashMap<_, _> = files.iter().copied().collect();
+        let mut sources = SourceMap::new();
+        let module = Module::parse_file(Path::new("main.clifp"), &files, &mut sources)
+            .map_err(|errors| render_all_in(&errors, &sources));
+        (module, sources)
+    }
+
+    fn error(files: &[(&str, &str)]) -> String {
+        parse(files).0.unwrap_err()
+    }
+
+    #[test]
+    fn includes() {
+        let (module, sources) = parse(&[
+            (
+                "main.clifp",
+                "(include \"lib/helpers.clifp\")\n\
+                 (include \"./lib/limits.clifp\")\n\
+                 (func main () (i64) (call clamp 5))",
+            ),
+            (
+                "lib/helpers.clifp",
+                "(include \"limits.clifp\")\n\
+                 (func clamp ((x i64)) (i64) (if (> x LIMIT) LIMIT x))",
+            ),
+            ("lib/limits.clifp", "(const LIMIT 3)"),
+        ]);
+        let module = module.unwrap();
+        let names: Vec<&str> = module.functions.iter().map(|f| f.name.as_str()).collect();
+        // The forms of each file take the place of its first include, and the second include of
+        // `lib/limits.clifp` adds nothing.
+        assert_eq!(names, ["clamp", "main"]);
+        assert_eq!(module.constants.len(), 1);
+        let paths: Vec<&Path> = sources.files().iter().map(|f| f.path.as_path()).collect();
+        assert_eq!(
+            paths,
+            [
+                Path::new("main.clifp"),
+                Path::new("lib/helpers.clifp"),
+                Path::new("lib/limits.clifp"),
+            ]
+        );
+
+        // The spans of each file are offsets of its own.
+        let span = module.functions[0].spans.item(1).span;
+        let (file, start) = sources.lookup(span.start).unwrap();
+        assert_eq!(file.path, Path::new("lib/helpers.clifp"));
+        assert_eq!(&file.text[start..start + 5], "clamp");
+        assert_eq!(sources.lookup(0).unwrap().0.path, Path::new("main.clifp"));
+        let end = sources.files()[2].start + "(const LIMIT 3)".len();
+        assert_eq!(sources.lookup(end).unwrap().1, 15);
+        assert_eq!(sources.lookup(end + 1), None);
+
+        // A file can include files of other directories.
+        let (module, _) = parse(&[
+            (
+                "main.clifp",
+                "(include \"a/b.clifp\") (include \"c/d.clifp\")",
+            ),
+            ("a/b.clifp", "(include \"../c/d.clifp\") (const B D)"),
+            ("c/d.clifp", "(const D 1)"),
+        ]);
+        assert_eq!(module.unwrap().constants[1].value, 1);
+    }
+
+    #[test]
+    fn include_errors() {
+        assert_eq!(
+            error(&[
+                ("main.clifp", "(const A 1)\n(include \"a.clifp\")"),
+                ("a.clifp", "(include \"b.clifp\")"),
+                ("b.clifp", "(func f () (i64) 1)\n(include \"main.clifp\")"),
+            ]),
+            "\
+error: `main.clifp` includes itself: `main.clifp` includes `a.clifp`, which includes \
+`b.clifp`, which includes `main.clifp` at line 2, column 10 of b.clifp
+  |
+2 | (include \"main.clifp\")
+  |          ^^^^^^^^^^^^"
+        );
+        assert_eq!(
+            error(&[("main.clifp", "(include \"./main.clifp\")")]),
+            "\
+error: `main.clifp` includes itself: `main.clifp` includes `main.clifp` at line 1, column 10 \
+of main.clifp
+  |
+1 | (include \"./main.clifp\")
+  |          ^^^^^^^^^^^^^^"
+        );
+        assert_eq!(
+            error(&[("main.clifp", "(include \"lib/missing.clifp\")")]),
+            "\
+error: can't read `lib/missing.clifp`: no such file at line 1, column 10 of main.clifp
+  |
+1 | (include \"lib/missing.clifp\")
+  |          ^^^^^^^^^^^^^^^^^^^"
+        );
+        assert_eq!(error(&[]), "error: can't read `main.clifp`: no such file");
+
+        // Every file which doesn't lex has an error, and so does every malformed include.
+        assert_eq!(
+            error(&[
+                (
+                    "main.clifp",
+                    "(include \"a.clifp\")\n(include a.clifp)\n(include \"b.clifp\")"
+                ),
+                ("a.clifp", "(func f () (i64)\n  #)"),
+                ("b.clifp", "(func g () (i64) 1"),
+            ]),
+            "\
+error: unexpected character '#' at line 2, column 3 of a.clifp
+  |
+2 |   #)
+  |   ^
+
+error: expected `(include \"path\")`, found `(include a.clifp)` at line 2, column 1 of main.clifp
+  |
+2 | (include a.clifp)
+  | ^^^^^^^^^^^^^^^^^
+
+error: `(` is never closed at line 1, column 1 of b.clifp
+  |
+1 | (func g () (i64) 1
+  | ^"
+        );
+        assert_eq!(
+            error(&[("main.clifp", "(include \"a.clifp\" \"b.clifp\")")]),
+            "\
+error: expected `(include \"path\")`, found `(include \"a.clifp\" \"b.clifp\")` at line 1, \
+column 1 of main.clifp
+  |
+1 | (include \"a.clifp\" \"b.clifp\")
+  | ^^^^^^^^^^^^^^^^^^^^^^^^^^^^^"
+        );
+    }
+
+    #[test]
+    fn errors_in_included_files() {
+        // The names of all the files clash, and a clash is reported at the later name.
+        assert_eq!(
+            error(&[
+                ("main.clifp", "(include \"a.clifp\")\n(func f () (i64) 2)"),
+                ("a.clifp", "(func f () (i64) 1)"),
+            ]),
+            "\
+error: function `f` is defined twice at line 2, column 7 of main.clifp
+  |
+2 | (func f () (i64) 2)
+  |       ^"
+        );
+
+        // Errors lowering the functions of an included file are in that file.
+        let (module, sources) = parse(&[
+            (
+                "main.clifp",
+                "(include \"a.clifp\")\n(func main () (i64) (call f))",
+            ),
+            ("a.clifp", "; helpers\n(func f () (i64)\n  (imull 2 3))"),
+        ]);
+        let mut target = JITModule::new(JITBuilder::new(default_libcall_names()).unwrap());
+        let errors = match compile::compile(&mut target, &module.unwrap()) {
+            Err(CompileError::Diagnostics(errors)) => errors,
+            _ => panic!("the program compiled"),
+        };
+        assert_eq!(
+            render_all_in(&errors, &sources),
+            "\
+error: in function `f`: unknown opcode `imull` at line 3, column 4 of a.clifp
+  |
+3 |   (imull 2 3))
+  |    ^^^^^"
+        );
+    }
+}
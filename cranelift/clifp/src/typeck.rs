@@ -0,0 +1,310 @@
+//! Resolving the types of the functions and host functions of a clifp [`Module`].
+//!
+//! The bodies of the functions are checked as they are lowered, see
+//! [`lower`](super::compile::lower).
+
+use super::diagnostic::Diagnostic;
+use super::lexer::Span;
+use super::parser::{Extern, Function, Module};
+use super::sexp::SpanTree;
+use cranelift_codegen::ir::{types, AbiParam, Signature, Type};
+use cranelift_codegen::isa::CallConv;
+use std::fmt;
+
+/// The types of the parameters and of the result of a function.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FuncType {
+    pub params: Vec<Type>,
+    pub returns: Vec<Type>,
+}
+
+/// A type name of a function which isn't the name of a type.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TypeError {
+    /// The type of the parameter at `position`, starting at 0.
+    Param {
+        /// The position of the parameter.
+        position: usize,
+        /// The type name.
+        name: String,
+    },
+    /// The type of the result at `position`, starting at 0.
+    Return {
+        /// The position of the result.
+        position: usize,
+        /// The type name.
+        name: String,
+    },
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Param { position, name } => {
+                write!(f, "unknown type `{name}` of parameter {position}")
+            }
+            Self::Return { position, name } => {
+                write!(f, "unknown type `{name}` of result {position}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TypeError {}
+
+impl TypeError {
+    /// The span of the type name in the signature of a function whose parameters are the items
+    /// of `params` and whose results are those of `returns`.
+    fn span(&self, params: &SpanTree, returns: &SpanTree, param_type: usize) -> Span {
+        match *self {
+            Self::Param { position, .. } => params.item(position).item(param_type).span,
+            Self::Return { position, .. } => returns.item(position).span,
+        }
+    }
+}
+
+impl FuncType {
+    /// Resolve the type names of the parameters and results of a function.
+    fn resolve<'a>(
+        params: impl Iterator<Item = &'a String>,
+        returns: &[String],
+    ) -> Result<FuncType, TypeError> {
+        let params = params
+            .enumerate()
+            .map(|(position, name)| {
+                resolve_type(name).ok_or_else(|| TypeError::Param {
+                    position,
+                    name: name.clone(),
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        let returns = returns
+            .iter()
+            .enumerate()
+            .map(|(position, name)| {
+                resolve_type(name).ok_or_else(|| TypeError::Return {
+                    position,
+                    name: name.clone(),
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(FuncType { params, returns })
+    }
+
+    /// The signature of a function of this type in the calling convention `call_conv`.
+    pub fn signature(&self, call_conv: CallConv) -> Signature {
+        let mut sig = Signature::new(call_conv);
+        sig.params
+            .extend(self.params.iter().map(|&param| AbiParam::new(param)));
+        sig.returns
+            .extend(self.returns.iter().map(|&ret| AbiParam::new(ret)));
+        sig
+    }
+}
+
+impl Function {
+    /// The types of the parameters and results.
+    pub fn func_type(&self) -> Result<FuncType, TypeError> {
+        FuncType::resolve(self.params.iter().map(|(_, ty)| ty), &self.returns)
+    }
+
+    /// The signature of the function in the calling convention `call_conv`.
+    pub fn signature(&self, call_conv: CallConv) -> Result<Signature, TypeError> {
+        Ok(self.func_type()?.signature(call_conv))
+    }
+
+    /// `err` as an error at the type name it's about.
+    pub fn type_error(&self, err: &TypeError) -> Diagnostic {
+        // The parameters are `(name type)`.
+        let span = err.span(self.spans.item(2), self.spans.item(3), 1);
+        Diagnostic::error(span, format!("in function `{}`: {err}", self.name))
+    }
+}
+
+impl Extern {
+    /// The types of the parameters and results.
+    pub fn func_type(&self) -> Result<FuncType, TypeError> {
+        FuncType::resolve(self.params.iter(), &self.returns)
+    }
+
+    /// The signature of the host function in the calling convention `call_conv`.
+    pub fn signature(&self, call_conv: CallConv) -> Result<Signature, TypeError> {
+        Ok(self.func_type()?.signature(call_conv))
+    }
+
+    /// `err` as an error at the type name it's about.
+    pub fn type_error(&self, err: &TypeError) -> Diagnostic {
+        // The parameters are `(type)`.
+        let span = err.span(self.spans.item(2), self.spans.item(3), 0);
+        Diagnostic::error(span, format!("in extern `{}`: {err}", self.name))
+    }
+}
+
+/// Resolve the types of the parameters and results of every function of `module`, in order,
+/// checking those of the host functions too. Every function or host function with an unknown
+/// type has an error, and the errors are in the order of the source text.
+pub fn check(module: &Module) -> Result<Vec<FuncType>, Vec<Diagnostic>> {
+    let mut errors: Vec<Diagnostic> = module
+        .externs
+        .iter()
+        .filter_map(|host| host.func_type().err().map(|e| host.type_error(&e)))
+        .collect();
+    let mut types = Vec::new();
+    for func in &module.functions {
+        match func.func_type() {
+            Ok(ty) => types.push(ty),
+            Err(e) => errors.push(func.type_error(&e)),
+        }
+    }
+    if errors.is_empty() {
+        Ok(types)
+    } else {
+        errors.sort_by_key(|error| error.span.start);
+        Err(errors)
+    }
+}
+
+/// The Cranelift type named `name`: a scalar integer or float type such as `i32` or `f64`, or a
+/// vector of them such as `i32x4` or `f64x2`.
+pub fn resolve_type(name: &str) -> Option<Type> {
+    let (lane, lanes) = match name.split_once('x') {
+        Some((lane, lanes)) => {
+            // Only a power of two of lanes, spelled without leading zeros or a sign, is valid.
+            if lanes.starts_with(['0', '+']) {
+                return None;
+            }
+            (lane, Some(lanes.parse::<u16>().ok()?))
+        }
+        None => (name, None),
+    };
+    let lane = match lane {
+        "i8" => types::I8,
+        "i16" => types::I16,
+        "i32" => types::I32,
+        "i64" => types::I64,
+        "i128" => types::I128,
+        "f32" => types::F32,
+        "f64" => types::F64,
+        _ => return None,
+    };
+    match lanes {
+        Some(lanes) if lanes >= 2 => lane.by(lanes.into()),
+        Some(_) => None,
+        None => Some(lane),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn type_names() {
+        for (name, ty) in [
+            ("i8", types::I8),
+            ("i16", types::I16),
+            ("i32", types::I32),
+            ("i64", types::I64),
+            ("i128", types::I128),
+            ("f32", types::F32),
+            ("f64", types::F64),
+            ("i8x16", types::I8X16),
+            ("i32x4", types::I32X4),
+            ("i64x2", types::I64X2),
+            ("f32x4", types::F32X4),
+            ("f64x2", types::F64X2),
+            ("i8x32", types::I8X32),
+        ] {
+            assert_eq!(resolve_type(name), Some(ty), "{name}");
+            assert_eq!(ty.to_string(), name);
+        }
+        for name in [
+            "",
+            "i1",
+            "u32",
+            "int",
+            "I32",
+            "b1",
+            "r64",
+            "i32x",
+            "i32x3",
+            "i32x1",
+            "i32x0",
+            "i32x04",
+            "i32x+4",
+            "xi32",
+            "i32x4x2",
+            "f64x65536",
+        ] {
+            assert_eq!(resolve_type(name), None, "{name}");
+        }
+    }
+
+    #[test]
+    fn signatures() {
+        let module =
+            Module::parse("(func f ((x i32) (v f32x4)) (i64) x) (func g ((a i8) (b u8)) () a)")
+                .unwrap();
+        let sig = module.functions[0].signature(CallConv::SystemV).unwrap();
+        let mut expected = Signature::new(CallConv::SystemV);
+        expected.params.push(AbiParam::new(types::I32));
+        expected.params.push(AbiParam::new(types::F32X4));
+        expected.returns.push(AbiParam::new(types::I64));
+        assert_eq!(sig, expected);
+
+        let err = module.functions[1]
+            .signature(CallConv::SystemV)
+            .unwrap_err();
+        assert_eq!(
+            err,
+            TypeError::Param {
+                position: 1,
+                name: "u8".to_string(),
+            }
+        );
+        assert_eq!(err.to_string(), "unknown type `u8` of parameter 1");
+
+        let source = "(func h () (i32 bool) 1)";
+        let errors = check(&Module::parse(source).unwrap()).unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "in function `h`: unknown type `bool` of result 1"
+        );
+        assert_eq!(&source[errors[0].span.start..errors[0].span.end], "bool");
+
+        let module =
+            Module::parse("(extern e ((i32) (i8x16)) (f64)) (extern u ((u8)) ())").unwrap();
+        let sig = module.externs[0].signature(CallConv::SystemV).unwrap();
+        let mut expected = Signature::new(CallConv::SystemV);
+        expected.params.push(AbiParam::new(types::I32));
+        expected.params.push(AbiParam::new(types::I8X16));
+        expected.returns.push(AbiParam::new(types::F64));
+        assert_eq!(sig, expected);
+        let errors = check(&module).unwrap_err();
+        assert_eq!(
+            errors[0].message,
+            "in extern `u`: unknown type `u8` of parameter 0"
+        );
+        assert_eq!(errors[0].span.start, 45);
+
+        // Every function is checked.
+        let module = Module::parse(
+            "(func f ((x u8)) (i64) 1) (func g () (i64) 1) (func h () (bool) 1) \
+             (extern e () (u16))",
+        )
+        .unwrap();
+        let messages: Vec<String> = check(&module)
+            .unwrap_err()
+            .iter()
+            .map(|e| e.message.clone())
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "in function `f`: unknown type `u8` of parameter 0",
+                "in function `h`: unknown type `bool` of result 0",
+                "in extern `e`: unknown type `u16` of result 0",
+            ]
+        );
+    }
+}
@@ -0,0 +1,110 @@
+//! Measure the cost of releasing the memory of a reused `Context` after each
+//! compilation, compared to keeping it for the next function, and the gain of
+//! also reusing the memory of the backend with a `CompilationSession`; the
+//! benchmarking is feature-gated on `x86` since it compiles for that backend.
+
+#[cfg(feature = "x86")]
+mod x86 {
+    use cranelift_codegen::cursor::{Cursor, FuncCursor};
+    use cranelift_codegen::ir::{types, AbiParam, Function, InstBuilder, Signature};
+    use cranelift_codegen::isa::{self, CallConv, TargetIsa};
+    use cranelift_codegen::settings::{self, Flags};
+    use cranelift_codegen::{CompilationSession, Context};
+    use criterion::{criterion_group, BenchmarkId, Criterion};
+    use std::str::FromStr;
+    use target_lexicon::Triple;
+
+    /// Build a function summing its parameter with itself `size` times, in
+    /// straight-line code split into blocks of ten instructions.
+    fn function(size: usize) -> Function {
+        let mut sig = Signature::new(CallConv::SystemV);
+        sig.params.push(AbiParam::new(types::I64));
+        sig.returns.push(AbiParam::new(types::I64));
+        let mut func = Function::new();
+        func.signature = sig;
+        let mut pos = FuncCursor::new(&mut func);
+        let entry = pos.func.dfg.make_block();
+        pos.insert_block(entry);
+        let mut sum = pos.func.dfg.append_block_param(entry, types::I64);
+        for i in 0..size {
+            if i % 10 == 9 {
+                let block = pos.func.dfg.make_block();
+                let param = pos.func.dfg.append_block_param(block, types::I64);
+                pos.ins().jump(block, &[sum]);
+                pos.insert_block(block);
+                sum = param;
+            }
+            sum = pos.ins().iadd(sum, sum);
+        }
+        pos.ins().return_(&[sum]);
+        func
+    }
+
+    fn compile(ctx: &mut Context, isa: &dyn TargetIsa, func: &Function) {
+        ctx.func = func.clone();
+        ctx.compile(isa, &mut Default::default()).unwrap();
+        ctx.clear();
+    }
+
+    fn compile_in_session(
+        session: &mut CompilationSession,
+        ctx: &mut Context,
+        isa: &dyn TargetIsa,
+        func: &Function,
+    ) {
+        ctx.func = func.clone();
+        session.compile(ctx, isa, &mut Default::default()).unwrap();
+        ctx.clear();
+    }
+
+    // Define the benchmarks.
+    fn context_reuse_benchmarks(c: &mut Criterion) {
+        let isa = isa::lookup(Triple::from_str("x86_64").unwrap())
+            .unwrap()
+            .finish(Flags::new(settings::builder()))
+            .unwrap();
+        let mut group = c.benchmark_group("context reuse");
+        let large = function(10_000);
+        for size in [10, 1000] {
+            let small = function(size);
+
+            // Compile a large function once, and then small ones with the
+            // same context.
+            group.bench_function(BenchmarkId::new("clear", size), |b| {
+                let mut ctx = Context::new();
+                compile(&mut ctx, &*isa, &large);
+                b.iter(|| compile(&mut ctx, &*isa, &small));
+            });
+            group.bench_function(BenchmarkId::new("clear and shrink", size), |b| {
+                let mut ctx = Context::new();
+                ctx.set_shrink_threshold(Some(0));
+                compile(&mut ctx, &*isa, &large);
+                b.iter(|| compile(&mut ctx, &*isa, &small));
+            });
+            group.bench_function(BenchmarkId::new("session", size), |b| {
+                let mut ctx = Context::new();
+                let mut session = CompilationSession::new();
+                compile_in_session(&mut session, &mut ctx, &*isa, &large);
+                b.iter(|| compile_in_session(&mut session, &mut ctx, &*isa, &small));
+            });
+        }
+    }
+    criterion_group!(benches, context_reuse_benchmarks);
+
+    /// Using an inner module to feature-gate the benchmarks means that we must
+    /// manually specify how to run the benchmarks (see `criterion_main!`).
+    pub fn run_benchmarks() {
+        benches();
+        Criterion::default().configure_from_args().final_summary();
+    }
+}
+
+fn main() {
+    #[cfg(feature = "x86")]
+    x86::run_benchmarks();
+
+    #[cfg(not(feature = "x86"))]
+    println!(
+        "Unable to run the context-reuse benchmark; the `x86` feature must be enabled in Cargo.",
+    );
+}
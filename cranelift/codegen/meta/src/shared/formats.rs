@@ -23,6 +23,7 @@ pub(crate) struct Formats {
     pub(crate) load_no_offset: Rc<InstructionFormat>,
     pub(crate) multiary: Rc<InstructionFormat>,
     pub(crate) nullary: Rc<InstructionFormat>,
+    pub(crate) patchable_jump: Rc<InstructionFormat>,
     pub(crate) shuffle: Rc<InstructionFormat>,
     pub(crate) stack_load: Rc<InstructionFormat>,
     pub(crate) stack_store: Rc<InstructionFormat>,
@@ -115,6 +116,8 @@ impl Formats {
 
             brif: Builder::new("Brif").value().block().block().build(),
 
+            patchable_jump: Builder::new("PatchableJump").block().block().build(),
+
             branch_table: Builder::new("BranchTable")
                 .value()
                 .imm(&entities.jump_table)
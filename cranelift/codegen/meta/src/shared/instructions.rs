@@ -58,6 +58,31 @@ fn define_control_flow(
         .branches(),
     );
 
+    ig.push(
+        Inst::new(
+            "patchable_jump",
+            r#"
+        Jump whose destination can be switched at runtime.
+
+        Jump to ``block_initial`` until the jump is patched to go to
+        ``block_alternate`` instead, and back. The jump is always emitted as a
+        single direct jump instruction whose encoding of the destination is a
+        naturally aligned 32-bit word. That word can be atomically rewritten
+        to select either destination while other threads execute the jump,
+        and its location is reported as a patch point of the compiled code,
+        along with the offsets of both destinations.
+        "#,
+            &formats.patchable_jump,
+        )
+        .operands_in(vec![
+            Operand::new("block_initial", &entities.block_call)
+                .with_doc("Destination until the jump is patched"),
+            Operand::new("block_alternate", &entities.block_call)
+                .with_doc("Destination the jump can be patched to"),
+        ])
+        .branches(),
+    );
+
     {
         let _i32 = &TypeVar::new(
             "i32",
@@ -232,6 +257,32 @@ fn define_control_flow(
         .call(),
     );
 
+    ig.push(
+        Inst::new(
+            "patchable_call",
+            r#"
+        Direct function call whose target can be changed at runtime.
+
+        This behaves like `call`, but is always emitted as a single direct
+        call instruction whose encoding of the target is a naturally aligned
+        32-bit word. That word can be atomically rewritten to call another
+        function with the same signature while other threads execute the
+        call, and its location is reported as a patch point of the compiled
+        code.
+        "#,
+            &formats.call,
+        )
+        .operands_in(vec![
+            Operand::new("FN", &entities.func_ref)
+                .with_doc("function to call initially, declared by `function`"),
+            Operand::new("args", &entities.varargs).with_doc("call arguments"),
+        ])
+        .operands_out(vec![
+            Operand::new("rvals", &entities.varargs).with_doc("return values")
+        ])
+        .call(),
+    );
+
     ig.push(
         Inst::new(
             "call_indirect",
@@ -616,6 +667,55 @@ fn define_simd_arithmetic(
     );
 }
 
+#[inline(never)]
+fn define_frontend_extensions(
+    ig: &mut InstructionGroupBuilder,
+    formats: &Formats,
+    entities: &EntityRefs,
+) {
+    let Any = &TypeVar::new(
+        "Any",
+        "Any integer, float, or reference scalar or vector type",
+        TypeSetBuilder::new()
+            .ints(Interval::All)
+            .floats(Interval::All)
+            .refs(Interval::All)
+            .simd_lanes(Interval::All)
+            .includes_scalars(true)
+            .build(),
+    );
+
+    for n in 0..4 {
+        ig.push(
+            Inst::new(
+                format!("ext{}", n),
+                r#"
+        Frontend extension operation.
+
+        Reserved for frontends to carry their own operations through the IR
+        until a late pass replaces them with real instructions, see
+        `Function::replace_ext_insts`. Cranelift treats it as an opaque
+        operation that may read and write memory and have other side
+        effects, so it is never moved, merged, or removed. It must not
+        survive to code generation, where it is rejected.
+
+        The meaning of the arguments and of the result, whose type is
+        chosen by the frontend, is up to the frontend.
+        "#
+                .to_string(),
+                &formats.multiary,
+            )
+            .operands_in(vec![
+                Operand::new("args", &entities.varargs).with_doc("Frontend-defined payload")
+            ])
+            .operands_out(vec![Operand::new("a", Any)])
+            .other_side_effects()
+            .can_load()
+            .can_store(),
+        );
+    }
+}
+
 #[allow(clippy::many_single_char_names)]
 pub(crate) fn define(
     all_instructions: &mut AllInstructions,
@@ -628,6 +728,7 @@ pub(crate) fn define(
     define_control_flow(&mut ig, formats, imm, entities);
     define_simd_lane_access(&mut ig, formats, imm, entities);
     define_simd_arithmetic(&mut ig, formats, imm, entities);
+    define_frontend_extensions(&mut ig, formats, entities);
 
     // Operand kind shorthands.
     let i8: &TypeVar = &ValueType::from(LaneType::from(types::Int::I8)).into();
@@ -2185,6 +2286,106 @@ pub(crate) fn define(
         .side_effects_idempotent(),
     );
 
+    ig.push(
+        Inst::new(
+            "sadd_overflow_trap",
+            r#"
+        Signed addition of x and y, trapping if the result over- or underflows.
+
+        Accepts 32 or 64-bit integers, and does not support vector types.
+        "#,
+            &formats.int_add_trap,
+        )
+        .operands_in(vec![
+            Operand::new("x", i32_64),
+            Operand::new("y", i32_64),
+            Operand::new("code", &imm.trapcode),
+        ])
+        .operands_out(vec![Operand::new("a", i32_64)])
+        .can_trap()
+        .side_effects_idempotent(),
+    );
+
+    ig.push(
+        Inst::new(
+            "usub_overflow_trap",
+            r#"
+        Unsigned subtraction of y from x, trapping if the result underflows.
+
+        Accepts 32 or 64-bit integers, and does not support vector types.
+        "#,
+            &formats.int_add_trap,
+        )
+        .operands_in(vec![
+            Operand::new("x", i32_64),
+            Operand::new("y", i32_64),
+            Operand::new("code", &imm.trapcode),
+        ])
+        .operands_out(vec![Operand::new("a", i32_64)])
+        .can_trap()
+        .side_effects_idempotent(),
+    );
+
+    ig.push(
+        Inst::new(
+            "ssub_overflow_trap",
+            r#"
+        Signed subtraction of y from x, trapping if the result over- or underflows.
+
+        Accepts 32 or 64-bit integers, and does not support vector types.
+        "#,
+            &formats.int_add_trap,
+        )
+        .operands_in(vec![
+            Operand::new("x", i32_64),
+            Operand::new("y", i32_64),
+            Operand::new("code", &imm.trapcode),
+        ])
+        .operands_out(vec![Operand::new("a", i32_64)])
+        .can_trap()
+        .side_effects_idempotent(),
+    );
+
+    ig.push(
+        Inst::new(
+            "umul_overflow_trap",
+            r#"
+        Unsigned multiplication of x and y, trapping if the result overflows.
+
+        Accepts 32 or 64-bit integers, and does not support vector types.
+        "#,
+            &formats.int_add_trap,
+        )
+        .operands_in(vec![
+            Operand::new("x", i32_64),
+            Operand::new("y", i32_64),
+            Operand::new("code", &imm.trapcode),
+        ])
+        .operands_out(vec![Operand::new("a", i32_64)])
+        .can_trap()
+        .side_effects_idempotent(),
+    );
+
+    ig.push(
+        Inst::new(
+            "smul_overflow_trap",
+            r#"
+        Signed multiplication of x and y, trapping if the result over- or underflows.
+
+        Accepts 32 or 64-bit integers, and does not support vector types.
+        "#,
+            &formats.int_add_trap,
+        )
+        .operands_in(vec![
+            Operand::new("x", i32_64),
+            Operand::new("y", i32_64),
+            Operand::new("code", &imm.trapcode),
+        ])
+        .operands_out(vec![Operand::new("a", i32_64)])
+        .can_trap()
+        .side_effects_idempotent(),
+    );
+
     ig.push(
         Inst::new(
             "isub_bin",
@@ -3713,6 +3914,12 @@ pub(crate) fn define(
         8, 16, 32 or 64 bits, even on a 32-bit target.  The type of the returned value is the
         same as the type of `x`.  This operation is sequentially consistent and creates
         happens-before edges that order normal (non-atomic) loads and stores.
+
+        `MemFlags` have the same meaning as for normal loads and stores, except that the
+        address must be naturally aligned for the access size whether or not `aligned` is set.
+        Targets whose atomic accesses can't byte-swap, like x86_64 and aarch64, reject an
+        explicitly non-native byte order with an "unsupported" error instead of ignoring it.
+        The same applies to all atomic memory operations.
         "#,
             &formats.atomic_rmw,
         )
@@ -3741,7 +3948,7 @@ pub(crate) fn define(
         integer type of 8, 16, 32 or 64 bits, even on a 32-bit target.  The type of the returned
         value is the same as the type of `x` and `e`.  This operation is sequentially
         consistent and creates happens-before edges that order normal (non-atomic) loads and
-        stores.
+        stores.  See `atomic_rmw` for the meaning of `MemFlags`.
         "#,
             &formats.atomic_cas,
         )
@@ -3768,7 +3975,7 @@ pub(crate) fn define(
         This is a polymorphic instruction that can load any value type which has a memory
         representation.  It should only be used for integer types with 8, 16, 32 or 64 bits.
         This operation is sequentially consistent and creates happens-before edges that order
-        normal (non-atomic) loads and stores.
+        normal (non-atomic) loads and stores.  See `atomic_rmw` for the meaning of `MemFlags`.
         "#,
             &formats.load_no_offset,
         )
@@ -3792,7 +3999,7 @@ pub(crate) fn define(
         This is a polymorphic instruction that can store any value type with a memory
         representation.  It should only be used for integer types with 8, 16, 32 or 64 bits.
         This operation is sequentially consistent and creates happens-before edges that order
-        normal (non-atomic) loads and stores.
+        normal (non-atomic) loads and stores.  See `atomic_rmw` for the meaning of `MemFlags`.
         "#,
             &formats.store_no_offset,
         )
@@ -53,6 +53,64 @@ pub(crate) fn define() -> SettingGroup {
         true,
     );
 
+    settings.add_bool(
+        "enable_pressure_scheduling",
+        "Reorder instructions within blocks to reduce register pressure.",
+        r#"
+            This enables a list scheduling pass over the instructions of each block, run just
+            before lowering, which moves instructions closer to the uses of their results to
+            reduce the number of values live at once, and so the number of spills. Instructions
+            with side effects keep their order.
+        "#,
+        false,
+    );
+
+    settings.add_bool(
+        "enable_loop_unrolling",
+        "Unroll innermost loops counted by an induction variable.",
+        r#"
+            This enables a pass which fully unrolls loops with a constant trip count whose
+            unrolled body fits within `loop_unroll_budget` instructions, and partially unrolls
+            the others by `loop_partial_unroll_factor`. Only effective when `opt_level` is
+            `speed` or `speed_and_size`.
+        "#,
+        false,
+    );
+
+    settings.add_num(
+        "loop_unroll_budget",
+        "The maximum number of instructions of a fully unrolled loop.",
+        r#"
+            A loop with a constant trip count is only fully unrolled if its trip count times
+            the number of instructions of its body is at most this number.
+        "#,
+        64,
+    );
+
+    settings.add_num(
+        "loop_partial_unroll_factor",
+        "The number of iterations of loops run at once by partial unrolling.",
+        r#"
+            Loops which aren't fully unrolled get a main loop running this many iterations
+            at once, followed by the original loop running the remaining ones. Only 2 and 4
+            enable partial unrolling; the default of 0 disables it.
+        "#,
+        0,
+    );
+
+    settings.add_bool(
+        "enable_redundant_check_elimination",
+        "Remove comparisons, branches and checks whose outcome is known.",
+        r#"
+            This enables a pass which uses a value range and known-bits analysis to fold
+            integer comparisons with a known result, to turn conditional branches and traps
+            which always go the same way into jumps or nothing, and to remove extensions
+            restoring a value a reduction truncated. Only effective when `opt_level` is `speed`
+            or `speed_and_size`.
+        "#,
+        false,
+    );
+
     settings.add_bool(
         "enable_verifier",
         "Run the Cranelift IR verifier at strategic times during compilation.",
@@ -202,6 +260,11 @@ pub(crate) fn define() -> SettingGroup {
             side tables or metadata (like `.eh_frame` sections). Many sampling
             profilers and similar tools walk frame pointers to capture stacks.
             Enabling this option will play nice with those tools.
+
+            When enabled, every function maintains a frame record holding the
+            caller's frame pointer and the return address, including leaf
+            functions and functions that make tail calls, so that the frame
+            pointer chain is never broken by Cranelift-compiled code.
         "#,
         false,
     );
@@ -0,0 +1,66 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// A target without a standard library. Building for the host alone doesn't prove much: as soon as
+// any crate in the dependency graph links std, its inherent methods on primitive types (e.g.
+// `f32::sqrt`) become available to `no_std` crates too.
+const BARE_METAL_TARGET: &str = "x86_64-unknown-none";
+
+fn main() {
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    let guest = Path::new(env!("CARGO_MANIFEST_DIR")).join("guest");
+
+    println!("cargo:rerun-if-changed=./guest/src");
+    println!("cargo:rerun-if-changed=./guest/Cargo.toml");
+    println!("cargo:rerun-if-changed=../src");
+    println!("cargo:rerun-if-changed=../Cargo.toml");
+
+    // Build the guest in its own workspace and target directory, so that the features of
+    // `cranelift-codegen` aren't unified with those of the rest of the workspace.
+    let cargo = |args: &[&str]| {
+        let status = Command::new(env::var_os("CARGO").unwrap())
+            .args(args)
+            .arg("--manifest-path")
+            .arg(guest.join("Cargo.toml"))
+            .env("CARGO_TARGET_DIR", &out_dir)
+            .env_remove("CARGO_ENCODED_RUSTFLAGS")
+            .status()
+            .unwrap();
+        assert!(status.success(), "failed to build the no_std guest");
+    };
+
+    cargo(&["build", "--bin", "cranelift-codegen-no-std-guest"]);
+    println!(
+        "cargo:rustc-env=NO_STD_GUEST={}",
+        out_dir
+            .join("debug")
+            .join(format!(
+                "cranelift-codegen-no-std-guest{}",
+                env::consts::EXE_SUFFIX
+            ))
+            .display()
+    );
+
+    if bare_metal_target_installed() {
+        cargo(&["build", "--lib", "--target", BARE_METAL_TARGET]);
+    } else {
+        println!(
+            "cargo:warning=skipping the {BARE_METAL_TARGET} build of the no_std guest; \
+             install it with `rustup target add {BARE_METAL_TARGET}`"
+        );
+    }
+}
+
+fn bare_metal_target_installed() -> bool {
+    let rustc = env::var_os("RUSTC").unwrap();
+    let output = Command::new(rustc)
+        .args(["--print", "sysroot"])
+        .output()
+        .unwrap();
+    let sysroot = String::from_utf8(output.stdout).unwrap();
+    Path::new(sysroot.trim())
+        .join("lib/rustlib")
+        .join(BARE_METAL_TARGET)
+        .exists()
+}
@@ -0,0 +1,72 @@
+//! Compiles a function with `cranelift-codegen` built without its `std` feature. This crate is
+//! `no_std` itself, so it only links if `cranelift-codegen` doesn't depend on std either.
+
+#![no_std]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use cranelift_codegen::cursor::{Cursor, FuncCursor};
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{
+    types, AbiParam, ExtFuncData, ExternalName, Function, InstBuilder, Signature, UserExternalName,
+    UserFuncName,
+};
+use cranelift_codegen::isa::{self, CallConv};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_codegen::{CodegenError, Context};
+use target_lexicon::triple;
+
+/// Build a recursive factorial function: `fact(n) = if n <= 1 { 1 } else { n * fact(n - 1) }`.
+pub fn factorial() -> Function {
+    let mut sig = Signature::new(CallConv::SystemV);
+    sig.params.push(AbiParam::new(types::I32));
+    sig.returns.push(AbiParam::new(types::I32));
+    let mut func = Function::with_name_signature(UserFuncName::user(0, 0), sig.clone());
+    let sig_ref = func.import_signature(sig);
+    let name = func.declare_imported_user_function(UserExternalName::new(0, 0));
+    let fact = func.import_function(ExtFuncData {
+        name: ExternalName::user(name),
+        signature: sig_ref,
+        colocated: true,
+    });
+
+    let entry = func.dfg.make_block();
+    let n = func.dfg.append_block_param(entry, types::I32);
+    let base = func.dfg.make_block();
+    let recurse = func.dfg.make_block();
+
+    let mut pos = FuncCursor::new(&mut func);
+    pos.insert_block(entry);
+    let is_base = pos.ins().icmp_imm(IntCC::UnsignedLessThanOrEqual, n, 1);
+    pos.ins().brif(is_base, base, &[], recurse, &[]);
+
+    pos.insert_block(base);
+    let one = pos.ins().iconst(types::I32, 1);
+    pos.ins().return_(&[one]);
+
+    pos.insert_block(recurse);
+    let n_minus_1 = pos.ins().iadd_imm(n, -1);
+    let call = pos.ins().call(fact, &[n_minus_1]);
+    let fact_n_minus_1 = pos.func.dfg.first_result(call);
+    let result = pos.ins().imul(n, fact_n_minus_1);
+    pos.ins().return_(&[result]);
+
+    func
+}
+
+/// Compile [factorial] for x86_64 and return the emitted machine code.
+pub fn compile_factorial() -> Result<Vec<u8>, CodegenError> {
+    let mut flags = settings::builder();
+    flags.set("opt_level", "speed").unwrap();
+    let isa = isa::lookup(triple!("x86_64"))
+        .unwrap()
+        .finish(settings::Flags::new(flags))?;
+
+    let mut context = Context::for_function(factorial());
+    let mut code = Vec::new();
+    context
+        .compile_and_emit(&*isa, &mut code, &mut Default::default())
+        .map_err(|err| err.inner)?;
+    Ok(code)
+}
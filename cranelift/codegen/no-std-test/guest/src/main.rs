@@ -0,0 +1,8 @@
+//! Runs the `no_std` library as a regular program so that the emitted code can be checked from the
+//! test suite; see `../tests/no_std.rs`.
+
+fn main() {
+    let code = cranelift_codegen_no_std_guest::compile_factorial().expect("compilation failed");
+    assert!(!code.is_empty(), "no code emitted");
+    println!("{}", code.len());
+}
@@ -0,0 +1,4 @@
+//! Checks that `cranelift-codegen` can compile functions in a `no_std` environment.
+//!
+//! The actual `no_std` code lives in the `guest` crate, which `build.rs` builds in a workspace of
+//! its own; `tests/no_std.rs` runs it.
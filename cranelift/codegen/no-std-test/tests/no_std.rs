@@ -0,0 +1,17 @@
+use std::process::Command;
+
+#[test]
+fn compile_factorial() {
+    let output = Command::new(env!("NO_STD_GUEST")).output().unwrap();
+    assert!(
+        output.status.success(),
+        "guest failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let len: usize = String::from_utf8(output.stdout)
+        .unwrap()
+        .trim()
+        .parse()
+        .unwrap();
+    assert!(len > 0);
+}
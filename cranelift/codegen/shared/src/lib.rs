@@ -18,6 +18,7 @@
         clippy::use_self
     )
 )]
+#![no_std]
 
 pub mod constant_hash;
 pub mod constants;
@@ -50,25 +50,30 @@
 //! location, or "store-to-load forwarding" if the value came from an
 //! earlier store to the same location.
 //!
-//! In theory we could also do *dead-store elimination*, where if a
-//! store overwrites a key in the table, *and* if no other load/store
-//! to the abstract state category occurred, *and* no other trapping
-//! instruction occurred (at which point we need an up-to-date memory
-//! state because post-trap-termination memory state can be observed),
-//! *and* we can prove the original store could not have trapped, then
-//! we can eliminate the original store. Because this is so complex,
-//! and the conditions for doing it correctly when post-trap state
-//! must be correct likely reduce the potential benefit, we don't yet
-//! do this.
+//! Forwarding requires the `MemFlags` of both accesses to be
+//! identical as well, so that e.g. a big-endian load never sees the
+//! value of a little-endian store, and atomic accesses are never part
+//! of the table.
+//!
+//! We also do *dead-store elimination*, within a block: a store is
+//! removed if a later store in the same block overwrites exactly the
+//! same location (same address, offset, type, opcode and flags), and
+//! nothing in between could have observed the earlier store. Loads of the same category of abstract state observe it,
+//! of course, but so does any instruction which may trap, or be a
+//! call, a fence or an atomic access: after a trap, the memory state
+//! at the trapping instruction can be observed. A store which may
+//! trap can only be removed if no store was executed between it and
+//! the store overwriting it, which then traps in its place.
 
 use crate::{
     cursor::{Cursor, FuncCursor},
     dominator_tree::DominatorTree,
     fx::{FxHashMap, FxHashSet},
     inst_predicates::{
-        has_memory_fence_semantics, inst_addr_offset_type, inst_store_data, visit_block_succs,
+        has_memory_fence_semantics, has_side_effect, inst_addr_offset_type, inst_store_data,
+        visit_block_succs,
     },
-    ir::{immediates::Offset32, Block, Function, Inst, Opcode, Type, Value},
+    ir::{immediates::Offset32, Block, Function, Inst, MemFlags, Opcode, Type, Value},
     trace,
 };
 use cranelift_entity::{packed_option::PackedOption, EntityRef};
@@ -93,14 +98,11 @@ impl LastStores {
             self.other = inst.into();
         } else if opcode.can_store() {
             if let Some(memflags) = func.dfg.insts[inst].memflags() {
-                if memflags.heap() {
-                    self.heap = inst.into();
-                } else if memflags.table() {
-                    self.table = inst.into();
-                } else if memflags.vmctx() {
-                    self.vmctx = inst.into();
-                } else {
-                    self.other = inst.into();
+                match Category::of(memflags) {
+                    Category::Heap => self.heap = inst.into(),
+                    Category::Table => self.table = inst.into(),
+                    Category::Vmctx => self.vmctx = inst.into(),
+                    Category::Other => self.other = inst.into(),
                 }
             } else {
                 self.heap = inst.into();
@@ -113,14 +115,11 @@ impl LastStores {
 
     fn get_last_store(&self, func: &Function, inst: Inst) -> PackedOption<Inst> {
         if let Some(memflags) = func.dfg.insts[inst].memflags() {
-            if memflags.heap() {
-                self.heap
-            } else if memflags.table() {
-                self.table
-            } else if memflags.vmctx() {
-                self.vmctx
-            } else {
-                self.other
+            match Category::of(memflags) {
+                Category::Heap => self.heap,
+                Category::Table => self.table,
+                Category::Vmctx => self.vmctx,
+                Category::Other => self.other,
             }
         } else if func.dfg.insts[inst].opcode().can_load()
             || func.dfg.insts[inst].opcode().can_store()
@@ -149,6 +148,29 @@ impl LastStores {
     }
 }
 
+/// One of the disjoint categories of abstract state.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Category {
+    Heap,
+    Table,
+    Vmctx,
+    Other,
+}
+
+impl Category {
+    fn of(memflags: MemFlags) -> Category {
+        if memflags.heap() {
+            Category::Heap
+        } else if memflags.table() {
+            Category::Table
+        } else if memflags.vmctx() {
+            Category::Vmctx
+        } else {
+            Category::Other
+        }
+    }
+}
+
 /// A key identifying a unique memory location.
 ///
 /// For the result of a load to be equivalent to the result of another
@@ -157,8 +179,9 @@ impl LastStores {
 /// instruction to touch the disjoint category of abstract state we're
 /// accessing); (ii) the address must be the same (here ensured by
 /// having the same SSA value, which doesn't change after computed);
-/// (iii) the offset must be the same; and (iv) the accessed type and
-/// extension mode (e.g., 8-to-32, signed) must be the same.
+/// (iii) the offset must be the same; (iv) the accessed type and
+/// extension mode (e.g., 8-to-32, signed) must be the same; and (v)
+/// the flags must be the same.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 struct MemoryLoc {
     last_store: PackedOption<Inst>,
@@ -177,6 +200,19 @@ struct MemoryLoc {
     /// in place of extending loads when we know the memory value, but
     /// we haven't yet done this.
     extending_opcode: Option<Opcode>,
+    flags: MemFlags,
+}
+
+/// A key identifying the location written by a store, for dead-store
+/// elimination: a store to the same key writes exactly the same bytes
+/// in the same way.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct StoreLoc {
+    address: Value,
+    offset: Offset32,
+    ty: Type,
+    opcode: Opcode,
+    flags: MemFlags,
 }
 
 /// An alias-analysis pass.
@@ -193,6 +229,14 @@ pub struct AliasAnalysis<'a> {
     ///
     /// We keep the defining inst around for quick dominance checks.
     mem_values: FxHashMap<MemoryLoc, (Inst, Value)>,
+
+    /// The stores of `dead_store_block` which nothing has observed
+    /// yet, and which are dead if overwritten by a store to the same
+    /// location.
+    unobserved_stores: FxHashMap<StoreLoc, Inst>,
+
+    /// The block of the instructions last processed.
+    dead_store_block: PackedOption<Block>,
 }
 
 impl<'a> AliasAnalysis<'a> {
@@ -203,6 +247,8 @@ impl<'a> AliasAnalysis<'a> {
             domtree,
             block_input: FxHashMap::default(),
             mem_values: FxHashMap::default(),
+            unobserved_stores: FxHashMap::default(),
+            dead_store_block: None.into(),
         };
 
         analysis.compute_block_input_states(func);
@@ -268,7 +314,9 @@ impl<'a> AliasAnalysis<'a> {
     /// within a block, and ideally in RPO or at least some domtree
     /// preorder for maximal reuse.
     ///
-    /// Returns `true` if instruction was removed.
+    /// Returns the value to replace the result of the instruction
+    /// with, if it is a redundant load. Earlier stores which the
+    /// instruction makes dead are removed from the layout.
     pub fn process_inst(
         &mut self,
         func: &mut Function,
@@ -282,12 +330,17 @@ impl<'a> AliasAnalysis<'a> {
             func.dfg.insts[inst],
         );
 
+        self.eliminate_dead_stores(func, inst);
+
         let replacing_value = if let Some((address, offset, ty)) = inst_addr_offset_type(func, inst)
         {
             let address = func.dfg.resolve_aliases(address);
             let opcode = func.dfg.insts[inst].opcode();
+            let flags = func.dfg.insts[inst].memflags().unwrap();
 
-            if opcode.can_store() {
+            if has_memory_fence_semantics(opcode) {
+                None
+            } else if opcode.can_store() {
                 let store_data = inst_store_data(func, inst).unwrap();
                 let store_data = func.dfg.resolve_aliases(store_data);
                 let mem_loc = MemoryLoc {
@@ -296,6 +349,7 @@ impl<'a> AliasAnalysis<'a> {
                     offset,
                     ty,
                     extending_opcode: get_ext_opcode(opcode),
+                    flags,
                 };
                 trace!(
                     "alias analysis: at inst{}: store with data v{} at loc {:?}",
@@ -315,6 +369,7 @@ impl<'a> AliasAnalysis<'a> {
                     offset,
                     ty,
                     extending_opcode: get_ext_opcode(opcode),
+                    flags,
                 };
                 trace!(
                     "alias analysis: at inst{}: load with last_store inst{} at loc {:?}",
@@ -378,6 +433,66 @@ impl<'a> AliasAnalysis<'a> {
         replacing_value
     }
 
+    /// Remove the earlier store to the same location as `inst`, if it
+    /// is a store and nothing observed the earlier one, and update the
+    /// set of unobserved stores. Meant to be invoked for every
+    /// instruction kept in the function, in program order within a
+    /// block; `process_inst` does so itself.
+    pub fn eliminate_dead_stores(&mut self, func: &mut Function, inst: Inst) {
+        let block = func.layout.inst_block(inst);
+        if self.dead_store_block.expand() != block {
+            self.unobserved_stores.clear();
+            self.dead_store_block = block.into();
+        }
+
+        let opcode = func.dfg.insts[inst].opcode();
+        let flags = func.dfg.insts[inst].memflags();
+        match (flags, inst_addr_offset_type(func, inst)) {
+            (Some(flags), Some((address, offset, ty)))
+                if opcode.can_store() && !has_memory_fence_semantics(opcode) =>
+            {
+                let loc = StoreLoc {
+                    address: func.dfg.resolve_aliases(address),
+                    offset,
+                    ty,
+                    opcode,
+                    flags,
+                };
+                if let Some(dead) = self.unobserved_stores.remove(&loc) {
+                    trace!(
+                        "alias analysis: at inst{}: store overwrites inst{}, which is removed",
+                        inst.index(),
+                        dead.index()
+                    );
+                    func.layout.remove_inst(dead);
+                }
+                if flags.notrap() {
+                    // A store which may trap can't be removed any more:
+                    // this store would then be visible when the store
+                    // overwriting it traps instead.
+                    self.unobserved_stores.retain(|loc, _| loc.flags.notrap());
+                } else {
+                    self.unobserved_stores.clear();
+                }
+                self.unobserved_stores.insert(loc, inst);
+            }
+            (Some(flags), Some(_)) if opcode.can_load() && !has_memory_fence_semantics(opcode) => {
+                if flags.notrap() {
+                    let category = Category::of(flags);
+                    self.unobserved_stores
+                        .retain(|loc, _| Category::of(loc.flags) != category);
+                } else {
+                    self.unobserved_stores.clear();
+                }
+            }
+            _ => {
+                if has_side_effect(func, inst) || opcode.can_load() {
+                    self.unobserved_stores.clear();
+                }
+            }
+        }
+    }
+
     /// Make a pass and update known-redundant loads to aliased
     /// values. We interleave the updates with the memory-location
     /// tracking because resolving some aliases may expose others
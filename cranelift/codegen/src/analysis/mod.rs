@@ -0,0 +1,8 @@
+//! Analyses of CLIF functions.
+//!
+//! These compute facts about a function on demand, for optimization passes and for external
+//! tools. They don't modify the function.
+
+mod value_facts;
+
+pub use self::value_facts::{KnownBits, ValueFacts, ValueRange};
@@ -0,0 +1,1580 @@
+//! Facts about the integer values of a function: their known bits and their ranges.
+//!
+//! [`ValueFacts`] derives what is known about a value from its definition (constants,
+//! extensions, masking and arithmetic) and from the conditions which hold wherever the value is
+//! used: a use may only be reached when a `brif` on the value, or on an `icmp` of the value,
+//! takes a certain edge, or when a `trapz` or `trapnz` testing it didn't trap.
+//!
+//! Facts are computed on demand, following definitions and dominating conditions up to a
+//! bounded depth, so that a query costs about the same no matter how large the function is.
+
+use crate::dominator_tree::DominatorTree;
+use crate::entity::SecondaryMap;
+use crate::ir::condcodes::{CondCode, IntCC};
+use crate::ir::{Block, Function, Inst, InstructionData, Opcode, Type, Value, ValueDef};
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+use smallvec::SmallVec;
+
+/// How many definitions deep facts are derived.
+const MAX_DEPTH: u32 = 6;
+
+/// How many dominating blocks are searched for conditions.
+const MAX_DOMINATORS: usize = 64;
+
+/// A mask of the low `bits` bits.
+fn mask(bits: u32) -> u128 {
+    u128::MAX.checked_shr(128 - bits).unwrap_or(0)
+}
+
+fn sign_bit(bits: u32) -> u128 {
+    1 << (bits - 1)
+}
+
+/// The low `bits` bits of `value` as a signed integer.
+fn to_signed(bits: u32, value: u128) -> i128 {
+    ((value << (128 - bits)) as i128) >> (128 - bits)
+}
+
+fn int_bits(ty: Type) -> u32 {
+    assert!(ty.is_int(), "no value facts for values of type {}", ty);
+    ty.bits()
+}
+
+/// A set of values of an integer type: the interval of values from [`start`](Self::start) to
+/// [`end`](Self::end), which wraps around from the largest unsigned value to zero if `start` is
+/// larger than `end`.
+///
+/// Values are represented by their bits, zero-extended to `u128`. A range is never empty.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ValueRange {
+    bits: u32,
+    start: u128,
+    /// The number of values in the range minus one.
+    extent: u128,
+}
+
+impl ValueRange {
+    /// All values of the integer type `ty`.
+    pub fn full(ty: Type) -> Self {
+        Self::full_bits(int_bits(ty))
+    }
+
+    /// The single value of the integer type `ty` whose bits are the low bits of `value`.
+    pub fn constant(ty: Type, value: u128) -> Self {
+        Self::constant_bits(int_bits(ty), value)
+    }
+
+    /// The values of the integer type `ty` from `min` to `max` when compared as unsigned.
+    pub fn unsigned(ty: Type, min: u128, max: u128) -> Self {
+        Self::unsigned_bits(int_bits(ty), min, max)
+    }
+
+    /// The values of the integer type `ty` from `min` to `max` when compared as signed.
+    pub fn signed(ty: Type, min: i128, max: i128) -> Self {
+        Self::signed_bits(int_bits(ty), min, max)
+    }
+
+    fn full_bits(bits: u32) -> Self {
+        Self {
+            bits,
+            start: 0,
+            extent: mask(bits),
+        }
+    }
+
+    fn constant_bits(bits: u32, value: u128) -> Self {
+        Self {
+            bits,
+            start: value & mask(bits),
+            extent: 0,
+        }
+    }
+
+    fn unsigned_bits(bits: u32, min: u128, max: u128) -> Self {
+        assert!(min <= max && max <= mask(bits));
+        Self {
+            bits,
+            start: min,
+            extent: max - min,
+        }
+    }
+
+    fn signed_bits(bits: u32, min: i128, max: i128) -> Self {
+        assert!(min <= max);
+        assert!(
+            min >= to_signed(bits, sign_bit(bits)) && max <= to_signed(bits, sign_bit(bits) - 1)
+        );
+        Self {
+            bits,
+            start: min as u128 & mask(bits),
+            extent: (max as u128).wrapping_sub(min as u128) & mask(bits),
+        }
+    }
+
+    /// The width of the integer type of the range.
+    pub fn width(&self) -> u32 {
+        self.bits
+    }
+
+    /// The first value of the range.
+    pub fn start(&self) -> u128 {
+        self.start
+    }
+
+    /// The last value of the range.
+    pub fn end(&self) -> u128 {
+        self.start.wrapping_add(self.extent) & mask(self.bits)
+    }
+
+    /// Whether the value with the low bits of `value` is in the range.
+    pub fn contains(&self, value: u128) -> bool {
+        value.wrapping_sub(self.start) & mask(self.bits) <= self.extent
+    }
+
+    /// Whether the range contains all values of its type.
+    pub fn is_full(&self) -> bool {
+        self.extent == mask(self.bits)
+    }
+
+    /// The only value of the range, if it contains a single one.
+    pub fn as_constant(&self) -> Option<u128> {
+        if self.extent == 0 {
+            Some(self.start)
+        } else {
+            None
+        }
+    }
+
+    /// Whether zero is not in the range.
+    pub fn is_nonzero(&self) -> bool {
+        !self.contains(0)
+    }
+
+    /// The smallest value of the range when compared as unsigned.
+    pub fn umin(&self) -> u128 {
+        if self.start > self.end() {
+            0
+        } else {
+            self.start
+        }
+    }
+
+    /// The largest value of the range when compared as unsigned.
+    pub fn umax(&self) -> u128 {
+        if self.start > self.end() {
+            mask(self.bits)
+        } else {
+            self.end()
+        }
+    }
+
+    fn wraps_signed(&self) -> bool {
+        let sign = sign_bit(self.bits);
+        self.start ^ sign > self.end() ^ sign
+    }
+
+    /// The smallest value of the range when compared as signed.
+    pub fn smin(&self) -> i128 {
+        if self.wraps_signed() {
+            to_signed(self.bits, sign_bit(self.bits))
+        } else {
+            to_signed(self.bits, self.start)
+        }
+    }
+
+    /// The largest value of the range when compared as signed.
+    pub fn smax(&self) -> i128 {
+        if self.wraps_signed() {
+            to_signed(self.bits, sign_bit(self.bits) - 1)
+        } else {
+            to_signed(self.bits, self.end())
+        }
+    }
+
+    /// Whether the range has any value in common with `other`.
+    pub fn overlaps(&self, other: &Self) -> bool {
+        self.contains(other.start) || other.contains(self.start)
+    }
+
+    /// A range containing all values which are in both `self` and `other`.
+    ///
+    /// The values in both ranges may form two separate intervals, in which case the result
+    /// contains more values. If there are no values in both ranges, the result is arbitrary.
+    pub fn intersect(self, other: Self) -> Self {
+        debug_assert_eq!(self.bits, other.bits);
+        let mask = mask(self.bits);
+        let other_offset = other.start.wrapping_sub(self.start) & mask;
+        let self_offset = self.start.wrapping_sub(other.start) & mask;
+        match (other_offset <= self.extent, self_offset <= other.extent) {
+            (true, false) => Self {
+                start: other.start,
+                extent: (self.extent - other_offset).min(other.extent),
+                ..self
+            },
+            (false, true) => Self {
+                extent: (other.extent - self_offset).min(self.extent),
+                ..self
+            },
+            (true, true) if self.start == other.start => Self {
+                extent: self.extent.min(other.extent),
+                ..self
+            },
+            _ => {
+                if self.extent <= other.extent {
+                    self
+                } else {
+                    other
+                }
+            }
+        }
+    }
+
+    /// The smallest range containing all values of `self` and of `other`.
+    pub fn union(self, other: Self) -> Self {
+        debug_assert_eq!(self.bits, other.bits);
+        let mask = mask(self.bits);
+        // The extent of the range from the start of `a` which contains all of `b`.
+        let cover = |a: &Self, b: &Self| {
+            let start = b.start.wrapping_sub(a.start) & mask;
+            let end = b.end().wrapping_sub(a.start) & mask;
+            if start <= end {
+                a.extent.max(end)
+            } else {
+                mask
+            }
+        };
+        let from_self = cover(&self, &other);
+        let from_other = cover(&other, &self);
+        if from_self <= from_other {
+            Self {
+                extent: from_self,
+                ..self
+            }
+        } else {
+            Self {
+                extent: from_other,
+                ..other
+            }
+        }
+    }
+
+    /// The range without `value`, if it is at one of its ends.
+    fn exclude(self, value: u128) -> Self {
+        if self.extent == 0 {
+            self
+        } else if value == self.start {
+            Self {
+                start: self.start.wrapping_add(1) & mask(self.bits),
+                extent: self.extent - 1,
+                ..self
+            }
+        } else if value == self.end() {
+            Self {
+                extent: self.extent - 1,
+                ..self
+            }
+        } else {
+            self
+        }
+    }
+
+    fn add(self, other: Self) -> Self {
+        match self.extent.checked_add(other.extent) {
+            Some(extent) if extent <= mask(self.bits) => Self {
+                start: self.start.wrapping_add(other.start) & mask(self.bits),
+                extent,
+                ..self
+            },
+            _ => Self::full_bits(self.bits),
+        }
+    }
+
+    fn neg(self) -> Self {
+        Self {
+            start: self.end().wrapping_neg() & mask(self.bits),
+            ..self
+        }
+    }
+
+    fn sub(self, other: Self) -> Self {
+        self.add(other.neg())
+    }
+
+    fn not(self) -> Self {
+        Self {
+            start: !self.end() & mask(self.bits),
+            ..self
+        }
+    }
+
+    fn uextend(self, bits: u32) -> Self {
+        Self::unsigned_bits(bits, self.umin(), self.umax())
+    }
+
+    fn sextend(self, bits: u32) -> Self {
+        Self::signed_bits(bits, self.smin(), self.smax())
+    }
+
+    fn ireduce(self, bits: u32) -> Self {
+        if self.extent <= mask(bits) {
+            Self {
+                bits,
+                start: self.start & mask(bits),
+                extent: self.extent,
+            }
+        } else {
+            Self::full_bits(bits)
+        }
+    }
+
+    fn ushr(self, amount: u32) -> Self {
+        Self::unsigned_bits(self.bits, self.umin() >> amount, self.umax() >> amount)
+    }
+
+    fn sshr(self, amount: u32) -> Self {
+        Self::signed_bits(self.bits, self.smin() >> amount, self.smax() >> amount)
+    }
+
+    fn ishl(self, amount: u32) -> Self {
+        if self.umax() <= mask(self.bits) >> amount {
+            Self::unsigned_bits(self.bits, self.umin() << amount, self.umax() << amount)
+        } else {
+            Self::full_bits(self.bits)
+        }
+    }
+
+    fn umin_with(self, other: Self) -> Self {
+        Self::unsigned_bits(
+            self.bits,
+            self.umin().min(other.umin()),
+            self.umax().min(other.umax()),
+        )
+    }
+
+    fn umax_with(self, other: Self) -> Self {
+        Self::unsigned_bits(
+            self.bits,
+            self.umin().max(other.umin()),
+            self.umax().max(other.umax()),
+        )
+    }
+
+    fn smin_with(self, other: Self) -> Self {
+        Self::signed_bits(
+            self.bits,
+            self.smin().min(other.smin()),
+            self.smax().min(other.smax()),
+        )
+    }
+
+    fn smax_with(self, other: Self) -> Self {
+        Self::signed_bits(
+            self.bits,
+            self.smin().max(other.smin()),
+            self.smax().max(other.smax()),
+        )
+    }
+
+    /// The range of `x` for which `x cc y` holds for some `y` in `self`.
+    fn satisfying(self, cc: IntCC) -> Self {
+        let bits = self.bits;
+        let smin = to_signed(bits, sign_bit(bits));
+        let smax = to_signed(bits, sign_bit(bits) - 1);
+        match cc {
+            IntCC::Equal => self,
+            IntCC::NotEqual => match self.as_constant() {
+                Some(value) => Self::full_bits(bits).exclude(value),
+                None => Self::full_bits(bits),
+            },
+            IntCC::UnsignedLessThan if self.umax() > 0 => {
+                Self::unsigned_bits(bits, 0, self.umax() - 1)
+            }
+            IntCC::UnsignedLessThanOrEqual => Self::unsigned_bits(bits, 0, self.umax()),
+            IntCC::UnsignedGreaterThan if self.umin() < mask(bits) => {
+                Self::unsigned_bits(bits, self.umin() + 1, mask(bits))
+            }
+            IntCC::UnsignedGreaterThanOrEqual => Self::unsigned_bits(bits, self.umin(), mask(bits)),
+            IntCC::SignedLessThan if self.smax() > smin => {
+                Self::signed_bits(bits, smin, self.smax() - 1)
+            }
+            IntCC::SignedLessThanOrEqual => Self::signed_bits(bits, smin, self.smax()),
+            IntCC::SignedGreaterThan if self.smin() < smax => {
+                Self::signed_bits(bits, self.smin() + 1, smax)
+            }
+            IntCC::SignedGreaterThanOrEqual => Self::signed_bits(bits, self.smin(), smax),
+            // No value satisfies the condition, so the code testing it is unreachable.
+            _ => Self::full_bits(bits),
+        }
+    }
+
+    /// Whether `x cc y` holds for all `x` in `self` and `y` in `other`, or for none of them.
+    fn compare(self, cc: IntCC, other: Self) -> Option<bool> {
+        let (always, never) = match cc {
+            IntCC::Equal | IntCC::NotEqual => {
+                let equal = self.as_constant().is_some() && self == other;
+                let unequal = !self.overlaps(&other);
+                if cc == IntCC::Equal {
+                    (equal, unequal)
+                } else {
+                    (unequal, equal)
+                }
+            }
+            IntCC::UnsignedLessThan => (self.umax() < other.umin(), self.umin() >= other.umax()),
+            IntCC::UnsignedLessThanOrEqual => {
+                (self.umax() <= other.umin(), self.umin() > other.umax())
+            }
+            IntCC::SignedLessThan => (self.smax() < other.smin(), self.smin() >= other.smax()),
+            IntCC::SignedLessThanOrEqual => {
+                (self.smax() <= other.smin(), self.smin() > other.smax())
+            }
+            _ => return other.compare(cc.reverse(), self),
+        };
+        match (always, never) {
+            (true, _) => Some(true),
+            (_, true) => Some(false),
+            _ => None,
+        }
+    }
+}
+
+/// The bits of a value of an integer type which are known to be zero or one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct KnownBits {
+    bits: u32,
+    zeros: u128,
+    ones: u128,
+}
+
+impl KnownBits {
+    /// Nothing known about a value of the integer type `ty`.
+    pub fn unknown(ty: Type) -> Self {
+        Self::unknown_bits(int_bits(ty))
+    }
+
+    /// All bits of a value of the integer type `ty` whose bits are the low bits of `value`.
+    pub fn constant(ty: Type, value: u128) -> Self {
+        Self::constant_bits(int_bits(ty), value)
+    }
+
+    fn unknown_bits(bits: u32) -> Self {
+        Self {
+            bits,
+            zeros: 0,
+            ones: 0,
+        }
+    }
+
+    fn constant_bits(bits: u32, value: u128) -> Self {
+        Self {
+            bits,
+            zeros: !value & mask(bits),
+            ones: value & mask(bits),
+        }
+    }
+
+    /// The width of the integer type of the value.
+    pub fn width(&self) -> u32 {
+        self.bits
+    }
+
+    /// The bits known to be zero.
+    pub fn zeros(&self) -> u128 {
+        self.zeros
+    }
+
+    /// The bits known to be one.
+    pub fn ones(&self) -> u128 {
+        self.ones
+    }
+
+    /// The value, if all its bits are known.
+    pub fn as_constant(&self) -> Option<u128> {
+        if self.zeros | self.ones == mask(self.bits) {
+            Some(self.ones)
+        } else {
+            None
+        }
+    }
+
+    /// Whether the value with the low bits of `value` has the known bits.
+    pub fn contains(&self, value: u128) -> bool {
+        value & self.zeros == 0 && value & self.ones == self.ones
+    }
+
+    /// The values with the known bits, compared as unsigned.
+    pub fn range(&self) -> ValueRange {
+        ValueRange::unsigned_bits(self.bits, self.ones, !self.zeros & mask(self.bits))
+    }
+
+    /// The bits known for a value which is either one with the bits of `self` or one with the
+    /// bits of `other`.
+    fn common(self, other: Self) -> Self {
+        Self {
+            zeros: self.zeros & other.zeros,
+            ones: self.ones & other.ones,
+            ..self
+        }
+    }
+
+    /// The number of low bits known to be zero.
+    fn trailing_zeros(&self) -> u32 {
+        self.zeros.trailing_ones().min(self.bits)
+    }
+
+    fn not(self) -> Self {
+        Self {
+            zeros: self.ones,
+            ones: self.zeros,
+            ..self
+        }
+    }
+
+    fn and(self, other: Self) -> Self {
+        Self {
+            zeros: self.zeros | other.zeros,
+            ones: self.ones & other.ones,
+            ..self
+        }
+    }
+
+    fn or(self, other: Self) -> Self {
+        self.not().and(other.not()).not()
+    }
+
+    fn xor(self, other: Self) -> Self {
+        Self {
+            zeros: (self.zeros & other.zeros) | (self.ones & other.ones),
+            ones: (self.zeros & other.ones) | (self.ones & other.zeros),
+            ..self
+        }
+    }
+
+    /// The bits of `self + other + carry`, where the carry is either known or unknown.
+    fn add_with_carry(self, other: Self, carry: Option<bool>) -> Self {
+        let mask = mask(self.bits);
+        let (carry_zero, carry_one) = match carry {
+            Some(carry) => (u128::from(!carry), u128::from(carry)),
+            None => (0, 0),
+        };
+        // The sums with all unknown bits set and with all of them clear.
+        let max_sum = (!self.zeros & mask)
+            .wrapping_add(!other.zeros & mask)
+            .wrapping_add(1 - carry_zero)
+            & mask;
+        let min_sum = self.ones.wrapping_add(other.ones).wrapping_add(carry_one) & mask;
+        // The carries into each bit in both sums, which agree wherever they are known.
+        let carry_known_zero = !(max_sum ^ self.zeros ^ other.zeros) & mask;
+        let carry_known_one = (min_sum ^ self.ones ^ other.ones) & mask;
+        let known = (self.zeros | self.ones)
+            & (other.zeros | other.ones)
+            & (carry_known_zero | carry_known_one);
+        Self {
+            zeros: !max_sum & known,
+            ones: min_sum & known,
+            ..self
+        }
+    }
+
+    fn add(self, other: Self) -> Self {
+        self.add_with_carry(other, Some(false))
+    }
+
+    fn sub(self, other: Self) -> Self {
+        self.add_with_carry(other.not(), Some(true))
+    }
+
+    fn mul(self, other: Self) -> Self {
+        if let (Some(a), Some(b)) = (self.as_constant(), other.as_constant()) {
+            return Self::constant_bits(self.bits, a.wrapping_mul(b));
+        }
+        let trailing_zeros = (self.trailing_zeros() + other.trailing_zeros()).min(self.bits);
+        Self {
+            zeros: mask(trailing_zeros),
+            ones: 0,
+            ..self
+        }
+    }
+
+    fn uextend(self, bits: u32) -> Self {
+        Self {
+            bits,
+            zeros: self.zeros | (mask(bits) & !mask(self.bits)),
+            ones: self.ones,
+        }
+    }
+
+    fn sextend(self, bits: u32) -> Self {
+        let high = mask(bits) & !mask(self.bits);
+        let sign = sign_bit(self.bits);
+        Self {
+            bits,
+            zeros: self.zeros | if self.zeros & sign != 0 { high } else { 0 },
+            ones: self.ones | if self.ones & sign != 0 { high } else { 0 },
+        }
+    }
+
+    fn ireduce(self, bits: u32) -> Self {
+        Self {
+            bits,
+            zeros: self.zeros & mask(bits),
+            ones: self.ones & mask(bits),
+        }
+    }
+
+    fn ishl(self, amount: u32) -> Self {
+        Self {
+            zeros: ((self.zeros << amount) | mask(amount)) & mask(self.bits),
+            ones: (self.ones << amount) & mask(self.bits),
+            ..self
+        }
+    }
+
+    fn ushr(self, amount: u32) -> Self {
+        let high = mask(self.bits) & !(mask(self.bits) >> amount);
+        Self {
+            zeros: (self.zeros >> amount) | high,
+            ones: self.ones >> amount,
+            ..self
+        }
+    }
+
+    fn sshr(self, amount: u32) -> Self {
+        let shift = |bits: u128| (to_signed(self.bits, bits) >> amount) as u128 & mask(self.bits);
+        Self {
+            zeros: shift(self.zeros),
+            ones: shift(self.ones),
+            ..self
+        }
+    }
+
+    /// The bits of a value which is at most `max`.
+    fn at_most(bits: u32, max: u128) -> Self {
+        Self {
+            bits,
+            zeros: mask(bits) & !mask(128 - max.leading_zeros()),
+            ones: 0,
+        }
+    }
+}
+
+/// A condition known to hold at some point: `value` is non-zero if `nonzero` is set, and zero
+/// otherwise.
+#[derive(Clone, Copy, Debug)]
+struct Guard {
+    value: Value,
+    nonzero: bool,
+}
+
+/// Facts about the integer values of a function.
+///
+/// The facts are computed on demand, from the function and its dominator tree as they were when
+/// the `ValueFacts` was created.
+pub struct ValueFacts<'a> {
+    func: &'a Function,
+    domtree: &'a DominatorTree,
+    /// For the blocks which are only reached through one edge of a `brif`, the condition that
+    /// edge is taken for.
+    entry_guards: SecondaryMap<Block, Option<Guard>>,
+    /// The `trapz` and `trapnz` instructions of every block, with the conditions which hold after
+    /// them.
+    trap_guards: SecondaryMap<Block, Vec<(Inst, Guard)>>,
+}
+
+impl<'a> ValueFacts<'a> {
+    /// Prepare computing facts about the values of `func`, whose dominator tree is `domtree`.
+    pub fn new(func: &'a Function, domtree: &'a DominatorTree) -> Self {
+        debug_assert!(domtree.is_valid());
+        let mut edges = SecondaryMap::<Block, u32>::new();
+        let mut trap_guards = SecondaryMap::<Block, Vec<_>>::new();
+        for block in func.layout.blocks() {
+            for inst in func.layout.block_insts(block) {
+                let nonzero = match func.dfg.insts[inst] {
+                    InstructionData::CondTrap {
+                        opcode: Opcode::Trapz,
+                        ..
+                    } => true,
+                    InstructionData::CondTrap {
+                        opcode: Opcode::Trapnz,
+                        ..
+                    } => false,
+                    _ => continue,
+                };
+                let value = func.dfg.inst_args(inst)[0];
+                trap_guards[block].push((inst, Guard { value, nonzero }));
+            }
+            if let Some(inst) = func.layout.last_inst(block) {
+                for dest in func.dfg.insts[inst].branch_destination(&func.dfg.jump_tables) {
+                    edges[dest.block(&func.dfg.value_lists)] += 1;
+                }
+            }
+        }
+
+        let mut entry_guards = SecondaryMap::new();
+        for block in func.layout.blocks() {
+            let (value, blocks) = match func.layout.last_inst(block).map(|i| &func.dfg.insts[i]) {
+                Some(&InstructionData::Brif { arg, blocks, .. }) => (arg, blocks),
+                _ => continue,
+            };
+            for (dest, nonzero) in [(blocks[0], true), (blocks[1], false)] {
+                let dest = dest.block(&func.dfg.value_lists);
+                // The entry block is also entered when the function is called.
+                if edges[dest] == 1 && Some(dest) != func.layout.entry_block() {
+                    entry_guards[dest] = Some(Guard { value, nonzero });
+                }
+            }
+        }
+
+        Self {
+            func,
+            domtree,
+            entry_guards,
+            trap_guards,
+        }
+    }
+
+    /// The range of values `value` can have whenever `at` is executed.
+    ///
+    /// `value` must be an integer which is defined by `at` or by an instruction dominating it, or
+    /// which is a parameter of a block dominating it.
+    pub fn range_of(&self, value: Value, at: Inst) -> ValueRange {
+        let guards = self.guards(at);
+        self.range(value, &guards, MAX_DEPTH)
+    }
+
+    /// The bits of `value` known from its definition.
+    pub fn known_bits(&self, value: Value) -> KnownBits {
+        self.known(value, MAX_DEPTH)
+    }
+
+    /// Whether `value` is non-zero whenever `at` is executed, with the same requirements as
+    /// [`ValueFacts::range_of`].
+    pub fn is_nonzero(&self, value: Value, at: Inst) -> bool {
+        self.range_of(value, at).is_nonzero()
+    }
+
+    /// The conditions which hold on every path to `at`.
+    fn guards(&self, at: Inst) -> SmallVec<[Guard; 8]> {
+        let layout = &self.func.layout;
+        let mut guards = SmallVec::new();
+        let mut block = layout
+            .inst_block(at)
+            .expect("instruction not in the layout");
+        let mut before = at;
+        for _ in 0..MAX_DOMINATORS {
+            guards.extend(
+                self.trap_guards[block]
+                    .iter()
+                    .take_while(|&&(inst, _)| layout.pp_cmp(inst, before) == Ordering::Less)
+                    .map(|&(_, guard)| guard),
+            );
+            guards.extend(self.entry_guards[block]);
+            match self.domtree.idom(block) {
+                Some(idom) => {
+                    before = idom;
+                    block = layout.inst_block(idom).unwrap();
+                }
+                None => break,
+            }
+        }
+        guards
+    }
+
+    fn range(&self, value: Value, guards: &[Guard], depth: u32) -> ValueRange {
+        let dfg = &self.func.dfg;
+        let value = dfg.resolve_aliases(value);
+        let mut range = self.known(value, depth).range();
+        if depth == 0 {
+            return range;
+        }
+        range = range.intersect(self.def_range(value, guards, depth - 1));
+        for guard in guards {
+            range = self.refine(range, value, *guard, guards, depth - 1);
+        }
+        range
+    }
+
+    /// The range of `value` given by its definition.
+    fn def_range(&self, value: Value, guards: &[Guard], depth: u32) -> ValueRange {
+        let dfg = &self.func.dfg;
+        let bits = int_bits(dfg.value_type(value));
+        let full = ValueRange::full_bits(bits);
+        let inst = match dfg.value_def(value) {
+            ValueDef::Result(inst, 0) => inst,
+            _ => return full,
+        };
+        let range = |value| self.range(value, guards, depth);
+        let constant = |imm: i64| ValueRange::constant_bits(bits, imm as i128 as u128);
+        match dfg.insts[inst] {
+            InstructionData::UnaryImm {
+                opcode: Opcode::Iconst,
+                imm,
+            } => constant(imm.bits()),
+            InstructionData::Unary { opcode, arg } => match opcode {
+                Opcode::Uextend => range(arg).uextend(bits),
+                Opcode::Sextend => range(arg).sextend(bits),
+                Opcode::Ireduce => range(arg).ireduce(bits),
+                Opcode::Ineg => range(arg).neg(),
+                Opcode::Bnot => range(arg).not(),
+                Opcode::Bmask => {
+                    let arg = range(arg);
+                    if arg.is_nonzero() {
+                        ValueRange::constant_bits(bits, mask(bits))
+                    } else if arg.as_constant() == Some(0) {
+                        ValueRange::constant_bits(bits, 0)
+                    } else {
+                        ValueRange::constant_bits(bits, 0)
+                            .union(ValueRange::constant_bits(bits, mask(bits)))
+                    }
+                }
+                Opcode::Popcnt | Opcode::Clz | Opcode::Ctz | Opcode::Cls => {
+                    ValueRange::unsigned_bits(bits, 0, bits.into())
+                }
+                _ => full,
+            },
+            InstructionData::Binary { opcode, args } => {
+                binary_range(opcode, range(args[0]), range(args[1]))
+            }
+            InstructionData::BinaryImm64 { opcode, arg, imm } => {
+                let imm = constant(imm.bits());
+                match opcode {
+                    Opcode::IaddImm => binary_range(Opcode::Iadd, range(arg), imm),
+                    Opcode::IrsubImm => binary_range(Opcode::Isub, imm, range(arg)),
+                    Opcode::UdivImm => binary_range(Opcode::Udiv, range(arg), imm),
+                    Opcode::UremImm => binary_range(Opcode::Urem, range(arg), imm),
+                    Opcode::IshlImm => binary_range(Opcode::Ishl, range(arg), imm),
+                    Opcode::UshrImm => binary_range(Opcode::Ushr, range(arg), imm),
+                    Opcode::SshrImm => binary_range(Opcode::Sshr, range(arg), imm),
+                    _ => full,
+                }
+            }
+            InstructionData::IntCompare { cond, args, .. } => {
+                compare_range(range(args[0]).compare(cond, range(args[1])))
+            }
+            InstructionData::IntCompareImm { cond, arg, imm, .. } => {
+                let arg = range(arg);
+                let imm = ValueRange::constant_bits(arg.bits, imm.bits() as i128 as u128);
+                compare_range(arg.compare(cond, imm))
+            }
+            InstructionData::Ternary {
+                opcode: Opcode::Select,
+                args,
+            } => {
+                let cond = range(args[0]);
+                if cond.is_nonzero() {
+                    range(args[1])
+                } else if cond.as_constant() == Some(0) {
+                    range(args[2])
+                } else {
+                    range(args[1]).union(range(args[2]))
+                }
+            }
+            _ => full,
+        }
+    }
+
+    /// Refine `range`, the range of `value`, with the condition `guard`.
+    fn refine(
+        &self,
+        range: ValueRange,
+        value: Value,
+        guard: Guard,
+        guards: &[Guard],
+        depth: u32,
+    ) -> ValueRange {
+        let dfg = &self.func.dfg;
+        let cond = dfg.resolve_aliases(guard.value);
+        if cond == value {
+            return if guard.nonzero {
+                range.exclude(0)
+            } else {
+                range.intersect(ValueRange::constant_bits(range.bits, 0))
+            };
+        }
+        let inst = match dfg.value_def(cond) {
+            ValueDef::Result(inst, 0) => inst,
+            _ => return range,
+        };
+        let (cc, other) = match dfg.insts[inst] {
+            InstructionData::IntCompare { cond, args, .. } => {
+                let (x, y) = (dfg.resolve_aliases(args[0]), dfg.resolve_aliases(args[1]));
+                if x == value && y != value {
+                    (cond, self.range(y, guards, depth))
+                } else if y == value && x != value {
+                    (cond.reverse(), self.range(x, guards, depth))
+                } else {
+                    return range;
+                }
+            }
+            InstructionData::IntCompareImm { cond, arg, imm, .. }
+                if dfg.resolve_aliases(arg) == value =>
+            {
+                (
+                    cond,
+                    ValueRange::constant_bits(range.bits, imm.bits() as i128 as u128),
+                )
+            }
+            _ => return range,
+        };
+        let cc = if guard.nonzero { cc } else { cc.inverse() };
+        range.intersect(other.satisfying(cc))
+    }
+
+    /// The bits of `value` known from its definition.
+    fn known(&self, value: Value, depth: u32) -> KnownBits {
+        let dfg = &self.func.dfg;
+        let value = dfg.resolve_aliases(value);
+        let bits = int_bits(dfg.value_type(value));
+        let unknown = KnownBits::unknown_bits(bits);
+        let inst = match dfg.value_def(value) {
+            ValueDef::Result(inst, 0) if depth > 0 => inst,
+            _ => return unknown,
+        };
+        let known = |value| self.known(value, depth - 1);
+        let constant = |imm: i64| KnownBits::constant_bits(bits, imm as i128 as u128);
+        match dfg.insts[inst] {
+            InstructionData::UnaryImm {
+                opcode: Opcode::Iconst,
+                imm,
+            } => constant(imm.bits()),
+            InstructionData::Unary { opcode, arg } => match opcode {
+                Opcode::Uextend => known(arg).uextend(bits),
+                Opcode::Sextend => known(arg).sextend(bits),
+                Opcode::Ireduce => known(arg).ireduce(bits),
+                Opcode::Bnot => known(arg).not(),
+                Opcode::Ineg => KnownBits::constant_bits(bits, 0).sub(known(arg)),
+                Opcode::Popcnt | Opcode::Clz | Opcode::Ctz | Opcode::Cls => {
+                    KnownBits::at_most(bits, bits.into())
+                }
+                _ => unknown,
+            },
+            InstructionData::Binary { opcode, args } => {
+                binary_known(opcode, known(args[0]), known(args[1]))
+            }
+            InstructionData::BinaryImm64 { opcode, arg, imm } => {
+                let imm = constant(imm.bits());
+                let opcode = match opcode {
+                    Opcode::IaddImm => Opcode::Iadd,
+                    Opcode::ImulImm => Opcode::Imul,
+                    Opcode::BandImm => Opcode::Band,
+                    Opcode::BorImm => Opcode::Bor,
+                    Opcode::BxorImm => Opcode::Bxor,
+                    Opcode::IshlImm => Opcode::Ishl,
+                    Opcode::UshrImm => Opcode::Ushr,
+                    Opcode::SshrImm => Opcode::Sshr,
+                    Opcode::IrsubImm => return imm.sub(known(arg)),
+                    _ => return unknown,
+                };
+                binary_known(opcode, known(arg), imm)
+            }
+            InstructionData::IntCompare { .. } | InstructionData::IntCompareImm { .. } => {
+                KnownBits::at_most(bits, 1)
+            }
+            InstructionData::Ternary {
+                opcode: Opcode::Select,
+                args,
+            } => known(args[1]).common(known(args[2])),
+            _ => unknown,
+        }
+    }
+}
+
+/// The shift amount of a shift by a value in `amount`, if it is known.
+fn shift_amount(bits: u32, amount: Option<u128>) -> Option<u32> {
+    amount.map(|amount| (amount & u128::from(bits - 1)) as u32)
+}
+
+fn binary_range(opcode: Opcode, x: ValueRange, y: ValueRange) -> ValueRange {
+    let bits = x.bits;
+    match opcode {
+        Opcode::Iadd => x.add(y),
+        Opcode::Isub => x.sub(y),
+        Opcode::Umin => x.umin_with(y),
+        Opcode::Umax => x.umax_with(y),
+        Opcode::Smin => x.smin_with(y),
+        Opcode::Smax => x.smax_with(y),
+        Opcode::Udiv => match y.as_constant() {
+            Some(divisor) if divisor != 0 => {
+                ValueRange::unsigned_bits(bits, x.umin() / divisor, x.umax() / divisor)
+            }
+            _ => ValueRange::full_bits(bits),
+        },
+        Opcode::Urem => match y.as_constant() {
+            Some(divisor) if x.umax() < divisor => x,
+            Some(divisor) if divisor != 0 => ValueRange::unsigned_bits(bits, 0, divisor - 1),
+            _ => ValueRange::full_bits(bits),
+        },
+        Opcode::Ishl | Opcode::Ushr | Opcode::Sshr => match shift_amount(bits, y.as_constant()) {
+            Some(amount) if opcode == Opcode::Ishl => x.ishl(amount),
+            Some(amount) if opcode == Opcode::Ushr => x.ushr(amount),
+            Some(amount) => x.sshr(amount),
+            None => ValueRange::full_bits(bits),
+        },
+        _ => ValueRange::full_bits(bits),
+    }
+}
+
+fn binary_known(opcode: Opcode, x: KnownBits, y: KnownBits) -> KnownBits {
+    let bits = x.bits;
+    match opcode {
+        Opcode::Band => x.and(y),
+        Opcode::Bor => x.or(y),
+        Opcode::Bxor => x.xor(y),
+        Opcode::BandNot => x.and(y.not()),
+        Opcode::BorNot => x.or(y.not()),
+        Opcode::BxorNot => x.xor(y.not()),
+        Opcode::Iadd => x.add(y),
+        Opcode::Isub => x.sub(y),
+        Opcode::Imul => x.mul(y),
+        Opcode::Umin | Opcode::Umax | Opcode::Smin | Opcode::Smax => x.common(y),
+        Opcode::Ishl | Opcode::Ushr | Opcode::Sshr => match shift_amount(bits, y.as_constant()) {
+            Some(amount) if opcode == Opcode::Ishl => x.ishl(amount),
+            Some(amount) if opcode == Opcode::Ushr => x.ushr(amount),
+            Some(amount) => x.sshr(amount),
+            None => KnownBits::unknown_bits(bits),
+        },
+        _ => KnownBits::unknown_bits(bits),
+    }
+}
+
+/// The range of the result of an `icmp`, whose outcome may be known.
+fn compare_range(outcome: Option<bool>) -> ValueRange {
+    match outcome {
+        Some(outcome) => ValueRange::constant_bits(8, outcome.into()),
+        None => ValueRange::unsigned_bits(8, 0, 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cursor::{Cursor, FuncCursor};
+    use crate::flowgraph::ControlFlowGraph;
+    use crate::fx::FxHashMap;
+    use crate::ir::types::*;
+    use crate::ir::{InstBuilder, ProgramPoint, TrapCode};
+    use alloc::vec;
+
+    /// A xorshift random number generator, good enough to pick instructions and constants.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        /// A constant which is often near a boundary of the signed or unsigned values.
+        fn constant(&mut self, bits: u32) -> u128 {
+            let value = u128::from(self.next()) << 64 | u128::from(self.next());
+            let small = u128::from(self.next() % 5);
+            let value = match self.below(6) {
+                0 => small,
+                1 => small.wrapping_neg(),
+                2 => sign_bit(bits).wrapping_add(small),
+                3 => sign_bit(bits).wrapping_sub(small),
+                _ => value,
+            };
+            value & mask(bits)
+        }
+
+        fn pick<T: Copy>(&mut self, items: &[T]) -> T {
+            items[self.below(items.len())]
+        }
+    }
+
+    const CONDS: [IntCC; 10] = [
+        IntCC::Equal,
+        IntCC::NotEqual,
+        IntCC::SignedLessThan,
+        IntCC::SignedGreaterThanOrEqual,
+        IntCC::SignedGreaterThan,
+        IntCC::SignedLessThanOrEqual,
+        IntCC::UnsignedLessThan,
+        IntCC::UnsignedGreaterThanOrEqual,
+        IntCC::UnsignedGreaterThan,
+        IntCC::UnsignedLessThanOrEqual,
+    ];
+
+    /// Append a random instruction computing a value of type `ty` from the values in `pool`.
+    fn random_inst(pos: &mut FuncCursor, rng: &mut Rng, ty: Type, pool: &mut Vec<Value>) {
+        let bits = ty.bits();
+        let x = rng.pick(pool);
+        let y = rng.pick(pool);
+        let imm = rng.constant(bits) as i64;
+        let amount = rng.below(bits as usize) as i64;
+        let value = match rng.below(24) {
+            0 => pos.ins().iconst(ty, imm),
+            1 => pos.ins().iadd(x, y),
+            2 => pos.ins().isub(x, y),
+            3 => pos.ins().imul(x, y),
+            4 => pos.ins().band(x, y),
+            5 => pos.ins().bor(x, y),
+            6 => pos.ins().bxor(x, y),
+            7 => pos.ins().bnot(x),
+            8 => pos.ins().ineg(x),
+            9 => pos.ins().iadd_imm(x, imm),
+            10 => pos.ins().band_imm(x, imm),
+            11 => pos.ins().bor_imm(x, imm),
+            12 => pos.ins().ishl_imm(x, amount),
+            13 => pos.ins().ushr_imm(x, amount),
+            14 => pos.ins().sshr_imm(x, amount),
+            15 => pos.ins().umin(x, y),
+            16 => pos.ins().smax(x, y),
+            17 => pos.ins().udiv_imm(x, imm.max(1)),
+            18 => pos.ins().urem_imm(x, imm.max(1)),
+            19 => {
+                let cond = pos.ins().icmp(rng.pick(&CONDS), x, y);
+                let z = rng.pick(pool);
+                pos.ins().select(cond, y, z)
+            }
+            20 => {
+                let cond = pos.ins().icmp_imm(rng.pick(&CONDS), x, imm);
+                if ty == I8 {
+                    cond
+                } else {
+                    pos.ins().uextend(ty, cond)
+                }
+            }
+            21 | 22 if ty != I8 => {
+                let narrow = rng.pick(&[I8, I16, I32, I64]);
+                let narrow = if narrow.bits() < bits { narrow } else { I8 };
+                let reduced = pos.ins().ireduce(narrow, x);
+                if rng.below(2) == 0 {
+                    pos.ins().uextend(ty, reduced)
+                } else {
+                    pos.ins().sextend(ty, reduced)
+                }
+            }
+            _ => pos.ins().popcnt(x),
+        };
+        pool.push(value);
+    }
+
+    /// Build a random function with a parameter of type `ty`, a `brif` on a comparison,
+    /// conditional traps and a join point.
+    fn random_function(rng: &mut Rng, ty: Type) -> Function {
+        let mut func = Function::new();
+        let block0 = func.dfg.make_block();
+        let block1 = func.dfg.make_block();
+        let block2 = func.dfg.make_block();
+        let block3 = func.dfg.make_block();
+        let param = func.dfg.append_block_param(block0, ty);
+        let joined = func.dfg.append_block_param(block3, ty);
+        let bits = ty.bits();
+
+        let mut pos = FuncCursor::new(&mut func);
+        pos.insert_block(block0);
+        let mut pool = vec![param];
+        for _ in 0..8 {
+            random_inst(&mut pos, rng, ty, &mut pool);
+        }
+        let x = rng.pick(&pool);
+        let cond = match rng.below(3) {
+            0 => x,
+            1 => pos
+                .ins()
+                .icmp_imm(rng.pick(&CONDS), x, rng.constant(bits) as i64),
+            _ => {
+                let y = rng.pick(&pool);
+                pos.ins().icmp(rng.pick(&CONDS), x, y)
+            }
+        };
+        pos.ins().brif(cond, block1, &[], block2, &[]);
+
+        pos.insert_block(block1);
+        let mut pool1 = pool.clone();
+        for _ in 0..4 {
+            random_inst(&mut pos, rng, ty, &mut pool1);
+        }
+        let x = rng.pick(&pool1);
+        let cond = pos
+            .ins()
+            .icmp_imm(rng.pick(&CONDS), x, rng.constant(bits) as i64);
+        pos.ins().trapnz(cond, TrapCode::User(0));
+        for _ in 0..4 {
+            random_inst(&mut pos, rng, ty, &mut pool1);
+        }
+        let x = rng.pick(&pool1);
+        pos.ins().jump(block3, &[x]);
+
+        pos.insert_block(block2);
+        let mut pool2 = pool.clone();
+        for _ in 0..4 {
+            random_inst(&mut pos, rng, ty, &mut pool2);
+        }
+        let x = rng.pick(&pool2);
+        pos.ins().jump(block3, &[x]);
+
+        pos.insert_block(block3);
+        pool.push(joined);
+        for _ in 0..4 {
+            random_inst(&mut pos, rng, ty, &mut pool);
+        }
+        let x = rng.pick(&pool);
+        pos.ins().trapz(x, TrapCode::User(1));
+        for _ in 0..4 {
+            random_inst(&mut pos, rng, ty, &mut pool);
+        }
+        let x = rng.pick(&pool);
+        pos.ins().return_(&[x]);
+        func
+    }
+
+    fn eval_inst(func: &Function, inst: Inst, env: &SecondaryMap<Value, u128>) -> Option<u128> {
+        let dfg = &func.dfg;
+        let ty = dfg.value_type(dfg.first_result(inst));
+        let bits = ty.bits();
+        let arg = |i: usize| env[dfg.inst_args(inst)[i]];
+        let arg_bits = |i: usize| dfg.value_type(dfg.inst_args(inst)[i]).bits();
+        let imm = match dfg.insts[inst] {
+            InstructionData::UnaryImm { imm, .. } | InstructionData::BinaryImm64 { imm, .. } => {
+                imm.bits() as i128 as u128
+            }
+            InstructionData::IntCompareImm { imm, .. } => imm.bits() as i128 as u128,
+            _ => 0,
+        };
+        let compare = |cc: IntCC, x: u128, y: u128| {
+            let bits = arg_bits(0);
+            let (x, y) = (x & mask(bits), y & mask(bits));
+            let (sx, sy) = (to_signed(bits, x), to_signed(bits, y));
+            u128::from(match cc {
+                IntCC::Equal => x == y,
+                IntCC::NotEqual => x != y,
+                IntCC::SignedLessThan => sx < sy,
+                IntCC::SignedGreaterThanOrEqual => sx >= sy,
+                IntCC::SignedGreaterThan => sx > sy,
+                IntCC::SignedLessThanOrEqual => sx <= sy,
+                IntCC::UnsignedLessThan => x < y,
+                IntCC::UnsignedGreaterThanOrEqual => x >= y,
+                IntCC::UnsignedGreaterThan => x > y,
+                IntCC::UnsignedLessThanOrEqual => x <= y,
+            })
+        };
+        let shift = |amount: u128| (amount & u128::from(bits - 1)) as u32;
+        let signed = |x: u128| to_signed(bits, x);
+        let value = match dfg.insts[inst].opcode() {
+            Opcode::Iconst => imm,
+            Opcode::Iadd => arg(0).wrapping_add(arg(1)),
+            Opcode::Isub => arg(0).wrapping_sub(arg(1)),
+            Opcode::Imul => arg(0).wrapping_mul(arg(1)),
+            Opcode::Band => arg(0) & arg(1),
+            Opcode::Bor => arg(0) | arg(1),
+            Opcode::Bxor => arg(0) ^ arg(1),
+            Opcode::Bnot => !arg(0),
+            Opcode::Ineg => arg(0).wrapping_neg(),
+            Opcode::IaddImm => arg(0).wrapping_add(imm),
+            Opcode::BandImm => arg(0) & imm,
+            Opcode::BorImm => arg(0) | imm,
+            Opcode::IshlImm => arg(0) << shift(imm),
+            Opcode::UshrImm => arg(0) >> shift(imm),
+            Opcode::SshrImm => (signed(arg(0)) >> shift(imm)) as u128,
+            Opcode::Umin => arg(0).min(arg(1)),
+            Opcode::Smax => signed(arg(0)).max(signed(arg(1))) as u128,
+            Opcode::UdivImm => arg(0) / (imm & mask(bits)),
+            Opcode::UremImm => arg(0) % (imm & mask(bits)),
+            Opcode::Icmp | Opcode::IcmpImm => {
+                let (cc, y) = match dfg.insts[inst] {
+                    InstructionData::IntCompare { cond, .. } => (cond, arg(1)),
+                    InstructionData::IntCompareImm { cond, .. } => (cond, imm),
+                    _ => unreachable!(),
+                };
+                compare(cc, arg(0), y)
+            }
+            Opcode::Select => {
+                if arg(0) != 0 {
+                    arg(1)
+                } else {
+                    arg(2)
+                }
+            }
+            Opcode::Uextend => arg(0),
+            Opcode::Sextend => to_signed(arg_bits(0), arg(0)) as u128,
+            Opcode::Ireduce => arg(0),
+            Opcode::Popcnt => arg(0).count_ones().into(),
+            opcode => panic!("can't evaluate {}", opcode),
+        };
+        Some(value & mask(bits))
+    }
+
+    /// Run `func` on `input`, checking the facts about every value wherever it is available.
+    fn check_execution(
+        func: &Function,
+        domtree: &DominatorTree,
+        facts: &ValueFacts,
+        ranges: &mut FxHashMap<(Value, Inst), ValueRange>,
+        input: u128,
+    ) {
+        let dfg = &func.dfg;
+        let entry = func.layout.entry_block().unwrap();
+        let mut env = SecondaryMap::new();
+        let mut defined = vec![dfg.block_params(entry)[0]];
+        env[defined[0]] = input;
+        let mut block = entry;
+        loop {
+            for inst in func.layout.block_insts(block) {
+                for &value in &defined {
+                    let pp = match dfg.value_def(value) {
+                        ValueDef::Result(def, _) => ProgramPoint::from(def),
+                        ValueDef::Param(def, _) => ProgramPoint::from(def),
+                        _ => unreachable!(),
+                    };
+                    if !domtree.dominates(pp, inst, &func.layout) {
+                        continue;
+                    }
+                    let range = *ranges
+                        .entry((value, inst))
+                        .or_insert_with(|| facts.range_of(value, inst));
+                    assert!(
+                        range.contains(env[value]),
+                        "{} = {:#x} at {} not in {:x?}\n{}",
+                        value,
+                        env[value],
+                        dfg.display_inst(inst),
+                        range,
+                        func.display()
+                    );
+                }
+
+                match dfg.insts[inst] {
+                    InstructionData::CondTrap { opcode, arg, .. } => {
+                        if (env[arg] == 0) == (opcode == Opcode::Trapz) {
+                            return;
+                        }
+                    }
+                    InstructionData::Brif { arg, blocks, .. } => {
+                        let taken = if env[arg] != 0 { blocks[0] } else { blocks[1] };
+                        block = taken.block(&dfg.value_lists);
+                    }
+                    InstructionData::Jump { destination, .. } => {
+                        block = destination.block(&dfg.value_lists);
+                        let args = destination.args_slice(&dfg.value_lists);
+                        for (&param, &arg) in dfg.block_params(block).iter().zip(args) {
+                            env[param] = env[arg];
+                            defined.push(param);
+                        }
+                    }
+                    InstructionData::MultiAry {
+                        opcode: Opcode::Return,
+                        ..
+                    } => return,
+                    _ => {
+                        let result = dfg.first_result(inst);
+                        env[result] = eval_inst(func, inst, &env).unwrap();
+                        let known = facts.known_bits(result);
+                        assert!(
+                            known.contains(env[result]),
+                            "{} = {:#x} doesn't have the bits {:x?}\n{}",
+                            result,
+                            env[result],
+                            known,
+                            func.display()
+                        );
+                        defined.push(result);
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_random_functions(ty: Type, seed: u64, functions: usize, inputs: &[u128]) {
+        let mut rng = Rng(seed);
+        for _ in 0..functions {
+            let func = random_function(&mut rng, ty);
+            let cfg = ControlFlowGraph::with_function(&func);
+            let domtree = DominatorTree::with_function(&func, &cfg);
+            let facts = ValueFacts::new(&func, &domtree);
+            let mut ranges = FxHashMap::default();
+            for &input in inputs {
+                check_execution(&func, &domtree, &facts, &mut ranges, input);
+            }
+            let bits = ty.bits();
+            for _ in 0..32 {
+                let input = rng.constant(bits);
+                check_execution(&func, &domtree, &facts, &mut ranges, input);
+            }
+        }
+    }
+
+    #[test]
+    fn random_i8_functions() {
+        let inputs = (0..=255).collect::<Vec<u128>>();
+        check_random_functions(I8, 0x1234_5678, 100, &inputs);
+    }
+
+    #[test]
+    fn random_i32_functions() {
+        let inputs = [0, 1, 0x7fff_ffff, 0x8000_0000, 0xffff_ffff, 0xff, 0x100];
+        check_random_functions(I32, 0x9abc_def0, 200, &inputs);
+    }
+
+    #[test]
+    fn random_i128_functions() {
+        let inputs = [0, 1, u128::MAX, 1 << 127, (1 << 127) - 1, u64::MAX.into()];
+        check_random_functions(I128, 0x0fed_cba9, 200, &inputs);
+    }
+
+    /// Check the operations on ranges against sets of `i8` values.
+    #[test]
+    fn range_operations() {
+        let mut rng = Rng(0x5555_aaaa);
+        let random_range = |rng: &mut Rng| ValueRange {
+            bits: 8,
+            start: rng.constant(8),
+            extent: if rng.below(4) == 0 {
+                rng.below(4) as u128
+            } else {
+                rng.constant(8)
+            },
+        };
+        for _ in 0..2000 {
+            let a = random_range(&mut rng);
+            let b = random_range(&mut rng);
+            let intersection = a.intersect(b);
+            let union = a.union(b);
+            let mut both = 0;
+            for value in 0..=255 {
+                let in_both = a.contains(value) && b.contains(value);
+                both += u128::from(in_both);
+                if in_both {
+                    assert!(intersection.contains(value), "{:?} & {:?}", a, b);
+                }
+                if a.contains(value) || b.contains(value) {
+                    assert!(union.contains(value), "{:?} | {:?}", a, b);
+                }
+            }
+            // Where the values in both form one interval, the intersection is exact.
+            let one_interval = !(a.contains(b.start) && b.contains(a.start)) || a.start == b.start;
+            if both > 0 && one_interval {
+                assert_eq!(intersection.extent + 1, both, "{:?} & {:?}", a, b);
+            }
+            assert!(a.umin() <= a.umax() && a.smin() <= a.smax());
+            for value in [a.umin(), a.umax(), a.smin() as u128, a.smax() as u128] {
+                assert!(a.contains(value));
+            }
+        }
+    }
+
+    /// Build `block0(v0: ty)` with `build` adding its instructions, and return whatever `build`
+    /// returns with the facts about the function.
+    fn with_function<T>(
+        ty: Type,
+        build: impl FnOnce(&mut FuncCursor, Value) -> T,
+        check: impl FnOnce(&ValueFacts, T),
+    ) {
+        let mut func = Function::new();
+        let block0 = func.dfg.make_block();
+        let param = func.dfg.append_block_param(block0, ty);
+        let mut pos = FuncCursor::new(&mut func);
+        pos.insert_block(block0);
+        let result = build(&mut pos, param);
+        let cfg = ControlFlowGraph::with_function(&func);
+        let domtree = DominatorTree::with_function(&func, &cfg);
+        check(&ValueFacts::new(&func, &domtree), result);
+    }
+
+    #[test]
+    fn wrapping_range() {
+        with_function(
+            I8,
+            |pos, x| {
+                let block1 = pos.func.dfg.make_block();
+                let block2 = pos.func.dfg.make_block();
+                let cond = pos.ins().icmp_imm(IntCC::UnsignedGreaterThan, x, 250);
+                pos.ins().brif(cond, block1, &[], block2, &[]);
+                pos.insert_block(block1);
+                let y = pos.ins().iadd_imm(x, 3);
+                let ret = pos.ins().return_(&[y]);
+                pos.insert_block(block2);
+                let z = pos.ins().iadd_imm(x, 3);
+                let ret2 = pos.ins().return_(&[z]);
+                (x, y, ret, z, ret2)
+            },
+            |facts, (x, y, ret, z, ret2)| {
+                assert_eq!(facts.range_of(x, ret), ValueRange::unsigned(I8, 251, 255));
+                let range = facts.range_of(y, ret);
+                assert_eq!((range.start(), range.end()), (254, 2));
+                assert_eq!((range.umin(), range.umax()), (0, 255));
+                assert_eq!((range.smin(), range.smax()), (-2, 2));
+                assert!(range.contains(0) && !range.contains(3) && !range.contains(253));
+                assert_eq!(facts.range_of(z, ret2), ValueRange::unsigned(I8, 3, 253));
+            },
+        );
+    }
+
+    #[test]
+    fn trap_guards() {
+        with_function(
+            I32,
+            |pos, x| {
+                let before = pos.ins().trapz(x, TrapCode::User(0));
+                let cond = pos.ins().icmp_imm(IntCC::UnsignedLessThan, x, 100);
+                let between = pos.ins().trapz(cond, TrapCode::User(0));
+                let after = pos.ins().return_(&[x]);
+                (x, before, between, after)
+            },
+            |facts, (x, before, between, after)| {
+                assert!(facts.range_of(x, before).is_full());
+                assert!(facts.is_nonzero(x, between));
+                assert_eq!(facts.range_of(x, after), ValueRange::unsigned(I32, 1, 99));
+            },
+        );
+    }
+
+    #[test]
+    fn i128_facts() {
+        with_function(
+            I64,
+            |pos, x| {
+                let wide = pos.ins().uextend(I128, x);
+                let shifted = pos.ins().ishl_imm(wide, 64);
+                let signed = pos.ins().sextend(I128, x);
+                let high = pos.ins().sshr_imm(signed, 36);
+                let sum = pos.ins().iadd(shifted, wide);
+                let ret = pos.ins().return_(&[sum]);
+                (wide, shifted, high, sum, ret)
+            },
+            |facts, (wide, shifted, high, sum, ret)| {
+                let low = u128::from(u64::MAX);
+                assert_eq!(facts.known_bits(wide).zeros(), !low);
+                assert_eq!(
+                    facts.range_of(wide, ret),
+                    ValueRange::unsigned(I128, 0, low)
+                );
+                assert_eq!(facts.known_bits(shifted).zeros(), low);
+                assert_eq!(
+                    facts.range_of(high, ret),
+                    ValueRange::signed(I128, -1 << 27, (1 << 27) - 1)
+                );
+                // The two halves don't overlap, so nothing is known about the sum.
+                assert_eq!(facts.known_bits(sum).zeros(), 0);
+                assert!(facts.range_of(sum, ret).is_full());
+            },
+        );
+    }
+
+    #[test]
+    fn known_bits_of_arithmetic() {
+        with_function(
+            I32,
+            |pos, x| {
+                let aligned = pos.ins().band_imm(x, !15);
+                let plus = pos.ins().iadd_imm(aligned, 3);
+                let times = pos.ins().imul(aligned, aligned);
+                let ret = pos.ins().return_(&[plus]);
+                (plus, times, ret)
+            },
+            |facts, (plus, times, _)| {
+                let plus = facts.known_bits(plus);
+                assert_eq!((plus.zeros() & 15, plus.ones()), (12, 3));
+                assert_eq!(facts.known_bits(times).zeros(), 0xff);
+            },
+        );
+    }
+}
@@ -0,0 +1,132 @@
+//! Limits on the work done to compile a single function.
+//!
+//! Some inputs, such as functions with huge blocks under high register pressure, make parts of
+//! the compiler take time superlinear in the size of the function. Embedders compiling untrusted
+//! input can bound the work done on each function with a [`CompileBudget`], which makes
+//! compilation fail with [`CodegenError::BudgetExceeded`] once it is used up.
+
+use crate::result::{CodegenError, CodegenResult};
+use crate::timing::Pass;
+
+/// Limits on the work done by [`Context::compile`](crate::Context::compile) on a single function.
+///
+/// The budget is checked between the passes of the compilation, while emitting each block, and
+/// for each trap, constant and branch fixup placed by branch relaxation. A pass which starts
+/// within the budget may thus overrun it.
+///
+/// The register allocator is the exception to checks within passes: it can't be interrupted, so
+/// it is charged one unit per instruction before it starts, and the iterations of its
+/// superlinear loops as reported in its statistics right after it returns. `max_millis` is also
+/// only checked at those two points, so a function can still spend far longer in the register
+/// allocator than the budget suggests; bound the size of the functions compiled as well to bound
+/// that time.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CompileBudget {
+    /// The number of instructions the passes may process, summed over all passes.
+    ///
+    /// Each pass over the IR uses up one unit per IR instruction of the function, and each pass
+    /// of the backend one unit per instruction it processes. Branch relaxation and the register
+    /// allocator also use up one unit per iteration of their inner loops.
+    pub max_insts_processed: u64,
+
+    /// The wall-clock time the compilation may take, in milliseconds.
+    ///
+    /// The clock is only read now and then, and only with the `std` feature.
+    pub max_millis: Option<u64>,
+}
+
+impl Default for CompileBudget {
+    /// An unlimited budget.
+    fn default() -> Self {
+        Self {
+            max_insts_processed: u64::MAX,
+            max_millis: None,
+        }
+    }
+}
+
+/// The number of charges within a pass between two reads of the clock.
+const CHARGES_PER_CLOCK_CHECK: u32 = 64;
+
+/// The remainder of a [`CompileBudget`] during a compilation.
+pub(crate) struct BudgetTracker {
+    fuel: u64,
+    #[cfg(feature = "std")]
+    deadline: Option<std::time::Instant>,
+    charges_until_clock_check: u32,
+}
+
+impl Default for BudgetTracker {
+    /// A tracker which never runs out.
+    fn default() -> Self {
+        Self::new(&CompileBudget::default())
+    }
+}
+
+impl BudgetTracker {
+    /// Start tracking `budget`.
+    pub(crate) fn new(budget: &CompileBudget) -> Self {
+        Self {
+            fuel: budget.max_insts_processed,
+            #[cfg(feature = "std")]
+            deadline: budget
+                .max_millis
+                .map(|millis| std::time::Instant::now() + std::time::Duration::from_millis(millis)),
+            charges_until_clock_check: CHARGES_PER_CLOCK_CHECK,
+        }
+    }
+
+    /// Charge the start of the pass `phase`, which processes `insts` instructions.
+    pub(crate) fn start_pass(&mut self, phase: Pass, insts: usize) -> CodegenResult<()> {
+        self.charge_fuel(phase, insts)?;
+        self.check_clock(phase)
+    }
+
+    /// Check the clock at the end of the pass `phase`, for passes which can't be charged while
+    /// they run.
+    pub(crate) fn end_pass(&mut self, phase: Pass) -> CodegenResult<()> {
+        self.check_clock(phase)
+    }
+
+    /// Charge `insts` instructions processed within the pass `phase`.
+    ///
+    /// This only reads the clock every so often, so that it can be called for each unit of work
+    /// of a pass.
+    #[inline]
+    pub(crate) fn charge(&mut self, phase: Pass, insts: usize) -> CodegenResult<()> {
+        self.charge_fuel(phase, insts)?;
+        self.charges_until_clock_check -= 1;
+        if self.charges_until_clock_check == 0 {
+            self.charges_until_clock_check = CHARGES_PER_CLOCK_CHECK;
+            self.check_clock(phase)?;
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn charge_fuel(&mut self, phase: Pass, insts: usize) -> CodegenResult<()> {
+        match self.fuel.checked_sub(insts as u64) {
+            Some(fuel) => {
+                self.fuel = fuel;
+                Ok(())
+            }
+            None => {
+                self.fuel = 0;
+                trace_event!(phase = ?phase, "compile budget exhausted");
+                Err(CodegenError::BudgetExceeded { phase })
+            }
+        }
+    }
+
+    fn check_clock(&mut self, phase: Pass) -> CodegenResult<()> {
+        #[cfg(feature = "std")]
+        if let Some(deadline) = self.deadline {
+            if std::time::Instant::now() >= deadline {
+                trace_event!(phase = ?phase, "compile deadline passed");
+                return Err(CodegenError::BudgetExceeded { phase });
+            }
+        }
+        let _ = phase;
+        Ok(())
+    }
+}
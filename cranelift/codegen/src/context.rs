@@ -10,19 +10,28 @@
 //! single ISA instance.
 
 use crate::alias_analysis::AliasAnalysis;
+use crate::budget::{BudgetTracker, CompileBudget};
+use crate::coverage::{do_block_coverage, CoverageConfig};
 use crate::dce::do_dce;
 use crate::dominator_tree::DominatorTree;
 use crate::egraph::EgraphPass;
 use crate::flowgraph::ControlFlowGraph;
 use crate::ir::Function;
+use crate::isa::support::verify_supported;
 use crate::isa::TargetIsa;
 use crate::legalizer::simple_legalize;
 use crate::loop_analysis::LoopAnalysis;
+use crate::loop_unrolling::do_unroll_loops;
 use crate::machinst::{CompiledCode, CompiledCodeStencil};
 use crate::nan_canonicalization::do_nan_canonicalization;
+use crate::pressure_scheduling::do_pressure_scheduling;
+use crate::redundant_checks::do_remove_redundant_checks;
 use crate::remove_constant_phis::do_remove_constant_phis;
 use crate::result::{CodegenResult, CompileResult};
+use crate::session::CompilationSession;
 use crate::settings::{FlagsOrIsa, OptLevel};
+use crate::simplify_block_params::do_simplify_block_params;
+use crate::timing::Pass;
 use crate::trace;
 use crate::unreachable_code::eliminate_unreachable_code;
 use crate::verifier::{verify_context, VerifierErrors, VerifierResult};
@@ -54,6 +63,30 @@ pub struct Context {
 
     /// Flag: do we want a disassembly with the CompiledCode?
     pub want_disasm: bool,
+
+    /// Flag: do we want a listing of the lowered VCode with the CompiledCode?
+    pub want_vcode: bool,
+
+    /// Flag: do we want the offset of each instruction with the CompiledCode?
+    pub collect_inst_offsets: bool,
+
+    /// If set, `clear()` releases the unused memory of the function if its DFG uses more than
+    /// this many bytes.
+    pub shrink_threshold: Option<usize>,
+
+    /// Block coverage instrumentation to insert, if any.
+    pub block_coverage: Option<CoverageConfig>,
+
+    /// Limits on the work done by each compilation.
+    pub compile_budget: CompileBudget,
+
+    /// Salt mixed into incremental cache keys.
+    #[cfg(feature = "incremental-cache")]
+    pub(crate) incremental_cache_salt: Vec<u8>,
+
+    /// Statistics accumulated by `compile_with_cache`.
+    #[cfg(feature = "incremental-cache")]
+    pub(crate) incremental_cache_stats: crate::incremental_cache::CacheStats,
 }
 
 impl Context {
@@ -77,17 +110,37 @@ impl Context {
             loop_analysis: LoopAnalysis::new(),
             compiled_code: None,
             want_disasm: false,
+            want_vcode: false,
+            collect_inst_offsets: false,
+            shrink_threshold: None,
+            block_coverage: None,
+            compile_budget: CompileBudget::default(),
+            #[cfg(feature = "incremental-cache")]
+            incremental_cache_salt: Vec::new(),
+            #[cfg(feature = "incremental-cache")]
+            incremental_cache_stats: Default::default(),
         }
     }
 
     /// Clear all data structures in this context.
+    ///
+    /// The memory of the data structures is kept for compiling the next function, unless a
+    /// threshold set with [`Context::set_shrink_threshold`] is exceeded.
     pub fn clear(&mut self) {
         self.func.clear();
+        if let Some(threshold) = self.shrink_threshold {
+            if self.func.dfg.memory_usage().total() > threshold {
+                self.func.shrink_to_fit();
+            }
+        }
         self.cfg.clear();
         self.domtree.clear();
         self.loop_analysis.clear();
         self.compiled_code = None;
         self.want_disasm = false;
+        self.want_vcode = false;
+        self.collect_inst_offsets = false;
+        self.block_coverage = None;
     }
 
     /// Returns the compilation result for this function, available after any `compile` function
@@ -102,6 +155,52 @@ impl Context {
         self.want_disasm = val;
     }
 
+    /// Set the flag to request a structured listing of the lowered VCode,
+    /// available through [`CompiledCode::vcode`], when compiling with a
+    /// `MachBackend` backend.
+    pub fn set_want_vcode(&mut self, val: bool) {
+        self.want_vcode = val;
+    }
+
+    /// Set the flag to request the offset and length of each machine
+    /// instruction, available through [`CompiledCode::inst_offsets`], when
+    /// compiling with a `MachBackend` backend.
+    pub fn set_collect_inst_offsets(&mut self, val: bool) {
+        self.collect_inst_offsets = val;
+    }
+
+    /// Instrument the function with a counter increment at the entry of each block when compiling
+    /// it, as configured by `config`. The index of the counter of each block is available through
+    /// [`CompiledCode::block_coverage`].
+    ///
+    /// The instrumentation is inserted before any optimization, so the counters describe the
+    /// blocks of the function as given; the counters of blocks removed as unreachable stay zero.
+    pub fn set_block_coverage(&mut self, config: CoverageConfig) {
+        self.block_coverage = Some(config);
+    }
+
+    /// Make `clear()` release the memory of the function being cleared if its DFG uses more than
+    /// `threshold` bytes, as reported by
+    /// [`DataFlowGraph::memory_usage`](crate::ir::DataFlowGraph::memory_usage), or never release
+    /// it if `threshold` is `None`, the default. Unlike the other flags, this one is kept by
+    /// `clear()`.
+    ///
+    /// Contexts which are reused to compile many functions keep the memory needed for the
+    /// largest one, which matters when an embedder keeps many contexts around.
+    pub fn set_shrink_threshold(&mut self, threshold: Option<usize>) {
+        self.shrink_threshold = threshold;
+    }
+
+    /// Limit the work done by each compilation to `budget`, beyond which compiling fails with
+    /// [`CodegenError::BudgetExceeded`](crate::CodegenError::BudgetExceeded). The default is
+    /// unlimited. Like the shrink threshold, the budget is kept by `clear()`.
+    ///
+    /// A compilation which runs out of budget leaves the function partially transformed, so
+    /// `clear()` the context before compiling the next function.
+    pub fn set_compile_budget(&mut self, budget: CompileBudget) {
+        self.compile_budget = budget;
+    }
+
     /// Compile the function, and emit machine code into a `Vec<u8>`.
     ///
     /// Run the function through all the passes necessary to generate
@@ -137,13 +236,57 @@ impl Context {
         isa: &dyn TargetIsa,
         ctrl_plane: &mut ControlPlane,
     ) -> CodegenResult<CompiledCodeStencil> {
+        self.compile_stencil_in(&mut CompilationSession::new(), isa, ctrl_plane)
+    }
+
+    /// Compile the function into a stencil, reusing the scratch allocations of `session`.
+    pub(crate) fn compile_stencil_in(
+        &mut self,
+        session: &mut CompilationSession,
+        isa: &dyn TargetIsa,
+        ctrl_plane: &mut ControlPlane,
+    ) -> CodegenResult<CompiledCodeStencil> {
+        trace_span!(
+            "compile",
+            function = %self.func.name,
+            insts = self.func.dfg.num_insts(),
+            blocks = self.func.dfg.num_blocks(),
+            isa = %isa.triple(),
+        );
         let _tt = timing::compile();
+        let mut budget = BudgetTracker::new(&self.compile_budget);
 
         self.verify_if(isa)?;
 
-        self.optimize(isa)?;
-
-        isa.compile_function(&self.func, &self.domtree, self.want_disasm, ctrl_plane)
+        let block_coverage = match self.block_coverage {
+            Some(config) => {
+                budget.start_pass(Pass::block_coverage, self.func.dfg.num_insts())?;
+                let pointer_type = self.func.global_values[config.counters_ptr_gv].global_type(isa);
+                let counters = do_block_coverage(&mut self.func, &config, pointer_type);
+                self.verify_if(isa)?;
+                counters
+            }
+            None => Vec::new(),
+        };
+
+        // The legalizer may rewrite unsupported instructions into ones which aren't checked, and
+        // may introduce instructions of its own, so check both before and after optimizing.
+        verify_supported(&self.func, isa)?;
+        self.optimize_within(isa, &mut budget)?;
+        verify_supported(&self.func, isa)?;
+
+        session.budget = budget;
+        let mut stencil = isa.compile_function(
+            &self.func,
+            &self.domtree,
+            self.want_disasm,
+            self.want_vcode,
+            self.collect_inst_offsets,
+            session,
+            ctrl_plane,
+        )?;
+        stencil.block_coverage = block_coverage;
+        Ok(stencil)
     }
 
     /// Optimize the function, performing all compilation steps up to
@@ -152,6 +295,15 @@ impl Context {
     ///
     /// Public only for testing purposes.
     pub fn optimize(&mut self, isa: &dyn TargetIsa) -> CodegenResult<()> {
+        self.optimize_within(isa, &mut BudgetTracker::default())
+    }
+
+    /// Optimize the function, charging each pass to `budget`.
+    fn optimize_within(
+        &mut self,
+        isa: &dyn TargetIsa,
+        budget: &mut BudgetTracker,
+    ) -> CodegenResult<()> {
         log::debug!(
             "Number of CLIF instructions to optimize: {}",
             self.func.dfg.num_insts()
@@ -170,22 +322,45 @@ impl Context {
 
         self.compute_cfg();
         if isa.flags().enable_nan_canonicalization() {
+            budget.start_pass(Pass::canonicalize_nans, self.func.dfg.num_insts())?;
             self.canonicalize_nans(isa)?;
         }
 
+        budget.start_pass(Pass::legalize, self.func.dfg.num_insts())?;
         self.legalize(isa)?;
 
+        budget.start_pass(Pass::domtree, self.func.dfg.num_blocks())?;
         self.compute_domtree();
+        budget.start_pass(Pass::unreachable_code, self.func.dfg.num_blocks())?;
         self.eliminate_unreachable_code(isa)?;
 
         if opt_level != OptLevel::None {
+            budget.start_pass(Pass::dce, self.func.dfg.num_insts())?;
             self.dce(isa)?;
         }
 
+        if opt_level != OptLevel::None && isa.flags().enable_loop_unrolling() {
+            budget.start_pass(Pass::loop_unrolling, self.func.dfg.num_insts())?;
+            self.unroll_loops(isa)?;
+        }
+
+        budget.start_pass(Pass::remove_constant_phis, self.func.dfg.num_insts())?;
         self.remove_constant_phis(isa)?;
 
         if opt_level != OptLevel::None {
-            self.egraph_pass()?;
+            budget.start_pass(Pass::simplify_block_params, self.func.dfg.num_blocks())?;
+            self.simplify_block_params(isa)?;
+            if isa.flags().enable_redundant_check_elimination() {
+                budget.start_pass(Pass::redundant_checks, self.func.dfg.num_insts())?;
+                self.remove_redundant_checks(isa)?;
+            }
+            budget.start_pass(Pass::egraph, self.func.dfg.num_insts())?;
+            self.egraph_pass_for_isa(isa)?;
+        }
+
+        if isa.flags().enable_pressure_scheduling() {
+            budget.start_pass(Pass::pressure_scheduling, self.func.dfg.num_insts())?;
+            self.schedule_for_pressure(isa)?;
         }
 
         Ok(())
@@ -195,16 +370,27 @@ impl Context {
     ///
     /// Run the function through all the passes necessary to generate code for the target ISA
     /// represented by `isa`. This does not include the final step of emitting machine code into a
-    /// code sink.
+    /// code sink. The allocations of the backend are made anew for each function; use a
+    /// [`CompilationSession`] to reuse them.
     ///
     /// Returns information about the function's code and read-only data.
     pub fn compile(
         &mut self,
         isa: &dyn TargetIsa,
         ctrl_plane: &mut ControlPlane,
+    ) -> CompileResult<&CompiledCode> {
+        self.compile_in(&mut CompilationSession::new(), isa, ctrl_plane)
+    }
+
+    /// Compile the function, reusing the scratch allocations of `session`.
+    pub(crate) fn compile_in(
+        &mut self,
+        session: &mut CompilationSession,
+        isa: &dyn TargetIsa,
+        ctrl_plane: &mut ControlPlane,
     ) -> CompileResult<&CompiledCode> {
         let stencil = self
-            .compile_stencil(isa, ctrl_plane)
+            .compile_stencil_in(session, isa, ctrl_plane)
             .map_err(|error| CompileError {
                 inner: error,
                 func: &self.func,
@@ -264,6 +450,24 @@ impl Context {
         Ok(())
     }
 
+    /// Unroll the innermost loops of the function counted by an induction variable, fully if
+    /// their trip count is known and small enough, and otherwise partially if the flags ask for it.
+    pub fn unroll_loops<'a, FOI: Into<FlagsOrIsa<'a>>>(&mut self, fisa: FOI) -> CodegenResult<()> {
+        let fisa = fisa.into();
+        let budget = usize::from(fisa.flags.loop_unroll_budget());
+        let factor = u32::from(fisa.flags.loop_partial_unroll_factor());
+        do_unroll_loops(
+            &mut self.func,
+            &mut self.cfg,
+            &mut self.domtree,
+            &mut self.loop_analysis,
+            budget,
+            factor,
+        );
+        self.verify_if(fisa)?;
+        Ok(())
+    }
+
     /// Perform constant-phi removal on the function.
     pub fn remove_constant_phis<'a, FOI: Into<FlagsOrIsa<'a>>>(
         &mut self,
@@ -274,6 +478,43 @@ impl Context {
         Ok(())
     }
 
+    /// Replace the parameters of blocks with a single predecessor with aliases of their
+    /// arguments, and thread jumps through blocks containing nothing else.
+    pub fn simplify_block_params<'a, FOI: Into<FlagsOrIsa<'a>>>(
+        &mut self,
+        fisa: FOI,
+    ) -> CodegenResult<()> {
+        if do_simplify_block_params(&mut self.func, &mut self.cfg, &self.domtree) {
+            self.compute_domtree();
+        }
+        self.verify_if(fisa)?;
+        Ok(())
+    }
+
+    /// Remove the comparisons, branches, conditional traps and extensions of the function whose
+    /// outcome is known.
+    pub fn remove_redundant_checks<'a, FOI: Into<FlagsOrIsa<'a>>>(
+        &mut self,
+        fisa: FOI,
+    ) -> CodegenResult<()> {
+        if do_remove_redundant_checks(&mut self.func, &self.domtree) {
+            self.compute_cfg();
+            self.compute_domtree();
+            eliminate_unreachable_code(&mut self.func, &mut self.cfg, &self.domtree);
+        }
+        self.verify_if(fisa)?;
+        Ok(())
+    }
+
+    /// Reorder the instructions within each block of the function to reduce register pressure.
+    pub fn schedule_for_pressure<'a, FOI: Into<FlagsOrIsa<'a>>>(
+        &mut self,
+        fisa: FOI,
+    ) -> CodegenResult<()> {
+        do_pressure_scheduling(&mut self.func);
+        self.verify_if(fisa)
+    }
+
     /// Perform NaN canonicalizing rewrites on the function.
     pub fn canonicalize_nans(&mut self, isa: &dyn TargetIsa) -> CodegenResult<()> {
         do_nan_canonicalization(&mut self.func);
@@ -288,6 +529,7 @@ impl Context {
         self.loop_analysis.clear();
 
         // Run some specific legalizations only.
+        let _tt = timing::legalize();
         simple_legalize(&mut self.func, &mut self.cfg, isa);
         self.verify_if(isa)
     }
@@ -345,7 +587,20 @@ impl Context {
     }
 
     /// Run optimizations via the egraph infrastructure.
+    ///
+    /// Rewrites that depend on target cost hints are skipped; use
+    /// [`Context::egraph_pass_for_isa`] to enable them.
     pub fn egraph_pass(&mut self) -> CodegenResult<()> {
+        self.run_egraph_pass(None)
+    }
+
+    /// Run optimizations via the egraph infrastructure, consulting `isa` for
+    /// the cost hints of target-dependent rewrites.
+    pub fn egraph_pass_for_isa(&mut self, isa: &dyn TargetIsa) -> CodegenResult<()> {
+        self.run_egraph_pass(Some(isa))
+    }
+
+    fn run_egraph_pass(&mut self, isa: Option<&dyn TargetIsa>) -> CodegenResult<()> {
         let _tt = timing::egraph();
 
         trace!(
@@ -359,9 +614,17 @@ impl Context {
             &self.domtree,
             &self.loop_analysis,
             &mut alias_analysis,
+            isa,
         );
         pass.run();
         log::debug!("egraph stats: {:?}", pass.stats);
+        trace_event!(
+            skeleton_insts = pass.stats.skeleton_inst,
+            pure_insts = pass.stats.pure_inst,
+            rewrites = pass.stats.rewrite_rule_invoked,
+            rewrite_depth_limits = pass.stats.rewrite_depth_limit,
+            "egraph optimized"
+        );
         trace!("After egraph optimization:\n{}", self.func.display());
         Ok(())
     }
@@ -0,0 +1,120 @@
+//! Block coverage instrumentation.
+//!
+//! Inserts an increment of a counter in a host-provided array at the entry of each basic block,
+//! so that the host can tell which blocks of the generated code ran, and how often. This is
+//! enabled with [`Context::set_block_coverage`](crate::Context::set_block_coverage).
+
+use crate::cursor::{Cursor, FuncCursor};
+use crate::ir::condcodes::IntCC;
+use crate::ir::{types, Block, Function, GlobalValue, InstBuilder, MemFlags, Type, Value};
+use crate::timing;
+use alloc::vec::Vec;
+
+/// The kind of counter which is incremented at each block entry.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum CoverageCounter {
+    /// An 8-bit counter which stops at 255, which is enough to tell blocks which ran a few times
+    /// from blocks which ran often, in little memory.
+    #[default]
+    Saturating8,
+    /// A 64-bit counter which wraps on overflow.
+    Wrapping64,
+}
+
+impl CoverageCounter {
+    /// The size of a counter in bytes.
+    pub fn bytes(self) -> u32 {
+        match self {
+            Self::Saturating8 => 1,
+            Self::Wrapping64 => 8,
+        }
+    }
+
+    fn ty(self) -> Type {
+        match self {
+            Self::Saturating8 => types::I8,
+            Self::Wrapping64 => types::I64,
+        }
+    }
+}
+
+/// Configuration of block coverage instrumentation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CoverageConfig {
+    /// A global value of the function being compiled, holding the address of the counter array.
+    ///
+    /// The array must have a counter for each block of the function, starting from the one at
+    /// `index_base`, and be aligned to the size of a counter.
+    pub counters_ptr_gv: GlobalValue,
+    /// The index of the counter of the first block of the function in the array.
+    pub index_base: u32,
+    /// The kind of the counters of the array.
+    pub counter: CoverageCounter,
+}
+
+impl CoverageConfig {
+    /// Count the blocks of a function in an array of saturating 8-bit counters at
+    /// `counters_ptr_gv`, starting from the counter at `index_base`.
+    pub fn new(counters_ptr_gv: GlobalValue, index_base: u32) -> Self {
+        Self {
+            counters_ptr_gv,
+            index_base,
+            counter: CoverageCounter::default(),
+        }
+    }
+}
+
+/// Insert a counter increment at the entry of each block of `func`, and return the index of the
+/// counter of each block, in layout order.
+///
+/// Blocks are assigned consecutive counters in layout order. `pointer_type` is the type of
+/// `config.counters_ptr_gv`.
+pub fn do_block_coverage(
+    func: &mut Function,
+    config: &CoverageConfig,
+    pointer_type: Type,
+) -> Vec<(Block, u32)> {
+    let _tt = timing::block_coverage();
+    let ty = config.counter.ty();
+    // The counters are written by the generated code only, and the host provides an aligned
+    // array for them.
+    let flags = MemFlags::trusted();
+
+    let mut counters = Vec::new();
+    let mut pos = FuncCursor::new(func);
+    while let Some(block) = pos.next_block() {
+        let index = config
+            .index_base
+            .checked_add(counters.len() as u32)
+            .expect("too many coverage counters");
+        counters.push((block, index));
+
+        // Insert the increment before the first instruction of the block.
+        pos.goto_first_insertion_point(block);
+        let base = pos.ins().global_value(pointer_type, config.counters_ptr_gv);
+        let bytes = config.counter.bytes();
+        let (addr, offset) = match i32::try_from(u64::from(index) * u64::from(bytes)) {
+            Ok(offset) => (base, offset),
+            Err(_) => {
+                let offset = i64::from(index) * i64::from(bytes);
+                (pos.ins().iadd_imm(base, offset), 0)
+            }
+        };
+        let old = pos.ins().load(ty, flags, addr, offset);
+        let new = increment(&mut pos, config.counter, old);
+        pos.ins().store(flags, new, addr, offset);
+    }
+    counters
+}
+
+/// Increment the counter `old` of the given kind.
+fn increment(pos: &mut FuncCursor, counter: CoverageCounter, old: Value) -> Value {
+    match counter {
+        CoverageCounter::Saturating8 => {
+            // `icmp` results are 1 if true, so this adds 1 unless the counter is saturated.
+            let below_max = pos.ins().icmp_imm(IntCC::NotEqual, old, 0xff);
+            pos.ins().iadd(old, below_max)
+        }
+        CoverageCounter::Wrapping64 => pos.ins().iadd_imm(old, 1),
+    }
+}
@@ -163,15 +163,22 @@ impl DataValue {
 
     /// Read a [DataValue] from a slice using a given [Type] with native-endian byte order.
     ///
+    /// Reference types are read as integers of the same width.
+    ///
     /// # Panics:
     ///
-    /// Panics if the slice does not have enough space to accommodate the [DataValue]
+    /// Panics if the slice does not have enough space to accommodate the [DataValue], or if no
+    /// [DataValue] can represent `ty` (e.g. vectors narrower than 64 bits).
     pub fn read_from_slice_ne(src: &[u8], ty: Type) -> Self {
         match ty {
             types::I8 => DataValue::I8(i8::from_ne_bytes(src[..1].try_into().unwrap())),
             types::I16 => DataValue::I16(i16::from_ne_bytes(src[..2].try_into().unwrap())),
-            types::I32 => DataValue::I32(i32::from_ne_bytes(src[..4].try_into().unwrap())),
-            types::I64 => DataValue::I64(i64::from_ne_bytes(src[..8].try_into().unwrap())),
+            types::I32 | types::R32 => {
+                DataValue::I32(i32::from_ne_bytes(src[..4].try_into().unwrap()))
+            }
+            types::I64 | types::R64 => {
+                DataValue::I64(i64::from_ne_bytes(src[..8].try_into().unwrap()))
+            }
             types::I128 => DataValue::I128(i128::from_ne_bytes(src[..16].try_into().unwrap())),
             types::F32 => DataValue::F32(Ieee32::with_bits(u32::from_ne_bytes(
                 src[..4].try_into().unwrap(),
@@ -185,10 +192,10 @@ impl DataValue {
                 } else if ty.bytes() == 8 {
                     DataValue::V64(src[..8].try_into().unwrap())
                 } else {
-                    unimplemented!()
+                    unimplemented!("no DataValue can hold a value of type {}", ty)
                 }
             }
-            _ => unimplemented!(),
+            _ => unimplemented!("no DataValue can hold a value of type {}", ty),
         }
     }
 
@@ -213,13 +220,13 @@ impl DataValue {
     /// Write a [DataValue] to a memory location in native-endian byte order.
     pub unsafe fn write_value_to(&self, p: *mut u128) {
         let size = self.ty().bytes() as usize;
-        self.write_to_slice_ne(std::slice::from_raw_parts_mut(p as *mut u8, size));
+        self.write_to_slice_ne(core::slice::from_raw_parts_mut(p as *mut u8, size));
     }
 
     /// Read a [DataValue] from a memory location using a given [Type] in native-endian byte order.
     pub unsafe fn read_value_from(p: *const u128, ty: Type) -> Self {
         DataValue::read_from_slice_ne(
-            std::slice::from_raw_parts(p as *const u8, ty.bytes() as usize),
+            core::slice::from_raw_parts(p as *const u8, ty.bytes() as usize),
             ty,
         )
     }
@@ -254,6 +261,7 @@ pub enum DataValueCastFailure {
 
 // This is manually implementing Error and Display instead of using thiserror to reduce the amount
 // of dependencies used by Cranelift.
+#[cfg(feature = "std")]
 impl std::error::Error for DataValueCastFailure {}
 
 impl Display for DataValueCastFailure {
@@ -383,4 +391,31 @@ mod test {
             DataValueCastFailure::TryInto(types::I8X16, types::I32)
         );
     }
+
+    #[test]
+    fn native_byte_round_trip() {
+        let values = [
+            DataValue::I8(-2),
+            DataValue::I16(0x1234),
+            DataValue::I32(-0x1234_5678),
+            DataValue::I64(0x0123_4567_89ab_cdef),
+            DataValue::I128(-0x0123_4567_89ab_cdef_0123_4567_89ab_cdef),
+            DataValue::F32(Ieee32::with_float(1.5)),
+            DataValue::F64(Ieee64::with_float(-2.25)),
+            DataValue::V128([1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16]),
+            DataValue::V64([1, 2, 3, 4, 5, 6, 7, 8]),
+        ];
+        for value in values {
+            let mut bytes = [0; 16];
+            value.write_to_slice_ne(&mut bytes);
+            assert_eq!(DataValue::read_from_slice_ne(&bytes, value.ty()), value);
+        }
+
+        let mut bytes = [0; 16];
+        DataValue::I64(42).write_to_slice_ne(&mut bytes);
+        assert_eq!(
+            DataValue::read_from_slice_ne(&bytes, types::R64),
+            DataValue::I64(42)
+        );
+    }
 }
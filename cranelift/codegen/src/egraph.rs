@@ -11,15 +11,16 @@ use crate::inst_predicates::{is_mergeable_for_egraph, is_pure_for_egraph};
 use crate::ir::{
     Block, DataFlowGraph, Function, Inst, InstructionData, Type, Value, ValueDef, ValueListPool,
 };
+use crate::isa::TargetIsa;
 use crate::loop_analysis::LoopAnalysis;
 use crate::opts::generated_code::ContextIter;
 use crate::opts::IsleContext;
 use crate::scoped_hash_map::{Entry as ScopedEntry, ScopedHashMap};
 use crate::trace;
 use crate::unionfind::UnionFind;
+use core::hash::Hasher;
 use cranelift_entity::packed_option::ReservedValue;
 use cranelift_entity::SecondaryMap;
-use std::hash::Hasher;
 
 mod cost;
 mod domtree;
@@ -56,6 +57,9 @@ pub struct EgraphPass<'a> {
     /// Loop analysis results, used for built-in LICM during
     /// elaboration.
     loop_analysis: &'a LoopAnalysis,
+    /// The target ISA, consulted for cost hints by some rewrite rules.
+    /// Rules that need a hint don't fire without one.
+    isa: Option<&'a dyn TargetIsa>,
     /// Which canonical Values do we want to rematerialize in each
     /// block where they're used?
     ///
@@ -84,6 +88,7 @@ where
     pub(crate) stats: &'opt mut Stats,
     pub(crate) alias_analysis: &'opt mut AliasAnalysis<'analysis>,
     pub(crate) alias_analysis_state: &'opt mut LastStores,
+    pub(crate) isa: Option<&'opt dyn TargetIsa>,
     // Held locally during optimization of one node (recursively):
     pub(crate) rewrite_depth: usize,
     pub(crate) subsume_values: FxHashSet<Value>,
@@ -226,6 +231,7 @@ where
         const REWRITE_LIMIT: usize = 5;
         if isle_ctx.ctx.rewrite_depth > REWRITE_LIMIT {
             isle_ctx.ctx.stats.rewrite_depth_limit += 1;
+            trace_event!(value = %orig_value, "egraph rewrite depth limit reached");
             return orig_value;
         }
         isle_ctx.ctx.rewrite_depth += 1;
@@ -322,6 +328,10 @@ where
                     self.value_to_opt_value[result] = result;
                     v.insert(result);
                     trace!(" -> inserts as new (no GVN)");
+                    // The instruction is kept, and may trap, which the
+                    // dead-store elimination needs to know. Loads can
+                    // still be forwarded across it, though.
+                    self.alias_analysis.eliminate_dead_stores(self.func, inst);
                     false
                 }
             }
@@ -365,6 +375,7 @@ impl<'a> EgraphPass<'a> {
         domtree: &'a DominatorTree,
         loop_analysis: &'a LoopAnalysis,
         alias_analysis: &'a mut AliasAnalysis<'a>,
+        isa: Option<&'a dyn TargetIsa>,
     ) -> Self {
         let num_values = func.dfg.num_values();
         let domtree_children = DomTreeWithChildren::new(func, domtree);
@@ -373,6 +384,7 @@ impl<'a> EgraphPass<'a> {
             domtree,
             domtree_children,
             loop_analysis,
+            isa,
             alias_analysis,
             stats: Stats::default(),
             eclasses: UnionFind::with_capacity(num_values),
@@ -522,6 +534,7 @@ impl<'a> EgraphPass<'a> {
                             stats: &mut self.stats,
                             alias_analysis: self.alias_analysis,
                             alias_analysis_state: &mut alias_analysis_state,
+                            isa: self.isa,
                         };
 
                         if is_pure_for_egraph(ctx.func, inst) {
@@ -637,7 +650,7 @@ impl<'a> CtxEq<(Type, InstructionData), (Type, InstructionData)> for GVNContext<
 
 impl<'a> CtxHash<(Type, InstructionData)> for GVNContext<'a> {
     fn ctx_hash<H: Hasher>(&self, state: &mut H, (ty, inst): &(Type, InstructionData)) {
-        std::hash::Hash::hash(&ty, state);
+        core::hash::Hash::hash(&ty, state);
         inst.hash(state, self.value_lists, |value| self.union_find.find(value));
     }
 }
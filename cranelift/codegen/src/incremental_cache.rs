@@ -16,9 +16,20 @@
 //! - `try_finish_recompile`, which reads binary blobs serialized with `serialize_compiled`,
 //! re-creating the compilation artifact from those.
 //!
-//! The `CacheStore` trait and `Context::compile_with_cache` method are provided as
+//! The `CacheKvStore` trait and `Context::compile_with_cache` method are provided as
 //! high-level, easy-to-use facilities to make use of that cache, and show an example of how to use
-//! the above three primitives to form a full incremental caching system.
+//! the above three primitives to form a full incremental caching system. Integrating it amounts to:
+//! - implementing `CacheKvStore` on top of your storage (`InMemoryCacheStore` is a reference
+//!   implementation keeping everything in memory);
+//! - optionally calling `Context::set_incremental_cache_salt` with a value identifying the
+//!   producer of the IR (e.g. a frontend version), so that fixing a bug in it invalidates stale
+//!   entries without wiping the store;
+//! - compiling with `Context::compile_with_cache` instead of `Context::compile`, and reading
+//!   `Context::incremental_cache_stats` to see how effective the cache was.
+//!
+//! The cache key covers the function's contents, the target triple, and every shared and
+//! ISA-specific setting with its name, so entries are never shared between differently configured
+//! ISAs.
 
 use core::fmt;
 
@@ -29,7 +40,7 @@ use crate::ir::Function;
 use crate::machinst::{CompiledCode, CompiledCodeStencil};
 use crate::result::CompileResult;
 use crate::{isa::TargetIsa, timing};
-use crate::{trace, CompileError, Context};
+use crate::{trace, CompileError, Context, HashMap};
 use alloc::borrow::{Cow, ToOwned as _};
 use alloc::string::ToString as _;
 use cranelift_control::ControlPlane;
@@ -37,6 +48,9 @@ use cranelift_control::ControlPlane;
 impl Context {
     /// Compile the function, as in `compile`, but tries to reuse compiled artifacts from former
     /// compilations using the provided cache store.
+    ///
+    /// The cache key mixes in the salt set with `set_incremental_cache_salt`, and the outcome of
+    /// the lookup is accumulated into `incremental_cache_stats`.
     pub fn compile_with_cache(
         &mut self,
         isa: &dyn TargetIsa,
@@ -46,11 +60,13 @@ impl Context {
         let cache_key_hash = {
             let _tt = timing::try_incremental_cache();
 
-            let cache_key_hash = compute_cache_key(isa, &self.func);
+            let cache_key_hash =
+                compute_salted_cache_key(isa, &self.func, &self.incremental_cache_salt);
 
             if let Some(blob) = cache_store.get(&cache_key_hash.0) {
                 match try_finish_recompile(&self.func, &blob) {
                     Ok(compiled_code) => {
+                        self.incremental_cache_stats.hits += 1;
                         let info = compiled_code.code_info();
 
                         if isa.flags().enable_incremental_compilation_cache_checks() {
@@ -70,6 +86,7 @@ impl Context {
                 }
             }
 
+            self.incremental_cache_stats.misses += 1;
             cache_key_hash
         };
 
@@ -84,6 +101,8 @@ impl Context {
             let _tt = timing::store_incremental_cache();
             let (stencil, res) = serialize_compiled(stencil);
             if let Ok(blob) = res {
+                self.incremental_cache_stats.stores += 1;
+                self.incremental_cache_stats.bytes_written += blob.len() as u64;
                 cache_store.insert(&cache_key_hash.0, blob);
             }
             stencil
@@ -95,6 +114,43 @@ impl Context {
 
         Ok((compiled_code, false))
     }
+
+    /// Set a salt that is mixed into the cache key of every function compiled with
+    /// `compile_with_cache`.
+    ///
+    /// Changing the salt invalidates all previously stored entries without having to clear the
+    /// cache store, e.g. after a bug fix in the frontend producing the functions. The salt is kept
+    /// across calls to `clear`.
+    pub fn set_incremental_cache_salt(&mut self, salt: &[u8]) {
+        self.incremental_cache_salt.clear();
+        self.incremental_cache_salt.extend_from_slice(salt);
+    }
+
+    /// Returns the cache statistics accumulated by `compile_with_cache` on this context.
+    ///
+    /// The statistics are kept across calls to `clear`; use `reset_incremental_cache_stats` to
+    /// start a new session.
+    pub fn incremental_cache_stats(&self) -> &CacheStats {
+        &self.incremental_cache_stats
+    }
+
+    /// Reset the statistics returned by `incremental_cache_stats`.
+    pub fn reset_incremental_cache_stats(&mut self) {
+        self.incremental_cache_stats = CacheStats::default();
+    }
+}
+
+/// Statistics about the incremental cache lookups done by `Context::compile_with_cache`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Number of functions whose compiled code was reused from the cache store.
+    pub hits: u64,
+    /// Number of functions that had to be compiled because no usable entry was found.
+    pub misses: u64,
+    /// Number of entries inserted into the cache store.
+    pub stores: u64,
+    /// Total size in bytes of the entries inserted into the cache store.
+    pub bytes_written: u64,
 }
 
 /// Backing storage for an incremental compilation cache, when enabled.
@@ -107,12 +163,50 @@ pub trait CacheKvStore {
     fn insert(&mut self, key: &[u8], val: Vec<u8>);
 }
 
+/// A `CacheKvStore` keeping all its entries in memory.
+///
+/// This is mostly useful for tests and as a reference implementation; entries are lost when the
+/// store is dropped.
+#[derive(Default)]
+pub struct InMemoryCacheStore {
+    entries: HashMap<Vec<u8>, Vec<u8>>,
+}
+
+impl InMemoryCacheStore {
+    /// Create an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of entries currently in the store.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether the store has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl CacheKvStore for InMemoryCacheStore {
+    fn get(&self, key: &[u8]) -> Option<Cow<[u8]>> {
+        self.entries
+            .get(key)
+            .map(|val| Cow::Borrowed(val.as_slice()))
+    }
+
+    fn insert(&mut self, key: &[u8], val: Vec<u8>) {
+        self.entries.insert(key.to_vec(), val);
+    }
+}
+
 /// Hashed `CachedKey`, to use as an identifier when looking up whether a function has already been
 /// compiled or not.
 #[derive(Clone, Hash, PartialEq, Eq)]
 pub struct CacheKeyHash([u8; 32]);
 
-impl std::fmt::Display for CacheKeyHash {
+impl core::fmt::Display for CacheKeyHash {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         write!(f, "CacheKeyHash:{:?}", self.0)
     }
@@ -137,6 +231,7 @@ struct CachedFunc {
 struct CacheKey<'a> {
     stencil: &'a FunctionStencil,
     parameters: CompileParameters,
+    salt: &'a [u8],
 }
 
 #[derive(Clone, PartialEq, Hash, serde::Serialize, serde::Deserialize)]
@@ -153,11 +248,9 @@ impl CompileParameters {
             isa: isa.name().to_owned(),
             triple: isa.triple().to_string(),
             flags: isa.flags().to_string(),
-            isa_flags: isa
-                .isa_flags()
-                .into_iter()
-                .map(|v| v.value_string())
-                .collect(),
+            // Include the setting names too: values alone don't tell apart two configurations
+            // that toggle different flags.
+            isa_flags: isa.isa_flags().into_iter().map(|v| v.to_string()).collect(),
         }
     }
 }
@@ -166,10 +259,11 @@ impl<'a> CacheKey<'a> {
     /// Creates a new cache store key for a function.
     ///
     /// This is a bit expensive to compute, so it should be cached and reused as much as possible.
-    fn new(isa: &dyn TargetIsa, f: &'a Function) -> Self {
+    fn new(isa: &dyn TargetIsa, f: &'a Function, salt: &'a [u8]) -> Self {
         CacheKey {
             stencil: &f.stencil,
             parameters: CompileParameters::from_isa(isa),
+            salt,
         }
     }
 }
@@ -178,6 +272,11 @@ impl<'a> CacheKey<'a> {
 ///
 /// Since computing the `CacheKey` is a bit expensive, it should be done as least as possible.
 pub fn compute_cache_key(isa: &dyn TargetIsa, func: &Function) -> CacheKeyHash {
+    compute_salted_cache_key(isa, func, &[])
+}
+
+/// Same as `compute_cache_key`, but mixes a user-provided `salt` into the key.
+pub fn compute_salted_cache_key(isa: &dyn TargetIsa, func: &Function, salt: &[u8]) -> CacheKeyHash {
     use core::hash::{Hash as _, Hasher};
     use sha2::Digest as _;
 
@@ -192,7 +291,7 @@ pub fn compute_cache_key(isa: &dyn TargetIsa, func: &Function) -> CacheKeyHash {
         }
     }
 
-    let cache_key = CacheKey::new(isa, func);
+    let cache_key = CacheKey::new(isa, func, salt);
 
     let mut hasher = Sha256Hasher(sha2::Sha256::new());
     cache_key.hash(&mut hasher);
@@ -254,3 +353,91 @@ pub fn try_finish_recompile(func: &Function, bytes: &[u8]) -> Result<CompiledCod
         Err(err) => Err(RecompileError::Deserialize(err)),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cursor::{Cursor, FuncCursor};
+    use crate::ir::{types, AbiParam, InstBuilder, Signature, UserFuncName};
+    use crate::isa::CallConv;
+    use crate::settings;
+    use core::str::FromStr;
+    use target_lexicon::Triple;
+
+    fn add_one() -> Function {
+        let mut sig = Signature::new(CallConv::SystemV);
+        sig.params.push(AbiParam::new(types::I32));
+        sig.returns.push(AbiParam::new(types::I32));
+        let mut func = Function::with_name_signature(UserFuncName::testcase("add_one"), sig);
+
+        let block = func.dfg.make_block();
+        let arg = func.dfg.append_block_param(block, types::I32);
+        let mut pos = FuncCursor::new(&mut func);
+        pos.insert_block(block);
+        let res = pos.ins().iadd_imm(arg, 1);
+        pos.ins().return_(&[res]);
+
+        func
+    }
+
+    #[test]
+    fn hit_miss_and_salt() {
+        let isa = match crate::isa::lookup(Triple::from_str("x86_64").unwrap()) {
+            Ok(builder) => builder
+                .finish(settings::Flags::new(settings::builder()))
+                .unwrap(),
+            Err(_) => return,
+        };
+        let mut store = InMemoryCacheStore::new();
+        let mut ctx = Context::for_function(add_one());
+
+        let (_, hit) = ctx
+            .compile_with_cache(&*isa, &mut store, &mut Default::default())
+            .unwrap();
+        assert!(!hit);
+        let stats = *ctx.incremental_cache_stats();
+        assert_eq!((stats.hits, stats.misses, stats.stores), (0, 1, 1));
+        assert!(stats.bytes_written > 0);
+        assert_eq!(store.len(), 1);
+
+        ctx.clear();
+        ctx.func = add_one();
+        let (_, hit) = ctx
+            .compile_with_cache(&*isa, &mut store, &mut Default::default())
+            .unwrap();
+        assert!(hit);
+        let stats = *ctx.incremental_cache_stats();
+        assert_eq!((stats.hits, stats.misses, stats.stores), (1, 1, 1));
+
+        ctx.reset_incremental_cache_stats();
+        ctx.set_incremental_cache_salt(b"frontend v2");
+        ctx.clear();
+        ctx.func = add_one();
+        let (_, hit) = ctx
+            .compile_with_cache(&*isa, &mut store, &mut Default::default())
+            .unwrap();
+        assert!(!hit);
+        let stats = *ctx.incremental_cache_stats();
+        assert_eq!((stats.hits, stats.misses, stats.stores), (0, 1, 1));
+        assert_eq!(store.len(), 2);
+    }
+
+    #[test]
+    fn isa_flag_names_are_part_of_the_key() {
+        let builder = |flag: &str| -> Option<crate::isa::OwnedTargetIsa> {
+            let mut isa_builder = crate::isa::lookup(Triple::from_str("x86_64").unwrap()).ok()?;
+            settings::Configurable::enable(&mut isa_builder, flag).unwrap();
+            Some(
+                isa_builder
+                    .finish(settings::Flags::new(settings::builder()))
+                    .unwrap(),
+            )
+        };
+        let (a, b) = match (builder("has_sse41"), builder("has_sse42")) {
+            (Some(a), Some(b)) => (a, b),
+            _ => return,
+        };
+        let func = add_one();
+        assert!(compute_cache_key(&*a, &func) != compute_cache_key(&*b, &func));
+    }
+}
@@ -0,0 +1,930 @@
+//! Function inlining.
+//!
+//! [`inline_call`] replaces a direct call with a copy of the body of the callee. On top of it,
+//! [`inline_module`] inlines the calls between a set of functions chosen by an [`InlinePolicy`],
+//! visiting the functions bottom-up over their [`CallGraph`] so that each callee is inlined after
+//! the calls within it have been.
+
+use crate::entity::SecondaryMap;
+use crate::fx::FxHashMap;
+use crate::ir::{
+    Block, BlockCall, ExtFuncData, ExternalName, FuncRef, Function, GlobalValue, GlobalValueData,
+    Inst, InstBuilder, InstructionData, JumpTableData, Opcode, SigRef, SourceLoc, StackSlot, Table,
+    TableData, UserFuncName, Value, ValueList,
+};
+use crate::packed_option::ReservedValue;
+use crate::trace;
+use alloc::vec::Vec;
+use core::fmt;
+
+/// An error preventing a call from being inlined.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InlineError {
+    /// The instruction isn't a direct `call`.
+    NotADirectCall,
+    /// The callee doesn't take the arguments of the call or doesn't return its results.
+    SignatureMismatch,
+    /// The callee uses a construct which only has a meaning in the function defining it.
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for InlineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotADirectCall => f.write_str("not a direct call"),
+            Self::SignatureMismatch => f.write_str("the callee doesn't match the call"),
+            Self::Unsupported(what) => write!(f, "the callee uses {}", what),
+        }
+    }
+}
+
+/// Check that the body of `callee` keeps its meaning when copied into another function.
+fn check_inlinable(callee: &Function) -> Result<(), InlineError> {
+    if callee.layout.entry_block().is_none() {
+        return Err(InlineError::Unsupported("no body"));
+    }
+    if !callee.dynamic_stack_slots.is_empty() || !callee.dfg.dynamic_types.is_empty() {
+        return Err(InlineError::Unsupported("dynamic types"));
+    }
+    if callee
+        .global_values
+        .values()
+        .any(|gv| matches!(gv, GlobalValueData::VMContext))
+    {
+        return Err(InlineError::Unsupported("the `vmctx` global value"));
+    }
+    for block in callee.layout.blocks() {
+        for inst in callee.layout.block_insts(block) {
+            match callee.dfg.insts[inst].opcode() {
+                Opcode::GetFramePointer | Opcode::GetStackPointer | Opcode::GetReturnAddress => {
+                    return Err(InlineError::Unsupported("its own frame"));
+                }
+                Opcode::ReturnCall | Opcode::ReturnCallIndirect => {
+                    return Err(InlineError::Unsupported("tail calls"));
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Replace the direct `call` instruction `call` of `caller` with a copy of the body of `callee`.
+///
+/// The block of the call is split after it, into a block taking the results of the call as
+/// parameters. The call is replaced with a jump to the copy of the entry block of the callee, and
+/// the returns of the callee with jumps to the new block. The copied instructions get the source
+/// location of the call.
+///
+/// `callee` must be the function called by `call`, which is not checked since functions don't
+/// know the names they are called by.
+pub fn inline_call(
+    caller: &mut Function,
+    call: Inst,
+    callee: &Function,
+) -> Result<(), InlineError> {
+    match caller.dfg.insts[call] {
+        InstructionData::Call {
+            opcode: Opcode::Call,
+            ..
+        } => {}
+        _ => return Err(InlineError::NotADirectCall),
+    }
+    check_inlinable(callee)?;
+    let args: Vec<Value> = caller.dfg.inst_args(call).to_vec();
+    let results: Vec<Value> = caller.dfg.inst_results(call).to_vec();
+    let entry = callee.layout.entry_block().unwrap();
+    let params = callee.dfg.block_params(entry);
+    if params.len() != args.len()
+        || callee.signature.returns.len() != results.len()
+        || params
+            .iter()
+            .zip(&args)
+            .any(|(&p, &a)| callee.dfg.value_type(p) != caller.dfg.value_type(a))
+        || callee
+            .signature
+            .returns
+            .iter()
+            .zip(&results)
+            .any(|(ret, &r)| ret.value_type != caller.dfg.value_type(r))
+    {
+        return Err(InlineError::SignatureMismatch);
+    }
+
+    let srcloc = caller.srcloc(call);
+
+    // Split the block of the call, and make the results of the call the parameters of the new
+    // block.
+    let after = caller.dfg.make_block();
+    let next = caller
+        .layout
+        .next_inst(call)
+        .expect("a call can't terminate a block");
+    caller.layout.split_block(after, next);
+    caller.dfg.detach_results(call);
+    for &result in &results {
+        caller.dfg.attach_block_param(after, result);
+    }
+
+    let mut inliner = Inliner {
+        caller,
+        callee,
+        after,
+        srcloc,
+        blocks: SecondaryMap::with_default(Block::reserved_value()),
+        values: SecondaryMap::with_default(Value::reserved_value()),
+        func_refs: FxHashMap::default(),
+        sig_refs: FxHashMap::default(),
+        global_values: FxHashMap::default(),
+        stack_slots: FxHashMap::default(),
+        tables: FxHashMap::default(),
+    };
+    let entry = inliner.copy_body();
+    inliner.caller.dfg.replace(call).jump(entry, &args);
+    Ok(())
+}
+
+/// The state of the copy of a callee into a caller.
+struct Inliner<'a> {
+    caller: &'a mut Function,
+    callee: &'a Function,
+    /// The block of the caller the returns of the callee jump to.
+    after: Block,
+    srcloc: SourceLoc,
+    blocks: SecondaryMap<Block, Block>,
+    values: SecondaryMap<Value, Value>,
+    func_refs: FxHashMap<FuncRef, FuncRef>,
+    sig_refs: FxHashMap<SigRef, SigRef>,
+    global_values: FxHashMap<GlobalValue, GlobalValue>,
+    stack_slots: FxHashMap<StackSlot, StackSlot>,
+    tables: FxHashMap<Table, Table>,
+}
+
+impl<'a> Inliner<'a> {
+    /// Copy the blocks of the callee before the block after the call, and return the copy of
+    /// its entry block.
+    fn copy_body(&mut self) -> Block {
+        let callee = self.callee;
+        for block in callee.layout.blocks() {
+            let new_block = self.caller.dfg.make_block();
+            self.caller.layout.insert_block(new_block, self.after);
+            self.blocks[block] = new_block;
+            for &param in callee.dfg.block_params(block) {
+                let ty = callee.dfg.value_type(param);
+                self.values[param] = self.caller.dfg.append_block_param(new_block, ty);
+            }
+        }
+
+        // Values may be used before their definitions in the layout, so they are only mapped once
+        // all instructions have been copied.
+        let mut new_insts = Vec::new();
+        for block in callee.layout.blocks() {
+            for inst in callee.layout.block_insts(block) {
+                let data = self.copy_inst_data(inst);
+                let new_inst = self.caller.dfg.make_inst(data);
+                for &result in callee.dfg.inst_results(inst) {
+                    let ty = callee.dfg.value_type(result);
+                    self.values[result] = self.caller.dfg.append_result(new_inst, ty);
+                }
+                self.caller.layout.append_inst(new_inst, self.blocks[block]);
+                self.caller.set_srcloc(new_inst, self.srcloc);
+                new_insts.push(new_inst);
+            }
+        }
+        let dfg = &mut self.caller.dfg;
+        let map = |v: Value| self.values[callee.dfg.resolve_aliases(v)];
+        for inst in new_insts {
+            let data = &mut dfg.insts[inst];
+            for arg in data.arguments_mut(&mut dfg.value_lists) {
+                *arg = map(*arg);
+            }
+            for dest in data.branch_destination_mut(&mut dfg.jump_tables) {
+                for arg in dest.args_slice_mut(&mut dfg.value_lists) {
+                    *arg = map(*arg);
+                }
+            }
+        }
+
+        self.blocks[callee.layout.entry_block().unwrap()]
+    }
+
+    /// Copy the data of `inst` with the entities of the callee replaced with the ones of the
+    /// caller, except for values, and returns replaced with jumps to the block after the call.
+    fn copy_inst_data(&mut self, inst: Inst) -> InstructionData {
+        let callee = self.callee;
+        let mut data = callee.dfg.insts[inst];
+        match &mut data {
+            InstructionData::MultiAry {
+                opcode: Opcode::Return,
+                args,
+            } => {
+                let args = args.as_slice(&callee.dfg.value_lists);
+                let destination =
+                    BlockCall::new(self.after, args, &mut self.caller.dfg.value_lists);
+                return InstructionData::Jump {
+                    opcode: Opcode::Jump,
+                    destination,
+                };
+            }
+            InstructionData::MultiAry { args, .. } => *args = self.copy_list(*args),
+            InstructionData::Call { args, func_ref, .. } => {
+                *args = self.copy_list(*args);
+                *func_ref = self.func_ref(*func_ref);
+            }
+            InstructionData::CallIndirect { args, sig_ref, .. } => {
+                *args = self.copy_list(*args);
+                *sig_ref = self.sig_ref(*sig_ref);
+            }
+            InstructionData::FuncAddr { func_ref, .. } => *func_ref = self.func_ref(*func_ref),
+            InstructionData::Jump { destination, .. } => {
+                *destination = self.copy_block_call(*destination);
+            }
+            InstructionData::Brif { blocks, .. }
+            | InstructionData::PatchableJump { blocks, .. } => {
+                for block in blocks {
+                    *block = self.copy_block_call(*block);
+                }
+            }
+            InstructionData::BranchTable { table, .. } => {
+                let data = &callee.dfg.jump_tables[*table];
+                let default = self.copy_block_call(data.default_block());
+                let entries: Vec<BlockCall> = data
+                    .as_slice()
+                    .iter()
+                    .map(|&entry| self.copy_block_call(entry))
+                    .collect();
+                *table = self
+                    .caller
+                    .dfg
+                    .jump_tables
+                    .push(JumpTableData::new(default, &entries));
+            }
+            InstructionData::UnaryGlobalValue { global_value, .. } => {
+                *global_value = self.global_value(*global_value);
+            }
+            InstructionData::UnaryConst {
+                constant_handle, ..
+            } => {
+                let constant = callee.dfg.constants.get(*constant_handle).clone();
+                *constant_handle = self.caller.dfg.constants.insert(constant);
+            }
+            InstructionData::Shuffle { imm, .. } => {
+                let mask = callee.dfg.immediates[*imm].clone();
+                *imm = self.caller.dfg.immediates.push(mask);
+            }
+            InstructionData::StackLoad { stack_slot, .. }
+            | InstructionData::StackStore { stack_slot, .. } => {
+                *stack_slot = self.stack_slot(*stack_slot);
+            }
+            InstructionData::TableAddr { table, .. } => *table = self.table(*table),
+            _ => {}
+        }
+        data
+    }
+
+    fn copy_list(&mut self, list: ValueList) -> ValueList {
+        let values = list.as_slice(&self.callee.dfg.value_lists);
+        ValueList::from_slice(values, &mut self.caller.dfg.value_lists)
+    }
+
+    fn copy_block_call(&mut self, call: BlockCall) -> BlockCall {
+        let pool = &self.callee.dfg.value_lists;
+        let block = self.blocks[call.block(pool)];
+        BlockCall::new(
+            block,
+            call.args_slice(pool),
+            &mut self.caller.dfg.value_lists,
+        )
+    }
+
+    fn external_name(&mut self, name: &ExternalName) -> ExternalName {
+        match name {
+            ExternalName::User(name) => {
+                let name = self.callee.params.user_named_funcs()[*name].clone();
+                ExternalName::User(self.caller.declare_imported_user_function(name))
+            }
+            _ => name.clone(),
+        }
+    }
+
+    fn sig_ref(&mut self, sig_ref: SigRef) -> SigRef {
+        if let Some(&new) = self.sig_refs.get(&sig_ref) {
+            return new;
+        }
+        let new = self
+            .caller
+            .import_signature(self.callee.dfg.signatures[sig_ref].clone());
+        self.sig_refs.insert(sig_ref, new);
+        new
+    }
+
+    fn func_ref(&mut self, func_ref: FuncRef) -> FuncRef {
+        if let Some(&new) = self.func_refs.get(&func_ref) {
+            return new;
+        }
+        let data = &self.callee.dfg.ext_funcs[func_ref];
+        let data = ExtFuncData {
+            name: self.external_name(&data.name),
+            signature: self.sig_ref(data.signature),
+            colocated: data.colocated,
+        };
+        let new = self.caller.import_function(data);
+        self.func_refs.insert(func_ref, new);
+        new
+    }
+
+    fn global_value(&mut self, gv: GlobalValue) -> GlobalValue {
+        if let Some(&new) = self.global_values.get(&gv) {
+            return new;
+        }
+        let data = match self.callee.global_values[gv].clone() {
+            GlobalValueData::Load {
+                base,
+                offset,
+                global_type,
+                readonly,
+            } => GlobalValueData::Load {
+                base: self.global_value(base),
+                offset,
+                global_type,
+                readonly,
+            },
+            GlobalValueData::IAddImm {
+                base,
+                offset,
+                global_type,
+            } => GlobalValueData::IAddImm {
+                base: self.global_value(base),
+                offset,
+                global_type,
+            },
+            GlobalValueData::Symbol {
+                name,
+                offset,
+                colocated,
+                tls,
+            } => GlobalValueData::Symbol {
+                name: self.external_name(&name),
+                offset,
+                colocated,
+                tls,
+            },
+            data => data,
+        };
+        let new = self.caller.create_global_value(data);
+        self.global_values.insert(gv, new);
+        new
+    }
+
+    fn stack_slot(&mut self, slot: StackSlot) -> StackSlot {
+        if let Some(&new) = self.stack_slots.get(&slot) {
+            return new;
+        }
+        let new = self
+            .caller
+            .create_sized_stack_slot(self.callee.sized_stack_slots[slot].clone());
+        self.stack_slots.insert(slot, new);
+        new
+    }
+
+    fn table(&mut self, table: Table) -> Table {
+        if let Some(&new) = self.tables.get(&table) {
+            return new;
+        }
+        let data = self.callee.tables[table].clone();
+        let data = TableData {
+            base_gv: self.global_value(data.base_gv),
+            bound_gv: self.global_value(data.bound_gv),
+            ..data
+        };
+        let new = self.caller.create_table(data);
+        self.tables.insert(table, new);
+        new
+    }
+}
+
+/// The direct calls between a set of functions.
+///
+/// Functions are identified by their index in the set, and calls are resolved to them by
+/// comparing the external names of the callees with the names of the functions: a user external
+/// name refers to the function with the same user name, and a test case name to the function with
+/// the same test case name.
+#[derive(Clone, Debug, Default)]
+pub struct CallGraph {
+    names: FxHashMap<UserFuncName, usize>,
+    callees: Vec<Vec<usize>>,
+}
+
+impl CallGraph {
+    /// Compute the call graph of `funcs`.
+    pub fn new(funcs: &[Function]) -> Self {
+        let mut names = FxHashMap::default();
+        for (i, func) in funcs.iter().enumerate() {
+            names.entry(func.name.clone()).or_insert(i);
+        }
+        let mut graph = Self {
+            names,
+            callees: Vec::with_capacity(funcs.len()),
+        };
+        for func in funcs {
+            let mut callees: Vec<usize> = func
+                .layout
+                .blocks()
+                .flat_map(|block| func.layout.block_insts(block))
+                .filter_map(|inst| match func.dfg.insts[inst] {
+                    InstructionData::Call { func_ref, .. } => graph.resolve(func, func_ref),
+                    _ => None,
+                })
+                .collect();
+            callees.sort_unstable();
+            callees.dedup();
+            graph.callees.push(callees);
+        }
+        graph
+    }
+
+    /// The functions directly called by the function at `index`.
+    pub fn callees(&self, index: usize) -> &[usize] {
+        &self.callees[index]
+    }
+
+    /// Find the index of the function called through `func_ref` by `caller`, if it is in the
+    /// set.
+    pub fn resolve(&self, caller: &Function, func_ref: FuncRef) -> Option<usize> {
+        let name = match &caller.dfg.ext_funcs[func_ref].name {
+            ExternalName::User(name) => {
+                UserFuncName::User(caller.params.user_named_funcs()[*name].clone())
+            }
+            ExternalName::TestCase(name) => UserFuncName::Testcase(name.clone()),
+            ExternalName::LibCall(_) | ExternalName::KnownSymbol(_) => return None,
+        };
+        self.names.get(&name).copied()
+    }
+
+    /// The strongly connected components of the call graph, with the callees of the functions of
+    /// each component in the same or previous components.
+    ///
+    /// A component has several functions if they are mutually recursive.
+    pub fn sccs(&self) -> Vec<Vec<usize>> {
+        // Tarjan's algorithm, which finds the components in reverse topological order, with an
+        // explicit stack of the functions being visited and their next callee.
+        let n = self.callees.len();
+        let unvisited = usize::MAX;
+        let mut index = vec![unvisited; n];
+        let mut lowlink = vec![0; n];
+        let mut on_stack = vec![false; n];
+        let mut stack = Vec::new();
+        let mut sccs = Vec::new();
+        let mut next_index = 0;
+        for root in 0..n {
+            if index[root] != unvisited {
+                continue;
+            }
+            let mut visiting = vec![(root, 0)];
+            index[root] = next_index;
+            lowlink[root] = next_index;
+            next_index += 1;
+            stack.push(root);
+            on_stack[root] = true;
+            while let Some(&mut (func, ref mut next)) = visiting.last_mut() {
+                if let Some(&callee) = self.callees[func].get(*next) {
+                    *next += 1;
+                    if index[callee] == unvisited {
+                        index[callee] = next_index;
+                        lowlink[callee] = next_index;
+                        next_index += 1;
+                        stack.push(callee);
+                        on_stack[callee] = true;
+                        visiting.push((callee, 0));
+                    } else if on_stack[callee] {
+                        lowlink[func] = lowlink[func].min(index[callee]);
+                    }
+                    continue;
+                }
+                visiting.pop();
+                if let Some(&(caller, _)) = visiting.last() {
+                    lowlink[caller] = lowlink[caller].min(lowlink[func]);
+                }
+                if lowlink[func] == index[func] {
+                    let mut scc = Vec::new();
+                    loop {
+                        let member = stack.pop().unwrap();
+                        on_stack[member] = false;
+                        scc.push(member);
+                        if member == func {
+                            break;
+                        }
+                    }
+                    scc.reverse();
+                    sccs.push(scc);
+                }
+            }
+        }
+        sccs
+    }
+}
+
+/// The policy deciding which calls [`inline_module`] inlines.
+#[derive(Clone, Debug)]
+pub struct InlinePolicy {
+    /// The maximum number of instructions of an inlined callee.
+    pub max_callee_size: usize,
+    /// The maximum number of instructions added to a function by inlining calls into it.
+    pub max_caller_growth: usize,
+    /// Never inline functions with a stack limit, whose check would be lost.
+    pub never_inline_stack_limit: bool,
+    /// Functions to inline whatever their size and the growth of their callers, unless they are
+    /// recursive or can't be inlined.
+    pub always_inline: Vec<UserFuncName>,
+}
+
+impl Default for InlinePolicy {
+    fn default() -> Self {
+        Self {
+            max_callee_size: 32,
+            max_caller_growth: 256,
+            never_inline_stack_limit: true,
+            always_inline: Vec::new(),
+        }
+    }
+}
+
+/// The decision of [`inline_module`] on a call.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InlineDecision {
+    /// The call was inlined, adding this number of instructions to the caller.
+    Inlined(usize),
+    /// The caller and the callee are in the same recursive component of the call graph.
+    Recursive,
+    /// The callee has a construct the policy never inlines.
+    NeverInline,
+    /// The callee has more instructions than the policy allows.
+    CalleeTooLarge(usize),
+    /// Inlining the callee would make the caller grow more than the policy allows.
+    BudgetExceeded(usize),
+    /// The callee can't be inlined.
+    Failed(InlineError),
+}
+
+/// A call considered by [`inline_module`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct InlineEvent {
+    /// The index of the calling function.
+    pub caller: usize,
+    /// The index of the called function.
+    pub callee: usize,
+    /// The call instruction, which is a jump to the inlined body if it was inlined.
+    pub call: Inst,
+    /// What was done with the call.
+    pub decision: InlineDecision,
+}
+
+/// The calls considered by [`inline_module`], in the order they were.
+#[derive(Clone, Debug, Default)]
+pub struct InlineLog {
+    /// The calls, in the order they were considered.
+    pub events: Vec<InlineEvent>,
+}
+
+impl InlineLog {
+    /// The calls which were inlined.
+    pub fn inlined(&self) -> impl Iterator<Item = &InlineEvent> {
+        self.events
+            .iter()
+            .filter(|event| matches!(event.decision, InlineDecision::Inlined(_)))
+    }
+}
+
+fn size(func: &Function) -> usize {
+    func.layout
+        .blocks()
+        .map(|block| func.layout.block_insts(block).count())
+        .sum()
+}
+
+/// Inline the calls between `funcs` allowed by `policy`.
+///
+/// The functions are visited bottom-up over `call_graph`, which must have been computed for
+/// `funcs`, so that the calls within the callees are inlined first. Calls within a recursive
+/// component of the call graph are never inlined, while calls out of it may be. The calls of each
+/// function are inlined greedily in layout order, while they fit in the growth budget of the
+/// policy; calls within inlined bodies are left alone.
+pub fn inline_module(
+    funcs: &mut [Function],
+    call_graph: &CallGraph,
+    policy: &InlinePolicy,
+) -> InlineLog {
+    let mut log = InlineLog::default();
+    for scc in call_graph.sccs() {
+        for &caller in &scc {
+            inline_calls(funcs, caller, &scc, call_graph, policy, &mut log);
+        }
+    }
+    log
+}
+
+fn inline_calls(
+    funcs: &mut [Function],
+    caller: usize,
+    scc: &[usize],
+    call_graph: &CallGraph,
+    policy: &InlinePolicy,
+    log: &mut InlineLog,
+) {
+    let func = &funcs[caller];
+    let calls: Vec<(Inst, usize)> = func
+        .layout
+        .blocks()
+        .flat_map(|block| func.layout.block_insts(block))
+        .filter_map(|inst| match func.dfg.insts[inst] {
+            InstructionData::Call {
+                opcode: Opcode::Call,
+                func_ref,
+                ..
+            } => Some((inst, call_graph.resolve(func, func_ref)?)),
+            _ => None,
+        })
+        .collect();
+
+    let mut growth = 0;
+    for (call, callee) in calls {
+        let decision = if scc.contains(&callee) {
+            InlineDecision::Recursive
+        } else {
+            let callee_func = &funcs[callee];
+            let size = size(callee_func);
+            let always = policy.always_inline.contains(&callee_func.name);
+            if policy.never_inline_stack_limit && callee_func.stack_limit.is_some() {
+                InlineDecision::NeverInline
+            } else if !always && size > policy.max_callee_size {
+                InlineDecision::CalleeTooLarge(size)
+            } else if !always && growth + size > policy.max_caller_growth {
+                InlineDecision::BudgetExceeded(size)
+            } else {
+                let (caller_func, callee_func) = pair_mut(funcs, caller, callee);
+                match inline_call(caller_func, call, callee_func) {
+                    Ok(()) => {
+                        growth += size;
+                        InlineDecision::Inlined(size)
+                    }
+                    Err(err) => InlineDecision::Failed(err),
+                }
+            }
+        };
+        trace!(
+            "{} calling {} at {}: {:?}",
+            funcs[caller].name,
+            funcs[callee].name,
+            call,
+            decision
+        );
+        log.events.push(InlineEvent {
+            caller,
+            callee,
+            call,
+            decision,
+        });
+    }
+}
+
+/// Borrow `funcs[a]` mutably and `funcs[b]` immutably, for distinct `a` and `b`.
+fn pair_mut(funcs: &mut [Function], a: usize, b: usize) -> (&mut Function, &Function) {
+    if a < b {
+        let (left, right) = funcs.split_at_mut(b);
+        (&mut left[a], &right[0])
+    } else {
+        let (left, right) = funcs.split_at_mut(a);
+        (&mut right[0], &left[b])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cursor::{Cursor, FuncCursor};
+    use crate::ir::condcodes::IntCC;
+    use crate::ir::{types, AbiParam, InstBuilder, Signature, StackSlotData, StackSlotKind};
+    use crate::isa::CallConv;
+    use crate::settings;
+    use crate::verifier::verify_function;
+    use alloc::string::ToString;
+
+    fn signature() -> Signature {
+        let mut sig = Signature::new(CallConv::SystemV);
+        sig.params.push(AbiParam::new(types::I32));
+        sig.returns.push(AbiParam::new(types::I32));
+        sig
+    }
+
+    /// Build the function `u0:index` taking and returning an `i32`, whose body is built by
+    /// `body` from the parameter.
+    fn function(index: u32, body: impl FnOnce(&mut FuncCursor, Value)) -> Function {
+        let mut func = Function::with_name_signature(UserFuncName::user(0, index), signature());
+        let mut pos = FuncCursor::new(&mut func);
+        let entry = pos.func.dfg.make_block();
+        pos.insert_block(entry);
+        let param = pos.func.dfg.append_block_param(entry, types::I32);
+        body(&mut pos, param);
+        func
+    }
+
+    fn call(pos: &mut FuncCursor, callee: u32, arg: Value) -> Value {
+        let signature = pos.func.import_signature(signature());
+        let name = pos
+            .func
+            .declare_imported_user_function(crate::ir::UserExternalName::new(0, callee));
+        let func_ref = pos.func.import_function(ExtFuncData {
+            name: ExternalName::User(name),
+            signature,
+            colocated: true,
+        });
+        let call = pos.ins().call(func_ref, &[arg]);
+        pos.func.dfg.first_result(call)
+    }
+
+    fn verify(funcs: &[Function]) {
+        let flags = settings::Flags::new(settings::builder());
+        for func in funcs {
+            verify_function(func, &flags).unwrap();
+        }
+    }
+
+    fn calls(func: &Function) -> usize {
+        func.layout
+            .blocks()
+            .flat_map(|block| func.layout.block_insts(block))
+            .filter(|&inst| func.dfg.insts[inst].opcode().is_call())
+            .count()
+    }
+
+    fn generous() -> InlinePolicy {
+        InlinePolicy {
+            max_callee_size: 1000,
+            max_caller_growth: 1000,
+            ..InlinePolicy::default()
+        }
+    }
+
+    #[test]
+    fn call_chain_collapses() {
+        let a = function(0, |pos, x| {
+            let y = call(pos, 1, x);
+            let z = pos.ins().iadd_imm(y, 1);
+            pos.ins().return_(&[z]);
+        });
+        let b = function(1, |pos, x| {
+            let y = call(pos, 2, x);
+            let z = pos.ins().imul(y, x);
+            pos.ins().return_(&[z]);
+        });
+        // The innermost callee has several returns, a stack slot and a jump table.
+        let c = function(2, |pos, x| {
+            let slot = pos
+                .func
+                .create_sized_stack_slot(StackSlotData::new(StackSlotKind::ExplicitSlot, 4));
+            let zero = pos.func.dfg.make_block();
+            let other = pos.func.dfg.make_block();
+            let join = pos.func.dfg.make_block();
+            let param = pos.func.dfg.append_block_param(join, types::I32);
+            let default = pos.func.dfg.block_call(other, &[]);
+            let zero_call = pos.func.dfg.block_call(zero, &[]);
+            let table = pos
+                .func
+                .create_jump_table(JumpTableData::new(default, &[zero_call]));
+            pos.ins().br_table(x, table);
+            pos.insert_block(zero);
+            let seven = pos.ins().iconst(types::I32, 7);
+            pos.ins().return_(&[seven]);
+            pos.insert_block(other);
+            pos.ins().stack_store(x, slot, 0);
+            let loaded = pos.ins().stack_load(types::I32, slot, 0);
+            let is_one = pos.ins().icmp_imm(IntCC::Equal, loaded, 1);
+            pos.ins().brif(is_one, join, &[loaded], join, &[x]);
+            pos.insert_block(join);
+            pos.ins().return_(&[param]);
+        });
+        let mut funcs = [a, b, c];
+        verify(&funcs);
+        let graph = CallGraph::new(&funcs);
+        assert_eq!(graph.callees(0), [1]);
+        assert_eq!(graph.sccs(), [[2], [1], [0]]);
+
+        let log = inline_module(&mut funcs, &graph, &generous());
+        verify(&funcs);
+        let inlined: Vec<_> = log.inlined().map(|e| (e.caller, e.callee)).collect();
+        assert_eq!(inlined, [(1, 2), (0, 1)]);
+        assert_eq!(log.events.len(), 2);
+        assert_eq!(calls(&funcs[0]), 0);
+        assert_eq!(calls(&funcs[1]), 0);
+        assert_eq!(funcs[0].sized_stack_slots.len(), 1);
+        let text = funcs[0].display().to_string();
+        assert!(text.contains("br_table"), "{}", text);
+        assert!(text.contains("stack_load"), "{}", text);
+    }
+
+    #[test]
+    fn recursion_is_left_alone() {
+        // `u0:0` calls itself, and `u0:1` and `u0:2` call each other.
+        let f = function(0, |pos, x| {
+            let y = call(pos, 0, x);
+            pos.ins().return_(&[y]);
+        });
+        let g = function(1, |pos, x| {
+            let y = call(pos, 2, x);
+            pos.ins().return_(&[y]);
+        });
+        let h = function(2, |pos, x| {
+            let y = call(pos, 1, x);
+            pos.ins().return_(&[y]);
+        });
+        let mut funcs = [f, g, h];
+        let before: Vec<_> = funcs.iter().map(|f| f.display().to_string()).collect();
+        let graph = CallGraph::new(&funcs);
+        assert_eq!(graph.sccs(), [vec![0], vec![1, 2]]);
+
+        let log = inline_module(&mut funcs, &graph, &generous());
+        let after: Vec<_> = funcs.iter().map(|f| f.display().to_string()).collect();
+        assert_eq!(before, after);
+        assert_eq!(log.events.len(), 3);
+        assert!(log
+            .events
+            .iter()
+            .all(|event| event.decision == InlineDecision::Recursive));
+    }
+
+    /// Build a function calling `u0:1` `n` times, and `u0:1`, which is two instructions long.
+    fn fan_out(n: usize) -> [Function; 2] {
+        let root = function(0, |pos, mut x| {
+            for _ in 0..n {
+                x = call(pos, 1, x);
+            }
+            pos.ins().return_(&[x]);
+        });
+        let leaf = function(1, |pos, x| {
+            let y = pos.ins().iadd_imm(x, 1);
+            pos.ins().return_(&[y]);
+        });
+        [root, leaf]
+    }
+
+    #[test]
+    fn budget_caps_growth() {
+        let mut funcs = fan_out(50);
+        let root_size = size(&funcs[0]);
+        let graph = CallGraph::new(&funcs);
+        let policy = InlinePolicy {
+            max_callee_size: 10,
+            max_caller_growth: 21,
+            ..InlinePolicy::default()
+        };
+        let log = inline_module(&mut funcs, &graph, &policy);
+        verify(&funcs);
+
+        assert_eq!(log.inlined().count(), 10);
+        assert_eq!(calls(&funcs[0]), 40);
+        assert!(log.events[10..]
+            .iter()
+            .all(|event| event.decision == InlineDecision::BudgetExceeded(2)));
+        // Each inlined call adds the two instructions of the callee, and replaces the call with a
+        // jump.
+        assert_eq!(size(&funcs[0]), root_size + 20);
+    }
+
+    #[test]
+    fn hints() {
+        // Functions too large for the policy can be forced inline.
+        let mut funcs = fan_out(50);
+        let graph = CallGraph::new(&funcs);
+        let policy = InlinePolicy {
+            max_callee_size: 1,
+            max_caller_growth: 1,
+            always_inline: vec![funcs[1].name.clone()],
+            ..InlinePolicy::default()
+        };
+        assert_eq!(
+            inline_module(&mut funcs, &graph, &policy).inlined().count(),
+            50
+        );
+        verify(&funcs);
+        assert_eq!(calls(&funcs[0]), 0);
+
+        // Functions with a stack limit are never inlined by default.
+        let mut funcs = fan_out(1);
+        let vmctx = funcs[1].create_global_value(GlobalValueData::VMContext);
+        funcs[1].stack_limit = Some(vmctx);
+        let graph = CallGraph::new(&funcs);
+        let log = inline_module(&mut funcs, &graph, &generous());
+        assert_eq!(log.events[0].decision, InlineDecision::NeverInline);
+
+        // Which doesn't make them inlinable if they use the `vmctx`.
+        let policy = InlinePolicy {
+            never_inline_stack_limit: false,
+            ..generous()
+        };
+        let log = inline_module(&mut funcs, &graph, &policy);
+        assert_eq!(
+            log.events[0].decision,
+            InlineDecision::Failed(InlineError::Unsupported("the `vmctx` global value"))
+        );
+    }
+}
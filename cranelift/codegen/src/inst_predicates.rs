@@ -159,7 +159,7 @@ pub fn has_memory_fence_semantics(op: Opcode) -> bool {
         | Opcode::AtomicStore
         | Opcode::Fence
         | Opcode::Debugtrap => true,
-        Opcode::Call | Opcode::CallIndirect => true,
+        Opcode::Call | Opcode::PatchableCall | Opcode::CallIndirect => true,
         op if op.can_trap() => true,
         _ => false,
     }
@@ -185,6 +185,10 @@ pub(crate) fn visit_block_succs<F: FnMut(Inst, Block, bool)>(
             ir::InstructionData::Brif {
                 blocks: [block_then, block_else],
                 ..
+            }
+            | ir::InstructionData::PatchableJump {
+                blocks: [block_then, block_else],
+                ..
             } => {
                 visit(inst, block_then.block(&f.dfg.value_lists), false);
                 visit(inst, block_else.block(&f.dfg.value_lists), false);
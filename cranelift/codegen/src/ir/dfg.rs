@@ -1,14 +1,14 @@
 //! Data flow graph tracking Instructions, Values, and blocks.
 
-use crate::entity::{self, PrimaryMap, SecondaryMap};
+use crate::entity::{self, EntityRef, PrimaryMap, SecondaryMap};
 use crate::ir;
 use crate::ir::builder::ReplaceBuilder;
 use crate::ir::dynamic_type::{DynamicTypeData, DynamicTypes};
 use crate::ir::instructions::{CallInfo, InstructionData};
 use crate::ir::{
     types, Block, BlockCall, ConstantData, ConstantPool, DynamicType, ExtFuncData, FuncRef,
-    Immediate, Inst, JumpTables, RelSourceLoc, SigRef, Signature, Type, Value,
-    ValueLabelAssignments, ValueList, ValueListPool,
+    Immediate, Inst, JumpTable, JumpTableData, JumpTables, RelSourceLoc, SigRef, Signature, Type,
+    Value, ValueLabelAssignments, ValueList, ValueListPool,
 };
 use crate::packed_option::ReservedValue;
 use crate::write::write_operands;
@@ -148,6 +148,40 @@ pub struct DataFlowGraph {
     pub jump_tables: JumpTables,
 }
 
+/// The number of bytes allocated for each of the pools of a [`DataFlowGraph`], as returned by
+/// [`DataFlowGraph::memory_usage`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DfgMemoryStats {
+    /// Instruction data.
+    pub insts: usize,
+    /// The result lists of instructions.
+    pub results: usize,
+    /// Block data.
+    pub blocks: usize,
+    /// The pool of value lists, holding block parameters, instruction results and long argument
+    /// lists.
+    pub value_lists: usize,
+    /// Value data.
+    pub values: usize,
+    /// Large immediates.
+    pub immediates: usize,
+    /// Jump tables, excluding their entries.
+    pub jump_tables: usize,
+}
+
+impl DfgMemoryStats {
+    /// The total number of bytes of all pools.
+    pub fn total(&self) -> usize {
+        self.insts
+            + self.results
+            + self.blocks
+            + self.value_lists
+            + self.values
+            + self.immediates
+            + self.jump_tables
+    }
+}
+
 impl DataFlowGraph {
     /// Create a new empty `DataFlowGraph`.
     pub fn new() -> Self {
@@ -185,6 +219,41 @@ impl DataFlowGraph {
         self.jump_tables.clear();
     }
 
+    /// Get the number of bytes allocated for each of the pools of this DFG, including capacity
+    /// which isn't in use.
+    pub fn memory_usage(&self) -> DfgMemoryStats {
+        fn bytes<T>(capacity: usize) -> usize {
+            capacity * mem::size_of::<T>()
+        }
+        DfgMemoryStats {
+            insts: bytes::<InstructionData>(self.insts.0.capacity()),
+            results: bytes::<ValueList>(self.results.capacity()),
+            blocks: bytes::<BlockData>(self.blocks.0.capacity()),
+            value_lists: bytes::<Value>(self.value_lists.capacity()),
+            values: bytes::<ValueDataPacked>(self.values.capacity()),
+            immediates: bytes::<ConstantData>(self.immediates.capacity()),
+            jump_tables: bytes::<JumpTableData>(self.jump_tables.capacity()),
+        }
+    }
+
+    /// Release the capacity of the pools of this DFG which isn't in use.
+    ///
+    /// Clearing a DFG keeps its capacity for reuse, so a DFG which once held a large function
+    /// keeps the memory for it until it is shrunk.
+    pub fn shrink_to_fit(&mut self) {
+        self.insts.0.shrink_to_fit();
+        self.results.shrink_to_fit();
+        self.blocks.0.shrink_to_fit();
+        self.dynamic_types.shrink_to_fit();
+        self.value_lists.shrink_to_fit();
+        self.values.shrink_to_fit();
+        self.signatures.shrink_to_fit();
+        self.old_signatures.shrink_to_fit();
+        self.ext_funcs.shrink_to_fit();
+        self.immediates.shrink_to_fit();
+        self.jump_tables.shrink_to_fit();
+    }
+
     /// Get the total number of instructions created in this function, whether they are currently
     /// inserted in the layout or not.
     ///
@@ -459,6 +528,20 @@ impl DataFlowGraph {
     }
 }
 
+/// Map each entity of `order` to its position in it.
+fn new_numbers<K: EntityRef + ReservedValue>(order: &[K]) -> SecondaryMap<K, K> {
+    let mut numbers = SecondaryMap::with_default(K::reserved_value());
+    for (new, &old) in order.iter().enumerate() {
+        numbers[old] = K::new(new);
+    }
+    numbers
+}
+
+/// Move the entries of `map` to their positions in `order`.
+fn permute<K: EntityRef, V: Clone>(map: &PrimaryMap<K, V>, order: &[K]) -> PrimaryMap<K, V> {
+    order.iter().map(|&k| map[k].clone()).collect()
+}
+
 /// Where did a value come from?
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum ValueDef {
@@ -760,6 +843,127 @@ impl DataFlowGraph {
         }
     }
 
+    /// Renumber the instructions, blocks, values and jump tables of the DFG.
+    ///
+    /// Each order lists every entity of its kind exactly once, by increasing new number, except
+    /// that `values` leaves out aliases: they are removed, and any remaining reference to an
+    /// alias refers to the aliased value instead.
+    ///
+    /// Returns the new numbers of the instructions and blocks, which the layout and source
+    /// locations must be updated with.
+    pub(crate) fn renumber(
+        &mut self,
+        insts: &[Inst],
+        blocks: &[Block],
+        values: &[Value],
+        jump_tables: &[JumpTable],
+    ) -> (SecondaryMap<Inst, Inst>, SecondaryMap<Block, Block>) {
+        let inst_map = new_numbers(insts);
+        let block_map = new_numbers(blocks);
+        let jump_table_map = new_numbers(jump_tables);
+        let mut value_map = new_numbers(values);
+        for (value, &data) in self.values.iter() {
+            if !valid_valuedata(data) {
+                continue;
+            }
+            if let ValueData::Alias { .. } = ValueData::from(data) {
+                value_map[value] = value_map[self.resolve_aliases(value)];
+            }
+        }
+
+        let map_call = |call: &mut BlockCall, pool: &mut ValueListPool| {
+            call.set_block(block_map[call.block(pool)], pool);
+            for arg in call.args_slice_mut(pool) {
+                *arg = value_map[*arg];
+            }
+        };
+
+        // Update every reference in place, then move the entities to their new numbers.
+        for inst in self.insts.0.keys() {
+            for arg in self.insts[inst].arguments_mut(&mut self.value_lists) {
+                *arg = value_map[*arg];
+            }
+            match &mut self.insts[inst] {
+                InstructionData::BranchTable { table, .. } => *table = jump_table_map[*table],
+                data => {
+                    for call in data.branch_destination_mut(&mut self.jump_tables) {
+                        map_call(call, &mut self.value_lists);
+                    }
+                }
+            }
+            for result in self.results[inst].as_mut_slice(&mut self.value_lists) {
+                *result = value_map[*result];
+            }
+        }
+        for table in self.jump_tables.values_mut() {
+            for call in table.all_branches_mut() {
+                map_call(call, &mut self.value_lists);
+            }
+        }
+        for block in self.blocks.0.values_mut() {
+            for param in block.params.as_mut_slice(&mut self.value_lists) {
+                *param = value_map[*param];
+            }
+        }
+        for value in self.values.values_mut() {
+            *value = match ValueData::from(*value) {
+                ValueData::Inst { ty, num, inst } => ValueData::Inst {
+                    ty,
+                    num,
+                    inst: inst_map[inst],
+                },
+                ValueData::Param { ty, num, block } => ValueData::Param {
+                    ty,
+                    num,
+                    block: block_map[block],
+                },
+                ValueData::Alias { ty, original } => ValueData::Alias {
+                    ty,
+                    original: value_map[original],
+                },
+                ValueData::Union { ty, x, y } => ValueData::Union {
+                    ty,
+                    x: value_map[x],
+                    y: value_map[y],
+                },
+            }
+            .into();
+        }
+        if let Some(labels) = &mut self.values_labels {
+            let values = &self.values;
+            *labels = mem::take(labels)
+                .into_iter()
+                .filter(|(value, _)| {
+                    !matches!(ValueData::from(values[*value]), ValueData::Alias { .. })
+                })
+                .map(|(value, assignments)| {
+                    let assignments = match assignments {
+                        ValueLabelAssignments::Alias { from, value } => {
+                            ValueLabelAssignments::Alias {
+                                from,
+                                value: value_map[value],
+                            }
+                        }
+                        starts => starts,
+                    };
+                    (value_map[value], assignments)
+                })
+                .collect();
+        }
+
+        self.insts.0 = permute(&self.insts.0, insts);
+        let mut results = SecondaryMap::with_capacity(insts.len());
+        for (new, &old) in insts.iter().enumerate() {
+            results[Inst::new(new)] = self.results[old];
+        }
+        self.results = results;
+        self.blocks.0 = permute(&self.blocks.0, blocks);
+        self.values = permute(&self.values, values);
+        self.jump_tables = permute(&self.jump_tables, jump_tables);
+
+        (inst_map, block_map)
+    }
+
     /// Get all value arguments on `inst` as a slice.
     pub fn inst_args(&self, inst: Inst) -> &[Value] {
         self.insts[inst].arguments(&self.value_lists)
@@ -815,6 +1019,10 @@ impl DataFlowGraph {
     /// provided `ctrl_typevar` type for polymorphic instructions. For non-polymorphic
     /// instructions, `ctrl_typevar` is ignored, and `INVALID` can be used.
     ///
+    /// The results of calls are the returns of their signature, whatever `ctrl_typevar` is, so it
+    /// must be `INVALID` for them, or the type of the callee of indirect calls.
+    /// `make_inst_results_for_signature` makes that explicit.
+    ///
     /// The type of the first result value is also set, even if it was already set in the
     /// `InstructionData` passed to `make_inst`. If this function is called with a single-result
     /// instruction, that is the only effect.
@@ -836,6 +1044,15 @@ impl DataFlowGraph {
     where
         I: Iterator<Item = Option<Value>>,
     {
+        debug_assert!(
+            ctrl_typevar == types::INVALID
+                || self.call_signature(inst).is_none()
+                || self.callee_type(inst) == Some(ctrl_typevar),
+            "the results of {} are the returns of its signature, so its controlling type must be \
+             INVALID rather than {}",
+            self.insts[inst].opcode(),
+            ctrl_typevar
+        );
         self.results[inst].clear(&mut self.value_lists);
 
         let mut reuse = reuse.fuse();
@@ -854,6 +1071,32 @@ impl DataFlowGraph {
         num_results
     }
 
+    /// The type of the callee of an indirect call, which is its controlling type variable.
+    fn callee_type(&self, inst: Inst) -> Option<Type> {
+        if !self.insts[inst].opcode().constraints().is_polymorphic() {
+            return None;
+        }
+        let callee = self.insts[inst].typevar_operand(&self.value_lists)?;
+        Some(self.value_type(callee))
+    }
+
+    /// Create result values for the call `inst`, one for each return of its signature `sig`.
+    ///
+    /// This works for direct and indirect calls alike. Tail calls such as `return_call` don't
+    /// return to the caller, so they get no result values whatever their signature returns.
+    ///
+    /// Panics if `inst` isn't a call with the signature `sig`.
+    pub fn make_inst_results_for_signature(&mut self, inst: Inst, sig: SigRef) -> usize {
+        assert_eq!(
+            self.call_signature(inst),
+            Some(sig),
+            "{} isn't a call with the signature {}",
+            self.display_inst(inst),
+            sig
+        );
+        self.make_inst_results(inst, types::INVALID)
+    }
+
     /// Create a `ReplaceBuilder` that will replace `inst` with a new instruction in place.
     pub fn replace(&mut self, inst: Inst) -> ReplaceBuilder {
         ReplaceBuilder::new(self, inst)
@@ -1479,8 +1722,12 @@ mod tests {
     use super::*;
     use crate::cursor::{Cursor, FuncCursor};
     use crate::ir::types;
-    use crate::ir::{Function, InstructionData, Opcode, TrapCode};
+    use crate::ir::{
+        AbiParam, ExternalName, Function, InstBuilder, InstructionData, Opcode, TrapCode,
+    };
+    use crate::isa;
     use alloc::string::ToString;
+    use alloc::vec::Vec;
 
     #[test]
     fn make_inst() {
@@ -1683,4 +1930,130 @@ mod tests {
         func.dfg.inst_args_mut(call_inst)[0] = v2;
         assert_eq!(v1, func.dfg.inst_args(call_inst_dup)[0]);
     }
+
+    /// Build a function with many values, instructions and blocks.
+    fn large_function() -> Function {
+        let mut func = Function::new();
+        let mut pos = FuncCursor::new(&mut func);
+        for _ in 0..1000 {
+            let block = pos.func.dfg.make_block();
+            pos.insert_block(block);
+            let mut sum = pos.func.dfg.append_block_param(block, types::I64);
+            for _ in 0..10 {
+                sum = pos.ins().iadd(sum, sum);
+            }
+            pos.ins().return_(&[sum]);
+        }
+        func
+    }
+
+    #[test]
+    fn shrink_to_fit() {
+        let mut func = large_function();
+        let usage = func.dfg.memory_usage().total();
+        assert!(usage > 0);
+
+        // Clearing the function keeps its memory.
+        func.clear();
+        assert_eq!(func.dfg.memory_usage().total(), usage);
+
+        func.shrink_to_fit();
+        assert!(func.dfg.memory_usage().total() * 10 < usage);
+
+        // Contexts only shrink their function when it uses more memory than the threshold.
+        let mut ctx = crate::Context::for_function(large_function());
+        ctx.set_shrink_threshold(Some(usage * 2));
+        ctx.clear();
+        assert_eq!(ctx.func.dfg.memory_usage().total(), usage);
+        ctx.func = large_function();
+        ctx.set_shrink_threshold(Some(usage / 2));
+        ctx.clear();
+        assert!(ctx.func.dfg.memory_usage().total() * 10 < usage);
+    }
+
+    /// A DFG with a function `fn0` of the signature `sig0`, which takes an `i64` and returns an
+    /// `i32` and an `f64`, and an `i64` value `v0` to pass to it.
+    fn call_dfg() -> (DataFlowGraph, SigRef, FuncRef, Value) {
+        let mut dfg = DataFlowGraph::new();
+        let mut sig = Signature::new(isa::CallConv::SystemV);
+        sig.params.push(AbiParam::new(types::I64));
+        sig.returns.push(AbiParam::new(types::I32));
+        sig.returns.push(AbiParam::new(types::F64));
+        let sig = dfg.signatures.push(sig);
+        let callee = dfg.ext_funcs.push(ExtFuncData {
+            name: ExternalName::testcase("callee"),
+            signature: sig,
+            colocated: false,
+        });
+        let block = dfg.make_block();
+        let arg = dfg.append_block_param(block, types::I64);
+        (dfg, sig, callee, arg)
+    }
+
+    #[test]
+    fn make_inst_results_for_signature() {
+        let (mut dfg, sig, callee, arg) = call_dfg();
+        let result_types = |dfg: &DataFlowGraph, inst| {
+            dfg.inst_results(inst)
+                .iter()
+                .map(|&result| dfg.value_type(result))
+                .collect::<Vec<_>>()
+        };
+
+        let args = ValueList::from_slice(&[arg], &mut dfg.value_lists);
+        let call = dfg.make_inst(InstructionData::Call {
+            opcode: Opcode::Call,
+            func_ref: callee,
+            args,
+        });
+        assert_eq!(dfg.make_inst_results_for_signature(call, sig), 2);
+        assert_eq!(result_types(&dfg, call), [types::I32, types::F64]);
+
+        let args = ValueList::from_slice(&[arg, arg], &mut dfg.value_lists);
+        let call_indirect = dfg.make_inst(InstructionData::CallIndirect {
+            opcode: Opcode::CallIndirect,
+            sig_ref: sig,
+            args,
+        });
+        assert_eq!(dfg.make_inst_results_for_signature(call_indirect, sig), 2);
+        assert_eq!(result_types(&dfg, call_indirect), [types::I32, types::F64]);
+
+        // Tail calls don't return to the caller.
+        let args = ValueList::from_slice(&[arg], &mut dfg.value_lists);
+        let return_call = dfg.make_inst(InstructionData::Call {
+            opcode: Opcode::ReturnCall,
+            func_ref: callee,
+            args,
+        });
+        assert_eq!(dfg.make_inst_results_for_signature(return_call, sig), 0);
+        assert!(dfg.inst_results(return_call).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "isn't a call with the signature sig1")]
+    fn make_inst_results_for_other_signature() {
+        let (mut dfg, _, callee, arg) = call_dfg();
+        let other = dfg.signatures.push(Signature::new(isa::CallConv::SystemV));
+        let args = ValueList::from_slice(&[arg], &mut dfg.value_lists);
+        let call = dfg.make_inst(InstructionData::Call {
+            opcode: Opcode::Call,
+            func_ref: callee,
+            args,
+        });
+        dfg.make_inst_results_for_signature(call, other);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "the results of call are the returns of its signature")]
+    fn make_call_results_for_type() {
+        let (mut dfg, _, callee, arg) = call_dfg();
+        let args = ValueList::from_slice(&[arg], &mut dfg.value_lists);
+        let call = dfg.make_inst(InstructionData::Call {
+            opcode: Opcode::Call,
+            func_ref: callee,
+            args,
+        });
+        dfg.make_inst_results(call, types::I32);
+    }
 }
@@ -131,6 +131,68 @@ impl fmt::Display for Signature {
     }
 }
 
+impl FromStr for Signature {
+    type Err = &'static str;
+
+    /// Parse a signature in the syntax used by CLIF, such as
+    /// `(i64 vmctx, i32 uext) -> i32 system_v`. This is the inverse of `Display::fmt`.
+    ///
+    /// As in CLIF, the calling convention defaults to `fast` when it is omitted.
+    fn from_str(s: &str) -> Result<Self, &'static str> {
+        let rest = s
+            .trim()
+            .strip_prefix('(')
+            .ok_or("Expected '(' to begin the parameters")?;
+        // `sarg(size)` purposes nest parentheses in the parameter list.
+        let mut depth = 0;
+        let close = rest
+            .find(|c| match c {
+                '(' => {
+                    depth += 1;
+                    false
+                }
+                ')' if depth == 0 => true,
+                ')' => {
+                    depth -= 1;
+                    false
+                }
+                _ => false,
+            })
+            .ok_or("Expected ')' to end the parameters")?;
+
+        let mut sig = Self::new(CallConv::Fast);
+        if !rest[..close].trim().is_empty() {
+            sig.params = parse_abi_param_list(&rest[..close])?;
+        }
+        let mut call_conv = rest[close + 1..].trim();
+        if let Some(returns) = call_conv.strip_prefix("->") {
+            // The calling convention, if any, is the word following the last return value.
+            let returns = match returns.trim_end().rsplit_once(char::is_whitespace) {
+                Some((returns, last)) if last.parse::<CallConv>().is_ok() => {
+                    call_conv = last;
+                    returns
+                }
+                _ => {
+                    call_conv = "";
+                    returns
+                }
+            };
+            sig.returns = parse_abi_param_list(returns)?;
+        }
+        if !call_conv.is_empty() {
+            sig.call_conv = call_conv
+                .parse()
+                .map_err(|()| "Unknown calling convention")?;
+        }
+        Ok(sig)
+    }
+}
+
+/// Parse a comma-separated list of parameters or return values.
+fn parse_abi_param_list(s: &str) -> Result<Vec<AbiParam>, &'static str> {
+    s.split(',').map(str::parse).collect()
+}
+
 /// Function parameter or return value descriptor.
 ///
 /// This describes the value type being passed to or from a function along with flags that affect
@@ -199,6 +261,30 @@ impl fmt::Display for AbiParam {
     }
 }
 
+impl FromStr for AbiParam {
+    type Err = &'static str;
+
+    /// Parse a type followed by its extension and purpose, if any. This is the inverse of
+    /// `Display::fmt`.
+    fn from_str(s: &str) -> Result<Self, &'static str> {
+        let mut words = s.split_whitespace();
+        let ty = words.next().ok_or("Expected a parameter type")?;
+        let mut param = Self::new(ty.parse()?);
+        for word in words {
+            match word {
+                "uext" => param.extension = ArgumentExtension::Uext,
+                "sext" => param.extension = ArgumentExtension::Sext,
+                _ => {
+                    param.purpose = word
+                        .parse()
+                        .map_err(|()| "Unknown parameter extension or purpose")?
+                }
+            }
+        }
+        Ok(param)
+    }
+}
+
 /// Function argument extension options.
 ///
 /// On some architectures, small integer function arguments and/or return values are extended to
@@ -364,7 +450,7 @@ impl<'a> fmt::Display for DisplayableExtFuncData<'a> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::ir::types::{F32, I32, I8};
+    use crate::ir::types::{F32, I32, I32X4, I64, I8};
     use alloc::string::ToString;
 
     #[test]
@@ -418,4 +504,64 @@ mod tests {
         sig.returns.push(AbiParam::new(I8));
         assert_eq!(sig.to_string(), "(i32, i32x4) -> f32, i8 windows_fastcall");
     }
+
+    #[test]
+    fn parse_signatures() {
+        let mut sig = Signature::new(CallConv::SystemV);
+        sig.params
+            .push(AbiParam::special(I64, ArgumentPurpose::StructReturn));
+        sig.params
+            .push(AbiParam::special(I64, ArgumentPurpose::VMContext));
+        sig.params.push(AbiParam::new(I8).uext());
+        sig.params.push(AbiParam::new(I32).sext());
+        sig.params
+            .push(AbiParam::special(I64, ArgumentPurpose::StructArgument(24)));
+        sig.params.push(AbiParam::new(I32X4));
+        sig.returns.push(AbiParam::new(I8).sext());
+        sig.returns.push(AbiParam::new(F32));
+        sig.returns
+            .push(AbiParam::special(I64, ArgumentPurpose::StructReturn));
+        assert_eq!(
+            sig.to_string(),
+            "(i64 sret, i64 vmctx, i8 uext, i32 sext, i64 sarg(24), i32x4) \
+             -> i8 sext, f32, i64 sret system_v"
+        );
+
+        for &call_conv in &[
+            CallConv::Fast,
+            CallConv::Cold,
+            CallConv::Tail,
+            CallConv::SystemV,
+            CallConv::WindowsFastcall,
+            CallConv::AppleAarch64,
+            CallConv::Probestack,
+            CallConv::WasmtimeSystemV,
+            CallConv::WasmtimeFastcall,
+            CallConv::WasmtimeAppleAarch64,
+        ] {
+            sig.call_conv = call_conv;
+            assert_eq!(sig.to_string().parse(), Ok(sig.clone()));
+
+            let mut no_returns = sig.clone();
+            no_returns.returns.clear();
+            assert_eq!(no_returns.to_string().parse(), Ok(no_returns));
+
+            let empty = Signature::new(call_conv);
+            assert_eq!(empty.to_string().parse(), Ok(empty));
+        }
+
+        // The calling convention is optional.
+        let mut sig = Signature::new(CallConv::Fast);
+        sig.params.push(AbiParam::new(I32));
+        sig.returns.push(AbiParam::new(I32));
+        assert_eq!("(i32) -> i32".parse(), Ok(sig));
+        assert_eq!("()".parse(), Ok(Signature::new(CallConv::Fast)));
+
+        assert!("i32".parse::<Signature>().is_err());
+        assert!("(i32".parse::<Signature>().is_err());
+        assert!("(i32,) -> i32".parse::<Signature>().is_err());
+        assert!("(i32) ->".parse::<Signature>().is_err());
+        assert!("(i32 zext) -> i32".parse::<Signature>().is_err());
+        assert!("(i32) -> i32 stdcall".parse::<Signature>().is_err());
+    }
 }
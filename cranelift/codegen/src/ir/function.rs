@@ -3,19 +3,20 @@
 //! The `Function` struct defined in this module owns all of its basic blocks and
 //! instructions.
 
-use crate::entity::{PrimaryMap, SecondaryMap};
+use crate::cursor::{Cursor, FuncCursor};
+use crate::entity::{EntityRef, PrimaryMap, SecondaryMap};
 use crate::ir::{
     self, Block, DataFlowGraph, DynamicStackSlot, DynamicStackSlotData, DynamicStackSlots,
-    DynamicType, ExtFuncData, FuncRef, GlobalValue, GlobalValueData, Inst, JumpTable,
-    JumpTableData, Layout, Opcode, SigRef, Signature, SourceLocs, StackSlot, StackSlotData,
-    StackSlots, Table, TableData, Type,
+    DynamicType, ExtFuncData, FuncRef, GlobalValue, GlobalValueData, Inst, InstructionData,
+    JumpTable, JumpTableData, Layout, Opcode, SigRef, Signature, SourceLocs, StackSlot,
+    StackSlotData, StackSlots, Table, TableData, Type, Value,
 };
 use crate::isa::CallConv;
 use crate::value_label::ValueLabelsRanges;
 use crate::write::write_function;
 use crate::HashMap;
-#[cfg(feature = "enable-serde")]
-use alloc::string::String;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
 use core::fmt;
 
 #[cfg(feature = "enable-serde")]
@@ -140,6 +141,11 @@ impl FunctionParameters {
         self.user_named_funcs.clear();
         self.user_ext_name_to_ref.clear();
     }
+
+    fn shrink_to_fit(&mut self) {
+        self.user_named_funcs.shrink_to_fit();
+        self.user_ext_name_to_ref.shrink_to_fit();
+    }
 }
 
 /// Function fields needed when compiling a function.
@@ -203,6 +209,16 @@ impl FunctionStencil {
         self.stack_limit = None;
     }
 
+    fn shrink_to_fit(&mut self) {
+        self.sized_stack_slots.shrink_to_fit();
+        self.dynamic_stack_slots.shrink_to_fit();
+        self.global_values.shrink_to_fit();
+        self.tables.shrink_to_fit();
+        self.dfg.shrink_to_fit();
+        self.layout.shrink_to_fit();
+        self.srclocs.shrink_to_fit();
+    }
+
     /// Creates a jump table in the function, to be used by `br_table` instructions.
     pub fn create_jump_table(&mut self, data: JumpTableData) -> JumpTable {
         self.dfg.jump_tables.push(data)
@@ -312,6 +328,49 @@ impl FunctionStencil {
         self.dfg.signatures.is_empty()
     }
 
+    /// Append the instruction `data` to the end of `block`, with results created for the
+    /// controlling type variable `ctrl_typevar` as by [`DataFlowGraph::make_inst_results`].
+    ///
+    /// Returns the instruction and its results, of which terminators have none and calls have as
+    /// many as their signature returns. This is the only step needed to add an instruction when
+    /// building a function by hand, without a [`FuncCursor`] or a `FunctionBuilder`.
+    pub fn emit(
+        &mut self,
+        block: Block,
+        data: InstructionData,
+        ctrl_typevar: Type,
+    ) -> (Inst, &[Value]) {
+        debug_assert!(
+            self.layout.is_block_inserted(block),
+            "{block} must be inserted in the layout before instructions are appended to it"
+        );
+        let inst = self.dfg.make_inst(data);
+        self.dfg.make_inst_results(inst, ctrl_typevar);
+        self.layout.append_inst(inst, block);
+        (inst, self.dfg.inst_results(inst))
+    }
+
+    /// Like [`FunctionStencil::emit`], for an instruction whose result types follow from the
+    /// instruction itself: one which isn't polymorphic, such as a call or a branch, or whose
+    /// controlling type variable is the type of one of its operands, such as `iadd` or `icmp`.
+    ///
+    /// Panics for instructions such as `iconst` or `load`, whose result types must be given.
+    pub fn emit_inferred(&mut self, block: Block, data: InstructionData) -> (Inst, &[Value]) {
+        let opcode = data.opcode();
+        let constraints = opcode.constraints();
+        let ctrl_typevar = if !constraints.is_polymorphic() {
+            ir::types::INVALID
+        } else if constraints.use_typevar_operand() {
+            let operand = data
+                .typevar_operand(&self.dfg.value_lists)
+                .expect("instructions using a typevar operand have one");
+            self.dfg.value_type(operand)
+        } else {
+            panic!("the result types of `{opcode}` can't be inferred and must be given to `emit`")
+        };
+        self.emit(block, data, ctrl_typevar)
+    }
+
     /// Replace the `dst` instruction's data with the `src` instruction's data
     /// and then remove `src`.
     ///
@@ -410,6 +469,16 @@ impl Function {
         self.name = UserFuncName::default();
     }
 
+    /// Release the capacity of the data structures of this function which isn't in use.
+    ///
+    /// Like [`Function::clear`], this is useful for functions reused across compilations: clearing
+    /// a function keeps its memory for the next one, and shrinking it afterwards releases the
+    /// memory accumulated by a large function.
+    pub fn shrink_to_fit(&mut self) {
+        self.stencil.shrink_to_fit();
+        self.params.shrink_to_fit();
+    }
+
     /// Create a new empty, anonymous function with a Fast calling convention.
     pub fn new() -> Self {
         Self::with_name_signature(Default::default(), Signature::new(CallConv::Fast))
@@ -428,6 +497,117 @@ impl Function {
         DisplayFunction(self, annotations)
     }
 
+    /// Renumber the values, instructions, blocks and jump tables of this function in a canonical
+    /// order, so that functions with the same structure are numbered the same regardless of the
+    /// order in which they were built.
+    ///
+    /// Blocks and instructions are numbered in layout order. Values are numbered in the order
+    /// they first appear in the layout: a block's parameters, then for each of its instructions
+    /// their arguments followed by their results. Jump tables are numbered in the order of the
+    /// `br_table`s using them. Entities that don't appear in the layout come last, in their
+    /// previous order.
+    ///
+    /// Aliases are resolved and removed.
+    pub fn renumber_canonical(&mut self) {
+        let dfg = &self.stencil.dfg;
+        let layout = &self.stencil.layout;
+
+        let mut blocks: Vec<Block> = layout.blocks().collect();
+        let mut insts: Vec<Inst> = blocks
+            .iter()
+            .flat_map(|&block| layout.block_insts(block))
+            .collect();
+        let (laid_out_blocks, laid_out_insts) = (blocks.len(), insts.len());
+        blocks.extend(
+            (0..dfg.num_blocks())
+                .map(Block::new)
+                .filter(|&block| !layout.is_block_inserted(block)),
+        );
+        insts.extend(
+            (0..dfg.num_insts())
+                .map(Inst::new)
+                .filter(|&inst| layout.inst_block(inst).is_none()),
+        );
+
+        let mut values = Vec::with_capacity(dfg.num_values());
+        let mut seen: SecondaryMap<Value, bool> = SecondaryMap::with_capacity(dfg.num_values());
+        let mut visit = |value: Value| {
+            let value = dfg.resolve_aliases(value);
+            if !seen[value] {
+                seen[value] = true;
+                values.push(value);
+            }
+        };
+        let visit_inst = |inst: Inst, visit: &mut dyn FnMut(Value)| {
+            dfg.inst_values(inst).for_each(&mut *visit);
+            dfg.inst_results(inst).iter().copied().for_each(visit);
+        };
+        for &block in &blocks[..laid_out_blocks] {
+            dfg.block_params(block).iter().copied().for_each(&mut visit);
+            for inst in layout.block_insts(block) {
+                visit_inst(inst, &mut visit);
+            }
+        }
+        for &block in &blocks[laid_out_blocks..] {
+            dfg.block_params(block).iter().copied().for_each(&mut visit);
+        }
+        for &inst in &insts[laid_out_insts..] {
+            visit_inst(inst, &mut visit);
+        }
+        for value in dfg.values() {
+            if dfg.value_alias_dest_for_serialization(value).is_none() {
+                visit(value);
+            }
+        }
+
+        let mut jump_tables = Vec::with_capacity(self.stencil.dfg.jump_tables.len());
+        let mut seen_tables: SecondaryMap<JumpTable, bool> =
+            SecondaryMap::with_capacity(self.stencil.dfg.jump_tables.len());
+        let tables_in_order = insts.iter().filter_map(|&inst| match dfg.insts[inst] {
+            InstructionData::BranchTable { table, .. } => Some(table),
+            _ => None,
+        });
+        for table in tables_in_order.chain(dfg.jump_tables.keys()) {
+            if !seen_tables[table] {
+                seen_tables[table] = true;
+                jump_tables.push(table);
+            }
+        }
+
+        let (inst_map, block_map) =
+            self.stencil
+                .dfg
+                .renumber(&insts, &blocks, &values, &jump_tables);
+
+        let mut layout = Layout::new();
+        for block in self.stencil.layout.blocks() {
+            layout.append_block(block_map[block]);
+            if self.stencil.layout.is_cold(block) {
+                layout.set_cold(block_map[block]);
+            }
+            for inst in self.stencil.layout.block_insts(block) {
+                layout.append_inst(inst_map[inst], block_map[block]);
+            }
+        }
+        self.stencil.layout = layout;
+
+        let mut srclocs = SourceLocs::with_capacity(insts.len());
+        for (new, &old) in insts.iter().enumerate() {
+            srclocs[Inst::new(new)] = self.stencil.srclocs[old];
+        }
+        self.stencil.srclocs = srclocs;
+    }
+
+    /// Display a copy of this function renumbered by [`Function::renumber_canonical`].
+    ///
+    /// Unlike [`Function::display`], the result doesn't depend on the order in which the
+    /// function was built, which makes it suitable for comparing functions.
+    pub fn display_canonical(&self) -> String {
+        let mut func = self.clone();
+        func.renumber_canonical();
+        func.display().to_string()
+    }
+
     /// Sets an absolute source location for the given instruction.
     ///
     /// If no base source location has been set yet, records it at the same time.
@@ -454,6 +634,64 @@ impl Function {
     pub fn import_function(&mut self, data: ExtFuncData) -> FuncRef {
         self.stencil.dfg.ext_funcs.push(data)
     }
+
+    /// Find all frontend extension instructions (`ext0` to `ext3`), in layout order.
+    pub fn ext_insts(&self) -> Vec<ExtInst> {
+        let mut exts = Vec::new();
+        for block in self.layout.blocks() {
+            for inst in self.layout.block_insts(block) {
+                if let Some(slot) = self.dfg.insts[inst].opcode().ext_slot() {
+                    exts.push(ExtInst {
+                        inst,
+                        slot,
+                        args: self.dfg.inst_args(inst).to_vec(),
+                        result: self.dfg.first_result(inst),
+                    });
+                }
+            }
+        }
+        exts
+    }
+
+    /// Replace frontend extension instructions with real ones.
+    ///
+    /// `replace` is called for every instruction found by `ext_insts`, with a cursor positioned
+    /// at it, so that instructions it inserts end up right before the extension instruction. When
+    /// it returns the value computing the extension's result, the extension instruction is
+    /// removed and its result becomes an alias of that value; when it returns `None` the
+    /// extension instruction is left in place.
+    ///
+    /// Returns the number of replaced instructions.
+    pub fn replace_ext_insts(
+        &mut self,
+        mut replace: impl FnMut(&mut FuncCursor, &ExtInst) -> Option<Value>,
+    ) -> usize {
+        let mut replaced = 0;
+        for ext in self.ext_insts() {
+            let mut pos = FuncCursor::new(self).at_inst(ext.inst);
+            if let Some(value) = replace(&mut pos, &ext) {
+                pos.goto_inst(ext.inst);
+                pos.remove_inst();
+                self.dfg.clear_results(ext.inst);
+                self.dfg.change_to_alias(ext.result, value);
+                replaced += 1;
+            }
+        }
+        replaced
+    }
+}
+
+/// A frontend extension instruction, as found by `Function::ext_insts`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExtInst {
+    /// The instruction itself.
+    pub inst: Inst,
+    /// Which of `ext0` to `ext3` the instruction is.
+    pub slot: u8,
+    /// The instruction's arguments.
+    pub args: Vec<Value>,
+    /// The instruction's result.
+    pub result: Value,
 }
 
 /// Additional annotations for function display.
@@ -483,3 +721,105 @@ impl fmt::Debug for Function {
         write_function(fmt, self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::condcodes::IntCC;
+    use crate::ir::{types, AbiParam, ExternalName, UserExternalName};
+
+    #[test]
+    fn emit() {
+        let mut sig = Signature::new(CallConv::SystemV);
+        sig.params.push(AbiParam::new(types::I32));
+        sig.returns.push(AbiParam::new(types::I32));
+        let mut func = Function::with_name_signature(UserFuncName::default(), sig.clone());
+        let mut pair = Signature::new(CallConv::SystemV);
+        pair.returns = vec![AbiParam::new(types::I64), AbiParam::new(types::I8)];
+        let sig_ref = func.import_signature(pair);
+        let name = func.declare_imported_user_function(UserExternalName::new(0, 1));
+        let callee = func.import_function(ExtFuncData {
+            name: ExternalName::user(name),
+            signature: sig_ref,
+            colocated: false,
+        });
+
+        let block = func.dfg.make_block();
+        func.layout.append_block(block);
+        let x = func.dfg.append_block_param(block, types::I32);
+
+        let data = InstructionData::UnaryImm {
+            opcode: Opcode::Iconst,
+            imm: 3.into(),
+        };
+        let (_, results) = func.emit(block, data, types::I32);
+        let three = results[0];
+        let data = InstructionData::Binary {
+            opcode: Opcode::Iadd,
+            args: [x, three],
+        };
+        let (add, results) = func.emit_inferred(block, data);
+        assert_eq!(results.len(), 1);
+        let sum = results[0];
+        assert_eq!(func.dfg.value_type(sum), types::I32);
+        let data = InstructionData::IntCompareImm {
+            opcode: Opcode::IcmpImm,
+            cond: IntCC::Equal,
+            arg: sum,
+            imm: 0.into(),
+        };
+        let cmp = func.emit_inferred(block, data).1[0];
+        assert_eq!(func.dfg.value_type(cmp), types::I8);
+
+        // A call has the results of its signature.
+        let data = InstructionData::Call {
+            opcode: Opcode::Call,
+            func_ref: callee,
+            args: ir::ValueList::new(),
+        };
+        let (call, results) = func.emit_inferred(block, data);
+        let results = results.to_vec();
+        let types: Vec<_> = results.iter().map(|&v| func.dfg.value_type(v)).collect();
+        assert_eq!(types, [types::I64, types::I8]);
+
+        // A terminator has none.
+        let args = ir::ValueList::from_slice(&[sum], &mut func.dfg.value_lists);
+        let data = InstructionData::MultiAry {
+            opcode: Opcode::Return,
+            args,
+        };
+        let (ret, results) = func.emit_inferred(block, data);
+        assert!(results.is_empty());
+
+        let insts: Vec<_> = func.layout.block_insts(block).collect();
+        assert_eq!(insts.len(), 5);
+        assert_eq!(insts[1], add);
+        assert_eq!(insts[3], call);
+        assert_eq!(insts[4], ret);
+    }
+
+    #[test]
+    #[should_panic(expected = "the result types of `iconst` can't be inferred")]
+    fn emit_uninferrable() {
+        let mut func = Function::new();
+        let block = func.dfg.make_block();
+        func.layout.append_block(block);
+        let data = InstructionData::UnaryImm {
+            opcode: Opcode::Iconst,
+            imm: 3.into(),
+        };
+        func.emit_inferred(block, data);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "must be inserted in the layout")]
+    fn emit_in_missing_block() {
+        let mut func = Function::new();
+        let block = func.dfg.make_block();
+        let data = InstructionData::NullAry {
+            opcode: Opcode::Nop,
+        };
+        func.emit(block, data, ir::types::INVALID);
+    }
+}
@@ -14,6 +14,37 @@ use core::{i32, u32};
 #[cfg(feature = "enable-serde")]
 use serde::{Deserialize, Serialize};
 
+/// The float operations that are only provided by std; without it, libm is used instead.
+#[cfg(feature = "std")]
+mod float {
+    pub fn sqrtf(x: f32) -> f32 {
+        x.sqrt()
+    }
+    pub fn ceilf(x: f32) -> f32 {
+        x.ceil()
+    }
+    pub fn floorf(x: f32) -> f32 {
+        x.floor()
+    }
+    pub fn truncf(x: f32) -> f32 {
+        x.trunc()
+    }
+    pub fn sqrt(x: f64) -> f64 {
+        x.sqrt()
+    }
+    pub fn ceil(x: f64) -> f64 {
+        x.ceil()
+    }
+    pub fn floor(x: f64) -> f64 {
+        x.floor()
+    }
+    pub fn trunc(x: f64) -> f64 {
+        x.trunc()
+    }
+}
+#[cfg(not(feature = "std"))]
+use libm as float;
+
 /// Convert a type into a vector of bytes; all implementors in this file must use little-endian
 /// orderings of bytes to match WebAssembly's little-endianness.
 pub trait IntoBytes {
@@ -790,7 +821,7 @@ impl Ieee32 {
 
     /// Returns the square root of self.
     pub fn sqrt(self) -> Self {
-        Self::with_float(self.as_f32().sqrt())
+        Self::with_float(float::sqrtf(self.as_f32()))
     }
 
     /// Computes the absolute value of self.
@@ -815,17 +846,17 @@ impl Ieee32 {
 
     /// Returns the smallest integer greater than or equal to `self`.
     pub fn ceil(self) -> Self {
-        Self::with_float(self.as_f32().ceil())
+        Self::with_float(float::ceilf(self.as_f32()))
     }
 
     /// Returns the largest integer less than or equal to `self`.
     pub fn floor(self) -> Self {
-        Self::with_float(self.as_f32().floor())
+        Self::with_float(float::floorf(self.as_f32()))
     }
 
     /// Returns the integer part of `self`. This means that non-integer numbers are always truncated towards zero.
     pub fn trunc(self) -> Self {
-        Self::with_float(self.as_f32().trunc())
+        Self::with_float(float::truncf(self.as_f32()))
     }
 
     /// Returns the nearest integer to `self`. Rounds half-way cases to the number
@@ -1008,7 +1039,7 @@ impl Ieee64 {
 
     /// Returns the square root of self.
     pub fn sqrt(self) -> Self {
-        Self::with_float(self.as_f64().sqrt())
+        Self::with_float(float::sqrt(self.as_f64()))
     }
 
     /// Computes the absolute value of self.
@@ -1033,17 +1064,17 @@ impl Ieee64 {
 
     /// Returns the smallest integer greater than or equal to `self`.
     pub fn ceil(self) -> Self {
-        Self::with_float(self.as_f64().ceil())
+        Self::with_float(float::ceil(self.as_f64()))
     }
 
     /// Returns the largest integer less than or equal to `self`.
     pub fn floor(self) -> Self {
-        Self::with_float(self.as_f64().floor())
+        Self::with_float(float::floor(self.as_f64()))
     }
 
     /// Returns the integer part of `self`. This means that non-integer numbers are always truncated towards zero.
     pub fn trunc(self) -> Self {
-        Self::with_float(self.as_f64().trunc())
+        Self::with_float(float::trunc(self.as_f64()))
     }
 
     /// Returns the nearest integer to `self`. Rounds half-way cases to the number
@@ -200,6 +200,29 @@ impl Opcode {
             _ => false,
         }
     }
+
+    /// Returns true if the instruction is an atomic memory access (`atomic_rmw`, `atomic_cas`,
+    /// `atomic_load` or `atomic_store`).
+    pub fn is_atomic(self) -> bool {
+        match self {
+            Opcode::AtomicRmw | Opcode::AtomicCas | Opcode::AtomicLoad | Opcode::AtomicStore => {
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns the slot of a frontend extension instruction (`ext0` to `ext3`), or `None` for all
+    /// other opcodes.
+    pub fn ext_slot(self) -> Option<u8> {
+        match self {
+            Opcode::Ext0 => Some(0),
+            Opcode::Ext1 => Some(1),
+            Opcode::Ext2 => Some(2),
+            Opcode::Ext3 => Some(3),
+            _ => None,
+        }
+    }
 }
 
 // This trait really belongs in cranelift-reader where it is used by the `.clif` file parser, but since
@@ -308,8 +331,8 @@ impl InstructionData {
         match self {
             Self::Jump {
                 ref destination, ..
-            } => std::slice::from_ref(destination),
-            Self::Brif { blocks, .. } => blocks.as_slice(),
+            } => core::slice::from_ref(destination),
+            Self::Brif { blocks, .. } | Self::PatchableJump { blocks, .. } => blocks.as_slice(),
             Self::BranchTable { table, .. } => jump_tables.get(*table).unwrap().all_branches(),
             _ => {
                 debug_assert!(!self.opcode().is_branch());
@@ -329,8 +352,8 @@ impl InstructionData {
             Self::Jump {
                 ref mut destination,
                 ..
-            } => std::slice::from_mut(destination),
-            Self::Brif { blocks, .. } => blocks.as_mut_slice(),
+            } => core::slice::from_mut(destination),
+            Self::Brif { blocks, .. } | Self::PatchableJump { blocks, .. } => blocks.as_mut_slice(),
             Self::BranchTable { table, .. } => {
                 jump_tables.get_mut(*table).unwrap().all_branches_mut()
             }
@@ -345,7 +368,9 @@ impl InstructionData {
     /// `None`.
     pub fn trap_code(&self) -> Option<TrapCode> {
         match *self {
-            Self::CondTrap { code, .. } | Self::Trap { code, .. } => Some(code),
+            Self::CondTrap { code, .. }
+            | Self::Trap { code, .. }
+            | Self::IntAddTrap { code, .. } => Some(code),
             _ => None,
         }
     }
@@ -373,7 +398,9 @@ impl InstructionData {
     /// trap code. Otherwise, return `None`.
     pub fn trap_code_mut(&mut self) -> Option<&mut TrapCode> {
         match self {
-            Self::CondTrap { code, .. } | Self::Trap { code, .. } => Some(code),
+            Self::CondTrap { code, .. }
+            | Self::Trap { code, .. }
+            | Self::IntAddTrap { code, .. } => Some(code),
             _ => None,
         }
     }
@@ -848,7 +875,7 @@ mod tests {
     fn inst_data_size() {
         // The size of `InstructionData` is performance sensitive, so make sure
         // we don't regress it unintentionally.
-        assert_eq!(std::mem::size_of::<InstructionData>(), 16);
+        assert_eq!(core::mem::size_of::<InstructionData>(), 16);
     }
 
     #[test]
@@ -64,6 +64,12 @@ impl Layout {
     pub fn block_capacity(&self) -> usize {
         self.blocks.capacity()
     }
+
+    /// Release the capacity of the layout which isn't in use.
+    pub fn shrink_to_fit(&mut self) {
+        self.blocks.shrink_to_fit();
+        self.insts.shrink_to_fit();
+    }
 }
 
 /// Sequence numbers.
@@ -2,6 +2,7 @@
 
 use core::default::Default;
 use core::fmt::{self, Debug, Display, Formatter};
+use core::str::FromStr;
 use cranelift_codegen_shared::constants;
 #[cfg(feature = "enable-serde")]
 use serde::{Deserialize, Serialize};
@@ -474,6 +475,38 @@ impl Default for Type {
     }
 }
 
+impl FromStr for Type {
+    type Err = &'static str;
+
+    /// Parse a scalar or fixed vector type. This is the inverse of `Display::fmt`.
+    fn from_str(s: &str) -> Result<Self, &'static str> {
+        let (lane, lanes) = match s.split_once('x') {
+            Some((lane, lanes)) => (lane, Some(lanes)),
+            None => (s, None),
+        };
+        let lane = match lane {
+            "i8" => I8,
+            "i16" => I16,
+            "i32" => I32,
+            "i64" => I64,
+            "i128" => I128,
+            "f32" => F32,
+            "f64" => F64,
+            "r32" => R32,
+            "r64" => R64,
+            _ => return Err("Unknown type"),
+        };
+        match lanes {
+            None => Ok(lane),
+            Some(lanes) => lanes
+                .parse()
+                .ok()
+                .and_then(|lanes| lane.by(lanes))
+                .ok_or("Invalid vector lane count"),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -602,6 +635,16 @@ mod tests {
         assert_eq!(INVALID.by(4), None);
     }
 
+    #[test]
+    fn parse_types() {
+        for ty in [I8, I64, I128, F32, R64, I8X16, F64X2, I32X8] {
+            assert_eq!(ty.to_string().parse(), Ok(ty));
+        }
+        assert_eq!("i9".parse::<Type>(), Err("Unknown type"));
+        assert_eq!("i8x3".parse::<Type>(), Err("Invalid vector lane count"));
+        assert_eq!("i8x".parse::<Type>(), Err("Invalid vector lane count"));
+    }
+
     #[test]
     fn as_truthy() {
         assert_eq!(I32X4.as_truthy(), I32X4);
@@ -670,6 +670,10 @@ impl MachInstEmitState<Inst> for EmitState {
     fn take_ctrl_plane(self) -> ControlPlane {
         self.ctrl_plane
     }
+
+    fn virtual_sp_offset(&self) -> i64 {
+        self.virtual_sp_offset
+    }
 }
 
 impl EmitState {
@@ -888,6 +892,26 @@ impl MachInstEmit for Inst {
                 );
             }
 
+            &Inst::Extr {
+                size,
+                rd,
+                rn,
+                rm,
+                ref lsb,
+            } => {
+                let rd = allocs.next_writable(rd);
+                let rn = allocs.next(rn);
+                let rm = allocs.next(rm);
+                let top11 = 0b00010011100 | size.sf_bit() << 10 | size.sf_bit() << 1;
+                sink.put4(
+                    (top11 << 21)
+                        | (machreg_to_gpr(rm) << 16)
+                        | (u32::from(lsb.value()) << 10)
+                        | (machreg_to_gpr(rn) << 5)
+                        | machreg_to_gpr(rd.to_reg()),
+                );
+            }
+
             &Inst::AluRRRShift {
                 alu_op,
                 size,
@@ -3110,6 +3134,16 @@ impl MachInstEmit for Inst {
                 // Emit the jump itself.
                 sink.put4(enc_jump26(0b000101, dest.as_offset26_or_zero()));
             }
+            &Inst::PatchableJump { initial, alternate } => {
+                // A `b` is a naturally aligned 32-bit word which is patched as a whole. It isn't
+                // registered as a branch, so that the MachBuffer never removes it.
+                let initial = initial.as_label().unwrap();
+                let alternate = alternate.as_label().unwrap();
+                let off = sink.cur_offset();
+                sink.use_label_at_offset(off, initial, LabelUse::Branch26);
+                sink.add_patchable_jump([initial, alternate]);
+                sink.put4(enc_jump26(0b000101, 0));
+            }
             &Inst::Args { .. } => {
                 // Nothing: this is a pseudoinstruction that serves
                 // only to constrain registers at a certain point.
@@ -3399,6 +3433,20 @@ impl MachInstEmit for Inst {
                         flags: MemFlags::trusted(),
                     };
                     inst.emit(&[], sink, emit_info, state);
+
+                    // The relocations above refer to the GOT entry rather than the symbol, so the
+                    // offset is added separately.
+                    if offset != 0 {
+                        let inst = Inst::LoadAddr {
+                            rd,
+                            mem: AMode::RegOffset {
+                                rn: rd.to_reg(),
+                                off: offset,
+                                ty: I8,
+                            },
+                        };
+                        inst.emit(&[], sink, emit_info, state);
+                    }
                 } else {
                     // With absolute offsets we set up a load from a preallocated space, and then jump
                     // over it.
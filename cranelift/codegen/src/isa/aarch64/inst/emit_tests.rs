@@ -1160,6 +1160,28 @@ fn test_aarch64_binemit() {
         "umulh x1, x2, x3",
     ));
 
+    insns.push((
+        Inst::Extr {
+            size: OperandSize::Size32,
+            rd: writable_xreg(1),
+            rn: xreg(2),
+            rm: xreg(3),
+            lsb: ImmShift::maybe_from_u64(5).unwrap(),
+        },
+        "41148313",
+        "extr w1, w2, w3, #5",
+    ));
+    insns.push((
+        Inst::Extr {
+            size: OperandSize::Size64,
+            rd: writable_xreg(20),
+            rn: xreg(21),
+            rm: xreg(22),
+            lsb: ImmShift::maybe_from_u64(42).unwrap(),
+        },
+        "B4AAD693",
+        "extr x20, x21, x22, #42",
+    ));
     insns.push((
         Inst::AluRRImmShift {
             alu_op: ALUOp::RotR,
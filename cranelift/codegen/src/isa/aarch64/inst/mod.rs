@@ -9,11 +9,11 @@ use crate::{settings, CodegenError, CodegenResult};
 
 use crate::machinst::{PrettyPrint, Reg, RegClass, Writable};
 
+use alloc::string::{String, ToString};
 use alloc::vec::Vec;
+use core::fmt::Write;
 use regalloc2::{PRegSet, VReg};
 use smallvec::{smallvec, SmallVec};
-use std::fmt::Write;
-use std::string::{String, ToString};
 
 pub(crate) mod regs;
 pub(crate) use self::regs::*;
@@ -148,7 +148,7 @@ fn count_zero_half_words(mut value: u64, num_half_words: u8) -> usize {
 fn inst_size_test() {
     // This test will help with unintentionally growing the size
     // of the Inst enum.
-    assert_eq!(32, std::mem::size_of::<Inst>());
+    assert_eq!(32, core::mem::size_of::<Inst>());
 }
 
 impl Inst {
@@ -426,6 +426,11 @@ fn aarch64_get_operands<F: Fn(VReg) -> VReg>(inst: &Inst, collector: &mut Operan
             collector.reg_def(rd);
             collector.reg_use(rn);
         }
+        &Inst::Extr { rd, rn, rm, .. } => {
+            collector.reg_def(rd);
+            collector.reg_use(rn);
+            collector.reg_use(rm);
+        }
         &Inst::AluRRRShift { rd, rn, rm, .. } => {
             collector.reg_def(rd);
             collector.reg_use(rn);
@@ -847,7 +852,7 @@ fn aarch64_get_operands<F: Fn(VReg) -> VReg>(inst: &Inst, collector: &mut Operan
                 collector.reg_fixed_use(ret.vreg, ret.preg);
             }
         }
-        &Inst::Jump { .. } => {}
+        &Inst::Jump { .. } | &Inst::PatchableJump { .. } => {}
         &Inst::Call { ref info, .. } => {
             for u in &info.uses {
                 collector.reg_fixed_use(u.vreg, u.preg);
@@ -1014,7 +1019,7 @@ impl MachInst for Inst {
         match self {
             &Inst::Ret { .. } | &Inst::AuthenticatedRet { .. } => MachTerminator::Ret,
             &Inst::Jump { .. } => MachTerminator::Uncond,
-            &Inst::CondBr { .. } => MachTerminator::Cond,
+            &Inst::CondBr { .. } | &Inst::PatchableJump { .. } => MachTerminator::Cond,
             &Inst::IndirectBr { .. } => MachTerminator::Indirect,
             &Inst::JTSequence { .. } => MachTerminator::Indirect,
             _ => MachTerminator::None,
@@ -1283,6 +1288,19 @@ impl Inst {
                 let immshift = immshift.pretty_print(0, allocs);
                 format!("{} {}, {}, {}", op, rd, rn, immshift)
             }
+            &Inst::Extr {
+                size,
+                rd,
+                rn,
+                rm,
+                ref lsb,
+            } => {
+                let rd = pretty_print_ireg(rd.to_reg(), size, allocs);
+                let rn = pretty_print_ireg(rn, size, allocs);
+                let rm = pretty_print_ireg(rm, size, allocs);
+                let lsb = lsb.pretty_print(0, allocs);
+                format!("extr {}, {}, {}, {}", rd, rn, rm, lsb)
+            }
             &Inst::AluRRRShift {
                 alu_op,
                 size,
@@ -2578,6 +2596,14 @@ impl Inst {
                 let dest = dest.pretty_print(0, allocs);
                 format!("b {}", dest)
             }
+            &Inst::PatchableJump {
+                ref initial,
+                ref alternate,
+            } => {
+                let initial = initial.pretty_print(0, allocs);
+                let alternate = alternate.pretty_print(0, allocs);
+                format!("patchable_b {}, {}", initial, alternate)
+            }
             &Inst::CondBr {
                 ref taken,
                 ref not_taken,
@@ -34,10 +34,10 @@ use crate::{
     },
 };
 use crate::{isle_common_prelude_methods, isle_lower_prelude_methods};
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
 use regalloc2::PReg;
-use std::boxed::Box;
-use std::convert::TryFrom;
-use std::vec::Vec;
 
 type BoxCallInfo = Box<CallInfo>;
 type BoxCallIndInfo = Box<CallIndInfo>;
@@ -86,6 +86,22 @@ impl Context for IsleContext<'_, '_, MInst, AArch64Backend> {
         AArch64CallSite
     );
 
+    fn gen_patchable_call(
+        &mut self,
+        sig_ref: SigRef,
+        extname: ExternalName,
+        args: ValueSlice,
+    ) -> InstOutput {
+        // Only a near call is a single instruction whose target can be patched.
+        self.gen_direct_call(
+            sig_ref,
+            extname,
+            RelocDistance::Near,
+            args,
+            Opcode::PatchableCall,
+        )
+    }
+
     fn gen_return_call(
         &mut self,
         callee_sig: SigRef,
@@ -171,7 +187,7 @@ impl Context for IsleContext<'_, '_, MInst, AArch64Backend> {
     fn lshl_from_u64(&mut self, ty: Type, n: u64) -> Option<ShiftOpAndAmt> {
         let shiftimm = ShiftOpShiftImm::maybe_from_shift(n)?;
         let shiftee_bits = ty_bits(ty);
-        if shiftee_bits <= std::u8::MAX as usize {
+        if shiftee_bits <= core::u8::MAX as usize {
             let shiftimm = shiftimm.mask(shiftee_bits as u8);
             Some(ShiftOpAndAmt::new(ShiftOp::LSL, shiftimm))
         } else {
@@ -182,7 +198,7 @@ impl Context for IsleContext<'_, '_, MInst, AArch64Backend> {
     fn ashr_from_u64(&mut self, ty: Type, n: u64) -> Option<ShiftOpAndAmt> {
         let shiftimm = ShiftOpShiftImm::maybe_from_shift(n)?;
         let shiftee_bits = ty_bits(ty);
-        if shiftee_bits <= std::u8::MAX as usize {
+        if shiftee_bits <= core::u8::MAX as usize {
             let shiftimm = shiftimm.mask(shiftee_bits as u8);
             Some(ShiftOpAndAmt::new(ShiftOp::ASR, shiftimm))
         } else {
@@ -726,49 +742,6 @@ impl Context for IsleContext<'_, '_, MInst, AArch64Backend> {
         }
     }
 
-    fn vec_extract_imm4_from_immediate(&mut self, imm: Immediate) -> Option<u8> {
-        let bytes = self.lower_ctx.get_immediate_data(imm).as_slice();
-
-        if bytes.windows(2).all(|a| a[0] + 1 == a[1]) && bytes[0] < 16 {
-            Some(bytes[0])
-        } else {
-            None
-        }
-    }
-
-    fn shuffle_dup8_from_imm(&mut self, imm: Immediate) -> Option<u8> {
-        let bytes = self.lower_ctx.get_immediate_data(imm).as_slice();
-        if bytes.iter().all(|b| *b == bytes[0]) && bytes[0] < 16 {
-            Some(bytes[0])
-        } else {
-            None
-        }
-    }
-    fn shuffle_dup16_from_imm(&mut self, imm: Immediate) -> Option<u8> {
-        let (a, b, c, d, e, f, g, h) = self.shuffle16_from_imm(imm)?;
-        if a == b && b == c && c == d && d == e && e == f && f == g && g == h && a < 8 {
-            Some(a)
-        } else {
-            None
-        }
-    }
-    fn shuffle_dup32_from_imm(&mut self, imm: Immediate) -> Option<u8> {
-        let (a, b, c, d) = self.shuffle32_from_imm(imm)?;
-        if a == b && b == c && c == d && a < 4 {
-            Some(a)
-        } else {
-            None
-        }
-    }
-    fn shuffle_dup64_from_imm(&mut self, imm: Immediate) -> Option<u8> {
-        let (a, b) = self.shuffle64_from_imm(imm)?;
-        if a == b && a < 2 {
-            Some(a)
-        } else {
-            None
-        }
-    }
-
     fn asimd_mov_mod_imm_zero(&mut self, size: &ScalarSize) -> ASIMDMovModImm {
         ASIMDMovModImm::zero(*size)
     }
@@ -1,16 +1,19 @@
 //! ARM 64-bit Instruction Set Architecture.
 
 use crate::dominator_tree::DominatorTree;
-use crate::ir::{Function, Type};
+use crate::ir::condcodes::FloatCC;
+use crate::ir::{Function, Opcode, Type};
 use crate::isa::aarch64::settings as aarch64_settings;
+use crate::isa::support::classify;
 #[cfg(feature = "unwind")]
 use crate::isa::unwind::systemv;
-use crate::isa::{Builder as IsaBuilder, FunctionAlignment, TargetIsa};
+use crate::isa::{Builder as IsaBuilder, FunctionAlignment, SupportLevel, TargetIsa};
 use crate::machinst::{
     compile, CompiledCode, CompiledCodeStencil, MachInst, MachTextSectionBuilder, Reg, SigSet,
     TextSectionBuilder, VCode,
 };
 use crate::result::CodegenResult;
+use crate::session::{BackendScratch, CompilationSession};
 use crate::settings as shared_settings;
 use alloc::{boxed::Box, vec::Vec};
 use core::fmt;
@@ -23,6 +26,7 @@ mod abi;
 pub mod inst;
 mod lower;
 pub mod settings;
+mod support;
 
 use inst::create_reg_env;
 
@@ -58,12 +62,15 @@ impl AArch64Backend {
         &self,
         func: &Function,
         domtree: &DominatorTree,
+        scratch: &mut BackendScratch<inst::Inst>,
         ctrl_plane: &mut ControlPlane,
     ) -> CodegenResult<(VCode<inst::Inst>, regalloc2::Output)> {
         let emit_info = EmitInfo::new(self.flags.clone());
         let sigs = SigSet::new::<abi::AArch64MachineDeps>(func, &self.flags)?;
         let abi = abi::AArch64Callee::new(func, self, &self.isa_flags, &sigs)?;
-        compile::compile::<AArch64Backend>(func, domtree, self, abi, emit_info, sigs, ctrl_plane)
+        compile::compile::<AArch64Backend>(
+            func, domtree, self, abi, emit_info, sigs, scratch, ctrl_plane,
+        )
     }
 }
 
@@ -73,11 +80,27 @@ impl TargetIsa for AArch64Backend {
         func: &Function,
         domtree: &DominatorTree,
         want_disasm: bool,
+        want_vcode: bool,
+        want_inst_offsets: bool,
+        session: &mut CompilationSession,
         ctrl_plane: &mut ControlPlane,
     ) -> CodegenResult<CompiledCodeStencil> {
-        let (vcode, regalloc_result) = self.compile_vcode(func, domtree, ctrl_plane)?;
+        let scratch = session.backend_scratch::<inst::Inst>();
+        let (vcode, regalloc_result) = self.compile_vcode(func, domtree, scratch, ctrl_plane)?;
+        let vcode_listing = if want_vcode {
+            Some(Box::new(vcode.listing(Some(&regalloc_result))))
+        } else {
+            None
+        };
 
-        let emit_result = vcode.emit(&regalloc_result, want_disasm, &self.flags, ctrl_plane);
+        let emit_result = vcode.emit(
+            &regalloc_result,
+            want_disasm,
+            want_inst_offsets,
+            &self.flags,
+            scratch,
+            ctrl_plane,
+        )?;
         let frame_size = emit_result.frame_size;
         let value_labels_ranges = emit_result.value_labels_ranges;
         let buffer = emit_result.buffer;
@@ -92,11 +115,13 @@ impl TargetIsa for AArch64Backend {
             buffer,
             frame_size,
             vcode: emit_result.disasm,
+            vcode_listing,
             value_labels_ranges,
             sized_stackslot_offsets,
             dynamic_stackslot_offsets,
             bb_starts: emit_result.bb_offsets,
             bb_edges: emit_result.bb_edges,
+            block_coverage: Vec::new(),
         })
     }
 
@@ -204,14 +229,37 @@ impl TargetIsa for AArch64Backend {
         Ok(cs)
     }
 
+    fn supports(&self, opcode: Opcode, ctrl_type: Type) -> SupportLevel {
+        classify(opcode, ctrl_type, support::support_level)
+    }
+
     fn has_native_fma(&self) -> bool {
         true
     }
 
+    fn has_fcmp_lowering(&self, cond: FloatCC, _ty: Type) -> bool {
+        // `FCMP` and the vector comparisons can't test for unordered operands
+        // together with another outcome.
+        !matches!(
+            cond,
+            FloatCC::OrderedNotEqual
+                | FloatCC::UnorderedOrEqual
+                | FloatCC::UnorderedOrLessThan
+                | FloatCC::UnorderedOrLessThanOrEqual
+                | FloatCC::UnorderedOrGreaterThan
+                | FloatCC::UnorderedOrGreaterThanOrEqual
+        )
+    }
+
     fn has_x86_blendv_lowering(&self, _: Type) -> bool {
         false
     }
 
+    fn has_fast_shift_add_lowering(&self, ty: Type) -> bool {
+        // `add` accepts a shifted register operand.
+        ty.is_int() && ty.bits() <= 64
+    }
+
     fn has_x86_pshufb_lowering(&self) -> bool {
         false
     }
@@ -23,7 +23,7 @@
 //! # #[macro_use] extern crate target_lexicon;
 //! use cranelift_codegen::isa;
 //! use cranelift_codegen::settings::{self, Configurable};
-//! use std::str::FromStr;
+//! use core::str::FromStr;
 //! use target_lexicon::Triple;
 //!
 //! let shared_builder = settings::builder();
@@ -45,12 +45,15 @@
 
 use crate::dominator_tree::DominatorTree;
 pub use crate::isa::call_conv::CallConv;
+pub use crate::isa::support::{SupportLevel, UnsupportedInstruction};
 
 use crate::flowgraph;
+use crate::ir::condcodes::FloatCC;
 use crate::ir::{self, Function, Type};
 #[cfg(feature = "unwind")]
 use crate::isa::unwind::systemv::RegisterMappingError;
 use crate::machinst::{CompiledCode, CompiledCodeStencil, TextSectionBuilder, UnwindInfoKind};
+use crate::session::CompilationSession;
 use crate::settings;
 use crate::settings::SetResult;
 use crate::CodegenResult;
@@ -77,6 +80,7 @@ mod s390x;
 pub mod unwind;
 
 mod call_conv;
+pub(crate) mod support;
 
 /// Returns a builder that can create a corresponding `TargetIsa`
 /// or `Err(LookupError::SupportDisabled)` if not enabled.
@@ -131,6 +135,7 @@ pub enum LookupError {
 
 // This is manually implementing Error and Display instead of using thiserror to reduce the amount
 // of dependencies used by Cranelift.
+#[cfg(feature = "std")]
 impl std::error::Error for LookupError {}
 
 impl fmt::Display for LookupError {
@@ -269,11 +274,21 @@ pub trait TargetIsa: fmt::Display + Send + Sync {
     fn dynamic_vector_bytes(&self, dynamic_ty: ir::Type) -> u32;
 
     /// Compile the given function.
+    ///
+    /// `want_disasm` requests a disassembly of the emitted code,
+    /// `want_vcode` requests a [`VCodeListing`](crate::machinst::VCodeListing)
+    /// of the lowered VCode, and `want_inst_offsets` requests the offset of
+    /// each instruction, in the returned stencil. The scratch allocations of
+    /// the backend are reused from `session`, and left in it for the next
+    /// compilation.
     fn compile_function(
         &self,
         func: &Function,
         domtree: &DominatorTree,
         want_disasm: bool,
+        want_vcode: bool,
+        want_inst_offsets: bool,
+        session: &mut CompilationSession,
         ctrl_plane: &mut ControlPlane,
     ) -> CodegenResult<CompiledCodeStencil>;
 
@@ -334,6 +349,19 @@ pub trait TargetIsa: fmt::Display + Send + Sync {
         Err(capstone::Error::UnsupportedArch)
     }
 
+    /// Returns how this ISA implements the instruction `opcode` when its controlling type is
+    /// `ctrl_type`, or `INVALID` if `opcode` isn't polymorphic.
+    ///
+    /// Only the arithmetic, bitwise and comparison instructions whose operand types all follow
+    /// from the controlling type are described; other instructions, and dynamic vector types,
+    /// are reported as `Unsupported`. The answer takes the ISA-specific flags into account.
+    /// `fcmp` conditions which the ISA can't lower, see `has_fcmp_lowering`, are split by the
+    /// legalizer, and the resulting instructions must be queried separately.
+    ///
+    /// Compiling a function with an instruction reported as `Unsupported` fails with
+    /// `CodegenError::Unsupported`.
+    fn supports(&self, opcode: ir::Opcode, ctrl_type: Type) -> SupportLevel;
+
     /// Returns whether this ISA has a native fused-multiply-and-add instruction
     /// for floats.
     ///
@@ -341,10 +369,25 @@ pub trait TargetIsa: fmt::Display + Send + Sync {
     /// not detected.
     fn has_native_fma(&self) -> bool;
 
+    /// Returns whether `fcmp` with the condition `cond` is implemented for
+    /// operands of type `ty`.
+    ///
+    /// The legalizer splits other comparisons into two which are implemented.
+    /// Targets must implement the ordered comparisons and `uno`.
+    fn has_fcmp_lowering(&self, cond: FloatCC, ty: Type) -> bool;
+
     /// Returns whether the CLIF `x86_blendv` instruction is implemented for
     /// this ISA for the specified type.
     fn has_x86_blendv_lowering(&self, ty: Type) -> bool;
 
+    /// Returns whether adding a value shifted left by a small constant to
+    /// another value (`iadd x (ishl y k)` with `k` in `1..=3`) lowers to a
+    /// single instruction for the specified type.
+    ///
+    /// The mid-end uses this as a cost hint to rewrite multiplications by
+    /// constants like 3, 5 and 9 into a shift and an add.
+    fn has_fast_shift_add_lowering(&self, ty: Type) -> bool;
+
     /// Returns whether the CLIF `x86_pshufb` instruction is implemented for
     /// this ISA.
     fn has_x86_pshufb_lowering(&self) -> bool;
@@ -70,7 +70,7 @@ impl RiscvFlags {
 
             // Due to a limitation in regalloc2, we can't support types
             // larger than 1024 bytes. So limit that here.
-            return std::cmp::min(size, 1024);
+            return core::cmp::min(size, 1024);
         }
 
         return 0;
@@ -175,7 +175,7 @@ impl ABIMachineSpec for Riscv64MachineDeps {
                     let size = if args_or_rets == ArgsOrRets::Rets && call_conv.extends_wasmtime() {
                         size
                     } else {
-                        std::cmp::max(size, 8)
+                        core::cmp::max(size, 8)
                     };
                     // Align.
                     debug_assert!(size.is_power_of_two());
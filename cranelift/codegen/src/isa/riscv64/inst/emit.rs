@@ -172,6 +172,10 @@ impl MachInstEmitState<Inst> for EmitState {
         self.ctrl_plane
     }
 
+    fn virtual_sp_offset(&self) -> i64 {
+        self.virtual_sp_offset
+    }
+
     fn on_new_block(&mut self) {
         // Reset the vector state.
         self.vstate = EmitVState::Unknown;
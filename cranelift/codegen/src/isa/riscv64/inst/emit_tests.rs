@@ -2,8 +2,8 @@
 use crate::ir::LibCall;
 use crate::isa::riscv64::inst::*;
 use crate::settings;
+use alloc::borrow::Cow;
 use alloc::vec::Vec;
-use std::borrow::Cow;
 
 #[test]
 fn test_riscv64_binemit() {
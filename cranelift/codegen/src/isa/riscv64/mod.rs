@@ -2,14 +2,17 @@
 
 use crate::dominator_tree::DominatorTree;
 use crate::ir;
-use crate::ir::{Function, Type};
+use crate::ir::condcodes::FloatCC;
+use crate::ir::{types, Function, Type};
 use crate::isa::riscv64::settings as riscv_settings;
-use crate::isa::{Builder as IsaBuilder, FunctionAlignment, TargetIsa};
+use crate::isa::support::classify;
+use crate::isa::{Builder as IsaBuilder, FunctionAlignment, SupportLevel, TargetIsa};
 use crate::machinst::{
     compile, CompiledCode, CompiledCodeStencil, MachInst, MachTextSectionBuilder, Reg, SigSet,
     TextSectionBuilder, VCode,
 };
 use crate::result::CodegenResult;
+use crate::session::{BackendScratch, CompilationSession};
 use crate::settings as shared_settings;
 use alloc::{boxed::Box, vec::Vec};
 use core::fmt;
@@ -20,6 +23,7 @@ mod abi;
 pub(crate) mod inst;
 mod lower;
 mod settings;
+mod support;
 #[cfg(feature = "unwind")]
 use crate::isa::unwind::systemv;
 
@@ -57,12 +61,15 @@ impl Riscv64Backend {
         &self,
         func: &Function,
         domtree: &DominatorTree,
+        scratch: &mut BackendScratch<inst::Inst>,
         ctrl_plane: &mut ControlPlane,
     ) -> CodegenResult<(VCode<inst::Inst>, regalloc2::Output)> {
         let emit_info = EmitInfo::new(self.flags.clone(), self.isa_flags.clone());
         let sigs = SigSet::new::<abi::Riscv64MachineDeps>(func, &self.flags)?;
         let abi = abi::Riscv64Callee::new(func, self, &self.isa_flags, &sigs)?;
-        compile::compile::<Riscv64Backend>(func, domtree, self, abi, emit_info, sigs, ctrl_plane)
+        compile::compile::<Riscv64Backend>(
+            func, domtree, self, abi, emit_info, sigs, scratch, ctrl_plane,
+        )
     }
 }
 
@@ -72,12 +79,28 @@ impl TargetIsa for Riscv64Backend {
         func: &Function,
         domtree: &DominatorTree,
         want_disasm: bool,
+        want_vcode: bool,
+        want_inst_offsets: bool,
+        session: &mut CompilationSession,
         ctrl_plane: &mut ControlPlane,
     ) -> CodegenResult<CompiledCodeStencil> {
-        let (vcode, regalloc_result) = self.compile_vcode(func, domtree, ctrl_plane)?;
+        let scratch = session.backend_scratch::<inst::Inst>();
+        let (vcode, regalloc_result) = self.compile_vcode(func, domtree, scratch, ctrl_plane)?;
+        let vcode_listing = if want_vcode {
+            Some(Box::new(vcode.listing(Some(&regalloc_result))))
+        } else {
+            None
+        };
 
         let want_disasm = want_disasm || log::log_enabled!(log::Level::Debug);
-        let emit_result = vcode.emit(&regalloc_result, want_disasm, &self.flags, ctrl_plane);
+        let emit_result = vcode.emit(
+            &regalloc_result,
+            want_disasm,
+            want_inst_offsets,
+            &self.flags,
+            scratch,
+            ctrl_plane,
+        )?;
         let frame_size = emit_result.frame_size;
         let value_labels_ranges = emit_result.value_labels_ranges;
         let buffer = emit_result.buffer;
@@ -92,11 +115,13 @@ impl TargetIsa for Riscv64Backend {
             buffer,
             frame_size,
             vcode: emit_result.disasm,
+            vcode_listing,
             value_labels_ranges,
             sized_stackslot_offsets,
             dynamic_stackslot_offsets,
             bb_starts: emit_result.bb_offsets,
             bb_edges: emit_result.bb_edges,
+            block_coverage: Vec::new(),
         })
     }
 
@@ -179,14 +204,29 @@ impl TargetIsa for Riscv64Backend {
         Ok(cs)
     }
 
+    fn supports(&self, opcode: ir::Opcode, ctrl_type: Type) -> SupportLevel {
+        classify(opcode, ctrl_type, |opcode, ty| {
+            support::support_level(opcode, ty, &self.isa_flags)
+        })
+    }
+
     fn has_native_fma(&self) -> bool {
         true
     }
 
+    fn has_fcmp_lowering(&self, _cond: FloatCC, _ty: Type) -> bool {
+        true
+    }
+
     fn has_x86_blendv_lowering(&self, _: Type) -> bool {
         false
     }
 
+    fn has_fast_shift_add_lowering(&self, ty: Type) -> bool {
+        // The `sh1add`, `sh2add` and `sh3add` instructions from `Zba`.
+        ty == types::I64 && self.isa_flags.has_zba()
+    }
+
     fn has_x86_pshufb_lowering(&self) -> bool {
         false
     }
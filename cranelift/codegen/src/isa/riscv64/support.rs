@@ -0,0 +1,178 @@
+//! The instructions the RISC-V backend can compile.
+//!
+//! This table is checked against what the backend actually compiles by the tests of
+//! `crate::isa::support`; update it together with the lowering rules.
+
+use crate::ir::types::*;
+use crate::ir::{Opcode, Type};
+use crate::isa::riscv64::settings::Flags;
+use crate::isa::SupportLevel::{self, *};
+
+/// How the backend implements `opcode` with the controlling type `ty`, which `opcode` accepts.
+pub(crate) fn support_level(opcode: Opcode, ty: Type, flags: &Flags) -> SupportLevel {
+    match opcode {
+        Opcode::Splat => match ty {
+            I8X2 | I8X4 | I8X8 | I8X16 | I16X2 | I16X4 | I16X8 | I16X16 | I32X2 | I32X4 | I32X8
+            | I64X2 | I64X4 | F32X2 | F32X4 | F32X8 | F64X2 | F64X4 => Native,
+            _ => Unsupported,
+        },
+        Opcode::Swizzle => if_fits(I8X16, flags, Native),
+        Opcode::Smin
+        | Opcode::Umin
+        | Opcode::Smax
+        | Opcode::Umax
+        | Opcode::Icmp
+        | Opcode::Iadd
+        | Opcode::Isub
+        | Opcode::Ineg
+        | Opcode::Imul
+        | Opcode::Popcnt => match ty {
+            I8 | I16 | I32 | I64 | I128 => Native,
+            ty if ty.is_vector() && matches!(ty.lane_type(), I8 | I16 | I32 | I64) => {
+                if_fits(ty, flags, Native)
+            }
+            _ => Unsupported,
+        },
+        Opcode::AvgRound
+        | Opcode::UaddSat
+        | Opcode::SaddSat
+        | Opcode::UsubSat
+        | Opcode::SsubSat => match ty {
+            ty if ty.is_vector() && matches!(ty.lane_type(), I8 | I16 | I32 | I64) => {
+                if_fits(ty, flags, Native)
+            }
+            _ => Unsupported,
+        },
+        Opcode::Bitselect => match ty {
+            I8 | I16 | I32 | I64 | I128 | F32 | F64 | R64 => Native,
+            ty if ty.is_vector() && matches!(ty.lane_type(), I8 | I16 | I32 | I64 | F32 | F64) => {
+                if_fits(ty, flags, Native)
+            }
+            _ => Unsupported,
+        },
+        Opcode::VanyTrue | Opcode::VallTrue | Opcode::ScalarToVector => match ty {
+            ty if ty.is_vector() && matches!(ty.lane_type(), I8 | I16 | I32 | I64 | F32 | F64) => {
+                if_fits(ty, flags, Native)
+            }
+            _ => Unsupported,
+        },
+        Opcode::Iabs | Opcode::Umulhi | Opcode::Smulhi => match ty {
+            I8 | I16 | I32 | I64 => Native,
+            ty if ty.is_vector() && matches!(ty.lane_type(), I8 | I16 | I32 | I64) => {
+                if_fits(ty, flags, Native)
+            }
+            _ => Unsupported,
+        },
+        Opcode::SqmulRoundSat
+        | Opcode::Snarrow
+        | Opcode::Unarrow
+        | Opcode::Uunarrow
+        | Opcode::IaddPairwise => match ty {
+            ty if ty.is_vector() => if_fits(ty, flags, Native),
+            _ => Unsupported,
+        },
+        Opcode::Udiv | Opcode::Sdiv | Opcode::Urem | Opcode::Srem => match ty {
+            I8 | I16 | I32 | I64 => Native,
+            _ => Unsupported,
+        },
+        Opcode::IaddImm
+        | Opcode::ImulImm
+        | Opcode::IrsubImm
+        | Opcode::BandImm
+        | Opcode::BorImm
+        | Opcode::BxorImm
+        | Opcode::RotlImm
+        | Opcode::RotrImm => match ty {
+            I8 | I16 | I32 | I64 | I128 => Emulated,
+            _ => Unsupported,
+        },
+        Opcode::UdivImm | Opcode::SdivImm | Opcode::UremImm | Opcode::SremImm => match ty {
+            I8 | I16 | I32 | I64 => Emulated,
+            _ => Unsupported,
+        },
+        Opcode::Band | Opcode::Bor | Opcode::Bxor | Opcode::Bnot => match ty {
+            I8 | I16 | I32 | I64 | I128 | F32 | F64 => Native,
+            ty if ty.is_vector() && matches!(ty.lane_type(), I8 | I16 | I32 | I64 | F32 | F64) => {
+                if_fits(ty, flags, Native)
+            }
+            _ => Unsupported,
+        },
+        Opcode::BandNot | Opcode::BorNot | Opcode::BxorNot => match ty {
+            I8 | I16 | I32 | I64 | I128 | F32 | F64 => Emulated,
+            ty if ty.is_vector() && matches!(ty.lane_type(), I8 | I16 | I32 | I64 | F32 | F64) => {
+                if_fits(ty, flags, Emulated)
+            }
+            _ => Unsupported,
+        },
+        Opcode::IshlImm | Opcode::UshrImm | Opcode::SshrImm => match ty {
+            I8 | I16 | I32 | I64 | I128 => Emulated,
+            ty if ty.is_vector() && matches!(ty.lane_type(), I8 | I16 | I32 | I64) => {
+                if_fits(ty, flags, Emulated)
+            }
+            _ => Unsupported,
+        },
+        Opcode::Bitrev | Opcode::Clz | Opcode::Cls | Opcode::Ctz => match ty {
+            I8 | I16 | I32 | I64 | I128 => Native,
+            _ => Unsupported,
+        },
+        Opcode::Fcmp
+        | Opcode::Fadd
+        | Opcode::Fsub
+        | Opcode::Fmul
+        | Opcode::Fdiv
+        | Opcode::Sqrt
+        | Opcode::Fneg => match ty {
+            F32 | F64 => Native,
+            ty if ty.is_vector() => if_fits(ty, flags, Native),
+            _ => Unsupported,
+        },
+        Opcode::Fma
+        | Opcode::Fabs
+        | Opcode::Fcopysign
+        | Opcode::Fmin
+        | Opcode::Fmax
+        | Opcode::Ceil
+        | Opcode::Floor
+        | Opcode::Trunc
+        | Opcode::Nearest => match ty {
+            F32 | F64 => Native,
+            _ => Unsupported,
+        },
+        Opcode::FminPseudo | Opcode::FmaxPseudo => match ty {
+            F32 | F64 | F32X2 | F32X4 | F32X8 | F64X2 | F64X4 => Native,
+            _ => Unsupported,
+        },
+        Opcode::IsNull | Opcode::IsInvalid => match ty {
+            R64 => Native,
+            _ => Unsupported,
+        },
+        Opcode::SwidenLow | Opcode::SwidenHigh | Opcode::UwidenLow | Opcode::UwidenHigh => match ty
+        {
+            I8X4 | I8X8 | I8X16 | I16X4 | I16X8 | I16X16 | I32X4 | I32X8 => {
+                if_fits(ty, flags, Native)
+            }
+            _ => Unsupported,
+        },
+        Opcode::Isplit => match ty {
+            I128 => Native,
+            _ => Unsupported,
+        },
+        Opcode::Iconcat => match ty {
+            I64 => Native,
+            _ => Unsupported,
+        },
+        _ => Unsupported,
+    }
+}
+
+/// `level` if the vector type `ty` fits in a vector register, and `Unsupported` otherwise.
+///
+/// Vectors are lowered with a static vector length, which the `V` extension encodes in a 5-bit
+/// immediate, so they also need fewer than 32 lanes.
+fn if_fits(ty: Type, flags: &Flags, level: SupportLevel) -> SupportLevel {
+    if flags.has_v() && u64::from(ty.bits()) <= flags.min_vec_reg_size() && ty.lane_count() < 32 {
+        level
+    } else {
+        Unsupported
+    }
+}
@@ -1,7 +1,7 @@
 //! S390x ISA definitions: immediate constants.
 
 use crate::machinst::{AllocationConsumer, PrettyPrint};
-use std::string::String;
+use alloc::string::String;
 
 /// An unsigned 12-bit immediate.
 #[derive(Clone, Copy, Debug)]
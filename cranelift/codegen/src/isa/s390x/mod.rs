@@ -1,16 +1,19 @@
 //! IBM Z 64-bit Instruction Set Architecture.
 
 use crate::dominator_tree::DominatorTree;
-use crate::ir::{Function, Type};
+use crate::ir::condcodes::FloatCC;
+use crate::ir::{Function, Opcode, Type};
 use crate::isa::s390x::settings as s390x_settings;
+use crate::isa::support::classify;
 #[cfg(feature = "unwind")]
 use crate::isa::unwind::systemv::RegisterMappingError;
-use crate::isa::{Builder as IsaBuilder, FunctionAlignment, TargetIsa};
+use crate::isa::{Builder as IsaBuilder, FunctionAlignment, SupportLevel, TargetIsa};
 use crate::machinst::{
     compile, CompiledCode, CompiledCodeStencil, MachInst, MachTextSectionBuilder, Reg, SigSet,
     TextSectionBuilder, VCode,
 };
 use crate::result::CodegenResult;
+use crate::session::{BackendScratch, CompilationSession};
 use crate::settings as shared_settings;
 use alloc::{boxed::Box, vec::Vec};
 use core::fmt;
@@ -23,6 +26,7 @@ mod abi;
 pub(crate) mod inst;
 mod lower;
 mod settings;
+mod support;
 
 use inst::create_machine_env;
 
@@ -58,12 +62,15 @@ impl S390xBackend {
         &self,
         func: &Function,
         domtree: &DominatorTree,
+        scratch: &mut BackendScratch<inst::Inst>,
         ctrl_plane: &mut ControlPlane,
     ) -> CodegenResult<(VCode<inst::Inst>, regalloc2::Output)> {
         let emit_info = EmitInfo::new(self.isa_flags.clone());
         let sigs = SigSet::new::<abi::S390xMachineDeps>(func, &self.flags)?;
         let abi = abi::S390xCallee::new(func, self, &self.isa_flags, &sigs)?;
-        compile::compile::<S390xBackend>(func, domtree, self, abi, emit_info, sigs, ctrl_plane)
+        compile::compile::<S390xBackend>(
+            func, domtree, self, abi, emit_info, sigs, scratch, ctrl_plane,
+        )
     }
 }
 
@@ -73,12 +80,28 @@ impl TargetIsa for S390xBackend {
         func: &Function,
         domtree: &DominatorTree,
         want_disasm: bool,
+        want_vcode: bool,
+        want_inst_offsets: bool,
+        session: &mut CompilationSession,
         ctrl_plane: &mut ControlPlane,
     ) -> CodegenResult<CompiledCodeStencil> {
+        let scratch = session.backend_scratch::<inst::Inst>();
         let flags = self.flags();
-        let (vcode, regalloc_result) = self.compile_vcode(func, domtree, ctrl_plane)?;
-
-        let emit_result = vcode.emit(&regalloc_result, want_disasm, flags, ctrl_plane);
+        let (vcode, regalloc_result) = self.compile_vcode(func, domtree, scratch, ctrl_plane)?;
+        let vcode_listing = if want_vcode {
+            Some(Box::new(vcode.listing(Some(&regalloc_result))))
+        } else {
+            None
+        };
+
+        let emit_result = vcode.emit(
+            &regalloc_result,
+            want_disasm,
+            want_inst_offsets,
+            flags,
+            scratch,
+            ctrl_plane,
+        )?;
         let frame_size = emit_result.frame_size;
         let value_labels_ranges = emit_result.value_labels_ranges;
         let buffer = emit_result.buffer;
@@ -93,11 +116,13 @@ impl TargetIsa for S390xBackend {
             buffer,
             frame_size,
             vcode: emit_result.disasm,
+            vcode_listing,
             value_labels_ranges,
             sized_stackslot_offsets,
             dynamic_stackslot_offsets,
             bb_starts: emit_result.bb_offsets,
             bb_edges: emit_result.bb_edges,
+            block_coverage: Vec::new(),
         })
     }
 
@@ -179,14 +204,26 @@ impl TargetIsa for S390xBackend {
         Ok(cs)
     }
 
+    fn supports(&self, opcode: Opcode, ctrl_type: Type) -> SupportLevel {
+        classify(opcode, ctrl_type, support::support_level)
+    }
+
     fn has_native_fma(&self) -> bool {
         true
     }
 
+    fn has_fcmp_lowering(&self, _cond: FloatCC, _ty: Type) -> bool {
+        true
+    }
+
     fn has_x86_blendv_lowering(&self, _: Type) -> bool {
         false
     }
 
+    fn has_fast_shift_add_lowering(&self, _: Type) -> bool {
+        false
+    }
+
     fn has_x86_pshufb_lowering(&self) -> bool {
         false
     }
@@ -0,0 +1,138 @@
+//! The instructions the s390x backend can compile.
+//!
+//! This table is checked against what the backend actually compiles by the tests of
+//! `crate::isa::support`; update it together with the lowering rules.
+
+use crate::ir::types::*;
+use crate::ir::{Opcode, Type};
+use crate::isa::SupportLevel::{self, *};
+
+/// How the backend implements `opcode` with the controlling type `ty`, which `opcode` accepts.
+pub(crate) fn support_level(opcode: Opcode, ty: Type) -> SupportLevel {
+    match opcode {
+        Opcode::Splat | Opcode::VanyTrue | Opcode::VallTrue | Opcode::ScalarToVector => match ty {
+            I8X16 | I16X8 | I32X4 | I64X2 | F32X4 | F64X2 => Native,
+            _ => Unsupported,
+        },
+        Opcode::Swizzle | Opcode::Fvdemote | Opcode::FvpromoteLow => Native,
+        Opcode::Smin
+        | Opcode::Umin
+        | Opcode::Smax
+        | Opcode::Umax
+        | Opcode::Icmp
+        | Opcode::Iadd
+        | Opcode::Isub
+        | Opcode::Ineg
+        | Opcode::Iabs
+        | Opcode::Imul
+        | Opcode::Popcnt => match ty {
+            I8 | I16 | I32 | I64 | I128 | I8X16 | I16X8 | I32X4 | I64X2 => Native,
+            _ => Unsupported,
+        },
+        Opcode::AvgRound | Opcode::UaddSat | Opcode::UsubSat => match ty {
+            I8X16 | I16X8 | I32X4 | I64X2 => Native,
+            _ => Unsupported,
+        },
+        Opcode::SaddSat
+        | Opcode::SsubSat
+        | Opcode::SwidenLow
+        | Opcode::SwidenHigh
+        | Opcode::UwidenLow
+        | Opcode::UwidenHigh
+        | Opcode::IaddPairwise => match ty {
+            I8X16 | I16X8 | I32X4 => Native,
+            _ => Unsupported,
+        },
+        Opcode::Bitselect => match ty {
+            I8 | I16 | I32 | I64 | I128 | R64 | I8X16 | I16X8 | I32X4 | I64X2 | F32X4 | F64X2 => {
+                Native
+            }
+            _ => Unsupported,
+        },
+        Opcode::Umulhi | Opcode::Smulhi => match ty {
+            I8 | I16 | I32 | I64 | I8X16 | I16X8 | I32X4 | I64X2 => Native,
+            _ => Unsupported,
+        },
+        Opcode::SqmulRoundSat => match ty {
+            I16X8 | I32X4 => Native,
+            _ => Unsupported,
+        },
+        Opcode::Udiv | Opcode::Sdiv | Opcode::Urem | Opcode::Srem => match ty {
+            I8 | I16 | I32 | I64 => Native,
+            _ => Unsupported,
+        },
+        Opcode::IaddImm
+        | Opcode::ImulImm
+        | Opcode::IrsubImm
+        | Opcode::BandImm
+        | Opcode::BorImm
+        | Opcode::BxorImm => match ty {
+            I8 | I16 | I32 | I64 | I128 => Emulated,
+            _ => Unsupported,
+        },
+        Opcode::UdivImm | Opcode::SdivImm | Opcode::UremImm | Opcode::SremImm => match ty {
+            I8 | I16 | I32 | I64 => Emulated,
+            _ => Unsupported,
+        },
+        Opcode::Band | Opcode::Bor | Opcode::Bxor | Opcode::Bnot => match ty {
+            I8 | I16 | I32 | I64 | I128 | I8X16 | I16X8 | I32X4 | I64X2 | F32X4 | F64X2 => Native,
+            _ => Unsupported,
+        },
+        Opcode::BandNot | Opcode::BorNot | Opcode::BxorNot => match ty {
+            I8 | I16 | I32 | I64 | I128 | I8X16 | I16X8 | I32X4 | I64X2 | F32X4 | F64X2 => Emulated,
+            _ => Unsupported,
+        },
+        Opcode::RotlImm | Opcode::RotrImm | Opcode::IshlImm | Opcode::UshrImm | Opcode::SshrImm => {
+            match ty {
+                I8 | I16 | I32 | I64 | I128 | I8X16 | I16X8 | I32X4 | I64X2 => Emulated,
+                _ => Unsupported,
+            }
+        }
+        Opcode::Bitrev | Opcode::Clz | Opcode::Cls | Opcode::Ctz => match ty {
+            I8 | I16 | I32 | I64 | I128 => Native,
+            _ => Unsupported,
+        },
+        Opcode::Bswap => match ty {
+            I16 | I32 | I64 | I128 => Native,
+            _ => Unsupported,
+        },
+        Opcode::Fcmp
+        | Opcode::Fadd
+        | Opcode::Fsub
+        | Opcode::Fmul
+        | Opcode::Fdiv
+        | Opcode::Sqrt
+        | Opcode::Fma
+        | Opcode::Fneg
+        | Opcode::Fabs
+        | Opcode::Fcopysign
+        | Opcode::Fmin
+        | Opcode::FminPseudo
+        | Opcode::Fmax
+        | Opcode::FmaxPseudo
+        | Opcode::Ceil
+        | Opcode::Floor
+        | Opcode::Trunc
+        | Opcode::Nearest => match ty {
+            F32 | F64 | F32X4 | F64X2 => Native,
+            _ => Unsupported,
+        },
+        Opcode::IsNull | Opcode::IsInvalid => match ty {
+            R64 => Native,
+            _ => Unsupported,
+        },
+        Opcode::Snarrow | Opcode::Unarrow | Opcode::Uunarrow => match ty {
+            I16X8 | I32X4 | I64X2 => Native,
+            _ => Unsupported,
+        },
+        Opcode::Isplit => match ty {
+            I128 => Native,
+            _ => Unsupported,
+        },
+        Opcode::Iconcat => match ty {
+            I64 => Native,
+            _ => Unsupported,
+        },
+        _ => Unsupported,
+    }
+}
@@ -0,0 +1,442 @@
+//! Which instructions a target ISA can compile.
+//!
+//! Each backend describes the instructions it lowers with a table, queried through
+//! [`TargetIsa::supports`]. Compiling a function which contains instructions the table of the
+//! target claims are unsupported fails with [`CodegenError::UnsupportedInstructions`], listing all
+//! of them, instead of a panic in instruction selection. Instructions with a constant operand are
+//! exempt from this check, since backends may have lowerings for them which the tables don't
+//! describe.
+
+use crate::ir::instructions::{InstructionFormat, ResolvedConstraint};
+use crate::ir::{types, Block, Function, Inst, Opcode, SourceLoc, Type, Value};
+use crate::isa::TargetIsa;
+use crate::{CodegenError, CodegenResult};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+
+/// How a target ISA implements an instruction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SupportLevel {
+    /// The backend lowers the instruction to machine instructions.
+    Native,
+    /// The backend lowers the instruction to a call to a library routine.
+    ViaLibcall,
+    /// The legalizer rewrites the instruction into other instructions before lowering.
+    Emulated,
+    /// The instruction can't be compiled.
+    Unsupported,
+}
+
+/// An instruction which the target ISA can't compile, as reported by
+/// [`CodegenError::UnsupportedInstructions`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnsupportedInstruction {
+    /// The instruction.
+    pub inst: Inst,
+    /// The block containing the instruction.
+    pub block: Block,
+    /// The source location of the instruction.
+    pub srcloc: SourceLoc,
+    /// The opcode of the instruction.
+    pub opcode: Opcode,
+    /// The controlling type of the instruction, or `INVALID` if it isn't polymorphic.
+    pub ctrl_type: Type,
+    /// The instruction as written in CLIF.
+    pub text: String,
+}
+
+/// Whether the tables of the backends describe `opcode`.
+///
+/// The tables cover the arithmetic, bitwise and comparison instructions whose operand types are
+/// all determined by the controlling type, for static types only. Instructions
+/// outside of this set, such as memory accesses, control flow or conversions, are reported as
+/// unsupported by [`TargetIsa::supports`] and aren't checked before compiling.
+pub(crate) fn is_classified(opcode: Opcode) -> bool {
+    matches!(
+        opcode.format(),
+        InstructionFormat::Unary
+            | InstructionFormat::Binary
+            | InstructionFormat::Ternary
+            | InstructionFormat::BinaryImm64
+            | InstructionFormat::IntCompare
+            | InstructionFormat::FloatCompare
+    ) && !opcode.other_side_effects()
+        && {
+            let constraints = opcode.constraints();
+            let ctrl_type = constraints
+                .ctrl_typeset()
+                .map_or(types::INVALID, |set| set.example());
+            (0..constraints.num_fixed_value_arguments()).all(|n| {
+                matches!(
+                    constraints.value_argument_constraint(n, ctrl_type),
+                    ResolvedConstraint::Bound(_)
+                )
+            })
+        }
+}
+
+/// Whether `ctrl_type` is a static controlling type `opcode` accepts.
+///
+/// Instructions which aren't polymorphic take `INVALID` as their controlling type.
+fn is_valid_ctrl_type(opcode: Opcode, ctrl_type: Type) -> bool {
+    match opcode.constraints().ctrl_typeset() {
+        Some(set) => !ctrl_type.is_dynamic_vector() && set.contains(ctrl_type),
+        None => ctrl_type == types::INVALID,
+    }
+}
+
+/// The support for `opcode` with the controlling type `ctrl_type`, according to the `table` of a
+/// backend, which is only consulted for classified instructions and valid controlling types.
+pub(crate) fn classify(
+    opcode: Opcode,
+    ctrl_type: Type,
+    table: impl FnOnce(Opcode, Type) -> SupportLevel,
+) -> SupportLevel {
+    if is_classified(opcode) && is_valid_ctrl_type(opcode, ctrl_type) {
+        table(opcode, ctrl_type)
+    } else {
+        SupportLevel::Unsupported
+    }
+}
+
+/// Check that `isa` can compile every instruction of `func` described by its table, and report
+/// all those it can't otherwise.
+pub(crate) fn verify_supported(func: &Function, isa: &dyn TargetIsa) -> CodegenResult<()> {
+    let mut unsupported = Vec::new();
+    for block in func.layout.blocks() {
+        for inst in func.layout.block_insts(block) {
+            let opcode = func.dfg.insts[inst].opcode();
+            if !is_classified(opcode) {
+                continue;
+            }
+            let ctrl_type = func.dfg.ctrl_typevar(inst);
+            if ctrl_type.is_dynamic_vector() {
+                continue;
+            }
+            // Backends have dedicated lowerings for some instructions with constant operands,
+            // which the tables don't describe, so leave those to the backend.
+            if func
+                .dfg
+                .inst_args(inst)
+                .iter()
+                .any(|&arg| is_constant(func, arg))
+            {
+                continue;
+            }
+            if isa.supports(opcode, ctrl_type) == SupportLevel::Unsupported {
+                unsupported.push(UnsupportedInstruction {
+                    inst,
+                    block,
+                    srcloc: func.srcloc(inst),
+                    opcode,
+                    ctrl_type,
+                    text: func.dfg.display_inst(inst).to_string(),
+                });
+            }
+        }
+    }
+    if unsupported.is_empty() {
+        Ok(())
+    } else {
+        Err(CodegenError::UnsupportedInstructions(unsupported))
+    }
+}
+
+/// Whether `value` is the result of a constant instruction.
+fn is_constant(func: &Function, value: Value) -> bool {
+    match func.dfg.value_def(value).inst() {
+        Some(inst) => matches!(
+            func.dfg.insts[inst].opcode(),
+            Opcode::Iconst | Opcode::F32const | Opcode::F64const | Opcode::Vconst
+        ),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::condcodes::{FloatCC, IntCC};
+    use crate::ir::types::*;
+    use crate::ir::{AbiParam, Block, ExternalName, InstructionData, Signature, ValueList};
+    use crate::isa::{lookup_by_name, CallConv, OwnedTargetIsa};
+    use crate::session::CompilationSession;
+    use crate::settings::{self, Configurable};
+    use crate::Context;
+    use alloc::string::String;
+    use alloc::vec::Vec;
+    use cranelift_control::ControlPlane;
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    /// The controlling types to try: the scalars, and the vectors of any width up to 256 bits.
+    fn ctrl_types() -> Vec<Type> {
+        let mut types = Vec::new();
+        for lane in [I8, I16, I32, I64, I128, F32, F64, R32, R64] {
+            types.push(lane);
+            for lanes in [2, 4, 8, 16, 32] {
+                if let Some(vector) = lane.by(lanes) {
+                    if vector.bits() <= 256 && !lane.is_ref() {
+                        types.push(vector);
+                    }
+                }
+            }
+        }
+        types
+    }
+
+    /// The instructions to try for `opcode`, one for each condition code of the comparisons.
+    fn variants(isa: &dyn TargetIsa, opcode: Opcode, ctrl_type: Type) -> Vec<Function> {
+        match opcode.format() {
+            InstructionFormat::IntCompare => IntCC::all()
+                .iter()
+                .map(|&cond| {
+                    wrapper(opcode, ctrl_type, |args| InstructionData::IntCompare {
+                        opcode,
+                        args: [args[0], args[1]],
+                        cond,
+                    })
+                })
+                .collect(),
+            // Conditions the legalizer splits are covered by the instructions they are split
+            // into.
+            InstructionFormat::FloatCompare => FloatCC::all()
+                .iter()
+                .filter(|&&cond| isa.has_fcmp_lowering(cond, ctrl_type))
+                .map(|&cond| {
+                    wrapper(opcode, ctrl_type, |args| InstructionData::FloatCompare {
+                        opcode,
+                        args: [args[0], args[1]],
+                        cond,
+                    })
+                })
+                .collect(),
+            InstructionFormat::Unary => {
+                vec![wrapper(opcode, ctrl_type, |args| InstructionData::Unary {
+                    opcode,
+                    arg: args[0],
+                })]
+            }
+            InstructionFormat::Binary => {
+                vec![wrapper(opcode, ctrl_type, |args| InstructionData::Binary {
+                    opcode,
+                    args: [args[0], args[1]],
+                })]
+            }
+            InstructionFormat::Ternary => vec![wrapper(opcode, ctrl_type, |args| {
+                InstructionData::Ternary {
+                    opcode,
+                    args: [args[0], args[1], args[2]],
+                }
+            })],
+            InstructionFormat::BinaryImm64 => vec![wrapper(opcode, ctrl_type, |args| {
+                InstructionData::BinaryImm64 {
+                    opcode,
+                    arg: args[0],
+                    imm: 3.into(),
+                }
+            })],
+            format => unreachable!("unclassified format {format:?}"),
+        }
+    }
+
+    /// A function which applies an instruction built by `data` to its parameters, and returns
+    /// the results.
+    fn wrapper(
+        opcode: Opcode,
+        ctrl_type: Type,
+        data: impl FnOnce(&[Value]) -> InstructionData,
+    ) -> Function {
+        let constraints = opcode.constraints();
+        let mut sig = Signature::new(CallConv::SystemV);
+        for n in 0..constraints.num_fixed_value_arguments() {
+            match constraints.value_argument_constraint(n, ctrl_type) {
+                ResolvedConstraint::Bound(ty) => sig.params.push(AbiParam::new(ty)),
+                ResolvedConstraint::Free(_) => unreachable!("unclassified opcode {opcode}"),
+            }
+        }
+        for n in 0..constraints.num_fixed_results() {
+            sig.returns
+                .push(AbiParam::new(constraints.result_type(n, ctrl_type)));
+        }
+
+        let mut func = Function::with_name_signature(Default::default(), sig);
+        let block: Block = func.dfg.make_block();
+        func.layout.append_block(block);
+        let params: Vec<Value> = func
+            .signature
+            .params
+            .clone()
+            .iter()
+            .map(|param| func.dfg.append_block_param(block, param.value_type))
+            .collect();
+        let inst = func.dfg.make_inst(data(&params));
+        func.dfg.make_inst_results(inst, ctrl_type);
+        func.layout.append_inst(inst, block);
+        let results = func.dfg.inst_results(inst).to_vec();
+        let args = ValueList::from_slice(&results, &mut func.dfg.value_lists);
+        let ret = func.dfg.make_inst(InstructionData::MultiAry {
+            opcode: Opcode::Return,
+            args,
+        });
+        func.layout.append_inst(ret, block);
+        func
+    }
+
+    /// How `isa` compiles `func`, a wrapper of `opcode`, regardless of what its table claims.
+    fn observe(isa: &dyn TargetIsa, opcode: Opcode, func: Function) -> SupportLevel {
+        let mut ctx = Context::for_function(func);
+        let compiled = catch_unwind(AssertUnwindSafe(|| {
+            ctx.optimize(isa)?;
+            isa.compile_function(
+                &ctx.func,
+                &ctx.domtree,
+                false,
+                false,
+                false,
+                &mut CompilationSession::new(),
+                &mut ControlPlane::default(),
+            )
+        }));
+        match compiled {
+            Ok(Ok(stencil)) => {
+                let remains = ctx.func.layout.blocks().any(|block| {
+                    ctx.func
+                        .layout
+                        .block_insts(block)
+                        .any(|inst| ctx.func.dfg.insts[inst].opcode() == opcode)
+                });
+                if stencil
+                    .buffer
+                    .relocs()
+                    .iter()
+                    .any(|reloc| matches!(reloc.name, ExternalName::LibCall(_)))
+                {
+                    SupportLevel::ViaLibcall
+                } else if remains {
+                    SupportLevel::Native
+                } else {
+                    SupportLevel::Emulated
+                }
+            }
+            Ok(Err(_)) | Err(_) => SupportLevel::Unsupported,
+        }
+    }
+
+    /// Check the table of the `name` ISA, with the ISA-specific flags `enabled`, against what
+    /// actually compiles.
+    fn check_table(name: &str, enabled: &[&str]) {
+        let mut flags = settings::builder();
+        flags.enable("enable_llvm_abi_extensions").unwrap();
+        let mut builder = lookup_by_name(name).unwrap();
+        for flag in enabled {
+            builder.enable(flag).unwrap();
+        }
+        let isa: OwnedTargetIsa = builder.finish(settings::Flags::new(flags)).unwrap();
+
+        let mut mismatches = String::new();
+        for &opcode in Opcode::all() {
+            if !is_classified(opcode) {
+                continue;
+            }
+            let ctrl_types = if opcode.constraints().is_polymorphic() {
+                ctrl_types()
+            } else {
+                vec![INVALID]
+            };
+            for ctrl_type in ctrl_types {
+                if !is_valid_ctrl_type(opcode, ctrl_type) {
+                    continue;
+                }
+                let claimed = isa.supports(opcode, ctrl_type);
+                for func in variants(&*isa, opcode, ctrl_type) {
+                    let observed = observe(&*isa, opcode, func.clone());
+                    let mut ctx = Context::for_function(func);
+                    let compiled = catch_unwind(AssertUnwindSafe(|| {
+                        ctx.compile(&*isa, &mut ControlPlane::default())
+                            .map(|_| ())
+                            .map_err(|e| e.inner)
+                    }));
+                    let consistent = match compiled {
+                        Ok(Ok(())) => claimed != SupportLevel::Unsupported,
+                        Ok(Err(CodegenError::UnsupportedInstructions(_))) => {
+                            claimed == SupportLevel::Unsupported
+                        }
+                        Ok(Err(_)) | Err(_) => false,
+                    };
+                    if observed != claimed || !consistent {
+                        let inst = match ctrl_type {
+                            INVALID => format!("{opcode}"),
+                            _ => format!("{opcode}.{ctrl_type}"),
+                        };
+                        mismatches +=
+                            &format!("{inst}: claimed {claimed:?}, observed {observed:?}\n");
+                        break;
+                    }
+                }
+            }
+        }
+        assert!(
+            mismatches.is_empty(),
+            "the table of {name} with {enabled:?} is wrong:\n{mismatches}"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "x86")]
+    fn x64_table() {
+        check_table("x86_64", &[]);
+        check_table("x86_64", &["has_sse3", "has_ssse3"]);
+        check_table("x86_64", &["has_sse3", "has_ssse3", "has_sse41"]);
+        let all = [
+            "has_sse3",
+            "has_ssse3",
+            "has_sse41",
+            "has_sse42",
+            "has_popcnt",
+            "has_avx",
+            "has_avx2",
+            "has_fma",
+            "has_bmi1",
+            "has_bmi2",
+            "has_lzcnt",
+        ];
+        check_table("x86_64", &all);
+        let avx512 = [
+            "has_avx512bitalg",
+            "has_avx512dq",
+            "has_avx512vl",
+            "has_avx512vbmi",
+            "has_avx512f",
+        ];
+        check_table("x86_64", &[&all[..], &avx512[..]].concat());
+    }
+
+    #[test]
+    #[cfg(feature = "arm64")]
+    fn aarch64_table() {
+        check_table("aarch64", &[]);
+        check_table("aarch64", &["has_lse"]);
+    }
+
+    #[test]
+    #[cfg(feature = "riscv64")]
+    fn riscv64_table() {
+        check_table("riscv64", &[]);
+        check_table("riscv64", &["has_v"]);
+        check_table("riscv64", &["has_v", "has_zvl256b"]);
+        check_table(
+            "riscv64",
+            &[
+                "has_m", "has_a", "has_f", "has_d", "has_v", "has_c", "has_zbkb", "has_zba",
+                "has_zbb", "has_zbc", "has_zbs",
+            ],
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "s390x")]
+    fn s390x_table() {
+        check_table("s390x", &[]);
+        check_table("s390x", &["arch13"]);
+    }
+}
@@ -8,6 +8,8 @@ use serde::{Deserialize, Serialize};
 #[cfg(feature = "unwind")]
 pub mod systemv;
 
+pub mod table;
+
 #[cfg(feature = "unwind")]
 pub mod winx64;
 
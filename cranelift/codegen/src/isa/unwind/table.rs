@@ -0,0 +1,159 @@
+//! Unwind tables which can be queried at runtime.
+//!
+//! The System V and Windows unwind formats are meant to be registered with the unwinder of the
+//! platform. An embedder walking the stack itself, e.g. to take a backtrace from a signal handler,
+//! needs something simpler: for a given program counter, where the caller's frame starts relative
+//! to the stack pointer, and where the return address and the caller's frame pointer are. An
+//! [`UnwindTable`] answers that for every offset of a function, without relying on frame
+//! pointers.
+
+use crate::binemit::CodeOffset;
+use crate::isa::unwind::UnwindInst;
+use alloc::vec::Vec;
+use target_lexicon::Architecture;
+
+#[cfg(feature = "enable-serde")]
+use serde::{Deserialize, Serialize};
+
+/// The state of the frame of a function from some code offset on, as recorded while emitting it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
+pub enum FrameState {
+    /// Nothing was pushed yet, or everything was popped again, as on entry to the function.
+    Entry,
+    /// The frame is set up as in the body of the function, and `sp_offset` bytes were pushed
+    /// below its nominal stack pointer, e.g. for the arguments of a call.
+    Body {
+        /// The distance from the nominal stack pointer down to the stack pointer.
+        sp_offset: i64,
+    },
+    /// The frame is being set up or torn down, by a prologue, an epilogue or a tail call.
+    Unknown,
+}
+
+/// Where the return address of a frame is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
+pub enum RaLocation {
+    /// Still in the link register.
+    LinkRegister,
+    /// Saved on the stack at this offset from the CFA.
+    CfaOffset(i32),
+}
+
+/// The layout of a frame at some code offset of a function.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
+pub struct FrameLayout {
+    /// The distance from the stack pointer up to the CFA, which is the value of the stack
+    /// pointer in the caller before the call.
+    pub cfa_offset_from_sp: u32,
+    /// Where the return address is.
+    pub ra_location: RaLocation,
+    /// The offset from the CFA at which the frame pointer of the caller is saved, or `None` if
+    /// the frame pointer register still holds it.
+    pub fp_location: Option<i32>,
+}
+
+/// The layouts of the frame of a function at each code offset.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
+pub struct UnwindTable {
+    /// The layout from each offset on, until the offset of the next row. `None` where the layout
+    /// isn't known.
+    rows: Vec<(CodeOffset, Option<FrameLayout>)>,
+    len: CodeOffset,
+}
+
+impl UnwindTable {
+    /// Build the table of a function of `len` bytes compiled for `arch`, from the unwind
+    /// instructions of its prologue, the size of its frame below the saved frame registers, and
+    /// the states of its frame recorded while emitting it.
+    ///
+    /// Returns `None` for architectures whose frames can't be described, and for frames with
+    /// signed return addresses.
+    pub(crate) fn new(
+        arch: Architecture,
+        unwind_info: &[(CodeOffset, UnwindInst)],
+        frame_size: u32,
+        frame_states: &[(CodeOffset, FrameState)],
+        len: CodeOffset,
+    ) -> Option<Self> {
+        let entry = match arch {
+            // The call pushed the return address.
+            Architecture::X86_64 => FrameLayout {
+                cfa_offset_from_sp: 8,
+                ra_location: RaLocation::CfaOffset(-8),
+                fp_location: None,
+            },
+            Architecture::Aarch64(_) | Architecture::Riscv64(_) => FrameLayout {
+                cfa_offset_from_sp: 0,
+                ra_location: RaLocation::LinkRegister,
+                fp_location: None,
+            },
+            _ => return None,
+        };
+
+        // Once set up, a frame has the frame pointer and the return address of the caller saved
+        // just below the CFA, at the same offsets on all supported architectures.
+        let mut body = entry;
+        for (_, inst) in unwind_info {
+            match *inst {
+                UnwindInst::PushFrameRegs {
+                    offset_upward_to_caller_sp,
+                } => {
+                    body.cfa_offset_from_sp = offset_upward_to_caller_sp;
+                    body.ra_location = RaLocation::CfaOffset(-8);
+                    body.fp_location = Some(-(offset_upward_to_caller_sp as i32));
+                }
+                UnwindInst::Aarch64SetPointerAuth {
+                    return_addresses: true,
+                } => return None,
+                _ => {}
+            }
+        }
+        body.cfa_offset_from_sp += frame_size;
+
+        let rows = frame_states
+            .iter()
+            .map(|&(offset, state)| {
+                let layout = match state {
+                    FrameState::Entry => Some(entry),
+                    FrameState::Body { sp_offset } => u32::try_from(sp_offset)
+                        .ok()
+                        .and_then(|sp_offset| body.cfa_offset_from_sp.checked_add(sp_offset))
+                        .map(|cfa_offset_from_sp| FrameLayout {
+                            cfa_offset_from_sp,
+                            ..body
+                        }),
+                    FrameState::Unknown => None,
+                };
+                (offset, layout)
+            })
+            .collect();
+        Some(Self { rows, len })
+    }
+
+    /// Returns the layout of the frame at `offset` within the function, or `None` if it's beyond
+    /// the end of the function or within its prologue or an epilogue, where the layout isn't
+    /// known.
+    ///
+    /// The layout at the return address of a call is the one in effect while the callee runs.
+    pub fn unwind_info_at(&self, offset: CodeOffset) -> Option<FrameLayout> {
+        if offset >= self.len {
+            return None;
+        }
+        let row = match self.rows.binary_search_by_key(&offset, |&(start, _)| start) {
+            Ok(row) => row,
+            Err(0) => return None,
+            Err(next) => next - 1,
+        };
+        self.rows[row].1
+    }
+
+    /// The rows of the table: the layout of the frame from each offset on, until the offset of
+    /// the next row.
+    pub fn rows(&self) -> &[(CodeOffset, Option<FrameLayout>)] {
+        &self.rows
+    }
+}
@@ -223,9 +223,9 @@ pub(crate) fn emit(
             } else {
                 let (opcode_r, opcode_m, subopcode_i) = match op {
                     AluRmiROpcode::Add => (0x01, 0x03, 0),
-                    AluRmiROpcode::Adc => (0x11, 0x03, 0),
+                    AluRmiROpcode::Adc => (0x11, 0x13, 2),
                     AluRmiROpcode::Sub => (0x29, 0x2B, 5),
-                    AluRmiROpcode::Sbb => (0x19, 0x2B, 5),
+                    AluRmiROpcode::Sbb => (0x19, 0x1B, 3),
                     AluRmiROpcode::And => (0x21, 0x23, 4),
                     AluRmiROpcode::Or => (0x09, 0x0B, 1),
                     AluRmiROpcode::Xor => (0x31, 0x33, 6),
@@ -1178,6 +1178,30 @@ pub(crate) fn emit(
             }
         }
 
+        Inst::Shld {
+            size,
+            src1,
+            src2,
+            imm,
+            dst,
+        } => {
+            let src1 = allocs.next(src1.to_reg());
+            let dst = allocs.next(dst.to_reg().to_reg());
+            let src2 = allocs.next(src2.to_reg());
+            debug_assert_eq!(src1, dst);
+            let prefix = match size {
+                OperandSize::Size16 => LegacyPrefixes::_66,
+                OperandSize::Size32 | OperandSize::Size64 => LegacyPrefixes::None,
+                OperandSize::Size8 => panic!("shld has no 8-bit form"),
+            };
+
+            // SHLD $ib, reg16, reg16 is 66 (REX.W==0) 0F A4 /r ib
+            // SHLD $ib, reg32, reg32 is (REX.W==0) 0F A4 /r ib
+            // SHLD $ib, reg64, reg64 is (REX.W==1) 0F A4 /r ib
+            emit_std_reg_reg(sink, prefix, 0x0FA4, 2, src2, dst, RexFlags::from(*size));
+            sink.put1(*imm);
+        }
+
         Inst::XmmRmiReg {
             opcode,
             src1,
@@ -1569,6 +1593,14 @@ pub(crate) fn emit(
             info: call_info,
             ..
         } => {
+            if call_info.opcode == Opcode::PatchableCall {
+                // The displacement is patched with a single aligned store, so that threads
+                // executing the call see either the old or the new target.
+                let misalignment = (sink.cur_offset() + 1) % 4;
+                if misalignment != 0 {
+                    Inst::nop((4 - misalignment) as u8).emit(&[], sink, info, state);
+                }
+            }
             if let Some(s) = state.take_stack_map() {
                 sink.add_stack_map(StackMapExtent::UpcomingBytes(5), s);
             }
@@ -1712,6 +1744,22 @@ pub(crate) fn emit(
             sink.put4(0x0);
         }
 
+        Inst::PatchableJmp { initial, alternate } => {
+            // Like the displacement of a `patchable_call`, the displacement is patched with a
+            // single aligned store. The jump isn't registered as a branch, so that the MachBuffer
+            // never shrinks or removes it.
+            let misalignment = (sink.cur_offset() + 1) % 4;
+            if misalignment != 0 {
+                Inst::nop((4 - misalignment) as u8).emit(&[], sink, info, state);
+            }
+            sink.put1(0xE9);
+            let disp_off = sink.cur_offset();
+            sink.use_label_at_offset(disp_off, *initial, LabelUse::JmpRel32);
+            sink.add_patchable_jump([*initial, *alternate]);
+            // Placeholder for the label value.
+            sink.put4(0x0);
+        }
+
         Inst::JmpIf { cc, taken } => {
             let cond_start = sink.cur_offset();
             let cond_disp_off = cond_start + 2;
@@ -1855,7 +1903,7 @@ pub(crate) fn emit(
             // Emit jump table (table of 32-bit offsets).
             sink.bind_label(start_of_jumptable, state.ctrl_plane_mut());
             let jt_off = sink.cur_offset();
-            for &target in targets.iter().chain(std::iter::once(default_target)) {
+            for &target in targets.iter().chain(core::iter::once(default_target)) {
                 let word_off = sink.cur_offset();
                 // off_into_table is an addend here embedded in the label to be later patched at
                 // the end of codegen. The offset is initially relative to this jump table entry;
@@ -3759,7 +3807,7 @@ pub(crate) fn emit(
                 sink.put1(0x48 | ((enc_dst >> 3) & 1) << 2);
                 sink.put1(0x8D);
                 sink.put1(0x05 | ((enc_dst & 7) << 3));
-                emit_reloc(sink, Reloc::X86CallPCRel4, name, -4);
+                emit_reloc(sink, Reloc::X86CallPCRel4, name, *offset - 4);
                 sink.put4(0);
             } else {
                 // The full address can be encoded in the register, with a relocation.
@@ -44,6 +44,10 @@ impl MachInstEmitState<Inst> for EmitState {
     fn take_ctrl_plane(self) -> ControlPlane {
         self.ctrl_plane
     }
+
+    fn virtual_sp_offset(&self) -> i64 {
+        self.virtual_sp_offset
+    }
 }
 
 impl EmitState {
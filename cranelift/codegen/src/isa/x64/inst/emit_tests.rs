@@ -20,6 +20,16 @@ use alloc::vec::Vec;
 use cranelift_entity::EntityRef as _;
 
 impl Inst {
+    fn shld(size: OperandSize, imm: u8, src2: Reg, dst: Writable<Reg>) -> Inst {
+        Inst::Shld {
+            size,
+            src1: Gpr::new(dst.to_reg()).unwrap(),
+            src2: Gpr::new(src2).unwrap(),
+            imm,
+            dst: WritableGpr::from_writable_reg(dst).unwrap(),
+        }
+    }
+
     fn neg(size: OperandSize, src: Writable<Reg>) -> Inst {
         debug_assert_eq!(src.to_reg().class(), RegClass::Int);
         Inst::Neg {
@@ -1261,6 +1271,46 @@ fn test_x64_emit() {
         "037763",
         "addl    %esi, 99(%rdi), %esi",
     ));
+    insns.push((
+        Inst::alu_rmi_r(
+            OperandSize::Size64,
+            AluRmiROpcode::Adc,
+            RegMemImm::imm(0),
+            w_rdx,
+        ),
+        "4883D200",
+        "adcq    %rdx, $0, %rdx",
+    ));
+    insns.push((
+        Inst::alu_rmi_r(
+            OperandSize::Size64,
+            AluRmiROpcode::Adc,
+            RegMemImm::mem(Amode::imm_reg(99, rdi)),
+            w_rdx,
+        ),
+        "48135763",
+        "adcq    %rdx, 99(%rdi), %rdx",
+    ));
+    insns.push((
+        Inst::alu_rmi_r(
+            OperandSize::Size64,
+            AluRmiROpcode::Sbb,
+            RegMemImm::imm(1),
+            w_r8,
+        ),
+        "4983D801",
+        "sbbq    %r8, $1, %r8",
+    ));
+    insns.push((
+        Inst::alu_rmi_r(
+            OperandSize::Size32,
+            AluRmiROpcode::Sbb,
+            RegMemImm::mem(Amode::imm_reg(99, rdi)),
+            w_rsi,
+        ),
+        "1B7763",
+        "sbbl    %esi, 99(%rdi), %esi",
+    ));
     insns.push((
         Inst::alu_rmi_r(
             OperandSize::Size64,
@@ -3832,6 +3882,29 @@ fn test_x64_emit() {
         "rorw    $5, %r15w, %r15w",
     ));
 
+    // ========================================================
+    // Shld
+    insns.push((
+        Inst::shld(OperandSize::Size64, 7, rbx, w_rax),
+        "480FA4D807",
+        "shldq   $7, %rbx, %rax, %rax",
+    ));
+    insns.push((
+        Inst::shld(OperandSize::Size64, 63, r12, w_r11),
+        "4D0FA4E33F",
+        "shldq   $63, %r12, %r11, %r11",
+    ));
+    insns.push((
+        Inst::shld(OperandSize::Size32, 31, r9, w_rdx),
+        "440FA4CA1F",
+        "shldl   $31, %r9d, %edx, %edx",
+    ));
+    insns.push((
+        Inst::shld(OperandSize::Size16, 1, rcx, w_r13),
+        "66410FA4CD01",
+        "shldw   $1, %cx, %r13w, %r13w",
+    ));
+
     // ========================================================
     // CmpRMIR
     insns.push((
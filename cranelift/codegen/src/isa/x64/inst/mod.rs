@@ -11,11 +11,11 @@ use crate::isa::{CallConv, FunctionAlignment};
 use crate::{machinst::*, trace};
 use crate::{settings, CodegenError, CodegenResult};
 use alloc::boxed::Box;
+use alloc::string::{String, ToString};
 use alloc::vec::Vec;
+use core::fmt::{self, Write};
 use regalloc2::{Allocation, PRegSet, VReg};
 use smallvec::{smallvec, SmallVec};
-use std::fmt::{self, Write};
-use std::string::{String, ToString};
 
 pub mod args;
 mod emit;
@@ -78,7 +78,7 @@ pub struct ReturnCallInfo {
 fn inst_size_test() {
     // This test will help with unintentionally growing the size
     // of the Inst enum.
-    assert_eq!(40, std::mem::size_of::<Inst>());
+    assert_eq!(40, core::mem::size_of::<Inst>());
 }
 
 pub(crate) fn low32_will_sign_extend_to_64(x: u64) -> bool {
@@ -136,6 +136,7 @@ impl Inst {
             | Inst::Neg { .. }
             | Inst::Not { .. }
             | Inst::Nop { .. }
+            | Inst::PatchableJmp { .. }
             | Inst::Pop64 { .. }
             | Inst::Push64 { .. }
             | Inst::StackProbeLoop { .. }
@@ -143,6 +144,7 @@ impl Inst {
             | Inst::Ret { .. }
             | Inst::Setcc { .. }
             | Inst::ShiftR { .. }
+            | Inst::Shld { .. }
             | Inst::SignExtendData { .. }
             | Inst::TrapIf { .. }
             | Inst::TrapIfAnd { .. }
@@ -1481,6 +1483,20 @@ impl PrettyPrint for Inst {
                 }
             }
 
+            Inst::Shld {
+                size,
+                src1,
+                src2,
+                imm,
+                dst,
+            } => {
+                let src1 = pretty_print_reg(src1.to_reg(), size.to_bytes(), allocs);
+                let dst = pretty_print_reg(dst.to_reg().to_reg(), size.to_bytes(), allocs);
+                let src2 = pretty_print_reg(src2.to_reg(), size.to_bytes(), allocs);
+                let op = ljustify2("shld".to_string(), suffix_bwlq(*size));
+                format!("{op} ${imm}, {src2}, {src1}, {dst}")
+            }
+
             Inst::XmmRmiReg {
                 opcode,
                 src1,
@@ -1690,6 +1706,13 @@ impl PrettyPrint for Inst {
                 format!("{op} {dst}")
             }
 
+            Inst::PatchableJmp { initial, alternate } => {
+                let op = ljustify("patchable_jmp".to_string());
+                let initial = initial.to_string();
+                let alternate = alternate.to_string();
+                format!("{op} {initial}, {alternate}")
+            }
+
             Inst::JmpIf { cc, taken } => {
                 let taken = taken.to_string();
                 let op = ljustify2("j".to_string(), cc.to_string());
@@ -2219,6 +2242,13 @@ fn x64_get_operands<F: Fn(VReg) -> VReg>(inst: &Inst, collector: &mut OperandCol
                 collector.reg_fixed_use(reg, regs::rcx());
             }
         }
+        Inst::Shld {
+            src1, src2, dst, ..
+        } => {
+            collector.reg_use(src1.to_reg());
+            collector.reg_reuse_def(dst.to_writable_reg(), 0);
+            collector.reg_use(src2.to_reg());
+        }
         Inst::CmpRmiR { src, dst, .. } => {
             // N.B.: use, not def (cmp doesn't write its result).
             collector.reg_use(dst.to_reg());
@@ -2401,6 +2431,7 @@ fn x64_get_operands<F: Fn(VReg) -> VReg>(inst: &Inst, collector: &mut OperandCol
         }
 
         Inst::JmpKnown { .. }
+        | Inst::PatchableJmp { .. }
         | Inst::JmpIf { .. }
         | Inst::JmpCond { .. }
         | Inst::Nop { .. }
@@ -2518,7 +2549,7 @@ impl MachInst for Inst {
                 MachTerminator::RetCall
             }
             &Self::JmpKnown { .. } => MachTerminator::Uncond,
-            &Self::JmpCond { .. } => MachTerminator::Cond,
+            &Self::JmpCond { .. } | &Self::PatchableJmp { .. } => MachTerminator::Cond,
             &Self::JmpTableSeq { .. } => MachTerminator::Indirect,
             // All other cases are boring.
             _ => MachTerminator::None,
@@ -2556,7 +2587,7 @@ impl MachInst for Inst {
     }
 
     fn gen_nop(preferred_size: usize) -> Inst {
-        Inst::nop(std::cmp::min(preferred_size, 15) as u8)
+        Inst::nop(core::cmp::min(preferred_size, 15) as u8)
     }
 
     fn rc_for_type(ty: Type) -> CodegenResult<(&'static [RegClass], &'static [Type])> {
@@ -34,11 +34,11 @@ use crate::{
         VCodeConstant, VCodeConstantData,
     },
 };
+use alloc::boxed::Box;
 use alloc::vec::Vec;
+use core::convert::TryFrom;
 use regalloc2::PReg;
 use smallvec::SmallVec;
-use std::boxed::Box;
-use std::convert::TryFrom;
 
 type BoxCallInfo = Box<CallInfo>;
 type BoxReturnCallInfo = Box<ReturnCallInfo>;
@@ -160,6 +160,22 @@ impl Context for IsleContext<'_, '_, MInst, X64Backend> {
         InstOutput::new()
     }
 
+    fn gen_patchable_call(
+        &mut self,
+        sig_ref: SigRef,
+        extname: ExternalName,
+        args: ValueSlice,
+    ) -> InstOutput {
+        // Only a near call is a single instruction whose target can be patched.
+        self.gen_direct_call(
+            sig_ref,
+            extname,
+            RelocDistance::Near,
+            args,
+            Opcode::PatchableCall,
+        )
+    }
+
     fn gen_return_call(
         &mut self,
         callee_sig: SigRef,
@@ -179,6 +195,7 @@ impl Context for IsleContext<'_, '_, MInst, X64Backend> {
             callee_sig,
             &callee,
             distance,
+            Opcode::ReturnCall,
             caller_conv,
             self.backend.flags().clone(),
         )
@@ -879,10 +896,15 @@ impl Context for IsleContext<'_, '_, MInst, X64Backend> {
     }
 
     #[inline]
-    fn vconst_all_ones_or_all_zeros(&mut self, constant: Constant) -> Option<()> {
+    fn vconst_all_ones_or_all_zeros(&mut self, lane_bits: u32, constant: Constant) -> Option<bool> {
         let const_data = self.lower_ctx.get_constant_data(constant);
-        if const_data.iter().all(|&b| b == 0 || b == 0xFF) {
-            return Some(());
+        let lane_bytes = usize::try_from(lane_bits / 8).unwrap();
+        if const_data
+            .as_slice()
+            .chunks(lane_bytes)
+            .all(|lane| lane.iter().all(|&b| b == 0) || lane.iter().all(|&b| b == 0xFF))
+        {
+            return Some(true);
         }
         None
     }
@@ -1085,16 +1107,6 @@ impl Context for IsleContext<'_, '_, MInst, X64Backend> {
         }
     }
 
-    fn palignr_imm_from_immediate(&mut self, imm: Immediate) -> Option<u8> {
-        let bytes = self.lower_ctx.get_immediate_data(imm).as_slice();
-
-        if bytes.windows(2).all(|a| a[0] + 1 == a[1]) {
-            Some(bytes[0])
-        } else {
-            None
-        }
-    }
-
     fn pblendw_imm(&mut self, imm: Immediate) -> Option<u8> {
         // First make sure that the shuffle immediate is selecting 16-bit lanes.
         let (a, b, c, d, e, f, g, h) = self.shuffle16_from_imm(imm)?;
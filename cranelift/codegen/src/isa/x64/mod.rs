@@ -4,16 +4,19 @@ pub use self::inst::{args, CallInfo, EmitInfo, EmitState, Inst};
 
 use super::{OwnedTargetIsa, TargetIsa};
 use crate::dominator_tree::DominatorTree;
-use crate::ir::{types, Function, Type};
+use crate::ir::condcodes::FloatCC;
+use crate::ir::{types, Function, Opcode, Type};
+use crate::isa::support::classify;
 #[cfg(feature = "unwind")]
 use crate::isa::unwind::systemv;
 use crate::isa::x64::{inst::regs::create_reg_env_systemv, settings as x64_settings};
-use crate::isa::{Builder as IsaBuilder, FunctionAlignment};
+use crate::isa::{Builder as IsaBuilder, FunctionAlignment, SupportLevel};
 use crate::machinst::{
     compile, CompiledCode, CompiledCodeStencil, MachInst, MachTextSectionBuilder, Reg, SigSet,
     TextSectionBuilder, VCode,
 };
 use crate::result::CodegenResult;
+use crate::session::{BackendScratch, CompilationSession};
 use crate::settings::{self as shared_settings, Flags};
 use alloc::{boxed::Box, vec::Vec};
 use core::fmt;
@@ -26,6 +29,7 @@ pub mod encoding;
 mod inst;
 mod lower;
 pub mod settings;
+mod support;
 
 /// An X64 backend.
 pub(crate) struct X64Backend {
@@ -51,6 +55,7 @@ impl X64Backend {
         &self,
         func: &Function,
         domtree: &DominatorTree,
+        scratch: &mut BackendScratch<inst::Inst>,
         ctrl_plane: &mut ControlPlane,
     ) -> CodegenResult<(VCode<inst::Inst>, regalloc2::Output)> {
         // This performs lowering to VCode, register-allocates the code, computes
@@ -58,7 +63,9 @@ impl X64Backend {
         let emit_info = EmitInfo::new(self.flags.clone(), self.x64_flags.clone());
         let sigs = SigSet::new::<abi::X64ABIMachineSpec>(func, &self.flags)?;
         let abi = abi::X64Callee::new(func, self, &self.x64_flags, &sigs)?;
-        compile::compile::<Self>(func, domtree, self, abi, emit_info, sigs, ctrl_plane)
+        compile::compile::<Self>(
+            func, domtree, self, abi, emit_info, sigs, scratch, ctrl_plane,
+        )
     }
 }
 
@@ -68,11 +75,27 @@ impl TargetIsa for X64Backend {
         func: &Function,
         domtree: &DominatorTree,
         want_disasm: bool,
+        want_vcode: bool,
+        want_inst_offsets: bool,
+        session: &mut CompilationSession,
         ctrl_plane: &mut ControlPlane,
     ) -> CodegenResult<CompiledCodeStencil> {
-        let (vcode, regalloc_result) = self.compile_vcode(func, domtree, ctrl_plane)?;
+        let scratch = session.backend_scratch::<inst::Inst>();
+        let (vcode, regalloc_result) = self.compile_vcode(func, domtree, scratch, ctrl_plane)?;
+        let vcode_listing = if want_vcode {
+            Some(Box::new(vcode.listing(Some(&regalloc_result))))
+        } else {
+            None
+        };
 
-        let emit_result = vcode.emit(&regalloc_result, want_disasm, &self.flags, ctrl_plane);
+        let emit_result = vcode.emit(
+            &regalloc_result,
+            want_disasm,
+            want_inst_offsets,
+            &self.flags,
+            scratch,
+            ctrl_plane,
+        )?;
         let frame_size = emit_result.frame_size;
         let value_labels_ranges = emit_result.value_labels_ranges;
         let buffer = emit_result.buffer;
@@ -87,11 +110,13 @@ impl TargetIsa for X64Backend {
             buffer,
             frame_size,
             vcode: emit_result.disasm,
+            vcode_listing,
             value_labels_ranges,
             sized_stackslot_offsets,
             dynamic_stackslot_offsets,
             bb_starts: emit_result.bb_offsets,
             bb_edges: emit_result.bb_edges,
+            block_coverage: Vec::new(),
         })
     }
 
@@ -175,10 +200,22 @@ impl TargetIsa for X64Backend {
             .build()
     }
 
+    fn supports(&self, opcode: Opcode, ctrl_type: Type) -> SupportLevel {
+        classify(opcode, ctrl_type, |opcode, ty| {
+            support::support_level(opcode, ty, &self.x64_flags)
+        })
+    }
+
     fn has_native_fma(&self) -> bool {
         self.x64_flags.use_fma()
     }
 
+    fn has_fcmp_lowering(&self, cond: FloatCC, ty: Type) -> bool {
+        // `CMPPS` and `CMPPD` only encode the ordered and unordered conditions
+        // which a single comparison can test.
+        !ty.is_vector() || !matches!(cond, FloatCC::OrderedNotEqual | FloatCC::UnorderedOrEqual)
+    }
+
     fn has_x86_blendv_lowering(&self, ty: Type) -> bool {
         // The `blendvpd`, `blendvps`, and `pblendvb` instructions are all only
         // available from SSE 4.1 and onwards. Otherwise the i16x8 type has no
@@ -187,6 +224,12 @@ impl TargetIsa for X64Backend {
         self.x64_flags.use_sse41() && ty != types::I16X8
     }
 
+    fn has_fast_shift_add_lowering(&self, ty: Type) -> bool {
+        // 32 and 64-bit additions are lowered to `lea`, which can scale one of
+        // its operands by 2, 4 or 8.
+        ty == types::I32 || ty == types::I64
+    }
+
     fn has_x86_pshufb_lowering(&self) -> bool {
         self.x64_flags.use_ssse3()
     }
@@ -0,0 +1,214 @@
+//! The instructions the x64 backend can compile.
+//!
+//! This table is checked against what the backend actually compiles by the tests of
+//! `crate::isa::support`; update it together with the lowering rules.
+
+use crate::ir::types::*;
+use crate::ir::{Opcode, Type};
+use crate::isa::x64::settings::Flags;
+use crate::isa::SupportLevel::{self, *};
+
+/// How the backend implements `opcode` with the controlling type `ty`, which `opcode` accepts.
+pub(crate) fn support_level(opcode: Opcode, ty: Type, flags: &Flags) -> SupportLevel {
+    match opcode {
+        Opcode::Splat | Opcode::VallTrue => match ty {
+            I8X16 | I16X8 | I32X4 | I64X2 | F32X4 | F64X2 => Native,
+            _ => Unsupported,
+        },
+        Opcode::Swizzle => {
+            if flags.use_ssse3() {
+                Native
+            } else {
+                ViaLibcall
+            }
+        }
+        Opcode::X86Pshufb | Opcode::X86Pmaddubsw => {
+            if flags.use_ssse3() {
+                Native
+            } else {
+                Unsupported
+            }
+        }
+        Opcode::Smin | Opcode::Umin | Opcode::Smax | Opcode::Umax | Opcode::Iabs => match ty {
+            I8 | I16 | I32 | I64 | I8X16 | I16X8 | I32X4 | I64X2 => Native,
+            _ => Unsupported,
+        },
+        Opcode::AvgRound
+        | Opcode::UaddSat
+        | Opcode::SaddSat
+        | Opcode::UsubSat
+        | Opcode::SsubSat => match ty {
+            I8X16 | I16X8 => Native,
+            _ => Unsupported,
+        },
+        Opcode::Bitselect => match ty {
+            I8 | I16 | I32 | I64 | I128 | F32 | F64 | R64 | I8X2 | I8X4 | I8X8 | I8X16 | I16X2
+            | I16X4 | I16X8 | I32X2 | I32X4 | I64X2 | F32X2 | F32X4 | F64X2 => Native,
+            _ => Unsupported,
+        },
+        Opcode::X86Blendv => match ty {
+            I8X16 | I32X4 | I64X2 => {
+                if flags.use_sse41() {
+                    Native
+                } else {
+                    Unsupported
+                }
+            }
+            _ => Unsupported,
+        },
+        Opcode::VanyTrue => match ty {
+            I8X2 | I8X4 | I8X8 | I8X16 | I16X2 | I16X4 | I16X8 | I32X2 | I32X4 | I64X2 | F32X2
+            | F32X4 | F64X2 => Native,
+            _ => Unsupported,
+        },
+        Opcode::Icmp | Opcode::Iadd | Opcode::Isub | Opcode::Ineg => match ty {
+            I8 | I16 | I32 | I64 | I128 | I8X16 | I16X8 | I32X4 | I64X2 => Native,
+            _ => Unsupported,
+        },
+        Opcode::Imul => match ty {
+            I8 | I16 | I32 | I64 | I128 | I16X8 | I32X4 | I64X2 => Native,
+            _ => Unsupported,
+        },
+        Opcode::Umulhi
+        | Opcode::Smulhi
+        | Opcode::UaddOverflow
+        | Opcode::SaddOverflow
+        | Opcode::UsubOverflow
+        | Opcode::SsubOverflow
+        | Opcode::Bitrev
+        | Opcode::Clz
+        | Opcode::Ctz => match ty {
+            I8 | I16 | I32 | I64 | I128 => Native,
+            _ => Unsupported,
+        },
+        Opcode::SqmulRoundSat => match ty {
+            I16X8 => Native,
+            _ => Unsupported,
+        },
+        Opcode::X86Pmulhrsw => match ty {
+            I16X8 => {
+                if flags.use_ssse3() {
+                    Native
+                } else {
+                    Unsupported
+                }
+            }
+            _ => Unsupported,
+        },
+        Opcode::Udiv
+        | Opcode::Sdiv
+        | Opcode::Urem
+        | Opcode::Srem
+        | Opcode::UmulOverflow
+        | Opcode::SmulOverflow => match ty {
+            I8 | I16 | I32 | I64 => Native,
+            _ => Unsupported,
+        },
+        Opcode::IaddImm
+        | Opcode::ImulImm
+        | Opcode::IrsubImm
+        | Opcode::BandImm
+        | Opcode::BorImm
+        | Opcode::BxorImm
+        | Opcode::RotlImm
+        | Opcode::RotrImm => match ty {
+            I8 | I16 | I32 | I64 | I128 => Emulated,
+            _ => Unsupported,
+        },
+        Opcode::UdivImm | Opcode::SdivImm | Opcode::UremImm | Opcode::SremImm => match ty {
+            I8 | I16 | I32 | I64 => Emulated,
+            _ => Unsupported,
+        },
+        Opcode::Band | Opcode::Bor | Opcode::Bxor | Opcode::Bnot => match ty {
+            I8 | I16 | I32 | I64 | I128 | F32 | F64 | I8X2 | I8X4 | I8X8 | I8X16 | I16X2
+            | I16X4 | I16X8 | I32X2 | I32X4 | I64X2 | F32X2 | F32X4 | F64X2 => Native,
+            _ => Unsupported,
+        },
+        Opcode::BandNot | Opcode::BorNot | Opcode::BxorNot => match ty {
+            I8 | I16 | I32 | I64 | I128 | F32 | F64 | I8X2 | I8X4 | I8X8 | I8X16 | I16X2
+            | I16X4 | I16X8 | I32X2 | I32X4 | I64X2 | F32X2 | F32X4 | F64X2 => Emulated,
+            _ => Unsupported,
+        },
+        Opcode::IshlImm | Opcode::UshrImm | Opcode::SshrImm => match ty {
+            I8 | I16 | I32 | I64 | I128 | I8X16 | I16X8 | I32X4 | I64X2 => Emulated,
+            _ => Unsupported,
+        },
+        Opcode::Bswap => match ty {
+            I16 | I32 | I64 | I128 => Native,
+            _ => Unsupported,
+        },
+        Opcode::Popcnt => match ty {
+            I8 | I16 | I32 | I64 | I128 | I8X16 => Native,
+            _ => Unsupported,
+        },
+        Opcode::Fcmp
+        | Opcode::Fadd
+        | Opcode::Fsub
+        | Opcode::Fmul
+        | Opcode::Fdiv
+        | Opcode::Sqrt
+        | Opcode::Fneg
+        | Opcode::Fabs
+        | Opcode::Fmin
+        | Opcode::FminPseudo
+        | Opcode::Fmax
+        | Opcode::FmaxPseudo => match ty {
+            F32 | F64 | F32X4 | F64X2 => Native,
+            _ => Unsupported,
+        },
+        Opcode::Fma => match ty {
+            F32 | F64 | F32X4 | F64X2 => {
+                if flags.use_fma() {
+                    Native
+                } else {
+                    ViaLibcall
+                }
+            }
+            _ => Unsupported,
+        },
+        Opcode::Fcopysign => match ty {
+            F32 | F64 => Native,
+            _ => Unsupported,
+        },
+        Opcode::Ceil | Opcode::Floor | Opcode::Trunc | Opcode::Nearest => match ty {
+            F32 | F64 | F32X4 | F64X2 => {
+                if flags.use_sse41() {
+                    Native
+                } else {
+                    ViaLibcall
+                }
+            }
+            _ => Unsupported,
+        },
+        Opcode::IsNull | Opcode::IsInvalid => match ty {
+            R64 => Native,
+            _ => Unsupported,
+        },
+        Opcode::ScalarToVector => match ty {
+            I32X2 | I32X4 | I64X2 | F32X2 | F32X4 | F64X2 => Native,
+            _ => Unsupported,
+        },
+        Opcode::Snarrow | Opcode::Unarrow => match ty {
+            I16X8 | I32X4 => Native,
+            _ => Unsupported,
+        },
+        Opcode::SwidenLow
+        | Opcode::SwidenHigh
+        | Opcode::UwidenLow
+        | Opcode::UwidenHigh
+        | Opcode::IaddPairwise => match ty {
+            I8X16 | I16X8 | I32X4 => Native,
+            _ => Unsupported,
+        },
+        Opcode::Fvdemote | Opcode::FvpromoteLow => Native,
+        Opcode::Isplit => match ty {
+            I128 => Native,
+            _ => Unsupported,
+        },
+        Opcode::Iconcat => match ty {
+            I64 => Native,
+            _ => Unsupported,
+        },
+        _ => Unsupported,
+    }
+}
@@ -198,7 +198,7 @@ macro_rules! isle_common_prelude_methods {
 
         #[inline]
         fn ty_bits(&mut self, ty: Type) -> u8 {
-            use std::convert::TryInto;
+            use core::convert::TryInto;
             ty.bits().try_into().unwrap()
         }
 
@@ -694,6 +694,16 @@ macro_rules! isle_common_prelude_methods {
             Imm64::new(x as i64)
         }
 
+        #[inline]
+        fn ty_imm64_power_of_two(&mut self, ty: Type, x: Imm64) -> Option<u64> {
+            let x = x.bits() as u64 & self.ty_mask(ty);
+            if x.is_power_of_two() {
+                Some(x.trailing_zeros().into())
+            } else {
+                None
+            }
+        }
+
         #[inline]
         fn imm64_masked(&mut self, ty: Type, x: u64) -> Imm64 {
             Imm64::new((x & self.ty_mask(ty)) as i64)
@@ -797,6 +807,14 @@ macro_rules! isle_common_prelude_methods {
             MemFlags::trusted()
         }
 
+        #[inline]
+        fn little_endian_mem_flags(&mut self, flags: MemFlags) -> Option<MemFlags> {
+            match flags.endianness(crate::ir::Endianness::Little) {
+                crate::ir::Endianness::Little => Some(flags),
+                crate::ir::Endianness::Big => None,
+            }
+        }
+
         #[inline]
         fn intcc_unsigned(&mut self, x: &IntCC) -> IntCC {
             x.unsigned()
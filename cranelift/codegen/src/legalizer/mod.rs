@@ -15,6 +15,7 @@
 
 use crate::cursor::{Cursor, FuncCursor};
 use crate::flowgraph::ControlFlowGraph;
+use crate::ir::condcodes::FloatCC;
 use crate::ir::immediates::Imm64;
 use crate::ir::types::{I128, I64};
 use crate::ir::{self, InstBuilder, InstructionData, MemFlags, Value};
@@ -226,6 +227,19 @@ pub fn simple_legalize(func: &mut ir::Function, cfg: &mut ControlFlowGraph, isa:
                     pos.func.dfg.replace(inst).icmp(cond, arg, imm);
                 }
 
+                // Split floating-point comparisons the target can't lower into two
+                // comparisons it can.
+                InstructionData::FloatCompare {
+                    opcode: ir::Opcode::Fcmp,
+                    cond,
+                    args,
+                } if !isa.has_fcmp_lowering(cond, pos.func.dfg.value_type(args[0])) => {
+                    let (first, second) = split_float_cc(cond);
+                    let first = pos.ins().fcmp(first, args[0], args[1]);
+                    let second = pos.ins().fcmp(second, args[0], args[1]);
+                    pos.func.dfg.replace(inst).bor(first, second);
+                }
+
                 // Legalize the fused bitwise-plus-not instructions into simpler
                 // instructions to assist with optimizations. Lowering will
                 // pattern match this sequence regardless when architectures
@@ -263,6 +277,21 @@ pub fn simple_legalize(func: &mut ir::Function, cfg: &mut ControlFlowGraph, isa:
     trace!("Post-legalization function:\n{}", func.display());
 }
 
+/// Split `cond` into two conditions which hold exactly when either of them does.
+///
+/// Every target is expected to lower the resulting conditions.
+fn split_float_cc(cond: FloatCC) -> (FloatCC, FloatCC) {
+    match cond {
+        FloatCC::OrderedNotEqual => (FloatCC::LessThan, FloatCC::GreaterThan),
+        FloatCC::UnorderedOrEqual => (FloatCC::Unordered, FloatCC::Equal),
+        FloatCC::UnorderedOrLessThan => (FloatCC::Unordered, FloatCC::LessThan),
+        FloatCC::UnorderedOrLessThanOrEqual => (FloatCC::Unordered, FloatCC::LessThanOrEqual),
+        FloatCC::UnorderedOrGreaterThan => (FloatCC::Unordered, FloatCC::GreaterThan),
+        FloatCC::UnorderedOrGreaterThanOrEqual => (FloatCC::Unordered, FloatCC::GreaterThanOrEqual),
+        _ => panic!("the `{cond}` condition can't be split"),
+    }
+}
+
 /// Custom expansion for conditional trap instructions.
 fn expand_cond_trap(
     inst: ir::Inst,
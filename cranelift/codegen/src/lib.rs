@@ -53,12 +53,18 @@ extern crate alloc;
 #[macro_use]
 extern crate std;
 
+#[cfg(not(any(feature = "std", feature = "core")))]
+compile_error!("cranelift-codegen requires either the `std` or the `core` feature");
+
 #[cfg(not(feature = "std"))]
 use hashbrown::{hash_map, HashMap, HashSet};
 #[cfg(feature = "std")]
 use std::collections::{hash_map, HashMap, HashSet};
 
+pub use crate::budget::CompileBudget;
 pub use crate::context::Context;
+pub use crate::coverage::{CoverageConfig, CoverageCounter};
+pub use crate::session::CompilationSession;
 pub use crate::value_label::{ValueLabelsRanges, ValueLocRange};
 pub use crate::verifier::verify_function;
 pub use crate::write::write_function;
@@ -69,9 +75,29 @@ pub use cranelift_entity as entity;
 #[cfg(feature = "unwind")]
 pub use gimli;
 
+/// Enter a `tracing` span at the debug level, named and with the fields given as to
+/// `tracing::debug_span!`, until the end of the enclosing block. Only with the `trace-log`
+/// feature.
+macro_rules! trace_span {
+    ($($tt:tt)*) => {
+        #[cfg(feature = "trace-log")]
+        let _span = ::tracing::debug_span!($($tt)*).entered();
+    };
+}
+
+/// Record a `tracing` event at the debug level, with the fields and message given as to
+/// `tracing::debug!`, in the current span. Only with the `trace-log` feature.
+macro_rules! trace_event {
+    ($($tt:tt)*) => {
+        #[cfg(feature = "trace-log")]
+        ::tracing::debug!($($tt)*);
+    };
+}
+
 #[macro_use]
 mod machinst;
 
+pub mod analysis;
 pub mod binemit;
 pub mod cfg_printer;
 pub mod cursor;
@@ -79,6 +105,7 @@ pub mod data_value;
 pub mod dbg;
 pub mod dominator_tree;
 pub mod flowgraph;
+pub mod inline;
 pub mod ir;
 pub mod isa;
 pub mod loop_analysis;
@@ -90,17 +117,21 @@ pub mod write;
 
 pub use crate::entity::packed_option;
 pub use crate::machinst::buffer::{
-    MachCallSite, MachReloc, MachSrcLoc, MachStackMap, MachTextSectionBuilder, MachTrap,
+    MachCallSite, MachPatchableJump, MachReloc, MachSrcLoc, MachStackMap, MachTextSectionBuilder,
+    MachTrap,
 };
 pub use crate::machinst::{
     CompiledCode, Final, MachBuffer, MachBufferFinalized, MachInst, MachInstEmit,
-    MachInstEmitState, MachLabel, Reg, TextSectionBuilder, Writable,
+    MachInstEmitState, MachLabel, Reg, TextSectionBuilder, VCodeListing, VCodeListingBlock,
+    VCodeListingInst, VCodeRegallocStats, Writable,
 };
 
 mod alias_analysis;
 mod bitset;
+mod budget;
 mod constant_hash;
 mod context;
+mod coverage;
 mod ctxhash;
 mod dce;
 mod egraph;
@@ -109,11 +140,16 @@ mod inst_predicates;
 mod isle_prelude;
 mod iterators;
 mod legalizer;
+mod loop_unrolling;
 mod nan_canonicalization;
 mod opts;
+mod pressure_scheduling;
+mod redundant_checks;
 mod remove_constant_phis;
 mod result;
 mod scoped_hash_map;
+mod session;
+mod simplify_block_params;
 mod unionfind;
 mod unreachable_code;
 mod value_label;
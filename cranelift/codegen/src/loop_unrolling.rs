@@ -0,0 +1,670 @@
+//! Loop unrolling.
+//!
+//! Unrolls the innermost loops whose body is a chain of blocks, each falling through to the next
+//! and the last jumping back to the header, which leave the loop from a single block testing an
+//! induction variable: a header parameter which the back edge increases by a constant step.
+//!
+//! - A loop whose induction variable starts from and is compared against constants has a known
+//!   trip count. If the unrolled code stays within the instruction budget, the loop is replaced
+//!   with that many copies of its body, so that later passes see straight-line code.
+//! - Otherwise, if a partial unrolling factor is set, a main loop running that many copies of the
+//!   body without testing the induction variable is put in front of the loop, which then runs the
+//!   remaining iterations. The main loop only starts an iteration when all the tests it skips
+//!   would continue the loop.
+
+use crate::cursor::{Cursor, FuncCursor};
+use crate::dominator_tree::DominatorTree;
+use crate::flowgraph::ControlFlowGraph;
+use crate::fx::{FxHashMap, FxHashSet};
+use crate::ir::condcodes::{CondCode, IntCC};
+use crate::ir::{Block, BlockCall, Function, Inst, InstBuilder, InstructionData, Opcode, Type};
+use crate::ir::{Value, ValueDef};
+use crate::loop_analysis::{Loop, LoopAnalysis};
+use crate::timing;
+use crate::trace;
+use alloc::vec::Vec;
+
+/// The loop-invariant side of the test of a counted loop.
+#[derive(Clone, Copy)]
+enum Bound {
+    Value(Value),
+    Imm(i64),
+}
+
+/// A loop which [`do_unroll_loops`] knows how to unroll.
+struct CountedLoop {
+    /// The blocks of the loop, from the header to the block jumping back to it.
+    chain: Vec<Block>,
+    /// The index in `chain` of the block leaving the loop.
+    exit_index: usize,
+    /// The edge leaving the loop.
+    exit_edge: BlockCall,
+    /// The edge of the exiting block staying in the loop.
+    stay_edge: BlockCall,
+    /// The instruction computing the condition of the exiting branch.
+    test: Inst,
+    /// The index of the induction variable among the header parameters.
+    iv: usize,
+    /// The amount the induction variable changes by on each iteration.
+    step: i128,
+    /// The type of the induction variable.
+    ty: Type,
+    /// Whether the test compares the value of the induction variable for the next iteration
+    /// rather than the current one.
+    tests_next: bool,
+    /// The condition `iv cc bound` under which the loop continues.
+    cc: IntCC,
+    bound: Bound,
+}
+
+/// Unroll the innermost counted loops of `func`, as described in the module documentation.
+///
+/// Loops are fully unrolled when their trip count times the number of instructions in their body
+/// is at most `budget`, and partially unrolled by `partial_factor` if that is 2 or 4. Returns
+/// whether any loop was unrolled, in which case `cfg`, `domtree` and `loop_analysis` were
+/// recomputed.
+pub fn do_unroll_loops(
+    func: &mut Function,
+    cfg: &mut ControlFlowGraph,
+    domtree: &mut DominatorTree,
+    loop_analysis: &mut LoopAnalysis,
+    budget: usize,
+    partial_factor: u32,
+) -> bool {
+    let _tt = timing::loop_unrolling();
+    let mut visited = FxHashSet::default();
+    let mut changed = false;
+    loop {
+        // Unrolling changes the control flow, so the loops are found anew after each one.
+        loop_analysis.compute(func, cfg, domtree);
+        let candidate = loop_analysis.loops().find_map(|lp| {
+            let header = loop_analysis.loop_header(lp);
+            if !visited.insert(header) || !is_innermost(loop_analysis, lp) {
+                return None;
+            }
+            CountedLoop::find(func, cfg, loop_analysis, lp)
+        });
+        let counted = match candidate {
+            Some(counted) => counted,
+            None => break,
+        };
+
+        let unrolled = if let Some(exits_at) = counted.constant_exit_iteration(func, cfg, budget) {
+            trace!(
+                "fully unrolling the loop of {} into {} iterations",
+                counted.chain[0],
+                exits_at + 1
+            );
+            counted.unroll_fully(func, cfg, exits_at);
+            true
+        } else if let Some(margin) = counted.partial_margin(partial_factor) {
+            trace!(
+                "unrolling the loop of {} by {}",
+                counted.chain[0],
+                partial_factor
+            );
+            let main_header = counted.unroll_partially(func, cfg, partial_factor, margin);
+            visited.insert(main_header);
+            true
+        } else {
+            false
+        };
+        if unrolled {
+            changed = true;
+            cfg.compute(func);
+            domtree.compute(func, cfg);
+        }
+    }
+    changed
+}
+
+fn is_innermost(loop_analysis: &LoopAnalysis, lp: Loop) -> bool {
+    loop_analysis
+        .loops()
+        .all(|other| loop_analysis.loop_parent(other) != Some(lp))
+}
+
+/// The constant defined by `value`, if any, as raw bits.
+fn constant(func: &Function, value: Value) -> Option<i64> {
+    match func.dfg.value_def(func.dfg.resolve_aliases(value)) {
+        ValueDef::Result(inst, 0) => match func.dfg.insts[inst] {
+            InstructionData::UnaryImm {
+                opcode: Opcode::Iconst,
+                imm,
+            } => Some(imm.bits()),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The bits of `value` in the low `bits` bits of a `u128`.
+fn truncate(value: i128, bits: u32) -> u128 {
+    (value as u128) & (u128::MAX >> (128 - bits))
+}
+
+/// Whether `x cc y` holds for the `bits`-bit integers `x` and `y`.
+fn holds(cc: IntCC, x: u128, y: u128, bits: u32) -> bool {
+    let signed = |v: u128| ((v << (128 - bits)) as i128) >> (128 - bits);
+    let (sx, sy) = (signed(x), signed(y));
+    match cc {
+        IntCC::Equal => x == y,
+        IntCC::NotEqual => x != y,
+        IntCC::SignedLessThan => sx < sy,
+        IntCC::SignedGreaterThanOrEqual => sx >= sy,
+        IntCC::SignedGreaterThan => sx > sy,
+        IntCC::SignedLessThanOrEqual => sx <= sy,
+        IntCC::UnsignedLessThan => x < y,
+        IntCC::UnsignedGreaterThanOrEqual => x >= y,
+        IntCC::UnsignedGreaterThan => x > y,
+        IntCC::UnsignedLessThanOrEqual => x <= y,
+    }
+}
+
+impl CountedLoop {
+    /// Recognize `lp` as a counted loop.
+    fn find(
+        func: &Function,
+        cfg: &ControlFlowGraph,
+        loop_analysis: &LoopAnalysis,
+        lp: Loop,
+    ) -> Option<Self> {
+        let dfg = &func.dfg;
+        let header = loop_analysis.loop_header(lp);
+        let num_blocks = func
+            .layout
+            .blocks()
+            .filter(|&block| loop_analysis.innermost_loop(block) == Some(lp))
+            .count();
+
+        // Follow the chain of blocks from the header.
+        let mut chain = Vec::new();
+        let mut exit = None;
+        let mut back_edge = None;
+        let mut block = header;
+        while back_edge.is_none() {
+            if chain.len() == num_blocks || (block != header && cfg.pred_iter(block).count() != 1) {
+                return None;
+            }
+            chain.push(block);
+            let terminator = func.layout.last_inst(block)?;
+            let edges = match dfg.insts[terminator] {
+                InstructionData::Jump { .. } | InstructionData::Brif { .. } => {
+                    dfg.insts[terminator].branch_destination(&dfg.jump_tables)
+                }
+                _ => return None,
+            };
+            let mut next = None;
+            for &edge in edges {
+                let target = edge.block(&dfg.value_lists);
+                if target == header {
+                    back_edge = Some(edge);
+                } else if loop_analysis.innermost_loop(target) == Some(lp) {
+                    if next.is_some() {
+                        return None;
+                    }
+                    next = Some(target);
+                } else {
+                    if exit.is_some() {
+                        return None;
+                    }
+                    exit = Some((chain.len() - 1, terminator, edge));
+                }
+            }
+            match (next, back_edge) {
+                (Some(next), None) if next != block => block = next,
+                (None, Some(_)) => {}
+                _ => return None,
+            }
+        }
+        if chain.len() != num_blocks {
+            return None;
+        }
+        let back_edge = back_edge.unwrap();
+        let (exit_index, exiting_branch, exit_edge) = exit?;
+        // Whether the loop continues when the condition of the exiting branch holds.
+        let (condition, stays_if, stay_edge) = match dfg.insts[exiting_branch] {
+            InstructionData::Brif { arg, blocks, .. } => {
+                let stays_if = blocks[0] != exit_edge;
+                (arg, stays_if, blocks[usize::from(!stays_if)])
+            }
+            _ => return None,
+        };
+        let test = match dfg.value_def(dfg.resolve_aliases(condition)) {
+            ValueDef::Result(test, 0) => test,
+            _ => return None,
+        };
+
+        // Find the induction variable among the operands of the test.
+        let in_loop = |value: Value| match dfg.value_def(dfg.resolve_aliases(value)) {
+            ValueDef::Result(inst, _) => {
+                matches!(func.layout.inst_block(inst), Some(block) if chain.contains(&block))
+            }
+            ValueDef::Param(block, _) => chain.contains(&block),
+            ValueDef::Union(..) => true,
+        };
+        let (cc, lhs, rhs) = match dfg.insts[test] {
+            InstructionData::IntCompare { cond, args, .. } => {
+                (cond, args[0], Some(Bound::Value(args[1])))
+            }
+            InstructionData::IntCompareImm { cond, arg, imm, .. } => {
+                (cond, arg, Some(Bound::Imm(imm.bits())))
+            }
+            _ => return None,
+        };
+        let header_params = dfg.block_params(header);
+        let back_args = back_edge.args_slice(&dfg.value_lists);
+        let mut operands = [(cc, lhs, rhs), (cc.reverse(), lhs, None)];
+        if let Some(Bound::Value(rhs)) = rhs {
+            operands[1] = (cc.reverse(), rhs, Some(Bound::Value(lhs)));
+        }
+        for (cc, x, bound) in operands {
+            // Legalization turns immediates into constants, which may be defined in the loop.
+            let bound = match bound {
+                Some(Bound::Value(value)) => match constant(func, value) {
+                    Some(imm) => Bound::Imm(imm),
+                    None if in_loop(value) => continue,
+                    None => Bound::Value(value),
+                },
+                Some(bound) => bound,
+                None => continue,
+            };
+            let x = dfg.resolve_aliases(x);
+            for (iv, (&param, &next)) in header_params.iter().zip(back_args).enumerate() {
+                let next = dfg.resolve_aliases(next);
+                let tests_next = if x == param {
+                    false
+                } else if x == next {
+                    true
+                } else {
+                    continue;
+                };
+                let ty = dfg.value_type(param);
+                if !ty.is_int() || ty.bits() > 64 {
+                    continue;
+                }
+                if let Some(step) = Self::step(func, param, next) {
+                    return Some(Self {
+                        chain,
+                        exit_index,
+                        exit_edge,
+                        stay_edge,
+                        test,
+                        iv,
+                        step,
+                        ty,
+                        tests_next,
+                        cc: if stays_if { cc } else { cc.inverse() },
+                        bound,
+                    });
+                }
+            }
+        }
+        None
+    }
+
+    /// The constant amount `next` adds to `param`, if it does.
+    fn step(func: &Function, param: Value, next: Value) -> Option<i128> {
+        let dfg = &func.dfg;
+        let bits = dfg.value_type(param).bits();
+        let inst = match dfg.value_def(next) {
+            ValueDef::Result(inst, 0) => inst,
+            _ => return None,
+        };
+        let is_param = |value: Value| dfg.resolve_aliases(value) == param;
+        let step = match dfg.insts[inst] {
+            InstructionData::BinaryImm64 {
+                opcode: Opcode::IaddImm,
+                arg,
+                imm,
+            } if is_param(arg) => imm.bits(),
+            InstructionData::Binary {
+                opcode: Opcode::Iadd,
+                args,
+            } => {
+                if is_param(args[0]) {
+                    constant(func, args[1])?
+                } else if is_param(args[1]) {
+                    constant(func, args[0])?
+                } else {
+                    return None;
+                }
+            }
+            InstructionData::Binary {
+                opcode: Opcode::Isub,
+                args,
+            } if is_param(args[0]) => constant(func, args[1])?.wrapping_neg(),
+            _ => return None,
+        };
+        // Interpret the step as a signed integer of the type of the induction variable.
+        let step = ((step as i128) << (128 - bits)) >> (128 - bits);
+        (step != 0).then_some(step)
+    }
+
+    /// The number of instructions of the blocks of the chain, up to the block at `end`.
+    fn size(&self, func: &Function, end: usize) -> usize {
+        self.chain[..end]
+            .iter()
+            .map(|&block| func.layout.block_insts(block).count())
+            .sum()
+    }
+
+    /// The blocks entering the loop, other than through the back edge.
+    fn entries(&self, cfg: &ControlFlowGraph) -> Vec<Inst> {
+        let latch = *self.chain.last().unwrap();
+        let mut entries: Vec<Inst> = cfg
+            .pred_iter(self.chain[0])
+            .filter(|pred| pred.block != latch)
+            .map(|pred| pred.inst)
+            .collect();
+        entries.sort();
+        entries.dedup();
+        entries
+    }
+
+    /// If the loop always starts from the same constant and compares against a constant, the
+    /// index of the iteration leaving the loop, as long as unrolling that many iterations stays
+    /// within `budget` instructions.
+    fn constant_exit_iteration(
+        &self,
+        func: &Function,
+        cfg: &ControlFlowGraph,
+        budget: usize,
+    ) -> Option<usize> {
+        let dfg = &func.dfg;
+        let header = self.chain[0];
+        let mut init = None;
+        for inst in self.entries(cfg) {
+            for edge in dfg.insts[inst].branch_destination(&dfg.jump_tables) {
+                if edge.block(&dfg.value_lists) == header {
+                    let value = constant(func, edge.args_slice(&dfg.value_lists)[self.iv])?;
+                    if matches!(init, Some(init) if init != value) {
+                        return None;
+                    }
+                    init = Some(value);
+                }
+            }
+        }
+        let bound = match self.bound {
+            Bound::Value(value) => constant(func, value)?,
+            Bound::Imm(imm) => imm,
+        };
+
+        let bits = self.ty.bits();
+        let bound = truncate(bound.into(), bits);
+        let step = truncate(self.step, bits);
+        let mut iv = truncate(init?.into(), bits);
+        let chain_size = self.size(func, self.chain.len());
+        let exit_size = self.size(func, self.exit_index + 1);
+        for iteration in 0.. {
+            if iteration * chain_size + exit_size > budget {
+                return None;
+            }
+            let next = truncate((iv + step) as i128, bits);
+            let tested = if self.tests_next { next } else { iv };
+            if !holds(self.cc, tested, bound, bits) {
+                return Some(iteration);
+            }
+            iv = next;
+        }
+        unreachable!()
+    }
+
+    /// If the loop can be unrolled by `factor`, the amount by which the induction variable must
+    /// stay away from the bound for the main loop to start an iteration.
+    fn partial_margin(&self, factor: u32) -> Option<u128> {
+        if factor != 2 && factor != 4 {
+            return None;
+        }
+        let increasing = match self.cc {
+            IntCC::SignedLessThan | IntCC::UnsignedLessThan => true,
+            IntCC::SignedGreaterThan | IntCC::UnsignedGreaterThan => false,
+            _ => return None,
+        };
+        if increasing != (self.step > 0) {
+            return None;
+        }
+        // The tests of the iterations run by the main loop compare the induction variable up to
+        // this far from its value at the start of the main loop.
+        let tested_iterations = i128::from(factor) - 1 + i128::from(self.tests_next);
+        let margin = self.step.unsigned_abs() * tested_iterations as u128;
+        let max = u128::MAX >> (128 - self.ty.bits());
+        (margin < max && margin <= i64::MAX as u128).then_some(margin)
+    }
+
+    /// Make `Inst`s entering the header of the loop enter `block` instead.
+    fn redirect_entries(&self, func: &mut Function, cfg: &ControlFlowGraph, block: Block) {
+        let header = self.chain[0];
+        for inst in self.entries(cfg) {
+            let dfg = &mut func.dfg;
+            for edge in dfg.insts[inst].branch_destination_mut(&mut dfg.jump_tables) {
+                if edge.block(&dfg.value_lists) == header {
+                    edge.set_block(block, &mut dfg.value_lists);
+                }
+            }
+        }
+    }
+
+    /// A new block with the parameters of the header, which `map` maps the parameters of the
+    /// header to, inserted before the header.
+    fn header_copy(&self, func: &mut Function, map: &mut FxHashMap<Value, Value>) -> Block {
+        let header = self.chain[0];
+        let block = func.dfg.make_block();
+        for i in 0..func.dfg.num_block_params(header) {
+            let param = func.dfg.block_params(header)[i];
+            let ty = func.dfg.value_type(param);
+            let copy = func.dfg.append_block_param(block, ty);
+            map.insert(param, copy);
+        }
+        func.layout.insert_block(block, header);
+        block
+    }
+
+    /// Replace the loop with `exits_at + 1` copies of its body, the last one leaving the loop.
+    fn unroll_fully(&self, func: &mut Function, cfg: &ControlFlowGraph, exits_at: usize) {
+        let mut map = FxHashMap::default();
+        let entry = self.header_copy(func, &mut map);
+        if func.layout.is_cold(self.chain[0]) {
+            func.layout.set_cold(entry);
+        }
+        self.redirect_entries(func, cfg, entry);
+
+        let mut copier = Copier {
+            func,
+            map,
+            current: entry,
+        };
+        for iteration in 0..=exits_at {
+            for (index, &block) in self.chain.iter().enumerate() {
+                copier.copy_insts(block);
+                if iteration == exits_at && index == self.exit_index {
+                    copier.jump(self.exit_edge);
+                    break;
+                }
+                copier.follow(self.staying_edge(copier.func, index));
+            }
+        }
+        let Copier { func, map, .. } = copier;
+
+        // The values of the last iteration are the ones visible after the loop.
+        let chain = self.chain.clone();
+        let mut blocks = func.layout.blocks().collect::<Vec<_>>();
+        blocks.retain(|block| !chain.contains(block));
+        for block in blocks {
+            let mut next = func.layout.first_inst(block);
+            while let Some(inst) = next {
+                func.dfg.map_inst_values(inst, |dfg, value| {
+                    let value = dfg.resolve_aliases(value);
+                    map.get(&value).copied().unwrap_or(value)
+                });
+                next = func.layout.next_inst(inst);
+            }
+        }
+        for block in chain {
+            while let Some(inst) = func.layout.first_inst(block) {
+                func.layout.remove_inst(inst);
+            }
+            func.layout.remove_block(block);
+        }
+    }
+
+    /// Put a main loop running `factor` iterations at a time in front of the loop, and return
+    /// its header.
+    fn unroll_partially(
+        &self,
+        func: &mut Function,
+        cfg: &ControlFlowGraph,
+        factor: u32,
+        margin: u128,
+    ) -> Block {
+        let header = self.chain[0];
+        let mut map = FxHashMap::default();
+        let main_header = self.header_copy(func, &mut map);
+        self.redirect_entries(func, cfg, main_header);
+        let main_body = func.dfg.make_block();
+        func.layout.insert_block(main_body, header);
+
+        // Start an iteration of the main loop only if the loop would run `factor` more
+        // iterations: `iv cc bound`, and the distance between `iv` and `bound`, which is then
+        // positive, is greater than `margin`.
+        let iv = func.dfg.block_params(main_header)[self.iv];
+        let header_args = func.dfg.block_params(main_header).to_vec();
+        let srcloc = func.srcloc(self.test);
+        let mut pos = FuncCursor::new(func)
+            .at_bottom(main_header)
+            .with_srcloc(srcloc);
+        let bound = match self.bound {
+            Bound::Value(value) => value,
+            Bound::Imm(imm) => pos.ins().iconst(self.ty, imm),
+        };
+        let continues = pos.ins().icmp(self.cc, iv, bound);
+        let distance = if self.step > 0 {
+            pos.ins().isub(bound, iv)
+        } else {
+            pos.ins().isub(iv, bound)
+        };
+        // This runs after legalization, which expands instructions with immediates.
+        let margin = pos.ins().iconst(self.ty, margin as i64);
+        let far = pos.ins().icmp(IntCC::UnsignedGreaterThan, distance, margin);
+        let start = pos.ins().band(continues, far);
+        pos.ins().brif(start, main_body, &[], header, &header_args);
+
+        let mut copier = Copier {
+            func,
+            map,
+            current: main_body,
+        };
+        for iteration in 0..factor {
+            for (index, &block) in self.chain.iter().enumerate() {
+                copier.copy_insts(block);
+                let edge = self.staying_edge(copier.func, index);
+                if iteration + 1 == factor && index + 1 == self.chain.len() {
+                    let args = copier.args(edge);
+                    let current = copier.current;
+                    FuncCursor::new(copier.func)
+                        .at_bottom(current)
+                        .ins()
+                        .jump(main_header, &args);
+                } else {
+                    copier.follow(edge);
+                }
+            }
+        }
+        main_header
+    }
+
+    /// The edge continuing the loop from the block at `index` of the chain.
+    fn staying_edge(&self, func: &Function, index: usize) -> BlockCall {
+        if index == self.exit_index {
+            return self.stay_edge;
+        }
+        let dfg = &func.dfg;
+        let terminator = func.layout.last_inst(self.chain[index]).unwrap();
+        dfg.insts[terminator].branch_destination(&dfg.jump_tables)[0]
+    }
+}
+
+/// Copies the bodies of the blocks of a loop into straight-line code.
+struct Copier<'a> {
+    func: &'a mut Function,
+    /// The copy of each value of the loop in the iteration being copied.
+    map: FxHashMap<Value, Value>,
+    /// The block the copies are appended to.
+    current: Block,
+}
+
+impl Copier<'_> {
+    fn mapped(&self, value: Value) -> Value {
+        let value = self.func.dfg.resolve_aliases(value);
+        self.map.get(&value).copied().unwrap_or(value)
+    }
+
+    fn args(&self, edge: BlockCall) -> Vec<Value> {
+        edge.args_slice(&self.func.dfg.value_lists)
+            .iter()
+            .map(|&arg| self.mapped(arg))
+            .collect()
+    }
+
+    /// Append copies of the instructions of `block`, except for its terminator, to the current
+    /// block, starting a new block if `block` isn't as cold as the current one.
+    fn copy_insts(&mut self, block: Block) {
+        let cold = self.func.layout.is_cold(block);
+        if cold != self.func.layout.is_cold(self.current) {
+            let next = self.func.dfg.make_block();
+            if cold {
+                self.func.layout.set_cold(next);
+            }
+            self.func.layout.insert_block_after(next, self.current);
+            FuncCursor::new(self.func)
+                .at_bottom(self.current)
+                .ins()
+                .jump(next, &[]);
+            self.current = next;
+        }
+
+        let terminator = self.func.layout.last_inst(block).unwrap();
+        let mut next = self.func.layout.first_inst(block);
+        while let Some(inst) = next {
+            if inst == terminator {
+                break;
+            }
+            let copy = self.func.dfg.clone_inst(inst);
+            let map = &self.map;
+            self.func.dfg.map_inst_values(copy, |dfg, value| {
+                let value = dfg.resolve_aliases(value);
+                map.get(&value).copied().unwrap_or(value)
+            });
+            for i in 0..self.func.dfg.inst_results(inst).len() {
+                let result = self.func.dfg.inst_results(inst)[i];
+                let copied = self.func.dfg.inst_results(copy)[i];
+                self.map.insert(result, copied);
+            }
+            self.func.layout.append_inst(copy, self.current);
+            let srcloc = self.func.srcloc(inst);
+            self.func.set_srcloc(copy, srcloc);
+            next = self.func.layout.next_inst(inst);
+        }
+    }
+
+    /// Continue the straight-line code into the target of `edge`, whose parameters take the
+    /// copies of the arguments of `edge`.
+    fn follow(&mut self, edge: BlockCall) {
+        let args = self.args(edge);
+        let target = edge.block(&self.func.dfg.value_lists);
+        for (i, arg) in args.into_iter().enumerate() {
+            let param = self.func.dfg.block_params(target)[i];
+            self.map.insert(param, arg);
+        }
+    }
+
+    /// End the straight-line code with a jump along `edge`.
+    fn jump(&mut self, edge: BlockCall) {
+        let args = self.args(edge);
+        let target = edge.block(&self.func.dfg.value_lists);
+        FuncCursor::new(self.func)
+            .at_bottom(self.current)
+            .ins()
+            .jump(target, &args);
+    }
+}
@@ -109,16 +109,16 @@ use crate::ir::{ArgumentExtension, ArgumentPurpose, DynamicStackSlot, Signature,
 use crate::isa::TargetIsa;
 use crate::settings;
 use crate::settings::ProbestackStrategy;
+use crate::HashMap;
 use crate::{ir, isa};
 use crate::{machinst::*, trace};
 use crate::{CodegenError, CodegenResult};
 use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::marker::PhantomData;
+use core::mem;
 use regalloc2::{PReg, PRegSet};
 use smallvec::{smallvec, SmallVec};
-use std::collections::HashMap;
-use std::convert::TryFrom;
-use std::marker::PhantomData;
-use std::mem;
 
 /// A small vector of instructions (with some reasonable size); appropriate for
 /// a small fixed sequence implementing one operation.
@@ -986,7 +986,7 @@ impl SigSet {
 
 // NB: we do _not_ implement `IndexMut` because these signatures are
 // deduplicated and shared!
-impl std::ops::Index<Sig> for SigSet {
+impl core::ops::Index<Sig> for SigSet {
     type Output = SigData;
 
     fn index(&self, sig: Sig) -> &Self::Output {
@@ -1794,7 +1794,7 @@ impl<M: ABIMachineSpec> Callee<M> {
             // locations defined by the ABI.
             Some(M::gen_args(
                 &self.isa_flags,
-                std::mem::take(&mut self.reg_args),
+                core::mem::take(&mut self.reg_args),
             ))
         } else {
             None
@@ -1836,7 +1836,7 @@ impl<M: ABIMachineSpec> Callee<M> {
         let map_size = (virtual_sp_offset + nominal_sp_to_fp) as u32;
         let bytes = M::word_bytes();
         let map_words = (map_size + bytes - 1) / bytes;
-        let mut bits = std::iter::repeat(false)
+        let mut bits = core::iter::repeat(false)
             .take(map_words as usize)
             .collect::<Vec<bool>>();
 
@@ -2103,6 +2103,7 @@ impl<M: ABIMachineSpec> CallSite<M> {
         sig_ref: ir::SigRef,
         extname: &ir::ExternalName,
         dist: RelocDistance,
+        opcode: ir::Opcode,
         caller_conv: isa::CallConv,
         flags: settings::Flags,
     ) -> CodegenResult<CallSite<M>> {
@@ -2114,7 +2115,7 @@ impl<M: ABIMachineSpec> CallSite<M> {
             defs: smallvec![],
             clobbers,
             dest: CallDest::ExtName(extname.clone(), dist),
-            opcode: ir::Opcode::Call,
+            opcode,
             caller_conv,
             flags,
             _mach: PhantomData,
@@ -2602,6 +2603,6 @@ mod tests {
     fn sig_data_size() {
         // The size of `SigData` is performance sensitive, so make sure
         // we don't regress it unintentionally.
-        assert_eq!(std::mem::size_of::<SigData>(), 24);
+        assert_eq!(core::mem::size_of::<SigData>(), 24);
     }
 }
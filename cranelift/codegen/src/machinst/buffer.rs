@@ -141,20 +141,23 @@
 //! semantics below (grep for "Preserves execution semantics").
 
 use crate::binemit::{Addend, CodeOffset, Reloc, StackMap};
-use crate::ir::{ExternalName, Opcode, RelSourceLoc, SourceLoc, TrapCode};
+use crate::budget::BudgetTracker;
+use crate::ir::{ExternalName, Inst, Opcode, RelSourceLoc, SourceLoc, TrapCode};
+use crate::isa::unwind::table::FrameState;
 use crate::isa::unwind::UnwindInst;
 use crate::machinst::{
     BlockIndex, MachInstLabelUse, TextSectionBuilder, VCodeConstant, VCodeConstants, VCodeInst,
 };
+use crate::result::CodegenResult;
 use crate::timing;
 use crate::trace;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::mem;
 use cranelift_control::ControlPlane;
 use cranelift_entity::{entity_impl, PrimaryMap};
 use smallvec::SmallVec;
-use std::convert::TryFrom;
-use std::mem;
-use std::string::String;
-use std::vec::Vec;
 
 #[cfg(feature = "enable-serde")]
 use serde::{Deserialize, Serialize};
@@ -208,12 +211,21 @@ pub struct MachBuffer<I: VCodeInst> {
     traps: SmallVec<[MachTrap; 16]>,
     /// Any call site records referring to this code.
     call_sites: SmallVec<[MachCallSite; 16]>,
+    /// The patch points of `patchable_jump`s, with the labels of their
+    /// destinations, which are resolved once all code is emitted.
+    patchable_jumps: SmallVec<[(CodeOffset, [MachLabel; 2]); 4]>,
     /// Any source location mappings referring to this code.
     srclocs: SmallVec<[MachSrcLoc<Stencil>; 64]>,
     /// Any stack maps referring to this code.
     stack_maps: SmallVec<[MachStackMap; 8]>,
     /// Any unwind info at a given location.
     unwind_info: SmallVec<[(CodeOffset, UnwindInst); 8]>,
+    /// The state of the frame from each offset on, as set with `set_frame_state()`.
+    frame_states: SmallVec<[(CodeOffset, FrameState); 8]>,
+    /// The `(start, end)` offsets of each instruction emitted so far, with the
+    /// IR instruction it was lowered from, if requested with
+    /// `collect_inst_offsets()`.
+    inst_offsets: Option<Vec<(CodeOffset, CodeOffset, Option<Inst>)>>,
     /// The current source location in progress (after `start_srcloc()` and
     /// before `end_srcloc()`).  This is a (start_offset, src_loc) tuple.
     cur_srcloc: Option<(CodeOffset, RelSourceLoc)>,
@@ -276,6 +288,40 @@ pub struct MachBuffer<I: VCodeInst> {
     used_constants: SmallVec<[(VCodeConstant, CodeOffset); 4]>,
 }
 
+/// The allocations of a [MachBuffer] which are only used during emission, kept
+/// empty between compilations by a
+/// [`CompilationSession`](crate::CompilationSession).
+///
+/// The code and metadata of a buffer are moved into its [MachBufferFinalized],
+/// so they can't be reused.
+pub(crate) struct MachBufferScratch<I: VCodeInst> {
+    label_offsets: SmallVec<[CodeOffset; 16]>,
+    label_aliases: SmallVec<[MachLabel; 16]>,
+    pending_constants: SmallVec<[VCodeConstant; 16]>,
+    pending_traps: SmallVec<[MachLabelTrap; 16]>,
+    fixup_records: SmallVec<[MachLabelFixup<I>; 16]>,
+    latest_branches: SmallVec<[MachBranch; 4]>,
+    labels_at_tail: SmallVec<[MachLabel; 4]>,
+    constants: PrimaryMap<VCodeConstant, MachBufferConstant>,
+    used_constants: SmallVec<[(VCodeConstant, CodeOffset); 4]>,
+}
+
+impl<I: VCodeInst> Default for MachBufferScratch<I> {
+    fn default() -> Self {
+        Self {
+            label_offsets: SmallVec::new(),
+            label_aliases: SmallVec::new(),
+            pending_constants: SmallVec::new(),
+            pending_traps: SmallVec::new(),
+            fixup_records: SmallVec::new(),
+            latest_branches: SmallVec::new(),
+            labels_at_tail: SmallVec::new(),
+            constants: Default::default(),
+            used_constants: Default::default(),
+        }
+    }
+}
+
 impl MachBufferFinalized<Stencil> {
     /// Get a finalized machine buffer by applying the function's base source location.
     pub fn apply_base_srcloc(self, base_srcloc: SourceLoc) -> MachBufferFinalized<Final> {
@@ -284,6 +330,7 @@ impl MachBufferFinalized<Stencil> {
             relocs: self.relocs,
             traps: self.traps,
             call_sites: self.call_sites,
+            patchable_jumps: self.patchable_jumps,
             srclocs: self
                 .srclocs
                 .into_iter()
@@ -291,6 +338,8 @@ impl MachBufferFinalized<Stencil> {
                 .collect(),
             stack_maps: self.stack_maps,
             unwind_info: self.unwind_info,
+            frame_states: self.frame_states,
+            inst_offsets: self.inst_offsets,
             alignment: self.alignment,
         }
     }
@@ -311,12 +360,19 @@ pub struct MachBufferFinalized<T: CompilePhase> {
     pub(crate) traps: SmallVec<[MachTrap; 16]>,
     /// Any call site records referring to this code.
     pub(crate) call_sites: SmallVec<[MachCallSite; 16]>,
+    /// Any patchable jump records referring to this code.
+    pub(crate) patchable_jumps: SmallVec<[MachPatchableJump; 4]>,
     /// Any source location mappings referring to this code.
     pub(crate) srclocs: SmallVec<[T::MachSrcLocType; 64]>,
     /// Any stack maps referring to this code.
     pub(crate) stack_maps: SmallVec<[MachStackMap; 8]>,
     /// Any unwind info at a given location.
     pub unwind_info: SmallVec<[(CodeOffset, UnwindInst); 8]>,
+    /// The state of the frame from each offset on.
+    pub(crate) frame_states: SmallVec<[(CodeOffset, FrameState); 8]>,
+    /// The offset and length of each instruction, and the IR instruction it
+    /// was lowered from, if requested.
+    pub(crate) inst_offsets: Vec<(CodeOffset, u8, Option<Inst>)>,
     /// The requireed alignment of this buffer
     pub alignment: u32,
 }
@@ -375,27 +431,46 @@ impl<I: VCodeInst> MachBuffer<I> {
     /// Create a new section, known to start at `start_offset` and with a size limited to
     /// `length_limit`.
     pub fn new() -> MachBuffer<I> {
+        Self::with_scratch(MachBufferScratch::default())
+    }
+
+    /// Create a new buffer, reusing the allocations in `scratch`.
+    pub(crate) fn with_scratch(scratch: MachBufferScratch<I>) -> MachBuffer<I> {
+        let MachBufferScratch {
+            label_offsets,
+            label_aliases,
+            pending_constants,
+            pending_traps,
+            fixup_records,
+            latest_branches,
+            labels_at_tail,
+            constants,
+            used_constants,
+        } = scratch;
         MachBuffer {
             data: SmallVec::new(),
             relocs: SmallVec::new(),
             traps: SmallVec::new(),
             call_sites: SmallVec::new(),
+            patchable_jumps: SmallVec::new(),
             srclocs: SmallVec::new(),
             stack_maps: SmallVec::new(),
             unwind_info: SmallVec::new(),
+            frame_states: SmallVec::new(),
+            inst_offsets: None,
             cur_srcloc: None,
-            label_offsets: SmallVec::new(),
-            label_aliases: SmallVec::new(),
-            pending_constants: SmallVec::new(),
-            pending_traps: SmallVec::new(),
-            fixup_records: SmallVec::new(),
+            label_offsets,
+            label_aliases,
+            pending_constants,
+            pending_traps,
+            fixup_records,
             island_deadline: UNKNOWN_LABEL_OFFSET,
             island_worst_case_size: 0,
-            latest_branches: SmallVec::new(),
-            labels_at_tail: SmallVec::new(),
+            latest_branches,
+            labels_at_tail,
             labels_at_tail_off: 0,
-            constants: Default::default(),
-            used_constants: Default::default(),
+            constants,
+            used_constants,
         }
     }
 
@@ -740,6 +815,30 @@ impl<I: VCodeInst> MachBuffer<I> {
             }
             self.srclocs.pop();
         }
+        if let Some(inst_offsets) = &mut self.inst_offsets {
+            while let Some(last) = inst_offsets.last_mut() {
+                if last.1 <= b.start {
+                    break;
+                }
+                if last.0 < b.start {
+                    last.1 = b.start;
+                    break;
+                }
+                inst_offsets.pop();
+            }
+        }
+        // A frame state set after the branch now holds from its start.
+        let mut frame_state = None;
+        while let Some(&(offset, state)) = self.frame_states.last() {
+            if offset <= b.start {
+                break;
+            }
+            frame_state.get_or_insert(state);
+            self.frame_states.pop();
+        }
+        if let Some(state) = frame_state {
+            self.set_frame_state(state);
+        }
         // State:
         //    [PRE CODE]
         //  cur_off, Offset b.start, b.labels_at_this_branch:
@@ -1158,17 +1257,39 @@ impl<I: VCodeInst> MachBuffer<I> {
     /// actually reach a deadline. It's not necessarily a problem to do so
     /// otherwise but it may result in unnecessary work during emission.
     pub fn emit_island(&mut self, distance: CodeOffset, ctrl_plane: &mut ControlPlane) {
-        self.emit_island_maybe_forced(false, distance, ctrl_plane);
+        self.emit_island_maybe_forced(false, distance, &mut BudgetTracker::default(), ctrl_plane)
+            .expect("an unlimited budget is never exceeded");
+    }
+
+    /// Same as `emit_island`, but charges each trap, constant and fixup
+    /// processed to `budget`, and fails once it is exceeded.
+    pub(crate) fn emit_island_within(
+        &mut self,
+        distance: CodeOffset,
+        budget: &mut BudgetTracker,
+        ctrl_plane: &mut ControlPlane,
+    ) -> CodegenResult<()> {
+        self.emit_island_maybe_forced(false, distance, budget, ctrl_plane)
     }
 
-    /// Same as `emit_island`, but an internal API with a `force_veneers`
-    /// argument to force all veneers to always get emitted for debugging.
+    /// Same as `emit_island_within`, but an internal API with a
+    /// `force_veneers` argument to force all veneers to always get emitted for
+    /// debugging.
     fn emit_island_maybe_forced(
         &mut self,
         force_veneers: bool,
         distance: CodeOffset,
+        budget: &mut BudgetTracker,
         ctrl_plane: &mut ControlPlane,
-    ) {
+    ) -> CodegenResult<()> {
+        let _tt = timing::branch_relaxation();
+        trace_event!(
+            offset = self.cur_offset(),
+            fixups = self.fixup_records.len(),
+            force_veneers,
+            "emitting island"
+        );
+
         // We're going to purge fixups, so no latest-branch editing can happen
         // anymore.
         self.latest_branches.clear();
@@ -1205,6 +1326,7 @@ impl<I: VCodeInst> MachBuffer<I> {
             loc,
         } in mem::take(&mut self.pending_traps)
         {
+            budget.charge(timing::Pass::branch_relaxation, 1)?;
             // If this trap has source information associated with it then
             // emit this information for the trap instruction going out now too.
             if let Some(loc) = loc {
@@ -1224,6 +1346,7 @@ impl<I: VCodeInst> MachBuffer<I> {
         }
 
         for constant in mem::take(&mut self.pending_constants) {
+            budget.charge(timing::Pass::branch_relaxation, 1)?;
             let MachBufferConstant { align, size, .. } = self.constants[constant];
             let label = self.constants[constant].upcoming_label.take().unwrap();
             self.align_to(align);
@@ -1234,6 +1357,7 @@ impl<I: VCodeInst> MachBuffer<I> {
 
         for fixup in mem::take(&mut self.fixup_records) {
             trace!("emit_island: fixup {:?}", fixup);
+            budget.charge(timing::Pass::branch_relaxation, 1)?;
             let MachLabelFixup {
                 label,
                 offset,
@@ -1306,6 +1430,7 @@ impl<I: VCodeInst> MachBuffer<I> {
         if let Some(loc) = cur_loc {
             self.start_srcloc(loc);
         }
+        Ok(())
     }
 
     /// Emits a "veneer" the `kind` code at `offset` to jump to `label`.
@@ -1355,8 +1480,9 @@ impl<I: VCodeInst> MachBuffer<I> {
     fn finish_emission_maybe_forcing_veneers(
         &mut self,
         force_veneers: bool,
+        budget: &mut BudgetTracker,
         ctrl_plane: &mut ControlPlane,
-    ) {
+    ) -> CodegenResult<()> {
         while !self.pending_constants.is_empty()
             || !self.pending_traps.is_empty()
             || !self.fixup_records.is_empty()
@@ -1364,44 +1490,110 @@ impl<I: VCodeInst> MachBuffer<I> {
             // `emit_island()` will emit any pending veneers and constants, and
             // as a side-effect, will also take care of any fixups with resolved
             // labels eagerly.
-            self.emit_island_maybe_forced(force_veneers, u32::MAX, ctrl_plane);
+            self.emit_island_maybe_forced(force_veneers, u32::MAX, budget, ctrl_plane)?;
         }
 
         // Ensure that all labels have been fixed up after the last island is emitted. This is a
         // full (release-mode) assert because an unresolved label means the emitted code is
         // incorrect.
         assert!(self.fixup_records.is_empty());
+        Ok(())
     }
 
     /// Finish any deferred emissions and/or fixups.
     pub fn finish(
-        mut self,
+        self,
         constants: &VCodeConstants,
         ctrl_plane: &mut ControlPlane,
     ) -> MachBufferFinalized<Stencil> {
+        self.finish_with_scratch(constants, &mut BudgetTracker::default(), ctrl_plane)
+            .expect("an unlimited budget is never exceeded")
+            .0
+    }
+
+    /// Finish any deferred emissions and/or fixups, charging the islands they
+    /// need to `budget`, and return the allocations of this buffer which can be
+    /// reused by the next one along with the finalized buffer.
+    pub(crate) fn finish_with_scratch(
+        mut self,
+        constants: &VCodeConstants,
+        budget: &mut BudgetTracker,
+        ctrl_plane: &mut ControlPlane,
+    ) -> CodegenResult<(MachBufferFinalized<Stencil>, MachBufferScratch<I>)> {
         let _tt = timing::vcode_emit_finish();
 
         // Do any optimizations on branches at tail of buffer, as if we
         // had bound one last label.
         self.optimize_branches(ctrl_plane);
 
-        self.finish_emission_maybe_forcing_veneers(false, ctrl_plane);
+        self.finish_emission_maybe_forcing_veneers(false, budget, ctrl_plane)?;
 
         let alignment = self.finish_constants(constants);
 
+        // All labels are bound by now, including the ones of the blocks only
+        // reachable by patching a jump.
+        let patchable_jumps = self
+            .patchable_jumps
+            .iter()
+            .map(|&(offset, [initial, alternate])| MachPatchableJump {
+                offset,
+                destinations: [
+                    self.resolve_label_offset(initial),
+                    self.resolve_label_offset(alternate),
+                ],
+            })
+            .collect();
+
         let mut srclocs = self.srclocs;
         srclocs.sort_by_key(|entry| entry.start);
 
-        MachBufferFinalized {
+        // Instructions longer than `u8::MAX` bytes, such as pseudo-instructions
+        // with inline jump tables, are split into several entries.
+        let mut inst_offsets = Vec::new();
+        for (start, end, inst) in self.inst_offsets.unwrap_or_default() {
+            let mut offset = start;
+            while offset < end {
+                let len = (end - offset).min(u32::from(u8::MAX));
+                inst_offsets.push((offset, len as u8, inst));
+                offset += len;
+            }
+        }
+        inst_offsets.sort_by_key(|&(offset, _, _)| offset);
+
+        let finalized = MachBufferFinalized {
             data: self.data,
             relocs: self.relocs,
             traps: self.traps,
             call_sites: self.call_sites,
+            patchable_jumps,
             srclocs,
             stack_maps: self.stack_maps,
             unwind_info: self.unwind_info,
+            frame_states: self.frame_states,
+            inst_offsets,
             alignment,
-        }
+        };
+        let mut scratch = MachBufferScratch {
+            label_offsets: self.label_offsets,
+            label_aliases: self.label_aliases,
+            pending_constants: self.pending_constants,
+            pending_traps: self.pending_traps,
+            fixup_records: self.fixup_records,
+            latest_branches: self.latest_branches,
+            labels_at_tail: self.labels_at_tail,
+            constants: self.constants,
+            used_constants: self.used_constants,
+        };
+        scratch.label_offsets.clear();
+        scratch.label_aliases.clear();
+        scratch.pending_constants.clear();
+        scratch.pending_traps.clear();
+        scratch.fixup_records.clear();
+        scratch.latest_branches.clear();
+        scratch.labels_at_tail.clear();
+        scratch.constants.clear();
+        scratch.used_constants.clear();
+        Ok((finalized, scratch))
     }
 
     /// Add an external relocation at the current offset.
@@ -1468,11 +1660,33 @@ impl<I: VCodeInst> MachBuffer<I> {
         });
     }
 
+    /// Add a record of a `patchable_jump` whose patch point is at the current
+    /// offset, and which can be patched to jump to either of `destinations`.
+    pub fn add_patchable_jump(&mut self, destinations: [MachLabel; 2]) {
+        debug_assert_eq!(self.cur_offset() % 4, 0, "misaligned patch point");
+        self.patchable_jumps.push((self.cur_offset(), destinations));
+    }
+
     /// Add an unwind record at the current offset.
     pub fn add_unwind(&mut self, unwind: UnwindInst) {
         self.unwind_info.push((self.cur_offset(), unwind));
     }
 
+    /// Set the state of the frame from the current offset on.
+    pub fn set_frame_state(&mut self, state: FrameState) {
+        let offset = self.cur_offset();
+        while let Some(&(last_offset, _)) = self.frame_states.last() {
+            if last_offset < offset {
+                break;
+            }
+            // Nothing was emitted in that state.
+            self.frame_states.pop();
+        }
+        if self.frame_states.last().map(|&(_, last)| last) != Some(state) {
+            self.frame_states.push((offset, state));
+        }
+    }
+
     /// Set the `SourceLoc` for code from this offset until the offset at the
     /// next call to `end_srcloc()`.
     pub fn start_srcloc(&mut self, loc: RelSourceLoc) {
@@ -1494,6 +1708,25 @@ impl<I: VCodeInst> MachBuffer<I> {
         }
     }
 
+    /// Record the offset of each instruction from now on, as passed to
+    /// `add_inst_offset()`.
+    pub fn collect_inst_offsets(&mut self) {
+        self.inst_offsets = Some(Vec::new());
+    }
+
+    /// Record that the code from `start` to the current offset is the
+    /// encoding of a single instruction, lowered from the IR instruction
+    /// `inst` if any. Does nothing unless `collect_inst_offsets()` was called.
+    pub fn add_inst_offset(&mut self, start: CodeOffset, inst: Option<Inst>) {
+        let end = self.cur_offset();
+        debug_assert!(end >= start);
+        match &mut self.inst_offsets {
+            // Skip zero-length instructions.
+            Some(inst_offsets) if end > start => inst_offsets.push((start, end, inst)),
+            _ => {}
+        }
+    }
+
     /// Add stack map metadata for this program point: a set of stack offsets
     /// (from SP upward) that contain live references.
     ///
@@ -1527,6 +1760,13 @@ impl<T: CompilePhase> MachBufferFinalized<T> {
         &self.srclocs[..]
     }
 
+    /// Get the offset and length of each instruction, in sorted-by-offset
+    /// order, with the IR instruction it was lowered from, if any. This is
+    /// empty unless requested with [`MachBuffer::collect_inst_offsets`].
+    pub fn inst_offsets(&self) -> &[(CodeOffset, u8, Option<Inst>)] {
+        &self.inst_offsets[..]
+    }
+
     /// Get the total required size for the code.
     pub fn total_size(&self) -> CodeOffset {
         self.data.len() as CodeOffset
@@ -1535,7 +1775,7 @@ impl<T: CompilePhase> MachBufferFinalized<T> {
     /// Return the code in this mach buffer as a hex string for testing purposes.
     pub fn stringify_code_bytes(&self) -> String {
         // This is pretty lame, but whatever ..
-        use std::fmt::Write;
+        use core::fmt::Write;
         let mut s = String::with_capacity(self.data.len() * 2);
         for b in &self.data {
             write!(&mut s, "{:02X}", b).unwrap();
@@ -1578,6 +1818,25 @@ impl<T: CompilePhase> MachBufferFinalized<T> {
     pub fn call_sites(&self) -> &[MachCallSite] {
         &self.call_sites[..]
     }
+
+    /// Get the patch points of this code: the offsets of the naturally aligned 32-bit words
+    /// which encode the targets of `patchable_call`s and the destinations of `patchable_jump`s,
+    /// *relative to the containing section*.
+    ///
+    /// On every architecture supporting `patchable_call` this word ends at the call's return
+    /// address.
+    pub fn patch_points(&self) -> impl Iterator<Item = CodeOffset> + '_ {
+        self.call_sites
+            .iter()
+            .filter(|site| site.opcode == Opcode::PatchableCall)
+            .map(|site| site.ret_addr - 4)
+            .chain(self.patchable_jumps.iter().map(|jump| jump.offset))
+    }
+
+    /// Get the list of patchable jumps for this code.
+    pub fn patchable_jumps(&self) -> &[MachPatchableJump] {
+        &self.patchable_jumps[..]
+    }
 }
 
 /// Metadata about a constant.
@@ -1659,6 +1918,17 @@ pub struct MachCallSite {
     pub opcode: Opcode,
 }
 
+/// A patchable jump record resulting from a compilation.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "enable-serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MachPatchableJump {
+    /// The offset of the jump's patch point, *relative to the containing section*.
+    pub offset: CodeOffset,
+    /// The offsets of the initial and the alternate destination of the jump,
+    /// *relative to the containing section*.
+    pub destinations: [CodeOffset; 2],
+}
+
 /// A source-location mapping resulting from a compilation.
 #[derive(PartialEq, Debug, Clone)]
 #[cfg_attr(feature = "enable-serde", derive(serde::Serialize, serde::Deserialize))]
@@ -1764,7 +2034,13 @@ impl<I: VCodeInst> TextSectionBuilder for MachTextSectionBuilder<I> {
         let size = func.len() as u32;
         if self.force_veneers || self.buf.island_needed(size) {
             self.buf
-                .emit_island_maybe_forced(self.force_veneers, size, ctrl_plane);
+                .emit_island_maybe_forced(
+                    self.force_veneers,
+                    size,
+                    &mut BudgetTracker::default(),
+                    ctrl_plane,
+                )
+                .expect("an unlimited budget is never exceeded");
         }
 
         self.buf.align_to(align);
@@ -1802,7 +2078,12 @@ impl<I: VCodeInst> TextSectionBuilder for MachTextSectionBuilder<I> {
 
         // Finish up any veneers, if necessary.
         self.buf
-            .finish_emission_maybe_forcing_veneers(self.force_veneers, ctrl_plane);
+            .finish_emission_maybe_forcing_veneers(
+                self.force_veneers,
+                &mut BudgetTracker::default(),
+                ctrl_plane,
+            )
+            .expect("an unlimited budget is never exceeded");
 
         // We don't need the data any more, so return it to the caller.
         mem::take(&mut self.buf.data).into_vec()
@@ -1820,8 +2101,8 @@ mod test {
     use crate::isa::aarch64::inst::{BranchTarget, CondBrKind, EmitInfo, Inst};
     use crate::machinst::{MachInstEmit, MachInstEmitState};
     use crate::settings;
-    use std::default::Default;
-    use std::vec::Vec;
+    use alloc::vec::Vec;
+    use core::default::Default;
 
     fn label(n: u32) -> MachLabel {
         MachLabel::from_block(BlockIndex::new(n as usize))
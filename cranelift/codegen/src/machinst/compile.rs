@@ -4,13 +4,14 @@ use crate::dominator_tree::DominatorTree;
 use crate::ir::Function;
 use crate::isa::TargetIsa;
 use crate::machinst::*;
-use crate::timing;
+use crate::session::BackendScratch;
+use crate::timing::{self, Pass};
 use crate::trace;
 
 use regalloc2::RegallocOptions;
 
 /// Compile the given function down to VCode with allocated registers, ready
-/// for binary emission.
+/// for binary emission, reusing the allocations in `scratch`.
 pub fn compile<B: LowerBackend + TargetIsa>(
     f: &Function,
     domtree: &DominatorTree,
@@ -18,15 +19,20 @@ pub fn compile<B: LowerBackend + TargetIsa>(
     abi: Callee<<<B as LowerBackend>::MInst as MachInst>::ABIMachineSpec>,
     emit_info: <B::MInst as MachInstEmit>::Info,
     sigs: SigSet,
+    scratch: &mut BackendScratch<B::MInst>,
     ctrl_plane: &mut ControlPlane,
 ) -> CodegenResult<(VCode<B::MInst>, regalloc2::Output)> {
     let machine_env = b.machine_env();
+    scratch
+        .budget
+        .start_pass(Pass::vcode_lower, f.dfg.num_insts())?;
 
     // Compute lowered block order.
     let block_order = BlockLoweringOrder::new(f, domtree, ctrl_plane);
 
     // Build the lowering context.
-    let lower = crate::machinst::Lower::new(f, machine_env, abi, emit_info, block_order, sigs)?;
+    let lower =
+        crate::machinst::Lower::new(f, machine_env, abi, emit_info, block_order, sigs, scratch)?;
 
     // Lower the IR.
     let vcode = {
@@ -37,7 +43,7 @@ pub fn compile<B: LowerBackend + TargetIsa>(
         log::debug!("Number of CLIF blocks to lower: {}", f.dfg.num_blocks());
 
         let _tt = timing::vcode_lower();
-        lower.lower(b, ctrl_plane)?
+        lower.lower(b, scratch, ctrl_plane)?
     };
 
     log::debug!(
@@ -47,7 +53,12 @@ pub fn compile<B: LowerBackend + TargetIsa>(
     log::debug!("Number of lowered vcode blocks: {}", vcode.num_blocks());
     trace!("vcode from lowering: \n{:?}", vcode);
 
-    // Perform register allocation.
+    // Perform register allocation. The register allocator can't be interrupted, so it is charged
+    // one unit per instruction up front, and the iterations of its superlinear loops, which it
+    // reports in its statistics, once it returns.
+    scratch
+        .budget
+        .start_pass(Pass::regalloc, vcode.num_insts())?;
     let regalloc_result = {
         let _tt = timing::regalloc();
         let mut options = RegallocOptions::default();
@@ -69,9 +80,30 @@ pub fn compile<B: LowerBackend + TargetIsa>(
             })
             .expect("register allocation")
     };
+    trace_event!(
+        livein_iterations = regalloc_result.stats.livein_iterations,
+        bundles = regalloc_result.stats.process_bundle_count,
+        edits = regalloc_result.edits.len(),
+        spillslots = regalloc_result.num_spillslots,
+        "register allocation done"
+    );
+    let stats = &regalloc_result.stats;
+    scratch.budget.charge(
+        Pass::regalloc,
+        stats.livein_iterations
+            + stats.process_bundle_count
+            + stats.process_bundle_reg_probes_fixed
+            + stats.process_bundle_reg_probes_any
+            + stats.evict_bundle_count
+            + stats.splits,
+    )?;
+    scratch.budget.end_pass(Pass::regalloc)?;
 
     // Run the regalloc checker, if requested.
     if b.flags().regalloc_checker() {
+        scratch
+            .budget
+            .start_pass(Pass::regalloc_checker, vcode.num_insts())?;
         let _tt = timing::regalloc_checker();
         let mut checker = regalloc2::checker::Checker::new(&vcode, machine_env);
         checker.prepare(&regalloc_result);
@@ -1,8 +1,8 @@
 use crate::ir::{BlockCall, Value, ValueList};
 use alloc::boxed::Box;
 use alloc::vec::Vec;
+use core::cell::Cell;
 use smallvec::SmallVec;
-use std::cell::Cell;
 
 pub use super::MachLabel;
 use super::RetPair;
@@ -83,7 +83,7 @@ macro_rules! isle_lower_prelude_methods {
 
         #[inline]
         fn output_builder_new(&mut self) -> InstOutputBuilder {
-            std::cell::Cell::new(InstOutput::new())
+            core::cell::Cell::new(InstOutput::new())
         }
 
         #[inline]
@@ -614,6 +614,40 @@ macro_rules! isle_lower_prelude_methods {
             ))
         }
 
+        fn shuffle_splat_from_imm(&mut self, imm: Immediate) -> Option<(ShuffleOperand, u8, u8)> {
+            use crate::machinst::isle::shuffle_imm_as_splat;
+            shuffle_imm_as_splat(self.lower_ctx.get_immediate_data(imm).as_slice())
+        }
+
+        fn shuffle_zip_from_imm(
+            &mut self,
+            imm: Immediate,
+        ) -> Option<(ShuffleOperand, ShuffleOperand, u8, bool)> {
+            use crate::machinst::isle::shuffle_imm_as_zip;
+            shuffle_imm_as_zip(self.lower_ctx.get_immediate_data(imm).as_slice())
+        }
+
+        fn shuffle_unzip_from_imm(
+            &mut self,
+            imm: Immediate,
+        ) -> Option<(ShuffleOperand, ShuffleOperand, u8, bool)> {
+            use crate::machinst::isle::shuffle_imm_as_unzip;
+            shuffle_imm_as_unzip(self.lower_ctx.get_immediate_data(imm).as_slice())
+        }
+
+        fn shuffle_rev_from_imm(&mut self, imm: Immediate) -> Option<(ShuffleOperand, u8, u8)> {
+            use crate::machinst::isle::shuffle_imm_as_rev;
+            shuffle_imm_as_rev(self.lower_ctx.get_immediate_data(imm).as_slice())
+        }
+
+        fn shuffle_rotate_from_imm(
+            &mut self,
+            imm: Immediate,
+        ) -> Option<(ShuffleOperand, ShuffleOperand, u8)> {
+            use crate::machinst::isle::shuffle_imm_as_rotate;
+            shuffle_imm_as_rotate(self.lower_ctx.get_immediate_data(imm).as_slice())
+        }
+
         fn safe_divisor_from_imm64(&mut self, ty: Type, val: Imm64) -> Option<u64> {
             let minus_one = if ty.bytes() == 8 {
                 -1
@@ -666,6 +700,118 @@ pub fn shuffle_imm_as_le_lane_idx(size: u8, bytes: &[u8]) -> Option<u8> {
     Some(bytes[0] / size)
 }
 
+/// One of the two operands of a `shuffle`.
+///
+/// The `shuffle_imm_as_*` classifiers below recognize shuffles of one or two
+/// vectors, and return which operands of the `shuffle` these vectors are.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShuffleOperand {
+    /// The first operand, whose bytes have the indices 0 to 15.
+    A,
+    /// The second operand, whose bytes have the indices 16 to 31.
+    B,
+}
+
+/// Checks whether the shuffle immediate `bytes` is an instance of `pattern`,
+/// which maps the index of each byte of the result to the index of a byte of
+/// two vectors `x` and `y` (0 to 15 for `x` and 16 to 31 for `y`).
+///
+/// Each of `x` and `y` can be either operand of the shuffle, and both can be
+/// the same operand. Returns the operands which are `x` and `y`, `y` being the
+/// same as `x` if `pattern` only uses `x`.
+fn shuffle_imm_as_pattern(
+    bytes: &[u8],
+    pattern: impl Fn(u8) -> u8,
+) -> Option<(ShuffleOperand, ShuffleOperand)> {
+    let mut operands = [None, None];
+    for (i, &byte) in (0..).zip(bytes) {
+        let operand = match byte {
+            0..=15 => ShuffleOperand::A,
+            16..=31 => ShuffleOperand::B,
+            _ => return None,
+        };
+        let index = pattern(i);
+        if byte % 16 != index % 16 {
+            return None;
+        }
+        let vector = &mut operands[usize::from(index / 16)];
+        if *vector.get_or_insert(operand) != operand {
+            return None;
+        }
+    }
+    match operands {
+        [Some(x), Some(y)] => Some((x, y)),
+        [Some(x), None] | [None, Some(x)] => Some((x, x)),
+        [None, None] => None,
+    }
+}
+
+/// Recognizes a broadcast of the `size`-byte lane `lane` of `x` to all the
+/// lanes of the result, returning `(x, size, lane)`.
+pub fn shuffle_imm_as_splat(bytes: &[u8]) -> Option<(ShuffleOperand, u8, u8)> {
+    [8, 4, 2, 1].into_iter().find_map(|size| {
+        let lane = bytes[0] % 16 / size;
+        let (x, _) = shuffle_imm_as_pattern(bytes, |i| lane * size + i % size)?;
+        Some((x, size, lane))
+    })
+}
+
+/// Recognizes an interleaving of the `size`-byte lanes of the low halves of
+/// `x` and `y`, or of their high halves if `high`, starting with the lowest
+/// lane of `x`. Returns `(x, y, size, high)`.
+pub fn shuffle_imm_as_zip(bytes: &[u8]) -> Option<(ShuffleOperand, ShuffleOperand, u8, bool)> {
+    [8, 4, 2, 1].into_iter().find_map(|size| {
+        [false, true].into_iter().find_map(|high| {
+            let half = if high { 8 } else { 0 };
+            let (x, y) = shuffle_imm_as_pattern(bytes, |i| {
+                let lane = i / size;
+                16 * (lane % 2) + half + lane / 2 * size + i % size
+            })?;
+            Some((x, y, size, high))
+        })
+    })
+}
+
+/// Recognizes the even `size`-byte lanes of the concatenation of `x` and `y`,
+/// or its odd lanes if `odd`. Returns `(x, y, size, odd)`.
+pub fn shuffle_imm_as_unzip(bytes: &[u8]) -> Option<(ShuffleOperand, ShuffleOperand, u8, bool)> {
+    [8, 4, 2, 1].into_iter().find_map(|size| {
+        [false, true].into_iter().find_map(|odd| {
+            let (x, y) = shuffle_imm_as_pattern(bytes, |i| {
+                (2 * (i / size) + u8::from(odd)) * size + i % size
+            })?;
+            Some((x, y, size, odd))
+        })
+    })
+}
+
+/// Recognizes a reversal of the order of the `size`-byte lanes within each
+/// `group`-byte group of lanes of `x`, returning `(x, size, group)`. A `group`
+/// of 16 bytes reverses all the lanes of `x`.
+pub fn shuffle_imm_as_rev(bytes: &[u8]) -> Option<(ShuffleOperand, u8, u8)> {
+    [8, 4, 2, 1].into_iter().find_map(|size| {
+        [2, 4, 8, 16]
+            .into_iter()
+            .filter(|&group| group > size)
+            .find_map(|group| {
+                let (x, _) = shuffle_imm_as_pattern(bytes, |i| {
+                    let lane = i % group / size;
+                    i / group * group + (group / size - 1 - lane) * size + i % size
+                })?;
+                Some((x, size, group))
+            })
+    })
+}
+
+/// Recognizes the 16 bytes of the concatenation of `x` and `y` starting at
+/// byte `n`, with `n` less than 16, returning `(x, y, n)`. When `x` and `y`
+/// are the same operand, this is a rotation of its bytes.
+pub fn shuffle_imm_as_rotate(bytes: &[u8]) -> Option<(ShuffleOperand, ShuffleOperand, u8)> {
+    let n = bytes[0] % 16;
+    let (x, y) = shuffle_imm_as_pattern(bytes, |i| n + i)?;
+    Some((x, y, n))
+}
+
 /// Helpers specifically for machines that use `abi::CallSite`.
 #[macro_export]
 #[doc(hidden)]
@@ -676,17 +822,27 @@ macro_rules! isle_prelude_caller_methods {
             sig_ref: SigRef,
             extname: ExternalName,
             dist: RelocDistance,
+            args: ValueSlice,
+        ) -> InstOutput {
+            self.gen_direct_call(sig_ref, extname, dist, args, Opcode::Call)
+        }
+
+        fn gen_call_indirect(
+            &mut self,
+            sig_ref: SigRef,
+            val: Value,
             args @ (inputs, off): ValueSlice,
         ) -> InstOutput {
             let caller_conv = self.lower_ctx.abi().call_conv(self.lower_ctx.sigs());
+            let ptr = self.put_in_reg(val);
             let sig = &self.lower_ctx.dfg().signatures[sig_ref];
             let num_rets = sig.returns.len();
             let abi = self.lower_ctx.sigs().abi_sig_for_sig_ref(sig_ref);
-            let caller = <$abicaller>::from_func(
+            let caller = <$abicaller>::from_ptr(
                 self.lower_ctx.sigs(),
                 sig_ref,
-                &extname,
-                dist,
+                ptr,
+                Opcode::CallIndirect,
                 caller_conv,
                 self.backend.flags().clone(),
             )
@@ -699,23 +855,33 @@ macro_rules! isle_prelude_caller_methods {
 
             self.gen_call_common(abi, num_rets, caller, args)
         }
+    };
+}
 
-        fn gen_call_indirect(
+/// Helpers for the above ISLE prelude implementations. Meant to go
+/// inside the `impl` for the context type, not the trait impl.
+#[macro_export]
+#[doc(hidden)]
+macro_rules! isle_prelude_method_helpers {
+    ($abicaller:ty) => {
+        fn gen_direct_call(
             &mut self,
             sig_ref: SigRef,
-            val: Value,
+            extname: ExternalName,
+            dist: RelocDistance,
             args @ (inputs, off): ValueSlice,
+            opcode: Opcode,
         ) -> InstOutput {
             let caller_conv = self.lower_ctx.abi().call_conv(self.lower_ctx.sigs());
-            let ptr = self.put_in_reg(val);
             let sig = &self.lower_ctx.dfg().signatures[sig_ref];
             let num_rets = sig.returns.len();
             let abi = self.lower_ctx.sigs().abi_sig_for_sig_ref(sig_ref);
-            let caller = <$abicaller>::from_ptr(
+            let caller = <$abicaller>::from_func(
                 self.lower_ctx.sigs(),
                 sig_ref,
-                ptr,
-                Opcode::CallIndirect,
+                &extname,
+                dist,
+                opcode,
                 caller_conv,
                 self.backend.flags().clone(),
             )
@@ -728,15 +894,7 @@ macro_rules! isle_prelude_caller_methods {
 
             self.gen_call_common(abi, num_rets, caller, args)
         }
-    };
-}
 
-/// Helpers for the above ISLE prelude implementations. Meant to go
-/// inside the `impl` for the context type, not the trait impl.
-#[macro_export]
-#[doc(hidden)]
-macro_rules! isle_prelude_method_helpers {
-    ($abicaller:ty) => {
         fn gen_call_common_args(&mut self, call_site: &mut $abicaller, (inputs, off): ValueSlice) {
             let num_args = call_site.num_args(self.lower_ctx.sigs());
 
@@ -836,3 +994,56 @@ where
     pub lower_ctx: &'a mut Lower<'b, I>,
     pub backend: &'a B,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ShuffleOperand::{A, B};
+
+    fn imm(bytes: &[u8]) -> Vec<u8> {
+        assert_eq!(bytes.len(), 16);
+        bytes.to_vec()
+    }
+
+    #[test]
+    fn shuffle_patterns() {
+        let splat = imm(&[
+            20, 21, 20, 21, 20, 21, 20, 21, 20, 21, 20, 21, 20, 21, 20, 21,
+        ]);
+        assert_eq!(shuffle_imm_as_splat(&splat), Some((B, 2, 2)));
+        assert_eq!(shuffle_imm_as_splat(&[7; 16]), Some((A, 1, 7)));
+
+        let zip = imm(&[4, 20, 5, 21, 6, 22, 7, 23, 8, 24, 9, 25, 10, 26, 11, 27]);
+        assert_eq!(shuffle_imm_as_zip(&zip), None);
+        let zip = imm(&[24, 8, 25, 9, 26, 10, 27, 11, 28, 12, 29, 13, 30, 14, 31, 15]);
+        assert_eq!(shuffle_imm_as_zip(&zip), Some((B, A, 1, true)));
+        let zip = imm(&[0, 1, 2, 3, 0, 1, 2, 3, 4, 5, 6, 7, 4, 5, 6, 7]);
+        assert_eq!(shuffle_imm_as_zip(&zip), Some((A, A, 4, false)));
+
+        let unzip = imm(&[2, 3, 6, 7, 10, 11, 14, 15, 18, 19, 22, 23, 26, 27, 30, 31]);
+        assert_eq!(shuffle_imm_as_unzip(&unzip), Some((A, B, 2, true)));
+
+        let rev = imm(&[15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0]);
+        assert_eq!(shuffle_imm_as_rev(&rev), Some((A, 1, 16)));
+        let rev = imm(&[
+            22, 23, 20, 21, 18, 19, 16, 17, 30, 31, 28, 29, 26, 27, 24, 25,
+        ]);
+        assert_eq!(shuffle_imm_as_rev(&rev), Some((B, 2, 8)));
+
+        let rotate = imm(&[3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 0, 1, 2]);
+        assert_eq!(shuffle_imm_as_rotate(&rotate), Some((A, A, 3)));
+        let rotate = imm(&[28, 29, 30, 31, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11]);
+        assert_eq!(shuffle_imm_as_rotate(&rotate), Some((B, A, 12)));
+
+        // Bytes out of range, and bytes mixing both operands in a lane of one vector.
+        let none = imm(&[32, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]);
+        let mixed = imm(&[0, 17, 0, 17, 0, 17, 0, 17, 0, 17, 0, 17, 0, 17, 0, 17]);
+        for bytes in [none, mixed] {
+            assert_eq!(shuffle_imm_as_splat(&bytes), None);
+            assert_eq!(shuffle_imm_as_zip(&bytes), None);
+            assert_eq!(shuffle_imm_as_unzip(&bytes), None);
+            assert_eq!(shuffle_imm_as_rev(&bytes), None);
+            assert_eq!(shuffle_imm_as_rotate(&bytes), None);
+        }
+    }
+}
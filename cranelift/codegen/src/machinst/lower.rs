@@ -18,12 +18,13 @@ use crate::machinst::{
     SigSet, VCode, VCodeBuilder, VCodeConstant, VCodeConstantData, VCodeConstants, VCodeInst,
     ValueRegs, Writable,
 };
-use crate::{trace, CodegenResult};
+use crate::session::BackendScratch;
+use crate::{trace, CodegenError, CodegenResult};
 use alloc::vec::Vec;
+use core::fmt::Debug;
 use cranelift_control::ControlPlane;
 use regalloc2::{MachineEnv, PRegSet};
 use smallvec::{smallvec, SmallVec};
-use std::fmt::Debug;
 
 use super::{VCodeBuildDirection, VRegAllocator};
 
@@ -212,6 +213,34 @@ pub struct Lower<'func, I: VCodeInst> {
     pinned_reg: Option<Reg>,
 }
 
+/// The allocations of a [Lower], kept empty between compilations by a
+/// [`CompilationSession`](crate::CompilationSession).
+pub(crate) struct LowerScratch<I: VCodeInst> {
+    value_regs: SecondaryMap<Value, ValueRegs<Reg>>,
+    block_end_colors: SecondaryMap<Block, InstColor>,
+    side_effect_inst_entry_colors: FxHashMap<Inst, InstColor>,
+    inst_constants: FxHashMap<Inst, u64>,
+    value_ir_uses: SecondaryMap<Value, ValueUseState>,
+    value_lowered_uses: SecondaryMap<Value, u32>,
+    inst_sunk: FxHashSet<Inst>,
+    ir_insts: Vec<I>,
+}
+
+impl<I: VCodeInst> Default for LowerScratch<I> {
+    fn default() -> Self {
+        Self {
+            value_regs: SecondaryMap::with_default(ValueRegs::invalid()),
+            block_end_colors: SecondaryMap::with_default(InstColor::new(0)),
+            side_effect_inst_entry_colors: FxHashMap::default(),
+            inst_constants: FxHashMap::default(),
+            value_ir_uses: SecondaryMap::with_default(ValueUseState::Unused),
+            value_lowered_uses: SecondaryMap::default(),
+            inst_sunk: FxHashSet::default(),
+            ir_insts: vec![],
+        }
+    }
+}
+
 /// How is a value used in the IR?
 ///
 /// This can be seen as a coarsening of an integer count. We only need
@@ -326,15 +355,28 @@ pub enum RelocDistance {
 }
 
 impl<'func, I: VCodeInst> Lower<'func, I> {
-    /// Prepare a new lowering context for the given IR function.
-    pub fn new(
+    /// Prepare a new lowering context for the given IR function, reusing the
+    /// allocations in `scratch`.
+    pub(crate) fn new(
         f: &'func Function,
         machine_env: &MachineEnv,
         abi: Callee<I::ABIMachineSpec>,
         emit_info: I::Info,
         block_order: BlockLoweringOrder,
         sigs: SigSet,
+        scratch: &mut BackendScratch<I>,
     ) -> CodegenResult<Self> {
+        let LowerScratch {
+            mut value_regs,
+            mut block_end_colors,
+            mut side_effect_inst_entry_colors,
+            mut inst_constants,
+            value_ir_uses,
+            value_lowered_uses,
+            inst_sunk,
+            ir_insts,
+        } = core::mem::take(&mut scratch.lower);
+        let mut vregs = VRegAllocator::new(&mut scratch.vcode);
         let constants = VCodeConstants::with_capacity(f.dfg.constants.len());
         let vcode = VCodeBuilder::new(
             sigs,
@@ -343,12 +385,9 @@ impl<'func, I: VCodeInst> Lower<'func, I> {
             block_order,
             constants,
             VCodeBuildDirection::Backward,
+            core::mem::take(&mut scratch.vcode),
         );
 
-        let mut vregs = VRegAllocator::new();
-
-        let mut value_regs = SecondaryMap::with_default(ValueRegs::invalid());
-
         // Assign a vreg to each block param and each inst result.
         for bb in f.layout.blocks() {
             for &param in f.dfg.block_params(bb) {
@@ -405,9 +444,6 @@ impl<'func, I: VCodeInst> Lower<'func, I> {
         // Compute instruction colors, find constant instructions, and find instructions with
         // side-effects, in one combined pass.
         let mut cur_color = 0;
-        let mut block_end_colors = SecondaryMap::with_default(InstColor::new(0));
-        let mut side_effect_inst_entry_colors = FxHashMap::default();
-        let mut inst_constants = FxHashMap::default();
         for bb in f.layout.blocks() {
             cur_color += 1;
             for inst in f.layout.block_insts(bb) {
@@ -430,7 +466,7 @@ impl<'func, I: VCodeInst> Lower<'func, I> {
             block_end_colors[bb] = InstColor::new(cur_color);
         }
 
-        let value_ir_uses = Self::compute_use_states(f);
+        let value_ir_uses = Self::compute_use_states(f, value_ir_uses);
 
         Ok(Lower {
             f,
@@ -443,11 +479,11 @@ impl<'func, I: VCodeInst> Lower<'func, I> {
             side_effect_inst_entry_colors,
             inst_constants,
             value_ir_uses,
-            value_lowered_uses: SecondaryMap::default(),
-            inst_sunk: FxHashSet::default(),
+            value_lowered_uses,
+            inst_sunk,
             cur_scan_entry_color: None,
             cur_inst: None,
-            ir_insts: vec![],
+            ir_insts,
             pinned_reg: None,
         })
     }
@@ -463,7 +499,10 @@ impl<'func, I: VCodeInst> Lower<'func, I> {
     /// Pre-analysis: compute `value_ir_uses`. See comment on
     /// `ValueUseState` for a description of what this analysis
     /// computes.
-    fn compute_use_states<'a>(f: &'a Function) -> SecondaryMap<Value, ValueUseState> {
+    fn compute_use_states<'a>(
+        f: &'a Function,
+        mut value_ir_uses: SecondaryMap<Value, ValueUseState>,
+    ) -> SecondaryMap<Value, ValueUseState> {
         // We perform the analysis without recursion, so we don't
         // overflow the stack on long chains of ops in the input.
         //
@@ -481,8 +520,6 @@ impl<'func, I: VCodeInst> Lower<'func, I> {
         // Once, Multiple} is part of what makes this pass more
         // efficient than a full indirect-use-counting pass.
 
-        let mut value_ir_uses = SecondaryMap::with_default(ValueUseState::Unused);
-
         // Stack of iterators over Values as we do DFS to mark
         // Multiple-state subtrees. The iterator type is whatever is
         // returned by `uses` below.
@@ -740,7 +777,22 @@ impl<'func, I: VCodeInst> Lower<'func, I> {
             // or any of its outputs its used.
             if has_side_effect || value_needed {
                 trace!("lowering: inst {}: {:?}", inst, self.f.dfg.insts[inst]);
-                let temp_regs = backend.lower(self, inst).unwrap_or_else(|| {
+                if data.opcode().ext_slot().is_some() {
+                    return Err(CodegenError::Unsupported(format!(
+                        "frontend extension instruction must be replaced before code generation: `{}`",
+                        self.f.dfg.display_inst(inst)
+                    )));
+                }
+                let temp_regs = backend.lower(self, inst);
+                if temp_regs.is_none() && data.opcode().is_atomic() {
+                    // Falling back to a plain memory access would silently lose atomicity, so
+                    // report atomics without a lowering as unsupported rather than a bug.
+                    return Err(CodegenError::Unsupported(format!(
+                        "atomic operation not supported by this target: `{}`",
+                        self.f.dfg.display_inst(inst)
+                    )));
+                }
+                let temp_regs = temp_regs.unwrap_or_else(|| {
                     let ty = if self.num_outputs(inst) > 0 {
                         Some(self.output_ty(inst, 0))
                     } else {
@@ -774,8 +826,7 @@ impl<'func, I: VCodeInst> Lower<'func, I> {
                 }
             }
 
-            let loc = self.srcloc(inst);
-            self.finish_ir_inst(loc);
+            self.finish_lowered_ir_inst(inst);
 
             // maybe insert random instruction
             if ctrl_plane.get_decision() {
@@ -902,6 +953,14 @@ impl<'func, I: VCodeInst> Lower<'func, I> {
         }
     }
 
+    /// Like `finish_ir_inst`, but for the instructions lowered from `inst`, which are attributed
+    /// to it.
+    fn finish_lowered_ir_inst(&mut self, inst: Inst) {
+        self.vcode.set_ir_inst(Some(inst));
+        self.finish_ir_inst(self.srcloc(inst));
+        self.vcode.set_ir_inst(None);
+    }
+
     fn finish_bb(&mut self) {
         self.vcode.end_bb();
     }
@@ -935,8 +994,7 @@ impl<'func, I: VCodeInst> Lower<'func, I> {
                     self.f.dfg.display_inst(branch),
                 )
             });
-        let loc = self.srcloc(branch);
-        self.finish_ir_inst(loc);
+        self.finish_lowered_ir_inst(branch);
         // Add block param outputs for current block.
         self.lower_branch_blockparam_args(bindex);
         Ok(())
@@ -987,6 +1045,7 @@ impl<'func, I: VCodeInst> Lower<'func, I> {
     pub fn lower<B: LowerBackend<MInst = I>>(
         mut self,
         backend: &B,
+        scratch: &mut BackendScratch<I>,
         ctrl_plane: &mut ControlPlane,
     ) -> CodegenResult<VCode<I>> {
         trace!("about to lower function: {:?}", self.f);
@@ -1082,6 +1141,25 @@ impl<'func, I: VCodeInst> Lower<'func, I> {
         let vcode = self.vcode.build(self.allocatable, self.vregs);
         trace!("built vcode: {:?}", vcode);
 
+        self.value_regs.clear();
+        self.block_end_colors.clear();
+        self.side_effect_inst_entry_colors.clear();
+        self.inst_constants.clear();
+        self.value_ir_uses.clear();
+        self.value_lowered_uses.clear();
+        self.inst_sunk.clear();
+        self.ir_insts.clear();
+        scratch.lower = LowerScratch {
+            value_regs: self.value_regs,
+            block_end_colors: self.block_end_colors,
+            side_effect_inst_entry_colors: self.side_effect_inst_entry_colors,
+            inst_constants: self.inst_constants,
+            value_ir_uses: self.value_ir_uses,
+            value_lowered_uses: self.value_lowered_uses,
+            inst_sunk: self.inst_sunk,
+            ir_insts: self.ir_insts,
+        };
+
         Ok(vcode)
     }
 }
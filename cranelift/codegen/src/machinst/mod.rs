@@ -46,19 +46,20 @@
 
 use crate::binemit::{Addend, CodeInfo, CodeOffset, Reloc, StackMap};
 use crate::ir::function::FunctionParameters;
-use crate::ir::{DynamicStackSlot, RelSourceLoc, StackSlot, Type};
+use crate::ir::{Block, DynamicStackSlot, Inst, RelSourceLoc, StackSlot, Type};
 use crate::isa::FunctionAlignment;
 use crate::result::CodegenResult;
 use crate::settings;
 use crate::settings::Flags;
 use crate::value_label::ValueLabelsRanges;
+use alloc::boxed::Box;
+use alloc::string::String;
 use alloc::vec::Vec;
 use core::fmt::Debug;
 use cranelift_control::ControlPlane;
 use cranelift_entity::PrimaryMap;
 use regalloc2::{Allocation, VReg};
 use smallvec::{smallvec, SmallVec};
-use std::string::String;
 
 #[cfg(feature = "enable-serde")]
 use serde::{Deserialize, Serialize};
@@ -313,6 +314,9 @@ pub trait MachInstEmitState<I: VCodeInst>: Default + Clone + Debug {
     /// A hook that triggers when first emitting a new block.
     /// It is guaranteed to be called before any instructions are emitted.
     fn on_new_block(&mut self) {}
+    /// The distance from the nominal SP down to the actual SP at the current
+    /// program point.
+    fn virtual_sp_offset(&self) -> i64;
 }
 
 /// The result of a `MachBackend::compile_function()` call. Contains machine
@@ -326,6 +330,9 @@ pub struct CompiledCodeBase<T: CompilePhase> {
     pub frame_size: u32,
     /// Disassembly, if requested.
     pub vcode: Option<String>,
+    /// Listing of the lowered VCode, if requested; see
+    /// [`CompiledCodeBase::vcode`].
+    pub vcode_listing: Option<Box<VCodeListing>>,
     /// Debug info: value labels to registers/stackslots at code offsets.
     pub value_labels_ranges: ValueLabelsRanges,
     /// Debug info: stackslots to stack pointer offsets.
@@ -344,6 +351,11 @@ pub struct CompiledCodeBase<T: CompilePhase> {
     /// This info is generated only if the `machine_code_cfg_info`
     /// flag is set.
     pub bb_edges: Vec<(CodeOffset, CodeOffset)>,
+    /// Block coverage info: the index of the counter of each block, in layout order.
+    ///
+    /// This info is generated only if block coverage was requested with
+    /// [`Context::set_block_coverage`](crate::Context::set_block_coverage).
+    pub block_coverage: Vec<(Block, u32)>,
 }
 
 impl CompiledCodeStencil {
@@ -353,11 +365,13 @@ impl CompiledCodeStencil {
             buffer: self.buffer.apply_base_srcloc(params.base_srcloc()),
             frame_size: self.frame_size,
             vcode: self.vcode,
+            vcode_listing: self.vcode_listing,
             value_labels_ranges: self.value_labels_ranges,
             sized_stackslot_offsets: self.sized_stackslot_offsets,
             dynamic_stackslot_offsets: self.dynamic_stackslot_offsets,
             bb_starts: self.bb_starts,
             bb_edges: self.bb_edges,
+            block_coverage: self.block_coverage,
         }
     }
 }
@@ -370,6 +384,36 @@ impl<T: CompilePhase> CompiledCodeBase<T> {
         }
     }
 
+    /// Returns the structured listing of the lowered VCode, including the
+    /// registers chosen by register allocation. This is only available if it
+    /// was requested with [`Context::set_want_vcode`](crate::Context::set_want_vcode)
+    /// before compiling.
+    pub fn vcode(&self) -> Option<&VCodeListing> {
+        self.vcode_listing.as_deref()
+    }
+
+    /// Returns the offset and length in bytes of each machine instruction, in
+    /// increasing offset order, with the IR instruction it was lowered from.
+    /// Instructions not lowered from a particular IR instruction, such as the
+    /// prologue, block starts and register allocator moves, have no IR
+    /// instruction, and the epilogue is attributed to the IR `return`.
+    ///
+    /// The offsets are those of the final code, after branch optimizations.
+    /// Alignment padding and constant islands are not covered by any entry.
+    /// This is only available if it was requested with
+    /// [`Context::set_collect_inst_offsets`](crate::Context::set_collect_inst_offsets)
+    /// before compiling, and is empty otherwise.
+    pub fn inst_offsets(&self) -> &[(CodeOffset, u8, Option<Inst>)] {
+        self.buffer.inst_offsets()
+    }
+
+    /// Returns the index of the coverage counter of each block of the function, in layout order,
+    /// if block coverage was requested with
+    /// [`Context::set_block_coverage`](crate::Context::set_block_coverage), and is empty otherwise.
+    pub fn block_coverage(&self) -> &[(Block, u32)] {
+        &self.block_coverage
+    }
+
     /// Returns a reference to the machine code generated for this function compilation.
     pub fn code_buffer(&self) -> &[u8] {
         self.buffer.data()
@@ -382,7 +426,7 @@ impl<T: CompilePhase> CompiledCodeBase<T> {
         params: Option<&crate::ir::function::FunctionParameters>,
         cs: &capstone::Capstone,
     ) -> Result<String, anyhow::Error> {
-        use std::fmt::Write;
+        use core::fmt::Write;
 
         let mut buf = String::new();
 
@@ -472,6 +516,29 @@ impl CompiledCode {
         )
     }
 
+    /// Creates a table of the layout of the frame of the function at each
+    /// code offset, which can be queried at runtime to unwind the stack
+    /// without relying on frame pointers.
+    ///
+    /// Returns `None` if the `unwind_info` setting is off, which leaves the
+    /// layout of the frame undescribed, or if the frames of `isa` can't be
+    /// described by an [`UnwindTable`](crate::isa::unwind::table::UnwindTable).
+    pub fn unwind_table(
+        &self,
+        isa: &dyn crate::isa::TargetIsa,
+    ) -> Option<crate::isa::unwind::table::UnwindTable> {
+        if !isa.flags().unwind_info() {
+            return None;
+        }
+        crate::isa::unwind::table::UnwindTable::new(
+            isa.triple().architecture,
+            &self.buffer.unwind_info,
+            self.frame_size,
+            &self.buffer.frame_states,
+            self.buffer.total_size(),
+        )
+    }
+
     /// Creates unwind information for the function.
     ///
     /// Returns `None` if the function has no unwind information.
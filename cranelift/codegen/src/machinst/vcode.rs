@@ -21,7 +21,9 @@ use crate::fx::FxHashMap;
 use crate::fx::FxHashSet;
 use crate::ir::RelSourceLoc;
 use crate::ir::{self, types, Constant, ConstantData, DynamicStackSlot, LabelValueLoc, ValueLabel};
+use crate::isa::unwind::table::FrameState;
 use crate::machinst::*;
+use crate::session::BackendScratch;
 use crate::timing;
 use crate::trace;
 use crate::CodegenError;
@@ -32,11 +34,16 @@ use regalloc2::{
     RegClass, VReg,
 };
 
+use crate::hash_map::Entry;
+use crate::HashMap;
+use alloc::string::String;
 use alloc::vec::Vec;
+use core::fmt;
+use cranelift_entity::packed_option::PackedOption;
 use cranelift_entity::{entity_impl, Keys, PrimaryMap};
-use std::collections::hash_map::Entry;
-use std::collections::HashMap;
-use std::fmt;
+
+#[cfg(feature = "enable-serde")]
+use serde::{Deserialize, Serialize};
 
 /// Index referring to an instruction in VCode.
 pub type InsnIndex = regalloc2::Inst;
@@ -86,6 +93,9 @@ pub struct VCode<I: VCodeInst> {
     /// reasonable to keep one of these per instruction.)
     srclocs: Vec<RelSourceLoc>,
 
+    /// The IR instruction each instruction was lowered from, if any.
+    ir_insts: Vec<PackedOption<ir::Inst>>,
+
     /// Entry block.
     entry: BlockIndex,
 
@@ -215,6 +225,57 @@ pub struct EmitResult {
     pub frame_size: u32,
 }
 
+/// The allocations of a [VCode], and of the [VRegAllocator] used to build it,
+/// kept empty between compilations by a
+/// [`CompilationSession`](crate::CompilationSession).
+pub(crate) struct VCodeScratch<I: VCodeInst> {
+    vreg_types: Vec<Type>,
+    insts: Vec<I>,
+    operands: Vec<Operand>,
+    operand_ranges: Vec<(u32, u32)>,
+    clobbers: FxHashMap<InsnIndex, PRegSet>,
+    srclocs: Vec<RelSourceLoc>,
+    ir_insts: Vec<PackedOption<ir::Inst>>,
+    block_ranges: Vec<(InsnIndex, InsnIndex)>,
+    block_succ_range: Vec<(u32, u32)>,
+    block_pred_range: Vec<(u32, u32)>,
+    block_succs_preds: Vec<regalloc2::Block>,
+    block_params_range: Vec<(u32, u32)>,
+    block_params: Vec<regalloc2::VReg>,
+    branch_block_args: Vec<regalloc2::VReg>,
+    branch_block_arg_range: Vec<(u32, u32)>,
+    branch_block_arg_succ_range: Vec<(u32, u32)>,
+    vreg_aliases: FxHashMap<regalloc2::VReg, regalloc2::VReg>,
+    reftyped_vregs: Vec<VReg>,
+    debug_value_labels: Vec<(VReg, InsnIndex, InsnIndex, u32)>,
+}
+
+impl<I: VCodeInst> Default for VCodeScratch<I> {
+    fn default() -> Self {
+        Self {
+            vreg_types: vec![],
+            insts: vec![],
+            operands: vec![],
+            operand_ranges: vec![],
+            clobbers: FxHashMap::default(),
+            srclocs: vec![],
+            ir_insts: vec![],
+            block_ranges: vec![],
+            block_succ_range: vec![],
+            block_pred_range: vec![],
+            block_succs_preds: vec![],
+            block_params_range: vec![],
+            block_params: vec![],
+            branch_block_args: vec![],
+            branch_block_arg_range: vec![],
+            branch_block_arg_succ_range: vec![],
+            vreg_aliases: FxHashMap::default(),
+            reftyped_vregs: vec![],
+            debug_value_labels: vec![],
+        }
+    }
+}
+
 /// A builder for a VCode function body.
 ///
 /// This builder has the ability to accept instructions in either
@@ -255,6 +316,9 @@ pub struct VCodeBuilder<I: VCodeInst> {
     /// Current source location.
     cur_srcloc: RelSourceLoc,
 
+    /// Current IR instruction being lowered, if any.
+    cur_ir_inst: PackedOption<ir::Inst>,
+
     /// Debug-value label in-progress map, keyed by label. For each
     /// label, we keep disjoint ranges mapping to vregs. We'll flatten
     /// this into (vreg, range, label) tuples when done.
@@ -279,8 +343,9 @@ impl<I: VCodeInst> VCodeBuilder<I> {
         block_order: BlockLoweringOrder,
         constants: VCodeConstants,
         direction: VCodeBuildDirection,
+        scratch: VCodeScratch<I>,
     ) -> VCodeBuilder<I> {
-        let vcode = VCode::new(sigs, abi, emit_info, block_order, constants);
+        let vcode = VCode::new(sigs, abi, emit_info, block_order, constants, scratch);
 
         VCodeBuilder {
             vcode,
@@ -290,6 +355,7 @@ impl<I: VCodeInst> VCodeBuilder<I> {
             block_params_start: 0,
             branch_block_arg_succ_start: 0,
             cur_srcloc: Default::default(),
+            cur_ir_inst: None.into(),
             debug_info: FxHashMap::default(),
         }
     }
@@ -377,6 +443,7 @@ impl<I: VCodeInst> VCodeBuilder<I> {
     pub fn push(&mut self, insn: I) {
         self.vcode.insts.push(insn);
         self.vcode.srclocs.push(self.cur_srcloc);
+        self.vcode.ir_insts.push(self.cur_ir_inst);
     }
 
     /// Add a successor block with branch args.
@@ -390,6 +457,11 @@ impl<I: VCodeInst> VCodeBuilder<I> {
         self.cur_srcloc = srcloc;
     }
 
+    /// Set the IR instruction that the following instructions are lowered from.
+    pub fn set_ir_inst(&mut self, inst: Option<ir::Inst>) {
+        self.cur_ir_inst = inst.into();
+    }
+
     /// Add a debug value label to a register.
     pub fn add_value_label(&mut self, reg: Reg, label: ValueLabel) {
         // We'll fix up labels in reverse(). Because we're generating
@@ -495,6 +567,7 @@ impl<I: VCodeInst> VCodeBuilder<I> {
         self.vcode.block_succ_range.reverse();
         self.vcode.insts.reverse();
         self.vcode.srclocs.reverse();
+        self.vcode.ir_insts.reverse();
         // Likewise, branch_block_arg_succ_range is indexed by block
         // so must be reversed.
         self.vcode.branch_block_arg_succ_range.reverse();
@@ -628,33 +701,74 @@ impl<I: VCodeInst> VCode<I> {
         emit_info: I::Info,
         block_order: BlockLoweringOrder,
         constants: VCodeConstants,
+        scratch: VCodeScratch<I>,
     ) -> VCode<I> {
         let n_blocks = block_order.lowered_order().len();
+        fn reserved<T>(mut v: Vec<T>, additional: usize) -> Vec<T> {
+            v.reserve(additional);
+            v
+        }
+        let mut vreg_aliases = scratch.vreg_aliases;
+        vreg_aliases.reserve(10 * n_blocks);
         VCode {
             sigs,
             vreg_types: vec![],
-            insts: Vec::with_capacity(10 * n_blocks),
-            operands: Vec::with_capacity(30 * n_blocks),
-            operand_ranges: Vec::with_capacity(10 * n_blocks),
-            clobbers: FxHashMap::default(),
-            srclocs: Vec::with_capacity(10 * n_blocks),
+            insts: reserved(scratch.insts, 10 * n_blocks),
+            operands: reserved(scratch.operands, 30 * n_blocks),
+            operand_ranges: reserved(scratch.operand_ranges, 10 * n_blocks),
+            clobbers: scratch.clobbers,
+            srclocs: reserved(scratch.srclocs, 10 * n_blocks),
+            ir_insts: reserved(scratch.ir_insts, 10 * n_blocks),
             entry: BlockIndex::new(0),
-            block_ranges: Vec::with_capacity(n_blocks),
-            block_succ_range: Vec::with_capacity(n_blocks),
-            block_succs_preds: Vec::with_capacity(2 * n_blocks),
-            block_pred_range: Vec::with_capacity(n_blocks),
-            block_params_range: Vec::with_capacity(n_blocks),
-            block_params: Vec::with_capacity(5 * n_blocks),
-            branch_block_args: Vec::with_capacity(10 * n_blocks),
-            branch_block_arg_range: Vec::with_capacity(2 * n_blocks),
-            branch_block_arg_succ_range: Vec::with_capacity(n_blocks),
+            block_ranges: reserved(scratch.block_ranges, n_blocks),
+            block_succ_range: reserved(scratch.block_succ_range, n_blocks),
+            block_succs_preds: reserved(scratch.block_succs_preds, 2 * n_blocks),
+            block_pred_range: reserved(scratch.block_pred_range, n_blocks),
+            block_params_range: reserved(scratch.block_params_range, n_blocks),
+            block_params: reserved(scratch.block_params, 5 * n_blocks),
+            branch_block_args: reserved(scratch.branch_block_args, 10 * n_blocks),
+            branch_block_arg_range: reserved(scratch.branch_block_arg_range, 2 * n_blocks),
+            branch_block_arg_succ_range: reserved(scratch.branch_block_arg_succ_range, n_blocks),
             block_order,
             abi,
             emit_info,
             reftyped_vregs: vec![],
             constants,
-            debug_value_labels: vec![],
-            vreg_aliases: FxHashMap::with_capacity_and_hasher(10 * n_blocks, Default::default()),
+            debug_value_labels: scratch.debug_value_labels,
+            vreg_aliases,
+        }
+    }
+
+    /// Clear this VCode, keeping its allocations for the next one.
+    fn into_scratch(self) -> VCodeScratch<I> {
+        fn cleared<T>(mut v: Vec<T>) -> Vec<T> {
+            v.clear();
+            v
+        }
+        let mut clobbers = self.clobbers;
+        clobbers.clear();
+        let mut vreg_aliases = self.vreg_aliases;
+        vreg_aliases.clear();
+        VCodeScratch {
+            vreg_types: cleared(self.vreg_types),
+            insts: cleared(self.insts),
+            operands: cleared(self.operands),
+            operand_ranges: cleared(self.operand_ranges),
+            clobbers,
+            srclocs: cleared(self.srclocs),
+            ir_insts: cleared(self.ir_insts),
+            block_ranges: cleared(self.block_ranges),
+            block_succ_range: cleared(self.block_succ_range),
+            block_pred_range: cleared(self.block_pred_range),
+            block_succs_preds: cleared(self.block_succs_preds),
+            block_params_range: cleared(self.block_params_range),
+            block_params: cleared(self.block_params),
+            branch_block_args: cleared(self.branch_block_args),
+            branch_block_arg_range: cleared(self.branch_block_arg_range),
+            branch_block_arg_succ_range: cleared(self.branch_block_arg_succ_range),
+            vreg_aliases,
+            reftyped_vregs: cleared(self.reftyped_vregs),
+            debug_value_labels: cleared(self.debug_value_labels),
         }
     }
 
@@ -747,9 +861,11 @@ impl<I: VCodeInst> VCode<I> {
         mut self,
         regalloc: &regalloc2::Output,
         want_disasm: bool,
+        want_inst_offsets: bool,
         flags: &settings::Flags,
+        scratch: &mut BackendScratch<I>,
         ctrl_plane: &mut ControlPlane,
-    ) -> EmitResult
+    ) -> CodegenResult<EmitResult>
     where
         I: VCodeInst,
     {
@@ -757,7 +873,13 @@ impl<I: VCodeInst> VCode<I> {
         use core::fmt::Write;
 
         let _tt = timing::vcode_emit();
-        let mut buffer = MachBuffer::new();
+        scratch
+            .budget
+            .start_pass(timing::Pass::vcode_emit, self.num_blocks())?;
+        let mut buffer = MachBuffer::with_scratch(core::mem::take(&mut scratch.buffer));
+        if want_inst_offsets {
+            buffer.collect_inst_offsets();
+        }
         let mut bb_starts: Vec<Option<CodeOffset>> = vec![];
 
         // The first M MachLabels are reserved for block indices.
@@ -802,7 +924,7 @@ impl<I: VCodeInst> VCode<I> {
         let mut cur_srcloc = None;
         let mut last_offset = None;
         let mut inst_offsets = vec![];
-        let mut state = I::State::new(&self.abi, std::mem::take(ctrl_plane));
+        let mut state = I::State::new(&self.abi, core::mem::take(ctrl_plane));
 
         let mut disasm = String::new();
 
@@ -848,6 +970,7 @@ impl<I: VCodeInst> VCode<I> {
             assert_eq!(buffer.cur_offset(), new_offset);
 
             let do_emit = |inst: &I,
+                           ir_inst: Option<ir::Inst>,
                            allocs: &[Allocation],
                            disasm: &mut String,
                            buffer: &mut MachBuffer<I>,
@@ -856,7 +979,9 @@ impl<I: VCodeInst> VCode<I> {
                     let mut s = state.clone();
                     writeln!(disasm, "  {}", inst.pretty_print_inst(allocs, &mut s)).unwrap();
                 }
+                let start = buffer.cur_offset();
                 inst.emit(allocs, buffer, &self.emit_info, state);
+                buffer.add_inst_offset(start, ir_inst);
             };
 
             // Is this the first block? Emit the prologue directly if so.
@@ -864,9 +989,18 @@ impl<I: VCodeInst> VCode<I> {
                 trace!(" -> entry block");
                 buffer.start_srcloc(Default::default());
                 state.pre_sourceloc(Default::default());
+                buffer.set_frame_state(FrameState::Entry);
                 for inst in &prologue_insts {
-                    do_emit(&inst, &[], &mut disasm, &mut buffer, &mut state);
+                    do_emit(inst, None, &[], &mut disasm, &mut buffer, &mut state);
+                    // Only the first instruction of the prologue starts in the
+                    // frame as it was on entry.
+                    if buffer.cur_offset() > 0 {
+                        buffer.set_frame_state(FrameState::Unknown);
+                    }
                 }
+                buffer.set_frame_state(FrameState::Body {
+                    sp_offset: state.virtual_sp_offset(),
+                });
                 buffer.end_srcloc();
             }
 
@@ -898,7 +1032,14 @@ impl<I: VCodeInst> VCode<I> {
                 self.block_order.is_indirect_branch_target(block),
                 is_forward_edge_cfi_enabled,
             ) {
-                do_emit(&block_start, &[], &mut disasm, &mut buffer, &mut state);
+                do_emit(
+                    &block_start,
+                    None,
+                    &[],
+                    &mut disasm,
+                    &mut buffer,
+                    &mut state,
+                );
             }
 
             for inst_or_edit in regalloc.block_insts_and_edits(&self, block) {
@@ -945,9 +1086,9 @@ impl<I: VCodeInst> VCode<I> {
                                 .safepoint_slots
                                 .binary_search_by(|(progpoint, _alloc)| {
                                     if progpoint.inst() >= iix {
-                                        std::cmp::Ordering::Greater
+                                        core::cmp::Ordering::Greater
                                     } else {
-                                        std::cmp::Ordering::Less
+                                        core::cmp::Ordering::Less
                                     }
                                 })
                                 .unwrap_err();
@@ -974,20 +1115,39 @@ impl<I: VCodeInst> VCode<I> {
                         // a return, place an epilogue at this point
                         // (and don't emit the return; the actual
                         // epilogue will contain it).
-                        if self.insts[iix.index()].is_term() == MachTerminator::Ret {
-                            for inst in self.abi.gen_epilogue(&self.sigs) {
-                                do_emit(&inst, &[], &mut disasm, &mut buffer, &mut state);
+                        let ir_inst = self.ir_insts[iix.index()].expand();
+                        let term = self.insts[iix.index()].is_term();
+                        if term == MachTerminator::Ret {
+                            let epilogue = self.abi.gen_epilogue(&self.sigs);
+                            let (ret, teardown) = epilogue.split_last().unwrap();
+                            for inst in teardown {
+                                do_emit(inst, ir_inst, &[], &mut disasm, &mut buffer, &mut state);
+                                buffer.set_frame_state(FrameState::Unknown);
                             }
+                            // The frame is torn down once the return is
+                            // reached.
+                            buffer.set_frame_state(FrameState::Entry);
+                            do_emit(ret, ir_inst, &[], &mut disasm, &mut buffer, &mut state);
                         } else {
+                            // Tail calls tear down the frame themselves.
+                            if term == MachTerminator::RetCall {
+                                buffer.set_frame_state(FrameState::Unknown);
+                            }
                             // Emit the instruction!
                             do_emit(
                                 &self.insts[iix.index()],
+                                ir_inst,
                                 allocs,
                                 &mut disasm,
                                 &mut buffer,
                                 &mut state,
                             );
                         }
+                        // Calls may push their arguments, or have the callee
+                        // pop them.
+                        buffer.set_frame_state(FrameState::Body {
+                            sp_offset: state.virtual_sp_offset(),
+                        });
                     }
 
                     InstOrEdit::Edit(Edit::Move { from, to }) => {
@@ -1001,21 +1161,21 @@ impl<I: VCodeInst> VCode<I> {
                                 debug_assert_eq!(from.class(), to.class());
                                 let ty = I::canonical_type_for_rc(from.class());
                                 let mv = I::gen_move(to_rreg, from_rreg, ty);
-                                do_emit(&mv, &[], &mut disasm, &mut buffer, &mut state);
+                                do_emit(&mv, None, &[], &mut disasm, &mut buffer, &mut state);
                             }
                             (Some(from), None) => {
                                 // Spill from register to spillslot.
                                 let to = to.as_stack().unwrap();
                                 let from_rreg = RealReg::from(from);
                                 let spill = self.abi.gen_spill(to, from_rreg);
-                                do_emit(&spill, &[], &mut disasm, &mut buffer, &mut state);
+                                do_emit(&spill, None, &[], &mut disasm, &mut buffer, &mut state);
                             }
                             (None, Some(to)) => {
                                 // Load from spillslot to register.
                                 let from = from.as_stack().unwrap();
                                 let to_rreg = Writable::from_reg(RealReg::from(to));
                                 let reload = self.abi.gen_reload(to_rreg, from);
-                                do_emit(&reload, &[], &mut disasm, &mut buffer, &mut state);
+                                do_emit(&reload, None, &[], &mut disasm, &mut buffer, &mut state);
                             }
                             (None, None) => {
                                 panic!("regalloc2 should have eliminated stack-to-stack moves!");
@@ -1049,7 +1209,22 @@ impl<I: VCodeInst> VCode<I> {
                 bb_padding.len() as u32 + I::LabelUse::ALIGN - 1
             };
             if buffer.island_needed(padding + worst_case_next_bb) {
-                buffer.emit_island(padding + worst_case_next_bb, ctrl_plane);
+                let island = buffer.emit_island_within(
+                    padding + worst_case_next_bb,
+                    &mut scratch.budget,
+                    ctrl_plane,
+                );
+                if let Err(e) = island {
+                    *ctrl_plane = state.take_ctrl_plane();
+                    return Err(e);
+                }
+            }
+
+            let (start, end) = self.block_ranges[block.index()];
+            let insts = end.index() - start.index() + ra_edits_per_block[block.index()] as usize;
+            if let Err(e) = scratch.budget.charge(timing::Pass::vcode_emit, insts) {
+                *ctrl_plane = state.take_ctrl_plane();
+                return Err(e);
             }
 
             // Insert padding, if configured, to stress the `MachBuffer`'s
@@ -1089,19 +1264,28 @@ impl<I: VCodeInst> VCode<I> {
         let value_labels_ranges =
             self.compute_value_labels_ranges(regalloc, &inst_offsets[..], func_body_len);
         let frame_size = self.abi.frame_size();
-
-        EmitResult {
-            buffer: buffer.finish(&self.constants, ctrl_plane),
+        scratch
+            .budget
+            .start_pass(timing::Pass::vcode_emit_finish, self.insts.len())?;
+        let (buffer, buffer_scratch) =
+            buffer.finish_with_scratch(&self.constants, &mut scratch.budget, ctrl_plane)?;
+        let sized_stackslot_offsets = self.abi.sized_stackslot_offsets().clone();
+        let dynamic_stackslot_offsets = self.abi.dynamic_stackslot_offsets().clone();
+        scratch.buffer = buffer_scratch;
+        scratch.vcode = self.into_scratch();
+
+        Ok(EmitResult {
+            buffer,
             bb_offsets,
             bb_edges,
             inst_offsets,
             func_body_len,
             disasm: if want_disasm { Some(disasm) } else { None },
-            sized_stackslot_offsets: self.abi.sized_stackslot_offsets().clone(),
-            dynamic_stackslot_offsets: self.abi.dynamic_stackslot_offsets().clone(),
+            sized_stackslot_offsets,
+            dynamic_stackslot_offsets,
             value_labels_ranges,
             frame_size,
-        }
+        })
     }
 
     fn compute_value_labels_ranges(
@@ -1285,7 +1469,7 @@ impl<I: VCodeInst> RegallocFunction for VCode<I> {
     }
 
     fn num_vregs(&self) -> usize {
-        std::cmp::max(self.vreg_types.len(), first_user_vreg_index())
+        core::cmp::max(self.vreg_types.len(), first_user_vreg_index())
     }
 
     fn reftype_vregs(&self) -> &[VReg] {
@@ -1314,46 +1498,197 @@ impl<I: VCodeInst> RegallocFunction for VCode<I> {
     }
 }
 
-impl<I: VCodeInst> fmt::Debug for VCode<I> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(f, "VCode {{")?;
-        writeln!(f, "  Entry block: {}", self.entry.index())?;
-
+impl<I: VCodeInst> VCode<I> {
+    /// Produce an owned, printable listing of this VCode. If the register
+    /// allocation results are given, each instruction is also printed with
+    /// its allocated registers.
+    pub fn listing(&self, regalloc: Option<&regalloc2::Output>) -> VCodeListing {
         let mut state = Default::default();
+        let mut ra_state = Default::default();
 
         let mut alias_keys = self.vreg_aliases.keys().cloned().collect::<Vec<_>>();
         alias_keys.sort_unstable();
-        for key in alias_keys {
-            let dest = self.vreg_aliases.get(&key).unwrap();
-            writeln!(f, "  {:?} := {:?}", Reg::from(key), Reg::from(*dest))?;
+        let vreg_aliases = alias_keys
+            .into_iter()
+            .map(|key| {
+                let dest = self.vreg_aliases.get(&key).unwrap();
+                (
+                    format!("{:?}", Reg::from(key)),
+                    format!("{:?}", Reg::from(*dest)),
+                )
+            })
+            .collect();
+
+        let blocks = (0..self.num_blocks())
+            .map(|block| {
+                let block = BlockIndex::new(block);
+                let (start, end) = self.block_ranges[block.index()];
+                let insts = (start.index()..end.index())
+                    .map(|inst| {
+                        let iix = InsnIndex::new(inst);
+                        let (op_start, op_end) = self.operand_ranges[inst];
+                        let allocs = regalloc.map(|ra| ra.inst_allocs(iix));
+                        VCodeListingInst {
+                            index: inst,
+                            text: self.insts[inst].pretty_print_inst(&[], &mut state),
+                            operands: self.operands[op_start as usize..op_end as usize]
+                                .iter()
+                                .map(|op| format!("{}", op))
+                                .collect(),
+                            allocated_text: allocs.map(|allocs| {
+                                self.insts[inst].pretty_print_inst(allocs, &mut ra_state)
+                            }),
+                            allocations: allocs
+                                .map(|allocs| allocs.iter().map(|a| format!("{}", a)).collect())
+                                .unwrap_or_default(),
+                        }
+                    })
+                    .collect();
+                VCodeListingBlock {
+                    index: block.index(),
+                    ir_block: self.bindex_to_bb(block),
+                    succs: self.succs(block).iter().map(|b| b.index()).collect(),
+                    inst_range: start.index()..end.index(),
+                    insts,
+                }
+            })
+            .collect();
+
+        let regalloc = regalloc.map(|ra| {
+            let mut stats = VCodeRegallocStats {
+                spillslots: ra.num_spillslots,
+                spills: 0,
+                reloads: 0,
+                moves: 0,
+            };
+            for (_, edit) in &ra.edits {
+                match edit {
+                    Edit::Move { from, to } if from.is_reg() && to.is_stack() => stats.spills += 1,
+                    Edit::Move { from, to } if from.is_stack() && to.is_reg() => stats.reloads += 1,
+                    Edit::Move { .. } => stats.moves += 1,
+                }
+            }
+            stats
+        });
+
+        VCodeListing {
+            entry: self.entry.index(),
+            vreg_aliases,
+            blocks,
+            regalloc,
         }
+    }
+}
 
-        for block in 0..self.num_blocks() {
-            let block = BlockIndex::new(block);
-            writeln!(f, "Block {}:", block.index())?;
-            if let Some(bb) = self.bindex_to_bb(block) {
+impl<I: VCodeInst> fmt::Debug for VCode<I> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.listing(None))
+    }
+}
+
+/// An owned snapshot of a function's VCode, as produced by lowering, suitable
+/// for inspection tooling.
+///
+/// This is retained in the compilation result when requested with
+/// [`Context::set_want_vcode`](crate::Context::set_want_vcode). Its `Display`
+/// implementation produces the same text as the `VCode` debug dump.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
+pub struct VCodeListing {
+    /// Index of the entry block.
+    pub entry: usize,
+    /// VReg aliases created during lowering, as `(alias, target)` pairs.
+    pub vreg_aliases: Vec<(String, String)>,
+    /// Blocks in lowered order.
+    pub blocks: Vec<VCodeListingBlock>,
+    /// Statistics about the moves inserted by register allocation, if its
+    /// results were available.
+    pub regalloc: Option<VCodeRegallocStats>,
+}
+
+impl VCodeListing {
+    /// The total number of lowered instructions, over all blocks.
+    pub fn num_insts(&self) -> usize {
+        self.blocks.iter().map(|b| b.insts.len()).sum()
+    }
+}
+
+/// Statistics about the work done by the register allocator for a
+/// [`VCodeListing`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
+pub struct VCodeRegallocStats {
+    /// Number of spill slots in the frame.
+    pub spillslots: usize,
+    /// Number of inserted moves from a register to a spill slot.
+    pub spills: usize,
+    /// Number of inserted moves from a spill slot to a register.
+    pub reloads: usize,
+    /// Number of other inserted moves.
+    pub moves: usize,
+}
+
+/// A block in a [`VCodeListing`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
+pub struct VCodeListingBlock {
+    /// Index of this block in the VCode.
+    pub index: usize,
+    /// The CLIF block this block was lowered from, if it is not an edge
+    /// block inserted during lowering.
+    pub ir_block: Option<ir::Block>,
+    /// Indices of successor blocks.
+    pub succs: Vec<usize>,
+    /// Range of VCode instruction indices in this block.
+    pub inst_range: core::ops::Range<usize>,
+    /// Instructions in this block.
+    pub insts: Vec<VCodeListingInst>,
+}
+
+/// An instruction in a [`VCodeListing`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "enable-serde", derive(Serialize, Deserialize))]
+pub struct VCodeListingInst {
+    /// Index of this instruction in the VCode.
+    pub index: usize,
+    /// The instruction printed with virtual registers, before register
+    /// allocation.
+    pub text: String,
+    /// The instruction printed with allocated registers, if register
+    /// allocation results were available.
+    pub allocated_text: Option<String>,
+    /// The register allocator operands of this instruction, with their
+    /// constraints.
+    pub operands: Vec<String>,
+    /// The allocation chosen for each operand, if register allocation
+    /// results were available.
+    pub allocations: Vec<String>,
+}
+
+impl fmt::Display for VCodeListing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "VCode {{")?;
+        writeln!(f, "  Entry block: {}", self.entry)?;
+        for (alias, target) in &self.vreg_aliases {
+            writeln!(f, "  {} := {}", alias, target)?;
+        }
+        for block in &self.blocks {
+            writeln!(f, "Block {}:", block.index)?;
+            if let Some(bb) = block.ir_block {
                 writeln!(f, "    (original IR block: {})", bb)?;
             }
-            for succ in self.succs(block) {
-                writeln!(f, "    (successor: Block {})", succ.index())?;
+            for succ in &block.succs {
+                writeln!(f, "    (successor: Block {})", succ)?;
             }
-            let (start, end) = self.block_ranges[block.index()];
             writeln!(
                 f,
                 "    (instruction range: {} .. {})",
-                start.index(),
-                end.index()
+                block.inst_range.start, block.inst_range.end
             )?;
-            for inst in start.index()..end.index() {
-                writeln!(
-                    f,
-                    "  Inst {}: {}",
-                    inst,
-                    self.insts[inst].pretty_print_inst(&[], &mut state)
-                )?;
+            for inst in &block.insts {
+                writeln!(f, "  Inst {}: {}", inst.index, inst.text)?;
             }
         }
-
         writeln!(f, "}}")?;
         Ok(())
     }
@@ -1381,13 +1716,13 @@ pub struct VRegAllocator<I> {
 }
 
 impl<I: VCodeInst> VRegAllocator<I> {
-    /// Make a new VRegAllocator.
-    pub fn new() -> Self {
+    /// Make a new VRegAllocator, reusing the allocations in `scratch`.
+    pub(crate) fn new(scratch: &mut VCodeScratch<I>) -> Self {
         Self {
             next_vreg: first_user_vreg_index(),
-            vreg_types: vec![],
+            vreg_types: core::mem::take(&mut scratch.vreg_types),
             reftyped_vregs_set: FxHashSet::default(),
-            reftyped_vregs: vec![],
+            reftyped_vregs: core::mem::take(&mut scratch.reftyped_vregs),
             _inst: core::marker::PhantomData::default(),
         }
     }
@@ -1561,7 +1896,7 @@ impl VCodeConstantData {
 #[cfg(test)]
 mod test {
     use super::*;
-    use std::mem::size_of;
+    use core::mem::size_of;
 
     #[test]
     fn size_of_constant_structs() {
@@ -1577,4 +1912,194 @@ mod test {
         // With certain versions of Rust, each `HashMap` in `VCodeConstants` occupied at
         // least 48 bytes, making an empty `VCodeConstants` cost 120 bytes.
     }
+
+    /// Build a recursive factorial function: `fact(n) = if n <= 1 { 1 } else { n * fact(n - 1) }`.
+    #[cfg(feature = "x86")]
+    fn factorial() -> ir::Function {
+        use crate::cursor::{Cursor, FuncCursor};
+        use crate::ir::condcodes::IntCC;
+        use crate::ir::{
+            AbiParam, ExtFuncData, ExternalName, Function, InstBuilder, Signature,
+            UserExternalName, UserFuncName,
+        };
+        use crate::isa::CallConv;
+
+        let mut sig = Signature::new(CallConv::SystemV);
+        sig.params.push(AbiParam::new(types::I32));
+        sig.returns.push(AbiParam::new(types::I32));
+        let mut func = Function::with_name_signature(UserFuncName::user(0, 0), sig.clone());
+        let sig_ref = func.import_signature(sig);
+        let name = func.declare_imported_user_function(UserExternalName::new(0, 0));
+        let fact = func.import_function(ExtFuncData {
+            name: ExternalName::user(name),
+            signature: sig_ref,
+            colocated: true,
+        });
+
+        let entry = func.dfg.make_block();
+        let n = func.dfg.append_block_param(entry, types::I32);
+        let base = func.dfg.make_block();
+        let recurse = func.dfg.make_block();
+
+        let mut pos = FuncCursor::new(&mut func);
+        pos.insert_block(entry);
+        let is_base = pos.ins().icmp_imm(IntCC::UnsignedLessThanOrEqual, n, 1);
+        pos.ins().brif(is_base, base, &[], recurse, &[]);
+
+        pos.insert_block(base);
+        let one = pos.ins().iconst(types::I32, 1);
+        pos.ins().return_(&[one]);
+
+        pos.insert_block(recurse);
+        let n_minus_1 = pos.ins().iadd_imm(n, -1);
+        let call = pos.ins().call(fact, &[n_minus_1]);
+        let fact_n_minus_1 = pos.func.dfg.first_result(call);
+        let result = pos.ins().imul(n, fact_n_minus_1);
+        pos.ins().return_(&[result]);
+
+        func
+    }
+
+    #[test]
+    #[cfg(feature = "x86")]
+    fn vcode_listing() {
+        use crate::flowgraph::ControlFlowGraph;
+        use crate::isa::lookup;
+        use crate::settings::{builder, Flags};
+        use crate::Context;
+        use core::str::FromStr;
+        use target_lexicon::triple;
+
+        let isa = lookup(triple!("x86_64"))
+            .expect("expect x86 ISA")
+            .finish(Flags::new(builder()))
+            .expect("expect backend creation to succeed");
+
+        // Without the flag, no listing is retained.
+        let mut context = Context::for_function(factorial());
+        let code = context.compile(&*isa, &mut Default::default()).unwrap();
+        assert!(code.vcode().is_none());
+
+        let func = factorial();
+        let mut context = Context::for_function(func.clone());
+        context.set_want_vcode(true);
+        let code = context.compile(&*isa, &mut Default::default()).unwrap();
+        let listing = code.vcode().expect("a VCode listing").clone();
+
+        // The call is lowered into the block that makes the recursive call.
+        let recurse = func.layout.blocks().nth(2).unwrap();
+        let recurse_block = listing
+            .blocks
+            .iter()
+            .find(|b| b.ir_block == Some(recurse))
+            .expect("the recursive block to be lowered");
+        let call = recurse_block
+            .insts
+            .iter()
+            .find(|i| i.text.starts_with("call"))
+            .expect("a call instruction");
+        assert!(call.allocated_text.is_some());
+        assert_eq!(call.operands.len(), call.allocations.len());
+        assert!(listing
+            .blocks
+            .iter()
+            .filter(|b| b.ir_block != Some(recurse))
+            .all(|b| b.insts.iter().all(|i| !i.text.starts_with("call"))));
+
+        // Every CLIF successor edge is reflected in the VCode successors, possibly through an
+        // edge block inserted during lowering.
+        let cfg = ControlFlowGraph::with_function(&func);
+        let vblock_of = |ir: ir::Block| {
+            listing
+                .blocks
+                .iter()
+                .position(|b| b.ir_block == Some(ir))
+                .unwrap()
+        };
+        for block in func.layout.blocks() {
+            let vblock = &listing.blocks[vblock_of(block)];
+            let mut expected: Vec<_> = cfg.succ_iter(block).map(vblock_of).collect();
+            let mut actual: Vec<_> = vblock
+                .succs
+                .iter()
+                .map(|&succ| match listing.blocks[succ].ir_block {
+                    Some(_) => succ,
+                    None => {
+                        assert_eq!(listing.blocks[succ].succs.len(), 1);
+                        listing.blocks[succ].succs[0]
+                    }
+                })
+                .collect();
+            expected.sort();
+            actual.sort();
+            assert_eq!(expected, actual);
+        }
+
+        // The listing prints like the VCode debug dump.
+        let text = format!("{}", listing);
+        assert!(text.starts_with("VCode {\n  Entry block: 0\n"));
+        assert!(text.contains(&format!("    (original IR block: {})\n", recurse)));
+        assert!(text.contains(&format!("  Inst {}: {}\n", call.index, call.text)));
+    }
+
+    #[test]
+    #[cfg(feature = "x86")]
+    fn inst_offsets() {
+        use crate::binemit::Reloc;
+        use crate::isa::lookup;
+        use crate::settings::{builder, Flags};
+        use crate::Context;
+        use core::str::FromStr;
+        use target_lexicon::triple;
+
+        let isa = lookup(triple!("x86_64"))
+            .expect("expect x86 ISA")
+            .finish(Flags::new(builder()))
+            .expect("expect backend creation to succeed");
+
+        // Without the flag, no offsets are collected.
+        let mut context = Context::for_function(factorial());
+        let code = context.compile(&*isa, &mut Default::default()).unwrap();
+        assert!(code.inst_offsets().is_empty());
+
+        let func = factorial();
+        let mut context = Context::for_function(func.clone());
+        context.set_collect_inst_offsets(true);
+        let code = context.compile(&*isa, &mut Default::default()).unwrap();
+        let offsets = code.inst_offsets();
+
+        // The instructions are in increasing offset order, and cover the whole function: x64
+        // doesn't align blocks, and there are no constants.
+        let mut end = 0;
+        for &(offset, len, _) in offsets {
+            assert_eq!(offset, end);
+            assert!(len > 0);
+            end = offset + CodeOffset::from(len);
+        }
+        assert_eq!(end, code.buffer.total_size());
+
+        // The call relocation is in the code attributed to the CLIF `call`.
+        let call = func
+            .layout
+            .blocks()
+            .flat_map(|block| func.layout.block_insts(block))
+            .find(|&inst| func.dfg.insts[inst].opcode().is_call())
+            .unwrap();
+        let call_reloc = code
+            .buffer
+            .relocs()
+            .iter()
+            .find(|reloc| matches!(reloc.kind, Reloc::X86CallPCRel4 | Reloc::X86CallPLTRel4))
+            .expect("a call relocation");
+        let &(_, _, inst) = offsets
+            .iter()
+            .find(|&&(offset, len, _)| {
+                (offset..offset + CodeOffset::from(len)).contains(&call_reloc.offset)
+            })
+            .unwrap();
+        assert_eq!(inst, Some(call));
+
+        // Some instructions, like the prologue, aren't lowered from any CLIF instruction.
+        assert_eq!(offsets[0].2, None);
+    }
 }
@@ -14,9 +14,9 @@ pub use crate::ir::{
 use crate::isle_common_prelude_methods;
 use crate::machinst::isle::*;
 use crate::trace;
+use core::marker::PhantomData;
 use cranelift_entity::packed_option::ReservedValue;
 use smallvec::{smallvec, SmallVec};
-use std::marker::PhantomData;
 
 #[allow(dead_code)]
 pub type Unit = ();
@@ -129,6 +129,14 @@ impl<'a, 'b, 'c> generated_code::Context for IsleContext<'a, 'b, 'c> {
         value
     }
 
+    fn has_fast_shift_add(&mut self, ty: Type) -> Option<()> {
+        if matches!(self.ctx.isa, Some(isa) if isa.has_fast_shift_add_lowering(ty)) {
+            Some(())
+        } else {
+            None
+        }
+    }
+
     fn splat64(&mut self, val: u64) -> Constant {
         let val = u128::from(val);
         let val = val | (val << 64);
@@ -0,0 +1,359 @@
+//! Instruction scheduling within blocks to reduce register pressure.
+//!
+//! Code which computes many values before using any of them, such as a series of loads followed
+//! by the arithmetic consuming them, keeps all of the values live at once, and makes the register
+//! allocator spill them when there are more values than registers. This pass reorders the
+//! instructions of each block with a list scheduler which prefers the instructions ending the
+//! live ranges of their arguments, and delays the instructions defining new values until these
+//! are needed, so that values are computed close to their uses.
+//!
+//! The pass only changes the order of instructions within a block, and obeys these rules:
+//!
+//! - An instruction is placed after the instructions of the block defining its arguments.
+//! - Instructions with side effects, as well as instructions which may trap or read state that
+//!   other instructions may change, such as the pinned register or the stack pointer, keep their
+//!   order relative to each other.
+//! - Loads which can't trap may be reordered with other loads, which never changes the values
+//!   they read, but not with the instructions of the previous rule, which may store to the same
+//!   memory.
+//! - Instructions without side effects may be moved anywhere their arguments allow.
+//! - The terminator stays the last instruction of the block.
+//!
+//! The instructions keep their source locations, and the pass doesn't change the control flow
+//! graph, so the dominator tree stays valid.
+
+use crate::entity::SecondaryMap;
+use crate::fx::FxHashMap;
+use crate::inst_predicates::has_side_effect;
+use crate::ir::{Block, Function, Inst, InstructionData, Opcode, Value, ValueDef};
+use crate::timing;
+use crate::trace;
+use alloc::vec::Vec;
+use smallvec::SmallVec;
+
+/// How an instruction may be reordered with others.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Class {
+    /// Keeps its order relative to other `Ordered` instructions, and to `Load`s.
+    Ordered,
+    /// Keeps its order relative to `Ordered` instructions.
+    Load,
+    /// May be moved anywhere.
+    Free,
+}
+
+fn class(func: &Function, inst: Inst) -> Class {
+    let data = &func.dfg.insts[inst];
+    match data.opcode() {
+        // These read state which other instructions change.
+        Opcode::GetPinnedReg
+        | Opcode::GetFramePointer
+        | Opcode::GetStackPointer
+        | Opcode::GetReturnAddress => return Class::Ordered,
+        _ => {}
+    }
+    if has_side_effect(func, inst) {
+        return Class::Ordered;
+    }
+    match data {
+        // `has_side_effect` covers loads which may trap.
+        InstructionData::Load { .. } => Class::Load,
+        _ if data.opcode().can_load() || data.opcode().can_store() => Class::Ordered,
+        _ => Class::Free,
+    }
+}
+
+/// Reorder the instructions within each block of `func` to reduce register pressure.
+pub fn do_pressure_scheduling(func: &mut Function) {
+    let _tt = timing::pressure_scheduling();
+
+    // The number of uses of each value in the whole function, to know which values die within
+    // the block defining them.
+    let mut uses = SecondaryMap::<Value, u32>::with_capacity(func.dfg.num_values());
+    for block in func.layout.blocks() {
+        for inst in func.layout.block_insts(block) {
+            for arg in func.dfg.inst_values(inst) {
+                uses[func.dfg.resolve_aliases(arg)] += 1;
+            }
+        }
+    }
+
+    let mut scheduler = Scheduler::default();
+    let blocks: Vec<Block> = func.layout.blocks().collect();
+    for block in blocks {
+        scheduler.schedule_block(func, block, &uses);
+    }
+}
+
+/// A node of the dependency graph of a block.
+#[derive(Default)]
+struct Node {
+    inst: Option<Inst>,
+    /// The number of unscheduled instructions this one depends on.
+    preds: u32,
+    /// The instructions depending on this one.
+    succs: SmallVec<[u32; 4]>,
+    /// The arguments of the instruction, repeated as often as they are used.
+    args: SmallVec<[Value; 4]>,
+    /// The number of results of the instruction which are used.
+    live_defs: u32,
+}
+
+/// Reused state of the scheduler.
+#[derive(Default)]
+struct Scheduler {
+    nodes: Vec<Node>,
+    /// The number of unscheduled uses of each value defined in the block whose uses are all in
+    /// the block.
+    remaining: FxHashMap<Value, u32>,
+    ready: Vec<u32>,
+    order: Vec<Inst>,
+}
+
+impl Scheduler {
+    fn schedule_block(
+        &mut self,
+        func: &mut Function,
+        block: Block,
+        uses: &SecondaryMap<Value, u32>,
+    ) {
+        let terminator = match func.layout.last_inst(block) {
+            Some(inst) if func.dfg.insts[inst].opcode().is_terminator() => inst,
+            _ => return,
+        };
+        let insts: Vec<Inst> = func
+            .layout
+            .block_insts(block)
+            .take_while(|&inst| inst != terminator)
+            .collect();
+        if insts.len() < 3 {
+            return;
+        }
+
+        // Build the dependency graph.
+        self.nodes.clear();
+        self.remaining.clear();
+        let mut index = FxHashMap::default();
+        let mut last_ordered: Option<u32> = None;
+        let mut loads_since_ordered: SmallVec<[u32; 8]> = SmallVec::new();
+        for (i, &inst) in insts.iter().enumerate() {
+            let i = i as u32;
+            index.insert(inst, i);
+            let mut preds: SmallVec<[u32; 8]> = SmallVec::new();
+            let mut args = SmallVec::new();
+            for arg in func.dfg.inst_values(inst) {
+                let arg = func.dfg.resolve_aliases(arg);
+                args.push(arg);
+                if let ValueDef::Result(def, _) = func.dfg.value_def(arg) {
+                    if let Some(&d) = index.get(&def) {
+                        preds.push(d);
+                    }
+                }
+            }
+            match class(func, inst) {
+                Class::Ordered => {
+                    preds.extend(last_ordered);
+                    preds.extend(loads_since_ordered.drain(..));
+                    last_ordered = Some(i);
+                }
+                Class::Load => {
+                    preds.extend(last_ordered);
+                    loads_since_ordered.push(i);
+                }
+                Class::Free => {}
+            }
+            preds.sort_unstable();
+            preds.dedup();
+            for &p in &preds {
+                self.nodes[p as usize].succs.push(i);
+            }
+            let live_defs = func
+                .dfg
+                .inst_results(inst)
+                .iter()
+                .filter(|&&v| uses[v] > 0)
+                .count() as u32;
+            self.nodes.push(Node {
+                inst: Some(inst),
+                preds: preds.len() as u32,
+                succs: SmallVec::new(),
+                args,
+                live_defs,
+            });
+        }
+
+        // Find the values which die within the block: those defined in it, or its parameters,
+        // which are only used by the instructions being scheduled.
+        let mut block_uses: FxHashMap<Value, u32> = FxHashMap::default();
+        for node in &self.nodes {
+            for &arg in &node.args {
+                *block_uses.entry(arg).or_default() += 1;
+            }
+        }
+        for (value, count) in block_uses {
+            let defined_here = match func.dfg.value_def(value) {
+                ValueDef::Result(def, _) => index.contains_key(&def),
+                ValueDef::Param(b, _) => b == block,
+                _ => false,
+            };
+            if defined_here && uses[value] == count {
+                self.remaining.insert(value, count);
+            }
+        }
+
+        // Schedule the instructions.
+        self.ready.clear();
+        self.ready.extend(
+            self.nodes
+                .iter()
+                .enumerate()
+                .filter(|(_, node)| node.preds == 0)
+                .map(|(i, _)| i as u32),
+        );
+        self.order.clear();
+        while !self.ready.is_empty() {
+            let (pos, _) = self
+                .ready
+                .iter()
+                .enumerate()
+                .max_by_key(|&(_, &i)| (self.score(i), core::cmp::Reverse(i)))
+                .unwrap();
+            let i = self.ready.swap_remove(pos) as usize;
+            let node = &mut self.nodes[i];
+            self.order.push(node.inst.take().unwrap());
+            for arg in &node.args {
+                if let Some(remaining) = self.remaining.get_mut(arg) {
+                    *remaining -= 1;
+                }
+            }
+            let succs = core::mem::take(&mut node.succs);
+            for succ in succs {
+                let succ_node = &mut self.nodes[succ as usize];
+                succ_node.preds -= 1;
+                if succ_node.preds == 0 {
+                    self.ready.push(succ);
+                }
+            }
+        }
+        debug_assert_eq!(self.order.len(), insts.len());
+
+        if self.order == insts {
+            return;
+        }
+        trace!("Rescheduling {}: {:?}", block, self.order);
+        for &inst in &self.order {
+            func.layout.remove_inst(inst);
+            func.layout.insert_inst(inst, terminator);
+        }
+    }
+
+    /// The decrease in the number of live values caused by scheduling node `i` next.
+    fn score(&self, i: u32) -> i64 {
+        let node = &self.nodes[i as usize];
+        let mut kills = 0;
+        for (j, arg) in node.args.iter().enumerate() {
+            // Count each argument once.
+            if node.args[..j].contains(arg) {
+                continue;
+            }
+            let count = node.args.iter().filter(|&a| a == arg).count() as u32;
+            if self.remaining.get(arg) == Some(&count) {
+                kills += 1;
+            }
+        }
+        kills - i64::from(node.live_defs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cursor::{Cursor, FuncCursor};
+    use crate::ir::{types, AbiParam, InstBuilder, MemFlags, Signature};
+    use crate::isa::CallConv;
+
+    /// Build a function loading `n` values before multiplying all of them.
+    fn loads_then_uses(n: i32) -> Function {
+        let mut sig = Signature::new(CallConv::SystemV);
+        sig.params.push(AbiParam::new(types::I64));
+        sig.returns.push(AbiParam::new(types::I64));
+        let mut func = Function::new();
+        func.signature = sig;
+        let mut pos = FuncCursor::new(&mut func);
+        let block = pos.func.dfg.make_block();
+        pos.insert_block(block);
+        let base = pos.func.dfg.append_block_param(block, types::I64);
+        let flags = MemFlags::trusted();
+        let values: Vec<Value> = (0..n)
+            .map(|i| pos.ins().load(types::I64, flags, base, 8 * i))
+            .collect();
+        let mut product = values[0];
+        for &value in &values[1..] {
+            let squared = pos.ins().imul(value, value);
+            product = pos.ins().iadd(product, squared);
+        }
+        pos.ins().return_(&[product]);
+        func
+    }
+
+    #[test]
+    #[cfg(feature = "x86")]
+    fn fewer_spills() {
+        use crate::isa::lookup;
+        use crate::settings::{builder, Configurable, Flags};
+        use crate::Context;
+        use core::str::FromStr;
+        use target_lexicon::triple;
+
+        let spills = |scheduling: &str| {
+            let mut flags = builder();
+            flags.set("opt_level", "speed").unwrap();
+            flags.set("enable_pressure_scheduling", scheduling).unwrap();
+            let isa = lookup(triple!("x86_64"))
+                .unwrap()
+                .finish(Flags::new(flags))
+                .unwrap();
+            let mut context = Context::for_function(loads_then_uses(40));
+            context.set_want_vcode(true);
+            let code = context.compile(&*isa, &mut Default::default()).unwrap();
+            code.vcode().unwrap().regalloc.unwrap().spills
+        };
+        let unscheduled = spills("false");
+        let scheduled = spills("true");
+        assert!(unscheduled > 10, "{} spills", unscheduled);
+        assert_eq!(scheduled, 0);
+    }
+
+    #[test]
+    fn dependencies_are_respected() {
+        let mut func = loads_then_uses(10);
+        do_pressure_scheduling(&mut func);
+        crate::verifier::verify_function(
+            &func,
+            &crate::settings::Flags::new(crate::settings::builder()),
+        )
+        .unwrap();
+        let block = func.layout.entry_block().unwrap();
+        let insts: Vec<Inst> = func.layout.block_insts(block).collect();
+        // Loaded values are now squared soon after being loaded, instead of all being live at
+        // once.
+        let mut live_loads = 0;
+        let mut max_live_loads = 0;
+        for &inst in &insts {
+            match func.dfg.insts[inst].opcode() {
+                Opcode::Load => live_loads += 1,
+                Opcode::Imul => live_loads -= 1,
+                _ => {}
+            }
+            max_live_loads = max_live_loads.max(live_loads);
+        }
+        assert!(
+            max_live_loads <= 3,
+            "{} loaded values live at once",
+            max_live_loads
+        );
+        assert!(func.dfg.insts[*insts.last().unwrap()]
+            .opcode()
+            .is_terminator());
+    }
+}
@@ -0,0 +1,158 @@
+//! Removal of checks whose outcome is known.
+//!
+//! Uses [`ValueFacts`] to find integer comparisons which always have the same result, branches
+//! and conditional traps which always go the same way, and extensions which restore the value a
+//! reduction truncated. Such
+//! checks are common in code produced from bounds-checked and overflow-checked source languages,
+//! after the source already tested the same condition.
+
+use crate::analysis::ValueFacts;
+use crate::dominator_tree::DominatorTree;
+use crate::ir::{BlockCall, Function, Inst, InstBuilder, InstructionData, Opcode, Value};
+use crate::timing;
+use crate::trace;
+use alloc::vec::Vec;
+
+/// What to do with a redundant instruction.
+enum Rewrite {
+    /// Replace a comparison with its constant result.
+    Fold(Inst, u128),
+    /// Replace a conditional branch with a jump to the destination it always takes.
+    Jump(Inst, BlockCall),
+    /// Remove a conditional trap which never traps.
+    RemoveTrap(Inst),
+    /// Replace the result of an extension with the value it always equals.
+    Alias(Inst, Value),
+}
+
+/// Remove the checks of `func` whose outcome is known.
+///
+/// Returns whether any branch was replaced with a jump, in which case the control flow graph
+/// and the dominator tree need to be recomputed.
+pub fn do_remove_redundant_checks(func: &mut Function, domtree: &DominatorTree) -> bool {
+    let _tt = timing::redundant_checks();
+    debug_assert!(domtree.is_valid());
+
+    // All rewrites are found before any is made, so that the facts describe one unchanged
+    // function.
+    let mut rewrites = Vec::new();
+    {
+        let facts = ValueFacts::new(func, domtree);
+        let dfg = &func.dfg;
+        for block in func.layout.blocks() {
+            for inst in func.layout.block_insts(block) {
+                match dfg.insts[inst] {
+                    InstructionData::IntCompare { .. } | InstructionData::IntCompareImm { .. }
+                        if dfg.value_type(dfg.inst_args(inst)[0]).is_int() =>
+                    {
+                        let result = dfg.first_result(inst);
+                        if let Some(value) = facts.range_of(result, inst).as_constant() {
+                            rewrites.push(Rewrite::Fold(inst, value));
+                        }
+                    }
+                    InstructionData::Brif { arg, blocks, .. } => {
+                        match facts.range_of(arg, inst).as_constant() {
+                            Some(0) => rewrites.push(Rewrite::Jump(inst, blocks[1])),
+                            Some(_) => rewrites.push(Rewrite::Jump(inst, blocks[0])),
+                            None => {}
+                        }
+                    }
+                    InstructionData::CondTrap { opcode, arg, .. }
+                        if dfg.value_type(arg).is_int() =>
+                    {
+                        let range = facts.range_of(arg, inst);
+                        let never_traps = match opcode {
+                            Opcode::Trapz => range.is_nonzero(),
+                            Opcode::Trapnz => range.as_constant() == Some(0),
+                            _ => false,
+                        };
+                        if never_traps {
+                            rewrites.push(Rewrite::RemoveTrap(inst));
+                        }
+                    }
+                    InstructionData::Unary {
+                        opcode: opcode @ (Opcode::Uextend | Opcode::Sextend),
+                        arg,
+                    } => {
+                        if let Some(original) = extended_reduction(func, &facts, inst, opcode, arg)
+                        {
+                            rewrites.push(Rewrite::Alias(inst, original));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    let mut changed_cfg = false;
+    for rewrite in rewrites {
+        match rewrite {
+            Rewrite::Fold(inst, value) => {
+                trace!("Folding {} to {}", func.dfg.display_inst(inst), value);
+                let ty = func.dfg.value_type(func.dfg.first_result(inst));
+                func.dfg.replace(inst).iconst(ty, value as i64);
+            }
+            Rewrite::Jump(inst, destination) => {
+                trace!("Replacing {} with a jump", func.dfg.display_inst(inst));
+                func.dfg.insts[inst] = InstructionData::Jump {
+                    opcode: Opcode::Jump,
+                    destination,
+                };
+                changed_cfg = true;
+            }
+            Rewrite::RemoveTrap(inst) => {
+                trace!("Removing {}", func.dfg.display_inst(inst));
+                func.layout.remove_inst(inst);
+            }
+            Rewrite::Alias(inst, original) => {
+                trace!(
+                    "Replacing {} with {}",
+                    func.dfg.display_inst(inst),
+                    original
+                );
+                let result = func.dfg.first_result(inst);
+                func.dfg.clear_results(inst);
+                func.dfg.change_to_alias(result, original);
+                func.layout.remove_inst(inst);
+            }
+        }
+    }
+    changed_cfg
+}
+
+/// If `inst` extends the result of an `ireduce` back to the type of the reduced value, and the
+/// reduction never changes that value wherever `inst` is executed, return the reduced value.
+fn extended_reduction(
+    func: &Function,
+    facts: &ValueFacts,
+    inst: Inst,
+    opcode: Opcode,
+    arg: Value,
+) -> Option<Value> {
+    let dfg = &func.dfg;
+    let reduce = dfg.value_def(arg).inst()?;
+    let original = match dfg.insts[reduce] {
+        InstructionData::Unary {
+            opcode: Opcode::Ireduce,
+            arg,
+        } => arg,
+        _ => return None,
+    };
+    if dfg.value_type(original) != dfg.value_type(dfg.first_result(inst)) {
+        return None;
+    }
+    let bits = dfg.value_type(arg).bits();
+    let range = facts.range_of(original, inst);
+    let fits = if opcode == Opcode::Uextend {
+        range.umax() >> bits == 0
+    } else {
+        let bound = 1i128 << (bits - 1);
+        range.smin() >= -bound && range.smax() < bound
+    };
+    if fits {
+        Some(original)
+    } else {
+        None
+    }
+}
@@ -3,7 +3,8 @@
 use regalloc2::checker::CheckerErrors;
 
 use crate::{ir::Function, verifier::VerifierErrors};
-use std::string::String;
+use alloc::string::String;
+use alloc::vec::Vec;
 
 /// A compilation error.
 ///
@@ -35,12 +36,23 @@ pub enum CodegenError {
     /// unsupported by a given target backend.
     Unsupported(String),
 
+    /// Instructions of the function which the target ISA can't compile, all found before
+    /// lowering, in layout order.
+    UnsupportedInstructions(Vec<crate::isa::UnsupportedInstruction>),
+
     /// A failure to map Cranelift register representation to a DWARF register representation.
     #[cfg(feature = "unwind")]
     RegisterMappingError(crate::isa::unwind::systemv::RegisterMappingError),
 
     /// Register allocator internal error discovered by the symbolic checker.
     Regalloc(CheckerErrors),
+
+    /// The [`CompileBudget`](crate::CompileBudget) of the compilation was used up during the
+    /// pass `phase`.
+    BudgetExceeded {
+        /// The pass which used up the budget.
+        phase: crate::timing::Pass,
+    },
 }
 
 /// A convenient alias for a `Result` that uses `CodegenError` as the error type.
@@ -48,30 +60,44 @@ pub type CodegenResult<T> = Result<T, CodegenError>;
 
 // This is manually implementing Error and Display instead of using thiserror to reduce the amount
 // of dependencies used by Cranelift.
+#[cfg(feature = "std")]
 impl std::error::Error for CodegenError {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             CodegenError::Verifier(source) => Some(source),
             CodegenError::ImplLimitExceeded { .. }
             | CodegenError::CodeTooLarge { .. }
-            | CodegenError::Unsupported { .. } => None,
+            | CodegenError::Unsupported { .. }
+            | CodegenError::UnsupportedInstructions(..) => None,
             #[cfg(feature = "unwind")]
             CodegenError::RegisterMappingError { .. } => None,
             CodegenError::Regalloc(..) => None,
+            CodegenError::BudgetExceeded { .. } => None,
         }
     }
 }
 
-impl std::fmt::Display for CodegenError {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+impl core::fmt::Display for CodegenError {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
         match self {
             CodegenError::Verifier(_) => write!(f, "Verifier errors"),
             CodegenError::ImplLimitExceeded => write!(f, "Implementation limit exceeded"),
             CodegenError::CodeTooLarge => write!(f, "Code for function is too large"),
             CodegenError::Unsupported(feature) => write!(f, "Unsupported feature: {}", feature),
+            CodegenError::UnsupportedInstructions(insts) => {
+                write!(f, "Unsupported instructions:")?;
+                for (i, inst) in insts.iter().enumerate() {
+                    let separator = if i == 0 { " " } else { ", " };
+                    write!(f, "{}`{}` in {}", separator, inst.text, inst.block)?;
+                }
+                Ok(())
+            }
             #[cfg(feature = "unwind")]
             CodegenError::RegisterMappingError(_0) => write!(f, "Register mapping error"),
             CodegenError::Regalloc(errors) => write!(f, "Regalloc validation errors: {:?}", errors),
+            CodegenError::BudgetExceeded { phase } => {
+                write!(f, "Compilation budget exceeded during: {}", phase)
+            }
         }
     }
 }
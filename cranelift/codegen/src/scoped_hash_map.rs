@@ -13,6 +13,16 @@ use crate::fx::FxHasher;
 #[cfg(not(feature = "std"))]
 type Hasher = core::hash::BuildHasherDefault<FxHasher>;
 
+// hashbrown's entry types are additionally parameterized by the hasher.
+#[cfg(feature = "std")]
+type MapOccupiedEntry<'a, K, V> = super::hash_map::OccupiedEntry<'a, K, Val<V>>;
+#[cfg(not(feature = "std"))]
+type MapOccupiedEntry<'a, K, V> = super::hash_map::OccupiedEntry<'a, K, Val<V>, Hasher>;
+#[cfg(feature = "std")]
+type MapVacantEntry<'a, K, V> = super::hash_map::VacantEntry<'a, K, Val<V>>;
+#[cfg(not(feature = "std"))]
+type MapVacantEntry<'a, K, V> = super::hash_map::VacantEntry<'a, K, Val<V>, Hasher>;
+
 struct Val<V> {
     value: V,
     level: u32,
@@ -21,7 +31,7 @@ struct Val<V> {
 
 /// A view into an occupied entry in a `ScopedHashMap`. It is part of the `Entry` enum.
 pub struct OccupiedEntry<'a, K: 'a, V: 'a> {
-    entry: super::hash_map::OccupiedEntry<'a, K, Val<V>>,
+    entry: MapOccupiedEntry<'a, K, V>,
 }
 
 impl<'a, K, V> OccupiedEntry<'a, K, V> {
@@ -41,11 +51,11 @@ pub struct VacantEntry<'a, K: 'a, V: 'a> {
 /// Where to insert from a `VacantEntry`. May be vacant or occupied in
 /// the underlying map because of lazy (generation-based) deletion.
 enum InsertLoc<'a, K: 'a, V: 'a> {
-    Vacant(super::hash_map::VacantEntry<'a, K, Val<V>>),
-    Occupied(super::hash_map::OccupiedEntry<'a, K, Val<V>>),
+    Vacant(MapVacantEntry<'a, K, V>),
+    Occupied(MapOccupiedEntry<'a, K, V>),
 }
 
-impl<'a, K, V> VacantEntry<'a, K, V> {
+impl<'a, K: Hash, V> VacantEntry<'a, K, V> {
     /// Sets the value of the entry with the `VacantEntry`'s key.
     pub fn insert(self, value: V) {
         let val = Val {
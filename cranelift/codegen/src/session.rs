@@ -0,0 +1,94 @@
+//! Scratch allocations reused across compilations.
+//!
+//! A [`Context`] keeps the allocations of the IR and of its analyses from one function to the
+//! next, but the backend allocates its lowering state, the lowered VCode and the bookkeeping of
+//! its `MachBuffer` anew for each function. For small functions, these allocations account for
+//! a large part of the compilation time. A [`CompilationSession`] keeps them between
+//! compilations instead.
+
+use crate::budget::BudgetTracker;
+use crate::isa::TargetIsa;
+use crate::machinst::{CompiledCode, LowerScratch, MachBufferScratch, VCodeInst, VCodeScratch};
+use crate::result::CompileResult;
+use crate::Context;
+use alloc::boxed::Box;
+use core::any::Any;
+use cranelift_control::ControlPlane;
+
+/// Scratch allocations reused across compilations.
+///
+/// Compiling with [`CompilationSession::compile`] instead of [`Context::compile`] reuses the
+/// allocations made by the backend while compiling the previous function, which makes compiling
+/// many small functions faster. [`Context::compile`] uses a new session for each compilation.
+///
+/// The allocations are only reused by compilations for the same backend. The emitted code, which
+/// is owned by the [`Context`], and the state of the register allocator are allocated for each
+/// compilation.
+///
+/// A session is meant to be used by a single compilation thread, along with its `Context`: keep
+/// one session per thread rather than sharing one.
+#[derive(Default)]
+pub struct CompilationSession {
+    /// The [`BackendScratch`] of the backend used by the last compilation.
+    backend: Option<Box<dyn Any + Send>>,
+
+    /// The remaining budget of the current compilation, handed to the backend along with its
+    /// scratch allocations.
+    pub(crate) budget: BudgetTracker,
+}
+
+impl CompilationSession {
+    /// Create a new session, without any allocations.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compile the function of `ctx` for `isa`, reusing the allocations of this session.
+    ///
+    /// This produces the same code as [`Context::compile`].
+    pub fn compile<'a>(
+        &mut self,
+        ctx: &'a mut Context,
+        isa: &dyn TargetIsa,
+        ctrl_plane: &mut ControlPlane,
+    ) -> CompileResult<'a, &'a CompiledCode> {
+        ctx.compile_in(self, isa, ctrl_plane)
+    }
+
+    /// The scratch allocations of the backend whose instructions are `I`, replacing those of the
+    /// previously used backend, if any, along with the budget of the current compilation.
+    pub(crate) fn backend_scratch<I: VCodeInst + 'static>(&mut self) -> &mut BackendScratch<I>
+    where
+        BackendScratch<I>: Send,
+    {
+        let reusable = matches!(&self.backend, Some(scratch) if scratch.is::<BackendScratch<I>>());
+        if !reusable {
+            self.backend = Some(Box::new(BackendScratch::<I>::default()));
+        }
+        let scratch: &mut BackendScratch<I> =
+            self.backend.as_mut().unwrap().downcast_mut().unwrap();
+        scratch.budget = core::mem::take(&mut self.budget);
+        scratch
+    }
+}
+
+/// The scratch allocations of a backend whose instructions are `I`.
+///
+/// Each part is moved out of the session while in use, and moved back once emptied.
+pub(crate) struct BackendScratch<I: VCodeInst> {
+    pub lower: LowerScratch<I>,
+    pub vcode: VCodeScratch<I>,
+    pub buffer: MachBufferScratch<I>,
+    pub budget: BudgetTracker,
+}
+
+impl<I: VCodeInst> Default for BackendScratch<I> {
+    fn default() -> Self {
+        Self {
+            lower: LowerScratch::default(),
+            vcode: VCodeScratch::default(),
+            buffer: MachBufferScratch::default(),
+            budget: BudgetTracker::default(),
+        }
+    }
+}
@@ -301,6 +301,7 @@ pub enum SetError {
     BadValue(String),
 }
 
+#[cfg(feature = "std")]
 impl std::error::Error for SetError {}
 
 impl fmt::Display for SetError {
@@ -521,6 +522,8 @@ mod tests {
         let actual = f.to_string();
         let expected = r#"[shared]
 opt_level = "none"
+loop_unroll_budget = 64
+loop_partial_unroll_factor = 0
 tls_model = "none"
 libcall_call_conv = "isa_default"
 probestack_size_log2 = 12
@@ -529,6 +532,9 @@ bb_padding_log2_minus_one = 0
 regalloc_checker = false
 regalloc_verbose_logs = false
 enable_alias_analysis = true
+enable_pressure_scheduling = false
+enable_loop_unrolling = false
+enable_redundant_check_elimination = false
 enable_verifier = true
 is_pic = false
 use_colocated_libcalls = false
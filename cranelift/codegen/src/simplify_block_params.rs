@@ -0,0 +1,204 @@
+//! Removal of the parameters of blocks with a single predecessor.
+//!
+//! A block which is only reached through a single edge always receives the arguments of that
+//! edge, so its parameters can be replaced with aliases of them. Frontends create such blocks for
+//! joins of control flow which turn out to have a single arm, and other passes leave them behind
+//! when they remove edges.
+//!
+//! The pass also threads jumps through blocks which contain nothing but a `jump`, which removes
+//! those blocks and can leave their successors with a single predecessor. Both are repeated until
+//! neither finds anything to do.
+
+use crate::cursor::{Cursor, FuncCursor};
+use crate::dominator_tree::DominatorTree;
+use crate::entity::EntitySet;
+use crate::flowgraph::ControlFlowGraph;
+use crate::ir::{Block, BlockCall, Function, Inst, InstructionData, Value};
+use crate::timing;
+use crate::trace;
+use smallvec::SmallVec;
+
+/// Simplify the parameters of the blocks of `func` with a single predecessor.
+///
+/// The blocks unreachable according to `domtree` are left alone, so unreachable code must have
+/// been removed first.
+///
+/// Returns whether any jump was threaded, in which case `cfg` is up to date but the dominator
+/// tree needs to be recomputed.
+pub fn do_simplify_block_params(
+    func: &mut Function,
+    cfg: &mut ControlFlowGraph,
+    domtree: &DominatorTree,
+) -> bool {
+    let _tt = timing::simplify_block_params();
+    debug_assert!(cfg.is_valid());
+    debug_assert!(domtree.is_valid());
+
+    let mut threaded = false;
+    loop {
+        let aliased = alias_params(func, cfg, domtree);
+        let threaded_now = thread_jumps(func, cfg, domtree);
+        threaded |= threaded_now;
+        if !aliased && !threaded_now {
+            return threaded;
+        }
+    }
+}
+
+/// Returns the only edge to `block`, if it has exactly one.
+fn single_edge(func: &Function, cfg: &ControlFlowGraph, block: Block) -> Option<(Inst, usize)> {
+    let mut preds = cfg.pred_iter(block);
+    let pred = preds.next()?;
+    if preds.next().is_some() || pred.block == block {
+        return None;
+    }
+    let dests = func.dfg.insts[pred.inst].branch_destination(&func.dfg.jump_tables);
+    let mut edges = dests
+        .iter()
+        .enumerate()
+        .filter(|(_, call)| call.block(&func.dfg.value_lists) == block);
+    let (index, _) = edges.next()?;
+    if edges.next().is_some() {
+        // The block is reached through several edges of the same branch, which may pass
+        // different arguments.
+        return None;
+    }
+    Some((pred.inst, index))
+}
+
+/// Replace the parameters of the blocks reached through a single edge with aliases of the
+/// arguments of that edge.
+fn alias_params(func: &mut Function, cfg: &ControlFlowGraph, domtree: &DominatorTree) -> bool {
+    let entry = func.layout.entry_block();
+    let mut changed = false;
+    let mut block = entry;
+    while let Some(b) = block {
+        block = func.layout.next_block(b);
+        if Some(b) == entry || !domtree.is_reachable(b) || func.dfg.num_block_params(b) == 0 {
+            continue;
+        }
+        let (inst, index) = match single_edge(func, cfg, b) {
+            Some(edge) => edge,
+            None => continue,
+        };
+
+        trace!("Aliasing the parameters of {}", b);
+        let dfg = &mut func.dfg;
+        let call = &mut dfg.insts[inst].branch_destination_mut(&mut dfg.jump_tables)[index];
+        let args: SmallVec<[Value; 8]> =
+            call.args_slice(&dfg.value_lists).iter().copied().collect();
+        call.clear(&mut dfg.value_lists);
+        let params: SmallVec<[Value; 8]> = dfg.block_params(b).iter().copied().collect();
+        dfg.detach_block_params(b);
+        for (param, arg) in params.into_iter().zip(args) {
+            dfg.change_to_alias(param, arg);
+        }
+        changed = true;
+    }
+    changed
+}
+
+/// Returns the instruction of `block` if it is its only one and a `jump` elsewhere.
+fn only_jump(func: &Function, block: Block) -> Option<Inst> {
+    let inst = func.layout.first_inst(block)?;
+    match func.dfg.insts[inst] {
+        InstructionData::Jump { destination, .. }
+            if func.layout.last_inst(block) == Some(inst)
+                && destination.block(&func.dfg.value_lists) != block =>
+        {
+            Some(inst)
+        }
+        _ => None,
+    }
+}
+
+/// Find the values used anywhere except by the `jump` of a block containing only that `jump`,
+/// when they are parameters of that block.
+///
+/// Jump threading moves these uses to the predecessors of the block, so a block whose
+/// parameters are not in this set can be removed. Threading keeps the set conservative: every
+/// use it creates is either of a value in the set, or of a parameter of a block in the
+/// predecessor's own `jump`.
+fn used_values(func: &Function) -> EntitySet<Value> {
+    let mut used = EntitySet::with_capacity(func.dfg.num_values());
+    for block in func.layout.blocks() {
+        let only_jump = only_jump(func, block);
+        let params = func.dfg.block_params(block);
+        for inst in func.layout.block_insts(block) {
+            for value in func.dfg.inst_values(inst) {
+                let value = func.dfg.resolve_aliases(value);
+                if Some(inst) != only_jump || !params.contains(&value) {
+                    used.insert(value);
+                }
+            }
+        }
+    }
+    used
+}
+
+/// Redirect the edges to blocks containing only a `jump` to the destination of that jump, and
+/// remove those blocks.
+fn thread_jumps(func: &mut Function, cfg: &mut ControlFlowGraph, domtree: &DominatorTree) -> bool {
+    let entry = func.layout.entry_block();
+    let used = used_values(func);
+    let mut changed = false;
+    let mut pos = FuncCursor::new(func);
+    while let Some(block) = pos.next_block() {
+        if Some(block) == entry || !domtree.is_reachable(block) {
+            continue;
+        }
+        let inst = match only_jump(pos.func, block) {
+            Some(inst) => inst,
+            None => continue,
+        };
+        let params: SmallVec<[Value; 8]> =
+            pos.func.dfg.block_params(block).iter().copied().collect();
+        if params.iter().any(|&param| used.contains(param)) {
+            continue;
+        }
+        let destination = match pos.func.dfg.insts[inst] {
+            InstructionData::Jump { destination, .. } => destination,
+            _ => unreachable!(),
+        };
+
+        trace!("Threading jumps through {}", block);
+        let target = destination.block(&pos.func.dfg.value_lists);
+        let target_args: SmallVec<[Value; 8]> = destination
+            .args_slice(&pos.func.dfg.value_lists)
+            .iter()
+            .map(|&arg| pos.func.dfg.resolve_aliases(arg))
+            .collect();
+        let preds: SmallVec<[(Block, Inst); 4]> = cfg
+            .pred_iter(block)
+            .map(|pred| (pred.block, pred.inst))
+            .collect();
+        for &(_, pred_inst) in &preds {
+            let dfg = &mut pos.func.dfg;
+            for call in dfg.insts[pred_inst].branch_destination_mut(&mut dfg.jump_tables) {
+                if call.block(&dfg.value_lists) != block {
+                    continue;
+                }
+                let args: SmallVec<[Value; 8]> = target_args
+                    .iter()
+                    .map(|&arg| match params.iter().position(|&param| param == arg) {
+                        Some(i) => call.args_slice(&dfg.value_lists)[i],
+                        None => arg,
+                    })
+                    .collect();
+                call.clear(&mut dfg.value_lists);
+                *call = BlockCall::new(target, &args, &mut dfg.value_lists);
+            }
+        }
+        for &(pred_block, _) in &preds {
+            cfg.recompute_block(pos.func, pred_block);
+        }
+
+        // Nothing reaches the block anymore.
+        pos.func.layout.remove_inst(inst);
+        cfg.recompute_block(pos.func, block);
+        pos.prev_block();
+        pos.func.layout.remove_block(block);
+        changed = true;
+    }
+    changed
+}
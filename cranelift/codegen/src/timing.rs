@@ -1,13 +1,19 @@
 //! Pass timing.
 //!
 //! This modules provides facilities for timing the execution of individual compilation passes.
+//!
+//! Timing requires a clock and thread-local storage, so it is only available with the `std`
+//! feature. Without it, the pass functions return a dummy token and nothing is measured.
+//!
+//! With the `trace-log` feature, each pass also runs in a `tracing` span named `pass`, whose
+//! `name` field is the name of the pass.
 
+use alloc::boxed::Box;
+use core::any::Any;
 use core::fmt;
-use std::any::Any;
-use std::boxed::Box;
-use std::cell::{Cell, RefCell};
-use std::mem;
-use std::time::{Duration, Instant};
+
+#[cfg(feature = "std")]
+pub use enabled::*;
 
 // Each pass that can be timed is predefined with the `define_passes!` macro. Each pass has a
 // snake_case name and a plain text description used when printing out the timing report.
@@ -38,7 +44,7 @@ macro_rules! define_passes {
             #[doc=$desc]
             #[must_use]
             pub fn $pass() -> Box<dyn Any> {
-                start_pass(Pass::$pass)
+                start_traced_pass(Pass::$pass)
             }
         )+
     }
@@ -62,16 +68,23 @@ define_passes! {
     domtree: "Dominator tree",
     loop_analysis: "Loop analysis",
     preopt: "Pre-legalization rewriting",
+    legalize: "Legalization",
     dce: "Dead code elimination",
     egraph: "Egraph based optimizations",
     gvn: "Global value numbering",
     licm: "Loop invariant code motion",
     unreachable_code: "Remove unreachable blocks",
     remove_constant_phis: "Remove constant phi-nodes",
+    redundant_checks: "Remove redundant checks",
+    simplify_block_params: "Simplify block parameters",
+    block_coverage: "Instrument blocks for coverage",
+    pressure_scheduling: "Schedule instructions for register pressure",
+    loop_unrolling: "Loop unrolling",
 
     vcode_lower: "VCode lowering",
     vcode_emit: "VCode emission",
     vcode_emit_finish: "VCode emission finalization",
+    branch_relaxation: "Branch relaxation",
 
     regalloc: "Register allocation",
     regalloc_checker: "Register allocation symbolic verification",
@@ -111,151 +124,179 @@ pub trait Profiler {
     fn start_pass(&self, pass: Pass) -> Box<dyn Any>;
 }
 
-// Information about passes in a single thread.
-thread_local! {
-    static PROFILER: RefCell<Box<dyn Profiler>> = RefCell::new(Box::new(DefaultProfiler));
-}
-
-/// Set the profiler for the current thread.
-///
-/// Returns the old profiler.
-pub fn set_thread_profiler(new_profiler: Box<dyn Profiler>) -> Box<dyn Profiler> {
-    PROFILER.with(|profiler| std::mem::replace(&mut *profiler.borrow_mut(), new_profiler))
+/// Start timing `pass`, and enter its span with the `trace-log` feature.
+fn start_traced_pass(pass: Pass) -> Box<dyn Any> {
+    let token = start_pass(pass);
+    // The timing token is dropped before the span is exited.
+    #[cfg(feature = "trace-log")]
+    let token = Box::new((token, tracing::debug_span!("pass", name = ?pass).entered()));
+    token
 }
 
 /// Start timing `pass` as a child of the currently running pass, if any.
 ///
 /// This function is called by the publicly exposed pass functions.
-fn start_pass(pass: Pass) -> Box<dyn Any> {
-    PROFILER.with(|profiler| profiler.borrow().start_pass(pass))
+#[cfg(not(feature = "std"))]
+fn start_pass(_pass: Pass) -> Box<dyn Any> {
+    Box::new(())
 }
 
-/// A timing token is responsible for timing the currently running pass. Timing starts when it
-/// is created and ends when it is dropped.
-///
-/// Multiple passes can be active at the same time, but they must be started and stopped in a
-/// LIFO fashion.
-struct DefaultTimingToken {
-    /// Start time for this pass.
-    start: Instant,
-
-    // Pass being timed by this token.
-    pass: Pass,
+#[cfg(feature = "std")]
+mod enabled {
+    use super::{Pass, Profiler, DESCRIPTIONS, NUM_PASSES};
+    use alloc::boxed::Box;
+    use core::any::Any;
+    use core::cell::{Cell, RefCell};
+    use core::fmt;
+    use core::mem;
+    use std::time::{Duration, Instant};
+
+    // Information about passes in a single thread.
+    thread_local! {
+        static PROFILER: RefCell<Box<dyn Profiler>> = RefCell::new(Box::new(DefaultProfiler));
+    }
 
-    // The previously active pass which will be restored when this token is dropped.
-    prev: Pass,
-}
+    /// Set the profiler for the current thread.
+    ///
+    /// Returns the old profiler.
+    pub fn set_thread_profiler(new_profiler: Box<dyn Profiler>) -> Box<dyn Profiler> {
+        PROFILER.with(|profiler| mem::replace(&mut *profiler.borrow_mut(), new_profiler))
+    }
 
-/// Accumulated timing information for a single pass.
-#[derive(Default, Copy, Clone)]
-struct PassTime {
-    /// Total time spent running this pass including children.
-    total: Duration,
+    /// Start timing `pass` as a child of the currently running pass, if any.
+    ///
+    /// This function is called by the publicly exposed pass functions.
+    pub(super) fn start_pass(pass: Pass) -> Box<dyn Any> {
+        PROFILER.with(|profiler| profiler.borrow().start_pass(pass))
+    }
 
-    /// Time spent running in child passes.
-    child: Duration,
-}
+    /// A timing token is responsible for timing the currently running pass. Timing starts when it
+    /// is created and ends when it is dropped.
+    ///
+    /// Multiple passes can be active at the same time, but they must be started and stopped in a
+    /// LIFO fashion.
+    struct DefaultTimingToken {
+        /// Start time for this pass.
+        start: Instant,
 
-/// Accumulated timing for all passes.
-pub struct PassTimes {
-    pass: [PassTime; NUM_PASSES],
-}
+        // Pass being timed by this token.
+        pass: Pass,
 
-impl PassTimes {
-    /// Add `other` to the timings of this `PassTimes`.
-    pub fn add(&mut self, other: &Self) {
-        for (a, b) in self.pass.iter_mut().zip(&other.pass[..]) {
-            a.total += b.total;
-            a.child += b.child;
-        }
+        // The previously active pass which will be restored when this token is dropped.
+        prev: Pass,
     }
 
-    /// Returns the total amount of time taken by all the passes measured.
-    pub fn total(&self) -> Duration {
-        self.pass.iter().map(|p| p.total - p.child).sum()
+    /// Accumulated timing information for a single pass.
+    #[derive(Default, Copy, Clone)]
+    struct PassTime {
+        /// Total time spent running this pass including children.
+        total: Duration,
+
+        /// Time spent running in child passes.
+        child: Duration,
     }
-}
 
-impl Default for PassTimes {
-    fn default() -> Self {
-        Self {
-            pass: [Default::default(); NUM_PASSES],
-        }
+    /// Accumulated timing for all passes.
+    pub struct PassTimes {
+        pass: [PassTime; NUM_PASSES],
     }
-}
 
-impl fmt::Display for PassTimes {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        writeln!(f, "======== ========  ==================================")?;
-        writeln!(f, "   Total     Self  Pass")?;
-        writeln!(f, "-------- --------  ----------------------------------")?;
-        for (time, desc) in self.pass.iter().zip(&DESCRIPTIONS[..]) {
-            // Omit passes that haven't run.
-            if time.total == Duration::default() {
-                continue;
+    impl PassTimes {
+        /// Add `other` to the timings of this `PassTimes`.
+        pub fn add(&mut self, other: &Self) {
+            for (a, b) in self.pass.iter_mut().zip(&other.pass[..]) {
+                a.total += b.total;
+                a.child += b.child;
             }
+        }
 
-            // Write a duration as secs.millis, trailing space.
-            fn fmtdur(mut dur: Duration, f: &mut fmt::Formatter) -> fmt::Result {
-                // Round to nearest ms by adding 500us.
-                dur += Duration::new(0, 500_000);
-                let ms = dur.subsec_millis();
-                write!(f, "{:4}.{:03} ", dur.as_secs(), ms)
+        /// Returns the total amount of time taken by all the passes measured.
+        pub fn total(&self) -> Duration {
+            self.pass.iter().map(|p| p.total - p.child).sum()
+        }
+    }
+
+    impl Default for PassTimes {
+        fn default() -> Self {
+            Self {
+                pass: [Default::default(); NUM_PASSES],
             }
+        }
+    }
 
-            fmtdur(time.total, f)?;
-            if let Some(s) = time.total.checked_sub(time.child) {
-                fmtdur(s, f)?;
+    impl fmt::Display for PassTimes {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            writeln!(f, "======== ========  ==================================")?;
+            writeln!(f, "   Total     Self  Pass")?;
+            writeln!(f, "-------- --------  ----------------------------------")?;
+            for (time, desc) in self.pass.iter().zip(&DESCRIPTIONS[..]) {
+                // Omit passes that haven't run.
+                if time.total == Duration::default() {
+                    continue;
+                }
+
+                // Write a duration as secs.millis, trailing space.
+                fn fmtdur(mut dur: Duration, f: &mut fmt::Formatter) -> fmt::Result {
+                    // Round to nearest ms by adding 500us.
+                    dur += Duration::new(0, 500_000);
+                    let ms = dur.subsec_millis();
+                    write!(f, "{:4}.{:03} ", dur.as_secs(), ms)
+                }
+
+                fmtdur(time.total, f)?;
+                if let Some(s) = time.total.checked_sub(time.child) {
+                    fmtdur(s, f)?;
+                }
+                writeln!(f, " {}", desc)?;
             }
-            writeln!(f, " {}", desc)?;
+            writeln!(f, "======== ========  ==================================")
         }
-        writeln!(f, "======== ========  ==================================")
     }
-}
 
-// Information about passes in a single thread.
-thread_local! {
-    static CURRENT_PASS: Cell<Pass> = const { Cell::new(Pass::None) };
-    static PASS_TIME: RefCell<PassTimes> = RefCell::new(Default::default());
-}
+    // Information about passes in a single thread.
+    thread_local! {
+        static CURRENT_PASS: Cell<Pass> = const { Cell::new(Pass::None) };
+        static PASS_TIME: RefCell<PassTimes> = RefCell::new(Default::default());
+    }
 
-/// The default profiler. You can get the results using [`take_current`].
-pub struct DefaultProfiler;
-
-impl Profiler for DefaultProfiler {
-    fn start_pass(&self, pass: Pass) -> Box<dyn Any> {
-        let prev = CURRENT_PASS.with(|p| p.replace(pass));
-        log::debug!("timing: Starting {}, (during {})", pass, prev);
-        Box::new(DefaultTimingToken {
-            start: Instant::now(),
-            pass,
-            prev,
-        })
+    /// The default profiler. You can get the results using [`take_current`].
+    pub struct DefaultProfiler;
+
+    impl Profiler for DefaultProfiler {
+        fn start_pass(&self, pass: Pass) -> Box<dyn Any> {
+            let prev = CURRENT_PASS.with(|p| p.replace(pass));
+            log::debug!("timing: Starting {}, (during {})", pass, prev);
+            Box::new(DefaultTimingToken {
+                start: Instant::now(),
+                pass,
+                prev,
+            })
+        }
     }
-}
 
-/// Dropping a timing token indicated the end of the pass.
-impl Drop for DefaultTimingToken {
-    fn drop(&mut self) {
-        let duration = self.start.elapsed();
-        log::debug!("timing: Ending {}", self.pass);
-        let old_cur = CURRENT_PASS.with(|p| p.replace(self.prev));
-        debug_assert_eq!(self.pass, old_cur, "Timing tokens dropped out of order");
-        PASS_TIME.with(|rc| {
-            let mut table = rc.borrow_mut();
-            table.pass[self.pass.idx()].total += duration;
-            if let Some(parent) = table.pass.get_mut(self.prev.idx()) {
-                parent.child += duration;
-            }
-        })
+    /// Dropping a timing token indicated the end of the pass.
+    impl Drop for DefaultTimingToken {
+        fn drop(&mut self) {
+            let duration = self.start.elapsed();
+            log::debug!("timing: Ending {}", self.pass);
+            let old_cur = CURRENT_PASS.with(|p| p.replace(self.prev));
+            debug_assert_eq!(self.pass, old_cur, "Timing tokens dropped out of order");
+            PASS_TIME.with(|rc| {
+                let mut table = rc.borrow_mut();
+                table.pass[self.pass.idx()].total += duration;
+                if let Some(parent) = table.pass.get_mut(self.prev.idx()) {
+                    parent.child += duration;
+                }
+            })
+        }
     }
-}
 
-/// Take the current accumulated pass timings and reset the timings for the current thread.
-///
-/// Only applies when [`DefaultProfiler`] is used.
-pub fn take_current() -> PassTimes {
-    PASS_TIME.with(|rc| mem::take(&mut *rc.borrow_mut()))
+    /// Take the current accumulated pass timings and reset the timings for the current thread.
+    ///
+    /// Only applies when [`DefaultProfiler`] is used.
+    pub fn take_current() -> PassTimes {
+        PASS_TIME.with(|rc| mem::take(&mut *rc.borrow_mut()))
+    }
 }
 
 #[cfg(test)]
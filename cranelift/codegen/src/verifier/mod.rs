@@ -89,10 +89,49 @@ pub struct VerifierError {
     pub context: Option<String>,
     /// The error message.
     pub message: String,
+    /// The block the error is about, if any.
+    pub block: Option<Block>,
+    /// The instruction the error is about, if any.
+    pub inst: Option<Inst>,
+    /// The value the error is about, if any.
+    pub value: Option<Value>,
+}
+
+impl VerifierError {
+    fn new(location: AnyEntity, context: Option<String>, message: String) -> Self {
+        let mut error = Self {
+            location,
+            context,
+            message,
+            block: None,
+            inst: None,
+            value: None,
+        };
+        match location {
+            AnyEntity::Block(block) => error.block = Some(block),
+            AnyEntity::Inst(inst) => error.inst = Some(inst),
+            AnyEntity::Value(value) => error.value = Some(value),
+            _ => {}
+        }
+        error
+    }
+
+    /// Set the instruction the error is about, when it isn't the location.
+    fn with_inst(mut self, inst: Inst) -> Self {
+        self.inst = Some(inst);
+        self
+    }
+
+    /// Set the value the error is about, when it isn't the location.
+    fn with_value(mut self, value: Value) -> Self {
+        self.value = Some(value);
+        self
+    }
 }
 
 // This is manually implementing Error and Display instead of using thiserror to reduce the amount
 // of dependencies used by Cranelift.
+#[cfg(feature = "std")]
 impl std::error::Error for VerifierError {}
 
 impl Display for VerifierError {
@@ -122,11 +161,7 @@ where
 {
     fn from(items: (L, C, M)) -> Self {
         let (location, context, message) = items;
-        Self {
-            location: location.into(),
-            context: Some(context.into()),
-            message: message.into(),
-        }
+        Self::new(location.into(), Some(context.into()), message.into())
     }
 }
 
@@ -140,14 +175,21 @@ where
 {
     fn from(items: (L, M)) -> Self {
         let (location, message) = items;
-        Self {
-            location: location.into(),
-            context: None,
-            message: message.into(),
-        }
+        Self::new(location.into(), None, message.into())
     }
 }
 
+/// How to fix a block which doesn't end in a terminator.
+const TERMINATOR_HINT: &str = "every block must end in a terminator such as return, jump, or brif";
+
+/// The error message for `inst` having no results when it should have some.
+fn missing_results(inst: Inst) -> String {
+    format!(
+        "instruction {} has no result values; did you forget make_inst_results?",
+        inst
+    )
+}
+
 /// Result of a step in the verification process.
 ///
 /// Functions that return `VerifierStepResult<()>` should also take a
@@ -172,6 +214,7 @@ pub struct VerifierErrors(pub Vec<VerifierError>);
 
 // This is manually implementing Error and Display instead of using thiserror to reduce the amount
 // of dependencies used by Cranelift.
+#[cfg(feature = "std")]
 impl std::error::Error for VerifierErrors {}
 
 impl VerifierErrors {
@@ -476,7 +519,16 @@ impl<'a> Verifier<'a> {
             ));
         }
         if is_last_inst && !is_terminator {
-            return errors.fatal((block, "block does not end in a terminator instruction"));
+            let error = VerifierError::from((
+                block,
+                format!(
+                    "{} does not end in a terminator instruction, but in `{}`; {}",
+                    block,
+                    self.context(inst),
+                    TERMINATOR_HINT
+                ),
+            ));
+            return errors.fatal(error.with_inst(inst));
         }
 
         // Instructions belong to the correct block.
@@ -527,6 +579,10 @@ impl<'a> Verifier<'a> {
 
         // All result values for multi-valued instructions are created
         let got_results = dfg.inst_results(inst).len();
+        if got_results == 0 && expected_num_results > 0 {
+            // Instructions can't be displayed without their results.
+            return errors.fatal((inst, missing_results(inst)));
+        }
         if got_results != expected_num_results {
             return errors.fatal((
                 inst,
@@ -579,6 +635,21 @@ impl<'a> Verifier<'a> {
                 self.verify_block(inst, block_then.block(&self.func.dfg.value_lists), errors)?;
                 self.verify_block(inst, block_else.block(&self.func.dfg.value_lists), errors)?;
             }
+            PatchableJump {
+                blocks: [block_initial, block_alternate],
+                ..
+            } => {
+                self.verify_block(
+                    inst,
+                    block_initial.block(&self.func.dfg.value_lists),
+                    errors,
+                )?;
+                self.verify_block(
+                    inst,
+                    block_alternate.block(&self.func.dfg.value_lists),
+                    errors,
+                )?;
+            }
             BranchTable { table, .. } => {
                 self.verify_jump_table(inst, table, errors)?;
             }
@@ -902,6 +973,15 @@ impl<'a> Verifier<'a> {
                         format!("{} is defined by invalid instruction {}", v, def_inst),
                     ));
                 }
+                // Results have been made for the defining instruction.
+                if dfg.inst_results(def_inst).is_empty() {
+                    let error = VerifierError::from((
+                        loc_inst,
+                        self.context(loc_inst),
+                        format!("uses {}, but {}", v, missing_results(def_inst)),
+                    ));
+                    return errors.fatal(error.with_inst(def_inst).with_value(v));
+                }
                 // Defining instruction is inserted in a block.
                 if self.func.layout.inst_block(def_inst) == None {
                     return errors.fatal((
@@ -1299,6 +1379,13 @@ impl<'a> Verifier<'a> {
                 self.typecheck_block_call(inst, block_then, errors)?;
                 self.typecheck_block_call(inst, block_else, errors)?;
             }
+            ir::InstructionData::PatchableJump {
+                blocks: [block_initial, block_alternate],
+                ..
+            } => {
+                self.typecheck_block_call(inst, block_initial, errors)?;
+                self.typecheck_block_call(inst, block_alternate, errors)?;
+            }
             ir::InstructionData::BranchTable { table, .. } => {
                 for block in self.func.stencil.dfg.jump_tables[*table].all_branches() {
                     self.typecheck_block_call(inst, block, errors)?;
@@ -1728,7 +1815,10 @@ impl<'a> Verifier<'a> {
 
         for block in self.func.layout.blocks() {
             if self.func.layout.first_inst(block).is_none() {
-                return errors.fatal((block, format!("{} cannot be empty", block)));
+                return errors.fatal((
+                    block,
+                    format!("{} cannot be empty; {}", block, TERMINATOR_HINT),
+                ));
             }
             for inst in self.func.layout.block_insts(block) {
                 self.block_integrity(block, inst, errors)?;
@@ -1754,8 +1844,9 @@ impl<'a> Verifier<'a> {
 #[cfg(test)]
 mod tests {
     use super::{Verifier, VerifierError, VerifierErrors};
+    use crate::ir::entities::AnyEntity;
     use crate::ir::instructions::{InstructionData, Opcode};
-    use crate::ir::{types, AbiParam, Function};
+    use crate::ir::{types, AbiParam, Function, TrapCode, ValueList};
     use crate::settings;
 
     macro_rules! assert_err_with_msg {
@@ -1881,4 +1972,108 @@ mod tests {
 
         assert_err_with_msg!(errors, "block0 cannot be empty");
     }
+
+    #[test]
+    fn test_missing_terminator() {
+        // block0(v0: i32):
+        //     v1 = iadd_imm v0, 1
+        let mut func = Function::new();
+        func.signature.params.push(AbiParam::new(types::I32));
+        let block0 = func.dfg.make_block();
+        func.layout.append_block(block0);
+        let v0 = func.dfg.append_block_param(block0, types::I32);
+        let (iadd, _) = func.emit_inferred(
+            block0,
+            InstructionData::BinaryImm64 {
+                opcode: Opcode::IaddImm,
+                arg: v0,
+                imm: 1.into(),
+            },
+        );
+
+        let flags = &settings::Flags::new(settings::builder());
+        let verifier = Verifier::new(&func, flags.into());
+        let mut errors = VerifierErrors::default();
+        let _ = verifier.run(&mut errors);
+
+        let error = &errors.0[0];
+        assert_eq!(error.location, AnyEntity::Block(block0));
+        assert_eq!(error.block, Some(block0));
+        assert_eq!(error.inst, Some(iadd));
+        assert_eq!(error.value, None);
+        assert_eq!(
+            error.message,
+            "block0 does not end in a terminator instruction, but in `v1 = iadd_imm.i32 v0, 1`; \
+             every block must end in a terminator such as return, jump, or brif"
+        );
+    }
+
+    #[test]
+    fn test_missing_results() {
+        // block0:
+        //     return v0
+        //
+        // block1:
+        //     iconst.i32 42
+        //     trap user0
+        let mut func = Function::new();
+        func.signature.returns.push(AbiParam::new(types::I32));
+        let block0 = func.dfg.make_block();
+        func.layout.append_block(block0);
+        let block1 = func.dfg.make_block();
+        func.layout.append_block(block1);
+        let iconst = func.dfg.make_inst(InstructionData::UnaryImm {
+            opcode: Opcode::Iconst,
+            imm: 42.into(),
+        });
+        func.dfg.make_inst_results(iconst, types::I32);
+        let v0 = func.dfg.first_result(iconst);
+        let args = ValueList::from_slice(&[v0], &mut func.dfg.value_lists);
+        let (ret, _) = func.emit_inferred(
+            block0,
+            InstructionData::MultiAry {
+                opcode: Opcode::Return,
+                args,
+            },
+        );
+        func.emit_inferred(
+            block1,
+            InstructionData::Trap {
+                opcode: Opcode::Trap,
+                code: TrapCode::User(0),
+            },
+        );
+        func.dfg.clear_results(iconst);
+
+        // The use of `v0` is reported when it comes first, and the instruction without results
+        // is reported otherwise.
+        let flags = &settings::Flags::new(settings::builder());
+        let mut errors = VerifierErrors::default();
+        let trap = func.layout.last_inst(block1).unwrap();
+        func.layout.insert_inst(iconst, trap);
+        let _ = Verifier::new(&func, flags.into()).run(&mut errors);
+        let error = &errors.0[0];
+        assert_eq!(error.location, AnyEntity::Inst(ret));
+        assert_eq!(error.block, None);
+        assert_eq!(error.inst, Some(iconst));
+        assert_eq!(error.value, Some(v0));
+        assert_eq!(
+            error.message,
+            "uses v0, but instruction inst0 has no result values; did you forget \
+             make_inst_results?"
+        );
+
+        let mut errors = VerifierErrors::default();
+        func.layout.remove_inst(iconst);
+        func.layout.insert_inst(iconst, ret);
+        let _ = Verifier::new(&func, flags.into()).run(&mut errors);
+        let error = &errors.0[0];
+        assert_eq!(error.location, AnyEntity::Inst(iconst));
+        assert_eq!(error.inst, Some(iconst));
+        assert_eq!(error.value, None);
+        assert_eq!(
+            error.message,
+            "instruction inst0 has no result values; did you forget make_inst_results?"
+        );
+    }
 }
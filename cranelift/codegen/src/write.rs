@@ -421,6 +421,13 @@ pub fn write_operands(w: &mut dyn Write, dfg: &DataFlowGraph, inst: Inst) -> fmt
             write!(w, " {}, {}", arg, block_then.display(pool))?;
             write!(w, ", {}", block_else.display(pool))
         }
+        PatchableJump {
+            blocks: [block_initial, block_alternate],
+            ..
+        } => {
+            write!(w, " {}", block_initial.display(pool))?;
+            write!(w, ", {}", block_alternate.display(pool))
+        }
         BranchTable { arg, table, .. } => {
             write!(w, " {}, {}", arg, jump_tables[table].display(pool))
         }
@@ -0,0 +1,14 @@
+//! Fixtures shared by the cranelift-codegen integration tests.
+
+use cranelift_codegen::isa::{self, OwnedTargetIsa};
+use cranelift_codegen::settings::{self, Flags};
+use std::str::FromStr;
+use target_lexicon::Triple;
+
+/// x86-64 with the default flags, without any of the optional SSE extensions.
+pub fn x86_64_isa() -> OwnedTargetIsa {
+    isa::lookup(Triple::from_str("x86_64").unwrap())
+        .unwrap()
+        .finish(Flags::new(settings::builder()))
+        .unwrap()
+}
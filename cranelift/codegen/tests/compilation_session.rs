@@ -0,0 +1,141 @@
+//! Compare compiling with a reused `CompilationSession` to compiling with `Context::compile`,
+//! counting allocations with a global allocator.
+
+#![cfg(feature = "x86")]
+
+use cranelift_codegen::cursor::{Cursor, FuncCursor};
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{types, AbiParam, Function, InstBuilder, Signature};
+use cranelift_codegen::isa::CallConv;
+use cranelift_codegen::{CompilationSession, Context};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+mod common;
+use common::x86_64_isa;
+
+/// Counts the allocations of each thread, so that tests running concurrently don't count each
+/// other's allocations.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+fn count_allocation() {
+    let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count_allocation();
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count_allocation();
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// The number of allocations made by `f`.
+fn allocations(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    f();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+/// Build `fact(n)`, computing the factorial of `n` with a loop.
+fn factorial() -> Function {
+    let mut sig = Signature::new(CallConv::SystemV);
+    sig.params.push(AbiParam::new(types::I64));
+    sig.returns.push(AbiParam::new(types::I64));
+    let mut func = Function::new();
+    func.signature = sig;
+    let mut pos = FuncCursor::new(&mut func);
+    let entry = pos.func.dfg.make_block();
+    let header = pos.func.dfg.make_block();
+    let body = pos.func.dfg.make_block();
+    let exit = pos.func.dfg.make_block();
+
+    pos.insert_block(entry);
+    let n = pos.func.dfg.append_block_param(entry, types::I64);
+    let one = pos.ins().iconst(types::I64, 1);
+    pos.ins().jump(header, &[n, one]);
+
+    pos.insert_block(header);
+    let i = pos.func.dfg.append_block_param(header, types::I64);
+    let acc = pos.func.dfg.append_block_param(header, types::I64);
+    let done = pos.ins().icmp_imm(IntCC::UnsignedLessThanOrEqual, i, 1);
+    pos.ins().brif(done, exit, &[acc], body, &[]);
+
+    pos.insert_block(body);
+    let acc = pos.ins().imul(acc, i);
+    let i = pos.ins().iadd_imm(i, -1);
+    pos.ins().jump(header, &[i, acc]);
+
+    pos.insert_block(exit);
+    let result = pos.func.dfg.append_block_param(exit, types::I64);
+    pos.ins().return_(&[result]);
+    func
+}
+
+#[test]
+fn same_code() {
+    let isa = x86_64_isa();
+    let mut ctx = Context::for_function(factorial());
+    let expected = ctx
+        .compile(&*isa, &mut Default::default())
+        .unwrap()
+        .code_buffer()
+        .to_vec();
+
+    let mut session = CompilationSession::new();
+    for _ in 0..3 {
+        ctx.clear();
+        ctx.func = factorial();
+        let code = session.compile(&mut ctx, &*isa, &mut Default::default());
+        assert_eq!(code.unwrap().code_buffer(), &expected[..]);
+    }
+}
+
+#[test]
+fn fewer_allocations() {
+    let isa = x86_64_isa();
+    let func = factorial();
+    let mut ctx = Context::new();
+    let mut session = CompilationSession::new();
+    let mut compile_with_context = || {
+        ctx.clear();
+        ctx.func = func.clone();
+        ctx.compile(&*isa, &mut Default::default()).unwrap();
+    };
+    // Let the context grow to its final size.
+    compile_with_context();
+    let with_context = allocations(compile_with_context);
+
+    let mut ctx = Context::new();
+    let mut compile_with_session = || {
+        ctx.clear();
+        ctx.func = func.clone();
+        session
+            .compile(&mut ctx, &*isa, &mut Default::default())
+            .unwrap();
+    };
+    compile_with_session();
+    let with_session = allocations(compile_with_session);
+
+    assert!(
+        with_session + 10 < with_context,
+        "{} allocations with a session, {} without",
+        with_session,
+        with_context
+    );
+}
@@ -0,0 +1,163 @@
+//! Check that a `CompileBudget` stops the compilation of a function within each phase, and that
+//! the context can compile again afterwards.
+
+#![cfg(feature = "x86")]
+
+use cranelift_codegen::cursor::{Cursor, FuncCursor};
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{types, AbiParam, Function, InstBuilder, MemFlags, Signature};
+use cranelift_codegen::isa::CallConv;
+use cranelift_codegen::timing::Pass;
+use cranelift_codegen::{CodegenError, CompileBudget, Context};
+
+mod common;
+use common::x86_64_isa;
+
+/// The number of values live at once in `high_pressure`.
+const VALUES: i32 = 300;
+
+/// The number of conditional branches in `many_branches`.
+const BRANCHES: usize = 300;
+
+/// Build a function with a single huge block which loads `VALUES` values and only then sums them
+/// up, so that they are all live at once and most of them must be spilled.
+fn high_pressure() -> Function {
+    let mut sig = Signature::new(CallConv::SystemV);
+    sig.params.push(AbiParam::new(types::I64));
+    sig.returns.push(AbiParam::new(types::I64));
+    let mut func = Function::new();
+    func.signature = sig;
+    let mut pos = FuncCursor::new(&mut func);
+    let entry = pos.func.dfg.make_block();
+    pos.insert_block(entry);
+    let ptr = pos.func.dfg.append_block_param(entry, types::I64);
+    let values: Vec<_> = (0..VALUES)
+        .map(|i| pos.ins().load(types::I64, MemFlags::trusted(), ptr, i * 8))
+        .collect();
+    let sum = values
+        .into_iter()
+        .rev()
+        .reduce(|sum, value| pos.ins().iadd(sum, value))
+        .unwrap();
+    pos.ins().return_(&[sum]);
+    func
+}
+
+/// Build a function with a chain of `BRANCHES` blocks which each branch either to the next one or
+/// to the exit, so that branch relaxation has as many branches to fix up.
+fn many_branches() -> Function {
+    let mut sig = Signature::new(CallConv::SystemV);
+    sig.params.push(AbiParam::new(types::I64));
+    sig.returns.push(AbiParam::new(types::I64));
+    let mut func = Function::new();
+    func.signature = sig;
+    let mut pos = FuncCursor::new(&mut func);
+    let blocks: Vec<_> = (0..BRANCHES).map(|_| pos.func.dfg.make_block()).collect();
+    let exit = pos.func.dfg.make_block();
+    let x = pos.func.dfg.append_block_param(blocks[0], types::I64);
+    for (i, &block) in blocks.iter().enumerate() {
+        pos.insert_block(block);
+        let cond = pos.ins().icmp_imm(IntCC::UnsignedGreaterThan, x, i as i64);
+        match blocks.get(i + 1) {
+            Some(&next) => pos.ins().brif(cond, next, &[], exit, &[]),
+            None => pos.ins().jump(exit, &[]),
+        };
+    }
+    pos.insert_block(exit);
+    pos.ins().return_(&[x]);
+    func
+}
+
+fn compile(ctx: &mut Context, func: Function, budget: CompileBudget) -> Result<(), CodegenError> {
+    ctx.clear();
+    ctx.func = func;
+    ctx.set_compile_budget(budget);
+    ctx.compile(&*x86_64_isa(), &mut Default::default())
+        .map(|_| ())
+        .map_err(|e| e.inner)
+}
+
+fn insts_budget(max_insts_processed: u64) -> CompileBudget {
+    CompileBudget {
+        max_insts_processed,
+        max_millis: None,
+    }
+}
+
+/// Raise the budget by `step` until the function built by `func` compiles, and return the phases
+/// where the attempts before ran out of it.
+fn exceeded_phases(ctx: &mut Context, func: fn() -> Function, step: u64) -> Vec<Pass> {
+    let mut phases = vec![];
+    let mut max_insts_processed = 0;
+    while let Err(error) = compile(ctx, func(), insts_budget(max_insts_processed)) {
+        let phase = match error {
+            CodegenError::BudgetExceeded { phase } => phase,
+            error => panic!("unexpected error: {}", error),
+        };
+        if phases.last() != Some(&phase) {
+            phases.push(phase);
+        }
+        max_insts_processed += step;
+    }
+    phases
+}
+
+#[test]
+fn exceeded_in_each_phase() {
+    let mut ctx = Context::new();
+    let phases = exceeded_phases(&mut ctx, high_pressure, u64::from(VALUES as u32 / 2));
+
+    for phase in [
+        Pass::legalize,
+        Pass::vcode_lower,
+        Pass::regalloc,
+        Pass::vcode_emit,
+        Pass::vcode_emit_finish,
+    ] {
+        assert!(phases.contains(&phase), "{:?} not in {:?}", phase, phases);
+    }
+    assert_eq!(
+        CodegenError::BudgetExceeded {
+            phase: Pass::regalloc
+        }
+        .to_string(),
+        "Compilation budget exceeded during: Register allocation"
+    );
+
+    // A context which ran out of budget compiles normally again.
+    assert!(compile(&mut ctx, high_pressure(), insts_budget(0)).is_err());
+    compile(&mut ctx, high_pressure(), CompileBudget::default()).unwrap();
+    assert!(!ctx.compiled_code().unwrap().code_buffer().is_empty());
+}
+
+#[test]
+fn exceeded_in_branch_relaxation() {
+    let mut ctx = Context::new();
+    let phases = exceeded_phases(&mut ctx, many_branches, BRANCHES as u64 / 2);
+    assert!(
+        phases.contains(&Pass::branch_relaxation),
+        "branch_relaxation not in {:?}",
+        phases
+    );
+
+    compile(&mut ctx, many_branches(), CompileBudget::default()).unwrap();
+}
+
+#[test]
+fn exceeded_deadline() {
+    let mut ctx = Context::new();
+    let budget = CompileBudget {
+        max_insts_processed: u64::MAX,
+        max_millis: Some(0),
+    };
+    match compile(&mut ctx, high_pressure(), budget) {
+        Err(CodegenError::BudgetExceeded { phase }) => assert_eq!(phase, Pass::legalize),
+        result => panic!("unexpected result: {:?}", result),
+    }
+
+    let budget = CompileBudget {
+        max_insts_processed: u64::MAX,
+        max_millis: Some(60_000),
+    };
+    compile(&mut ctx, high_pressure(), budget).unwrap();
+}
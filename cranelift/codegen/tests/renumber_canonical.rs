@@ -0,0 +1,119 @@
+//! Check that `Function::renumber_canonical` numbers structurally identical functions the same,
+//! however they were built.
+
+use cranelift_codegen::cursor::{Cursor, FuncCursor};
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{types, AbiParam, Function, InstBuilder, Signature};
+use cranelift_codegen::isa::CallConv;
+use cranelift_codegen::settings::{self, Flags};
+use cranelift_codegen::verify_function;
+
+fn signature() -> Signature {
+    let mut sig = Signature::new(CallConv::SystemV);
+    sig.params.push(AbiParam::new(types::I64));
+    sig.returns.push(AbiParam::new(types::I64));
+    sig
+}
+
+/// Build a loop computing the factorial of its argument, block by block in layout order.
+fn factorial() -> Function {
+    let mut func = Function::new();
+    func.signature = signature();
+    let mut pos = FuncCursor::new(&mut func);
+    let entry = pos.func.dfg.make_block();
+    let header = pos.func.dfg.make_block();
+    let body = pos.func.dfg.make_block();
+    let exit = pos.func.dfg.make_block();
+
+    pos.insert_block(entry);
+    let n = pos.func.dfg.append_block_param(entry, types::I64);
+    let one = pos.ins().iconst(types::I64, 1);
+    pos.ins().jump(header, &[n, one]);
+
+    pos.insert_block(header);
+    let i = pos.func.dfg.append_block_param(header, types::I64);
+    let acc = pos.func.dfg.append_block_param(header, types::I64);
+    let done = pos.ins().icmp_imm(IntCC::UnsignedLessThanOrEqual, i, 1);
+    pos.ins().brif(done, exit, &[acc], body, &[]);
+
+    pos.insert_block(body);
+    let acc = pos.ins().imul(acc, i);
+    let i = pos.ins().iadd_imm(i, -1);
+    pos.ins().jump(header, &[i, acc]);
+
+    pos.insert_block(exit);
+    let result = pos.func.dfg.append_block_param(exit, types::I64);
+    pos.ins().return_(&[result]);
+    func
+}
+
+/// Build the same function as `factorial`, but creating its blocks and instructions backwards,
+/// and going through an alias and a removed instruction.
+fn factorial_backwards() -> Function {
+    let mut func = Function::new();
+    func.signature = signature();
+    let mut pos = FuncCursor::new(&mut func);
+    let exit = pos.func.dfg.make_block();
+    let body = pos.func.dfg.make_block();
+    let header = pos.func.dfg.make_block();
+    let entry = pos.func.dfg.make_block();
+    for block in [entry, header, body, exit] {
+        pos.func.layout.append_block(block);
+    }
+
+    pos.goto_bottom(exit);
+    let result = pos.func.dfg.append_block_param(exit, types::I64);
+    pos.ins().return_(&[result]);
+
+    pos.goto_bottom(header);
+    let i = pos.func.dfg.append_block_param(header, types::I64);
+    let acc = pos.func.dfg.append_block_param(header, types::I64);
+    let done = pos.ins().icmp_imm(IntCC::UnsignedLessThanOrEqual, i, 1);
+    pos.ins().brif(done, exit, &[acc], body, &[]);
+
+    // Create the `iadd_imm` before the `imul` which precedes it.
+    pos.goto_bottom(body);
+    let i_next = pos.ins().iadd_imm(i, -1);
+    pos.goto_inst(pos.func.dfg.value_def(i_next).unwrap_inst());
+    let acc = pos.ins().imul(acc, i);
+    pos.goto_bottom(body);
+    pos.ins().jump(header, &[i_next, acc]);
+
+    pos.goto_bottom(entry);
+    let n = pos.func.dfg.append_block_param(entry, types::I64);
+    let dup = pos.ins().iconst(types::I64, 1);
+    let one = pos.ins().iconst(types::I64, 1);
+    pos.ins().jump(header, &[n, dup]);
+    let dup_inst = pos.func.dfg.value_def(dup).unwrap_inst();
+    let one_inst = pos.func.dfg.value_def(one).unwrap_inst();
+    pos.func.dfg.replace_with_aliases(dup_inst, one_inst);
+    pos.func.layout.remove_inst(dup_inst);
+    func
+}
+
+#[test]
+fn same_display() {
+    let forwards = factorial();
+    let backwards = factorial_backwards();
+    assert_ne!(
+        forwards.display().to_string(),
+        backwards.display().to_string()
+    );
+    assert_eq!(forwards.display_canonical(), backwards.display_canonical());
+
+    // The display of a canonical function doesn't change when renumbering it again.
+    let mut func = backwards;
+    func.renumber_canonical();
+    assert_eq!(func.display().to_string(), forwards.display_canonical());
+    func.renumber_canonical();
+    assert_eq!(func.display().to_string(), forwards.display_canonical());
+}
+
+#[test]
+fn verifies_after_renumbering() {
+    let flags = Flags::new(settings::builder());
+    for mut func in [factorial(), factorial_backwards()] {
+        func.renumber_canonical();
+        verify_function(&func, &flags).unwrap();
+    }
+}
@@ -0,0 +1,83 @@
+//! Check that compiling a function with instructions the target can't compile reports all of
+//! them before lowering.
+
+#![cfg(feature = "x86")]
+
+use cranelift_codegen::cursor::{Cursor, FuncCursor};
+use cranelift_codegen::ir::{types, AbiParam, Function, InstBuilder, Opcode, Signature, SourceLoc};
+use cranelift_codegen::isa::CallConv;
+use cranelift_codegen::{CodegenError, Context};
+use cranelift_control::ControlPlane;
+
+mod common;
+use common::x86_64_isa;
+
+#[test]
+fn all_unsupported_instructions_are_reported() {
+    let mut sig = Signature::new(CallConv::SystemV);
+    sig.params.push(AbiParam::new(types::I8X16));
+    sig.params.push(AbiParam::new(types::I32X4));
+    sig.returns.push(AbiParam::new(types::I8X16));
+    sig.returns.push(AbiParam::new(types::I32X4));
+    let mut func = Function::new();
+    func.signature = sig;
+
+    let mut pos = FuncCursor::new(&mut func);
+    let entry = pos.func.dfg.make_block();
+    pos.insert_block(entry);
+    let bytes = pos.func.dfg.append_block_param(entry, types::I8X16);
+    let words = pos.func.dfg.append_block_param(entry, types::I32X4);
+    // Without SSSE3, `swizzle` is lowered to a libcall, so it isn't reported.
+    let swizzled = pos.ins().swizzle(bytes, bytes);
+    pos.set_srcloc(SourceLoc::new(7));
+    let product = pos.ins().imul(swizzled, bytes);
+    let next = pos.func.dfg.make_block();
+    pos.ins().jump(next, &[]);
+    pos.insert_block(next);
+    pos.set_srcloc(SourceLoc::new(9));
+    let average = pos.ins().avg_round(words, words);
+    pos.set_srcloc(SourceLoc::default());
+    pos.ins().return_(&[product, average]);
+
+    let imul = pos.func.dfg.value_def(product).unwrap_inst();
+    let avg_round = pos.func.dfg.value_def(average).unwrap_inst();
+    let mut ctx = Context::for_function(func);
+    let err = ctx
+        .compile(&*x86_64_isa(), &mut ControlPlane::default())
+        .unwrap_err()
+        .inner;
+    let unsupported = match err {
+        CodegenError::UnsupportedInstructions(insts) => insts,
+        err => panic!("unexpected error: {}", err),
+    };
+    let found: Vec<_> = unsupported
+        .iter()
+        .map(|inst| {
+            (
+                inst.inst,
+                inst.block,
+                inst.srcloc,
+                inst.opcode,
+                inst.ctrl_type,
+            )
+        })
+        .collect();
+    assert_eq!(
+        found,
+        [
+            (imul, entry, SourceLoc::new(7), Opcode::Imul, types::I8X16),
+            (
+                avg_round,
+                next,
+                SourceLoc::new(9),
+                Opcode::AvgRound,
+                types::I32X4
+            ),
+        ]
+    );
+    assert_eq!(
+        CodegenError::UnsupportedInstructions(unsupported).to_string(),
+        "Unsupported instructions: `v3 = imul.i8x16 v2, v0` in block0, \
+         `v4 = avg_round.i32x4 v1, v1` in block1"
+    );
+}
@@ -31,9 +31,17 @@
 //! cargo fuzz run --features chaos $TARGET -- --fuel=16
 //! ```
 //!
+//! The `arbitrary` constructor requires the `fuzz` feature, which is enabled
+//! by default and by chaos mode. Without it this crate is `no_std`.
+//!
 //! [arbitrary]: ControlPlane#method.arbitrary
 //! [default]: ControlPlane#method.default
 
+#![no_std]
+
+#[cfg(feature = "chaos")]
+extern crate alloc;
+
 #[cfg(not(feature = "chaos"))]
 mod zero_sized;
 #[cfg(not(feature = "chaos"))]
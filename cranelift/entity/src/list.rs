@@ -172,6 +172,15 @@ impl<T: EntityRef + ReservedValue> ListPool<T> {
         self.free.clear();
     }
 
+    /// Release the memory of the pool that isn't used by any list, except for
+    /// the free space between lists.
+    ///
+    /// Existing entity lists remain valid.
+    pub fn shrink_to_fit(&mut self) {
+        self.data.shrink_to_fit();
+        self.free.shrink_to_fit();
+    }
+
     /// Read the length of a list field, if it exists.
     fn len_of(&self, list: &EntityList<T>) -> Option<usize> {
         let idx = list.index as usize;
@@ -952,4 +961,23 @@ mod tests {
             &[i4, i3, i2, i1, i1, i2, i4, i3, i2, i1, i1, i2, i3, i4, i4, i3, i3, i4, i4, i3]
         )
     }
+
+    #[test]
+    fn shrink_to_fit() {
+        let pool = &mut ListPool::<Inst>::new();
+        let insts: Vec<Inst> = (0..1000).map(Inst::new).collect();
+        let list = EntityList::from_slice(&insts[..3], pool);
+        let mut long = EntityList::from_slice(&insts, pool);
+        long.clear(pool);
+        let capacity = pool.capacity();
+
+        // The live list survives shrinking.
+        pool.shrink_to_fit();
+        assert!(pool.capacity() <= capacity);
+        assert_eq!(list.as_slice(pool), &insts[..3]);
+
+        pool.clear();
+        pool.shrink_to_fit();
+        assert_eq!(pool.capacity(), 0);
+    }
 }
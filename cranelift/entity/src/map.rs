@@ -82,6 +82,11 @@ where
         self.elems.capacity()
     }
 
+    /// Shrinks the capacity of the map as much as possible.
+    pub fn shrink_to_fit(&mut self) {
+        self.elems.shrink_to_fit()
+    }
+
     /// Get the element at `k` if it exists.
     #[inline(always)]
     pub fn get(&self, k: K) -> Option<&V> {
@@ -0,0 +1,149 @@
+//! Compile a clifp program to a native object file, or run it in the JIT.
+//!
+//! Usage: `clifp-aot [--jit | --clif] [-O] [-o out.o] <file.clifp>`
+//!
+//! Without `--jit`, every function of the program is compiled for the host into an object file
+//! and a summary is printed. With `--jit`, the program is compiled in memory instead, and its
+//! `main` function, which must take no arguments and return an `i64`, is run. With `--clif`, the
+//! CLIF text of the functions is printed, for `clif-util`. With `-O`, the program is simplified
+//! by `clifp::optimize` before it's compiled.
+//!
+//! The errors of a program which doesn't compile, and the warnings of one which does, are printed
+//! with the lines they're about and the files those are in, since the program can include
+//! other files.
+
+use clifp::compile::CompileError;
+use clifp::diagnostic::Diagnostic;
+use clifp::source::{FileSystem, SourceMap};
+use cranelift_clifp as clifp;
+use cranelift_codegen::ir::types;
+use cranelift_codegen::isa::OwnedTargetIsa;
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::default_libcall_names;
+use cranelift_object::{ObjectBuilder, ObjectModule};
+use std::path::Path;
+use std::process::exit;
+
+fn main() {
+    let mut jit = false;
+    let mut clif = false;
+    let mut optimize = false;
+    let mut output = "out.o".to_string();
+    let mut input = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--jit" => jit = true,
+            "--clif" => clif = true,
+            "-O" => optimize = true,
+            "-o" => output = args.next().unwrap_or_else(|| usage()),
+            _ if input.is_none() => input = Some(arg),
+            _ => usage(),
+        }
+    }
+    let input = input.unwrap_or_else(|| usage());
+
+    let mut sources = SourceMap::new();
+    let (mut program, types) = clifp::frontend_file(Path::new(&input), &FileSystem, &mut sources)
+        .unwrap_or_else(|e| fail_diagnostics(&sources, &e));
+    if !program.warnings.is_empty() {
+        let rendered = clifp::diagnostic::render_all_in(&program.warnings, &sources);
+        eprintln!("{rendered}");
+    }
+    if optimize {
+        clifp::optimize(&mut program);
+    }
+
+    if clif {
+        let clif = program
+            .to_clif()
+            .unwrap_or_else(|e| fail_compile(&input, &sources, e));
+        print!("{clif}");
+    } else if jit {
+        run_jit(&input, &sources, &program, &types);
+    } else {
+        let builder = ObjectBuilder::new(host_isa(true), "clifp", default_libcall_names())
+            .unwrap_or_else(|e| fail(&input, e));
+        let mut module = ObjectModule::new(builder);
+        let compiled = clifp::compile::compile(&mut module, &program)
+            .unwrap_or_else(|e| fail_compile(&input, &sources, e));
+        let bytes = module.finish().emit().unwrap_or_else(|e| fail(&input, e));
+        std::fs::write(&output, bytes).unwrap_or_else(|e| fail(&output, e));
+        println!(
+            "compiled {} functions ({} bytes of code) into {output}",
+            compiled.functions.len(),
+            compiled.code_bytes,
+        );
+    }
+}
+
+fn run_jit(
+    input: &str,
+    sources: &SourceMap,
+    program: &clifp::parser::Module,
+    types: &[clifp::typeck::FuncType],
+) {
+    let builder = JITBuilder::with_isa(host_isa(false), default_libcall_names());
+    let mut module = JITModule::new(builder);
+    let compiled = clifp::compile::compile(&mut module, program)
+        .unwrap_or_else(|e| fail_compile(input, sources, e));
+    module
+        .finalize_definitions()
+        .unwrap_or_else(|e| fail(input, e));
+
+    let main = program
+        .functions
+        .iter()
+        .position(|func| func.name == "main")
+        .unwrap_or_else(|| fail(input, "no `main` function"));
+    let ty = &types[main];
+    if !ty.params.is_empty() || ty.returns != [types::I64] {
+        fail(input, "`main` must take no arguments and return i64");
+    }
+    let code = module.get_finalized_function(compiled.functions[main].1);
+    // Safety: the signature of `main` was checked above.
+    let main = unsafe { std::mem::transmute::<_, extern "C" fn() -> i64>(code) };
+    println!("main returned {}", main());
+}
+
+/// An ISA for the host, with the optimizations a real frontend would enable.
+///
+/// Object files may be linked anywhere, so their code should be position-independent. Tail
+/// calls on x86_64 rely on frame pointers, so they are kept.
+fn host_isa(pic: bool) -> OwnedTargetIsa {
+    let mut flags = settings::builder();
+    flags.set("opt_level", "speed").unwrap();
+    flags.set("preserve_frame_pointers", "true").unwrap();
+    flags
+        .set("is_pic", if pic { "true" } else { "false" })
+        .unwrap();
+    cranelift_native::builder()
+        .unwrap_or_else(|e| fail("host", e))
+        .finish(settings::Flags::new(flags))
+        .unwrap_or_else(|e| fail("host", e))
+}
+
+fn usage() -> ! {
+    eprintln!("usage: clifp-aot [--jit | --clif] [-O] [-o out.o] <file.clifp>");
+    exit(2)
+}
+
+fn fail(context: &str, error: impl std::fmt::Display) -> ! {
+    eprintln!("{context}: {error}");
+    exit(1)
+}
+
+/// Fail with the errors of a program whose files are `sources`. Each error names the file it's
+/// in.
+fn fail_diagnostics(sources: &SourceMap, diagnostics: &[Diagnostic]) -> ! {
+    eprintln!("{}", clifp::diagnostic::render_all_in(diagnostics, sources));
+    exit(1)
+}
+
+fn fail_compile(input: &str, sources: &SourceMap, error: CompileError) -> ! {
+    match error {
+        CompileError::Diagnostics(diagnostics) => fail_diagnostics(sources, &diagnostics),
+        error => fail(input, error),
+    }
+}
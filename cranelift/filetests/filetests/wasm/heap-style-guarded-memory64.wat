@@ -0,0 +1,145 @@
+;;! target = "x86_64"
+;;!
+;;! optimize = true
+;;!
+;;! settings = [
+;;!   "enable_heap_access_spectre_mitigation=false",
+;;!   "enable_redundant_check_elimination=true",
+;;!   "opt_level=speed_and_size",
+;;! ]
+;;!
+;;! [globals.vmctx]
+;;! type = "i64"
+;;! vmctx = true
+;;!
+;;! [globals.heap_base]
+;;! type = "i64"
+;;! load = { base = "vmctx", offset = 0, readonly = true }
+;;!
+;;! [[heaps]]
+;;! base = "heap_base"
+;;! min_size = 0x10000
+;;! offset_guard_size = 0
+;;! index_type = "i64"
+;;! style = { kind = "guarded", guard_size = 0x10000, reservation = 0x100000000 }
+
+;; The same loads under every heap style: with small and large static offsets,
+;; and at constant addresses in and out of bounds.
+(module
+  (memory i64 1)
+  (func (export "load") (param i64) (result i32)
+    local.get 0
+    i32.load)
+  (func (export "load-offset") (param i64) (result i32)
+    local.get 0
+    i32.load offset=0x1000)
+  (func (export "load-large-offset") (param i64) (result i32)
+    local.get 0
+    i32.load offset=0xffff0000)
+  (func (export "load-const") (result i32)
+    i64.const 0x100
+    i32.load offset=0x10)
+  (func (export "load-const-oob") (result i32)
+    i64.const 0xfffffffffffffff0
+    i32.load offset=0x1000)
+)
+;; function u0:0(i64, i64 vmctx) -> i32 fast {
+;;     gv0 = vmctx
+;;     gv1 = load.i64 notrap aligned readonly gv0
+;;
+;;                                 block0(v0: i64, v1: i64):
+;;                                     v7 = iconst.i64 0x0001_0000_fffc
+;; @0072                               v3 = icmp ugt v0, v7  ; v7 = 0x0001_0000_fffc
+;; @0072                               brif v3, block2, block3
+;;
+;;                                 block2 cold:
+;; @0072                               trap heap_oob
+;;
+;;                                 block3:
+;; @0072                               v4 = load.i64 notrap aligned readonly v1
+;; @0072                               v5 = iadd v4, v0
+;; @0072                               v6 = load.i32 little heap v5
+;;                                     v2 -> v6
+;; @0075                               jump block1
+;;
+;;                                 block1:
+;; @0075                               return v6
+;; }
+;;
+;; function u0:1(i64, i64 vmctx) -> i32 fast {
+;;     gv0 = vmctx
+;;     gv1 = load.i64 notrap aligned readonly gv0
+;;
+;;                                 block0(v0: i64, v1: i64):
+;;                                     v8 = iconst.i64 0x0001_0000_effc
+;; @007a                               v3 = icmp ugt v0, v8  ; v8 = 0x0001_0000_effc
+;; @007a                               brif v3, block2, block3
+;;
+;;                                 block2 cold:
+;; @007a                               trap heap_oob
+;;
+;;                                 block3:
+;; @007a                               v4 = load.i64 notrap aligned readonly v1
+;; @007a                               v5 = iadd v4, v0
+;;                                     v9 = iconst.i64 4096
+;; @007a                               v6 = iadd v5, v9  ; v9 = 4096
+;; @007a                               v7 = load.i32 little heap v6
+;;                                     v2 -> v7
+;; @007e                               jump block1
+;;
+;;                                 block1:
+;; @007e                               return v7
+;; }
+;;
+;; function u0:2(i64, i64 vmctx) -> i32 fast {
+;;     gv0 = vmctx
+;;     gv1 = load.i64 notrap aligned readonly gv0
+;;
+;;                                 block0(v0: i64, v1: i64):
+;;                                     v8 = iconst.i64 0x0001_fffc
+;; @0083                               v3 = icmp ugt v0, v8  ; v8 = 0x0001_fffc
+;; @0083                               brif v3, block2, block3
+;;
+;;                                 block2 cold:
+;; @0083                               trap heap_oob
+;;
+;;                                 block3:
+;; @0083                               v4 = load.i64 notrap aligned readonly v1
+;; @0083                               v5 = iadd v4, v0
+;;                                     v9 = iconst.i64 0xffff_0000
+;; @0083                               v6 = iadd v5, v9  ; v9 = 0xffff_0000
+;; @0083                               v7 = load.i32 little heap v6
+;;                                     v2 -> v7
+;; @008a                               jump block1
+;;
+;;                                 block1:
+;; @008a                               return v7
+;; }
+;;
+;; function u0:3(i64 vmctx) -> i32 fast {
+;;     gv0 = vmctx
+;;     gv1 = load.i64 notrap aligned readonly gv0
+;;
+;;                                 block0(v0: i64):
+;; @0090                               v3 = load.i64 notrap aligned readonly v0
+;;                                     v14 = iconst.i64 272
+;;                                     v16 = iadd v3, v14  ; v14 = 272
+;;                                     v17 -> v16
+;; @0090                               v6 = load.i32 little heap v16
+;;                                     v1 -> v6
+;; @0093                               jump block1
+;;
+;;                                 block1:
+;; @0093                               return v6
+;; }
+;;
+;; function u0:4(i64 vmctx) -> i32 fast {
+;;     gv0 = vmctx
+;;     gv1 = load.i64 notrap aligned readonly gv0
+;;
+;;                                 block0(v0: i64):
+;; @0098                               jump block2
+;;
+;;                                 block2 cold:
+;; @0098                               trap heap_oob
+;; }
@@ -0,0 +1,193 @@
+//! Exhaustive checks of the lowering of integer operations at `i8` width.
+//!
+//! Each operation is wrapped in a function taking its operands as parameters and returning its
+//! result, which is compiled for the host and called through a trampoline with every possible
+//! operand, and compared to the result of the interpreter. Corner values such as `-128`, shift
+//! amounts larger than the width, or `sdiv` of `-128` by `-1` are easy to miss with random
+//! inputs, and are all covered here.
+//!
+//! The inputs for which an operation traps are only checked against the interpreter, which must
+//! trap with the expected code: a trap in the compiled code would abort the test process.
+//!
+//! Checking all the inputs takes a while, so the test is ignored by default; run it with
+//! `cargo test -p cranelift-filetests exhaustive_i8 -- --ignored`.
+
+use crate::function_runner::TestFileCompiler;
+use cranelift_codegen::data_value::DataValue;
+use cranelift_codegen::ir::{Function, TrapCode};
+use cranelift_control::ControlPlane;
+use cranelift_interpreter::environment::FunctionStore;
+use cranelift_interpreter::interpreter::{Interpreter, InterpreterState};
+use cranelift_interpreter::step::{ControlFlow, CraneliftTrap};
+use cranelift_reader::parse_functions;
+
+const UNARY: &[&str] = &["clz", "ctz", "popcnt"];
+
+const BINARY: &[&str] = &[
+    "iadd", "isub", "imul", "udiv", "sdiv", "urem", "srem", "band", "bor", "bxor", "ishl", "ushr",
+    "sshr", "rotl", "rotr",
+];
+
+const CONDITIONS: &[&str] = &[
+    "eq", "ne", "slt", "sge", "sgt", "sle", "ult", "uge", "ugt", "ule",
+];
+
+/// An operation to check, and the CLIF text of the function wrapping it.
+struct Case {
+    name: String,
+    arity: usize,
+    clif: String,
+}
+
+fn cases() -> Vec<Case> {
+    let unary = UNARY.iter().map(|op| Case {
+        name: op.to_string(),
+        arity: 1,
+        clif: format!(
+            "function %{op}(i8) -> i8 {{
+            block0(v0: i8):
+                v1 = {op} v0
+                return v1
+            }}"
+        ),
+    });
+    let binary = BINARY.iter().map(|op| Case {
+        name: op.to_string(),
+        arity: 2,
+        clif: format!(
+            "function %{op}(i8, i8) -> i8 {{
+            block0(v0: i8, v1: i8):
+                v2 = {op} v0, v1
+                return v2
+            }}"
+        ),
+    });
+    let icmp = CONDITIONS.iter().map(|cond| Case {
+        name: format!("icmp_{cond}"),
+        arity: 2,
+        clif: format!(
+            "function %icmp_{cond}(i8, i8) -> i8 {{
+            block0(v0: i8, v1: i8):
+                v2 = icmp {cond} v0, v1
+                return v2
+            }}"
+        ),
+    });
+    unary.chain(binary).chain(icmp).collect()
+}
+
+/// The trap expected when running `op` with `args`, if any.
+fn expected_trap(op: &str, args: &[i8]) -> Option<TrapCode> {
+    match (op, args) {
+        ("udiv" | "sdiv" | "urem" | "srem", [_, 0]) => Some(TrapCode::IntegerDivisionByZero),
+        ("sdiv", [i8::MIN, -1]) => Some(TrapCode::IntegerOverflow),
+        _ => None,
+    }
+}
+
+/// Every combination of `arity` operands.
+fn inputs(arity: usize) -> Vec<Vec<i8>> {
+    let mut inputs = vec![vec![]];
+    for _ in 0..arity {
+        inputs = inputs
+            .into_iter()
+            .flat_map(|prefix| {
+                (i8::MIN..=i8::MAX).map(move |x| {
+                    let mut input = prefix.clone();
+                    input.push(x);
+                    input
+                })
+            })
+            .collect();
+    }
+    inputs
+}
+
+/// Check every input of every case, returning a description of the first mismatching input of
+/// each case which has one.
+fn check(cases: &[Case]) -> Vec<String> {
+    let ctrl_plane = &mut ControlPlane::default();
+    let funcs: Vec<Function> = cases
+        .iter()
+        .map(|case| {
+            parse_functions(&case.clif)
+                .unwrap()
+                .into_iter()
+                .next()
+                .unwrap()
+        })
+        .collect();
+
+    let mut compiler = TestFileCompiler::with_default_host_isa().unwrap();
+    for func in &funcs {
+        compiler.declare_function(func).unwrap();
+    }
+    for func in &funcs {
+        compiler.define_function(func.clone(), ctrl_plane).unwrap();
+        compiler
+            .create_trampoline_for_function(func, ctrl_plane)
+            .unwrap();
+    }
+    let compiled = compiler.compile().unwrap();
+
+    let mut store = FunctionStore::default();
+    for func in &funcs {
+        store.add(func.name.to_string(), func);
+    }
+
+    let mut mismatches = vec![];
+    for (case, func) in cases.iter().zip(&funcs) {
+        let trampoline = compiled.get_trampoline(func).unwrap();
+        let mut interpreter =
+            Interpreter::new(InterpreterState::default().with_function_store(store.clone()));
+        let name = func.name.to_string();
+        for input in inputs(case.arity) {
+            let args: Vec<DataValue> = input.iter().map(|&x| DataValue::I8(x)).collect();
+            let interpreted = interpreter.call_by_name(&name, &args).unwrap();
+            let mismatch = match (interpreted, expected_trap(&case.name, &input)) {
+                (ControlFlow::Trap(CraneliftTrap::User(code)), Some(expected))
+                    if code == expected =>
+                {
+                    None
+                }
+                (ControlFlow::Return(expected), None) => {
+                    let returned = trampoline.call(&args);
+                    (returned[..] != expected[..]).then(|| {
+                        format!(
+                            "returned {:?}, interpreter returned {:?}",
+                            returned, expected
+                        )
+                    })
+                }
+                (interpreted, expected) => Some(format!(
+                    "interpreter gave {:?}, expected trap {:?}",
+                    interpreted, expected
+                )),
+            };
+            if let Some(mismatch) = mismatch {
+                mismatches.push(format!("{}{:?}: {}", case.name, input, mismatch));
+                break;
+            }
+        }
+    }
+    mismatches
+}
+
+#[test]
+#[ignore]
+fn exhaustive_i8() {
+    let mismatches = check(&cases());
+    assert!(mismatches.is_empty(), "{}", mismatches.join("\n"));
+}
+
+/// A quick check of the harness itself, running only the unary operations.
+#[test]
+fn inputs_and_cases() {
+    assert_eq!(inputs(1).len(), 256);
+    assert_eq!(inputs(2).len(), 65536);
+    assert_eq!(inputs(2)[257], vec![-127, -127]);
+    let cases = cases();
+    assert_eq!(cases.len(), UNARY.len() + BINARY.len() + CONDITIONS.len());
+    let unary: Vec<Case> = cases.into_iter().filter(|case| case.arity == 1).collect();
+    assert!(check(&unary).is_empty());
+}
@@ -2,18 +2,16 @@
 use anyhow::{anyhow, Result};
 use core::mem;
 use cranelift_codegen::data_value::DataValue;
-use cranelift_codegen::ir::{
-    ExternalName, Function, InstBuilder, Signature, UserExternalName, UserFuncName,
-};
-use cranelift_codegen::isa::{OwnedTargetIsa, TargetIsa};
+use cranelift_codegen::ir::{ExternalName, Function, Signature, UserExternalName, UserFuncName};
+use cranelift_codegen::isa::OwnedTargetIsa;
 use cranelift_codegen::{ir, settings, CodegenError, Context};
 use cranelift_control::ControlPlane;
-use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::signals::{catch_traps, Trap};
+use cranelift_jit::trampoline::{make_trampoline, TrampolineValues};
 use cranelift_jit::{JITBuilder, JITModule};
 use cranelift_module::{FuncId, Linkage, Module, ModuleError};
 use cranelift_native::builder_with_options;
 use cranelift_reader::TestFile;
-use std::cmp::max;
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use thiserror::Error;
@@ -350,17 +348,25 @@ pub struct Trampoline<'a> {
 impl<'a> Trampoline<'a> {
     /// Call the target function of this trampoline, passing in [DataValue]s using a compiled trampoline.
     pub fn call(&self, arguments: &[DataValue]) -> Vec<DataValue> {
-        let mut values = UnboxedValues::make_arguments(arguments, &self.func_signature);
+        let mut values = TrampolineValues::make_arguments(arguments, self.func_signature);
         let arguments_address = values.as_mut_ptr();
 
         let function_ptr = self.module.get_finalized_function(self.func_id);
         let trampoline_ptr = self.module.get_finalized_function(self.trampoline_id);
 
-        let callable_trampoline: fn(*const u8, *mut u128) -> () =
+        let callable_trampoline: extern "C" fn(*const u8, *mut u128) =
             unsafe { mem::transmute(trampoline_ptr) };
         callable_trampoline(function_ptr, arguments_address);
 
-        values.collect_returns(&self.func_signature)
+        values.collect_returns(self.func_signature)
+    }
+
+    /// Like [Trampoline::call], but returns the trap if the target function traps instead of
+    /// crashing the process.
+    pub fn try_call(&self, arguments: &[DataValue]) -> Result<Vec<DataValue>, Trap> {
+        // The trampoline and the target function are both JIT-compiled code of `module`, which
+        // outlives the call.
+        unsafe { catch_traps(self.module, || self.call(arguments)) }
     }
 }
 
@@ -378,139 +384,6 @@ pub enum CompilationError {
     IoError(#[from] std::io::Error),
 }
 
-/// A container for laying out the [ValueData]s in memory in a way that the [Trampoline] can
-/// understand.
-struct UnboxedValues(Vec<u128>);
-
-impl UnboxedValues {
-    /// The size in bytes of each slot location in the allocated [DataValue]s. Though [DataValue]s
-    /// could be smaller than 16 bytes (e.g. `I16`), this simplifies the creation of the [DataValue]
-    /// array and could be used to align the slots to the largest used [DataValue] (i.e. 128-bit
-    /// vectors).
-    const SLOT_SIZE: usize = 16;
-
-    /// Build the arguments vector for passing the [DataValue]s into the [Trampoline]. The size of
-    /// `u128` used here must match [Trampoline::SLOT_SIZE].
-    pub fn make_arguments(arguments: &[DataValue], signature: &ir::Signature) -> Self {
-        assert_eq!(arguments.len(), signature.params.len());
-        let mut values_vec = vec![0; max(signature.params.len(), signature.returns.len())];
-
-        // Store the argument values into `values_vec`.
-        for ((arg, slot), param) in arguments.iter().zip(&mut values_vec).zip(&signature.params) {
-            assert!(
-                arg.ty() == param.value_type || arg.is_vector(),
-                "argument type mismatch: {} != {}",
-                arg.ty(),
-                param.value_type
-            );
-            unsafe {
-                arg.write_value_to(slot);
-            }
-        }
-
-        Self(values_vec)
-    }
-
-    /// Return a pointer to the underlying memory for passing to the trampoline.
-    pub fn as_mut_ptr(&mut self) -> *mut u128 {
-        self.0.as_mut_ptr()
-    }
-
-    /// Collect the returned [DataValue]s into a [Vec]. The size of `u128` used here must match
-    /// [Trampoline::SLOT_SIZE].
-    pub fn collect_returns(&self, signature: &ir::Signature) -> Vec<DataValue> {
-        assert!(self.0.len() >= signature.returns.len());
-        let mut returns = Vec::with_capacity(signature.returns.len());
-
-        // Extract the returned values from this vector.
-        for (slot, param) in self.0.iter().zip(&signature.returns) {
-            let value = unsafe { DataValue::read_value_from(slot, param.value_type) };
-            returns.push(value);
-        }
-
-        returns
-    }
-}
-
-/// Build the Cranelift IR for moving the memory-allocated [DataValue]s to their correct location
-/// (e.g. register, stack) prior to calling a [CompiledFunction]. The [Function] returned by
-/// [make_trampoline] is compiled to a [Trampoline]. Note that this uses the [TargetIsa]'s default
-/// calling convention so we must also check that the [CompiledFunction] has the same calling
-/// convention (see [TestFileCompiler::compile]).
-fn make_trampoline(name: UserFuncName, signature: &ir::Signature, isa: &dyn TargetIsa) -> Function {
-    // Create the trampoline signature: (callee_address: pointer, values_vec: pointer) -> ()
-    let pointer_type = isa.pointer_type();
-    let mut wrapper_sig = ir::Signature::new(isa.frontend_config().default_call_conv);
-    wrapper_sig.params.push(ir::AbiParam::new(pointer_type)); // Add the `callee_address` parameter.
-    wrapper_sig.params.push(ir::AbiParam::new(pointer_type)); // Add the `values_vec` parameter.
-
-    let mut func = ir::Function::with_name_signature(name, wrapper_sig);
-
-    // The trampoline has a single block filled with loads, one call to callee_address, and some loads.
-    let mut builder_context = FunctionBuilderContext::new();
-    let mut builder = FunctionBuilder::new(&mut func, &mut builder_context);
-    let block0 = builder.create_block();
-    builder.append_block_params_for_function_params(block0);
-    builder.switch_to_block(block0);
-    builder.seal_block(block0);
-
-    // Extract the incoming SSA values.
-    let (callee_value, values_vec_ptr_val) = {
-        let params = builder.func.dfg.block_params(block0);
-        (params[0], params[1])
-    };
-
-    // Load the argument values out of `values_vec`.
-    let callee_args = signature
-        .params
-        .iter()
-        .enumerate()
-        .map(|(i, param)| {
-            // We always store vector types in little-endian byte order as DataValue.
-            let mut flags = ir::MemFlags::trusted();
-            if param.value_type.is_vector() {
-                flags.set_endianness(ir::Endianness::Little);
-            }
-
-            // Load the value.
-            builder.ins().load(
-                param.value_type,
-                flags,
-                values_vec_ptr_val,
-                (i * UnboxedValues::SLOT_SIZE) as i32,
-            )
-        })
-        .collect::<Vec<_>>();
-
-    // Call the passed function.
-    let new_sig = builder.import_signature(signature.clone());
-    let call = builder
-        .ins()
-        .call_indirect(new_sig, callee_value, &callee_args);
-
-    // Store the return values into `values_vec`.
-    let results = builder.func.dfg.inst_results(call).to_vec();
-    for ((i, value), param) in results.iter().enumerate().zip(&signature.returns) {
-        // We always store vector types in little-endian byte order as DataValue.
-        let mut flags = ir::MemFlags::trusted();
-        if param.value_type.is_vector() {
-            flags.set_endianness(ir::Endianness::Little);
-        }
-        // Store the value.
-        builder.ins().store(
-            flags,
-            *value,
-            values_vec_ptr_val,
-            (i * UnboxedValues::SLOT_SIZE) as i32,
-        );
-    }
-
-    builder.ins().return_(&[]);
-    builder.finalize();
-
-    func
-}
-
 #[cfg(target_arch = "x86_64")]
 use std::arch::x86_64::__m128i;
 #[cfg(target_arch = "x86_64")]
@@ -28,6 +28,8 @@ use cranelift_reader::TestCommand;
 use std::path::Path;
 
 mod concurrent;
+#[cfg(test)]
+mod exhaustive_i8;
 pub mod function_runner;
 mod match_directive;
 mod runner;
@@ -12,8 +12,8 @@ use cranelift_codegen::settings::Flags;
 use cranelift_codegen::{self, ir};
 use cranelift_interpreter::environment::FunctionStore;
 use cranelift_interpreter::interpreter::{Interpreter, InterpreterState};
-use cranelift_interpreter::step::ControlFlow;
-use cranelift_reader::{parse_run_command, Details, TestCommand, TestFile};
+use cranelift_interpreter::step::{ControlFlow, CraneliftTrap};
+use cranelift_reader::{parse_run_command, Details, Outcome, TestCommand, TestFile};
 use log::{info, trace};
 use std::borrow::Cow;
 
@@ -91,7 +91,8 @@ fn run_test(func_store: &FunctionStore, func: &Function, details: &Details) -> a
                     // Because we have stored function names with a leading %, we need to re-add it.
                     let func_name = &format!("%{}", func_name);
                     match Interpreter::new(state).call_by_name(func_name, &args) {
-                        Ok(ControlFlow::Return(results)) => Ok(results.to_vec()),
+                        Ok(ControlFlow::Return(results)) => Ok(Outcome::Return(results.to_vec())),
+                        Ok(ControlFlow::Trap(CraneliftTrap::User(code))) => Ok(Outcome::Trap(code)),
                         Ok(e) => {
                             panic!("Unexpected returned control flow: {:?}", e)
                         }
@@ -12,7 +12,7 @@ use cranelift_codegen::isa::{OwnedTargetIsa, TargetIsa};
 use cranelift_codegen::settings::{Configurable, Flags};
 use cranelift_codegen::{ir, settings};
 use cranelift_reader::TestCommand;
-use cranelift_reader::{parse_run_command, TestFile};
+use cranelift_reader::{parse_run_command, Outcome, TestFile};
 use log::{info, trace};
 use std::borrow::Cow;
 use target_lexicon::Architecture;
@@ -129,7 +129,10 @@ fn run_test(
                     args.extend_from_slice(run_args);
 
                     let trampoline = testfile.get_trampoline(func).unwrap();
-                    Ok(trampoline.call(&args))
+                    Ok(match trampoline.try_call(&args) {
+                        Ok(results) => Outcome::Return(results),
+                        Err(trap) => Outcome::Trap(trap.code()),
+                    })
                 })
                 .map_err(|s| anyhow::anyhow!("{}", s))?;
         }
@@ -69,13 +69,13 @@ impl TestConfig {
 
             match heap.style.kind.as_str() {
                 "static" => match &heap.style.bound {
-                    toml::value::Value::Integer(x) => {
+                    Some(toml::value::Value::Integer(x)) => {
                         ensure!(*x >= 0, "static heap bound cannot be negative")
                     }
                     _ => bail!("static heap bounds must be integers"),
                 },
                 "dynamic" => match &heap.style.bound {
-                    toml::value::Value::String(g) => {
+                    Some(toml::value::Value::String(g)) => {
                         ensure!(
                             self.globals.contains_key(g),
                             "dynamic heap bound must be a declared global"
@@ -83,9 +83,19 @@ impl TestConfig {
                     }
                     _ => bail!("dynamic heap bounds must be strings"),
                 },
+                "guarded" => {
+                    ensure!(
+                        heap.style.bound.is_none(),
+                        "guarded heaps have a reservation instead of a bound"
+                    );
+                    ensure!(
+                        heap.style.reservation.is_some(),
+                        "guarded heaps must have a reservation"
+                    );
+                }
                 other => {
                     bail!(
-                        "heap style must be 'static' or 'dynamic', found '{}'",
+                        "heap style must be 'static', 'dynamic' or 'guarded', found '{}'",
                         other
                     )
                 }
@@ -188,7 +198,7 @@ impl TestHeap {
         let mut deps = vec![self.base.as_str()];
         if self.style.kind == "dynamic" {
             deps.push(match &self.style.bound {
-                toml::Value::String(g) => g.as_str(),
+                Some(toml::Value::String(g)) => g.as_str(),
                 _ => unreachable!(),
             });
         }
@@ -199,7 +209,15 @@ impl TestHeap {
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct TestHeapStyle {
     pub kind: String,
-    pub bound: toml::value::Value,
+
+    #[serde(default)]
+    pub bound: Option<toml::value::Value>,
+
+    #[serde(default)]
+    pub guard_size: u64,
+
+    #[serde(default)]
+    pub reservation: Option<u64>,
 }
 
 impl TestHeapStyle {
@@ -210,16 +228,20 @@ impl TestHeapStyle {
         match self.kind.as_str() {
             "static" => cranelift_wasm::HeapStyle::Static {
                 bound: match &self.bound {
-                    toml::Value::Integer(x) => u64::try_from(*x).unwrap().into(),
+                    Some(toml::Value::Integer(x)) => u64::try_from(*x).unwrap().into(),
                     _ => unreachable!(),
                 },
             },
             "dynamic" => cranelift_wasm::HeapStyle::Dynamic {
                 bound_gv: match &self.bound {
-                    toml::Value::String(g) => name_to_ir_global[g],
+                    Some(toml::Value::String(g)) => name_to_ir_global[g],
                     _ => unreachable!(),
                 },
             },
+            "guarded" => cranelift_wasm::HeapStyle::Guarded {
+                guard_size: self.guard_size,
+                reservation: self.reservation.unwrap(),
+            },
             _ => unreachable!(),
         }
     }
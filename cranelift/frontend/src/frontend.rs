@@ -1,20 +1,23 @@
 //! A frontend for building Cranelift IR from other languages.
 use crate::ssa::{SSABuilder, SideEffects};
+use crate::struct_return::StructReturn;
 use crate::variable::Variable;
 use core::fmt::{self, Debug};
 use cranelift_codegen::cursor::{Cursor, FuncCursor};
 use cranelift_codegen::entity::{EntityRef, EntitySet, SecondaryMap};
 use cranelift_codegen::ir;
 use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::immediates::Offset32;
 use cranelift_codegen::ir::{
-    types, AbiParam, Block, DataFlowGraph, DynamicStackSlot, DynamicStackSlotData, ExtFuncData,
-    ExternalName, FuncRef, Function, GlobalValue, GlobalValueData, Inst, InstBuilder,
-    InstBuilderBase, InstructionData, JumpTable, JumpTableData, LibCall, MemFlags, RelSourceLoc,
-    SigRef, Signature, StackSlot, StackSlotData, Type, Value, ValueLabel, ValueLabelAssignments,
-    ValueLabelStart,
+    types, AbiParam, ArgumentPurpose, Block, DataFlowGraph, DynamicStackSlot, DynamicStackSlotData,
+    Endianness, ExtFuncData, ExternalName, FuncRef, Function, GlobalValue, GlobalValueData, Inst,
+    InstBuilder, InstBuilderBase, InstructionData, JumpTable, JumpTableData, LibCall, MemFlags,
+    RelSourceLoc, SigRef, Signature, StackSlot, StackSlotData, StackSlotKind, Type, Value,
+    ValueLabel, ValueLabelAssignments, ValueLabelStart,
 };
 use cranelift_codegen::isa::TargetFrontendConfig;
 use cranelift_codegen::packed_option::PackedOption;
+use smallvec::SmallVec;
 
 /// Structure used for translating a series of functions into Cranelift IR.
 ///
@@ -119,6 +122,10 @@ impl<'short, 'long> InstBuilderBase<'short> for FuncInstBuilder<'short, 'long> {
             ir::InstructionData::Brif {
                 blocks: [branch_then, branch_else],
                 ..
+            }
+            | ir::InstructionData::PatchableJump {
+                blocks: [branch_then, branch_else],
+                ..
             } => {
                 let block_then = branch_then.block(&self.builder.func.dfg.value_lists);
                 let block_else = branch_else.block(&self.builder.func.dfg.value_lists);
@@ -1078,6 +1085,146 @@ impl<'a> FunctionBuilder<'a> {
         let cmp = self.call_memcmp(config, left, right, size);
         self.ins().icmp_imm(zero_cc, cmp, 0)
     }
+
+    /// Create a stack slot for the struct returned by a call through a hidden pointer, to pass
+    /// to [`FunctionBuilder::call_with_struct_return`].
+    pub fn create_struct_return_slot(&mut self, sret: StructReturn) -> StackSlot {
+        // Stack slots are aligned to the largest power of two dividing their size, which is at
+        // least the alignment of the struct.
+        self.create_sized_stack_slot(StackSlotData::new(StackSlotKind::ExplicitSlot, sret.size()))
+    }
+
+    /// The hidden pointer a struct is returned through, if the function has one.
+    ///
+    /// This is a parameter of the entry block, so it is only available after the parameters of
+    /// the function have been appended to it.
+    pub fn struct_return_pointer(&self) -> Option<Value> {
+        let index = self
+            .func
+            .signature
+            .special_param_index(ArgumentPurpose::StructReturn)?;
+        // Until the first instruction is inserted, the current block is the one which will become
+        // the entry block.
+        let entry = self
+            .func
+            .layout
+            .entry_block()
+            .or_else(|| self.current_block())?;
+        self.func.dfg.block_params(entry).get(index).copied()
+    }
+
+    /// Return the struct at `src` through the hidden pointer of the function.
+    ///
+    /// This copies the struct to the memory the caller provided and returns. `src` must be
+    /// aligned like the struct, and must either be the hidden pointer itself or not overlap the
+    /// memory it points to.
+    pub fn return_struct(
+        &mut self,
+        config: TargetFrontendConfig,
+        sret: StructReturn,
+        src: Value,
+    ) -> Inst {
+        let dest = self
+            .struct_return_pointer()
+            .expect("function has no struct return parameter");
+        if src != dest {
+            let align = sret.align();
+            self.emit_small_memory_copy(
+                config,
+                dest,
+                src,
+                sret.size().into(),
+                align,
+                align,
+                true,
+                MemFlags::trusted(),
+            );
+        }
+        self.ins().return_(&[])
+    }
+
+    /// Call `func_ref`, which returns a struct through a hidden pointer, with `args` as its other
+    /// arguments and `slot` as the memory for the struct.
+    ///
+    /// The slot should have been created with [`FunctionBuilder::create_struct_return_slot`],
+    /// and holds the struct after the call.
+    pub fn call_with_struct_return(
+        &mut self,
+        func_ref: FuncRef,
+        args: &[Value],
+        slot: StackSlot,
+    ) -> Inst {
+        let sig = &self.func.dfg.signatures[self.func.dfg.ext_funcs[func_ref].signature];
+        let index = sig
+            .special_param_index(ArgumentPurpose::StructReturn)
+            .expect("callee has no struct return parameter");
+        let pointer_type = sig.params[index].value_type;
+        let addr = self.ins().stack_addr(pointer_type, slot, 0);
+        let mut call_args: SmallVec<[Value; 8]> = args.into();
+        call_args.insert(index, addr);
+        self.ins().call(func_ref, &call_args)
+    }
+
+    /// Load a value of type `ty` stored in little-endian byte order at `addr + offset`.
+    ///
+    /// Loads and stores use the byte order of the target unless their flags say otherwise, so
+    /// data whose layout doesn't depend on the target, such as the memory of a wasm module, must
+    /// be accessed with explicit byte order to give the same results on big-endian targets. For
+    /// vectors, this also makes the lanes little-endian in memory.
+    ///
+    /// Panics if `flags` specifies big-endian byte order.
+    pub fn load_le(
+        &mut self,
+        ty: Type,
+        flags: MemFlags,
+        addr: Value,
+        offset: impl Into<Offset32>,
+    ) -> Value {
+        let flags = flags.with_endianness(Endianness::Little);
+        self.ins().load(ty, flags, addr, offset)
+    }
+
+    /// Load a value of type `ty` stored in big-endian byte order at `addr + offset`.
+    ///
+    /// Panics if `flags` specifies little-endian byte order.
+    pub fn load_be(
+        &mut self,
+        ty: Type,
+        flags: MemFlags,
+        addr: Value,
+        offset: impl Into<Offset32>,
+    ) -> Value {
+        let flags = flags.with_endianness(Endianness::Big);
+        self.ins().load(ty, flags, addr, offset)
+    }
+
+    /// Store `value` in little-endian byte order at `addr + offset`.
+    ///
+    /// Panics if `flags` specifies big-endian byte order.
+    pub fn store_le(
+        &mut self,
+        flags: MemFlags,
+        value: Value,
+        addr: Value,
+        offset: impl Into<Offset32>,
+    ) -> Inst {
+        let flags = flags.with_endianness(Endianness::Little);
+        self.ins().store(flags, value, addr, offset)
+    }
+
+    /// Store `value` in big-endian byte order at `addr + offset`.
+    ///
+    /// Panics if `flags` specifies little-endian byte order.
+    pub fn store_be(
+        &mut self,
+        flags: MemFlags,
+        value: Value,
+        addr: Value,
+        offset: impl Into<Offset32>,
+    ) -> Inst {
+        let flags = flags.with_endianness(Endianness::Big);
+        self.ins().store(flags, value, addr, offset)
+    }
 }
 
 fn greatest_divisible_power_of_two(size: u64) -> u64 {
@@ -1113,12 +1260,15 @@ mod tests {
         DeclareVariableError, DefVariableError, FunctionBuilder, FunctionBuilderContext,
         UseVariableError,
     };
-    use crate::Variable;
+    use crate::{StructReturn, Variable};
     use alloc::string::ToString;
     use cranelift_codegen::entity::EntityRef;
     use cranelift_codegen::ir::condcodes::IntCC;
     use cranelift_codegen::ir::{types::*, UserFuncName};
-    use cranelift_codegen::ir::{AbiParam, Function, InstBuilder, MemFlags, Signature, Value};
+    use cranelift_codegen::ir::{
+        AbiParam, Endianness, ExtFuncData, ExternalName, Function, InstBuilder, MemFlags,
+        Signature, Value,
+    };
     use cranelift_codegen::isa::{CallConv, TargetFrontendConfig, TargetIsa};
     use cranelift_codegen::settings;
     use cranelift_codegen::verifier::verify_function;
@@ -1851,4 +2001,110 @@ block0:
             );
         }
     }
+
+    #[test]
+    fn struct_return() {
+        let frontend_config = systemv_frontend_config();
+        let sret = StructReturn::new(32, 8);
+        let mut callee_sig = Signature::new(frontend_config.default_call_conv);
+        callee_sig.params.push(AbiParam::new(I64));
+        sret.add_to_signature(&mut callee_sig, frontend_config.pointer_type());
+        let mut sig = Signature::new(frontend_config.default_call_conv);
+        sig.params.push(AbiParam::new(I64));
+        sret.add_to_signature(&mut sig, frontend_config.pointer_type());
+
+        let mut fn_ctx = FunctionBuilderContext::new();
+        let mut func = Function::with_name_signature(UserFuncName::testcase("sample"), sig);
+        {
+            let mut builder = FunctionBuilder::new(&mut func, &mut fn_ctx);
+            let block0 = builder.create_block();
+            builder.append_block_params_for_function_params(block0);
+            builder.switch_to_block(block0);
+            assert_eq!(
+                builder.struct_return_pointer(),
+                Some(builder.block_params(block0)[0])
+            );
+
+            let arg = builder.block_params(block0)[1];
+            let callee_sig = builder.import_signature(callee_sig);
+            let callee = builder.import_function(ExtFuncData {
+                name: ExternalName::testcase("callee"),
+                signature: callee_sig,
+                colocated: true,
+            });
+            let slot = builder.create_struct_return_slot(sret);
+            builder.call_with_struct_return(callee, &[arg], slot);
+            let src = builder.ins().stack_addr(I64, slot, 0);
+            builder.return_struct(frontend_config, sret, src);
+
+            builder.seal_all_blocks();
+            builder.finalize();
+        }
+
+        check(
+            &func,
+            "function %sample(i64 sret, i64) system_v {
+    ss0 = explicit_slot 32
+    sig0 = (i64 sret, i64) system_v
+    fn0 = colocated %callee sig0
+
+block0(v0: i64, v1: i64):
+    v2 = stack_addr.i64 ss0
+    call fn0(v2, v1)
+    v3 = stack_addr.i64 ss0
+    v4 = load.i64 notrap aligned v3
+    v5 = load.i64 notrap aligned v3+8
+    v6 = load.i64 notrap aligned v3+16
+    v7 = load.i64 notrap aligned v3+24
+    store notrap aligned v4, v0
+    store notrap aligned v5, v0+8
+    store notrap aligned v6, v0+16
+    store notrap aligned v7, v0+24
+    return
+}
+",
+        );
+    }
+
+    #[test]
+    fn explicit_endianness() {
+        let mut sig = Signature::new(CallConv::SystemV);
+        sig.params.push(AbiParam::new(I64));
+        sig.returns.push(AbiParam::new(I32));
+
+        let mut fn_ctx = FunctionBuilderContext::new();
+        let mut func = Function::with_name_signature(UserFuncName::testcase("sample"), sig);
+        {
+            let mut builder = FunctionBuilder::new(&mut func, &mut fn_ctx);
+            let block0 = builder.create_block();
+            builder.append_block_params_for_function_params(block0);
+            builder.switch_to_block(block0);
+            let addr = builder.block_params(block0)[0];
+            let flags = MemFlags::trusted();
+            let le = builder.load_le(I32, flags, addr, 0);
+            let be = builder.load_be(I32, MemFlags::new(), addr, 4);
+            builder.store_le(flags, be, addr, 8);
+            builder.store_be(MemFlags::new(), le, addr, 12);
+            // Flags which already have the requested byte order are fine.
+            let le_flags = flags.with_endianness(Endianness::Little);
+            let v = builder.load_le(I32, le_flags, addr, 16);
+            builder.ins().return_(&[v]);
+            builder.seal_all_blocks();
+            builder.finalize();
+        }
+
+        check(
+            &func,
+            "function %sample(i64) -> i32 system_v {
+block0(v0: i64):
+    v1 = load.i32 notrap aligned little v0
+    v2 = load.i32 big v0+4
+    store notrap aligned little v2, v0+8
+    store big v1, v0+12
+    v3 = load.i32 notrap aligned little v0+16
+    return v3
+}
+",
+        );
+    }
 }
@@ -192,11 +192,13 @@ use hashbrown::HashMap;
 use std::collections::HashMap;
 
 pub use crate::frontend::{FunctionBuilder, FunctionBuilderContext};
-pub use crate::switch::Switch;
+pub use crate::struct_return::StructReturn;
+pub use crate::switch::{Switch, SwitchStrategy};
 pub use crate::variable::Variable;
 
 mod frontend;
 mod ssa;
+mod struct_return;
 mod switch;
 mod variable;
 
@@ -0,0 +1,140 @@
+//! Returning structs through a hidden pointer.
+
+use cranelift_codegen::ir::{AbiParam, ArgumentPurpose, Signature, Type};
+use cranelift_codegen::isa::CallConv;
+use target_lexicon::{Architecture, Triple};
+
+/// The layout of a struct which a function returns through a hidden pointer.
+///
+/// C returns structs which don't fit in registers by having the caller pass the address of
+/// memory for the struct as a hidden first argument, which the callee stores the struct to. The
+/// ABI code of Cranelift passes a parameter with the [`ArgumentPurpose::StructReturn`] purpose
+/// where the platform expects that address: in the first argument register on x86_64 (`rdi`
+/// with System V, `rcx` with Windows x64, shifting the other arguments), and in `x8` on aarch64.
+/// It also returns the address in `rax` on x86_64, as both System V and Windows x64 require.
+///
+/// Add the parameter with [`StructReturn::add_to_signature`], return a struct with
+/// [`FunctionBuilder::return_struct`](crate::FunctionBuilder::return_struct) and call a function
+/// returning one with
+/// [`FunctionBuilder::call_with_struct_return`](crate::FunctionBuilder::call_with_struct_return).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct StructReturn {
+    size: u32,
+    align: u8,
+}
+
+impl StructReturn {
+    /// The layout of a struct of `size` bytes aligned to `align` bytes.
+    ///
+    /// As for any C struct, `align` must be a power of two and `size` a non-zero multiple of it.
+    /// Stack slots are aligned to at most 16 bytes, so `align` can't be larger than that.
+    pub fn new(size: u32, align: u8) -> Self {
+        assert!(
+            align.is_power_of_two() && align <= 16,
+            "unsupported struct alignment {}",
+            align
+        );
+        assert!(
+            size > 0 && size % u32::from(align) == 0,
+            "struct size {} is not a non-zero multiple of its alignment {}",
+            size,
+            align
+        );
+        Self { size, align }
+    }
+
+    /// The size of the struct in bytes.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// The alignment of the struct in bytes.
+    pub fn align(&self) -> u8 {
+        self.align
+    }
+
+    /// Whether C returns every struct of this size through a hidden pointer with the calling
+    /// convention `call_conv` on `triple`, no matter what its fields are.
+    ///
+    /// Smaller structs are returned in registers or in memory depending on their fields, which
+    /// frontends need to classify themselves.
+    pub fn is_returned_in_memory(&self, triple: &Triple, call_conv: CallConv) -> bool {
+        match (triple.architecture, call_conv) {
+            // Windows x64 returns structs of 1, 2, 4 or 8 bytes in `rax`, and all others in
+            // memory.
+            (Architecture::X86_64, CallConv::WindowsFastcall) => {
+                !matches!(self.size, 1 | 2 | 4 | 8)
+            }
+            // All aggregates are returned in memory on s390x.
+            (Architecture::S390x, _) => true,
+            // System V on x86_64, AAPCS64 and the RISC-V psABI return structs of up to two
+            // registers in registers.
+            _ => self.size > 16,
+        }
+    }
+
+    /// Add the hidden pointer as the first parameter of `sig`.
+    ///
+    /// `pointer_type` is the pointer type of the target, and `sig` must not have a struct return
+    /// parameter yet.
+    pub fn add_to_signature(&self, sig: &mut Signature, pointer_type: Type) {
+        assert!(
+            !sig.uses_struct_return_param(),
+            "signature already has a struct return parameter"
+        );
+        sig.params.insert(
+            0,
+            AbiParam::special(pointer_type, ArgumentPurpose::StructReturn),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use core::str::FromStr;
+    use cranelift_codegen::ir::types::*;
+
+    #[test]
+    fn returned_in_memory() {
+        let x86_64 = Triple::from_str("x86_64-unknown-linux-gnu").unwrap();
+        let aarch64 = Triple::from_str("aarch64-apple-darwin").unwrap();
+        let s390x = Triple::from_str("s390x-unknown-linux-gnu").unwrap();
+        let cases = [
+            (&x86_64, CallConv::SystemV, 16, false),
+            (&x86_64, CallConv::SystemV, 24, true),
+            (&x86_64, CallConv::WindowsFastcall, 8, false),
+            (&x86_64, CallConv::WindowsFastcall, 12, true),
+            (&x86_64, CallConv::WindowsFastcall, 16, true),
+            (&aarch64, CallConv::AppleAarch64, 16, false),
+            (&aarch64, CallConv::AppleAarch64, 32, true),
+            (&s390x, CallConv::SystemV, 4, true),
+        ];
+        for &(triple, call_conv, size, in_memory) in &cases {
+            let sret = StructReturn::new(size, 4);
+            assert_eq!(
+                sret.is_returned_in_memory(triple, call_conv),
+                in_memory,
+                "{} bytes on {} {}",
+                size,
+                triple,
+                call_conv
+            );
+        }
+    }
+
+    #[test]
+    fn add_to_signature() {
+        let mut sig = Signature::new(CallConv::SystemV);
+        sig.params.push(AbiParam::new(I32));
+        StructReturn::new(32, 8).add_to_signature(&mut sig, I64);
+        assert_eq!(sig.to_string(), "(i64 sret, i32) system_v");
+    }
+
+    #[test]
+    #[should_panic(expected = "not a non-zero multiple")]
+    fn unaligned_size() {
+        StructReturn::new(12, 8);
+    }
+}
@@ -7,8 +7,27 @@ use cranelift_codegen::ir::*;
 
 type EntryIndex = u128;
 
+/// How a [Switch] dispatches to its cases.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SwitchStrategy {
+    /// Use a jump table for every run of consecutive cases and a balanced tree of comparisons to
+    /// find the right run. Sparse case sets are lowered to comparisons only, while dense ones
+    /// become a single jump table.
+    #[default]
+    Auto,
+    /// Use a single jump table spanning all cases, sending the gaps between cases to the default
+    /// block. A switch with a single case is still lowered to a comparison.
+    ///
+    /// The span between the smallest and largest case must fit in a jump table, i.e. contain fewer
+    /// than 2^32 entries.
+    JumpTable,
+    /// Use a balanced tree of comparisons and never emit a jump table.
+    Tree,
+}
+
 /// Unlike with `br_table`, `Switch` cases may be sparse or non-0-based.
-/// They emit efficient code using branches, jump tables, or a combination of both.
+/// They emit efficient code using branches, jump tables, or a combination of both, as selected by
+/// the [SwitchStrategy].
 ///
 /// # Example
 ///
@@ -42,6 +61,7 @@ type EntryIndex = u128;
 #[derive(Debug, Default)]
 pub struct Switch {
     cases: HashMap<EntryIndex, Block>,
+    strategy: SwitchStrategy,
 }
 
 impl Switch {
@@ -49,9 +69,16 @@ impl Switch {
     pub fn new() -> Self {
         Self {
             cases: HashMap::new(),
+            strategy: SwitchStrategy::Auto,
         }
     }
 
+    /// Set the strategy used to lower this switch. Defaults to [SwitchStrategy::Auto].
+    pub fn strategy(&mut self, strategy: SwitchStrategy) -> &mut Self {
+        self.strategy = strategy;
+        self
+    }
+
     /// Set a switch entry
     pub fn set_entry(&mut self, index: EntryIndex, block: Block) {
         let prev = self.cases.insert(index, block);
@@ -77,8 +104,7 @@ impl Switch {
     /// * No `ContiguousCaseRange`s will be empty.
     fn collect_contiguous_case_ranges(self) -> Vec<ContiguousCaseRange> {
         log::trace!("build_contiguous_case_ranges before: {:#?}", self.cases);
-        let mut cases = self.cases.into_iter().collect::<Vec<(_, _)>>();
-        cases.sort_by_key(|&(index, _)| index);
+        let cases = self.sorted_cases();
 
         let mut contiguous_case_ranges: Vec<ContiguousCaseRange> = vec![];
         let mut last_index = None;
@@ -107,6 +133,45 @@ impl Switch {
         contiguous_case_ranges
     }
 
+    /// Turn every case into its own `ContiguousCaseRange`, so that no jump tables are built.
+    fn collect_single_case_ranges(self) -> Vec<ContiguousCaseRange> {
+        self.sorted_cases()
+            .into_iter()
+            .map(|(index, block)| ContiguousCaseRange {
+                first_index: index,
+                blocks: vec![block],
+            })
+            .collect()
+    }
+
+    /// Turn all cases into a single `ContiguousCaseRange`, filling the gaps between them with
+    /// `otherwise`.
+    fn collect_spanning_case_range(self, otherwise: Block) -> Vec<ContiguousCaseRange> {
+        let cases = self.sorted_cases();
+        let (first_index, last_index) = match (cases.first(), cases.last()) {
+            (Some(&(first, _)), Some(&(last, _))) => (first, last),
+            _ => return vec![],
+        };
+        let len = usize::try_from(last_index - first_index + 1)
+            .ok()
+            .filter(|&len| u32::try_from(len).is_ok())
+            .expect("Jump tables bigger than 2^32-1 are not yet supported");
+
+        let mut range = ContiguousCaseRange::new(first_index);
+        range.blocks = vec![otherwise; len];
+        for (index, block) in cases {
+            range.blocks[(index - first_index) as usize] = block;
+        }
+        vec![range]
+    }
+
+    /// Return all cases sorted by their entry index.
+    fn sorted_cases(self) -> Vec<(EntryIndex, Block)> {
+        let mut cases = self.cases.into_iter().collect::<Vec<(_, _)>>();
+        cases.sort_by_key(|&(index, _)| index);
+        cases
+    }
+
     /// Binary search for the right `ContiguousCaseRange`.
     fn build_search_tree<'a>(
         bx: &mut FunctionBuilder,
@@ -284,7 +349,11 @@ impl Switch {
             );
         }
 
-        let contiguous_case_ranges = self.collect_contiguous_case_ranges();
+        let contiguous_case_ranges = match self.strategy {
+            SwitchStrategy::Auto => self.collect_contiguous_case_ranges(),
+            SwitchStrategy::JumpTable => self.collect_spanning_case_range(otherwise),
+            SwitchStrategy::Tree => self.collect_single_case_ranges(),
+        };
         Self::build_search_tree(bx, val, otherwise, &contiguous_case_ranges);
     }
 }
@@ -346,7 +415,10 @@ mod tests {
     use cranelift_codegen::ir::Function;
 
     macro_rules! setup {
-        ($default:expr, [$($index:expr,)*]) => {{
+        ($default:expr, [$($index:expr,)*]) => {
+            setup!($default, [$($index,)*], SwitchStrategy::Auto)
+        };
+        ($default:expr, [$($index:expr,)*], $strategy:expr) => {{
             let mut func = Function::new();
             let mut func_ctx = FunctionBuilderContext::new();
             {
@@ -356,6 +428,7 @@ mod tests {
                 let val = bx.ins().iconst(types::I8, 0);
                 #[allow(unused_mut)]
                 let mut switch = Switch::new();
+                switch.strategy($strategy);
                 $(
                     let block = bx.create_block();
                     switch.set_entry($index, block);
@@ -477,6 +550,48 @@ block12:
         );
     }
 
+    #[test]
+    fn switch_sparse_auto() {
+        let func = setup!(0, [3, 17, 40, 42, 90, 128, 200, 250,]);
+        assert!(!func.contains("br_table"), "{}", func);
+    }
+
+    #[test]
+    fn switch_tree() {
+        let func = setup!(0, [0, 1, 2,], SwitchStrategy::Tree);
+        assert_eq_output!(
+            func,
+            "block0:
+    v0 = iconst.i8 0
+    v1 = icmp_imm eq v0, 2  ; v0 = 0
+    brif v1, block3, block4
+
+block4:
+    v2 = icmp_imm.i8 eq v0, 1  ; v0 = 0
+    brif v2, block2, block5
+
+block5:
+    brif.i8 v0, block0, block1  ; v0 = 0"
+        );
+    }
+
+    #[test]
+    fn switch_jump_table() {
+        let func = setup!(0, [1, 2, 5,], SwitchStrategy::JumpTable);
+        assert_eq_output!(
+            func,
+            "block0:
+    v0 = iconst.i8 0
+    v1 = icmp_imm uge v0, 1  ; v0 = 0
+    brif v1, block4, block0
+
+block4:
+    v2 = iadd_imm.i8 v0, -1  ; v0 = 0
+    v3 = uextend.i32 v2
+    br_table v3, block0, [block1, block2, block0, block0, block3]"
+        );
+    }
+
     #[test]
     fn switch_min_index_value() {
         let func = setup!(0, [i8::MIN as u8 as u128, 1,]);
@@ -736,7 +736,11 @@ static OPCODE_SIGNATURES: Lazy<Vec<OpcodeSignature>> = Lazy::new(|| {
         .filter(|op| {
             match op {
                 // Control flow opcodes should not be generated through `generate_instructions`.
-                Opcode::BrTable | Opcode::Brif | Opcode::Jump | Opcode::Return => false,
+                Opcode::BrTable
+                | Opcode::Brif
+                | Opcode::Jump
+                | Opcode::PatchableJump
+                | Opcode::Return => false,
 
                 // Constants are generated outside of `generate_instructions`
                 Opcode::Iconst => false,
@@ -745,6 +749,9 @@ static OPCODE_SIGNATURES: Lazy<Vec<OpcodeSignature>> = Lazy::new(|| {
                 // uses dynamic vectors.
                 Opcode::ExtractVector => false,
 
+                // Frontend extension operations must be replaced before code generation.
+                Opcode::Ext0 | Opcode::Ext1 | Opcode::Ext2 | Opcode::Ext3 => false,
+
                 _ => true,
             }
         })
@@ -853,6 +860,11 @@ static OPCODE_SIGNATURES: Lazy<Vec<OpcodeSignature>> = Lazy::new(|| {
                 (Opcode::IaddCin),
                 (Opcode::IaddCarry),
                 (Opcode::UaddOverflowTrap),
+                (Opcode::SaddOverflowTrap),
+                (Opcode::UsubOverflowTrap),
+                (Opcode::SsubOverflowTrap),
+                (Opcode::UmulOverflowTrap),
+                (Opcode::SmulOverflowTrap),
                 (Opcode::IsubBin),
                 (Opcode::IsubBorrow),
                 (Opcode::BandImm),
@@ -1431,7 +1443,8 @@ fn inserter_for_format(fmt: InstructionFormat) -> OpcodeInserter {
         InstructionFormat::BranchTable
         | InstructionFormat::Brif
         | InstructionFormat::Jump
-        | InstructionFormat::MultiAry => {
+        | InstructionFormat::MultiAry
+        | InstructionFormat::PatchableJump => {
             panic!(
                 "Control-flow instructions should be handled by 'insert_terminator': {:?}",
                 fmt
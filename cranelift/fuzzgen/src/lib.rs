@@ -174,6 +174,9 @@ where
         //   aarch64: https://github.com/bytecodealliance/wasmtime/issues/2735
         let bool_settings = [
             "enable_alias_analysis",
+            "enable_pressure_scheduling",
+            "enable_loop_unrolling",
+            "enable_redundant_check_elimination",
             "enable_safepoints",
             "unwind_info",
             "preserve_frame_pointers",
@@ -0,0 +1,152 @@
+//! Hooks for controlling the [Interpreter](crate::interpreter::Interpreter) one instruction at a
+//! time, e.g. to single-step CLIF or to stop at breakpoints.
+//!
+//! An [InterpreterControl] is consulted before the interpreter executes an instruction and decides
+//! how execution proceeds: it can keep stepping, step over a call, let execution run freely until
+//! the next [Breakpoint], or stop with [ControlAction::Break]. A stopped interpreter keeps all of
+//! its frames alive so that the state can be inspected (see [FrameView]) and execution later
+//! continued with [Interpreter::resume](crate::interpreter::Interpreter::resume).
+use crate::frame::Frame;
+use cranelift_codegen::data_value::DataValue;
+use cranelift_codegen::ir::{Function, Inst, SourceLoc, Value};
+use std::fmt;
+
+/// The decision returned by an [InterpreterControl] before an instruction is executed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlAction {
+    /// Execute the instruction and run freely: the control hook is not consulted again until a
+    /// [Breakpoint] is hit.
+    Continue,
+    /// Execute the instruction and consult the control hook before the next executed
+    /// instruction; if the instruction is a call, that is the first instruction of the callee.
+    StepInto,
+    /// Execute the instruction and consult the control hook before the next instruction in this
+    /// frame (or a caller's frame); calls are run to completion without consulting the hook.
+    /// Breakpoints inside the callee still stop execution.
+    StepOver,
+    /// Stop execution before the instruction is executed.
+    Break(String),
+}
+
+/// Why the interpreter stopped executing; see
+/// [InterpreterError::Break](crate::interpreter::InterpreterError::Break).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BreakReason {
+    /// The control hook returned [ControlAction::Break] with this message.
+    Requested(String),
+    /// Execution reached a [Breakpoint].
+    Breakpoint(Breakpoint),
+}
+
+impl fmt::Display for BreakReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BreakReason::Requested(reason) => write!(f, "{}", reason),
+            BreakReason::Breakpoint(bp) => write!(f, "breakpoint at {}", bp),
+        }
+    }
+}
+
+/// A location at which the interpreter stops before executing an instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// Stop at `inst` of the function named `function`; the name is the one used in the
+    /// [FunctionStore](crate::environment::FunctionStore), e.g. `%fact`.
+    Inst { function: String, inst: Inst },
+    /// Stop at any instruction with this source location.
+    SourceLoc(SourceLoc),
+}
+
+impl Breakpoint {
+    /// Check if this breakpoint applies to `inst` in `function`.
+    pub(crate) fn matches(&self, function: &Function, inst: Inst) -> bool {
+        match self {
+            Breakpoint::Inst {
+                function: name,
+                inst: bp_inst,
+            } => *bp_inst == inst && function.name.to_string() == *name,
+            Breakpoint::SourceLoc(srcloc) => function.srcloc(inst) == *srcloc,
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Breakpoint::Inst { function, inst } => write!(f, "{}:{}", function, inst),
+            Breakpoint::SourceLoc(srcloc) => write!(f, "srcloc {}", srcloc),
+        }
+    }
+}
+
+/// A read-only view of an interpreter frame while execution is stopped before an instruction.
+pub struct FrameView<'f, 'a> {
+    frame: &'f Frame<'a>,
+    inst: Inst,
+    depth: usize,
+}
+
+impl<'f, 'a> FrameView<'f, 'a> {
+    pub(crate) fn new(frame: &'f Frame<'a>, inst: Inst, depth: usize) -> Self {
+        Self { frame, inst, depth }
+    }
+
+    /// The function executing in this frame.
+    pub fn function(&self) -> &'a Function {
+        self.frame.function()
+    }
+
+    /// The instruction about to be executed (or, for a caller's frame, the pending call).
+    pub fn inst(&self) -> Inst {
+        self.inst
+    }
+
+    /// The source location of [FrameView::inst].
+    pub fn srcloc(&self) -> SourceLoc {
+        self.function().srcloc(self.inst)
+    }
+
+    /// The depth of this frame in the call stack; the outermost frame has depth 0.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// The value bound to `value`, if it has been defined by this point of the execution.
+    pub fn value(&self, value: Value) -> Option<&DataValue> {
+        self.frame.try_get(value)
+    }
+
+    /// The current values of the arguments of [FrameView::inst].
+    pub fn inst_args(&self) -> Vec<Option<DataValue>> {
+        self.function()
+            .dfg
+            .inst_args(self.inst)
+            .iter()
+            .map(|v| self.value(*v).cloned())
+            .collect()
+    }
+
+    /// All values that have been defined in this frame so far, in value order.
+    pub fn defined_values(&self) -> impl Iterator<Item = (Value, &DataValue)> + '_ {
+        self.function()
+            .dfg
+            .values()
+            .filter_map(move |v| Some((v, self.frame.try_get(v)?)))
+    }
+}
+
+/// A hook that is consulted before the interpreter executes an instruction; see the module
+/// documentation.
+pub trait InterpreterControl {
+    /// Decide how to proceed before executing `frame.inst()`.
+    fn before_inst(&mut self, frame: &FrameView) -> ControlAction;
+}
+
+impl<F> InterpreterControl for F
+where
+    F: FnMut(&FrameView) -> ControlAction,
+{
+    fn before_inst(&mut self, frame: &FrameView) -> ControlAction {
+        self(frame)
+    }
+}
@@ -1,7 +1,7 @@
 //! Implements a call frame (activation record) for the Cranelift interpreter.
 
 use cranelift_codegen::data_value::DataValue;
-use cranelift_codegen::ir::{types, Function, Value as ValueRef};
+use cranelift_codegen::ir::{types, Function, Inst, Value as ValueRef};
 use cranelift_entity::EntityRef;
 use log::trace;
 
@@ -16,6 +16,9 @@ pub struct Frame<'a> {
     /// The current mapping of SSA value-references to their actual values. For efficiency, each SSA value is used as an
     /// index into the Vec, meaning some slots may be unused.
     registers: Entries,
+    /// The instruction that will be (or is being) executed next in this frame. For frames that are
+    /// not at the top of the stack this is the call instruction waiting on its callee.
+    current_inst: Option<Inst>,
 }
 
 impl<'a> Frame<'a> {
@@ -27,6 +30,7 @@ impl<'a> Frame<'a> {
         Self {
             function,
             registers: vec![None; num_slots],
+            current_inst: None,
         }
     }
 
@@ -59,6 +63,19 @@ impl<'a> Frame<'a> {
             .unwrap_or_else(|| panic!("empty slot: {}", name))
     }
 
+    /// Retrieve the value associated with an SSA reference, if it has been defined yet. Unlike
+    /// `get`, this never panics; it is intended for inspecting a frame from outside the
+    /// interpreter (e.g. while debugging).
+    pub fn try_get(&self, name: ValueRef) -> Option<&DataValue> {
+        self.registers.get(name.index())?.as_ref().or_else(|| {
+            if self.function.dfg.value_type(name) == types::INVALID {
+                return None;
+            }
+            let alias = self.function.dfg.resolve_aliases(name);
+            self.registers.get(alias.index())?.as_ref()
+        })
+    }
+
     /// Retrieve multiple SSA references; see `get`.
     pub fn get_all(&self, names: &[ValueRef]) -> Vec<DataValue> {
         names.iter().map(|r| self.get(*r)).cloned().collect()
@@ -105,6 +122,16 @@ impl<'a> Frame<'a> {
     pub fn function(&self) -> &'a Function {
         self.function
     }
+
+    /// The instruction this frame will execute next, if any.
+    pub fn current_inst(&self) -> Option<Inst> {
+        self.current_inst
+    }
+
+    /// Set the instruction this frame will execute next.
+    pub fn set_current_inst(&mut self, inst: Option<Inst>) {
+        self.current_inst = inst;
+    }
 }
 
 #[cfg(test)]
@@ -3,6 +3,7 @@
 //! This module partially contains the logic for interpreting Cranelift IR.
 
 use crate::address::{Address, AddressFunctionEntry, AddressRegion, AddressSize};
+use crate::control::{BreakReason, Breakpoint, ControlAction, FrameView, InterpreterControl};
 use crate::environment::{FuncIndex, FunctionStore};
 use crate::frame::Frame;
 use crate::instruction::DfgInstructionContext;
@@ -11,8 +12,8 @@ use crate::step::{step, ControlFlow, StepError};
 use crate::value::{DataValueExt, ValueError};
 use cranelift_codegen::data_value::DataValue;
 use cranelift_codegen::ir::{
-    ArgumentPurpose, Block, Endianness, ExternalName, FuncRef, Function, GlobalValue,
-    GlobalValueData, LibCall, MemFlags, StackSlot, TrapCode, Type,
+    ArgumentPurpose, Endianness, ExternalName, FuncRef, Function, GlobalValue, GlobalValueData,
+    Inst, LibCall, MemFlags, StackSlot, TrapCode, Type,
 };
 use log::trace;
 use smallvec::SmallVec;
@@ -27,11 +28,37 @@ use thiserror::Error;
 pub struct Interpreter<'a> {
     state: InterpreterState<'a>,
     fuel: Option<u64>,
+    control: Option<Box<dyn InterpreterControl + 'a>>,
+    breakpoints: Vec<Breakpoint>,
+    step_mode: StepMode,
+    /// The depth of the frame stack when the outermost call of this interpreter started.
+    entry_depth: usize,
+    /// Set when execution stopped at a breakpoint or by request and can be resumed.
+    paused: bool,
+}
+
+/// When the [InterpreterControl] hook should be consulted.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum StepMode {
+    /// Before every instruction.
+    Step,
+    /// Before every instruction in frames at or below this depth.
+    StepOver(usize),
+    /// Only after a breakpoint has been hit.
+    Run,
 }
 
 impl<'a> Interpreter<'a> {
     pub fn new(state: InterpreterState<'a>) -> Self {
-        Self { state, fuel: None }
+        Self {
+            state,
+            fuel: None,
+            control: None,
+            breakpoints: vec![],
+            step_mode: StepMode::Step,
+            entry_depth: 0,
+            paused: false,
+        }
     }
 
     /// The `fuel` mechanism sets a number of instructions that
@@ -41,6 +68,28 @@ impl<'a> Interpreter<'a> {
         Self { fuel, ..self }
     }
 
+    /// Install a hook that is consulted before instructions are executed; see
+    /// [InterpreterControl].
+    pub fn with_control(self, control: impl InterpreterControl + 'a) -> Self {
+        Self {
+            control: Some(Box::new(control)),
+            ..self
+        }
+    }
+
+    /// Stop execution whenever `breakpoint` is reached; execution can be continued with
+    /// [Interpreter::resume].
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        self.breakpoints.push(breakpoint);
+    }
+
+    /// Remove a previously added breakpoint, returning whether it was present.
+    pub fn remove_breakpoint(&mut self, breakpoint: &Breakpoint) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|bp| bp != breakpoint);
+        self.breakpoints.len() != len
+    }
+
     /// Call a function by name; this is a helpful proxy for [Interpreter::call_by_index].
     pub fn call_by_name(
         &mut self,
@@ -68,12 +117,44 @@ impl<'a> Interpreter<'a> {
         }
     }
 
+    /// Continue an execution that was stopped with [InterpreterError::Break], starting with the
+    /// instruction it stopped at.
+    pub fn resume(&mut self) -> Result<ControlFlow<'a>, InterpreterError> {
+        if !self.paused {
+            return Err(InterpreterError::NotPaused);
+        }
+        self.run()
+    }
+
+    /// Inspect the innermost frame of a stopped execution.
+    pub fn current_frame(&self) -> Option<FrameView<'_, 'a>> {
+        self.frames().last()
+    }
+
+    /// Inspect all frames of a stopped execution, from the outermost to the innermost.
+    pub fn frames(&self) -> impl Iterator<Item = FrameView<'_, 'a>> {
+        self.state
+            .frame_stack
+            .iter()
+            .enumerate()
+            .filter_map(|(depth, frame)| Some(FrameView::new(frame, frame.current_inst()?, depth)))
+    }
+
     /// Interpret a call to a [Function] given its [DataValue] arguments.
     fn call(
         &mut self,
         function: &'a Function,
         arguments: &[DataValue],
     ) -> Result<ControlFlow<'a>, InterpreterError> {
+        self.entry_depth = self.state.frame_stack.len();
+        self.paused = false;
+        self.step_mode = StepMode::Step;
+        self.push_call(function, arguments);
+        self.run()
+    }
+
+    /// Push a new frame for `function`, binding `arguments` to the entry block's parameters.
+    fn push_call(&mut self, function: &'a Function, arguments: &[DataValue]) {
         trace!("Call: {}({:?})", function.name, arguments);
         let first_block = function
             .layout
@@ -82,21 +163,29 @@ impl<'a> Interpreter<'a> {
             .expect("to have a first block");
         let parameters = function.dfg.block_params(first_block);
         self.state.push_frame(function);
-        self.state
-            .current_frame_mut()
-            .set_all(parameters, arguments.to_vec());
-
-        self.block(first_block)
+        let frame = self.state.current_frame_mut();
+        frame.set_all(parameters, arguments.to_vec());
+        frame.set_current_inst(function.layout.first_inst(first_block));
+        trace!("Block: {}", first_block);
     }
 
-    /// Interpret a [Block] in a [Function]. This drives the interpretation over sequences of
-    /// instructions, which may continue in other blocks, until the function returns.
-    fn block(&mut self, block: Block) -> Result<ControlFlow<'a>, InterpreterError> {
-        trace!("Block: {}", block);
-        let function = self.state.current_frame_mut().function();
-        let layout = &function.layout;
-        let mut maybe_inst = layout.first_inst(block);
-        while let Some(inst) = maybe_inst {
+    /// Interpret instructions, starting at the current instruction of the innermost frame and
+    /// following calls and branches, until the outermost function returns.
+    fn run(&mut self) -> Result<ControlFlow<'a>, InterpreterError> {
+        loop {
+            let frame = self.state.current_frame();
+            let function = frame.function();
+            let layout = &function.layout;
+            let inst = frame.current_inst().ok_or(InterpreterError::Unreachable)?;
+
+            // A resumed execution must not stop again at the instruction it stopped at.
+            if !std::mem::take(&mut self.paused) {
+                if let Some(reason) = self.check_control(inst) {
+                    self.paused = true;
+                    return Err(InterpreterError::Break(reason));
+                }
+            }
+
             if self.consume_fuel() == FuelResult::Stop {
                 return Err(InterpreterError::FuelExhausted);
             }
@@ -104,52 +193,82 @@ impl<'a> Interpreter<'a> {
             let inst_context = DfgInstructionContext::new(inst, &function.dfg);
             match step(&mut self.state, inst_context)? {
                 ControlFlow::Assign(values) => {
+                    let frame = self.state.current_frame_mut();
+                    frame.set_all(function.dfg.inst_results(inst), values.to_vec());
+                    frame.set_current_inst(layout.next_inst(inst));
+                }
+                ControlFlow::Continue => {
                     self.state
                         .current_frame_mut()
-                        .set_all(function.dfg.inst_results(inst), values.to_vec());
-                    maybe_inst = layout.next_inst(inst)
+                        .set_current_inst(layout.next_inst(inst));
                 }
-                ControlFlow::Continue => maybe_inst = layout.next_inst(inst),
                 ControlFlow::ContinueAt(block, block_arguments) => {
                     trace!("Block: {}", block);
-                    self.state
-                        .current_frame_mut()
-                        .set_all(function.dfg.block_params(block), block_arguments.to_vec());
-                    maybe_inst = layout.first_inst(block)
+                    let frame = self.state.current_frame_mut();
+                    frame.set_all(function.dfg.block_params(block), block_arguments.to_vec());
+                    frame.set_current_inst(layout.first_inst(block));
                 }
                 ControlFlow::Call(called_function, arguments) => {
-                    match self.call(called_function, &arguments)? {
-                        ControlFlow::Return(rets) => {
-                            self.state
-                                .current_frame_mut()
-                                .set_all(function.dfg.inst_results(inst), rets.to_vec());
-                            maybe_inst = layout.next_inst(inst)
-                        }
-                        ControlFlow::Trap(trap) => return Ok(ControlFlow::Trap(trap)),
-                        cf => {
-                            panic!("invalid control flow after call: {:?}", cf)
-                        }
-                    }
+                    // The caller's frame stays at the call instruction until the callee returns.
+                    self.push_call(called_function, &arguments);
                 }
                 ControlFlow::ReturnCall(callee, args) => {
                     self.state.pop_frame();
-
-                    return match self.call(callee, &args)? {
-                        ControlFlow::Return(rets) => Ok(ControlFlow::Return(rets)),
-                        ControlFlow::Trap(trap) => Ok(ControlFlow::Trap(trap)),
-                        cf => {
-                            panic!("invalid control flow after return_call: {:?}", cf)
-                        }
-                    };
+                    self.push_call(callee, &args);
                 }
                 ControlFlow::Return(returned_values) => {
                     self.state.pop_frame();
-                    return Ok(ControlFlow::Return(returned_values));
+                    if self.state.frame_stack.len() <= self.entry_depth {
+                        return Ok(ControlFlow::Return(returned_values));
+                    }
+
+                    // Deliver the results to the call instruction waiting in the caller.
+                    let frame = self.state.current_frame_mut();
+                    let caller = frame.function();
+                    let call = frame
+                        .current_inst()
+                        .expect("caller frame to be at a call instruction");
+                    frame.set_all(caller.dfg.inst_results(call), returned_values.to_vec());
+                    frame.set_current_inst(caller.layout.next_inst(call));
                 }
                 ControlFlow::Trap(trap) => return Ok(ControlFlow::Trap(trap)),
             }
         }
-        Err(InterpreterError::Unreachable)
+    }
+
+    /// Check the breakpoints and consult the control hook before executing `inst`, returning the
+    /// reason execution should stop, if any.
+    fn check_control(&mut self, inst: Inst) -> Option<BreakReason> {
+        let frame = self.state.current_frame();
+        let function = frame.function();
+        if let Some(bp) = self
+            .breakpoints
+            .iter()
+            .find(|bp| bp.matches(function, inst))
+        {
+            // Hitting a breakpoint hands control back to the hook once execution resumes.
+            self.step_mode = StepMode::Step;
+            return Some(BreakReason::Breakpoint(bp.clone()));
+        }
+
+        let control = self.control.as_mut()?;
+        let depth = self.state.frame_stack.len() - 1;
+        let consult = match self.step_mode {
+            StepMode::Step => true,
+            StepMode::StepOver(d) => depth <= d,
+            StepMode::Run => false,
+        };
+        if !consult {
+            return None;
+        }
+
+        match control.before_inst(&FrameView::new(frame, inst, depth)) {
+            ControlAction::Continue => self.step_mode = StepMode::Run,
+            ControlAction::StepInto => self.step_mode = StepMode::Step,
+            ControlAction::StepOver => self.step_mode = StepMode::StepOver(depth),
+            ControlAction::Break(reason) => return Some(BreakReason::Requested(reason)),
+        }
+        None
     }
 
     fn consume_fuel(&mut self) -> FuelResult {
@@ -190,6 +309,10 @@ pub enum InterpreterError {
     ValueError(#[from] ValueError),
     #[error("fuel exhausted")]
     FuelExhausted,
+    #[error("execution stopped: {0}")]
+    Break(BreakReason),
+    #[error("there is no stopped execution to resume")]
+    NotPaused,
 }
 
 pub type LibCallValues = SmallVec<[DataValue; 1]>;
@@ -345,9 +468,18 @@ impl<'a> State<'a> for InterpreterState<'a> {
             return Err(MemoryError::MisalignedLoad { addr, load_size });
         }
 
-        Ok(match mem_flags.endianness(self.native_endianness) {
-            Endianness::Big => DataValue::read_from_slice_be(src, ty),
-            Endianness::Little => DataValue::read_from_slice_le(src, ty),
+        let endianness = mem_flags.endianness(self.native_endianness);
+        Ok(if ty.is_vector() {
+            let mut bytes = src.to_vec();
+            if endianness != self.native_endianness {
+                swap_lane_bytes(&mut bytes, ty);
+            }
+            DataValue::read_from_slice_ne(&bytes, ty)
+        } else {
+            match endianness {
+                Endianness::Big => DataValue::read_from_slice_be(src, ty),
+                Endianness::Little => DataValue::read_from_slice_le(src, ty),
+            }
         })
     }
 
@@ -355,9 +487,10 @@ impl<'a> State<'a> for InterpreterState<'a> {
         &mut self,
         addr: Address,
         v: DataValue,
+        ty: Type,
         mem_flags: MemFlags,
     ) -> Result<(), MemoryError> {
-        let store_size = v.ty().bytes() as usize;
+        let store_size = ty.bytes() as usize;
         let addr_start = addr.offset as usize;
         let addr_end = addr_start + store_size;
 
@@ -377,10 +510,19 @@ impl<'a> State<'a> for InterpreterState<'a> {
             return Err(MemoryError::MisalignedStore { addr, store_size });
         }
 
-        Ok(match mem_flags.endianness(self.native_endianness) {
-            Endianness::Big => v.write_to_slice_be(dst),
-            Endianness::Little => v.write_to_slice_le(dst),
-        })
+        let endianness = mem_flags.endianness(self.native_endianness);
+        if ty.is_vector() {
+            v.write_to_slice_ne(dst);
+            if endianness != self.native_endianness {
+                swap_lane_bytes(dst, ty);
+            }
+        } else {
+            match endianness {
+                Endianness::Big => v.write_to_slice_be(dst),
+                Endianness::Little => v.write_to_slice_le(dst),
+            }
+        }
+        Ok(())
     }
 
     fn function_address(
@@ -551,12 +693,22 @@ impl<'a> State<'a> for InterpreterState<'a> {
     }
 }
 
+/// Swap the bytes of each lane of the vector of type `ty` held in `bytes`.
+///
+/// Vectors are accessed in memory lane by lane, so a vector accessed in the non-native byte order
+/// has the bytes of each of its lanes swapped, while its lanes stay in place.
+fn swap_lane_bytes(bytes: &mut [u8], ty: Type) {
+    for lane in bytes.chunks_mut(ty.lane_type().bytes() as usize) {
+        lane.reverse();
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::step::CraneliftTrap;
     use cranelift_codegen::ir::immediates::Ieee32;
-    use cranelift_codegen::ir::TrapCode;
+    use cranelift_codegen::ir::{Opcode, SourceLoc, TrapCode};
     use cranelift_reader::parse_functions;
     use smallvec::smallvec;
 
@@ -916,7 +1068,7 @@ mod tests {
     }
 
     #[test]
-    fn srem_trap() {
+    fn srem_min_by_minus_one() {
         let code = "function %test() -> i64 {
         block0:
             v0 = iconst.i64 0x8000_0000_0000_0000
@@ -929,12 +1081,9 @@ mod tests {
         let mut env = FunctionStore::default();
         env.add(func.name.to_string(), &func);
         let state = InterpreterState::default().with_function_store(env);
-        let trap = Interpreter::new(state).call_by_name("%test", &[]).unwrap();
+        let result = Interpreter::new(state).call_by_name("%test", &[]).unwrap();
 
-        assert_eq!(
-            trap,
-            ControlFlow::Trap(CraneliftTrap::User(TrapCode::IntegerOverflow))
-        );
+        assert_eq!(result, ControlFlow::Return(smallvec![DataValue::I64(0)]));
     }
 
     #[test]
@@ -1061,4 +1210,181 @@ mod tests {
             ControlFlow::Trap(CraneliftTrap::User(TrapCode::HeapMisaligned))
         );
     }
+
+    const FACTORIAL: &str = "
+        function %fact(i32) -> i32 {
+            fn0 = %fact(i32) -> i32
+
+        block0(v0: i32):
+            v1 = icmp_imm ule v0, 1
+            brif v1, block1, block2
+
+        block1:
+            v2 = iconst.i32 1
+            return v2
+
+        block2:
+            v3 = iadd_imm v0, -1
+            v4 = call fn0(v3)
+            v5 = imul v0, v4
+            return v5
+        }";
+
+    /// Find the first instruction with the given opcode in the function.
+    fn find_inst(func: &Function, opcode: Opcode) -> Inst {
+        func.layout
+            .blocks()
+            .flat_map(|block| func.layout.block_insts(block))
+            .find(|inst| func.dfg.insts[*inst].opcode() == opcode)
+            .unwrap()
+    }
+
+    #[test]
+    fn breakpoint_and_resume() {
+        let func = parse_functions(FACTORIAL)
+            .unwrap()
+            .into_iter()
+            .next()
+            .unwrap();
+        let imul = find_inst(&func, Opcode::Imul);
+        let env = FunctionStore::from(&func);
+        let state = InterpreterState::default().with_function_store(env);
+        let mut interpreter = Interpreter::new(state);
+        let breakpoint = Breakpoint::Inst {
+            function: "%fact".to_string(),
+            inst: imul,
+        };
+        interpreter.add_breakpoint(breakpoint.clone());
+
+        // The innermost multiplication of fact(5) is 2 * fact(1), then 3 * fact(2), and so on.
+        let mut result = interpreter.call_by_name("%fact", &[DataValue::I32(5)]);
+        for (n, fact_n_minus_1) in [(2, 1), (3, 2), (4, 6), (5, 24)] {
+            match result {
+                Err(InterpreterError::Break(BreakReason::Breakpoint(ref bp))) => {
+                    assert_eq!(bp, &breakpoint)
+                }
+                _ => panic!("expected to stop at the breakpoint, got {:?}", result),
+            }
+            let frame = interpreter.current_frame().unwrap();
+            assert_eq!(frame.inst(), imul);
+            assert_eq!(
+                frame.inst_args(),
+                vec![
+                    Some(DataValue::I32(n)),
+                    Some(DataValue::I32(fact_n_minus_1))
+                ]
+            );
+            // The values of the not yet executed `imul` are not visible.
+            let v5 = func.dfg.first_result(imul);
+            assert!(frame.value(v5).is_none());
+            // Each stop at the multiplication is one frame shallower than the previous one.
+            assert_eq!(frame.depth(), 5 - n as usize);
+            assert_eq!(interpreter.frames().count(), 6 - n as usize);
+            result = interpreter.resume();
+        }
+
+        assert_eq!(
+            result.unwrap(),
+            ControlFlow::Return(smallvec![DataValue::I32(120)])
+        );
+        assert!(matches!(
+            interpreter.resume(),
+            Err(InterpreterError::NotPaused)
+        ));
+    }
+
+    #[test]
+    fn control_hook_breaks_and_steps() {
+        let func = parse_functions(FACTORIAL)
+            .unwrap()
+            .into_iter()
+            .next()
+            .unwrap();
+        let call = find_inst(&func, Opcode::Call);
+        let env = FunctionStore::from(&func);
+        let state = InterpreterState::default().with_function_store(env);
+
+        // Step over the outermost call and break at the multiplication that follows it, so that
+        // none of the recursive frames are ever inspected.
+        let mut visited = vec![];
+        let hook = |frame: &FrameView| {
+            assert_eq!(frame.depth(), 0, "stepped into callee at {}", frame.inst());
+            let opcode = frame.function().dfg.insts[frame.inst()].opcode();
+            visited.push(opcode);
+            match opcode {
+                Opcode::Call => ControlAction::StepOver,
+                Opcode::Imul => ControlAction::Break("reached imul".to_string()),
+                _ => ControlAction::StepInto,
+            }
+        };
+        let mut interpreter = Interpreter::new(state).with_control(hook);
+        let result = interpreter.call_by_name("%fact", &[DataValue::I32(4)]);
+        match result {
+            Err(InterpreterError::Break(BreakReason::Requested(ref reason))) => {
+                assert_eq!(reason, "reached imul")
+            }
+            _ => panic!("expected the hook to stop execution, got {:?}", result),
+        }
+        let frame = interpreter.current_frame().unwrap();
+        assert_eq!(
+            frame.inst_args(),
+            vec![Some(DataValue::I32(4)), Some(DataValue::I32(6))]
+        );
+        let v4 = func.dfg.first_result(call);
+        assert_eq!(frame.value(v4), Some(&DataValue::I32(6)));
+        assert!(frame
+            .defined_values()
+            .any(|(v, d)| v == v4 && d == &DataValue::I32(6)));
+
+        // Resuming executes the multiplication; the hook is consulted once more at the `return`.
+        let result = interpreter.resume().unwrap();
+        assert_eq!(result, ControlFlow::Return(smallvec![DataValue::I32(24)]));
+        drop(interpreter);
+        assert_eq!(
+            visited,
+            vec![
+                Opcode::IcmpImm,
+                Opcode::Brif,
+                Opcode::IaddImm,
+                Opcode::Call,
+                Opcode::Imul,
+                Opcode::Return
+            ]
+        );
+    }
+
+    #[test]
+    fn srcloc_breakpoint_after_continue() {
+        let mut func = parse_functions(FACTORIAL)
+            .unwrap()
+            .into_iter()
+            .next()
+            .unwrap();
+        let imul = find_inst(&func, Opcode::Imul);
+        let srcloc = SourceLoc::new(42);
+        func.set_srcloc(imul, srcloc);
+        let env = FunctionStore::from(&func);
+        let state = InterpreterState::default().with_function_store(env);
+
+        // A hook that lets the program run freely is only consulted again after a breakpoint.
+        let mut consulted = 0;
+        let hook = |_: &FrameView| {
+            consulted += 1;
+            ControlAction::Continue
+        };
+        let mut interpreter = Interpreter::new(state).with_control(hook);
+        interpreter.add_breakpoint(Breakpoint::SourceLoc(srcloc));
+        let result = interpreter.call_by_name("%fact", &[DataValue::I32(2)]);
+        assert!(matches!(
+            result,
+            Err(InterpreterError::Break(BreakReason::Breakpoint(
+                Breakpoint::SourceLoc(_)
+            )))
+        ));
+        assert_eq!(interpreter.current_frame().unwrap().srcloc(), srcloc);
+        let result = interpreter.resume().unwrap();
+        assert_eq!(result, ControlFlow::Return(smallvec![DataValue::I32(2)]));
+        drop(interpreter);
+        assert_eq!(consulted, 2);
+    }
 }
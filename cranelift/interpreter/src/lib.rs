@@ -3,6 +3,7 @@
 //! This module is a project for interpreting Cranelift IR.
 
 pub mod address;
+pub mod control;
 pub mod environment;
 pub mod frame;
 pub mod instruction;
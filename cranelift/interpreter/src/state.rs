@@ -63,6 +63,7 @@ pub trait State<'a> {
         &mut self,
         address: Address,
         v: DataValue,
+        ty: Type,
         mem_flags: MemFlags,
     ) -> Result<(), MemoryError>;
 
@@ -333,6 +333,18 @@ where
                 unreachable!()
             }
         }
+        Opcode::PatchableJump => {
+            // Nothing patches the jump while it is being interpreted.
+            if let InstructionData::PatchableJump {
+                blocks: [block_initial, _],
+                ..
+            } = inst
+            {
+                continue_at(block_initial)?
+            } else {
+                unreachable!()
+            }
+        }
         Opcode::BrTable => {
             if let InstructionData::BranchTable { table, .. } = inst {
                 let jt_data = &state.get_current_function().stencil.dfg.jump_tables[table];
@@ -356,7 +368,7 @@ where
         Opcode::Trapnz => trap_when(arg(0).into_bool()?, CraneliftTrap::User(trap_code())),
         Opcode::ResumableTrapnz => trap_when(arg(0).into_bool()?, CraneliftTrap::Resumable),
         Opcode::Return => ControlFlow::Return(args()),
-        Opcode::Call | Opcode::ReturnCall => {
+        Opcode::Call | Opcode::PatchableCall | Opcode::ReturnCall => {
             let func_ref = if let InstructionData::Call { func_ref, .. } = inst {
                 func_ref
             } else {
@@ -384,7 +396,7 @@ where
             };
 
             let make_control_flow = match inst.opcode() {
-                Opcode::Call => ControlFlow::Call,
+                Opcode::Call | Opcode::PatchableCall => ControlFlow::Call,
                 Opcode::ReturnCall => ControlFlow::ReturnCall,
                 _ => unreachable!(),
             };
@@ -481,11 +493,11 @@ where
             }
         }
         Opcode::Store | Opcode::Istore8 | Opcode::Istore16 | Opcode::Istore32 => {
-            let kind = match inst.opcode() {
-                Opcode::Store => None,
-                Opcode::Istore8 => Some(ValueConversionKind::Truncate(types::I8)),
-                Opcode::Istore16 => Some(ValueConversionKind::Truncate(types::I16)),
-                Opcode::Istore32 => Some(ValueConversionKind::Truncate(types::I32)),
+            let (store_ty, kind) = match inst.opcode() {
+                Opcode::Store => (ctrl_ty, None),
+                Opcode::Istore8 => (types::I8, Some(ValueConversionKind::Truncate(types::I8))),
+                Opcode::Istore16 => (types::I16, Some(ValueConversionKind::Truncate(types::I16))),
+                Opcode::Istore32 => (types::I32, Some(ValueConversionKind::Truncate(types::I32))),
                 _ => unreachable!(),
             };
 
@@ -498,7 +510,7 @@ where
             };
             continue_or_memtrap(
                 Address::try_from(addr_value)
-                    .and_then(|addr| state.checked_store(addr, reduced, mem_flags)),
+                    .and_then(|addr| state.checked_store(addr, reduced, store_ty, mem_flags)),
             )
         }
         Opcode::StackLoad => {
@@ -520,7 +532,7 @@ where
             continue_or_memtrap({
                 state
                     .stack_address(AddressSize::_64, slot, offset)
-                    .and_then(|addr| state.checked_store(addr, arg, mem_flags))
+                    .and_then(|addr| state.checked_store(addr, arg, ctrl_ty, mem_flags))
             })
         }
         Opcode::StackAddr => {
@@ -671,6 +683,21 @@ where
             assign(vectorizelanes(&new_vec, ctrl_ty)?)
         }
         Opcode::Imul => binary(DataValueExt::mul, arg(0), arg(1))?,
+        Opcode::Umulhi | Opcode::Smulhi if ctrl_ty.lane_bits() == 128 => {
+            // There is no wider type to multiply in, so compute the upper half from 64-bit limbs.
+            let x = arg(0).into_int_unsigned()?;
+            let y = arg(1).into_int_unsigned()?;
+            let mut hi = umulhi_u128(x, y);
+            if inst.opcode() == Opcode::Smulhi {
+                if (x as i128) < 0 {
+                    hi = hi.wrapping_sub(y);
+                }
+                if (y as i128) < 0 {
+                    hi = hi.wrapping_sub(x);
+                }
+            }
+            assign(DataValue::I128(hi as i128))
+        }
         Opcode::Umulhi | Opcode::Smulhi => {
             let double_length = match ctrl_ty.lane_bits() {
                 8 => types::I16,
@@ -758,13 +785,25 @@ where
 
             assign_multiple(&[sum, DataValueExt::bool(carry, false, types::I8)?])
         }
-        Opcode::UaddOverflowTrap => {
-            let sum = DataValueExt::add(arg(0), arg(1))?;
-            let carry = sum < arg(0) && sum < arg(1);
-            if carry {
+        Opcode::UaddOverflowTrap
+        | Opcode::SaddOverflowTrap
+        | Opcode::UsubOverflowTrap
+        | Opcode::SsubOverflowTrap
+        | Opcode::UmulOverflowTrap
+        | Opcode::SmulOverflowTrap => {
+            let (result, overflow) = match inst.opcode() {
+                Opcode::UaddOverflowTrap => arg(0).uadd_overflow(arg(1))?,
+                Opcode::SaddOverflowTrap => arg(0).sadd_overflow(arg(1))?,
+                Opcode::UsubOverflowTrap => arg(0).usub_overflow(arg(1))?,
+                Opcode::SsubOverflowTrap => arg(0).ssub_overflow(arg(1))?,
+                Opcode::UmulOverflowTrap => arg(0).umul_overflow(arg(1))?,
+                Opcode::SmulOverflowTrap => arg(0).smul_overflow(arg(1))?,
+                _ => unreachable!(),
+            };
+            if overflow {
                 ControlFlow::Trap(CraneliftTrap::User(trap_code()))
             } else {
-                assign(sum)
+                assign(result)
             }
         }
         Opcode::IsubBin => choose(
@@ -1223,7 +1262,7 @@ where
                 AtomicRmwOp::Umin => DataValueExt::umin(val, prev_val),
             }?;
             let stored = Address::try_from(addr)
-                .and_then(|addr| state.checked_store(addr, replace, mem_flags));
+                .and_then(|addr| state.checked_store(addr, replace, ctrl_ty, mem_flags));
             assign_or_memtrap(stored.map(|_| prev_val_to_assign))
         }
         Opcode::AtomicCas => {
@@ -1239,7 +1278,7 @@ where
             let val_to_assign = if loaded_val == expected_val {
                 let val_to_store = arg(2);
                 Address::try_from(addr)
-                    .and_then(|addr| state.checked_store(addr, val_to_store, mem_flags))
+                    .and_then(|addr| state.checked_store(addr, val_to_store, ctrl_ty, mem_flags))
                     .map(|_| loaded_val)
             } else {
                 Ok(loaded_val)
@@ -1262,7 +1301,8 @@ where
             let mem_flags = inst.memflags().expect("instruction to have memory flags");
             // We are doing a regular store here, this isn't actually thread safe.
             continue_or_memtrap(
-                Address::try_from(addr).and_then(|addr| state.checked_store(addr, val, mem_flags)),
+                Address::try_from(addr)
+                    .and_then(|addr| state.checked_store(addr, val, ctrl_ty, mem_flags)),
             )
         }
         Opcode::Fence => {
@@ -1308,6 +1348,9 @@ where
         Opcode::GetStackPointer => unimplemented!("GetStackPointer"),
         Opcode::GetReturnAddress => unimplemented!("GetReturnAddress"),
         Opcode::X86Pshufb => unimplemented!("X86Pshufb"),
+        Opcode::Ext0 | Opcode::Ext1 | Opcode::Ext2 | Opcode::Ext3 => {
+            unimplemented!("frontend extension instruction {}", inst.opcode())
+        }
         Opcode::X86Blendv => unimplemented!("X86Blendv"),
         Opcode::X86Pmulhrsw => unimplemented!("X86Pmulhrsw"),
         Opcode::X86Pmaddubsw => unimplemented!("X86Pmaddubsw"),
@@ -1363,6 +1406,19 @@ pub enum CraneliftTrap {
     Resumable,
 }
 
+/// The upper half of the full 256-bit product of `x` and `y`.
+fn umulhi_u128(x: u128, y: u128) -> u128 {
+    let (x_lo, x_hi) = (x as u64 as u128, x >> 64);
+    let (y_lo, y_hi) = (y as u64 as u128, y >> 64);
+    let lo_lo = x_lo * y_lo;
+    let lo_hi = x_lo * y_hi;
+    let hi_lo = x_hi * y_lo;
+    let hi_hi = x_hi * y_hi;
+    // The carry into the upper half out of the middle limb.
+    let mid = (lo_lo >> 64) + (lo_hi as u64 as u128) + (hi_lo as u64 as u128);
+    hi_hi + (lo_hi >> 64) + (hi_lo >> 64) + (mid >> 64)
+}
+
 /// Compare two values using the given integer condition `code`.
 fn icmp(
     ctrl_ty: types::Type,
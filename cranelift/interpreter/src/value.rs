@@ -251,6 +251,9 @@ macro_rules! bitop {
                 }
                 DataValue::V128(a2)
             }
+            (DataValue::V64(a), DataValue::V64(b)) => {
+                DataValue::V64((u64::from_ne_bytes(a) $op u64::from_ne_bytes(b)).to_ne_bytes())
+            }
             _ => unimplemented!(),
         })
     };
@@ -591,16 +594,16 @@ impl DataValueExt for DataValue {
     fn srem(self, other: Self) -> ValueResult<Self> {
         let denominator = other.clone().into_int_signed()?;
 
-        // Check if we are dividing INT_MIN / -1. This causes an integer overflow trap.
-        let min = DataValueExt::int(1i128 << (self.ty().bits() - 1), self.ty())?;
-        if self == min && denominator == -1 {
-            return Err(ValueError::IntegerOverflow);
-        }
-
         if denominator == 0 {
             return Err(ValueError::IntegerDivisionByZero);
         }
 
+        // The remainder of INT_MIN / -1 is 0; unlike `sdiv`, this doesn't overflow.
+        let min = DataValueExt::int(1i128 << (self.ty().bits() - 1), self.ty())?;
+        if self == min && denominator == -1 {
+            return DataValueExt::int(0, self.ty());
+        }
+
         binary_match!(%(&self, &other); [I8, I16, I32, I64, I128])
     }
 
@@ -781,7 +784,7 @@ impl DataValueExt for DataValue {
                 }
                 DataValue::V128(a2)
             }
-            _ => unimplemented!(),
+            DataValue::V64(a) => DataValue::V64((!u64::from_ne_bytes(a)).to_ne_bytes()),
         })
     }
 
@@ -0,0 +1,126 @@
+//! Measure compiling and running a recursive Ackermann function over 64-bit integers, whose
+//! nested calls and branches on both arguments make deeper control flow than the factorial
+//! functions, and compare it with the same function in Rust. It's run on `ackermann(2, n)`,
+//! which is `2 * n + 3` and takes a number of calls quadratic in `n`, and its results are checked
+//! with the JIT before they are timed. The function is built directly in the function, and
+//! compiling it is measured phase by phase as for the factorial functions.
+
+use cranelift_codegen::ir::{types, Function};
+use cranelift_jit::{JITModule, OwnedJitFn};
+use cranelift_module::{FuncId, Module};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use ir_builders::{
+    append_block, declare, emit_brif, emit_call, emit_iadd_imm, emit_iconst, emit_return,
+};
+
+// Each bench only uses some of the helpers.
+#[path = "support/ir_builders.rs"]
+#[allow(dead_code)]
+mod ir_builders;
+
+/// The inputs `n` of the run benchmarks of `ackermann(2, n)`.
+///
+/// The recursion is about `2 * n` calls deep, so it stays well within the stack of the main
+/// thread which runs the benchmarks.
+const INPUTS: [i64; 4] = [1, 10, 100, 1_000];
+
+/// The first argument of the run benchmarks.
+const M: i64 = 2;
+
+/// A function computing `ackermann(m, n)`, with its name.
+type Variant<'a> = (&'a str, &'a dyn Fn(i64, i64) -> i64);
+
+/// `ackermann(m, n)`, for `m` and `n` which aren't negative.
+fn ackermann(m: i64, n: i64) -> i64 {
+    if m == 0 {
+        n + 1
+    } else if n == 0 {
+        ackermann(m - 1, 1)
+    } else {
+        ackermann(m - 1, ackermann(m, n - 1))
+    }
+}
+
+/// Declare `fn ackermann(m: i64, n: i64) -> i64` in `module`.
+fn declare_ackermann(module: &mut JITModule) -> FuncId {
+    let ty = types::I64;
+    declare(module, "ackermann", &[ty, ty], &[ty])
+}
+
+/// [`ackermann`], directly in the function, with a block for each of its cases, which return
+/// on their own.
+fn recursive_dfg(func: &mut Function, id: FuncId, module: &mut JITModule) {
+    let ty = types::I64;
+    let callee = module.declare_func_in_func(id, func);
+    let entry = append_block(func);
+    let m_zero = append_block(func);
+    let m_positive = append_block(func);
+    let n_zero = append_block(func);
+    let recurse = append_block(func);
+    let m = func.dfg.append_block_param(entry, ty);
+    let n = func.dfg.append_block_param(entry, ty);
+
+    emit_brif(func, entry, m, (m_positive, &[]), (m_zero, &[]));
+
+    let n_plus_one = emit_iadd_imm(func, m_zero, n, 1);
+    emit_return(func, m_zero, n_plus_one);
+
+    let m_minus_one = emit_iadd_imm(func, m_positive, m, -1);
+    emit_brif(func, m_positive, n, (recurse, &[]), (n_zero, &[]));
+
+    let one = emit_iconst(func, n_zero, ty, 1);
+    let result = emit_call(func, n_zero, callee, &[m_minus_one, one]);
+    emit_return(func, n_zero, result);
+
+    let n_minus_one = emit_iadd_imm(func, recurse, n, -1);
+    let inner = emit_call(func, recurse, callee, &[m, n_minus_one]);
+    let result = emit_call(func, recurse, callee, &[m_minus_one, inner]);
+    emit_return(func, recurse, result);
+}
+
+fn ackermann_benchmarks(c: &mut Criterion) {
+    let verify_first = ir_builders::verify_before_define();
+    let name = "recursive dfg i64";
+    let (func, code): (_, OwnedJitFn<extern "C" fn(i64, i64) -> i64>) =
+        ir_builders::compile(name, &declare_ackermann, recursive_dfg);
+    for m in 0..=M {
+        for n in 0..=20 {
+            assert_eq!(code.call(m, n), ackermann(m, n), "{name}({m}, {n})");
+        }
+    }
+    for n in INPUTS {
+        assert_eq!(code.call(M, n), 2 * n + 3, "{name}({M}, {n})");
+    }
+
+    let mut group = c.benchmark_group("compile ackermann");
+    ir_builders::compile_benchmarks(
+        &mut group,
+        name,
+        &func,
+        &declare_ackermann,
+        recursive_dfg,
+        verify_first,
+    );
+    group.finish();
+
+    let mut group = c.benchmark_group("verify ackermann");
+    ir_builders::verify_benchmark(&mut group, name, &func);
+    group.finish();
+
+    let mut group = c.benchmark_group("run ackermann");
+    let jit = |m, n| code.call(m, n);
+    let variants: [Variant; 2] = [("rust recursive i64", &ackermann), (name, &jit)];
+    for n in INPUTS {
+        group.throughput(Throughput::Elements(n as u64));
+        for (name, ackermann) in variants {
+            assert_eq!(ackermann(M, n), 2 * n + 3, "{name}({M}, {n})");
+            group.bench_with_input(BenchmarkId::new(name, n), &n, |b, &n| {
+                b.iter(|| ackermann(black_box(M), black_box(n)))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, ackermann_benchmarks);
+criterion_main!(benches);
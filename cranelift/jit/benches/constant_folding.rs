@@ -0,0 +1,125 @@
+//! Measure how much folding the literal arithmetic of generated code before it's lowered, with
+//! `clifp::optimize`, saves on compiling it, on clifp functions of 16, 64 and 256 steps made
+//! mostly of literal arithmetic. Each step updates an accumulator with arithmetic of literals,
+//! inside an `if` whose condition is a literal, and binds an unused value, as a generator which
+//! doesn't simplify its output would. Each function is run on a few inputs, with and without
+//! the pass, and checked against the same steps in Rust before it's timed.
+//!
+//! Compiling is measured phase by phase as for the factorial functions, where building the IR
+//! includes parsing, optimizing if the function is folded, and lowering the clifp source. The
+//! functions are compiled as they're generated in one group and folded in another, where the
+//! number of steps is the parameter of each entry, so that criterion shows the phases of both
+//! as curves over the sizes. The number of instructions of each function is printed before the
+//! benchmarks.
+
+use cranelift_clifp as clifp;
+use cranelift_codegen::ir::{types, Function};
+use cranelift_jit::{JITModule, OwnedJitFn};
+use cranelift_module::FuncId;
+use criterion::{criterion_group, criterion_main, Criterion};
+use ir_builders::{declare, Build};
+
+// Each bench only uses some of the helpers.
+#[path = "support/ir_builders.rs"]
+#[allow(dead_code)]
+mod ir_builders;
+
+/// The inputs the functions are checked on.
+const INPUTS: [i64; 4] = [-7, 0, 1, 1 << 40];
+
+/// `steps` times, multiplying the accumulator, starting at `x`, by `3 + 4k` and adding
+/// `(7k - 2) ^ (4k | 1)`, wrapping, at the step `k`.
+fn literals(steps: usize, x: i64) -> i64 {
+    (0..steps as i64).fold(x, |acc, k| {
+        acc.wrapping_mul(3 + 4 * k)
+            .wrapping_add((k * 7 - 2) ^ ((k * 4) | 1))
+    })
+}
+
+/// The clifp source of [`literals`] for `steps`, whose steps are `let` bindings, with the
+/// literal arithmetic of each step written out.
+fn literals_source(steps: usize) -> String {
+    let mut src = String::from("(func literals ((x i64)) (i64)\n  (let ((v0 x)\n");
+    for k in 0..steps {
+        src.push_str(&format!(
+            "        (v{} (if 1 (iadd (imul v{k} (+ 3 (* 4 {k}))) \
+                               (bxor (- (* {k} 7) 2) (bor (* {k} 4) 1))) 0))\n        \
+                     (unused{k} (* v{k} (+ {k} 1)))\n",
+            k + 1
+        ));
+    }
+    src.push_str(&format!("       )\n    v{steps}))"));
+    src
+}
+
+/// Declare `fn literals(x: i64) -> i64` in `module`.
+fn declare_literals(module: &mut JITModule) -> FuncId {
+    declare(module, "literals", &[types::I64], &[types::I64])
+}
+
+/// [`literals`] for `STEPS`, lowered from the clifp source of [`literals_source`], once it's
+/// optimized if `FOLD`. The parameters are those of the type, since a [`Build`] is a function
+/// pointer.
+fn literals_clifp<const STEPS: usize, const FOLD: bool>(
+    func: &mut Function,
+    _: FuncId,
+    module: &mut JITModule,
+) {
+    let (mut program, _) = clifp::frontend(&literals_source(STEPS)).unwrap();
+    if FOLD {
+        clifp::optimize(&mut program);
+    }
+    // The clifp function isn't declared in the module, so it's named after itself instead of
+    // the id of the declaration.
+    let name = func.name.clone();
+    *func = clifp::compile::lower(&program.functions[0], &program.constants, module).unwrap();
+    func.name = name;
+}
+
+fn constant_folding_benchmarks(c: &mut Criterion) {
+    let verify_first = ir_builders::verify_before_define();
+    let builds: [(usize, Build, Build); 3] = [
+        (16, literals_clifp::<16, false>, literals_clifp::<16, true>),
+        (64, literals_clifp::<64, false>, literals_clifp::<64, true>),
+        (
+            256,
+            literals_clifp::<256, false>,
+            literals_clifp::<256, true>,
+        ),
+    ];
+    for (folded, group_name) in [
+        (false, "compile literal arithmetic"),
+        (true, "compile folded literal arithmetic"),
+    ] {
+        let funcs: Vec<_> = builds
+            .iter()
+            .map(|&(steps, build, folded_build)| {
+                let build = if folded { folded_build } else { build };
+                let name = format!("{group_name} {steps}");
+                let (func, code): (_, OwnedJitFn<extern "C" fn(i64) -> i64>) =
+                    ir_builders::compile(&name, &declare_literals, build);
+                eprintln!("{name}: {} instructions", func.dfg.num_insts());
+                for x in INPUTS {
+                    assert_eq!(code.call(x), literals(steps, x), "{name}({x})");
+                }
+                (steps, build, func)
+            })
+            .collect();
+
+        let mut group = c.benchmark_group(group_name);
+        for (steps, build, func) in &funcs {
+            ir_builders::compile_benchmarks(
+                &mut group,
+                &steps.to_string(),
+                func,
+                &declare_literals,
+                *build,
+                verify_first,
+            );
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, constant_folding_benchmarks);
+criterion_main!(benches);
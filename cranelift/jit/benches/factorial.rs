@@ -0,0 +1,1180 @@
+//! Measure compiling and running a recursive and an iterative factorial function over 32-, 64- and
+//! 128-bit integers, whose results are checked with the JIT before they are timed, so that the two
+//! shapes of control flow and the widths of the multiplications can be compared with each other and
+//! with the same functions in Rust. Each function is built by hand, directly in the function and
+//! with a cursor, and with a `FunctionBuilder`, the 32-bit ones also by parsing their CLIF text,
+//! and the iterative 32- and 64-bit ones also by lowering clifp, the language of the `clifp-aot`
+//! example of `cranelift-tools`, once branching on each step and once taking two steps per
+//! iteration with a branchless `select`, to compare the two loops on the same inputs. On x86-64, a
+//! tail-recursive 32- and 64-bit clifp function, which passes an accumulator to itself with a
+//! `return_call`, compares the cost of the calls with that of the recursive functions, in constant
+//! stack space at any input. Compiling is measured phase by phase: building the IR, which for the
+//! text and clifp includes parsing it, generating code with `define_function`, and making it
+//! executable with `finalize_definitions`. A separate entry times parsing the text alone, and
+//! verifying each function with `verify_function` is timed on its own, the recursive functions
+//! apart from the others, since embedders may enable the verifier in production. Setting
+//! `CRANELIFT_BENCH_VERIFY` also verifies each function before it's defined, as builds with debug
+//! assertions always do. The size of the code of each function is printed as it's compiled, and
+//! again once the benchmarks ran, in a table of the shapes of the functions by type, to spot a
+//! change in size in the log of a run. Code of more than [`MAX_CODE_SIZE`] bytes, on any target,
+//! fails the bench.
+//!
+//! The recursive and iterative functions built with a `FunctionBuilder` are also compiled for
+//! aarch64, riscv64, s390x and x86-64 with `Context::compile`, without a module, to compare the
+//! backends. Only the backends built into `cranelift-codegen` are measured, so the others are
+//! skipped unless the bench is run with `--features cranelift-codegen/all-arch`.
+//!
+//! Modules of embedders define many functions, so a chain of up to a thousand iterative 64-bit
+//! factorial functions, each calling the previous one, is defined in one module, either all at
+//! once and then finalized, or finalizing each function as soon as it's defined.
+//!
+//! The recursive and iterative 64-bit functions built with a `FunctionBuilder` are also compiled
+//! and run in modules of each [`JitConfig`], with ids such as `run recursive factorial/hotswap`.
+//! Hotswapping and strict position independence call the recursive function through the GOT
+//! instead of directly, and code which isn't position-independent has no GOT at all, so the cost
+//! of each configuration shows on the 30 calls the recursive function makes on an input of 30.
+//!
+//! The recursive and iterative 32-bit functions built with a `FunctionBuilder` are also run by
+//! `cranelift-interpreter`, next to the code the JIT compiled from the same IR, to quote the
+//! overhead of interpreting it. Each interpreted run includes setting up the state of the
+//! interpreter and extracting the result.
+
+use cranelift_clifp as clifp;
+use cranelift_codegen::binemit::Reloc;
+use cranelift_codegen::cursor::{Cursor, FuncCursor};
+use cranelift_codegen::data_value::DataValue;
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{types, FuncRef, Function, InstBuilder, Opcode, Type, UserFuncName};
+use cranelift_codegen::isa::{self, CallConv, LookupError, OwnedTargetIsa};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_codegen::Context;
+use cranelift_control::ControlPlane;
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_interpreter::environment::{FuncIndex, FunctionStore};
+use cranelift_interpreter::interpreter::{Interpreter, InterpreterState};
+use cranelift_interpreter::step::ControlFlow;
+use cranelift_jit::{JITModule, JitValue, OwnedJitFn};
+use cranelift_module::{FuncId, Linkage, Module};
+use cranelift_reader::parse_functions;
+use criterion::measurement::WallTime;
+use criterion::{
+    black_box, criterion_group, criterion_main, BatchSize, BenchmarkGroup, BenchmarkId, Criterion,
+    Throughput,
+};
+use ir_builders::{
+    append_block, declare, emit_binary, emit_brif, emit_call, emit_iadd_imm, emit_icmp_imm,
+    emit_iconst, emit_jump, emit_return, iconst, Build, JitConfig, ScratchModule,
+};
+use std::cell::RefCell;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+
+// Each bench only uses some of the helpers.
+#[path = "support/ir_builders.rs"]
+#[allow(dead_code)]
+mod ir_builders;
+
+/// The inputs of the run benchmarks, whose factorials take as many multiplications.
+const INPUTS: [i32; 4] = [5, 30, 1_000, 100_000];
+
+/// The largest input of the recursive functions, which recurse once per multiplication.
+///
+/// A frame of the recursive functions takes a few dozen bytes of stack at most, even without
+/// optimizations, so this depth stays well within the 8 MiB stack of the main thread which runs
+/// the benchmarks. Larger inputs are only run by the iterative functions.
+const MAX_RECURSION_DEPTH: i32 = 10_000;
+
+/// An input whose recursion would overflow the stack of the main thread many times over, which
+/// the tail-recursive functions are checked on before they are timed.
+#[cfg(target_arch = "x86_64")]
+const TAIL_CALL_DEPTH: i32 = 10_000_000;
+
+/// The most bytes of code, including its constants, which any of the functions may compile to on
+/// any target. The largest take a few hundred bytes, so only a pathological blowup of the code
+/// fails the bench.
+const MAX_CODE_SIZE: usize = 4 * 1024;
+
+/// The types of the columns of the table of [`CodeSizes`].
+const TYPES: [Type; 3] = [types::I32, types::I64, types::I128];
+
+/// An integer type of the factorial functions, with the same functions in Rust.
+trait Int: JitValue + Into<DataValue> + PartialEq + fmt::Debug + 'static {
+    /// The Cranelift type of the integers.
+    const TYPE: Type;
+    /// The factorial of 30, wrapped to the width of the type.
+    const FACTORIAL_30: Self;
+
+    fn from_i32(n: i32) -> Self;
+
+    /// The integer of `value`, a value of [`Int::TYPE`] returned by the interpreter.
+    fn from_data_value(value: DataValue) -> Self;
+
+    fn recursive_factorial(n: Self) -> Self;
+
+    fn iterative_factorial(n: Self) -> Self;
+}
+
+macro_rules! impl_int {
+    ($($int:ident: $ty:ident, $factorial_30:literal;)*) => {$(
+        impl Int for $int {
+            const TYPE: Type = types::$ty;
+            const FACTORIAL_30: Self = $factorial_30;
+
+            fn from_i32(n: i32) -> Self {
+                n.into()
+            }
+
+            fn from_data_value(value: DataValue) -> Self {
+                value.try_into().unwrap()
+            }
+
+            fn recursive_factorial(n: Self) -> Self {
+                if n <= 1 {
+                    1
+                } else {
+                    n.wrapping_mul(Self::recursive_factorial(n - 1))
+                }
+            }
+
+            fn iterative_factorial(mut n: Self) -> Self {
+                let mut acc: Self = 1;
+                while n > 1 {
+                    acc = acc.wrapping_mul(n);
+                    n -= 1;
+                }
+                acc
+            }
+        }
+    )*};
+}
+
+impl_int! {
+    i32: I32, 1_409_286_144;
+    i64: I64, -8_764_578_968_847_253_504;
+    i128: I128, 265_252_859_812_191_058_636_308_480_000_000;
+}
+
+/// Define the functions over the given type which a factorial function calls, in a module where
+/// it's declared.
+type Prepare = fn(&mut JITModule, Type);
+
+/// Declare the factorial function `name` over `T`, `fn(n: T) -> T`, in `module`, and `prepare`
+/// it.
+fn declare_factorial<T: Int>(module: &mut JITModule, name: &str, prepare: Prepare) -> FuncId {
+    let id = declare(module, name, &[T::TYPE], &[T::TYPE]);
+    prepare(module, T::TYPE);
+    id
+}
+
+// Each function is built in three styles: by hand, creating the blocks, their parameters and
+// the instructions either directly in the function with `Function::emit` or with a cursor on the
+// function, or with a `FunctionBuilder`, which is how most embedders build IR and which computes
+// the SSA values of `Variable`s. Either way they compute in the integer type of their signature.
+
+/// The integer type of the parameter and of the result of `func`.
+fn int_type(func: &Function) -> Type {
+    func.signature.returns[0].value_type
+}
+
+/// `if n <= 1 { 1 } else { n * factorial(n - 1) }`, directly in the function.
+fn recursive_dfg(func: &mut Function, id: FuncId, module: &mut JITModule) {
+    let ty = int_type(func);
+    let callee = module.declare_func_in_func(id, func);
+    let entry = append_block(func);
+    let recurse = append_block(func);
+    let done = append_block(func);
+    let n = func.dfg.append_block_param(entry, ty);
+    let result = func.dfg.append_block_param(done, ty);
+
+    let one = emit_iconst(func, entry, ty, 1);
+    let base = emit_icmp_imm(func, entry, IntCC::SignedLessThanOrEqual, n, 1);
+    emit_brif(func, entry, base, (done, &[one]), (recurse, &[]));
+
+    let n_minus_one = emit_iadd_imm(func, recurse, n, -1);
+    let rest = emit_call(func, recurse, callee, &[n_minus_one]);
+    let product = emit_binary(func, recurse, Opcode::Imul, n, rest);
+    emit_jump(func, recurse, done, &[product]);
+
+    emit_return(func, done, result);
+}
+
+/// `if n <= 1 { 1 } else { n * factorial(n - 1) }`, with a cursor.
+fn recursive_cursor(func: &mut Function, id: FuncId, module: &mut JITModule) {
+    let ty = int_type(func);
+    let callee = module.declare_func_in_func(id, func);
+    let mut pos = FuncCursor::new(func);
+    let entry = pos.func.dfg.make_block();
+    let recurse = pos.func.dfg.make_block();
+    let done = pos.func.dfg.make_block();
+    let n = pos.func.dfg.append_block_param(entry, ty);
+    let result = pos.func.dfg.append_block_param(done, ty);
+
+    pos.insert_block(entry);
+    let one = iconst(&mut pos, ty, 1);
+    let base = pos.ins().icmp_imm(IntCC::SignedLessThanOrEqual, n, 1);
+    pos.ins().brif(base, done, &[one], recurse, &[]);
+
+    pos.insert_block(recurse);
+    let n_minus_one = pos.ins().iadd_imm(n, -1);
+    let call = pos.ins().call(callee, &[n_minus_one]);
+    let rest = pos.func.dfg.first_result(call);
+    let product = pos.ins().imul(n, rest);
+    pos.ins().jump(done, &[product]);
+
+    pos.insert_block(done);
+    pos.ins().return_(&[result]);
+}
+
+/// `if n <= 1 { 1 } else { n * factorial(n - 1) }`, with a `FunctionBuilder`.
+fn recursive_frontend(func: &mut Function, id: FuncId, module: &mut JITModule) {
+    let ty = int_type(func);
+    let callee = module.declare_func_in_func(id, func);
+    let mut func_ctx = FunctionBuilderContext::new();
+    let mut bcx = FunctionBuilder::new(func, &mut func_ctx);
+    let entry = bcx.create_block();
+    let recurse = bcx.create_block();
+    let done = bcx.create_block();
+    bcx.append_block_params_for_function_params(entry);
+    bcx.append_block_param(done, ty);
+
+    bcx.switch_to_block(entry);
+    let n = bcx.block_params(entry)[0];
+    let one = iconst(&mut bcx.cursor(), ty, 1);
+    let base = bcx.ins().icmp_imm(IntCC::SignedLessThanOrEqual, n, 1);
+    bcx.ins().brif(base, done, &[one], recurse, &[]);
+
+    bcx.switch_to_block(recurse);
+    let n_minus_one = bcx.ins().iadd_imm(n, -1);
+    let call = bcx.ins().call(callee, &[n_minus_one]);
+    let rest = bcx.inst_results(call)[0];
+    let result = bcx.ins().imul(n, rest);
+    bcx.ins().jump(done, &[result]);
+
+    bcx.switch_to_block(done);
+    let result = bcx.block_params(done)[0];
+    bcx.ins().return_(&[result]);
+    bcx.seal_all_blocks();
+    bcx.finalize();
+}
+
+/// `let mut acc = 1; while n > 1 { acc *= n; n -= 1; } acc`, directly in the function, passing
+/// the counter and the accumulator around the loop as block parameters.
+fn iterative_dfg(func: &mut Function, _: FuncId, _: &mut JITModule) {
+    let ty = int_type(func);
+    let entry = append_block(func);
+    let header = append_block(func);
+    let body = append_block(func);
+    let done = append_block(func);
+    let n = func.dfg.append_block_param(entry, ty);
+    let header_n = func.dfg.append_block_param(header, ty);
+    let header_acc = func.dfg.append_block_param(header, ty);
+    let result = func.dfg.append_block_param(done, ty);
+
+    let one = emit_iconst(func, entry, ty, 1);
+    emit_jump(func, entry, header, &[n, one]);
+
+    let more = emit_icmp_imm(func, header, IntCC::SignedGreaterThan, header_n, 1);
+    emit_brif(func, header, more, (body, &[]), (done, &[header_acc]));
+
+    let acc = emit_binary(func, body, Opcode::Imul, header_acc, header_n);
+    let n = emit_iadd_imm(func, body, header_n, -1);
+    emit_jump(func, body, header, &[n, acc]);
+
+    emit_return(func, done, result);
+}
+
+/// `let mut acc = 1; while n > 1 { acc *= n; n -= 1; } acc`, with a cursor, passing the counter
+/// and the accumulator around the loop as block parameters.
+fn iterative_cursor(func: &mut Function, _: FuncId, _: &mut JITModule) {
+    let ty = int_type(func);
+    let mut pos = FuncCursor::new(func);
+    let entry = pos.func.dfg.make_block();
+    let header = pos.func.dfg.make_block();
+    let body = pos.func.dfg.make_block();
+    let done = pos.func.dfg.make_block();
+    let n = pos.func.dfg.append_block_param(entry, ty);
+    let header_n = pos.func.dfg.append_block_param(header, ty);
+    let header_acc = pos.func.dfg.append_block_param(header, ty);
+    let result = pos.func.dfg.append_block_param(done, ty);
+
+    pos.insert_block(entry);
+    let one = iconst(&mut pos, ty, 1);
+    pos.ins().jump(header, &[n, one]);
+
+    pos.insert_block(header);
+    let more = pos.ins().icmp_imm(IntCC::SignedGreaterThan, header_n, 1);
+    pos.ins().brif(more, body, &[], done, &[header_acc]);
+
+    pos.insert_block(body);
+    let acc = pos.ins().imul(header_acc, header_n);
+    let n = pos.ins().iadd_imm(header_n, -1);
+    pos.ins().jump(header, &[n, acc]);
+
+    pos.insert_block(done);
+    pos.ins().return_(&[result]);
+}
+
+/// `let mut acc = 1; while n > 1 { acc *= n; n -= 1; } acc`, with a `FunctionBuilder`, the
+/// counter and the accumulator being variables.
+fn iterative_frontend(func: &mut Function, _: FuncId, _: &mut JITModule) {
+    let ty = int_type(func);
+    let mut func_ctx = FunctionBuilderContext::new();
+    let mut bcx = FunctionBuilder::new(func, &mut func_ctx);
+    let n = Variable::from_u32(0);
+    let acc = Variable::from_u32(1);
+    bcx.declare_var(n, ty);
+    bcx.declare_var(acc, ty);
+    let entry = bcx.create_block();
+    let header = bcx.create_block();
+    let body = bcx.create_block();
+    let done = bcx.create_block();
+    bcx.append_block_params_for_function_params(entry);
+
+    bcx.switch_to_block(entry);
+    let param = bcx.block_params(entry)[0];
+    bcx.def_var(n, param);
+    let one = iconst(&mut bcx.cursor(), ty, 1);
+    bcx.def_var(acc, one);
+    bcx.ins().jump(header, &[]);
+
+    bcx.switch_to_block(header);
+    let n_value = bcx.use_var(n);
+    let more = bcx.ins().icmp_imm(IntCC::SignedGreaterThan, n_value, 1);
+    bcx.ins().brif(more, body, &[], done, &[]);
+
+    bcx.switch_to_block(body);
+    let (n_value, acc_value) = (bcx.use_var(n), bcx.use_var(acc));
+    let product = bcx.ins().imul(acc_value, n_value);
+    bcx.def_var(acc, product);
+    let n_minus_one = bcx.ins().iadd_imm(n_value, -1);
+    bcx.def_var(n, n_minus_one);
+    bcx.ins().jump(header, &[]);
+
+    bcx.switch_to_block(done);
+    let result = bcx.use_var(acc);
+    bcx.ins().return_(&[result]);
+    bcx.seal_all_blocks();
+    bcx.finalize();
+}
+
+// The same functions, as `clif-util` and the filetests read them. They are written for a
+// function with id 0, calling itself through the external name `u0:0`, as the only function of
+// its module.
+
+/// `if n <= 1 { 1 } else { n * factorial(n - 1) }`, in CLIF text.
+const RECURSIVE_CLIF: &str = "
+function u0:0(i32) -> i32 system_v {
+    fn0 = colocated u0:0(i32) -> i32 system_v
+
+block0(v0: i32):
+    v1 = iconst.i32 1
+    v2 = icmp_imm sle v0, 1
+    brif v2, block2(v1), block1
+
+block1:
+    v3 = iadd_imm v0, -1
+    v4 = call fn0(v3)
+    v5 = imul v0, v4
+    jump block2(v5)
+
+block2(v6: i32):
+    return v6
+}
+";
+
+/// `let mut acc = 1; while n > 1 { acc *= n; n -= 1; } acc`, in CLIF text.
+const ITERATIVE_CLIF: &str = "
+function u0:0(i32) -> i32 system_v {
+block0(v0: i32):
+    v1 = iconst.i32 1
+    jump block1(v0, v1)
+
+block1(v2: i32, v3: i32):
+    v4 = icmp_imm sgt v2, 1
+    brif v4, block2, block3(v3)
+
+block2:
+    v5 = imul v3, v2
+    v6 = iadd_imm v2, -1
+    jump block1(v6, v5)
+
+block3(v7: i32):
+    return v7
+}
+";
+
+/// Parse the single function of `text` in place of `func`.
+///
+/// The text fixes the calling convention of the function and of its calls, which may not be the
+/// one of the host, so they are replaced by the convention of the declaration of the function.
+fn parse_clif(text: &str, func: &mut Function, id: FuncId) {
+    assert_eq!(
+        id.as_u32(),
+        0,
+        "the CLIF text is written for the function 0"
+    );
+    let mut parsed = parse_functions(text).unwrap().pop().unwrap();
+    set_call_conv(&mut parsed, func.signature.call_conv);
+    assert_eq!(parsed.signature, func.signature);
+    *func = parsed;
+}
+
+/// Give `func` and the functions it calls the calling convention `call_conv`.
+fn set_call_conv(func: &mut Function, call_conv: CallConv) {
+    func.signature.call_conv = call_conv;
+    for sig in func.dfg.signatures.values_mut() {
+        sig.call_conv = call_conv;
+    }
+}
+
+fn recursive_text(func: &mut Function, id: FuncId, _: &mut JITModule) {
+    parse_clif(RECURSIVE_CLIF, func, id);
+}
+
+fn iterative_text(func: &mut Function, id: FuncId, _: &mut JITModule) {
+    parse_clif(ITERATIVE_CLIF, func, id);
+}
+
+/// `let mut acc = 1; while n > 1 { acc *= n; n -= 1; } acc`, lowered from clifp, with a `loop`
+/// whose variables are the counter and the accumulator. clifp literals have at most 64 bits, so
+/// there is no 128-bit function.
+fn iterative_clifp(func: &mut Function, _: FuncId, module: &mut JITModule) {
+    let ty = int_type(func);
+    let src = format!(
+        "(func fact ((n {ty})) ({ty})
+           (loop ((i n) (acc {ty} 1))
+             (if (> i 1) (continue (- i 1) (* acc i)) acc)))"
+    );
+    let (program, _) = clifp::frontend(&src).unwrap();
+    // The clifp function isn't declared in the module, so it's named after itself instead of
+    // the id of the declaration.
+    let name = func.name.clone();
+    *func = clifp::compile::lower(&program.functions[0], &program.constants, module).unwrap();
+    func.name = name;
+}
+
+/// [`iterative_clifp`], taking two steps per iteration and choosing the second factor with a
+/// `select` instead of a branch: `i - 1` while it's above 1, and 1 at the end of an odd count.
+fn iterative_select_clifp(func: &mut Function, _: FuncId, module: &mut JITModule) {
+    let ty = int_type(func);
+    let src = format!(
+        "(func fact ((n {ty})) ({ty})
+           (loop ((i n) (acc {ty} 1))
+             (if (> i 1)
+               (continue (- i 2) (* (* acc i) (select (> i 2) (- i 1) 1)))
+               acc)))"
+    );
+    let (program, _) = clifp::frontend(&src).unwrap();
+    let name = func.name.clone();
+    *func = clifp::compile::lower(&program.functions[0], &program.constants, module).unwrap();
+    func.name = name;
+}
+
+/// The helper `go(n, acc)` of the tail-recursive factorial, which multiplies the accumulator as
+/// it counts down, calling itself with a `return_call`. Both ends of a tail call use the `tail`
+/// calling convention, which the host can't call, so the factorial calls `go` like any function.
+#[cfg(target_arch = "x86_64")]
+fn define_tail_recursive_go(module: &mut JITModule, ty: Type) {
+    let src = format!(
+        "(func go ((n {ty}) (acc {ty})) ({ty})
+           (if (> n 1) (tail_call go (- n 1) (* acc n)) acc))"
+    );
+    let (program, _) = clifp::frontend(&src).unwrap();
+    let go = &program.functions[0];
+    let sig = go
+        .signature(cranelift_codegen::isa::CallConv::Tail)
+        .unwrap();
+    let id = module.declare_function("go", Linkage::Local, &sig).unwrap();
+    let mut ctx =
+        Context::for_function(clifp::compile::lower(go, &program.constants, module).unwrap());
+    module.define_function(id, &mut ctx).unwrap();
+}
+
+/// `go(n, 1)`, lowered from clifp, where `go` is defined by [`define_tail_recursive_go`].
+#[cfg(target_arch = "x86_64")]
+fn tail_recursive_clifp(func: &mut Function, _: FuncId, module: &mut JITModule) {
+    let ty = int_type(func);
+    let src = format!("(func fact ((n {ty})) ({ty}) (call go n 1))");
+    let (program, _) = clifp::frontend(&src).unwrap();
+    let name = func.name.clone();
+    *func = clifp::compile::lower(&program.functions[0], &program.constants, module).unwrap();
+    func.name = name;
+}
+
+/// The tail-recursive factorial over `T`, checked at an input whose recursion wouldn't fit on
+/// the stack.
+#[cfg(target_arch = "x86_64")]
+fn tail_recursive_factorial<T: Int>() -> JitFactorial<T> {
+    let fact = JitFactorial::with_helpers(
+        "tail-recursive clifp",
+        false,
+        define_tail_recursive_go,
+        tail_recursive_clifp,
+    );
+    let n = T::from_i32(TAIL_CALL_DEPTH);
+    assert_eq!(
+        fact.call(n),
+        T::iterative_factorial(n),
+        "{}({n:?})",
+        fact.name
+    );
+    fact
+}
+
+/// A factorial function over `T`, compiled in a JIT module of its own, which its code owns.
+struct JitFactorial<T: Int> {
+    /// The shape of the function, followed by its type and the configuration of its module
+    /// unless it's the default one.
+    name: String,
+    shape: String,
+    config: JitConfig,
+    /// Whether the function calls itself, taking a frame of stack per multiplication.
+    recurses: bool,
+    prepare: Prepare,
+    build: Build,
+    /// The function before it was compiled.
+    func: Function,
+    code: OwnedJitFn<extern "C" fn(T) -> T>,
+    /// The size of the code in bytes, including its constants.
+    size: usize,
+}
+
+impl<T: Int> JitFactorial<T> {
+    /// Build, verify, compile and finalize the function `shape` over `T`, and check that it
+    /// computes the same as the Rust function of the same shape.
+    fn new(shape: &str, recurses: bool, build: Build) -> Self {
+        Self::with_helpers(shape, recurses, |_, _| {}, build)
+    }
+
+    /// [`JitFactorial::new`], for a function which calls the functions defined by `prepare`.
+    fn with_helpers(shape: &str, recurses: bool, prepare: Prepare, build: Build) -> Self {
+        Self::in_config(JitConfig::Default, shape, recurses, prepare, build)
+    }
+
+    /// [`JitFactorial::with_helpers`], in a module of the configuration `config`.
+    fn in_config(
+        config: JitConfig,
+        shape: &str,
+        recurses: bool,
+        prepare: Prepare,
+        build: Build,
+    ) -> Self {
+        let expected = if recurses {
+            T::recursive_factorial
+        } else {
+            T::iterative_factorial
+        };
+        let name = match config {
+            JitConfig::Default => format!("{shape} {}", T::TYPE),
+            _ => format!("{shape} {} {}", T::TYPE, config.name()),
+        };
+        let declare = |module: &mut JITModule| declare_factorial::<T>(module, &name, prepare);
+        let (func, code, size) = ir_builders::compile_in(config, &name, &declare, build);
+        assert!(
+            size <= MAX_CODE_SIZE,
+            "{name} compiled to {size} bytes of code, more than {MAX_CODE_SIZE}"
+        );
+        let compiled = Self {
+            name,
+            shape: shape.to_string(),
+            config,
+            recurses,
+            prepare,
+            build,
+            func,
+            code,
+            size,
+        };
+        for n in (-1..=30).map(T::from_i32) {
+            assert_eq!(compiled.call(n), expected(n), "{}({n:?})", compiled.name);
+        }
+        assert_eq!(compiled.call(T::from_i32(30)), T::FACTORIAL_30);
+        compiled
+    }
+
+    /// Declare the function in `module`, and define the functions it calls.
+    fn declare(&self, module: &mut JITModule) -> FuncId {
+        declare_factorial::<T>(module, &self.name, self.prepare)
+    }
+
+    fn call(&self, n: T) -> T {
+        self.code.call(n)
+    }
+}
+
+/// A factorial function run by `cranelift-interpreter` instead of the JIT.
+struct InterpretedFactorial<'a> {
+    /// "interpreted", followed by the name of the JIT-compiled function.
+    name: String,
+    recurses: bool,
+    /// The function, under its own name and under the names its calls refer to it by.
+    functions: FunctionStore<'a>,
+    index: FuncIndex,
+}
+
+impl<'a> InterpretedFactorial<'a> {
+    /// Interpret the IR `fact` was compiled from, and check that it computes the same as the
+    /// code of `fact`.
+    fn new<T: Int>(fact: &'a JitFactorial<T>) -> Self {
+        let func = &fact.func;
+        let name = func.name.to_string();
+        let mut functions = FunctionStore::default();
+        functions.add(name.clone(), func);
+        // The interpreter looks the callee of a call up in the store by the name of its
+        // `FuncRef`, so the function is also registered under the names of the functions it
+        // calls: factorial functions only call themselves, whatever their `FuncRef`s are named.
+        for (_, callee) in func.dfg.ext_funcs.iter() {
+            let callee = callee.name.display(Some(&func.params)).to_string();
+            if functions.index_of(&callee).is_none() {
+                functions.add(callee, func);
+            }
+        }
+        let interpreted = Self {
+            name: format!("interpreted {}", fact.name),
+            recurses: fact.recurses,
+            index: functions.index_of(&name).unwrap(),
+            functions,
+        };
+        for n in (-1..=30).map(T::from_i32) {
+            assert_eq!(
+                interpreted.call(n),
+                fact.call(n),
+                "{}({n:?})",
+                interpreted.name
+            );
+        }
+        assert_eq!(interpreted.call(T::from_i32(30)), T::FACTORIAL_30);
+        interpreted
+    }
+
+    /// Interpret the function on `n` in a fresh interpreter, panicking with the opcode of the
+    /// instruction the interpreter couldn't execute, if any.
+    fn call<T: Int>(&self, n: T) -> T {
+        let state = InterpreterState::default().with_function_store(self.functions.clone());
+        let mut interpreter = Interpreter::new(state);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            interpreter.call_by_index(self.index, &[n.into()])
+        }));
+        // The innermost frame stays at the instruction which failed.
+        let opcode = || {
+            let frame = interpreter.current_frame().unwrap();
+            frame.function().dfg.insts[frame.inst()].opcode()
+        };
+        match result {
+            Ok(Ok(ControlFlow::Return(results))) => T::from_data_value(results[0].clone()),
+            Ok(Ok(flow)) => panic!("{} ended with {flow:?}", self.name),
+            Ok(Err(err)) => panic!("{} failed to interpret `{}`: {err}", self.name, opcode()),
+            Err(_) => panic!("{} panicked interpreting `{}`", self.name, opcode()),
+        }
+    }
+}
+
+/// The sizes of the code of the JIT-compiled functions, in a row for each shape and configuration
+/// of module, with a column for each of [`TYPES`].
+#[derive(Default)]
+struct CodeSizes {
+    rows: Vec<(String, [Option<usize>; TYPES.len()])>,
+}
+
+impl CodeSizes {
+    /// Record the size of the code of each of `facts`.
+    fn record<T: Int>(&mut self, facts: &[JitFactorial<T>]) {
+        let column = TYPES.iter().position(|&ty| ty == T::TYPE).unwrap();
+        for fact in facts {
+            let row = match fact.config {
+                JitConfig::Default => fact.shape.clone(),
+                config => format!("{} {}", fact.shape, config.name()),
+            };
+            let sizes = match self.rows.iter().position(|(name, _)| *name == row) {
+                Some(index) => &mut self.rows[index].1,
+                None => {
+                    self.rows.push((row, [None; TYPES.len()]));
+                    &mut self.rows.last_mut().unwrap().1
+                }
+            };
+            sizes[column] = Some(fact.size);
+        }
+    }
+
+    /// Print the table of the sizes, in the order the rows were first recorded in, with a `-`
+    /// for the types a shape isn't built for.
+    fn print(&self) {
+        const HEADER: &str = "bytes of code";
+        let width = self
+            .rows
+            .iter()
+            .map(|(row, _)| row.len())
+            .fold(HEADER.len(), usize::max);
+        let mut table = format!("{HEADER:width$}");
+        for ty in TYPES {
+            table += &format!(" {:>6}", ty.to_string());
+        }
+        for (row, sizes) in &self.rows {
+            table += &format!("\n{row:width$}");
+            for size in sizes {
+                match size {
+                    Some(size) => table += &format!(" {size:>6}"),
+                    None => table += &format!(" {:>6}", "-"),
+                }
+            }
+        }
+        eprintln!("\n{table}");
+    }
+}
+
+/// The functions over `T` built in memory.
+fn built_factorials<T: Int>() -> Vec<JitFactorial<T>> {
+    vec![
+        JitFactorial::new("recursive dfg", true, recursive_dfg),
+        JitFactorial::new("recursive cursor", true, recursive_cursor),
+        JitFactorial::new("recursive frontend", true, recursive_frontend),
+        JitFactorial::new("iterative dfg", false, iterative_dfg),
+        JitFactorial::new("iterative cursor", false, iterative_cursor),
+        JitFactorial::new("iterative frontend", false, iterative_frontend),
+    ]
+}
+
+/// Time each phase of compiling `facts` separately, verifying them first if `verify_first`.
+fn compile_benchmarks<T: Int>(
+    group: &mut BenchmarkGroup<WallTime>,
+    facts: &[JitFactorial<T>],
+    verify_first: bool,
+) {
+    for fact in facts {
+        let declare = |module: &mut JITModule| fact.declare(module);
+        let (name, func) = (&fact.name, &fact.func);
+        ir_builders::compile_benchmarks(group, name, func, &declare, fact.build, verify_first);
+    }
+}
+
+/// Time verifying those of `facts` which recurse if `recursive`, and the others if not.
+fn verify_benchmarks<T: Int>(
+    group: &mut BenchmarkGroup<WallTime>,
+    facts: &[JitFactorial<T>],
+    recursive: bool,
+) {
+    for fact in facts.iter().filter(|fact| fact.recurses == recursive) {
+        ir_builders::verify_benchmark(group, &fact.name, &fact.func);
+    }
+}
+
+/// The architectures the functions are compiled for without a module, with their targets and the
+/// relocation of the call of the recursive functions to themselves on each.
+const CROSS_TARGETS: [(&str, &str, Reloc); 4] = [
+    ("aarch64", "aarch64-unknown-linux-gnu", Reloc::Arm64Call),
+    ("riscv64", "riscv64gc-unknown-linux-gnu", Reloc::RiscvCall),
+    ("s390x", "s390x-unknown-linux-gnu", Reloc::S390xPLTRel32Dbl),
+    ("x86_64", "x86_64-unknown-linux-gnu", Reloc::X86CallPCRel4),
+];
+
+/// An ISA the functions are compiled for without a module: its architecture, the ISA, and the
+/// relocation of the call of the recursive functions.
+type CrossTarget = (&'static str, OwnedTargetIsa, Reloc);
+
+/// The [`CROSS_TARGETS`] whose backends are built. The others are skipped with a line on stderr.
+fn cross_targets() -> Vec<CrossTarget> {
+    CROSS_TARGETS
+        .into_iter()
+        .filter_map(|(arch, triple, call_reloc)| Some((arch, cross_isa(triple)?, call_reloc)))
+        .collect()
+}
+
+/// The ISA of `triple`, with the ABI extensions of [`JitConfig::module`] for the 128-bit
+/// functions, or `None` if its backend isn't built.
+fn cross_isa(triple: &str) -> Option<OwnedTargetIsa> {
+    let mut isa_builder = match isa::lookup_by_name(triple) {
+        Ok(isa_builder) => isa_builder,
+        Err(LookupError::SupportDisabled) => {
+            eprintln!("skipping the {triple} compile benchmarks: its backend isn't built");
+            return None;
+        }
+        Err(err) => panic!("{triple}: {err}"),
+    };
+    if triple.starts_with("riscv64") {
+        // `imul` needs the M extension, which the `gc` targets have.
+        isa_builder.enable("has_m").unwrap();
+    }
+    let mut flag_builder = settings::builder();
+    flag_builder.enable("enable_llvm_abi_extensions").unwrap();
+    Some(
+        isa_builder
+            .finish(settings::Flags::new(flag_builder))
+            .unwrap(),
+    )
+}
+
+/// Time generating the code of the functions of `facts` built with a `FunctionBuilder` for each
+/// of `targets` with `Context::compile`, after checking that it has code and that only the
+/// recursive functions have a relocation, for their call.
+fn cross_compile_benchmarks<T: Int>(
+    group: &mut BenchmarkGroup<WallTime>,
+    targets: &[CrossTarget],
+    facts: &[JitFactorial<T>],
+) {
+    for &(arch, ref isa, call_reloc) in targets {
+        for fact in facts.iter().filter(|fact| fact.name.contains("frontend")) {
+            let mut func = fact.func.clone();
+            set_call_conv(&mut func, isa.default_call_conv());
+            ir_builders::verify(&fact.name, &func, &**isa);
+
+            let mut ctx = Context::for_function(func.clone());
+            let code = ctx.compile(&**isa, &mut ControlPlane::default()).unwrap();
+            assert!(!code.code_buffer().is_empty(), "{} on {arch}", fact.name);
+            let size = code.code_info().total_size as usize;
+            assert!(
+                size <= MAX_CODE_SIZE,
+                "{} compiled to {size} bytes of code on {arch}, more than {MAX_CODE_SIZE}",
+                fact.name
+            );
+            let relocs: Vec<_> = code
+                .buffer
+                .relocs()
+                .iter()
+                .map(|reloc| (reloc.kind, reloc.name.clone()))
+                .collect();
+            let expected: Vec<_> = func
+                .dfg
+                .ext_funcs
+                .values()
+                .map(|callee| (call_reloc, callee.name.clone()))
+                .collect();
+            assert_eq!(expected.len(), usize::from(fact.recurses));
+            assert_eq!(relocs, expected, "{} on {arch}", fact.name);
+
+            group.bench_function(BenchmarkId::new(arch, &fact.name), |b| {
+                b.iter_batched(
+                    || Context::for_function(func.clone()),
+                    |mut ctx| {
+                        ctx.compile(&**isa, &mut ControlPlane::default()).unwrap();
+                        ctx
+                    },
+                    BatchSize::SmallInput,
+                )
+            });
+        }
+    }
+}
+
+/// The numbers of functions of the chains of [`chain_benchmarks`].
+const CHAIN_LENGTHS: [usize; 4] = [1, 10, 100, 1_000];
+
+/// The input of the functions of a chain when they are checked.
+const CHAIN_INPUT: i64 = 5;
+
+/// Declare the function `link` of a chain in `module`.
+fn declare_chain_link(module: &mut JITModule, link: usize) -> FuncId {
+    let ty = types::I64;
+    declare(module, &format!("chain {link}"), &[ty], &[ty])
+}
+
+/// The `link`th function of a chain, `fn(n: i64) -> i64`, which adds `link` to the factorial of
+/// `n` computed as [`iterative_cursor`] does, and to the result of the previous function of the
+/// chain, `prev`, on `n`.
+fn chain_link(func: &mut Function, link: usize, prev: Option<FuncRef>) {
+    let ty = types::I64;
+    let mut func_ctx = FunctionBuilderContext::new();
+    let mut bcx = FunctionBuilder::new(func, &mut func_ctx);
+    let entry = bcx.create_block();
+    let header = bcx.create_block();
+    let body = bcx.create_block();
+    let done = bcx.create_block();
+    bcx.append_block_params_for_function_params(entry);
+    let header_n = bcx.append_block_param(header, ty);
+    let header_acc = bcx.append_block_param(header, ty);
+    let result = bcx.append_block_param(done, ty);
+
+    bcx.switch_to_block(entry);
+    let n = bcx.block_params(entry)[0];
+    let one = bcx.ins().iconst(ty, 1);
+    bcx.ins().jump(header, &[n, one]);
+
+    bcx.switch_to_block(header);
+    let more = bcx.ins().icmp_imm(IntCC::SignedGreaterThan, header_n, 1);
+    bcx.ins().brif(more, body, &[], done, &[header_acc]);
+
+    bcx.switch_to_block(body);
+    let acc = bcx.ins().imul(header_acc, header_n);
+    let n_minus_one = bcx.ins().iadd_imm(header_n, -1);
+    bcx.ins().jump(header, &[n_minus_one, acc]);
+
+    bcx.switch_to_block(done);
+    let mut result = bcx.ins().iadd_imm(result, link as i64);
+    if let Some(prev) = prev {
+        let call = bcx.ins().call(prev, &[n]);
+        let rest = bcx.inst_results(call)[0];
+        result = bcx.ins().iadd(result, rest);
+    }
+    bcx.ins().return_(&[result]);
+    bcx.seal_all_blocks();
+    bcx.finalize();
+}
+
+/// The IR of a chain of `len` functions, for a module which declares them in order, as
+/// [`define_chain`] does.
+fn build_chain(len: usize) -> Vec<Function> {
+    let mut module = ScratchModule::new();
+    let ids: Vec<_> = (0..len)
+        .map(|link| declare_chain_link(module.get(), link))
+        .collect();
+    ids.iter()
+        .enumerate()
+        .map(|(link, &id)| {
+            let sig = module
+                .get()
+                .declarations()
+                .get_function_decl(id)
+                .signature
+                .clone();
+            let mut func = Function::with_name_signature(UserFuncName::user(0, id.as_u32()), sig);
+            let prev = link
+                .checked_sub(1)
+                .map(|prev| module.get().declare_func_in_func(ids[prev], &mut func));
+            chain_link(&mut func, link, prev);
+            ir_builders::verify(&format!("chain {link}"), &func, module.get().isa());
+            func
+        })
+        .collect()
+}
+
+/// Declare and define the functions of `chain` in the empty `module`, finalizing them all at the
+/// end, or each as soon as it's defined if `finalize_each`. Return the id of the last one.
+fn define_chain(module: &mut JITModule, chain: Vec<Context>, finalize_each: bool) -> FuncId {
+    let mut last = None;
+    if finalize_each {
+        for (link, mut ctx) in chain.into_iter().enumerate() {
+            let id = declare_chain_link(module, link);
+            module.define_function(id, &mut ctx).unwrap();
+            module.finalize_definitions().unwrap();
+            last = Some(id);
+        }
+    } else {
+        let ids: Vec<_> = (0..chain.len())
+            .map(|link| declare_chain_link(module, link))
+            .collect();
+        for (&id, mut ctx) in ids.iter().zip(chain) {
+            module.define_function(id, &mut ctx).unwrap();
+        }
+        module.finalize_definitions().unwrap();
+        last = ids.last().copied();
+    }
+    last.unwrap()
+}
+
+/// Time defining chains of each of the [`CHAIN_LENGTHS`] in a reset module, finalizing once or
+/// after each function, after checking what the last function of each computes.
+fn chain_benchmarks(group: &mut BenchmarkGroup<WallTime>) {
+    let module = RefCell::new(ScratchModule::new());
+    for len in CHAIN_LENGTHS {
+        let chain = build_chain(len);
+        let contexts = || -> Vec<_> { chain.iter().cloned().map(Context::for_function).collect() };
+        group.throughput(Throughput::Elements(len as u64));
+        for (name, finalize_each) in [("finalize once", false), ("finalize each", true)] {
+            {
+                // Each function adds the factorial of the input and its position in the chain.
+                let mut scratch = module.borrow_mut();
+                unsafe { scratch.get().reset() }.unwrap();
+                let last = define_chain(scratch.get(), contexts(), finalize_each);
+                let code = scratch.get().get_finalized_function(last);
+                let last =
+                    unsafe { std::mem::transmute::<*const u8, extern "C" fn(i64) -> i64>(code) };
+                let len = len as i64;
+                let expected = len * i64::iterative_factorial(CHAIN_INPUT) + len * (len - 1) / 2;
+                assert_eq!(last(CHAIN_INPUT), expected, "{name} of {len}");
+            }
+
+            group.bench_with_input(BenchmarkId::new(name, len), &len, |b, _| {
+                b.iter_batched(
+                    || {
+                        unsafe { module.borrow_mut().get().reset() }.unwrap();
+                        contexts()
+                    },
+                    |chain| define_chain(module.borrow_mut().get(), chain, finalize_each),
+                    BatchSize::PerIteration,
+                )
+            });
+        }
+    }
+}
+
+/// A function computing factorials, with its name and whether it recurses.
+type Variant<'a, T> = (&'a str, bool, &'a dyn Fn(T) -> T);
+
+/// Time running `facts`, the `interpreted` functions and the Rust functions over `T` on each of
+/// the inputs.
+fn run_benchmarks<T: Int>(
+    group: &mut BenchmarkGroup<WallTime>,
+    facts: &[JitFactorial<T>],
+    interpreted: &[InterpretedFactorial],
+) {
+    // Every variant computes the same results, with wrapping multiplications, as the iterative
+    // Rust function.
+    let rust_names = [
+        format!("rust recursive {}", T::TYPE),
+        format!("rust iterative {}", T::TYPE),
+    ];
+    let mut variants: Vec<Variant<T>> = vec![
+        (&rust_names[0], true, &T::recursive_factorial),
+        (&rust_names[1], false, &T::iterative_factorial),
+    ];
+    let calls: Vec<_> = facts.iter().map(|fact| move |n| fact.call(n)).collect();
+    for (fact, call) in facts.iter().zip(&calls) {
+        variants.push((&fact.name, fact.recurses, call));
+    }
+    let interpreted_calls: Vec<_> = interpreted
+        .iter()
+        .map(|fact| move |n| fact.call(n))
+        .collect();
+    for (fact, call) in interpreted.iter().zip(&interpreted_calls) {
+        variants.push((&fact.name, fact.recurses, call));
+    }
+    for n in INPUTS {
+        let expected = T::iterative_factorial(T::from_i32(n));
+        group.throughput(Throughput::Elements(n as u64));
+        for &(name, recurses, fact) in &variants {
+            if recurses && n > MAX_RECURSION_DEPTH {
+                continue;
+            }
+            assert_eq!(fact(T::from_i32(n)), expected, "{name}({n})");
+            group.bench_with_input(BenchmarkId::new(name, n), &n, |b, &n| {
+                b.iter(|| fact(black_box(T::from_i32(n))))
+            });
+        }
+    }
+}
+
+/// The input of the benchmarks comparing the configurations of the modules, on which the
+/// recursive functions make 30 calls.
+const CONFIG_INPUT: i32 = 30;
+
+/// Time compiling and running the 64-bit functions built with a `FunctionBuilder` in modules of
+/// each configuration, verifying them first if `verify_first`. The recursive function is timed
+/// apart from the iterative one, which doesn't call any function, so that the cost of calling
+/// through the GOT shows on its own. The sizes of their code are recorded in `sizes`.
+fn config_benchmarks(c: &mut Criterion, sizes: &mut CodeSizes, verify_first: bool) {
+    for (shape, recurses, build) in [
+        ("recursive", true, recursive_frontend as Build),
+        ("iterative", false, iterative_frontend),
+    ] {
+        let facts: Vec<JitFactorial<i64>> = JitConfig::ALL
+            .into_iter()
+            .map(|config| {
+                let shape = format!("{shape} frontend");
+                JitFactorial::in_config(config, &shape, recurses, |_, _| {}, build)
+            })
+            .collect();
+        sizes.record(&facts);
+
+        let mut group = c.benchmark_group(format!("compile {shape} factorial"));
+        for fact in &facts {
+            let declare = |module: &mut JITModule| fact.declare(module);
+            let (config, func) = (fact.config, &fact.func);
+            ir_builders::compile_benchmarks_in(
+                config,
+                &mut group,
+                config.name(),
+                func,
+                &declare,
+                fact.build,
+                verify_first,
+            );
+        }
+        group.finish();
+
+        let mut group = c.benchmark_group(format!("run {shape} factorial"));
+        let n = i64::from(CONFIG_INPUT);
+        group.throughput(Throughput::Elements(n as u64));
+        for fact in &facts {
+            group.bench_function(fact.config.name(), |b| b.iter(|| fact.call(black_box(n))));
+        }
+        group.finish();
+    }
+}
+
+fn factorial_benchmarks(c: &mut Criterion) {
+    let verify_first = ir_builders::verify_before_define();
+    let mut i32_facts = built_factorials::<i32>();
+    i32_facts.push(JitFactorial::new("recursive text", true, recursive_text));
+    i32_facts.push(JitFactorial::new("iterative text", false, iterative_text));
+    i32_facts.push(JitFactorial::new("iterative clifp", false, iterative_clifp));
+    i32_facts.push(JitFactorial::new(
+        "iterative select clifp",
+        false,
+        iterative_select_clifp,
+    ));
+    // The wide types are only built in memory; the text only exercises the parser, which doesn't
+    // depend on the type.
+    let mut i64_facts = built_factorials::<i64>();
+    i64_facts.push(JitFactorial::new("iterative clifp", false, iterative_clifp));
+    i64_facts.push(JitFactorial::new(
+        "iterative select clifp",
+        false,
+        iterative_select_clifp,
+    ));
+    // Tail calls are only lowered on x86-64.
+    #[cfg(target_arch = "x86_64")]
+    {
+        i32_facts.push(tail_recursive_factorial());
+        i64_facts.push(tail_recursive_factorial());
+    }
+    let i128_facts = built_factorials::<i128>();
+    // The interpreter runs the same IR as the JIT, so one shape of each function is enough.
+    let interpreted: Vec<_> = i32_facts
+        .iter()
+        .filter(|fact| fact.name.ends_with("frontend i32"))
+        .map(InterpretedFactorial::new)
+        .collect();
+
+    let mut group = c.benchmark_group("compile factorial");
+    for (name, text) in [
+        ("recursive text i32", RECURSIVE_CLIF),
+        ("iterative text i32", ITERATIVE_CLIF),
+    ] {
+        group.bench_function(BenchmarkId::new("parse", name), |b| {
+            b.iter(|| parse_functions(black_box(text)).unwrap())
+        });
+    }
+    compile_benchmarks(&mut group, &i32_facts, verify_first);
+    compile_benchmarks(&mut group, &i64_facts, verify_first);
+    compile_benchmarks(&mut group, &i128_facts, verify_first);
+    group.finish();
+
+    // The tail-recursive functions run in constant stack space, so they are verified with the
+    // iterative ones.
+    for (name, recursive) in [
+        ("verify recursive factorial", true),
+        ("verify iterative factorial", false),
+    ] {
+        let mut group = c.benchmark_group(name);
+        verify_benchmarks(&mut group, &i32_facts, recursive);
+        verify_benchmarks(&mut group, &i64_facts, recursive);
+        verify_benchmarks(&mut group, &i128_facts, recursive);
+        group.finish();
+    }
+
+    let targets = cross_targets();
+    let mut group = c.benchmark_group("cross-compile factorial");
+    cross_compile_benchmarks(&mut group, &targets, &i32_facts);
+    cross_compile_benchmarks(&mut group, &targets, &i64_facts);
+    cross_compile_benchmarks(&mut group, &targets, &i128_facts);
+    group.finish();
+
+    let mut group = c.benchmark_group("define chained factorials");
+    chain_benchmarks(&mut group);
+    group.finish();
+
+    let mut group = c.benchmark_group("run factorial");
+    run_benchmarks(&mut group, &i32_facts, &interpreted);
+    run_benchmarks(&mut group, &i64_facts, &[]);
+    run_benchmarks(&mut group, &i128_facts, &[]);
+    group.finish();
+
+    let mut sizes = CodeSizes::default();
+    sizes.record(&i32_facts);
+    sizes.record(&i64_facts);
+    sizes.record(&i128_facts);
+    config_benchmarks(c, &mut sizes, verify_first);
+    sizes.print();
+}
+
+criterion_group!(benches, factorial_benchmarks);
+criterion_main!(benches);
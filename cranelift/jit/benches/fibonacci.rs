@@ -0,0 +1,114 @@
+//! Measure compiling and running an iterative fibonacci function over 64-bit integers, whose
+//! loop carries the counter and two values of the sequence as block parameters, and compare it
+//! with the same function in Rust. Its results are checked with the JIT before they are timed.
+//! The function is built directly in the function, and compiling it is measured phase by phase
+//! as for the factorial functions.
+
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{types, Function, Opcode};
+use cranelift_jit::{JITModule, OwnedJitFn};
+use cranelift_module::FuncId;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use ir_builders::{
+    append_block, declare, emit_binary, emit_brif, emit_iadd_imm, emit_icmp_imm, emit_iconst,
+    emit_jump, emit_return,
+};
+
+// Each bench only uses some of the helpers.
+#[path = "support/ir_builders.rs"]
+#[allow(dead_code)]
+mod ir_builders;
+
+/// The inputs of the run benchmarks, which take as many iterations of the loop.
+const INPUTS: [i64; 4] = [10, 90, 10_000, 1_000_000];
+
+/// The 90th fibonacci number, the largest one below 2^63.
+const FIBONACCI_90: i64 = 2_880_067_194_370_816_120;
+
+/// `let (mut a, mut b) = (0, 1); for _ in 0..n { (a, b) = (b, a + b); } a`, wrapping.
+fn fibonacci(n: i64) -> i64 {
+    let (mut a, mut b) = (0i64, 1i64);
+    for _ in 0..n {
+        (a, b) = (b, a.wrapping_add(b));
+    }
+    a
+}
+
+/// Declare `fn fibonacci(n: i64) -> i64` in `module`.
+fn declare_fibonacci(module: &mut JITModule) -> FuncId {
+    declare(module, "fibonacci", &[types::I64], &[types::I64])
+}
+
+/// [`fibonacci`], directly in the function, passing the counter and the last two numbers of the
+/// sequence around the loop as block parameters, and the next number back to the loop from its
+/// body with the last one.
+fn iterative_dfg(func: &mut Function, _: FuncId, _: &mut JITModule) {
+    let ty = types::I64;
+    let entry = append_block(func);
+    let header = append_block(func);
+    let body = append_block(func);
+    let done = append_block(func);
+    let n = func.dfg.append_block_param(entry, ty);
+    let header_n = func.dfg.append_block_param(header, ty);
+    let header_a = func.dfg.append_block_param(header, ty);
+    let header_b = func.dfg.append_block_param(header, ty);
+    let result = func.dfg.append_block_param(done, ty);
+
+    let zero = emit_iconst(func, entry, ty, 0);
+    let one = emit_iconst(func, entry, ty, 1);
+    emit_jump(func, entry, header, &[n, zero, one]);
+
+    let more = emit_icmp_imm(func, header, IntCC::SignedGreaterThan, header_n, 0);
+    emit_brif(func, header, more, (body, &[]), (done, &[header_a]));
+
+    let next = emit_binary(func, body, Opcode::Iadd, header_a, header_b);
+    let n = emit_iadd_imm(func, body, header_n, -1);
+    emit_jump(func, body, header, &[n, header_b, next]);
+
+    emit_return(func, done, result);
+}
+
+fn fibonacci_benchmarks(c: &mut Criterion) {
+    let verify_first = ir_builders::verify_before_define();
+    let name = "iterative dfg i64";
+    let (func, code): (_, OwnedJitFn<extern "C" fn(i64) -> i64>) =
+        ir_builders::compile(name, &declare_fibonacci, iterative_dfg);
+    for n in -1..=90 {
+        assert_eq!(code.call(n), fibonacci(n), "{name}({n})");
+    }
+    assert_eq!(code.call(90), FIBONACCI_90);
+
+    let mut group = c.benchmark_group("compile fibonacci");
+    ir_builders::compile_benchmarks(
+        &mut group,
+        name,
+        &func,
+        &declare_fibonacci,
+        iterative_dfg,
+        verify_first,
+    );
+    group.finish();
+
+    let mut group = c.benchmark_group("verify fibonacci");
+    ir_builders::verify_benchmark(&mut group, name, &func);
+    group.finish();
+
+    let mut group = c.benchmark_group("run fibonacci");
+    let jit = |n| code.call(n);
+    let variants: [(&str, &dyn Fn(i64) -> i64); 2] =
+        [("rust iterative i64", &fibonacci), (name, &jit)];
+    for n in INPUTS {
+        let expected = fibonacci(n);
+        group.throughput(Throughput::Elements(n as u64));
+        for (name, fibonacci) in variants {
+            assert_eq!(fibonacci(n), expected, "{name}({n})");
+            group.bench_with_input(BenchmarkId::new(name, n), &n, |b, &n| {
+                b.iter(|| fibonacci(black_box(n)))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, fibonacci_benchmarks);
+criterion_main!(benches);
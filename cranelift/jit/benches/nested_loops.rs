@@ -0,0 +1,135 @@
+//! Measure how compiling a function scales with the depth of its nested loops, which is a common
+//! pathology of compile time, on clifp functions of `while`s nested 1, 4 and 16 deep. Every
+//! level counts its own variable up to the input and updates an accumulator with a value which
+//! doesn't depend on the loops, so each loop has some invariant code. Each function is run on a
+//! few inputs and checked against the same loops in Rust before it's timed.
+//!
+//! Compiling is measured phase by phase as for the factorial functions, where building the IR
+//! includes parsing and lowering the clifp source. The depth is the parameter of each entry, so
+//! that criterion shows each phase as a curve over the depths, and a regression in the loop
+//! analysis or the register allocation as a change of its slope.
+
+use cranelift_clifp as clifp;
+use cranelift_codegen::ir::{types, Function};
+use cranelift_jit::{JITModule, OwnedJitFn};
+use cranelift_module::FuncId;
+use criterion::{criterion_group, criterion_main, Criterion};
+use ir_builders::{declare, Build};
+
+// Each bench only uses some of the helpers.
+#[path = "support/ir_builders.rs"]
+#[allow(dead_code)]
+mod ir_builders;
+
+/// The inputs the functions are checked on. Each takes `n.pow(depth)` iterations of the
+/// innermost loop, so they stay small for the deepest function.
+const INPUTS: [i64; 3] = [0, 1, 2];
+
+/// `depth` nested loops, each counting from 0 to `n` and then multiplying the accumulator by 31
+/// and adding its count and `3 * n`, wrapping, after the loop inside it.
+fn nested(depth: usize, n: i64) -> i64 {
+    fn level(k: usize, depth: usize, n: i64, acc: &mut i64) {
+        let mut i = 0;
+        while i < n {
+            if k + 1 < depth {
+                level(k + 1, depth, n, acc);
+            }
+            *acc = acc.wrapping_mul(31).wrapping_add(i).wrapping_add(n * 3);
+            i += 1;
+        }
+    }
+    let mut acc = 0;
+    level(0, depth, n, &mut acc);
+    acc
+}
+
+/// The clifp source of [`nested`] at `depth`, whose counters and accumulator are in stack slots,
+/// since the values of clifp names don't change.
+fn nested_source(depth: usize) -> String {
+    let mut src = format!(
+        "(func nested ((n i64)) (i64)\n  \
+           (stack i {})\n  \
+           (stack acc 8)\n  \
+           (stack_store i64 acc 0 0)\n",
+        8 * depth
+    );
+    for k in 0..depth {
+        let indent = "  ".repeat(k + 1);
+        src.push_str(&format!(
+            "{indent}(stack_store i64 i {} 0)\n\
+             {indent}(while (< (stack_load i64 i {}) n)\n",
+            8 * k,
+            8 * k
+        ));
+    }
+    for k in (0..depth).rev() {
+        let indent = "  ".repeat(k + 2);
+        let i = format!("(stack_load i64 i {})", 8 * k);
+        src.push_str(&format!(
+            "{indent}(stack_store i64 acc 0 (+ (+ (* (stack_load i64 acc 0) 31) {i}) (* n 3)))\n\
+             {indent}(stack_store i64 i {} (+ {i} 1)))\n",
+            8 * k
+        ));
+    }
+    src.push_str("  (stack_load i64 acc 0))");
+    src
+}
+
+/// Declare `fn nested(n: i64) -> i64` in `module`.
+fn declare_nested(module: &mut JITModule) -> FuncId {
+    declare(module, "nested", &[types::I64], &[types::I64])
+}
+
+/// [`nested`] at `DEPTH`, lowered from the clifp source of [`nested_source`]. The depth is a
+/// parameter of the type, since a [`Build`] is a function pointer.
+fn nested_clifp<const DEPTH: usize>(func: &mut Function, _: FuncId, module: &mut JITModule) {
+    let (program, _) = clifp::frontend(&nested_source(DEPTH)).unwrap();
+    // The clifp function isn't declared in the module, so it's named after itself instead of
+    // the id of the declaration.
+    let name = func.name.clone();
+    *func = clifp::compile::lower(&program.functions[0], &program.constants, module).unwrap();
+    func.name = name;
+}
+
+fn nested_loops_benchmarks(c: &mut Criterion) {
+    let verify_first = ir_builders::verify_before_define();
+    let builds: [(usize, Build); 3] = [
+        (1, nested_clifp::<1>),
+        (4, nested_clifp::<4>),
+        (16, nested_clifp::<16>),
+    ];
+    let funcs: Vec<_> = builds
+        .iter()
+        .map(|&(depth, build)| {
+            let name = format!("nested whiles {depth}");
+            let (func, code): (_, OwnedJitFn<extern "C" fn(i64) -> i64>) =
+                ir_builders::compile(&name, &declare_nested, build);
+            for n in INPUTS {
+                assert_eq!(code.call(n), nested(depth, n), "{name}({n})");
+            }
+            func
+        })
+        .collect();
+
+    let mut group = c.benchmark_group("compile nested whiles");
+    for (&(depth, build), func) in builds.iter().zip(&funcs) {
+        ir_builders::compile_benchmarks(
+            &mut group,
+            &depth.to_string(),
+            func,
+            &declare_nested,
+            build,
+            verify_first,
+        );
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("verify nested whiles");
+    for (&(depth, _), func) in builds.iter().zip(&funcs) {
+        ir_builders::verify_benchmark(&mut group, &depth.to_string(), func);
+    }
+    group.finish();
+}
+
+criterion_group!(benches, nested_loops_benchmarks);
+criterion_main!(benches);
@@ -0,0 +1,408 @@
+//! Building the IR of the functions the JIT benchmarks compile and run, and compiling each of
+//! them in a JIT module of its own, for the benchmark of each function.
+//!
+//! The functions are verified before they're first compiled. The compile benchmarks also verify
+//! each function before they define it in builds with debug assertions, such as those of `cargo
+//! test`, or when [`VERIFY_VAR`] is set, so that broken IR panics with the errors of the verifier
+//! and the text of the function instead of being timed.
+//!
+//! The modules are built in the [`JitConfig::Default`] configuration unless a benchmark compares
+//! the configurations, which change how the functions of a module call each other.
+//!
+//! The functions built directly in the function, without a cursor or a `FunctionBuilder`,
+//! create their blocks with [`append_block`] and append each instruction to the end of a block
+//! with one of the `emit_*` helpers, which build its `InstructionData` for `Function::emit`.
+
+use cranelift_codegen::cursor::FuncCursor;
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{
+    types, AbiParam, Block, FuncRef, Function, InstBuilder, InstructionData, Opcode, Type,
+    UserFuncName, Value, ValueList,
+};
+use cranelift_codegen::settings::{self, Configurable, FlagsOrIsa};
+use cranelift_codegen::{verify_function, write_function, Context};
+use cranelift_jit::{JITBuilder, JITModule, JitFn, OwnedJitFn};
+use cranelift_module::{default_libcall_names, FuncId, Linkage, Module};
+use criterion::measurement::WallTime;
+use criterion::{BatchSize, BenchmarkGroup, BenchmarkId};
+use std::cell::RefCell;
+
+/// The environment variable which, set to anything but `0`, makes the compile benchmarks verify
+/// each function before they define it, as they always do in builds with debug assertions.
+pub const VERIFY_VAR: &str = "CRANELIFT_BENCH_VERIFY";
+
+/// Whether the compile benchmarks verify each function before they define it, which a bench
+/// reads once when it starts.
+pub fn verify_before_define() -> bool {
+    cfg!(debug_assertions) || matches!(std::env::var_os(VERIFY_VAR), Some(value) if value != "0")
+}
+
+/// Build the body of the function `id` of a module.
+pub type Build = fn(&mut Function, FuncId, &mut JITModule);
+
+/// Declare a function in a module, along with the functions it calls, and return its id.
+pub type Declare<'a> = &'a dyn Fn(&mut JITModule) -> FuncId;
+
+/// The flags of every JIT module. The 128-bit functions take and return their integers in pairs
+/// of registers, as Rust compiles `extern "C"` functions over `i128`, which needs the LLVM ABI
+/// extensions on x86-64. Tail calls on x86-64 rely on frame pointers, so they are preserved.
+const FLAGS: [(&str, &str); 2] = [
+    ("enable_llvm_abi_extensions", "true"),
+    ("preserve_frame_pointers", "true"),
+];
+
+/// A configuration of a JIT module, which changes how its functions reach each other, themselves
+/// included.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JitConfig {
+    /// Position-independent code, which `JITBuilder::with_flags` always makes, calling the
+    /// functions of the module directly.
+    Default,
+    /// Code which isn't position-independent, whose calls are relocated to the functions they
+    /// call, without a GOT.
+    NonPic,
+    /// Strictly position-independent code, which loads the address of every function it calls
+    /// from the GOT.
+    PicStrict,
+    /// Hotswapping, which also calls every function through its entry in the GOT, so that the
+    /// function can be redefined.
+    Hotswap,
+}
+
+impl JitConfig {
+    pub const ALL: [Self; 4] = [Self::Default, Self::NonPic, Self::PicStrict, Self::Hotswap];
+
+    /// The name of the configuration, which is the id of its benchmarks.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Default => "default",
+            Self::NonPic => "non-pic",
+            Self::PicStrict => "pic-strict",
+            Self::Hotswap => "hotswap",
+        }
+    }
+
+    /// A JIT module for the host, in this configuration.
+    pub fn module(self) -> JITModule {
+        let mut builder = match self {
+            // `JITBuilder::with_flags` sets `is_pic`, so the ISA is built like it does without it.
+            Self::NonPic => {
+                let mut flags = settings::builder();
+                for (name, value) in FLAGS {
+                    flags.set(name, value).unwrap();
+                }
+                flags.set("use_colocated_libcalls", "false").unwrap();
+                let isa = cranelift_native::builder()
+                    .unwrap()
+                    .finish(settings::Flags::new(flags))
+                    .unwrap();
+                JITBuilder::with_isa(isa, default_libcall_names())
+            }
+            _ => JITBuilder::with_flags(&FLAGS, default_libcall_names()).unwrap(),
+        };
+        builder
+            .pic_strict(self == Self::PicStrict)
+            .hotswap(self == Self::Hotswap);
+        JITModule::new(builder)
+    }
+}
+
+/// A JIT module whose memory is freed when it's dropped, for modules whose code is never run.
+pub struct ScratchModule(Option<JITModule>);
+
+impl ScratchModule {
+    pub fn new() -> Self {
+        Self::with_config(JitConfig::Default)
+    }
+
+    pub fn with_config(config: JitConfig) -> Self {
+        Self(Some(config.module()))
+    }
+
+    pub fn get(&mut self) -> &mut JITModule {
+        self.0.as_mut().unwrap()
+    }
+
+    /// Reset the module and `declare` a function in it again, reusing the memory of what it
+    /// compiled before.
+    pub fn redeclare(&mut self, declare: Declare) -> FuncId {
+        unsafe { self.get().reset() }.unwrap();
+        declare(self.get())
+    }
+}
+
+impl Drop for ScratchModule {
+    fn drop(&mut self) {
+        unsafe { self.0.take().unwrap().free_memory() };
+    }
+}
+
+/// Declare `fn name(params...) -> returns...` in `module`.
+pub fn declare(module: &mut JITModule, name: &str, params: &[Type], returns: &[Type]) -> FuncId {
+    let mut sig = module.make_signature();
+    sig.params
+        .extend(params.iter().map(|&ty| AbiParam::new(ty)));
+    sig.returns
+        .extend(returns.iter().map(|&ty| AbiParam::new(ty)));
+    module.declare_function(name, Linkage::Local, &sig).unwrap()
+}
+
+/// Build the IR of the function `id` of `module` with `build`.
+pub fn build_function(module: &mut JITModule, id: FuncId, build: Build) -> Function {
+    let sig = module
+        .declarations()
+        .get_function_decl(id)
+        .signature
+        .clone();
+    let mut func = Function::with_name_signature(UserFuncName::user(0, id.as_u32()), sig);
+    build(&mut func, id, module);
+    func
+}
+
+/// Verify `func`, built by the benchmark `name`, panicking with the errors of the verifier
+/// followed by the text of the function if it's invalid, to debug it from the log of a CI run.
+pub fn verify<'a>(name: &str, func: &Function, flags_or_isa: impl Into<FlagsOrIsa<'a>>) {
+    if let Err(errors) = verify_function(func, flags_or_isa) {
+        let mut text = String::new();
+        write_function(&mut text, func).unwrap();
+        panic!("{name} doesn't verify:\n{errors}\n{text}");
+    }
+}
+
+/// Build the function `name` with `build` in a module of its own where `declare` declared it,
+/// verify it, and compile and finalize it, printing the size of its code. Return the function
+/// before it was compiled, and its code, which owns the module.
+pub fn compile<F: JitFn>(name: &str, declare: Declare, build: Build) -> (Function, OwnedJitFn<F>) {
+    let (func, code, _) = compile_in(JitConfig::Default, name, declare, build);
+    (func, code)
+}
+
+/// [`compile`], in a module of the configuration `config`, also returning the size of the code
+/// in bytes, including its constants.
+pub fn compile_in<F: JitFn>(
+    config: JitConfig,
+    name: &str,
+    declare: Declare,
+    build: Build,
+) -> (Function, OwnedJitFn<F>, usize) {
+    let mut module = config.module();
+    let id = declare(&mut module);
+    let func = build_function(&mut module, id, build);
+    verify(name, &func, module.isa());
+    let mut ctx = Context::for_function(func.clone());
+    module.define_function(id, &mut ctx).unwrap();
+    // `define_function` leaves the code it compiled in the context, which the module copies as
+    // is into the memory it finalizes.
+    let size = ctx.compiled_code().unwrap().code_info().total_size as usize;
+    let code = unsafe { module.finalize_into_owned_fn(id) }.unwrap();
+    // The sizes of the code are printed before the benchmarks, to compare the functions.
+    let info = code.module().get_finalized_function_info(id);
+    assert!(info.size > 0, "{name}");
+    assert_eq!(info.size, size, "{name}");
+    eprintln!("{name}: {size} bytes of code");
+    (func, code, size)
+}
+
+/// Time each phase of compiling the function `name`, which `build` built as `func` in a module
+/// where `declare` declared it: building the IR, generating its code with `define_function`,
+/// and making it executable with `finalize_definitions`. Each phase starts from a fresh context
+/// or a reset module, prepared outside of the timing, where the function is verified before
+/// it's defined if `verify_first`.
+pub fn compile_benchmarks(
+    group: &mut BenchmarkGroup<WallTime>,
+    name: &str,
+    func: &Function,
+    declare: Declare,
+    build: Build,
+    verify_first: bool,
+) {
+    compile_benchmarks_in(
+        JitConfig::Default,
+        group,
+        name,
+        func,
+        declare,
+        build,
+        verify_first,
+    );
+}
+
+/// [`compile_benchmarks`], in modules of the configuration `config`.
+pub fn compile_benchmarks_in(
+    config: JitConfig,
+    group: &mut BenchmarkGroup<WallTime>,
+    name: &str,
+    func: &Function,
+    declare: Declare,
+    build: Build,
+    verify_first: bool,
+) {
+    let mut scratch = ScratchModule::with_config(config);
+    let id = declare(scratch.get());
+    group.bench_function(BenchmarkId::new("build IR", name), |b| {
+        b.iter(|| build_function(scratch.get(), id, build))
+    });
+    // The module is reset before each iteration, so each definition starts from an empty module,
+    // reusing the memory of the previous one. The ids of the declarations are the same each time.
+    let module = RefCell::new(ScratchModule::with_config(config));
+    let context = |module: &mut ScratchModule| {
+        if verify_first {
+            verify(name, func, module.get().isa());
+        }
+        Context::for_function(func.clone())
+    };
+    group.bench_function(BenchmarkId::new("define_function", name), |b| {
+        b.iter_batched(
+            || {
+                let mut module = module.borrow_mut();
+                let id = module.redeclare(declare);
+                (id, context(&mut module))
+            },
+            |(id, mut ctx)| {
+                module
+                    .borrow_mut()
+                    .get()
+                    .define_function(id, &mut ctx)
+                    .unwrap()
+            },
+            BatchSize::PerIteration,
+        )
+    });
+    group.bench_function(BenchmarkId::new("finalize_definitions", name), |b| {
+        b.iter_batched(
+            || {
+                let mut module = module.borrow_mut();
+                let id = module.redeclare(declare);
+                let mut ctx = context(&mut module);
+                module.get().define_function(id, &mut ctx).unwrap();
+            },
+            |()| module.borrow_mut().get().finalize_definitions().unwrap(),
+            BatchSize::PerIteration,
+        )
+    });
+}
+
+/// Time verifying `func`, built by the benchmark `name`, with the flags of the ISA of the JIT
+/// modules. Without the ISA itself, the checks of the types of global values and tables against
+/// the target are skipped, which the functions of the benchmarks have none of.
+pub fn verify_benchmark(group: &mut BenchmarkGroup<WallTime>, name: &str, func: &Function) {
+    let flags = ScratchModule::new().get().isa().flags().clone();
+    verify(name, func, &flags);
+    group.bench_function(name, |b| b.iter(|| verify_function(func, &flags).unwrap()));
+}
+
+/// `imm` as a value of the integer type `ty`. `iconst` only makes integers of up to 64 bits, so
+/// wider ones are extended from a 64-bit constant.
+pub fn iconst(pos: &mut FuncCursor, ty: Type, imm: i64) -> Value {
+    if ty.bits() <= 64 {
+        pos.ins().iconst(ty, imm)
+    } else {
+        let imm = pos.ins().iconst(types::I64, imm);
+        pos.ins().sextend(ty, imm)
+    }
+}
+
+/// Append a new block to the layout of `func`.
+pub fn append_block(func: &mut Function) -> Block {
+    let block = func.dfg.make_block();
+    func.layout.append_block(block);
+    block
+}
+
+/// `imm` as a value of the integer type `ty`, appended to `block` like [`iconst`].
+pub fn emit_iconst(func: &mut Function, block: Block, ty: Type, imm: i64) -> Value {
+    let data = InstructionData::UnaryImm {
+        opcode: Opcode::Iconst,
+        imm: imm.into(),
+    };
+    if ty.bits() <= 64 {
+        func.emit(block, data, ty).1[0]
+    } else {
+        let imm = func.emit(block, data, types::I64).1[0];
+        let data = InstructionData::Unary {
+            opcode: Opcode::Sextend,
+            arg: imm,
+        };
+        func.emit(block, data, ty).1[0]
+    }
+}
+
+/// `opcode x, y`, appended to `block`, for an opcode of two values such as `imul`.
+pub fn emit_binary(func: &mut Function, block: Block, opcode: Opcode, x: Value, y: Value) -> Value {
+    let data = InstructionData::Binary {
+        opcode,
+        args: [x, y],
+    };
+    func.emit_inferred(block, data).1[0]
+}
+
+/// `iadd_imm x, imm`, appended to `block`.
+pub fn emit_iadd_imm(func: &mut Function, block: Block, x: Value, imm: i64) -> Value {
+    let data = InstructionData::BinaryImm64 {
+        opcode: Opcode::IaddImm,
+        arg: x,
+        imm: imm.into(),
+    };
+    func.emit_inferred(block, data).1[0]
+}
+
+/// `icmp_imm cond x, imm`, appended to `block`.
+pub fn emit_icmp_imm(func: &mut Function, block: Block, cond: IntCC, x: Value, imm: i64) -> Value {
+    let data = InstructionData::IntCompareImm {
+        opcode: Opcode::IcmpImm,
+        cond,
+        arg: x,
+        imm: imm.into(),
+    };
+    func.emit_inferred(block, data).1[0]
+}
+
+/// `call callee(args...)`, appended to `block`, for a callee with a single result.
+pub fn emit_call(func: &mut Function, block: Block, callee: FuncRef, args: &[Value]) -> Value {
+    let args = ValueList::from_slice(args, &mut func.dfg.value_lists);
+    let data = InstructionData::Call {
+        opcode: Opcode::Call,
+        func_ref: callee,
+        args,
+    };
+    func.emit_inferred(block, data).1[0]
+}
+
+/// `jump destination(args...)`, appended to `block`.
+pub fn emit_jump(func: &mut Function, block: Block, destination: Block, args: &[Value]) {
+    let data = InstructionData::Jump {
+        opcode: Opcode::Jump,
+        destination: func.dfg.block_call(destination, args),
+    };
+    func.emit_inferred(block, data);
+}
+
+/// `brif cond, then(args...), else(args...)`, appended to `block`.
+pub fn emit_brif(
+    func: &mut Function,
+    block: Block,
+    cond: Value,
+    (then, then_args): (Block, &[Value]),
+    (else_, else_args): (Block, &[Value]),
+) {
+    let blocks = [
+        func.dfg.block_call(then, then_args),
+        func.dfg.block_call(else_, else_args),
+    ];
+    let data = InstructionData::Brif {
+        opcode: Opcode::Brif,
+        arg: cond,
+        blocks,
+    };
+    func.emit_inferred(block, data);
+}
+
+/// `return value`, appended to `block`.
+pub fn emit_return(func: &mut Function, block: Block, value: Value) {
+    let args = ValueList::from_slice(&[value], &mut func.dfg.value_lists);
+    let data = InstructionData::MultiAry {
+        opcode: Opcode::Return,
+        args,
+    };
+    func.emit_inferred(block, data);
+}
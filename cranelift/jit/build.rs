@@ -0,0 +1,14 @@
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+
+    #[cfg(feature = "signal-handlers")]
+    {
+        let mut build = cc::Build::new();
+        build.warnings(true);
+        let os = std::env::var("CARGO_CFG_TARGET_OS").unwrap();
+        build.define(&format!("CFG_TARGET_OS_{}", os), None);
+        println!("cargo:rerun-if-changed=src/helpers.c");
+        build.file("src/helpers.c");
+        build.compile("cranelift-jit-helpers");
+    }
+}
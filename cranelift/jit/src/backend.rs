@@ -1,19 +1,31 @@
 //! Defines `JITModule`.
 
+use crate::batch::{BatchError, JITBatch};
+use crate::heap::{Heap, HeapConfig};
+use crate::patching::CodePatcher;
+use crate::profiling::{self, ProfilingStrategy};
+use crate::stack_map::{StackMapTable, UserStackMapView};
+use crate::traps::TrapTable;
+use crate::typed::{self, JitFn, OwnedJitFn, SignatureMismatch};
+use crate::unwind::{JitFrame, UnwindTables};
 use crate::{compiled_blob::CompiledBlob, memory::BranchProtection, memory::Memory};
-use cranelift_codegen::binemit::Reloc;
+use cranelift_codegen::binemit::{CodeOffset, Reloc};
+use cranelift_codegen::isa::unwind::table::{FrameLayout, RaLocation};
 use cranelift_codegen::isa::{OwnedTargetIsa, TargetIsa};
 use cranelift_codegen::settings::Configurable;
-use cranelift_codegen::{self, ir, settings, MachReloc};
+use cranelift_codegen::{self, ir, settings, CompileError, CompiledCode, MachReloc};
 use cranelift_control::ControlPlane;
 use cranelift_entity::SecondaryMap;
+#[cfg(feature = "incremental-cache")]
+use cranelift_module::SaltedModuleCache;
 use cranelift_module::{
-    DataDescription, DataId, FuncId, Init, Linkage, Module, ModuleDeclarations, ModuleError,
-    ModuleExtName, ModuleReloc, ModuleResult,
+    ConstantPool, DataDescription, DataId, FuncId, Init, Linkage, Module, ModuleCompiledFunction,
+    ModuleDeclarations, ModuleError, ModuleExtName, ModuleReloc, ModuleResult, TargetVariant,
 };
 use log::info;
+use std::borrow::Cow;
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::convert::{TryFrom, TryInto};
 use std::ffi::CString;
 use std::io::Write;
@@ -21,6 +33,7 @@ use std::ptr;
 use std::ptr::NonNull;
 use std::sync::atomic::{AtomicPtr, Ordering};
 use target_lexicon::PointerWidth;
+use wasmtime_jit_icache_coherence as icache_coherence;
 
 const WRITABLE_DATA_ALIGNMENT: u64 = 0x8;
 const READONLY_DATA_ALIGNMENT: u64 = 0x1;
@@ -31,7 +44,13 @@ pub struct JITBuilder {
     symbols: HashMap<String, *const u8>,
     lookup_symbols: Vec<Box<dyn Fn(&str) -> Option<*const u8>>>,
     libcall_names: Box<dyn Fn(ir::LibCall) -> String + Send + Sync>,
+    libcall_resolver: Option<Box<dyn Fn(ir::LibCall) -> Option<*const u8>>>,
     hotswap_enabled: bool,
+    pic_strict: bool,
+    pool_constants: bool,
+    profiling: ProfilingStrategy,
+    #[cfg(feature = "incremental-cache")]
+    cache: Option<SaltedModuleCache>,
 }
 
 impl JITBuilder {
@@ -95,7 +114,13 @@ impl JITBuilder {
             symbols,
             lookup_symbols,
             libcall_names,
+            libcall_resolver: None,
             hotswap_enabled: false,
+            pic_strict: false,
+            pool_constants: false,
+            profiling: ProfilingStrategy::None,
+            #[cfg(feature = "incremental-cache")]
+            cache: None,
         }
     }
 
@@ -147,6 +172,20 @@ impl JITBuilder {
         self
     }
 
+    /// Set the function resolving the address of each libcall.
+    ///
+    /// The resolver is asked first, and libcalls it returns `None` for are looked up by their
+    /// name from `libcall_names` like any other symbol. This lets embedders without a C runtime
+    /// provide their own implementations. Use [`JITModule::referenced_libcalls`] to find out
+    /// which libcalls the compiled code needs.
+    pub fn libcall_resolver(
+        &mut self,
+        libcall_resolver: Box<dyn Fn(ir::LibCall) -> Option<*const u8>>,
+    ) -> &mut Self {
+        self.libcall_resolver = Some(libcall_resolver);
+        self
+    }
+
     /// Enable or disable hotswap support. See [`JITModule::prepare_for_function_redefine`]
     /// for more information.
     ///
@@ -155,6 +194,41 @@ impl JITBuilder {
         self.hotswap_enabled = enabled;
         self
     }
+
+    /// Enable or disable strict position independence. See [`JITModule::clone_function_to`]
+    /// for more information.
+    ///
+    /// Functions then refer to other functions, data objects and libcalls only through the GOT,
+    /// and defining functions with any other relocation, such as those of patchable calls,
+    /// fails. This requires PIC code, and is only supported on x86_64.
+    pub fn pic_strict(&mut self, enabled: bool) -> &mut Self {
+        self.pic_strict = enabled;
+        self
+    }
+
+    /// Enable or disable pooling of vector constants. See [`ConstantPool`] for more information.
+    ///
+    /// The pool is placed in read-only memory by [`JITModule::finalize_definitions`].
+    pub fn pool_constants(&mut self, enabled: bool) -> &mut Self {
+        self.pool_constants = enabled;
+        self
+    }
+
+    /// Describe the functions to profilers as `strategy` says each time they are finalized, so that
+    /// the samples in their code are attributed to them. See [`ProfilingStrategy`] for more
+    /// information.
+    pub fn profiling(&mut self, strategy: ProfilingStrategy) -> &mut Self {
+        self.profiling = strategy;
+        self
+    }
+
+    /// Reuse the code compiled for functions from `cache`, and store the code of functions which
+    /// aren't in it yet. See [`SaltedModuleCache`] for more information.
+    #[cfg(feature = "incremental-cache")]
+    pub fn cache(&mut self, cache: SaltedModuleCache) -> &mut Self {
+        self.cache = Some(cache);
+        self
+    }
 }
 
 /// A pending update to the GOT.
@@ -166,6 +240,29 @@ struct GotUpdate {
     ptr: *const u8,
 }
 
+/// What moving the code of a function with [`JITModule::clone_function_to`] involves, as
+/// returned by [`JITModule::pic_fixups`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PicFixups {
+    /// The size of the code, in bytes.
+    pub code_size: usize,
+    /// The entries of the module's GOT which the code uses, each once, in the order their
+    /// counterparts must have in the GOT of a copy.
+    pub got_entries: Vec<*const *const u8>,
+}
+
+/// Where the code of a finalized function is, as returned by
+/// [`JITModule::get_finalized_function_info`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FinalizedFuncInfo {
+    /// The address of the code, the same as [`JITModule::get_finalized_function`] returns.
+    pub ptr: *const u8,
+    /// The size of the code, including its constants, in bytes.
+    pub size: usize,
+    /// The alignment the code was allocated with, in bytes.
+    pub alignment: u64,
+}
+
 /// A `JITModule` implements `Module` and emits code and data into memory where it can be
 /// directly called and accessed.
 ///
@@ -173,9 +270,12 @@ struct GotUpdate {
 pub struct JITModule {
     isa: OwnedTargetIsa,
     hotswap_enabled: bool,
+    pic_strict: bool,
+    profiling: ProfilingStrategy,
     symbols: RefCell<HashMap<String, *const u8>>,
     lookup_symbols: Vec<Box<dyn Fn(&str) -> Option<*const u8>>>,
     libcall_names: Box<dyn Fn(ir::LibCall) -> String>,
+    libcall_resolver: Option<Box<dyn Fn(ir::LibCall) -> Option<*const u8>>>,
     memory: MemoryHandle,
     declarations: ModuleDeclarations,
     function_got_entries: SecondaryMap<FuncId, Option<NonNull<AtomicPtr<u8>>>>,
@@ -184,12 +284,42 @@ pub struct JITModule {
     libcall_got_entries: HashMap<ir::LibCall, NonNull<AtomicPtr<u8>>>,
     libcall_plt_entries: HashMap<ir::LibCall, NonNull<[u8; 16]>>,
     compiled_functions: SecondaryMap<FuncId, Option<CompiledBlob>>,
+    /// The variant chosen for each multi-versioned function, which the function resolves to.
+    variant_functions: SecondaryMap<FuncId, Option<FuncId>>,
     compiled_data_objects: SecondaryMap<DataId, Option<CompiledBlob>>,
     functions_to_finalize: Vec<FuncId>,
     data_objects_to_finalize: Vec<DataId>,
 
+    /// Stack maps of the safepoints in all defined functions.
+    stack_maps: StackMapTable,
+
+    /// Trap sites in all defined functions.
+    traps: TrapTable,
+
+    /// Unwind tables of all defined functions.
+    unwind_tables: UnwindTables,
+
+    /// Addresses of the patch points of the `patchable_call`s and `patchable_jump`s in all
+    /// defined functions.
+    patch_points: BTreeSet<usize>,
+
+    /// Addresses of the initial and alternate destinations of the `patchable_jump`s in all
+    /// defined functions, by the address of their patch point.
+    jump_destinations: BTreeMap<usize, [usize; 2]>,
+
     /// Updates to the GOT awaiting relocations to be made and region protections to be set
     pending_got_updates: Vec<GotUpdate>,
+
+    /// The vector constants of the functions defined since the last finalization, if constant
+    /// pooling is enabled.
+    constant_pool: Option<ConstantPool>,
+
+    /// The libcalls referenced by all defined functions, in the order they were first referenced.
+    referenced_libcalls: Vec<ir::LibCall>,
+
+    /// The cache of compiled functions consulted by `define_function`, if any.
+    #[cfg(feature = "incremental-cache")]
+    cache: Option<SaltedModuleCache>,
 }
 
 /// A handle to allow freeing memory allocated by the `Module`.
@@ -214,6 +344,82 @@ impl JITModule {
         self.memory.writable.free_memory();
     }
 
+    /// Forget all declarations and definitions, leaving the module as it was when it was made by
+    /// [`JITModule::new`], but keep the memory allocated for them to reuse for the following
+    /// definitions.
+    ///
+    /// This is how a module can be reused for unrelated functions without its memory growing
+    /// with each of them, which [`JITModule::allocated_bytes`] accounts for.
+    ///
+    /// # Safety
+    ///
+    /// Like [`JITModule::free_memory`], this invalidates all pointers retrieved from the module,
+    /// so none of its functions may be executing and none of their pointers may be called
+    /// afterwards.
+    ///
+    /// If making the memory writable again fails, nothing is forgotten, but the pointers may
+    /// only be used again after the next call to [`JITModule::finalize_definitions`].
+    pub unsafe fn reset(&mut self) -> ModuleResult<()> {
+        self.memory.code.make_writable()?;
+        self.memory.readonly.make_writable()?;
+        self.memory.writable.make_writable()?;
+        self.memory.code.recycle();
+        self.memory.readonly.recycle();
+        self.memory.writable.recycle();
+
+        self.declarations = ModuleDeclarations::default();
+        self.function_got_entries = SecondaryMap::new();
+        self.function_plt_entries = SecondaryMap::new();
+        self.data_object_got_entries = SecondaryMap::new();
+        self.libcall_got_entries.clear();
+        self.libcall_plt_entries.clear();
+        self.compiled_functions = SecondaryMap::new();
+        self.variant_functions = SecondaryMap::new();
+        self.compiled_data_objects = SecondaryMap::new();
+        self.functions_to_finalize.clear();
+        self.data_objects_to_finalize.clear();
+        self.stack_maps = StackMapTable::default();
+        self.traps = TrapTable::default();
+        self.unwind_tables = UnwindTables::default();
+        self.patch_points.clear();
+        self.jump_destinations.clear();
+        self.pending_got_updates.clear();
+        if self.constant_pool.is_some() {
+            self.constant_pool = Some(ConstantPool::new());
+        }
+        self.referenced_libcalls.clear();
+        self.create_libcall_entries();
+        Ok(())
+    }
+
+    /// The number of bytes of memory allocated for the code and data of the module, including
+    /// the memory kept for reuse by [`JITModule::reset`].
+    pub fn allocated_bytes(&self) -> usize {
+        self.memory.code.allocated_bytes()
+            + self.memory.readonly.allocated_bytes()
+            + self.memory.writable.allocated_bytes()
+    }
+
+    /// Create a GOT and PLT entry for each libcall, which calls of PIC code go through.
+    fn create_libcall_entries(&mut self) {
+        let all_libcalls = if self.isa.flags().is_pic() {
+            ir::LibCall::all_libcalls()
+        } else {
+            &[] // Not PIC, so no GOT and PLT entries necessary
+        };
+        for &libcall in all_libcalls {
+            let addr = if let Some(addr) = self.lookup_libcall(libcall) {
+                addr
+            } else {
+                continue;
+            };
+            let got_entry = self.new_got_entry(addr);
+            self.libcall_got_entries.insert(libcall, got_entry);
+            let plt_entry = self.new_plt_entry(got_entry);
+            self.libcall_plt_entries.insert(libcall, plt_entry);
+        }
+    }
+
     fn lookup_symbol(&self, name: &str) -> Option<*const u8> {
         match self.symbols.borrow_mut().entry(name.to_owned()) {
             std::collections::hash_map::Entry::Occupied(occ) => Some(*occ.get()),
@@ -231,6 +437,259 @@ impl JITModule {
         }
     }
 
+    fn lookup_libcall(&self, libcall: ir::LibCall) -> Option<*const u8> {
+        self.libcall_resolver
+            .as_ref()
+            .and_then(|resolve| resolve(libcall))
+            .or_else(|| self.lookup_symbol(&(self.libcall_names)(libcall)))
+    }
+
+    /// Compile the function of `ctx`, reusing its code from the cache if there is one.
+    fn compile<'a>(
+        &self,
+        ctx: &'a mut cranelift_codegen::Context,
+        isa: &dyn TargetIsa,
+        ctrl_plane: &mut ControlPlane,
+    ) -> Result<&'a CompiledCode, CompileError<'a>> {
+        #[cfg(feature = "incremental-cache")]
+        if let Some(cache) = &self.cache {
+            return cache.compile(ctx, isa, ctrl_plane);
+        }
+        ctx.compile(isa, ctrl_plane)
+    }
+
+    /// Define the function `id` by compiling the function of `ctx` for `isa`, which is the ISA of
+    /// the module unless `id` is a variant of a multi-versioned function.
+    fn define_function_for_isa(
+        &mut self,
+        id: FuncId,
+        ctx: &mut cranelift_codegen::Context,
+        isa: &dyn TargetIsa,
+        ctrl_plane: &mut ControlPlane,
+    ) -> ModuleResult<ModuleCompiledFunction> {
+        info!("defining function {}: {}", id, ctx.func.display());
+        let redefine = self.check_definable(id)?;
+
+        self.pool_constants(&mut ctx.func)?;
+
+        if self.hotswap_enabled || self.pic_strict {
+            // Disable colocated if hotswapping is enabled to avoid a PLT indirection in case of
+            // calls and to allow data objects to be hotswapped in the future. Strictly
+            // position-independent code must reach everything through the GOT.
+            for func in ctx.func.dfg.ext_funcs.values_mut() {
+                func.colocated = false;
+            }
+
+            for gv in ctx.func.global_values.values_mut() {
+                match gv {
+                    ir::GlobalValueData::Symbol { colocated, .. } => *colocated = false,
+                    _ => {}
+                }
+            }
+        }
+
+        // work around borrow-checker to allow reuse of ctx below
+        let res = self.compile(ctx, isa, ctrl_plane)?;
+        let alignment = res.buffer.alignment as u64;
+        let compiled_code = ctx.compiled_code().unwrap();
+        if self.hotswap_enabled
+            && compiled_code
+                .buffer
+                .call_sites()
+                .iter()
+                .any(|site| site.opcode == ir::Opcode::PatchableCall)
+        {
+            // Patchable calls must be direct, while hotswapping calls through the PLT.
+            return Err(ModuleError::Backend(anyhow::anyhow!(
+                "patchable calls are not supported with hotswapping"
+            )));
+        }
+
+        let relocs = compiled_code
+            .buffer
+            .relocs()
+            .iter()
+            .map(|reloc| ModuleReloc::from_mach_reloc(reloc, &ctx.func))
+            .collect::<Vec<_>>();
+        if self.pic_strict {
+            if let Some(reloc) = relocs
+                .iter()
+                .find(|reloc| reloc.kind != Reloc::X86GOTPCRel4)
+            {
+                return Err(ModuleError::Backend(anyhow::anyhow!(
+                    "function {} has a {} relocation to {}, which isn't position-independent",
+                    self.declarations.get_function_decl(id).linkage_name(id),
+                    reloc.kind,
+                    reloc.name
+                )));
+            }
+        }
+
+        // The earlier definition is only replaced once the new one compiled. Until the GOT entry
+        // is updated, its callers keep calling the earlier code.
+        if redefine {
+            self.remove_definition(id);
+        }
+
+        let size = compiled_code.code_info().total_size as usize;
+        let align = alignment
+            .max(self.isa.function_alignment().minimum as u64)
+            .max(self.isa.symbol_alignment());
+        let ptr = self
+            .memory
+            .code
+            .allocate(size, align)
+            .map_err(|e| ModuleError::Allocation {
+                message: "unable to alloc function",
+                err: e,
+            })?;
+
+        {
+            let mem = unsafe { std::slice::from_raw_parts_mut(ptr, size) };
+            mem.copy_from_slice(compiled_code.code_buffer());
+        }
+
+        self.record_libcalls(&relocs);
+        if self.hotswap_enabled {
+            self.check_libcalls(&relocs)?;
+        }
+
+        let reference_type = match self.isa.pointer_bits() {
+            32 => ir::types::R32,
+            _ => ir::types::R64,
+        };
+        self.stack_maps
+            .insert(ptr, compiled_code.buffer.stack_maps(), reference_type);
+        self.traps.insert(ptr, id, compiled_code.buffer.traps());
+        if let Some(table) = compiled_code.unwind_table(isa) {
+            self.unwind_tables.insert(ptr, id, table);
+        }
+        self.patch_points.extend(
+            compiled_code
+                .buffer
+                .patch_points()
+                .map(|offset| ptr as usize + offset as usize),
+        );
+        self.jump_destinations
+            .extend(compiled_code.buffer.patchable_jumps().iter().map(|jump| {
+                let addr = |offset| ptr as usize + offset as usize;
+                (
+                    addr(jump.offset),
+                    [addr(jump.destinations[0]), addr(jump.destinations[1])],
+                )
+            }));
+
+        let decl = self.declarations.get_function_decl(id);
+        self.record_function_for_perf(ptr, size, &decl.linkage_name(id));
+        self.compiled_functions[id] = Some(CompiledBlob {
+            ptr,
+            size,
+            align,
+            relocs,
+        });
+
+        if self.isa.flags().is_pic() {
+            self.pending_got_updates.push(GotUpdate {
+                entry: self.function_got_entries[id].unwrap(),
+                ptr,
+            })
+        }
+
+        if self.hotswap_enabled {
+            self.compiled_functions[id]
+                .as_ref()
+                .unwrap()
+                .perform_relocations(
+                    |name| match *name {
+                        ModuleExtName::User { .. } => {
+                            unreachable!("non GOT or PLT relocation in function {} to {}", id, name)
+                        }
+                        ModuleExtName::LibCall(ref libcall) => self
+                            .libcall_plt_entries
+                            .get(libcall)
+                            .unwrap_or_else(|| panic!("can't resolve libcall {}", libcall))
+                            .as_ptr()
+                            .cast::<u8>(),
+                        _ => panic!("invalid name"),
+                    },
+                    |name| self.get_got_address(name).as_ptr().cast(),
+                    |name| self.get_plt_address(name),
+                );
+        } else {
+            self.functions_to_finalize.push(id);
+        }
+
+        Ok(ModuleCompiledFunction {
+            size: compiled_code.code_info().total_size,
+        })
+    }
+
+    /// Record the libcalls referenced by `relocs` of a newly defined function.
+    fn record_libcalls(&mut self, relocs: &[ModuleReloc]) {
+        for reloc in relocs {
+            if let ModuleExtName::LibCall(libcall) = reloc.name {
+                if !self.referenced_libcalls.contains(&libcall) {
+                    self.referenced_libcalls.push(libcall);
+                }
+            }
+        }
+    }
+
+    /// Check that every libcall referenced by `relocs` can be resolved.
+    fn check_libcalls(&self, relocs: &[ModuleReloc]) -> ModuleResult<()> {
+        for reloc in relocs {
+            let libcall = match reloc.name {
+                ModuleExtName::LibCall(libcall) => libcall,
+                _ => continue,
+            };
+            let resolved = if self.isa.flags().is_pic() {
+                self.libcall_got_entries.contains_key(&libcall)
+            } else {
+                self.lookup_libcall(libcall).is_some()
+            };
+            if !resolved {
+                return Err(ModuleError::Backend(anyhow::anyhow!(
+                    "can't resolve libcall {} ({})",
+                    (self.libcall_names)(libcall),
+                    libcall
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that every imported function and data object referenced by `relocs` can be
+    /// resolved, rather than leaving a null address in the code.
+    fn check_imports(&self, relocs: &[ModuleReloc]) -> ModuleResult<()> {
+        for reloc in relocs {
+            if !matches!(reloc.name, ModuleExtName::User { .. }) {
+                continue;
+            }
+            let (name, linkage) = if ModuleDeclarations::is_function(&reloc.name) {
+                let decl = self
+                    .declarations
+                    .get_function_decl(FuncId::from_name(&reloc.name));
+                (&decl.name, decl.linkage)
+            } else {
+                let decl = self
+                    .declarations
+                    .get_data_decl(DataId::from_name(&reloc.name));
+                (&decl.name, decl.linkage)
+            };
+            if linkage != Linkage::Import {
+                continue;
+            }
+            if let Some(name) = name {
+                if self.lookup_symbol(name).is_none() {
+                    return Err(ModuleError::Backend(anyhow::anyhow!(
+                        "can't resolve symbol {name}"
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
     fn new_got_entry(&mut self, val: *const u8) -> NonNull<AtomicPtr<u8>> {
         let got_entry = self
             .memory
@@ -307,7 +766,7 @@ impl JITModule {
                     if self.hotswap_enabled {
                         return self.get_plt_address(name);
                     } else {
-                        let func_id = FuncId::from_name(name);
+                        let func_id = self.resolve_variant(FuncId::from_name(name));
                         match &self.compiled_functions[func_id] {
                             Some(compiled) => return compiled.ptr,
                             None => {
@@ -337,11 +796,9 @@ impl JITModule {
                     panic!("can't resolve symbol {}", name);
                 }
             }
-            ModuleExtName::LibCall(ref libcall) => {
-                let sym = (self.libcall_names)(*libcall);
-                self.lookup_symbol(&sym)
-                    .unwrap_or_else(|| panic!("can't resolve libcall {}", sym))
-            }
+            ModuleExtName::LibCall(libcall) => self.lookup_libcall(libcall).unwrap_or_else(|| {
+                panic!("can't resolve libcall {}", (self.libcall_names)(libcall))
+            }),
             _ => panic!("invalid name"),
         }
     }
@@ -396,11 +853,137 @@ impl JITModule {
         }
     }
 
+    /// Returns the stack map of the safepoint whose call returns to `pc`, if any.
+    ///
+    /// Stack maps are only recorded for functions compiled with the `enable_safepoints` setting,
+    /// and only for calls which have live references. They stay available until the function is
+    /// redefined or the module's memory is freed.
+    pub fn stack_map_at(&self, pc: *const u8) -> Option<&UserStackMapView> {
+        self.stack_maps.get(pc)
+    }
+
+    /// Returns the trap code of the trapping instruction at `pc`, if any.
+    ///
+    /// This covers every defined function, so an embedder's own signal handler can use it to
+    /// tell whether a fault happened in JIT-compiled code and why. It doesn't allocate or take
+    /// locks, so it is safe to call from a signal handler.
+    pub fn lookup_trap(&self, pc: *const u8) -> Option<ir::TrapCode> {
+        self.lookup_trap_site(pc).map(|(_, code)| code)
+    }
+
+    /// Returns the function containing the trapping instruction at `pc` along with its trap
+    /// code, if any.
+    pub fn lookup_trap_site(&self, pc: *const u8) -> Option<(FuncId, ir::TrapCode)> {
+        self.traps.get(pc)
+    }
+
+    /// Returns the function containing `pc` along with the layout of its frame there, if `pc`
+    /// is in a function defined from a [`Context`](cranelift_codegen::Context) and the layout is
+    /// known.
+    ///
+    /// This lets a stack walker unwind JIT-compiled frames without frame pointers, see
+    /// [`JITModule::jit_backtrace`]. The layout is only recorded with the `unwind_info` setting,
+    /// and isn't known within prologues and epilogues. It doesn't allocate or take locks, so it
+    /// is safe to call from a signal handler.
+    pub fn unwind_info_at(&self, pc: *const u8) -> Option<(FuncId, FrameLayout)> {
+        self.unwind_tables.get(pc)
+    }
+
+    /// Walks the frames of JIT-compiled code, starting from the frame whose stack pointer is
+    /// `entry_sp` at `entry_pc`, using [`JITModule::unwind_info_at`].
+    ///
+    /// The walk stops at the first frame which isn't in JIT-compiled code or whose layout isn't
+    /// known, and at a return address still in the link register, which only the innermost
+    /// frame can have and which can't be read from memory. The frames are returned innermost
+    /// first.
+    ///
+    /// # Safety
+    ///
+    /// `entry_sp` and `entry_pc` must be the stack pointer and program counter of a suspended
+    /// frame of a finalized function of this module, e.g. as passed to a signal handler or to a
+    /// host function called by JIT-compiled code, and the stack above it must stay unchanged
+    /// during the walk.
+    pub unsafe fn jit_backtrace(&self, entry_sp: usize, entry_pc: *const u8) -> Vec<JitFrame> {
+        let mut frames = vec![];
+        let (mut sp, mut pc) = (entry_sp, entry_pc);
+        while let Some((func_id, layout)) = self.unwind_info_at(pc) {
+            frames.push(JitFrame::new(func_id, pc, sp));
+            let cfa = sp + layout.cfa_offset_from_sp as usize;
+            pc = match layout.ra_location {
+                RaLocation::CfaOffset(offset) => {
+                    *(cfa.wrapping_add(offset as isize as usize) as *const *const u8)
+                }
+                RaLocation::LinkRegister => break,
+            };
+            sp = cfa;
+        }
+        frames
+    }
+
+    /// Returns the defined function whose code contains `pc`, if any.
+    ///
+    /// Together with functions compiled with the `preserve_frame_pointers` setting, this lets a
+    /// profiler or debugger walk the frame pointer chain of JIT-compiled code and attribute each
+    /// return address to a function. This searches all the defined functions, so it takes time
+    /// linear in their number.
+    pub fn function_at(&self, pc: *const u8) -> Option<FuncId> {
+        let pc = pc as usize;
+        self.compiled_functions.iter().find_map(|(id, blob)| {
+            let blob = blob.as_ref()?;
+            let start = blob.ptr as usize;
+            (start..start + blob.size).contains(&pc).then_some(id)
+        })
+    }
+
+    /// Returns the addresses of the patch points of the `patchable_call`s and `patchable_jump`s in
+    /// the defined function `func_id`, in code order.
+    pub fn patch_points(&self, func_id: FuncId) -> Vec<*const u8> {
+        let blob = match &self.compiled_functions[func_id] {
+            Some(blob) => blob,
+            None => return vec![],
+        };
+        let start = blob.ptr as usize;
+        self.patch_points
+            .range(start..start + blob.size)
+            .map(|&addr| addr as *const u8)
+            .collect()
+    }
+
+    /// Returns the libcalls referenced by the functions defined so far, which the
+    /// [`JITBuilder::libcall_resolver`] or the symbol lookup must be able to resolve.
+    ///
+    /// The libcalls are listed in the order they were first referenced.
+    pub fn referenced_libcalls(&self) -> &[ir::LibCall] {
+        &self.referenced_libcalls
+    }
+
+    /// Returns a [`CodePatcher`] for the patch points of all functions defined so far.
+    ///
+    /// The patcher can be sent to other threads and used while the module's code runs, but only
+    /// on functions which have been finalized, as finalization applies relocations over the
+    /// patch points.
+    pub fn code_patcher(&self) -> CodePatcher {
+        CodePatcher::new(
+            self.patch_points.clone(),
+            self.jump_destinations.clone(),
+            self.memory.code.branch_protection(),
+        )
+    }
+
+    /// The function `func_id` stands for: the chosen variant if `func_id` was defined with
+    /// [`Module::define_function_multiversion`], and `func_id` itself otherwise.
+    fn resolve_variant(&self, func_id: FuncId) -> FuncId {
+        self.variant_functions[func_id].unwrap_or(func_id)
+    }
+
     /// Returns the address of a finalized function.
     ///
+    /// For a multi-versioned function this is the address of the chosen variant.
+    ///
     /// The pointer remains valid until either [`JITModule::free_memory`] is called or in the future
     /// some way of deallocating this individual function is used.
     pub fn get_finalized_function(&self, func_id: FuncId) -> *const u8 {
+        let func_id = self.resolve_variant(func_id);
         let info = &self.compiled_functions[func_id];
         assert!(
             !self.functions_to_finalize.iter().any(|x| *x == func_id),
@@ -411,6 +994,73 @@ impl JITModule {
             .ptr
     }
 
+    /// Returns the address, size and alignment of the code of a finalized function.
+    ///
+    /// For a multi-versioned function this describes the chosen variant. Like
+    /// [`JITModule::get_finalized_function`], this panics if the function isn't finalized.
+    pub fn get_finalized_function_info(&self, func_id: FuncId) -> FinalizedFuncInfo {
+        let blob = self.finalized_blob(func_id);
+        FinalizedFuncInfo {
+            ptr: blob.ptr,
+            size: blob.size,
+            alignment: blob.align,
+        }
+    }
+
+    /// Returns a finalized function as a pointer of the function type `F`, such as
+    /// `extern "C" fn(i32) -> i32`, or an error if the function wasn't declared with the
+    /// parameter and result types of `F` in the calling convention of `extern "C"` functions.
+    ///
+    /// Like [`JITModule::get_finalized_function`], this panics if the function isn't finalized,
+    /// which remains the way to get functions whose signatures have no Rust equivalent.
+    ///
+    /// # Safety
+    ///
+    /// Calling the function through the pointer must be sound: the code must only access memory
+    /// it's allowed to, with arguments valid for it, and the pointer must not be called after
+    /// [`JITModule::free_memory`].
+    pub unsafe fn get_finalized_function_typed<F: JitFn>(
+        &self,
+        func_id: FuncId,
+    ) -> Result<F, SignatureMismatch> {
+        let decl = self.declarations.get_function_decl(func_id);
+        let call_conv = self.isa.default_call_conv();
+        if !typed::matches::<F>(&decl.signature, call_conv) {
+            return Err(SignatureMismatch {
+                name: decl.linkage_name(func_id).into_owned(),
+                signature: decl.signature.clone(),
+                requested: std::any::type_name::<F>(),
+                call_conv,
+            });
+        }
+        Ok(F::from_ptr(self.get_finalized_function(func_id)))
+    }
+
+    /// Finalize the definitions of the module, as [`JITModule::finalize_definitions`] does, and
+    /// return the function `func_id` as a function of the type `F` which owns the module, so
+    /// that its code stays valid as long as the function can be called.
+    ///
+    /// A function which wasn't declared with the parameter and result types of `F` in the
+    /// calling convention of `extern "C"` functions is a [`ModuleError::Backend`] wrapping a
+    /// [`SignatureMismatch`]. Like [`JITModule::get_finalized_function`], this panics if the
+    /// function isn't defined.
+    ///
+    /// # Safety
+    ///
+    /// Calling the function must be sound, as for [`JITModule::get_finalized_function_typed`],
+    /// and the pointers retrieved from the module before must not be used after the returned
+    /// function is dropped, which frees the memory of the module.
+    pub unsafe fn finalize_into_owned_fn<F: JitFn>(
+        mut self,
+        func_id: FuncId,
+    ) -> ModuleResult<OwnedJitFn<F>> {
+        self.finalize_definitions()?;
+        let func = self
+            .get_finalized_function_typed(func_id)
+            .map_err(|err| ModuleError::Backend(anyhow::Error::new(err)))?;
+        Ok(OwnedJitFn::new(self, func))
+    }
+
     /// Returns the address and size of a finalized data object.
     ///
     /// The pointer remains valid until either [`JITModule::free_memory`] is called or in the future
@@ -452,14 +1102,41 @@ impl JITModule {
     /// Use `get_finalized_function` and `get_finalized_data` to obtain the final
     /// artifacts.
     ///
-    /// Returns ModuleError in case of allocation or syscall failure
+    /// Returns ModuleError in case of allocation or syscall failure, or if a referenced libcall
+    /// can't be resolved.
     pub fn finalize_definitions(&mut self) -> ModuleResult<()> {
-        for func in std::mem::take(&mut self.functions_to_finalize) {
-            let decl = self.declarations.get_function_decl(func);
-            assert!(decl.linkage.is_definable());
+        if let Some((id, data)) = self.constant_pool.as_mut().and_then(|pool| pool.take()) {
+            self.define_data(id, &data)?;
+        }
+
+        for &func in &self.functions_to_finalize {
             let func = self.compiled_functions[func]
                 .as_ref()
                 .expect("function must be compiled before it can be finalized");
+            self.check_libcalls(&func.relocs)?;
+            self.check_imports(&func.relocs)?;
+        }
+        for &data in &self.data_objects_to_finalize {
+            let data = self.compiled_data_objects[data]
+                .as_ref()
+                .expect("data object must be compiled before it can be finalized");
+            self.check_imports(&data.relocs)?;
+        }
+
+        let mut finalized = Vec::new();
+        for id in std::mem::take(&mut self.functions_to_finalize) {
+            let decl = self.declarations.get_function_decl(id);
+            assert!(decl.linkage.is_definable());
+            let func = self.compiled_functions[id]
+                .as_ref()
+                .expect("function must be compiled before it can be finalized");
+            if self.profiling != ProfilingStrategy::None {
+                let name = match &decl.name {
+                    Some(name) => Cow::Borrowed(name.as_str()),
+                    None => Cow::Owned(id.to_string()),
+                };
+                finalized.push((name, func.ptr as *const u8, func.size));
+            }
             func.perform_relocations(
                 |name| self.get_address(name),
                 |name| self.get_got_address(name).as_ptr().cast(),
@@ -487,6 +1164,7 @@ impl JITModule {
         for update in self.pending_got_updates.drain(..) {
             unsafe { update.entry.as_ref() }.store(update.ptr as *mut _, Ordering::SeqCst);
         }
+        profiling::register(self.profiling, &finalized);
         Ok(())
     }
 
@@ -498,6 +1176,12 @@ impl JITModule {
                 "Hotswapping requires PIC code"
             );
         }
+        if builder.pic_strict {
+            assert!(
+                builder.isa.flags().is_pic(),
+                "Strict position independence requires PIC code"
+            );
+        }
 
         let branch_protection =
             if cfg!(target_arch = "aarch64") && use_bti(&builder.isa.isa_flags()) {
@@ -508,9 +1192,12 @@ impl JITModule {
         let mut module = Self {
             isa: builder.isa,
             hotswap_enabled: builder.hotswap_enabled,
+            pic_strict: builder.pic_strict,
+            profiling: builder.profiling,
             symbols: RefCell::new(builder.symbols),
             lookup_symbols: builder.lookup_symbols,
             libcall_names: builder.libcall_names,
+            libcall_resolver: builder.libcall_resolver,
             memory: MemoryHandle {
                 code: Memory::new(branch_protection),
                 // Branch protection is not applicable to non-executable memory.
@@ -524,41 +1211,148 @@ impl JITModule {
             libcall_got_entries: HashMap::new(),
             libcall_plt_entries: HashMap::new(),
             compiled_functions: SecondaryMap::new(),
+            variant_functions: SecondaryMap::new(),
             compiled_data_objects: SecondaryMap::new(),
             functions_to_finalize: Vec::new(),
             data_objects_to_finalize: Vec::new(),
+            stack_maps: StackMapTable::default(),
+            traps: TrapTable::default(),
+            unwind_tables: UnwindTables::default(),
+            patch_points: BTreeSet::new(),
+            jump_destinations: BTreeMap::new(),
             pending_got_updates: Vec::new(),
+            constant_pool: builder.pool_constants.then(ConstantPool::new),
+            referenced_libcalls: Vec::new(),
+            #[cfg(feature = "incremental-cache")]
+            cache: builder.cache,
         };
 
         // Pre-create a GOT and PLT entry for each libcall.
-        let all_libcalls = if module.isa.flags().is_pic() {
-            ir::LibCall::all_libcalls()
-        } else {
-            &[] // Not PIC, so no GOT and PLT entries necessary
-        };
-        for &libcall in all_libcalls {
-            let sym = (module.libcall_names)(libcall);
-            let addr = if let Some(addr) = module.lookup_symbol(&sym) {
-                addr
-            } else {
-                continue;
-            };
-            let got_entry = module.new_got_entry(addr);
-            module.libcall_got_entries.insert(libcall, got_entry);
-            let plt_entry = module.new_plt_entry(got_entry);
-            module.libcall_plt_entries.insert(libcall, plt_entry);
+        module.create_libcall_entries();
+        module
+    }
+
+    /// The constant pool of the functions defined since the last call to
+    /// [`finalize_definitions`], if constant pooling is enabled.
+    ///
+    /// [`finalize_definitions`]: JITModule::finalize_definitions
+    pub fn constant_pool(&self) -> Option<&ConstantPool> {
+        self.constant_pool.as_ref()
+    }
+
+    /// Create a heap for the code of this module, with the sizes of `config`.
+    ///
+    /// Functions access it through [`Heap::declare_heap_in_func`].
+    pub fn create_heap(&mut self, config: HeapConfig) -> ModuleResult<Heap> {
+        Heap::new(config, |definition| {
+            let name = format!("__cranelift_jit_heap_{:p}", definition);
+            self.symbols.borrow_mut().insert(name.clone(), definition);
+            self.declare_data(&name, Linkage::Import, true, false)
+        })
+    }
+
+    /// Define the functions added to the batch by `build`, then finalize all definitions.
+    ///
+    /// Before anything is defined, the functions of the batch are checked to only call imported
+    /// functions, functions which are already defined, and functions of the batch, so that
+    /// functions calling each other can be declared, built and handed to the batch in any order.
+    /// Calls to any other function are reported by [`BatchError::Unresolved`].
+    pub fn define_batch<T>(
+        &mut self,
+        build: impl FnOnce(&mut JITBatch<'_>) -> T,
+    ) -> Result<T, BatchError> {
+        let mut batch = JITBatch::new(self);
+        let output = build(&mut batch);
+        batch.finish()?;
+        Ok(output)
+    }
+
+    /// Returns what moving the code of the finalized function `func_id` involves.
+    ///
+    /// Panics if strict position independence isn't enabled with [`JITBuilder::pic_strict`].
+    pub fn pic_fixups(&self, func_id: FuncId) -> PicFixups {
+        assert!(
+            self.pic_strict,
+            "Strict position independence is not enabled"
+        );
+        let blob = self.finalized_blob(func_id);
+        PicFixups {
+            code_size: blob.size,
+            got_entries: blob
+                .got_entries(|name| self.get_got_address(name).as_ptr() as *const *const u8),
         }
+    }
 
-        module
+    /// Copy the code of the finalized function `func_id` to `dest`, and make the copy use the
+    /// GOT at `got_base` instead of the module's.
+    ///
+    /// The copy's GOT is an array of pointers, one per entry of [`PicFixups::got_entries`], in
+    /// the same order. It doesn't need to be filled in until the copy runs, which is when the
+    /// copy reads it. Copying the module's entries makes the copy behave like the original, but
+    /// the copy of a recursive function can e.g. be pointed at itself instead.
+    ///
+    /// The trap, stack map and unwind information of the function isn't available for the copy,
+    /// and neither can its patch points be patched.
+    ///
+    /// Panics if strict position independence isn't enabled with [`JITBuilder::pic_strict`],
+    /// or if the copy's GOT is more than 2 GiB away from `dest`.
+    ///
+    /// # Safety
+    ///
+    /// `dest` must be valid for writing [`PicFixups::code_size`] bytes, which must be made
+    /// executable before the copy runs, and `got_base` must be valid for reading its entries
+    /// while the copy runs.
+    pub unsafe fn clone_function_to(
+        &self,
+        func_id: FuncId,
+        dest: *mut u8,
+        got_base: *mut u8,
+    ) -> ModuleResult<()> {
+        let fixups = self.pic_fixups(func_id);
+        let blob = self.finalized_blob(func_id);
+        blob.copy_to(dest, got_base, |name| {
+            let entry = self.get_got_address(name).as_ptr() as *const *const u8;
+            fixups
+                .got_entries
+                .iter()
+                .position(|&used| used == entry)
+                .unwrap()
+        });
+        icache_coherence::clear_cache(dest.cast(), blob.size)
+            .map_err(|e| ModuleError::Backend(anyhow::Error::new(e)))?;
+        icache_coherence::pipeline_flush_mt()
+            .map_err(|e| ModuleError::Backend(anyhow::Error::new(e)))?;
+        Ok(())
+    }
+
+    /// The code of the finalized function `func_id`.
+    fn finalized_blob(&self, func_id: FuncId) -> &CompiledBlob {
+        let func_id = self.resolve_variant(func_id);
+        assert!(
+            !self.functions_to_finalize.contains(&func_id),
+            "function not yet finalized"
+        );
+        self.compiled_functions[func_id]
+            .as_ref()
+            .expect("function must be compiled before it can be finalized")
+    }
+
+    /// Whether the function `func_id` has a definition, possibly not yet finalized.
+    pub(crate) fn is_defined(&self, func_id: FuncId) -> bool {
+        self.compiled_functions[func_id].is_some() || self.variant_functions[func_id].is_some()
     }
 
     /// Allow a single future `define_function` on a previously defined function. This allows for
     /// hot code swapping and lazy compilation of functions.
     ///
-    /// This requires hotswap support to be enabled first using [`JITBuilder::hotswap`].
+    /// This requires hotswap support to be enabled first using [`JITBuilder::hotswap`]. With
+    /// hotswap support, `define_function` also redefines functions directly, so this is only
+    /// needed to leave a function undefined until it is defined again.
     pub fn prepare_for_function_redefine(&mut self, func_id: FuncId) -> ModuleResult<()> {
-        assert!(self.hotswap_enabled, "Hotswap support is not enabled");
         let decl = self.declarations.get_function_decl(func_id);
+        if !self.hotswap_enabled {
+            return Err(self.redefinition_without_hotswap(func_id));
+        }
         if !decl.linkage.is_definable() {
             return Err(ModuleError::InvalidImportDefinition(
                 decl.linkage_name(func_id).into_owned(),
@@ -572,12 +1366,64 @@ impl JITModule {
             )));
         }
 
-        self.compiled_functions[func_id] = None;
+        self.remove_definition(func_id);
 
         // FIXME return some kind of handle that allows for deallocating the function
 
         Ok(())
     }
+
+    /// Forget the code of the function `func_id`, which is replaced by a new definition. The code
+    /// stays in memory, and stays reachable until the GOT entry of the function is updated.
+    fn remove_definition(&mut self, func_id: FuncId) {
+        if let Some(blob) = self.compiled_functions[func_id].take() {
+            self.stack_maps.remove(blob.ptr, blob.size);
+            self.traps.remove(blob.ptr, blob.size);
+            self.unwind_tables.remove(blob.ptr);
+            let start = blob.ptr as usize;
+            let removed: Vec<usize> = self
+                .patch_points
+                .range(start..start + blob.size)
+                .copied()
+                .collect();
+            for addr in removed {
+                self.patch_points.remove(&addr);
+                self.jump_destinations.remove(&addr);
+            }
+        }
+    }
+
+    /// Check that the function `id` can be defined, and whether it replaces an earlier
+    /// definition, which only hotswapping allows.
+    fn check_definable(&self, id: FuncId) -> ModuleResult<bool> {
+        let decl = self.declarations.get_function_decl(id);
+        if !decl.linkage.is_definable() {
+            return Err(ModuleError::InvalidImportDefinition(
+                decl.linkage_name(id).into_owned(),
+            ));
+        }
+        if self.variant_functions[id].is_some() {
+            return Err(ModuleError::DuplicateDefinition(
+                decl.linkage_name(id).into_owned(),
+            ));
+        }
+        match self.compiled_functions[id] {
+            Some(_) if self.hotswap_enabled => Ok(true),
+            Some(_) => Err(self.redefinition_without_hotswap(id)),
+            None => Ok(false),
+        }
+    }
+
+    /// The error for redefining the function `func_id` while hotswapping is disabled.
+    fn redefinition_without_hotswap(&self, func_id: FuncId) -> ModuleError {
+        ModuleError::Backend(anyhow::anyhow!(
+            "function {} can't be redefined without hotswap support, which must be enabled with \
+             `JITBuilder::hotswap` before the module is made",
+            self.declarations
+                .get_function_decl(func_id)
+                .linkage_name(func_id),
+        ))
+    }
 }
 
 impl Module for JITModule {
@@ -650,106 +1496,87 @@ impl Module for JITModule {
         Ok(id)
     }
 
+    fn pool_constants(&mut self, func: &mut ir::Function) -> ModuleResult<()> {
+        match self.constant_pool.take() {
+            Some(mut pool) => {
+                let res = pool.pool_function_constants(self, func);
+                self.constant_pool = Some(pool);
+                res
+            }
+            None => Ok(()),
+        }
+    }
+
     fn define_function_with_control_plane(
         &mut self,
         id: FuncId,
         ctx: &mut cranelift_codegen::Context,
         ctrl_plane: &mut ControlPlane,
-    ) -> ModuleResult<()> {
-        info!("defining function {}: {}", id, ctx.func.display());
-        let decl = self.declarations.get_function_decl(id);
+    ) -> ModuleResult<ModuleCompiledFunction> {
+        let isa = self.isa.clone();
+        self.define_function_for_isa(id, ctx, &*isa, ctrl_plane)
+    }
+
+    /// Defines every variant, then makes `func` resolve to the first variant supported by the
+    /// host, as detected by `cranelift_native`. The host doesn't change while the code runs, so
+    /// there is no resolver: calls and [`JITModule::get_finalized_function`] go straight to the
+    /// chosen variant, which [`JITModule::function_at`] then reports.
+    ///
+    /// This isn't supported with hotswapping, as the variants couldn't be redefined together.
+    fn define_function_multiversion(
+        &mut self,
+        func: FuncId,
+        ctx: &mut cranelift_codegen::Context,
+        variants: &[TargetVariant],
+    ) -> ModuleResult<Vec<FuncId>> {
+        let decl = self.declarations.get_function_decl(func);
         if !decl.linkage.is_definable() {
             return Err(ModuleError::InvalidImportDefinition(
-                decl.linkage_name(id).into_owned(),
+                decl.linkage_name(func).into_owned(),
             ));
         }
-
-        if !self.compiled_functions[id].is_none() {
+        if self.compiled_functions[func].is_some() || self.variant_functions[func].is_some() {
             return Err(ModuleError::DuplicateDefinition(
-                decl.linkage_name(id).into_owned(),
+                decl.linkage_name(func).into_owned(),
             ));
         }
-
         if self.hotswap_enabled {
-            // Disable colocated if hotswapping is enabled to avoid a PLT indirection in case of
-            // calls and to allow data objects to be hotswapped in the future.
-            for func in ctx.func.dfg.ext_funcs.values_mut() {
-                func.colocated = false;
-            }
-
-            for gv in ctx.func.global_values.values_mut() {
-                match gv {
-                    ir::GlobalValueData::Symbol { colocated, .. } => *colocated = false,
-                    _ => {}
-                }
-            }
+            return Err(ModuleError::Backend(anyhow::anyhow!(
+                "multi-versioned function {} can't be defined with hotswapping enabled",
+                decl.linkage_name(func)
+            )));
         }
 
-        // work around borrow-checker to allow reuse of ctx below
-        let res = ctx.compile(self.isa(), ctrl_plane)?;
-        let alignment = res.buffer.alignment as u64;
-        let compiled_code = ctx.compiled_code().unwrap();
-
-        let size = compiled_code.code_info().total_size as usize;
-        let align = alignment
-            .max(self.isa.function_alignment().minimum as u64)
-            .max(self.isa.symbol_alignment());
-        let ptr = self
-            .memory
-            .code
-            .allocate(size, align)
-            .map_err(|e| ModuleError::Allocation {
-                message: "unable to alloc function",
-                err: e,
+        let host = cranelift_native::builder()
+            .and_then(|builder| {
+                builder
+                    .finish(self.isa.flags().clone())
+                    .map_err(|_| "the host ISA can't be built")
+            })
+            .map_err(|msg| ModuleError::Backend(anyhow::anyhow!(msg)))?;
+        let chosen = variants
+            .iter()
+            .position(|variant| variant.is_supported_by(&*host))
+            .ok_or_else(|| {
+                ModuleError::Backend(anyhow::anyhow!(
+                    "no variant of {} is supported by the host",
+                    self.declarations.get_function_decl(func).linkage_name(func)
+                ))
             })?;
 
-        {
-            let mem = unsafe { std::slice::from_raw_parts_mut(ptr, size) };
-            mem.copy_from_slice(compiled_code.code_buffer());
+        let ids = cranelift_module::declare_function_variants(self, func, variants)?;
+        for (&id, variant) in ids.iter().zip(variants) {
+            self.define_function_for_isa(id, ctx, variant.isa(), &mut ControlPlane::default())?;
         }
-
-        let relocs = compiled_code
-            .buffer
-            .relocs()
-            .iter()
-            .map(|reloc| ModuleReloc::from_mach_reloc(reloc, &ctx.func))
-            .collect();
-
-        self.record_function_for_perf(ptr, size, &decl.linkage_name(id));
-        self.compiled_functions[id] = Some(CompiledBlob { ptr, size, relocs });
-
+        let variant = ids[chosen];
+        self.variant_functions[func] = Some(variant);
         if self.isa.flags().is_pic() {
             self.pending_got_updates.push(GotUpdate {
-                entry: self.function_got_entries[id].unwrap(),
-                ptr,
+                entry: self.function_got_entries[func].unwrap(),
+                ptr: self.compiled_functions[variant].as_ref().unwrap().ptr,
             })
         }
-
-        if self.hotswap_enabled {
-            self.compiled_functions[id]
-                .as_ref()
-                .unwrap()
-                .perform_relocations(
-                    |name| match *name {
-                        ModuleExtName::User { .. } => {
-                            unreachable!("non GOT or PLT relocation in function {} to {}", id, name)
-                        }
-                        ModuleExtName::LibCall(ref libcall) => self
-                            .libcall_plt_entries
-                            .get(libcall)
-                            .unwrap_or_else(|| panic!("can't resolve libcall {}", libcall))
-                            .as_ptr()
-                            .cast::<u8>(),
-                        _ => panic!("invalid name"),
-                    },
-                    |name| self.get_got_address(name).as_ptr().cast(),
-                    |name| self.get_plt_address(name),
-                );
-        } else {
-            self.functions_to_finalize.push(id);
-        }
-
-        Ok(())
+        Ok(ids)
     }
 
     fn define_function_bytes(
@@ -759,19 +1586,10 @@ impl Module for JITModule {
         alignment: u64,
         bytes: &[u8],
         relocs: &[MachReloc],
-    ) -> ModuleResult<()> {
+    ) -> ModuleResult<ModuleCompiledFunction> {
         info!("defining function {} with bytes", id);
-        let decl = self.declarations.get_function_decl(id);
-        if !decl.linkage.is_definable() {
-            return Err(ModuleError::InvalidImportDefinition(
-                decl.linkage_name(id).into_owned(),
-            ));
-        }
-
-        if !self.compiled_functions[id].is_none() {
-            return Err(ModuleError::DuplicateDefinition(
-                decl.linkage_name(id).into_owned(),
-            ));
+        if self.check_definable(id)? {
+            self.remove_definition(id);
         }
 
         let size = bytes.len();
@@ -791,14 +1609,21 @@ impl Module for JITModule {
             ptr::copy_nonoverlapping(bytes.as_ptr(), ptr, size);
         }
 
+        let decl = self.declarations.get_function_decl(id);
         self.record_function_for_perf(ptr, size, &decl.linkage_name(id));
+        let relocs: Vec<_> = relocs
+            .iter()
+            .map(|reloc| ModuleReloc::from_mach_reloc(reloc, func))
+            .collect();
+        self.record_libcalls(&relocs);
+        if self.hotswap_enabled {
+            self.check_libcalls(&relocs)?;
+        }
         self.compiled_functions[id] = Some(CompiledBlob {
             ptr,
             size,
-            relocs: relocs
-                .iter()
-                .map(|reloc| ModuleReloc::from_mach_reloc(reloc, func))
-                .collect(),
+            align,
+            relocs,
         });
 
         if self.isa.flags().is_pic() {
@@ -821,7 +1646,9 @@ impl Module for JITModule {
             self.functions_to_finalize.push(id);
         }
 
-        Ok(())
+        Ok(ModuleCompiledFunction {
+            size: size as CodeOffset,
+        })
     }
 
     fn define_data(&mut self, id: DataId, data: &DataDescription) -> ModuleResult<()> {
@@ -851,10 +1678,15 @@ impl Module for JITModule {
         } = data;
 
         let size = init.size();
+        let align = align.unwrap_or(if decl.writable {
+            WRITABLE_DATA_ALIGNMENT
+        } else {
+            READONLY_DATA_ALIGNMENT
+        });
         let ptr = if decl.writable {
             self.memory
                 .writable
-                .allocate(size, align.unwrap_or(WRITABLE_DATA_ALIGNMENT))
+                .allocate(size, align)
                 .map_err(|e| ModuleError::Allocation {
                     message: "unable to alloc writable data",
                     err: e,
@@ -862,7 +1694,7 @@ impl Module for JITModule {
         } else {
             self.memory
                 .readonly
-                .allocate(size, align.unwrap_or(READONLY_DATA_ALIGNMENT))
+                .allocate(size, align)
                 .map_err(|e| ModuleError::Allocation {
                     message: "unable to alloc readonly data",
                     err: e,
@@ -889,7 +1721,12 @@ impl Module for JITModule {
         };
         let relocs = data.all_relocs(pointer_reloc).collect::<Vec<_>>();
 
-        self.compiled_data_objects[id] = Some(CompiledBlob { ptr, size, relocs });
+        self.compiled_data_objects[id] = Some(CompiledBlob {
+            ptr,
+            size,
+            align,
+            relocs,
+        });
         self.data_objects_to_finalize.push(id);
         if self.isa.flags().is_pic() {
             self.pending_got_updates.push(GotUpdate {
@@ -0,0 +1,160 @@
+//! Defining functions which call each other together.
+//!
+//! Recursive and mutually recursive functions must all be declared before any of them is built,
+//! and all be defined before the module is finalized. [`JITModule::define_batch`] takes care of
+//! the last two steps: the functions given to a [`JITBatch`] are checked to only call functions
+//! which will be available once they are all defined, then defined and finalized together.
+
+use cranelift_codegen::ir::{self, ExternalName};
+use cranelift_codegen::Context;
+use cranelift_module::{FuncId, Linkage, Module, ModuleError};
+use std::fmt;
+
+use crate::JITModule;
+
+/// The functions to define together, collected by the closure given to
+/// [`JITModule::define_batch`].
+pub struct JITBatch<'a> {
+    module: &'a mut JITModule,
+    functions: Vec<(FuncId, ir::Function)>,
+}
+
+/// A call from a function of a batch to a function which is neither defined in the module nor
+/// in the batch, nor imported.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnresolvedReference {
+    /// The name of the calling function, which is part of the batch.
+    pub caller: String,
+    /// The name of the called function.
+    pub callee: String,
+}
+
+/// An error defining a batch of functions.
+#[derive(Debug)]
+pub enum BatchError {
+    /// Functions of the batch call functions which wouldn't have a definition. Nothing was
+    /// defined.
+    Unresolved(Vec<UnresolvedReference>),
+    /// Defining or finalizing the functions failed.
+    Module(ModuleError),
+}
+
+impl From<ModuleError> for BatchError {
+    fn from(err: ModuleError) -> Self {
+        Self::Module(err)
+    }
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Unresolved(references) => {
+                write!(f, "the batch calls undefined functions:")?;
+                for (i, reference) in references.iter().enumerate() {
+                    let separator = if i == 0 { " " } else { ", " };
+                    write!(
+                        f,
+                        "{}{} calls {}",
+                        separator, reference.caller, reference.callee
+                    )?;
+                }
+                Ok(())
+            }
+            Self::Module(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for BatchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Unresolved(_) => None,
+            Self::Module(err) => Some(err),
+        }
+    }
+}
+
+impl<'a> JITBatch<'a> {
+    pub(crate) fn new(module: &'a mut JITModule) -> Self {
+        Self {
+            module,
+            functions: Vec::new(),
+        }
+    }
+
+    /// The module the batch defines functions of.
+    pub fn module(&self) -> &JITModule {
+        self.module
+    }
+
+    /// Add `func` to the batch, as the definition of the function `id`.
+    pub fn define(&mut self, id: FuncId, func: ir::Function) {
+        self.functions.push((id, func));
+    }
+
+    /// Declare the function `id` in `func`, so that `func` can call it, like
+    /// [`Module::declare_func_in_func`].
+    pub fn declare_func_in_func(&mut self, id: FuncId, func: &mut ir::Function) -> ir::FuncRef {
+        self.module.declare_func_in_func(id, func)
+    }
+
+    /// Check the functions of the batch, then define and finalize them.
+    pub(crate) fn finish(self) -> Result<(), BatchError> {
+        let module = self.module;
+        let name = |id: FuncId| {
+            module
+                .declarations()
+                .get_function_decl(id)
+                .linkage_name(id)
+                .into_owned()
+        };
+        let in_batch = |id: FuncId| self.functions.iter().any(|&(defined, _)| defined == id);
+
+        for (i, &(id, _)) in self.functions.iter().enumerate() {
+            let decl = module.declarations().get_function_decl(id);
+            if !decl.linkage.is_definable() {
+                return Err(ModuleError::InvalidImportDefinition(name(id)).into());
+            }
+            if module.is_defined(id) || self.functions[..i].iter().any(|&(other, _)| other == id) {
+                return Err(ModuleError::DuplicateDefinition(name(id)).into());
+            }
+        }
+
+        let mut unresolved = Vec::new();
+        for (id, func) in &self.functions {
+            for ext_func in func.dfg.ext_funcs.values() {
+                let callee = match ext_func.name {
+                    ExternalName::User(name_ref) => &func.params.user_named_funcs()[name_ref],
+                    _ => continue,
+                };
+                if callee.namespace != 0 {
+                    continue;
+                }
+                let callee = FuncId::from_u32(callee.index);
+                let linkage = module.declarations().get_function_decl(callee).linkage;
+                if linkage == Linkage::Import || module.is_defined(callee) || in_batch(callee) {
+                    continue;
+                }
+                let reference = UnresolvedReference {
+                    caller: name(*id),
+                    callee: name(callee),
+                };
+                if !unresolved.contains(&reference) {
+                    unresolved.push(reference);
+                }
+            }
+        }
+        if !unresolved.is_empty() {
+            return Err(BatchError::Unresolved(unresolved));
+        }
+
+        let mut ctx = Context::new();
+        for (id, func) in self.functions {
+            ctx.func = func;
+            module.define_function(id, &mut ctx)?;
+            ctx.clear();
+        }
+        module.finalize_definitions()?;
+        Ok(())
+    }
+}
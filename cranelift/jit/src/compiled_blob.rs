@@ -15,6 +15,8 @@ unsafe fn modify_inst32(iptr: *mut u32, modifier: impl FnOnce(u32) -> u32) {
 pub(crate) struct CompiledBlob {
     pub(crate) ptr: *mut u8,
     pub(crate) size: usize,
+    /// The alignment `ptr` was allocated with.
+    pub(crate) align: u64,
     pub(crate) relocs: Vec<ModuleReloc>,
 }
 
@@ -147,4 +149,49 @@ impl CompiledBlob {
             }
         }
     }
+
+    /// The GOT entries referenced by the relocations, each once, in the order they are first
+    /// referenced.
+    pub(crate) fn got_entries(
+        &self,
+        get_got_entry: impl Fn(&ModuleExtName) -> *const *const u8,
+    ) -> Vec<*const *const u8> {
+        let mut entries = Vec::new();
+        for reloc in &self.relocs {
+            let entry = get_got_entry(&reloc.name);
+            if !entries.contains(&entry) {
+                entries.push(entry);
+            }
+        }
+        entries
+    }
+
+    /// Copy the code to `dest`, making it use the GOT at `got_base`, in which `got_index` gives
+    /// the index of the entry of each name.
+    ///
+    /// All the relocations must be of the GOT.
+    pub(crate) unsafe fn copy_to(
+        &self,
+        dest: *mut u8,
+        got_base: *mut u8,
+        got_index: impl Fn(&ModuleExtName) -> usize,
+    ) {
+        std::ptr::copy_nonoverlapping(self.ptr, dest, self.size);
+        for &ModuleReloc {
+            kind,
+            offset,
+            ref name,
+            addend,
+        } in &self.relocs
+        {
+            assert_eq!(kind, Reloc::X86GOTPCRel4);
+            let at = dest.offset(isize::try_from(offset).unwrap());
+            let entry = got_base.add(got_index(name) * std::mem::size_of::<*const u8>());
+            let what = entry.offset(isize::try_from(addend).unwrap());
+            let pcrel = i32::try_from((what as isize) - (at as isize))
+                .expect("the GOT of the copy is too far from its code");
+            #[cfg_attr(feature = "cargo-clippy", allow(clippy::cast_ptr_alignment))]
+            std::ptr::write_unaligned(at as *mut i32, pcrel);
+        }
+    }
 }
@@ -0,0 +1,359 @@
+//! Bounds-checked, growable heaps for language runtimes built on the JIT.
+//!
+//! A [`Heap`] is a region of linear memory, like a WebAssembly memory, which JIT-compiled code
+//! accesses through a base address and a bound, and which the host can grow up to a maximum size
+//! and look at. The maximum size, followed by a guard region, is reserved when the heap is
+//! created, so the base address never moves: growing the heap only makes more of the reserved
+//! pages accessible. Accessing any other reserved page faults.
+//!
+//! Functions use a heap through the global values returned by [`Heap::declare_heap_in_func`],
+//! and compute the addresses of their accesses with [`HeapGlobals::addr`], which emits the
+//! bounds checks. Out-of-bounds accesses trap with [`TrapCode::HeapOutOfBounds`], either from an
+//! explicit check or from a fault on an inaccessible page; the latter are only turned into traps
+//! by [`signals::catch_traps`](crate::signals::catch_traps) or a similar handler.
+
+use cranelift_codegen::cursor::FuncCursor;
+use cranelift_codegen::ir::{
+    self, GlobalValue, GlobalValueData, InstBuilder, MemFlags, TrapCode, Value,
+};
+use cranelift_module::{DataId, Module, ModuleError, ModuleResult};
+use std::io;
+use std::ptr;
+
+use crate::JITModule;
+
+/// The sizes of a [`Heap`], in bytes. They are rounded up to the host page size.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeapConfig {
+    /// The initial length of the heap.
+    pub initial: usize,
+    /// The length beyond which the heap can't grow.
+    pub maximum: usize,
+    /// The size of the inaccessible region after the maximum length, which lets accesses whose
+    /// offset and size fit in it skip the check against the current length.
+    pub guard: usize,
+}
+
+/// The state of a heap read by JIT-compiled code.
+#[repr(C)]
+struct HeapDefinition {
+    base: *mut u8,
+    length: usize,
+}
+
+/// A heap created by [`JITModule::create_heap`].
+///
+/// Dropping the heap releases its memory, so it must outlive the execution of the code using it.
+pub struct Heap {
+    /// Boxed so that its address, which the code of the module refers to, doesn't change.
+    definition: Box<HeapDefinition>,
+    data_id: DataId,
+    maximum: usize,
+    guard: usize,
+}
+
+impl Heap {
+    /// Reserve the memory of a heap configured by `config`, and make its definition available
+    /// to the code of a module through the data object returned by `declare`, which is given the
+    /// address of the definition.
+    pub(crate) fn new(
+        config: HeapConfig,
+        declare: impl FnOnce(*const u8) -> ModuleResult<DataId>,
+    ) -> ModuleResult<Self> {
+        let initial = region::page::ceil(config.initial);
+        let maximum = region::page::ceil(config.maximum);
+        let guard = region::page::ceil(config.guard);
+        if initial > maximum {
+            return Err(ModuleError::Backend(anyhow::anyhow!(
+                "the initial length of a heap, {:#x}, exceeds its maximum length, {:#x}",
+                initial,
+                maximum
+            )));
+        }
+        let reserved = maximum.checked_add(guard).ok_or_else(|| {
+            ModuleError::Backend(anyhow::anyhow!("heap reservation size overflows"))
+        })?;
+
+        let base = unsafe { sys::reserve(reserved) }.map_err(|err| ModuleError::Allocation {
+            message: "unable to reserve heap memory",
+            err,
+        })?;
+        let mut heap = Self {
+            definition: Box::new(HeapDefinition { base, length: 0 }),
+            data_id: DataId::from_u32(0),
+            maximum,
+            guard,
+        };
+        heap.grow(initial)?;
+        heap.data_id = declare(&*heap.definition as *const HeapDefinition as *const u8)?;
+        Ok(heap)
+    }
+
+    /// The data object holding the base address and current length of the heap, both pointer
+    /// sized, in that order.
+    pub fn data_id(&self) -> DataId {
+        self.data_id
+    }
+
+    /// The current length of the heap, in bytes.
+    pub fn length(&self) -> usize {
+        self.definition.length
+    }
+
+    /// The length beyond which the heap can't grow, in bytes.
+    pub fn maximum(&self) -> usize {
+        self.maximum
+    }
+
+    /// The base address of the heap, which doesn't change when it grows.
+    pub fn base(&self) -> *mut u8 {
+        self.definition.base
+    }
+
+    /// Grow the heap by `delta` bytes, rounded up to the host page size, and return its previous
+    /// length. The heap can't grow beyond its maximum length.
+    ///
+    /// The new bytes are zeroed. Code which runs afterwards sees the new length.
+    pub fn grow(&mut self, delta: usize) -> ModuleResult<usize> {
+        let old_length = self.definition.length;
+        let delta = region::page::ceil(delta);
+        let new_length = match old_length.checked_add(delta) {
+            Some(new_length) if new_length <= self.maximum => new_length,
+            _ => {
+                return Err(ModuleError::Backend(anyhow::anyhow!(
+                    "can't grow a heap of {:#x} bytes by {:#x} beyond its maximum of {:#x}",
+                    old_length,
+                    delta,
+                    self.maximum
+                )))
+            }
+        };
+        if delta != 0 {
+            unsafe { sys::commit(self.definition.base.add(old_length), delta) }.map_err(|err| {
+                ModuleError::Allocation {
+                    message: "unable to commit heap memory",
+                    err,
+                }
+            })?;
+        }
+        self.definition.length = new_length;
+        Ok(old_length)
+    }
+
+    /// The accessible bytes of the heap.
+    pub fn view(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.definition.base, self.definition.length) }
+    }
+
+    /// The accessible bytes of the heap, mutably.
+    pub fn view_mut(&mut self) -> &mut [u8] {
+        unsafe { std::slice::from_raw_parts_mut(self.definition.base, self.definition.length) }
+    }
+
+    /// Declare the global values for the base address and the bound of the heap in `func`, which
+    /// is to be defined in `module`.
+    pub fn declare_heap_in_func(&self, module: &JITModule, func: &mut ir::Function) -> HeapGlobals {
+        let pointer_type = module.isa().pointer_type();
+        let definition = module.declare_data_in_func(self.data_id, func);
+        // The base never changes, unlike the length.
+        let base = func.create_global_value(GlobalValueData::Load {
+            base: definition,
+            offset: 0.into(),
+            global_type: pointer_type,
+            readonly: true,
+        });
+        let bound = func.create_global_value(GlobalValueData::Load {
+            base: definition,
+            offset: (pointer_type.bytes() as i32).into(),
+            global_type: pointer_type,
+            readonly: false,
+        });
+        HeapGlobals {
+            base,
+            bound,
+            pointer_type,
+            maximum: self.maximum as u64,
+            guard: self.guard as u64,
+        }
+    }
+}
+
+impl Drop for Heap {
+    fn drop(&mut self) {
+        unsafe { sys::release(self.definition.base, self.maximum + self.guard) };
+    }
+}
+
+/// The global values of a [`Heap`] declared in a function, and the emission of the bounds checks
+/// of its accesses.
+#[derive(Clone, Copy, Debug)]
+pub struct HeapGlobals {
+    /// The base address of the heap.
+    pub base: GlobalValue,
+    /// The current length of the heap in bytes.
+    pub bound: GlobalValue,
+    pointer_type: ir::Type,
+    maximum: u64,
+    guard: u64,
+}
+
+impl HeapGlobals {
+    /// The flags of loads and stores of the heap.
+    ///
+    /// They may trap, since the accesses relying on the guard region fault when out of bounds.
+    pub fn flags() -> MemFlags {
+        MemFlags::new().with_heap()
+    }
+
+    /// Emit, at `pos`, the computation of the address of an access of `size` bytes at `offset`
+    /// past `index`, an unsigned integer of any type up to the pointer type. The access itself
+    /// uses [`HeapGlobals::flags`].
+    ///
+    /// If `offset + size` fits in the guard region, the heap relies on it: only `index` is
+    /// checked, against the maximum length, and only when its type can exceed it. For a 32-bit
+    /// index on a 64-bit target:
+    ///
+    /// ```text
+    /// v1 = uextend.i64 v0
+    /// v2 = icmp_imm ugt v1, <maximum>
+    /// trapnz v2, heap_oob
+    /// v3 = global_value.i64 base
+    /// v4 = iadd v3, v1
+    /// v5 = iadd_imm v4, <offset>
+    /// ```
+    ///
+    /// Otherwise, the end of the access is checked against the current length:
+    ///
+    /// ```text
+    /// v1 = uextend.i64 v0
+    /// v2 = uadd_overflow_trap v1, <offset + size>, heap_oob
+    /// v3 = global_value.i64 bound
+    /// v4 = icmp ugt v2, v3
+    /// trapnz v4, heap_oob
+    /// v5 = global_value.i64 base
+    /// v6 = iadd v5, v1
+    /// v7 = iadd_imm v6, <offset>
+    /// ```
+    ///
+    /// With a `FunctionBuilder`, use its `cursor()` as `pos`.
+    pub fn addr(&self, pos: &mut FuncCursor, index: Value, offset: u32, size: u32) -> Value {
+        let index_type = pos.func.dfg.value_type(index);
+        assert!(index_type.is_int() && index_type.bits() <= self.pointer_type.bits());
+        let index = if index_type == self.pointer_type {
+            index
+        } else {
+            pos.ins().uextend(self.pointer_type, index)
+        };
+
+        let end = u64::from(offset) + u64::from(size);
+        if end <= self.guard {
+            // Any `index` up to the maximum length keeps the access within the reservation.
+            let index_max = u64::MAX >> (64 - index_type.bits());
+            if index_max > self.maximum {
+                let out_of_bounds = pos.ins().icmp_imm(
+                    ir::condcodes::IntCC::UnsignedGreaterThan,
+                    index,
+                    self.maximum as i64,
+                );
+                pos.ins().trapnz(out_of_bounds, TrapCode::HeapOutOfBounds);
+            }
+        } else {
+            let end = pos.ins().iconst(self.pointer_type, end as i64);
+            let end = pos
+                .ins()
+                .uadd_overflow_trap(index, end, TrapCode::HeapOutOfBounds);
+            let bound = pos.ins().global_value(self.pointer_type, self.bound);
+            let out_of_bounds =
+                pos.ins()
+                    .icmp(ir::condcodes::IntCC::UnsignedGreaterThan, end, bound);
+            pos.ins().trapnz(out_of_bounds, TrapCode::HeapOutOfBounds);
+        }
+
+        let base = pos.ins().global_value(self.pointer_type, self.base);
+        let addr = pos.ins().iadd(base, index);
+        if offset == 0 {
+            addr
+        } else {
+            pos.ins().iadd_imm(addr, i64::from(offset))
+        }
+    }
+}
+
+#[cfg(unix)]
+mod sys {
+    use super::*;
+
+    /// Reserve `size` inaccessible bytes.
+    pub(super) unsafe fn reserve(size: usize) -> io::Result<*mut u8> {
+        if size == 0 {
+            return Ok(ptr::NonNull::dangling().as_ptr());
+        }
+        let ptr = libc::mmap(
+            ptr::null_mut(),
+            size,
+            libc::PROT_NONE,
+            libc::MAP_PRIVATE | libc::MAP_ANON,
+            -1,
+            0,
+        );
+        if ptr == libc::MAP_FAILED {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(ptr.cast())
+        }
+    }
+
+    /// Make the `len` reserved bytes at `ptr` readable and writable.
+    pub(super) unsafe fn commit(ptr: *mut u8, len: usize) -> io::Result<()> {
+        if libc::mprotect(ptr.cast(), len, libc::PROT_READ | libc::PROT_WRITE) == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    /// Release the `size` bytes reserved at `ptr`.
+    pub(super) unsafe fn release(ptr: *mut u8, size: usize) {
+        if size != 0 {
+            libc::munmap(ptr.cast(), size);
+        }
+    }
+}
+
+#[cfg(windows)]
+mod sys {
+    use super::*;
+    use windows_sys::Win32::System::Memory::{
+        VirtualAlloc, VirtualFree, MEM_COMMIT, MEM_RELEASE, MEM_RESERVE, PAGE_NOACCESS,
+        PAGE_READWRITE,
+    };
+
+    /// Reserve `size` inaccessible bytes.
+    pub(super) unsafe fn reserve(size: usize) -> io::Result<*mut u8> {
+        if size == 0 {
+            return Ok(ptr::NonNull::dangling().as_ptr());
+        }
+        let ptr = VirtualAlloc(ptr::null_mut(), size, MEM_RESERVE, PAGE_NOACCESS);
+        if ptr.is_null() {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(ptr.cast())
+        }
+    }
+
+    /// Make the `len` reserved bytes at `ptr` readable and writable.
+    pub(super) unsafe fn commit(ptr: *mut u8, len: usize) -> io::Result<()> {
+        if VirtualAlloc(ptr.cast(), len, MEM_COMMIT, PAGE_READWRITE).is_null() {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Release the `size` bytes reserved at `ptr`.
+    pub(super) unsafe fn release(ptr: *mut u8, size: usize) {
+        if size != 0 {
+            VirtualFree(ptr.cast(), 0, MEM_RELEASE);
+        }
+    }
+}
@@ -0,0 +1,51 @@
+// Non-local jumps used by `cranelift_jit::signals` to unwind from a signal
+// handler back to the `catch_traps` call which ran the trapping code.
+
+#include <setjmp.h>
+
+#ifdef CFG_TARGET_OS_windows
+
+#define platform_setjmp(buf) setjmp(buf)
+#define platform_longjmp(buf, arg) longjmp(buf, arg)
+typedef jmp_buf platform_jmp_buf;
+
+#elif defined(__clang__) && (defined(__aarch64__) || defined(__s390x__))
+
+// Clang on aarch64 and s390x doesn't support `__builtin_setjmp`, so use
+// `sigsetjmp` from libc, without preserving the signal mask.
+#define platform_setjmp(buf) sigsetjmp(buf, 0)
+#define platform_longjmp(buf, arg) siglongjmp(buf, arg)
+typedef sigjmp_buf platform_jmp_buf;
+
+#else
+
+// `__builtin_setjmp` is implemented inline by the compiler and only saves the
+// registers it needs to, which makes it much cheaper than libc's `setjmp`.
+// This assumes the host compiler knows about all the registers Cranelift
+// treats as callee-saved.
+#define platform_setjmp(buf) __builtin_setjmp(buf)
+#define platform_longjmp(buf, arg) __builtin_longjmp(buf, arg)
+typedef void *platform_jmp_buf[5]; // this is the documented size
+
+#endif
+
+// Call `body(payload)` and return 1, unless `cranelift_jit_longjmp` is called
+// with the jump buffer stored in `*buf_storage` meanwhile, in which case 0 is
+// returned.
+int cranelift_jit_setjmp(
+    void **buf_storage,
+    void (*body)(void*),
+    void *payload) {
+  platform_jmp_buf buf;
+  if (platform_setjmp(buf) != 0) {
+    return 0;
+  }
+  *buf_storage = &buf;
+  body(payload);
+  return 1;
+}
+
+void cranelift_jit_longjmp(void *jmp_buf) {
+  platform_jmp_buf *buf = (platform_jmp_buf*) jmp_buf;
+  platform_longjmp(*buf, 1);
+}
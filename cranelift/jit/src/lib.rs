@@ -27,10 +27,28 @@
 )]
 
 mod backend;
+mod batch;
 mod compiled_blob;
+mod heap;
 mod memory;
+mod patching;
+mod profiling;
+#[cfg(feature = "signal-handlers")]
+pub mod signals;
+mod stack_map;
+pub mod trampoline;
+mod traps;
+mod typed;
+mod unwind;
 
-pub use crate::backend::{JITBuilder, JITModule};
+pub use crate::backend::{FinalizedFuncInfo, JITBuilder, JITModule, PicFixups};
+pub use crate::batch::{BatchError, JITBatch, UnresolvedReference};
+pub use crate::heap::{Heap, HeapConfig, HeapGlobals};
+pub use crate::patching::CodePatcher;
+pub use crate::profiling::ProfilingStrategy;
+pub use crate::stack_map::UserStackMapView;
+pub use crate::typed::{JitFn, JitValue, OwnedJitFn, SignatureMismatch};
+pub use crate::unwind::JitFrame;
 
 /// Version number of this crate.
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -113,7 +113,7 @@ impl Drop for PtrLen {
 // TODO: add a `Drop` impl for `cfg(target_os = "windows")`
 
 /// Type of branch protection to apply to executable memory.
-#[derive(Clone, Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub(crate) enum BranchProtection {
     /// No protection.
     None,
@@ -121,12 +121,61 @@ pub(crate) enum BranchProtection {
     BTI,
 }
 
+/// Make the `len` bytes of code at `ptr` readable and executable, and also writable if `writable`
+/// is set.
+pub(crate) fn protect_code(
+    ptr: *mut u8,
+    len: usize,
+    branch_protection: BranchProtection,
+    writable: bool,
+) -> ModuleResult<()> {
+    let context = if writable {
+        "unable to make memory readable+writable+executable"
+    } else {
+        "unable to make memory readable+executable"
+    };
+
+    if branch_protection == BranchProtection::BTI {
+        #[cfg(all(target_arch = "aarch64", target_os = "linux"))]
+        if std::arch::is_aarch64_feature_detected!("bti") {
+            let mut prot = libc::PROT_EXEC | libc::PROT_READ | /* PROT_BTI */ 0x10;
+            if writable {
+                prot |= libc::PROT_WRITE;
+            }
+
+            unsafe {
+                if libc::mprotect(ptr as *mut libc::c_void, len, prot) < 0 {
+                    return Err(ModuleError::Backend(
+                        anyhow::Error::new(io::Error::last_os_error()).context(context),
+                    ));
+                }
+            }
+
+            return Ok(());
+        }
+    }
+
+    let protection = if writable {
+        region::Protection::READ_WRITE_EXECUTE
+    } else {
+        region::Protection::READ_EXECUTE
+    };
+    unsafe {
+        region::protect(ptr, len, protection)
+            .map_err(|e| ModuleError::Backend(anyhow::Error::new(e).context(context)))?;
+    }
+    Ok(())
+}
+
 /// JIT memory manager. This manages pages of suitably aligned and
 /// accessible memory. Memory will be leaked by default to have
 /// function pointers remain valid for the remainder of the
 /// program's life.
 pub(crate) struct Memory {
     allocations: Vec<PtrLen>,
+    /// Allocations made before the last reset, which are readable and writable again and reused
+    /// before allocating more memory.
+    free: Vec<PtrLen>,
     already_protected: usize,
     current: PtrLen,
     position: usize,
@@ -137,6 +186,7 @@ impl Memory {
     pub(crate) fn new(branch_protection: BranchProtection) -> Self {
         Self {
             allocations: Vec::new(),
+            free: Vec::new(),
             already_protected: 0,
             current: PtrLen::new(),
             position: 0,
@@ -144,6 +194,10 @@ impl Memory {
         }
     }
 
+    pub(crate) fn branch_protection(&self) -> BranchProtection {
+        self.branch_protection
+    }
+
     fn finish_current(&mut self) {
         self.allocations
             .push(mem::replace(&mut self.current, PtrLen::new()));
@@ -167,7 +221,10 @@ impl Memory {
         self.finish_current();
 
         // TODO: Allocate more at a time.
-        self.current = PtrLen::with_size(size)?;
+        self.current = match self.free.iter().position(|free| free.len >= size) {
+            Some(i) => self.free.swap_remove(i),
+            None => PtrLen::with_size(size)?,
+        };
         self.position = size;
 
         Ok(self.current.ptr)
@@ -188,37 +245,8 @@ impl Memory {
             };
         }
 
-        let set_region_readable_and_executable = |ptr, len| -> ModuleResult<()> {
-            if self.branch_protection == BranchProtection::BTI {
-                #[cfg(all(target_arch = "aarch64", target_os = "linux"))]
-                if std::arch::is_aarch64_feature_detected!("bti") {
-                    let prot = libc::PROT_EXEC | libc::PROT_READ | /* PROT_BTI */ 0x10;
-
-                    unsafe {
-                        if libc::mprotect(ptr as *mut libc::c_void, len, prot) < 0 {
-                            return Err(ModuleError::Backend(
-                                anyhow::Error::new(io::Error::last_os_error())
-                                    .context("unable to make memory readable+executable"),
-                            ));
-                        }
-                    }
-
-                    return Ok(());
-                }
-            }
-
-            unsafe {
-                region::protect(ptr, len, region::Protection::READ_EXECUTE).map_err(|e| {
-                    ModuleError::Backend(
-                        anyhow::Error::new(e).context("unable to make memory readable+executable"),
-                    )
-                })?;
-            }
-            Ok(())
-        };
-
         for &PtrLen { ptr, len, .. } in self.non_protected_allocations_iter() {
-            set_region_readable_and_executable(ptr, len)?;
+            protect_code(ptr, len, self.branch_protection, false)?;
         }
 
         // Flush any in-flight instructions from the pipeline
@@ -257,10 +285,61 @@ impl Memory {
         return iter.filter(|&PtrLen { len, .. }| *len != 0);
     }
 
+    /// The number of bytes of all allocated memory regions, including the ones kept for reuse.
+    pub(crate) fn allocated_bytes(&self) -> usize {
+        self.allocations
+            .iter()
+            .chain(&self.free)
+            .chain([&self.current])
+            .map(|allocation| allocation.len)
+            .sum()
+    }
+
+    /// Makes all allocated memory regions readable and writable again, to prepare reusing them
+    /// with [`Memory::recycle`].
+    ///
+    /// If this fails, all regions stay allocated. The ones already made writable are protected
+    /// again by the next call to [`Memory::set_readable_and_executable`] or
+    /// [`Memory::set_readonly`].
+    pub(crate) unsafe fn make_writable(&mut self) -> ModuleResult<()> {
+        self.finish_current();
+        self.already_protected = 0;
+        for allocation in &self.allocations {
+            if allocation.len == 0 {
+                continue;
+            }
+            region::protect(
+                allocation.ptr,
+                allocation.len,
+                region::Protection::READ_WRITE,
+            )
+            .map_err(|e| {
+                ModuleError::Backend(
+                    anyhow::Error::new(e).context("unable to make memory writable"),
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Reuses all allocated memory regions, which [`Memory::make_writable`] made writable, for
+    /// the following allocations. Invalidates existing pointers into the memory, like
+    /// [`Memory::free_memory`].
+    pub(crate) unsafe fn recycle(&mut self) {
+        self.finish_current();
+        self.free.extend(
+            self.allocations
+                .drain(..)
+                .filter(|allocation| allocation.len != 0),
+        );
+        self.already_protected = 0;
+    }
+
     /// Frees all allocated memory regions that would be leaked otherwise.
     /// Likely to invalidate existing function pointers, causing unsafety.
     pub(crate) unsafe fn free_memory(&mut self) {
         self.allocations.clear();
+        self.free.clear();
         self.already_protected = 0;
     }
 }
@@ -273,3 +352,37 @@ impl Drop for Memory {
             .for_each(mem::forget);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(all(target_os = "linux", not(feature = "selinux-fix")))]
+    fn failed_make_writable_keeps_allocations() {
+        let mut memory = Memory::new(BranchProtection::None);
+        memory.allocate(16, 16).unwrap();
+        memory.set_readonly().unwrap();
+
+        // Linux never maps the lowest pages of the address space, so protecting this fails.
+        let page_size = region::page::size();
+        memory.allocations.push(PtrLen {
+            ptr: page_size as *mut u8,
+            len: page_size,
+        });
+        let bytes = memory.allocated_bytes();
+        assert!(unsafe { memory.make_writable() }.is_err());
+        assert_eq!(memory.allocated_bytes(), bytes);
+
+        // The unmapped region was never allocated, so it must not be deallocated either.
+        let unmapped = memory
+            .allocations
+            .iter()
+            .position(|allocation| allocation.ptr == page_size as *mut u8)
+            .unwrap();
+        mem::forget(memory.allocations.remove(unmapped));
+        unsafe { memory.recycle() };
+        assert_eq!(memory.allocated_bytes(), bytes - page_size);
+        assert!(memory.allocations.is_empty());
+    }
+}
@@ -0,0 +1,162 @@
+//! Retargeting `patchable_call`s and `patchable_jump`s of JIT-compiled code while it runs.
+
+use crate::memory::{protect_code, BranchProtection};
+use cranelift_module::{ModuleError, ModuleResult};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use wasmtime_jit_icache_coherence as icache_coherence;
+
+/// Serializes all patching, so that two patchers never change the protection of the same page
+/// at once.
+static PATCH_LOCK: Mutex<()> = Mutex::new(());
+
+/// Rewrites the patch points of the `patchable_call`s and `patchable_jump`s in finalized
+/// functions of a `JITModule`, while other threads may be executing them.
+///
+/// Every patch point is a naturally aligned 32-bit word which the backend placed such that
+/// replacing it with a single atomic store leaves a valid instruction sequence: on x86_64 it is
+/// the displacement of a `call` or `jmp` instruction, and on aarch64 it is a whole `bl` or `b`
+/// instruction. Threads executing the call or jump concurrently with a patch either go to the old
+/// target or the new one, never anything else.
+///
+/// A `CodePatcher` is a snapshot of the patch points of the functions defined when it was
+/// created, see [`JITModule::code_patcher`](crate::JITModule::code_patcher).
+///
+/// Calls can be retargeted to any function with the right signature, while jumps can only be
+/// switched between the two destinations they were compiled with, see
+/// [`CodePatcher::retarget_jump`].
+pub struct CodePatcher {
+    patch_points: BTreeSet<usize>,
+    jump_destinations: BTreeMap<usize, [usize; 2]>,
+    branch_protection: BranchProtection,
+}
+
+impl CodePatcher {
+    pub(crate) fn new(
+        patch_points: BTreeSet<usize>,
+        jump_destinations: BTreeMap<usize, [usize; 2]>,
+        branch_protection: BranchProtection,
+    ) -> Self {
+        Self {
+            patch_points,
+            jump_destinations,
+            branch_protection,
+        }
+    }
+
+    /// Returns whether `addr` is a patch point known to this patcher.
+    pub fn is_patch_point(&self, addr: *const u8) -> bool {
+        self.patch_points.contains(&(addr as usize))
+    }
+
+    /// Atomically replace the 32-bit word at the patch point `addr` with `new_bytes`.
+    ///
+    /// On aarch64 this also makes every thread discard instructions it may have fetched from
+    /// the old word before returning, so the new word is observed by all calls starting after
+    /// this returns.
+    ///
+    /// # Safety
+    ///
+    /// The function containing `addr` must be finalized and not freed, and `new_bytes` must keep
+    /// the code valid, see [`CodePatcher::retarget_call`] and [`CodePatcher::retarget_jump`] for
+    /// safe encodings.
+    pub unsafe fn patch_word(&self, addr: *const u8, new_bytes: [u8; 4]) -> ModuleResult<()> {
+        if !self.is_patch_point(addr) {
+            return Err(ModuleError::Backend(anyhow::anyhow!(
+                "{:p} is not a patch point",
+                addr
+            )));
+        }
+        debug_assert_eq!(addr as usize % 4, 0, "misaligned patch point");
+
+        let _guard = PATCH_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        // The aligned word can't straddle pages.
+        let page_size = region::page::size();
+        let page = (addr as usize & !(page_size - 1)) as *mut u8;
+        protect_code(page, page_size, self.branch_protection, true)?;
+        (*(addr as *const AtomicU32)).store(u32::from_ne_bytes(new_bytes), Ordering::SeqCst);
+        protect_code(page, page_size, self.branch_protection, false)?;
+
+        icache_coherence::clear_cache(addr.cast(), 4)
+            .map_err(|e| ModuleError::Backend(anyhow::Error::new(e)))?;
+        icache_coherence::pipeline_flush_mt()
+            .map_err(|e| ModuleError::Backend(anyhow::Error::new(e)))?;
+        Ok(())
+    }
+
+    /// Make the `patchable_call` whose patch point is at `addr` call `target` instead.
+    ///
+    /// # Safety
+    ///
+    /// The function containing `addr` must be finalized and not freed, and `target` must be a
+    /// function with the signature of the call.
+    pub unsafe fn retarget_call(&self, addr: *const u8, target: *const u8) -> ModuleResult<()> {
+        if self.jump_destinations.contains_key(&(addr as usize)) {
+            return Err(ModuleError::Backend(anyhow::anyhow!(
+                "{:p} is the patch point of a jump",
+                addr
+            )));
+        }
+        let new_bytes = encode_branch(addr, target, true)?;
+        self.patch_word(addr, new_bytes)
+    }
+
+    /// Returns the initial and the alternate destination of the `patchable_jump` whose patch
+    /// point is at `addr`, if there is one.
+    pub fn jump_destinations(&self, addr: *const u8) -> Option<[*const u8; 2]> {
+        let [initial, alternate] = *self.jump_destinations.get(&(addr as usize))?;
+        Some([initial as *const u8, alternate as *const u8])
+    }
+
+    /// Make the `patchable_jump` whose patch point is at `addr` go to its alternate destination
+    /// if `alternate` is set, and to its initial destination otherwise.
+    ///
+    /// # Safety
+    ///
+    /// The function containing `addr` must be finalized and not freed.
+    pub unsafe fn retarget_jump(&self, addr: *const u8, alternate: bool) -> ModuleResult<()> {
+        let destinations = self.jump_destinations(addr).ok_or_else(|| {
+            ModuleError::Backend(anyhow::anyhow!(
+                "{:p} is not the patch point of a jump",
+                addr
+            ))
+        })?;
+        let new_bytes = encode_branch(addr, destinations[usize::from(alternate)], false)?;
+        self.patch_word(addr, new_bytes)
+    }
+}
+
+/// Encode the patch point at `addr` of a `patchable_call` calling `target` if `link` is set, or
+/// of a `patchable_jump` going to `target` otherwise.
+fn encode_branch(addr: *const u8, target: *const u8, link: bool) -> ModuleResult<[u8; 4]> {
+    let out_of_range = || {
+        ModuleError::Backend(anyhow::anyhow!(
+            "{:p} is out of range of the branch at {:p}",
+            target,
+            addr
+        ))
+    };
+
+    if cfg!(target_arch = "x86_64") {
+        // The displacement is relative to the end of the `call` or `jmp` instruction, which the
+        // patch point ends.
+        let disp = (target as isize).wrapping_sub(addr as isize + 4);
+        let disp = i32::try_from(disp).map_err(|_| out_of_range())?;
+        Ok(disp.to_le_bytes())
+    } else if cfg!(target_arch = "aarch64") {
+        let off = (target as isize).wrapping_sub(addr as isize);
+        if off % 4 != 0 || !(-(1 << 27)..(1 << 27)).contains(&off) {
+            return Err(out_of_range());
+        }
+        // `bl` and `b` only differ in their top bit.
+        let opcode = if link { 0x9400_0000 } else { 0x1400_0000 };
+        let branch = opcode | ((off >> 2) as u32 & 0x03ff_ffff);
+        Ok(branch.to_le_bytes())
+    } else {
+        Err(ModuleError::Backend(anyhow::anyhow!(
+            "patchable calls and jumps are not supported on this architecture"
+        )))
+    }
+}
@@ -0,0 +1,202 @@
+//! Describing the finalized functions of a module to profilers such as `perf`, which otherwise
+//! only see the anonymous memory their code is in.
+//!
+//! Profilers expect a single perf map or jitdump file per process, so the files are shared by
+//! all the modules of the process, and every finalization appends to them.
+
+use std::borrow::Cow;
+
+/// How the functions of a [`JITModule`](crate::JITModule) are described to profilers, see
+/// [`JITBuilder::profiling`](crate::JITBuilder::profiling).
+///
+/// The files are only written on Linux. On other targets, every strategy does nothing.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ProfilingStrategy {
+    /// Don't describe the functions.
+    #[default]
+    None,
+    /// Write a line `address size name` for each function to `/tmp/perf-<pid>.map`, which
+    /// `perf report` reads to name the samples in the code of the function.
+    PerfMap,
+    /// Write a code load record with a copy of the code of each function to `jit-<pid>.dump` in
+    /// the temporary directory, which `perf inject --jit` adds to the samples of a profile
+    /// recorded with `perf record -k mono`.
+    JitDump,
+}
+
+/// A finalized function: its name, and the address and size of its code.
+pub(crate) type FinalizedFunction<'a> = (Cow<'a, str>, *const u8, usize);
+
+/// Describe the finalized `functions` as `strategy` says.
+///
+/// Profiling is only an aid, so failing to write the files doesn't fail finalization, and is
+/// logged instead.
+pub(crate) fn register(strategy: ProfilingStrategy, functions: &[FinalizedFunction]) {
+    if strategy == ProfilingStrategy::None || functions.is_empty() {
+        return;
+    }
+    #[cfg(target_os = "linux")]
+    {
+        let result = match strategy {
+            ProfilingStrategy::None => Ok(()),
+            ProfilingStrategy::PerfMap => linux::write_perf_map(functions),
+            ProfilingStrategy::JitDump => linux::write_jitdump(functions),
+        };
+        if let Err(err) = result {
+            log::warn!("failed to describe JIT-compiled functions to profilers: {err}");
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use super::FinalizedFunction;
+    use std::fs::{File, OpenOptions};
+    use std::io::{self, BufWriter, Write};
+    use std::sync::Mutex;
+    use std::{mem, process, ptr, slice};
+    use target_lexicon::Architecture;
+
+    /// The perf map of the process, created by the first finalization which writes to it.
+    static PERF_MAP: Mutex<Option<BufWriter<File>>> = Mutex::new(None);
+
+    /// The jitdump file of the process, created by the first finalization which writes to it.
+    static JITDUMP: Mutex<Option<JitDump>> = Mutex::new(None);
+
+    pub(super) fn write_perf_map(functions: &[FinalizedFunction]) -> io::Result<()> {
+        let mut perf_map = PERF_MAP.lock().unwrap();
+        let file = match &mut *perf_map {
+            Some(file) => file,
+            None => {
+                let path = format!("/tmp/perf-{}.map", process::id());
+                perf_map.insert(BufWriter::new(File::create(path)?))
+            }
+        };
+        // The format is described in `tools/perf/Documentation/jit-interface.txt` of Linux.
+        for (name, ptr, size) in functions {
+            let name = name.replace(['\n', '\r'], "_");
+            writeln!(file, "{:x} {:x} {}", *ptr as usize, size, name)?;
+        }
+        file.flush()
+    }
+
+    pub(super) fn write_jitdump(functions: &[FinalizedFunction]) -> io::Result<()> {
+        let mut jitdump = JITDUMP.lock().unwrap();
+        let jitdump = match &mut *jitdump {
+            Some(jitdump) => jitdump,
+            None => jitdump.insert(JitDump::new()?),
+        };
+        for &(ref name, ptr, size) in functions {
+            // Safety: the code of finalized functions is readable.
+            let code = unsafe { slice::from_raw_parts(ptr, size) };
+            jitdump.write_code_load(name, ptr, code)?;
+        }
+        jitdump.file.flush()
+    }
+
+    /// A jitdump file, in the format of `tools/perf/Documentation/jitdump-specification.txt` of
+    /// Linux.
+    struct JitDump {
+        file: BufWriter<File>,
+        /// The index of the next code load record.
+        code_index: u64,
+    }
+
+    /// The size of the file header.
+    const HEADER_SIZE: u32 = 40;
+    /// The size of a code load record, without the name and the code which follow it.
+    const CODE_LOAD_SIZE: u32 = 56;
+    /// The id of code load records.
+    const JIT_CODE_LOAD: u32 = 0;
+
+    impl JitDump {
+        fn new() -> io::Result<Self> {
+            let path = std::env::temp_dir().join(format!("jit-{}.dump", process::id()));
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)?;
+
+            // `perf inject` finds the file through an executable mapping of it, which `perf
+            // record` saw being made. The mapping is kept for the rest of the process.
+            let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as usize;
+            let map = unsafe {
+                libc::mmap(
+                    ptr::null_mut(),
+                    page_size,
+                    libc::PROT_READ | libc::PROT_EXEC,
+                    libc::MAP_PRIVATE,
+                    std::os::unix::io::AsRawFd::as_raw_fd(&file),
+                    0,
+                )
+            };
+            if map == libc::MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+
+            let mut jitdump = Self {
+                file: BufWriter::new(file),
+                code_index: 0,
+            };
+            let e_machine: u32 = match target_lexicon::HOST.architecture {
+                Architecture::X86_64 => 62,
+                Architecture::X86_32(_) => 3,
+                Architecture::Arm(_) => 40,
+                Architecture::Aarch64(_) => 183,
+                Architecture::S390x => 22,
+                Architecture::Riscv64(_) => 243,
+                _ => 0,
+            };
+            let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+            header.extend_from_slice(&0x4A69_5444u32.to_ne_bytes()); // "JiTD"
+            header.extend_from_slice(&1u32.to_ne_bytes()); // version
+            header.extend_from_slice(&HEADER_SIZE.to_ne_bytes());
+            header.extend_from_slice(&e_machine.to_ne_bytes());
+            header.extend_from_slice(&0u32.to_ne_bytes()); // padding
+            header.extend_from_slice(&process::id().to_ne_bytes());
+            header.extend_from_slice(&timestamp().to_ne_bytes());
+            header.extend_from_slice(&0u64.to_ne_bytes()); // flags
+            debug_assert_eq!(header.len(), HEADER_SIZE as usize);
+            jitdump.file.write_all(&header)?;
+            Ok(jitdump)
+        }
+
+        fn write_code_load(&mut self, name: &str, ptr: *const u8, code: &[u8]) -> io::Result<()> {
+            let name = name.replace('\0', "_");
+            let size = CODE_LOAD_SIZE as usize + name.len() + 1 + code.len();
+            let size = u32::try_from(size)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "function too large"))?;
+            let tid = unsafe { libc::syscall(libc::SYS_gettid) } as u32;
+
+            let mut record = Vec::with_capacity(CODE_LOAD_SIZE as usize);
+            record.extend_from_slice(&JIT_CODE_LOAD.to_ne_bytes());
+            record.extend_from_slice(&size.to_ne_bytes());
+            record.extend_from_slice(&timestamp().to_ne_bytes());
+            record.extend_from_slice(&process::id().to_ne_bytes());
+            record.extend_from_slice(&tid.to_ne_bytes());
+            record.extend_from_slice(&(ptr as u64).to_ne_bytes()); // vma
+            record.extend_from_slice(&(ptr as u64).to_ne_bytes()); // code_addr
+            record.extend_from_slice(&(code.len() as u64).to_ne_bytes());
+            record.extend_from_slice(&self.code_index.to_ne_bytes());
+            debug_assert_eq!(record.len(), CODE_LOAD_SIZE as usize);
+            self.code_index += 1;
+
+            self.file.write_all(&record)?;
+            self.file.write_all(name.as_bytes())?;
+            self.file.write_all(b"\0")?;
+            self.file.write_all(code)
+        }
+    }
+
+    /// The time in nanoseconds of the monotonic clock, which `perf record -k mono` uses too.
+    fn timestamp() -> u64 {
+        let mut ts = mem::MaybeUninit::<libc::timespec>::uninit();
+        let ts = unsafe {
+            libc::clock_gettime(libc::CLOCK_MONOTONIC, ts.as_mut_ptr());
+            ts.assume_init()
+        };
+        ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+    }
+}
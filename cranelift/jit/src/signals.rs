@@ -0,0 +1,184 @@
+//! Catching traps of JIT-compiled code.
+//!
+//! Trapping instructions in code compiled by Cranelift either fault (e.g. an out-of-bounds load
+//! or a division by zero on x86_64) or execute an illegal instruction. Without a handler for the
+//! resulting signal (or exception on Windows) the process crashes. This module installs such
+//! handlers: when a fault happens at a trap site of a [`JITModule`] which is running code inside
+//! [`catch_traps`], execution unwinds back to that `catch_traps` call, which returns the
+//! [`Trap`]. Faults anywhere else are forwarded to the previously installed handler.
+//!
+//! Embedders with their own signal handlers can use [`JITModule::lookup_trap`] instead.
+
+use crate::JITModule;
+use cranelift_codegen::ir::TrapCode;
+use cranelift_module::FuncId;
+use std::cell::Cell;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::sync::Once;
+
+cfg_if::cfg_if! {
+    if #[cfg(unix)] {
+        mod unix;
+        use unix as sys;
+    } else if #[cfg(windows)] {
+        mod windows;
+        use windows as sys;
+    } else {
+        compile_error!("signal handlers are not supported on this platform");
+    }
+}
+
+extern "C" {
+    fn cranelift_jit_setjmp(
+        jmp_buf: *mut *const u8,
+        body: unsafe extern "C" fn(*mut u8),
+        payload: *mut u8,
+    ) -> i32;
+    fn cranelift_jit_longjmp(jmp_buf: *const u8) -> !;
+}
+
+/// A trap raised by JIT-compiled code, as caught by [`catch_traps`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Trap {
+    code: TrapCode,
+    func_id: FuncId,
+    pc: usize,
+}
+
+impl Trap {
+    /// The code of the trap.
+    pub fn code(&self) -> TrapCode {
+        self.code
+    }
+
+    /// The function containing the trapping instruction.
+    pub fn func_id(&self) -> FuncId {
+        self.func_id
+    }
+
+    /// The address of the trapping instruction.
+    pub fn pc(&self) -> *const u8 {
+        self.pc as *const u8
+    }
+}
+
+impl fmt::Display for Trap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "trap {} in {} at {:#x}",
+            self.code, self.func_id, self.pc
+        )
+    }
+}
+
+impl std::error::Error for Trap {}
+
+/// An active [`catch_traps`] call.
+///
+/// The entries of a thread form a stack linked through `prev`, so that a trap unwinds to the
+/// innermost call whose module knows the trapping instruction.
+struct Entry {
+    module: *const JITModule,
+    prev: *const Entry,
+    jmp_buf: Cell<*const u8>,
+    trap: Cell<Option<Trap>>,
+}
+
+thread_local! {
+    static ENTRIES: Cell<*const Entry> = const { Cell::new(ptr::null()) };
+}
+
+/// Install the signal handlers (or the vectored exception handler on Windows) used by
+/// [`catch_traps`].
+///
+/// This is done automatically by the first call to `catch_traps`, and does nothing when called
+/// again.
+pub fn init_signal_handlers() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| unsafe { sys::platform_init() });
+}
+
+/// Run `f`, catching the traps raised by code of `module` while it runs.
+///
+/// Returns the result of `f`, or the trap which interrupted it. Panics in `f` are propagated.
+/// Calls to `catch_traps` can be nested, e.g. when JIT-compiled code calls back into a host
+/// function which runs more JIT-compiled code; each trap unwinds to the innermost call whose
+/// module contains the trapping instruction.
+///
+/// # Safety
+///
+/// A trap unwinds the stack without running destructors, so every frame between the
+/// `catch_traps` call and the trapping instruction must be fine to skip. This is the case for
+/// JIT-compiled code, but not for host functions called by it which own resources or hold
+/// locks. `module` must not be freed or modified while `f` runs.
+pub unsafe fn catch_traps<F, R>(module: &JITModule, f: F) -> Result<R, Trap>
+where
+    F: FnOnce() -> R,
+{
+    init_signal_handlers();
+
+    struct Call<F, R> {
+        f: Option<F>,
+        result: Option<std::thread::Result<R>>,
+    }
+
+    unsafe extern "C" fn call<F: FnOnce() -> R, R>(payload: *mut u8) {
+        let call = &mut *(payload as *mut Call<F, R>);
+        let f = call.f.take().unwrap();
+        // Unwinding out of an `extern "C"` function aborts, so carry panics across the C frame.
+        call.result = Some(panic::catch_unwind(AssertUnwindSafe(f)));
+    }
+
+    let entry = Entry {
+        module,
+        prev: ENTRIES.with(|entries| entries.get()),
+        jmp_buf: Cell::new(ptr::null()),
+        trap: Cell::new(None),
+    };
+    ENTRIES.with(|entries| entries.set(&entry));
+    let mut payload = Call {
+        f: Some(f),
+        result: None,
+    };
+    let returned = cranelift_jit_setjmp(
+        entry.jmp_buf.as_ptr(),
+        call::<F, R>,
+        &mut payload as *mut Call<F, R> as *mut u8,
+    );
+    // A trap may have skipped nested entries, which are all gone now.
+    ENTRIES.with(|entries| entries.set(entry.prev));
+
+    if returned == 0 {
+        return Err(entry.trap.get().expect("unwound without a trap"));
+    }
+    match payload.result.unwrap() {
+        Ok(result) => Ok(result),
+        Err(panic) => panic::resume_unwind(panic),
+    }
+}
+
+/// Find the innermost `catch_traps` call of this thread whose module has a trap site at `pc`,
+/// record the trap in it and return its jump buffer, or null if there is none.
+///
+/// Called from signal handlers, so this must not allocate or take locks.
+unsafe fn take_jmp_buf_if_trap(pc: *const u8) -> *const u8 {
+    // `try_with` as the thread might be shutting down.
+    let mut entry = ENTRIES
+        .try_with(|entries| entries.get())
+        .unwrap_or(ptr::null());
+    while let Some(e) = entry.as_ref() {
+        if let Some((func_id, code)) = (*e.module).lookup_trap_site(pc) {
+            e.trap.set(Some(Trap {
+                code,
+                func_id,
+                pc: pc as usize,
+            }));
+            return e.jmp_buf.get();
+        }
+        entry = e.prev;
+    }
+    ptr::null()
+}
@@ -0,0 +1,154 @@
+use super::{cranelift_jit_longjmp, take_jmp_buf_if_trap};
+use std::io;
+use std::mem::{self, MaybeUninit};
+use std::ptr;
+
+static mut PREV_SIGSEGV: MaybeUninit<libc::sigaction> = MaybeUninit::uninit();
+static mut PREV_SIGBUS: MaybeUninit<libc::sigaction> = MaybeUninit::uninit();
+static mut PREV_SIGILL: MaybeUninit<libc::sigaction> = MaybeUninit::uninit();
+static mut PREV_SIGFPE: MaybeUninit<libc::sigaction> = MaybeUninit::uninit();
+
+pub(super) unsafe fn platform_init() {
+    let register = |slot: *mut MaybeUninit<libc::sigaction>, signal: i32| {
+        let mut handler: libc::sigaction = mem::zeroed();
+        // SA_SIGINFO gives access to the context of the fault, SA_ONSTACK runs the handler on
+        // the alternate stack Rust installs for each thread, and SA_NODEFER allows a fault in
+        // the handler itself to reach the previous handler.
+        handler.sa_flags = libc::SA_SIGINFO | libc::SA_NODEFER | libc::SA_ONSTACK;
+        handler.sa_sigaction = trap_handler as *const () as usize;
+        libc::sigemptyset(&mut handler.sa_mask);
+        if libc::sigaction(signal, &handler, (*slot).as_mut_ptr()) != 0 {
+            panic!(
+                "unable to install signal handler: {}",
+                io::Error::last_os_error(),
+            );
+        }
+    };
+
+    // Out-of-bounds accesses to memory.
+    register(ptr::addr_of_mut!(PREV_SIGSEGV), libc::SIGSEGV);
+
+    // Explicit traps, like `trap` and the checks of `sdiv`, are `ud2` or `udf` instructions.
+    register(ptr::addr_of_mut!(PREV_SIGILL), libc::SIGILL);
+
+    // x86_64 and s390x report division by zero with SIGFPE.
+    if cfg!(target_arch = "x86_64") || cfg!(target_arch = "s390x") {
+        register(ptr::addr_of_mut!(PREV_SIGFPE), libc::SIGFPE);
+    }
+
+    // On macOS and FreeBSD guard page accesses raise SIGBUS.
+    if cfg!(target_os = "macos") || cfg!(target_os = "freebsd") {
+        register(ptr::addr_of_mut!(PREV_SIGBUS), libc::SIGBUS);
+    }
+}
+
+unsafe extern "C" fn trap_handler(
+    signum: libc::c_int,
+    siginfo: *mut libc::siginfo_t,
+    context: *mut libc::c_void,
+) {
+    let previous = match signum {
+        libc::SIGSEGV => ptr::addr_of!(PREV_SIGSEGV),
+        libc::SIGBUS => ptr::addr_of!(PREV_SIGBUS),
+        libc::SIGFPE => ptr::addr_of!(PREV_SIGFPE),
+        libc::SIGILL => ptr::addr_of!(PREV_SIGILL),
+        _ => panic!("unknown signal: {}", signum),
+    };
+
+    let jmp_buf = take_jmp_buf_if_trap(get_pc(context, signum));
+    if !jmp_buf.is_null() {
+        // Jumping out of the signal handler on macOS leaves the kernel believing the signal
+        // stack is still in use, so return from the handler into a shim which does the jump.
+        if cfg!(target_os = "macos") {
+            unsafe extern "C" fn longjmp_shim(jmp_buf: *const u8) {
+                cranelift_jit_longjmp(jmp_buf)
+            }
+            set_pc(
+                context,
+                longjmp_shim as *const () as usize,
+                jmp_buf as usize,
+            );
+            return;
+        }
+        cranelift_jit_longjmp(jmp_buf)
+    }
+
+    // Not a trap of JIT-compiled code we are catching, so forward the signal to the previous
+    // handler. If there is none, restore the default disposition and return, which re-executes
+    // the faulting instruction and crashes the usual way.
+    let previous = &*(*previous).as_ptr();
+    if previous.sa_flags & libc::SA_SIGINFO != 0 {
+        mem::transmute::<usize, extern "C" fn(libc::c_int, *mut libc::siginfo_t, *mut libc::c_void)>(
+            previous.sa_sigaction,
+        )(signum, siginfo, context)
+    } else if previous.sa_sigaction == libc::SIG_DFL || previous.sa_sigaction == libc::SIG_IGN {
+        libc::sigaction(signum, previous, ptr::null_mut());
+    } else {
+        mem::transmute::<usize, extern "C" fn(libc::c_int)>(previous.sa_sigaction)(signum)
+    }
+}
+
+unsafe fn get_pc(cx: *mut libc::c_void, _signum: libc::c_int) -> *const u8 {
+    cfg_if::cfg_if! {
+        if #[cfg(all(any(target_os = "linux", target_os = "android"), target_arch = "x86_64"))] {
+            let cx = &*(cx as *const libc::ucontext_t);
+            cx.uc_mcontext.gregs[libc::REG_RIP as usize] as *const u8
+        } else if #[cfg(all(any(target_os = "linux", target_os = "android"), target_arch = "aarch64"))] {
+            let cx = &*(cx as *const libc::ucontext_t);
+            cx.uc_mcontext.pc as *const u8
+        } else if #[cfg(all(target_os = "linux", target_arch = "s390x"))] {
+            // SIGILL and SIGFPE are delivered with the PSW address pointing after the faulting
+            // instruction, so Cranelift registers those traps on its last byte.
+            let trap_offset = match _signum {
+                libc::SIGILL | libc::SIGFPE => 1,
+                _ => 0,
+            };
+            let cx = &*(cx as *const libc::ucontext_t);
+            (cx.uc_mcontext.psw.addr - trap_offset) as *const u8
+        } else if #[cfg(all(target_os = "linux", target_arch = "riscv64"))] {
+            let cx = &*(cx as *const libc::ucontext_t);
+            cx.uc_mcontext.__gregs[libc::REG_PC] as *const u8
+        } else if #[cfg(all(target_os = "macos", target_arch = "x86_64"))] {
+            let cx = &*(cx as *const libc::ucontext_t);
+            (*cx.uc_mcontext).__ss.__rip as *const u8
+        } else if #[cfg(all(target_os = "macos", target_arch = "aarch64"))] {
+            let cx = &*(cx as *const libc::ucontext_t);
+            (*cx.uc_mcontext).__ss.__pc as *const u8
+        } else if #[cfg(all(target_os = "freebsd", target_arch = "x86_64"))] {
+            let cx = &*(cx as *const libc::ucontext_t);
+            cx.uc_mcontext.mc_rip as *const u8
+        } else if #[cfg(all(target_os = "freebsd", target_arch = "aarch64"))] {
+            let cx = &*(cx as *const libc::mcontext_t);
+            cx.mc_gpregs.gp_elr as *const u8
+        } else {
+            compile_error!("signal handlers are not supported on this platform");
+        }
+    }
+}
+
+/// Make the signal handler return into `pc`, called with `arg1` as its first argument.
+///
+/// Only used on macOS, see `trap_handler`.
+unsafe fn set_pc(cx: *mut libc::c_void, pc: usize, arg1: usize) {
+    cfg_if::cfg_if! {
+        if #[cfg(not(target_os = "macos"))] {
+            let _ = (cx, pc, arg1);
+            unreachable!();
+        } else if #[cfg(target_arch = "x86_64")] {
+            let cx = &mut *(cx as *mut libc::ucontext_t);
+            (*cx.uc_mcontext).__ss.__rip = pc as u64;
+            (*cx.uc_mcontext).__ss.__rdi = arg1 as u64;
+            // Simulate a call: the stack pointer is 16-byte aligned right before a `call`, so
+            // it is off by 8 on entry to the callee.
+            if (*cx.uc_mcontext).__ss.__rsp % 16 == 0 {
+                (*cx.uc_mcontext).__ss.__rsp -= 8;
+            }
+        } else if #[cfg(target_arch = "aarch64")] {
+            let cx = &mut *(cx as *mut libc::ucontext_t);
+            (*cx.uc_mcontext).__ss.__pc = pc as u64;
+            (*cx.uc_mcontext).__ss.__x[0] = arg1 as u64;
+        } else {
+            compile_error!("unsupported macos target architecture");
+        }
+    }
+}
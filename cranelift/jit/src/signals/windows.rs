@@ -0,0 +1,45 @@
+use super::{cranelift_jit_longjmp, take_jmp_buf_if_trap};
+use std::io;
+use windows_sys::Win32::Foundation::*;
+use windows_sys::Win32::System::Diagnostics::Debug::*;
+use windows_sys::Win32::System::Kernel::*;
+
+pub(super) unsafe fn platform_init() {
+    // Pass `1` so that the handler runs before any other and can recover from traps.
+    if AddVectoredExceptionHandler(1, Some(exception_handler)).is_null() {
+        panic!(
+            "failed to add exception handler: {}",
+            io::Error::last_os_error()
+        );
+    }
+}
+
+unsafe extern "system" fn exception_handler(exception_info: *mut EXCEPTION_POINTERS) -> i32 {
+    // Only the exceptions raised by trapping instructions are handled; everything else goes to
+    // the rest of the system.
+    let record = &*(*exception_info).ExceptionRecord;
+    if record.ExceptionCode != EXCEPTION_ACCESS_VIOLATION
+        && record.ExceptionCode != EXCEPTION_ILLEGAL_INSTRUCTION
+        && record.ExceptionCode != EXCEPTION_INT_DIVIDE_BY_ZERO
+        && record.ExceptionCode != EXCEPTION_INT_OVERFLOW
+    {
+        return ExceptionContinueSearch;
+    }
+
+    cfg_if::cfg_if! {
+        if #[cfg(target_arch = "x86_64")] {
+            let pc = (*(*exception_info).ContextRecord).Rip as *const u8;
+        } else if #[cfg(target_arch = "aarch64")] {
+            let pc = (*(*exception_info).ContextRecord).Pc as *const u8;
+        } else {
+            compile_error!("signal handlers are not supported on this platform");
+        }
+    }
+
+    let jmp_buf = take_jmp_buf_if_trap(pc);
+    if jmp_buf.is_null() {
+        ExceptionContinueSearch
+    } else {
+        cranelift_jit_longjmp(jmp_buf)
+    }
+}
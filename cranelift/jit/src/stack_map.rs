@@ -0,0 +1,96 @@
+//! Stack maps of safepoints in JIT-compiled code.
+
+use cranelift_codegen::binemit::StackMap;
+use cranelift_codegen::ir;
+use std::collections::BTreeMap;
+
+/// The stack map of one safepoint in JIT-compiled code, as returned by
+/// [`JITModule::stack_map_at`](crate::JITModule::stack_map_at).
+///
+/// A stack map covers the [`frame_size`](Self::frame_size) bytes right below the frame pointer of
+/// the function containing the safepoint. While that function is suspended at the safepoint (i.e.
+/// while the call it made is executing) its stack pointer is `fp - frame_size()`, and every entry
+/// is the offset from that stack pointer of a slot holding a live reference.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UserStackMapView {
+    frame_size: u32,
+    ty: ir::Type,
+    offsets: Vec<u32>,
+}
+
+impl UserStackMapView {
+    fn new(stack_map: &StackMap, ty: ir::Type) -> Self {
+        let word_size = ty.bytes();
+        let offsets = (0..stack_map.mapped_words())
+            .filter(|&word| stack_map.get_bit(word as usize))
+            .map(|word| word * word_size)
+            .collect();
+        Self {
+            frame_size: stack_map.mapped_words() * word_size,
+            ty,
+            offsets,
+        }
+    }
+
+    /// The number of bytes below the frame pointer described by this stack map.
+    pub fn frame_size(&self) -> u32 {
+        self.frame_size
+    }
+
+    /// The type and stack pointer relative offset in bytes of each slot holding a live
+    /// reference, in ascending order of offset.
+    pub fn entries(&self) -> impl ExactSizeIterator<Item = (ir::Type, u32)> + '_ {
+        self.offsets.iter().map(move |&offset| (self.ty, offset))
+    }
+
+    /// The addresses of the slots holding live references, given the frame pointer of the
+    /// function suspended at this safepoint.
+    pub fn slot_addresses(&self, fp: *const u8) -> impl Iterator<Item = *const u8> + '_ {
+        let sp = fp.wrapping_sub(self.frame_size as usize);
+        self.offsets
+            .iter()
+            .map(move |&offset| sp.wrapping_add(offset as usize))
+    }
+}
+
+/// The stack maps of all functions defined in a `JITModule`, keyed by the return address of their
+/// safepoint.
+#[derive(Default)]
+pub(crate) struct StackMapTable {
+    stack_maps: BTreeMap<usize, UserStackMapView>,
+}
+
+impl StackMapTable {
+    /// Record the stack maps of a function whose code was placed at `code`.
+    pub(crate) fn insert(
+        &mut self,
+        code: *const u8,
+        stack_maps: &[cranelift_codegen::MachStackMap],
+        ty: ir::Type,
+    ) {
+        for stack_map in stack_maps {
+            // The safepoint is a call, so the address it will return to is the one seen while
+            // walking the stack.
+            let pc = code as usize + stack_map.offset_end as usize;
+            self.stack_maps
+                .insert(pc, UserStackMapView::new(&stack_map.stack_map, ty));
+        }
+    }
+
+    /// Forget the stack maps of the function whose code occupies `size` bytes at `code`.
+    pub(crate) fn remove(&mut self, code: *const u8, size: usize) {
+        let start = code as usize;
+        let pcs: Vec<usize> = self
+            .stack_maps
+            .range(start..=start + size)
+            .map(|(&pc, _)| pc)
+            .collect();
+        for pc in pcs {
+            self.stack_maps.remove(&pc);
+        }
+    }
+
+    pub(crate) fn get(&self, pc: *const u8) -> Option<&UserStackMapView> {
+        self.stack_maps.get(&(pc as usize))
+    }
+}
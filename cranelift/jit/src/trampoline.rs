@@ -0,0 +1,342 @@
+//! Universal trampolines for calling JIT-compiled functions with [DataValue]s.
+//!
+//! A trampoline is a function with the signature
+//! `extern "C" fn(callee: *const u8, values: *mut u128)`. It loads the callee's arguments out of
+//! `values`, calls `callee` with them and stores its results back into `values`. Each argument
+//! and result occupies one [SLOT_SIZE]-byte slot: argument `i` is read from slot `i` and result
+//! `i` is written to slot `i`. Values are stored in native-endian byte order, except for vectors
+//! which are always stored in little-endian lane order, matching the byte representation of
+//! [DataValue::V128] and [DataValue::V64].
+//!
+//! The trampolines of a [TrampolineCache] instead read the arguments and write the results
+//! through separate buffers, with the signature
+//! `extern "C" fn(callee: *const u8, arguments: *const u128, results: *mut u128)`, and are
+//! compiled into the module of the functions they call, once per signature.
+
+use crate::{JITBuilder, JITModule};
+use cranelift_codegen::cursor::{Cursor, FuncCursor};
+use cranelift_codegen::data_value::DataValue;
+use cranelift_codegen::ir::{
+    self, AbiParam, Endianness, Function, InstBuilder, MemFlags, Signature, UserFuncName,
+};
+use cranelift_codegen::isa::{OwnedTargetIsa, TargetIsa};
+use cranelift_codegen::Context;
+use cranelift_module::{default_libcall_names, Linkage, Module, ModuleResult};
+use std::cmp::max;
+use std::collections::HashMap;
+use std::mem;
+
+/// The size in bytes of each argument and result slot in a trampoline's value buffer.
+///
+/// Every [DataValue] fits in a slot; using a fixed size keeps the layout independent of the
+/// signature.
+pub const SLOT_SIZE: usize = 16;
+
+/// Build the IR of a trampoline for functions with the given `signature`.
+///
+/// The trampoline uses the default calling convention of `isa`, so it can be called as an
+/// `extern "C"` function when `isa` targets the host.
+pub fn make_trampoline(name: UserFuncName, signature: &Signature, isa: &dyn TargetIsa) -> Function {
+    build_trampoline(name, signature, isa, false)
+}
+
+/// Build the IR of a trampoline for functions with the given `signature`, which reads the
+/// arguments and writes the results through separate buffers, as the trampolines of a
+/// [TrampolineCache] do.
+pub fn make_split_trampoline(
+    name: UserFuncName,
+    signature: &Signature,
+    isa: &dyn TargetIsa,
+) -> Function {
+    build_trampoline(name, signature, isa, true)
+}
+
+fn build_trampoline(
+    name: UserFuncName,
+    signature: &Signature,
+    isa: &dyn TargetIsa,
+    split: bool,
+) -> Function {
+    let pointer_type = isa.pointer_type();
+    let mut wrapper_sig = Signature::new(isa.default_call_conv());
+    wrapper_sig.params.push(AbiParam::new(pointer_type)); // The callee's address.
+    wrapper_sig.params.push(AbiParam::new(pointer_type)); // The arguments.
+    if split {
+        wrapper_sig.params.push(AbiParam::new(pointer_type)); // The results.
+    }
+
+    let mut func = Function::with_name_signature(name, wrapper_sig);
+    let callee_sig = func.import_signature(signature.clone());
+
+    let block0 = func.dfg.make_block();
+    let callee = func.dfg.append_block_param(block0, pointer_type);
+    let arguments = func.dfg.append_block_param(block0, pointer_type);
+    let results = if split {
+        func.dfg.append_block_param(block0, pointer_type)
+    } else {
+        arguments
+    };
+    let mut pos = FuncCursor::new(&mut func);
+    pos.insert_block(block0);
+
+    let flags = |ty: ir::Type| {
+        let mut flags = MemFlags::trusted();
+        if ty.is_vector() {
+            flags.set_endianness(Endianness::Little);
+        }
+        flags
+    };
+    let args: Vec<_> = signature
+        .params
+        .iter()
+        .enumerate()
+        .map(|(i, param)| {
+            let ty = param.value_type;
+            pos.ins()
+                .load(ty, flags(ty), arguments, (i * SLOT_SIZE) as i32)
+        })
+        .collect();
+
+    let call = pos.ins().call_indirect(callee_sig, callee, &args);
+
+    let returned = pos.func.dfg.inst_results(call).to_vec();
+    for (i, result) in returned.into_iter().enumerate() {
+        let ty = pos.func.dfg.value_type(result);
+        pos.ins()
+            .store(flags(ty), result, results, (i * SLOT_SIZE) as i32);
+    }
+    pos.ins().return_(&[]);
+
+    func
+}
+
+/// A buffer of argument and result slots laid out as a trampoline expects.
+pub struct TrampolineValues(Vec<u128>);
+
+impl TrampolineValues {
+    /// Pack `arguments` for a call to a function with the given `signature`.
+    ///
+    /// The buffer is large enough to also hold the function's results.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `arguments` doesn't match the parameters of `signature`.
+    pub fn make_arguments(arguments: &[DataValue], signature: &Signature) -> Self {
+        let slots = max(signature.params.len(), signature.returns.len());
+        Self(pack(arguments, signature, slots))
+    }
+
+    /// Return a pointer to the slots, to pass to a trampoline.
+    pub fn as_mut_ptr(&mut self) -> *mut u128 {
+        self.0.as_mut_ptr()
+    }
+
+    /// Unpack the results of a call to a function with the given `signature`.
+    pub fn collect_returns(&self, signature: &Signature) -> Vec<DataValue> {
+        assert!(self.0.len() >= signature.returns.len());
+        self.0
+            .iter()
+            .zip(&signature.returns)
+            .map(|(slot, ret)| unsafe { DataValue::read_value_from(slot, ret.value_type) })
+            .collect()
+    }
+}
+
+/// A compiled trampoline bound to a target function.
+///
+/// The trampoline lives in its own [JITModule], which is freed when the `BoundTrampoline` is
+/// dropped.
+pub struct BoundTrampoline {
+    module: Option<JITModule>,
+    signature: Signature,
+    trampoline: *const u8,
+    target: *const u8,
+}
+
+impl BoundTrampoline {
+    /// Compile a trampoline for calling `target` with `isa`.
+    ///
+    /// # Safety
+    ///
+    /// `target` must point to a function with the given `signature` for as long as the returned
+    /// `BoundTrampoline` is used, and `isa` must target the host.
+    pub unsafe fn new(
+        isa: OwnedTargetIsa,
+        signature: &Signature,
+        target: *const u8,
+    ) -> ModuleResult<Self> {
+        let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+        let func = make_trampoline(UserFuncName::default(), signature, module.isa());
+        let id = module.declare_function("trampoline", Linkage::Local, &func.signature)?;
+        let mut ctx = Context::for_function(func);
+        module.define_function(id, &mut ctx)?;
+        module.finalize_definitions()?;
+        let trampoline = module.get_finalized_function(id);
+
+        Ok(Self {
+            module: Some(module),
+            signature: signature.clone(),
+            trampoline,
+            target,
+        })
+    }
+
+    /// The signature of the target function.
+    pub fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    /// Call the target function with the arguments packed in `values`, which receives the
+    /// results.
+    ///
+    /// # Safety
+    ///
+    /// `values` must have been created for this trampoline's signature, and calling the target
+    /// function must be safe.
+    pub unsafe fn call_packed(&self, values: &mut TrampolineValues) {
+        let trampoline: extern "C" fn(*const u8, *mut u128) = mem::transmute(self.trampoline);
+        trampoline(self.target, values.as_mut_ptr());
+    }
+
+    /// Call the target function with `arguments` and return its results.
+    ///
+    /// # Safety
+    ///
+    /// Calling the target function must be safe.
+    pub unsafe fn call(&self, arguments: &[DataValue]) -> Vec<DataValue> {
+        let mut values = TrampolineValues::make_arguments(arguments, &self.signature);
+        self.call_packed(&mut values);
+        values.collect_returns(&self.signature)
+    }
+}
+
+impl Drop for BoundTrampoline {
+    fn drop(&mut self) {
+        // The trampoline's code is never handed out, so it can't be used past this point.
+        unsafe { self.module.take().unwrap().free_memory() }
+    }
+}
+
+/// Pack `arguments` into slots for a function with the given `signature`.
+///
+/// # Panics
+///
+/// Panics if `arguments` doesn't match the parameters of `signature`.
+fn pack(arguments: &[DataValue], signature: &Signature, slots: usize) -> Vec<u128> {
+    assert_eq!(arguments.len(), signature.params.len());
+    let mut packed = vec![0; slots];
+    for ((arg, slot), param) in arguments.iter().zip(&mut packed).zip(&signature.params) {
+        assert!(
+            arg.ty() == param.value_type || arg.is_vector(),
+            "argument type mismatch: {} != {}",
+            arg.ty(),
+            param.value_type
+        );
+        unsafe {
+            arg.write_value_to(slot);
+        }
+    }
+    packed
+}
+
+/// A trampoline compiled by a [TrampolineCache], which can call any function with its signature.
+#[derive(Clone)]
+pub struct Trampoline {
+    signature: Signature,
+    code: *const u8,
+}
+
+impl Trampoline {
+    /// The signature of the functions this trampoline calls.
+    pub fn signature(&self) -> &Signature {
+        &self.signature
+    }
+
+    /// Call `target` with the arguments in the slots at `arguments`, and write its results into
+    /// the slots at `results`.
+    ///
+    /// # Safety
+    ///
+    /// The module the trampoline was compiled into must not have been freed, `target` must
+    /// point to a function with the trampoline's signature which is safe to call, and the
+    /// buffers must hold a slot for each argument and result.
+    pub unsafe fn call_raw(&self, target: *const u8, arguments: *const u128, results: *mut u128) {
+        let trampoline: extern "C" fn(*const u8, *const u128, *mut u128) =
+            mem::transmute(self.code);
+        trampoline(target, arguments, results);
+    }
+
+    /// Call `target` with `arguments` and return its results.
+    ///
+    /// # Safety
+    ///
+    /// The module the trampoline was compiled into must not have been freed, and `target` must
+    /// point to a function with the trampoline's signature which is safe to call.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `arguments` doesn't match the parameters of the signature.
+    pub unsafe fn call(&self, target: *const u8, arguments: &[DataValue]) -> Vec<DataValue> {
+        let arguments = pack(arguments, &self.signature, self.signature.params.len());
+        let mut results = vec![0; self.signature.returns.len()];
+        self.call_raw(target, arguments.as_ptr(), results.as_mut_ptr());
+        results
+            .iter()
+            .zip(&self.signature.returns)
+            .map(|(slot, ret)| DataValue::read_value_from(slot, ret.value_type))
+            .collect()
+    }
+}
+
+/// The trampolines compiled into a [JITModule], by signature.
+///
+/// A cache must only be used with one module, into which [TrampolineCache::get] compiles the
+/// trampoline of each distinct signature the first time it is asked for it.
+#[derive(Default)]
+pub struct TrampolineCache {
+    trampolines: HashMap<Signature, Trampoline>,
+}
+
+impl TrampolineCache {
+    /// Create an empty cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Return the trampoline of `module` for functions with the given `signature`, compiling it
+    /// if needed.
+    ///
+    /// Compiling a trampoline finalizes all the functions defined in `module` so far.
+    pub fn get(
+        &mut self,
+        module: &mut JITModule,
+        signature: &Signature,
+    ) -> ModuleResult<Trampoline> {
+        if let Some(trampoline) = self.trampolines.get(signature) {
+            return Ok(trampoline.clone());
+        }
+
+        let func = make_split_trampoline(UserFuncName::default(), signature, module.isa());
+        let id = module.declare_anonymous_function(&func.signature)?;
+        let mut ctx = Context::for_function(func);
+        module.define_function(id, &mut ctx)?;
+        module.finalize_definitions()?;
+        let trampoline = Trampoline {
+            signature: signature.clone(),
+            code: module.get_finalized_function(id),
+        };
+        self.trampolines
+            .insert(signature.clone(), trampoline.clone());
+        Ok(trampoline)
+    }
+
+    /// The number of trampolines compiled so far.
+    pub fn len(&self) -> usize {
+        self.trampolines.len()
+    }
+
+    /// Whether no trampoline has been compiled yet.
+    pub fn is_empty(&self) -> bool {
+        self.trampolines.is_empty()
+    }
+}
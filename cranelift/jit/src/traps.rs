@@ -0,0 +1,43 @@
+//! Trap sites in JIT-compiled code.
+
+use cranelift_codegen::ir::TrapCode;
+use cranelift_codegen::MachTrap;
+use cranelift_module::FuncId;
+use std::collections::BTreeMap;
+
+/// The trap sites of all functions defined in a `JITModule`, keyed by the address of their
+/// trapping instruction.
+#[derive(Default)]
+pub(crate) struct TrapTable {
+    traps: BTreeMap<usize, (FuncId, TrapCode)>,
+}
+
+impl TrapTable {
+    /// Record the trap sites of the function `func_id` whose code was placed at `code`.
+    pub(crate) fn insert(&mut self, code: *const u8, func_id: FuncId, traps: &[MachTrap]) {
+        for trap in traps {
+            let pc = code as usize + trap.offset as usize;
+            self.traps.insert(pc, (func_id, trap.code));
+        }
+    }
+
+    /// Forget the trap sites of the function whose code occupies `size` bytes at `code`.
+    pub(crate) fn remove(&mut self, code: *const u8, size: usize) {
+        let start = code as usize;
+        let pcs: Vec<usize> = self
+            .traps
+            .range(start..start + size)
+            .map(|(&pc, _)| pc)
+            .collect();
+        for pc in pcs {
+            self.traps.remove(&pc);
+        }
+    }
+
+    /// Find the function and trap code of the trapping instruction at `pc`.
+    ///
+    /// This doesn't allocate, so it can be called from a signal handler.
+    pub(crate) fn get(&self, pc: *const u8) -> Option<(FuncId, TrapCode)> {
+        self.traps.get(&(pc as usize)).copied()
+    }
+}
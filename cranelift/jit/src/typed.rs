@@ -0,0 +1,267 @@
+//! Getting finalized functions as Rust function pointers whose types are checked against the
+//! signatures the functions were declared with.
+//!
+//! A [`JitFn`] is an `extern "C"` function pointer type whose parameters and result are
+//! [`JitValue`]s, such as `extern "C" fn(i32, *const u8) -> f64`.
+//! [`JITModule::get_finalized_function_typed`](crate::JITModule::get_finalized_function_typed)
+//! only returns such a pointer if the declared signature of the function has the same parameter
+//! and result types, in the calling convention of `extern "C"` functions on the host.
+//!
+//! A pointer is only valid as long as the memory of its module, which it doesn't keep alive. An
+//! [`OwnedJitFn`], from
+//! [`JITModule::finalize_into_owned_fn`](crate::JITModule::finalize_into_owned_fn), owns the
+//! module along with the pointer, and frees the memory of the module when it's dropped.
+
+use crate::JITModule;
+use cranelift_codegen::ir::{self, types, ArgumentPurpose, Signature};
+use cranelift_codegen::isa::CallConv;
+use std::fmt;
+use std::mem::ManuallyDrop;
+
+/// A Rust type which is passed to and returned from `extern "C"` functions the way Cranelift
+/// passes values of [`ir_type`](Self::ir_type).
+///
+/// # Safety
+///
+/// `ir_type` must be the Cranelift type of the same size and register class as `Self`.
+pub unsafe trait JitValue: Copy {
+    /// The Cranelift type of the values.
+    fn ir_type() -> ir::Type;
+}
+
+macro_rules! jit_values {
+    ($($rust:ty: $ty:expr),* $(,)?) => {$(
+        unsafe impl JitValue for $rust {
+            fn ir_type() -> ir::Type {
+                $ty
+            }
+        }
+    )*};
+}
+
+jit_values! {
+    i8: types::I8,
+    u8: types::I8,
+    i16: types::I16,
+    u16: types::I16,
+    i32: types::I32,
+    u32: types::I32,
+    i64: types::I64,
+    u64: types::I64,
+    i128: types::I128,
+    u128: types::I128,
+    f32: types::F32,
+    f64: types::F64,
+}
+
+fn pointer_type() -> ir::Type {
+    ir::Type::int(usize::BITS as u16).unwrap()
+}
+
+unsafe impl JitValue for isize {
+    fn ir_type() -> ir::Type {
+        pointer_type()
+    }
+}
+
+unsafe impl JitValue for usize {
+    fn ir_type() -> ir::Type {
+        pointer_type()
+    }
+}
+
+unsafe impl<T> JitValue for *const T {
+    fn ir_type() -> ir::Type {
+        pointer_type()
+    }
+}
+
+unsafe impl<T> JitValue for *mut T {
+    fn ir_type() -> ir::Type {
+        pointer_type()
+    }
+}
+
+/// An `extern "C"` function pointer type which a function compiled for the host can be called
+/// through.
+///
+/// # Safety
+///
+/// `params` and `returns` must be the types of the parameters and results of the function
+/// pointer type, and `from_ptr` must only reinterpret the address as `Self`.
+pub unsafe trait JitFn: Copy {
+    /// The Cranelift types of the parameters.
+    fn params() -> Vec<ir::Type>;
+
+    /// The Cranelift types of the results.
+    fn returns() -> Vec<ir::Type>;
+
+    /// The function at `ptr`.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be the address of a function of this type.
+    unsafe fn from_ptr(ptr: *const u8) -> Self;
+}
+
+macro_rules! jit_fns {
+    ($(($($param:ident),*))*) => {$(
+        unsafe impl<$($param: JitValue,)*> JitFn for extern "C" fn($($param),*) {
+            fn params() -> Vec<ir::Type> {
+                vec![$($param::ir_type()),*]
+            }
+
+            fn returns() -> Vec<ir::Type> {
+                vec![]
+            }
+
+            unsafe fn from_ptr(ptr: *const u8) -> Self {
+                std::mem::transmute::<*const u8, Self>(ptr)
+            }
+        }
+
+        unsafe impl<$($param: JitValue,)* R: JitValue> JitFn for extern "C" fn($($param),*) -> R {
+            fn params() -> Vec<ir::Type> {
+                vec![$($param::ir_type()),*]
+            }
+
+            fn returns() -> Vec<ir::Type> {
+                vec![R::ir_type()]
+            }
+
+            unsafe fn from_ptr(ptr: *const u8) -> Self {
+                std::mem::transmute::<*const u8, Self>(ptr)
+            }
+        }
+    )*};
+}
+
+jit_fns! {
+    ()
+    (A)
+    (A, B)
+    (A, B, C)
+    (A, B, C, D)
+    (A, B, C, D, E)
+    (A, B, C, D, E, F)
+    (A, B, C, D, E, F, G)
+    (A, B, C, D, E, F, G, H)
+}
+
+/// A finalized function of the type `F` together with the module its code is in, which can't be
+/// freed or reset while the function can be called.
+///
+/// Dropping it frees the memory of the module, which invalidates the pointers to its functions
+/// and data objects retrieved from it before.
+pub struct OwnedJitFn<F: JitFn> {
+    module: ManuallyDrop<JITModule>,
+    func: F,
+}
+
+impl<F: JitFn> OwnedJitFn<F> {
+    pub(crate) fn new(module: JITModule, func: F) -> Self {
+        Self {
+            module: ManuallyDrop::new(module),
+            func,
+        }
+    }
+
+    /// The module the function is in, to look up the other finalized functions and data objects
+    /// it defines, which are valid as long as `self`.
+    pub fn module(&self) -> &JITModule {
+        &self.module
+    }
+}
+
+impl<F: JitFn> Drop for OwnedJitFn<F> {
+    fn drop(&mut self) {
+        // Safety: the function can't be called any more, and the safety contract of
+        // `finalize_into_owned_fn` rules out using the other pointers into the module.
+        unsafe { ManuallyDrop::take(&mut self.module).free_memory() };
+    }
+}
+
+impl<F: JitFn> fmt::Debug for OwnedJitFn<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OwnedJitFn")
+            .field("type", &std::any::type_name::<F>())
+            .finish_non_exhaustive()
+    }
+}
+
+macro_rules! owned_calls {
+    ($(($($arg:ident: $param:ident),*))*) => {$(
+        impl<$($param,)* R> OwnedJitFn<extern "C" fn($($param),*) -> R>
+        where
+            extern "C" fn($($param),*) -> R: JitFn,
+        {
+            /// Call the function.
+            // The arguments are those of the function pointer, up to eight of them.
+            #[allow(clippy::too_many_arguments)]
+            pub fn call(&self, $($arg: $param),*) -> R {
+                (self.func)($($arg),*)
+            }
+        }
+    )*};
+}
+
+owned_calls! {
+    ()
+    (a: A)
+    (a: A, b: B)
+    (a: A, b: B, c: C)
+    (a: A, b: B, c: C, d: D)
+    (a: A, b: B, c: C, d: D, e: E)
+    (a: A, b: B, c: C, d: D, e: E, f: F)
+    (a: A, b: B, c: C, d: D, e: E, f: F, g: G)
+    (a: A, b: B, c: C, d: D, e: E, f: F, g: G, h: H)
+}
+
+/// A function whose declared signature doesn't match the function pointer type it was asked
+/// for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SignatureMismatch {
+    /// The name of the function.
+    pub name: String,
+    /// The signature the function was declared with.
+    pub signature: Signature,
+    /// The name of the requested function pointer type.
+    pub requested: &'static str,
+    /// The calling convention of `extern "C"` functions on the host.
+    pub call_conv: CallConv,
+}
+
+impl fmt::Display for SignatureMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "function `{}` has the signature `{}`, which can't be called as `{}`",
+            self.name, self.signature, self.requested
+        )?;
+        if self.signature.call_conv != self.call_conv {
+            write!(
+                f,
+                ": `extern \"C\"` functions use the {} calling convention",
+                self.call_conv
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for SignatureMismatch {}
+
+/// Whether a function with the given `signature` can be called through `F`, where `call_conv`
+/// is the calling convention of `extern "C"` functions.
+pub(crate) fn matches<F: JitFn>(signature: &Signature, call_conv: CallConv) -> bool {
+    // Special parameters such as a struct return pointer change how the others are passed.
+    let normal_types = |params: &[ir::AbiParam]| {
+        params
+            .iter()
+            .map(|param| (param.purpose == ArgumentPurpose::Normal).then_some(param.value_type))
+            .collect::<Option<Vec<_>>>()
+    };
+    signature.call_conv == call_conv
+        && normal_types(&signature.params) == Some(F::params())
+        && normal_types(&signature.returns) == Some(F::returns())
+}
@@ -0,0 +1,65 @@
+//! Unwind tables of JIT-compiled code.
+
+use cranelift_codegen::isa::unwind::table::{FrameLayout, UnwindTable};
+use cranelift_module::FuncId;
+use std::collections::BTreeMap;
+
+/// One frame of JIT-compiled code found by
+/// [`JITModule::jit_backtrace`](crate::JITModule::jit_backtrace).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct JitFrame {
+    func_id: FuncId,
+    pc: *const u8,
+    sp: usize,
+}
+
+impl JitFrame {
+    pub(crate) fn new(func_id: FuncId, pc: *const u8, sp: usize) -> Self {
+        Self { func_id, pc, sp }
+    }
+
+    /// The function the frame belongs to.
+    pub fn func_id(&self) -> FuncId {
+        self.func_id
+    }
+
+    /// The program counter in the function: the address of the instruction being executed in
+    /// the innermost frame, and the return address of the call in progress in the others.
+    pub fn pc(&self) -> *const u8 {
+        self.pc
+    }
+
+    /// The value of the stack pointer in the frame at `pc`.
+    pub fn sp(&self) -> usize {
+        self.sp
+    }
+}
+
+/// The unwind tables of all functions defined in a `JITModule`, keyed by the address of their
+/// code.
+#[derive(Default)]
+pub(crate) struct UnwindTables {
+    tables: BTreeMap<usize, (FuncId, UnwindTable)>,
+}
+
+impl UnwindTables {
+    /// Record the unwind table of the function `func_id` whose code was placed at `code`.
+    pub(crate) fn insert(&mut self, code: *const u8, func_id: FuncId, table: UnwindTable) {
+        self.tables.insert(code as usize, (func_id, table));
+    }
+
+    /// Forget the unwind table of the function whose code was placed at `code`.
+    pub(crate) fn remove(&mut self, code: *const u8) {
+        self.tables.remove(&(code as usize));
+    }
+
+    /// Find the function containing `pc` and the layout of its frame there.
+    ///
+    /// This doesn't allocate, so it can be called from a signal handler.
+    pub(crate) fn get(&self, pc: *const u8) -> Option<(FuncId, FrameLayout)> {
+        let pc = pc as usize;
+        let (&start, (func_id, table)) = self.tables.range(..=pc).next_back()?;
+        let offset = u32::try_from(pc - start).ok()?;
+        Some((*func_id, table.unwind_info_at(offset)?))
+    }
+}
@@ -0,0 +1,338 @@
+use cranelift_codegen::ir::*;
+use cranelift_codegen::{CodegenError, Context};
+use cranelift_frontend::*;
+use cranelift_jit::*;
+use cranelift_module::*;
+use std::sync::atomic::{AtomicI64, AtomicU16, AtomicU32, AtomicU64, AtomicU8, Ordering};
+use std::sync::Barrier;
+use std::thread;
+
+mod common;
+use common::jit_module;
+
+const THREADS: usize = 8;
+
+/// Declare and define a function, returning the result of `define_function`.
+fn try_define(
+    module: &mut JITModule,
+    name: &str,
+    params: &[Type],
+    returns: &[Type],
+    body: impl FnOnce(&mut FunctionBuilder, &[Value]),
+) -> ModuleResult<FuncId> {
+    let mut sig = module.make_signature();
+    sig.params = params.iter().map(|&ty| AbiParam::new(ty)).collect();
+    sig.returns = returns.iter().map(|&ty| AbiParam::new(ty)).collect();
+    let id = module.declare_function(name, Linkage::Local, &sig)?;
+
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.append_block_params_for_function_params(block);
+        bcx.switch_to_block(block);
+        let params = bcx.block_params(block).to_vec();
+        body(&mut bcx, &params);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module.define_function(id, &mut ctx)?;
+    Ok(id)
+}
+
+/// Define a function and return its finalized code.
+fn define(
+    module: &mut JITModule,
+    name: &str,
+    params: &[Type],
+    returns: &[Type],
+    body: impl FnOnce(&mut FunctionBuilder, &[Value]),
+) -> *const u8 {
+    let id = try_define(module, name, params, returns, body).unwrap();
+    module.finalize_definitions().unwrap();
+    module.get_finalized_function(id)
+}
+
+/// Emit `for _ in 0..count { body }`, leaving the builder in the block after the loop.
+fn emit_loop(bcx: &mut FunctionBuilder, count: Value, body: impl FnOnce(&mut FunctionBuilder)) {
+    let header = bcx.create_block();
+    let body_block = bcx.create_block();
+    let exit = bcx.create_block();
+    let counter_ty = bcx.func.dfg.value_type(count);
+    bcx.append_block_param(header, counter_ty);
+    bcx.ins().jump(header, &[count]);
+
+    bcx.switch_to_block(header);
+    let remaining = bcx.block_params(header)[0];
+    bcx.ins().brif(remaining, body_block, &[], exit, &[]);
+
+    bcx.switch_to_block(body_block);
+    body(bcx);
+    let remaining = bcx.ins().iadd_imm(remaining, -1);
+    bcx.ins().jump(header, &[remaining]);
+
+    bcx.switch_to_block(exit);
+}
+
+/// `fn(counter: *mut ty, iterations: i64)` doing `iterations` atomic increments of `counter`.
+fn define_fetch_add_loop(module: &mut JITModule, ty: Type) -> extern "C" fn(usize, i64) {
+    let ptr = module.target_config().pointer_type();
+    let code = define(
+        module,
+        &format!("fetch_add_{}", ty),
+        &[ptr, types::I64],
+        &[],
+        |bcx, params| {
+            let (counter, iterations) = (params[0], params[1]);
+            emit_loop(bcx, iterations, |bcx| {
+                let one = bcx.ins().iconst(ty, 1);
+                bcx.ins()
+                    .atomic_rmw(ty, MemFlags::trusted(), AtomicRmwOp::Add, counter, one);
+            });
+            bcx.ins().return_(&[]);
+        },
+    );
+    unsafe { std::mem::transmute(code) }
+}
+
+fn check_fetch_add(ty: Type, counter: usize, read: impl Fn() -> u64) {
+    const ITERATIONS: i64 = 20_000;
+    let mut module = jit_module();
+    let fetch_add = define_fetch_add_loop(&mut module, ty);
+
+    thread::scope(|s| {
+        for _ in 0..THREADS {
+            s.spawn(|| fetch_add(counter, ITERATIONS));
+        }
+    });
+
+    let total = THREADS as u64 * ITERATIONS as u64;
+    let mask = u64::MAX >> (64 - ty.bits());
+    assert_eq!(read(), total & mask, "{} fetch-add lost updates", ty);
+
+    unsafe { module.free_memory() };
+}
+
+#[test]
+fn concurrent_fetch_add() {
+    let counter = AtomicU8::new(0);
+    check_fetch_add(types::I8, counter.as_ptr() as usize, || {
+        counter.load(Ordering::SeqCst).into()
+    });
+    let counter = AtomicU16::new(0);
+    check_fetch_add(types::I16, counter.as_ptr() as usize, || {
+        counter.load(Ordering::SeqCst).into()
+    });
+    let counter = AtomicU32::new(0);
+    check_fetch_add(types::I32, counter.as_ptr() as usize, || {
+        counter.load(Ordering::SeqCst).into()
+    });
+    let counter = AtomicU64::new(0);
+    check_fetch_add(types::I64, counter.as_ptr() as usize, || {
+        counter.load(Ordering::SeqCst)
+    });
+}
+
+#[test]
+fn cas_spinlock() {
+    const ITERATIONS: i64 = 20_000;
+    let mut module = jit_module();
+    let ptr = module.target_config().pointer_type();
+
+    // fn(lock: *mut i32, counter: *mut i64, iterations: i64) incrementing `counter` with plain
+    // loads and stores while holding `lock`.
+    let code = define(
+        &mut module,
+        "locked_increment",
+        &[ptr, ptr, types::I64],
+        &[],
+        |bcx, params| {
+            let (lock, counter, iterations) = (params[0], params[1], params[2]);
+            emit_loop(bcx, iterations, |bcx| {
+                let acquire = bcx.create_block();
+                let locked = bcx.create_block();
+                let zero = bcx.ins().iconst(types::I32, 0);
+                let one = bcx.ins().iconst(types::I32, 1);
+                bcx.ins().jump(acquire, &[]);
+
+                bcx.switch_to_block(acquire);
+                let old = bcx.ins().atomic_cas(MemFlags::trusted(), lock, zero, one);
+                bcx.ins().brif(old, acquire, &[], locked, &[]);
+
+                bcx.switch_to_block(locked);
+                let value = bcx.ins().load(types::I64, MemFlags::trusted(), counter, 0);
+                let value = bcx.ins().iadd_imm(value, 1);
+                bcx.ins().store(MemFlags::trusted(), value, counter, 0);
+                bcx.ins().atomic_store(MemFlags::trusted(), zero, lock);
+            });
+            bcx.ins().return_(&[]);
+        },
+    );
+    let locked_increment: extern "C" fn(usize, usize, i64) = unsafe { std::mem::transmute(code) };
+
+    let lock = AtomicU32::new(0);
+    // Only ever accessed by the JIT-compiled code while holding the lock.
+    let counter = AtomicI64::new(0);
+    let (lock_ptr, counter_ptr) = (lock.as_ptr() as usize, counter.as_ptr() as usize);
+    thread::scope(|s| {
+        for _ in 0..THREADS {
+            s.spawn(|| locked_increment(lock_ptr, counter_ptr, ITERATIONS));
+        }
+    });
+
+    assert_eq!(lock.load(Ordering::SeqCst), 0);
+    assert_eq!(counter.load(Ordering::SeqCst), THREADS as i64 * ITERATIONS);
+
+    unsafe { module.free_memory() };
+}
+
+#[test]
+fn message_passing() {
+    const ROUNDS: usize = 2_000;
+    let mut module = jit_module();
+    let ptr = module.target_config().pointer_type();
+
+    // fn(data: *mut i64, flag: *mut i32, value: i64) { *data = value; atomic_store(flag, 1) }
+    let publish = define(
+        &mut module,
+        "publish",
+        &[ptr, ptr, types::I64],
+        &[],
+        |bcx, params| {
+            let (data, flag, value) = (params[0], params[1], params[2]);
+            bcx.ins().store(MemFlags::trusted(), value, data, 0);
+            let one = bcx.ins().iconst(types::I32, 1);
+            bcx.ins().atomic_store(MemFlags::trusted(), one, flag);
+            bcx.ins().return_(&[]);
+        },
+    );
+    // fn(data: *mut i64, flag: *mut i32) -> i64 { while atomic_load(flag) == 0 {} *data }
+    let consume = define(
+        &mut module,
+        "consume",
+        &[ptr, ptr],
+        &[types::I64],
+        |bcx, params| {
+            let (data, flag) = (params[0], params[1]);
+            let spin = bcx.create_block();
+            let done = bcx.create_block();
+            bcx.ins().jump(spin, &[]);
+
+            bcx.switch_to_block(spin);
+            let ready = bcx.ins().atomic_load(types::I32, MemFlags::trusted(), flag);
+            bcx.ins().brif(ready, done, &[], spin, &[]);
+
+            bcx.switch_to_block(done);
+            let value = bcx.ins().load(types::I64, MemFlags::trusted(), data, 0);
+            bcx.ins().return_(&[value]);
+        },
+    );
+    let publish: extern "C" fn(usize, usize, i64) = unsafe { std::mem::transmute(publish) };
+    let consume: extern "C" fn(usize, usize) -> i64 = unsafe { std::mem::transmute(consume) };
+
+    let data = AtomicI64::new(0);
+    let flag = AtomicU32::new(0);
+    let (data_ptr, flag_ptr) = (data.as_ptr() as usize, flag.as_ptr() as usize);
+    let barrier = Barrier::new(2);
+    thread::scope(|s| {
+        s.spawn(|| {
+            for round in 0..ROUNDS {
+                barrier.wait();
+                publish(data_ptr, flag_ptr, round as i64 + 1);
+                barrier.wait();
+            }
+        });
+        for round in 0..ROUNDS {
+            barrier.wait();
+            assert_eq!(consume(data_ptr, flag_ptr), round as i64 + 1);
+            barrier.wait();
+            flag.store(0, Ordering::SeqCst);
+        }
+    });
+
+    unsafe { module.free_memory() };
+}
+
+#[test]
+fn store_load_ordering() {
+    const ROUNDS: usize = 2_000;
+    let mut module = jit_module();
+    let ptr = module.target_config().pointer_type();
+
+    // fn(store: *mut i32, load: *mut i32) -> i32 { atomic_store(store, 1); atomic_load(load) }
+    let code = define(
+        &mut module,
+        "store_load",
+        &[ptr, ptr],
+        &[types::I32],
+        |bcx, params| {
+            let (store, load) = (params[0], params[1]);
+            let one = bcx.ins().iconst(types::I32, 1);
+            bcx.ins().atomic_store(MemFlags::trusted(), one, store);
+            let value = bcx.ins().atomic_load(types::I32, MemFlags::trusted(), load);
+            bcx.ins().return_(&[value]);
+        },
+    );
+    let store_load: extern "C" fn(usize, usize) -> i32 = unsafe { std::mem::transmute(code) };
+
+    // With sequentially consistent stores and loads, at least one of the two threads must see
+    // the other's store.
+    let (x, y) = (AtomicU32::new(0), AtomicU32::new(0));
+    let (x_ptr, y_ptr) = (x.as_ptr() as usize, y.as_ptr() as usize);
+    let barrier = Barrier::new(2);
+    let seen_by_other = AtomicU32::new(0);
+    thread::scope(|s| {
+        s.spawn(|| {
+            for _ in 0..ROUNDS {
+                barrier.wait();
+                seen_by_other.store(store_load(y_ptr, x_ptr) as u32, Ordering::SeqCst);
+                barrier.wait();
+                barrier.wait();
+            }
+        });
+        for round in 0..ROUNDS {
+            barrier.wait();
+            let seen = store_load(x_ptr, y_ptr);
+            barrier.wait();
+            assert!(
+                seen == 1 || seen_by_other.load(Ordering::SeqCst) == 1,
+                "both threads missed the other's store in round {}",
+                round
+            );
+            x.store(0, Ordering::SeqCst);
+            y.store(0, Ordering::SeqCst);
+            barrier.wait();
+        }
+    });
+
+    unsafe { module.free_memory() };
+}
+
+#[test]
+#[cfg_attr(not(any(target_arch = "x86_64", target_arch = "aarch64")), ignore)]
+fn big_endian_atomics_are_unsupported() {
+    let mut module = jit_module();
+    let ptr = module.target_config().pointer_type();
+    let big = MemFlags::trusted().with_endianness(Endianness::Big);
+
+    let result = try_define(
+        &mut module,
+        "big_endian_rmw",
+        &[ptr, types::I32],
+        &[types::I32],
+        |bcx, params| {
+            let old = bcx
+                .ins()
+                .atomic_rmw(types::I32, big, AtomicRmwOp::Add, params[0], params[1]);
+            bcx.ins().return_(&[old]);
+        },
+    );
+    match result {
+        Err(ModuleError::Compilation(CodegenError::Unsupported(_))) => {}
+        other => panic!("expected an unsupported error, got {:?}", other),
+    }
+
+    unsafe { module.free_memory() };
+}
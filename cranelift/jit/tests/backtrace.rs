@@ -0,0 +1,176 @@
+//! Take a backtrace of JIT-compiled code compiled without frame pointers from a host callback,
+//! using only the unwind tables of `JITModule::unwind_info_at`.
+
+// The shim passing the stack pointer and return address to the callback is written for x86_64.
+#![cfg(all(target_arch = "x86_64", unix))]
+
+use cranelift_codegen::ir::*;
+use cranelift_codegen::Context;
+use cranelift_frontend::*;
+use cranelift_jit::*;
+use cranelift_module::*;
+use std::cell::RefCell;
+
+mod common;
+
+thread_local! {
+    static MODULE: RefCell<*const JITModule> = RefCell::new(std::ptr::null());
+    static FRAMES: RefCell<Vec<JitFrame>> = RefCell::new(Vec::new());
+}
+
+extern "C" {
+    /// Calls `capture_backtrace` with the stack pointer of its caller after the call returns and
+    /// the return address into it.
+    fn capture_backtrace_shim();
+}
+
+std::arch::global_asm!(
+    ".globl capture_backtrace_shim",
+    "capture_backtrace_shim:",
+    "lea rdi, [rsp + 8]",
+    "mov rsi, [rsp]",
+    "jmp {capture}",
+    capture = sym capture_backtrace,
+);
+
+extern "C" fn capture_backtrace(sp: usize, pc: *const u8) {
+    let module = unsafe { &*MODULE.with(|m| *m.borrow()) };
+    let frames = unsafe { module.jit_backtrace(sp, pc) };
+    FRAMES.with(|f| *f.borrow_mut() = frames);
+}
+
+fn jit_module() -> JITModule {
+    let mut builder = common::jit_builder(&[
+        ("preserve_frame_pointers", "false"),
+        ("unwind_info", "true"),
+    ]);
+    builder.symbol("capture_backtrace", capture_backtrace_shim as *const u8);
+    JITModule::new(builder)
+}
+
+fn signature(module: &JITModule, params: usize) -> Signature {
+    let mut sig = module.make_signature();
+    sig.params = vec![AbiParam::new(types::I64); params];
+    sig.returns = vec![AbiParam::new(types::I64)];
+    sig
+}
+
+/// Define `name` taking `params` arguments, which calls `callee` with `callee_params` arguments
+/// or `capture_backtrace` if there's no callee, and returns the sum of the arguments and the
+/// result of the call.
+///
+/// Each function spills its arguments to a stack slot of `slot_size` bytes, so the frames have
+/// different sizes.
+fn define(
+    module: &mut JITModule,
+    name: &str,
+    params: usize,
+    slot_size: u32,
+    callee: Option<(FuncId, usize)>,
+) -> FuncId {
+    let sig = signature(module, params);
+    let id = module.declare_function(name, Linkage::Local, &sig).unwrap();
+
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.append_block_params_for_function_params(block);
+        bcx.switch_to_block(block);
+        let args = bcx.block_params(block).to_vec();
+        let slot =
+            bcx.create_sized_stack_slot(StackSlotData::new(StackSlotKind::ExplicitSlot, slot_size));
+        for (i, &arg) in args.iter().enumerate() {
+            bcx.ins().stack_store(arg, slot, (i * 8) as i32);
+        }
+
+        let result = match callee {
+            Some((callee, callee_params)) => {
+                let callee = module.declare_func_in_func(callee, bcx.func);
+                let callee_args: Vec<_> = (0..callee_params as i64)
+                    .map(|i| bcx.ins().iconst(types::I64, i))
+                    .collect();
+                let call = bcx.ins().call(callee, &callee_args);
+                bcx.inst_results(call)[0]
+            }
+            None => {
+                let sig = module.make_signature();
+                let capture = module
+                    .declare_function("capture_backtrace", Linkage::Import, &sig)
+                    .unwrap();
+                let capture = module.declare_func_in_func(capture, bcx.func);
+                bcx.ins().call(capture, &[]);
+                bcx.ins().iconst(types::I64, 0)
+            }
+        };
+
+        let mut sum = result;
+        for i in 0..args.len() {
+            let arg = bcx.ins().stack_load(types::I64, slot, (i * 8) as i32);
+            sum = bcx.ins().iadd(sum, arg);
+        }
+        bcx.ins().return_(&[sum]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module.define_function(id, &mut ctx).unwrap();
+    id
+}
+
+#[test]
+fn five_frames_without_frame_pointers() {
+    let mut module = jit_module();
+
+    // f1() -> f2(0) -> f3(0, ..., 9) -> f4(0, 1) -> f5() -> capture_backtrace()
+    //
+    // `f2` passes some of the arguments of `f3` on the stack, so its stack pointer is below its
+    // nominal one during the call.
+    let f5 = define(&mut module, "f5", 0, 24, None);
+    let f4 = define(&mut module, "f4", 2, 16, Some((f5, 0)));
+    let f3 = define(&mut module, "f3", 10, 80, Some((f4, 2)));
+    let f2 = define(&mut module, "f2", 1, 8, Some((f3, 10)));
+    let f1 = define(&mut module, "f1", 0, 40, Some((f2, 1)));
+    module.finalize_definitions().unwrap();
+
+    MODULE.with(|m| *m.borrow_mut() = &module);
+    let code = module.get_finalized_function(f1);
+    let f1_fn: extern "C" fn() -> i64 = unsafe { std::mem::transmute(code) };
+    assert_eq!(f1_fn(), 45 + 1);
+    MODULE.with(|m| *m.borrow_mut() = std::ptr::null());
+
+    let frames = FRAMES.with(|f| f.take());
+    let funcs: Vec<FuncId> = frames.iter().map(|frame| frame.func_id()).collect();
+    assert_eq!(funcs, vec![f5, f4, f3, f2, f1]);
+    for pair in frames.windows(2) {
+        assert!(
+            pair[1].sp() > pair[0].sp(),
+            "stack pointers must grow towards older frames"
+        );
+        assert_eq!(module.function_at(pair[1].pc()), Some(pair[1].func_id()));
+    }
+
+    unsafe { module.free_memory() };
+}
+
+#[test]
+fn entry_layout() {
+    use cranelift_codegen::isa::unwind::table::{FrameLayout, RaLocation};
+
+    let mut module = jit_module();
+    let f5 = define(&mut module, "f5", 0, 8, None);
+    module.finalize_definitions().unwrap();
+    let code = module.get_finalized_function(f5);
+
+    // On entry only the return address was pushed.
+    let entry = FrameLayout {
+        cfa_offset_from_sp: 8,
+        ra_location: RaLocation::CfaOffset(-8),
+        fp_location: None,
+    };
+    assert_eq!(module.unwind_info_at(code), Some((f5, entry)));
+    assert_eq!(module.unwind_info_at(std::ptr::null()), None);
+
+    unsafe { module.free_memory() };
+}
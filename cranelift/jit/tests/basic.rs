@@ -7,6 +7,8 @@ use cranelift_frontend::*;
 use cranelift_jit::*;
 use cranelift_module::*;
 
+mod common;
+
 #[test]
 fn error_on_incompatible_sig_in_declare_function() {
     let mut flag_builder = settings::builder();
@@ -30,10 +32,15 @@ fn error_on_incompatible_sig_in_declare_function() {
         .declare_function("abc", Linkage::Local, &sig)
         .unwrap();
     sig.params[0] = AbiParam::new(types::I32);
-    module
+    let err = module
         .declare_function("abc", Linkage::Local, &sig)
         .err()
         .unwrap(); // Make sure this is an error
+    assert_eq!(
+        err.to_string(),
+        "Function abc signature (i32) system_v is incompatible with previous declaration \
+         (i64) system_v"
+    );
 }
 
 fn define_simple_function(module: &mut JITModule) -> FuncId {
@@ -63,7 +70,7 @@ fn define_simple_function(module: &mut JITModule) -> FuncId {
 }
 
 #[test]
-#[should_panic(expected = "Result::unwrap()` on an `Err` value: DuplicateDefinition(\"abc\")")]
+#[should_panic(expected = "function abc can't be redefined without hotswap support")]
 fn panic_on_define_after_finalize() {
     let mut flag_builder = settings::builder();
     flag_builder.set("use_colocated_libcalls", "false").unwrap();
@@ -213,3 +220,247 @@ fn libcall_function() {
 
     module.finalize_definitions().unwrap();
 }
+
+#[test]
+fn frontend_extension_replaced_before_compilation() {
+    let mut module = common::jit_module();
+
+    let mut sig = module.make_signature();
+    sig.params.push(AbiParam::new(types::I32));
+    sig.params.push(AbiParam::new(types::I32));
+    sig.returns.push(AbiParam::new(types::I32));
+
+    let func_id = module
+        .declare_function("double_and_add", Linkage::Local, &sig)
+        .unwrap();
+
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, func_id.as_u32()), sig);
+
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx: FunctionBuilder = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.switch_to_block(block);
+        bcx.append_block_params_for_function_params(block);
+        let x = bcx.block_params(block)[0];
+        let y = bcx.block_params(block)[1];
+
+        // A frontend-specific "x * 2 + y" operation.
+        let res = bcx.ins().ext0(types::I32, &[x, y]);
+        bcx.ins().return_(&[res]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+
+    // Extension instructions are rejected if they reach code generation.
+    let mut unreplaced = Context::for_function(ctx.func.clone());
+    let err = unreplaced
+        .compile(module.isa(), &mut Default::default())
+        .unwrap_err();
+    assert!(
+        format!("{:?}", err.inner).contains("frontend extension instruction"),
+        "{:?}",
+        err.inner
+    );
+
+    let exts = ctx.func.ext_insts();
+    assert_eq!(exts.len(), 1);
+    assert_eq!(exts[0].slot, 0);
+    assert_eq!(exts[0].args.len(), 2);
+
+    let replaced = ctx.func.replace_ext_insts(|pos, ext| {
+        let doubled = pos.ins().iadd(ext.args[0], ext.args[0]);
+        Some(pos.ins().iadd(doubled, ext.args[1]))
+    });
+    assert_eq!(replaced, 1);
+    assert!(ctx.func.ext_insts().is_empty());
+
+    module.define_function(func_id, &mut ctx).unwrap();
+    module.finalize_definitions().unwrap();
+
+    let code = module.get_finalized_function(func_id);
+    let double_and_add = unsafe { std::mem::transmute::<_, extern "C" fn(i32, i32) -> i32>(code) };
+    assert_eq!(double_and_add(20, 2), 42);
+}
+
+/// Define `fn() -> i64 { value }`.
+fn define_constant_function(module: &mut JITModule, name: &str, value: i64) -> FuncId {
+    let mut sig = module.make_signature();
+    sig.returns.push(AbiParam::new(types::I64));
+    let func_id = module.declare_function(name, Linkage::Local, &sig).unwrap();
+
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, func_id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.switch_to_block(block);
+        let value = bcx.ins().iconst(types::I64, value);
+        bcx.ins().return_(&[value]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module.define_function(func_id, &mut ctx).unwrap();
+    func_id
+}
+
+#[test]
+fn appended_data_layout() {
+    for is_pic in ["false", "true"] {
+        let mut module = JITModule::new(common::jit_builder(&[("is_pic", is_pic)]));
+        let pointer_bytes = module.isa().pointer_bytes();
+
+        let funcs = [
+            define_constant_function(&mut module, "ten", 10),
+            define_constant_function(&mut module, "twenty", 20),
+        ];
+
+        // A table of `(u32 tag, padding, fn pointer)` records, followed by a pointer to the tag
+        // of the second record.
+        let table = module
+            .declare_data("table", Linkage::Local, false, false)
+            .unwrap();
+        let mut data = DataDescription::new();
+        for (tag, &func) in [0xaaaa_0001u32, 0xbbbb_0002].iter().zip(&funcs) {
+            data.align_to(8);
+            data.append_bytes(&tag.to_ne_bytes());
+            data.align_to(pointer_bytes.into());
+            data.append_func_addr(func, pointer_bytes);
+        }
+        let record_size = 8 + usize::from(pointer_bytes);
+        let tag_addr = data.append_data_addr(table, record_size as i64, pointer_bytes);
+        assert_eq!(tag_addr as usize, 2 * record_size);
+        assert_eq!(data.len(), 2 * record_size + usize::from(pointer_bytes));
+        module.define_data(table, &data).unwrap();
+        module.finalize_definitions().unwrap();
+
+        let (ptr, len) = module.get_finalized_data(table);
+        assert_eq!(len, data.len());
+        assert_eq!(ptr as usize % 8, 0);
+        let read_pointer = |offset: usize| -> usize {
+            let bytes =
+                unsafe { std::slice::from_raw_parts(ptr.add(offset), pointer_bytes.into()) };
+            match bytes.len() {
+                4 => u32::from_ne_bytes(bytes.try_into().unwrap()) as usize,
+                _ => u64::from_ne_bytes(bytes.try_into().unwrap()) as usize,
+            }
+        };
+        for (i, (tag, &func)) in [0xaaaa_0001u32, 0xbbbb_0002].iter().zip(&funcs).enumerate() {
+            let record = i * record_size;
+            let bytes = unsafe { std::slice::from_raw_parts(ptr.add(record), 8) };
+            assert_eq!(&bytes[..4], &tag.to_ne_bytes());
+            assert_eq!(&bytes[4..], &[0; 4]);
+            let code = read_pointer(record + 8);
+            assert_eq!(code, module.get_finalized_function(func) as usize);
+            let f = unsafe { std::mem::transmute::<usize, extern "C" fn() -> i64>(code) };
+            assert_eq!(f(), 10 * (i as i64 + 1));
+        }
+        assert_eq!(read_pointer(tag_addr as usize), ptr as usize + record_size);
+    }
+}
+
+#[test]
+fn strip_unreachable_is_unsupported() {
+    let mut module = common::jit_module();
+    let unused = define_constant_function(&mut module, "unused", 1);
+
+    // Definitions of a JIT module may already be running, so none of them are dropped.
+    assert!(module.strip_unreachable(&[]).is_err());
+    module.finalize_definitions().unwrap();
+    let unused = module.get_finalized_function(unused);
+    let unused = unsafe { std::mem::transmute::<*const u8, extern "C" fn() -> i64>(unused) };
+    assert_eq!(unused(), 1);
+}
+
+#[test]
+fn unresolved_import() {
+    for is_pic in ["false", "true"] {
+        let mut module = JITModule::new(common::jit_builder(&[("is_pic", is_pic)]));
+
+        let mut sig = module.make_signature();
+        sig.returns.push(AbiParam::new(types::I64));
+        let missing = module
+            .declare_function("missing_host_function", Linkage::Import, &sig)
+            .unwrap();
+        let caller = module
+            .declare_function("caller", Linkage::Local, &sig)
+            .unwrap();
+        let mut ctx = Context::new();
+        ctx.func = Function::with_name_signature(UserFuncName::user(0, caller.as_u32()), sig);
+        let mut func_ctx = FunctionBuilderContext::new();
+        {
+            let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+            let callee = module.declare_func_in_func(missing, bcx.func);
+            let block = bcx.create_block();
+            bcx.switch_to_block(block);
+            let call = bcx.ins().call(callee, &[]);
+            let value = bcx.inst_results(call)[0];
+            bcx.ins().return_(&[value]);
+            bcx.seal_all_blocks();
+            bcx.finalize();
+        }
+        module.define_function(caller, &mut ctx).unwrap();
+
+        // The call isn't left calling a null address.
+        let err = module.finalize_definitions().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Backend error: can't resolve symbol missing_host_function"
+        );
+        unsafe { module.free_memory() };
+    }
+}
+
+/// Define `fn(x: i64) -> i64` computing a polynomial of `x` with `terms` terms.
+fn define_polynomial(module: &mut JITModule, name: &str, terms: i64) -> FuncId {
+    let mut sig = module.make_signature();
+    sig.params.push(AbiParam::new(types::I64));
+    sig.returns.push(AbiParam::new(types::I64));
+    let func_id = module.declare_function(name, Linkage::Local, &sig).unwrap();
+
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, func_id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.append_block_params_for_function_params(block);
+        bcx.switch_to_block(block);
+        let x = bcx.block_params(block)[0];
+        let mut acc = bcx.ins().iconst(types::I64, 1);
+        for coefficient in 2..terms + 2 {
+            acc = bcx.ins().imul(acc, x);
+            acc = bcx.ins().iadd_imm(acc, coefficient * 0x1_0001);
+        }
+        bcx.ins().return_(&[acc]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module.define_function(func_id, &mut ctx).unwrap();
+    func_id
+}
+
+#[test]
+fn finalized_function_info() {
+    let mut module = JITModule::new(JITBuilder::new(default_libcall_names()).unwrap());
+    let big = define_polynomial(&mut module, "big", 64);
+    let small = define_polynomial(&mut module, "small", 1);
+    module.finalize_definitions().unwrap();
+    // Functions finalized later don't change those finalized before.
+    let constant = define_constant_function(&mut module, "constant", 7);
+    module.finalize_definitions().unwrap();
+
+    let min_alignment = module.isa().function_alignment().minimum.into();
+    let infos = [big, small, constant].map(|id| {
+        let info = module.get_finalized_function_info(id);
+        assert_eq!(info.ptr, module.get_finalized_function(id));
+        assert!(info.size > 0);
+        assert!(info.alignment >= min_alignment);
+        assert_eq!(info.ptr as u64 % info.alignment, 0);
+        info
+    });
+    assert!(infos[0].size > infos[1].size, "{infos:?}");
+    unsafe { module.free_memory() };
+}
@@ -0,0 +1,179 @@
+use cranelift_codegen::ir::*;
+use cranelift_frontend::*;
+use cranelift_jit::*;
+use cranelift_module::*;
+
+mod common;
+use common::jit_module;
+
+fn signature(module: &JITModule) -> Signature {
+    let mut sig = module.make_signature();
+    sig.params.push(AbiParam::new(types::I64));
+    sig.returns.push(AbiParam::new(types::I64));
+    sig
+}
+
+/// Build `fn(n: i64) -> i64` returning `base` if `n` is 0, and `combine(n, callee(n - 1))`
+/// otherwise.
+fn build_recursive(
+    batch: &mut JITBatch,
+    id: FuncId,
+    callee: FuncId,
+    base: i64,
+    combine: fn(&mut FunctionBuilder, Value, Value) -> Value,
+) -> Function {
+    let sig = signature(batch.module());
+    let mut func = Function::with_name_signature(UserFuncName::user(0, id.as_u32()), sig);
+    let callee = batch.declare_func_in_func(callee, &mut func);
+    let mut func_ctx = FunctionBuilderContext::new();
+    let mut bcx = FunctionBuilder::new(&mut func, &mut func_ctx);
+    let entry = bcx.create_block();
+    let recurse = bcx.create_block();
+    let done = bcx.create_block();
+    bcx.append_block_params_for_function_params(entry);
+    bcx.append_block_param(done, types::I64);
+
+    bcx.switch_to_block(entry);
+    let n = bcx.block_params(entry)[0];
+    let base = bcx.ins().iconst(types::I64, base);
+    bcx.ins().brif(n, recurse, &[], done, &[base]);
+
+    bcx.switch_to_block(recurse);
+    let n_minus_one = bcx.ins().iadd_imm(n, -1);
+    let call = bcx.ins().call(callee, &[n_minus_one]);
+    let result = bcx.inst_results(call)[0];
+    let result = combine(&mut bcx, n, result);
+    bcx.ins().jump(done, &[result]);
+
+    bcx.switch_to_block(done);
+    let result = bcx.block_params(done)[0];
+    bcx.ins().return_(&[result]);
+    bcx.seal_all_blocks();
+    bcx.finalize();
+    func
+}
+
+fn call(module: &JITModule, id: FuncId, n: i64) -> i64 {
+    let code = module.get_finalized_function(id);
+    let f = unsafe { std::mem::transmute::<*const u8, extern "C" fn(i64) -> i64>(code) };
+    f(n)
+}
+
+#[test]
+fn self_recursive_function() {
+    let mut module = jit_module();
+    let sig = signature(&module);
+    let factorial = module
+        .declare_function("factorial", Linkage::Local, &sig)
+        .unwrap();
+    module
+        .define_batch(|batch| {
+            let func = build_recursive(batch, factorial, factorial, 1, |bcx, n, rest| {
+                bcx.ins().imul(n, rest)
+            });
+            batch.define(factorial, func);
+        })
+        .unwrap();
+    assert_eq!(call(&module, factorial, 0), 1);
+    assert_eq!(call(&module, factorial, 10), 3628800);
+}
+
+#[test]
+fn mutually_recursive_functions() {
+    let mut module = jit_module();
+    let sig = signature(&module);
+    let is_even = module
+        .declare_function("is_even", Linkage::Local, &sig)
+        .unwrap();
+    let is_odd = module
+        .declare_function("is_odd", Linkage::Export, &sig)
+        .unwrap();
+    let keep = |_: &mut FunctionBuilder, _: Value, rest: Value| rest;
+    // The order the functions are added in doesn't matter.
+    let defined = module
+        .define_batch(|batch| {
+            let odd = build_recursive(batch, is_odd, is_even, 0, keep);
+            batch.define(is_odd, odd);
+            let even = build_recursive(batch, is_even, is_odd, 1, keep);
+            batch.define(is_even, even);
+            2
+        })
+        .unwrap();
+    assert_eq!(defined, 2);
+    assert_eq!(call(&module, is_even, 10), 1);
+    assert_eq!(call(&module, is_even, 7), 0);
+    assert_eq!(call(&module, is_odd, 7), 1);
+
+    // Later batches can call the functions of earlier ones.
+    let twice_even = module
+        .declare_function("twice_even", Linkage::Local, &sig)
+        .unwrap();
+    module
+        .define_batch(|batch| {
+            let func = build_recursive(batch, twice_even, is_even, 0, |bcx, _, rest| {
+                bcx.ins().iadd(rest, rest)
+            });
+            batch.define(twice_even, func);
+        })
+        .unwrap();
+    assert_eq!(call(&module, twice_even, 5), 2);
+}
+
+#[test]
+fn dangling_reference() {
+    let mut module = jit_module();
+    let sig = signature(&module);
+    let caller = module
+        .declare_function("caller", Linkage::Local, &sig)
+        .unwrap();
+    let missing = module
+        .declare_function("missing", Linkage::Local, &sig)
+        .unwrap();
+    let other = module
+        .declare_function("other", Linkage::Local, &sig)
+        .unwrap();
+    let err = module
+        .define_batch(|batch| {
+            let func = build_recursive(batch, caller, missing, 0, |_, _, rest| rest);
+            batch.define(caller, func);
+            let func = build_recursive(batch, other, caller, 0, |_, _, rest| rest);
+            batch.define(other, func);
+        })
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "the batch calls undefined functions: caller calls missing"
+    );
+    match err {
+        BatchError::Unresolved(references) => assert_eq!(
+            references,
+            [UnresolvedReference {
+                caller: "caller".to_string(),
+                callee: "missing".to_string(),
+            }]
+        ),
+        err => panic!("unexpected error {}", err),
+    }
+
+    // Nothing was defined, so the batch can be fixed up.
+    module
+        .define_batch(|batch| {
+            let func = build_recursive(batch, missing, missing, 3, |_, _, rest| rest);
+            batch.define(missing, func);
+            let func = build_recursive(batch, caller, missing, 0, |_, _, rest| rest);
+            batch.define(caller, func);
+        })
+        .unwrap();
+    assert_eq!(call(&module, caller, 4), 3);
+
+    let err = module
+        .define_batch(|batch| {
+            let func = build_recursive(batch, caller, missing, 0, |_, _, rest| rest);
+            batch.define(caller, func);
+        })
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        BatchError::Module(ModuleError::DuplicateDefinition(name)) if name == "caller"
+    ));
+}
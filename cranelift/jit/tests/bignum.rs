@@ -0,0 +1,248 @@
+//! Check multi-word arithmetic built from CLIF ops against a Rust reference, on random and
+//! corner-case inputs.
+
+// `umulhi.i128` is only lowered on these.
+#![cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::*;
+use cranelift_codegen::Context;
+use cranelift_frontend::*;
+use cranelift_jit::*;
+use cranelift_module::*;
+
+mod common;
+use common::jit_module;
+
+fn define(
+    module: &mut JITModule,
+    name: &str,
+    returns: &[Type],
+    body: impl FnOnce(&mut FunctionBuilder, &[Value]),
+) -> FuncId {
+    // All functions take the addresses of their two operands and of their result.
+    let ptr = module.target_config().pointer_type();
+    let mut sig = module.make_signature();
+    sig.params = vec![AbiParam::new(ptr); 3];
+    sig.returns = returns.iter().map(|&ty| AbiParam::new(ty)).collect();
+    let id = module.declare_function(name, Linkage::Local, &sig).unwrap();
+
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.append_block_params_for_function_params(block);
+        bcx.switch_to_block(block);
+        let params = bcx.block_params(block).to_vec();
+        body(&mut bcx, &params);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module.define_function(id, &mut ctx).unwrap();
+    id
+}
+
+/// `fn add256(a: &[u64; 4], b: &[u64; 4], out: &mut [u64; 4]) -> bool`, detecting the carries
+/// with `icmp ult` on the sums, and returning the carry out.
+fn define_add256(module: &mut JITModule) -> FuncId {
+    define(module, "add256", &[types::I8], |bcx, params| {
+        let flags = MemFlags::trusted();
+        let mut carry = None;
+        for limb in 0..4 {
+            let offset = limb * 8;
+            let a = bcx.ins().load(types::I64, flags, params[0], offset);
+            let b = bcx.ins().load(types::I64, flags, params[1], offset);
+            let sum = bcx.ins().iadd(a, b);
+            let mut carry_out = bcx.ins().icmp(IntCC::UnsignedLessThan, sum, a);
+            let sum = match carry {
+                Some(carry_in) => {
+                    let carry_in = bcx.ins().uextend(types::I64, carry_in);
+                    let sum_in = bcx.ins().iadd(sum, carry_in);
+                    let carry_in_out = bcx.ins().icmp(IntCC::UnsignedLessThan, sum_in, sum);
+                    carry_out = bcx.ins().bor(carry_out, carry_in_out);
+                    sum_in
+                }
+                None => sum,
+            };
+            bcx.ins().store(flags, sum, params[2], offset);
+            carry = Some(carry_out);
+        }
+        bcx.ins().return_(&[carry.unwrap()]);
+    })
+}
+
+/// `fn mul128(a: &u128, b: &u128, out: &mut [u128; 2])`, with `imul` and `umulhi` on `i128`.
+fn define_mul128(module: &mut JITModule) -> FuncId {
+    define(module, "mul128", &[], |bcx, params| {
+        let flags = MemFlags::trusted();
+        let a = bcx.ins().load(types::I128, flags, params[0], 0);
+        let b = bcx.ins().load(types::I128, flags, params[1], 0);
+        let lo = bcx.ins().imul(a, b);
+        let hi = bcx.ins().umulhi(a, b);
+        bcx.ins().store(flags, lo, params[2], 0);
+        bcx.ins().store(flags, hi, params[2], 16);
+        bcx.ins().return_(&[]);
+    })
+}
+
+/// `fn smulhi128(a: &i128, b: &i128, out: &mut i128)`.
+fn define_smulhi128(module: &mut JITModule) -> FuncId {
+    define(module, "smulhi128", &[], |bcx, params| {
+        let flags = MemFlags::trusted();
+        let a = bcx.ins().load(types::I128, flags, params[0], 0);
+        let b = bcx.ins().load(types::I128, flags, params[1], 0);
+        let hi = bcx.ins().smulhi(a, b);
+        bcx.ins().store(flags, hi, params[2], 0);
+        bcx.ins().return_(&[]);
+    })
+}
+
+/// A 256-bit unsigned integer, as little-endian 64-bit limbs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(transparent)]
+struct U256([u64; 4]);
+
+impl U256 {
+    fn from_halves(lo: u128, hi: u128) -> Self {
+        Self([lo as u64, (lo >> 64) as u64, hi as u64, (hi >> 64) as u64])
+    }
+
+    fn hi(self) -> u128 {
+        (self.0[3] as u128) << 64 | self.0[2] as u128
+    }
+
+    fn wrapping_neg(self) -> Self {
+        let inverted = Self(self.0.map(|limb| !limb));
+        inverted.overflowing_add(Self([1, 0, 0, 0])).0
+    }
+
+    fn overflowing_add(self, other: Self) -> (Self, bool) {
+        let mut sum = [0; 4];
+        let mut carry = 0;
+        for (limb, (&x, &y)) in sum.iter_mut().zip(self.0.iter().zip(&other.0)) {
+            let wide = x as u128 + y as u128 + carry;
+            *limb = wide as u64;
+            carry = wide >> 64;
+        }
+        (Self(sum), carry != 0)
+    }
+
+    /// The full product of `a` and `b`, by schoolbook multiplication of 64-bit limbs.
+    fn widening_mul(a: u128, b: u128) -> Self {
+        let a = [a as u64, (a >> 64) as u64];
+        let b = [b as u64, (b >> 64) as u64];
+        let mut product = [0; 4];
+        for i in 0..2 {
+            let mut carry = 0;
+            for j in 0..2 {
+                let limb = a[i] as u128 * b[j] as u128 + product[i + j] as u128 + carry;
+                product[i + j] = limb as u64;
+                carry = limb >> 64;
+            }
+            product[i + 2] = carry as u64;
+        }
+        Self(product)
+    }
+}
+
+/// A xorshift64* generator, so failures are reproducible.
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn next_u128(&mut self) -> u128 {
+        (self.next_u64() as u128) << 64 | self.next_u64() as u128
+    }
+}
+
+const CORNER_CASES: [u128; 10] = [
+    0,
+    1,
+    2,
+    u64::MAX as u128,
+    1 << 64,
+    (1 << 64) + 1,
+    i128::MAX as u128,
+    1 << 127,
+    u128::MAX - 1,
+    u128::MAX,
+];
+
+/// Every pair of corner cases, followed by random pairs.
+fn inputs() -> impl Iterator<Item = (u128, u128)> {
+    let corners = CORNER_CASES
+        .iter()
+        .flat_map(|&a| CORNER_CASES.iter().map(move |&b| (a, b)));
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    let random = std::iter::repeat_with(move || (rng.next_u128(), rng.next_u128())).take(1000);
+    corners.chain(random)
+}
+
+#[test]
+fn add256() {
+    let mut module = jit_module();
+    let add256 = define_add256(&mut module);
+    module.finalize_definitions().unwrap();
+    let add256: extern "C" fn(&U256, &U256, &mut U256) -> i8 =
+        unsafe { std::mem::transmute(module.get_finalized_function(add256)) };
+
+    let mut halves = inputs();
+    while let (Some((a_lo, a_hi)), Some((b_lo, b_hi))) = (halves.next(), halves.next()) {
+        let a = U256::from_halves(a_lo, a_hi);
+        let b = U256::from_halves(b_lo, b_hi);
+        let (expected, expected_carry) = a.overflowing_add(b);
+        let mut sum = U256([0; 4]);
+        let carry = add256(&a, &b, &mut sum);
+        assert_eq!(
+            (sum, carry != 0),
+            (expected, expected_carry),
+            "{a:x?} + {b:x?}"
+        );
+    }
+
+    unsafe { module.free_memory() };
+}
+
+#[test]
+fn mul128() {
+    let mut module = jit_module();
+    let mul128 = define_mul128(&mut module);
+    let smulhi128 = define_smulhi128(&mut module);
+    module.finalize_definitions().unwrap();
+    let mul128: extern "C" fn(&u128, &u128, &mut [u128; 2]) =
+        unsafe { std::mem::transmute(module.get_finalized_function(mul128)) };
+    let smulhi128: extern "C" fn(&i128, &i128, &mut i128) =
+        unsafe { std::mem::transmute(module.get_finalized_function(smulhi128)) };
+
+    for (a, b) in inputs() {
+        let mut product = [0; 2];
+        mul128(&a, &b, &mut product);
+        assert_eq!(
+            U256::from_halves(product[0], product[1]),
+            U256::widening_mul(a, b),
+            "{a:#x} * {b:#x}"
+        );
+
+        // The signed product is the product of the magnitudes, negated if the signs differ.
+        let (sa, sb) = (a as i128, b as i128);
+        let magnitude = U256::widening_mul(sa.unsigned_abs(), sb.unsigned_abs());
+        let expected = if (sa < 0) != (sb < 0) {
+            magnitude.wrapping_neg()
+        } else {
+            magnitude
+        };
+        let mut hi = 0;
+        smulhi128(&sa, &sb, &mut hi);
+        assert_eq!(hi as u128, expected.hi(), "smulhi {sa:#x} * {sb:#x}");
+    }
+
+    unsafe { module.free_memory() };
+}
@@ -0,0 +1,178 @@
+#![cfg(feature = "incremental-cache")]
+
+use cranelift_codegen::ir::*;
+use cranelift_codegen::Context;
+use cranelift_frontend::*;
+use cranelift_jit::*;
+use cranelift_module::*;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+mod common;
+
+/// Counts the functions compiled by running codegen and those reusing cached code.
+#[derive(Default)]
+struct Counts {
+    codegen: AtomicU64,
+    reused: AtomicU64,
+}
+
+impl Counts {
+    /// The counts since the previous call, as `(codegen, reused)`.
+    fn take(&self) -> (u64, u64) {
+        (
+            self.codegen.swap(0, Ordering::SeqCst),
+            self.reused.swap(0, Ordering::SeqCst),
+        )
+    }
+}
+
+impl CompileObserver for Counts {
+    fn codegen(&self, _name: &UserFuncName) {
+        self.codegen.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn reused(&self, _name: &UserFuncName) {
+        self.reused.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+fn jit_module(cache: SaltedModuleCache, counts: &Arc<Counts>) -> JITModule {
+    let mut builder = common::jit_builder(&[("is_pic", "true")]);
+    builder.cache(cache.with_observer(counts.clone()));
+    JITModule::new(builder)
+}
+
+/// Define `fn times_three(x: i64) -> i64 { x * 3 }` and
+/// `fn plus_one_times_three(x: i64) -> i64 { times_three(x + 1) }`, returning the code of both
+/// along with the ids of the functions.
+fn define_functions(module: &mut JITModule, ctx: &mut Context) -> (FuncId, Vec<u8>, Vec<u8>) {
+    let mut sig = module.make_signature();
+    sig.params.push(AbiParam::new(types::I64));
+    sig.returns.push(AbiParam::new(types::I64));
+    let times_three = module
+        .declare_function("times_three", Linkage::Local, &sig)
+        .unwrap();
+    let plus_one_times_three = module
+        .declare_function("plus_one_times_three", Linkage::Local, &sig)
+        .unwrap();
+    let mut func_ctx = FunctionBuilderContext::new();
+
+    ctx.func =
+        Function::with_name_signature(UserFuncName::user(0, times_three.as_u32()), sig.clone());
+    {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.append_block_params_for_function_params(block);
+        bcx.switch_to_block(block);
+        let x = bcx.block_params(block)[0];
+        let result = bcx.ins().imul_imm(x, 3);
+        bcx.ins().return_(&[result]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module.define_function(times_three, ctx).unwrap();
+    let times_three_code = ctx.compiled_code().unwrap().code_buffer().to_vec();
+    module.clear_context(ctx);
+
+    ctx.func =
+        Function::with_name_signature(UserFuncName::user(0, plus_one_times_three.as_u32()), sig);
+    let callee = module.declare_func_in_func(times_three, &mut ctx.func);
+    {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.append_block_params_for_function_params(block);
+        bcx.switch_to_block(block);
+        let x = bcx.block_params(block)[0];
+        let x = bcx.ins().iadd_imm(x, 1);
+        let call = bcx.ins().call(callee, &[x]);
+        let result = bcx.inst_results(call)[0];
+        bcx.ins().return_(&[result]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module.define_function(plus_one_times_three, ctx).unwrap();
+    let caller_code = ctx.compiled_code().unwrap().code_buffer().to_vec();
+    module.clear_context(ctx);
+
+    (plus_one_times_three, times_three_code, caller_code)
+}
+
+fn run(module: &mut JITModule, id: FuncId, x: i64) -> i64 {
+    module.finalize_definitions().unwrap();
+    let func: extern "C" fn(i64) -> i64 =
+        unsafe { std::mem::transmute(module.get_finalized_function(id)) };
+    func(x)
+}
+
+#[test]
+fn reuse_across_modules() {
+    let cache = Arc::new(InMemoryModuleCache::new());
+    let counts = Arc::new(Counts::default());
+
+    let mut module = jit_module(SaltedModuleCache::new(cache.clone(), b"v1"), &counts);
+    let mut ctx = module.make_context();
+    let (id, callee_code, caller_code) = define_functions(&mut module, &mut ctx);
+    assert_eq!(counts.take(), (2, 0));
+    assert_eq!(cache.len(), 2);
+    assert_eq!(run(&mut module, id, 4), 15);
+
+    // Declaring another function first gives the functions different ids, so reusing the code
+    // relies on relocations being applied to the new ids.
+    let mut module = jit_module(SaltedModuleCache::new(cache.clone(), b"v1"), &counts);
+    let mut sig = module.make_signature();
+    sig.params.push(AbiParam::new(types::I64));
+    module
+        .declare_function("unused", Linkage::Import, &sig)
+        .unwrap();
+    let mut ctx = module.make_context();
+    let (id, cached_callee_code, cached_caller_code) = define_functions(&mut module, &mut ctx);
+    assert_eq!(counts.take(), (0, 2));
+    assert_eq!(cache.len(), 2);
+    assert_eq!(cached_callee_code, callee_code);
+    assert_eq!(cached_caller_code, caller_code);
+    assert_eq!(run(&mut module, id, 4), 15);
+    assert_eq!(run(&mut module, id, -1), 0);
+}
+
+#[test]
+fn salt_is_part_of_the_key() {
+    let cache = Arc::new(InMemoryModuleCache::new());
+    let counts = Arc::new(Counts::default());
+
+    let mut module = jit_module(SaltedModuleCache::new(cache.clone(), b"v1"), &counts);
+    let mut ctx = module.make_context();
+    define_functions(&mut module, &mut ctx);
+    assert_eq!(counts.take(), (2, 0));
+
+    let mut module = jit_module(SaltedModuleCache::new(cache.clone(), b"v2"), &counts);
+    let mut ctx = module.make_context();
+    let (id, _, _) = define_functions(&mut module, &mut ctx);
+    assert_eq!(counts.take(), (2, 0));
+    assert_eq!(cache.len(), 4);
+    assert_eq!(run(&mut module, id, 1), 6);
+}
+
+#[test]
+fn directory_cache() {
+    let dir = std::env::temp_dir().join(format!("cranelift-jit-cache-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let counts = Arc::new(Counts::default());
+    let cache: Arc<dyn ModuleCache> = Arc::new(DirectoryModuleCache::new(&dir).unwrap());
+    let mut module = jit_module(SaltedModuleCache::new(cache, b""), &counts);
+    let mut ctx = module.make_context();
+    define_functions(&mut module, &mut ctx);
+    assert_eq!(counts.take(), (2, 0));
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+    // A new cache using the same directory sees the entries of the first one.
+    let cache: Arc<dyn ModuleCache> = Arc::new(DirectoryModuleCache::new(&dir).unwrap());
+    let mut module = jit_module(SaltedModuleCache::new(cache, b""), &counts);
+    let mut ctx = module.make_context();
+    let (id, _, _) = define_functions(&mut module, &mut ctx);
+    assert_eq!(counts.take(), (0, 2));
+    assert_eq!(run(&mut module, id, 2), 9);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,50 @@
+//! Fixtures shared by the cranelift-jit integration tests.
+
+// Each test binary compiles this module on its own and uses only some of it.
+#![allow(dead_code)]
+
+use cranelift_codegen::isa::{self, OwnedTargetIsa};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::default_libcall_names;
+
+/// The flags of the tests, with `flags` set after the defaults: `use_colocated_libcalls` and
+/// `is_pic` off.
+fn test_flags(flags: &[(&str, &str)]) -> settings::Flags {
+    let mut flag_builder = settings::builder();
+    flag_builder.set("use_colocated_libcalls", "false").unwrap();
+    flag_builder.set("is_pic", "false").unwrap();
+    for (name, value) in flags {
+        flag_builder.set(name, value).unwrap();
+    }
+    settings::Flags::new(flag_builder)
+}
+
+/// Create an ISA for the host, with `flags` set after the defaults of the tests:
+/// `use_colocated_libcalls` and `is_pic` off.
+pub fn host_isa(flags: &[(&str, &str)]) -> OwnedTargetIsa {
+    let isa_builder = cranelift_native::builder().unwrap_or_else(|msg| {
+        panic!("host machine is not supported: {}", msg);
+    });
+    isa_builder.finish(test_flags(flags)).unwrap()
+}
+
+/// Create a `JITBuilder` for the host, with `flags` set after the defaults of the tests.
+pub fn jit_builder(flags: &[(&str, &str)]) -> JITBuilder {
+    JITBuilder::with_isa(host_isa(flags), default_libcall_names())
+}
+
+/// Create a `JITModule` for the host with the default flags of the tests.
+pub fn jit_module() -> JITModule {
+    JITModule::new(jit_builder(&[]))
+}
+
+/// Create an ISA for the host triple with only the ISA features of `features` enabled, rather
+/// than those detected on the host, and `flags` set after the defaults of the tests.
+pub fn isa_with_features(features: &[&str], flags: &[(&str, &str)]) -> OwnedTargetIsa {
+    let mut isa_builder = isa::lookup(target_lexicon::Triple::host()).unwrap();
+    for feature in features {
+        isa_builder.enable(feature).unwrap();
+    }
+    isa_builder.finish(test_flags(flags)).unwrap()
+}
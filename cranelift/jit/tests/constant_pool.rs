@@ -0,0 +1,108 @@
+//! Check that `JITModule` pools the vector constants of all functions into read-only memory.
+
+use cranelift_codegen::ir::*;
+use cranelift_codegen::Context;
+use cranelift_frontend::*;
+use cranelift_jit::*;
+use cranelift_module::*;
+
+mod common;
+
+const FUNCTIONS: usize = 100;
+
+/// The vector constants shared by the functions, whose first lane is returned.
+const CONSTANTS: [[i64; 2]; 4] = [[1, 2], [3, 4], [5, 6], [7, 8]];
+
+fn jit_module(pool_constants: bool) -> JITModule {
+    let mut builder = common::jit_builder(&[]);
+    builder.pool_constants(pool_constants);
+    JITModule::new(builder)
+}
+
+/// Define a function returning the sum of its argument and the first lane of `constant`.
+fn define_function(module: &mut JITModule, constant: [i64; 2]) -> FuncId {
+    let mut sig = module.make_signature();
+    sig.params = vec![AbiParam::new(types::I64)];
+    sig.returns = vec![AbiParam::new(types::I64)];
+    let id = module.declare_anonymous_function(&sig).unwrap();
+
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.append_block_params_for_function_params(block);
+        bcx.switch_to_block(block);
+        let x = bcx.block_params(block)[0];
+        let bytes = constant
+            .iter()
+            .flat_map(|lane| lane.to_le_bytes())
+            .collect::<Vec<_>>();
+        let constant = bcx.func.dfg.constants.insert(bytes.into());
+        let v = bcx.ins().vconst(types::I64X2, constant);
+        let lane = bcx.ins().extractlane(v, 0);
+        let sum = bcx.ins().iadd(x, lane);
+        bcx.ins().return_(&[sum]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module.define_function(id, &mut ctx).unwrap();
+    id
+}
+
+fn call(module: &JITModule, id: FuncId, x: i64) -> i64 {
+    let code = module.get_finalized_function(id);
+    let func: extern "C" fn(i64) -> i64 = unsafe { std::mem::transmute(code) };
+    func(x)
+}
+
+#[test]
+fn pooled_constants() {
+    let mut module = jit_module(true);
+    let funcs = (0..FUNCTIONS)
+        .map(|i| define_function(&mut module, CONSTANTS[i % CONSTANTS.len()]))
+        .collect::<Vec<_>>();
+    // Every distinct constant is stored once, however many functions use it.
+    assert_eq!(
+        module.constant_pool().unwrap().size(),
+        CONSTANTS.len() * CONSTANT_POOL_ENTRY_SIZE
+    );
+    module.finalize_definitions().unwrap();
+    assert_eq!(module.constant_pool().unwrap().size(), 0);
+
+    for (i, &func) in funcs.iter().enumerate() {
+        assert_eq!(
+            call(&module, func, 100),
+            100 + CONSTANTS[i % CONSTANTS.len()][0]
+        );
+    }
+
+    // Functions defined after finalization get a new pool.
+    let late = define_function(&mut module, [9, 10]);
+    assert_eq!(
+        module.constant_pool().unwrap().size(),
+        CONSTANT_POOL_ENTRY_SIZE
+    );
+    module.finalize_definitions().unwrap();
+    assert_eq!(call(&module, late, 100), 109);
+    assert_eq!(call(&module, funcs[1], 100), 103);
+
+    unsafe { module.free_memory() };
+}
+
+#[test]
+fn unpooled_constants() {
+    let mut module = jit_module(false);
+    let funcs = (0..CONSTANTS.len())
+        .map(|i| define_function(&mut module, CONSTANTS[i]))
+        .collect::<Vec<_>>();
+    assert!(module.constant_pool().is_none());
+    module.finalize_definitions().unwrap();
+
+    for (i, &func) in funcs.iter().enumerate() {
+        assert_eq!(call(&module, func, 100), 100 + CONSTANTS[i][0]);
+    }
+
+    unsafe { module.free_memory() };
+}
@@ -0,0 +1,146 @@
+//! Count the blocks run by JIT-compiled code with block coverage instrumentation.
+
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::*;
+use cranelift_codegen::{Context, CoverageConfig, CoverageCounter};
+use cranelift_frontend::*;
+use cranelift_jit::*;
+use cranelift_module::*;
+use std::mem;
+
+mod common;
+
+/// Build a JIT module in which `coverage_counters` is the address of `counters`.
+fn jit_module(counters: *const u8) -> JITModule {
+    let mut builder = common::jit_builder(&[]);
+    builder.symbol("coverage_counters", counters);
+    JITModule::new(builder)
+}
+
+/// Define `fact(n) = if n == 0 { 1 } else { n * fact(n - 1) }` with block coverage counted by
+/// `counter`s from index 1, so that the counter at index 0 must remain untouched.
+///
+/// Returns the function and the counter indices of its entry block, zero branch and nonzero
+/// branch.
+fn define_factorial(module: &mut JITModule, counter: CoverageCounter) -> (FuncId, [u32; 3]) {
+    let mut sig = module.make_signature();
+    sig.params.push(AbiParam::new(types::I64));
+    sig.returns.push(AbiParam::new(types::I64));
+    let id = module
+        .declare_function("fact", Linkage::Local, &sig)
+        .unwrap();
+    let counters = module
+        .declare_data("coverage_counters", Linkage::Import, true, false)
+        .unwrap();
+
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, id.as_u32()), sig);
+    let counters_ptr_gv = module.declare_data_in_func(counters, &mut ctx.func);
+    let mut func_ctx = FunctionBuilderContext::new();
+    let blocks = {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let fact = module.declare_func_in_func(id, bcx.func);
+        let entry = bcx.create_block();
+        let zero = bcx.create_block();
+        let nonzero = bcx.create_block();
+        bcx.append_block_params_for_function_params(entry);
+
+        bcx.switch_to_block(entry);
+        let n = bcx.block_params(entry)[0];
+        let is_zero = bcx.ins().icmp_imm(IntCC::Equal, n, 0);
+        bcx.ins().brif(is_zero, zero, &[], nonzero, &[]);
+
+        bcx.switch_to_block(zero);
+        let one = bcx.ins().iconst(types::I64, 1);
+        bcx.ins().return_(&[one]);
+
+        bcx.switch_to_block(nonzero);
+        let n_minus_1 = bcx.ins().iadd_imm(n, -1);
+        let call = bcx.ins().call(fact, &[n_minus_1]);
+        let fact_n_minus_1 = bcx.inst_results(call)[0];
+        let result = bcx.ins().imul(n, fact_n_minus_1);
+        bcx.ins().return_(&[result]);
+
+        bcx.seal_all_blocks();
+        bcx.finalize();
+        [entry, zero, nonzero]
+    };
+
+    ctx.set_block_coverage(CoverageConfig {
+        counters_ptr_gv,
+        index_base: 1,
+        counter,
+    });
+    module.define_function(id, &mut ctx).unwrap();
+
+    let coverage = ctx.compiled_code().unwrap().block_coverage();
+    assert_eq!(coverage.len(), 3);
+    let index_of = |block| {
+        coverage
+            .iter()
+            .find(|&&(b, _)| b == block)
+            .map(|&(_, index)| index)
+            .unwrap()
+    };
+    (id, blocks.map(index_of))
+}
+
+#[test]
+fn wrapping_counters() {
+    let mut counters = Box::new([0u64; 4]);
+    let mut module = jit_module(counters.as_ptr().cast());
+    let (id, [entry, zero, nonzero]) = define_factorial(&mut module, CoverageCounter::Wrapping64);
+    // Blocks are counted in layout order.
+    assert_eq!([entry, zero, nonzero], [1, 2, 3]);
+    module.finalize_definitions().unwrap();
+    let code = module.get_finalized_function(id);
+    let fact = unsafe { mem::transmute::<_, extern "C" fn(i64) -> i64>(code) };
+
+    assert_eq!(fact(0), 1);
+    assert_eq!(*counters, [0, 1, 1, 0]);
+
+    *counters = [0; 4];
+    assert_eq!(fact(5), 120);
+    assert_eq!(*counters, [0, 6, 1, 5]);
+}
+
+#[test]
+fn saturating_counters() {
+    let counters = Box::new([0u8; 4]);
+    let mut module = jit_module(counters.as_ptr());
+    let (id, _) = define_factorial(&mut module, CoverageCounter::Saturating8);
+    module.finalize_definitions().unwrap();
+    let code = module.get_finalized_function(id);
+    let fact = unsafe { mem::transmute::<_, extern "C" fn(i64) -> i64>(code) };
+
+    assert_eq!(fact(5), 120);
+    assert_eq!(*counters, [0, 6, 1, 5]);
+
+    // The counters of blocks run more than 255 times stop at 255.
+    fact(300);
+    assert_eq!(*counters, [0, 255, 2, 255]);
+}
+
+#[test]
+fn no_coverage_by_default() {
+    let mut module = jit_module(std::ptr::null());
+    let mut sig = module.make_signature();
+    sig.returns.push(AbiParam::new(types::I64));
+    let id = module.declare_function("f", Linkage::Local, &sig).unwrap();
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.switch_to_block(block);
+        let zero = bcx.ins().iconst(types::I64, 0);
+        bcx.ins().return_(&[zero]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module.define_function(id, &mut ctx).unwrap();
+    let compiled = ctx.compiled_code().unwrap();
+    assert!(compiled.block_coverage().is_empty());
+    assert!(compiled.buffer.relocs().is_empty());
+}
@@ -0,0 +1,262 @@
+//! Walk the frame pointer chain of JIT-compiled code from a host callback, the way a sampling
+//! profiler does, and check that every function compiled with `preserve_frame_pointers` keeps the
+//! chain intact.
+
+#![cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::*;
+use cranelift_codegen::isa::CallConv;
+use cranelift_codegen::Context;
+use cranelift_frontend::*;
+use cranelift_jit::*;
+use cranelift_module::*;
+use std::cell::RefCell;
+
+mod common;
+
+thread_local! {
+    static MODULE: RefCell<*const JITModule> = RefCell::new(std::ptr::null());
+    static FRAMES: RefCell<Vec<FuncId>> = RefCell::new(Vec::new());
+}
+
+/// Called by JIT-compiled code with its own frame pointer. Follows the chain of frame records,
+/// each holding the caller's frame pointer followed by the return address into the caller, and
+/// records the function of each return address until one which isn't in JIT-compiled code.
+extern "C" fn walk_frames(mut fp: *const usize) {
+    let module = unsafe { &*MODULE.with(|m| *m.borrow()) };
+    let mut frames = vec![];
+    loop {
+        let return_address = unsafe { *fp.add(1) } as *const u8;
+        match module.function_at(return_address) {
+            Some(func) => frames.push(func),
+            None => break,
+        }
+        let caller_fp = unsafe { *fp } as *const usize;
+        assert!(
+            caller_fp > fp,
+            "frame pointers must grow towards older frames"
+        );
+        fp = caller_fp;
+    }
+    FRAMES.with(|f| *f.borrow_mut() = frames);
+}
+
+fn jit_module() -> JITModule {
+    let mut builder = common::jit_builder(&[("preserve_frame_pointers", "true")]);
+    builder.symbol("walk_frames", walk_frames as *const u8);
+    JITModule::new(builder)
+}
+
+fn signature(call_conv: CallConv, params: &[Type], returns: &[Type]) -> Signature {
+    let mut sig = Signature::new(call_conv);
+    sig.params = params.iter().map(|&ty| AbiParam::new(ty)).collect();
+    sig.returns = returns.iter().map(|&ty| AbiParam::new(ty)).collect();
+    sig
+}
+
+fn declare(module: &mut JITModule, name: &str, sig: &Signature) -> FuncId {
+    module.declare_function(name, Linkage::Local, sig).unwrap()
+}
+
+fn define(
+    module: &mut JITModule,
+    id: FuncId,
+    sig: Signature,
+    body: impl FnOnce(&mut FunctionBuilder, &mut JITModule, &[Value]),
+) {
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.append_block_params_for_function_params(block);
+        bcx.switch_to_block(block);
+        let params = bcx.block_params(block).to_vec();
+        body(&mut bcx, module, &params);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module.define_function(id, &mut ctx).unwrap();
+}
+
+/// Emit a call to `walk_frames` with the current frame pointer.
+fn call_walk_frames(bcx: &mut FunctionBuilder, module: &mut JITModule) {
+    let ptr = module.target_config().pointer_type();
+    let sig = signature(module.isa().default_call_conv(), &[ptr], &[]);
+    let walk = module
+        .declare_function("walk_frames", Linkage::Import, &sig)
+        .unwrap();
+    let walk = module.declare_func_in_func(walk, bcx.func);
+    let fp = bcx.ins().get_frame_pointer(ptr);
+    bcx.ins().call(walk, &[fp]);
+}
+
+/// Run `entry`, returning the functions of the frames recorded by `walk_frames`.
+fn run(module: &JITModule, entry: FuncId) -> Vec<FuncId> {
+    MODULE.with(|m| *m.borrow_mut() = module);
+    let code = module.get_finalized_function(entry);
+    let entry: extern "C" fn() = unsafe { std::mem::transmute(code) };
+    entry();
+    MODULE.with(|m| *m.borrow_mut() = std::ptr::null());
+    FRAMES.with(|f| f.take())
+}
+
+#[test]
+fn deep_call_stack() {
+    const DEPTH: i64 = 20;
+    let mut module = jit_module();
+    let call_conv = module.isa().default_call_conv();
+
+    // fn recurse(n: i64) { if n != 0 { recurse(n - 1) } else { walk_frames(fp) } }
+    let recurse_sig = signature(call_conv, &[types::I64], &[]);
+    let recurse = declare(&mut module, "recurse", &recurse_sig);
+    define(&mut module, recurse, recurse_sig, |bcx, module, params| {
+        let deeper = bcx.create_block();
+        let bottom = bcx.create_block();
+        bcx.ins().brif(params[0], deeper, &[], bottom, &[]);
+
+        bcx.switch_to_block(deeper);
+        let callee = module.declare_func_in_func(recurse, bcx.func);
+        let n = bcx.ins().iadd_imm(params[0], -1);
+        bcx.ins().call(callee, &[n]);
+        bcx.ins().return_(&[]);
+
+        bcx.switch_to_block(bottom);
+        call_walk_frames(bcx, module);
+        bcx.ins().return_(&[]);
+    });
+
+    // fn entry() { recurse(DEPTH) }
+    let entry_sig = signature(call_conv, &[], &[]);
+    let entry = declare(&mut module, "entry", &entry_sig);
+    define(&mut module, entry, entry_sig, |bcx, module, _| {
+        let callee = module.declare_func_in_func(recurse, bcx.func);
+        let n = bcx.ins().iconst(types::I64, DEPTH);
+        bcx.ins().call(callee, &[n]);
+        bcx.ins().return_(&[]);
+    });
+    module.finalize_definitions().unwrap();
+
+    let mut expected = vec![recurse; DEPTH as usize];
+    expected.push(entry);
+    assert_eq!(run(&module, entry), expected);
+
+    unsafe { module.free_memory() };
+}
+
+#[test]
+fn leaf_frame_record() {
+    let mut module = jit_module();
+    let call_conv = module.isa().default_call_conv();
+    let ptr = module.target_config().pointer_type();
+
+    // fn leaf() -> *const usize { fp }
+    let leaf_sig = signature(call_conv, &[], &[ptr]);
+    let leaf = declare(&mut module, "leaf", &leaf_sig);
+    define(&mut module, leaf, leaf_sig, |bcx, _, _| {
+        let fp = bcx.ins().get_frame_pointer(ptr);
+        bcx.ins().return_(&[fp]);
+    });
+
+    // fn caller() -> (usize, bool) {
+    //     let leaf_fp = leaf();
+    //     (leaf_fp[1], leaf_fp[0] == fp)
+    // }
+    //
+    // The frame record of `leaf` is read right after it returns, before anything else reuses its
+    // stack space.
+    let caller_sig = signature(call_conv, &[], &[ptr, types::I8]);
+    let caller = declare(&mut module, "caller", &caller_sig);
+    define(&mut module, caller, caller_sig, |bcx, module, _| {
+        let callee = module.declare_func_in_func(leaf, bcx.func);
+        let call = bcx.ins().call(callee, &[]);
+        let leaf_fp = bcx.inst_results(call)[0];
+        let saved_fp = bcx.ins().load(ptr, MemFlags::trusted(), leaf_fp, 0);
+        let return_address = bcx.ins().load(ptr, MemFlags::trusted(), leaf_fp, 8);
+        let fp = bcx.ins().get_frame_pointer(ptr);
+        let linked = bcx.ins().icmp(IntCC::Equal, saved_fp, fp);
+        bcx.ins().return_(&[return_address, linked]);
+    });
+    module.finalize_definitions().unwrap();
+
+    let code = module.get_finalized_function(caller);
+    let caller_fn: extern "C" fn() -> (usize, i8) = unsafe { std::mem::transmute(code) };
+    let (return_address, linked) = caller_fn();
+    assert_eq!(
+        linked, 1,
+        "the leaf's frame record must point to its caller's"
+    );
+    assert_eq!(
+        module.function_at(return_address as *const u8),
+        Some(caller)
+    );
+
+    unsafe { module.free_memory() };
+}
+
+// Only x64 implements tail calls so far.
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn tail_calls() {
+    let mut module = jit_module();
+    let call_conv = module.isa().default_call_conv();
+
+    // With more arguments than fit in registers, `tail_callee` needs more stack argument space
+    // than `tail_caller`, which must grow its frame before jumping.
+    let many_args = [types::I64; 16];
+
+    // fn innermost() { walk_frames(fp) }
+    let innermost_sig = signature(CallConv::Tail, &[], &[]);
+    let innermost = declare(&mut module, "innermost", &innermost_sig);
+    define(&mut module, innermost, innermost_sig, |bcx, module, _| {
+        call_walk_frames(bcx, module);
+        bcx.ins().return_(&[]);
+    });
+
+    // fn tail_callee(...) { innermost() }
+    let tail_callee_sig = signature(CallConv::Tail, &many_args, &[]);
+    let tail_callee = declare(&mut module, "tail_callee", &tail_callee_sig);
+    define(
+        &mut module,
+        tail_callee,
+        tail_callee_sig,
+        |bcx, module, _| {
+            let callee = module.declare_func_in_func(innermost, bcx.func);
+            bcx.ins().call(callee, &[]);
+            bcx.ins().return_(&[]);
+        },
+    );
+
+    // fn tail_caller() { return_call tail_callee(0, 1, ...) }
+    let tail_caller_sig = signature(CallConv::Tail, &[], &[]);
+    let tail_caller = declare(&mut module, "tail_caller", &tail_caller_sig);
+    define(
+        &mut module,
+        tail_caller,
+        tail_caller_sig,
+        |bcx, module, _| {
+            let callee = module.declare_func_in_func(tail_callee, bcx.func);
+            let args: Vec<_> = (0..many_args.len() as i64)
+                .map(|i| bcx.ins().iconst(types::I64, i))
+                .collect();
+            bcx.ins().return_call(callee, &args);
+        },
+    );
+
+    // fn entry() { tail_caller() }
+    let entry_sig = signature(call_conv, &[], &[]);
+    let entry = declare(&mut module, "entry", &entry_sig);
+    define(&mut module, entry, entry_sig, |bcx, module, _| {
+        let callee = module.declare_func_in_func(tail_caller, bcx.func);
+        bcx.ins().call(callee, &[]);
+        bcx.ins().return_(&[]);
+    });
+    module.finalize_definitions().unwrap();
+
+    // `tail_caller`'s frame was replaced by `tail_callee`'s, which returns to `entry`.
+    assert_eq!(run(&module, entry), vec![tail_callee, entry]);
+
+    unsafe { module.free_memory() };
+}
@@ -0,0 +1,228 @@
+use cranelift_codegen::ir::*;
+use cranelift_codegen::Context;
+use cranelift_frontend::*;
+use cranelift_jit::*;
+use cranelift_module::*;
+
+mod common;
+
+const PAGE: usize = 0x10000;
+
+fn jit_module(is_pic: bool) -> JITModule {
+    JITModule::new(common::jit_builder(&[(
+        "is_pic",
+        if is_pic { "true" } else { "false" },
+    )]))
+}
+
+/// `fn store(index: I, value: i32) { heap[index + offset] = value }`
+fn define_store(
+    module: &mut JITModule,
+    heap: &Heap,
+    name: &str,
+    index_type: Type,
+    offset: u32,
+) -> FuncId {
+    let mut sig = module.make_signature();
+    sig.params.push(AbiParam::new(index_type));
+    sig.params.push(AbiParam::new(types::I32));
+    let id = module.declare_function(name, Linkage::Local, &sig).unwrap();
+
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, id.as_u32()), sig);
+    let globals = heap.declare_heap_in_func(module, &mut ctx.func);
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.append_block_params_for_function_params(block);
+        bcx.switch_to_block(block);
+        let (index, value) = (bcx.block_params(block)[0], bcx.block_params(block)[1]);
+        let addr = globals.addr(&mut bcx.cursor(), index, offset, 4);
+        bcx.ins().store(HeapGlobals::flags(), value, addr, 0);
+        bcx.ins().return_(&[]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module.define_function(id, &mut ctx).unwrap();
+    id
+}
+
+/// `fn load(index: i64) -> i32 { heap[index] }`
+fn define_load(module: &mut JITModule, heap: &Heap) -> FuncId {
+    let mut sig = module.make_signature();
+    sig.params.push(AbiParam::new(types::I64));
+    sig.returns.push(AbiParam::new(types::I32));
+    let id = module
+        .declare_function("load", Linkage::Local, &sig)
+        .unwrap();
+
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, id.as_u32()), sig);
+    let globals = heap.declare_heap_in_func(module, &mut ctx.func);
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.append_block_params_for_function_params(block);
+        bcx.switch_to_block(block);
+        let index = bcx.block_params(block)[0];
+        let addr = globals.addr(&mut bcx.cursor(), index, 0, 4);
+        let value = bcx.ins().load(types::I32, HeapGlobals::flags(), addr, 0);
+        bcx.ins().return_(&[value]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module.define_function(id, &mut ctx).unwrap();
+    id
+}
+
+#[test]
+fn view_observes_guest_writes() {
+    for is_pic in [false, true] {
+        let mut module = jit_module(is_pic);
+        let mut heap = module
+            .create_heap(HeapConfig {
+                initial: PAGE,
+                maximum: 4 * PAGE,
+                guard: PAGE,
+            })
+            .unwrap();
+        let store = define_store(&mut module, &heap, "store", types::I64, 0);
+        let store32 = define_store(&mut module, &heap, "store32", types::I32, 8);
+        let load = define_load(&mut module, &heap);
+        module.finalize_definitions().unwrap();
+        let store: extern "C" fn(i64, i32) =
+            unsafe { std::mem::transmute(module.get_finalized_function(store)) };
+        let store32: extern "C" fn(i32, i32) =
+            unsafe { std::mem::transmute(module.get_finalized_function(store32)) };
+        let load: extern "C" fn(i64) -> i32 =
+            unsafe { std::mem::transmute(module.get_finalized_function(load)) };
+
+        assert_eq!(heap.length(), PAGE);
+        assert_eq!(heap.view().len(), PAGE);
+        assert!(heap.view().iter().all(|&byte| byte == 0));
+
+        store(16, 0x0403_0201);
+        store32(0xff, 0x0807_0605);
+        assert_eq!(&heap.view()[16..20], &0x0403_0201i32.to_ne_bytes());
+        assert_eq!(&heap.view()[0x107..0x10b], &0x0807_0605i32.to_ne_bytes());
+
+        heap.view_mut()[PAGE - 4..].copy_from_slice(&42i32.to_ne_bytes());
+        assert_eq!(load(PAGE as i64 - 4), 42);
+
+        // Growing keeps the base and the contents, and zeroes the new bytes.
+        let base = heap.base();
+        assert_eq!(heap.grow(PAGE).unwrap(), PAGE);
+        assert_eq!(heap.base(), base);
+        assert_eq!(heap.length(), 2 * PAGE);
+        assert_eq!(load(16), 0x0403_0201);
+        store(PAGE as i64 + 4, -1);
+        assert_eq!(&heap.view()[PAGE + 4..PAGE + 8], &[0xff; 4]);
+        assert!(heap.view()[PAGE + 8..].iter().all(|&byte| byte == 0));
+
+        unsafe { module.free_memory() };
+    }
+}
+
+#[test]
+fn grow_up_to_maximum() {
+    let mut module = jit_module(false);
+    let mut heap = module
+        .create_heap(HeapConfig {
+            initial: 0,
+            maximum: 2 * PAGE,
+            guard: 0,
+        })
+        .unwrap();
+    assert_eq!(heap.length(), 0);
+    assert!(heap.view().is_empty());
+    assert_eq!(heap.grow(PAGE).unwrap(), 0);
+    assert_eq!(heap.grow(0).unwrap(), PAGE);
+    assert!(heap.grow(2 * PAGE).is_err());
+    assert_eq!(heap.length(), PAGE);
+    assert_eq!(heap.grow(PAGE).unwrap(), PAGE);
+    assert_eq!(heap.length(), heap.maximum());
+
+    assert!(module
+        .create_heap(HeapConfig {
+            initial: 2 * PAGE,
+            maximum: PAGE,
+            guard: 0,
+        })
+        .is_err());
+}
+
+#[cfg(feature = "signal-handlers")]
+mod signal_handlers {
+    use super::*;
+    use cranelift_jit::signals::catch_traps;
+
+    #[test]
+    fn guard_strategy() {
+        let mut module = jit_module(false);
+        let mut heap = module
+            .create_heap(HeapConfig {
+                initial: PAGE,
+                maximum: 4 * PAGE,
+                guard: PAGE,
+            })
+            .unwrap();
+        let store_id = define_store(&mut module, &heap, "store", types::I64, 0);
+        module.finalize_definitions().unwrap();
+        let store: extern "C" fn(i64, i32) =
+            unsafe { std::mem::transmute(module.get_finalized_function(store_id)) };
+
+        // Beyond the committed region, the access faults on an inaccessible page.
+        let index = 2 * PAGE as i64 + 8;
+        let trap = unsafe { catch_traps(&module, || store(index, 7)) }.unwrap_err();
+        assert_eq!(trap.code(), TrapCode::HeapOutOfBounds);
+        assert_eq!(trap.func_id(), store_id);
+
+        heap.grow(2 * PAGE).unwrap();
+        assert_eq!(unsafe { catch_traps(&module, || store(index, 7)) }, Ok(()));
+        assert_eq!(heap.view()[index as usize], 7);
+
+        // Indices beyond the maximum length are checked explicitly.
+        let trap = unsafe { catch_traps(&module, || store(i64::MAX, 7)) }.unwrap_err();
+        assert_eq!(trap.code(), TrapCode::HeapOutOfBounds);
+        let trap = unsafe { catch_traps(&module, || store(-1, 7)) }.unwrap_err();
+        assert_eq!(trap.code(), TrapCode::HeapOutOfBounds);
+
+        unsafe { module.free_memory() };
+    }
+
+    #[test]
+    fn explicit_strategy() {
+        let mut module = jit_module(true);
+        let mut heap = module
+            .create_heap(HeapConfig {
+                initial: PAGE,
+                maximum: 4 * PAGE,
+                guard: 0,
+            })
+            .unwrap();
+        let store_id = define_store(&mut module, &heap, "store", types::I64, 2);
+        module.finalize_definitions().unwrap();
+        let store: extern "C" fn(i64, i32) =
+            unsafe { std::mem::transmute(module.get_finalized_function(store_id)) };
+
+        // The last 4 bytes of the heap are at `PAGE - 6 + 2`.
+        let last = PAGE as i64 - 6;
+        assert_eq!(unsafe { catch_traps(&module, || store(last, 9)) }, Ok(()));
+        let trap = unsafe { catch_traps(&module, || store(last + 1, 9)) }.unwrap_err();
+        assert_eq!(trap.code(), TrapCode::HeapOutOfBounds);
+        let trap = unsafe { catch_traps(&module, || store(-1, 9)) }.unwrap_err();
+        assert_eq!(trap.code(), TrapCode::HeapOutOfBounds);
+
+        // The bound follows the length of the heap.
+        heap.grow(PAGE).unwrap();
+        assert_eq!(
+            unsafe { catch_traps(&module, || store(last + 1, 9)) },
+            Ok(())
+        );
+        assert_eq!(heap.view()[PAGE - 3], 9);
+
+        unsafe { module.free_memory() };
+    }
+}
@@ -0,0 +1,191 @@
+//! Redefine functions of a module with hotswap support, and check that both new and existing
+//! callers reach the new code.
+
+// The PLT and GOT entries of hotswapping are only implemented on x86_64.
+#![cfg(target_arch = "x86_64")]
+
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::*;
+use cranelift_codegen::Context;
+use cranelift_frontend::*;
+use cranelift_jit::*;
+use cranelift_module::*;
+
+mod common;
+
+fn jit_module(hotswap: bool) -> JITModule {
+    let mut builder = common::jit_builder(&[("is_pic", "true")]);
+    builder.hotswap(hotswap);
+    JITModule::new(builder)
+}
+
+/// Declare `fn name(n: i64) -> i64`.
+fn declare(module: &mut JITModule, name: &str) -> FuncId {
+    let mut sig = module.make_signature();
+    sig.params.push(AbiParam::new(types::I64));
+    sig.returns.push(AbiParam::new(types::I64));
+    module.declare_function(name, Linkage::Local, &sig).unwrap()
+}
+
+/// Define the function `id` with the body built by `build` from its parameter.
+fn define(
+    module: &mut JITModule,
+    id: FuncId,
+    build: impl FnOnce(&mut FunctionBuilder, &mut JITModule, Value) -> Value,
+) -> ModuleResult<()> {
+    let sig = module
+        .declarations()
+        .get_function_decl(id)
+        .signature
+        .clone();
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+    let block = bcx.create_block();
+    bcx.append_block_params_for_function_params(block);
+    bcx.switch_to_block(block);
+    let n = bcx.block_params(block)[0];
+    let result = build(&mut bcx, module, n);
+    bcx.ins().return_(&[result]);
+    bcx.seal_all_blocks();
+    bcx.finalize();
+    module.define_function(id, &mut ctx).map(|_| ())
+}
+
+/// Define `fact` as `if n <= 1 { 1 } else { n * fact(n - 1) }`.
+fn define_factorial(module: &mut JITModule, fact: FuncId) -> ModuleResult<()> {
+    define(module, fact, |bcx, module, n| {
+        let callee = module.declare_func_in_func(fact, bcx.func);
+        let recurse = bcx.create_block();
+        let done = bcx.create_block();
+        let result = bcx.append_block_param(done, types::I64);
+        let one = bcx.ins().iconst(types::I64, 1);
+        let base = bcx.ins().icmp_imm(IntCC::SignedLessThanOrEqual, n, 1);
+        bcx.ins().brif(base, done, &[one], recurse, &[]);
+
+        bcx.switch_to_block(recurse);
+        let n_minus_one = bcx.ins().iadd_imm(n, -1);
+        let call = bcx.ins().call(callee, &[n_minus_one]);
+        let rest = bcx.inst_results(call)[0];
+        let product = bcx.ins().imul(n, rest);
+        bcx.ins().jump(done, &[product]);
+
+        bcx.switch_to_block(done);
+        result
+    })
+}
+
+/// Define `id` as the identity function.
+fn define_identity(module: &mut JITModule, id: FuncId) -> ModuleResult<()> {
+    define(module, id, |_, _, n| n)
+}
+
+/// Declare and define `fn caller(n: i64) -> i64`, returning `callee(n)`.
+fn define_caller(module: &mut JITModule, callee: FuncId) -> FuncId {
+    let caller = declare(module, "caller");
+    define(module, caller, |bcx, module, n| {
+        let callee = module.declare_func_in_func(callee, bcx.func);
+        let call = bcx.ins().call(callee, &[n]);
+        bcx.inst_results(call)[0]
+    })
+    .unwrap();
+    caller
+}
+
+type Code = extern "C" fn(i64) -> i64;
+
+fn get(module: &JITModule, id: FuncId) -> Code {
+    unsafe { module.get_finalized_function_typed(id) }.unwrap()
+}
+
+#[test]
+fn redefine_after_finalize() {
+    let mut module = jit_module(true);
+    let fact = declare(&mut module, "fact");
+    define_factorial(&mut module, fact).unwrap();
+    let caller = define_caller(&mut module, fact);
+    module.finalize_definitions().unwrap();
+    let (old_fact, caller) = (get(&module, fact), get(&module, caller));
+    assert_eq!(old_fact(5), 120);
+    assert_eq!(caller(5), 120);
+
+    // Defining the function again replaces it, for new pointers and for existing calls.
+    define_identity(&mut module, fact).unwrap();
+    module.finalize_definitions().unwrap();
+    let new_fact = get(&module, fact);
+    assert_ne!(new_fact as usize, old_fact as usize);
+    assert_eq!(new_fact(5), 5);
+    assert_eq!(caller(5), 5);
+    assert_eq!(module.read_got_entry(fact), new_fact as *const u8);
+    // The old code stays in memory, and its recursive call reaches the new code too.
+    assert_eq!(old_fact(5), 5 * 4);
+
+    // And it can be redefined any number of times.
+    define_factorial(&mut module, fact).unwrap();
+    module.finalize_definitions().unwrap();
+    assert_eq!(get(&module, fact)(6), 720);
+    assert_eq!(caller(6), 720);
+
+    unsafe { module.free_memory() };
+}
+
+#[test]
+fn redefine_before_finalize() {
+    let mut module = jit_module(true);
+    let fact = declare(&mut module, "fact");
+    define_factorial(&mut module, fact).unwrap();
+    let caller = define_caller(&mut module, fact);
+    define_identity(&mut module, fact).unwrap();
+    module.finalize_definitions().unwrap();
+
+    // Only the last definition was ever reachable.
+    assert_eq!(get(&module, fact)(5), 5);
+    assert_eq!(get(&module, caller)(5), 5);
+
+    unsafe { module.free_memory() };
+}
+
+#[test]
+fn failed_redefinition() {
+    let mut module = jit_module(true);
+    let fact = declare(&mut module, "fact");
+    define_factorial(&mut module, fact).unwrap();
+    module.finalize_definitions().unwrap();
+
+    // A redefinition which doesn't compile leaves the earlier one in place.
+    let err = define(&mut module, fact, |bcx, _, n| {
+        bcx.ins().iadd_imm(n, 1);
+        bcx.ins().iconst(types::I32, 0)
+    })
+    .unwrap_err();
+    assert!(matches!(err, ModuleError::Compilation(_)), "{err:?}");
+    module.finalize_definitions().unwrap();
+    assert_eq!(get(&module, fact)(5), 120);
+
+    unsafe { module.free_memory() };
+}
+
+#[test]
+fn redefine_without_hotswap() {
+    let mut module = jit_module(false);
+    let fact = declare(&mut module, "fact");
+    define_factorial(&mut module, fact).unwrap();
+    let message = "Backend error: function fact can't be redefined without hotswap support, \
+                   which must be enabled with `JITBuilder::hotswap` before the module is made";
+    assert_eq!(
+        define_identity(&mut module, fact).unwrap_err().to_string(),
+        message
+    );
+    assert_eq!(
+        module
+            .prepare_for_function_redefine(fact)
+            .unwrap_err()
+            .to_string(),
+        message
+    );
+
+    module.finalize_definitions().unwrap();
+    assert_eq!(get(&module, fact)(5), 120);
+    unsafe { module.free_memory() };
+}
@@ -0,0 +1,107 @@
+//! Check that `JITBuilder::libcall_resolver` resolves the libcalls emitted by lowerings, and that
+//! unresolved libcalls are reported by `finalize_definitions`.
+
+// Without FMA support, x86_64 lowers `fma` to a libcall.
+#![cfg(target_arch = "x86_64")]
+
+use cranelift_codegen::ir::*;
+use cranelift_codegen::isa;
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_codegen::Context;
+use cranelift_frontend::*;
+use cranelift_jit::*;
+use cranelift_module::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static FMA_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn counting_fmaf(a: f32, b: f32, c: f32) -> f32 {
+    FMA_CALLS.fetch_add(1, Ordering::SeqCst);
+    a.mul_add(b, c)
+}
+
+fn jit_builder(
+    is_pic: bool,
+    libcall_names: Box<dyn Fn(LibCall) -> String + Send + Sync>,
+) -> JITBuilder {
+    let mut flag_builder = settings::builder();
+    flag_builder.set("use_colocated_libcalls", "false").unwrap();
+    flag_builder
+        .set("is_pic", if is_pic { "true" } else { "false" })
+        .unwrap();
+    let isa = isa::lookup(target_lexicon::Triple::host())
+        .unwrap()
+        .finish(settings::Flags::new(flag_builder))
+        .unwrap();
+    JITBuilder::with_isa(isa, libcall_names)
+}
+
+/// Define a function computing `fma` of its three arguments.
+fn define_fma(module: &mut JITModule) -> FuncId {
+    let mut sig = module.make_signature();
+    sig.params = vec![AbiParam::new(types::F32); 3];
+    sig.returns = vec![AbiParam::new(types::F32)];
+    let id = module
+        .declare_function("fma", Linkage::Local, &sig)
+        .unwrap();
+
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.append_block_params_for_function_params(block);
+        bcx.switch_to_block(block);
+        let params = bcx.block_params(block).to_vec();
+        let v = bcx.ins().fma(params[0], params[1], params[2]);
+        bcx.ins().return_(&[v]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module.define_function(id, &mut ctx).unwrap();
+    id
+}
+
+#[test]
+fn resolved_by_host_shim() {
+    for is_pic in [false, true] {
+        let mut builder = jit_builder(is_pic, default_libcall_names());
+        builder.libcall_resolver(Box::new(|libcall| match libcall {
+            LibCall::FmaF32 => Some(counting_fmaf as *const u8),
+            _ => None,
+        }));
+        let mut module = JITModule::new(builder);
+        let id = define_fma(&mut module);
+        assert_eq!(module.referenced_libcalls(), &[LibCall::FmaF32]);
+        module.finalize_definitions().unwrap();
+
+        let fma: extern "C" fn(f32, f32, f32) -> f32 =
+            unsafe { std::mem::transmute(module.get_finalized_function(id)) };
+        let calls = FMA_CALLS.load(Ordering::SeqCst);
+        assert_eq!(fma(2.0, 3.0, 4.0), 10.0);
+        assert_eq!(fma(-1.5, 2.0, 0.5), -2.5);
+        assert_eq!(FMA_CALLS.load(Ordering::SeqCst), calls + 2);
+
+        unsafe { module.free_memory() };
+    }
+}
+
+#[test]
+fn unresolved_libcall() {
+    for is_pic in [false, true] {
+        let builder = jit_builder(
+            is_pic,
+            Box::new(|libcall| format!("__cranelift_test_missing_{}", libcall)),
+        );
+        let mut module = JITModule::new(builder);
+        define_fma(&mut module);
+        assert_eq!(module.referenced_libcalls(), &[LibCall::FmaF32]);
+        let err = module.finalize_definitions().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Backend error: can't resolve libcall __cranelift_test_missing_FmaF32 (FmaF32)"
+        );
+        unsafe { module.free_memory() };
+    }
+}
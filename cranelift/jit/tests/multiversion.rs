@@ -0,0 +1,162 @@
+#![cfg(target_arch = "x86_64")]
+
+use cranelift_codegen::ir::*;
+use cranelift_codegen::Context;
+use cranelift_frontend::*;
+use cranelift_jit::*;
+use cranelift_module::*;
+
+mod common;
+
+/// The AVX2 variant first, then the baseline variant as the fallback.
+fn variants(is_pic: &str) -> Vec<TargetVariant> {
+    let flags = [("is_pic", is_pic)];
+    vec![
+        TargetVariant::new(
+            "avx2",
+            common::isa_with_features(&["has_avx", "has_avx2", "has_fma"], &flags),
+        ),
+        TargetVariant::new("baseline", common::isa_with_features(&[], &flags)),
+    ]
+}
+
+/// Build `fn(a: *const f32, b: *const f32, out: *mut f32)` computing `out[i] = a[i] * b[i] +
+/// a[i]` for 16 floats, four lanes at a time.
+fn build_multiply_add(module: &JITModule, ctx: &mut Context, func_id: FuncId) {
+    let pointer = module.target_config().pointer_type();
+    let mut sig = module.make_signature();
+    sig.params.extend([AbiParam::new(pointer); 3]);
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, func_id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+    let block = bcx.create_block();
+    bcx.append_block_params_for_function_params(block);
+    bcx.switch_to_block(block);
+    let (a, b, out) = match *bcx.block_params(block) {
+        [a, b, out] => (a, b, out),
+        _ => unreachable!(),
+    };
+    for offset in (0..64).step_by(16) {
+        let x = bcx.ins().load(types::F32X4, MemFlags::new(), a, offset);
+        let y = bcx.ins().load(types::F32X4, MemFlags::new(), b, offset);
+        let product = bcx.ins().fmul(x, y);
+        let sum = bcx.ins().fadd(product, x);
+        bcx.ins().store(MemFlags::new(), sum, out, offset);
+    }
+    bcx.ins().return_(&[]);
+    bcx.seal_all_blocks();
+    bcx.finalize();
+}
+
+fn call_multiply_add(code: *const u8, a: &[f32; 16], b: &[f32; 16]) -> [f32; 16] {
+    let f = unsafe {
+        std::mem::transmute::<*const u8, extern "C" fn(*const f32, *const f32, *mut f32)>(code)
+    };
+    let mut out = [0.0; 16];
+    f(a.as_ptr(), b.as_ptr(), out.as_mut_ptr());
+    out
+}
+
+#[test]
+fn host_variant_is_chosen() {
+    let a: [f32; 16] = std::array::from_fn(|i| i as f32 * 0.5 - 3.0);
+    let b: [f32; 16] = std::array::from_fn(|i| 7.0 - i as f32 * 1.25);
+
+    for is_pic in ["false", "true"] {
+        let mut module = JITModule::new(JITBuilder::with_isa(
+            common::isa_with_features(&[], &[("is_pic", is_pic)]),
+            default_libcall_names(),
+        ));
+        let mut ctx = module.make_context();
+        let pointer = module.target_config().pointer_type();
+        let mut sig = module.make_signature();
+        sig.params.extend([AbiParam::new(pointer); 3]);
+        let func_id = module
+            .declare_function("multiply_add", Linkage::Local, &sig)
+            .unwrap();
+        build_multiply_add(&module, &mut ctx, func_id);
+        let ids = module
+            .define_function_multiversion(func_id, &mut ctx, &variants(is_pic))
+            .unwrap();
+        assert_eq!(ids.len(), 2);
+        assert!(matches!(
+            module.define_function(func_id, &mut ctx),
+            Err(ModuleError::DuplicateDefinition(_))
+        ));
+
+        // Calls from other functions also reach the chosen variant.
+        let caller_id = module
+            .declare_function("caller", Linkage::Local, &sig)
+            .unwrap();
+        ctx.func = Function::with_name_signature(UserFuncName::user(0, caller_id.as_u32()), sig);
+        let callee = module.declare_func_in_func(func_id, &mut ctx.func);
+        {
+            let mut func_ctx = FunctionBuilderContext::new();
+            let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+            let block = bcx.create_block();
+            bcx.append_block_params_for_function_params(block);
+            bcx.switch_to_block(block);
+            let args = bcx.block_params(block).to_vec();
+            bcx.ins().call(callee, &args);
+            bcx.ins().return_(&[]);
+            bcx.seal_all_blocks();
+            bcx.finalize();
+        }
+        module.define_function(caller_id, &mut ctx).unwrap();
+        module.finalize_definitions().unwrap();
+
+        let chosen =
+            if std::is_x86_feature_detected!("avx2") && std::is_x86_feature_detected!("fma") {
+                ids[0]
+            } else {
+                ids[1]
+            };
+        let code = module.get_finalized_function(func_id);
+        assert_eq!(module.function_at(code), Some(chosen));
+
+        let expected: [f32; 16] = std::array::from_fn(|i| a[i] * b[i] + a[i]);
+        let chosen_result = call_multiply_add(code, &a, &b);
+        let baseline_result = call_multiply_add(module.get_finalized_function(ids[1]), &a, &b);
+        assert_eq!(chosen_result, baseline_result);
+        assert_eq!(chosen_result, expected);
+        let caller = module.get_finalized_function(caller_id);
+        assert_eq!(call_multiply_add(caller, &a, &b), expected);
+    }
+}
+
+#[test]
+fn variants_keep_trap_information() {
+    let mut module = common::jit_module();
+    let mut ctx = module.make_context();
+    let mut sig = module.make_signature();
+    sig.params.push(AbiParam::new(types::I64));
+    sig.returns.push(AbiParam::new(types::I64));
+    let func_id = module
+        .declare_function("checked", Linkage::Local, &sig)
+        .unwrap();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, func_id.as_u32()), sig);
+    {
+        let mut func_ctx = FunctionBuilderContext::new();
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.append_block_params_for_function_params(block);
+        bcx.switch_to_block(block);
+        let x = bcx.block_params(block)[0];
+        bcx.ins().trapz(x, TrapCode::User(7));
+        bcx.ins().return_(&[x]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    let ids = module
+        .define_function_multiversion(func_id, &mut ctx, &variants("false"))
+        .unwrap();
+    module.finalize_definitions().unwrap();
+
+    // Each variant is compiled like any other function, so its traps can be looked up.
+    for id in ids {
+        let info = module.get_finalized_function_info(id);
+        let trap = (0..info.size)
+            .find_map(|offset| module.lookup_trap_site(info.ptr.wrapping_add(offset)));
+        assert_eq!(trap, Some((id, TrapCode::User(7))));
+    }
+}
@@ -0,0 +1,332 @@
+//! Flip a `patchable_call` between two targets, and a `patchable_jump` between its two
+//! destinations, while another thread executes it in a loop, and check that the loop only ever
+//! observes one of the two.
+
+#![cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::*;
+use cranelift_codegen::Context;
+use cranelift_frontend::*;
+use cranelift_jit::*;
+use cranelift_module::*;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+mod common;
+use common::jit_module;
+
+fn define(
+    module: &mut JITModule,
+    name: &str,
+    params: &[Type],
+    returns: &[Type],
+    body: impl FnOnce(&mut FunctionBuilder, &mut JITModule, &[Value]),
+) -> FuncId {
+    let mut sig = module.make_signature();
+    sig.params = params.iter().map(|&ty| AbiParam::new(ty)).collect();
+    sig.returns = returns.iter().map(|&ty| AbiParam::new(ty)).collect();
+    let id = module.declare_function(name, Linkage::Local, &sig).unwrap();
+
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.append_block_params_for_function_params(block);
+        bcx.switch_to_block(block);
+        let params = bcx.block_params(block).to_vec();
+        body(&mut bcx, module, &params);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module.define_function(id, &mut ctx).unwrap();
+    id
+}
+
+/// Define `target_a` and `target_b`, returning 1 and 2, and `spin`, which calls `target_a`
+/// through a patchable call until the flag it is passed is set.
+///
+/// `spin` returns the number of calls and the number of calls which reached `target_b`, or -1 if
+/// any call returned something else.
+fn define_functions(module: &mut JITModule) -> (FuncId, FuncId, FuncId) {
+    let target = |module: &mut JITModule, name, value| {
+        define(module, name, &[], &[types::I64], |bcx, _, _| {
+            let v = bcx.ins().iconst(types::I64, value);
+            bcx.ins().return_(&[v]);
+        })
+    };
+    let target_a = target(module, "target_a", 1);
+    let target_b = target(module, "target_b", 2);
+
+    let ptr = module.target_config().pointer_type();
+    let spin = define(
+        module,
+        "spin",
+        &[ptr],
+        &[types::I64, types::I64],
+        |bcx, module, params| {
+            let flag = params[0];
+            let header = bcx.create_block();
+            let check = bcx.create_block();
+            let invalid = bcx.create_block();
+            let done = bcx.create_block();
+            for block in [header, check, done] {
+                bcx.append_block_param(block, types::I64);
+                bcx.append_block_param(block, types::I64);
+            }
+
+            let zero = bcx.ins().iconst(types::I64, 0);
+            bcx.ins().jump(header, &[zero, zero]);
+
+            // header(calls, b_calls): r = target_a(); if r - 1 > 1 { invalid }
+            bcx.switch_to_block(header);
+            let calls = bcx.block_params(header)[0];
+            let b_calls = bcx.block_params(header)[1];
+            let callee = module.declare_func_in_func(target_a, bcx.func);
+            let call = bcx.ins().patchable_call(callee, &[]);
+            let r = bcx.inst_results(call)[0];
+            let is_b = bcx.ins().iadd_imm(r, -1);
+            let bad = bcx.ins().icmp_imm(IntCC::UnsignedGreaterThan, is_b, 1);
+            let calls = bcx.ins().iadd_imm(calls, 1);
+            let b_calls = bcx.ins().iadd(b_calls, is_b);
+            bcx.ins().brif(bad, invalid, &[], check, &[calls, b_calls]);
+
+            // check(calls, b_calls): if *flag { done } else { header }
+            bcx.switch_to_block(check);
+            let calls = bcx.block_params(check)[0];
+            let b_calls = bcx.block_params(check)[1];
+            let stop = bcx.ins().atomic_load(types::I8, MemFlags::trusted(), flag);
+            bcx.ins()
+                .brif(stop, done, &[calls, b_calls], header, &[calls, b_calls]);
+
+            bcx.switch_to_block(done);
+            let results = bcx.block_params(done).to_vec();
+            bcx.ins().return_(&results);
+
+            bcx.switch_to_block(invalid);
+            let minus_one = bcx.ins().iconst(types::I64, -1);
+            bcx.ins().return_(&[minus_one, minus_one]);
+        },
+    );
+    (target_a, target_b, spin)
+}
+
+/// Define `spin_jump`, which goes through a patchable jump to one of two blocks until the flag it
+/// is passed is set.
+///
+/// `spin_jump` returns the number of jumps and the number of jumps which reached the alternate
+/// destination.
+fn define_spin_jump(module: &mut JITModule) -> FuncId {
+    let ptr = module.target_config().pointer_type();
+    define(
+        module,
+        "spin_jump",
+        &[ptr],
+        &[types::I64, types::I64],
+        |bcx, _, params| {
+            let flag = params[0];
+            let header = bcx.create_block();
+            let initial = bcx.create_block();
+            let alternate = bcx.create_block();
+            let check = bcx.create_block();
+            let done = bcx.create_block();
+            for block in [header, initial, alternate, check, done] {
+                bcx.append_block_param(block, types::I64);
+                bcx.append_block_param(block, types::I64);
+            }
+
+            let zero = bcx.ins().iconst(types::I64, 0);
+            bcx.ins().jump(header, &[zero, zero]);
+
+            // header(jumps, b_jumps): goto initial or alternate
+            bcx.switch_to_block(header);
+            let args = bcx.block_params(header).to_vec();
+            bcx.ins().patchable_jump(initial, &args, alternate, &args);
+
+            // initial(jumps, b_jumps): check(jumps + 1, b_jumps)
+            bcx.switch_to_block(initial);
+            let jumps = bcx.block_params(initial)[0];
+            let b_jumps = bcx.block_params(initial)[1];
+            let jumps = bcx.ins().iadd_imm(jumps, 1);
+            bcx.ins().jump(check, &[jumps, b_jumps]);
+
+            // alternate(jumps, b_jumps): check(jumps + 1, b_jumps + 1)
+            bcx.switch_to_block(alternate);
+            let jumps = bcx.block_params(alternate)[0];
+            let b_jumps = bcx.block_params(alternate)[1];
+            let jumps = bcx.ins().iadd_imm(jumps, 1);
+            let b_jumps = bcx.ins().iadd_imm(b_jumps, 1);
+            bcx.ins().jump(check, &[jumps, b_jumps]);
+
+            // check(jumps, b_jumps): if *flag { done } else { header }
+            bcx.switch_to_block(check);
+            let args = bcx.block_params(check).to_vec();
+            let stop = bcx.ins().atomic_load(types::I8, MemFlags::trusted(), flag);
+            bcx.ins().brif(stop, done, &args, header, &args);
+
+            bcx.switch_to_block(done);
+            let results = bcx.block_params(done).to_vec();
+            bcx.ins().return_(&results);
+        },
+    )
+}
+
+/// The results of `spin`: the number of calls it made, and how many of them reached `target_b`.
+///
+/// For `spin_jump`, the number of jumps and how many of them reached the alternate destination.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Calls {
+    calls: i64,
+    b_calls: i64,
+}
+
+type SpinFn = extern "C" fn(*const AtomicBool) -> Calls;
+
+#[test]
+fn concurrent_retargeting() {
+    const FLIPS: usize = 10_000;
+
+    let mut module = jit_module();
+    let (target_a, target_b, spin) = define_functions(&mut module);
+    module.finalize_definitions().unwrap();
+
+    let patch_points = module.patch_points(spin);
+    assert_eq!(patch_points.len(), 1);
+    let patch_point = patch_points[0];
+    assert_eq!(patch_point as usize % 4, 0);
+    assert!(module.patch_points(target_a).is_empty());
+
+    let patcher = module.code_patcher();
+    let a = module.get_finalized_function(target_a);
+    let b = module.get_finalized_function(target_b);
+    let spin: SpinFn = unsafe { std::mem::transmute(module.get_finalized_function(spin)) };
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let runner = {
+        let stop = stop.clone();
+        std::thread::spawn(move || spin(&*stop))
+    };
+    for i in 0..FLIPS {
+        let target = if i % 2 == 0 { b } else { a };
+        unsafe { patcher.retarget_call(patch_point, target).unwrap() };
+    }
+    stop.store(true, Ordering::SeqCst);
+    let Calls { calls, b_calls } = runner.join().unwrap();
+    assert_ne!(calls, -1, "the patchable call reached an invalid target");
+    assert!(calls > 0);
+    assert!((0..=calls).contains(&b_calls));
+
+    // The last flip made the call reach `target_a` again.
+    let stop = AtomicBool::new(true);
+    assert_eq!(
+        spin(&stop),
+        Calls {
+            calls: 1,
+            b_calls: 0
+        }
+    );
+    unsafe { patcher.retarget_call(patch_point, b).unwrap() };
+    assert_eq!(
+        spin(&stop),
+        Calls {
+            calls: 1,
+            b_calls: 1
+        }
+    );
+
+    unsafe { module.free_memory() };
+}
+
+#[test]
+fn concurrent_jump_retargeting() {
+    const FLIPS: usize = 10_000;
+
+    let mut module = jit_module();
+    let spin = define_spin_jump(&mut module);
+    module.finalize_definitions().unwrap();
+
+    let patch_points = module.patch_points(spin);
+    assert_eq!(patch_points.len(), 1);
+    let patch_point = patch_points[0];
+    assert_eq!(patch_point as usize % 4, 0);
+
+    let patcher = module.code_patcher();
+    let spin_code = module.get_finalized_function(spin);
+    let [initial, alternate] = patcher.jump_destinations(patch_point).unwrap();
+    assert_ne!(initial, alternate);
+    for destination in [initial, alternate] {
+        assert_eq!(module.function_at(destination), Some(spin));
+    }
+    assert!(patcher.jump_destinations(spin_code).is_none());
+    let spin: SpinFn = unsafe { std::mem::transmute(spin_code) };
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let runner = {
+        let stop = stop.clone();
+        std::thread::spawn(move || spin(&*stop))
+    };
+    for i in 0..FLIPS {
+        unsafe { patcher.retarget_jump(patch_point, i % 2 == 0).unwrap() };
+    }
+    stop.store(true, Ordering::SeqCst);
+    let Calls { calls, b_calls } = runner.join().unwrap();
+    assert!(calls > 0);
+    assert!((0..=calls).contains(&b_calls));
+
+    // The last flip made the jump go to the initial destination again.
+    let stop = AtomicBool::new(true);
+    assert_eq!(
+        spin(&stop),
+        Calls {
+            calls: 1,
+            b_calls: 0
+        }
+    );
+    unsafe { patcher.retarget_jump(patch_point, true).unwrap() };
+    assert_eq!(
+        spin(&stop),
+        Calls {
+            calls: 1,
+            b_calls: 1
+        }
+    );
+
+    unsafe { module.free_memory() };
+}
+
+#[test]
+fn only_patch_points_can_be_patched() {
+    let mut module = jit_module();
+    let (target_a, _, spin) = define_functions(&mut module);
+    let spin_jump = define_spin_jump(&mut module);
+    module.finalize_definitions().unwrap();
+
+    let patcher = module.code_patcher();
+    let spin_code = module.get_finalized_function(spin);
+    let a = module.get_finalized_function(target_a);
+    assert!(!patcher.is_patch_point(spin_code));
+    assert!(unsafe { patcher.patch_word(spin_code, [0; 4]) }.is_err());
+    assert!(unsafe { patcher.retarget_call(spin_code, a) }.is_err());
+    assert!(unsafe { patcher.retarget_jump(spin_code, true) }.is_err());
+
+    // Calls and jumps can't be patched like one another.
+    let call = module.patch_points(spin)[0];
+    let jump = module.patch_points(spin_jump)[0];
+    assert!(unsafe { patcher.retarget_jump(call, true) }.is_err());
+    assert!(unsafe { patcher.retarget_call(jump, a) }.is_err());
+
+    // The code is left untouched.
+    let spin: SpinFn = unsafe { std::mem::transmute(spin_code) };
+    assert_eq!(
+        spin(&AtomicBool::new(true)),
+        Calls {
+            calls: 1,
+            b_calls: 0
+        }
+    );
+
+    unsafe { module.free_memory() };
+}
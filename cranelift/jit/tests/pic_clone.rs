@@ -0,0 +1,193 @@
+//! Copy strictly position-independent functions to memory of their own, with a GOT of their own,
+//! and run the copies.
+
+#![cfg(all(target_arch = "x86_64", unix))]
+
+use cranelift_codegen::ir::*;
+use cranelift_codegen::Context;
+use cranelift_frontend::*;
+use cranelift_jit::*;
+use cranelift_module::*;
+
+mod common;
+
+fn pic_strict_module() -> JITModule {
+    let mut builder = common::jit_builder(&[("is_pic", "true")]);
+    builder.pic_strict(true);
+    JITModule::new(builder)
+}
+
+/// Define `fn factorial(n: i64) -> i64`, which calls itself.
+fn define_factorial(module: &mut JITModule) -> FuncId {
+    let mut sig = module.make_signature();
+    sig.params.push(AbiParam::new(types::I64));
+    sig.returns.push(AbiParam::new(types::I64));
+    let id = module
+        .declare_function("factorial", Linkage::Local, &sig)
+        .unwrap();
+
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, id.as_u32()), sig);
+    // The call is to a colocated function, which strict position independence overrides.
+    let callee = module.declare_func_in_func(id, &mut ctx.func);
+    let mut func_ctx = FunctionBuilderContext::new();
+    let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+    let entry = bcx.create_block();
+    let recurse = bcx.create_block();
+    let done = bcx.create_block();
+    bcx.append_block_params_for_function_params(entry);
+    bcx.append_block_param(done, types::I64);
+
+    bcx.switch_to_block(entry);
+    let n = bcx.block_params(entry)[0];
+    let one = bcx.ins().iconst(types::I64, 1);
+    bcx.ins().brif(n, recurse, &[], done, &[one]);
+
+    bcx.switch_to_block(recurse);
+    let n_minus_one = bcx.ins().iadd_imm(n, -1);
+    let call = bcx.ins().call(callee, &[n_minus_one]);
+    let rest = bcx.inst_results(call)[0];
+    let result = bcx.ins().imul(n, rest);
+    bcx.ins().jump(done, &[result]);
+
+    bcx.switch_to_block(done);
+    let result = bcx.block_params(done)[0];
+    bcx.ins().return_(&[result]);
+    bcx.seal_all_blocks();
+    bcx.finalize();
+
+    module.define_function(id, &mut ctx).unwrap();
+    id
+}
+
+/// A mapping of `size` bytes of readable and writable memory.
+struct Mapping {
+    ptr: *mut u8,
+    size: usize,
+}
+
+impl Mapping {
+    fn new(size: usize) -> Self {
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_PRIVATE | libc::MAP_ANONYMOUS,
+                -1,
+                0,
+            )
+        };
+        assert_ne!(ptr, libc::MAP_FAILED);
+        Self {
+            ptr: ptr.cast(),
+            size,
+        }
+    }
+
+    fn make_executable(&self) {
+        let res = unsafe {
+            libc::mprotect(
+                self.ptr.cast(),
+                self.size,
+                libc::PROT_READ | libc::PROT_EXEC,
+            )
+        };
+        assert_eq!(res, 0);
+    }
+
+    fn as_fn(&self) -> extern "C" fn(i64) -> i64 {
+        unsafe { std::mem::transmute::<*mut u8, extern "C" fn(i64) -> i64>(self.ptr) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr.cast(), self.size) };
+    }
+}
+
+extern "C" fn hundred(_: i64) -> i64 {
+    100
+}
+
+#[test]
+fn clone_recursive_function() {
+    let mut module = pic_strict_module();
+    let factorial = define_factorial(&mut module);
+    module.finalize_definitions().unwrap();
+    let code = module.get_finalized_function(factorial);
+    let original = unsafe { std::mem::transmute::<*const u8, extern "C" fn(i64) -> i64>(code) };
+
+    // The only GOT entry used is the function's own.
+    let fixups = module.pic_fixups(factorial);
+    assert_eq!(fixups.got_entries.len(), 1);
+    assert_eq!(unsafe { *fixups.got_entries[0] }, code);
+
+    // A copy whose recursive call goes to the copy itself.
+    let copy = Mapping::new(fixups.code_size);
+    let copy_got = [copy.ptr as *const u8];
+    unsafe {
+        module
+            .clone_function_to(factorial, copy.ptr, copy_got.as_ptr() as *mut u8)
+            .unwrap()
+    };
+    copy.make_executable();
+
+    // A copy whose recursive call goes to a host function instead.
+    let other = Mapping::new(fixups.code_size);
+    let other_got = [hundred as *const u8];
+    unsafe {
+        module
+            .clone_function_to(factorial, other.ptr, other_got.as_ptr() as *mut u8)
+            .unwrap()
+    };
+    other.make_executable();
+
+    assert_eq!(original(10), 3628800);
+    assert_eq!(copy.as_fn()(10), 3628800);
+    assert_eq!(copy.as_fn()(0), 1);
+    assert_eq!(other.as_fn()(5), 500);
+    assert_eq!(other.as_fn()(0), 1);
+    // Neither copy changed the original or the other copy.
+    assert_eq!(original(5), 120);
+    assert_eq!(copy.as_fn()(5), 120);
+
+    // The copy doesn't depend on the original's memory.
+    unsafe { module.free_memory() };
+    assert_eq!(copy.as_fn()(20), 2432902008176640000);
+}
+
+#[test]
+fn reject_position_dependent_relocations() {
+    let mut module = pic_strict_module();
+    let mut sig = module.make_signature();
+    sig.returns.push(AbiParam::new(types::I64));
+    let callee = module
+        .declare_function("callee", Linkage::Local, &sig)
+        .unwrap();
+    let caller = module
+        .declare_function("caller", Linkage::Local, &sig)
+        .unwrap();
+
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, caller.as_u32()), sig);
+    let callee = module.declare_func_in_func(callee, &mut ctx.func);
+    let mut func_ctx = FunctionBuilderContext::new();
+    let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+    let entry = bcx.create_block();
+    bcx.switch_to_block(entry);
+    // Patchable calls are always direct, so they can't go through the GOT.
+    let call = bcx.ins().patchable_call(callee, &[]);
+    let result = bcx.inst_results(call)[0];
+    bcx.ins().return_(&[result]);
+    bcx.seal_all_blocks();
+    bcx.finalize();
+
+    let err = module.define_function(caller, &mut ctx).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Backend error: function caller has a CallPCRel4 relocation to u0:0, which isn't \
+         position-independent"
+    );
+}
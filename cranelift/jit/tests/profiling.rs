@@ -0,0 +1,128 @@
+//! Describe finalized functions to profilers in the perf map and jitdump files of the process.
+
+// The files are only written on Linux.
+#![cfg(target_os = "linux")]
+
+use cranelift_codegen::ir::*;
+use cranelift_codegen::Context;
+use cranelift_frontend::*;
+use cranelift_jit::*;
+use cranelift_module::*;
+
+mod common;
+
+fn jit_module(strategy: ProfilingStrategy) -> JITModule {
+    let mut builder = common::jit_builder(&[("is_pic", "true")]);
+    builder.profiling(strategy);
+    JITModule::new(builder)
+}
+
+/// Define `fn() -> i64` returning `value`, named `name` unless it's `None`.
+fn define_const(module: &mut JITModule, name: Option<&str>, value: i64) -> FuncId {
+    let mut sig = module.make_signature();
+    sig.returns.push(AbiParam::new(types::I64));
+    let id = match name {
+        Some(name) => module.declare_function(name, Linkage::Local, &sig),
+        None => module.declare_anonymous_function(&sig),
+    }
+    .unwrap();
+
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+    let block = bcx.create_block();
+    bcx.switch_to_block(block);
+    let value = bcx.ins().iconst(types::I64, value);
+    bcx.ins().return_(&[value]);
+    bcx.seal_all_blocks();
+    bcx.finalize();
+    module.define_function(id, &mut ctx).unwrap();
+    id
+}
+
+/// The entries `(address, size, name)` of the perf map of this process.
+fn perf_map() -> Vec<(usize, usize, String)> {
+    let map = std::fs::read_to_string(format!("/tmp/perf-{}.map", std::process::id())).unwrap();
+    map.lines()
+        .map(|line| {
+            let mut fields = line.splitn(3, ' ');
+            let mut number = || usize::from_str_radix(fields.next().unwrap(), 16).unwrap();
+            let (address, size) = (number(), number());
+            (address, size, fields.next().unwrap().to_string())
+        })
+        .collect()
+}
+
+/// Whether the perf map has an entry named `name` covering the code of `func`.
+fn in_perf_map(module: &JITModule, func: FuncId, name: &str) -> bool {
+    let code = module.get_finalized_function(func) as usize;
+    perf_map()
+        .iter()
+        .any(|(address, size, entry)| entry == name && (*address..address + size).contains(&code))
+}
+
+#[test]
+fn perf_map_entries() {
+    let mut module = jit_module(ProfilingStrategy::PerfMap);
+    let first = define_const(&mut module, Some("perf_map_first"), 1);
+    let anonymous = define_const(&mut module, None, 2);
+    module.finalize_definitions().unwrap();
+    assert!(in_perf_map(&module, first, "perf_map_first"));
+    assert!(in_perf_map(&module, anonymous, &anonymous.to_string()));
+
+    // Later finalizations and other modules append to the same file.
+    let second = define_const(&mut module, Some("perf_map_second"), 3);
+    module.finalize_definitions().unwrap();
+    let mut other = jit_module(ProfilingStrategy::PerfMap);
+    let third = define_const(&mut other, Some("perf_map_third"), 4);
+    other.finalize_definitions().unwrap();
+    assert!(in_perf_map(&module, first, "perf_map_first"));
+    assert!(in_perf_map(&module, second, "perf_map_second"));
+    assert!(in_perf_map(&other, third, "perf_map_third"));
+
+    // Modules which don't profile don't write entries.
+    let mut quiet = jit_module(ProfilingStrategy::None);
+    let unlisted = define_const(&mut quiet, Some("perf_map_unlisted"), 5);
+    quiet.finalize_definitions().unwrap();
+    assert!(!in_perf_map(&quiet, unlisted, "perf_map_unlisted"));
+
+    unsafe {
+        module.free_memory();
+        other.free_memory();
+        quiet.free_memory();
+    }
+}
+
+#[test]
+fn jitdump_records() {
+    let mut module = jit_module(ProfilingStrategy::JitDump);
+    let id = define_const(&mut module, Some("jitdump_const"), 42);
+    module.finalize_definitions().unwrap();
+    let code = module.get_finalized_function(id);
+
+    let path = std::env::temp_dir().join(format!("jit-{}.dump", std::process::id()));
+    let dump = std::fs::read(path).unwrap();
+    let u32_at = |offset: usize| u32::from_ne_bytes(dump[offset..offset + 4].try_into().unwrap());
+    let u64_at = |offset: usize| u64::from_ne_bytes(dump[offset..offset + 8].try_into().unwrap());
+    assert_eq!(u32_at(0), 0x4A69_5444);
+    assert_eq!(u32_at(20), std::process::id());
+
+    // Find the code load record of the function among the records after the header.
+    let mut offset = u32_at(8) as usize;
+    let mut found = false;
+    while offset < dump.len() {
+        let (id, size) = (u32_at(offset), u32_at(offset + 4) as usize);
+        if id == 0 && u64_at(offset + 32) == code as u64 {
+            let code_size = u64_at(offset + 40) as usize;
+            let name = &dump[offset + 56..offset + size - code_size];
+            assert_eq!(name, b"jitdump_const\0");
+            let copy = &dump[offset + size - code_size..offset + size];
+            assert_eq!(copy, unsafe { std::slice::from_raw_parts(code, code_size) });
+            found = true;
+        }
+        offset += size;
+    }
+    assert!(found);
+    unsafe { module.free_memory() };
+}
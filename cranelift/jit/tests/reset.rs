@@ -0,0 +1,110 @@
+//! Reset a module and define functions again in the memory it kept.
+
+use cranelift_codegen::ir::*;
+use cranelift_codegen::Context;
+use cranelift_frontend::*;
+use cranelift_jit::*;
+use cranelift_module::*;
+
+mod common;
+
+fn jit_module(is_pic: bool) -> JITModule {
+    JITModule::new(common::jit_builder(&[(
+        "is_pic",
+        if is_pic { "true" } else { "false" },
+    )]))
+}
+
+/// Define and finalize `fn add_n(x: i64) -> i64`, adding the 8-byte data object `n` to `x`.
+fn define_add(module: &mut JITModule, n: i64) -> FuncId {
+    let data = module
+        .declare_data("n", Linkage::Local, false, false)
+        .unwrap();
+    let mut desc = DataDescription::new();
+    desc.define(n.to_ne_bytes().into());
+    module.define_data(data, &desc).unwrap();
+
+    let mut sig = module.make_signature();
+    sig.params.push(AbiParam::new(types::I64));
+    sig.returns.push(AbiParam::new(types::I64));
+    let id = module
+        .declare_function("add_n", Linkage::Local, &sig)
+        .unwrap();
+
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, id.as_u32()), sig);
+    let n = module.declare_data_in_func(data, &mut ctx.func);
+    let pointer_type = module.target_config().pointer_type();
+    let mut func_ctx = FunctionBuilderContext::new();
+    let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+    let block = bcx.create_block();
+    bcx.append_block_params_for_function_params(block);
+    bcx.switch_to_block(block);
+    let x = bcx.block_params(block)[0];
+    let address = bcx.ins().global_value(pointer_type, n);
+    let n = bcx.ins().load(types::I64, MemFlags::trusted(), address, 0);
+    let sum = bcx.ins().iadd(x, n);
+    bcx.ins().return_(&[sum]);
+    bcx.seal_all_blocks();
+    bcx.finalize();
+
+    module.define_function(id, &mut ctx).unwrap();
+    module.finalize_definitions().unwrap();
+    id
+}
+
+#[test]
+fn define_after_reset() {
+    for is_pic in [false, true] {
+        let mut module = jit_module(is_pic);
+        let mut allocated = None;
+        for n in 0..100 {
+            // The declarations are forgotten with the definitions, so the same names are declared
+            // again, with the same ids.
+            let id = define_add(&mut module, n);
+            assert_eq!(id.as_u32(), 0);
+            let add: extern "C" fn(i64) -> i64 =
+                unsafe { module.get_finalized_function_typed(id) }.unwrap();
+            assert_eq!(add(1), n + 1);
+
+            // Every definition reuses the memory of the previous one.
+            let bytes = module.allocated_bytes();
+            assert_ne!(bytes, 0);
+            assert_eq!(*allocated.get_or_insert(bytes), bytes, "after {n} resets");
+            unsafe { module.reset() }.unwrap();
+            assert_eq!(module.allocated_bytes(), bytes);
+        }
+        unsafe { module.free_memory() };
+    }
+}
+
+#[test]
+fn reset_before_finalizing() {
+    let mut module = jit_module(false);
+    let mut sig = module.make_signature();
+    sig.returns.push(AbiParam::new(types::I64));
+    let id = module
+        .declare_function("unfinalized", Linkage::Local, &sig)
+        .unwrap();
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+    let block = bcx.create_block();
+    bcx.switch_to_block(block);
+    let zero = bcx.ins().iconst(types::I64, 0);
+    bcx.ins().return_(&[zero]);
+    bcx.seal_all_blocks();
+    bcx.finalize();
+    module.define_function(id, &mut ctx).unwrap();
+
+    // The pending definition is dropped rather than finalized.
+    unsafe { module.reset() }.unwrap();
+    assert!(module.declarations().get_functions().next().is_none());
+    module.finalize_definitions().unwrap();
+    let id = define_add(&mut module, 2);
+    let add: extern "C" fn(i64) -> i64 =
+        unsafe { module.get_finalized_function_typed(id) }.unwrap();
+    assert_eq!(add(40), 42);
+    unsafe { module.free_memory() };
+}
@@ -0,0 +1,144 @@
+use cranelift_codegen::ir::*;
+use cranelift_codegen::Context;
+use cranelift_frontend::*;
+use cranelift_jit::*;
+use cranelift_module::*;
+use std::cell::RefCell;
+
+mod common;
+
+const REF_A: usize = 0x1000_0a0a;
+const REF_B: usize = 0x2000_0b0b;
+
+thread_local! {
+    static MODULE: RefCell<*const JITModule> = RefCell::new(std::ptr::null());
+    static SAFEPOINTS: RefCell<Vec<(usize, Vec<usize>)>> = RefCell::new(Vec::new());
+}
+
+/// Called by the `shim` function with its own frame pointer. Walks one frame up to the function
+/// at the safepoint and records the references its stack map reports.
+extern "C" fn gc_callback(shim_fp: *const usize) {
+    let (caller_fp, return_address) = unsafe { (*shim_fp, *shim_fp.add(1)) };
+    let module = MODULE.with(|m| *m.borrow());
+    let stack_map = unsafe { &*module }
+        .stack_map_at(return_address as *const u8)
+        .expect("no stack map for the safepoint");
+
+    assert!(stack_map.entries().all(|(ty, _)| ty == types::R64));
+    let mut refs: Vec<usize> = stack_map
+        .slot_addresses(caller_fp as *const u8)
+        .map(|slot| unsafe { *(slot as *const usize) })
+        .collect();
+    refs.sort();
+    SAFEPOINTS.with(|s| s.borrow_mut().push((return_address, refs)));
+}
+
+fn define(
+    module: &mut JITModule,
+    id: FuncId,
+    sig: Signature,
+    body: impl FnOnce(&mut FunctionBuilder, &mut JITModule),
+) {
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.append_block_params_for_function_params(block);
+        bcx.switch_to_block(block);
+        body(&mut bcx, module);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module.define_function(id, &mut ctx).unwrap();
+}
+
+#[test]
+#[cfg_attr(not(any(target_arch = "x86_64", target_arch = "aarch64")), ignore)]
+fn stack_maps_at_safepoints() {
+    let mut builder = common::jit_builder(&[
+        ("is_pic", "true"),
+        ("enable_safepoints", "true"),
+        ("preserve_frame_pointers", "true"),
+    ]);
+    builder.symbol("gc_callback", gc_callback as *const u8);
+    builder.hotswap(true);
+    let mut module = JITModule::new(builder);
+    let ptr = module.target_config().pointer_type();
+
+    // fn gc_callback(fp: i64)
+    let mut callback_sig = module.make_signature();
+    callback_sig.params.push(AbiParam::new(ptr));
+    let callback = module
+        .declare_function("gc_callback", Linkage::Import, &callback_sig)
+        .unwrap();
+
+    // fn shim() { gc_callback(get_frame_pointer()) }
+    let shim_sig = module.make_signature();
+    let shim = module
+        .declare_function("shim", Linkage::Local, &shim_sig)
+        .unwrap();
+    define(&mut module, shim, shim_sig.clone(), |bcx, module| {
+        let callback = module.declare_func_in_func(callback, bcx.func);
+        let fp = bcx.ins().get_frame_pointer(ptr);
+        bcx.ins().call(callback, &[fp]);
+        bcx.ins().return_(&[]);
+    });
+
+    // fn test(a: r64, b: r64) -> (r64, i8) {
+    //     shim();           // safepoint with `a` and `b` live
+    //     let b_null = is_null(b);
+    //     shim();           // safepoint with only `a` live
+    //     (a, b_null)
+    // }
+    let mut test_sig = module.make_signature();
+    test_sig.params.push(AbiParam::new(types::R64));
+    test_sig.params.push(AbiParam::new(types::R64));
+    test_sig.returns.push(AbiParam::new(types::R64));
+    test_sig.returns.push(AbiParam::new(types::I8));
+    let test = module
+        .declare_function("test", Linkage::Local, &test_sig)
+        .unwrap();
+    let define_test = |module: &mut JITModule| {
+        define(module, test, test_sig.clone(), |bcx, module| {
+            let shim = module.declare_func_in_func(shim, bcx.func);
+            let block = bcx.current_block().unwrap();
+            let (a, b) = (bcx.block_params(block)[0], bcx.block_params(block)[1]);
+            bcx.ins().call(shim, &[]);
+            let b_null = bcx.ins().is_null(b);
+            bcx.ins().call(shim, &[]);
+            bcx.ins().return_(&[a, b_null]);
+        })
+    };
+    define_test(&mut module);
+    module.finalize_definitions().unwrap();
+
+    let run = |module: &JITModule| {
+        MODULE.with(|m| *m.borrow_mut() = module);
+        let code = module.get_finalized_function(test);
+        let test: extern "C" fn(usize, usize) -> (usize, i8) = unsafe { std::mem::transmute(code) };
+        assert_eq!(test(REF_A, REF_B), (REF_A, 0));
+        MODULE.with(|m| *m.borrow_mut() = std::ptr::null());
+
+        let safepoints = SAFEPOINTS.with(|s| s.take());
+        let refs: Vec<_> = safepoints.iter().map(|(_, refs)| refs.clone()).collect();
+        assert_eq!(refs, vec![vec![REF_A, REF_B], vec![REF_A]]);
+        safepoints
+            .into_iter()
+            .map(|(pc, _)| pc as *const u8)
+            .collect::<Vec<_>>()
+    };
+    let pcs = run(&module);
+
+    // Redefining the function drops the stack maps of its old code and records the new ones.
+    module.prepare_for_function_redefine(test).unwrap();
+    for &pc in &pcs {
+        assert!(module.stack_map_at(pc).is_none());
+    }
+    define_test(&mut module);
+    module.finalize_definitions().unwrap();
+    run(&module);
+
+    unsafe { module.free_memory() };
+}
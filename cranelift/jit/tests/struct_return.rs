@@ -0,0 +1,193 @@
+//! Return a 32-byte struct through a hidden pointer from JIT-compiled code and check that it
+//! agrees with the C ABI as implemented by Rust's `extern "C"` functions, in both directions.
+
+use cranelift_codegen::ir::*;
+use cranelift_codegen::Context;
+use cranelift_frontend::*;
+use cranelift_jit::*;
+use cranelift_module::*;
+use std::mem;
+
+mod common;
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Quad {
+    a: i64,
+    b: i64,
+    c: i64,
+    d: i64,
+}
+
+fn quad_return() -> StructReturn {
+    StructReturn::new(mem::size_of::<Quad>() as u32, mem::align_of::<Quad>() as u8)
+}
+
+/// Build a `Quad` like the JIT-compiled functions do, so that calls can be checked.
+extern "C" fn host_quad(x: i64, y: i64, z: i64, w: i64, u: i64, v: i64) -> Quad {
+    Quad {
+        a: x + y,
+        b: z - w,
+        c: u * v,
+        d: x ^ v,
+    }
+}
+
+fn jit_module() -> JITModule {
+    let mut builder = common::jit_builder(&[]);
+    builder.symbol("host_quad", host_quad as *const u8);
+    JITModule::new(builder)
+}
+
+/// The signature of `host_quad` and of the JIT-compiled function computing the same.
+fn quad_signature(module: &JITModule) -> Signature {
+    let mut sig = module.make_signature();
+    sig.params = vec![AbiParam::new(types::I64); 6];
+    quad_return().add_to_signature(&mut sig, module.target_config().pointer_type());
+    sig
+}
+
+fn define_function(
+    module: &mut JITModule,
+    id: FuncId,
+    body: impl FnOnce(&mut FunctionBuilder, &mut JITModule, &[Value]),
+) {
+    let sig = module
+        .declarations()
+        .get_function_decl(id)
+        .signature
+        .clone();
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.append_block_params_for_function_params(block);
+        bcx.switch_to_block(block);
+        let params = bcx.block_params(block).to_vec();
+        body(&mut bcx, module, &params);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module.define_function(id, &mut ctx).unwrap();
+}
+
+/// Define `jit_quad`, which computes the same as `host_quad` in a stack slot and returns it.
+fn define_jit_quad(module: &mut JITModule) -> FuncId {
+    let sig = quad_signature(module);
+    let id = module
+        .declare_function("jit_quad", Linkage::Local, &sig)
+        .unwrap();
+    let config = module.target_config();
+    define_function(module, id, |bcx, _, params| {
+        // The first parameter is the hidden pointer.
+        assert_eq!(bcx.struct_return_pointer(), Some(params[0]));
+        let &[_, x, y, z, w, u, v] = params else {
+            unreachable!()
+        };
+        let fields = [
+            bcx.ins().iadd(x, y),
+            bcx.ins().isub(z, w),
+            bcx.ins().imul(u, v),
+            bcx.ins().bxor(x, v),
+        ];
+        let slot = bcx.create_struct_return_slot(quad_return());
+        for (i, &field) in fields.iter().enumerate() {
+            bcx.ins().stack_store(field, slot, 8 * i as i32);
+        }
+        let src = bcx.ins().stack_addr(config.pointer_type(), slot, 0);
+        bcx.return_struct(config, quad_return(), src);
+    });
+    id
+}
+
+/// Define a function calling `callee` with the arguments `(1, 2, ..., 6)` and returning the
+/// fields of the struct it returns, combined with different weights.
+fn define_caller(module: &mut JITModule, name: &str, callee: FuncId) -> FuncId {
+    let mut sig = module.make_signature();
+    sig.returns.push(AbiParam::new(types::I64));
+    let id = module.declare_function(name, Linkage::Local, &sig).unwrap();
+    define_function(module, id, |bcx, module, _| {
+        let callee = module.declare_func_in_func(callee, bcx.func);
+        let args = (1..=6)
+            .map(|arg| bcx.ins().iconst(types::I64, arg))
+            .collect::<Vec<_>>();
+        let slot = bcx.create_struct_return_slot(quad_return());
+        bcx.call_with_struct_return(callee, &args, slot);
+        let mut result = bcx.ins().iconst(types::I64, 0);
+        for i in 0..4 {
+            let field = bcx.ins().stack_load(types::I64, slot, 8 * i);
+            let weighted = bcx.ins().imul_imm(field, 1000_i64.pow(i as u32));
+            result = bcx.ins().iadd(result, weighted);
+        }
+        bcx.ins().return_(&[result]);
+    });
+    id
+}
+
+fn weighted(quad: Quad) -> i64 {
+    quad.a + quad.b * 1000 + quad.c * 1_000_000 + quad.d * 1_000_000_000
+}
+
+#[test]
+fn return_struct_to_rust() {
+    let mut module = jit_module();
+    let id = define_jit_quad(&mut module);
+    module.finalize_definitions().unwrap();
+    let code = module.get_finalized_function(id);
+
+    let jit_quad =
+        unsafe { mem::transmute::<_, extern "C" fn(i64, i64, i64, i64, i64, i64) -> Quad>(code) };
+    for &args in &[(1, 2, 3, 4, 5, 6), (-7, i64::MAX, 0, 9, -1, 3)] {
+        let (x, y, z, w, u, v) = args;
+        assert_eq!(jit_quad(x, y, z, w, u, v), host_quad(x, y, z, w, u, v));
+    }
+}
+
+#[test]
+fn call_struct_returning_functions() {
+    let mut module = jit_module();
+    let host_sig = quad_signature(&module);
+    let host = module
+        .declare_function("host_quad", Linkage::Import, &host_sig)
+        .unwrap();
+    let jit = define_jit_quad(&mut module);
+    let call_host = define_caller(&mut module, "call_host", host);
+    let call_jit = define_caller(&mut module, "call_jit", jit);
+    module.finalize_definitions().unwrap();
+
+    let expected = weighted(host_quad(1, 2, 3, 4, 5, 6));
+    for id in [call_host, call_jit] {
+        let code = module.get_finalized_function(id);
+        let caller = unsafe { mem::transmute::<_, extern "C" fn() -> i64>(code) };
+        assert_eq!(caller(), expected);
+    }
+}
+
+/// Both x86_64 conventions require the callee to return the hidden pointer in `rax`, and pass it
+/// like an ordinary first argument. Calling the function as one taking the pointer explicitly
+/// checks both.
+#[test]
+#[cfg(target_arch = "x86_64")]
+fn struct_return_pointer_in_rax() {
+    let mut module = jit_module();
+    let id = define_jit_quad(&mut module);
+    module.finalize_definitions().unwrap();
+    let code = module.get_finalized_function(id);
+
+    let jit_quad = unsafe {
+        mem::transmute::<_, extern "C" fn(*mut Quad, i64, i64, i64, i64, i64, i64) -> *mut Quad>(
+            code,
+        )
+    };
+    let mut quad = Quad {
+        a: 0,
+        b: 0,
+        c: 0,
+        d: 0,
+    };
+    let returned = jit_quad(&mut quad, 1, 2, 3, 4, 5, 6);
+    assert_eq!(returned, &mut quad as *mut Quad);
+    assert_eq!(quad, host_quad(1, 2, 3, 4, 5, 6));
+}
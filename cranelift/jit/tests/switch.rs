@@ -0,0 +1,118 @@
+use cranelift_codegen::ir::*;
+use cranelift_codegen::Context;
+use cranelift_frontend::*;
+use cranelift_module::*;
+
+mod common;
+
+/// Compile `fn(i32) -> i32` returning `case * 10` for each of `cases` and `-1` otherwise, and
+/// check every case and a few values in between.
+fn check_switch(cases: &[u32], strategy: SwitchStrategy) {
+    let mut module = common::jit_module();
+
+    let mut sig = module.make_signature();
+    sig.params.push(AbiParam::new(types::I32));
+    sig.returns.push(AbiParam::new(types::I32));
+    let func_id = module
+        .declare_function("switch", Linkage::Local, &sig)
+        .unwrap();
+
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, func_id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let entry = bcx.create_block();
+        let default = bcx.create_block();
+        let merge = bcx.create_block();
+        bcx.append_block_params_for_function_params(entry);
+        bcx.append_block_param(merge, types::I32);
+
+        bcx.switch_to_block(entry);
+        let val = bcx.block_params(entry)[0];
+        let mut switch = Switch::new();
+        switch.strategy(strategy);
+        let case_blocks: Vec<_> = cases
+            .iter()
+            .map(|&case| {
+                let block = bcx.create_block();
+                switch.set_entry(case.into(), block);
+                (case, block)
+            })
+            .collect();
+        switch.emit(&mut bcx, val, default);
+
+        for (case, block) in case_blocks {
+            bcx.switch_to_block(block);
+            let result = bcx.ins().iconst(types::I32, i64::from(case * 10));
+            bcx.ins().jump(merge, &[result]);
+        }
+        bcx.switch_to_block(default);
+        let result = bcx.ins().iconst(types::I32, -1);
+        bcx.ins().jump(merge, &[result]);
+
+        bcx.switch_to_block(merge);
+        let result = bcx.block_params(merge)[0];
+        bcx.ins().return_(&[result]);
+
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module.define_function(func_id, &mut ctx).unwrap();
+    module.finalize_definitions().unwrap();
+
+    let code = module.get_finalized_function(func_id);
+    let switch: extern "C" fn(u32) -> i32 = unsafe { std::mem::transmute(code) };
+
+    let max = cases.iter().copied().max().unwrap_or(0);
+    let mut probes: Vec<u32> = cases.to_vec();
+    probes.extend((0..=max.saturating_add(2)).step_by(((max / 64) as usize).max(1)));
+    probes.extend([max.saturating_add(1), u32::MAX, i32::MAX as u32]);
+    for probe in probes {
+        let expected = if cases.contains(&probe) {
+            (probe * 10) as i32
+        } else {
+            -1
+        };
+        assert_eq!(
+            switch(probe),
+            expected,
+            "{:?} switch over {:?} called with {}",
+            strategy,
+            cases,
+            probe
+        );
+    }
+
+    unsafe { module.free_memory() };
+}
+
+const CASE_SETS: &[&[u32]] = &[
+    &[],
+    &[0],
+    &[7],
+    &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9],
+    &[3, 17, 40, 42, 90, 128, 200, 250],
+    &[0, 1, 5, 7, 10, 11, 12, 1000, 100_000],
+];
+
+#[test]
+fn switch_auto() {
+    for cases in CASE_SETS {
+        check_switch(cases, SwitchStrategy::Auto);
+    }
+}
+
+#[test]
+fn switch_jump_table() {
+    for cases in CASE_SETS {
+        check_switch(cases, SwitchStrategy::JumpTable);
+    }
+}
+
+#[test]
+fn switch_tree() {
+    for cases in CASE_SETS {
+        check_switch(cases, SwitchStrategy::Tree);
+    }
+}
@@ -0,0 +1,323 @@
+use cranelift_codegen::data_value::DataValue;
+use cranelift_codegen::ir::immediates::{Ieee32, Ieee64};
+use cranelift_codegen::ir::*;
+use cranelift_codegen::isa;
+use cranelift_codegen::Context;
+use cranelift_frontend::*;
+use cranelift_jit::trampoline::{BoundTrampoline, TrampolineCache};
+use cranelift_jit::*;
+use cranelift_module::*;
+
+mod common;
+
+/// Passing `i128` values on x86_64 needs the LLVM ABI extensions.
+const FLAGS: &[(&str, &str)] = &[("enable_llvm_abi_extensions", "true")];
+
+/// Compile a function returning its arguments unchanged and call it through a [BoundTrampoline].
+fn round_trip(values: &[DataValue], types: &[Type]) -> Vec<DataValue> {
+    let mut module = JITModule::new(common::jit_builder(FLAGS));
+
+    let mut sig = module.make_signature();
+    sig.params = types.iter().map(|&ty| AbiParam::new(ty)).collect();
+    sig.returns = sig.params.clone();
+    let func_id = module
+        .declare_function("identity", Linkage::Local, &sig)
+        .unwrap();
+
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, 0), sig.clone());
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.append_block_params_for_function_params(block);
+        bcx.switch_to_block(block);
+        let params = bcx.block_params(block).to_vec();
+        bcx.ins().return_(&params);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module.define_function(func_id, &mut ctx).unwrap();
+    module.finalize_definitions().unwrap();
+
+    let returned = unsafe {
+        let target = module.get_finalized_function(func_id);
+        let trampoline = BoundTrampoline::new(common::host_isa(FLAGS), &sig, target).unwrap();
+        trampoline.call(values)
+    };
+    unsafe { module.free_memory() };
+    returned
+}
+
+#[test]
+fn scalars() {
+    let cases = [
+        (DataValue::I8(-0x12), types::I8),
+        (DataValue::I16(0x1234), types::I16),
+        (DataValue::I32(-0x1234_5678), types::I32),
+        (DataValue::I64(0x0123_4567_89ab_cdef), types::I64),
+        (
+            DataValue::I128(-0x0123_4567_89ab_cdef_fedc_ba98_7654_3210),
+            types::I128,
+        ),
+        (DataValue::F32(Ieee32::with_float(-1.5)), types::F32),
+        (DataValue::F64(Ieee64::with_float(6.25e100)), types::F64),
+    ];
+    for (value, ty) in cases {
+        let values = [value];
+        assert_eq!(round_trip(&values, &[ty]), values, "{}", ty);
+    }
+}
+
+#[test]
+fn vectors() {
+    let bytes = [
+        0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0xfe, 0xdc, 0xba, 0x98, 0x76, 0x54, 0x32,
+        0x10,
+    ];
+    let mut vector_types = vec![
+        types::I8X16,
+        types::I16X8,
+        types::I32X4,
+        types::I64X2,
+        types::F32X4,
+        types::F64X2,
+    ];
+    if cfg!(target_arch = "aarch64") {
+        vector_types.extend([types::I8X8, types::I16X4, types::I32X2, types::F32X2]);
+    }
+    for ty in vector_types {
+        let value = DataValue::read_from_slice_le(&bytes, ty);
+        let values = [value];
+        assert_eq!(round_trip(&values, &[ty]), values, "{}", ty);
+    }
+}
+
+#[test]
+fn mixed_signature() {
+    let values = [
+        DataValue::I8(7),
+        DataValue::F64(Ieee64::with_float(0.5)),
+        DataValue::V128([0xaa; 16]),
+        DataValue::I128(1 << 100),
+        DataValue::F32(Ieee32::with_float(3.0)),
+        DataValue::I64(-1),
+    ];
+    let types = [
+        types::I8,
+        types::F64,
+        types::I32X4,
+        types::I128,
+        types::F32,
+        types::I64,
+    ];
+    assert_eq!(round_trip(&values, &types), values);
+}
+
+#[test]
+fn memory_returns() {
+    // More results than there are return registers on any host, so that some are returned
+    // through memory; they must still come back in declaration order.
+    let values: Vec<_> = (0..10).map(|i| DataValue::I64(i * 0x1_0001 - 3)).collect();
+    assert_eq!(round_trip(&values, &[types::I64; 10]), values);
+    let values: Vec<_> = (0..10)
+        .map(|i| DataValue::F64(Ieee64::with_float(f64::from(i) + 0.25)))
+        .collect();
+    assert_eq!(round_trip(&values, &[types::F64; 10]), values);
+}
+
+/// Define a function with the signature `sig` in `module`, whose results `body` computes from
+/// its parameters.
+fn define(
+    module: &mut JITModule,
+    sig: &Signature,
+    body: impl FnOnce(&mut FunctionBuilder, &[Value]) -> Vec<Value>,
+) -> FuncId {
+    let func_id = module.declare_anonymous_function(sig).unwrap();
+    let mut ctx = module.make_context();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, func_id.as_u32()), sig.clone());
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.append_block_params_for_function_params(block);
+        bcx.switch_to_block(block);
+        let params = bcx.block_params(block).to_vec();
+        let results = body(&mut bcx, &params);
+        bcx.ins().return_(&results);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module.define_function(func_id, &mut ctx).unwrap();
+    func_id
+}
+
+fn signature(call_conv: isa::CallConv, params: &[Type], returns: &[Type]) -> Signature {
+    let mut sig = Signature::new(call_conv);
+    sig.params = params.iter().map(|&ty| AbiParam::new(ty)).collect();
+    sig.returns = returns.iter().map(|&ty| AbiParam::new(ty)).collect();
+    sig
+}
+
+/// The sum of `params[i] * (i + 1)`, which depends on the order of the parameters.
+fn weighted_sum(bcx: &mut FunctionBuilder, params: &[Value]) -> Vec<Value> {
+    let mut sum = bcx.ins().iconst(types::I64, 0);
+    for (i, &param) in params.iter().enumerate() {
+        let term = bcx.ins().imul_imm(param, i as i64 + 1);
+        sum = bcx.ins().iadd(sum, term);
+    }
+    vec![sum]
+}
+
+#[test]
+fn cache() {
+    let mut module = JITModule::new(common::jit_builder(FLAGS));
+    let conv = module.isa().default_call_conv();
+    let i64s = [types::I64; 9];
+    let nine: Vec<DataValue> = (1..=9).map(DataValue::I64).collect();
+    // 1 * 1 + 2 * 2 + ... + 9 * 9
+    let nine_sum = vec![DataValue::I64(285)];
+
+    let mut cases: Vec<(Signature, FuncId, Vec<DataValue>, Vec<DataValue>)> = Vec::new();
+    let mut case = |module: &mut JITModule,
+                    sig: Signature,
+                    body: &dyn Fn(&mut FunctionBuilder, &[Value]) -> Vec<Value>,
+                    args: Vec<DataValue>,
+                    expected: Vec<DataValue>| {
+        let id = define(module, &sig, body);
+        cases.push((sig, id, args, expected));
+    };
+
+    case(
+        &mut module,
+        signature(conv, &[], &[]),
+        &|_, _| vec![],
+        vec![],
+        vec![],
+    );
+    case(
+        &mut module,
+        signature(conv, &[types::I32], &[types::I32]),
+        &|bcx, p| vec![bcx.ins().iadd_imm(p[0], 1)],
+        vec![DataValue::I32(41)],
+        vec![DataValue::I32(42)],
+    );
+    case(
+        &mut module,
+        signature(conv, &[types::I64, types::I64], &[types::I64]),
+        &|bcx, p| vec![bcx.ins().isub(p[0], p[1])],
+        vec![DataValue::I64(10), DataValue::I64(3)],
+        vec![DataValue::I64(7)],
+    );
+    case(
+        &mut module,
+        signature(conv, &[types::F32, types::F32], &[types::F32]),
+        &|bcx, p| vec![bcx.ins().fmul(p[0], p[1])],
+        vec![
+            DataValue::F32(Ieee32::with_float(1.5)),
+            DataValue::F32(Ieee32::with_float(-4.0)),
+        ],
+        vec![DataValue::F32(Ieee32::with_float(-6.0))],
+    );
+    case(
+        &mut module,
+        signature(conv, &[types::F64], &[types::F64]),
+        &|bcx, p| vec![bcx.ins().fneg(p[0])],
+        vec![DataValue::F64(Ieee64::with_float(2.5))],
+        vec![DataValue::F64(Ieee64::with_float(-2.5))],
+    );
+    case(
+        &mut module,
+        signature(conv, &[types::I128, types::I128], &[types::I128]),
+        &|bcx, p| vec![bcx.ins().iadd(p[0], p[1])],
+        vec![DataValue::I128(u64::MAX as i128), DataValue::I128(1 << 64)],
+        vec![DataValue::I128((u64::MAX as i128) + (1 << 64))],
+    );
+    case(
+        &mut module,
+        signature(conv, &[types::I8X16, types::I8X16], &[types::I8X16]),
+        &|bcx, p| vec![bcx.ins().iadd(p[0], p[1])],
+        vec![DataValue::V128([1; 16]), DataValue::V128([2; 16])],
+        vec![DataValue::V128([3; 16])],
+    );
+    let halves = [0.5f64.to_le_bytes(), (-1.0f64).to_le_bytes()].concat();
+    let doubled = [1.0f64.to_le_bytes(), (-2.0f64).to_le_bytes()].concat();
+    case(
+        &mut module,
+        signature(conv, &[types::F64X2], &[types::F64X2]),
+        &|bcx, p| vec![bcx.ins().fadd(p[0], p[0])],
+        vec![DataValue::read_from_slice_le(&halves, types::F64X2)],
+        vec![DataValue::read_from_slice_le(&doubled, types::F64X2)],
+    );
+    let mixed = [types::I32, types::I64, types::F32, types::F64];
+    let reversed = [types::F64, types::F32, types::I64, types::I32];
+    case(
+        &mut module,
+        signature(conv, &mixed, &reversed),
+        &|_, p| p.iter().rev().copied().collect(),
+        vec![
+            DataValue::I32(-1),
+            DataValue::I64(1 << 40),
+            DataValue::F32(Ieee32::with_float(0.25)),
+            DataValue::F64(Ieee64::with_float(1e300)),
+        ],
+        vec![
+            DataValue::F64(Ieee64::with_float(1e300)),
+            DataValue::F32(Ieee32::with_float(0.25)),
+            DataValue::I64(1 << 40),
+            DataValue::I32(-1),
+        ],
+    );
+    case(
+        &mut module,
+        signature(conv, &[types::I128], &[types::I64, types::I64]),
+        &|bcx, p| {
+            let (lo, hi) = bcx.ins().isplit(p[0]);
+            vec![hi, lo]
+        },
+        vec![DataValue::I128((7 << 64) | 9)],
+        vec![DataValue::I64(7), DataValue::I64(9)],
+    );
+    // Nine arguments don't fit in the argument registers of any calling convention, so some are
+    // passed on the stack.
+    case(
+        &mut module,
+        signature(conv, &i64s, &[types::I64]),
+        &weighted_sum,
+        nine.clone(),
+        nine_sum.clone(),
+    );
+    if cfg!(target_arch = "x86_64") {
+        for call_conv in [isa::CallConv::SystemV, isa::CallConv::WindowsFastcall] {
+            case(
+                &mut module,
+                signature(call_conv, &i64s, &[types::I64]),
+                &weighted_sum,
+                nine.clone(),
+                nine_sum.clone(),
+            );
+        }
+    }
+    module.finalize_definitions().unwrap();
+
+    let mut cache = TrampolineCache::new();
+    for (sig, id, args, expected) in &cases {
+        let target = module.get_finalized_function(*id);
+        let trampoline = cache.get(&mut module, sig).unwrap();
+        assert_eq!(trampoline.signature(), sig);
+        let results = unsafe { trampoline.call(target, args) };
+        assert_eq!(&results, expected, "{sig}");
+    }
+    // The default calling convention may be one of the explicit ones, whose trampoline is shared.
+    let distinct: std::collections::HashSet<_> = cases.iter().map(|case| &case.0).collect();
+    assert_eq!(cache.len(), distinct.len());
+
+    // Asking again for the same signatures reuses the trampolines.
+    for (sig, id, args, expected) in &cases {
+        let target = module.get_finalized_function(*id);
+        let trampoline = cache.get(&mut module, sig).unwrap();
+        assert_eq!(&unsafe { trampoline.call(target, args) }, expected, "{sig}");
+    }
+    assert_eq!(cache.len(), distinct.len());
+    unsafe { module.free_memory() };
+}
@@ -0,0 +1,179 @@
+use cranelift_codegen::ir::*;
+use cranelift_codegen::Context;
+use cranelift_frontend::*;
+use cranelift_jit::*;
+use cranelift_module::*;
+
+mod common;
+use common::jit_module;
+
+fn define(
+    module: &mut JITModule,
+    name: &str,
+    params: &[Type],
+    returns: &[Type],
+    body: impl FnOnce(&mut FunctionBuilder, &[Value]),
+) -> FuncId {
+    let mut sig = module.make_signature();
+    sig.params = params.iter().map(|&ty| AbiParam::new(ty)).collect();
+    sig.returns = returns.iter().map(|&ty| AbiParam::new(ty)).collect();
+    let id = module.declare_function(name, Linkage::Local, &sig).unwrap();
+
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.append_block_params_for_function_params(block);
+        bcx.switch_to_block(block);
+        let params = bcx.block_params(block).to_vec();
+        body(&mut bcx, &params);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module.define_function(id, &mut ctx).unwrap();
+    id
+}
+
+/// `fn sdiv(a: i32, b: i32) -> i32 { a / b }`
+#[cfg(feature = "signal-handlers")]
+fn define_sdiv(module: &mut JITModule) -> FuncId {
+    define(
+        module,
+        "sdiv",
+        &[types::I32, types::I32],
+        &[types::I32],
+        |bcx, params| {
+            let quotient = bcx.ins().sdiv(params[0], params[1]);
+            bcx.ins().return_(&[quotient]);
+        },
+    )
+}
+
+/// `fn user_trap() { trap user0 }`
+fn define_user_trap(module: &mut JITModule) -> FuncId {
+    define(module, "user_trap", &[], &[], |bcx, _| {
+        bcx.ins().trap(TrapCode::User(0));
+    })
+}
+
+#[test]
+fn lookup_trap() {
+    let mut module = jit_module();
+    let user_trap = define_user_trap(&mut module);
+    let empty = define(&mut module, "empty", &[], &[], |bcx, _| {
+        bcx.ins().return_(&[]);
+    });
+    module.finalize_definitions().unwrap();
+
+    // The function consists of a prologue and the trapping instruction.
+    let code = module.get_finalized_function(user_trap);
+    let sites: Vec<_> = (0..64)
+        .map(|offset| code.wrapping_add(offset))
+        .filter_map(|pc| module.lookup_trap_site(pc))
+        .collect();
+    assert_eq!(sites, vec![(user_trap, TrapCode::User(0))]);
+
+    let code = module.get_finalized_function(empty);
+    assert!((0..16).all(|offset| module.lookup_trap(code.wrapping_add(offset)).is_none()));
+
+    unsafe { module.free_memory() };
+}
+
+#[cfg(feature = "signal-handlers")]
+mod signal_handlers {
+    use super::*;
+    use cranelift_jit::signals::catch_traps;
+    use std::cell::Cell;
+
+    #[test]
+    fn sdiv_by_zero() {
+        let mut module = jit_module();
+        let sdiv = define_sdiv(&mut module);
+        module.finalize_definitions().unwrap();
+        let code = module.get_finalized_function(sdiv);
+        let sdiv: extern "C" fn(i32, i32) -> i32 = unsafe { std::mem::transmute(code) };
+
+        assert_eq!(unsafe { catch_traps(&module, || sdiv(7, 2)) }, Ok(3));
+        let trap = unsafe { catch_traps(&module, || sdiv(7, 0)) }.unwrap_err();
+        assert_eq!(trap.code(), TrapCode::IntegerDivisionByZero);
+        assert_eq!(
+            module.lookup_trap_site(trap.pc()),
+            Some((trap.func_id(), trap.code()))
+        );
+        let trap = unsafe { catch_traps(&module, || sdiv(i32::MIN, -1)) }.unwrap_err();
+        assert_eq!(trap.code(), TrapCode::IntegerOverflow);
+
+        // The handlers keep working after a trap.
+        assert_eq!(unsafe { catch_traps(&module, || sdiv(-9, 3)) }, Ok(-3));
+
+        unsafe { module.free_memory() };
+    }
+
+    #[test]
+    fn user_trap() {
+        let mut module = jit_module();
+        let user_trap_id = define_user_trap(&mut module);
+        module.finalize_definitions().unwrap();
+        let code = module.get_finalized_function(user_trap_id);
+        let user_trap: extern "C" fn() = unsafe { std::mem::transmute(code) };
+
+        for _ in 0..3 {
+            let trap = unsafe { catch_traps(&module, || user_trap()) }.unwrap_err();
+            assert_eq!(trap.code(), TrapCode::User(0));
+            assert_eq!(trap.func_id(), user_trap_id);
+            assert_eq!(module.lookup_trap(trap.pc()), Some(TrapCode::User(0)));
+            assert!(trap.pc() >= code);
+        }
+
+        unsafe { module.free_memory() };
+    }
+
+    #[test]
+    fn nested() {
+        let mut outer = jit_module();
+        let outer_trap = define_user_trap(&mut outer);
+        outer.finalize_definitions().unwrap();
+        let mut inner = jit_module();
+        let inner_sdiv = define_sdiv(&mut inner);
+        inner.finalize_definitions().unwrap();
+
+        let outer_trap: extern "C" fn() =
+            unsafe { std::mem::transmute(outer.get_finalized_function(outer_trap)) };
+        let sdiv: extern "C" fn(i32, i32) -> i32 =
+            unsafe { std::mem::transmute(inner.get_finalized_function(inner_sdiv)) };
+
+        // A trap in the inner module is caught by the inner call, and the outer call still
+        // catches its own traps afterwards.
+        let inner_result = Cell::new(None);
+        let trap = unsafe {
+            catch_traps(&outer, || {
+                inner_result.set(Some(catch_traps(&inner, || sdiv(1, 0))));
+                outer_trap()
+            })
+        }
+        .unwrap_err();
+        assert_eq!(trap.code(), TrapCode::User(0));
+        let inner_trap = inner_result.get().unwrap().unwrap_err();
+        assert_eq!(inner_trap.code(), TrapCode::IntegerDivisionByZero);
+        assert_eq!(inner_trap.func_id(), inner_sdiv);
+
+        // A trap of the outer module unwinds through the inner call.
+        let trap =
+            unsafe { catch_traps(&outer, || catch_traps(&inner, || outer_trap())) }.unwrap_err();
+        assert_eq!(trap.code(), TrapCode::User(0));
+
+        unsafe {
+            outer.free_memory();
+            inner.free_memory();
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "host panic")]
+    fn panics_propagate() {
+        let module = jit_module();
+        let _ = unsafe { catch_traps::<_, ()>(&module, || panic!("host panic")) };
+    }
+}
@@ -0,0 +1,174 @@
+//! Get finalized functions as typed function pointers, which are checked against the declared
+//! signatures.
+
+use cranelift_codegen::ir::*;
+use cranelift_codegen::isa::CallConv;
+use cranelift_codegen::Context;
+use cranelift_frontend::*;
+use cranelift_jit::*;
+use cranelift_module::*;
+
+mod common;
+use common::jit_module;
+
+/// Define and finalize `fn name(params...) -> ty` returning its first parameter, or 0 if it has
+/// none.
+fn define_first(module: &mut JITModule, name: &str, params: &[Type], ty: Type) -> FuncId {
+    let mut sig = module.make_signature();
+    sig.params = params.iter().map(|&param| AbiParam::new(param)).collect();
+    sig.returns.push(AbiParam::new(ty));
+    let id = module.declare_function(name, Linkage::Local, &sig).unwrap();
+
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+    let block = bcx.create_block();
+    bcx.append_block_params_for_function_params(block);
+    bcx.switch_to_block(block);
+    let result = match bcx.block_params(block).first() {
+        Some(&param) => param,
+        None => bcx.ins().iconst(ty, 0),
+    };
+    bcx.ins().return_(&[result]);
+    bcx.seal_all_blocks();
+    bcx.finalize();
+
+    module.define_function(id, &mut ctx).unwrap();
+    module.finalize_definitions().unwrap();
+    id
+}
+
+fn mismatch<F>(result: Result<F, SignatureMismatch>) -> SignatureMismatch {
+    result.map(|_| ()).unwrap_err()
+}
+
+#[test]
+fn matching_types() {
+    let mut module = jit_module();
+    let id = define_first(&mut module, "id_i32", &[types::I32], types::I32);
+    let f: extern "C" fn(i32) -> i32 = unsafe { module.get_finalized_function_typed(id) }.unwrap();
+    assert_eq!(f(-7), -7);
+    // Signedness isn't part of Cranelift types.
+    let f: extern "C" fn(u32) -> u32 = unsafe { module.get_finalized_function_typed(id) }.unwrap();
+    assert_eq!(f(7), 7);
+
+    let pointer_type = module.target_config().pointer_type();
+    let id = define_first(
+        &mut module,
+        "first_pointer",
+        &[pointer_type, types::I8],
+        pointer_type,
+    );
+    let f: extern "C" fn(*const u8, u8) -> *const u8 =
+        unsafe { module.get_finalized_function_typed(id) }.unwrap();
+    let byte = 0u8;
+    assert_eq!(f(&byte, 1), &byte as *const u8);
+
+    let id = define_first(&mut module, "zero", &[], types::I64);
+    let f: extern "C" fn() -> u64 = unsafe { module.get_finalized_function_typed(id) }.unwrap();
+    assert_eq!(f(), 0);
+}
+
+#[test]
+fn mismatched_types() {
+    let mut module = jit_module();
+    let id = define_first(&mut module, "id_i32", &[types::I32], types::I32);
+
+    let err =
+        mismatch(unsafe { module.get_finalized_function_typed::<extern "C" fn(i64) -> i64>(id) });
+    assert_eq!(err.name, "id_i32");
+    assert_eq!(err.requested, "extern \"C\" fn(i64) -> i64");
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "function `id_i32` has the signature `(i32) -> i32 {}`, which can't be called as \
+             `extern \"C\" fn(i64) -> i64`",
+            module.isa().default_call_conv()
+        )
+    );
+
+    // The number of parameters and results, and the types of the results, are checked too.
+    mismatch(unsafe { module.get_finalized_function_typed::<extern "C" fn(i32, i32) -> i32>(id) });
+    mismatch(unsafe { module.get_finalized_function_typed::<extern "C" fn() -> i32>(id) });
+    mismatch(unsafe { module.get_finalized_function_typed::<extern "C" fn(i32)>(id) });
+    mismatch(unsafe { module.get_finalized_function_typed::<extern "C" fn(i32) -> f32>(id) });
+
+    let id = define_first(&mut module, "id_f64", &[types::F64], types::F64);
+    mismatch(unsafe { module.get_finalized_function_typed::<extern "C" fn(i64) -> i64>(id) });
+    let f: extern "C" fn(f64) -> f64 = unsafe { module.get_finalized_function_typed(id) }.unwrap();
+    assert_eq!(f(1.5), 1.5);
+}
+
+#[test]
+fn mismatched_call_conv() {
+    let mut module = jit_module();
+    let mut sig = module.make_signature();
+    sig.call_conv = CallConv::Fast;
+    sig.params.push(AbiParam::new(types::I32));
+    sig.returns.push(AbiParam::new(types::I32));
+    let id = module
+        .declare_function("fast", Linkage::Local, &sig)
+        .unwrap();
+
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+    let block = bcx.create_block();
+    bcx.append_block_params_for_function_params(block);
+    bcx.switch_to_block(block);
+    let param = bcx.block_params(block)[0];
+    bcx.ins().return_(&[param]);
+    bcx.seal_all_blocks();
+    bcx.finalize();
+    module.define_function(id, &mut ctx).unwrap();
+    module.finalize_definitions().unwrap();
+
+    let err =
+        mismatch(unsafe { module.get_finalized_function_typed::<extern "C" fn(i32) -> i32>(id) });
+    assert_eq!(
+        err.to_string(),
+        format!(
+            "function `fast` has the signature `(i32) -> i32 fast`, which can't be called as \
+             `extern \"C\" fn(i32) -> i32`: `extern \"C\"` functions use the {} calling \
+             convention",
+            module.isa().default_call_conv()
+        )
+    );
+}
+
+#[test]
+fn owned_functions() {
+    // The function owns the module, which is otherwise gone by the time it's called.
+    let (first, id) = {
+        let mut module = jit_module();
+        let id = define_first(&mut module, "first", &[types::I32, types::I32], types::I32);
+        let first = unsafe { module.finalize_into_owned_fn::<extern "C" fn(i32, i32) -> i32>(id) };
+        (first.unwrap(), id)
+    };
+    assert_eq!(first.call(3, 4), 3);
+    assert_eq!(first.call(-1, 4), -1);
+    let info = first.module().get_finalized_function_info(id);
+    assert_eq!(info.ptr, first.module().get_finalized_function(id));
+    drop(first);
+
+    // Functions with no parameters or no results are called the same way.
+    let mut module = jit_module();
+    let id = define_first(&mut module, "zero", &[], types::I64);
+    let zero = unsafe { module.finalize_into_owned_fn::<extern "C" fn() -> u64>(id) }.unwrap();
+    assert_eq!(zero.call(), 0);
+
+    // The signature is checked as it is for the pointers.
+    let mut module = jit_module();
+    let id = define_first(&mut module, "id_i32", &[types::I32], types::I32);
+    let err =
+        unsafe { module.finalize_into_owned_fn::<extern "C" fn(i64) -> i64>(id) }.unwrap_err();
+    match err {
+        ModuleError::Backend(err) => {
+            let err = err.downcast_ref::<SignatureMismatch>().unwrap();
+            assert_eq!(err.name, "id_i32");
+        }
+        err => panic!("unexpected error: {err}"),
+    }
+}
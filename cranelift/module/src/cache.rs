@@ -0,0 +1,194 @@
+//! Reusing the compiled code of functions across module definitions.
+//!
+//! A [`ModuleCache`] stores the code compiled for each defined function, keyed by a hash of the
+//! function's contents, the target ISA and its flags, and a salt chosen by the module. Backends
+//! configured with a [`SaltedModuleCache`] consult it when defining a function: on a hit the
+//! stored code and relocations are used instead of running codegen, and on a miss the freshly
+//! compiled code is stored. Relocations are applied as usual in both cases. A
+//! [`CompileObserver`] registered with the cache is told which of the two happened for each
+//! function.
+
+use std::borrow::Cow;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::string::String;
+use std::sync::{Arc, Mutex};
+use std::vec::Vec;
+
+use cranelift_codegen::incremental_cache::CacheKvStore;
+use cranelift_codegen::ir::UserFuncName;
+use cranelift_codegen::isa::TargetIsa;
+use cranelift_codegen::{CompileError, CompiledCode, Context};
+use cranelift_control::ControlPlane;
+
+use crate::HashMap;
+
+/// Storage for the compiled code of functions, shared by any number of modules.
+///
+/// The keys are opaque hashes which cover everything the compiled code depends on, so entries
+/// never need to be invalidated; the values are opaque serialized artifacts. Implementations use
+/// interior mutability so that several modules, possibly on several threads, can share a cache.
+pub trait ModuleCache: Send + Sync {
+    /// Retrieve the value stored for `key`, if any.
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>>;
+
+    /// Store `value` for `key`, replacing any previous value.
+    fn put(&self, key: &[u8], value: Vec<u8>);
+}
+
+/// A [`ModuleCache`] keeping all its entries in memory.
+#[derive(Default)]
+pub struct InMemoryModuleCache {
+    entries: Mutex<HashMap<Vec<u8>, Vec<u8>>>,
+}
+
+impl InMemoryModuleCache {
+    /// Create an empty cache.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of entries currently in the cache.
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Whether the cache has no entries.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl ModuleCache for InMemoryModuleCache {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.entries.lock().unwrap().get(key).cloned()
+    }
+
+    fn put(&self, key: &[u8], value: Vec<u8>) {
+        self.entries.lock().unwrap().insert(key.to_vec(), value);
+    }
+}
+
+/// A [`ModuleCache`] keeping each entry in a file of a directory, so that it persists across
+/// processes.
+///
+/// Entries are written to a temporary file which is then renamed, so that concurrent readers
+/// never observe a partially written entry. Failing to read or write an entry isn't an error: the
+/// function is compiled anyway.
+pub struct DirectoryModuleCache {
+    dir: PathBuf,
+}
+
+impl DirectoryModuleCache {
+    /// Use the directory `dir` as a cache, creating it if it doesn't exist yet.
+    pub fn new(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    fn entry_path(&self, key: &[u8]) -> PathBuf {
+        let mut name = String::with_capacity(2 * key.len());
+        for byte in key {
+            name.push_str(&format!("{byte:02x}"));
+        }
+        self.dir.join(name)
+    }
+}
+
+impl ModuleCache for DirectoryModuleCache {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        fs::read(self.entry_path(key)).ok()
+    }
+
+    fn put(&self, key: &[u8], value: Vec<u8>) {
+        let path = self.entry_path(key);
+        let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+        if fs::write(&tmp, value).is_err() || fs::rename(&tmp, &path).is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+    }
+}
+
+/// Observes how the functions compiled through a [`SaltedModuleCache`] get their code.
+///
+/// Both methods do nothing by default.
+pub trait CompileObserver: Send + Sync {
+    /// Codegen ran for the function `name`, because the cache had no code for it.
+    fn codegen(&self, name: &UserFuncName) {
+        let _ = name;
+    }
+
+    /// The code of the function `name` was taken from the cache, without running codegen.
+    fn reused(&self, name: &UserFuncName) {
+        let _ = name;
+    }
+}
+
+/// A [`ModuleCache`] together with the salt a module mixes into the keys of its functions.
+///
+/// The salt identifies the producer of the IR, e.g. a frontend version, so that changing it
+/// stops reusing code compiled from IR that the previous producer generated.
+#[derive(Clone)]
+pub struct SaltedModuleCache {
+    cache: Arc<dyn ModuleCache>,
+    salt: Vec<u8>,
+    observer: Option<Arc<dyn CompileObserver>>,
+}
+
+impl SaltedModuleCache {
+    /// Use `cache` with the given `salt`.
+    pub fn new(cache: Arc<dyn ModuleCache>, salt: &[u8]) -> Self {
+        Self {
+            cache,
+            salt: salt.to_vec(),
+            observer: None,
+        }
+    }
+
+    /// Notify `observer` of each function compiled through this cache.
+    pub fn with_observer(mut self, observer: Arc<dyn CompileObserver>) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Compile the function of `ctx`, as in [`Context::compile`], reusing the code stored in the
+    /// cache if there is any.
+    ///
+    /// This replaces the salt of `ctx` set with [`Context::set_incremental_cache_salt`] by the
+    /// salt of this cache. Whether the code was reused is recorded in
+    /// [`Context::incremental_cache_stats`] and reported to the observer, if there is one.
+    pub fn compile<'a>(
+        &self,
+        ctx: &'a mut Context,
+        isa: &dyn TargetIsa,
+        ctrl_plane: &mut ControlPlane,
+    ) -> Result<&'a CompiledCode, CompileError<'a>> {
+        ctx.set_incremental_cache_salt(&self.salt);
+        let name = self.observer.as_ref().map(|_| ctx.func.name.clone());
+        let (compiled_code, reused) =
+            ctx.compile_with_cache(isa, &mut KvStore(&*self.cache), ctrl_plane)?;
+        if let (Some(observer), Some(name)) = (&self.observer, name) {
+            if reused {
+                observer.reused(&name);
+            } else {
+                observer.codegen(&name);
+            }
+        }
+        Ok(compiled_code)
+    }
+}
+
+/// Adapts a shared [`ModuleCache`] to the `CacheKvStore` interface of `Context`.
+struct KvStore<'a>(&'a dyn ModuleCache);
+
+impl CacheKvStore for KvStore<'_> {
+    fn get(&self, key: &[u8]) -> Option<Cow<'_, [u8]>> {
+        self.0.get(key).map(Cow::Owned)
+    }
+
+    fn insert(&mut self, key: &[u8], val: Vec<u8>) {
+        self.0.put(key, val);
+    }
+}
@@ -0,0 +1,140 @@
+//! Defines `ConstantPool`.
+
+use crate::data_context::DataDescription;
+use crate::module::{DataId, Module, ModuleResult};
+use crate::HashMap;
+use cranelift_codegen::cursor::{Cursor, FuncCursor};
+use cranelift_codegen::ir::{self, immediates::Imm64, InstBuilder, InstructionData, Opcode};
+use std::vec::Vec;
+
+/// The size and alignment of every entry of a [`ConstantPool`].
+///
+/// This matches the size of the largest vector type, so that every entry can be loaded with an
+/// aligned load and object files can mark the pool as a section of fixed-size mergeable entries.
+pub const CONSTANT_POOL_ENTRY_SIZE: usize = 16;
+
+/// A read-only data object shared by the functions of a module, holding their vector constants.
+///
+/// Normally every function carries its own copy of each vector constant it uses, emitted right
+/// after its code. Pooling instead rewrites every `vconst` into a load from the shared data
+/// object, so that a constant used by many functions is only stored once.
+///
+/// Constants which are all zeros or all ones are left alone, as backends typically materialize
+/// them without touching memory. Constants created by the backends themselves, such as masks
+/// used to lower some instructions, are still emitted per function.
+#[derive(Default)]
+pub struct ConstantPool {
+    data_id: Option<DataId>,
+    offsets: HashMap<Vec<u8>, u32>,
+    contents: Vec<u8>,
+}
+
+impl ConstantPool {
+    /// Create a new, empty `ConstantPool`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The size in bytes of the data object which is currently being filled.
+    pub fn size(&self) -> usize {
+        self.contents.len()
+    }
+
+    /// Move the vector constants of `func` into the pool, replacing every `vconst` with a load
+    /// from the pool's data object.
+    ///
+    /// The data object is declared in `module` the first time it is needed.
+    pub fn pool_function_constants<M: Module + ?Sized>(
+        &mut self,
+        module: &mut M,
+        func: &mut ir::Function,
+    ) -> ModuleResult<()> {
+        let mut pool_gv = None;
+        let mut entry_gvs = HashMap::new();
+        let pointer_type = module.target_config().pointer_type();
+
+        let mut pos = FuncCursor::new(func);
+        while let Some(_block) = pos.next_block() {
+            while let Some(inst) = pos.next_inst() {
+                let constant_handle = match pos.func.dfg.insts[inst] {
+                    InstructionData::UnaryConst {
+                        opcode: Opcode::Vconst,
+                        constant_handle,
+                    } => constant_handle,
+                    _ => continue,
+                };
+                let bytes = pos.func.dfg.constants.get(constant_handle).as_slice();
+                if bytes.len() > CONSTANT_POOL_ENTRY_SIZE
+                    || bytes.iter().all(|&b| b == 0)
+                    || bytes.iter().all(|&b| b == 0xff)
+                {
+                    continue;
+                }
+                let offset = self.insert(bytes);
+
+                let pool_gv = match pool_gv {
+                    Some(gv) => gv,
+                    None => {
+                        let data_id = match self.data_id {
+                            Some(data_id) => data_id,
+                            None => {
+                                let data_id = module.declare_anonymous_data(false, false)?;
+                                self.data_id = Some(data_id);
+                                data_id
+                            }
+                        };
+                        *pool_gv.insert(module.declare_data_in_func(data_id, pos.func))
+                    }
+                };
+                // Every entry is addressed through its own symbol offset rather than a load
+                // offset, so that the linker can relocate each entry on its own when it merges
+                // pools.
+                let gv = *entry_gvs.entry(offset).or_insert_with(|| {
+                    let mut data = pos.func.global_values[pool_gv].clone();
+                    if let ir::GlobalValueData::Symbol { offset: o, .. } = &mut data {
+                        *o = Imm64::new(offset.into());
+                    }
+                    pos.func.create_global_value(data)
+                });
+
+                let ty = pos.func.dfg.ctrl_typevar(inst);
+                let addr = pos.ins().symbol_value(pointer_type, gv);
+                pos.func.dfg.replace(inst).load(
+                    ty,
+                    ir::MemFlags::trusted().with_readonly(),
+                    addr,
+                    0,
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Take the contents of the pool, returning the data object to define with them, if any
+    /// constants were pooled.
+    ///
+    /// Functions pooling their constants after this go into a new data object, as a data object
+    /// can't grow once it is defined.
+    pub fn take(&mut self) -> Option<(DataId, DataDescription)> {
+        let data_id = self.data_id.take()?;
+        self.offsets.clear();
+        let mut data = DataDescription::new();
+        data.define(core::mem::take(&mut self.contents).into_boxed_slice());
+        data.set_align(CONSTANT_POOL_ENTRY_SIZE as u64);
+        Some((data_id, data))
+    }
+
+    /// Return the offset of the entry holding `bytes`, adding one if there is none yet.
+    fn insert(&mut self, bytes: &[u8]) -> u32 {
+        if let Some(&offset) = self.offsets.get(bytes) {
+            return offset;
+        }
+        let offset = self.contents.len() as u32;
+        self.contents.extend_from_slice(bytes);
+        self.contents
+            .resize(offset as usize + CONSTANT_POOL_ENTRY_SIZE, 0);
+        self.offsets.insert(bytes.to_vec(), offset);
+        offset
+    }
+}
@@ -9,7 +9,7 @@ use std::string::String;
 use std::vec::Vec;
 
 use crate::module::ModuleReloc;
-use crate::ModuleExtName;
+use crate::{DataId, FuncId, ModuleExtName};
 
 /// This specifies how data is to be initialized.
 #[derive(Clone, PartialEq, Eq, Debug)]
@@ -141,6 +141,114 @@ impl DataDescription {
         self.data_relocs.push((offset, data, addend))
     }
 
+    /// The size of the data defined so far, which is zero if it isn't initialized yet.
+    ///
+    /// This is the offset at which the `append_*` methods place what they append.
+    pub fn len(&self) -> usize {
+        match self.init {
+            Init::Uninitialized => 0,
+            _ => self.init.size(),
+        }
+    }
+
+    /// Whether the size of the data defined so far is zero.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Append `bytes` to the data, returning the offset at which they were placed.
+    ///
+    /// The `append_*` methods lay out the data piece by piece, starting from an uninitialized,
+    /// zero-initialized or already defined object. A data object stays zero-initialized as long as
+    /// only zeros are appended to it.
+    pub fn append_bytes(&mut self, bytes: &[u8]) -> CodeOffset {
+        let offset = self.append_offset();
+        let mut contents = match core::mem::replace(&mut self.init, Init::Uninitialized) {
+            Init::Uninitialized => Vec::new(),
+            Init::Zeros { size } => vec![0; size],
+            Init::Bytes { contents } => contents.into_vec(),
+        };
+        contents.extend_from_slice(bytes);
+        self.init = Init::Bytes {
+            contents: contents.into_boxed_slice(),
+        };
+        offset
+    }
+
+    /// Append `size` zero bytes to the data, returning the offset at which they were placed.
+    pub fn append_zeros(&mut self, size: usize) -> CodeOffset {
+        let offset = self.append_offset();
+        match &mut self.init {
+            Init::Uninitialized => self.init = Init::Zeros { size },
+            Init::Zeros { size: len } => *len += size,
+            Init::Bytes { .. } => {
+                self.append_bytes(&vec![0; size]);
+            }
+        }
+        offset
+    }
+
+    /// Append zeros until the size of the data is a multiple of `align`, which must be a power of
+    /// two, so that whatever is appended next is aligned to `align` bytes.
+    ///
+    /// This raises the alignment of the data object to `align` if it's lower, so that the
+    /// offsets are aligned in memory too.
+    pub fn align_to(&mut self, align: u64) {
+        assert!(align.is_power_of_two());
+        if !matches!(self.align, Some(current) if current >= align) {
+            self.align = Some(align);
+        }
+        let padding = self.len().wrapping_neg() & (align as usize - 1);
+        if padding != 0 {
+            self.append_zeros(padding);
+        }
+    }
+
+    /// Append the address of the function `func`, which is `pointer_bytes` large, returning the
+    /// offset at which it was placed.
+    ///
+    /// The address isn't aligned automatically; use `align_to` first if it should be.
+    pub fn append_func_addr(&mut self, func: FuncId, pointer_bytes: u8) -> CodeOffset {
+        let name = ModuleExtName::from(func);
+        let func_ref = match self.function_decls.iter().find(|(_, decl)| **decl == name) {
+            Some((func_ref, _)) => func_ref,
+            None => self.import_function(name),
+        };
+        let offset = self.append_pointer(pointer_bytes);
+        self.write_function_addr(offset, func_ref);
+        offset
+    }
+
+    /// Append the address of the data object `data` plus `addend`, which is `pointer_bytes`
+    /// large, returning the offset at which it was placed.
+    ///
+    /// The address isn't aligned automatically; use `align_to` first if it should be.
+    pub fn append_data_addr(
+        &mut self,
+        data: DataId,
+        addend: Addend,
+        pointer_bytes: u8,
+    ) -> CodeOffset {
+        let name = ModuleExtName::from(data);
+        let global_value = match self.data_decls.iter().find(|(_, decl)| **decl == name) {
+            Some((global_value, _)) => global_value,
+            None => self.import_global_value(name),
+        };
+        let offset = self.append_pointer(pointer_bytes);
+        self.write_data_addr(offset, global_value, addend);
+        offset
+    }
+
+    fn append_offset(&self) -> CodeOffset {
+        CodeOffset::try_from(self.len()).expect("data object too large")
+    }
+
+    /// Append the zeros which a relocation of an address of `pointer_bytes` overwrites.
+    fn append_pointer(&mut self, pointer_bytes: u8) -> CodeOffset {
+        assert!(pointer_bytes == 4 || pointer_bytes == 8);
+        self.append_zeros(pointer_bytes.into())
+    }
+
     /// An iterator over all relocations of the data object.
     pub fn all_relocs<'a>(
         &'a self,
@@ -170,7 +278,8 @@ impl DataDescription {
 
 #[cfg(test)]
 mod tests {
-    use crate::ModuleExtName;
+    use crate::{DataId, FuncId, ModuleExtName};
+    use cranelift_codegen::entity::EntityRef;
 
     use super::{DataDescription, Init};
 
@@ -224,4 +333,61 @@ mod tests {
         assert_eq!(data.function_relocs.len(), 0);
         assert_eq!(data.data_relocs.len(), 0);
     }
+
+    #[test]
+    fn append_layout() {
+        let mut data = DataDescription::new();
+        assert_eq!(data.len(), 0);
+        assert_eq!(data.append_zeros(3), 0);
+        data.align_to(4);
+        assert_eq!(data.init, Init::Zeros { size: 4 });
+        assert_eq!(data.align, Some(4));
+
+        assert_eq!(data.append_bytes(&[1, 2, 3, 4, 5]), 4);
+        data.align_to(8);
+        assert_eq!(data.len(), 16);
+        assert_eq!(data.append_func_addr(FuncId::new(7), 8), 16);
+        assert_eq!(data.append_data_addr(DataId::new(2), -4, 4), 24);
+        assert_eq!(data.append_func_addr(FuncId::new(7), 8), 28);
+        data.align_to(2);
+        assert_eq!(data.align, Some(8));
+        assert_eq!(data.len(), 36);
+
+        let mut expected = vec![0; 36];
+        expected[4..9].copy_from_slice(&[1, 2, 3, 4, 5]);
+        assert_eq!(
+            data.init,
+            Init::Bytes {
+                contents: expected.into_boxed_slice()
+            }
+        );
+        assert_eq!(data.function_decls.len(), 1);
+        assert_eq!(
+            data.function_decls.values().next(),
+            Some(&ModuleExtName::user(0, 7))
+        );
+        assert_eq!(
+            data.data_decls.values().next(),
+            Some(&ModuleExtName::user(1, 2))
+        );
+        let func = data.function_decls.keys().next().unwrap();
+        let global_value = data.data_decls.keys().next().unwrap();
+        assert_eq!(data.function_relocs, vec![(16, func), (28, func)]);
+        assert_eq!(data.data_relocs, vec![(24, global_value, -4)]);
+    }
+
+    #[test]
+    fn append_to_defined_data() {
+        let mut data = DataDescription::new();
+        data.define(vec![9; 3].into_boxed_slice());
+        data.append_zeros(2);
+        data.append_bytes(&[8]);
+        assert_eq!(
+            data.init,
+            Init::Bytes {
+                contents: vec![9, 9, 9, 0, 0, 8].into_boxed_slice()
+            }
+        );
+        assert_eq!(data.len(), 6);
+    }
 }
@@ -36,15 +36,28 @@ use std::string::String;
 
 use cranelift_codegen::ir;
 
+#[cfg(feature = "incremental-cache")]
+mod cache;
+mod constant_pool;
 mod data_context;
 mod module;
+mod multiversion;
+mod reachability;
 mod traps;
 
+#[cfg(feature = "incremental-cache")]
+pub use crate::cache::{
+    CompileObserver, DirectoryModuleCache, InMemoryModuleCache, ModuleCache, SaltedModuleCache,
+};
+pub use crate::constant_pool::{ConstantPool, CONSTANT_POOL_ENTRY_SIZE};
 pub use crate::data_context::{DataDescription, Init};
 pub use crate::module::{
     DataDeclaration, DataId, FuncId, FuncOrDataId, FunctionDeclaration, Linkage, Module,
-    ModuleDeclarations, ModuleError, ModuleExtName, ModuleReloc, ModuleResult,
+    ModuleCompiledFunction, ModuleDeclarations, ModuleError, ModuleExtName, ModuleReloc,
+    ModuleResult,
 };
+pub use crate::multiversion::{declare_function_variants, define_function_variants, TargetVariant};
+pub use crate::reachability::Reachability;
 pub use crate::traps::TrapSite;
 
 /// Version number of this crate.
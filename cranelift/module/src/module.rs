@@ -7,6 +7,7 @@
 
 use super::HashMap;
 use crate::data_context::DataDescription;
+use crate::multiversion::TargetVariant;
 use core::fmt::Display;
 use cranelift_codegen::binemit::{CodeOffset, Reloc};
 use cranelift_codegen::entity::{entity_impl, PrimaryMap};
@@ -17,6 +18,7 @@ use cranelift_codegen::{ir, isa, CodegenError, CompileError, Context};
 use cranelift_control::ControlPlane;
 use std::borrow::{Cow, ToOwned};
 use std::string::String;
+use std::vec::Vec;
 
 /// A module relocation.
 #[derive(Clone)]
@@ -305,7 +307,7 @@ impl std::fmt::Display for ModuleError {
             Self::IncompatibleSignature(name, prev_sig, new_sig) => {
                 write!(
                     f,
-                    "Function {} signature {:?} is incompatible with previous declaration {:?}",
+                    "Function {} signature {} is incompatible with previous declaration {}",
                     name, new_sig, prev_sig,
                 )
             }
@@ -346,6 +348,13 @@ impl std::convert::From<SetError> for ModuleError {
 /// A convenient alias for a `Result` that uses `ModuleError` as the error type.
 pub type ModuleResult<T> = Result<T, ModuleError>;
 
+/// Information about a function which was just defined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModuleCompiledFunction {
+    /// The size of the function's code and constant data, in bytes.
+    pub size: CodeOffset,
+}
+
 /// Information about a data object which can be accessed.
 #[derive(Debug)]
 #[cfg_attr(feature = "enable-serde", derive(serde::Serialize, serde::Deserialize))]
@@ -385,7 +394,7 @@ impl DataDeclaration {
 }
 
 /// A translated `ExternalName` into something global we can handle.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 #[cfg_attr(feature = "enable-serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ModuleExtName {
     /// User defined function, converted from `ExternalName::User`.
@@ -905,6 +914,19 @@ pub trait Module {
         data.import_global_value(ModuleExtName::user(1, data_id.as_u32()))
     }
 
+    /// Move the vector constants of `func` into a read-only data object shared by all functions
+    /// of the module, if this module pools constants. See [`ConstantPool`] for details.
+    ///
+    /// Modules which pool constants call this from `define_function` before compiling `func`.
+    /// The default implementation leaves `func` unchanged, so that every function keeps its own
+    /// constants.
+    ///
+    /// [`ConstantPool`]: crate::ConstantPool
+    fn pool_constants(&mut self, func: &mut ir::Function) -> ModuleResult<()> {
+        let _ = func;
+        Ok(())
+    }
+
     /// Define a function, producing the function body from the given `Context`.
     ///
     /// Returns the size of the function's code and constant data.
@@ -914,8 +936,22 @@ pub trait Module {
     ///
     /// Note: After calling this function the given `Context` will contain the compiled function.
     ///
+    /// With the `trace-log` feature, the function is defined in a `tracing` span named
+    /// `define_function`, with the `func` id and declared `name` of the function as fields.
+    ///
     /// [`define_function_with_control_plane`]: Self::define_function_with_control_plane
-    fn define_function(&mut self, func: FuncId, ctx: &mut Context) -> ModuleResult<()> {
+    fn define_function(
+        &mut self,
+        func: FuncId,
+        ctx: &mut Context,
+    ) -> ModuleResult<ModuleCompiledFunction> {
+        #[cfg(feature = "trace-log")]
+        let _span = tracing::debug_span!(
+            "define_function",
+            func = %func,
+            name = self.declarations().get_function_decl(func).name.as_deref(),
+        )
+        .entered();
         self.define_function_with_control_plane(func, ctx, &mut ControlPlane::default())
     }
 
@@ -929,7 +965,7 @@ pub trait Module {
         func: FuncId,
         ctx: &mut Context,
         ctrl_plane: &mut ControlPlane,
-    ) -> ModuleResult<()>;
+    ) -> ModuleResult<ModuleCompiledFunction>;
 
     /// Define a function, taking the function body from the given `bytes`.
     ///
@@ -945,10 +981,50 @@ pub trait Module {
         alignment: u64,
         bytes: &[u8],
         relocs: &[MachReloc],
-    ) -> ModuleResult<()>;
+    ) -> ModuleResult<ModuleCompiledFunction>;
 
     /// Define a data object, producing the data contents from the given `DataContext`.
     fn define_data(&mut self, data_id: DataId, data: &DataDescription) -> ModuleResult<()>;
+
+    /// Define the function `func` by compiling the function of `ctx` once for each of
+    /// `variants`, so that calls to `func` run the code of the first variant supported by the
+    /// machine running it. The last variant should be supported by every machine the code may run
+    /// on. Returns the ids of the functions defined for the variants, in the order of `variants`.
+    ///
+    /// Each variant gets its own local function, named after `func` and the variant, which is
+    /// declared with [`declare_function_variants`] and typically defined with
+    /// [`define_function_variants`]. How the variant is chosen is up to the module. The default
+    /// implementation returns an error, for modules which can't choose between variants.
+    ///
+    /// [`declare_function_variants`]: crate::declare_function_variants
+    /// [`define_function_variants`]: crate::define_function_variants
+    fn define_function_multiversion(
+        &mut self,
+        func: FuncId,
+        ctx: &mut Context,
+        variants: &[TargetVariant],
+    ) -> ModuleResult<Vec<FuncId>> {
+        let _ = (ctx, variants);
+        Err(ModuleError::Backend(anyhow::anyhow!(
+            "this module doesn't support multi-versioned function {}",
+            self.declarations()
+                .get_function_decl(func)
+                .linkage_name(func)
+        )))
+    }
+
+    /// Drop the definitions which can't be reached through relocations from `roots`, or from a
+    /// declaration visible outside of the module, and return the ids of the dropped definitions.
+    ///
+    /// Definitions made afterwards are kept, but must not refer to a dropped definition. The
+    /// default implementation returns an error, for modules which can't drop definitions, e.g.
+    /// because they may already be in use.
+    fn strip_unreachable(&mut self, roots: &[FuncOrDataId]) -> ModuleResult<Vec<FuncOrDataId>> {
+        let _ = roots;
+        Err(ModuleError::Backend(anyhow::anyhow!(
+            "this module doesn't support dropping unreachable definitions"
+        )))
+    }
 }
 
 impl<M: Module> Module for &mut M {
@@ -1027,7 +1103,15 @@ impl<M: Module> Module for &mut M {
         (**self).declare_data_in_data(data_id, data)
     }
 
-    fn define_function(&mut self, func: FuncId, ctx: &mut Context) -> ModuleResult<()> {
+    fn pool_constants(&mut self, func: &mut ir::Function) -> ModuleResult<()> {
+        (**self).pool_constants(func)
+    }
+
+    fn define_function(
+        &mut self,
+        func: FuncId,
+        ctx: &mut Context,
+    ) -> ModuleResult<ModuleCompiledFunction> {
         (**self).define_function(func, ctx)
     }
 
@@ -1036,7 +1120,7 @@ impl<M: Module> Module for &mut M {
         func: FuncId,
         ctx: &mut Context,
         ctrl_plane: &mut ControlPlane,
-    ) -> ModuleResult<()> {
+    ) -> ModuleResult<ModuleCompiledFunction> {
         (**self).define_function_with_control_plane(func, ctx, ctrl_plane)
     }
 
@@ -1047,11 +1131,24 @@ impl<M: Module> Module for &mut M {
         alignment: u64,
         bytes: &[u8],
         relocs: &[MachReloc],
-    ) -> ModuleResult<()> {
+    ) -> ModuleResult<ModuleCompiledFunction> {
         (**self).define_function_bytes(func_id, func, alignment, bytes, relocs)
     }
 
     fn define_data(&mut self, data_id: DataId, data: &DataDescription) -> ModuleResult<()> {
         (**self).define_data(data_id, data)
     }
+
+    fn define_function_multiversion(
+        &mut self,
+        func: FuncId,
+        ctx: &mut Context,
+        variants: &[TargetVariant],
+    ) -> ModuleResult<Vec<FuncId>> {
+        (**self).define_function_multiversion(func, ctx, variants)
+    }
+
+    fn strip_unreachable(&mut self, roots: &[FuncOrDataId]) -> ModuleResult<Vec<FuncOrDataId>> {
+        (**self).strip_unreachable(roots)
+    }
 }
@@ -0,0 +1,126 @@
+//! Defines `TargetVariant`.
+
+use crate::module::{FuncId, Linkage, Module, ModuleError, ModuleResult};
+use cranelift_codegen::isa::{OwnedTargetIsa, TargetIsa};
+use cranelift_codegen::Context;
+use cranelift_control::ControlPlane;
+use std::format;
+use std::string::String;
+use std::vec::Vec;
+
+/// A set of ISA features to compile one version of a multi-versioned function for, see
+/// [`Module::define_function_multiversion`].
+#[derive(Clone)]
+pub struct TargetVariant {
+    name: String,
+    isa: OwnedTargetIsa,
+}
+
+impl TargetVariant {
+    /// A variant compiling for `isa`, whose function gets the symbol name of the multi-versioned
+    /// function followed by `.` and `name`.
+    ///
+    /// `isa` must have the same triple as the ISA of the module.
+    pub fn new(name: impl Into<String>, isa: OwnedTargetIsa) -> Self {
+        Self {
+            name: name.into(),
+            isa,
+        }
+    }
+
+    /// The name of the variant.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The ISA the variant compiles for.
+    pub fn isa(&self) -> &dyn TargetIsa {
+        &*self.isa
+    }
+
+    /// Whether the code of this variant can run on `host`, i.e. whether every ISA feature
+    /// enabled for this variant is also enabled for `host`.
+    pub fn is_supported_by(&self, host: &dyn TargetIsa) -> bool {
+        let host_flags = host.isa_flags();
+        self.isa.isa_flags().iter().all(|flag| {
+            flag.as_bool() != Some(true)
+                || host_flags.iter().any(|host_flag| {
+                    host_flag.name == flag.name && host_flag.as_bool() == Some(true)
+                })
+        })
+    }
+
+    /// The names of the ISA features enabled for this variant.
+    pub fn enabled_features(&self) -> Vec<&'static str> {
+        self.isa
+            .isa_flags()
+            .iter()
+            .filter(|flag| flag.as_bool() == Some(true))
+            .map(|flag| flag.name)
+            .collect()
+    }
+}
+
+/// Declare a function for each of `variants`, named after `func` and the variant. Returns the ids
+/// of the functions, in the order of `variants`.
+///
+/// This checks that there is at least one variant, and that every variant targets the triple of
+/// the ISA of the module.
+pub fn declare_function_variants<M: Module + ?Sized>(
+    module: &mut M,
+    func: FuncId,
+    variants: &[TargetVariant],
+) -> ModuleResult<Vec<FuncId>> {
+    let decl = module.declarations().get_function_decl(func);
+    let name = decl.linkage_name(func).into_owned();
+    let signature = decl.signature.clone();
+    if variants.is_empty() {
+        return Err(ModuleError::Backend(anyhow::anyhow!(
+            "multi-versioned function {} has no variants",
+            name
+        )));
+    }
+
+    let mut ids = Vec::with_capacity(variants.len());
+    for variant in variants {
+        if variant.isa().triple() != module.isa().triple() {
+            return Err(ModuleError::Backend(anyhow::anyhow!(
+                "variant {} of {} targets {} instead of {}",
+                variant.name(),
+                name,
+                variant.isa().triple(),
+                module.isa().triple()
+            )));
+        }
+        ids.push(module.declare_function(
+            &format!("{}.{}", name, variant.name()),
+            Linkage::Local,
+            &signature,
+        )?);
+    }
+    Ok(ids)
+}
+
+/// Declare a function for each of `variants` with [`declare_function_variants`], and define it by
+/// compiling the function of `ctx` for the ISA of the variant. Returns the ids of the functions,
+/// in the order of `variants`.
+///
+/// This is the part of [`Module::define_function_multiversion`] which doesn't depend on the
+/// backend. The functions are defined with [`Module::define_function_bytes`], so they get no trap,
+/// stack map or unwind information.
+pub fn define_function_variants<M: Module + ?Sized>(
+    module: &mut M,
+    func: FuncId,
+    ctx: &mut Context,
+    variants: &[TargetVariant],
+) -> ModuleResult<Vec<FuncId>> {
+    let ids = declare_function_variants(module, func, variants)?;
+    for (&id, variant) in ids.iter().zip(variants) {
+        let compiled = ctx.compile(variant.isa(), &mut ControlPlane::default())?;
+        let alignment = compiled.buffer.alignment as u64;
+        let code = compiled.code_buffer().to_vec();
+        let relocs = compiled.buffer.relocs().to_vec();
+        module.define_function_bytes(id, &ctx.func, alignment, &code, &relocs)?;
+    }
+    Ok(ids)
+}
@@ -0,0 +1,91 @@
+//! Defines `Reachability`.
+
+use crate::module::{DataId, FuncId, FuncOrDataId, Linkage, ModuleDeclarations, ModuleExtName};
+use cranelift_codegen::entity::SecondaryMap;
+use std::vec::Vec;
+
+/// The set of definitions of a module which are reachable through relocations from its roots,
+/// used by backends to drop the definitions nothing can refer to.
+///
+/// The roots are the given definitions and every declaration which is visible outside of the
+/// module, as other modules may refer to those. A backend computes the set with a worklist:
+///
+/// ```ignore
+/// let mut reachability = Reachability::new(module.declarations(), roots);
+/// while let Some(id) = reachability.pop() {
+///     for reloc in relocs_of_definition(id) {
+///         reachability.mark(&reloc.name);
+///     }
+/// }
+/// ```
+///
+/// Relocations of a data object count like those of a function, so that a function is reachable
+/// whenever a table holding its address is.
+pub struct Reachability {
+    functions: SecondaryMap<FuncId, bool>,
+    data_objects: SecondaryMap<DataId, bool>,
+    worklist: Vec<FuncOrDataId>,
+}
+
+impl Reachability {
+    /// Start with `roots` and the declarations of `declarations` which aren't local to the
+    /// module marked reachable.
+    pub fn new(declarations: &ModuleDeclarations, roots: &[FuncOrDataId]) -> Self {
+        let mut reachability = Self {
+            functions: SecondaryMap::new(),
+            data_objects: SecondaryMap::new(),
+            worklist: Vec::new(),
+        };
+        for &root in roots {
+            reachability.mark_id(root);
+        }
+        for (id, decl) in declarations.get_functions() {
+            if decl.linkage.is_definable() && decl.linkage != Linkage::Local {
+                reachability.mark_id(FuncOrDataId::Func(id));
+            }
+        }
+        for (id, decl) in declarations.get_data_objects() {
+            if decl.linkage.is_definable() && decl.linkage != Linkage::Local {
+                reachability.mark_id(FuncOrDataId::Data(id));
+            }
+        }
+        reachability
+    }
+
+    /// Mark the function or data object named by `name` reachable. Libcalls and known symbols
+    /// are never defined by the module, so they are ignored.
+    pub fn mark(&mut self, name: &ModuleExtName) {
+        match name {
+            ModuleExtName::User { .. } if ModuleDeclarations::is_function(name) => {
+                self.mark_id(FuncOrDataId::Func(FuncId::from_name(name)))
+            }
+            ModuleExtName::User { .. } => self.mark_id(FuncOrDataId::Data(DataId::from_name(name))),
+            ModuleExtName::LibCall(_) | ModuleExtName::KnownSymbol(_) => {}
+        }
+    }
+
+    fn mark_id(&mut self, id: FuncOrDataId) {
+        let reachable = match id {
+            FuncOrDataId::Func(id) => &mut self.functions[id],
+            FuncOrDataId::Data(id) => &mut self.data_objects[id],
+        };
+        if !*reachable {
+            *reachable = true;
+            self.worklist.push(id);
+        }
+    }
+
+    /// Take a reachable definition whose relocations haven't been marked yet, if there are any
+    /// left.
+    pub fn pop(&mut self) -> Option<FuncOrDataId> {
+        self.worklist.pop()
+    }
+
+    /// Whether `id` was marked reachable.
+    pub fn is_reachable(&self, id: FuncOrDataId) -> bool {
+        match id {
+            FuncOrDataId::Func(id) => self.functions[id],
+            FuncOrDataId::Data(id) => self.data_objects[id],
+        }
+    }
+}
@@ -1,26 +1,39 @@
 //! Defines `ObjectModule`.
 
+use crate::compact_unwind;
+use crate::ifunc;
 use anyhow::anyhow;
 use cranelift_codegen::binemit::{Addend, CodeOffset, Reloc};
 use cranelift_codegen::entity::SecondaryMap;
 use cranelift_codegen::isa::{OwnedTargetIsa, TargetIsa};
-use cranelift_codegen::{self, ir, MachReloc};
+use cranelift_codegen::{self, ir, CompileError, CompiledCode, MachReloc};
 use cranelift_control::ControlPlane;
+#[cfg(feature = "incremental-cache")]
+use cranelift_module::SaltedModuleCache;
 use cranelift_module::{
-    DataDescription, DataId, FuncId, Init, Linkage, Module, ModuleDeclarations, ModuleError,
-    ModuleExtName, ModuleReloc, ModuleResult,
+    ConstantPool, DataDescription, DataId, FuncId, FuncOrDataId, Init, Linkage, Module,
+    ModuleCompiledFunction, ModuleDeclarations, ModuleError, ModuleExtName, ModuleReloc,
+    ModuleResult, Reachability, TargetVariant, CONSTANT_POOL_ENTRY_SIZE,
 };
 use log::info;
 use object::write::{
     Object, Relocation, SectionId, StandardSection, Symbol, SymbolId, SymbolSection,
 };
 use object::{
-    RelocationEncoding, RelocationKind, SectionKind, SymbolFlags, SymbolKind, SymbolScope,
+    RelocationEncoding, RelocationKind, SectionFlags, SectionKind, SymbolFlags, SymbolKind,
+    SymbolScope,
 };
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::mem;
 use target_lexicon::PointerWidth;
 
+/// The number of functions which can be defined in sections of their own in a Mach-O object, see
+/// [`ObjectBuilder::per_function_section`].
+///
+/// Mach-O objects can't have more than 255 sections, and this leaves room for the sections of
+/// data objects and unwind info.
+pub const MACHO_MAX_FUNCTION_SECTIONS: usize = 240;
+
 /// A builder for `ObjectModule`.
 pub struct ObjectBuilder {
     isa: OwnedTargetIsa,
@@ -31,6 +44,12 @@ pub struct ObjectBuilder {
     name: Vec<u8>,
     libcall_names: Box<dyn Fn(ir::LibCall) -> String + Send + Sync>,
     per_function_section: bool,
+    subsections_via_symbols: bool,
+    compact_unwind: bool,
+    pool_constants: bool,
+    arm64e: bool,
+    #[cfg(feature = "incremental-cache")]
+    cache: Option<SaltedModuleCache>,
 }
 
 impl ObjectBuilder {
@@ -107,23 +126,103 @@ impl ObjectBuilder {
             name: name.into(),
             libcall_names,
             per_function_section: false,
+            subsections_via_symbols: false,
+            compact_unwind: false,
+            pool_constants: false,
+            arm64e: false,
+            #[cfg(feature = "incremental-cache")]
+            cache: None,
         })
     }
 
     /// Set if every function should end up in their own section.
+    ///
+    /// On Mach-O every function gets a `__TEXT,__text_N` section, where `N` is the index of the
+    /// function in hexadecimal. Mach-O objects can't have more than 255 sections, so defining
+    /// more than [`MACHO_MAX_FUNCTION_SECTIONS`] functions then fails; use
+    /// [`ObjectBuilder::subsections_via_symbols`] to let the linker strip the functions of a
+    /// single section separately instead.
     pub fn per_function_section(&mut self, per_function_section: bool) -> &mut Self {
         self.per_function_section = per_function_section;
         self
     }
+
+    /// Set if a Mach-O object should have the `MH_SUBSECTIONS_VIA_SYMBOLS` flag.
+    ///
+    /// With this flag the linker splits every section at its symbols, so that it can strip
+    /// every unused function and data object of the object file separately. This has no effect
+    /// for other binary formats.
+    pub fn subsections_via_symbols(&mut self, subsections_via_symbols: bool) -> &mut Self {
+        self.subsections_via_symbols = subsections_via_symbols;
+        self
+    }
+
+    /// Set if a Mach-O object should contain compact unwind info for x86_64 and aarch64
+    /// functions, which the linker turns into the `__unwind_info` section used by the system
+    /// unwinder and by profilers.
+    ///
+    /// Functions whose frame layout can't be described by a compact unwind encoding and
+    /// functions defined with [`Module::define_function_bytes`] get no unwind info. Nothing is
+    /// emitted if the `unwind_info` setting of the ISA is disabled. This has no effect for other
+    /// binary formats.
+    pub fn compact_unwind(&mut self, compact_unwind: bool) -> &mut Self {
+        self.compact_unwind = compact_unwind;
+        self
+    }
+
+    /// Set if the vector constants of all functions should be pooled in a single read-only data
+    /// object. See [`ConstantPool`] for more information.
+    ///
+    /// On ELF the pool gets its own `.rodata.cst16` section marked as mergeable, with every
+    /// entry referenced relative to the section. Note that linkers only merge entries across
+    /// object files once the section also records its entry size, which the `object` writer
+    /// doesn't support yet.
+    pub fn pool_constants(&mut self, pool_constants: bool) -> &mut Self {
+        self.pool_constants = pool_constants;
+        self
+    }
+
+    /// Set if an aarch64 Mach-O object should be marked as using the arm64e ABI, in which code
+    /// pointers are signed with pointer authentication.
+    ///
+    /// This only sets the CPU subtype of the object to `CPU_SUBTYPE_ARM64E` with the
+    /// `CPU_SUBTYPE_PTRAUTH_ABI` flag, which [`ObjectProduct::emit`] writes. The code is compiled
+    /// as configured by the ISA, so return addresses are only signed with the
+    /// `sign_return_address` and `sign_return_address_with_bkey` aarch64 settings enabled. This
+    /// has no effect for other targets and binary formats.
+    pub fn arm64e(&mut self, arm64e: bool) -> &mut Self {
+        self.arm64e = arm64e;
+        self
+    }
+
+    /// Reuse the code compiled for functions from `cache`, and store the code of functions which
+    /// aren't in it yet. See [`SaltedModuleCache`] for more information.
+    #[cfg(feature = "incremental-cache")]
+    pub fn cache(&mut self, cache: SaltedModuleCache) -> &mut Self {
+        self.cache = Some(cache);
+        self
+    }
 }
 
 /// An `ObjectModule` implements `Module` and emits ".o" files using the `object` library.
 ///
+/// Definitions are only added to the object by [`ObjectModule::finish`], so that
+/// [`Module::strip_unreachable`] can drop the definitions which nothing refers to.
+///
 /// See the `ObjectBuilder` for a convenient way to construct `ObjectModule` instances.
 pub struct ObjectModule {
     isa: OwnedTargetIsa,
     object: Object<'static>,
     declarations: ModuleDeclarations,
+    /// The definitions which haven't been added to `object` yet. Definitions are only added by
+    /// `finish`, so that `strip_unreachable` can still drop them.
+    definitions: Vec<Definition>,
+    defined: HashSet<FuncOrDataId>,
+    dropped: HashSet<FuncOrDataId>,
+    /// The multi-versioned functions, whose definitions are the resolvers of ELF indirect
+    /// functions.
+    ifuncs: HashSet<FuncId>,
+    /// The symbols of the declarations, which are created by `finish`.
     functions: SecondaryMap<FuncId, Option<(SymbolId, bool)>>,
     data_objects: SecondaryMap<DataId, Option<(SymbolId, bool)>>,
     relocs: Vec<SymbolRelocs>,
@@ -131,6 +230,14 @@ pub struct ObjectModule {
     libcall_names: Box<dyn Fn(ir::LibCall) -> String + Send + Sync>,
     known_symbols: HashMap<ir::KnownSymbol, SymbolId>,
     per_function_section: bool,
+    /// The number of functions defined in sections of their own in a Mach-O object.
+    function_sections: usize,
+    compact_unwind: Option<Vec<CompactUnwindEntry>>,
+    constant_pool: Option<ConstantPool>,
+    constant_pool_section: Option<(DataId, SectionId)>,
+    macho_cpu_subtype: Option<u32>,
+    #[cfg(feature = "incremental-cache")]
+    cache: Option<SaltedModuleCache>,
 }
 
 impl ObjectModule {
@@ -138,11 +245,33 @@ impl ObjectModule {
     pub fn new(builder: ObjectBuilder) -> Self {
         let mut object = Object::new(builder.binary_format, builder.architecture, builder.endian);
         object.flags = builder.flags;
+        let macho = builder.binary_format == object::BinaryFormat::MachO;
+        if macho && builder.subsections_via_symbols {
+            object.flags = object::FileFlags::MachO {
+                flags: object::macho::MH_SUBSECTIONS_VIA_SYMBOLS,
+            };
+        }
         object.add_file_symbol(builder.name);
+        let compact_unwind = if macho && builder.compact_unwind && builder.isa.flags().unwind_info()
+        {
+            Some(Vec::new())
+        } else {
+            None
+        };
+        let macho_cpu_subtype =
+            if macho && builder.arm64e && builder.architecture == object::Architecture::Aarch64 {
+                Some(object::macho::CPU_SUBTYPE_ARM64E | object::macho::CPU_SUBTYPE_PTRAUTH_ABI)
+            } else {
+                None
+            };
         Self {
             isa: builder.isa,
             object,
             declarations: ModuleDeclarations::default(),
+            definitions: Vec::new(),
+            defined: HashSet::new(),
+            dropped: HashSet::new(),
+            ifuncs: HashSet::new(),
             functions: SecondaryMap::new(),
             data_objects: SecondaryMap::new(),
             relocs: Vec::new(),
@@ -150,7 +279,115 @@ impl ObjectModule {
             libcall_names: builder.libcall_names,
             known_symbols: HashMap::new(),
             per_function_section: builder.per_function_section,
+            function_sections: 0,
+            compact_unwind,
+            constant_pool: builder.pool_constants.then(ConstantPool::new),
+            constant_pool_section: None,
+            macho_cpu_subtype,
+            #[cfg(feature = "incremental-cache")]
+            cache: builder.cache,
+        }
+    }
+
+    /// Compile the function of `ctx`, reusing its code from the cache if there is one.
+    fn compile<'a>(
+        &self,
+        ctx: &'a mut cranelift_codegen::Context,
+        ctrl_plane: &mut ControlPlane,
+    ) -> Result<&'a CompiledCode, CompileError<'a>> {
+        #[cfg(feature = "incremental-cache")]
+        if let Some(cache) = &self.cache {
+            return cache.compile(ctx, self.isa(), ctrl_plane);
+        }
+        ctx.compile(self.isa(), ctrl_plane)
+    }
+
+    /// The constant pool of this module, if constant pooling is enabled.
+    pub fn constant_pool(&self) -> Option<&ConstantPool> {
+        self.constant_pool.as_ref()
+    }
+
+    /// Define `alias` as another name for the code of the function `target`, starting `offset`
+    /// bytes into it, e.g. to give a function a second entry point.
+    ///
+    /// `target` must already be defined, and `alias` gets the binding and visibility of its own
+    /// linkage. On Mach-O the alias is marked as `N_ALT_ENTRY`, so that the linker keeps it
+    /// together with `target` when it splits sections at their symbols.
+    pub fn define_function_alias(
+        &mut self,
+        alias: FuncId,
+        target: FuncId,
+        offset: u64,
+    ) -> ModuleResult<()> {
+        let decl = self.declarations.get_function_decl(alias);
+        if !decl.linkage.is_definable() {
+            return Err(ModuleError::InvalidImportDefinition(
+                decl.linkage_name(alias).into_owned(),
+            ));
         }
+        if self.defined.contains(&FuncOrDataId::Func(alias)) {
+            return Err(ModuleError::DuplicateDefinition(
+                decl.linkage_name(alias).into_owned(),
+            ));
+        }
+        let size = self
+            .definitions
+            .iter()
+            .find_map(|definition| match *definition {
+                Definition::Function { id, ref bytes, .. } if id == target => Some(bytes.len()),
+                _ => None,
+            });
+        let target_name = self
+            .declarations
+            .get_function_decl(target)
+            .linkage_name(target);
+        let size = match size {
+            Some(size) if !self.ifuncs.contains(&target) => size as u64,
+            _ => {
+                return Err(ModuleError::Backend(anyhow!(
+                    "alias {} needs {} to be a defined function",
+                    decl.linkage_name(alias),
+                    target_name
+                )))
+            }
+        };
+        if offset >= size {
+            return Err(ModuleError::Backend(anyhow!(
+                "alias {} at offset {} is outside of the {} bytes of {}",
+                decl.linkage_name(alias),
+                offset,
+                size,
+                target_name
+            )));
+        }
+
+        self.defined.insert(FuncOrDataId::Func(alias));
+        self.definitions.push(Definition::Alias {
+            id: alias,
+            target,
+            offset,
+        });
+        Ok(())
+    }
+}
+
+/// The ELF flags of the resolver symbol of an indirect function, which has the binding and
+/// visibility `symbol` would otherwise get. Setting the flags overrides both.
+fn ifunc_symbol_flags(symbol: &Symbol) -> SymbolFlags<SectionId> {
+    let bind = if symbol.weak {
+        object::elf::STB_WEAK
+    } else if symbol.scope == SymbolScope::Compilation {
+        object::elf::STB_LOCAL
+    } else {
+        object::elf::STB_GLOBAL
+    };
+    let visibility = match symbol.scope {
+        SymbolScope::Linkage => object::elf::STV_HIDDEN,
+        _ => object::elf::STV_DEFAULT,
+    };
+    SymbolFlags::Elf {
+        st_info: (bind << 4) | object::elf::STT_GNU_IFUNC,
+        st_other: visibility,
     }
 }
 
@@ -182,55 +419,14 @@ impl Module for ObjectModule {
         signature: &ir::Signature,
     ) -> ModuleResult<FuncId> {
         validate_symbol(name)?;
-
-        let (id, linkage) = self
+        let (id, _linkage) = self
             .declarations
             .declare_function(name, linkage, signature)?;
-
-        let (scope, weak) = translate_linkage(linkage);
-
-        if let Some((function, _defined)) = self.functions[id] {
-            let symbol = self.object.symbol_mut(function);
-            symbol.scope = scope;
-            symbol.weak = weak;
-        } else {
-            let symbol_id = self.object.add_symbol(Symbol {
-                name: name.as_bytes().to_vec(),
-                value: 0,
-                size: 0,
-                kind: SymbolKind::Text,
-                scope,
-                weak,
-                section: SymbolSection::Undefined,
-                flags: SymbolFlags::None,
-            });
-            self.functions[id] = Some((symbol_id, false));
-        }
-
         Ok(id)
     }
 
     fn declare_anonymous_function(&mut self, signature: &ir::Signature) -> ModuleResult<FuncId> {
-        let id = self.declarations.declare_anonymous_function(signature)?;
-
-        let symbol_id = self.object.add_symbol(Symbol {
-            name: self
-                .declarations
-                .get_function_decl(id)
-                .linkage_name(id)
-                .into_owned()
-                .into_bytes(),
-            value: 0,
-            size: 0,
-            kind: SymbolKind::Text,
-            scope: SymbolScope::Compilation,
-            weak: false,
-            section: SymbolSection::Undefined,
-            flags: SymbolFlags::None,
-        });
-        self.functions[id] = Some((symbol_id, false));
-
-        Ok(id)
+        self.declarations.declare_anonymous_function(signature)
     }
 
     fn declare_data(
@@ -241,69 +437,25 @@ impl Module for ObjectModule {
         tls: bool,
     ) -> ModuleResult<DataId> {
         validate_symbol(name)?;
-
-        let (id, linkage) = self
+        let (id, _linkage) = self
             .declarations
             .declare_data(name, linkage, writable, tls)?;
-
-        // Merging declarations with conflicting values for tls is not allowed, so it is safe to use
-        // the passed in tls value here.
-        let kind = if tls {
-            SymbolKind::Tls
-        } else {
-            SymbolKind::Data
-        };
-        let (scope, weak) = translate_linkage(linkage);
-
-        if let Some((data, _defined)) = self.data_objects[id] {
-            let symbol = self.object.symbol_mut(data);
-            symbol.kind = kind;
-            symbol.scope = scope;
-            symbol.weak = weak;
-        } else {
-            let symbol_id = self.object.add_symbol(Symbol {
-                name: name.as_bytes().to_vec(),
-                value: 0,
-                size: 0,
-                kind,
-                scope,
-                weak,
-                section: SymbolSection::Undefined,
-                flags: SymbolFlags::None,
-            });
-            self.data_objects[id] = Some((symbol_id, false));
-        }
-
         Ok(id)
     }
 
     fn declare_anonymous_data(&mut self, writable: bool, tls: bool) -> ModuleResult<DataId> {
-        let id = self.declarations.declare_anonymous_data(writable, tls)?;
-
-        let kind = if tls {
-            SymbolKind::Tls
-        } else {
-            SymbolKind::Data
-        };
-
-        let symbol_id = self.object.add_symbol(Symbol {
-            name: self
-                .declarations
-                .get_data_decl(id)
-                .linkage_name(id)
-                .into_owned()
-                .into_bytes(),
-            value: 0,
-            size: 0,
-            kind,
-            scope: SymbolScope::Compilation,
-            weak: false,
-            section: SymbolSection::Undefined,
-            flags: SymbolFlags::None,
-        });
-        self.data_objects[id] = Some((symbol_id, false));
+        self.declarations.declare_anonymous_data(writable, tls)
+    }
 
-        Ok(id)
+    fn pool_constants(&mut self, func: &mut ir::Function) -> ModuleResult<()> {
+        match self.constant_pool.take() {
+            Some(mut pool) => {
+                let res = pool.pool_function_constants(self, func);
+                self.constant_pool = Some(pool);
+                res
+            }
+            None => Ok(()),
+        }
     }
 
     fn define_function_with_control_plane(
@@ -311,19 +463,26 @@ impl Module for ObjectModule {
         func_id: FuncId,
         ctx: &mut cranelift_codegen::Context,
         ctrl_plane: &mut ControlPlane,
-    ) -> ModuleResult<()> {
+    ) -> ModuleResult<ModuleCompiledFunction> {
         info!("defining function {}: {}", func_id, ctx.func.display());
-        let mut code: Vec<u8> = Vec::new();
-
-        let res = ctx.compile_and_emit(self.isa(), &mut code, ctrl_plane)?;
+        self.pool_constants(&mut ctx.func)?;
+        let res = self.compile(ctx, ctrl_plane)?;
         let alignment = res.buffer.alignment as u64;
+        let code = res.code_buffer().to_vec();
 
-        self.define_function_bytes(
+        let compiled_code = ctx.compiled_code().unwrap();
+        let compact_unwind = match self.compact_unwind {
+            Some(_) => compact_unwind::encoding(&*self.isa, &compiled_code.buffer.unwind_info),
+            None => None,
+        };
+
+        self.define_function_inner(
             func_id,
             &ctx.func,
             alignment,
-            &code,
-            ctx.compiled_code().unwrap().buffer.relocs(),
+            code,
+            compiled_code.buffer.relocs(),
+            compact_unwind,
         )
     }
 
@@ -334,45 +493,338 @@ impl Module for ObjectModule {
         alignment: u64,
         bytes: &[u8],
         relocs: &[MachReloc],
-    ) -> ModuleResult<()> {
+    ) -> ModuleResult<ModuleCompiledFunction> {
         info!("defining function {} with bytes", func_id);
+        self.define_function_inner(func_id, func, alignment, bytes.to_vec(), relocs, None)
+    }
+
+    fn define_data(&mut self, data_id: DataId, data: &DataDescription) -> ModuleResult<()> {
+        let decl = self.declarations.get_data_decl(data_id);
+        if !decl.linkage.is_definable() {
+            return Err(ModuleError::InvalidImportDefinition(
+                decl.linkage_name(data_id).into_owned(),
+            ));
+        }
+        let pointer_reloc = match self.isa.triple().pointer_width().unwrap() {
+            PointerWidth::U16 => unimplemented!("16bit pointers"),
+            PointerWidth::U32 => Reloc::Abs4,
+            PointerWidth::U64 => Reloc::Abs8,
+        };
+        let relocs = data
+            .all_relocs(pointer_reloc)
+            .map(|record| self.process_reloc(&record))
+            .collect::<Vec<_>>();
+        self.check_references(
+            &format!("data object {}", decl.linkage_name(data_id)),
+            &relocs,
+        )?;
+        if !self.defined.insert(FuncOrDataId::Data(data_id)) {
+            return Err(ModuleError::DuplicateDefinition(
+                decl.linkage_name(data_id).into_owned(),
+            ));
+        }
+        if decl.tls && data.custom_segment_section.is_some() {
+            return Err(cranelift_module::ModuleError::Backend(anyhow::anyhow!(
+                "Custom section not supported for TLS"
+            )));
+        }
+        if let Init::Uninitialized = data.init {
+            panic!("data is not initialized yet");
+        }
+
+        self.definitions.push(Definition::Data {
+            id: data_id,
+            data: data.clone(),
+            relocs,
+        });
+        Ok(())
+    }
+
+    /// Defines every variant as a local function and `func` as an ELF indirect function, whose
+    /// resolver checks the CPU features of the variants with `cpuid` when the dynamic loader
+    /// binds `func`. This is only supported for x86-64 ELF objects.
+    ///
+    /// The ISA flags enabled for a variant are the features its resolver checks, so the variants
+    /// can't enable flags which aren't CPU features.
+    fn define_function_multiversion(
+        &mut self,
+        func: FuncId,
+        ctx: &mut cranelift_codegen::Context,
+        variants: &[TargetVariant],
+    ) -> ModuleResult<Vec<FuncId>> {
+        let decl = self.declarations.get_function_decl(func);
+        if !decl.linkage.is_definable() {
+            return Err(ModuleError::InvalidImportDefinition(
+                decl.linkage_name(func).into_owned(),
+            ));
+        }
+        if self.defined.contains(&FuncOrDataId::Func(func)) {
+            return Err(ModuleError::DuplicateDefinition(
+                decl.linkage_name(func).into_owned(),
+            ));
+        }
+        if self.object.format() != object::BinaryFormat::Elf
+            || self.object.architecture() != object::Architecture::X86_64
+        {
+            return Err(ModuleError::Backend(anyhow!(
+                "multi-versioned function {} needs an x86-64 ELF object",
+                decl.linkage_name(func)
+            )));
+        }
+
+        let ids = cranelift_module::define_function_variants(self, func, ctx, variants)?;
+        let checks: Vec<_> = ids
+            .iter()
+            .zip(variants)
+            .map(|(&id, variant)| (id, variant.enabled_features()))
+            .collect();
+        let resolver = ifunc::x86_64_resolver(&checks)?;
+        let relocs = resolver
+            .relocs
+            .iter()
+            .map(|&(offset, variant)| {
+                self.process_reloc(&ModuleReloc {
+                    offset,
+                    kind: Reloc::X86PCRel4,
+                    name: variant.into(),
+                    addend: -4,
+                })
+            })
+            .collect();
+
+        self.defined.insert(FuncOrDataId::Func(func));
+        self.ifuncs.insert(func);
+        self.definitions.push(Definition::Function {
+            id: func,
+            alignment: 1,
+            bytes: resolver.code,
+            relocs,
+            compact_unwind: None,
+        });
+        Ok(ids)
+    }
+
+    fn strip_unreachable(&mut self, roots: &[FuncOrDataId]) -> ModuleResult<Vec<FuncOrDataId>> {
+        let definitions: HashMap<FuncOrDataId, &Definition> = self
+            .definitions
+            .iter()
+            .map(|definition| (definition.id(), definition))
+            .collect();
+        let mut reachability = Reachability::new(&self.declarations, roots);
+        while let Some(id) = reachability.pop() {
+            if let Some(definition) = definitions.get(&id) {
+                for reloc in definition.relocs() {
+                    reachability.mark(&reloc.name);
+                }
+                if let Definition::Alias { target, .. } = **definition {
+                    reachability.mark(&ModuleExtName::from(target));
+                }
+            }
+        }
+
+        let mut dropped = Vec::new();
+        self.definitions.retain(|definition| {
+            let reachable = reachability.is_reachable(definition.id());
+            if !reachable {
+                dropped.push(definition.id());
+            }
+            reachable
+        });
+        for &id in &dropped {
+            info!(
+                "dropping unreachable definition {}",
+                ModuleExtName::from(id)
+            );
+        }
+        self.dropped.extend(dropped.iter().copied());
+        Ok(dropped)
+    }
+}
+
+impl ObjectModule {
+    fn define_function_inner(
+        &mut self,
+        func_id: FuncId,
+        func: &ir::Function,
+        alignment: u64,
+        bytes: Vec<u8>,
+        relocs: &[MachReloc],
+        compact_unwind: Option<u32>,
+    ) -> ModuleResult<ModuleCompiledFunction> {
         let decl = self.declarations.get_function_decl(func_id);
         if !decl.linkage.is_definable() {
             return Err(ModuleError::InvalidImportDefinition(
                 decl.linkage_name(func_id).into_owned(),
             ));
         }
-
-        let &mut (symbol, ref mut defined) = self.functions[func_id].as_mut().unwrap();
-        if *defined {
+        let own_section =
+            self.per_function_section && self.object.format() == object::BinaryFormat::MachO;
+        if own_section && self.function_sections == MACHO_MAX_FUNCTION_SECTIONS {
+            return Err(ModuleError::Backend(anyhow!(
+                "function {} can't get a section of its own, as a Mach-O object has room for at \
+                 most {} function sections",
+                decl.linkage_name(func_id),
+                MACHO_MAX_FUNCTION_SECTIONS
+            )));
+        }
+        let relocs: Vec<_> = relocs
+            .iter()
+            .map(|record| self.process_reloc(&ModuleReloc::from_mach_reloc(record, func)))
+            .collect();
+        self.check_references(&format!("function {}", decl.linkage_name(func_id)), &relocs)?;
+        if !self.defined.insert(FuncOrDataId::Func(func_id)) {
             return Err(ModuleError::DuplicateDefinition(
                 decl.linkage_name(func_id).into_owned(),
             ));
         }
+        if own_section {
+            self.function_sections += 1;
+        }
+
+        let size = bytes.len() as CodeOffset;
+        self.definitions.push(Definition::Function {
+            id: func_id,
+            alignment,
+            bytes,
+            relocs,
+            compact_unwind,
+        });
+        Ok(ModuleCompiledFunction { size })
+    }
+
+    /// Check that the relocations of `definition`, which is being defined, don't refer to a
+    /// definition dropped by `strip_unreachable`, which gets no symbol.
+    fn check_references(&self, definition: &str, relocs: &[ObjectRelocRecord]) -> ModuleResult<()> {
+        for reloc in relocs {
+            let name = match reloc.name {
+                ModuleExtName::User { .. } if ModuleDeclarations::is_function(&reloc.name) => {
+                    let id = FuncId::from_name(&reloc.name);
+                    if !self.dropped.contains(&FuncOrDataId::Func(id)) {
+                        continue;
+                    }
+                    self.declarations.get_function_decl(id).linkage_name(id)
+                }
+                ModuleExtName::User { .. } => {
+                    let id = DataId::from_name(&reloc.name);
+                    if !self.dropped.contains(&FuncOrDataId::Data(id)) {
+                        continue;
+                    }
+                    self.declarations.get_data_decl(id).linkage_name(id)
+                }
+                ModuleExtName::LibCall(_) | ModuleExtName::KnownSymbol(_) => continue,
+            };
+            return Err(ModuleError::Backend(anyhow!(
+                "{} refers to {}, which was dropped by `strip_unreachable`",
+                definition,
+                name
+            )));
+        }
+        Ok(())
+    }
+
+    /// Create the symbols of all declarations, except for the dropped definitions.
+    fn declare_symbols(&mut self) {
+        for (id, decl) in self.declarations.get_functions() {
+            if self.dropped.contains(&FuncOrDataId::Func(id)) {
+                continue;
+            }
+            let (scope, weak) = translate_linkage(decl.linkage);
+            let symbol = self.object.add_symbol(Symbol {
+                name: decl.linkage_name(id).into_owned().into_bytes(),
+                value: 0,
+                size: 0,
+                kind: SymbolKind::Text,
+                scope,
+                weak,
+                section: SymbolSection::Undefined,
+                flags: SymbolFlags::None,
+            });
+            self.functions[id] = Some((symbol, false));
+        }
+        for (id, decl) in self.declarations.get_data_objects() {
+            if self.dropped.contains(&FuncOrDataId::Data(id)) {
+                continue;
+            }
+            let (scope, weak) = translate_linkage(decl.linkage);
+            let symbol = self.object.add_symbol(Symbol {
+                name: decl.linkage_name(id).into_owned().into_bytes(),
+                value: 0,
+                size: 0,
+                kind: if decl.tls {
+                    SymbolKind::Tls
+                } else {
+                    SymbolKind::Data
+                },
+                scope,
+                weak,
+                section: SymbolSection::Undefined,
+                flags: SymbolFlags::None,
+            });
+            self.data_objects[id] = Some((symbol, false));
+        }
+    }
+
+    /// Add the pending definitions to the object, in the order they were made.
+    fn emit_definitions(&mut self) {
+        for definition in mem::take(&mut self.definitions) {
+            match definition {
+                Definition::Function {
+                    id,
+                    alignment,
+                    bytes,
+                    relocs,
+                    compact_unwind,
+                } => self.emit_function(id, alignment, &bytes, relocs, compact_unwind),
+                Definition::Alias { id, target, offset } => self.emit_alias(id, target, offset),
+                Definition::Data { id, data, relocs } => self.emit_data(id, &data, relocs),
+            }
+        }
+    }
+
+    /// Add the function `func_id` to the object.
+    fn emit_function(
+        &mut self,
+        func_id: FuncId,
+        alignment: u64,
+        bytes: &[u8],
+        relocs: Vec<ObjectRelocRecord>,
+        compact_unwind: Option<u32>,
+    ) {
+        let &mut (symbol, ref mut defined) = self.functions[func_id].as_mut().unwrap();
         *defined = true;
+        if self.ifuncs.contains(&func_id) {
+            self.object.symbol_mut(symbol).flags = ifunc_symbol_flags(self.object.symbol(symbol));
+        }
 
         let align = alignment
             .max(self.isa.function_alignment().minimum.into())
             .max(self.isa.symbol_alignment());
-        let (section, offset) = if self.per_function_section {
-            let symbol_name = self.object.symbol(symbol).name.clone();
-            let (section, offset) =
-                self.object
-                    .add_subsection(StandardSection::Text, &symbol_name, bytes, align);
-            self.object.symbol_mut(symbol).section = SymbolSection::Section(section);
-            self.object.symbol_mut(symbol).value = offset;
-            (section, offset)
-        } else {
-            let section = self.object.section_id(StandardSection::Text);
-            let offset = self.object.add_symbol_data(symbol, section, bytes, align);
-            (section, offset)
-        };
+        let (section, offset) =
+            if self.per_function_section && self.object.format() == object::BinaryFormat::MachO {
+                // `add_subsection` would keep the function in `__text` and only mark the object as
+                // having subsections via symbols.
+                let section = self.object.add_section(
+                    b"__TEXT".to_vec(),
+                    format!("__text_{:x}", func_id.as_u32()).into_bytes(),
+                    SectionKind::Text,
+                );
+                let offset = self.object.add_symbol_data(symbol, section, bytes, align);
+                (section, offset)
+            } else if self.per_function_section {
+                let symbol_name = self.object.symbol(symbol).name.clone();
+                let (section, offset) =
+                    self.object
+                        .add_subsection(StandardSection::Text, &symbol_name, bytes, align);
+                self.object.symbol_mut(symbol).section = SymbolSection::Section(section);
+                self.object.symbol_mut(symbol).value = offset;
+                (section, offset)
+            } else {
+                let section = self.object.section_id(StandardSection::Text);
+                let offset = self.object.add_symbol_data(symbol, section, bytes, align);
+                (section, offset)
+            };
 
         if !relocs.is_empty() {
-            let relocs = relocs
-                .iter()
-                .map(|record| self.process_reloc(&ModuleReloc::from_mach_reloc(&record, func)))
-                .collect();
             self.relocs.push(SymbolRelocs {
                 section,
                 offset,
@@ -380,23 +832,49 @@ impl Module for ObjectModule {
             });
         }
 
-        Ok(())
+        if let (Some(entries), Some(encoding)) = (&mut self.compact_unwind, compact_unwind) {
+            entries.push(CompactUnwindEntry {
+                symbol,
+                length: bytes.len() as u32,
+                encoding,
+            });
+        }
     }
 
-    fn define_data(&mut self, data_id: DataId, data: &DataDescription) -> ModuleResult<()> {
-        let decl = self.declarations.get_data_decl(data_id);
-        if !decl.linkage.is_definable() {
-            return Err(ModuleError::InvalidImportDefinition(
-                decl.linkage_name(data_id).into_owned(),
-            ));
+    /// Add the alias `alias` of the function `target`, which has been added already.
+    fn emit_alias(&mut self, alias: FuncId, target: FuncId, offset: u64) {
+        let target = self.object.symbol(self.functions[target].unwrap().0);
+        let (section, value, size) = (target.section, target.value, target.size);
+        let &mut (symbol, ref mut defined) = self.functions[alias].as_mut().unwrap();
+        *defined = true;
+
+        let format = self.object.format();
+        let symbol = self.object.symbol_mut(symbol);
+        symbol.section = section;
+        symbol.value = value + offset;
+        symbol.size = size - offset;
+        if format == object::BinaryFormat::MachO {
+            // Setting the flags overrides the weak flag.
+            let weak = if symbol.weak {
+                object::macho::N_WEAK_DEF
+            } else {
+                0
+            };
+            symbol.flags = SymbolFlags::MachO {
+                n_desc: object::macho::N_ALT_ENTRY | weak,
+            };
         }
+    }
 
+    /// Add the data object `data_id` to the object.
+    fn emit_data(
+        &mut self,
+        data_id: DataId,
+        data: &DataDescription,
+        relocs: Vec<ObjectRelocRecord>,
+    ) {
+        let decl = self.declarations.get_data_decl(data_id);
         let &mut (symbol, ref mut defined) = self.data_objects[data_id].as_mut().unwrap();
-        if *defined {
-            return Err(ModuleError::DuplicateDefinition(
-                decl.linkage_name(data_id).into_owned(),
-            ));
-        }
         *defined = true;
 
         let &DataDescription {
@@ -409,18 +887,12 @@ impl Module for ObjectModule {
             align,
         } = data;
 
-        let pointer_reloc = match self.isa.triple().pointer_width().unwrap() {
-            PointerWidth::U16 => unimplemented!("16bit pointers"),
-            PointerWidth::U32 => Reloc::Abs4,
-            PointerWidth::U64 => Reloc::Abs8,
-        };
-        let relocs = data
-            .all_relocs(pointer_reloc)
-            .map(|record| self.process_reloc(&record))
-            .collect::<Vec<_>>();
+        // Relocations can't be applied to uninitialized sections, so zero-initialized data which
+        // contains addresses is emitted as explicit zero bytes instead.
+        let uninitialized = matches!(*init, Init::Zeros { .. }) && relocs.is_empty();
 
         let section = if custom_segment_section.is_none() {
-            let section_kind = if let Init::Zeros { .. } = *init {
+            let section_kind = if uninitialized {
                 if decl.tls {
                     StandardSection::UninitializedTls
                 } else {
@@ -437,11 +909,6 @@ impl Module for ObjectModule {
             };
             self.object.section_id(section_kind)
         } else {
-            if decl.tls {
-                return Err(cranelift_module::ModuleError::Backend(anyhow::anyhow!(
-                    "Custom section not supported for TLS"
-                )));
-            }
             let (seg, sec) = &custom_segment_section.as_ref().unwrap();
             self.object.add_section(
                 seg.clone().into_bytes(),
@@ -458,15 +925,18 @@ impl Module for ObjectModule {
 
         let align = std::cmp::max(align.unwrap_or(1), self.isa.symbol_alignment());
         let offset = match *init {
-            Init::Uninitialized => {
-                panic!("data is not initialized yet");
+            Init::Uninitialized => unreachable!("checked by `define_data`"),
+            Init::Zeros { size } if uninitialized => {
+                self.object
+                    .add_symbol_bss(symbol, section, size as u64, align)
+            }
+            Init::Zeros { size } => {
+                self.object
+                    .add_symbol_data(symbol, section, &vec![0; size], align)
             }
-            Init::Zeros { size } => self
-                .object
-                .add_symbol_bss(symbol, section, size as u64, align),
             Init::Bytes { ref contents } => self
                 .object
-                .add_symbol_data(symbol, section, &contents, align),
+                .add_symbol_data(symbol, section, contents, align),
         };
         if !relocs.is_empty() {
             self.relocs.push(SymbolRelocs {
@@ -475,13 +945,19 @@ impl Module for ObjectModule {
                 relocs,
             });
         }
-        Ok(())
     }
 }
 
 impl ObjectModule {
     /// Finalize all relocations and output an object.
     pub fn finish(mut self) -> ObjectProduct {
+        self.declare_symbols();
+        self.emit_definitions();
+        if let Some((id, data)) = self.constant_pool.as_mut().and_then(|pool| pool.take()) {
+            self.define_constant_pool(id, &data);
+            self.emit_definitions();
+        }
+
         let symbol_relocs = mem::take(&mut self.relocs);
         for symbol in symbol_relocs {
             for &ObjectRelocRecord {
@@ -510,6 +986,10 @@ impl ObjectModule {
             }
         }
 
+        if let Some(compact_unwind) = self.compact_unwind.take() {
+            self.emit_compact_unwind(compact_unwind);
+        }
+
         // Indicate that this object has a non-executable stack.
         if self.object.format() == object::BinaryFormat::Elf {
             self.object.add_section(
@@ -523,6 +1003,75 @@ impl ObjectModule {
             object: self.object,
             functions: self.functions,
             data_objects: self.data_objects,
+            macho_cpu_subtype: self.macho_cpu_subtype,
+        }
+    }
+
+    /// Define the data object of the constant pool.
+    ///
+    /// On ELF the pool is placed in its own mergeable section, and code refers to it through the
+    /// section symbol (see `get_symbol`), as linkers only merge entries of a section which are
+    /// referenced relative to the section.
+    fn define_constant_pool(&mut self, data_id: DataId, data: &DataDescription) {
+        if self.object.format() != object::BinaryFormat::Elf {
+            self.define_data(data_id, data).unwrap();
+            return;
+        }
+
+        let contents = match data.init {
+            Init::Bytes { ref contents } => contents,
+            _ => unreachable!("the constant pool is always initialized with bytes"),
+        };
+        let section =
+            self.object
+                .add_section(vec![], b".rodata.cst16".to_vec(), SectionKind::ReadOnlyData);
+        self.object.section_mut(section).flags = SectionFlags::Elf {
+            sh_flags: (object::elf::SHF_ALLOC | object::elf::SHF_MERGE).into(),
+        };
+        let &mut (symbol, ref mut defined) = self.data_objects[data_id].as_mut().unwrap();
+        *defined = true;
+        let align = CONSTANT_POOL_ENTRY_SIZE as u64;
+        let offset = self
+            .object
+            .add_symbol_data(symbol, section, contents, align);
+        debug_assert_eq!(offset, 0);
+        self.constant_pool_section = Some((data_id, section));
+    }
+
+    /// Emit the `__LD,__compact_unwind` section of a Mach-O object.
+    fn emit_compact_unwind(&mut self, entries: Vec<CompactUnwindEntry>) {
+        if entries.is_empty() {
+            return;
+        }
+        let section = self.object.add_section(
+            b"__LD".to_vec(),
+            b"__compact_unwind".to_vec(),
+            SectionKind::Debug,
+        );
+        let mut data = Vec::with_capacity(entries.len() * compact_unwind::ENTRY_SIZE);
+        for entry in &entries {
+            // The address of the function is filled in by the relocation below, and there is no
+            // personality function or LSDA.
+            data.extend_from_slice(&[0; 8]);
+            data.extend_from_slice(&entry.length.to_le_bytes());
+            data.extend_from_slice(&entry.encoding.to_le_bytes());
+            data.extend_from_slice(&[0; 16]);
+        }
+        self.object.append_section_data(section, &data, 8);
+        for (i, entry) in entries.iter().enumerate() {
+            self.object
+                .add_relocation(
+                    section,
+                    Relocation {
+                        offset: (i * compact_unwind::ENTRY_SIZE) as u64,
+                        size: 64,
+                        kind: RelocationKind::Absolute,
+                        encoding: RelocationEncoding::Generic,
+                        symbol: entry.symbol,
+                        addend: 0,
+                    },
+                )
+                .unwrap();
         }
     }
 
@@ -533,10 +1082,19 @@ impl ObjectModule {
             ModuleExtName::User { .. } => {
                 if ModuleDeclarations::is_function(name) {
                     let id = FuncId::from_name(name);
-                    self.functions[id].unwrap().0
+                    self.functions[id]
+                        .expect("reference to a function dropped by `strip_unreachable`")
+                        .0
                 } else {
                     let id = DataId::from_name(name);
-                    self.data_objects[id].unwrap().0
+                    match self.constant_pool_section {
+                        Some((pool, section)) if pool == id => self.object.section_symbol(section),
+                        _ => {
+                            self.data_objects[id]
+                                .expect("reference to a data object dropped by `strip_unreachable`")
+                                .0
+                        }
+                    }
                 }
             }
             ModuleExtName::LibCall(ref libcall) => {
@@ -814,6 +1372,8 @@ pub struct ObjectProduct {
     pub functions: SecondaryMap<FuncId, Option<(SymbolId, bool)>>,
     /// Symbol IDs for data objects (both declared and defined).
     pub data_objects: SecondaryMap<DataId, Option<(SymbolId, bool)>>,
+    /// The CPU subtype to write to the header of a Mach-O object, see [`ObjectBuilder::arm64e`].
+    macho_cpu_subtype: Option<u32>,
 }
 
 impl ObjectProduct {
@@ -830,10 +1390,64 @@ impl ObjectProduct {
     }
 
     /// Write the object bytes in memory.
-    #[inline]
+    ///
+    /// This also applies [`ObjectBuilder::arm64e`], which writing `object` directly doesn't.
     pub fn emit(self) -> Result<Vec<u8>, object::write::Error> {
-        self.object.write()
+        let mut bytes = self.object.write()?;
+        if let Some(cpusubtype) = self.macho_cpu_subtype {
+            // The `object` writer always uses the generic subtype of the architecture. The
+            // subtype follows the magic and the CPU type in the header of little-endian aarch64
+            // objects.
+            bytes[8..12].copy_from_slice(&cpusubtype.to_le_bytes());
+        }
+        Ok(bytes)
+    }
+}
+
+/// A definition which hasn't been added to the object yet.
+enum Definition {
+    Function {
+        id: FuncId,
+        alignment: u64,
+        bytes: Vec<u8>,
+        relocs: Vec<ObjectRelocRecord>,
+        /// The compact unwind encoding of the function, if it has one.
+        compact_unwind: Option<u32>,
+    },
+    /// An alias of the function `target`, `offset` bytes into its code.
+    Alias {
+        id: FuncId,
+        target: FuncId,
+        offset: u64,
+    },
+    Data {
+        id: DataId,
+        data: DataDescription,
+        relocs: Vec<ObjectRelocRecord>,
+    },
+}
+
+impl Definition {
+    fn id(&self) -> FuncOrDataId {
+        match *self {
+            Self::Function { id, .. } | Self::Alias { id, .. } => FuncOrDataId::Func(id),
+            Self::Data { id, .. } => FuncOrDataId::Data(id),
+        }
     }
+
+    fn relocs(&self) -> &[ObjectRelocRecord] {
+        match self {
+            Self::Function { relocs, .. } | Self::Data { relocs, .. } => relocs,
+            Self::Alias { .. } => &[],
+        }
+    }
+}
+
+/// The compact unwind info of a function of a Mach-O object.
+struct CompactUnwindEntry {
+    symbol: SymbolId,
+    length: u32,
+    encoding: u32,
 }
 
 #[derive(Clone)]
@@ -0,0 +1,149 @@
+//! Mach-O compact unwind info.
+//!
+//! Every entry of the `__LD,__compact_unwind` section of a Mach-O object describes how to unwind
+//! the frame of one function with a single 32-bit encoding. The linker turns these entries into
+//! the `__TEXT,__unwind_info` section of the linked image, which is what the system unwinder and
+//! sampling profilers read. The encodings are defined in `<mach-o/compact_unwind_encoding.h>`.
+
+use cranelift_codegen::binemit::CodeOffset;
+use cranelift_codegen::isa::unwind::UnwindInst;
+use cranelift_codegen::isa::TargetIsa;
+use target_lexicon::Architecture;
+
+/// The size of a `__compact_unwind` entry on 64-bit targets: the address and length of the
+/// function, its encoding, and the addresses of its personality function and LSDA.
+pub(crate) const ENTRY_SIZE: usize = 32;
+
+const UNWIND_X86_64_MODE_RBP_FRAME: u32 = 0x0100_0000;
+const UNWIND_X86_64_MODE_STACK_IMMD: u32 = 0x0200_0000;
+
+const UNWIND_ARM64_MODE_FRAMELESS: u32 = 0x0200_0000;
+const UNWIND_ARM64_MODE_FRAME: u32 = 0x0400_0000;
+const UNWIND_ARM64_FRAME_X19_X20_PAIR: u32 = 0x0000_0001;
+const UNWIND_ARM64_FRAME_D8_D9_PAIR: u32 = 0x0000_0100;
+
+/// Compute the compact unwind encoding of a function from the unwind instructions of its
+/// prologue, or `None` if its frame can't be described by one.
+///
+/// This is only the case for frames which save the callee-saved registers exactly where the
+/// system unwinder expects them, so functions without an encoding should be left without an
+/// entry rather than be given a wrong one.
+pub(crate) fn encoding(
+    isa: &dyn TargetIsa,
+    unwind_info: &[(CodeOffset, UnwindInst)],
+) -> Option<u32> {
+    // The distance from the frame pointer down to the clobber save area, when there is a
+    // frame.
+    let mut frame = None;
+    // The DWARF numbers of the saved registers and their offsets from the frame pointer.
+    let mut saves = vec![];
+    for (_, inst) in unwind_info {
+        match *inst {
+            // The return address and the frame pointer of the caller, pushed just below the
+            // stack pointer of the caller.
+            UnwindInst::PushFrameRegs {
+                offset_upward_to_caller_sp: 16,
+            } => {}
+            UnwindInst::DefineNewFrame {
+                offset_upward_to_caller_sp: 16,
+                offset_downward_to_clobbers,
+            } => frame = Some(offset_downward_to_clobbers),
+            UnwindInst::SaveReg {
+                clobber_offset,
+                reg,
+            } => {
+                let frame = frame?;
+                let reg = isa.map_regalloc_reg_to_dwarf(reg.into()).ok()?;
+                saves.push((i64::from(clobber_offset) - i64::from(frame), reg));
+            }
+            // Only frames without signed return addresses can be described.
+            UnwindInst::Aarch64SetPointerAuth {
+                return_addresses: false,
+            } => {}
+            _ => return None,
+        }
+    }
+
+    match isa.triple().architecture {
+        Architecture::X86_64 => x86_64_encoding(frame, &saves),
+        Architecture::Aarch64(_) => aarch64_encoding(frame, &saves),
+        _ => None,
+    }
+}
+
+fn x86_64_encoding(frame: Option<u32>, saves: &[(i64, u16)]) -> Option<u32> {
+    let frame = match frame {
+        Some(frame) => frame,
+        // Without a frame nothing but the return address is on the stack.
+        None if saves.is_empty() => return Some(UNWIND_X86_64_MODE_STACK_IMMD | (1 << 16)),
+        None => return None,
+    };
+
+    // The saved registers are stored in up to five consecutive slots starting `8 * offset`
+    // bytes below the frame pointer, and each slot takes three bits of the encoding.
+    if frame % 8 != 0 || frame / 8 > 0xff {
+        return None;
+    }
+    let offset = frame / 8;
+    let mut registers = 0;
+    for &(frame_offset, reg) in saves {
+        let slot = frame_offset + i64::from(frame);
+        if slot % 8 != 0 || !(0..5 * 8).contains(&slot) {
+            return None;
+        }
+        let shift = 3 * (slot / 8) as u32;
+        if registers & (0b111 << shift) != 0 {
+            return None;
+        }
+        let number = match reg {
+            3 => 1,  // rbx
+            12 => 2, // r12
+            13 => 3, // r13
+            14 => 4, // r14
+            15 => 5, // r15
+            _ => return None,
+        };
+        registers |= number << shift;
+    }
+    Some(UNWIND_X86_64_MODE_RBP_FRAME | (offset << 16) | registers)
+}
+
+fn aarch64_encoding(frame: Option<u32>, saves: &[(i64, u16)]) -> Option<u32> {
+    if frame.is_none() {
+        // Without a frame nothing is on the stack and the return address is in the link
+        // register.
+        return if saves.is_empty() {
+            Some(UNWIND_ARM64_MODE_FRAMELESS)
+        } else {
+            None
+        };
+    }
+
+    // Registers are saved in pairs. Each saved pair sets a bit of the encoding, and the saved
+    // pairs are stored just below the frame pointer in the order of these bits, with the first
+    // register of a pair at the higher address.
+    // The DWARF numbers of x19 to x28 are 19 to 28, and those of d8 to d15 are 72 to 79.
+    let pair_bit = |reg: u16| match reg {
+        19..=28 => Some(UNWIND_ARM64_FRAME_X19_X20_PAIR << ((reg - 19) / 2)),
+        72..=79 => Some(UNWIND_ARM64_FRAME_D8_D9_PAIR << ((reg - 72) / 2)),
+        _ => None,
+    };
+    let mut pairs: u32 = 0;
+    for &(_, reg) in saves {
+        pairs |= pair_bit(reg)?;
+    }
+    if saves.len() != 2 * pairs.count_ones() as usize {
+        return None;
+    }
+    for &(frame_offset, reg) in saves {
+        let pair = pair_bit(reg).unwrap();
+        let pairs_above = (pairs & (pair - 1)).count_ones() as i64;
+        // x20 and d9 are the second registers of their pairs.
+        let second = reg % 2 == if reg < 72 { 0 } else { 1 };
+        let expected = -16 * pairs_above - if second { 16 } else { 8 };
+        if frame_offset != expected {
+            return None;
+        }
+    }
+    Some(UNWIND_ARM64_MODE_FRAME | pairs)
+}
@@ -0,0 +1,174 @@
+//! Resolvers of ELF indirect functions.
+//!
+//! A symbol of type `STT_GNU_IFUNC` doesn't point at the code of a function but at a resolver,
+//! which the dynamic loader calls once when binding the symbol and whose return value is the
+//! address of the code to use. Multi-versioned functions get a resolver which checks the CPU
+//! features needed by each variant with `cpuid` and returns the first variant that can run.
+
+use anyhow::anyhow;
+use cranelift_codegen::binemit::CodeOffset;
+use cranelift_module::{FuncId, ModuleError, ModuleResult};
+
+/// A CPU feature as reported by `cpuid`: the leaf to query, the register the feature shows up in
+/// (numbered as in ModR/M bytes), and its bit in that register.
+#[derive(Clone, Copy)]
+struct CpuidBit {
+    leaf: u32,
+    reg: u8,
+    bit: u8,
+}
+
+const ECX: u8 = 1;
+const EBX: u8 = 3;
+
+/// The extended state components which must be enabled by the OS, in `XCR0`, before the
+/// registers of a feature can be used.
+const XCR0_AVX: u32 = 0x06;
+const XCR0_AVX512: u32 = 0xe6;
+
+/// The `cpuid` bit of the x86-64 ISA flag `flag`, and the `XCR0` components it needs.
+fn x86_64_feature(flag: &str) -> Option<(CpuidBit, u32)> {
+    let (leaf, reg, bit) = match flag {
+        "has_sse3" => (1, ECX, 0),
+        "has_ssse3" => (1, ECX, 9),
+        "has_fma" => (1, ECX, 12),
+        "has_sse41" => (1, ECX, 19),
+        "has_sse42" => (1, ECX, 20),
+        "has_popcnt" => (1, ECX, 23),
+        "has_avx" => (1, ECX, 28),
+        "has_bmi1" => (7, EBX, 3),
+        "has_avx2" => (7, EBX, 5),
+        "has_bmi2" => (7, EBX, 8),
+        "has_avx512f" => (7, EBX, 16),
+        "has_avx512dq" => (7, EBX, 17),
+        "has_avx512vl" => (7, EBX, 31),
+        "has_avx512vbmi" => (7, ECX, 1),
+        "has_avx512bitalg" => (7, ECX, 12),
+        "has_lzcnt" => (0x8000_0001, ECX, 5),
+        _ => return None,
+    };
+    let xcr0 = match flag {
+        "has_fma" | "has_avx" | "has_avx2" => XCR0_AVX,
+        _ if flag.starts_with("has_avx512") => XCR0_AVX512,
+        _ => 0,
+    };
+    Some((CpuidBit { leaf, reg, bit }, xcr0))
+}
+
+/// The code of a resolver, along with the offsets of the 32-bit PC-relative fields which must be
+/// relocated to the address of a variant, with an addend of -4.
+pub(crate) struct Resolver {
+    pub code: Vec<u8>,
+    pub relocs: Vec<(CodeOffset, FuncId)>,
+}
+
+/// Assemble an x86-64 resolver returning the first of `variants` whose ISA flags are all
+/// supported by the CPU. `variants` pairs each variant with its enabled ISA flags; the last
+/// variant is returned without checking its flags.
+pub(crate) fn x86_64_resolver(variants: &[(FuncId, Vec<&str>)]) -> ModuleResult<Resolver> {
+    let mut asm = Assembler::default();
+    // `cpuid` clobbers `ebx`, which is callee-saved.
+    asm.bytes(&[0x53]); // push rbx
+    let (last, checked) = variants.split_last().expect("a resolver needs a variant");
+    for (func, flags) in checked {
+        let mut fail_jumps = Vec::new();
+        let mut xcr0 = 0;
+        for flag in flags {
+            let (feature, needs_xcr0) = x86_64_feature(flag).ok_or_else(|| {
+                ModuleError::Backend(anyhow!(
+                    "the resolver can't check for the ISA flag {}",
+                    flag
+                ))
+            })?;
+            xcr0 |= needs_xcr0;
+            asm.check_cpuid_bit(feature, &mut fail_jumps);
+        }
+        if xcr0 != 0 {
+            // The OS must support saving the extended state, as shown by OSXSAVE, for `xgetbv`
+            // to be available.
+            let osxsave = CpuidBit {
+                leaf: 1,
+                reg: ECX,
+                bit: 27,
+            };
+            asm.check_cpuid_bit(osxsave, &mut fail_jumps);
+            asm.bytes(&[0x31, 0xc9]); // xor ecx, ecx
+            asm.bytes(&[0x0f, 0x01, 0xd0]); // xgetbv
+            asm.bytes(&[0x25]); // and eax, imm32
+            asm.imm32(xcr0);
+            asm.bytes(&[0x3d]); // cmp eax, imm32
+            asm.imm32(xcr0);
+            fail_jumps.push(asm.jcc(0x85)); // jne
+        }
+        asm.return_variant(*func);
+        for jump in fail_jumps {
+            asm.patch_jump(jump);
+        }
+    }
+    asm.return_variant(last.0);
+    Ok(Resolver {
+        code: asm.code,
+        relocs: asm.relocs,
+    })
+}
+
+#[derive(Default)]
+struct Assembler {
+    code: Vec<u8>,
+    relocs: Vec<(CodeOffset, FuncId)>,
+}
+
+impl Assembler {
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    fn imm32(&mut self, imm: u32) {
+        self.bytes(&imm.to_le_bytes());
+    }
+
+    /// Emit `cpuid` for `leaf` and jump to the failure path, adding the jumps to `fail_jumps`,
+    /// unless the bit of `feature` is set. This first checks that the leaf exists.
+    fn check_cpuid_bit(&mut self, feature: CpuidBit, fail_jumps: &mut Vec<usize>) {
+        if feature.leaf != 1 {
+            let max_leaf_query = feature.leaf & 0x8000_0000;
+            self.bytes(&[0xb8]); // mov eax, imm32
+            self.imm32(max_leaf_query);
+            self.bytes(&[0x31, 0xc9]); // xor ecx, ecx
+            self.bytes(&[0x0f, 0xa2]); // cpuid
+            self.bytes(&[0x3d]); // cmp eax, imm32
+            self.imm32(feature.leaf);
+            fail_jumps.push(self.jcc(0x82)); // jb
+        }
+        self.bytes(&[0xb8]); // mov eax, imm32
+        self.imm32(feature.leaf);
+        self.bytes(&[0x31, 0xc9]); // xor ecx, ecx
+        self.bytes(&[0x0f, 0xa2]); // cpuid
+        self.bytes(&[0x0f, 0xba, 0xe0 | feature.reg, feature.bit]); // bt reg, imm8
+        fail_jumps.push(self.jcc(0x83)); // jae, i.e. the bit is clear
+    }
+
+    /// Emit a conditional jump with a 32-bit displacement to be patched by `patch_jump`, and
+    /// return the offset of the displacement.
+    fn jcc(&mut self, opcode: u8) -> usize {
+        self.bytes(&[0x0f, opcode]);
+        let offset = self.code.len();
+        self.imm32(0);
+        offset
+    }
+
+    /// Make the jump whose displacement is at `offset` target the current end of the code.
+    fn patch_jump(&mut self, offset: usize) {
+        let displacement = (self.code.len() - (offset + 4)) as u32;
+        self.code[offset..offset + 4].copy_from_slice(&displacement.to_le_bytes());
+    }
+
+    /// Emit `lea rax, [rip + func]`, then restore `rbx` and return.
+    fn return_variant(&mut self, func: FuncId) {
+        self.bytes(&[0x48, 0x8d, 0x05]);
+        self.relocs.push((self.code.len() as CodeOffset, func));
+        self.imm32(0);
+        self.bytes(&[0x5b]); // pop rbx
+        self.bytes(&[0xc3]); // ret
+    }
+}
@@ -25,8 +25,10 @@
 )]
 
 mod backend;
+mod compact_unwind;
+mod ifunc;
 
-pub use crate::backend::{ObjectBuilder, ObjectModule, ObjectProduct};
+pub use crate::backend::{ObjectBuilder, ObjectModule, ObjectProduct, MACHO_MAX_FUNCTION_SECTIONS};
 
 /// Version number of this crate.
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
@@ -239,3 +239,336 @@ fn reject_nul_byte_symbol_for_data() {
         )
         .unwrap();
 }
+
+#[test]
+fn appended_data_layout() {
+    use object::{Object, ObjectSection, ObjectSymbol, RelocationTarget};
+
+    let isa = cranelift_codegen::isa::lookup_by_name("x86_64-unknown-linux-gnu")
+        .unwrap()
+        .finish(settings::Flags::new(settings::builder()))
+        .unwrap();
+    let mut module =
+        ObjectModule::new(ObjectBuilder::new(isa, "foo", default_libcall_names()).unwrap());
+    let pointer_bytes = module.isa().pointer_bytes();
+    let sig = module.make_signature();
+    let funcs = [
+        module
+            .declare_function("first", Linkage::Import, &sig)
+            .unwrap(),
+        module
+            .declare_function("second", Linkage::Import, &sig)
+            .unwrap(),
+    ];
+
+    // A table of `(u32 tag, padding, fn pointer)` records, followed by a pointer to the tag of
+    // the second record.
+    let table = module
+        .declare_data("table", Linkage::Export, false, false)
+        .unwrap();
+    let mut data = DataDescription::new();
+    for (tag, &func) in [0xaaaa_0001u32, 0xbbbb_0002].iter().zip(&funcs) {
+        data.align_to(8);
+        data.append_bytes(&tag.to_le_bytes());
+        data.align_to(pointer_bytes.into());
+        data.append_func_addr(func, pointer_bytes);
+    }
+    data.append_data_addr(table, 16, pointer_bytes);
+    module.define_data(table, &data).unwrap();
+
+    let bytes = module.finish().emit().unwrap();
+    let file = object::File::parse(&bytes[..]).unwrap();
+    let symbol = file.symbols().find(|s| s.name() == Ok("table")).unwrap();
+    assert_eq!(symbol.size(), 40);
+    let section = file
+        .section_by_index(symbol.section_index().unwrap())
+        .unwrap();
+    assert_eq!(section.align() % 8, 0);
+    let contents = section.data().unwrap();
+    let contents = &contents[symbol.address() as usize..][..40];
+    assert_eq!(&contents[0..8], &[0x01, 0x00, 0xaa, 0xaa, 0, 0, 0, 0]);
+    assert_eq!(&contents[16..24], &[0x02, 0x00, 0xbb, 0xbb, 0, 0, 0, 0]);
+
+    let mut relocs: Vec<_> = section
+        .relocations()
+        .map(|(offset, reloc)| {
+            let target = match reloc.target() {
+                RelocationTarget::Symbol(index) => file.symbol_by_index(index).unwrap(),
+                target => panic!("unexpected relocation target {:?}", target),
+            };
+            (
+                offset - symbol.address(),
+                target.name().unwrap().to_string(),
+                reloc.addend(),
+            )
+        })
+        .collect();
+    relocs.sort();
+    assert_eq!(
+        relocs,
+        [
+            (8, "first".to_string(), 0),
+            (24, "second".to_string(), 0),
+            (32, "table".to_string(), 16),
+        ]
+    );
+}
+
+/// Define `fn name() -> i64`, whose body is built by `body`.
+fn define_i64_function(
+    module: &mut ObjectModule,
+    name: &str,
+    linkage: Linkage,
+    body: impl FnOnce(&mut FunctionBuilder, &mut ObjectModule) -> Value,
+) -> FuncId {
+    let mut sig = module.make_signature();
+    sig.returns.push(AbiParam::new(types::I64));
+    let func_id = module.declare_function(name, linkage, &sig).unwrap();
+    let mut ctx = i64_function(module, func_id, body);
+    module.define_function(func_id, &mut ctx).unwrap();
+    func_id
+}
+
+/// Build the function `func_id`, declared as `fn() -> i64`, whose body is built by `body`.
+fn i64_function(
+    module: &mut ObjectModule,
+    func_id: FuncId,
+    body: impl FnOnce(&mut FunctionBuilder, &mut ObjectModule) -> Value,
+) -> Context {
+    let sig = module
+        .declarations()
+        .get_function_decl(func_id)
+        .signature
+        .clone();
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, func_id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.switch_to_block(block);
+        let result = body(&mut bcx, module);
+        bcx.ins().return_(&[result]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    ctx
+}
+
+/// Build a module with an exported `entry` calling `a` and loading from `table`, which holds the
+/// address of `b`, along with an unreferenced function `c` and data object `unused`.
+fn define_callgraph(module: &mut ObjectModule) -> [FuncOrDataId; 6] {
+    let a = define_i64_function(module, "a", Linkage::Local, |bcx, _| {
+        bcx.ins().iconst(types::I64, 1)
+    });
+    let b = define_i64_function(module, "b", Linkage::Local, |bcx, _| {
+        bcx.ins().iconst(types::I64, 2)
+    });
+    let c = define_i64_function(module, "c", Linkage::Local, |bcx, _| {
+        bcx.ins().iconst(types::I64, 3)
+    });
+    let pointer_bytes = module.isa().pointer_bytes();
+    let table = module
+        .declare_data("table", Linkage::Local, false, false)
+        .unwrap();
+    let mut data = DataDescription::new();
+    data.append_func_addr(b, pointer_bytes);
+    module.define_data(table, &data).unwrap();
+    let unused = module
+        .declare_data("unused", Linkage::Local, false, false)
+        .unwrap();
+    let mut data = DataDescription::new();
+    data.append_func_addr(c, pointer_bytes);
+    module.define_data(unused, &data).unwrap();
+    let entry = define_i64_function(module, "entry", Linkage::Export, |bcx, module| {
+        let a = module.declare_func_in_func(a, bcx.func);
+        let call = bcx.ins().call(a, &[]);
+        let result = bcx.inst_results(call)[0];
+        let table = module.declare_data_in_func(table, bcx.func);
+        let table = bcx.ins().symbol_value(types::I64, table);
+        let b = bcx.ins().load(types::I64, MemFlags::trusted(), table, 0);
+        bcx.ins().iadd(result, b)
+    });
+    [
+        FuncOrDataId::Func(entry),
+        FuncOrDataId::Func(a),
+        FuncOrDataId::Func(b),
+        FuncOrDataId::Func(c),
+        FuncOrDataId::Data(table),
+        FuncOrDataId::Data(unused),
+    ]
+}
+
+/// The names of the symbols defined by `bytes`.
+fn defined_symbols(bytes: &[u8]) -> Vec<String> {
+    use object::{Object, ObjectSymbol};
+
+    let file = object::File::parse(bytes).unwrap();
+    let undefined: Vec<_> = file
+        .symbols()
+        .filter(|s| s.is_undefined() && !s.name().unwrap().is_empty())
+        .map(|s| s.name().unwrap().to_string())
+        .collect();
+    assert_eq!(undefined, Vec::<String>::new());
+    let mut names: Vec<_> = file
+        .symbols()
+        .filter(|s| s.is_definition())
+        .map(|s| s.name().unwrap().to_string())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn strip_unreachable() {
+    let isa = cranelift_codegen::isa::lookup_by_name("x86_64-unknown-linux-gnu")
+        .unwrap()
+        .finish(settings::Flags::new(settings::builder()))
+        .unwrap();
+    let mut module =
+        ObjectModule::new(ObjectBuilder::new(isa, "foo", default_libcall_names()).unwrap());
+    let [_entry, _a, _b, c, _table, unused] = define_callgraph(&mut module);
+
+    assert_eq!(module.strip_unreachable(&[]).unwrap(), [c, unused]);
+    assert_eq!(module.strip_unreachable(&[]).unwrap(), []);
+    let bytes = module.finish().emit().unwrap();
+    assert_eq!(defined_symbols(&bytes), ["a", "b", "entry", "table"]);
+}
+
+#[test]
+fn strip_unreachable_keeps_roots() {
+    let isa = cranelift_codegen::isa::lookup_by_name("x86_64-unknown-linux-gnu")
+        .unwrap()
+        .finish(settings::Flags::new(settings::builder()))
+        .unwrap();
+    let mut module =
+        ObjectModule::new(ObjectBuilder::new(isa, "foo", default_libcall_names()).unwrap());
+    let [_entry, _a, _b, c, _table, unused] = define_callgraph(&mut module);
+
+    // `c` is only reachable through `unused`.
+    assert_eq!(module.strip_unreachable(&[unused]).unwrap(), []);
+    assert_eq!(module.strip_unreachable(&[c]).unwrap(), [unused]);
+    let bytes = module.finish().emit().unwrap();
+    assert_eq!(defined_symbols(&bytes), ["a", "b", "c", "entry", "table"]);
+}
+
+#[test]
+fn define_reference_to_stripped() {
+    let isa = cranelift_codegen::isa::lookup_by_name("x86_64-unknown-linux-gnu")
+        .unwrap()
+        .finish(settings::Flags::new(settings::builder()))
+        .unwrap();
+    let mut module =
+        ObjectModule::new(ObjectBuilder::new(isa, "foo", default_libcall_names()).unwrap());
+    let [_entry, _a, _b, c, _table, unused] = define_callgraph(&mut module);
+    assert_eq!(module.strip_unreachable(&[]).unwrap(), [c, unused]);
+    let (c, unused) = match (c, unused) {
+        (FuncOrDataId::Func(c), FuncOrDataId::Data(unused)) => (c, unused),
+        _ => unreachable!(),
+    };
+
+    let mut sig = module.make_signature();
+    sig.returns.push(AbiParam::new(types::I64));
+    let late = module
+        .declare_function("late", Linkage::Export, &sig)
+        .unwrap();
+    let mut ctx = i64_function(&mut module, late, |bcx, module| {
+        let c = module.declare_func_in_func(c, bcx.func);
+        let call = bcx.ins().call(c, &[]);
+        bcx.inst_results(call)[0]
+    });
+    assert_eq!(
+        module
+            .define_function(late, &mut ctx)
+            .unwrap_err()
+            .to_string(),
+        "Backend error: function late refers to c, which was dropped by `strip_unreachable`"
+    );
+
+    let pointer_bytes = module.isa().pointer_bytes();
+    let late_table = module
+        .declare_data("late_table", Linkage::Export, false, false)
+        .unwrap();
+    let mut data = DataDescription::new();
+    data.append_data_addr(unused, 0, pointer_bytes);
+    assert_eq!(
+        module
+            .define_data(late_table, &data)
+            .unwrap_err()
+            .to_string(),
+        "Backend error: data object late_table refers to unused, which was dropped by \
+         `strip_unreachable`"
+    );
+
+    // The rejected definitions can be made again without the references.
+    let mut ctx = i64_function(&mut module, late, |bcx, _| bcx.ins().iconst(types::I64, 4));
+    module.define_function(late, &mut ctx).unwrap();
+    let mut data = DataDescription::new();
+    data.define_zeroinit(8);
+    module.define_data(late_table, &data).unwrap();
+    let bytes = module.finish().emit().unwrap();
+    assert_eq!(
+        defined_symbols(&bytes),
+        ["a", "b", "entry", "late", "late_table", "table"]
+    );
+}
+
+#[test]
+fn multiversion_function_is_ifunc() {
+    use object::{Object, ObjectSymbol};
+
+    let flags = || settings::Flags::new(settings::builder());
+    let isa = |features: &[&str]| {
+        let mut isa_builder =
+            cranelift_codegen::isa::lookup_by_name("x86_64-unknown-linux-gnu").unwrap();
+        for feature in features {
+            settings::Configurable::enable(&mut isa_builder, feature).unwrap();
+        }
+        isa_builder.finish(flags()).unwrap()
+    };
+    let mut module =
+        ObjectModule::new(ObjectBuilder::new(isa(&[]), "foo", default_libcall_names()).unwrap());
+    let mut sig = module.make_signature();
+    sig.returns.push(AbiParam::new(types::I64));
+    let func_id = module
+        .declare_function("value", Linkage::Export, &sig)
+        .unwrap();
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, func_id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.switch_to_block(block);
+        let value = bcx.ins().iconst(types::I64, 42);
+        bcx.ins().return_(&[value]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    let variants = [
+        TargetVariant::new("avx2", isa(&["has_avx", "has_avx2"])),
+        TargetVariant::new("baseline", isa(&[])),
+    ];
+    module
+        .define_function_multiversion(func_id, &mut ctx, &variants)
+        .unwrap();
+    assert!(matches!(
+        module.define_function_multiversion(func_id, &mut ctx, &variants),
+        Err(ModuleError::DuplicateDefinition(_))
+    ));
+    let bytes = module.finish().emit().unwrap();
+
+    // The symbol of the resolver is an indirect function, which `object` doesn't count as a
+    // definition.
+    assert_eq!(defined_symbols(&bytes), ["value.avx2", "value.baseline"]);
+    let file = object::File::parse(&*bytes).unwrap();
+    let symbol = file.symbols().find(|s| s.name() == Ok("value")).unwrap();
+    assert!(symbol.section_index().is_some());
+    assert!(symbol.is_global());
+    match symbol.flags() {
+        object::SymbolFlags::Elf { st_info, .. } => {
+            assert_eq!(st_info & 0xf, object::elf::STT_GNU_IFUNC)
+        }
+        _ => unreachable!(),
+    }
+}
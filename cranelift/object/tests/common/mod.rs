@@ -0,0 +1,16 @@
+//! Helpers shared by the cranelift-object integration tests.
+
+use std::path::Path;
+use std::process::Command;
+
+/// Whether `cc` can build executables on this host.
+pub fn have_c_compiler(dir: &Path) -> bool {
+    let source = dir.join("probe.c");
+    std::fs::write(&source, "int main(void) { return 0; }\n").unwrap();
+    Command::new("cc")
+        .arg(&source)
+        .arg("-o")
+        .arg(dir.join("probe"))
+        .output()
+        .map_or(false, |output| output.status.success())
+}
@@ -0,0 +1,183 @@
+//! Check that `ObjectModule` pools the vector constants of all functions into a single mergeable
+//! section, and that linked code still finds its constants.
+
+use cranelift_codegen::ir::*;
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_codegen::Context;
+use cranelift_frontend::*;
+use cranelift_module::*;
+use cranelift_object::*;
+use object::{Object, ObjectSection, SectionFlags, SectionKind};
+use std::path::Path;
+use std::process::Command;
+
+mod common;
+
+const FUNCTIONS: usize = 100;
+
+/// The vector constants shared by the functions, whose first lane is returned.
+const CONSTANTS: [[i64; 2]; 4] = [[1, 2], [3, 4], [5, 6], [7, 8]];
+
+fn object_module(
+    isa_builder: cranelift_codegen::isa::Builder,
+    pool_constants: bool,
+    name: &str,
+) -> ObjectModule {
+    let mut flag_builder = settings::builder();
+    // The C compiler may produce position independent executables.
+    flag_builder.set("is_pic", "true").unwrap();
+    let isa = isa_builder
+        .finish(settings::Flags::new(flag_builder))
+        .unwrap();
+    let mut builder = ObjectBuilder::new(isa, name, default_libcall_names()).unwrap();
+    builder.pool_constants(pool_constants);
+    ObjectModule::new(builder)
+}
+
+/// Define `FUNCTIONS` exported functions `{prefix}{i}`, each returning the sum of its argument
+/// and the first lane of one of `CONSTANTS`.
+fn define_functions(module: &mut ObjectModule, prefix: &str) {
+    let mut sig = module.make_signature();
+    sig.params = vec![AbiParam::new(types::I64)];
+    sig.returns = vec![AbiParam::new(types::I64)];
+    for i in 0..FUNCTIONS {
+        let name = format!("{}{}", prefix, i);
+        let id = module
+            .declare_function(&name, Linkage::Export, &sig)
+            .unwrap();
+
+        let mut ctx = Context::new();
+        ctx.func = Function::with_name_signature(UserFuncName::user(0, id.as_u32()), sig.clone());
+        let mut func_ctx = FunctionBuilderContext::new();
+        {
+            let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+            let block = bcx.create_block();
+            bcx.append_block_params_for_function_params(block);
+            bcx.switch_to_block(block);
+            let x = bcx.block_params(block)[0];
+            let bytes = CONSTANTS[i % CONSTANTS.len()]
+                .iter()
+                .flat_map(|lane| lane.to_le_bytes())
+                .collect::<Vec<_>>();
+            let constant = bcx.func.dfg.constants.insert(bytes.into());
+            let v = bcx.ins().vconst(types::I64X2, constant);
+            let lane = bcx.ins().extractlane(v, 0);
+            let sum = bcx.ins().iadd(x, lane);
+            bcx.ins().return_(&[sum]);
+            bcx.seal_all_blocks();
+            bcx.finalize();
+        }
+        module.define_function(id, &mut ctx).unwrap();
+    }
+}
+
+fn section_size(file: &object::File, name: &str) -> Option<u64> {
+    file.section_by_name(name).map(|section| section.size())
+}
+
+#[test]
+fn pool_is_shared_by_all_functions() {
+    let isa_builder =
+        || cranelift_codegen::isa::lookup_by_name("x86_64-unknown-linux-gnu").unwrap();
+
+    let mut module = object_module(isa_builder(), true, "pooled");
+    define_functions(&mut module, "f");
+    // Every distinct constant is stored once, however many functions use it.
+    assert_eq!(
+        module.constant_pool().unwrap().size(),
+        CONSTANTS.len() * CONSTANT_POOL_ENTRY_SIZE
+    );
+    let pooled = module.finish().emit().unwrap();
+    let pooled = object::File::parse(&*pooled).unwrap();
+
+    let pool = pooled.section_by_name(".rodata.cst16").unwrap();
+    assert_eq!(pool.kind(), SectionKind::ReadOnlyData);
+    assert_eq!(
+        pool.size(),
+        (CONSTANTS.len() * CONSTANT_POOL_ENTRY_SIZE) as u64
+    );
+    assert_eq!(pool.align(), CONSTANT_POOL_ENTRY_SIZE as u64);
+    assert_eq!(
+        pool.flags(),
+        SectionFlags::Elf {
+            sh_flags: (object::elf::SHF_ALLOC | object::elf::SHF_MERGE).into()
+        }
+    );
+
+    // Without pooling every function carries its own copy of its constant.
+    let mut module = object_module(isa_builder(), false, "unpooled");
+    define_functions(&mut module, "f");
+    assert!(module.constant_pool().is_none());
+    let unpooled = module.finish().emit().unwrap();
+    let unpooled = object::File::parse(&*unpooled).unwrap();
+    assert!(unpooled.section_by_name(".rodata.cst16").is_none());
+
+    let pooled_size = section_size(&pooled, ".text").unwrap() + pool.size();
+    let unpooled_size = section_size(&unpooled, ".text").unwrap();
+    assert!(
+        pooled_size + (FUNCTIONS * CONSTANT_POOL_ENTRY_SIZE / 2) as u64 <= unpooled_size,
+        "pooled: {} bytes, unpooled: {} bytes",
+        pooled_size,
+        unpooled_size
+    );
+}
+
+const MAIN_C: &str = r#"
+#include <stdint.h>
+#include <stdio.h>
+
+extern int64_t a0(int64_t), a1(int64_t), a2(int64_t), a99(int64_t);
+extern int64_t b0(int64_t), b1(int64_t), b2(int64_t), b99(int64_t);
+
+int main(void) {
+    printf("%lld %lld %lld %lld\n", (long long)a0(10), (long long)a1(10), (long long)a2(10),
+           (long long)a99(10));
+    printf("%lld %lld %lld %lld\n", (long long)b0(20), (long long)b1(20), (long long)b2(20),
+           (long long)b99(20));
+    return 0;
+}
+"#;
+
+/// Link two objects with pools holding the same constants, which the linker may merge.
+#[test]
+#[cfg_attr(not(all(target_os = "linux", target_arch = "x86_64")), ignore)]
+fn link_pooled_objects() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("cranelift-object-constant-pool");
+    std::fs::create_dir_all(&dir).unwrap();
+    if !common::have_c_compiler(&dir) {
+        println!("skipping test: no working C compiler and linker found");
+        return;
+    }
+
+    let mut objects = vec![];
+    for prefix in ["a", "b"] {
+        let isa_builder = cranelift_native::builder().unwrap();
+        let mut module = object_module(isa_builder, true, prefix);
+        define_functions(&mut module, prefix);
+        let object = dir.join(format!("{}.o", prefix));
+        std::fs::write(&object, module.finish().emit().unwrap()).unwrap();
+        objects.push(object);
+    }
+    let main = dir.join("main.c");
+    std::fs::write(&main, MAIN_C).unwrap();
+    let executable = dir.join("constant_pool");
+    let output = Command::new("cc")
+        .arg(&main)
+        .args(&objects)
+        .arg("-o")
+        .arg(&executable)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "linking failed:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let output = Command::new(&executable).output().unwrap();
+    assert!(output.status.success(), "{:?}", output.status);
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "11 13 15 17\n21 23 25 27\n"
+    );
+}
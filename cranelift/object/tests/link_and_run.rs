@@ -0,0 +1,372 @@
+//! Compile an object file with `ObjectModule`, link it into an executable with the system C
+//! compiler and check what the executable prints.
+//!
+//! The object exercises the symbolic references an AOT compiler needs: calls to and data imported
+//! from the C side, exported functions and data used by the C side, and data objects containing
+//! the addresses of other data objects and functions, and aliases of functions. The tests also
+//! check that the linker can strip unused functions of the object.
+
+use cranelift_codegen::ir::*;
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_codegen::Context;
+use cranelift_frontend::*;
+use cranelift_module::*;
+use cranelift_object::*;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+mod common;
+
+const MESSAGE: &[u8] = b"hello from cranelift\0";
+
+const MAIN_C: &str = r#"
+#include <stdint.h>
+#include <stdio.h>
+
+int64_t host_counter = 10;
+
+int64_t host_add(int64_t a, int64_t b) {
+    return a + b;
+}
+
+extern const char cl_message[];
+extern const char *cl_message_ptr(void);
+extern int64_t cl_entry(int64_t x);
+extern int64_t cl_entry_alias(int64_t x);
+
+int main(void) {
+    printf("%s\n", cl_message_ptr());
+    printf("%d\n", cl_message_ptr() == cl_message);
+    printf("%lld\n", (long long)cl_entry(5));
+    printf("%lld\n", (long long)cl_entry_alias(1));
+    printf("%lld\n", (long long)host_counter);
+    return 0;
+}
+"#;
+
+/// What the program of `MAIN_C` prints.
+const MAIN_OUTPUT: &str = "hello from cranelift\n1\n30\n24\n12\n";
+
+fn define_function(
+    module: &mut ObjectModule,
+    id: FuncId,
+    body: impl FnOnce(&mut FunctionBuilder, &mut ObjectModule, &[Value]),
+) {
+    let sig = module
+        .declarations()
+        .get_function_decl(id)
+        .signature
+        .clone();
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.append_block_params_for_function_params(block);
+        bcx.switch_to_block(block);
+        let params = bcx.block_params(block).to_vec();
+        body(&mut bcx, module, &params);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module.define_function(id, &mut ctx).unwrap();
+}
+
+fn build_object(configure: impl FnOnce(&mut ObjectBuilder)) -> Vec<u8> {
+    let mut flag_builder = settings::builder();
+    // The C compiler may produce position independent executables.
+    flag_builder.set("is_pic", "true").unwrap();
+    let isa_builder = cranelift_native::builder().unwrap_or_else(|msg| {
+        panic!("host machine is not supported: {}", msg);
+    });
+    let isa = isa_builder
+        .finish(settings::Flags::new(flag_builder))
+        .unwrap();
+    let mut builder = ObjectBuilder::new(isa, "link_and_run", default_libcall_names()).unwrap();
+    // This only affects Mach-O objects, whose functions then get unwind info.
+    builder.compact_unwind(true);
+    configure(&mut builder);
+    let mut module = ObjectModule::new(builder);
+    let ptr = module.target_config().pointer_type();
+
+    // Symbols defined by the C side.
+    let mut add_sig = module.make_signature();
+    add_sig.params = vec![AbiParam::new(types::I64), AbiParam::new(types::I64)];
+    add_sig.returns = vec![AbiParam::new(types::I64)];
+    let host_add = module
+        .declare_function("host_add", Linkage::Import, &add_sig)
+        .unwrap();
+    let host_counter = module
+        .declare_data("host_counter", Linkage::Import, true, false)
+        .unwrap();
+
+    // const char cl_message[] = "hello from cranelift";
+    let message = module
+        .declare_data("cl_message", Linkage::Export, false, false)
+        .unwrap();
+    let mut data = DataDescription::new();
+    data.define(MESSAGE.into());
+    module.define_data(message, &data).unwrap();
+
+    // static int64_t cl_double(int64_t x) { return x * 2; }
+    let mut double_sig = module.make_signature();
+    double_sig.params = vec![AbiParam::new(types::I64)];
+    double_sig.returns = vec![AbiParam::new(types::I64)];
+    let double = module
+        .declare_function("cl_double", Linkage::Local, &double_sig)
+        .unwrap();
+    define_function(&mut module, double, |bcx, _, params| {
+        let doubled = bcx.ins().imul_imm(params[0], 2);
+        bcx.ins().return_(&[doubled]);
+    });
+
+    // static int64_t cl_unused(int64_t x) { return x + 1; }
+    let unused = module
+        .declare_function("cl_unused", Linkage::Local, &double_sig)
+        .unwrap();
+    define_function(&mut module, unused, |bcx, _, params| {
+        let incremented = bcx.ins().iadd_imm(params[0], 1);
+        bcx.ins().return_(&[incremented]);
+    });
+
+    // static const void *cl_table[] = { cl_message, cl_double };
+    let table = module
+        .declare_data("cl_table", Linkage::Local, false, false)
+        .unwrap();
+    let mut data = DataDescription::new();
+    data.define_zeroinit(2 * ptr.bytes() as usize);
+    let message_gv = module.declare_data_in_data(message, &mut data);
+    data.write_data_addr(0, message_gv, 0);
+    let double_ref = module.declare_func_in_data(double, &mut data);
+    data.write_function_addr(ptr.bytes(), double_ref);
+    module.define_data(table, &data).unwrap();
+
+    // const char *cl_message_ptr(void) { return cl_table[0]; }
+    let mut message_ptr_sig = module.make_signature();
+    message_ptr_sig.returns = vec![AbiParam::new(ptr)];
+    let message_ptr = module
+        .declare_function("cl_message_ptr", Linkage::Export, &message_ptr_sig)
+        .unwrap();
+    define_function(&mut module, message_ptr, |bcx, module, _| {
+        let table = module.declare_data_in_func(table, bcx.func);
+        let table = bcx.ins().symbol_value(ptr, table);
+        let message = bcx.ins().load(ptr, MemFlags::trusted(), table, 0);
+        bcx.ins().return_(&[message]);
+    });
+
+    // int64_t cl_entry(int64_t x) {
+    //     int64_t sum = host_add(x, host_counter++);
+    //     return ((int64_t (*)(int64_t))cl_table[1])(sum);
+    // }
+    let entry = module
+        .declare_function("cl_entry", Linkage::Export, &double_sig)
+        .unwrap();
+    define_function(&mut module, entry, |bcx, module, params| {
+        let counter = module.declare_data_in_func(host_counter, bcx.func);
+        let counter = bcx.ins().symbol_value(ptr, counter);
+        let count = bcx.ins().load(types::I64, MemFlags::trusted(), counter, 0);
+        let incremented = bcx.ins().iadd_imm(count, 1);
+        bcx.ins()
+            .store(MemFlags::trusted(), incremented, counter, 0);
+
+        let host_add = module.declare_func_in_func(host_add, bcx.func);
+        let call = bcx.ins().call(host_add, &[params[0], count]);
+        let sum = bcx.inst_results(call)[0];
+
+        let table = module.declare_data_in_func(table, bcx.func);
+        let table = bcx.ins().symbol_value(ptr, table);
+        let double = bcx
+            .ins()
+            .load(ptr, MemFlags::trusted(), table, ptr.bytes() as i32);
+        let double_sig = bcx.import_signature(double_sig.clone());
+        let call = bcx.ins().call_indirect(double_sig, double, &[sum]);
+        let result = bcx.inst_results(call)[0];
+        bcx.ins().return_(&[result]);
+    });
+
+    // extern int64_t cl_entry_alias(int64_t x) __attribute__((alias("cl_entry")));
+    let entry_alias = module
+        .declare_function("cl_entry_alias", Linkage::Export, &double_sig)
+        .unwrap();
+    module.define_function_alias(entry_alias, entry, 0).unwrap();
+
+    module.finish().emit().unwrap()
+}
+
+/// Link `object` and the C program `main` into the executable `name` in `dir`, passing `args` to
+/// the C compiler.
+fn link(dir: &Path, name: &str, object: &[u8], main: &str, args: &[&str]) -> PathBuf {
+    let object_path = dir.join(format!("{}.o", name));
+    std::fs::write(&object_path, object).unwrap();
+    let main_path = dir.join(format!("{}.c", name));
+    std::fs::write(&main_path, main).unwrap();
+    let executable = dir.join(name);
+    let output = Command::new("cc")
+        .arg(&main_path)
+        .arg(&object_path)
+        .args(args)
+        .arg("-o")
+        .arg(&executable)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "linking failed:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    executable
+}
+
+/// Run `executable` and return what it prints.
+fn run(executable: &Path) -> String {
+    let output = Command::new(executable).output().unwrap();
+    assert!(output.status.success(), "{:?}", output.status);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// The symbols of `executable`, as listed by `nm`.
+fn symbols(executable: &Path) -> String {
+    let output = Command::new("nm").arg(executable).output().unwrap();
+    assert!(output.status.success(), "{:?}", output.status);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+#[cfg_attr(not(any(target_os = "linux", target_os = "macos")), ignore)]
+fn link_and_run() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("cranelift-object-link-and-run");
+    std::fs::create_dir_all(&dir).unwrap();
+    if !common::have_c_compiler(&dir) {
+        println!("skipping test: no working C compiler and linker found");
+        return;
+    }
+
+    // The linker splits Mach-O objects at every symbol.
+    let object = build_object(|builder| {
+        builder.subsections_via_symbols(true);
+    });
+    let executable = link(&dir, "link_and_run", &object, MAIN_C, &[]);
+    assert_eq!(run(&executable), MAIN_OUTPUT);
+    // Nothing is stripped by default.
+    assert!(symbols(&executable).contains("cl_unused"));
+}
+
+/// Link the object with unused functions stripped, which needs every function in a section of
+/// its own on ELF, and either that or subsections via symbols on Mach-O.
+#[test]
+#[cfg_attr(not(any(target_os = "linux", target_os = "macos")), ignore)]
+fn link_and_run_dead_strip() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("cranelift-object-dead-strip");
+    std::fs::create_dir_all(&dir).unwrap();
+    if !common::have_c_compiler(&dir) {
+        println!("skipping test: no working C compiler and linker found");
+        return;
+    }
+
+    let (gc_sections, options): (_, &[_]) = if cfg!(target_os = "macos") {
+        ("-Wl,-dead_strip", &[(true, false), (false, true)])
+    } else {
+        ("-Wl,--gc-sections", &[(false, true)])
+    };
+    for &(subsections_via_symbols, per_function_section) in options {
+        let object = build_object(|builder| {
+            builder
+                .subsections_via_symbols(subsections_via_symbols)
+                .per_function_section(per_function_section);
+        });
+        let name = format!(
+            "dead_strip_{}_{}",
+            subsections_via_symbols, per_function_section
+        );
+        let executable = link(&dir, &name, &object, MAIN_C, &[gc_sections]);
+        assert_eq!(run(&executable), MAIN_OUTPUT);
+        assert!(!symbols(&executable).contains("cl_unused"));
+    }
+}
+
+const MULTIVERSION_C: &str = r#"
+#include <stdio.h>
+
+extern void cl_multiply_add(const float *a, const float *b, float *out);
+
+int main(void) {
+    float a[4] = {1.5f, -2.0f, 3.0f, 0.25f};
+    float b[4] = {2.0f, 4.0f, -1.0f, 8.0f};
+    float out[4];
+    cl_multiply_add(a, b, out);
+    printf("%g %g %g %g\n", out[0], out[1], out[2], out[3]);
+    return 0;
+}
+"#;
+
+/// Build an object exporting `cl_multiply_add`, computing `out = a * b + a` on four floats, with
+/// an AVX2 variant and a baseline variant.
+fn build_multiversion_object() -> Vec<u8> {
+    let isa = |features: &[&str]| {
+        let mut flag_builder = settings::builder();
+        flag_builder.set("is_pic", "true").unwrap();
+        // Start from the baseline rather than the features of the host.
+        let mut isa_builder =
+            cranelift_codegen::isa::lookup(target_lexicon::Triple::host()).unwrap();
+        for feature in features {
+            isa_builder.enable(feature).unwrap();
+        }
+        isa_builder
+            .finish(settings::Flags::new(flag_builder))
+            .unwrap()
+    };
+    let builder = ObjectBuilder::new(isa(&[]), "multiversion", default_libcall_names()).unwrap();
+    let mut module = ObjectModule::new(builder);
+    let ptr = module.target_config().pointer_type();
+
+    let mut sig = module.make_signature();
+    sig.params = vec![AbiParam::new(ptr); 3];
+    let func_id = module
+        .declare_function("cl_multiply_add", Linkage::Export, &sig)
+        .unwrap();
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, func_id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.append_block_params_for_function_params(block);
+        bcx.switch_to_block(block);
+        let params = bcx.block_params(block).to_vec();
+        let a = bcx.ins().load(types::F32X4, MemFlags::new(), params[0], 0);
+        let b = bcx.ins().load(types::F32X4, MemFlags::new(), params[1], 0);
+        let product = bcx.ins().fmul(a, b);
+        let sum = bcx.ins().fadd(product, a);
+        bcx.ins().store(MemFlags::new(), sum, params[2], 0);
+        bcx.ins().return_(&[]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    let variants = [
+        TargetVariant::new("avx2", isa(&["has_avx", "has_avx2", "has_fma"])),
+        TargetVariant::new("baseline", isa(&[])),
+    ];
+    module
+        .define_function_multiversion(func_id, &mut ctx, &variants)
+        .unwrap();
+
+    module.finish().emit().unwrap()
+}
+
+#[test]
+#[cfg_attr(not(all(target_os = "linux", target_arch = "x86_64")), ignore)]
+fn link_and_run_multiversion() {
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("cranelift-object-multiversion");
+    std::fs::create_dir_all(&dir).unwrap();
+    if !common::have_c_compiler(&dir) {
+        println!("skipping test: no working C compiler and linker found");
+        return;
+    }
+
+    let object = build_multiversion_object();
+    let executable = link(&dir, "multiversion", &object, MULTIVERSION_C, &[]);
+
+    // The result doesn't depend on the variant the resolver picks.
+    assert_eq!(run(&executable), "4.5 -10 0 2.25\n");
+}
@@ -0,0 +1,416 @@
+//! Check the structure of Mach-O objects produced by `ObjectModule`.
+
+use cranelift_codegen::ir::*;
+use cranelift_codegen::settings;
+use cranelift_codegen::Context;
+use cranelift_frontend::*;
+use cranelift_module::*;
+use cranelift_object::*;
+use object::read::macho::MachHeader;
+use object::{Object, ObjectSection, ObjectSymbol, RelocationTarget, SymbolFlags, SymbolScope};
+use std::collections::HashMap;
+
+fn macho_module(triple: &str, configure: impl FnOnce(&mut ObjectBuilder)) -> ObjectModule {
+    let isa = cranelift_codegen::isa::lookup_by_name(triple)
+        .unwrap()
+        .finish(settings::Flags::new(settings::builder()))
+        .unwrap();
+    let mut builder = ObjectBuilder::new(isa, "macho", default_libcall_names()).unwrap();
+    configure(&mut builder);
+    ObjectModule::new(builder)
+}
+
+fn define(
+    module: &mut ObjectModule,
+    name: &str,
+    params: usize,
+    body: impl FnOnce(&mut FunctionBuilder, &mut ObjectModule, &[Value]),
+) -> FuncId {
+    let mut sig = module.make_signature();
+    sig.params = vec![AbiParam::new(types::I64); params];
+    sig.returns = vec![AbiParam::new(types::I64)];
+    let id = module.declare_function(name, Linkage::Local, &sig).unwrap();
+
+    let mut ctx = Context::new();
+    ctx.func = Function::with_name_signature(UserFuncName::user(0, id.as_u32()), sig);
+    let mut func_ctx = FunctionBuilderContext::new();
+    {
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.append_block_params_for_function_params(block);
+        bcx.switch_to_block(block);
+        let params = bcx.block_params(block).to_vec();
+        body(&mut bcx, module, &params);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+    }
+    module.define_function(id, &mut ctx).unwrap();
+    id
+}
+
+/// Define a leaf function, a function calling it and a function keeping values in callee-saved
+/// registers across a call to it.
+fn define_functions(module: &mut ObjectModule) {
+    let leaf = define(module, "leaf", 1, |bcx, _, params| {
+        let result = bcx.ins().iadd_imm(params[0], 1);
+        bcx.ins().return_(&[result]);
+    });
+    define(module, "caller", 1, |bcx, module, params| {
+        let leaf = module.declare_func_in_func(leaf, bcx.func);
+        let call = bcx.ins().call(leaf, &[params[0]]);
+        let result = bcx.inst_results(call)[0];
+        bcx.ins().return_(&[result]);
+    });
+    define(module, "saver", 3, |bcx, module, params| {
+        let leaf = module.declare_func_in_func(leaf, bcx.func);
+        let call = bcx.ins().call(leaf, &[params[0]]);
+        let mut result = bcx.inst_results(call)[0];
+        for &param in params {
+            result = bcx.ins().iadd(result, param);
+        }
+        bcx.ins().return_(&[result]);
+    });
+}
+
+fn header_flags(file: &object::File) -> u32 {
+    match file.flags() {
+        object::FileFlags::MachO { flags } => flags,
+        flags => panic!("unexpected file flags {:?}", flags),
+    }
+}
+
+/// The compact unwind encodings of the functions of `file`, by function name.
+fn compact_unwind(file: &object::File) -> HashMap<String, u32> {
+    let section = match file.section_by_name("__compact_unwind") {
+        Some(section) => section,
+        None => return HashMap::new(),
+    };
+    assert_eq!(section.segment_name().unwrap(), Some("__LD"));
+    let data = section.data().unwrap();
+    assert_eq!(data.len() % 32, 0);
+
+    let mut encodings = HashMap::new();
+    for (offset, reloc) in section.relocations() {
+        assert_eq!(offset % 32, 0);
+        assert_eq!(reloc.size(), 64);
+        let symbol = match reloc.target() {
+            RelocationTarget::Symbol(symbol) => file.symbol_by_index(symbol).unwrap(),
+            target => panic!("unexpected relocation target {:?}", target),
+        };
+        let entry = &data[offset as usize..][..32];
+        let length = u32::from_le_bytes(entry[8..12].try_into().unwrap());
+        let encoding = u32::from_le_bytes(entry[12..16].try_into().unwrap());
+        assert!(length > 0);
+        assert!(entry[16..].iter().all(|&byte| byte == 0));
+        encodings.insert(symbol.name().unwrap().to_string(), encoding);
+    }
+    assert_eq!(encodings.len() * 32, data.len());
+    encodings
+}
+
+#[test]
+fn subsections_via_symbols() {
+    for &(subsections_via_symbols, per_function_section) in
+        &[(false, false), (true, false), (false, true), (true, true)]
+    {
+        let mut module = macho_module("aarch64-apple-darwin", |builder| {
+            builder
+                .subsections_via_symbols(subsections_via_symbols)
+                .per_function_section(per_function_section);
+        });
+        define_functions(&mut module);
+        let bytes = module.finish().emit().unwrap();
+        let file = object::File::parse(&*bytes).unwrap();
+
+        assert_eq!(
+            header_flags(&file) & object::macho::MH_SUBSECTIONS_VIA_SYMBOLS != 0,
+            subsections_via_symbols
+        );
+
+        // Every function has its own symbol, either in the one `__text` section or in a
+        // `__text_N` section of its own.
+        let text_sections = file
+            .sections()
+            .filter(|section| section.name().unwrap().starts_with("__text"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            text_sections.len(),
+            if per_function_section { 3 } else { 1 }
+        );
+        for section in &text_sections {
+            assert_eq!(section.segment_name().unwrap(), Some("__TEXT"));
+            assert_eq!(
+                section.flags(),
+                object::SectionFlags::MachO {
+                    flags: object::macho::S_ATTR_PURE_INSTRUCTIONS
+                        | object::macho::S_ATTR_SOME_INSTRUCTIONS
+                }
+            );
+            assert_eq!(section.name() == Ok("__text"), !per_function_section);
+        }
+        let symbols = ["_leaf", "_caller", "_saver"]
+            .iter()
+            .map(|&name| file.symbols().find(|s| s.name() == Ok(name)).unwrap())
+            .collect::<Vec<_>>();
+        for symbol in &symbols {
+            let section = symbol.section_index().unwrap();
+            assert!(text_sections.iter().any(|text| text.index() == section));
+            assert_eq!(symbol.address() % 4, 0);
+        }
+        let mut addresses = symbols.iter().map(|s| s.address()).collect::<Vec<_>>();
+        addresses.sort();
+        addresses.dedup();
+        assert_eq!(addresses.len(), 3);
+        if per_function_section {
+            let mut sections = symbols
+                .iter()
+                .map(|s| s.section_index().unwrap().0)
+                .collect::<Vec<_>>();
+            sections.sort();
+            sections.dedup();
+            assert_eq!(sections.len(), 3);
+        }
+    }
+}
+
+/// The number of functions in sections of their own is limited, as Mach-O objects can only
+/// have 255 sections.
+#[test]
+fn per_function_section_limit() {
+    let mut module = macho_module("x86_64-apple-darwin", |builder| {
+        builder.per_function_section(true);
+    });
+    for i in 0..MACHO_MAX_FUNCTION_SECTIONS {
+        define(&mut module, &format!("f{}", i), 0, |bcx, _, _| {
+            let zero = bcx.ins().iconst(types::I64, 0);
+            bcx.ins().return_(&[zero]);
+        });
+    }
+
+    let mut sig = module.make_signature();
+    sig.returns = vec![AbiParam::new(types::I64)];
+    let id = module.declare_function("g", Linkage::Local, &sig).unwrap();
+    let err = module
+        .define_function_bytes(
+            id,
+            &Function::with_name_signature(UserFuncName::user(0, id.as_u32()), sig),
+            1,
+            &[0xc3],
+            &[],
+        )
+        .unwrap_err();
+    assert!(matches!(err, ModuleError::Backend(_)), "{:?}", err);
+
+    let bytes = module.finish().emit().unwrap();
+    let file = object::File::parse(&*bytes).unwrap();
+    assert_eq!(
+        file.sections()
+            .filter(|section| section.name().unwrap().starts_with("__text_"))
+            .count(),
+        MACHO_MAX_FUNCTION_SECTIONS
+    );
+}
+
+#[test]
+fn compact_unwind_x86_64() {
+    let mut module = macho_module("x86_64-apple-darwin", |builder| {
+        builder.compact_unwind(true);
+    });
+    define_functions(&mut module);
+    let bytes = module.finish().emit().unwrap();
+    let file = object::File::parse(&*bytes).unwrap();
+
+    let encodings = compact_unwind(&file);
+    assert_eq!(encodings.len(), 3);
+    // UNWIND_X86_64_MODE_RBP_FRAME without saved registers.
+    assert_eq!(encodings["_leaf"], 0x0100_0000);
+    assert_eq!(encodings["_caller"], 0x0100_0000);
+    // UNWIND_X86_64_MODE_RBP_FRAME with rbx, r13 and r14 saved 32 bytes below rbp.
+    assert_eq!(encodings["_saver"], 0x0100_0000 | (4 << 16) | 0b100_011_001);
+}
+
+#[test]
+fn compact_unwind_aarch64() {
+    let mut module = macho_module("aarch64-apple-darwin", |builder| {
+        builder.compact_unwind(true);
+    });
+    define_functions(&mut module);
+    let bytes = module.finish().emit().unwrap();
+    let file = object::File::parse(&*bytes).unwrap();
+
+    let encodings = compact_unwind(&file);
+    // UNWIND_ARM64_MODE_FRAMELESS as the leaf function doesn't set up a frame.
+    assert_eq!(encodings["_leaf"], 0x0200_0000);
+    // UNWIND_ARM64_MODE_FRAME without saved registers.
+    assert_eq!(encodings["_caller"], 0x0400_0000);
+    // The callee-saved registers are saved in a different order than the system unwinder
+    // expects them in, so there is no entry for this function.
+    assert!(!encodings.contains_key("_saver"));
+}
+
+#[test]
+fn no_compact_unwind() {
+    // Not requested.
+    let mut module = macho_module("x86_64-apple-darwin", |_| {});
+    define_functions(&mut module);
+    let bytes = module.finish().emit().unwrap();
+    let file = object::File::parse(&*bytes).unwrap();
+    assert!(file.section_by_name("__compact_unwind").is_none());
+
+    // Not a Mach-O object.
+    let mut module = macho_module("x86_64-unknown-linux-gnu", |builder| {
+        builder.compact_unwind(true).subsections_via_symbols(true);
+    });
+    define_functions(&mut module);
+    let bytes = module.finish().emit().unwrap();
+    let file = object::File::parse(&*bytes).unwrap();
+    assert!(file.section_by_name("__compact_unwind").is_none());
+    assert_eq!(file.format(), object::BinaryFormat::Elf);
+}
+
+fn cpu_subtype(bytes: &[u8]) -> u32 {
+    let header = object::macho::MachHeader64::<object::Endianness>::parse(bytes, 0).unwrap();
+    header.cpusubtype(header.endian().unwrap())
+}
+
+#[test]
+fn arm64e() {
+    let mut module = macho_module("aarch64-apple-darwin", |builder| {
+        builder.arm64e(true);
+    });
+    define_functions(&mut module);
+    let bytes = module.finish().emit().unwrap();
+    assert_eq!(
+        cpu_subtype(&bytes),
+        object::macho::CPU_SUBTYPE_ARM64E | object::macho::CPU_SUBTYPE_PTRAUTH_ABI
+    );
+    let file = object::File::parse(&*bytes).unwrap();
+    assert_eq!(file.architecture(), object::Architecture::Aarch64);
+
+    let mut module = macho_module("aarch64-apple-darwin", |_| {});
+    define_functions(&mut module);
+    let bytes = module.finish().emit().unwrap();
+    assert_eq!(cpu_subtype(&bytes), object::macho::CPU_SUBTYPE_ARM64_ALL);
+
+    // Not an aarch64 object.
+    let mut module = macho_module("x86_64-apple-darwin", |builder| {
+        builder.arm64e(true);
+    });
+    define_functions(&mut module);
+    let bytes = module.finish().emit().unwrap();
+    assert_eq!(cpu_subtype(&bytes), object::macho::CPU_SUBTYPE_X86_64_ALL);
+}
+
+#[test]
+fn aliases() {
+    let mut module = macho_module("aarch64-apple-darwin", |builder| {
+        builder.subsections_via_symbols(true);
+    });
+    define_functions(&mut module);
+    let leaf = match module.get_name("leaf") {
+        Some(FuncOrDataId::Func(leaf)) => leaf,
+        other => panic!("unexpected declaration {:?}", other),
+    };
+    let sig = module
+        .declarations()
+        .get_function_decl(leaf)
+        .signature
+        .clone();
+    let aliases = [
+        ("leaf_export", Linkage::Export, 0),
+        ("leaf_hidden", Linkage::Hidden, 4),
+        ("leaf_weak", Linkage::Preemptible, 0),
+        ("leaf_local", Linkage::Local, 4),
+    ];
+    for &(name, linkage, offset) in &aliases {
+        let alias = module.declare_function(name, linkage, &sig).unwrap();
+        module.define_function_alias(alias, leaf, offset).unwrap();
+        // An alias is a definition.
+        assert!(matches!(
+            module.define_function_alias(alias, leaf, offset),
+            Err(ModuleError::DuplicateDefinition(_))
+        ));
+    }
+
+    // The target must be a function which has been defined, and the alias must be inside it.
+    let undefined = module
+        .declare_function("undefined", Linkage::Local, &sig)
+        .unwrap();
+    let alias = module
+        .declare_function("alias", Linkage::Local, &sig)
+        .unwrap();
+    assert!(module.define_function_alias(alias, undefined, 0).is_err());
+    assert!(module.define_function_alias(alias, leaf, 4096).is_err());
+    let import = module
+        .declare_function("import", Linkage::Import, &sig)
+        .unwrap();
+    assert!(matches!(
+        module.define_function_alias(import, leaf, 0),
+        Err(ModuleError::InvalidImportDefinition(_))
+    ));
+
+    let bytes = module.finish().emit().unwrap();
+    let file = object::File::parse(&*bytes).unwrap();
+    let symbol = |name: &str| file.symbols().find(|s| s.name() == Ok(name)).unwrap();
+    let leaf = symbol("_leaf");
+    for &(name, _, offset) in &aliases {
+        let alias = symbol(&format!("_{}", name));
+        assert_eq!(alias.section_index(), leaf.section_index());
+        assert_eq!(alias.address(), leaf.address() + offset);
+        let weak = if name == "leaf_weak" {
+            object::macho::N_WEAK_DEF
+        } else {
+            0
+        };
+        assert_eq!(
+            alias.flags(),
+            SymbolFlags::MachO {
+                n_desc: object::macho::N_ALT_ENTRY | weak
+            }
+        );
+    }
+    assert_eq!(symbol("_leaf_export").scope(), SymbolScope::Dynamic);
+    assert_eq!(symbol("_leaf_hidden").scope(), SymbolScope::Linkage);
+    assert_eq!(symbol("_leaf_weak").scope(), SymbolScope::Dynamic);
+    assert!(symbol("_leaf_weak").is_weak());
+    assert_eq!(symbol("_leaf_local").scope(), SymbolScope::Compilation);
+    assert_eq!(leaf.flags(), SymbolFlags::MachO { n_desc: 0 });
+}
+
+/// An alias keeps the function it is an alias of.
+#[test]
+fn strip_unreachable_aliases() {
+    let mut module = macho_module("aarch64-apple-darwin", |_| {});
+    define_functions(&mut module);
+    let id = |module: &ObjectModule, name| match module.get_name(name) {
+        Some(FuncOrDataId::Func(id)) => id,
+        other => panic!("unexpected declaration {:?}", other),
+    };
+    let caller = id(&module, "caller");
+    let saver = id(&module, "saver");
+    let sig = module
+        .declarations()
+        .get_function_decl(caller)
+        .signature
+        .clone();
+    let alias = module
+        .declare_function("caller_alias", Linkage::Export, &sig)
+        .unwrap();
+    module.define_function_alias(alias, caller, 0).unwrap();
+
+    let dropped = module
+        .strip_unreachable(&[FuncOrDataId::Func(alias)])
+        .unwrap();
+    assert_eq!(dropped, [FuncOrDataId::Func(saver)]);
+
+    let bytes = module.finish().emit().unwrap();
+    let file = object::File::parse(&*bytes).unwrap();
+    let address = |name| {
+        file.symbols()
+            .find(|s| s.name() == Ok(name))
+            .unwrap()
+            .address()
+    };
+    assert_eq!(address("_caller_alias"), address("_caller"));
+    assert!(file.symbols().all(|s| s.name() != Ok("_saver")));
+}
@@ -24,6 +24,8 @@ pub enum Token<'a> {
     Minus,                 // '-'
     Plus,                  // '+'
     Multiply,              // '*'
+    Slash,                 // '/'
+    Tilde,                 // '~'
     Comma,                 // ','
     Dot,                   // '.'
     Colon,                 // ':'
@@ -478,6 +480,8 @@ impl<'a> Lexer<'a> {
                 Some('!') => Some(self.scan_char(Token::Not)),
                 Some('+') => Some(self.scan_number()),
                 Some('*') => Some(self.scan_char(Token::Multiply)),
+                Some('/') => Some(self.scan_char(Token::Slash)),
+                Some('~') => Some(self.scan_char(Token::Tilde)),
                 Some('-') => {
                     if self.looking_at("->") {
                         Some(self.scan_chars(2, Token::Arrow))
@@ -3,7 +3,7 @@
 use crate::error::{Location, ParseError, ParseResult};
 use crate::isaspec;
 use crate::lexer::{LexError, Lexer, LocatedError, LocatedToken, Token};
-use crate::run_command::{Comparison, Invocation, RunCommand};
+use crate::run_command::{Comparison, Expected, Invocation, RunCommand};
 use crate::sourcemap::SourceMap;
 use crate::testcommand::TestCommand;
 use crate::testfile::{Comment, Details, Feature, TestFile};
@@ -192,6 +192,20 @@ pub fn parse_run_command<'a>(text: &str, signature: &Signature) -> ParseResult<O
     }
 }
 
+/// Parse `text` as a function signature, such as `(i64 vmctx, i32 uext) -> i32 system_v`.
+///
+/// This is the syntax following `function %name` in CLIF, and the inverse of the `Display`
+/// implementation of `Signature`. The calling convention defaults to `fast` if it is omitted.
+pub fn parse_signature(text: &str) -> ParseResult<Signature> {
+    let _tt = timing::parse_text();
+    let mut parser = Parser::new(text);
+    let sig = parser.parse_signature()?;
+    if parser.token().is_some() {
+        return err!(parser.loc, "expected end of signature");
+    }
+    Ok(sig)
+}
+
 pub struct Parser<'a> {
     lex: Lexer<'a>,
 
@@ -2291,6 +2305,7 @@ impl<'a> Parser<'a> {
     /// Parse a CLIF run command.
     ///
     /// run-command ::= "run" [":" invocation comparison expected]
+    ///               \ "run" ":" invocation "traps" trap-code
     ///               \ "print" [":" invocation]
     fn parse_run_command(&mut self, sig: &Signature) -> ParseResult<RunCommand> {
         // skip semicolon
@@ -2299,6 +2314,10 @@ impl<'a> Parser<'a> {
                 self.consume();
                 if self.optional(Token::Colon) {
                     let invocation = self.parse_run_invocation(sig)?;
+                    if self.optional(Token::Identifier("traps")) {
+                        let code = self.match_enum("expected trap code")?;
+                        return Ok(RunCommand::Trap(invocation, code));
+                    }
                     let comparison = self.parse_run_comparison()?;
                     let expected = self.parse_run_returns(sig)?;
                     Ok(RunCommand::Run(invocation, comparison, expected))
@@ -2310,7 +2329,7 @@ impl<'a> Parser<'a> {
                     // invocation, we create an invocation from a function like `() -> i*` and
                     // require the result to be non-zero.
                     let invocation = Invocation::new("default", vec![]);
-                    let expected = vec![DataValue::I8(0)];
+                    let expected = vec![Expected::Exact(DataValue::I8(0))];
                     let comparison = Comparison::NotEquals;
                     Ok(RunCommand::Run(invocation, comparison, expected))
                 } else {
@@ -2392,22 +2411,69 @@ impl<'a> Parser<'a> {
     /// Parse the expected return values of a run invocation.
     ///
     /// expected ::= "[" "]"
-    ///            | data-value
-    ///            | "[" data-value-list "]"
-    fn parse_run_returns(&mut self, sig: &Signature) -> ParseResult<Vec<DataValue>> {
-        if sig.returns.len() != 1 {
+    ///            | expected-value
+    ///            | "[" expected-value {"," expected-value} "]"
+    ///
+    /// The brackets are optional for functions with a single result, unless it is a vector,
+    /// whose lanes are themselves written between brackets.
+    fn parse_run_returns(&mut self, sig: &Signature) -> ParseResult<Vec<Expected>> {
+        let bracketed = if sig.returns.len() == 1 {
+            let ty = sig.returns[0].value_type;
+            !ty.is_vector() && !ty.is_dynamic_vector() && self.optional(Token::LBracket)
+        } else {
             self.match_token(Token::LBracket, "expected a left bracket [")?;
-        }
+            true
+        };
 
-        let returns = self
-            .parse_data_value_list(&sig.returns.iter().map(|a| a.value_type).collect::<Vec<_>>())?;
+        let mut returns = vec![];
+        for (i, ret) in sig.returns.iter().enumerate() {
+            if i > 0 {
+                self.match_token(Token::Comma, "expected a comma between return values")?;
+            }
+            returns.push(self.parse_run_expected(ret.value_type)?);
+        }
 
-        if sig.returns.len() != 1 {
+        if bracketed {
             self.match_token(Token::RBracket, "expected a right bracket ]")?;
         }
         Ok(returns)
     }
 
+    /// Parse an expected return value of a run invocation; e.g. `4.2` or `~4.2 +/- ulp(1)`.
+    ///
+    /// expected-value ::= data-value
+    ///                  | "~" data-value ["+/-" "ulp" "(" uimm64 ")"]
+    fn parse_run_expected(&mut self, ty: Type) -> ParseResult<Expected> {
+        if !self.optional(Token::Tilde) {
+            return Ok(Expected::Exact(self.parse_data_value(ty)?));
+        }
+        let lane_type = ty.lane_type();
+        if lane_type != F32 && lane_type != F64 {
+            return err!(
+                self.loc,
+                "approximate comparisons need a float type, not {}",
+                ty
+            );
+        }
+        let value = self.parse_data_value(ty)?;
+        let ulps = if self.optional(Token::Plus) {
+            self.match_token(Token::Slash, "expected '+/-'")?;
+            self.match_token(Token::Minus, "expected '+/-'")?;
+            self.match_identifier("ulp", "expected a tolerance, e.g. ulp(1)")?;
+            self.match_token(Token::LPar, "expected '(' after ulp")?;
+            let ulps = self.match_uimm64("expected a number of ulps")?;
+            self.match_token(Token::RPar, "expected ')' after the number of ulps")?;
+            ulps.into()
+        } else {
+            0
+        };
+        Ok(Expected::Approx {
+            value,
+            lane_type,
+            ulps,
+        })
+    }
+
     /// Parse a comma-separated list of data values.
     ///
     /// data-value-list ::= [data-value {"," data-value-list}]
@@ -2588,6 +2654,23 @@ impl<'a> Parser<'a> {
                     blocks: [block_then, block_else],
                 }
             }
+            InstructionFormat::PatchableJump => {
+                let block_initial = {
+                    let block_num = self.match_block("expected initial destination block")?;
+                    let args = self.parse_opt_value_list()?;
+                    ctx.function.dfg.block_call(block_num, &args)
+                };
+                self.match_token(Token::Comma, "expected ',' between operands")?;
+                let block_alternate = {
+                    let block_num = self.match_block("expected alternate destination block")?;
+                    let args = self.parse_opt_value_list()?;
+                    ctx.function.dfg.block_call(block_num, &args)
+                };
+                InstructionData::PatchableJump {
+                    opcode,
+                    blocks: [block_initial, block_alternate],
+                }
+            }
             InstructionFormat::BranchTable => {
                 let arg = self.match_value("expected SSA value operand")?;
                 self.match_token(Token::Comma, "expected ',' between operands")?;
@@ -2895,6 +2978,21 @@ mod tests {
         }
     }
 
+    #[test]
+    fn public_signature() {
+        let text = "(i64 sret, i64 vmctx, i8 uext, i16 sext, i64 sarg(16)) -> i32 sext, f64 tail";
+        let sig = parse_signature(text).unwrap();
+        assert_eq!(sig.to_string(), text);
+        assert_eq!(text.parse(), Ok(sig));
+
+        assert_eq!(
+            parse_signature("() -> i32 system_v i32")
+                .unwrap_err()
+                .to_string(),
+            "1: expected end of signature"
+        );
+    }
+
     #[test]
     fn signature() {
         let sig = Parser::new("()system_v").parse_signature().unwrap();
@@ -3479,6 +3577,23 @@ mod tests {
             "run: %my_func(1) == 0x0f0e0d0c0b0a09080706050403020100",
             &sig(&[I32], &[I8X16]),
         );
+        assert_roundtrip(
+            "run: %sqrt(0x1.000000p1) == ~0x1.6a09e6p0 +/- ulp(1)",
+            &sig(&[F32], &[F32]),
+        );
+        assert_roundtrip("run: %fn0() == [~+NaN, 1]", &sig(&[], &[F64, I8]));
+        assert_roundtrip("run: %div(1, 0) traps int_divz", &sig(&[I32, I32], &[I32]));
+        assert_roundtrip("run: %fn0() == []", &sig(&[], &[]));
+        assert_roundtrip(
+            "run: %fn0(1) == [1, 2, 3, 4, 5]",
+            &sig(&[I64], &[I64, I64, I64, I64, I64]),
+        );
+        assert_eq!(
+            parse("run: %fn0() == [7]", &sig(&[], &[I32]))
+                .unwrap()
+                .to_string(),
+            "run: %fn0() == 7"
+        );
 
         // Verify that default invocations are created when not specified.
         assert_eq!(
@@ -3496,6 +3611,12 @@ mod tests {
         assert!(parse("print", &sig(&[I32], &[I32])).is_err());
         assert!(parse("print:", &sig(&[], &[])).is_err());
         assert!(parse("run: ", &sig(&[], &[])).is_err());
+        assert!(parse("run: %fn0() == ~1", &sig(&[], &[I32])).is_err());
+        assert!(parse("run: %fn0() == ~0x1.0 +/- 1", &sig(&[], &[F32])).is_err());
+        assert!(parse("run: %fn0() traps", &sig(&[], &[I32])).is_err());
+        assert!(parse("run: %fn0() == [1, 2]", &sig(&[], &[I32])).is_err());
+        assert!(parse("run: %fn0() == [1]", &sig(&[], &[I32, I32])).is_err());
+        assert!(parse("run: %fn0() == 1, 2", &sig(&[], &[I32, I32])).is_err());
     }
 
     #[test]
@@ -5,8 +5,14 @@
 //!
 //! - `; run`: this assumes the function has a signature like `() -> b*`.
 //! - `; run: %fn(42, 4.2) == false`: this syntax specifies the parameters and return values.
+//! - `; run: %fn(42) == [1, 2.5]`: this specifies all of the return values of a function with
+//!   several, in order.
+//! - `; run: %fn(4.2) == ~2.05 +/- ulp(2)`: this compares a float return value approximately,
+//!   see [Expected::Approx].
+//! - `; run: %fn(42, 0) traps int_divz`: this expects the function to trap with the given code.
 
 use cranelift_codegen::data_value::{self, DataValue, DisplayDataValues};
+use cranelift_codegen::ir::{types, TrapCode, Type};
 use std::fmt::{self, Display, Formatter};
 
 /// A run command appearing in a test file.
@@ -17,31 +23,44 @@ pub enum RunCommand {
     /// Invoke a function and print its result.
     Print(Invocation),
     /// Invoke a function and compare its result to a value sequence.
-    Run(Invocation, Comparison, Vec<DataValue>),
+    Run(Invocation, Comparison, Vec<Expected>),
+    /// Invoke a function and expect it to trap with the given code.
+    Trap(Invocation, TrapCode),
 }
 
 impl RunCommand {
     /// Run the [RunCommand]:
     ///  - for [RunCommand::Print], print the returned values from invoking the function.
     ///  - for [RunCommand::Run], compare the returned values from the invoked function and
-    ///    return an `Err` with a descriptive string if the comparison fails.
+    ///    return an `Err` with a descriptive string if the comparison fails or the function
+    ///    traps.
+    ///  - for [RunCommand::Trap], return an `Err` unless the invoked function trapped with the
+    ///    expected code.
     ///
     /// Accepts a function used for invoking the actual execution of the command. This function,
     /// `invoked_fn`, is passed the _function name_ and _function arguments_ of the [Invocation].
     pub fn run<F>(&self, invoke_fn: F) -> Result<(), String>
     where
-        F: FnOnce(&str, &[DataValue]) -> Result<Vec<DataValue>, String>,
+        F: FnOnce(&str, &[DataValue]) -> Result<Outcome, String>,
     {
         match self {
             RunCommand::Print(invoke) => {
                 let actual = invoke_fn(&invoke.func, &invoke.args)?;
-                println!("{} -> {}", invoke, DisplayDataValues(&actual))
+                println!("{} -> {}", invoke, actual)
             }
             RunCommand::Run(invoke, compare, expected) => {
                 let actual = invoke_fn(&invoke.func, &invoke.args)?;
-                let matched = Self::compare_results(compare, &actual, expected);
+                let matched = match &actual {
+                    Outcome::Return(values) => Self::compare_results(compare, values, expected),
+                    Outcome::Trap(_) => false,
+                };
                 if !matched {
-                    let actual = DisplayDataValues(&actual);
+                    return Err(format!("Failed test: {}, actual: {}", self, actual));
+                }
+            }
+            RunCommand::Trap(invoke, code) => {
+                let actual = invoke_fn(&invoke.func, &invoke.args)?;
+                if actual != Outcome::Trap(*code) {
                     return Err(format!("Failed test: {}, actual: {}", self, actual));
                 }
             }
@@ -52,13 +71,13 @@ impl RunCommand {
     fn compare_results(
         compare: &Comparison,
         actual: &Vec<DataValue>,
-        expected: &Vec<DataValue>,
+        expected: &Vec<Expected>,
     ) -> bool {
         let are_equal = actual.len() == expected.len()
             && actual
                 .into_iter()
                 .zip(expected.into_iter())
-                .all(|(a, b)| a.bitwise_eq(b));
+                .all(|(a, b)| b.matches(a));
 
         match compare {
             Comparison::Equals => are_equal,
@@ -72,13 +91,135 @@ impl Display for RunCommand {
         match self {
             RunCommand::Print(invocation) => write!(f, "print: {}", invocation),
             RunCommand::Run(invocation, comparison, expected) => {
-                let expected = DisplayDataValues(expected);
-                write!(f, "run: {} {} {}", invocation, comparison, expected)
+                write!(f, "run: {} {} ", invocation, comparison)?;
+                match &expected[..] {
+                    [expected] => write!(f, "{}", expected),
+                    expected => {
+                        write!(f, "[")?;
+                        for (i, expected) in expected.iter().enumerate() {
+                            if i > 0 {
+                                write!(f, ", ")?;
+                            }
+                            write!(f, "{}", expected)?;
+                        }
+                        write!(f, "]")
+                    }
+                }
+            }
+            RunCommand::Trap(invocation, code) => {
+                write!(f, "run: {} traps {}", invocation, code)
+            }
+        }
+    }
+}
+
+/// What a function invoked by a [RunCommand] did.
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    /// The function returned these values.
+    Return(Vec<DataValue>),
+    /// The function trapped with this code.
+    Trap(TrapCode),
+}
+
+impl Display for Outcome {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Return(values) => write!(f, "{}", DisplayDataValues(values)),
+            Outcome::Trap(code) => write!(f, "trap {}", code),
+        }
+    }
+}
+
+/// A value expected to be returned by a [RunCommand::Run].
+#[derive(Debug, PartialEq)]
+pub enum Expected {
+    /// The returned value must have the same bits, e.g. `4.2`, or `+NaN:0x1` for a NaN with
+    /// exactly that payload.
+    Exact(DataValue),
+    /// The returned float, or each float lane of the returned vector, must be at most `ulps`
+    /// units in the last place away from `value`, e.g. `~4.2 +/- ulp(2)`. Both zeros are equal,
+    /// and an expected NaN, e.g. `~NaN`, matches any NaN.
+    Approx {
+        /// The value to compare against.
+        value: DataValue,
+        /// The type of the compared floats, `f32` or `f64`.
+        lane_type: Type,
+        /// The tolerance, in units in the last place.
+        ulps: u64,
+    },
+}
+
+impl Expected {
+    /// Returns whether `actual` is the expected value.
+    pub fn matches(&self, actual: &DataValue) -> bool {
+        match self {
+            Expected::Exact(expected) => actual.bitwise_eq(expected),
+            Expected::Approx {
+                value,
+                lane_type,
+                ulps,
+            } => {
+                let (expected, actual) = match (float_bytes(value), float_bytes(actual)) {
+                    (Some(e), Some(a)) if e.len() == a.len() => (e, a),
+                    _ => return false,
+                };
+                let lane_bytes = lane_type.bytes() as usize;
+                expected
+                    .chunks(lane_bytes)
+                    .zip(actual.chunks(lane_bytes))
+                    .all(
+                        |(e, a)| match (ordered(*lane_type, e), ordered(*lane_type, a)) {
+                            (None, a) => a.is_none(),
+                            (Some(e), Some(a)) => e.abs_diff(a) <= *ulps,
+                            (Some(_), None) => false,
+                        },
+                    )
             }
         }
     }
 }
 
+impl Display for Expected {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Expected::Exact(value) => write!(f, "{}", value),
+            Expected::Approx { value, ulps: 0, .. } => write!(f, "~{}", value),
+            Expected::Approx { value, ulps, .. } => write!(f, "~{} +/- ulp({})", value, ulps),
+        }
+    }
+}
+
+/// Returns the little-endian bytes of a float or vector value.
+fn float_bytes(value: &DataValue) -> Option<Vec<u8>> {
+    match value {
+        DataValue::F32(x) => Some(x.bits().to_le_bytes().to_vec()),
+        DataValue::F64(x) => Some(x.bits().to_le_bytes().to_vec()),
+        DataValue::V128(bytes) => Some(bytes.to_vec()),
+        DataValue::V64(bytes) => Some(bytes.to_vec()),
+        _ => None,
+    }
+}
+
+/// Map the little-endian bytes of a float of type `ty` to an integer such that adjacent floats
+/// map to adjacent integers, or `None` for a NaN.
+fn ordered(ty: Type, bytes: &[u8]) -> Option<i64> {
+    let (bits, sign, nan) = if ty == types::F32 {
+        let bits = u32::from_le_bytes(bytes.try_into().unwrap());
+        (u64::from(bits), 1 << 31, f32::from_bits(bits).is_nan())
+    } else {
+        let bits = u64::from_le_bytes(bytes.try_into().unwrap());
+        (bits, 1 << 63, f64::from_bits(bits).is_nan())
+    };
+    if nan {
+        None
+    } else if bits & sign != 0 {
+        Some(-((bits & !sign) as i64))
+    } else {
+        Some(bits as i64)
+    }
+}
+
 /// Represent a function call; [RunCommand]s invoke a CLIF function using an [Invocation].
 #[derive(Debug, PartialEq)]
 pub struct Invocation {
@@ -125,6 +266,7 @@ impl Display for Comparison {
 mod test {
     use super::*;
     use crate::parse_run_command;
+    use cranelift_codegen::ir::immediates::Ieee32;
     use cranelift_codegen::ir::{types, AbiParam, Signature};
     use cranelift_codegen::isa::CallConv;
 
@@ -136,7 +278,68 @@ mod test {
             .unwrap()
             .unwrap();
 
-        assert!(command.run(|_, _| Ok(vec![DataValue::I32(42)])).is_ok());
-        assert!(command.run(|_, _| Ok(vec![DataValue::I32(43)])).is_err());
+        let returns = |value| command.run(|_, _| Ok(Outcome::Return(vec![DataValue::I32(value)])));
+        assert!(returns(42).is_ok());
+        assert!(returns(43).is_err());
+        assert!(command
+            .run(|_, _| Ok(Outcome::Trap(TrapCode::IntegerDivisionByZero)))
+            .is_err());
+    }
+
+    #[test]
+    fn run_an_approximate_command() {
+        let mut signature = Signature::new(CallConv::Fast);
+        signature.returns.push(AbiParam::new(types::F32));
+        let matches = |command: &str, bits: u32| {
+            parse_run_command(command, &signature)
+                .unwrap()
+                .unwrap()
+                .run(|_, _| {
+                    Ok(Outcome::Return(vec![DataValue::F32(Ieee32::with_bits(
+                        bits,
+                    ))]))
+                })
+                .is_ok()
+        };
+
+        let one = "; run: %f() == ~0x1.0p0 +/- ulp(2)";
+        assert!(matches(one, 0x3f80_0000));
+        assert!(matches(one, 0x3f80_0002));
+        assert!(matches(one, 0x3f7f_fffe));
+        assert!(!matches(one, 0x3f80_0003));
+        assert!(!matches(one, 0x3f7f_fffd));
+        assert!(!matches(one, 0x7fc0_0000));
+
+        // The tolerance spans both zeros.
+        let zero = "; run: %f() == ~0x0.0 +/- ulp(1)";
+        assert!(matches(zero, 0x8000_0000));
+        assert!(matches(zero, 0x8000_0001));
+        assert!(!matches(zero, 0x8000_0002));
+
+        // Any NaN matches `~NaN`, and exact NaNs must have the same bits.
+        assert!(matches("; run: %f() == ~NaN", 0xffc0_0001));
+        assert!(!matches("; run: %f() == ~NaN", 0x7f80_0000));
+        assert!(matches("; run: %f() == +NaN:0x1", 0x7fc0_0001));
+        assert!(!matches("; run: %f() == +NaN:0x1", 0x7fc0_0000));
+    }
+
+    #[test]
+    fn run_a_trap_command() {
+        let mut signature = Signature::new(CallConv::Fast);
+        signature.params.push(AbiParam::new(types::I32));
+        signature.returns.push(AbiParam::new(types::I32));
+        let command = parse_run_command("; run: %f(0) traps int_divz", &signature)
+            .unwrap()
+            .unwrap();
+
+        assert!(command
+            .run(|_, _| Ok(Outcome::Trap(TrapCode::IntegerDivisionByZero)))
+            .is_ok());
+        assert!(command
+            .run(|_, _| Ok(Outcome::Trap(TrapCode::IntegerOverflow)))
+            .is_err());
+        assert!(command
+            .run(|_, _| Ok(Outcome::Return(vec![DataValue::I32(0)])))
+            .is_err());
     }
 }
@@ -17,6 +17,7 @@ use std::path::PathBuf;
 
 mod bugpoint;
 mod cat;
+mod compare_compile;
 mod compile;
 mod disasm;
 mod interpret;
@@ -39,6 +40,7 @@ enum Commands {
     Cat(cat::Options),
     PrintCfg(print_cfg::Options),
     Compile(compile::Options),
+    CompareCompile(compare_compile::Options),
     Pass(PassOptions),
     Bugpoint(bugpoint::Options),
 
@@ -104,6 +106,7 @@ fn main() -> anyhow::Result<()> {
         Commands::Interpret(i) => interpret::run(&i)?,
         Commands::PrintCfg(p) => print_cfg::run(&p)?,
         Commands::Compile(c) => compile::run(&c)?,
+        Commands::CompareCompile(c) => compare_compile::run(&c)?,
         Commands::Bugpoint(b) => bugpoint::run(&b)?,
 
         #[cfg(feature = "wasm")]
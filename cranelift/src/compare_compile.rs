@@ -0,0 +1,558 @@
+//! CLI tool to compile a corpus of Cranelift IR files under two configurations and report the
+//! differences, e.g. to evaluate the effect of a lowering or mid-end change.
+
+use crate::utils::{iterate_files, read_to_string};
+use anyhow::{Context as _, Result};
+use clap::{Parser, ValueEnum};
+use cranelift_codegen::ir::{Function, Opcode};
+use cranelift_codegen::isa::{OwnedTargetIsa, TargetIsa};
+use cranelift_codegen::print_errors::pretty_error;
+use cranelift_codegen::{Context, VCodeRegallocStats};
+use cranelift_reader::{parse_sets_and_triple, parse_test, OwnedFlagsOrIsa, ParseOptions};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Compile every function in a set of Cranelift IR files under two configurations ("A" and "B")
+/// and report the per-function differences in compile time, code size, instruction counts and
+/// register allocation statistics.
+#[derive(Parser)]
+pub struct Options {
+    /// Configure Cranelift settings for both configurations
+    #[clap(long = "set")]
+    settings: Vec<String>,
+
+    /// Configure Cranelift settings for configuration A only
+    #[clap(long = "set-a")]
+    settings_a: Vec<String>,
+
+    /// Configure Cranelift settings for configuration B only
+    #[clap(long = "set-b")]
+    settings_b: Vec<String>,
+
+    /// Specify the Cranelift target of both configurations
+    #[clap(long = "target")]
+    target: String,
+
+    /// Specify a different Cranelift target for configuration B
+    #[clap(long = "target-b")]
+    target_b: Option<String>,
+
+    /// Compile each function this many times per configuration and report the fastest time
+    #[clap(long, default_value = "1")]
+    iterations: u32,
+
+    /// Output format of the report
+    #[clap(long, value_enum, default_value = "text")]
+    format: Format,
+
+    /// Number of regressions listed in the summary of the text report
+    #[clap(long, default_value = "10")]
+    top: usize,
+
+    /// Write the report to this file instead of stdout
+    #[clap(short = 'o', long = "output")]
+    output: Option<PathBuf>,
+
+    /// Specify input files or directories; directories are searched for `.clif` files
+    #[clap(required = true)]
+    files: Vec<PathBuf>,
+}
+
+/// The format of a comparison report.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Text,
+    Csv,
+    Json,
+}
+
+pub fn run(options: &Options) -> Result<()> {
+    let report = compare(options)?;
+    let output = match options.format {
+        Format::Text => report.to_text(options.top),
+        Format::Csv => report.to_csv(),
+        Format::Json => report.to_json(),
+    };
+    match &options.output {
+        Some(path) => std::fs::write(path, output)?,
+        None => print!("{}", output),
+    }
+    Ok(())
+}
+
+/// The instruction classes used to summarize the optimized CLIF of a function.
+const OPCODE_CLASSES: [&str; 5] = ["arith", "const", "memory", "call", "control"];
+
+fn opcode_class(opcode: Opcode) -> &'static str {
+    if opcode.is_call() {
+        "call"
+    } else if opcode.is_branch() || opcode.is_terminator() {
+        "control"
+    } else if opcode.can_load() || opcode.can_store() {
+        "memory"
+    } else if matches!(
+        opcode,
+        Opcode::Iconst | Opcode::F32const | Opcode::F64const | Opcode::Vconst
+    ) {
+        "const"
+    } else {
+        "arith"
+    }
+}
+
+/// Statistics about compiling a single function under one configuration.
+#[derive(Clone, Debug)]
+pub struct CompileStats {
+    /// The fastest compile time over all iterations.
+    pub time: Duration,
+    /// The total size of the emitted code, in bytes.
+    pub code_size: u32,
+    /// The number of lowered machine instructions.
+    pub vcode_insts: usize,
+    /// Counts of optimized CLIF instructions, by class.
+    pub clif_insts: BTreeMap<&'static str, usize>,
+    /// Register allocation statistics.
+    pub regalloc: VCodeRegallocStats,
+}
+
+/// The comparison of a single function.
+#[derive(Clone, Debug)]
+pub struct FunctionReport {
+    /// The file the function was read from.
+    pub file: String,
+    /// The name of the function.
+    pub name: String,
+    /// The result of compiling under configuration A.
+    pub a: Result<CompileStats, String>,
+    /// The result of compiling under configuration B.
+    pub b: Result<CompileStats, String>,
+}
+
+impl FunctionReport {
+    /// The change in code size from A to B, if both compiled.
+    pub fn code_size_delta(&self) -> Option<i64> {
+        match (&self.a, &self.b) {
+            (Ok(a), Ok(b)) => Some(i64::from(b.code_size) - i64::from(a.code_size)),
+            _ => None,
+        }
+    }
+
+    /// The change in compile time from A to B, in nanoseconds, if both compiled.
+    pub fn time_delta_ns(&self) -> Option<i128> {
+        match (&self.a, &self.b) {
+            (Ok(a), Ok(b)) => Some(b.time.as_nanos() as i128 - a.time.as_nanos() as i128),
+            _ => None,
+        }
+    }
+}
+
+/// The result of comparing a corpus under two configurations.
+#[derive(Clone, Debug)]
+pub struct Report {
+    /// A description of configuration A.
+    pub config_a: String,
+    /// A description of configuration B.
+    pub config_b: String,
+    /// One entry per compiled function, in input order.
+    pub functions: Vec<FunctionReport>,
+}
+
+/// Compile all input functions under both configurations.
+pub fn compare(options: &Options) -> Result<Report> {
+    let settings_a: Vec<String> = options
+        .settings
+        .iter()
+        .chain(&options.settings_a)
+        .cloned()
+        .collect();
+    let settings_b: Vec<String> = options
+        .settings
+        .iter()
+        .chain(&options.settings_b)
+        .cloned()
+        .collect();
+    let target_b = options.target_b.as_ref().unwrap_or(&options.target);
+    let isa_a = build_isa(&settings_a, &options.target)?;
+    let isa_b = build_isa(&settings_b, target_b)?;
+
+    let mut functions = vec![];
+    for path in iterate_files(&options.files) {
+        if path.extension().and_then(|ext| ext.to_str()) != Some("clif") {
+            continue;
+        }
+        let file = path.display().to_string();
+        let buffer = read_to_string(&path)?;
+        let test_file = parse_test(&buffer, ParseOptions::default())
+            .with_context(|| format!("failed to parse {}", file))?;
+        for (func, _) in test_file.functions {
+            functions.push(FunctionReport {
+                file: file.clone(),
+                name: func.name.to_string(),
+                a: compile(&func, &*isa_a, options.iterations),
+                b: compile(&func, &*isa_b, options.iterations),
+            });
+        }
+    }
+
+    Ok(Report {
+        config_a: describe(&options.target, &settings_a),
+        config_b: describe(target_b, &settings_b),
+        functions,
+    })
+}
+
+fn build_isa(settings: &[String], target: &str) -> Result<OwnedTargetIsa> {
+    match parse_sets_and_triple(settings, target)? {
+        OwnedFlagsOrIsa::Isa(isa) => Ok(isa),
+        OwnedFlagsOrIsa::Flags(_) => anyhow::bail!("compilation requires a target isa"),
+    }
+}
+
+fn describe(target: &str, settings: &[String]) -> String {
+    let mut s = target.to_string();
+    for setting in settings {
+        s.push(' ');
+        s.push_str(setting);
+    }
+    s
+}
+
+fn compile(func: &Function, isa: &dyn TargetIsa, iterations: u32) -> Result<CompileStats, String> {
+    let mut time = Duration::MAX;
+    let mut context = Context::new();
+    for _ in 0..iterations.max(1) {
+        context.clear();
+        context.func = func.clone();
+        context.set_want_vcode(true);
+        let start = Instant::now();
+        context
+            .compile(isa, &mut Default::default())
+            .map_err(|err| pretty_error(err.func, err.inner))?;
+        time = time.min(start.elapsed());
+    }
+
+    let compiled_code = context.compiled_code().unwrap();
+    let vcode = compiled_code.vcode().unwrap();
+    let mut clif_insts: BTreeMap<_, _> = OPCODE_CLASSES.iter().map(|c| (*c, 0)).collect();
+    let optimized = &context.func;
+    for block in optimized.layout.blocks() {
+        for inst in optimized.layout.block_insts(block) {
+            *clif_insts
+                .get_mut(opcode_class(optimized.dfg.insts[inst].opcode()))
+                .unwrap() += 1;
+        }
+    }
+
+    Ok(CompileStats {
+        time,
+        code_size: compiled_code.code_info().total_size,
+        vcode_insts: vcode.num_insts(),
+        clif_insts,
+        regalloc: vcode.regalloc.unwrap_or_default(),
+    })
+}
+
+/// The numeric columns reported for each configuration, in output order.
+fn columns(stats: &CompileStats) -> Vec<(String, i128)> {
+    let mut columns = vec![
+        ("time_ns".to_string(), stats.time.as_nanos() as i128),
+        ("code_size".to_string(), stats.code_size.into()),
+        ("vcode_insts".to_string(), stats.vcode_insts as i128),
+        ("spillslots".to_string(), stats.regalloc.spillslots as i128),
+        ("spills".to_string(), stats.regalloc.spills as i128),
+        ("reloads".to_string(), stats.regalloc.reloads as i128),
+        ("moves".to_string(), stats.regalloc.moves as i128),
+    ];
+    for (class, count) in &stats.clif_insts {
+        columns.push((format!("clif_{}", class), *count as i128));
+    }
+    columns
+}
+
+/// The column names of `columns`, without needing any statistics.
+fn column_names() -> Vec<String> {
+    let mut names: Vec<String> = [
+        "time_ns",
+        "code_size",
+        "vcode_insts",
+        "spillslots",
+        "spills",
+        "reloads",
+        "moves",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect();
+    let mut classes = OPCODE_CLASSES.to_vec();
+    classes.sort();
+    names.extend(classes.iter().map(|c| format!("clif_{}", c)));
+    names
+}
+
+impl Report {
+    /// The functions that compiled under both configurations, sorted by decreasing code size
+    /// regression and then by decreasing compile time regression.
+    pub fn regressions(&self) -> Vec<&FunctionReport> {
+        let mut sorted: Vec<_> = self
+            .functions
+            .iter()
+            .filter(|f| f.code_size_delta().is_some())
+            .collect();
+        sorted.sort_by_key(|f| {
+            (
+                std::cmp::Reverse(f.code_size_delta()),
+                std::cmp::Reverse(f.time_delta_ns()),
+            )
+        });
+        sorted
+    }
+
+    /// Render the report as CSV, with one row per function.
+    pub fn to_csv(&self) -> String {
+        let names = column_names();
+        let mut out = String::from("file,function");
+        for config in ["a", "b", "delta"] {
+            for name in &names {
+                write!(out, ",{}_{}", config, name).unwrap();
+            }
+        }
+        out.push_str(",error\n");
+
+        for f in &self.functions {
+            write!(out, "{},{}", csv_field(&f.file), csv_field(&f.name)).unwrap();
+            let a = f.a.as_ref().ok().map(columns);
+            let b = f.b.as_ref().ok().map(columns);
+            for stats in [&a, &b] {
+                for i in 0..names.len() {
+                    match stats {
+                        Some(columns) => write!(out, ",{}", columns[i].1).unwrap(),
+                        None => out.push(','),
+                    }
+                }
+            }
+            for i in 0..names.len() {
+                match (&a, &b) {
+                    (Some(a), Some(b)) => write!(out, ",{}", b[i].1 - a[i].1).unwrap(),
+                    _ => out.push(','),
+                }
+            }
+            let error = match (&f.a, &f.b) {
+                (Err(e), _) => format!("A: {}", e),
+                (_, Err(e)) => format!("B: {}", e),
+                _ => String::new(),
+            };
+            writeln!(out, ",{}", csv_field(&error)).unwrap();
+        }
+        out
+    }
+
+    /// Render the report as a JSON document.
+    pub fn to_json(&self) -> String {
+        use serde_json::{json, Map, Value};
+
+        let stats_json = |stats: &Result<CompileStats, String>| match stats {
+            Ok(stats) => Value::Object(
+                columns(stats)
+                    .into_iter()
+                    .map(|(name, value)| (name, json!(value as i64)))
+                    .collect::<Map<_, _>>(),
+            ),
+            Err(e) => json!({ "error": e }),
+        };
+        let functions: Vec<Value> = self
+            .functions
+            .iter()
+            .map(|f| {
+                json!({
+                    "file": f.file,
+                    "function": f.name,
+                    "a": stats_json(&f.a),
+                    "b": stats_json(&f.b),
+                    "code_size_delta": f.code_size_delta(),
+                    "time_delta_ns": f.time_delta_ns().map(|t| t as i64),
+                })
+            })
+            .collect();
+        let regressions: Vec<Value> = self
+            .regressions()
+            .iter()
+            .map(|f| json!({ "file": f.file, "function": f.name }))
+            .collect();
+        let report = json!({
+            "config_a": self.config_a,
+            "config_b": self.config_b,
+            "functions": functions,
+            "regressions": regressions,
+        });
+        let mut out = serde_json::to_string_pretty(&report).unwrap();
+        out.push('\n');
+        out
+    }
+
+    /// Render the report as a human-readable table followed by a summary of the `top` biggest
+    /// regressions.
+    pub fn to_text(&self, top: usize) -> String {
+        let mut out = String::new();
+        writeln!(out, "A: {}", self.config_a).unwrap();
+        writeln!(out, "B: {}", self.config_b).unwrap();
+        writeln!(out).unwrap();
+        writeln!(
+            out,
+            "{:<40} {:>10} {:>10} {:>8} {:>12} {:>12} {:>10} {:>10}",
+            "function",
+            "size A",
+            "size B",
+            "delta",
+            "time A (us)",
+            "time B (us)",
+            "spills A",
+            "spills B"
+        )
+        .unwrap();
+        for f in &self.functions {
+            match (&f.a, &f.b) {
+                (Ok(a), Ok(b)) => writeln!(
+                    out,
+                    "{:<40} {:>10} {:>10} {:>+8} {:>12.1} {:>12.1} {:>10} {:>10}",
+                    f.name,
+                    a.code_size,
+                    b.code_size,
+                    f.code_size_delta().unwrap(),
+                    a.time.as_secs_f64() * 1e6,
+                    b.time.as_secs_f64() * 1e6,
+                    a.regalloc.spills,
+                    b.regalloc.spills,
+                )
+                .unwrap(),
+                (Err(e), _) => writeln!(out, "{:<40} failed under A: {}", f.name, e).unwrap(),
+                (_, Err(e)) => writeln!(out, "{:<40} failed under B: {}", f.name, e).unwrap(),
+            }
+        }
+
+        let regressions: Vec<_> = self
+            .regressions()
+            .into_iter()
+            .filter(|f| f.code_size_delta().unwrap() > 0)
+            .take(top)
+            .collect();
+        writeln!(out).unwrap();
+        if regressions.is_empty() {
+            writeln!(out, "No code size regressions.").unwrap();
+        } else {
+            writeln!(out, "Biggest code size regressions:").unwrap();
+            for f in regressions {
+                writeln!(
+                    out,
+                    "  {:+} bytes: {} ({})",
+                    f.code_size_delta().unwrap(),
+                    f.name,
+                    f.file
+                )
+                .unwrap();
+            }
+        }
+        out
+    }
+}
+
+/// Quote a CSV field if necessary.
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn options(format: Format) -> Options {
+        Options {
+            settings: vec![],
+            settings_a: vec!["opt_level=none".into()],
+            settings_b: vec!["opt_level=speed".into()],
+            target: "x86_64".into(),
+            target_b: None,
+            iterations: 1,
+            format,
+            top: 10,
+            output: None,
+            files: vec![
+                PathBuf::from("filetests/filetests/runtests/arithmetic.clif"),
+                PathBuf::from("filetests/filetests/runtests/br.clif"),
+                PathBuf::from("filetests/filetests/runtests/call.clif"),
+            ],
+        }
+    }
+
+    /// Count the functions in the input files by parsing them directly.
+    fn expected_functions(options: &Options) -> Vec<String> {
+        options
+            .files
+            .iter()
+            .flat_map(|path| {
+                let buffer = read_to_string(path).unwrap();
+                let test_file = parse_test(&buffer, ParseOptions::default()).unwrap();
+                test_file
+                    .functions
+                    .into_iter()
+                    .map(|(func, _)| func.name.to_string())
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn opt_level_none_vs_speed() {
+        let options = options(Format::Text);
+        let report = compare(&options).unwrap();
+
+        let names: Vec<_> = report.functions.iter().map(|f| f.name.clone()).collect();
+        assert!(!names.is_empty());
+        assert_eq!(names, expected_functions(&options));
+        for f in &report.functions {
+            for stats in [&f.a, &f.b] {
+                let stats = stats.as_ref().unwrap();
+                assert!(stats.code_size > 0, "{}: empty code", f.name);
+                assert!(stats.time > Duration::ZERO, "{}: zero time", f.name);
+                assert!(stats.vcode_insts > 0, "{}: no instructions", f.name);
+                assert!(stats.clif_insts["control"] > 0, "{}: no terminator", f.name);
+            }
+        }
+        assert_eq!(report.regressions().len(), report.functions.len());
+        assert!(report.to_text(3).contains("A: x86_64 opt_level=none"));
+    }
+
+    #[test]
+    fn csv_and_json_output() {
+        let options = options(Format::Csv);
+        let report = compare(&options).unwrap();
+
+        let csv = report.to_csv();
+        let mut lines = csv.lines();
+        let header: Vec<_> = lines.next().unwrap().split(',').collect();
+        assert_eq!(header.len(), 3 + 3 * column_names().len());
+        let rows: Vec<_> = lines.collect();
+        assert_eq!(rows.len(), report.functions.len());
+        for row in rows {
+            assert_eq!(row.split(',').count(), header.len(), "{}", row);
+        }
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        let functions = json["functions"].as_array().unwrap();
+        assert_eq!(functions.len(), report.functions.len());
+        for f in functions {
+            assert!(f["a"]["code_size"].as_i64().unwrap() > 0);
+            assert!(f["b"]["time_ns"].as_i64().unwrap() > 0);
+            assert_eq!(
+                f["code_size_delta"].as_i64().unwrap(),
+                f["b"]["code_size"].as_i64().unwrap() - f["a"]["code_size"].as_i64().unwrap()
+            );
+        }
+        assert_eq!(json["config_b"], "x86_64 opt_level=speed");
+    }
+}
@@ -4,8 +4,8 @@ use crate::utils::iterate_files;
 use clap::Parser;
 use cranelift_interpreter::environment::FunctionStore;
 use cranelift_interpreter::interpreter::{Interpreter, InterpreterState};
-use cranelift_interpreter::step::ControlFlow;
-use cranelift_reader::{parse_run_command, parse_test, ParseError, ParseOptions};
+use cranelift_interpreter::step::{ControlFlow, CraneliftTrap};
+use cranelift_reader::{parse_run_command, parse_test, Outcome, ParseError, ParseOptions};
 use std::path::PathBuf;
 use std::{fs, io};
 use thiserror::Error;
@@ -125,7 +125,8 @@ impl FileInterpreter {
                     let func_name = &format!("%{}", func_name);
                     let state = InterpreterState::default().with_function_store(env.clone());
                     match Interpreter::new(state).call_by_name(func_name, args) {
-                        Ok(ControlFlow::Return(results)) => Ok(results.to_vec()),
+                        Ok(ControlFlow::Return(results)) => Ok(Outcome::Return(results.to_vec())),
+                        Ok(ControlFlow::Trap(CraneliftTrap::User(code))) => Ok(Outcome::Trap(code)),
                         Ok(_) => panic!("Unexpected returned control flow--this is likely a bug."),
                         Err(t) => Err(t.to_string()),
                     }
@@ -6,7 +6,7 @@ use clap::Parser;
 use cranelift_codegen::isa::{CallConv, OwnedTargetIsa};
 use cranelift_filetests::TestFileCompiler;
 use cranelift_native::builder as host_isa_builder;
-use cranelift_reader::{parse_run_command, parse_test, Details, IsaSpec, ParseOptions};
+use cranelift_reader::{parse_run_command, parse_test, Details, IsaSpec, Outcome, ParseOptions};
 use std::path::{Path, PathBuf};
 use target_lexicon::Triple;
 
@@ -17,7 +17,7 @@ pub struct Options {
     #[clap(required = true)]
     files: Vec<PathBuf>,
 
-    /// Be more verbose
+    /// Be more verbose, printing every result of every run command
     #[clap(short, long)]
     verbose: bool,
 }
@@ -42,7 +42,7 @@ pub fn run(options: &Options) -> Result<()> {
     }
     for file in iterate_files(&filtered_files).chain(special_files) {
         total += 1;
-        match run_single_file(&file) {
+        match run_single_file(&file, options.verbose) {
             Ok(_) => {
                 if options.verbose {
                     println!("{}", file.to_string_lossy());
@@ -73,13 +73,13 @@ pub fn run(options: &Options) -> Result<()> {
 }
 
 /// Run all functions in a file that are succeeded by "run:" comments
-fn run_single_file(path: &PathBuf) -> Result<()> {
+fn run_single_file(path: &PathBuf, verbose: bool) -> Result<()> {
     let file_contents = read_to_string(&path)?;
-    run_file_contents(file_contents)
+    run_file_contents(file_contents, verbose)
 }
 
 /// Main body of `run_single_file` separated for testing
-fn run_file_contents(file_contents: String) -> Result<()> {
+fn run_file_contents(file_contents: String, verbose: bool) -> Result<()> {
     let options = ParseOptions {
         default_calling_convention: CallConv::triple_default(&Triple::host()), // use the host's default calling convention
         ..ParseOptions::default()
@@ -96,7 +96,16 @@ fn run_file_contents(file_contents: String) -> Result<()> {
                 let trampoline = compiled.get_trampoline(&func).unwrap();
 
                 command
-                    .run(|_, args| Ok(trampoline.call(args)))
+                    .run(|_, args| {
+                        let outcome = match trampoline.try_call(args) {
+                            Ok(results) => Outcome::Return(results),
+                            Err(trap) => Outcome::Trap(trap.code()),
+                        };
+                        if verbose {
+                            println!("{} -> {}", command, outcome);
+                        }
+                        Ok(outcome)
+                    })
                     .map_err(|s| anyhow::anyhow!("{}", s))?;
             }
         }
@@ -135,6 +144,30 @@ mod test {
             ; run
             ",
         );
-        run_file_contents(code).unwrap()
+        run_file_contents(code, false).unwrap()
+    }
+
+    #[test]
+    fn multiple_results() {
+        let code = String::from(
+            "
+            function %test(i64) -> i64, i8, i64, i64, i64 {
+            block0(v0: i64):
+                v1 = iconst.i8 -1
+                v2 = iadd_imm v0, 2
+                v3 = iadd_imm v0, 3
+                v4 = iadd_imm v0, 4
+                return v0, v1, v2, v3, v4
+            }
+            ; run: %test(1) == [1, -1, 3, 4, 5]
+            ",
+        );
+        run_file_contents(code.clone(), true).unwrap();
+        let wrong = code.replace("[1, -1, 3, 4, 5]", "[1, -1, 3, 4, 6]");
+        let err = run_file_contents(wrong, false).unwrap_err();
+        assert!(
+            err.to_string().ends_with("actual: [1, -1, 3, 4, 5]"),
+            "{err}"
+        );
     }
 }
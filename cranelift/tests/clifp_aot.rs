@@ -0,0 +1,49 @@
+use std::path::Path;
+use std::process::Command;
+
+/// Run the `clifp-aot` example with `args`, and return what it printed.
+fn run_example(args: &[&str]) -> String {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let output = Command::new(env!("CARGO"))
+        .args([
+            "run",
+            "--quiet",
+            "--example",
+            "clifp-aot",
+            "--manifest-path",
+        ])
+        .arg(manifest_dir.join("Cargo.toml"))
+        .arg("--")
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "clifp-aot failed:\n{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn jit_runs_main() {
+    let sample = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/clifp/arith.clifp");
+    assert_eq!(run_example(&["--jit", sample]), "main returned 95\n");
+}
+
+#[test]
+fn optimized_jit_runs_main() {
+    let sample = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/clifp/arith.clifp");
+    assert_eq!(run_example(&["--jit", "-O", sample]), "main returned 95\n");
+}
+
+#[test]
+fn aot_writes_object() {
+    let sample = concat!(env!("CARGO_MANIFEST_DIR"), "/examples/clifp/arith.clifp");
+    let object = std::env::temp_dir().join(format!("clifp-aot-{}.o", std::process::id()));
+    let stdout = run_example(&["-o", object.to_str().unwrap(), sample]);
+    let bytes = std::fs::read(&object).unwrap();
+    std::fs::remove_file(&object).unwrap();
+    assert!(stdout.starts_with("compiled 2 functions ("), "{stdout}");
+    assert!(!bytes.is_empty());
+}
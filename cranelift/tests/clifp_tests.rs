@@ -0,0 +1,262 @@
+//! Run the `(test name (expect value) expr)` and `(test name (trap code) expr)` forms of the
+//! clifp programs in `tests/clifp`.
+//!
+//! Each program is compiled with its tests in a JIT module of its own, and each test is run and
+//! reported with the file it's in. A program which doesn't compile fails with its rendered
+//! errors, and the other programs are still run. The programs can include the files of
+//! subdirectories such as `tests/clifp/lib`, which aren't programs of their own.
+//!
+//! A trap in the JIT is a signal which kills the process, so the tests expecting a trap are run
+//! by `cranelift-interpreter` instead, on the IR of the program and of the test, where the trap
+//! is the result of the call. Those tests can't call host functions or read data objects.
+//!
+//! Each program is compiled a second time once `clifp::optimize` has simplified it, and each
+//! test passes if it computes the expected value in both.
+
+use clifp::compile::{self, CompileError};
+use clifp::diagnostic;
+use clifp::parser::Expected;
+use clifp::source::{FileSystem, SourceMap};
+use cranelift_clifp as clifp;
+use cranelift_codegen::data_value::DataValue;
+use cranelift_codegen::ir::{types, Function, TrapCode, Type};
+use cranelift_interpreter::environment::FunctionStore;
+use cranelift_interpreter::interpreter::{Interpreter, InterpreterState};
+use cranelift_interpreter::step::{ControlFlow, CraneliftTrap};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, FuncId};
+use std::ffi::OsStr;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// A program compiled with its tests.
+struct Program {
+    jit: JITModule,
+    tests: Vec<Test>,
+    /// The IR of the functions of the program, and that of its tests in order, if any of them
+    /// expects a trap.
+    ir: Option<(Vec<Function>, Vec<Function>)>,
+}
+
+/// A compiled test of a program.
+struct Test {
+    name: String,
+    /// The function computing the expression of the test.
+    id: FuncId,
+    /// The type of the expression, `i32` or `i64`.
+    ty: Type,
+    expected: Expected,
+}
+
+/// What running a test did.
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Value(i128),
+    Trap(TrapCode),
+}
+
+impl Outcome {
+    /// The outcome `test` expects.
+    fn expected(test: &Test) -> Self {
+        match test.expected {
+            // The expected value fits in the type, and one above the signed values of the type
+            // stands for the negative value of the same bits.
+            Expected::Value(value) => Self::Value(match test.ty {
+                types::I32 => i128::from(value as i32),
+                _ => i128::from(value as i64),
+            }),
+            Expected::Trap(code) => Self::Trap(code),
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Value(value) => write!(f, "{value}"),
+            Self::Trap(code) => write!(f, "a trap with `{code}`"),
+        }
+    }
+}
+
+fn main() -> anyhow::Result<()> {
+    let dir = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/clifp"));
+    let files = clifp_files(dir)?;
+    if files.is_empty() {
+        anyhow::bail!("no clifp programs in {}", dir.display());
+    }
+
+    let mut passed = 0;
+    let mut failures = Vec::new();
+    for path in &files {
+        let file = path.strip_prefix(dir).unwrap_or(path).display().to_string();
+        let compiled = compile_file(path, false).and_then(|compiled| {
+            let optimized = compile_file(path, true)
+                .map_err(|errors| format!("once the program is optimized:\n{errors}"))?;
+            Ok((compiled, optimized))
+        });
+        let (program, optimized) = match compiled {
+            Ok(compiled) => compiled,
+            Err(errors) => {
+                println!("{file} ... FAILED to compile");
+                failures.push(format!("{file}:\n{errors}"));
+                continue;
+            }
+        };
+        if program.tests.is_empty() {
+            println!("{file} ... FAILED");
+            failures.push(format!("{file}: the program has no tests"));
+        }
+        for (i, test) in program.tests.iter().enumerate() {
+            let name = &test.name;
+            let expected = Outcome::expected(test);
+            let failure = match (program.run(i), optimized.run(i)) {
+                (Err(err), _) => format!("{file}: test `{name}` couldn't be interpreted: {err}"),
+                (Ok(outcome), _) if outcome != expected => {
+                    let outcome = describe(&outcome);
+                    format!("{file}: test `{name}` {outcome}, but {expected} was expected")
+                }
+                (_, Err(err)) => format!(
+                    "{file}: test `{name}` couldn't be interpreted once the program was \
+                     optimized: {err}"
+                ),
+                (_, Ok(outcome)) if outcome != expected => {
+                    let outcome = describe(&outcome);
+                    format!(
+                        "{file}: test `{name}` {outcome} once the program was optimized, but \
+                         {expected} was expected"
+                    )
+                }
+                _ => {
+                    println!("{file}: {name} ... ok");
+                    passed += 1;
+                    continue;
+                }
+            };
+            println!("{file}: {name} ... FAILED");
+            failures.push(failure);
+        }
+    }
+
+    println!("\n{passed} clifp tests passed in {} files", files.len());
+    if !failures.is_empty() {
+        anyhow::bail!("{} failures:\n\n{}", failures.len(), failures.join("\n\n"));
+    }
+    Ok(())
+}
+
+/// The `.clifp` files of `dir`, sorted by name.
+fn clifp_files(dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension() == Some(OsStr::new("clifp")) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// What a test did, as a result of a failure report: "computed 5" or "trapped with `int_ovf`".
+fn describe(outcome: &Outcome) -> String {
+    match outcome {
+        Outcome::Value(value) => format!("computed {value}"),
+        Outcome::Trap(code) => format!("trapped with `{code}`"),
+    }
+}
+
+/// Compile the program at `path` and its tests in a new JIT module, once they're optimized if
+/// `optimize`, and lower them to IR if any test expects a trap. An error is the rendered message
+/// of each problem, which names its file.
+///
+/// Tail calls on x86_64 rely on frame pointers, so they are kept.
+fn compile_file(path: &Path, optimize: bool) -> Result<Program, String> {
+    let mut sources = SourceMap::new();
+    let (mut program, _) = clifp::frontend_file(path, &FileSystem, &mut sources)
+        .map_err(|e| diagnostic::render_all_in(&e, &sources))?;
+    if optimize {
+        clifp::optimize(&mut program);
+    }
+    let render = |err: CompileError| match err {
+        CompileError::Diagnostics(diagnostics) => diagnostic::render_all_in(&diagnostics, &sources),
+        err => err.to_string(),
+    };
+    let flags = [("preserve_frame_pointers", "true")];
+    let builder =
+        JITBuilder::with_flags(&flags, default_libcall_names()).map_err(|e| e.to_string())?;
+    let mut jit = JITModule::new(builder);
+    compile::compile(&mut jit, &program).map_err(render)?;
+    let tests = compile::compile_tests(&mut jit, &program).map_err(render)?;
+    jit.finalize_definitions().map_err(|e| e.to_string())?;
+    let tests = tests
+        .into_iter()
+        .zip(&program.tests)
+        .map(|((name, id, ty), test)| Test {
+            name,
+            id,
+            ty,
+            expected: test.expected,
+        })
+        .collect();
+    let traps = program
+        .tests
+        .iter()
+        .any(|test| matches!(test.expected, Expected::Trap(_)));
+    let ir = if traps {
+        Some(program.to_ir().map_err(render)?)
+    } else {
+        None
+    };
+    Ok(Program { jit, tests, ir })
+}
+
+impl Program {
+    /// Run the `i`th test, in the JIT unless it expects a trap, or return why it couldn't be
+    /// interpreted.
+    fn run(&self, i: usize) -> Result<Outcome, String> {
+        let test = &self.tests[i];
+        match (test.expected, &self.ir) {
+            (Expected::Trap(_), Some((functions, tests))) => interpret(functions, &tests[i]),
+            _ => Ok(Outcome::Value(run(&self.jit, test.id, test.ty))),
+        }
+    }
+}
+
+/// Call the test function `id` of `jit`, which returns a `ty`.
+fn run(jit: &JITModule, id: FuncId, ty: Type) -> i128 {
+    let code = jit.get_finalized_function(id);
+    // `compile_tests` gives the functions no parameters and a result of type `ty`, which is
+    // `i32` or `i64`.
+    unsafe {
+        if ty == types::I32 {
+            let test = std::mem::transmute::<*const u8, extern "C" fn() -> i32>(code);
+            i128::from(test())
+        } else {
+            let test = std::mem::transmute::<*const u8, extern "C" fn() -> i64>(code);
+            i128::from(test())
+        }
+    }
+}
+
+/// Interpret the test function `test` of a program whose functions are `functions`, which are
+/// looked up by name as the calls refer to them.
+fn interpret(functions: &[Function], test: &Function) -> Result<Outcome, String> {
+    let mut store = FunctionStore::default();
+    for func in functions.iter().chain(std::iter::once(test)) {
+        store.add(func.name.to_string(), func);
+    }
+    let state = InterpreterState::default().with_function_store(store);
+    let flow = Interpreter::new(state)
+        .call_by_name(&test.name.to_string(), &[])
+        .map_err(|err| err.to_string())?;
+    match flow {
+        ControlFlow::Return(values) => match values[..] {
+            [DataValue::I32(value)] => Ok(Outcome::Value(value.into())),
+            [DataValue::I64(value)] => Ok(Outcome::Value(value.into())),
+            _ => Err(format!("the test returned {values:?}")),
+        },
+        ControlFlow::Trap(CraneliftTrap::User(code)) => Ok(Outcome::Trap(code)),
+        flow => Err(format!("the test ended with {flow:?}")),
+    }
+}
@@ -0,0 +1,207 @@
+//! Check that the `tracing` events of a compilation are attributed to the function being compiled.
+
+use cranelift_codegen::ir::{types, AbiParam, Function, InstBuilder, UserFuncName};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_module::{default_libcall_names, Linkage, Module};
+use cranelift_object::{ObjectBuilder, ObjectModule};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+/// A span, with its fields and its parent.
+#[derive(Debug)]
+struct SpanData {
+    name: &'static str,
+    fields: HashMap<&'static str, String>,
+    parent: Option<u64>,
+}
+
+/// An event, with its fields and the span it happened in.
+#[derive(Debug)]
+struct EventData {
+    fields: HashMap<&'static str, String>,
+    span: Option<u64>,
+}
+
+#[derive(Default)]
+struct Recording {
+    /// The span with id `n + 1` is `spans[n]`.
+    spans: Vec<SpanData>,
+    events: Vec<EventData>,
+    /// The entered spans, innermost last.
+    stack: Vec<u64>,
+}
+
+impl Recording {
+    /// The innermost ancestor of the span `id`, including itself, named `name`.
+    fn ancestor(&self, mut id: Option<u64>, name: &str) -> Option<&SpanData> {
+        while let Some(span) = id.map(|id| &self.spans[id as usize - 1]) {
+            if span.name == name {
+                return Some(span);
+            }
+            id = span.parent;
+        }
+        None
+    }
+}
+
+/// A subscriber recording every span and event.
+#[derive(Clone, Default)]
+struct Recorder(Arc<Mutex<Recording>>);
+
+struct FieldVisitor<'a>(&'a mut HashMap<&'static str, String>);
+
+impl Visit for FieldVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0.insert(field.name(), format!("{value:?}"));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name(), value.to_string());
+    }
+}
+
+impl Subscriber for Recorder {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+        let mut recording = self.0.lock().unwrap();
+        let mut fields = HashMap::new();
+        attrs.record(&mut FieldVisitor(&mut fields));
+        let parent = recording.stack.last().copied();
+        recording.spans.push(SpanData {
+            name: attrs.metadata().name(),
+            fields,
+            parent,
+        });
+        Id::from_u64(recording.spans.len() as u64)
+    }
+
+    fn record(&self, id: &Id, values: &Record<'_>) {
+        let mut recording = self.0.lock().unwrap();
+        let span = &mut recording.spans[id.into_u64() as usize - 1];
+        values.record(&mut FieldVisitor(&mut span.fields));
+    }
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut recording = self.0.lock().unwrap();
+        let mut fields = HashMap::new();
+        event.record(&mut FieldVisitor(&mut fields));
+        let span = recording.stack.last().copied();
+        recording.events.push(EventData { fields, span });
+    }
+
+    fn enter(&self, id: &Id) {
+        self.0.lock().unwrap().stack.push(id.into_u64());
+    }
+
+    fn exit(&self, id: &Id) {
+        let popped = self.0.lock().unwrap().stack.pop();
+        assert_eq!(popped, Some(id.into_u64()));
+    }
+}
+
+/// Define the functions `small` and `large` in an object module, the latter with more
+/// instructions.
+fn compile_two_functions() {
+    let mut flags = settings::builder();
+    flags.set("opt_level", "speed").unwrap();
+    let isa = cranelift_native::builder()
+        .unwrap()
+        .finish(settings::Flags::new(flags))
+        .unwrap();
+    let builder = ObjectBuilder::new(isa, "tracing", default_libcall_names()).unwrap();
+    let mut module = ObjectModule::new(builder);
+    let mut func_ctx = FunctionBuilderContext::new();
+
+    for (name, terms) in [("small", 1), ("large", 8)] {
+        let mut sig = module.make_signature();
+        sig.params.push(AbiParam::new(types::I64));
+        sig.returns.push(AbiParam::new(types::I64));
+        let id = module
+            .declare_function(name, Linkage::Export, &sig)
+            .unwrap();
+
+        let mut ctx = module.make_context();
+        ctx.func = Function::with_name_signature(UserFuncName::user(0, id.as_u32()), sig);
+        let mut bcx = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+        let block = bcx.create_block();
+        bcx.append_block_params_for_function_params(block);
+        bcx.switch_to_block(block);
+        let x = bcx.block_params(block)[0];
+        let mut sum = x;
+        for i in 0..terms {
+            let term = bcx.ins().imul_imm(x, i + 3);
+            sum = bcx.ins().iadd(sum, term);
+        }
+        bcx.ins().return_(&[sum]);
+        bcx.seal_all_blocks();
+        bcx.finalize();
+
+        module.define_function(id, &mut ctx).unwrap();
+    }
+}
+
+#[test]
+fn events_are_attributed_to_functions() {
+    let recorder = Recorder::default();
+    tracing::subscriber::with_default(recorder.clone(), compile_two_functions);
+    let recording = recorder.0.lock().unwrap();
+
+    // Each compilation happens within the definition of its function.
+    let compiles: Vec<&SpanData> = recording
+        .spans
+        .iter()
+        .filter(|span| span.name == "compile")
+        .collect();
+    assert_eq!(compiles.len(), 2, "{:#?}", recording.spans);
+    let mut insts = Vec::new();
+    for (compile, name) in compiles.iter().zip(["small", "large"]) {
+        let define = recording
+            .ancestor(compile.parent, "define_function")
+            .unwrap();
+        assert_eq!(define.fields["name"], name);
+        assert!(compile.fields.contains_key("isa"));
+        insts.push(compile.fields["insts"].parse::<usize>().unwrap());
+    }
+    assert!(insts[0] < insts[1], "{insts:?}");
+
+    // Every register allocation is reported once, in the compilation of its function.
+    let mut regallocs = HashMap::new();
+    for event in &recording.events {
+        if event.fields["message"] != "register allocation done" {
+            continue;
+        }
+        assert!(recording.ancestor(event.span, "compile").is_some());
+        let define = recording.ancestor(event.span, "define_function").unwrap();
+        *regallocs.entry(define.fields["name"].as_str()).or_insert(0) += 1;
+    }
+    assert_eq!(regallocs, HashMap::from([("small", 1), ("large", 1)]));
+
+    // So is the summary of the egraph pass, in the span of the pass, with more rewrites in the
+    // larger function.
+    let rewrites: Vec<(String, u64)> = recording
+        .events
+        .iter()
+        .filter(|event| event.fields["message"] == "egraph optimized")
+        .map(|event| {
+            let pass = recording.ancestor(event.span, "pass").unwrap();
+            assert_eq!(pass.fields["name"], "egraph");
+            let define = recording.ancestor(event.span, "define_function").unwrap();
+            let rewrites = event.fields["rewrites"].parse().unwrap();
+            (define.fields["name"].clone(), rewrites)
+        })
+        .collect();
+    assert_eq!(rewrites.len(), 2, "{rewrites:?}");
+    assert_eq!(rewrites[0].0, "small");
+    assert_eq!(rewrites[1].0, "large");
+    assert!(rewrites[0].1 < rewrites[1].1, "{rewrites:?}");
+}
@@ -48,13 +48,19 @@ pub fn bounds_check_and_compute_addr<Env>(
 where
     Env: FuncEnvironment + ?Sized,
 {
+    // The end of the access, if the index is a constant and the end doesn't
+    // overflow. Constant indices let us decide statically whether some
+    // accesses are in or out of bounds.
+    let offset_and_size = offset_plus_size(offset, access_size);
+    let const_end = constant_index(builder.func, index, heap.index_type)
+        .and_then(|index| index.checked_add(offset_and_size));
+
     let index = cast_index_to_pointer_ty(
         index,
         heap.index_type,
         env.pointer_type(),
         &mut builder.cursor(),
     );
-    let offset_and_size = offset_plus_size(offset, access_size);
     let spectre_mitigations_enabled = env.heap_access_spectre_mitigation();
 
     // We need to emit code that will trap (or compute an address that will trap
@@ -79,6 +85,18 @@ where
     Ok(match heap.style {
         // ====== Dynamic Memories ======
         //
+        // 0. Special case for constant indices: the access is in bounds if it
+        //    ends before `min_size`, since `bound >= min_size`.
+        HeapStyle::Dynamic { .. } if matches!(const_end, Some(end) if end <= heap.min_size) => {
+            Reachable(compute_addr(
+                &mut builder.cursor(),
+                heap,
+                env.pointer_type(),
+                index,
+                offset,
+            ))
+        }
+
         // 1. First special case for when `offset + access_size == 1`:
         //
         //            index + 1 > bound
@@ -205,7 +223,23 @@ where
             Unreachable
         }
 
-        // 2. Second special case for when we can completely omit explicit
+        // 2. Special case for constant indices: the access either ends after
+        //    `bound`, and always traps, or it is within the mapped and unmapped
+        //    pages and needs no explicit check.
+        HeapStyle::Static { bound } if matches!(const_end, Some(end) if end > bound) => {
+            env.before_unconditionally_trapping_memory_access(builder)?;
+            builder.ins().trap(ir::TrapCode::HeapOutOfBounds);
+            Unreachable
+        }
+        HeapStyle::Static { .. } if const_end.is_some() => Reachable(compute_addr(
+            &mut builder.cursor(),
+            heap,
+            env.pointer_type(),
+            index,
+            offset,
+        )),
+
+        // 3. Third special case for when we can completely omit explicit
         //    bounds checks for 32-bit static memories.
         //
         //    First, let's rewrite our comparison to move all of the constants
@@ -257,7 +291,7 @@ where
             ))
         }
 
-        // 3. General case for static memories.
+        // 4. General case for static memories.
         //
         //    We have to explicitly test whether
         //
@@ -286,9 +320,104 @@ where
                 oob,
             ))
         }
+
+        // ====== Guarded Memories ======
+        //
+        // Guarded memories never grow out of their reservation, and any access
+        // within `reservation + guard_size` bytes of their base traps if it is
+        // out of bounds.
+        //
+        // 1. First special case: trap immediately if the access ends after the
+        //    reservation, either because `offset + access_size` alone does or
+        //    because the index is a constant that does.
+        HeapStyle::Guarded { reservation, .. }
+            if offset_and_size > reservation
+                || matches!(const_end, Some(end) if end > reservation) =>
+        {
+            env.before_unconditionally_trapping_memory_access(builder)?;
+            builder.ins().trap(ir::TrapCode::HeapOutOfBounds);
+            Unreachable
+        }
+
+        // 2. Second special case for when the access cannot reach past the
+        //    guard pages, whatever the index: a constant index that didn't hit
+        //    the first special case, or any index such that
+        //
+        //        index + offset + access_size <= reservation + guard_size
+        //
+        //    holds for the largest value of the index type. This is how
+        //    32-bit memories with a 4GiB reservation and a guard of at least
+        //    the largest static offset avoid all explicit checks. It never
+        //    holds for 64-bit memories, unless the reservation and guard span
+        //    the whole address space.
+        HeapStyle::Guarded {
+            guard_size,
+            reservation,
+        } if const_end.is_some()
+            || max_index(heap.index_type)
+                <= reservation.saturating_add(guard_size) - offset_and_size =>
+        {
+            Reachable(compute_addr(
+                &mut builder.cursor(),
+                heap,
+                env.pointer_type(),
+                index,
+                offset,
+            ))
+        }
+
+        // 3. General case for guarded memories, including large static offsets
+        //    which reach past the guard pages and all 64-bit memories: trap if
+        //
+        //        index > reservation + guard_size - (offset + access_size)
+        //
+        //    which relies on the guard pages to catch the accesses which are
+        //    out of bounds but still pass the check. The subtraction cannot
+        //    wrap because we didn't hit the first special case.
+        HeapStyle::Guarded {
+            guard_size,
+            reservation,
+        } => {
+            let adjusted_bound = reservation.saturating_add(guard_size) - offset_and_size;
+            let oob =
+                builder
+                    .ins()
+                    .icmp_imm(IntCC::UnsignedGreaterThan, index, adjusted_bound as i64);
+            Reachable(explicit_check_oob_condition_and_compute_addr(
+                &mut builder.cursor(),
+                heap,
+                env.pointer_type(),
+                index,
+                offset,
+                spectre_mitigations_enabled,
+                oob,
+            ))
+        }
     })
 }
 
+/// Returns the value of `index` if it is a constant, zero-extended from the
+/// heap's index type.
+fn constant_index(func: &ir::Function, index: ir::Value, index_ty: ir::Type) -> Option<u64> {
+    let inst = func.dfg.value_def(index).inst()?;
+    match func.dfg.insts[inst] {
+        ir::InstructionData::UnaryImm {
+            opcode: ir::Opcode::Iconst,
+            imm,
+        } => Some((imm.bits() as u64) & max_index(index_ty)),
+        _ => None,
+    }
+}
+
+/// The largest index of a heap indexed with `index_ty`.
+fn max_index(index_ty: ir::Type) -> u64 {
+    match index_ty {
+        ir::types::I32 => u32::MAX.into(),
+        ir::types::I64 => u64::MAX,
+        _ => unreachable!("heap indices are i32 or i64"),
+    }
+}
+
 fn cast_index_to_pointer_ty(
     index: ir::Value,
     index_ty: ir::Type,
@@ -10,9 +10,9 @@ use crate::func_translator::FuncTranslator;
 use crate::state::FuncTranslationState;
 use crate::WasmType;
 use crate::{
-    DataIndex, DefinedFuncIndex, ElemIndex, FuncIndex, Global, GlobalIndex, GlobalInit, Heap,
-    HeapData, HeapStyle, Memory, MemoryIndex, Table, TableIndex, TypeConvert, TypeIndex,
-    WasmFuncType, WasmHeapType, WasmResult,
+    DataIndex, DefinedFuncIndex, ElemIndex, FuncIndex, FuncNames, Global, GlobalIndex, GlobalInit,
+    Heap, HeapData, HeapStyle, Memory, MemoryIndex, Table, TableIndex, TypeConvert, TypeIndex,
+    WasmError, WasmFuncType, WasmHeapType, WasmResult,
 };
 use core::convert::TryFrom;
 use cranelift_codegen::cursor::FuncCursor;
@@ -20,10 +20,10 @@ use cranelift_codegen::ir::immediates::{Offset32, Uimm64};
 use cranelift_codegen::ir::{self, InstBuilder};
 use cranelift_codegen::ir::{types::*, UserFuncName};
 use cranelift_codegen::isa::{CallConv, TargetFrontendConfig};
-use cranelift_entity::{EntityRef, PrimaryMap, SecondaryMap};
+use cranelift_entity::{EntityRef, PrimaryMap};
 use cranelift_frontend::FunctionBuilder;
 use std::boxed::Box;
-use std::string::String;
+use std::string::{String, ToString};
 use std::vec::Vec;
 use wasmparser::{FuncValidator, FunctionBody, Operator, ValidatorResources, WasmFeatures};
 
@@ -84,6 +84,15 @@ pub struct DummyModuleInfo {
 
     /// The start function.
     pub start_func: Option<FuncIndex>,
+
+    /// Names of the functions, used to name the translated functions and their callees.
+    pub func_names: FuncNames,
+
+    /// Style of the heaps implementing the linear memories.
+    pub heap_style: HeapStyle,
+
+    /// Size in bytes of the offset-guard pages following static heaps.
+    pub heap_offset_guard_size: u64,
 }
 
 impl DummyModuleInfo {
@@ -102,6 +111,11 @@ impl DummyModuleInfo {
             memories: PrimaryMap::new(),
             globals: PrimaryMap::new(),
             start_func: None,
+            func_names: FuncNames::new(),
+            heap_style: HeapStyle::Static {
+                bound: 0x1_0000_0000,
+            },
+            heap_offset_guard_size: 0x8000_0000,
         }
     }
 }
@@ -150,9 +164,6 @@ pub struct DummyEnvironment {
     /// Name of the module from the wasm file.
     pub module_name: Option<String>,
 
-    /// Function names.
-    function_names: SecondaryMap<FuncIndex, String>,
-
     /// Expected reachability data (before/after for each op) to assert. This is used for testing.
     #[doc(hidden)]
     pub expected_reachability: Option<ExpectedReachability>,
@@ -167,7 +178,6 @@ impl DummyEnvironment {
             func_bytecode_sizes: Vec::new(),
             debug_info,
             module_name: None,
-            function_names: SecondaryMap::new(),
             expected_reachability: None,
         }
     }
@@ -191,7 +201,31 @@ impl DummyEnvironment {
     /// Return the name of the function, if a name for the function with
     /// the corresponding index exists.
     pub fn get_func_name(&self, func_index: FuncIndex) -> Option<&str> {
-        self.function_names.get(func_index).map(String::as_ref)
+        self.info.func_names.get(func_index)
+    }
+
+    /// Return the names of the functions of the module.
+    pub fn func_names(&self) -> &FuncNames {
+        &self.info.func_names
+    }
+
+    /// Name the functions translated afterwards, and their callees, with `names` instead of
+    /// numeric user names.
+    ///
+    /// The name section of a module is only read after its functions are translated, so the
+    /// names must be provided beforehand to be used, for example with [`FuncNames::parse`].
+    pub fn set_func_names(&mut self, names: FuncNames) {
+        self.info.func_names = names;
+    }
+
+    /// Implement the linear memories of the functions translated afterwards with heaps of
+    /// `style`, followed by `offset_guard_size` bytes of offset-guard pages if it is static.
+    ///
+    /// The base address of the heaps is stored at `vmctx+0`. Dynamic heaps aren't supported,
+    /// as their bound is a global value of each function.
+    pub fn set_heap_style(&mut self, style: HeapStyle, offset_guard_size: u64) {
+        self.info.heap_style = style;
+        self.info.heap_offset_guard_size = offset_guard_size;
     }
 
     /// Test reachability bits before and after every opcode during translation, as provided by the
@@ -294,8 +328,14 @@ impl<'dummy_environment> FuncEnvironment for DummyFuncEnvironment<'dummy_environ
         &self.heaps
     }
 
-    fn make_heap(&mut self, func: &mut ir::Function, _index: MemoryIndex) -> WasmResult<Heap> {
-        // Create a static heap whose base address is stored at `vmctx+0`.
+    fn make_heap(&mut self, func: &mut ir::Function, index: MemoryIndex) -> WasmResult<Heap> {
+        if let HeapStyle::Dynamic { .. } = self.mod_info.heap_style {
+            return Err(WasmError::Unsupported(
+                "dynamic heaps in the dummy environment".to_string(),
+            ));
+        }
+
+        // Create a heap whose base address is stored at `vmctx+0`.
         let addr = func.create_global_value(ir::GlobalValueData::VMContext);
         let gv = func.create_global_value(ir::GlobalValueData::Load {
             base: addr,
@@ -307,11 +347,13 @@ impl<'dummy_environment> FuncEnvironment for DummyFuncEnvironment<'dummy_environ
         Ok(self.heaps.push(HeapData {
             base: gv,
             min_size: 0,
-            offset_guard_size: 0x8000_0000,
-            style: HeapStyle::Static {
-                bound: 0x1_0000_0000,
+            offset_guard_size: self.mod_info.heap_offset_guard_size,
+            style: self.mod_info.heap_style.clone(),
+            index_type: if self.mod_info.memories[index].entity.memory64 {
+                I64
+            } else {
+                I32
             },
-            index_type: I32,
         }))
     }
 
@@ -359,11 +401,15 @@ impl<'dummy_environment> FuncEnvironment for DummyFuncEnvironment<'dummy_environ
         // A real implementation would probably add a `vmctx` argument.
         // And maybe attempt some signature de-duplication.
         let signature = func.import_signature(self.vmctx_sig(sigidx));
-        let name =
-            ir::ExternalName::User(func.declare_imported_user_function(ir::UserExternalName {
-                namespace: 0,
-                index: index.as_u32(),
-            }));
+        let name = match self.mod_info.func_names.external_name(index) {
+            Some(name) => name,
+            None => {
+                ir::ExternalName::User(func.declare_imported_user_function(ir::UserExternalName {
+                    namespace: 0,
+                    index: index.as_u32(),
+                }))
+            }
+        };
         Ok(func.import_function(ir::ExtFuncData {
             name,
             signature,
@@ -889,8 +935,12 @@ impl<'data> ModuleEnvironment<'data> for DummyEnvironment {
                 FuncIndex::new(self.get_num_func_imports() + self.info.function_bodies.len());
 
             let sig = func_environ.vmctx_sig(self.get_func_type(func_index));
-            let mut func =
-                ir::Function::with_name_signature(UserFuncName::user(0, func_index.as_u32()), sig);
+            let name = self
+                .info
+                .func_names
+                .func_name(func_index)
+                .unwrap_or_else(|| UserFuncName::user(0, func_index.as_u32()));
+            let mut func = ir::Function::with_name_signature(name, sig);
 
             if self.debug_info {
                 func.collect_debug_info();
@@ -909,7 +959,7 @@ impl<'data> ModuleEnvironment<'data> for DummyEnvironment {
     }
 
     fn declare_func_name(&mut self, func_index: FuncIndex, name: &'data str) {
-        self.function_names[func_index] = String::from(name);
+        self.info.func_names.insert(func_index, name);
     }
 
     fn wasm_features(&self) -> WasmFeatures {
@@ -918,6 +968,7 @@ impl<'data> ModuleEnvironment<'data> for DummyEnvironment {
             simd: true,
             reference_types: true,
             bulk_memory: true,
+            memory64: true,
             ..WasmFeatures::default()
         }
     }
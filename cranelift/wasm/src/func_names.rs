@@ -0,0 +1,102 @@
+//! Readable names for the functions translated from a wasm module.
+//!
+//! The name section of a module comes after its code section, so the names it gives to functions
+//! are only declared to a `ModuleEnvironment` once all of them have been translated. Parsing them
+//! beforehand with [`FuncNames::parse`] allows an environment to name each function and its
+//! callees as they are translated, so that the displayed CLIF, verifier errors and disassembly
+//! show the names of the source instead of `u0:17`.
+
+use crate::{FuncIndex, WasmResult};
+use cranelift_codegen::ir::{ExternalName, UserFuncName};
+use cranelift_entity::SecondaryMap;
+use std::string::String;
+use wasmparser::{Name, NameSectionReader, Naming, Parser, Payload};
+
+/// The names of the functions of a wasm module, as given by its name section.
+#[derive(Clone, Debug, Default)]
+pub struct FuncNames {
+    names: SecondaryMap<FuncIndex, String>,
+}
+
+impl FuncNames {
+    /// Create an empty set of names.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Collect the function names of the name section of the wasm binary `data`.
+    ///
+    /// Modules without a name section have no names. The rest of the module is not validated.
+    pub fn parse(data: &[u8]) -> WasmResult<Self> {
+        let mut names = Self::new();
+        for payload in Parser::new(0).parse_all(data) {
+            let section = match payload? {
+                Payload::CustomSection(s) if s.name() == "name" => s,
+                _ => continue,
+            };
+            for subsection in NameSectionReader::new(section.data(), section.data_offset()) {
+                if let Name::Function(naming) = subsection? {
+                    for naming in naming {
+                        let Naming { index, name } = naming?;
+                        // We reserve `u32::MAX` for our own use in cranelift-entity.
+                        if index != u32::MAX {
+                            names.insert(FuncIndex::from_u32(index), name);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(names)
+    }
+
+    /// Set the name of the function at `index`.
+    pub fn insert(&mut self, index: FuncIndex, name: &str) {
+        self.names[index] = String::from(name);
+    }
+
+    /// The name of the function at `index`, if it has one.
+    pub fn get(&self, index: FuncIndex) -> Option<&str> {
+        self.names
+            .get(index)
+            .map(String::as_str)
+            .filter(|name| !name.is_empty())
+    }
+
+    /// Iterate over the named functions and their names, in index order.
+    pub fn iter(&self) -> impl Iterator<Item = (FuncIndex, &str)> {
+        self.names
+            .iter()
+            .filter(|(_, name)| !name.is_empty())
+            .map(|(index, name)| (index, name.as_str()))
+    }
+
+    /// The name of the CLIF function translated from the function at `index`, if it has one.
+    ///
+    /// Wasm names may contain any character, while CLIF names may only contain ASCII
+    /// alphanumerics and `_`, so the other characters are replaced with `_`. Distinct wasm names
+    /// may thus give the same CLIF name.
+    pub fn func_name(&self, index: FuncIndex) -> Option<UserFuncName> {
+        self.get(index)
+            .map(|name| UserFuncName::testcase(sanitize(name)))
+    }
+
+    /// The external name with which to call the function at `index`, if it has one.
+    ///
+    /// This is the same name as [`FuncNames::func_name`].
+    pub fn external_name(&self, index: FuncIndex) -> Option<ExternalName> {
+        self.get(index)
+            .map(|name| ExternalName::testcase(sanitize(name)))
+    }
+}
+
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
@@ -40,8 +40,8 @@ entity_impl!(Heap, "heap");
 /// the bound that `heap_addr` checks against. Memory accesses inside the heap
 /// bounds can trap if they hit an unmapped page (which is not accessible).
 ///
-/// Two styles of heaps are supported, *static* and *dynamic*. They behave
-/// differently when resized.
+/// Three styles of heaps are supported, *static*, *dynamic* and *guarded*. They
+/// behave differently when resized, and need different bounds checks.
 ///
 /// #### Static heaps
 ///
@@ -58,6 +58,14 @@ entity_impl!(Heap, "heap");
 /// resized, and its bound can move dynamically. The offset-guard pages move
 /// when the heap is resized. The bound of a dynamic heap is stored in a global
 /// value.
+///
+/// #### Guarded heaps
+///
+/// A *guarded heap* is like a static heap, but it is described by the address
+/// space reserved for it and the size of the offset-guard pages following the
+/// reservation, and its bounds checks rely on the guard pages wherever they
+/// can: an access is only checked explicitly when it could reach past the
+/// guard pages, and then only against their end.
 #[derive(Clone, PartialEq, Hash)]
 #[cfg_attr(feature = "enable-serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct HeapData {
@@ -69,6 +77,8 @@ pub struct HeapData {
     pub min_size: u64,
 
     /// Size in bytes of the offset-guard pages following the heap.
+    ///
+    /// Guarded heaps ignore this in favor of the guard size of their style.
     pub offset_guard_size: u64,
 
     /// Heap style, with additional style-specific info.
@@ -96,4 +106,16 @@ pub enum HeapStyle {
         /// bound.
         bound: u64,
     },
+
+    /// A guarded heap has a fixed base address, a reservation of address space
+    /// the heap never grows out of, and offset-guard pages after the
+    /// reservation.
+    Guarded {
+        /// Size in bytes of the offset-guard pages following the reservation.
+        guard_size: u64,
+
+        /// Size in bytes of the address space reserved for the heap. Only the
+        /// pages backing the current size of the heap are accessible.
+        reservation: u64,
+    },
 }
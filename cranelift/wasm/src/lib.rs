@@ -50,6 +50,7 @@ use std::collections::{
 
 mod code_translator;
 mod environ;
+mod func_names;
 mod func_translator;
 mod heap;
 mod module_translator;
@@ -61,6 +62,7 @@ pub use crate::environ::{
     DummyEnvironment, DummyFuncEnvironment, DummyModuleInfo, ExpectedReachability, FuncEnvironment,
     GlobalVariable, ModuleEnvironment, TargetEnvironment,
 };
+pub use crate::func_names::FuncNames;
 pub use crate::func_translator::FuncTranslator;
 pub use crate::heap::{Heap, HeapData, HeapStyle};
 pub use crate::module_translator::translate_module;
@@ -0,0 +1,266 @@
+//! Run loads translated under each heap style against a real heap reservation, whose pages past
+//! the size of the memory and whose guard pages are inaccessible, and check that exactly the
+//! out-of-bounds accesses trap, whether through an explicit check or through the guard pages.
+
+#![cfg(all(unix, any(target_arch = "x86_64", target_arch = "aarch64")))]
+
+use cranelift_codegen::ir::TrapCode;
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_codegen::Context;
+use cranelift_jit::signals::catch_traps;
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, FuncId, Linkage, Module};
+use cranelift_wasm::{translate_module, DummyEnvironment, HeapStyle};
+use rustix::mm::{mmap_anonymous, mprotect, munmap, MapFlags, MprotectFlags, ProtFlags};
+use std::ptr;
+
+const WASM_PAGE_SIZE: u64 = 0x1_0000;
+const RESERVATION: u64 = 0x1_0000_0000;
+const GUARD_SIZE: u64 = 0x1_0000;
+
+/// A reservation of `RESERVATION + GUARD_SIZE` bytes of which only the first wasm page is
+/// accessible.
+struct Reservation {
+    base: *mut u8,
+}
+
+impl Reservation {
+    fn new() -> Self {
+        let len = (RESERVATION + GUARD_SIZE) as usize;
+        unsafe {
+            let base = mmap_anonymous(
+                ptr::null_mut(),
+                len,
+                ProtFlags::empty(),
+                MapFlags::PRIVATE | MapFlags::NORESERVE,
+            )
+            .unwrap();
+            mprotect(
+                base,
+                WASM_PAGE_SIZE as usize,
+                MprotectFlags::READ | MprotectFlags::WRITE,
+            )
+            .unwrap();
+            Self { base: base.cast() }
+        }
+    }
+
+    fn write_u32(&self, addr: u64, value: u32) {
+        assert!(addr + 4 <= WASM_PAGE_SIZE);
+        unsafe {
+            self.base
+                .add(addr as usize)
+                .cast::<u32>()
+                .write_unaligned(value)
+        };
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        let len = (RESERVATION + GUARD_SIZE) as usize;
+        unsafe { munmap(self.base.cast(), len).unwrap() };
+    }
+}
+
+/// The `vmctx` of the dummy environment, which stores the heap base at offset 0.
+#[repr(C)]
+struct VMContext {
+    heap_base: *mut u8,
+}
+
+/// The functions of `wat`, translated with heaps of `style` and compiled.
+struct Compiled {
+    module: JITModule,
+    funcs: Vec<FuncId>,
+}
+
+impl Compiled {
+    fn new(wat: &str, style: HeapStyle, offset_guard_size: u64) -> Self {
+        let mut flag_builder = settings::builder();
+        flag_builder.set("use_colocated_libcalls", "false").unwrap();
+        flag_builder.set("is_pic", "false").unwrap();
+        let isa = cranelift_native::builder()
+            .unwrap()
+            .finish(settings::Flags::new(flag_builder))
+            .unwrap();
+
+        let mut env = DummyEnvironment::new(isa.frontend_config(), false);
+        env.set_heap_style(style, offset_guard_size);
+        translate_module(&wat::parse_str(wat).unwrap(), &mut env).unwrap();
+
+        let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+        let funcs = env
+            .info
+            .function_bodies
+            .values()
+            .enumerate()
+            .map(|(i, func)| {
+                let id = module
+                    .declare_function(&format!("f{i}"), Linkage::Local, &func.signature)
+                    .unwrap();
+                let mut ctx = Context::for_function(func.clone());
+                module.define_function(id, &mut ctx).unwrap();
+                id
+            })
+            .collect();
+        module.finalize_definitions().unwrap();
+        Self { module, funcs }
+    }
+
+    fn free(self) {
+        unsafe { self.module.free_memory() };
+    }
+
+    /// Call the function `func` taking an index, catching its traps.
+    fn call_indexed<I>(&self, func: usize, vmctx: &VMContext, index: I) -> Result<u32, TrapCode> {
+        let code = self.module.get_finalized_function(self.funcs[func]);
+        let f: extern "C" fn(I, &VMContext) -> u32 = unsafe { std::mem::transmute(code) };
+        unsafe { catch_traps(&self.module, || f(index, vmctx)) }.map_err(|trap| trap.code())
+    }
+
+    /// Call the function `func` without parameters, catching its traps.
+    fn call(&self, func: usize, vmctx: &VMContext) -> Result<u32, TrapCode> {
+        let code = self.module.get_finalized_function(self.funcs[func]);
+        let f: extern "C" fn(&VMContext) -> u32 = unsafe { std::mem::transmute(code) };
+        unsafe { catch_traps(&self.module, || f(vmctx)) }.map_err(|trap| trap.code())
+    }
+}
+
+fn wat(index_type: &str) -> String {
+    let memory = if index_type == "i64" { "i64 1" } else { "1" };
+    format!(
+        r#"
+        (module
+          (memory {memory})
+          (func (param {index_type}) (result i32)
+            local.get 0
+            i32.load)
+          (func (param {index_type}) (result i32)
+            local.get 0
+            i32.load offset=0x1000)
+          (func (param {index_type}) (result i32)
+            local.get 0
+            i32.load offset=0xffff0000)
+          (func (result i32)
+            {index_type}.const 0x100
+            i32.load offset=0x10)
+          (func (result i32)
+            {index_type}.const 0xfff0
+            i32.load offset=0x1000))
+        "#
+    )
+}
+
+const LOAD: usize = 0;
+const LOAD_OFFSET: usize = 1;
+const LOAD_LARGE_OFFSET: usize = 2;
+const LOAD_CONST: usize = 3;
+const LOAD_CONST_OOB: usize = 4;
+
+const OOB: Result<u32, TrapCode> = Err(TrapCode::HeapOutOfBounds);
+
+fn check_32_bit(compiled: Compiled) {
+    let heap = Reservation::new();
+    let vmctx = VMContext {
+        heap_base: heap.base,
+    };
+    heap.write_u32(0x10, 1);
+    heap.write_u32(0x110, 2);
+    heap.write_u32(0x1010, 3);
+    heap.write_u32(WASM_PAGE_SIZE - 4, 4);
+
+    assert_eq!(compiled.call_indexed(LOAD, &vmctx, 0x10u32), Ok(1));
+    assert_eq!(
+        compiled.call_indexed(LOAD, &vmctx, (WASM_PAGE_SIZE - 4) as u32),
+        Ok(4)
+    );
+    // Past the accessible pages, in the reservation and in the guard pages.
+    assert_eq!(
+        compiled.call_indexed(LOAD, &vmctx, (WASM_PAGE_SIZE - 3) as u32),
+        OOB
+    );
+    assert_eq!(compiled.call_indexed(LOAD, &vmctx, u32::MAX), OOB);
+
+    assert_eq!(compiled.call_indexed(LOAD_OFFSET, &vmctx, 0x10u32), Ok(3));
+    assert_eq!(compiled.call_indexed(LOAD_OFFSET, &vmctx, u32::MAX), OOB);
+
+    // The offset reaches past the guard pages, so these need an explicit check.
+    assert_eq!(compiled.call_indexed(LOAD_LARGE_OFFSET, &vmctx, 0u32), OOB);
+    assert_eq!(
+        compiled.call_indexed(LOAD_LARGE_OFFSET, &vmctx, 0x1_0000u32),
+        OOB
+    );
+    assert_eq!(
+        compiled.call_indexed(LOAD_LARGE_OFFSET, &vmctx, u32::MAX),
+        OOB
+    );
+
+    assert_eq!(compiled.call(LOAD_CONST, &vmctx), Ok(2));
+    assert_eq!(compiled.call(LOAD_CONST_OOB, &vmctx), OOB);
+    compiled.free();
+}
+
+#[test]
+fn static_heap() {
+    let style = HeapStyle::Static { bound: RESERVATION };
+    check_32_bit(Compiled::new(&wat("i32"), style, GUARD_SIZE));
+}
+
+#[test]
+fn guarded_heap() {
+    let style = HeapStyle::Guarded {
+        guard_size: GUARD_SIZE,
+        reservation: RESERVATION,
+    };
+    check_32_bit(Compiled::new(&wat("i32"), style, 0));
+}
+
+#[test]
+fn guarded_heap_without_guard_pages() {
+    let style = HeapStyle::Guarded {
+        guard_size: 0,
+        reservation: RESERVATION,
+    };
+    check_32_bit(Compiled::new(&wat("i32"), style, 0));
+}
+
+#[test]
+fn guarded_heap_memory64() {
+    let style = HeapStyle::Guarded {
+        guard_size: GUARD_SIZE,
+        reservation: RESERVATION,
+    };
+    let compiled = Compiled::new(&wat("i64"), style, 0);
+    let heap = Reservation::new();
+    let vmctx = VMContext {
+        heap_base: heap.base,
+    };
+    heap.write_u32(0x10, 1);
+    heap.write_u32(0x110, 2);
+    heap.write_u32(0x1010, 3);
+
+    assert_eq!(compiled.call_indexed(LOAD, &vmctx, 0x10u64), Ok(1));
+    assert_eq!(compiled.call_indexed(LOAD, &vmctx, WASM_PAGE_SIZE), OOB);
+    // Indices past the reservation and the guard pages, which a 32-bit index can't reach, and
+    // which would wrap around the address space if the offset were added unchecked.
+    assert_eq!(
+        compiled.call_indexed(LOAD, &vmctx, RESERVATION + GUARD_SIZE),
+        OOB
+    );
+    assert_eq!(compiled.call_indexed(LOAD, &vmctx, u64::MAX), OOB);
+    assert_eq!(compiled.call_indexed(LOAD_OFFSET, &vmctx, 0x10u64), Ok(3));
+    assert_eq!(
+        compiled.call_indexed(LOAD_OFFSET, &vmctx, u64::MAX - 0x800),
+        OOB
+    );
+    assert_eq!(compiled.call_indexed(LOAD_LARGE_OFFSET, &vmctx, 0u64), OOB);
+    assert_eq!(
+        compiled.call_indexed(LOAD_LARGE_OFFSET, &vmctx, u64::MAX - 0xffff_0000),
+        OOB
+    );
+
+    assert_eq!(compiled.call(LOAD_CONST, &vmctx), Ok(2));
+    assert_eq!(compiled.call(LOAD_CONST_OOB, &vmctx), OOB);
+    compiled.free();
+}
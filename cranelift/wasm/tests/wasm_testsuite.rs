@@ -2,7 +2,7 @@ use cranelift_codegen::isa::{CallConv, TargetFrontendConfig};
 use cranelift_codegen::print_errors::pretty_verifier_error;
 use cranelift_codegen::settings::{self, Flags};
 use cranelift_codegen::verifier;
-use cranelift_wasm::{translate_module, DummyEnvironment, FuncIndex};
+use cranelift_wasm::{translate_module, DummyEnvironment, FuncIndex, FuncNames};
 use std::fs;
 use std::path::Path;
 use target_lexicon::PointerWidth;
@@ -59,6 +59,56 @@ fn use_name_section() {
     );
 }
 
+#[test]
+fn name_translated_functions() {
+    let data = wat::parse_str(
+        r#"
+        (module
+            (func $my.callee (param i32) (result i32)
+                local.get 0
+            )
+            (func $caller (result i32)
+                i32.const 1
+                call $my.callee
+            )
+            (func (result i32)
+                call $caller
+            )
+        )"#,
+    )
+    .unwrap();
+
+    let mut dummy_environ = DummyEnvironment::new(
+        TargetFrontendConfig {
+            default_call_conv: CallConv::SystemV,
+            pointer_width: PointerWidth::U64,
+        },
+        false,
+    );
+    dummy_environ.set_func_names(FuncNames::parse(&data).unwrap());
+    translate_module(data.as_ref(), &mut dummy_environ).unwrap();
+
+    let names: Vec<_> = dummy_environ.func_names().iter().collect();
+    assert_eq!(
+        names,
+        [
+            (FuncIndex::from_u32(0), "my.callee"),
+            (FuncIndex::from_u32(1), "caller")
+        ]
+    );
+
+    let bodies = &dummy_environ.info.function_bodies;
+    let callee = bodies.values().next().unwrap().display().to_string();
+    assert!(callee.starts_with("function %my_callee("), "{}", callee);
+    let caller = bodies.values().nth(1).unwrap().display().to_string();
+    assert!(caller.starts_with("function %caller("), "{}", caller);
+    assert!(caller.contains("fn0 = %my_callee sig"), "{}", caller);
+    // Functions without a name keep a numeric name.
+    let unnamed = bodies.values().nth(2).unwrap().display().to_string();
+    assert!(unnamed.starts_with("function u0:2("), "{}", unnamed);
+    assert!(unnamed.contains("fn0 = %caller sig"), "{}", unnamed);
+}
+
 fn read_module(path: &Path) -> Vec<u8> {
     match path.extension() {
         None => {
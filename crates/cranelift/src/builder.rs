@@ -19,6 +19,7 @@ struct Builder {
     linkopts: LinkOptions,
     cache_store: Option<Arc<dyn CacheStore>>,
     clif_dir: Option<path::PathBuf>,
+    clif_opt_dir: Option<path::PathBuf>,
 }
 
 #[derive(Clone, Default)]
@@ -40,6 +41,7 @@ pub fn builder() -> Box<dyn CompilerBuilder> {
         linkopts: LinkOptions::default(),
         cache_store: None,
         clif_dir: None,
+        clif_opt_dir: None,
     })
 }
 
@@ -53,6 +55,11 @@ impl CompilerBuilder for Builder {
         Ok(())
     }
 
+    fn clif_opt_dir(&mut self, path: &path::Path) -> Result<()> {
+        self.clif_opt_dir = Some(path.to_path_buf());
+        Ok(())
+    }
+
     fn target(&mut self, target: target_lexicon::Triple) -> Result<()> {
         self.inner.target(target)?;
         Ok(())
@@ -83,6 +90,7 @@ impl CompilerBuilder for Builder {
             self.cache_store.clone(),
             self.linkopts.clone(),
             self.clif_dir.clone(),
+            self.clif_opt_dir.clone(),
         )))
     }
 
@@ -13,8 +13,8 @@ use cranelift_codegen::{CompiledCode, MachStackMap};
 use cranelift_entity::{EntityRef, PrimaryMap};
 use cranelift_frontend::FunctionBuilder;
 use cranelift_wasm::{
-    DefinedFuncIndex, FuncIndex, FuncTranslator, MemoryIndex, OwnedMemoryIndex, WasmFuncType,
-    WasmType,
+    DefinedFuncIndex, FuncIndex, FuncTranslator, HeapStyle, MemoryIndex, OwnedMemoryIndex,
+    WasmFuncType, WasmType,
 };
 use object::write::{Object, StandardSegment, SymbolId};
 use object::{RelocationEncoding, RelocationKind, SectionKind};
@@ -70,6 +70,7 @@ pub(crate) struct Compiler {
     linkopts: LinkOptions,
     cache_store: Option<Arc<dyn CacheStore>>,
     clif_dir: Option<path::PathBuf>,
+    clif_opt_dir: Option<path::PathBuf>,
 }
 
 impl Drop for Compiler {
@@ -106,6 +107,7 @@ impl Compiler {
         cache_store: Option<Arc<dyn CacheStore>>,
         linkopts: LinkOptions,
         clif_dir: Option<path::PathBuf>,
+        clif_opt_dir: Option<path::PathBuf>,
     ) -> Compiler {
         Compiler {
             contexts: Default::default(),
@@ -113,6 +115,7 @@ impl Compiler {
             linkopts,
             cache_store,
             clif_dir,
+            clif_opt_dir,
         }
     }
 }
@@ -121,14 +124,14 @@ impl wasmtime_environ::Compiler for Compiler {
     fn compile_function(
         &self,
         translation: &ModuleTranslation<'_>,
-        func_index: DefinedFuncIndex,
+        def_func_index: DefinedFuncIndex,
         input: FunctionBodyData<'_>,
         tunables: &Tunables,
         types: &ModuleTypes,
     ) -> Result<(WasmFunctionInfo, Box<dyn Any + Send>), CompileError> {
         let isa = &*self.isa;
         let module = &translation.module;
-        let func_index = module.func_index(func_index);
+        let func_index = module.func_index(def_func_index);
         let sig = translation.module.functions[func_index].signature;
         let wasm_func_ty = &types[sig];
 
@@ -209,18 +212,20 @@ impl wasmtime_environ::Compiler for Compiler {
             &mut func_env,
         )?;
 
-        if let Some(path) = &self.clif_dir {
-            use std::io::Write;
-
-            let mut path = path.to_path_buf();
-            path.push(format!("wasm_func_{}", func_index.as_u32()));
-            path.set_extension("clif");
-
-            let mut output = std::fs::File::create(path).unwrap();
-            write!(output, "{}", context.func.display()).unwrap();
+        let clif_output = if self.clif_dir.is_some() || self.clif_opt_dir.is_some() {
+            Some(ClifOutput::new(translation, def_func_index, &func_env))
+        } else {
+            None
+        };
+        if let (Some(dir), Some(output)) = (&self.clif_dir, &clif_output) {
+            output.write(dir, &context.func)?;
         }
+        let opt_clif_output = match (&self.clif_opt_dir, clif_output) {
+            (Some(dir), Some(output)) => Some((dir.as_path(), output)),
+            _ => None,
+        };
 
-        let (info, func) = compiler.finish_with_info(Some((&body, tunables)))?;
+        let (info, func) = compiler.finish_with_info(Some((&body, tunables)), opt_clif_output)?;
 
         let timing = cranelift_codegen::timing::take_current();
         log::debug!("{:?} translated in {:?}", func_index, timing.total());
@@ -978,7 +983,7 @@ impl FunctionCompiler<'_> {
     }
 
     fn finish(self) -> Result<CompiledFunction<CompiledFuncEnv>, CompileError> {
-        let (info, func) = self.finish_with_info(None)?;
+        let (info, func) = self.finish_with_info(None, None)?;
         assert!(info.stack_maps.is_empty());
         Ok(func)
     }
@@ -986,13 +991,26 @@ impl FunctionCompiler<'_> {
     fn finish_with_info(
         mut self,
         body_and_tunables: Option<(&FunctionBody<'_>, &Tunables)>,
+        opt_clif_output: Option<(&path::Path, ClifOutput)>,
     ) -> Result<(WasmFunctionInfo, CompiledFunction<CompiledFuncEnv>), CompileError> {
         let context = &mut self.cx.codegen_context;
         let isa = &*self.compiler.isa;
-        let (_, _code_buf) =
-            compile_maybe_cached(context, isa, self.cx.incremental_cache_ctx.as_mut())?;
+        // Code from the incremental cache is reused without running the optimizer, so the
+        // optimized IR can only be written out when the function is actually compiled.
+        let cache_ctx = if opt_clif_output.is_some() {
+            None
+        } else {
+            self.cx.incremental_cache_ctx.as_mut()
+        };
+        let (_, _code_buf) = compile_maybe_cached(context, isa, cache_ctx)?;
         let compiled_code = context.compiled_code().unwrap();
 
+        // After compilation `context.func` holds the optimized IR; write it out before the
+        // stack slots are moved out of it below.
+        if let Some((dir, output)) = opt_clif_output {
+            output.write(dir, &context.func)?;
+        }
+
         // Give wasm functions, user defined code, a "preferred" alignment
         // instead of the minimum alignment as this can help perf in niche
         // situations.
@@ -1076,6 +1094,83 @@ fn mach_stack_maps_to_stack_maps(mach_stack_maps: &[MachStackMap]) -> Vec<StackM
     stack_maps
 }
 
+/// The CLIF of a wasm function written out by `--emit-clif` and `--emit-clif-opt`.
+struct ClifOutput {
+    /// The file name, from the defined function index and the name section.
+    file_name: String,
+    /// Comments describing the function and the wasm heaps it accesses, which
+    /// aren't CLIF entities and so don't appear in the function itself.
+    header: String,
+}
+
+impl ClifOutput {
+    fn new(
+        translation: &ModuleTranslation<'_>,
+        def_func_index: DefinedFuncIndex,
+        func_env: &FuncEnvironment<'_>,
+    ) -> ClifOutput {
+        use std::fmt::Write;
+
+        let func_index = translation.module.func_index(def_func_index);
+        let name = translation
+            .debuginfo
+            .name_section
+            .func_names
+            .get(&func_index);
+
+        let mut file_name = format!("wasm_func_{}", def_func_index.as_u32());
+        let mut header = format!(
+            "; wasm function {} (defined function {})",
+            func_index.as_u32(),
+            def_func_index.as_u32()
+        );
+        if let Some(name) = name {
+            file_name.push('_');
+            file_name.extend(name.chars().map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            }));
+            write!(header, ": {:?}", name).unwrap();
+        }
+        file_name.push_str(".clif");
+        header.push('\n');
+
+        for (heap, data) in cranelift_wasm::FuncEnvironment::heaps(func_env) {
+            write!(
+                header,
+                "; {}: base {}, min_size {:#x}, offset_guard_size {:#x}, index_type {}, ",
+                heap, data.base, data.min_size, data.offset_guard_size, data.index_type
+            )
+            .unwrap();
+            match data.style {
+                HeapStyle::Dynamic { bound_gv } => writeln!(header, "dynamic bound {}", bound_gv),
+                HeapStyle::Static { bound } => writeln!(header, "static bound {:#x}", bound),
+                HeapStyle::Guarded {
+                    guard_size,
+                    reservation,
+                } => writeln!(
+                    header,
+                    "guarded reservation {:#x}, guard_size {:#x}",
+                    reservation, guard_size
+                ),
+            }
+            .unwrap();
+        }
+
+        ClifOutput { file_name, header }
+    }
+
+    fn write(&self, dir: &path::Path, func: &ir::Function) -> Result<(), CompileError> {
+        let path = dir.join(&self.file_name);
+        std::fs::write(&path, format!("{}\n{}", self.header, func.display())).map_err(|e| {
+            CompileError::Codegen(format!("failed to write {}: {}", path.display(), e))
+        })
+    }
+}
+
 fn declare_and_call(
     builder: &mut FunctionBuilder,
     signature: ir::Signature,
@@ -1198,3 +1293,71 @@ fn save_last_wasm_exit_fp_and_pc(
         ptr.vmruntime_limits_last_wasm_exit_pc(),
     );
 }
+
+#[cfg(all(test, feature = "incremental-cache"))]
+mod tests {
+    use anyhow::Result;
+    use std::borrow::Cow;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use wasmtime_environ::{CacheStore, ModuleEnvironment, ModuleTypesBuilder, Tunables};
+
+    #[derive(Debug, Default)]
+    struct MemoryCacheStore(Mutex<HashMap<Vec<u8>, Vec<u8>>>);
+
+    impl CacheStore for MemoryCacheStore {
+        fn get(&self, key: &[u8]) -> Option<Cow<[u8]>> {
+            self.0.lock().unwrap().get(key).cloned().map(Cow::Owned)
+        }
+
+        fn insert(&self, key: &[u8], value: Vec<u8>) -> bool {
+            self.0.lock().unwrap().insert(key.to_vec(), value);
+            true
+        }
+    }
+
+    #[test]
+    fn optimized_clif_with_incremental_cache() -> Result<()> {
+        let wasm = wat::parse_str(
+            r#"
+            (module
+                (func $f (param i32) (result i32)
+                    local.get 0
+                    i32.const 1
+                    i32.const 2
+                    i32.add
+                    i32.mul))
+            "#,
+        )?;
+        let clif_opt_dir = tempfile::tempdir()?;
+
+        let mut builder = crate::builder();
+        builder.set("opt_level", "speed")?;
+        builder.clif_opt_dir(clif_opt_dir.path())?;
+        builder.enable_incremental_compilation(Arc::new(MemoryCacheStore::default()))?;
+        let compiler = builder.build()?;
+
+        // The second compilation would be a cache hit, which must not replace the dump with
+        // unoptimized IR.
+        let tunables = Tunables::default();
+        let mut dumps = Vec::new();
+        for _ in 0..2 {
+            let mut validator = wasmparser::Validator::new();
+            let mut types = ModuleTypesBuilder::default();
+            let mut translation = ModuleEnvironment::new(&tunables, &mut validator, &mut types)
+                .translate(wasmparser::Parser::new(0), &wasm)?;
+            let functions = std::mem::take(&mut translation.function_body_inputs);
+            let types = types.finish();
+            for (index, body) in functions {
+                compiler.compile_function(&translation, index, body, &tunables, &types)?;
+            }
+            dumps.push(std::fs::read_to_string(
+                clif_opt_dir.path().join("wasm_func_0_f.clif"),
+            )?);
+        }
+        // The multiplication by the folded constant 3 is strength-reduced.
+        assert!(!dumps[0].contains("imul"), "{}", dumps[0]);
+        assert_eq!(dumps[0], dumps[1]);
+        Ok(())
+    }
+}
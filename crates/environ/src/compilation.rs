@@ -95,6 +95,11 @@ pub trait CompilerBuilder: Send + Sync + fmt::Debug {
         anyhow::bail!("clif output not supported");
     }
 
+    /// Enables output of the optimized clif in the directory specified.
+    fn clif_opt_dir(&mut self, _path: &path::Path) -> Result<()> {
+        anyhow::bail!("optimized clif output not supported");
+    }
+
     /// Returns the currently configured target triple that compilation will
     /// produce artifacts for.
     fn triple(&self) -> &target_lexicon::Triple;
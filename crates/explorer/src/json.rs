@@ -0,0 +1,506 @@
+//! Machine-readable output of the explorer.
+//!
+//! The document written by [`generate_json`] is a single JSON object whose
+//! `kind` is either `"module"` or `"component"`:
+//!
+//! ```text
+//! module = {
+//!   "kind": "module",
+//!   "wasm_size": int,          // size in bytes of the module's binary
+//!   "text_size": int,          // size in bytes of the whole compiled text
+//!                              // section, including trampolines
+//!   "totals": totals,
+//!   "sections": [section],
+//!   "memories": [memory],
+//!   "functions": [function],
+//! }
+//!
+//! component = {
+//!   "kind": "component",
+//!   "wasm_size": int,
+//!   "totals": totals,          // summed over all nested core modules
+//!   "sections": [section],
+//!   "modules": [module],       // inner core modules, in binary order
+//!   "components": [component], // inner components, in binary order
+//! }
+//!
+//! totals = {
+//!   "functions": int,          // defined functions
+//!   "imported_functions": int,
+//!   "wasm_code_size": int,     // sum of the defined functions' `wasm_size`
+//!   "native_code_size": int,   // sum of the defined functions' `native_size`
+//! }
+//!
+//! section = { "id": int, "name": string, "offset": int, "size": int }
+//!
+//! memory = {
+//!   "index": int, "imported": bool, "memory64": bool, "shared": bool,
+//!   "minimum": int, "maximum": int | null,  // in wasm pages
+//! }
+//!
+//! function = {
+//!   "index": int,              // index in the function index space
+//!   "name": string | null,     // from the `name` section, or the import name
+//!   "imported": bool,
+//!   // The following are `null` for imported functions.
+//!   "wasm_offset": int | null, // start of the body in the module's binary
+//!   "wasm_size": int | null,
+//!   "native_start": int | null,// range of the native code within the text
+//!   "native_end": int | null,  // section
+//!   "native_size": int | null,
+//!   "address_map": [{ "native_offset": int, "wasm_offset": int | null }],
+//! }
+//! ```
+//!
+//! All offsets are relative to the start of the enclosing module or
+//! component binary, so offsets of inner modules of a component can be fed
+//! directly to tools looking at that module in isolation.
+
+use anyhow::{bail, Result};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::io::Write;
+use std::ops::Range;
+use wasmparser::{Encoding, Name, NameSectionReader, Parser, Payload, TypeRef};
+
+/// Compiles `wasm`, a core module or a component, and writes a JSON
+/// description of the result to `dest`, following the schema documented in
+/// this module.
+pub fn generate_json(config: &wasmtime::Config, wasm: &[u8], dest: &mut dyn Write) -> Result<()> {
+    let engine = wasmtime::Engine::new(config)?;
+    let report = explore(&engine, wasm)?;
+    serde_json::to_writer_pretty(&mut *dest, &report)?;
+    writeln!(dest)?;
+    Ok(())
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum Report {
+    Module(ModuleReport),
+    Component(ComponentReport),
+}
+
+impl Report {
+    fn totals(&self) -> &Totals {
+        match self {
+            Report::Module(m) => &m.totals,
+            Report::Component(c) => &c.totals,
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct ModuleReport {
+    wasm_size: usize,
+    text_size: usize,
+    totals: Totals,
+    sections: Vec<Section>,
+    memories: Vec<Memory>,
+    functions: Vec<Function>,
+}
+
+#[derive(Serialize, Debug)]
+struct ComponentReport {
+    wasm_size: usize,
+    totals: Totals,
+    sections: Vec<Section>,
+    // Always `Report::Module`s and `Report::Component`s respectively, so that
+    // nested entries carry their `kind` as well.
+    modules: Vec<Report>,
+    components: Vec<Report>,
+}
+
+#[derive(Serialize, Debug, Default)]
+struct Totals {
+    functions: usize,
+    imported_functions: usize,
+    wasm_code_size: usize,
+    native_code_size: usize,
+}
+
+impl Totals {
+    fn add(&mut self, other: &Totals) {
+        self.functions += other.functions;
+        self.imported_functions += other.imported_functions;
+        self.wasm_code_size += other.wasm_code_size;
+        self.native_code_size += other.native_code_size;
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct Section {
+    id: u8,
+    name: String,
+    offset: usize,
+    size: usize,
+}
+
+#[derive(Serialize, Debug)]
+struct Memory {
+    index: u32,
+    imported: bool,
+    memory64: bool,
+    shared: bool,
+    minimum: u64,
+    maximum: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+struct Function {
+    index: u32,
+    name: Option<String>,
+    imported: bool,
+    wasm_offset: Option<usize>,
+    wasm_size: Option<usize>,
+    native_start: Option<usize>,
+    native_end: Option<usize>,
+    native_size: Option<usize>,
+    address_map: Vec<AddressMapping>,
+}
+
+#[derive(Serialize, Debug)]
+struct AddressMapping {
+    native_offset: usize,
+    wasm_offset: Option<u32>,
+}
+
+/// What's been gathered so far about a module or component while walking
+/// the binary; nested modules and components push their own entry.
+struct Pending {
+    encoding: Encoding,
+    range: Range<usize>,
+    sections: Vec<Section>,
+    // Core modules only.
+    imported_functions: Vec<String>,
+    memories: Vec<Memory>,
+    bodies: Vec<Range<usize>>,
+    names: HashMap<u32, String>,
+    // Components only.
+    children: Vec<Report>,
+}
+
+fn explore(engine: &wasmtime::Engine, wasm: &[u8]) -> Result<Report> {
+    let mut stack: Vec<Pending> = Vec::new();
+    let mut nested_range = None;
+
+    for payload in Parser::new(0).parse_all(wasm) {
+        let payload = payload?;
+
+        if let Some((id, range)) = payload.as_section() {
+            if let Some(pending) = stack.last_mut() {
+                let name = match &payload {
+                    Payload::CustomSection(c) => format!("custom:{}", c.name()),
+                    _ => section_name(pending.encoding, id).to_string(),
+                };
+                pending.sections.push(Section {
+                    id,
+                    name,
+                    offset: range.start - pending.range.start,
+                    size: range.len(),
+                });
+            }
+        }
+
+        match payload {
+            Payload::Version {
+                encoding, range, ..
+            } => {
+                let range = nested_range.take().unwrap_or(range.start..wasm.len());
+                stack.push(Pending {
+                    encoding,
+                    range,
+                    sections: Vec::new(),
+                    imported_functions: Vec::new(),
+                    memories: Vec::new(),
+                    bodies: Vec::new(),
+                    names: HashMap::new(),
+                    children: Vec::new(),
+                });
+            }
+            Payload::ModuleSection { range, .. } | Payload::ComponentSection { range, .. } => {
+                nested_range = Some(range);
+            }
+            Payload::ImportSection(imports) => {
+                let pending = stack.last_mut().unwrap();
+                for import in imports {
+                    let import = import?;
+                    match import.ty {
+                        TypeRef::Func(_) => pending
+                            .imported_functions
+                            .push(format!("{}::{}", import.module, import.name)),
+                        TypeRef::Memory(ty) => {
+                            let index = pending.memories.len() as u32;
+                            pending.memories.push(memory(index, true, &ty));
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Payload::MemorySection(memories) => {
+                let pending = stack.last_mut().unwrap();
+                for ty in memories {
+                    let index = pending.memories.len() as u32;
+                    pending.memories.push(memory(index, false, &ty?));
+                }
+            }
+            Payload::CodeSectionEntry(body) => {
+                stack.last_mut().unwrap().bodies.push(body.range());
+            }
+            Payload::CustomSection(c) if c.name() == "name" => {
+                let pending = stack.last_mut().unwrap();
+                // The name section is purely informational, so a malformed one
+                // isn't a reason to fail.
+                for name in NameSectionReader::new(c.data(), c.data_offset()) {
+                    if let Ok(Name::Function(map)) = name {
+                        for naming in map.into_iter().flatten() {
+                            pending.names.insert(naming.index, naming.name.to_string());
+                        }
+                    }
+                }
+            }
+            Payload::End(_) => {
+                let pending = stack.pop().unwrap();
+                let report = finish(engine, wasm, pending)?;
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(report),
+                    None => return Ok(report),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    bail!("unexpected end of wasm binary")
+}
+
+fn finish(engine: &wasmtime::Engine, wasm: &[u8], pending: Pending) -> Result<Report> {
+    let wasm_size = pending.range.len();
+    if let Encoding::Component = pending.encoding {
+        let mut totals = Totals::default();
+        let mut modules = Vec::new();
+        let mut components = Vec::new();
+        for child in pending.children {
+            totals.add(child.totals());
+            match child {
+                Report::Module(_) => modules.push(child),
+                Report::Component(_) => components.push(child),
+            }
+        }
+        return Ok(Report::Component(ComponentReport {
+            wasm_size,
+            totals,
+            sections: pending.sections,
+            modules,
+            components,
+        }));
+    }
+
+    let start = pending.range.start;
+    let module = wasmtime::Module::new(engine, &wasm[pending.range.clone()])?;
+    let address_map: Vec<_> = module
+        .address_map()
+        .ok_or_else(|| anyhow::anyhow!("address maps must be enabled in the config"))?
+        .collect();
+
+    let mut functions = Vec::new();
+    let mut totals = Totals::default();
+    for (index, name) in pending.imported_functions.into_iter().enumerate() {
+        let index = index as u32;
+        functions.push(Function {
+            index,
+            name: pending.names.get(&index).cloned().or(Some(name)),
+            imported: true,
+            wasm_offset: None,
+            wasm_size: None,
+            native_start: None,
+            native_end: None,
+            native_size: None,
+            address_map: Vec::new(),
+        });
+        totals.imported_functions += 1;
+    }
+
+    for (body, (native_start, native_size)) in
+        pending.bodies.iter().zip(module.function_locations())
+    {
+        let index = functions.len() as u32;
+        let native_end = native_start + native_size;
+        let address_map = address_map
+            .iter()
+            .filter(|(native, _)| (native_start..native_end).contains(native))
+            .map(|&(native_offset, wasm_offset)| AddressMapping {
+                native_offset,
+                wasm_offset,
+            })
+            .collect();
+        functions.push(Function {
+            index,
+            name: pending.names.get(&index).cloned(),
+            imported: false,
+            wasm_offset: Some(body.start - start),
+            wasm_size: Some(body.len()),
+            native_start: Some(native_start),
+            native_end: Some(native_end),
+            native_size: Some(native_size),
+            address_map,
+        });
+        totals.functions += 1;
+        totals.wasm_code_size += body.len();
+        totals.native_code_size += native_size;
+    }
+
+    Ok(Report::Module(ModuleReport {
+        wasm_size,
+        text_size: module.text().len(),
+        totals,
+        sections: pending.sections,
+        memories: pending.memories,
+        functions,
+    }))
+}
+
+fn memory(index: u32, imported: bool, ty: &wasmparser::MemoryType) -> Memory {
+    Memory {
+        index,
+        imported,
+        memory64: ty.memory64,
+        shared: ty.shared,
+        minimum: ty.initial,
+        maximum: ty.maximum,
+    }
+}
+
+fn section_name(encoding: Encoding, id: u8) -> &'static str {
+    match (encoding, id) {
+        (_, 0) => "custom",
+        (Encoding::Module, 1) => "type",
+        (Encoding::Module, 2) => "import",
+        (Encoding::Module, 3) => "function",
+        (Encoding::Module, 4) => "table",
+        (Encoding::Module, 5) => "memory",
+        (Encoding::Module, 6) => "global",
+        (Encoding::Module, 7) => "export",
+        (Encoding::Module, 8) => "start",
+        (Encoding::Module, 9) => "element",
+        (Encoding::Module, 10) => "code",
+        (Encoding::Module, 11) => "data",
+        (Encoding::Module, 12) => "data_count",
+        (Encoding::Module, 13) => "tag",
+        (Encoding::Component, 1) => "core_module",
+        (Encoding::Component, 2) => "core_instance",
+        (Encoding::Component, 3) => "core_type",
+        (Encoding::Component, 4) => "component",
+        (Encoding::Component, 5) => "instance",
+        (Encoding::Component, 6) => "alias",
+        (Encoding::Component, 7) => "type",
+        (Encoding::Component, 8) => "canonical",
+        (Encoding::Component, 9) => "start",
+        (Encoding::Component, 10) => "import",
+        (Encoding::Component, 11) => "export",
+        _ => "unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    fn explore_wat(config: &wasmtime::Config, wat: &str) -> Value {
+        let wasm = wat::parse_str(wat).unwrap();
+        let mut out = Vec::new();
+        generate_json(config, &wasm, &mut out).unwrap();
+        serde_json::from_slice(&out).unwrap()
+    }
+
+    fn check_module_totals(module: &Value) {
+        let functions = module["functions"].as_array().unwrap();
+        let sum = |field: &str| -> u64 { functions.iter().filter_map(|f| f[field].as_u64()).sum() };
+        let totals = &module["totals"];
+        assert_eq!(totals["wasm_code_size"].as_u64().unwrap(), sum("wasm_size"));
+        assert_eq!(
+            totals["native_code_size"].as_u64().unwrap(),
+            sum("native_size")
+        );
+        assert_eq!(
+            totals["functions"].as_u64().unwrap() + totals["imported_functions"].as_u64().unwrap(),
+            functions.len() as u64
+        );
+    }
+
+    #[test]
+    fn module() {
+        let mut config = wasmtime::Config::new();
+        config.wasm_multi_memory(true);
+        let json = explore_wat(
+            &config,
+            r#"
+            (module
+                (import "env" "log" (func $log (param i32)))
+                (import "env" "mem" (memory 1))
+                (memory 2 3)
+                (func $add (export "add") (param i32 i32) (result i32)
+                    local.get 0
+                    local.get 1
+                    i32.add)
+                (func $load (param i32) (result i32)
+                    local.get 0
+                    call $log
+                    local.get 0
+                    i32.load 1))
+            "#,
+        );
+
+        assert_eq!(json["kind"], "module");
+        check_module_totals(&json);
+        assert_eq!(json["totals"]["functions"], 2);
+        assert_eq!(json["totals"]["imported_functions"], 1);
+
+        let functions = json["functions"].as_array().unwrap();
+        assert_eq!(functions[0]["name"], "log");
+        assert_eq!(functions[0]["imported"], true);
+        assert!(functions[0]["native_size"].is_null());
+        assert_eq!(functions[1]["name"], "add");
+        assert!(functions[1]["native_size"].as_u64().unwrap() > 0);
+        assert!(!functions[1]["address_map"].as_array().unwrap().is_empty());
+
+        let memories = json["memories"].as_array().unwrap();
+        assert_eq!(memories.len(), 2);
+        assert_eq!(memories[0]["imported"], true);
+        assert_eq!(memories[1]["maximum"], 3);
+
+        let sections: Vec<_> = json["sections"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|s| s["name"].as_str().unwrap())
+            .collect();
+        assert!(sections.contains(&"code"));
+        assert!(sections.contains(&"custom:name"));
+    }
+
+    #[test]
+    fn component() {
+        let json = explore_wat(
+            &wasmtime::Config::new(),
+            r#"
+            (component
+                (core module (func (result i32) i32.const 1))
+                (component
+                    (core module
+                        (func (result i32) i32.const 2)
+                        (func (result i32) i32.const 3))))
+            "#,
+        );
+
+        assert_eq!(json["kind"], "component");
+        assert_eq!(json["modules"].as_array().unwrap().len(), 1);
+        let inner = &json["components"][0];
+        assert_eq!(inner["kind"], "component");
+        assert_eq!(inner["modules"][0]["totals"]["functions"], 2);
+        check_module_totals(&json["modules"][0]);
+        check_module_totals(&inner["modules"][0]);
+        assert_eq!(json["totals"]["functions"], 3);
+    }
+}
@@ -3,6 +3,10 @@ use capstone::arch::BuildsCapstone;
 use serde::Serialize;
 use std::{io::Write, str::FromStr};
 
+mod json;
+
+pub use json::generate_json;
+
 pub fn generate(
     config: &wasmtime::Config,
     target: Option<&str>,
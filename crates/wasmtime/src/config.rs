@@ -123,6 +123,7 @@ struct CompilerConfig {
     #[cfg(any(feature = "cranelift", feature = "winch"))]
     cache_store: Option<Arc<dyn CacheStore>>,
     clif_dir: Option<std::path::PathBuf>,
+    clif_opt_dir: Option<std::path::PathBuf>,
 }
 
 #[cfg(any(feature = "cranelift", feature = "winch"))]
@@ -135,6 +136,7 @@ impl CompilerConfig {
             flags: HashSet::new(),
             cache_store: None,
             clif_dir: None,
+            clif_opt_dir: None,
         }
     }
 
@@ -1570,6 +1572,9 @@ impl Config {
         if let Some(path) = &self.compiler_config.clif_dir {
             compiler.clif_dir(path)?;
         }
+        if let Some(path) = &self.compiler_config.clif_opt_dir {
+            compiler.clif_opt_dir(path)?;
+        }
 
         // If probestack is enabled for a target, Wasmtime will always use the
         // inline strategy which doesn't require us to define a `__probestack`
@@ -1654,6 +1659,13 @@ impl Config {
     pub fn emit_clif(&mut self, path: &Path) {
         self.compiler_config.clif_dir = Some(path.to_path_buf());
     }
+
+    /// Enables output of the optimized clif, as it is after the mid-end
+    /// optimizations, when compiling a WebAssembly module.
+    #[cfg(any(feature = "cranelift", feature = "winch"))]
+    pub fn emit_clif_opt(&mut self, path: &Path) {
+        self.compiler_config.clif_opt_dir = Some(path.to_path_buf());
+    }
 }
 
 fn round_up_to_pages(val: u64) -> u64 {
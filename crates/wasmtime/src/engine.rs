@@ -137,6 +137,29 @@ impl Engine {
         &*self.inner.compiler
     }
 
+    /// Returns the resolved compiler settings that this engine compiles code
+    /// with.
+    ///
+    /// The first entry is always `("target", <triple>)`, followed by every
+    /// shared Cranelift setting and then every ISA-specific flag, each with
+    /// its final value. Because inference of native CPU features has already
+    /// happened by the time an `Engine` is built, this reflects what was
+    /// actually used rather than what was requested in the [`Config`].
+    #[cfg(any(feature = "cranelift", feature = "winch"))]
+    #[cfg_attr(nightlydoc, doc(cfg(any(feature = "cranelift", feature = "winch"))))]
+    pub fn compiler_settings(&self) -> Vec<(String, String)> {
+        let compiler = self.compiler();
+        let mut settings = vec![("target".to_string(), compiler.triple().to_string())];
+        settings.extend(
+            compiler
+                .flags()
+                .into_iter()
+                .chain(compiler.isa_flags())
+                .map(|(name, value)| (name, value.to_string())),
+        );
+        settings
+    }
+
     pub(crate) fn allocator(&self) -> &dyn InstanceAllocator {
         self.inner.allocator.as_ref()
     }
@@ -424,6 +447,11 @@ impl Engine {
             | "tls_model" // wasmtime doesn't use tls right now
             | "opt_level" // opt level doesn't change semantics
             | "enable_alias_analysis" // alias analysis-based opts don't change semantics
+            | "enable_pressure_scheduling" // instruction scheduling doesn't change semantics
+            | "enable_loop_unrolling" // loop unrolling doesn't change semantics
+            | "loop_unroll_budget" // loop unrolling doesn't change semantics
+            | "loop_partial_unroll_factor" // loop unrolling doesn't change semantics
+            | "enable_redundant_check_elimination" // removing known checks doesn't change semantics
             | "probestack_func_adjusts_sp" // probestack above asserted disabled
             | "probestack_size_log2" // probestack above asserted disabled
             | "regalloc" // shouldn't change semantics
@@ -753,4 +781,29 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn compiler_settings_are_resolved() -> Result<()> {
+        let mut cfg = Config::new();
+        cfg.cranelift_opt_level(OptLevel::SpeedAndSize);
+        let engine = Engine::new(&cfg)?;
+        let settings = engine.compiler_settings();
+
+        assert_eq!(
+            settings[0],
+            (
+                "target".to_string(),
+                target_lexicon::Triple::host().to_string()
+            )
+        );
+        assert!(settings
+            .iter()
+            .any(|(name, value)| name == "opt_level" && value == "speed_and_size"));
+        #[cfg(target_arch = "x86_64")]
+        assert!(settings.iter().any(|(name, _)| name == "has_avx2"));
+        #[cfg(target_arch = "aarch64")]
+        assert!(settings.iter().any(|(name, _)| name == "has_lse"));
+
+        Ok(())
+    }
 }
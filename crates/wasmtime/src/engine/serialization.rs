@@ -260,7 +260,13 @@ impl Metadata {
             engine
                 .check_compatible_with_shared_flag(name, val)
                 .map_err(|s| anyhow::Error::msg(s))
-                .context("compilation settings of module incompatible with native host")?;
+                .with_context(|| {
+                    #[cfg(any(feature = "cranelift", feature = "winch"))]
+                    let engine_flags = Some(engine.compiler().flags());
+                    #[cfg(not(any(feature = "cranelift", feature = "winch")))]
+                    let engine_flags = None;
+                    incompatible_flags_message(&self.shared_flags, engine_flags)
+                })?;
         }
         Ok(())
     }
@@ -270,7 +276,13 @@ impl Metadata {
             engine
                 .check_compatible_with_isa_flag(name, val)
                 .map_err(|s| anyhow::Error::msg(s))
-                .context("compilation settings of module incompatible with native host")?;
+                .with_context(|| {
+                    #[cfg(any(feature = "cranelift", feature = "winch"))]
+                    let engine_flags = Some(engine.compiler().isa_flags());
+                    #[cfg(not(any(feature = "cranelift", feature = "winch")))]
+                    let engine_flags = None;
+                    incompatible_flags_message(&self.isa_flags, engine_flags)
+                })?;
         }
         Ok(())
     }
@@ -450,6 +462,40 @@ impl Metadata {
     }
 }
 
+/// Builds the headline error for a module whose compilation settings were
+/// rejected, listing every flag whose value differs from the engine's when
+/// the engine has a compiler to compare against.
+fn incompatible_flags_message(
+    module: &BTreeMap<String, FlagValue>,
+    engine: Option<BTreeMap<String, FlagValue>>,
+) -> String {
+    let mut msg = String::from("compilation settings of module incompatible with native host");
+    let engine = match engine {
+        Some(engine) => engine,
+        None => return msg,
+    };
+    let mut differing = module
+        .iter()
+        .map(|(name, value)| (name, Some(value), engine.get(name)))
+        .chain(
+            engine
+                .iter()
+                .filter(|(name, _)| !module.contains_key(*name))
+                .map(|(name, value)| (name, None, Some(value))),
+        )
+        .filter(|(_, module, engine)| module != engine)
+        .peekable();
+    if differing.peek().is_some() {
+        msg.push_str("; differing settings (module vs engine):");
+        for (name, module, engine) in differing {
+            let show = |v: Option<&FlagValue>| v.map_or("<unset>".to_string(), |v| v.to_string());
+            msg.push_str(&format!(" {}={} vs {},", name, show(module), show(engine)));
+        }
+        msg.pop();
+    }
+    msg
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -507,7 +553,8 @@ mod test {
             Ok(_) => unreachable!(),
             Err(e) => assert!(format!("{:?}", e).starts_with(
                 "\
-compilation settings of module incompatible with native host
+compilation settings of module incompatible with native host; \
+differing settings (module vs engine): preserve_frame_pointers=false vs true
 
 Caused by:
     setting \"preserve_frame_pointers\" is configured to Bool(false) which is not supported"
@@ -530,7 +577,8 @@ Caused by:
             Ok(_) => unreachable!(),
             Err(e) => assert!(format!("{:?}", e).starts_with(
                 "\
-compilation settings of module incompatible with native host
+compilation settings of module incompatible with native host; \
+differing settings (module vs engine): not_a_flag=true vs <unset>
 
 Caused by:
     cannot test if target-specific flag \"not_a_flag\" is available at runtime",
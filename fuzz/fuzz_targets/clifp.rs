@@ -0,0 +1,80 @@
+//! Lex and parse arbitrary text as clifp, the language of the `cranelift-clifp` crate.
+//!
+//! Neither may panic, errors must point into the input and render against it, and the
+//! s-expressions of text which parses must print as text which parses back into the same
+//! s-expressions.
+
+#![no_main]
+
+use cranelift_clifp::sexp::{self, Sexp};
+use cranelift_clifp::{diagnostic, lexer, parser};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|src: &str| {
+    let tokens = match lexer::lex(src) {
+        Ok(tokens) => tokens,
+        Err(err) => {
+            assert!(src.is_char_boundary(err.offset), "{err:?}");
+            let before = &src[..err.offset];
+            assert_eq!(err.line, 1 + before.matches('\n').count(), "{err:?}");
+            let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+            assert_eq!(
+                err.column,
+                1 + before[line_start..].chars().count(),
+                "{err:?}"
+            );
+            return;
+        }
+    };
+    for (token, span) in &tokens {
+        assert!(span.start < span.end, "{token:?} at {span:?}");
+        assert!(
+            src.is_char_boundary(span.start) && src.is_char_boundary(span.end),
+            "{token:?} at {span:?}"
+        );
+    }
+
+    let (forms, spans) = match sexp::parse_with_spans(&tokens) {
+        Ok(parsed) => parsed,
+        Err(err) => {
+            let (span, paren) = match err {
+                sexp::ParseError::Unclosed(span) | sexp::ParseError::TooDeep(span) => (span, "("),
+                sexp::ParseError::Unopened(span) => (span, ")"),
+            };
+            assert_eq!(src.get(span.start..span.end), Some(paren), "{err:?}");
+            return;
+        }
+    };
+
+    let printed = sexp::print(&forms);
+    let reparsed = lexer::lex(&printed)
+        .map_err(|e| e.to_string())
+        .and_then(|tokens| sexp::parse(&tokens).map_err(|e| e.to_string()))
+        .unwrap_or_else(|e| panic!("{printed:?} doesn't parse: {e}"));
+    assert!(
+        forms.len() == reparsed.len() && forms.iter().zip(&reparsed).all(|(a, b)| same(a, b)),
+        "{printed:?} parses as {reparsed:?}, not {forms:?}"
+    );
+
+    if let Err(diagnostics) = parser::parse(&forms, &spans) {
+        for diagnostic in &diagnostics {
+            let span = diagnostic.span;
+            assert!(
+                span.start <= span.end && src.get(span.start..span.end).is_some(),
+                "{diagnostic:?}"
+            );
+            diagnostic.render(src);
+        }
+    }
+});
+
+/// Whether `a` and `b` are equal, taking all NaNs to be equal since they all print as `nan`.
+fn same(a: &Sexp, b: &Sexp) -> bool {
+    match (a, b) {
+        (Sexp::Float(a), Sexp::Float(b)) => a == b || (a.is_nan() && b.is_nan()),
+        (Sexp::List(a), Sexp::List(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same(a, b))
+        }
+        (a, b) => a == b,
+    }
+}
@@ -4,7 +4,8 @@ use anyhow::{bail, Context, Result};
 use clap::Parser;
 use once_cell::sync::Lazy;
 use std::fs;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use wasmtime::Engine;
 use wasmtime_cli_flags::CommonOptions;
 
@@ -54,6 +55,14 @@ pub struct CompileCommand {
     #[clap(long = "emit-clif", value_name = "PATH", parse(from_os_str))]
     emit_clif: Option<PathBuf>,
 
+    /// The directory path to write optimized clif files into, one clif file per wasm function.
+    #[clap(long = "emit-clif-opt", value_name = "PATH", parse(from_os_str))]
+    emit_clif_opt: Option<PathBuf>,
+
+    /// Print the resolved compiler settings and ISA flags used for compilation
+    #[clap(long)]
+    print_settings: bool,
+
     /// The path of the WebAssembly to compile
     #[clap(index = 1, value_name = "MODULE", parse(from_os_str))]
     module: PathBuf,
@@ -66,23 +75,21 @@ impl CompileCommand {
 
         let mut config = self.common.config(self.target.as_deref())?;
 
-        if let Some(path) = self.emit_clif {
-            if !path.exists() {
-                std::fs::create_dir(&path)?;
-            }
-
-            if !path.is_dir() {
-                bail!(
-                    "the path passed for '--emit-clif' ({}) must be a directory",
-                    path.display()
-                );
-            }
-
-            config.emit_clif(&path);
+        if let Some(path) = &self.emit_clif {
+            create_clif_dir(path, "--emit-clif")?;
+            config.emit_clif(path);
+        }
+        if let Some(path) = &self.emit_clif_opt {
+            create_clif_dir(path, "--emit-clif-opt")?;
+            config.emit_clif_opt(path);
         }
 
         let engine = Engine::new(&config)?;
 
+        if self.print_settings {
+            print_settings(&engine, &mut std::io::stdout().lock())?;
+        }
+
         if self.module.file_name().is_none() {
             bail!(
                 "'{}' is not a valid input module path",
@@ -122,10 +129,35 @@ impl CompileCommand {
     }
 }
 
+/// Creates the directory passed to `--emit-clif` or `--emit-clif-opt` if it doesn't exist yet.
+fn create_clif_dir(path: &Path, flag: &str) -> Result<()> {
+    if !path.exists() {
+        std::fs::create_dir(path)?;
+    }
+
+    if !path.is_dir() {
+        bail!(
+            "the path passed for '{}' ({}) must be a directory",
+            flag,
+            path.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Writes each of the engine's resolved compiler settings as a `name = value`
+/// line.
+fn print_settings(engine: &Engine, out: &mut impl Write) -> Result<()> {
+    for (name, value) in engine.compiler_settings() {
+        writeln!(out, "{} = {}", name, value)?;
+    }
+    Ok(())
+}
+
 #[cfg(all(test, not(miri)))]
 mod test {
     use super::*;
-    use std::io::Write;
     use tempfile::NamedTempFile;
     use wasmtime::{Instance, Module, Store};
 
@@ -160,6 +192,98 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_emit_clif() -> Result<()> {
+        let (mut input, input_path) = NamedTempFile::new()?.into_parts();
+        input.write_all(
+            r#"
+            (module
+                (type $t (func (param i32) (result i32)))
+                (memory 1)
+                (table 1 funcref)
+                (global $g (mut i32) (i32.const 0))
+                (func $load (export "load") (param i32) (result i32)
+                    local.get 0
+                    i32.load)
+                (func $bump (param i32)
+                    global.get $g
+                    local.get 0
+                    i32.add
+                    global.set $g)
+                (func (param i32) (result i32)
+                    local.get 0
+                    i32.const 0
+                    call_indirect (type $t)))
+            "#
+            .as_bytes(),
+        )?;
+        drop(input);
+
+        let output_path = NamedTempFile::new()?.into_temp_path();
+        let clif_dir = tempfile::tempdir()?;
+        let clif_opt_dir = tempfile::tempdir()?;
+
+        let command = CompileCommand::try_parse_from(vec![
+            "compile",
+            "--disable-logging",
+            "--emit-clif",
+            clif_dir.path().to_str().unwrap(),
+            "--emit-clif-opt",
+            clif_opt_dir.path().to_str().unwrap(),
+            "-o",
+            output_path.to_str().unwrap(),
+            input_path.to_str().unwrap(),
+        ])?;
+
+        command.execute()?;
+
+        for dir in [clif_dir.path(), clif_opt_dir.path()] {
+            let mut files = std::fs::read_dir(dir)?
+                .map(|entry| Ok(entry?.file_name().into_string().unwrap()))
+                .collect::<Result<Vec<_>>>()?;
+            files.sort();
+            assert_eq!(
+                files,
+                [
+                    "wasm_func_0_load.clif",
+                    "wasm_func_1_bump.clif",
+                    "wasm_func_2.clif"
+                ]
+            );
+
+            for file in &files {
+                let clif = std::fs::read_to_string(dir.join(file))?;
+                let funcs = cranelift_reader::parse_functions(&clif)
+                    .with_context(|| format!("failed to parse {}:\n{}", file, clif))?;
+                assert_eq!(funcs.len(), 1, "{}", file);
+            }
+
+            let load = std::fs::read_to_string(dir.join("wasm_func_0_load.clif"))?;
+            assert!(load.starts_with("; wasm function 0 (defined function 0): \"load\"\n"));
+            assert!(load.contains("; heap0: base gv"), "{}", load);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_print_settings() -> Result<()> {
+        let mut config = wasmtime::Config::new();
+        config.cranelift_opt_level(wasmtime::OptLevel::Speed);
+        let engine = Engine::new(&config)?;
+
+        let mut out = Vec::new();
+        print_settings(&engine, &mut out)?;
+        let out = String::from_utf8(out)?;
+
+        assert!(out.starts_with("target = "), "{}", out);
+        assert!(out.contains("\nopt_level = speed\n"), "{}", out);
+        #[cfg(target_arch = "x86_64")]
+        assert!(out.contains("\nhas_avx2 = "), "{}", out);
+
+        Ok(())
+    }
+
     #[cfg(target_arch = "x86_64")]
     #[test]
     fn test_x64_flags_compile() -> Result<()> {
@@ -24,6 +24,12 @@ pub struct ExploreCommand {
     /// provided)
     #[clap(short, long)]
     output: Option<PathBuf>,
+
+    /// Write per-function code size and address map data as JSON to this
+    /// path instead of producing the HTML explorer (unless `--output` is also
+    /// given)
+    #[clap(long, value_name = "PATH")]
+    json: Option<PathBuf>,
 }
 
 impl ExploreCommand {
@@ -36,6 +42,17 @@ impl ExploreCommand {
         let wasm = std::fs::read(&self.module)
             .with_context(|| format!("failed to read Wasm module: {}", self.module.display()))?;
 
+        if let Some(json) = &self.json {
+            let json_file = std::fs::File::create(json)
+                .with_context(|| format!("failed to create file: {}", json.display()))?;
+            let mut json_file = std::io::BufWriter::new(json_file);
+            wasmtime_explorer::generate_json(&config, &wasm, &mut json_file)?;
+            println!("Exploration data written to {}", json.display());
+            if self.output.is_none() {
+                return Ok(());
+            }
+        }
+
         let output = self
             .output
             .clone()